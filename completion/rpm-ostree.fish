@@ -0,0 +1,6 @@
+# fish completion for rpm-ostree
+#
+# The script is generated from the command line definitions by
+# `rpm-ostree completion fish`, so that it matches the installed version.
+
+rpm-ostree completion fish 2>/dev/null | source
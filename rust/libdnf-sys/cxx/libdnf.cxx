@@ -64,4 +64,24 @@ hy_split_nevra (rust::Str nevra)
   // the copy here *should* get elided
   return r;
 }
+
+// Compare two [epoch:]version-release strings like rpm does
+int32_t
+rpmver_cmp (rust::Str a, rust::Str b)
+{
+  g_autofree char *a_c = g_strndup (a.data (), a.length ());
+  g_autofree char *b_c = g_strndup (b.data (), b.length ());
+  rpmver va = rpmverParse (a_c);
+  rpmver vb = rpmverParse (b_c);
+  if (va == NULL || vb == NULL)
+    {
+      rpmverFree (va);
+      rpmverFree (vb);
+      throw std::runtime_error (std::string ("Failed to parse EVR: ") + (va ? b_c : a_c));
+    }
+  int r = rpmverCmp (va, vb);
+  rpmverFree (va);
+  rpmverFree (vb);
+  return r;
+}
 }
@@ -24,6 +24,7 @@
 
 #include <gio/gio.h>
 #include <libdnf/libdnf.h>
+#include <rpm/rpmver.h>
 
 #include "rust/cxx.h"
 
@@ -124,4 +125,5 @@ std::unique_ptr<DnfSack> dnf_sack_new () noexcept;
 // utility functions here
 struct Nevra;
 Nevra hy_split_nevra (rust::Str nevra);
+int32_t rpmver_cmp (rust::Str a, rust::Str b);
 }
@@ -72,6 +72,7 @@ pub mod ffi {
         fn dnf_sack_new() -> UniquePtr<DnfSack>;
 
         fn hy_split_nevra(nevra: &str) -> Result<Nevra>;
+        fn rpmver_cmp(a: &str, b: &str) -> Result<i32>;
     }
 }
 
@@ -97,4 +98,12 @@ pub(crate) mod tests {
         assert_eq!(n.release, "3");
         assert_eq!(n.arch, "mips");
     }
+
+    #[test]
+    fn test_rpmver_cmp() {
+        assert_eq!(rpmver_cmp("1.0-1", "1.0-1").unwrap(), 0);
+        assert!(rpmver_cmp("1:1.0-1", "2.0-1").unwrap() > 0);
+        assert!(rpmver_cmp("1.0-2.fc36", "1.0-10.fc36").unwrap() < 0);
+        assert!(rpmver_cmp("1.0~rc1-1", "1.0-1").unwrap() < 0);
+    }
 }
@@ -0,0 +1,362 @@
+//! APIs for talking to the rpm-ostree daemon directly over D-Bus, rather
+//! than through the client binary; enabled by the `dbus` feature.
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::{Progress, Result, Status};
+use gio::prelude::*;
+use glib::{Variant, VariantTy};
+use serde_json::{Map, Value};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+/// The well-known bus name.
+const BUS_NAME: &str = "org.projectatomic.rpmostree1";
+/// The global sysroot path
+const SYSROOT_PATH: &str = "/org/projectatomic/rpmostree1/Sysroot";
+const SYSROOT_INTERFACE: &str = "org.projectatomic.rpmostree1.Sysroot";
+const OS_INTERFACE: &str = "org.projectatomic.rpmostree1.OS";
+const TRANSACTION_INTERFACE: &str = "org.projectatomic.rpmostree1.Transaction";
+
+/// A connection to the rpm-ostree daemon on the system bus.
+#[derive(Debug)]
+pub struct DBusClient {
+    conn: gio::DBusConnection,
+    sysroot_proxy: gio::DBusProxy,
+}
+
+/// An event emitted by a transaction while it runs.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ProgressEvent {
+    /// A line of output
+    Message(String),
+    /// A task started
+    TaskBegin(String),
+    /// A task finished
+    TaskEnd(String),
+    /// Progress of a task by percentage
+    Percent { text: String, percentage: u32 },
+    /// Structured progress of a task
+    Progress(Progress),
+    /// The task reporting progress finished
+    ProgressEnd,
+}
+
+/// A transaction of the daemon, e.g. started by [`DBusClient::call_os`].
+#[derive(Debug)]
+pub struct TransactionClient {
+    conn: gio::DBusConnection,
+}
+
+/// Convert a GVariant to JSON the way `rpm-ostree status --json` does.
+fn variant_to_json(v: &Variant) -> Value {
+    let ty = v.type_().as_str();
+    match ty.as_bytes()[0] {
+        b'b' => v.get::<bool>().into(),
+        b'y' => v.get::<u8>().into(),
+        b'n' => v.get::<i16>().into(),
+        b'q' => v.get::<u16>().into(),
+        b'i' => v.get::<i32>().into(),
+        b'u' => v.get::<u32>().into(),
+        b'x' => v.get::<i64>().into(),
+        b't' => v.get::<u64>().into(),
+        b'd' => v.get::<f64>().into(),
+        b's' | b'o' | b'g' => v.str().into(),
+        b'v' => variant_to_json(&v.child_value(0)),
+        b'm' if v.n_children() == 0 => Value::Null,
+        b'm' => variant_to_json(&v.child_value(0)),
+        b'a' if ty.starts_with("a{") => {
+            let mut map = Map::new();
+            for i in 0..v.n_children() {
+                let entry = v.child_value(i);
+                let k = entry.child_value(0);
+                let k = match k.str() {
+                    Some(k) => k.to_string(),
+                    None => variant_to_json(&k).to_string(),
+                };
+                map.insert(k, variant_to_json(&entry.child_value(1)));
+            }
+            Value::Object(map)
+        }
+        b'a' | b'(' => (0..v.n_children())
+            .map(|i| variant_to_json(&v.child_value(i)))
+            .collect(),
+        _ => Value::Null,
+    }
+}
+
+impl DBusClient {
+    /// Connect to the daemon, starting it if needed, and register as a client
+    /// identified by `agent_id`; see [`crate::CliClient::new`].
+    pub fn new<S: AsRef<str>>(agent_id: S) -> Result<Self> {
+        let conn = gio::bus_get_sync(gio::BusType::System, gio::NONE_CANCELLABLE)?;
+        let options = glib::VariantDict::new(None);
+        options.insert_value("id", &agent_id.as_ref().to_variant());
+        let params = Variant::from_tuple(&[options.end()]);
+        // Retry if we caught the daemon while it was doing an idle exit, see
+        // https://github.com/projectatomic/rpm-ostree/pull/606
+        loop {
+            let r = conn.call_sync(
+                Some(BUS_NAME),
+                SYSROOT_PATH,
+                SYSROOT_INTERFACE,
+                "RegisterClient",
+                Some(&params),
+                Some(VariantTy::new("()").unwrap()),
+                gio::DBusCallFlags::NONE,
+                -1,
+                gio::NONE_CANCELLABLE,
+            );
+            match r {
+                Ok(_) => break,
+                Err(e) if e.matches(gio::DBusError::NoReply) => continue,
+                Err(e) => return Err(format!("Failed to invoke RegisterClient: {}", e).into()),
+            }
+        }
+        let sysroot_proxy = gio::DBusProxy::new_sync(
+            &conn,
+            gio::DBusProxyFlags::NONE,
+            None,
+            Some(BUS_NAME),
+            SYSROOT_PATH,
+            SYSROOT_INTERFACE,
+            gio::NONE_CANCELLABLE,
+        )?;
+        Ok(Self {
+            conn,
+            sysroot_proxy,
+        })
+    }
+
+    fn sysroot_property(&self, name: &str) -> Result<Variant> {
+        self.sysroot_proxy
+            .cached_property(name)
+            .ok_or_else(|| format!("Failed to find {} property", name).into())
+    }
+
+    /// The object path of the booted OS.
+    fn booted_os_path(&self) -> Result<String> {
+        let booted = self.sysroot_property("Booted")?;
+        booted
+            .str()
+            .map(|s| s.to_string())
+            .ok_or_else(|| "Booted sysroot is not a string".into())
+    }
+
+    /// Gather a snapshot of the system status, as [`crate::CliClient::query_status`]
+    /// does; the update driver isn't available over D-Bus and is always unset.
+    pub fn query_status(&self) -> Result<Status> {
+        let deployments = variant_to_json(&self.sysroot_property("Deployments")?);
+        let transaction = match variant_to_json(&self.sysroot_property("ActiveTransaction")?) {
+            Value::Array(t) if t.first().and_then(|m| m.as_str()) != Some("") => Value::Array(t),
+            _ => Value::Null,
+        };
+        let os_proxy = gio::DBusProxy::new_sync(
+            &self.conn,
+            gio::DBusProxyFlags::NONE,
+            None,
+            Some(BUS_NAME),
+            &self.booted_os_path()?,
+            OS_INTERFACE,
+            gio::NONE_CANCELLABLE,
+        )?;
+        let cached_update = match os_proxy.cached_property("CachedUpdate") {
+            Some(u) if u.n_children() > 0 => variant_to_json(&u),
+            _ => Value::Null,
+        };
+        let mut status = Map::new();
+        status.insert("deployments".into(), deployments);
+        status.insert("transaction".into(), transaction);
+        status.insert("cached-update".into(), cached_update);
+        Ok(serde_json::from_value(Value::Object(status))?)
+    }
+
+    /// Call `method` of the booted OS with `params`, for the methods which
+    /// start a transaction, e.g. `UpdateDeployment`; returns the transaction.
+    pub fn call_os(&self, method: &str, params: &Variant) -> Result<TransactionClient> {
+        let reply = self.conn.call_sync(
+            Some(BUS_NAME),
+            &self.booted_os_path()?,
+            OS_INTERFACE,
+            method,
+            Some(params),
+            None,
+            gio::DBusCallFlags::NONE,
+            -1,
+            gio::NONE_CANCELLABLE,
+        )?;
+        let address = reply.child_value(0);
+        let address = address
+            .str()
+            .ok_or_else(|| format!("{} did not return a transaction address", method))?;
+        TransactionClient::connect(address)
+    }
+
+    /// Connect to the transaction in progress, if any.
+    pub fn active_transaction(&self) -> Result<Option<TransactionClient>> {
+        let path = self.sysroot_property("ActiveTransactionPath")?;
+        match path.str() {
+            Some("") | None => Ok(None),
+            Some(address) => Ok(Some(TransactionClient::connect(address)?)),
+        }
+    }
+}
+
+impl ProgressEvent {
+    fn new(member: &str, params: &Variant) -> Result<Option<Self>> {
+        let text = || {
+            params
+                .child_value(0)
+                .str()
+                .map(|s| s.to_string())
+                .unwrap_or_default()
+        };
+        Ok(match member {
+            "Message" => Some(Self::Message(text())),
+            "TaskBegin" => Some(Self::TaskBegin(text())),
+            "TaskEnd" => Some(Self::TaskEnd(text())),
+            "PercentProgress" => Some(Self::Percent {
+                text: text(),
+                percentage: params.child_value(1).get().unwrap_or_default(),
+            }),
+            "Progress" => Some(Self::Progress(serde_json::from_value(variant_to_json(
+                &params.child_value(0),
+            ))?)),
+            "ProgressEnd" => Some(Self::ProgressEnd),
+            _ => None,
+        })
+    }
+}
+
+/// What the signal handlers pass on to [`TransactionClient::run`].
+#[derive(Debug, Default)]
+struct TransactionState {
+    events: VecDeque<Result<ProgressEvent>>,
+    /// The error message if the transaction failed
+    finished: Option<std::result::Result<(), String>>,
+    closed: bool,
+}
+
+impl TransactionClient {
+    /// Connect to the transaction at D-Bus address `address`.
+    pub fn connect(address: &str) -> Result<Self> {
+        let conn = gio::DBusConnection::for_address_sync(
+            address,
+            gio::DBusConnectionFlags::AUTHENTICATION_CLIENT,
+            None,
+            gio::NONE_CANCELLABLE,
+        )?;
+        Ok(Self { conn })
+    }
+
+    /// Start the transaction, or follow it if another client started it,
+    /// calling `f` with its progress until it finishes.
+    pub fn run(&self, mut f: impl FnMut(ProgressEvent)) -> Result<()> {
+        let state = Rc::new(RefCell::new(TransactionState::default()));
+        let s = state.clone();
+        let subscription = self.conn.signal_subscribe(
+            None,
+            Some(TRANSACTION_INTERFACE),
+            None,
+            Some("/"),
+            None,
+            gio::DBusSignalFlags::NONE,
+            move |_, _, _, _, member, params| {
+                let mut s = s.borrow_mut();
+                if member == "Finished" {
+                    let success = params.child_value(0).get().unwrap_or_default();
+                    let message = params.child_value(1).str().unwrap_or_default().to_string();
+                    s.finished = Some(if success { Ok(()) } else { Err(message) });
+                } else if let Some(event) = ProgressEvent::new(member, params).transpose() {
+                    s.events.push_back(event);
+                }
+            },
+        );
+        let s = state.clone();
+        let closed = self
+            .conn
+            .connect_closed(move |_, _, _| s.borrow_mut().closed = true);
+        let r = self.call_start().and_then(|_| {
+            let ctx = glib::MainContext::default();
+            loop {
+                let event = state.borrow_mut().events.pop_front();
+                if let Some(event) = event {
+                    f(event?);
+                    continue;
+                }
+                let s = state.borrow();
+                match (&s.finished, s.closed) {
+                    (Some(Ok(())), _) => return Ok(()),
+                    (Some(Err(e)), _) => return Err(e.clone().into()),
+                    (None, true) => return Err("Transaction connection closed".into()),
+                    (None, false) => {}
+                }
+                drop(s);
+                ctx.iteration(true);
+            }
+        });
+        self.conn.signal_unsubscribe(subscription);
+        self.conn.disconnect(closed);
+        r
+    }
+
+    fn call_start(&self) -> Result<()> {
+        self.conn.call_sync(
+            None,
+            "/",
+            TRANSACTION_INTERFACE,
+            "Start",
+            None,
+            Some(VariantTy::new("(b)").unwrap()),
+            gio::DBusCallFlags::NONE,
+            -1,
+            gio::NONE_CANCELLABLE,
+        )?;
+        Ok(())
+    }
+
+    /// Ask the transaction to stop; [`Self::run`] then returns an error.
+    pub fn cancel(&self) -> Result<()> {
+        self.conn.call_sync(
+            None,
+            "/",
+            TRANSACTION_INTERFACE,
+            "Cancel",
+            None,
+            None,
+            gio::DBusCallFlags::NONE,
+            -1,
+            gio::NONE_CANCELLABLE,
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_variant_to_json() {
+        let dict = glib::VariantDict::new(None);
+        dict.insert("checksum", &"41ab".to_string());
+        dict.insert("serial", &2i32);
+        dict.insert("booted", &true);
+        dict.insert("timestamp", &1665900000u64);
+        dict.insert("packages", &vec!["strace".to_string()]);
+        let v = Variant::from_tuple(&[dict.end(), "Upgrade".to_variant()]);
+        assert_eq!(
+            variant_to_json(&v),
+            serde_json::json!([
+                {
+                    "checksum": "41ab",
+                    "serial": 2,
+                    "booted": true,
+                    "timestamp": 1665900000u64,
+                    "packages": ["strace"],
+                },
+                "Upgrade",
+            ])
+        );
+    }
+}
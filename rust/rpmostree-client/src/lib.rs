@@ -6,6 +6,11 @@ use serde_derive::Deserialize;
 use std::collections::HashMap;
 use std::process::Command;
 
+#[cfg(feature = "dbus")]
+mod dbus;
+#[cfg(feature = "dbus")]
+pub use dbus::*;
+
 /// Our generic catchall fatal error, expected to be converted
 /// to a string to output to a terminal or logs.
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync + 'static>>;
@@ -38,6 +43,50 @@ impl CliClient {
 #[serde(rename_all = "kebab-case")]
 pub struct Status {
     pub deployments: Vec<Deployment>,
+    /// The transaction in progress
+    #[serde(default)]
+    pub transaction: Option<Transaction>,
+    /// An update found by `upgrade --check` or the automatic update policy
+    #[serde(default)]
+    pub cached_update: Option<CachedUpdate>,
+    /// The agent registered to drive updates
+    #[serde(default)]
+    pub update_driver: Option<UpdateDriver>,
+}
+
+/// The transaction in progress; serialized as a `[method, sender, path]` array.
+#[derive(Debug, Deserialize)]
+pub struct Transaction {
+    /// The D-Bus method which started the transaction, e.g. `Upgrade`
+    pub method: String,
+    /// The D-Bus name of the client
+    pub sender: String,
+    /// The D-Bus address of the transaction
+    pub path: String,
+}
+
+/// An update which was found but not deployed yet
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CachedUpdate {
+    /// Checksum of the ostree commit, or digest of the container image
+    pub checksum: String,
+    pub origin: Option<String>,
+    pub version: Option<String>,
+    /// Creation time, in seconds since the epoch
+    pub timestamp: Option<u64>,
+    /// Why the update isn't staged yet if its phased rollout hasn't reached
+    /// this machine
+    pub rollout_deferred: Option<String>,
+}
+
+/// The agent registered with `rpm-ostree deploy --register-driver`
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct UpdateDriver {
+    pub driver_name: String,
+    /// The systemd unit of the driver
+    pub driver_sd_unit: Option<String>,
 }
 
 /// A single deployment, i.e. a bootable ostree commit
@@ -56,6 +105,59 @@ pub struct Deployment {
     pub origin: Option<String>,
     pub container_image_reference: Option<String>,
     pub version: Option<String>,
+    #[serde(default)]
+    pub id: String,
+    /// Creation time of the commit, in seconds since the epoch
+    pub timestamp: Option<u64>,
+    /// Whether the deployment will not be finalized on shutdown
+    #[serde(default)]
+    pub finalization_locked: bool,
+    /// Digest of the manifest of the deployed container image
+    pub container_image_reference_digest: Option<String>,
+    /// Checksum of the commit applied live on top of the deployment
+    pub live_replaced: Option<String>,
+    /// Packages layered in the deployed commit
+    #[serde(default)]
+    pub packages: Vec<String>,
+    /// Packages requested to be layered
+    #[serde(default)]
+    pub requested_packages: Vec<String>,
+    /// NEVRAs of local packages requested to be layered
+    #[serde(default)]
+    pub requested_local_packages: Vec<String>,
+    /// Names of base packages requested to be removed
+    #[serde(default)]
+    pub requested_base_removals: Vec<String>,
+    /// NEVRAs of local packages requested to replace base packages
+    #[serde(default)]
+    pub requested_base_local_replacements: Vec<String>,
+    #[serde(default)]
+    pub regenerate_initramfs: bool,
+    #[serde(default)]
+    pub initramfs_args: Vec<String>,
+    /// Size in bytes of the objects not shared with other deployments
+    pub unique_size: Option<u64>,
+}
+
+/// Structured progress of a transaction, as carried by the `Progress` signal
+/// and written as JSON lines to `--json-progress-fd`.
+#[derive(Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct Progress {
+    /// One of `download`, `resolve`, `assemble`, `deploy` or `other`; more
+    /// stages may be added.
+    #[serde(default)]
+    pub stage: String,
+    /// The human-readable description of the task
+    #[serde(default)]
+    pub text: String,
+    pub items_done: Option<u64>,
+    pub items_total: Option<u64>,
+    pub bytes_done: Option<u64>,
+    pub bytes_total: Option<u64>,
+    pub percentage: Option<u32>,
+    /// Estimated time remaining
+    pub eta_seconds: Option<u64>,
 }
 
 impl Status {
@@ -69,6 +171,13 @@ impl Status {
         self.find_booted()
             .ok_or_else(|| "No booted deployment".to_string().into())
     }
+
+    /// Find the staged deployment, if any.
+    pub fn find_staged(&self) -> Option<&Deployment> {
+        self.deployments
+            .iter()
+            .find(|d| d.staged.unwrap_or_default())
+    }
 }
 
 impl Deployment {
@@ -22,3 +22,30 @@ fn parse_workstation() -> Result<()> {
     );
     Ok(())
 }
+
+#[test]
+fn parse_workstation_packages() -> Result<()> {
+    let data = include_str!("fixtures/workstation-status.json");
+    let state: &rpmostree_client::Status = &serde_json::from_str(data)?;
+    assert!(state.transaction.is_none());
+    assert!(state.cached_update.is_none());
+    assert!(state.find_staged().is_none());
+    let booted = state.require_booted().unwrap();
+    assert!(!booted.id.is_empty());
+    assert!(booted.timestamp.is_some());
+    assert!(!booted.regenerate_initramfs);
+    assert!(booted.packages.iter().any(|p| p == "strace"));
+    assert!(booted.requested_base_removals.is_empty());
+    Ok(())
+}
+
+#[test]
+fn parse_progress() -> Result<()> {
+    let line = r#"{"stage":"download","text":"Receiving objects","items-done":null,"items-total":null,"bytes-done":1024,"bytes-total":4096,"percentage":25,"eta-seconds":3}"#;
+    let progress: rpmostree_client::Progress = serde_json::from_str(line)?;
+    assert_eq!(progress.stage, "download");
+    assert_eq!(progress.bytes_total, Some(4096));
+    assert_eq!(progress.percentage, Some(25));
+    assert_eq!(progress.items_done, None);
+    Ok(())
+}
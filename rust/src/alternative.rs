@@ -0,0 +1,180 @@
+//! The alternative to the staged deployment, written by e.g. `rpm-ostree
+//! upgrade --alternative` and picked with `rpm-ostree finalize --alternative`.
+//!
+//! libostree drops all the rollback deployments but the booted one when
+//! finalizing a staged deployment, unless they're pinned.  So the alternative
+//! is pinned, and recorded in `/var/lib/rpm-ostree/alternative.json`, apart
+//! from the pins of `rpm-ostree pin`; the pin is released again once either of
+//! them is finalized.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::cxxrsutil::*;
+use crate::deployment_generate_id_impl;
+use crate::pin::set_pinned_now;
+use anyhow::{Context, Result};
+use ostree_ext::{glib, ostree};
+use serde_derive::{Deserialize, Serialize};
+use std::path::Path;
+
+const STATE_PATH: &str = "/var/lib/rpm-ostree/alternative.json";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct AlternativeState {
+    /// The ID of the alternative deployment
+    deployment: String,
+}
+
+fn load_state(path: &Path) -> Result<Option<AlternativeState>> {
+    match std::fs::read_to_string(path) {
+        Ok(s) => serde_json::from_str(&s)
+            .map(Some)
+            .with_context(|| format!("Parsing {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("Reading {}", path.display())),
+    }
+}
+
+fn store_state(path: &Path, state: Option<&AlternativeState>) -> Result<()> {
+    match state {
+        Some(state) => {
+            let buf = serde_json::to_vec(state)?;
+            crate::utils::write_file_atomic(path, buf)
+        }
+        None => match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("Removing {}", path.display()))
+            }
+            _ => Ok(()),
+        },
+    }
+}
+
+/// Whether `state` records `deployment` as the alternative.
+fn records(state: Option<&AlternativeState>, deployment: &ostree::Deployment) -> bool {
+    let id = deployment_generate_id_impl(deployment);
+    state.map_or(false, |s| s.deployment == id)
+}
+
+/// Whether `deployment` is the alternative recorded in `state`, and still pinned.
+fn is_alternative(state: Option<&AlternativeState>, deployment: &ostree::Deployment) -> bool {
+    deployment.is_pinned() && records(state, deployment)
+}
+
+fn alternative_pin_impl(
+    sysroot: &ostree::Sysroot,
+    path: &Path,
+    deployment: &ostree::Deployment,
+) -> Result<()> {
+    // There is only one alternative; the caller drops the previous one
+    set_pinned_now(sysroot, deployment, true)?;
+    let state = AlternativeState {
+        deployment: deployment_generate_id_impl(deployment),
+    };
+    store_state(path, Some(&state))
+}
+
+/// Pin `deployment` as the alternative to the staged deployment, replacing the
+/// previous one.  This must be called with the sysroot lock held.
+pub(crate) fn alternative_pin(
+    sysroot: &crate::ffi::OstreeSysroot,
+    deployment: &crate::ffi::OstreeDeployment,
+) -> CxxResult<()> {
+    let sysroot = &sysroot.glib_reborrow();
+    let deployment = &deployment.glib_reborrow();
+    alternative_pin_impl(sysroot, Path::new(STATE_PATH), deployment)?;
+    Ok(())
+}
+
+fn alternative_deployment_index_impl(
+    sysroot: &ostree::Sysroot,
+    path: &Path,
+) -> Result<Option<usize>> {
+    let state = load_state(path)?;
+    Ok(sysroot
+        .deployments()
+        .iter()
+        .position(|d| is_alternative(state.as_ref(), d)))
+}
+
+/// The index of the alternative to the staged deployment among the
+/// deployments, or -1 if there is none.
+pub(crate) fn alternative_deployment_index(sysroot: &crate::ffi::OstreeSysroot) -> CxxResult<i32> {
+    let sysroot = &sysroot.glib_reborrow();
+    let index = alternative_deployment_index_impl(sysroot, Path::new(STATE_PATH))?;
+    Ok(index.map_or(-1, |i| i as i32))
+}
+
+fn release_alternative_pin_impl(sysroot: &ostree::Sysroot, path: &Path, force: bool) -> Result<()> {
+    let state = load_state(path)?;
+    if state.is_none() || !(force || sysroot.staged_deployment().is_none()) {
+        return Ok(());
+    }
+    for d in sysroot.deployments() {
+        if is_alternative(state.as_ref(), &d) {
+            set_pinned_now(sysroot, &d, false)?;
+        }
+    }
+    store_state(path, None)
+}
+
+/// Release the pin of the alternative deployment if there is no staged
+/// deployment anymore, or if `force` is set.  This must be called with the
+/// sysroot lock held.
+pub(crate) fn release_alternative_pin(
+    sysroot: &crate::ffi::OstreeSysroot,
+    force: bool,
+) -> CxxResult<()> {
+    let sysroot = &sysroot.glib_reborrow();
+    release_alternative_pin_impl(sysroot, Path::new(STATE_PATH), force)?;
+    Ok(())
+}
+
+/// Forget that `deployment` is the alternative, e.g. because it is pinned or
+/// unpinned explicitly.  This must be called with the sysroot lock held.
+pub(crate) fn forget_alternative(deployment: &ostree::Deployment) -> Result<()> {
+    let path = Path::new(STATE_PATH);
+    let state = load_state(path)?;
+    if records(state.as_ref(), deployment) {
+        store_state(path, None)?;
+    }
+    Ok(())
+}
+
+/// Mark `deployment` as the alternative in `dict` if it is.
+pub(crate) fn deployment_populate_alternative(
+    deployment: &ostree::Deployment,
+    dict: &glib::VariantDict,
+) -> Result<()> {
+    if !deployment.is_pinned() {
+        return Ok(());
+    }
+    let state = load_state(Path::new(STATE_PATH))?;
+    if is_alternative(state.as_ref(), deployment) {
+        dict.insert("alternative", &true);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        let path = &td.path().join("alternative.json");
+        assert_eq!(load_state(path)?, None);
+        let state = AlternativeState {
+            deployment: "fedora-abc.0".into(),
+        };
+        store_state(path, Some(&state))?;
+        assert_eq!(load_state(path)?, Some(state));
+        store_state(path, None)?;
+        assert!(!path.exists());
+        // Removing it again is fine
+        store_state(path, None)?;
+        Ok(())
+    }
+}
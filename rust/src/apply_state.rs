@@ -0,0 +1,462 @@
+//! Implementation of `rpm-ostree ex apply`, which converges the system to a
+//! declarative description of its desired state, e.g.:
+//!
+//! ```yaml
+//! base: ostree-remote-image:fedora:registry:quay.io/fedora/fedora-coreos:stable
+//! packages: [htop, vim-enhanced]
+//! override-remove: [nano]
+//! override-replace:
+//!   - from: {repo: updates-testing}
+//!     packages: [podman]
+//! kargs:
+//!   append: [nosmt]
+//! initramfs:
+//!   regenerate: true
+//!   etc: [/etc/crypttab]
+//! ```
+//!
+//! Each key which is present describes that aspect of the system completely:
+//! e.g. the layered packages which aren't listed in `packages` are removed.
+//! The aspects whose key is absent are left as they are.  All the changes are
+//! made in a single new deployment, and applying the same state again is a
+//! no-op.
+//!
+//! The client rebases if the base differs; everything else is compared to the
+//! origin of the merge deployment by the daemon, in the deploy transaction.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::treefile::{DeriveKargs, RemoteOverrideReplace, TreeComposeConfig};
+use anyhow::{anyhow, bail, Context, Result};
+use camino::Utf8PathBuf;
+use clap::Parser;
+use gio::prelude::*;
+use glib::Variant;
+use ostree_ext::container::OstreeImageReference;
+use ostree_ext::{gio, glib};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+use crate::utils::print_treepkg_diff;
+
+/// The desired state of the system.
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct DesiredState {
+    /// The base, as an OSTree refspec or a container image reference.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) base: Option<String>,
+    /// The layered packages.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) packages: Option<BTreeSet<String>>,
+    /// The packages removed from the base.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) override_remove: Option<BTreeSet<String>>,
+    /// The packages of the base replaced from a repository.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) override_replace: Option<Vec<RemoteOverrideReplace>>,
+    /// The changes of the kernel arguments relative to those of the base.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) kargs: Option<StateKargs>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) initramfs: Option<StateInitramfs>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct StateKargs {
+    #[serde(default)]
+    pub(crate) append: Vec<String>,
+    #[serde(default)]
+    pub(crate) delete: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct StateInitramfs {
+    /// Regenerate the initramfs on the client.
+    #[serde(default)]
+    pub(crate) regenerate: bool,
+    /// Arguments of dracut when regenerating it.
+    #[serde(default)]
+    pub(crate) args: Vec<String>,
+    /// The files of `/etc` to include, as with `rpm-ostree initramfs-etc`.
+    #[serde(default)]
+    pub(crate) etc: BTreeSet<String>,
+}
+
+impl DesiredState {
+    /// Parse and validate a state file, in YAML or JSON.
+    fn parse(buf: &str) -> Result<Self> {
+        let mut state: Self = serde_yaml::from_str(buf)?;
+        state.validate()?;
+        Ok(state)
+    }
+
+    /// Validate the state, normalizing the paths of `/etc`.  The daemon
+    /// validates the state it's passed again, as it may not come from us.
+    fn validate(&mut self) -> Result<()> {
+        if let Some(ref mut initramfs) = self.initramfs {
+            let etc = std::mem::take(&mut initramfs.etc).into_iter().collect();
+            initramfs.etc = crate::initramfs::initramfs_etc_normalize_paths(etc)?
+                .into_iter()
+                .collect();
+            if !initramfs.regenerate && !initramfs.args.is_empty() {
+                bail!("initramfs: args requires regenerate");
+            }
+        }
+        let mut replaced = BTreeSet::new();
+        for pkg in self
+            .override_replace
+            .iter()
+            .flatten()
+            .flat_map(|r| &r.packages)
+        {
+            if !replaced.insert(pkg) {
+                bail!("override-replace: {} is replaced more than once", pkg);
+            }
+        }
+        if let Some(pkg) = self
+            .override_remove
+            .iter()
+            .flatten()
+            .find(|p| replaced.contains(p))
+        {
+            bail!("{} is both in override-remove and override-replace", pkg);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Parser)]
+#[clap(name = "rpm-ostree ex apply")]
+#[clap(rename_all = "kebab-case")]
+struct Opt {
+    /// Path to the YAML or JSON description of the desired state
+    state: Utf8PathBuf,
+    /// Initiate a reboot after the operation is complete
+    #[clap(long, short = 'r')]
+    reboot: bool,
+    /// Exit after printing the changes, without deploying
+    #[clap(long, short = 'n')]
+    dry_run: bool,
+    /// Allow removing or replacing protected packages
+    #[clap(long)]
+    allow_protected: bool,
+    /// Prevent automatic deployment finalization on shutdown
+    #[clap(long)]
+    lock_finalization: bool,
+}
+
+/// Whether the base `base` of the desired state differs from that of `origin`.
+fn base_differs(origin: &TreeComposeConfig, base: &str) -> bool {
+    if let Some(current) = origin.derive.container_image_reference.as_deref() {
+        match (
+            OstreeImageReference::try_from(base),
+            OstreeImageReference::try_from(current),
+        ) {
+            (Ok(base), Ok(current)) => base.to_string() != current.to_string(),
+            _ => true,
+        }
+    } else {
+        let current = origin.derive.base_refspec.as_deref().unwrap_or_default();
+        // As with `rebase`, the remote may be omitted to keep the current one.
+        match (base.contains(':'), current.split_once(':')) {
+            (false, Some((_, current_ref))) => base != current_ref,
+            _ => base != current,
+        }
+    }
+}
+
+pub(crate) fn apply_state_entrypoint(args: &Vec<String>) -> Result<()> {
+    let opt = Opt::parse_from(args.iter());
+    let buf =
+        std::fs::read_to_string(&opt.state).with_context(|| format!("Reading {}", opt.state))?;
+    let state = DesiredState::parse(&buf).with_context(|| format!("Parsing {}", opt.state))?;
+
+    let client = &mut crate::client::ClientConnection::new()?;
+    let previous_deployment = client
+        .get_os_proxy()
+        .cached_property("DefaultDeployment")
+        .ok_or_else(|| anyhow!("Failed to find default-deployment property"))?;
+    let id = glib::VariantDict::new(Some(&previous_deployment))
+        .lookup::<String>("id")?
+        .ok_or_else(|| anyhow!("Default deployment has no id"))?;
+    let origin = client.get_os_proxy().call_sync(
+        "GetDeploymentOrigin",
+        Some(&Variant::from_tuple(&[id.to_variant()])),
+        gio::DBusCallFlags::NONE,
+        -1,
+        gio::NONE_CANCELLABLE,
+    )?;
+    let (origin,) = origin
+        .get::<(String,)>()
+        .ok_or_else(|| anyhow!("Invalid reply"))?;
+    let origin: TreeComposeConfig = serde_json::from_str(&origin)?;
+
+    let modifiers = glib::VariantDict::new(None);
+    let options = glib::VariantDict::new(None);
+    let rebase = state
+        .base
+        .as_deref()
+        .filter(|base| base_differs(&origin, base));
+    match rebase {
+        Some(base) => {
+            let base = crate::core::container_refspec_make_absolute(base)?;
+            modifiers.insert("set-refspec", &base.as_str())
+        }
+        None => options.insert("no-pull-base", &true),
+    }
+    modifiers.insert("ex-apply-state", &serde_json::to_string(&state)?.as_str());
+    options.insert("reboot", &opt.reboot);
+    options.insert("dry-run", &opt.dry_run);
+    options.insert("allow-protected", &opt.allow_protected);
+    options.insert("lock-finalization", &opt.lock_finalization);
+    let command_line = format!("rpm-ostree ex apply {}", opt.state);
+    options.insert("initiating-command-line", &command_line.as_str());
+    let params = Variant::from_tuple(&[modifiers.end(), options.end()]);
+    let reply = &client.get_os_proxy().call_sync(
+        "UpdateDeployment",
+        Some(&params),
+        gio::DBusCallFlags::NONE,
+        -1,
+        gio::NONE_CANCELLABLE,
+    )?;
+    let reply = reply
+        .get::<(String,)>()
+        .ok_or_else(|| anyhow!("Invalid reply"))?;
+    client.transaction_connect_progress_sync(reply.0.as_str())?;
+    if !opt.reboot && !opt.dry_run {
+        let new_deployment = client
+            .get_os_proxy()
+            .cached_property("DefaultDeployment")
+            .ok_or_else(|| anyhow!("Failed to find default-deployment property"))?;
+        if previous_deployment != new_deployment {
+            print_treepkg_diff("/");
+        }
+    }
+    Ok(())
+}
+
+/// Print the additions and removals from `old` to `new`, if any; returns
+/// whether there are some.
+fn print_changes<'a>(
+    what: &str,
+    old: impl IntoIterator<Item = &'a String>,
+    new: impl IntoIterator<Item = &'a String>,
+) -> bool {
+    let old: BTreeSet<&String> = old.into_iter().collect();
+    let new: BTreeSet<&String> = new.into_iter().collect();
+    let mut changes: Vec<String> = old.difference(&new).map(|s| format!("-{}", s)).collect();
+    changes.extend(new.difference(&old).map(|s| format!("+{}", s)));
+    if changes.is_empty() {
+        return false;
+    }
+    crate::ffi::output_message(&format!("{}: {}", what, changes.join(" ")));
+    true
+}
+
+/// Converge the derivation treefile `cfg` of an origin to the desired state
+/// `state`, given the current kernel arguments `kargs`.
+pub(crate) fn apply(
+    cfg: &mut TreeComposeConfig,
+    state: &str,
+    kargs: &str,
+    allow_protected: bool,
+) -> Result<crate::ffi::AppliedState> {
+    let mut state: DesiredState = serde_json::from_str(state)?;
+    state.validate()?;
+    let mut r = crate::ffi::AppliedState::default();
+
+    if let Some(packages) = state.packages {
+        let current = cfg.packages.take().unwrap_or_default();
+        r.changed |= print_changes("Layered packages", &current, &packages);
+        cfg.packages = Some(packages).filter(|p| !p.is_empty());
+    }
+
+    if let Some(removals) = state.override_remove {
+        let current = cfg.derive.override_remove.take().unwrap_or_default();
+        if !allow_protected {
+            let added: Vec<String> = removals.difference(&current).cloned().collect();
+            crate::daemon::check_protected_packages(&added)?;
+        }
+        r.changed |= print_changes("Removed base packages", &current, &removals);
+        cfg.derive.override_remove = Some(removals).filter(|p| !p.is_empty());
+    }
+
+    if let Some(mut replacements) = state.override_replace {
+        replacements.retain(|r| !r.is_empty());
+        let current = cfg.derive.override_replace.take().unwrap_or_default();
+        if !allow_protected {
+            let current: BTreeSet<&String> = current.iter().flat_map(|r| &r.packages).collect();
+            let added: Vec<String> = replacements
+                .iter()
+                .flat_map(|r| &r.packages)
+                .filter(|p| !current.contains(p))
+                .cloned()
+                .collect();
+            crate::daemon::check_protected_packages(&added)?;
+        }
+        let describe = |v: &[RemoteOverrideReplace]| -> Vec<String> {
+            v.iter()
+                .flat_map(|r| {
+                    r.packages
+                        .iter()
+                        .map(move |p| format!("{} ({})", p, r.from))
+                })
+                .collect()
+        };
+        r.changed |= print_changes(
+            "Replaced base packages",
+            &describe(&current),
+            &describe(&replacements),
+        );
+        cfg.derive.override_replace = Some(replacements).filter(|r| !r.is_empty());
+    }
+
+    if let Some(state_kargs) = state.kargs {
+        let current = cfg.derive.kargs.take().unwrap_or_default();
+        let old_append = current.append.unwrap_or_default();
+        let old_delete = current.delete.unwrap_or_default();
+        let new = crate::kargs::retrack(
+            kargs,
+            &old_append,
+            &old_delete,
+            &state_kargs.append,
+            &state_kargs.delete,
+        );
+        let new_str = new.join(" ");
+        let validation = crate::kargs::kargs_validate(kargs, &new_str);
+        if !validation.errors.is_empty() {
+            bail!("Invalid kernel arguments: {}", validation.errors.join("; "));
+        }
+        for warning in validation.warnings {
+            crate::ffi::output_message(&format!("warning: {}", warning));
+        }
+        let diff = crate::kargs::kargs_diff(kargs, &new_str);
+        if !diff.is_empty() {
+            crate::ffi::output_message(&format!("Kernel arguments: {}", diff.join(" ")));
+            r.kargs = new;
+            r.kargs_changed = true;
+        }
+        r.changed |=
+            r.kargs_changed || old_append != state_kargs.append || old_delete != state_kargs.delete;
+        let mut tracked = DeriveKargs::default();
+        tracked.append = Some(state_kargs.append).filter(|v| !v.is_empty());
+        tracked.delete = Some(state_kargs.delete).filter(|v| !v.is_empty());
+        tracked.profiles = current.profiles;
+        cfg.derive.kargs = Some(tracked).filter(|k| k != &DeriveKargs::default());
+    }
+
+    if let Some(state_initramfs) = state.initramfs {
+        let current = cfg.derive.initramfs.take().unwrap_or_default();
+        let mut new = current.clone();
+        if !state_initramfs.regenerate {
+            // As with `rpm-ostree initramfs --disable`
+            new = Default::default();
+        }
+        new.regenerate = state_initramfs.regenerate;
+        new.args = Some(state_initramfs.args).filter(|a| !a.is_empty());
+        new.etc = Some(state_initramfs.etc).filter(|e| !e.is_empty());
+        if current.regenerate != new.regenerate {
+            let verb = if new.regenerate {
+                "Enabled"
+            } else {
+                "Disabled"
+            };
+            crate::ffi::output_message(&format!("{} initramfs regeneration", verb));
+        }
+        print_changes(
+            "Initramfs arguments",
+            current.args.iter().flatten(),
+            new.args.iter().flatten(),
+        );
+        print_changes(
+            "Initramfs files of /etc",
+            current.etc.iter().flatten(),
+            new.etc.iter().flatten(),
+        );
+        r.changed |= new != current;
+        cfg.derive.initramfs = Some(new).filter(|i| i != &Default::default());
+    }
+
+    Ok(r)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        let state = DesiredState::parse(indoc! {"
+            packages: [vim, htop]
+            initramfs:
+              regenerate: true
+              etc: [/etc/crypttab/]
+        "})?;
+        assert_eq!(state.base, None);
+        assert_eq!(state.packages.unwrap().len(), 2);
+        assert_eq!(state.override_remove, None);
+        let initramfs = state.initramfs.unwrap();
+        assert_eq!(
+            initramfs.etc.into_iter().collect::<Vec<_>>(),
+            ["/etc/crypttab"]
+        );
+        assert!(DesiredState::parse("packages: [vim]\nfoo: bar\n").is_err());
+        assert!(DesiredState::parse("initramfs: {etc: [/usr/foo]}\n").is_err());
+        assert!(DesiredState::parse("initramfs: {args: [--foo]}\n").is_err());
+
+        let state = DesiredState::parse(indoc! {"
+            override-replace:
+              - from: {repo: updates-testing}
+                packages: [podman, crun]
+        "})?;
+        let replacements = state.override_replace.unwrap();
+        assert_eq!(replacements[0].from.to_string(), "repo=updates-testing");
+        assert_eq!(replacements[0].packages.len(), 2);
+        assert!(DesiredState::parse(indoc! {"
+            override-replace:
+              - from: {repo: updates-testing}
+                packages: [podman]
+              - from: {repo: fedora}
+                packages: [podman]
+        "})
+        .is_err());
+        assert!(DesiredState::parse(indoc! {"
+            override-remove: [podman]
+            override-replace:
+              - from: {repo: updates-testing}
+                packages: [podman]
+        "})
+        .is_err());
+        // The daemon validates the states it's passed as well
+        let mut cfg = TreeComposeConfig::default();
+        let state = r#"{"initramfs": {"etc": ["/usr/foo"], "regenerate": true}}"#;
+        assert!(apply(&mut cfg, state, "", true).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_base_differs() {
+        let mut origin = TreeComposeConfig::default();
+        origin.derive.base_refspec = Some("fedora:fedora/36/x86_64/silverblue".into());
+        assert!(!base_differs(&origin, "fedora:fedora/36/x86_64/silverblue"));
+        assert!(!base_differs(&origin, "fedora/36/x86_64/silverblue"));
+        assert!(base_differs(&origin, "fedora/37/x86_64/silverblue"));
+        assert!(base_differs(&origin, "other:fedora/36/x86_64/silverblue"));
+        let mut origin = TreeComposeConfig::default();
+        origin.derive.container_image_reference =
+            Some("ostree-unverified-registry:quay.io/exampleos/os:stable".into());
+        assert!(!base_differs(
+            &origin,
+            "ostree-unverified-registry:quay.io/exampleos/os:stable"
+        ));
+        assert!(base_differs(
+            &origin,
+            "ostree-unverified-registry:quay.io/exampleos/os:testing"
+        ));
+        assert!(base_differs(&origin, "fedora:fedora/36/x86_64/silverblue"));
+    }
+}
@@ -0,0 +1,199 @@
+//! Failures of the automatic update policy.  After a failure, automatic
+//! updates back off exponentially, rather than failing in the same way on
+//! every run of the timer, and the last failure is shown by `rpm-ostree
+//! status` until an automatic update succeeds again, so that a loop of
+//! failures doesn't go unnoticed.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::cxxrsutil::*;
+use crate::ffi::AutoUpdateFailure;
+use anyhow::{Context, Result};
+use chrono::prelude::*;
+use serde_derive::{Deserialize, Serialize};
+use std::path::Path;
+
+const STATE_PATH: &str = "/var/lib/rpm-ostree/autoupdate-failure.json";
+/// The backoff after the first failure; it doubles with each further one.
+const BACKOFF_BASE_SECS: i64 = 60 * 60;
+const BACKOFF_MAX_SECS: i64 = 7 * 24 * 60 * 60;
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct FailureState {
+    category: String,
+    message: String,
+    /// The number of consecutive failures
+    count: u32,
+    /// Unix timestamp of the last failure
+    timestamp: i64,
+}
+
+impl FailureState {
+    fn load(path: &Path) -> Result<Option<Self>> {
+        match std::fs::read_to_string(path) {
+            Ok(s) => Ok(Some(
+                serde_json::from_str(&s).with_context(|| format!("Parsing {}", path.display()))?,
+            )),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Reading {}", path.display())),
+        }
+    }
+
+    fn store(&self, path: &Path) -> Result<()> {
+        let buf = serde_json::to_vec(self)?;
+        std::fs::write(path, buf).with_context(|| format!("Writing {}", path.display()))
+    }
+
+    fn backoff_secs(&self) -> i64 {
+        let exp = self.count.saturating_sub(1).min(16);
+        (BACKOFF_BASE_SECS << exp).min(BACKOFF_MAX_SECS)
+    }
+
+    fn next_attempt(&self) -> i64 {
+        self.timestamp + self.backoff_secs()
+    }
+}
+
+fn format_timestamp(t: i64) -> String {
+    Local
+        .timestamp(t, 0)
+        .format("%a %Y-%m-%d %H:%M %:z")
+        .to_string()
+}
+
+fn record_failure(path: &Path, category: &str, message: &str, now: i64) -> Result<FailureState> {
+    let count = FailureState::load(path)?.map_or(0, |s| s.count);
+    let state = FailureState {
+        category: category.to_string(),
+        message: message.to_string(),
+        count: count.saturating_add(1),
+        timestamp: now,
+    };
+    state.store(path)?;
+    Ok(state)
+}
+
+fn backoff_reason(path: &Path, now: i64) -> Result<String> {
+    let state = match FailureState::load(path)? {
+        Some(s) if now < s.next_attempt() => s,
+        _ => return Ok(String::new()),
+    };
+    Ok(format!(
+        "Backing off after {} consecutive failures of automatic updates; next attempt after {}",
+        state.count,
+        format_timestamp(state.next_attempt())
+    ))
+}
+
+impl From<FailureState> for AutoUpdateFailure {
+    fn from(state: FailureState) -> Self {
+        Self {
+            next_attempt: state.next_attempt(),
+            backoff_secs: state.backoff_secs() as u64,
+            category: state.category,
+            message: state.message,
+            count: state.count,
+            timestamp: state.timestamp,
+        }
+    }
+}
+
+/// Record a failure of the automatic update policy with `message`, classified
+/// by the caller from the error domain and code as `category`.
+pub(crate) fn autoupdate_record_failure(
+    category: &str,
+    message: &str,
+) -> CxxResult<AutoUpdateFailure> {
+    let state = record_failure(
+        Path::new(STATE_PATH),
+        category,
+        message,
+        Utc::now().timestamp(),
+    )?;
+    Ok(state.into())
+}
+
+/// Record a success of the automatic update policy, ending any backoff.
+pub(crate) fn autoupdate_record_success() -> CxxResult<()> {
+    match std::fs::remove_file(STATE_PATH) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(anyhow::Error::new(e)
+            .context(format!("Removing {}", STATE_PATH))
+            .into()),
+    }
+}
+
+/// If the automatic update policy is backing off after failures, explain
+/// until when; empty otherwise.
+pub(crate) fn autoupdate_backoff_reason() -> CxxResult<String> {
+    Ok(backoff_reason(
+        Path::new(STATE_PATH),
+        Utc::now().timestamp(),
+    )?)
+}
+
+/// The last failure of the automatic update policy, if it hasn't succeeded
+/// since; its count is 0 otherwise.  The daemon exposes it over D-Bus.
+pub(crate) fn autoupdate_last_failure() -> CxxResult<AutoUpdateFailure> {
+    match FailureState::load(Path::new(STATE_PATH))? {
+        Some(state) => Ok(state.into()),
+        None => Ok(AutoUpdateFailure {
+            category: String::new(),
+            message: String::new(),
+            count: 0,
+            timestamp: 0,
+            next_attempt: 0,
+            backoff_secs: 0,
+        }),
+    }
+}
+
+/// Describe a failure of the automatic update policy for `rpm-ostree status`.
+pub(crate) fn autoupdate_failure_describe(
+    category: &str,
+    message: &str,
+    count: u32,
+    timestamp: i64,
+    next_attempt: i64,
+) -> String {
+    let message = message.lines().next().unwrap_or_default();
+    format!(
+        "{} ({} error, {} in a row): {}; next attempt after {}",
+        format_timestamp(timestamp),
+        category,
+        count,
+        message,
+        format_timestamp(next_attempt)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        let path = &td.path().join("failure.json");
+        let now = 1_600_000_000;
+        assert!(backoff_reason(path, now)?.is_empty());
+        let state = record_failure(path, "network", "Timeout was reached", now)?;
+        assert_eq!(state.count, 1);
+        assert_eq!(state.category, "network");
+        assert_eq!(state.backoff_secs(), BACKOFF_BASE_SECS);
+        assert!(!backoff_reason(path, now + 60)?.is_empty());
+        assert!(backoff_reason(path, now + BACKOFF_BASE_SECS)?.is_empty());
+        let state = record_failure(path, "depsolve", "Packages not found: foo", now)?;
+        assert_eq!(state.count, 2);
+        assert_eq!(state.category, "depsolve");
+        assert_eq!(state.backoff_secs(), 2 * BACKOFF_BASE_SECS);
+        for _ in 0..20 {
+            record_failure(path, "network", "Timeout was reached", now)?;
+        }
+        let state = FailureState::load(path)?.unwrap();
+        assert_eq!(state.count, 22);
+        assert_eq!(state.backoff_secs(), BACKOFF_MAX_SECS);
+        Ok(())
+    }
+}
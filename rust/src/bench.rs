@@ -0,0 +1,440 @@
+//! Microbenchmarks for core code paths, backing `rpm-ostree testutils bench`.
+//! Each benchmark runs against a synthetic fixture generated on the fly (so
+//! no network or booted system is needed) for at least `--min-time`, and we
+//! report operations per second.  Numbers are only comparable on the same
+//! machine; the point is to catch large regressions in CI, and to give users
+//! something concrete to attach to performance reports.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::cxxrsutil::*;
+use anyhow::{Context, Result};
+use clap::Parser;
+use ostree_ext::{gio, glib, ostree};
+use serde_derive::Serialize;
+use std::fmt::Write as FmtWrite;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Benchmark {
+    /// Importing an RPM into an ostree repo
+    Import,
+    /// Checking out a commit
+    Checkout,
+    /// Depsolving against an rpm-md repo
+    Depsolve,
+    /// Parsing an origin file
+    Origin,
+}
+
+#[derive(Debug, Parser)]
+pub(crate) struct BenchOpts {
+    /// Benchmarks to run (default: all)
+    #[clap(value_enum)]
+    benchmarks: Vec<Benchmark>,
+
+    /// Minimum time to spend in each benchmark, in seconds
+    #[clap(long, default_value = "1")]
+    min_time: f64,
+
+    /// Number of files in the synthetic package and commit
+    #[clap(long, default_value = "1000")]
+    files: u32,
+
+    /// Number of packages in the synthetic rpm-md repo
+    #[clap(long, default_value = "2000")]
+    packages: u32,
+
+    /// Use this RPM for the import benchmark instead of building one
+    #[clap(long)]
+    rpm: Option<PathBuf>,
+
+    /// Output results as JSON
+    #[clap(long)]
+    json: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct BenchResult {
+    name: &'static str,
+    fixture: String,
+    iterations: u64,
+    seconds: f64,
+    ops_per_sec: f64,
+}
+
+impl Benchmark {
+    fn name(self) -> &'static str {
+        match self {
+            Benchmark::Import => "import",
+            Benchmark::Checkout => "checkout",
+            Benchmark::Depsolve => "depsolve",
+            Benchmark::Origin => "origin",
+        }
+    }
+}
+
+/// Run `setup` then `run` until at least `min_time` was spent in `run`, and at
+/// least once.  Only `run` is timed.
+fn measure<T>(
+    name: &'static str,
+    fixture: String,
+    min_time: Duration,
+    mut setup: impl FnMut(u64) -> Result<T>,
+    mut run: impl FnMut(T) -> Result<()>,
+) -> Result<BenchResult> {
+    let mut iterations = 0;
+    let mut elapsed = Duration::ZERO;
+    while iterations == 0 || elapsed < min_time {
+        let input = setup(iterations)?;
+        let start = Instant::now();
+        run(input)?;
+        elapsed += start.elapsed();
+        iterations += 1;
+    }
+    let seconds = elapsed.as_secs_f64();
+    Ok(BenchResult {
+        name,
+        fixture,
+        iterations,
+        seconds,
+        ops_per_sec: iterations as f64 / seconds,
+    })
+}
+
+fn new_repo(path: &Path) -> Result<ostree::Repo> {
+    let repo = ostree::Repo::new_for_path(path);
+    repo.create(ostree::RepoMode::BareUser, gio::NONE_CANCELLABLE)
+        .with_context(|| format!("Creating repo {:?}", path))?;
+    Ok(repo)
+}
+
+fn run_cmd(cmd: &mut Command) -> Result<()> {
+    let r = cmd.status().with_context(|| format!("Running {:?}", cmd))?;
+    if !r.success() {
+        anyhow::bail!("{:?} failed: {:?}", cmd, r);
+    }
+    Ok(())
+}
+
+/// Build a noarch RPM containing `n_files` files of random data with `rpmbuild`.
+fn build_synthetic_rpm(tmpdir: &Path, n_files: u32) -> Result<PathBuf> {
+    let spec = tmpdir.join("rpmostree-bench.spec");
+    std::fs::write(
+        &spec,
+        indoc::formatdoc! {r#"
+            Name: rpmostree-bench
+            Version: 1.0
+            Release: 1
+            Summary: Synthetic package for rpm-ostree testutils bench
+            License: MIT
+            BuildArch: noarch
+
+            %description
+            %{{summary}}
+
+            %install
+            mkdir -p %{{buildroot}}/usr/share/rpmostree-bench
+            for i in $(seq {}); do
+              head -c 4096 /dev/urandom > %{{buildroot}}/usr/share/rpmostree-bench/$i
+            done
+
+            %files
+            /usr/share/rpmostree-bench
+            "#,
+            n_files
+        },
+    )?;
+    let topdir = tmpdir.join("rpmbuild");
+    run_cmd(
+        Command::new("rpmbuild")
+            .arg("--quiet")
+            .arg("-bb")
+            .arg("--define")
+            .arg(format!("_topdir {}", topdir.to_str().unwrap()))
+            .arg(&spec),
+    )?;
+    Ok(topdir.join("RPMS/noarch/rpmostree-bench-1.0-1.noarch.rpm"))
+}
+
+fn bench_import(
+    opts: &BenchOpts,
+    tmpdir: &Path,
+    min_time: Duration,
+) -> Result<Option<BenchResult>> {
+    let (rpm, fixture) = if let Some(rpm) = opts.rpm.as_ref() {
+        (rpm.clone(), format!("{:?}", rpm))
+    } else if Path::new("/usr/bin/rpmbuild").exists() {
+        let rpm = build_synthetic_rpm(tmpdir, opts.files).context("Building synthetic RPM")?;
+        (rpm, format!("{} files", opts.files))
+    } else {
+        eprintln!("Skipping import: rpmbuild not found (use --rpm)");
+        return Ok(None);
+    };
+    let rpm = rpm.to_str().unwrap();
+    let r = measure(
+        Benchmark::Import.name(),
+        fixture,
+        min_time,
+        // Use a new repo each time, otherwise later imports only find existing objects
+        |i| new_repo(&tmpdir.join(format!("import-repo-{}", i))),
+        |repo| {
+            crate::ffi::bench_import_rpm(repo.reborrow_cxx(), rpm)?;
+            Ok(())
+        },
+    )?;
+    Ok(Some(r))
+}
+
+fn bench_checkout(opts: &BenchOpts, tmpdir: &Path, min_time: Duration) -> Result<BenchResult> {
+    let cancellable = gio::NONE_CANCELLABLE;
+    let tree = tmpdir.join("checkout-tree");
+    for i in 0..opts.files {
+        // Spread the files over some directories, like a real tree
+        let d = tree.join(format!("usr/share/d{}", i % 32));
+        std::fs::create_dir_all(&d)?;
+        std::fs::write(d.join(format!("f{}", i)), format!("synthetic file {}\n", i))?;
+    }
+    let repo_path = tmpdir.join("checkout-repo");
+    let repo = new_repo(&repo_path)?;
+    run_cmd(
+        Command::new("ostree")
+            .arg(format!("--repo={}", repo_path.to_str().unwrap()))
+            .args(&["commit", "--consume", "--no-xattrs", "-b", "bench"])
+            .arg(format!("--tree=dir={}", tree.to_str().unwrap())),
+    )?;
+    let rev = repo.require_rev("bench")?;
+    let destdir = tmpdir.join("checkouts");
+    std::fs::create_dir(&destdir)?;
+    let destdir = std::fs::File::open(&destdir)?;
+    let checkout_opts = ostree::RepoCheckoutAtOptions {
+        mode: ostree::RepoCheckoutMode::User,
+        ..Default::default()
+    };
+    measure(
+        Benchmark::Checkout.name(),
+        format!("{} files", opts.files),
+        min_time,
+        |i| Ok(format!("co-{}", i)),
+        |dest| {
+            repo.checkout_at(
+                Some(&checkout_opts),
+                destdir.as_raw_fd(),
+                dest,
+                rev.as_str(),
+                cancellable,
+            )?;
+            Ok(())
+        },
+    )
+}
+
+/// Generate rpm-md metadata for `n` packages, where `bench-i` requires
+/// `bench-2i+1` and `bench-2i+2`; installing `bench-0` pulls in all of them.
+fn synthetic_primary_xml(n: u32) -> String {
+    let mut r = String::new();
+    writeln!(
+        r,
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<metadata xmlns="http://linux.duke.edu/metadata/common" xmlns:rpm="http://linux.duke.edu/metadata/rpm" packages="{}">"#,
+        n
+    )
+    .unwrap();
+    for i in 0..n {
+        let requires = [2 * i + 1, 2 * i + 2]
+            .iter()
+            .filter(|&&j| j < n)
+            .map(|j| format!(r#"<rpm:entry name="bench-cap-{}"/>"#, j))
+            .collect::<String>();
+        writeln!(
+            r,
+            r#"<package type="rpm">
+  <name>bench-{i}</name>
+  <arch>noarch</arch>
+  <version epoch="0" ver="1.0" rel="1"/>
+  <checksum type="sha256" pkgid="YES">{i:064x}</checksum>
+  <summary>Synthetic package {i}</summary>
+  <description>Synthetic package {i}</description>
+  <location href="bench-{i}-1.0-1.noarch.rpm"/>
+  <format>
+    <rpm:license>MIT</rpm:license>
+    <rpm:provides><rpm:entry name="bench-{i}" flags="EQ" epoch="0" ver="1.0" rel="1"/><rpm:entry name="bench-cap-{i}"/></rpm:provides>
+    <rpm:requires>{requires}</rpm:requires>
+  </format>
+</package>"#
+        )
+        .unwrap();
+    }
+    r.push_str("</metadata>\n");
+    r
+}
+
+fn bench_depsolve(opts: &BenchOpts, tmpdir: &Path, min_time: Duration) -> Result<BenchResult> {
+    let repodata = tmpdir.join("depsolve-repo/repodata");
+    std::fs::create_dir_all(&repodata)?;
+    let repomd = repodata.join("repomd.xml");
+    let primary = repodata.join("primary.xml");
+    std::fs::write(
+        &repomd,
+        indoc::indoc! {r#"
+        <?xml version="1.0" encoding="UTF-8"?>
+        <repomd xmlns="http://linux.duke.edu/metadata/repo" xmlns:rpm="http://linux.duke.edu/metadata/rpm">
+          <revision>0</revision>
+          <data type="primary">
+            <location href="repodata/primary.xml"/>
+          </data>
+        </repomd>
+        "#},
+    )?;
+    std::fs::write(&primary, synthetic_primary_xml(opts.packages))?;
+    let cachedir = tmpdir.join("depsolve-cache");
+    std::fs::create_dir(&cachedir)?;
+    let pkgs = vec!["bench-0".to_string()];
+    measure(
+        Benchmark::Depsolve.name(),
+        format!("{} packages", opts.packages),
+        min_time,
+        |_| Ok(()),
+        |()| {
+            let n = crate::ffi::bench_depsolve(
+                repomd.to_str().unwrap(),
+                primary.to_str().unwrap(),
+                cachedir.to_str().unwrap(),
+                &pkgs,
+            )?;
+            if n != opts.packages {
+                anyhow::bail!(
+                    "Expected {} packages in transaction, found {}",
+                    opts.packages,
+                    n
+                );
+            }
+            Ok(())
+        },
+    )
+}
+
+/// An origin using most of the features clients set.
+const ORIGIN_FIXTURE: &str = indoc::indoc! {"
+    [origin]
+    baserefspec=fedora:fedora/x86_64/silverblue
+    override-commit=41af286dc0b172ed2f1ca934fd2278de4a1192302ffa07087cea2682e7d372e3
+
+    [rpmostree]
+    regenerate-initramfs=true
+    initramfs-args=-I;/etc/foobar.conf;
+    initramfs-etc=/etc/cmdline.d/foobar.conf;
+
+    [packages]
+    requested=libvirt;fish;vim-enhanced;htop;podman-docker;
+    requested-local=4ed748ba060fce4571e7ef19f3f5ed6209f67dbac8327af0d38ea70b96d2f723:foo-1.2-3.x86_64;
+
+    [modules]
+    enable=foo:2.0;bar:rolling;
+    install=baz:next/development;
+
+    [overrides]
+    remove=docker;firefox;
+    replace=repo=foobar,systemd;repo=bazboo,kernel,kernel-core,kernel-modules;
+"};
+
+fn parse_origin(s: &str) -> Result<()> {
+    let kf = glib::KeyFile::new();
+    kf.load_from_data(s, glib::KeyFileFlags::KEEP_COMMENTS)?;
+    crate::origin::origin_to_treefile_inner(&kf)?;
+    Ok(())
+}
+
+fn bench_origin(min_time: Duration) -> Result<BenchResult> {
+    measure(
+        Benchmark::Origin.name(),
+        "synthetic".to_string(),
+        min_time,
+        |_| Ok(()),
+        |()| parse_origin(ORIGIN_FIXTURE),
+    )
+}
+
+fn print_results(results: &[BenchResult], json: bool) -> Result<()> {
+    if json {
+        let stdout = std::io::stdout();
+        serde_json::to_writer_pretty(stdout.lock(), results)?;
+        println!();
+        return Ok(());
+    }
+    for r in results {
+        println!(
+            "{:<10} {:>14} {:>8} iterations in {:>6.2}s {:>12.1} ops/sec",
+            r.name, r.fixture, r.iterations, r.seconds, r.ops_per_sec
+        );
+    }
+    Ok(())
+}
+
+pub(crate) fn run(opts: &BenchOpts) -> Result<()> {
+    if !(opts.min_time.is_finite() && opts.min_time >= 0.0) {
+        anyhow::bail!("Invalid value for --min-time: {}", opts.min_time);
+    }
+    let min_time = Duration::from_secs_f64(opts.min_time);
+    let benchmarks = if opts.benchmarks.is_empty() {
+        vec![
+            Benchmark::Import,
+            Benchmark::Checkout,
+            Benchmark::Depsolve,
+            Benchmark::Origin,
+        ]
+    } else {
+        opts.benchmarks.clone()
+    };
+    let tmpdir = tempfile::tempdir()?;
+    let mut results = Vec::new();
+    for b in benchmarks {
+        // Each benchmark gets its own subdirectory so fixtures don't collide
+        let tmpdir = tmpdir.path().join(b.name());
+        std::fs::create_dir(&tmpdir)?;
+        let r = match b {
+            Benchmark::Import => bench_import(opts, &tmpdir, min_time)?,
+            Benchmark::Checkout => Some(bench_checkout(opts, &tmpdir, min_time)?),
+            Benchmark::Depsolve => Some(bench_depsolve(opts, &tmpdir, min_time)?),
+            Benchmark::Origin => Some(bench_origin(min_time)?),
+        };
+        results.extend(r);
+    }
+    print_results(&results, opts.json)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_origin_fixture() -> Result<()> {
+        parse_origin(ORIGIN_FIXTURE)
+    }
+
+    #[test]
+    fn test_synthetic_primary_xml() {
+        let xml = synthetic_primary_xml(5);
+        assert_eq!(xml.matches("<package ").count(), 5);
+        // bench-1 requires bench-3 and bench-4; bench-2 would require past the end
+        assert!(xml.contains(
+            r#"<rpm:requires><rpm:entry name="bench-cap-3"/><rpm:entry name="bench-cap-4"/></rpm:requires>"#
+        ));
+        assert!(xml.contains("<rpm:requires></rpm:requires>"));
+    }
+
+    #[test]
+    fn test_measure() -> Result<()> {
+        let mut n = 0;
+        let r = measure("test", String::new(), Duration::ZERO, Ok, |_| {
+            n += 1;
+            Ok(())
+        })?;
+        assert_eq!(r.iterations, 1);
+        assert_eq!(n, 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,308 @@
+//! Automatically roll back deployments which fail to boot.
+//!
+//! If `AutomaticRollbackBoots` is set in rpm-ostreed.conf, a deployment is on
+//! trial until one of its boots reaches `boot-complete.target`, which is where
+//! health checks hook in, like for `systemd-bless-boot.service`.
+//! `rpm-ostree-boot-trial.service` counts the boots of the deployment on trial
+//! and `rpm-ostree-boot-complete.service` marks it as good.  Once the
+//! configured number of boots failed, the staged deployment, if any, is
+//! removed and the daemon rolls back to the previous deployment and reboots;
+//! the failure is recorded in the history of the deployment.
+//!
+//! This relies on userspace coming up far enough to run the service; a
+//! deployment which doesn't get there needs to be rolled back manually.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::deployment_generate_id_impl;
+use anyhow::{anyhow, Context, Result};
+use cap_std::fs::Dir;
+use cap_std_ext::cap_std;
+use cap_std_ext::dirext::CapStdExtDirExt;
+use clap::Parser;
+use glib::prelude::*;
+use ostree_ext::{gio, glib, ostree};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::io::Read;
+use systemd::journal;
+
+/// State directory, shared with other daemon state.
+const STATE_DIR: &str = "/var/lib/rpm-ostree";
+/// File recording the deployment on trial and its boots.
+const STATE_FILE: &str = "boot-trial.json";
+const CONFIG_PATH: &str = "/etc/rpm-ostreed.conf";
+const CONFIG_GROUP: &str = "Daemon";
+const CONFIG_KEY: &str = "AutomaticRollbackBoots";
+/// The kernel-provided identifier for the current boot.
+const BOOT_ID_PATH: &str = "/proc/sys/kernel/random/boot_id";
+/// msg systemd emits when a unit failed
+const UNIT_FAILED_MSG: &str = "d9b373ed55a64feb8242e02dbe79a49c";
+
+#[derive(Debug, Parser)]
+#[clap(name = "rpm-ostree boot-trial", rename_all = "kebab-case")]
+enum Opts {
+    /// Count a boot of the deployment on trial, rolling back if it failed too often
+    Begin,
+    /// Mark the booted deployment as good
+    Complete,
+}
+
+/// A deployment which didn't complete a boot yet.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+struct Trial {
+    deployment: String,
+    /// The IDs of the boots of the deployment, in the format of `_BOOT_ID`.
+    boots: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+struct TrialState {
+    /// The last deployment which completed a boot.
+    good: Option<String>,
+    trial: Option<Trial>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Verdict {
+    /// The booted deployment completed a boot before.
+    Good,
+    /// The booted deployment is on trial, in the given boot.
+    Trial(usize),
+    /// The booted deployment failed the given boots; roll it back.
+    Failed(Vec<String>),
+}
+
+impl TrialState {
+    /// Record the boot `boot_id` of `deployment`, which is rolled back once
+    /// `max_failed` of its boots failed.
+    fn begin(&mut self, deployment: &str, boot_id: &str, max_failed: u32) -> Verdict {
+        // Trust the deployment booted when the automatic rollback is enabled
+        let good = self.good.get_or_insert_with(|| deployment.to_string());
+        if good.as_str() == deployment {
+            self.trial = None;
+            return Verdict::Good;
+        }
+        if self.trial.as_ref().map(|t| t.deployment != deployment) != Some(false) {
+            self.trial = Some(Trial {
+                deployment: deployment.to_string(),
+                boots: Vec::new(),
+            });
+        }
+        let trial = self.trial.as_mut().unwrap();
+        // The service may run again in the same boot
+        trial.boots.retain(|b| b != boot_id);
+        if trial.boots.len() >= max_failed as usize {
+            return Verdict::Failed(self.trial.take().unwrap().boots);
+        }
+        trial.boots.push(boot_id.to_string());
+        Verdict::Trial(trial.boots.len())
+    }
+
+    /// Mark `deployment` as good; returns whether it was on trial.
+    fn complete(&mut self, deployment: &str) -> bool {
+        let trial = self.trial.take();
+        self.good = Some(deployment.to_string());
+        trial.map(|t| t.deployment == deployment).unwrap_or(false)
+    }
+}
+
+/// The number of failed boots after which to roll back, or 0 if disabled.
+fn max_failed_boots() -> Result<u32> {
+    let kf = glib::KeyFile::new();
+    if let Err(e) = kf.load_from_file(CONFIG_PATH, glib::KeyFileFlags::NONE) {
+        if e.matches(glib::FileError::Noent) {
+            return Ok(0);
+        }
+        return Err(e).with_context(|| format!("Loading {}", CONFIG_PATH));
+    }
+    if !kf.has_key(CONFIG_GROUP, CONFIG_KEY).unwrap_or(false) {
+        return Ok(0);
+    }
+    let n = kf
+        .uint64(CONFIG_GROUP, CONFIG_KEY)
+        .with_context(|| format!("Parsing {}", CONFIG_KEY))?;
+    Ok(n.try_into().unwrap_or(u32::MAX))
+}
+
+fn load_state(statedir: &Dir) -> Result<TrialState> {
+    let mut content = String::new();
+    match statedir.open_optional(STATE_FILE)? {
+        Some(mut f) => f.read_to_string(&mut content)?,
+        None => return Ok(Default::default()),
+    };
+    match serde_json::from_str(&content) {
+        Ok(s) => Ok(s),
+        Err(e) => {
+            eprintln!("Ignoring invalid {}: {}", STATE_FILE, e);
+            Ok(Default::default())
+        }
+    }
+}
+
+fn store_state(statedir: &Dir, state: &TrialState) -> Result<()> {
+    statedir.atomic_replace_with(STATE_FILE, |w| -> Result<_> {
+        Ok(serde_json::to_writer(w, state)?)
+    })?;
+    Ok(())
+}
+
+/// The units which failed during the given boot.
+fn failed_units(boot_id: &str) -> Result<BTreeSet<String>> {
+    let mut j = journal::OpenOptions::default()
+        .system(true)
+        .local_only(true)
+        .runtime_only(false)
+        .open()?;
+    j.match_add("MESSAGE_ID", UNIT_FAILED_MSG)?;
+    j.match_add("_BOOT_ID", boot_id)?;
+    let mut r = BTreeSet::new();
+    while let Some(rec) = j.next_entry()? {
+        if let Some(unit) = rec.get("UNIT") {
+            r.insert(unit.clone());
+        }
+    }
+    Ok(r)
+}
+
+fn failure_reason(boots: &[String]) -> String {
+    let mut units = BTreeSet::new();
+    for boot in boots {
+        match failed_units(boot) {
+            Ok(u) => units.extend(u),
+            Err(e) => eprintln!("Failed to query failed units of boot {}: {}", boot, e),
+        }
+    }
+    let mut reason = format!(
+        "boot-complete.target not reached in {} boot(s)",
+        boots.len()
+    );
+    if !units.is_empty() {
+        let units: Vec<_> = units.into_iter().collect();
+        reason.push_str(&format!("; failed units: {}", units.join(", ")));
+    }
+    reason
+}
+
+/// Ask the daemon to remove the staged deployment, roll back and reboot.
+fn rollback(sysroot: &ostree::Sysroot) -> Result<()> {
+    let client = &mut crate::client::ClientConnection::new()?;
+    if sysroot.staged_deployment().is_some() {
+        let params = glib::Variant::from_tuple(&[vec!["pending-deploy"].to_variant()]);
+        let reply = &client.get_os_proxy().call_sync(
+            "Cleanup",
+            Some(&params),
+            gio::DBusCallFlags::NONE,
+            -1,
+            gio::NONE_CANCELLABLE,
+        )?;
+        let reply = reply
+            .get::<(String,)>()
+            .ok_or_else(|| anyhow!("Invalid reply"))?;
+        client.transaction_connect_progress_sync(reply.0.as_str())?;
+    }
+    let options = glib::VariantDict::new(None);
+    options.insert("reboot", &true);
+    let params = glib::Variant::from_tuple(&[options.end()]);
+    let reply = &client.get_os_proxy().call_sync(
+        "Rollback",
+        Some(&params),
+        gio::DBusCallFlags::NONE,
+        -1,
+        gio::NONE_CANCELLABLE,
+    )?;
+    let reply = reply
+        .get::<(String,)>()
+        .ok_or_else(|| anyhow!("Invalid reply"))?;
+    client.transaction_connect_progress_sync(reply.0.as_str())
+}
+
+/// Main entrypoint, run once per boot by each of the services.
+pub fn entrypoint(args: &[&str]) -> Result<()> {
+    let opts = Opts::parse_from(args.iter().skip(1));
+    let max_failed = max_failed_boots()?;
+    if max_failed == 0 {
+        return Ok(());
+    }
+    let sysroot = &ostree::Sysroot::new_default();
+    sysroot.load(gio::NONE_CANCELLABLE)?;
+    let booted = &sysroot.require_booted_deployment()?;
+    let id = deployment_generate_id_impl(booted);
+    let statedir = Dir::open_ambient_dir(STATE_DIR, cap_std::ambient_authority())?;
+    let mut state = load_state(&statedir)?;
+
+    match opts {
+        Opts::Complete => {
+            if state.complete(&id) {
+                println!("Deployment {} completed its boot", id);
+            }
+            store_state(&statedir, &state)
+        }
+        Opts::Begin => {
+            let boot_id = std::fs::read_to_string(BOOT_ID_PATH)
+                .with_context(|| format!("Reading {}", BOOT_ID_PATH))?;
+            let boot_id = boot_id.trim().replace('-', "");
+            let verdict = state.begin(&id, &boot_id, max_failed);
+            // Store the state first so that a failed rollback isn't retried in a loop
+            store_state(&statedir, &state)?;
+            match verdict {
+                Verdict::Good => Ok(()),
+                Verdict::Trial(n) => {
+                    println!(
+                        "Deployment {} is on trial: boot {} of {}",
+                        id, n, max_failed
+                    );
+                    Ok(())
+                }
+                Verdict::Failed(boots) => {
+                    let reason = failure_reason(&boots);
+                    println!("Rolling back deployment {}: {}", id, reason);
+                    let failure = glib::VariantDict::new(None);
+                    failure.insert("reason", &reason.as_str());
+                    failure.insert("failed-boots", &(boots.len() as u32));
+                    failure.insert("timestamp", &(chrono::Utc::now().timestamp() as u64));
+                    if let Err(e) =
+                        crate::history::history_record_boot_failure(sysroot, booted, &failure.end())
+                    {
+                        eprintln!("Failed to record boot failure in history: {:#}", e);
+                    }
+                    rollback(sysroot)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trial() {
+        let mut state = TrialState::default();
+        // The deployment booted first is trusted
+        assert_eq!(state.begin("a", "boot1", 2), Verdict::Good);
+        assert_eq!(state.begin("b", "boot2", 2), Verdict::Trial(1));
+        // Running again in the same boot doesn't count it twice
+        assert_eq!(state.begin("b", "boot2", 2), Verdict::Trial(1));
+        assert_eq!(state.begin("b", "boot3", 2), Verdict::Trial(2));
+        assert_eq!(
+            state.begin("b", "boot4", 2),
+            Verdict::Failed(vec!["boot2".into(), "boot3".into()])
+        );
+        assert_eq!(state.trial, None);
+        assert_eq!(state.begin("a", "boot5", 2), Verdict::Good);
+
+        // A completed boot ends the trial
+        assert_eq!(state.begin("c", "boot6", 2), Verdict::Trial(1));
+        assert!(state.complete("c"));
+        assert_eq!(state.good.as_deref(), Some("c"));
+        assert!(!state.complete("c"));
+        assert_eq!(state.begin("c", "boot7", 2), Verdict::Good);
+
+        // Booting another deployment starts a new trial
+        assert_eq!(state.begin("d", "boot8", 2), Verdict::Trial(1));
+        assert_eq!(state.begin("e", "boot9", 2), Verdict::Trial(1));
+        assert_eq!(state.trial.as_ref().unwrap().deployment, "e");
+    }
+}
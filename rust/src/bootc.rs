@@ -0,0 +1,190 @@
+//! Interoperability with bootc, which manages the same ostree deployments as
+//! we do on hosts installed from a bootable container image with
+//! `bootc install`.  There, we either hand off plain upgrades and rebases to
+//! bootc, or keep doing them ourselves while preserving its metadata; see
+//! `BootcInterop` in rpm-ostreed.conf(5).
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::cxxrsutil::*;
+use anyhow::{anyhow, bail, Context, Result};
+use cap_std::fs::Dir;
+use cap_std_ext::cap_std;
+use cap_std_ext::dirext::CapStdExtDirExt;
+use ostree_ext::container::{OstreeImageReference, SignatureSource, Transport};
+use ostree_ext::{gio, ostree};
+use std::process::Command;
+
+/// The bootc binary, relative to the root.
+const BOOTC_BIN: &str = "usr/bin/bootc";
+/// The state directory `bootc install` creates in the physical root.
+const BOOTC_STATE_DIR: &str = "sysroot/ostree/bootc";
+
+fn is_managed(rootfs: &Dir) -> Result<bool> {
+    Ok(rootfs.try_exists(BOOTC_BIN)? && rootfs.try_exists(BOOTC_STATE_DIR)?)
+}
+
+/// Whether the host is managed by bootc.
+pub(crate) fn bootc_is_managed() -> CxxResult<bool> {
+    let rootfs = Dir::open_ambient_dir("/", cap_std::ambient_authority())?;
+    Ok(is_managed(&rootfs)?)
+}
+
+/// Whether the deployment has local changes, which bootc can't carry over, or
+/// isn't from a container image at all.
+fn has_local_changes(deployment: &ostree::Deployment) -> Result<bool> {
+    let origin = deployment.origin().ok_or_else(|| {
+        anyhow!(
+            "No origin for deployment {}",
+            crate::deployment_generate_id_impl(deployment)
+        )
+    })?;
+    let tf = crate::origin::origin_to_treefile_inner(&origin)?;
+    Ok(tf.may_require_local_assembly() || tf.parsed.derive.base_refspec.is_some())
+}
+
+/// Whether bootc can take over from the booted and the default deployments,
+/// i.e. both of them are plain container image deployments.
+fn can_delegate() -> Result<bool> {
+    let sysroot = &ostree::Sysroot::new_default();
+    sysroot.load(gio::NONE_CANCELLABLE)?;
+    let booted = sysroot.require_booted_deployment()?;
+    if has_local_changes(&booted)? {
+        return Ok(false);
+    }
+    match sysroot.deployments().first() {
+        Some(default) => has_local_changes(default).map(|r| !r),
+        None => Ok(true),
+    }
+}
+
+/// The arguments of `bootc switch` to `imgref`, or `None` if bootc can't
+/// handle it.
+fn switch_args(imgref: &OstreeImageReference, reboot: bool) -> Option<Vec<String>> {
+    let mut args = vec!["switch".to_string()];
+    match imgref.sigverify {
+        SignatureSource::ContainerPolicy => args.push("--enforce-container-sigpolicy".into()),
+        SignatureSource::ContainerPolicyAllowInsecure => {}
+        SignatureSource::OstreeRemote(_) => return None,
+    }
+    let transport = match imgref.imgref.transport {
+        Transport::Registry => "registry",
+        Transport::OciDir => "oci",
+        Transport::OciArchive => "oci-archive",
+        Transport::ContainerStorage => "containers-storage",
+        _ => return None,
+    };
+    args.extend(["--transport".to_string(), transport.to_string()]);
+    if reboot {
+        args.push("--apply".into());
+    }
+    args.push(imgref.imgref.name.clone());
+    Some(args)
+}
+
+/// Unlike us, bootc can't be driven through polkit by unprivileged users;
+/// refuse clearly rather than have it fail.
+fn require_root(command: &str) -> Result<()> {
+    if !nix::unistd::getuid().is_root() {
+        bail!(
+            "On this host, `rpm-ostree {}` is delegated to bootc (BootcInterop=delegate), \
+             which must be run as root",
+            command
+        );
+    }
+    Ok(())
+}
+
+fn run_bootc(args: &[String]) -> Result<()> {
+    crate::ffi::output_message(&format!("Delegating to: bootc {}", args.join(" ")));
+    let status = Command::new("bootc")
+        .args(args)
+        .status()
+        .context("Running bootc")?;
+    if !status.success() {
+        return Err(anyhow!("bootc {}: {}", args[0], status));
+    }
+    Ok(())
+}
+
+/// Run `bootc upgrade` for `rpm-ostree upgrade`, unless the deployments have
+/// local changes; returns whether it did.
+pub(crate) fn bootc_delegate_upgrade(reboot: bool) -> CxxResult<bool> {
+    if !can_delegate()? {
+        return Ok(false);
+    }
+    require_root("upgrade")?;
+    let mut args = vec!["upgrade".to_string()];
+    if reboot {
+        args.push("--apply".into());
+    }
+    run_bootc(&args)?;
+    Ok(true)
+}
+
+/// Run `bootc switch` for `rpm-ostree rebase` to the container image
+/// reference `imgref`, unless the deployments have local changes or bootc
+/// doesn't support the reference; returns whether it did.
+pub(crate) fn bootc_delegate_switch(imgref: &str, reboot: bool) -> CxxResult<bool> {
+    let imgref = OstreeImageReference::try_from(imgref)?;
+    let args = match switch_args(&imgref, reboot) {
+        Some(args) => args,
+        None => return Ok(false),
+    };
+    if !can_delegate()? {
+        return Ok(false);
+    }
+    require_root("rebase")?;
+    run_bootc(&args)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cap_std_ext::cap_tempfile;
+
+    #[test]
+    fn test_is_managed() -> Result<()> {
+        let td = cap_tempfile::tempdir(cap_std::ambient_authority())?;
+        assert!(!is_managed(&td)?);
+        td.create_dir_all("usr/bin")?;
+        td.write(BOOTC_BIN, "")?;
+        assert!(!is_managed(&td)?);
+        td.create_dir_all(BOOTC_STATE_DIR)?;
+        assert!(is_managed(&td)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_switch_args() -> Result<()> {
+        let imgref: OstreeImageReference =
+            "ostree-unverified-registry:quay.io/example/os:stable".try_into()?;
+        assert_eq!(
+            switch_args(&imgref, true).unwrap(),
+            [
+                "switch",
+                "--transport",
+                "registry",
+                "--apply",
+                "quay.io/example/os:stable"
+            ]
+        );
+        let imgref: OstreeImageReference =
+            "ostree-image-signed:oci:/var/lib/images/os".try_into()?;
+        assert_eq!(
+            switch_args(&imgref, false).unwrap(),
+            [
+                "switch",
+                "--enforce-container-sigpolicy",
+                "--transport",
+                "oci",
+                "/var/lib/images/os"
+            ]
+        );
+        let imgref: OstreeImageReference =
+            "ostree-remote-registry:fedora:quay.io/fedora/fedora-coreos:stable".try_into()?;
+        assert!(switch_args(&imgref, false).is_none());
+        Ok(())
+    }
+}
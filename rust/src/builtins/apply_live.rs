@@ -15,16 +15,16 @@ struct Opts {
     /// Target provided commit instead of pending deployment
     #[clap(long)]
     target: Option<String>,
-    /// Reset back to booted commit
+    /// Revert the live-applied changes, back to the booted commit
     #[clap(long)]
     reset: bool,
 
-    /// Allow replacement of packages/files (default is pure additive)
+    /// Remove files even if running processes hold them
     #[clap(long)]
     allow_replacement: bool,
 }
 
-fn get_args_variant(sysroot: &ostree::Sysroot, opts: &Opts) -> Result<glib::Variant> {
+fn get_args_variant(opts: &Opts) -> Result<glib::Variant> {
     let r = glib::VariantDict::new(None);
 
     if let Some(target) = opts.target.as_ref() {
@@ -33,10 +33,7 @@ fn get_args_variant(sysroot: &ostree::Sysroot, opts: &Opts) -> Result<glib::Vari
         }
         r.insert(live::OPT_TARGET, &target.as_str());
     } else if opts.reset {
-        let booted = sysroot.require_booted_deployment()?;
-        // Unwrap safety: This can't return NULL
-        let csum = booted.csum().expect("csum");
-        r.insert(live::OPT_TARGET, &csum.as_str());
+        r.insert(live::OPT_RESET, &true);
     }
 
     if opts.allow_replacement {
@@ -47,12 +44,22 @@ fn get_args_variant(sysroot: &ostree::Sysroot, opts: &Opts) -> Result<glib::Vari
 }
 
 pub(crate) fn applylive_entrypoint(args: &Vec<String>) -> CxxResult<()> {
-    let opts = &Opts::parse_from(args.iter());
+    let opts = &crate::completion::parse_args::<Opts>(args)?;
     let client = &mut crate::client::ClientConnection::new()?;
     let sysroot = &ostree::Sysroot::new_default();
     sysroot.load(gio::NONE_CANCELLABLE)?;
 
-    let args = get_args_variant(sysroot, opts)?;
+    let args = get_args_variant(opts)?;
+    // What a reset reverts, to show it afterwards
+    let live_commit = if opts.reset {
+        let repo = &sysroot.repo().unwrap();
+        let booted = &sysroot.require_booted_deployment()?;
+        live::get_live_state(repo, booted)?
+            .map(|s| s.commit)
+            .filter(|c| !c.is_empty())
+    } else {
+        None
+    };
 
     let params = Variant::from_tuple(&[args]);
     let reply = &client.get_os_ex_proxy().call_sync(
@@ -66,7 +73,28 @@ pub(crate) fn applylive_entrypoint(args: &Vec<String>) -> CxxResult<()> {
         .get::<(String,)>()
         .ok_or_else(|| anyhow!("Invalid reply {:?}, expected (s)", reply.type_()))?;
     client.transaction_connect_progress_sync(txn_address.0.as_str())?;
-    applylive_finish(sysroot.reborrow_cxx())?;
+    if opts.reset {
+        applylive_reset_finish(sysroot, live_commit.as_deref())?;
+    } else {
+        applylive_finish(sysroot.reborrow_cxx())?;
+    }
+    Ok(())
+}
+
+// Postprocessing after a reset; print the rpmdb diff which was reverted.
+fn applylive_reset_finish(sysroot: &ostree::Sysroot, live_commit: Option<&str>) -> Result<()> {
+    sysroot.load_if_changed(gio::NONE_CANCELLABLE)?;
+    let repo = &sysroot.repo().unwrap();
+    let booted = &sysroot.require_booted_deployment()?;
+    let booted_commit = booted.csum().expect("csum");
+    if let Some(live_commit) = live_commit {
+        cxx::let_cxx_string!(from = live_commit);
+        cxx::let_cxx_string!(to = booted_commit.as_str());
+        crate::ffi::rpmdb_diff(repo.reborrow_cxx(), &from, &to, false)
+            .map_err(anyhow::Error::msg)?
+            .print();
+    }
+    crate::ffi::output_message("Successfully reset running filesystem tree to booted deployment.");
     Ok(())
 }
 
@@ -0,0 +1,355 @@
+//! CLI handler for `rpm-ostree audit-log`.
+//!
+//! The daemon logs a journal message with stable `MESSAGE_ID`s when a
+//! transaction starts and when it finishes, see `on_active_txn_changed()` and
+//! `transaction_log_finished()`.  Both carry the transaction's D-Bus address,
+//! which is unique to it, in `TRANSACTION_ADDRESS`; this is what we use to
+//! join them into a single entry.  A transaction which started but has no
+//! finish message was interrupted, e.g. the daemon was killed; the daemon
+//! logs another message when it cleans up after it on its next start.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use anyhow::Result;
+use chrono::prelude::*;
+use clap::Parser;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::Write;
+use systemd::journal::{self, JournalRecord};
+
+// msg the daemon emits when a transaction starts
+static TRANSACTION_STARTED_MSG: &str = "d5bea37a8fc84ff59dbcfd79177b7df8";
+// msg the daemon emits when a transaction finishes
+static TRANSACTION_FINISHED_MSG: &str = "e8c48a6ac6a34d4a8b9362d3c619bb66";
+// msg the daemon emits when it cleans up after an interrupted transaction
+static TRANSACTION_RECOVERED_MSG: &str = "1c466455f7c249b291524afb2f4b74b9";
+
+#[derive(Debug, Parser)]
+#[clap(name = "rpm-ostree audit-log", bin_name = "rpm-ostree audit-log")]
+#[clap(rename_all = "kebab-case")]
+#[clap(long_about = "Show the transactions which modified the system")]
+pub(crate) struct Opts {
+    /// Only show the last N transactions
+    #[clap(long, short = 'n')]
+    limit: Option<usize>,
+
+    /// Output JSON
+    #[clap(long)]
+    json: bool,
+}
+
+/// A transaction, as recorded in the journal.
+#[derive(Debug, Default, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+struct AuditEntry {
+    /// When the transaction started (or finished, if we only have that record), in
+    /// seconds since the epoch
+    timestamp: u64,
+    method: String,
+    /// The D-Bus object the method was invoked on
+    path: String,
+    /// The D-Bus method parameters, in GVariant text format
+    args: String,
+    client_uid: Option<u32>,
+    client_pid: Option<u32>,
+    client_id: Option<String>,
+    client_sd_unit: Option<String>,
+    /// Unset if the transaction was interrupted
+    success: Option<bool>,
+    error: Option<String>,
+    /// The default deployment once the transaction finished
+    deployment_checksum: Option<String>,
+    /// What the daemon cleaned up if the transaction was interrupted
+    recovery: Option<String>,
+}
+
+/// Get a field, treating empty values (the daemon's way to say unknown) as unset.
+fn field(record: &JournalRecord, key: &str) -> Option<String> {
+    record
+        .get(key)
+        .filter(|v| !v.is_empty())
+        .map(|v| v.to_string())
+}
+
+impl AuditEntry {
+    /// Fill in whatever isn't known yet from `record`.
+    fn update(&mut self, record: &JournalRecord) {
+        fn merge<T>(dest: &mut Option<T>, v: Option<T>) {
+            if dest.is_none() {
+                *dest = v;
+            }
+        }
+        for (dest, key) in [
+            (&mut self.method, "TRANSACTION_METHOD"),
+            (&mut self.path, "TRANSACTION_PATH"),
+            (&mut self.args, "TRANSACTION_ARGS"),
+        ] {
+            if dest.is_empty() {
+                *dest = field(record, key).unwrap_or_default();
+            }
+        }
+        let uid = field(record, "CLIENT_UID").and_then(|v| v.parse().ok());
+        merge(&mut self.client_uid, uid);
+        let pid = field(record, "CLIENT_PID").and_then(|v| v.parse().ok());
+        merge(&mut self.client_pid, pid);
+        merge(&mut self.client_id, field(record, "CLIENT_ID"));
+        merge(&mut self.client_sd_unit, field(record, "CLIENT_SD_UNIT"));
+        match record.get("MESSAGE_ID").map(|s| s.as_str()) {
+            Some(id) if id == TRANSACTION_FINISHED_MSG => {
+                self.success = Some(field(record, "TRANSACTION_SUCCESS").as_deref() == Some("1"));
+                self.error = field(record, "TRANSACTION_ERROR");
+                self.deployment_checksum = field(record, "DEPLOYMENT_CHECKSUM");
+            }
+            Some(id) if id == TRANSACTION_RECOVERED_MSG => {
+                self.recovery = field(record, "RECOVERY_ACTIONS");
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Join the start and finish records of transactions, given in journal
+/// order with their timestamps.
+fn entries_from_records(
+    records: impl IntoIterator<Item = (u64, JournalRecord)>,
+) -> Vec<AuditEntry> {
+    let mut entries: Vec<AuditEntry> = Vec::new();
+    // Index in `entries` of the transactions by address
+    let mut by_address = HashMap::new();
+    for (timestamp, record) in records {
+        let address = match field(&record, "TRANSACTION_ADDRESS") {
+            Some(a) => a,
+            // Logged by older versions, before we recorded these details
+            None => continue,
+        };
+        let i = *by_address.entry(address).or_insert_with(|| {
+            entries.push(AuditEntry {
+                timestamp,
+                ..Default::default()
+            });
+            entries.len() - 1
+        });
+        entries[i].update(&record);
+    }
+    entries
+}
+
+fn journal_records() -> Result<Vec<(u64, JournalRecord)>> {
+    let mut journal = journal::OpenOptions::default()
+        .system(true)
+        .local_only(true)
+        .runtime_only(false)
+        .open()?;
+    journal.seek(journal::JournalSeek::Head)?;
+    // Matches on the same field are ORed, and ANDed with the others. Only
+    // trust messages from the daemon, i.e. root.
+    journal.match_add("MESSAGE_ID", TRANSACTION_STARTED_MSG)?;
+    journal.match_add("MESSAGE_ID", TRANSACTION_FINISHED_MSG)?;
+    journal.match_add("MESSAGE_ID", TRANSACTION_RECOVERED_MSG)?;
+    journal.match_add("_UID", "0")?;
+    let mut records = Vec::new();
+    while let Some(record) = journal.next_entry()? {
+        let timestamp = journal
+            .timestamp()?
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs();
+        records.push((timestamp, record));
+    }
+    Ok(records)
+}
+
+fn print_entry(out: &mut impl Write, entry: &AuditEntry) -> Result<()> {
+    let timestamp = Utc
+        .timestamp_opt(entry.timestamp as i64, 0)
+        .single()
+        .map(|t| t.format("%Y-%m-%dT%H:%M:%SZ").to_string())
+        .unwrap_or_else(|| entry.timestamp.to_string());
+    let result = match (entry.success, &entry.error) {
+        (Some(true), _) => "succeeded".to_string(),
+        (Some(false), Some(e)) => format!("failed: {}", e),
+        (Some(false), None) => "failed".to_string(),
+        (None, _) => "interrupted".to_string(),
+    };
+    writeln!(out, "{} {} {}", timestamp, entry.method, result)?;
+    let mut client = Vec::new();
+    if let Some(uid) = entry.client_uid {
+        client.push(format!("uid {}", uid));
+    }
+    if let Some(pid) = entry.client_pid {
+        client.push(format!("pid {}", pid));
+    }
+    if let Some(id) = entry.client_id.as_ref() {
+        client.push(format!("id {}", id));
+    }
+    if let Some(unit) = entry.client_sd_unit.as_ref() {
+        client.push(format!("unit {}", unit));
+    }
+    if !client.is_empty() {
+        writeln!(out, "  Client: {}", client.join(", "))?;
+    }
+    if !entry.path.is_empty() {
+        writeln!(out, "  Path: {}", entry.path)?;
+    }
+    if !entry.args.is_empty() {
+        writeln!(out, "  Arguments: {}", entry.args)?;
+    }
+    if let Some(checksum) = entry.deployment_checksum.as_ref() {
+        writeln!(out, "  Deployment: {}", checksum)?;
+    }
+    if let Some(recovery) = entry.recovery.as_ref() {
+        writeln!(out, "  Recovery: {}", recovery)?;
+    }
+    Ok(())
+}
+
+/// Main entrypoint
+pub fn entrypoint(args: &[&str]) -> Result<()> {
+    let opts = Opts::parse_from(args.iter().skip(1));
+    let mut entries = entries_from_records(journal_records()?);
+    if let Some(limit) = opts.limit {
+        entries.drain(..entries.len().saturating_sub(limit));
+    }
+    let stdout = std::io::stdout();
+    let mut stdout = stdout.lock();
+    if opts.json {
+        serde_json::to_writer_pretty(&mut stdout, &entries)?;
+        writeln!(stdout)?;
+    } else {
+        for entry in entries.iter() {
+            print_entry(&mut stdout, entry)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn record(msg_id: &str, fields: &[(&str, &str)]) -> JournalRecord {
+        let mut r = JournalRecord::new();
+        r.insert("MESSAGE_ID".into(), msg_id.into());
+        for (k, v) in fields {
+            r.insert(k.to_string(), v.to_string());
+        }
+        r
+    }
+
+    #[test]
+    fn test_clap_cmd() {
+        use clap::CommandFactory;
+        Opts::command().debug_assert()
+    }
+
+    #[test]
+    fn test_entries_from_records() {
+        let started = |address| {
+            record(
+                TRANSACTION_STARTED_MSG,
+                &[
+                    ("TRANSACTION_ADDRESS", address),
+                    ("TRANSACTION_METHOD", "UpdateDeployment"),
+                    ("TRANSACTION_PATH", "/org/projectatomic/rpmostree1/fedora"),
+                    ("TRANSACTION_ARGS", "(@a{sv} {}, @a{sv} {})"),
+                    ("CLIENT_UID", "1000"),
+                    ("CLIENT_PID", "4242"),
+                    ("CLIENT_ID", ""),
+                    ("CLIENT_SD_UNIT", "session-2.scope"),
+                ],
+            )
+        };
+        let records = vec![
+            // Logged by an older daemon
+            (
+                5,
+                record(TRANSACTION_STARTED_MSG, &[("BUS_ADDRESS", ":1.20")]),
+            ),
+            (10, started("unix:path=/tmp/rpm-ostree/a")),
+            (20, started("unix:path=/tmp/rpm-ostree/b")),
+            (
+                30,
+                record(
+                    TRANSACTION_FINISHED_MSG,
+                    &[
+                        ("TRANSACTION_ADDRESS", "unix:path=/tmp/rpm-ostree/b"),
+                        ("TRANSACTION_METHOD", "UpdateDeployment"),
+                        ("TRANSACTION_SUCCESS", "1"),
+                        ("TRANSACTION_ERROR", ""),
+                        ("DEPLOYMENT_CHECKSUM", "41ab"),
+                    ],
+                ),
+            ),
+            (
+                35,
+                record(
+                    TRANSACTION_RECOVERED_MSG,
+                    &[
+                        ("TRANSACTION_ADDRESS", "unix:path=/tmp/rpm-ostree/a"),
+                        ("TRANSACTION_METHOD", "UpdateDeployment"),
+                        ("RECOVERY_ACTIONS", "deleted rootfs checkout"),
+                    ],
+                ),
+            ),
+            // The start record was rotated away
+            (
+                40,
+                record(
+                    TRANSACTION_FINISHED_MSG,
+                    &[
+                        ("TRANSACTION_ADDRESS", "unix:path=/tmp/rpm-ostree/c"),
+                        ("TRANSACTION_METHOD", "KernelArgs"),
+                        ("TRANSACTION_SUCCESS", "0"),
+                        ("TRANSACTION_ERROR", "Not found"),
+                        ("CLIENT_ID", "zincati"),
+                    ],
+                ),
+            ),
+        ];
+        let entries = entries_from_records(records);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].timestamp, 10);
+        assert_eq!(entries[0].success, None);
+        assert_eq!(entries[0].client_uid, Some(1000));
+        assert_eq!(entries[0].client_id, None);
+        assert_eq!(
+            entries[0].recovery.as_deref(),
+            Some("deleted rootfs checkout")
+        );
+        assert_eq!(
+            entries[1],
+            AuditEntry {
+                timestamp: 20,
+                method: "UpdateDeployment".into(),
+                path: "/org/projectatomic/rpmostree1/fedora".into(),
+                args: "(@a{sv} {}, @a{sv} {})".into(),
+                client_uid: Some(1000),
+                client_pid: Some(4242),
+                client_id: None,
+                client_sd_unit: Some("session-2.scope".into()),
+                success: Some(true),
+                error: None,
+                deployment_checksum: Some("41ab".into()),
+                recovery: None,
+            }
+        );
+        assert_eq!(entries[2].timestamp, 40);
+        assert_eq!(entries[2].method, "KernelArgs");
+        assert_eq!(entries[2].success, Some(false));
+        assert_eq!(entries[2].error.as_deref(), Some("Not found"));
+        assert_eq!(entries[2].client_id.as_deref(), Some("zincati"));
+
+        let mut out = Vec::new();
+        print_entry(&mut out, &entries[0]).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("1970-01-01T00:00:10Z UpdateDeployment interrupted\n"));
+        assert!(out.ends_with("  Recovery: deleted rootfs checkout\n"));
+
+        let mut out = Vec::new();
+        print_entry(&mut out, &entries[2]).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "1970-01-01T00:00:40Z KernelArgs failed: Not found\n  Client: id zincati\n"
+        );
+    }
+}
@@ -0,0 +1,213 @@
+//! CLI sub-command `compose build-derived-image`: convert the client-side
+//! state of the booted deployment (layered packages, overrides, initramfs
+//! settings) into a container image derived from the booted base image.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::treefile::{TreeComposeConfig, Treefile};
+use anyhow::{anyhow, bail, Context, Result};
+use camino::Utf8PathBuf;
+use clap::Parser;
+use ostree_ext::container::{OstreeImageReference, Transport};
+use ostree_ext::{container as ostree_container, gio, ostree};
+use std::fmt::Write as _;
+use std::process::Command;
+
+/// Name of the client treefile in the build context, applied by `rpm-ostree ex rebuild`.
+const DERIVED_TREEFILE: &str = "derived.yaml";
+
+#[derive(Debug, Parser)]
+#[clap(name = "rpm-ostree compose build-derived-image")]
+#[clap(rename_all = "kebab-case")]
+struct Opt {
+    /// Directory to write the build context (Containerfile) to
+    context: Utf8PathBuf,
+    /// Tag for the built image
+    #[clap(long, required_unless_present = "context_only")]
+    tag: Option<String>,
+    /// Only write the build context, don't build the image
+    #[clap(long)]
+    context_only: bool,
+}
+
+/// Generate the Containerfile and the client treefile which recreate the
+/// state of `origin` on top of `base_image`.
+fn render_derived_image(origin: &TreeComposeConfig, base_image: &str) -> Result<(String, String)> {
+    let derive = &origin.derive;
+    if origin.modules.is_some() {
+        bail!("Modules are not supported in container builds");
+    }
+    let local = [
+        &derive.packages_local,
+        &derive.packages_local_fileoverride,
+        &derive.override_replace_local,
+    ];
+    let local: Vec<&str> = local
+        .iter()
+        .filter_map(|m| m.as_ref())
+        .flat_map(|m| m.values().map(|s| s.as_str()))
+        .collect();
+    if !local.is_empty() {
+        bail!(
+            "Local packages cannot be included; layer them from a repository first: {}",
+            local.join(", ")
+        );
+    }
+
+    let mut treefile = TreeComposeConfig::default();
+    // Transient packages are meant to go away, so don't bake them in
+    let transient = derive.packages_transient.as_ref();
+    treefile.packages = origin.packages.as_ref().map(|pkgs| {
+        pkgs.iter()
+            .filter(|p| !transient.map(|t| t.contains_key(*p)).unwrap_or_default())
+            .cloned()
+            .collect()
+    });
+    treefile.derive.override_remove = derive.override_remove.clone();
+    treefile.derive.override_replace = derive.override_replace.clone();
+    let treefile = serde_yaml::to_string(&treefile)?;
+
+    let mut containerfile = String::new();
+    writeln!(
+        containerfile,
+        "# Generated by `rpm-ostree compose build-derived-image` from the booted deployment."
+    )?;
+    writeln!(containerfile, "FROM {}", base_image)?;
+    writeln!(
+        containerfile,
+        "COPY {} /etc/rpm-ostree/origin.d/",
+        DERIVED_TREEFILE
+    )?;
+    writeln!(
+        containerfile,
+        "RUN rpm-ostree ex rebuild && ostree container commit"
+    )?;
+    if let Some(initramfs) = derive.initramfs.as_ref() {
+        if initramfs.etc.is_some() {
+            bail!("Tracked initramfs /etc files must be added to the image manually");
+        }
+        if initramfs.regenerate {
+            let args = initramfs
+                .args
+                .iter()
+                .flatten()
+                .map(|a| crate::utils::maybe_shell_quote(a))
+                .collect::<Vec<_>>()
+                .join(" ");
+            writeln!(
+                containerfile,
+                "RUN kver=$(ls /usr/lib/modules) && \\\n    \
+                 dracut --no-hostonly --reproducible --add ostree --kver \"$kver\" \\\n    \
+                 -f \"/usr/lib/modules/$kver/initramfs.img\" {} && \\\n    \
+                 ostree container commit",
+                args
+            )?;
+        }
+    }
+    Ok((containerfile, treefile))
+}
+
+/// Find the image the booted deployment is based on, pinned by digest.
+fn booted_base_image(
+    repo: &ostree::Repo,
+    origin: &Treefile,
+) -> Result<(OstreeImageReference, String)> {
+    let imgref = origin
+        .parsed
+        .derive
+        .container_image_reference
+        .as_deref()
+        .ok_or_else(|| anyhow!("The booted deployment is not based on a container image"))?;
+    let imgref = OstreeImageReference::try_from(imgref)?;
+    if !matches!(imgref.imgref.transport, Transport::Registry) {
+        bail!("Unsupported base image transport: {}", imgref);
+    }
+    let state = ostree_container::store::query_image(repo, &imgref)?
+        .ok_or_else(|| anyhow!("Failed to find image {}", imgref))?;
+    let pinned = format!("{}@{}", imgref.imgref.name, state.manifest_digest);
+    Ok((imgref, pinned))
+}
+
+/// Main entrypoint for `compose build-derived-image`.
+pub(crate) fn compose_build_derived_image_entrypoint(args: &Vec<String>) -> Result<()> {
+    let opt = crate::completion::parse_args::<Opt>(args)?;
+    let sysroot = &ostree::Sysroot::new_default();
+    sysroot.load(gio::NONE_CANCELLABLE)?;
+    let booted = sysroot.require_booted_deployment()?;
+    let origin = booted
+        .origin()
+        .ok_or_else(|| anyhow!("No origin for booted deployment"))?;
+    let origin = crate::origin::origin_to_treefile_inner(&origin)?;
+    let repo = &sysroot.repo().unwrap();
+    let (imgref, base_image) = booted_base_image(repo, &origin)?;
+    if origin.parsed.derive.packages_transient.is_some() {
+        println!("Note: transient packages are not included in the image");
+    }
+    let (containerfile, treefile) = render_derived_image(&origin.parsed, &base_image)?;
+
+    std::fs::create_dir_all(&opt.context).with_context(|| format!("Creating {}", opt.context))?;
+    std::fs::write(opt.context.join("Containerfile"), containerfile)?;
+    std::fs::write(opt.context.join(DERIVED_TREEFILE), treefile)?;
+    println!("Wrote build context for {} to {}", imgref, opt.context);
+    if opt.context_only {
+        return Ok(());
+    }
+
+    // Unwrap safety: clap requires this unless --context-only
+    let tag = opt.tag.as_deref().unwrap();
+    let status = Command::new("podman")
+        .args(["build", "--tag", tag])
+        .arg(opt.context.as_str())
+        .status()
+        .context("Running podman")?;
+    if !status.success() {
+        bail!("podman build failed: {:?}", status);
+    }
+    println!("Built {}", tag);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::InputFormat;
+
+    const ORIGIN: &str = indoc::indoc! {r#"
+        container-image-reference: ostree-unverified-registry:quay.io/example/os:stable
+        packages:
+          - strace
+          - vim
+        packages-transient:
+          strace: 2
+        override-remove:
+          - nano
+        initramfs:
+          regenerate: true
+          args:
+            - --add-drivers
+            - nvme foo
+    "#};
+
+    #[test]
+    fn test_render_derived_image() -> Result<()> {
+        let origin = Treefile::new_from_string(InputFormat::YAML, ORIGIN)?;
+        let (containerfile, treefile) =
+            render_derived_image(&origin.parsed, "quay.io/example/os:stable@sha256:abcd")?;
+        assert!(containerfile.contains("FROM quay.io/example/os:stable@sha256:abcd\n"));
+        assert!(containerfile.contains("--add-drivers 'nvme foo'"));
+        let treefile: TreeComposeConfig = serde_yaml::from_str(&treefile)?;
+        assert_eq!(
+            treefile.packages.unwrap().into_iter().collect::<Vec<_>>(),
+            vec!["vim"]
+        );
+        assert_eq!(treefile.derive.override_remove.unwrap().len(), 1);
+        assert!(treefile.derive.container_image_reference.is_none());
+
+        let origin = Treefile::new_from_string(
+            InputFormat::YAML,
+            "packages-local:\n  foo: sha256:foo-1.0-1.x86_64\n",
+        )?;
+        assert!(render_derived_image(&origin.parsed, "quay.io/example/os").is_err());
+        Ok(())
+    }
+}
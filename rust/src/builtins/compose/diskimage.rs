@@ -0,0 +1,498 @@
+//! CLI sub-command `compose diskimage`: install a commit into a bootable disk
+//! image, as described by the treefile `disk-image` field.
+//!
+//! The image is a GPT disk with an ESP (and a BIOS boot partition on x86_64),
+//! an ext4 `/boot` and the root filesystem, optionally in LUKS.  The commit is
+//! deployed with `ostree admin`, and the bootloader installed by bootupd from
+//! the payloads in the commit, which must thus include them.  The tools
+//! (`sfdisk`, `losetup`, `mkfs.*`, `cryptsetup`, `bootupctl`, `qemu-img`)
+//! come from the host.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::treefile::{DiskImage, DiskImageFilesystem, DiskImageFormat};
+use anyhow::{anyhow, bail, Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use clap::Parser;
+use fn_error_context::context;
+use ostree_ext::prelude::*;
+use ostree_ext::{gio, ostree};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+#[derive(Debug, Parser)]
+#[clap(name = "rpm-ostree compose diskimage")]
+#[clap(rename_all = "kebab-case")]
+struct Opt {
+    /// Path to OSTree repository
+    #[clap(long)]
+    repo: Utf8PathBuf,
+    /// Ref or commit to install; defaults to the `ref` of the treefile
+    #[clap(long)]
+    rev: Option<String>,
+    /// Path to the treefile with the `disk-image` field
+    treefile: Utf8PathBuf,
+    /// Path of the image to write
+    output: Utf8PathBuf,
+}
+
+const ESP_LABEL: &str = "EFI-SYSTEM";
+const BOOT_LABEL: &str = "boot";
+const ROOT_LABEL: &str = "root";
+
+/// A partition of the image.
+#[derive(Debug, PartialEq, Eq)]
+struct Partition {
+    label: &'static str,
+    type_guid: &'static str,
+    /// Size in MiB; `None` for the last one, which takes the rest of the disk.
+    size_mib: Option<u64>,
+}
+
+/// The partitions for `basearch`, in order.
+fn partitions(basearch: &str) -> Result<Vec<Partition>> {
+    let esp = Partition {
+        label: ESP_LABEL,
+        type_guid: "C12A7328-F81F-11D2-BA4B-00A0C93EC93B",
+        size_mib: Some(127),
+    };
+    let boot = Partition {
+        label: BOOT_LABEL,
+        type_guid: "0FC63DAF-8483-4772-8E79-3D69D8477DE4",
+        size_mib: Some(384),
+    };
+    // The discoverable partitions specification's root types
+    let r = match basearch {
+        "x86_64" => vec![
+            Partition {
+                label: "BIOS-BOOT",
+                type_guid: "21686148-6449-6E6F-744E-656564454649",
+                size_mib: Some(1),
+            },
+            esp,
+            boot,
+            Partition {
+                label: ROOT_LABEL,
+                type_guid: "4F68BCE3-E8CD-4DB1-96E7-FBCAF984B709",
+                size_mib: None,
+            },
+        ],
+        "aarch64" => vec![
+            esp,
+            boot,
+            Partition {
+                label: ROOT_LABEL,
+                type_guid: "B921B045-1DF0-41C3-AF44-4C6F280D3FAE",
+                size_mib: None,
+            },
+        ],
+        o => bail!("Disk images are not supported on {}", o),
+    };
+    Ok(r)
+}
+
+/// The 1-based index of the partition labeled `label`.
+fn partition_number(partitions: &[Partition], label: &str) -> usize {
+    partitions
+        .iter()
+        .position(|p| p.label == label)
+        .expect("partition")
+        + 1
+}
+
+/// Render `partitions` as a sfdisk(8) script.
+fn sfdisk_script(partitions: &[Partition]) -> String {
+    let mut r = "label: gpt\n".to_string();
+    for p in partitions {
+        if let Some(size) = p.size_mib {
+            r.push_str(&format!("size={}MiB, ", size));
+        }
+        r.push_str(&format!("type={}, name=\"{}\"\n", p.type_guid, p.label));
+    }
+    r
+}
+
+/// Parse a size in bytes, optionally with a `K`, `M`, `G` or `T` suffix.
+fn parse_size(s: &str) -> Result<u64> {
+    let s = s.trim();
+    let (n, shift) = match s.chars().last() {
+        Some('K' | 'k') => (&s[..s.len() - 1], 10),
+        Some('M' | 'm') => (&s[..s.len() - 1], 20),
+        Some('G' | 'g') => (&s[..s.len() - 1], 30),
+        Some('T' | 't') => (&s[..s.len() - 1], 40),
+        _ => (s, 0),
+    };
+    n.parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(1 << shift))
+        .ok_or_else(|| anyhow!("Invalid size: {}", s))
+}
+
+fn run(cmd: &mut Command) -> Result<()> {
+    let status = cmd.status().with_context(|| format!("Running {:?}", cmd))?;
+    if !status.success() {
+        bail!("{:?} failed: {:?}", cmd, status);
+    }
+    Ok(())
+}
+
+/// Run `cmd` and return its trimmed standard output.
+fn run_output(cmd: &mut Command) -> Result<String> {
+    let out = cmd
+        .stderr(Stdio::inherit())
+        .output()
+        .with_context(|| format!("Running {:?}", cmd))?;
+    if !out.status.success() {
+        bail!("{:?} failed: {:?}", cmd, out.status);
+    }
+    Ok(String::from_utf8(out.stdout)?.trim().to_string())
+}
+
+/// Undoes a setup step (unmounting, detaching...) when dropped on errors;
+/// on success, [`Teardown::run`] does it and checks that it worked.
+struct Teardown(Option<Command>);
+
+impl Teardown {
+    fn new<const N: usize>(argv: [&str; N]) -> Self {
+        let mut cmd = Command::new(argv[0]);
+        cmd.args(&argv[1..]);
+        Self(Some(cmd))
+    }
+
+    fn run(mut self) -> Result<()> {
+        run(self.0.as_mut().expect("teardown"))?;
+        self.0 = None;
+        Ok(())
+    }
+}
+
+impl Drop for Teardown {
+    fn drop(&mut self) {
+        if let Some(cmd) = self.0.as_mut() {
+            if let Err(e) = run(cmd) {
+                eprintln!("warning: {:#}", e);
+            }
+        }
+    }
+}
+
+fn mount(device: &str, target: &Utf8Path) -> Result<Teardown> {
+    std::fs::create_dir_all(target).with_context(|| format!("Creating {}", target))?;
+    run(Command::new("mount").args([device, target.as_str()]))?;
+    Ok(Teardown::new(["umount", target.as_str()]))
+}
+
+fn uuid(device: &str) -> Result<String> {
+    run_output(Command::new("blkid").args(["-s", "UUID", "-o", "value", device]))
+}
+
+/// The `ID` of the os-release of the commit, as default stateroot.
+fn commit_os_id(repo: &ostree::Repo, rev: &str) -> Result<String> {
+    let (root, _) = repo.read_commit(rev, gio::NONE_CANCELLABLE)?;
+    let f = root.resolve_relative_path("usr/lib/os-release");
+    let (contents, _) = f
+        .load_contents(gio::NONE_CANCELLABLE)
+        .context("Reading os-release")?;
+    String::from_utf8_lossy(&contents)
+        .lines()
+        .filter_map(|l| l.split_once('='))
+        .find(|(k, _)| *k == "ID")
+        .map(|(_, v)| v.trim_matches(|c| c == '"' || c == '\'').to_string())
+        .ok_or_else(|| anyhow!("No ID in os-release; set disk-image.osname"))
+}
+
+/// Everything needed to write the image, resolved from the options.
+struct Target<'a> {
+    config: &'a DiskImage,
+    /// The directory of the treefile, for relative paths.
+    workdir: &'a Utf8Path,
+    repo: &'a Utf8Path,
+    rev: &'a str,
+    osname: String,
+    partitions: Vec<Partition>,
+}
+
+/// Partition the raw image `path`, create the filesystems, deploy the commit
+/// and install the bootloader.
+#[context("Installing {} into {}", target.rev, path)]
+fn write_raw(target: &Target, path: &Utf8Path, size: u64) -> Result<()> {
+    let config = target.config;
+    let f = std::fs::File::create(path).with_context(|| format!("Creating {}", path))?;
+    f.set_len(size)?;
+    drop(f);
+
+    let mut child = Command::new("sfdisk")
+        .args(["--quiet", path.as_str()])
+        .stdin(Stdio::piped())
+        .spawn()
+        .context("Running sfdisk")?;
+    child
+        .stdin
+        .take()
+        .expect("stdin")
+        .write_all(sfdisk_script(&target.partitions).as_bytes())?;
+    if !child.wait()?.success() {
+        bail!("Partitioning {} failed", path);
+    }
+
+    let loopdev = run_output(Command::new("losetup").args([
+        "--find",
+        "--show",
+        "--partscan",
+        path.as_str(),
+    ]))?;
+    let detach = Teardown::new(["losetup", "--detach", loopdev.as_str()]);
+    run(Command::new("udevadm").arg("settle"))?;
+    let part = |label| {
+        format!(
+            "{}p{}",
+            loopdev,
+            partition_number(&target.partitions, label)
+        )
+    };
+    let (esp, boot) = (part(ESP_LABEL), part(BOOT_LABEL));
+
+    println!("Creating filesystems");
+    run(Command::new("mkfs.fat").args(["-F", "32", "-n", ESP_LABEL, &esp]))?;
+    run(Command::new("mkfs.ext4").args(["-q", "-L", BOOT_LABEL, &boot]))?;
+    let mut kargs = Vec::new();
+    let (root, close_luks) = match config.luks.as_ref() {
+        Some(luks) => {
+            let keyfile = target.workdir.join(&luks.key_file);
+            let part = part(ROOT_LABEL);
+            run(Command::new("cryptsetup")
+                .args(["luksFormat", "--batch-mode", "--type", "luks2"])
+                .args([
+                    "--label",
+                    "luks-root",
+                    "--key-file",
+                    keyfile.as_str(),
+                    &part,
+                ]))?;
+            let name = format!("rpmostree-diskimage-{}", std::process::id());
+            run(Command::new("cryptsetup").args([
+                "open",
+                "--key-file",
+                keyfile.as_str(),
+                &part,
+                &name,
+            ]))?;
+            kargs.push(format!("rd.luks.uuid={}", uuid(&part)?));
+            let close = Teardown::new(["cryptsetup", "close", name.as_str()]);
+            (format!("/dev/mapper/{}", name), Some(close))
+        }
+        None => (part(ROOT_LABEL), None),
+    };
+    let filesystem = config.filesystem.unwrap_or(DiskImageFilesystem::Xfs);
+    match filesystem {
+        DiskImageFilesystem::Xfs => {
+            run(Command::new("mkfs.xfs").args(["-q", "-f", "-L", ROOT_LABEL, &root]))?
+        }
+        DiskImageFilesystem::Ext4 => {
+            run(Command::new("mkfs.ext4").args(["-q", "-L", ROOT_LABEL, &root]))?
+        }
+    }
+    kargs.push(format!("root=UUID={}", uuid(&root)?));
+    kargs.push("rw".to_string());
+
+    let tempdir = tempfile::tempdir()?;
+    let rootfs = Utf8Path::from_path(tempdir.path()).ok_or_else(|| anyhow!("Invalid tempdir"))?;
+    let umount_root = mount(&root, rootfs)?;
+    let umount_boot = mount(&boot, &rootfs.join("boot"))?;
+    let umount_esp = mount(&esp, &rootfs.join("boot/efi"))?;
+
+    if let Some(ignition) = config.ignition.as_ref() {
+        let platform = ignition.platform.as_deref().unwrap_or(
+            match config.format.unwrap_or(DiskImageFormat::Qcow2) {
+                DiskImageFormat::Qcow2 => "qemu",
+                DiskImageFormat::Raw => "metal",
+            },
+        );
+        kargs.push(format!("ignition.platform.id={}", platform));
+    }
+    kargs.extend(config.kargs.iter().flatten().cloned());
+
+    println!("Deploying {} ({})", target.rev, target.osname);
+    let run_ostree = |args: &[&str]| run(Command::new("ostree").args(args));
+    let sysroot_repo = rootfs.join("ostree/repo");
+    run_ostree(&["admin", "init-fs", "--modern", rootfs.as_str()])?;
+    // bootupd's static configs find the BLS entries written by ostree
+    run_ostree(&[
+        "config",
+        "--repo",
+        sysroot_repo.as_str(),
+        "set",
+        "sysroot.bootloader",
+        "none",
+    ])?;
+    run_ostree(&[
+        "pull-local",
+        "--repo",
+        sysroot_repo.as_str(),
+        target.repo.as_str(),
+        target.rev,
+    ])?;
+    run_ostree(&[
+        "admin",
+        "os-init",
+        "--sysroot",
+        rootfs.as_str(),
+        &target.osname,
+    ])?;
+    let mut deploy = vec![
+        "admin".to_string(),
+        "deploy".to_string(),
+        format!("--sysroot={}", rootfs),
+        format!("--os={}", target.osname),
+    ];
+    deploy.extend(kargs.iter().map(|k| format!("--karg={}", k)));
+    deploy.push(target.rev.to_string());
+    run(Command::new("ostree").args(&deploy))?;
+
+    let sysroot = ostree::Sysroot::new(Some(&gio::File::for_path(rootfs)));
+    sysroot.load(gio::NONE_CANCELLABLE)?;
+    let deployment = sysroot
+        .deployments()
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("No deployment"))?;
+    let deploydir = rootfs.join(sysroot.deployment_dirpath(&deployment).as_str());
+    std::fs::write(
+        deploydir.join("etc/fstab"),
+        format!(
+            "UUID={} /boot ext4 defaults 1 2\nUUID={} /boot/efi vfat umask=0077,shortname=winnt 0 2\n",
+            uuid(&boot)?,
+            uuid(&esp)?
+        ),
+    )
+    .context("Writing fstab")?;
+
+    println!("Installing bootloader");
+    if !deploydir.join("usr/lib/bootupd/updates").exists() {
+        bail!("{} doesn't include bootupd, which is required", target.rev);
+    }
+    run(Command::new("bootupctl")
+        .args([
+            "backend",
+            "install",
+            "--write-uuid",
+            "--with-static-configs",
+        ])
+        .args(["--device", &loopdev, "--src-root", deploydir.as_str()])
+        .arg(rootfs.as_str()))?;
+
+    if let Some(ignition) = config.ignition.as_ref() {
+        // Makes the bootloader config pass `ignition.firstboot`
+        std::fs::write(rootfs.join("boot/ignition.firstboot"), "")?;
+        if let Some(ign) = ignition.config.as_deref() {
+            let dest = rootfs.join("boot/ignition");
+            std::fs::create_dir(&dest)?;
+            let src = target.workdir.join(ign);
+            std::fs::copy(&src, dest.join("config.ign"))
+                .with_context(|| format!("Copying {}", src))?;
+        }
+    }
+
+    umount_esp.run()?;
+    umount_boot.run()?;
+    umount_root.run()?;
+    if let Some(close) = close_luks {
+        close.run()?;
+    }
+    detach.run()?;
+    Ok(())
+}
+
+/// Main entrypoint for `compose diskimage`.
+pub(crate) fn compose_diskimage_entrypoint(args: &Vec<String>) -> Result<()> {
+    let opt = crate::completion::parse_args::<Opt>(args)?;
+    let treefile = crate::treefile_new_compose(opt.treefile.as_str(), "")?;
+    let config = treefile
+        .parsed
+        .base
+        .disk_image
+        .as_ref()
+        .ok_or_else(|| anyhow!("{} has no disk-image field", opt.treefile))?;
+    let size = parse_size(&config.size)?;
+    let partitions = partitions(&crate::utils::get_rpm_basearch())?;
+    // Leave at least 1GiB for the root partition
+    let min_mib = partitions.iter().filter_map(|p| p.size_mib).sum::<u64>() + 1024;
+    if size < min_mib << 20 {
+        bail!("disk-image size must be at least {}MiB", min_mib);
+    }
+    let rev = opt.rev.clone().unwrap_or_else(|| treefile.get_ostree_ref());
+    if rev.is_empty() {
+        bail!("No --rev specified and the treefile has no ref");
+    }
+    let repo = ostree::Repo::new_for_path(&opt.repo);
+    repo.open(gio::NONE_CANCELLABLE)
+        .with_context(|| format!("Opening {}", opt.repo))?;
+    let commit = repo.require_rev(&rev)?;
+    let osname = match config.osname.as_ref() {
+        Some(o) => o.clone(),
+        None => commit_os_id(&repo, &commit)?,
+    };
+    let target = Target {
+        config,
+        workdir: Utf8Path::new(treefile.get_workdir()),
+        repo: &opt.repo,
+        rev: &rev,
+        osname,
+        partitions,
+    };
+
+    let format = config.format.unwrap_or(DiskImageFormat::Qcow2);
+    let raw = match format {
+        DiskImageFormat::Raw => opt.output.clone(),
+        DiskImageFormat::Qcow2 => Utf8PathBuf::from(format!("{}.raw.tmp", opt.output)),
+    };
+    let r = write_raw(&target, &raw, size).and_then(|_| match format {
+        DiskImageFormat::Raw => Ok(()),
+        DiskImageFormat::Qcow2 => {
+            println!("Converting to qcow2");
+            run(Command::new("qemu-img")
+                .args(["convert", "-f", "raw", "-O", "qcow2"])
+                .args([raw.as_str(), opt.output.as_str()]))
+        }
+    });
+    if format == DiskImageFormat::Qcow2 || r.is_err() {
+        let _ = std::fs::remove_file(&raw);
+    }
+    r?;
+    println!("Wrote {}", opt.output);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("4096").unwrap(), 4096);
+        assert_eq!(parse_size("512M").unwrap(), 512 << 20);
+        assert_eq!(parse_size(" 10G ").unwrap(), 10 << 30);
+        assert_eq!(parse_size("1t").unwrap(), 1 << 40);
+        for s in ["", "G", "10GB", "-1G", "99999999999T"] {
+            assert!(parse_size(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_partitions() {
+        let parts = partitions("x86_64").unwrap();
+        assert_eq!(partition_number(&parts, ESP_LABEL), 2);
+        assert_eq!(partition_number(&parts, ROOT_LABEL), 4);
+        assert_eq!(
+            sfdisk_script(&parts),
+            "label: gpt\n\
+             size=1MiB, type=21686148-6449-6E6F-744E-656564454649, name=\"BIOS-BOOT\"\n\
+             size=127MiB, type=C12A7328-F81F-11D2-BA4B-00A0C93EC93B, name=\"EFI-SYSTEM\"\n\
+             size=384MiB, type=0FC63DAF-8483-4772-8E79-3D69D8477DE4, name=\"boot\"\n\
+             type=4F68BCE3-E8CD-4DB1-96E7-FBCAF984B709, name=\"root\"\n"
+        );
+        let parts = partitions("aarch64").unwrap();
+        assert_eq!(partition_number(&parts, ROOT_LABEL), 3);
+        assert!(partitions("s390x").is_err());
+    }
+}
@@ -3,6 +3,11 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 
 pub(crate) mod commit;
+pub(crate) mod derived_image;
+pub(crate) mod diskimage;
+pub(crate) mod passwd_snapshot;
+pub(crate) mod pin_ids;
+pub(crate) mod schema;
 
 use crate::cxxrsutil::CxxResult;
 use anyhow::{Context, Result};
@@ -0,0 +1,44 @@
+//! CLI sub-command `compose passwd-snapshot`: export the users and groups of
+//! a commit as JSON, to be referenced by `check-passwd` and `check-groups` of
+//! type `snapshot` in later composes, without needing the commit itself.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::passwd::PasswdSnapshot;
+use anyhow::{Context, Result};
+use camino::Utf8PathBuf;
+use clap::Parser;
+use ostree_ext::{gio, ostree};
+use std::io::Write;
+
+#[derive(Debug, Parser)]
+#[clap(name = "rpm-ostree compose passwd-snapshot")]
+#[clap(rename_all = "kebab-case")]
+struct Opt {
+    /// Path to OSTree repository
+    #[clap(long)]
+    repo: Utf8PathBuf,
+    /// Ref or commit to export
+    rev: String,
+    /// Write the snapshot to this file instead of stdout
+    #[clap(long, short)]
+    output: Option<Utf8PathBuf>,
+}
+
+/// Main entrypoint for `compose passwd-snapshot`.
+pub(crate) fn compose_passwd_snapshot_entrypoint(args: &Vec<String>) -> Result<()> {
+    let opt = crate::completion::parse_args::<Opt>(args)?;
+    let repo = ostree::Repo::new_for_path(&opt.repo);
+    repo.open(gio::NONE_CANCELLABLE)
+        .with_context(|| format!("Opening {}", opt.repo))?;
+    let rev = repo.require_rev(&opt.rev)?;
+    let (root, _) = repo.read_commit(&rev, gio::NONE_CANCELLABLE)?;
+    let snapshot = PasswdSnapshot::from_root(&root)?;
+    let mut buf = serde_json::to_vec_pretty(&snapshot)?;
+    buf.push(b'\n');
+    match opt.output {
+        Some(path) => std::fs::write(&path, buf).with_context(|| format!("Writing {}", path))?,
+        None => std::io::stdout().write_all(&buf)?,
+    }
+    Ok(())
+}
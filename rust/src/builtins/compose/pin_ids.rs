@@ -0,0 +1,50 @@
+//! CLI sub-command `compose pin-ids`: add the users and groups of a commit to
+//! a pinned ID mapping, as referenced by the treefile `pinned-ids` field,
+//! failing if any of them drifted from it.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::passwd::PasswdSnapshot;
+use crate::pinned_ids::PinnedIds;
+use anyhow::{Context, Result};
+use camino::Utf8PathBuf;
+use clap::Parser;
+use ostree_ext::{gio, ostree};
+
+#[derive(Debug, Parser)]
+#[clap(name = "rpm-ostree compose pin-ids")]
+#[clap(rename_all = "kebab-case")]
+struct Opt {
+    /// Path to OSTree repository
+    #[clap(long)]
+    repo: Utf8PathBuf,
+    /// Ref or commit whose users and groups to pin
+    rev: String,
+    /// The mapping to update; created if it doesn't exist
+    #[clap(long)]
+    pins: Utf8PathBuf,
+}
+
+/// Main entrypoint for `compose pin-ids`.
+pub(crate) fn compose_pin_ids_entrypoint(args: &Vec<String>) -> Result<()> {
+    let opt = crate::completion::parse_args::<Opt>(args)?;
+    let repo = ostree::Repo::new_for_path(&opt.repo);
+    repo.open(gio::NONE_CANCELLABLE)
+        .with_context(|| format!("Opening {}", opt.repo))?;
+    let rev = repo.require_rev(&opt.rev)?;
+    let (root, _) = repo.read_commit(&rev, gio::NONE_CANCELLABLE)?;
+    let allocated = PinnedIds::from_snapshot(&PasswdSnapshot::from_root(&root)?)?;
+
+    let mut pinned: PinnedIds = if opt.pins.exists() {
+        let buf = std::fs::read(&opt.pins).with_context(|| format!("Reading {}", opt.pins))?;
+        serde_json::from_slice(&buf).with_context(|| format!("Parsing {}", opt.pins))?
+    } else {
+        PinnedIds::default()
+    };
+    let n = pinned.update(&allocated)?;
+    let mut buf = serde_json::to_vec_pretty(&pinned)?;
+    buf.push(b'\n');
+    std::fs::write(&opt.pins, buf).with_context(|| format!("Writing {}", opt.pins))?;
+    println!("Pinned {} new user and group ID(s) in {}", n, opt.pins);
+    Ok(())
+}
@@ -0,0 +1,66 @@
+//! CLI sub-command `compose schema`: print the schema of treefiles, derived
+//! from the types in `rust/treefile/src/config.rs`.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use anyhow::Result;
+use clap::Parser;
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum Format {
+    JsonSchema,
+}
+
+#[derive(Debug, Parser)]
+#[clap(name = "rpm-ostree compose schema")]
+#[clap(rename_all = "kebab-case")]
+struct Opt {
+    /// The format of the schema
+    #[clap(long, value_enum, default_value = "json-schema")]
+    format: Format,
+}
+
+/// Main entrypoint for `compose schema`.
+pub(crate) fn compose_schema_entrypoint(args: &Vec<String>) -> Result<()> {
+    let opt = crate::completion::parse_args::<Opt>(args)?;
+    match opt.format {
+        Format::JsonSchema => {
+            let schema = rpmostree_treefile::json_schema();
+            println!("{}", serde_json::to_string_pretty(&schema)?)
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_treefile_schema() -> Result<()> {
+        let schema = serde_json::to_value(rpmostree_treefile::json_schema())?;
+        let props = &schema["properties"];
+        assert_eq!(props["ref"]["type"], "string");
+        assert_eq!(props["packages"]["items"]["type"], "string");
+        assert_eq!(props["machineid-compat"]["default"], true);
+        assert_eq!(props["ima-sign-algorithm"]["default"], "sha256");
+        assert_eq!(props["gpg_key"]["deprecated"], true);
+        assert!(props["gpg-key"].get("deprecated").is_none());
+        // Fields of the flattened structs
+        assert!(props.get("base-refspec").is_some());
+        assert!(props.get("extra").is_none());
+        assert_eq!(schema["additionalProperties"], false);
+        assert!(schema["patternProperties"].get("^packages-.+$").is_some());
+        let defs = &schema["definitions"];
+        assert_eq!(
+            defs["BootLocation"]["enum"],
+            serde_json::json!(["new", "modules"])
+        );
+        assert_eq!(
+            defs["RepoPackage"]["required"],
+            serde_json::json!(["packages", "repo"])
+        );
+        assert_eq!(defs["IncludeCondition"]["type"], "string");
+        Ok(())
+    }
+}
@@ -2,5 +2,6 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 
 pub(crate) mod apply_live;
+pub mod audit_log;
 pub(crate) mod compose;
 pub mod usroverlay;
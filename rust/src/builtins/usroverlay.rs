@@ -1,15 +1,555 @@
 //! CLI handler for `rpm-ostree usroverlay`.
+//!
+//! By default this is `ostree admin unlock`, whose overlay goes away on
+//! reboot.  With `--persist`, we mount the overlay ourselves, keeping its
+//! upper directory in `/var/lib/rpm-ostree/usroverlay` along with the ID of
+//! the deployment it belongs to; `rpm-ostree-usroverlay.service` mounts it
+//! again on each boot of that deployment, until `--reset`.  Either way, the
+//! upper directory holds the changes, which are listed by `rpm-ostree status`
+//! and can be exported as a container image layer or an RPM.
 
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 
-use anyhow::{Context, Result};
-use clap::Command;
+use crate::cxxrsutil::*;
+use anyhow::{anyhow, bail, Context, Result};
+use clap::Parser;
+use ostree_ext::{gio, ostree};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::{CStr, CString};
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
 use std::os::unix::prelude::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The state of the persistent overlay.
+const STATE_DIR: &str = "/var/lib/rpm-ostree/usroverlay";
+/// In `STATE_DIR`, the ID of the deployment the overlay belongs to; the
+/// overlay is reset at boot if it's missing.
+const DEPLOYMENT: &str = "deployment";
+/// The extended attributes overlayfs marks opaque directories with, for
+/// overlays mounted with and without `userxattr`.
+const OPAQUE_XATTRS: &[&str] = &["trusted.overlay.opaque", "user.overlay.opaque"];
+
+/// Apply a transient overlayfs to /usr
+#[derive(Debug, Parser)]
+#[clap(name = "rpm-ostree usroverlay", bin_name = "rpm-ostree usroverlay")]
+#[clap(rename_all = "kebab-case", long_version = "")]
+pub(crate) struct UsrOverlayOpts {
+    /// Keep the overlay across reboots of this deployment, until --reset
+    #[clap(long, conflicts_with_all = &["reset", "export", "restore"])]
+    persist: bool,
+
+    /// With --persist, discard the changes in an existing persistent overlay
+    #[clap(long, requires = "persist")]
+    force: bool,
+
+    /// Discard the persistent overlay on the next reboot
+    #[clap(long, conflicts_with_all = &["export", "restore"])]
+    reset: bool,
+
+    /// Write the changes made in the overlay to PATH
+    #[clap(long, value_name = "PATH", conflicts_with = "restore")]
+    export: Option<PathBuf>,
+
+    /// With --export, the format of the changes
+    #[clap(long, value_enum, requires = "export")]
+    format: Option<ExportFormat>,
+
+    /// With --export --format=rpm, the name of the package
+    #[clap(long, requires = "export")]
+    name: Option<String>,
+
+    /// Mount the persistent overlay at boot
+    #[clap(long, hide = true)]
+    restore: bool,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum ExportFormat {
+    /// A container image layer tarball, with deleted files as whiteouts
+    Layer,
+    /// An RPM, which can't represent deleted files
+    Rpm,
+}
+
+/// A change made in the overlay, to the file at the same path in /usr.
+#[derive(Debug, PartialEq, Eq)]
+enum Change {
+    /// Added or modified
+    Changed,
+    Deleted,
+    /// A directory replacing the one in /usr, whose original contents are
+    /// hidden
+    Replaced,
+}
+
+/// The upper directory of the overlay mounted on /usr, if any, parsed from
+/// `/proc/self/mountinfo`.
+fn parse_usr_upperdir(mountinfo: &str) -> Option<PathBuf> {
+    mountinfo
+        .lines()
+        .filter_map(|line| {
+            let (mount, fs) = line.split_once(" - ")?;
+            let mountpoint = mount.split(' ').nth(4)?;
+            let mut fs = fs.split(' ');
+            let fstype = fs.next()?;
+            let opts = fs.nth(1)?;
+            if mountpoint != "/usr" || fstype != "overlay" {
+                return None;
+            }
+            opts.split(',')
+                .find_map(|o| o.strip_prefix("upperdir="))
+                .map(PathBuf::from)
+        })
+        .last()
+}
+
+fn usr_upperdir() -> Result<Option<PathBuf>> {
+    let mountinfo = std::fs::read_to_string("/proc/self/mountinfo")?;
+    Ok(parse_usr_upperdir(&mountinfo))
+}
+
+fn is_persistent(upperdir: &Path) -> bool {
+    upperdir.starts_with(STATE_DIR)
+}
+
+/// The names and values of the extended attributes of `path`, without the
+/// ones overlayfs keeps for itself.
+fn read_xattrs(path: &Path) -> Result<Vec<(CString, Vec<u8>)>> {
+    let cpath = CString::new(path.as_os_str().as_bytes())?;
+    let err = || format!("Reading extended attributes of {}", path.display());
+    let mut names = vec![0u8; 1024];
+    loop {
+        let n = unsafe {
+            libc::llistxattr(
+                cpath.as_ptr(),
+                names.as_mut_ptr() as *mut libc::c_char,
+                names.len(),
+            )
+        };
+        if n >= 0 {
+            names.truncate(n as usize);
+            break;
+        }
+        let e = std::io::Error::last_os_error();
+        match e.raw_os_error() {
+            Some(libc::ENOTSUP) => return Ok(Vec::new()),
+            Some(libc::ERANGE) => names.resize(names.len() * 4, 0),
+            _ => return Err(e).with_context(err),
+        }
+    }
+    let mut r = Vec::new();
+    for name in names.split_inclusive(|&c| c == 0) {
+        let name = CStr::from_bytes_with_nul(name)?;
+        let s = name.to_string_lossy();
+        if s.starts_with("trusted.overlay.") || s.starts_with("user.overlay.") {
+            continue;
+        }
+        if let Some(value) = read_xattr(&cpath, name).with_context(err)? {
+            r.push((name.to_owned(), value));
+        }
+    }
+    Ok(r)
+}
+
+fn read_xattr(path: &CStr, name: &CStr) -> std::io::Result<Option<Vec<u8>>> {
+    let mut buf = vec![0u8; 256];
+    loop {
+        let n = unsafe {
+            libc::lgetxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+            )
+        };
+        if n >= 0 {
+            buf.truncate(n as usize);
+            return Ok(Some(buf));
+        }
+        let e = std::io::Error::last_os_error();
+        match e.raw_os_error() {
+            Some(libc::ENODATA) | Some(libc::ENOTSUP) => return Ok(None),
+            Some(libc::ERANGE) => buf.resize(buf.len() * 4, 0),
+            _ => return Err(e),
+        }
+    }
+}
+
+fn is_opaque(path: &Path) -> Result<bool> {
+    let cpath = CString::new(path.as_os_str().as_bytes())?;
+    for name in OPAQUE_XATTRS {
+        let name = CString::new(*name)?;
+        let value = read_xattr(&cpath, &name)
+            .with_context(|| format!("Reading extended attributes of {}", path.display()))?;
+        if value.as_deref() == Some(b"y") {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// The changes in the upper directory `upper`, by path relative to /usr.
+/// Directories are only descended into, unless they're opaque.
+fn collect_changes(upper: &Path) -> Result<BTreeMap<PathBuf, Change>> {
+    fn walk(upper: &Path, rel: &Path, out: &mut BTreeMap<PathBuf, Change>) -> Result<()> {
+        let dir = upper.join(rel);
+        for entry in std::fs::read_dir(&dir).with_context(|| format!("Reading {:?}", dir))? {
+            let entry = entry?;
+            let path = rel.join(entry.file_name());
+            let ft = entry.file_type()?;
+            if ft.is_dir() {
+                if is_opaque(&entry.path())? {
+                    out.insert(path.clone(), Change::Replaced);
+                }
+                walk(upper, &path, out)?;
+            } else if ft.is_char_device() && entry.metadata()?.rdev() == 0 {
+                // An overlayfs whiteout
+                out.insert(path, Change::Deleted);
+            } else {
+                out.insert(path, Change::Changed);
+            }
+        }
+        Ok(())
+    }
+    let mut r = BTreeMap::new();
+    walk(upper, Path::new(""), &mut r)?;
+    Ok(r)
+}
+
+fn describe_change(path: &Path, change: &Change) -> String {
+    let path = Path::new("/usr").join(path);
+    match change {
+        Change::Changed => path.display().to_string(),
+        Change::Deleted => format!("{} (deleted)", path.display()),
+        Change::Replaced => format!("{} (replaced)", path.display()),
+    }
+}
+
+fn booted_deployment_id() -> Result<String> {
+    let sysroot = &ostree::Sysroot::new_default();
+    sysroot.load(gio::NONE_CANCELLABLE)?;
+    let booted = sysroot.require_booted_deployment()?;
+    Ok(crate::deployment_generate_id_impl(&booted))
+}
+
+fn mount_overlay(state: &Path) -> Result<()> {
+    let opts = format!(
+        "lowerdir=/usr,upperdir={},workdir={}",
+        state.join("upper").display(),
+        state.join("work").display()
+    );
+    let status = Command::new("mount")
+        .args(["-t", "overlay", "overlay", "-o", &opts, "/usr"])
+        .status()
+        .context("Running mount")?;
+    if !status.success() {
+        bail!("Mounting overlay on /usr: {}", status);
+    }
+    Ok(())
+}
+
+fn persist(force: bool) -> Result<()> {
+    if usr_upperdir()?.is_some() {
+        bail!("An overlay is already mounted on /usr");
+    }
+    let state = Path::new(STATE_DIR);
+    let upper = state.join("upper");
+    if upper.exists() {
+        if !force {
+            bail!(
+                "A persistent overlay with changes already exists in {}; use --force to discard it",
+                STATE_DIR
+            );
+        }
+        std::fs::remove_dir_all(&upper)?;
+    }
+    let work = state.join("work");
+    if work.exists() {
+        std::fs::remove_dir_all(&work)?;
+    }
+    for d in [upper, work] {
+        std::fs::create_dir_all(&d).with_context(|| format!("Creating {:?}", d))?;
+    }
+    std::fs::write(state.join(DEPLOYMENT), booted_deployment_id()?)?;
+    mount_overlay(state)?;
+    println!("Persistent overlay mounted on /usr; discard it with rpm-ostree usroverlay --reset");
+    Ok(())
+}
+
+fn reset() -> Result<()> {
+    let state = Path::new(STATE_DIR);
+    if !state.exists() {
+        println!("No persistent overlay.");
+        return Ok(());
+    }
+    let mounted = usr_upperdir()?.map_or(false, |u| is_persistent(&u));
+    if mounted {
+        // It can't be unmounted from under running processes; it's removed by
+        // rpm-ostree-usroverlay.service instead
+        std::fs::remove_file(state.join(DEPLOYMENT)).or_else(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => Ok(()),
+            _ => Err(e),
+        })?;
+        println!("The persistent overlay will be discarded on the next reboot.");
+    } else {
+        std::fs::remove_dir_all(state)?;
+        println!("Persistent overlay discarded.");
+    }
+    Ok(())
+}
+
+/// Mount the persistent overlay at boot if it belongs to the booted
+/// deployment, or remove it if it was reset.
+fn restore() -> Result<()> {
+    let state = Path::new(STATE_DIR);
+    let deployment = match std::fs::read_to_string(state.join(DEPLOYMENT)) {
+        Ok(d) => d,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            std::fs::remove_dir_all(state)?;
+            println!("Removed reset persistent overlay");
+            return Ok(());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let booted = booted_deployment_id()?;
+    if deployment.trim() != booted {
+        println!(
+            "Not mounting persistent overlay of deployment {}; booted {}",
+            deployment.trim(),
+            booted
+        );
+        return Ok(());
+    }
+    mount_overlay(state)?;
+    println!("Mounted persistent overlay on /usr");
+    Ok(())
+}
+
+/// The PAX extended header record of the extended attribute `name`.
+fn pax_xattr_record(name: &CStr, value: &[u8]) -> Vec<u8> {
+    let key = [b"SCHILY.xattr.", name.to_bytes()].concat();
+    // The length of the record includes its own digits
+    let len = key.len() + value.len() + 3;
+    let mut total = len + len.to_string().len();
+    if total.to_string().len() != len.to_string().len() {
+        total += 1;
+    }
+    let mut r = format!("{} ", total).into_bytes();
+    r.extend_from_slice(&key);
+    r.push(b'=');
+    r.extend_from_slice(value);
+    r.push(b'\n');
+    r
+}
+
+/// Append the file `src` to `tar` as `name`, with its extended attributes in
+/// a PAX header.
+fn append_file<W: Write>(tar: &mut tar::Builder<W>, src: &Path, name: &Path) -> Result<()> {
+    let pax: Vec<u8> = read_xattrs(src)?
+        .iter()
+        .flat_map(|(k, v)| pax_xattr_record(k, v))
+        .collect();
+    if !pax.is_empty() {
+        let mut header = tar::Header::new_ustar();
+        header.set_entry_type(tar::EntryType::XHeader);
+        header.set_size(pax.len() as u64);
+        header.set_mode(0o644);
+        tar.append_data(&mut header, "PaxHeader", pax.as_slice())?;
+    }
+    tar.append_path_with_name(src, name)?;
+    Ok(())
+}
+
+/// Write the changes as a tarball in the layout of a container image layer.
+fn export_layer(upper: &Path, changes: &BTreeMap<PathBuf, Change>, dest: &Path) -> Result<()> {
+    let f = std::fs::File::create(dest).with_context(|| format!("Creating {:?}", dest))?;
+    let mut tar = tar::Builder::new(std::io::BufWriter::new(f));
+    tar.follow_symlinks(false);
+    let usr = Path::new("usr");
+    let mut dirs = BTreeSet::new();
+    for (path, change) in changes {
+        // Include the directories, for their metadata
+        let mut parents: Vec<_> = path
+            .ancestors()
+            .skip(1)
+            .filter(|p| !p.as_os_str().is_empty())
+            .collect();
+        parents.reverse();
+        for p in parents {
+            if dirs.insert(p.to_path_buf()) {
+                append_file(&mut tar, &upper.join(p), &usr.join(p))?;
+            }
+        }
+        match change {
+            Change::Changed => append_file(&mut tar, &upper.join(path), &usr.join(path))?,
+            Change::Replaced => {
+                if dirs.insert(path.to_path_buf()) {
+                    append_file(&mut tar, &upper.join(path), &usr.join(path))?;
+                }
+                let mut header = tar::Header::new_gnu();
+                header.set_entry_type(tar::EntryType::Regular);
+                header.set_size(0);
+                header.set_mode(0o644);
+                tar.append_data(
+                    &mut header,
+                    usr.join(path).join(".wh..wh..opq"),
+                    std::io::empty(),
+                )?;
+            }
+            Change::Deleted => {
+                let name = path.file_name().unwrap().to_string_lossy();
+                let whiteout = usr.join(path).with_file_name(format!(".wh.{}", name));
+                let mut header = tar::Header::new_gnu();
+                header.set_entry_type(tar::EntryType::Regular);
+                header.set_size(0);
+                header.set_mode(0o644);
+                tar.append_data(&mut header, whiteout, std::io::empty())?;
+            }
+        }
+    }
+    tar.into_inner()?.flush()?;
+    Ok(())
+}
+
+/// The spec of an RPM `name` with the files `paths` from the upper directory
+/// `upper`.
+fn rpm_spec(name: &str, version: &str, upper: &Path, paths: &[&Path]) -> Result<String> {
+    let mut install = String::new();
+    let mut files = String::new();
+    for path in paths {
+        let s = path
+            .to_str()
+            .ok_or_else(|| anyhow!("Invalid UTF-8: {:?}", path))?;
+        if s.contains(|c| matches!(c, '"' | '\'' | '%' | '\n')) {
+            bail!("Unsupported file name in RPM: {}", s);
+        }
+        let parent = path.parent().unwrap().display();
+        install.push_str(&format!(
+            "mkdir -p '%{{buildroot}}/usr/{}'\ncp -a '{}' '%{{buildroot}}/usr/{}'\n",
+            parent,
+            upper.join(path).display(),
+            s
+        ));
+        files.push_str(&format!("\"/usr/{}\"\n", s));
+    }
+    Ok(indoc::formatdoc! {r#"
+        Name: {name}
+        Version: {version}
+        Release: 1
+        Summary: Changes made in the overlay on /usr
+        License: Unspecified
+        AutoReqProv: no
+        %global debug_package %{{nil}}
+        %global __os_install_post %{{nil}}
+        %global _build_id_links none
+
+        %description
+        %{{summary}}
+
+        %install
+        {install}
+        %files
+        {files}"#,
+        name = name,
+        version = version,
+        install = install,
+        files = files
+    })
+}
+
+/// Build an RPM with the changed files; deleted files, replaced directories
+/// and extended attributes other than SELinux labels can't be represented.
+fn export_rpm(
+    upper: &Path,
+    changes: &BTreeMap<PathBuf, Change>,
+    name: &str,
+    dest: &Path,
+) -> Result<()> {
+    let mut unsupported: Vec<_> = changes
+        .iter()
+        .filter(|(_, c)| **c != Change::Changed)
+        .map(|(p, c)| describe_change(p, c))
+        .collect();
+    for (path, _) in changes.iter().filter(|(_, c)| **c == Change::Changed) {
+        let xattrs = read_xattrs(&upper.join(path))?;
+        if xattrs
+            .iter()
+            .any(|(k, _)| k.as_bytes() != b"security.selinux")
+        {
+            unsupported.push(format!(
+                "{} (extended attributes)",
+                describe_change(path, &Change::Changed)
+            ));
+        }
+    }
+    if !unsupported.is_empty() {
+        bail!(
+            "Changes can't be exported as an RPM (use --format=layer): {}",
+            unsupported.join(", ")
+        );
+    }
+    let paths: Vec<_> = changes.keys().map(|p| p.as_path()).collect();
+    let version = chrono::Utc::now().format("%Y%m%d.%H%M%S").to_string();
+    let tmpdir = tempfile::tempdir()?;
+    let spec = tmpdir.path().join(format!("{}.spec", name));
+    std::fs::write(&spec, rpm_spec(name, &version, upper, &paths)?)?;
+    let topdir = tmpdir.path().join("rpmbuild");
+    let status = Command::new("rpmbuild")
+        .arg("--quiet")
+        .arg("-bb")
+        .arg("--define")
+        .arg(format!("_topdir {}", topdir.display()))
+        .arg(&spec)
+        .status()
+        .context("Running rpmbuild")?;
+    if !status.success() {
+        bail!("rpmbuild: {}", status);
+    }
+    let rpm = std::fs::read_dir(topdir.join("RPMS"))?
+        .filter_map(|d| d.ok())
+        .filter_map(|d| std::fs::read_dir(d.path()).ok())
+        .flatten()
+        .filter_map(|f| f.ok())
+        .map(|f| f.path())
+        .find(|p| p.extension().map_or(false, |e| e == "rpm"))
+        .ok_or_else(|| anyhow!("rpmbuild didn't produce an RPM"))?;
+    std::fs::copy(&rpm, dest).with_context(|| format!("Copying to {:?}", dest))?;
+    Ok(())
+}
+
+fn export(opts: &UsrOverlayOpts, dest: &Path) -> Result<()> {
+    let upper = usr_upperdir()?.ok_or_else(|| anyhow!("No overlay mounted on /usr"))?;
+    let changes = collect_changes(&upper)?;
+    if changes.is_empty() {
+        bail!("No changes in the overlay on /usr");
+    }
+    match opts.format.unwrap_or(ExportFormat::Layer) {
+        ExportFormat::Layer => export_layer(&upper, &changes, dest)?,
+        ExportFormat::Rpm => {
+            let name = opts.name.as_deref().unwrap_or("usroverlay");
+            export_rpm(&upper, &changes, name, dest)?
+        }
+    }
+    println!("Exported {} change(s) to {}", changes.len(), dest.display());
+    Ok(())
+}
 
 /// Directly exec(ostree admin unlock) - does not return on success.
 pub fn entrypoint(args: &[&str]) -> Result<()> {
-    let cmd = cli_cmd();
-    cmd.get_matches_from(args.iter().skip(1));
+    let opts = UsrOverlayOpts::parse_from(args.iter().skip(1));
+
+    if opts.persist {
+        return persist(opts.force);
+    } else if opts.reset {
+        return reset();
+    } else if opts.restore {
+        return restore();
+    } else if let Some(dest) = opts.export.as_deref() {
+        return export(&opts, dest);
+    }
 
     let exec_err = std::process::Command::new("ostree")
         .args(&["admin", "unlock"])
@@ -20,20 +560,97 @@ pub fn entrypoint(args: &[&str]) -> Result<()> {
     Err(exec_err).context("Failed to execute 'ostree admin unlock'")
 }
 
-/// CLI parser, handle --help and error on extra arguments.
-fn cli_cmd() -> Command<'static> {
-    Command::new("rpm-ostree usroverlay")
-        .bin_name("rpm-ostree usroverlay")
-        .long_version("")
-        .long_about("Apply a transient overlayfs to /usr")
+/// "persistent" or "transient" if an overlay is mounted on /usr, or an empty
+/// string.
+pub(crate) fn usroverlay_mode() -> CxxResult<String> {
+    Ok(match usr_upperdir()? {
+        Some(upper) if is_persistent(&upper) => "persistent".into(),
+        Some(_) => "transient".into(),
+        None => "".into(),
+    })
+}
+
+/// The changes made in the overlay mounted on /usr, as shown by
+/// `rpm-ostree status`; reading them requires privileges.
+pub(crate) fn usroverlay_changes() -> CxxResult<Vec<String>> {
+    let upper = usr_upperdir()?.ok_or_else(|| anyhow!("No overlay mounted on /usr"))?;
+    Ok(collect_changes(&upper)?
+        .iter()
+        .map(|(p, c)| describe_change(p, c))
+        .collect())
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use clap::CommandFactory;
 
     #[test]
     fn test_clap_cmd() {
-        cli_cmd().debug_assert()
+        UsrOverlayOpts::command().debug_assert()
+    }
+
+    #[test]
+    fn test_parse_usr_upperdir() {
+        let mountinfo = indoc::indoc! {"
+            23 1 0:21 / / rw,relatime shared:1 - xfs /dev/vda4 rw,seclabel
+            25 23 0:21 /ostree/deploy/fedora/deploy/abc.0/usr /usr ro,relatime shared:2 - xfs /dev/vda4 ro,seclabel
+            99 25 0:45 / /usr rw,relatime shared:50 - overlay overlay rw,seclabel,lowerdir=usr,upperdir=/var/tmp/ostree-unlock-ovl.X1/upper,workdir=/var/tmp/ostree-unlock-ovl.X1/work
+        "};
+        assert_eq!(
+            parse_usr_upperdir(mountinfo).unwrap(),
+            Path::new("/var/tmp/ostree-unlock-ovl.X1/upper")
+        );
+        let mountinfo = mountinfo.lines().take(2).collect::<Vec<_>>().join("\n");
+        assert!(parse_usr_upperdir(&mountinfo).is_none());
+    }
+
+    #[test]
+    fn test_collect_changes() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        let upper = td.path();
+        std::fs::create_dir_all(upper.join("bin"))?;
+        std::fs::write(upper.join("bin/foo"), "foo")?;
+        std::os::unix::fs::symlink("foo", upper.join("bin/bar"))?;
+        let changes = collect_changes(upper)?;
+        assert_eq!(
+            changes.iter().collect::<Vec<_>>(),
+            [
+                (&PathBuf::from("bin/bar"), &Change::Changed),
+                (&PathBuf::from("bin/foo"), &Change::Changed)
+            ]
+        );
+        assert_eq!(
+            describe_change(Path::new("lib/baz"), &Change::Deleted),
+            "/usr/lib/baz (deleted)"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_pax_xattr_record() {
+        let name = CString::new("user.foo").unwrap();
+        let r = pax_xattr_record(&name, b"bar");
+        assert_eq!(r, b"29 SCHILY.xattr.user.foo=bar\n");
+        assert_eq!(r.len(), 29);
+        // Where the length gains a digit by counting its own
+        let value = vec![b'x'; 74];
+        let r = pax_xattr_record(&name, &value);
+        assert_eq!(r.len(), 101);
+        assert!(r.starts_with(b"101 "));
+        assert_eq!(
+            describe_change(Path::new("share/foo"), &Change::Replaced),
+            "/usr/share/foo (replaced)"
+        );
+    }
+
+    #[test]
+    fn test_rpm_spec() -> Result<()> {
+        let upper = Path::new("/var/lib/rpm-ostree/usroverlay/upper");
+        let spec = rpm_spec("hotfix", "1", upper, &[Path::new("bin/foo")])?;
+        assert!(spec.contains("cp -a '/var/lib/rpm-ostree/usroverlay/upper/bin/foo'"));
+        assert!(spec.ends_with("%files\n\"/usr/bin/foo\"\n"));
+        assert!(rpm_spec("hotfix", "1", upper, &[Path::new("bin/100%")]).is_err());
+        Ok(())
     }
 }
@@ -329,6 +329,19 @@ impl Bubblewrap {
         self.launcher.setenv(k, v, true);
     }
 
+    /// Normalize the environment so that the output of the child is
+    /// reproducible: the locale, the timezone and `SOURCE_DATE_EPOCH`.  This
+    /// also requires the network to be unshared.
+    pub(crate) fn set_reproducible(&mut self, source_date_epoch: &str) -> CxxResult<()> {
+        if running_in_nspawn() {
+            return Err(anyhow::anyhow!("Cannot unshare the network in systemd-nspawn").into());
+        }
+        self.setenv("LANG", "C");
+        self.setenv("TZ", "UTC");
+        self.setenv("SOURCE_DATE_EPOCH", source_date_epoch);
+        Ok(())
+    }
+
     /// Take a file descriptor
     pub(crate) fn take_fd(&mut self, source_fd: i32, target_fd: i32) {
         self.launcher.take_fd(source_fd, target_fd);
@@ -423,7 +436,7 @@ impl Bubblewrap {
     }
 
     /// Execute the container.  This method uses the normal gtk-rs `Option<T>` for the cancellable.
-    fn run_inner(&mut self, cancellable: Option<&gio::Cancellable>) -> Result<()> {
+    pub(crate) fn run_inner(&mut self, cancellable: Option<&gio::Cancellable>) -> Result<()> {
         let (child, argv0) = self.spawn()?;
         child_wait_check(child, cancellable).context(argv0)?;
         Ok(())
@@ -9,7 +9,10 @@ use crate::utils;
 use anyhow::{anyhow, Result};
 use cap_std_ext::rustix;
 use gio::prelude::*;
-use ostree_ext::{gio, glib};
+use ostree_ext::{gio, glib, ostree};
+use serde_derive::Serialize;
+use std::collections::HashMap;
+use std::io::{Read, Seek};
 use std::os::unix::io::IntoRawFd;
 use std::process::Command;
 
@@ -24,7 +27,6 @@ const OS_EX_INTERFACE: &str = "org.projectatomic.rpmostree1.OSExperimental";
 /// This currently wraps a C++ client connection.
 pub(crate) struct ClientConnection {
     conn: cxx::UniquePtr<crate::ffi::ClientConnection>,
-    #[allow(dead_code)]
     sysroot_proxy: gio::DBusProxy,
     #[allow(dead_code)]
     booted_proxy: gio::DBusProxy,
@@ -83,7 +85,6 @@ impl ClientConnection {
     }
 
     /// Returns a proxy for the sysroot
-    #[allow(dead_code)]
     pub(crate) fn get_sysroot_proxy(&self) -> &gio::DBusProxy {
         &self.sysroot_proxy
     }
@@ -122,6 +123,73 @@ pub(crate) fn is_src_rpm_arg(arg: &str) -> bool {
     arg.ends_with(".src.rpm")
 }
 
+/// URL fragment used to pin the expected SHA-256 of a downloaded RPM.
+const SHA256_FRAGMENT: &str = "#sha256=";
+
+/// Split an optional `#sha256=<checksum>` suffix off a URL.
+fn split_url_sha256(arg: &str) -> Result<(&str, Option<&str>)> {
+    match arg.rsplit_once(SHA256_FRAGMENT) {
+        Some((url, sha256)) => {
+            ostree::validate_checksum_string(sha256)
+                .map_err(|e| anyhow!("Invalid checksum for {}: {}", url, e))?;
+            Ok((url, Some(sha256)))
+        }
+        None => Ok((arg, None)),
+    }
+}
+
+/// Attach the checksums given via `--sha256` to the HTTP(S) arguments, in order.
+pub(crate) fn client_pin_url_checksums(
+    pkgs: &Vec<String>,
+    checksums: &Vec<String>,
+) -> CxxResult<Vec<String>> {
+    let n_urls = pkgs.iter().filter(|p| is_http_arg(p)).count();
+    if n_urls != checksums.len() {
+        return Err(anyhow!(
+            "Got {} checksums for {} package URLs; --sha256 must be given once per URL",
+            checksums.len(),
+            n_urls
+        )
+        .into());
+    }
+    let mut checksums = checksums.iter();
+    Ok(pkgs
+        .iter()
+        .map(|p| {
+            if is_http_arg(p) {
+                // Unwrap safety: we checked the counts above
+                format!("{}{}{}", p, SHA256_FRAGMENT, checksums.next().unwrap())
+            } else {
+                p.clone()
+            }
+        })
+        .collect())
+}
+
+/// Verify that the content of `f` has the given SHA-256, and rewind it.
+fn verify_sha256(f: &mut std::fs::File, url: &str, expected: &str) -> Result<()> {
+    let mut hasher = glib::Checksum::new(glib::ChecksumType::Sha256).unwrap();
+    let mut buf = [0u8; 8192];
+    loop {
+        let n = f.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    let actual = hasher.string().expect("hash");
+    if actual != expected {
+        return Err(anyhow!(
+            "Checksum mismatch for {}: expected {}, got {}",
+            url,
+            expected,
+            actual
+        ));
+    }
+    f.seek(std::io::SeekFrom::Start(0))?;
+    Ok(())
+}
+
 /// Given a string from the command line, determine if it represents one or more
 /// RPM URLs we need to fetch, and if so download those URLs and return file
 /// descriptors for the content.
@@ -131,6 +199,16 @@ pub(crate) fn client_handle_fd_argument(
     arch: &str,
     is_replace: bool,
 ) -> CxxResult<Vec<i32>> {
+    let (arg, sha256) = split_url_sha256(arg)?;
+    if let Some(sha256) = sha256 {
+        if !is_http_arg(arg) {
+            return Err(anyhow!("A checksum can only be given for HTTP(S) URLs: {}", arg).into());
+        }
+        let mut f = utils::download_url_to_tmpfile(arg, true)?;
+        verify_sha256(&mut f, arg, sha256)?;
+        return Ok(vec![f.into_raw_fd()]);
+    }
+
     #[cfg(feature = "fedora-integration")]
     if let Some(fds) = crate::fedora_integration::handle_cli_arg(arg, arch, is_replace)? {
         return Ok(fds.into_iter().map(|f| f.into_raw_fd()).collect());
@@ -256,6 +334,127 @@ pub(crate) fn client_render_download_progress(progress: &crate::ffi::GVariant) -
     }
 }
 
+/// The package and file differences of a pending update, as printed by
+/// `rpm-ostree upgrade --preview-diff`.
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+struct UpdateDiff {
+    checksum: Option<String>,
+    version: Option<String>,
+    packages: PackageDiff,
+    files: FileDiff,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+struct PackageDiff {
+    added: Vec<String>,
+    removed: Vec<String>,
+    upgraded: Vec<PackageChange>,
+    downgraded: Vec<PackageChange>,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+struct PackageChange {
+    name: String,
+    arch: String,
+    from: String,
+    to: String,
+}
+
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+struct FileDiff {
+    added: Vec<String>,
+    removed: Vec<String>,
+    modified: Vec<String>,
+}
+
+/// RPM_OSTREE_PACKAGE_DOWNGRADED in the a(sua{sv}) package diff format
+const PACKAGE_DOWNGRADED: u32 = 3;
+
+/// A (name, evr, arch) package in the a(sua{sv}) package diff format.
+type PackageTriple = (String, String, String);
+
+/// An entry of the a(sua{sv}) package diff format: the name, the type of change, and
+/// the previous and new package.
+type PackageDiffEntry = (String, u32, Option<PackageTriple>, Option<PackageTriple>);
+
+impl PackageDiff {
+    fn new(entries: Vec<PackageDiffEntry>) -> Result<Self> {
+        let mut r = Self::default();
+        for (name, kind, previous, new) in entries {
+            match (previous, new) {
+                (None, Some((name, evr, arch))) => {
+                    r.added.push(format!("{name}-{evr}.{arch}"));
+                }
+                (Some((name, evr, arch)), None) => {
+                    r.removed.push(format!("{name}-{evr}.{arch}"));
+                }
+                (Some((_, from, _)), Some((_, to, arch))) => {
+                    let change = PackageChange {
+                        name,
+                        arch,
+                        from,
+                        to,
+                    };
+                    if kind == PACKAGE_DOWNGRADED {
+                        r.downgraded.push(change);
+                    } else {
+                        r.upgraded.push(change);
+                    }
+                }
+                (None, None) => return Err(anyhow!("Invalid package diff entry: {}", name)),
+            }
+        }
+        Ok(r)
+    }
+}
+
+impl UpdateDiff {
+    /// Parse the result of the GetCachedUpdateDiff DBus API.
+    fn from_variant(diff: &glib::Variant) -> Result<Self> {
+        let diff = glib::VariantDict::new(Some(diff));
+        let mut r = Self::default();
+        if let Some(details) = diff.lookup_value("details", None) {
+            let details = glib::VariantDict::new(Some(&details));
+            r.checksum = details.lookup("checksum").map_err(anyhow::Error::msg)?;
+            r.version = details.lookup("version").map_err(anyhow::Error::msg)?;
+        }
+        if let Some(packages) = diff.lookup_value("packages", None) {
+            let packages = packages
+                .get::<Vec<(String, u32, HashMap<String, glib::Variant>)>>()
+                .ok_or_else(|| anyhow!("Invalid package diff"))?;
+            let entries = packages
+                .into_iter()
+                .map(|(name, kind, info)| {
+                    let get = |k: &str| info.get(k).and_then(|v| v.get::<PackageTriple>());
+                    (name, kind, get("PreviousPackage"), get("NewPackage"))
+                })
+                .collect();
+            r.packages = PackageDiff::new(entries)?;
+        }
+        if let Some(files) = diff.lookup_value("files", None) {
+            let files = glib::VariantDict::new(Some(&files));
+            let lookup = |k: &str| -> Result<Vec<String>> {
+                Ok(files
+                    .lookup(k)
+                    .map_err(anyhow::Error::msg)?
+                    .unwrap_or_default())
+            };
+            r.files = FileDiff {
+                added: lookup("added")?,
+                removed: lookup("removed")?,
+                modified: lookup("modified")?,
+            };
+        }
+        Ok(r)
+    }
+}
+
+/// Render the result of the GetCachedUpdateDiff DBus API as JSON.
+pub(crate) fn client_render_update_diff_json(diff: &crate::ffi::GVariant) -> CxxResult<String> {
+    let diff = UpdateDiff::from_variant(&diff.glib_reborrow())?;
+    Ok(serde_json::to_string_pretty(&diff)? + "\n")
+}
+
 pub(crate) fn running_in_container() -> bool {
     ostree_ext::container_utils::running_in_container()
 }
@@ -318,4 +517,58 @@ mod tests {
             running_in_container()
         );
     }
+
+    #[test]
+    fn test_pin_url_checksums() {
+        let sha256 = "a".repeat(64);
+        let pkgs = vec!["foo".to_string(), "https://example.com/bar.rpm".to_string()];
+        let pinned = client_pin_url_checksums(&pkgs, &vec![sha256.clone()]).unwrap();
+        assert_eq!(pinned[0], "foo");
+        assert_eq!(
+            split_url_sha256(&pinned[1]).unwrap(),
+            ("https://example.com/bar.rpm", Some(sha256.as_str()))
+        );
+        assert!(client_pin_url_checksums(&pkgs, &vec![]).is_err());
+        assert!(client_pin_url_checksums(&pkgs, &vec![sha256.clone(), sha256]).is_err());
+        assert_eq!(split_url_sha256("foo").unwrap(), ("foo", None));
+        assert!(split_url_sha256("https://example.com/bar.rpm#sha256=nope").is_err());
+    }
+
+    #[test]
+    fn test_package_diff() -> Result<()> {
+        let pkg = |name: &str, evr: &str| Some((name.into(), evr.into(), "x86_64".into()));
+        let diff = PackageDiff::new(vec![
+            ("bash".into(), 2, pkg("bash", "5.1-1"), pkg("bash", "5.2-1")),
+            (
+                "vim".into(),
+                3,
+                pkg("vim", "2:9.0-2"),
+                pkg("vim", "2:9.0-1"),
+            ),
+            ("nano".into(), 1, pkg("nano", "6.0-1"), None),
+            ("fish".into(), 0, None, pkg("fish", "3.5-1")),
+        ])?;
+        assert_eq!(diff.added, ["fish-3.5-1.x86_64"]);
+        assert_eq!(diff.removed, ["nano-6.0-1.x86_64"]);
+        assert_eq!(
+            diff.upgraded,
+            [PackageChange {
+                name: "bash".into(),
+                arch: "x86_64".into(),
+                from: "5.1-1".into(),
+                to: "5.2-1".into(),
+            }]
+        );
+        assert_eq!(diff.downgraded[0].to, "2:9.0-1");
+        assert!(PackageDiff::new(vec![("bash".into(), 2, None, None)]).is_err());
+
+        let json = serde_json::to_value(UpdateDiff {
+            packages: diff,
+            ..Default::default()
+        })?;
+        assert_eq!(json["packages"]["upgraded"][0]["from"], "5.1-1");
+        assert_eq!(json["files"]["modified"], serde_json::json!([]));
+        assert_eq!(json["checksum"], serde_json::Value::Null);
+        Ok(())
+    }
 }
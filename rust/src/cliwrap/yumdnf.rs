@@ -1,8 +1,9 @@
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 
-use anyhow::{anyhow, Result};
-use clap::Parser;
+use anyhow::{anyhow, bail, Result};
+use clap::{Parser, Subcommand};
 use indoc::indoc;
+use std::io::{BufRead, Write};
 use std::os::unix::process::CommandExt;
 use std::path::Path;
 use std::process::Command;
@@ -36,6 +37,23 @@ Install RPM packages layered on the host root filesystem.
 Consider these "operating system extensions".
 Add `--apply-live` to immediately start using the layered packages."#};
 
+const INSTALL_EXPLANATION: &str = indoc! { r#"
+Packages aren't installed into the running system: they are layered on top of
+the base image in a new deployment, which is used from the next boot, or right
+away with `rpm-ostree apply-live`.  Layered packages are kept across upgrades
+until they are removed with `rpm-ostree uninstall`."#};
+
+const REMOVE_EXPLANATION: &str = indoc! { r#"
+Packages aren't removed from the running system: a new deployment is created
+without them, which is used from the next boot.  Packages of the base image
+are removed with `rpm-ostree override remove`, which can be undone with
+`rpm-ostree override reset`."#};
+
+const UPGRADE_EXPLANATION: &str = indoc! { r#"
+Packages can't be upgraded individually: the base image and all the layered
+packages are upgraded together in a new deployment, which is used from the
+next boot."#};
+
 #[derive(Debug, Parser)]
 #[clap(
     name = "yumdnf-rpmostree",
@@ -44,34 +62,63 @@ Add `--apply-live` to immediately start using the layered packages."#};
 )]
 #[clap(rename_all = "kebab-case")]
 /// Main options struct
+struct Cli {
+    /// Automatically answer yes for all questions
+    #[clap(long, short = 'y', global = true)]
+    assumeyes: bool,
+
+    #[clap(subcommand)]
+    cmd: Opt,
+}
+
+#[derive(Debug, Subcommand)]
+#[clap(rename_all = "kebab-case")]
+/// The handled yum/dnf verbs
 enum Opt {
     /// Start an upgrade of the operating system
-    Upgrade,
-    /// Start an upgrade of the operating system
-    Update,
+    #[clap(alias = "update")]
+    Upgrade {
+        /// Packages to upgrade; the whole system is upgraded anyway
+        packages: Vec<String>,
+    },
     /// Display information about system state
     Status,
-    /// Perform a search of packages.
+    /// Search for packages in the deployments of the system
     Search {
         /// Search terms
-        #[allow(dead_code)]
         terms: Vec<String>,
     },
-    /// Will return an error suggesting other approaches.
+    /// Layer packages on the operating system
     Install {
         /// Set of packages to install
         packages: Vec<String>,
     },
+    /// Remove packages from the operating system
+    #[clap(alias = "erase")]
+    Remove {
+        /// Set of packages to remove
+        packages: Vec<String>,
+    },
     Clean {
         subargs: Vec<String>,
     },
 }
 
+/// An rpm-ostree operation to run once the user confirmed it.
+#[derive(Debug, PartialEq, Eq)]
+struct Confirmation {
+    /// How the operation works on an image based system.
+    explanation: &'static str,
+    /// Whether to suggest the alternatives to layering packages.
+    alternatives: bool,
+    args: Vec<String>,
+}
+
 #[derive(Debug, PartialEq, Eq)]
 enum RunDisposition {
     HelpOrVersionDisplayed,
     ExecRpmOstree(Vec<String>),
-    UseSomethingElse,
+    ConfirmExecRpmOstree(Confirmation),
     NotImplementedYet(&'static str),
     Unsupported,
     Unhandled,
@@ -91,8 +138,39 @@ fn run_clean(argv: &Vec<String>) -> Result<RunDisposition> {
     }
 }
 
-fn disposition(hosttype: SystemHostType, argv: &[&str]) -> Result<RunDisposition> {
-    let opt = match Opt::try_parse_from(std::iter::once(&"yum").chain(argv.iter())) {
+/// The arguments of rpm-ostree to remove `packages`: the layered ones (as in
+/// `layered`) are uninstalled, and the ones of the base image overridden.
+fn remove_args(packages: Vec<String>, layered: &[String]) -> Vec<String> {
+    let (uninstall, base): (Vec<_>, Vec<_>) =
+        packages.into_iter().partition(|p| layered.contains(p));
+    if base.is_empty() {
+        return std::iter::once("uninstall".to_string())
+            .chain(uninstall)
+            .collect();
+    }
+    ["override", "remove"]
+        .iter()
+        .map(|&s| String::from(s))
+        .chain(base)
+        .chain(uninstall.into_iter().map(|p| format!("--uninstall={}", p)))
+        .collect()
+}
+
+/// Ask for confirmation unless `assumeyes` was passed.
+fn confirm(assumeyes: bool, confirmation: Confirmation) -> RunDisposition {
+    if assumeyes {
+        RunDisposition::ExecRpmOstree(confirmation.args)
+    } else {
+        RunDisposition::ConfirmExecRpmOstree(confirmation)
+    }
+}
+
+fn disposition(
+    hosttype: SystemHostType,
+    argv: &[&str],
+    layered: impl FnOnce() -> Result<Vec<String>>,
+) -> Result<RunDisposition> {
+    let cli = match Cli::try_parse_from(std::iter::once(&"yum").chain(argv.iter())) {
         Ok(v) => v,
         Err(e)
             if e.kind() == clap::ErrorKind::DisplayVersion
@@ -104,31 +182,56 @@ fn disposition(hosttype: SystemHostType, argv: &[&str]) -> Result<RunDisposition
             return Ok(RunDisposition::Unhandled);
         }
     };
+    let assumeyes = cli.assumeyes;
 
     let disp = match hosttype {
         SystemHostType::OstreeHost => {
-            match opt {
-                Opt::Upgrade | Opt::Update => RunDisposition::ExecRpmOstree(vec!["upgrade".into()]),
+            match cli.cmd {
+                Opt::Upgrade { packages } if packages.is_empty() => RunDisposition::ExecRpmOstree(vec!["upgrade".into()]),
+                Opt::Upgrade { .. } => confirm(assumeyes, Confirmation {
+                    explanation: UPGRADE_EXPLANATION,
+                    alternatives: false,
+                    args: vec!["upgrade".into()],
+                }),
                 Opt::Status => RunDisposition::ExecRpmOstree(vec!["status".into()]),
-                Opt::Install { packages: _ } => {
-                    // TODO analyze packages to find e.g. `gcc` (not ok, use `toolbox`) versus `libvirt` (ok)
-                    RunDisposition::UseSomethingElse
+                Opt::Install { mut packages } => {
+                    packages.insert(0, "install".into());
+                    confirm(assumeyes, Confirmation {
+                        explanation: INSTALL_EXPLANATION,
+                        alternatives: true,
+                        args: packages,
+                    })
                 },
+                Opt::Remove { packages } => {
+                    let args = remove_args(packages, &layered()?);
+                    confirm(assumeyes, Confirmation {
+                        explanation: REMOVE_EXPLANATION,
+                        alternatives: false,
+                        args,
+                    })
+                }
                 Opt::Clean { subargs } => {
                     run_clean(&subargs)?
                 }
-                Opt::Search { .. } => RunDisposition::NotImplementedYet(indoc! { r##"
-            Package search is not yet implemented.
-            For now, it's recommended to use e.g. `toolbox` and `dnf search` inside there.
+                Opt::Search { terms } => match terms.as_slice() {
+                    [term] => RunDisposition::ExecRpmOstree(vec!["db".into(), "search".into(), format!("*{}*", term)]),
+                    _ => RunDisposition::NotImplementedYet(indoc! { r##"
+            Only searching for a single term is implemented, in the packages of the deployments.
+            To search the repositories, it's recommended to use e.g. `toolbox` and `dnf search` inside there.
             "##}),
+                },
             }
         },
-        SystemHostType::OstreeContainer => match opt {
-            Opt::Upgrade | Opt::Update => RunDisposition::NotImplementedYet("At the current time, it is not supported to update packages independently of the base image."),
+        SystemHostType::OstreeContainer => match cli.cmd {
+            Opt::Upgrade { .. } => RunDisposition::NotImplementedYet("At the current time, it is not supported to update packages independently of the base image."),
             Opt::Install { mut packages } => {
                 packages.insert(0, "install".into());
                 RunDisposition::ExecRpmOstree(packages)
             },
+            Opt::Remove { mut packages } => {
+                packages.splice(0..0, ["override".into(), "remove".into()]);
+                RunDisposition::ExecRpmOstree(packages)
+            },
             Opt::Clean { subargs } => run_clean(&subargs)?,
             Opt::Status => RunDisposition::ExecRpmOstree(vec!["status".into()]),
             Opt::Search { .. } => {
@@ -141,35 +244,80 @@ fn disposition(hosttype: SystemHostType, argv: &[&str]) -> Result<RunDisposition
     Ok(disp)
 }
 
+/// The packages layered on the booted deployment.
+fn booted_layered_packages() -> Result<Vec<String>> {
+    let client = rpmostree_client::CliClient::new("cliwrap");
+    let status = client.query_status().map_err(anyhow::Error::msg)?;
+    let booted = status
+        .deployments
+        .into_iter()
+        .find(|d| d.booted)
+        .ok_or_else(|| anyhow!("Not booted into any deployment"))?;
+    Ok(booted.requested_packages)
+}
+
+/// Print the alternatives to layering packages which are available.
+fn print_alternatives() {
+    let mut valid_options: Vec<_> = OTHER_OPTIONS
+        .iter()
+        .filter(|(cmd, _)| Path::new(&format!("/usr/bin/{}", cmd)).exists())
+        .collect();
+    if !valid_options.is_empty() {
+        eprintln!(
+            "{}",
+            tr!("Before installing packages to the host root filesystem, consider other options:")
+        );
+    } else {
+        eprintln!("{}", tr!("To explicitly perform the operation:"));
+    }
+    valid_options.push(&("rpm-ostree install", RPMOSTREE_INSTALL_TEXT));
+    for (cmd, text) in valid_options {
+        let mut lines = text.lines();
+        eprintln!(" - `{}`: {}", cmd, lines.next().unwrap());
+        for line in lines {
+            eprintln!("   {}", line)
+        }
+    }
+}
+
+/// Explain the operation, and return whether the user wants to go ahead.
+fn ask_confirmation(confirmation: &Confirmation) -> Result<bool> {
+    if confirmation.alternatives {
+        print_alternatives();
+    }
+    eprintln!("{}", confirmation.explanation);
+    eprintln!(
+        "{}",
+        tr!("This will run: rpm-ostree {}", confirmation.args.join(" "))
+    );
+    if !nix::unistd::isatty(libc::STDIN_FILENO)? {
+        bail!("Refusing to continue without confirmation; pass -y to proceed");
+    }
+    // TRANSLATORS: the answers are only accepted in English
+    eprint!("{}", tr!("Is this ok [y/N]: "));
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
 /// Primary entrypoint to running our wrapped `yum`/`dnf` handling.
 pub(crate) fn main(hosttype: SystemHostType, argv: &[&str]) -> Result<()> {
-    match disposition(hosttype, argv)? {
+    match disposition(hosttype, argv, booted_layered_packages)? {
         RunDisposition::HelpOrVersionDisplayed => Ok(()),
         RunDisposition::ExecRpmOstree(args) => {
             eprintln!("{}", IMAGEBASED);
             Err(Command::new("rpm-ostree").args(args).exec().into())
         }
-        RunDisposition::UseSomethingElse => {
+        RunDisposition::ConfirmExecRpmOstree(confirmation) => {
             eprintln!("{}", IMAGEBASED);
-            let mut valid_options: Vec<_> = OTHER_OPTIONS
-                .iter()
-                .filter(|(cmd, _)| Path::new(&format!("/usr/bin/{}", cmd)).exists())
-                .collect();
-            if !valid_options.is_empty() {
-                eprintln!("Before installing packages to the host root filesystem, consider other options:");
-            } else {
-                eprintln!("To explicitly perform the operation:");
-            }
-            valid_options.push(&("rpm-ostree install", RPMOSTREE_INSTALL_TEXT));
-            for (cmd, text) in valid_options {
-                let mut lines = text.lines();
-                eprintln!(" - `{}`: {}", cmd, lines.next().unwrap());
-                for line in lines {
-                    eprintln!("   {}", line)
-                }
+            if !ask_confirmation(&confirmation)? {
+                bail!("Operation aborted.");
             }
-
-            Err(anyhow!("not implemented"))
+            Err(Command::new("rpm-ostree")
+                .args(confirmation.args)
+                .exec()
+                .into())
         }
         RunDisposition::Unhandled => Err(anyhow!("{}", UNHANDLED)),
         RunDisposition::Unsupported => Err(anyhow!(
@@ -183,60 +331,108 @@ pub(crate) fn main(hosttype: SystemHostType, argv: &[&str]) -> Result<()> {
 mod tests {
     use super::*;
 
+    fn strvec(s: impl IntoIterator<Item = &'static str>) -> Vec<String> {
+        s.into_iter().map(|s| String::from(s)).collect()
+    }
+
+    fn disp(hosttype: SystemHostType, argv: &[&str]) -> Result<RunDisposition> {
+        disposition(hosttype, argv, || Ok(strvec(["layered"])))
+    }
+
     #[test]
     fn test_yumdnf() -> Result<()> {
         for common in [SystemHostType::OstreeContainer, SystemHostType::OstreeHost] {
             assert!(matches!(
-                disposition(common, &["--version"])?,
+                disp(common, &["--version"])?,
                 RunDisposition::HelpOrVersionDisplayed
             ));
             assert!(matches!(
-                disposition(common, &["--help"])?,
+                disp(common, &["--help"])?,
                 RunDisposition::HelpOrVersionDisplayed
             ));
             assert!(matches!(
-                disposition(common, &["unknown", "--other"])?,
+                disp(common, &["unknown", "--other"])?,
                 RunDisposition::Unhandled
             ));
             assert!(matches!(
-                disposition(common, &["search", "foo", "bar"])?,
+                disp(common, &["search", "foo", "bar"])?,
                 RunDisposition::NotImplementedYet(_)
             ));
         }
 
         // Tests for the ostree host case
         let host = SystemHostType::OstreeHost;
+        assert_eq!(
+            disp(host, &["upgrade"])?,
+            RunDisposition::ExecRpmOstree(strvec(["upgrade"]))
+        );
+        assert_eq!(
+            disp(host, &["update", "foo"])?,
+            RunDisposition::ConfirmExecRpmOstree(Confirmation {
+                explanation: UPGRADE_EXPLANATION,
+                alternatives: false,
+                args: strvec(["upgrade"]),
+            })
+        );
+        assert_eq!(
+            disp(host, &["install", "foo", "bar"])?,
+            RunDisposition::ConfirmExecRpmOstree(Confirmation {
+                explanation: INSTALL_EXPLANATION,
+                alternatives: true,
+                args: strvec(["install", "foo", "bar"]),
+            })
+        );
+        for argv in [&["-y", "install", "foo"], &["install", "-y", "foo"]] {
+            assert_eq!(
+                disp(host, argv)?,
+                RunDisposition::ExecRpmOstree(strvec(["install", "foo"]))
+            );
+        }
+        assert_eq!(
+            disp(host, &["-y", "erase", "layered"])?,
+            RunDisposition::ExecRpmOstree(strvec(["uninstall", "layered"]))
+        );
+        assert_eq!(
+            disp(host, &["search", "foo"])?,
+            RunDisposition::ExecRpmOstree(strvec(["db", "search", "*foo*"]))
+        );
+        // The status is only queried to remove packages
         assert!(matches!(
-            disposition(host, &["upgrade"])?,
-            RunDisposition::ExecRpmOstree(_)
-        ));
-        assert!(matches!(
-            disposition(host, &["install", "foo"])?,
-            RunDisposition::UseSomethingElse
-        ));
-        assert!(matches!(
-            disposition(host, &["install", "foo", "bar"])?,
-            RunDisposition::UseSomethingElse
+            disposition(host, &["install", "foo"], || bail!("no status"))?,
+            RunDisposition::ConfirmExecRpmOstree(_)
         ));
 
-        fn strvec(s: impl IntoIterator<Item = &'static str>) -> Vec<String> {
-            s.into_iter().map(|s| String::from(s)).collect()
-        }
-
         // Tests for the ostree container case
         let host = SystemHostType::OstreeContainer;
         assert_eq!(
-            disposition(host, &["install", "foo", "bar"])?,
+            disp(host, &["install", "foo", "bar"])?,
             RunDisposition::ExecRpmOstree(strvec(["install", "foo", "bar"]))
         );
         assert_eq!(
-            disposition(host, &["clean", "all"])?,
+            disp(host, &["remove", "foo"])?,
+            RunDisposition::ExecRpmOstree(strvec(["override", "remove", "foo"]))
+        );
+        assert_eq!(
+            disp(host, &["clean", "all"])?,
             RunDisposition::ExecRpmOstree(strvec(["cleanup", "-m"]))
         );
         assert!(matches!(
-            disposition(host, &["upgrade"])?,
+            disp(host, &["upgrade"])?,
             RunDisposition::NotImplementedYet(_)
         ));
         Ok(())
     }
+
+    #[test]
+    fn test_remove_args() {
+        let layered = strvec(["foo", "bar"]);
+        assert_eq!(
+            remove_args(strvec(["foo", "bar"]), &layered),
+            strvec(["uninstall", "foo", "bar"])
+        );
+        assert_eq!(
+            remove_args(strvec(["foo", "vim", "bash"]), &layered),
+            strvec(["override", "remove", "vim", "bash", "--uninstall=foo"])
+        );
+    }
 }
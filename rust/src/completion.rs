@@ -0,0 +1,915 @@
+//! Shell completion, for `rpm-ostree completion bash|zsh|fish`.
+//!
+//! The scripts are generated from the definitions of the commands themselves.
+//! The C++ commands are run in a describe mode up to the parsing of their
+//! options, which records their tables of subcommands, option entries and
+//! parameter strings instead; see `describe_command()` in `libmain.cxx`.  The
+//! Rust commands contribute their clap definitions, through `parse_args()` when
+//! they're dispatched from C++, and from `rust_command()` otherwise.  Which
+//! values are completed follows from the placeholders of the arguments, like
+//! `DEPLOYMENT` or `[REV]`.
+//!
+//! Values like deployment indexes and checksums, layered packages and remotes
+//! are listed from the system by the hidden `rpm-ostree completion complete`;
+//! this saves copy-pasting them from `rpm-ostree status`.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::deployment_generate_id_impl;
+use anyhow::{anyhow, Result};
+use clap::{Command, CommandFactory, Parser};
+use ostree_ext::{gio, glib, ostree};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+/// Generate shell completion scripts
+#[derive(Debug, Parser)]
+#[clap(name = "rpm-ostree completion", bin_name = "rpm-ostree completion")]
+#[clap(rename_all = "kebab-case")]
+enum Opts {
+    /// Print the completion script for bash
+    Bash,
+    /// Print the completion script for zsh
+    Zsh,
+    /// Print the completion script for fish
+    Fish,
+    /// List the candidates of a kind, for the scripts
+    #[clap(hide = true)]
+    Complete {
+        #[clap(value_enum)]
+        kind: Kind,
+        /// For options, the command as named in the scripts, e.g. `rpm-ostree__db__diff`
+        path: Option<String>,
+        /// Print a description after a tab
+        #[clap(long)]
+        describe: bool,
+    },
+}
+
+/// What to complete.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// The options of a command
+    Options,
+    /// Deployment indexes, and booted, pending and rollback
+    Deployments,
+    /// Deployment indexes
+    Indexes,
+    /// Deployment checksums
+    Revisions,
+    /// The layered packages of the default deployment
+    Packages,
+    /// Remote names
+    Remotes,
+    /// Remote names with a colon, and the refspecs of the deployments
+    Refspecs,
+}
+
+impl Kind {
+    /// The kind of the values of an argument from its placeholder, e.g.
+    /// `DEPLOYMENT` or `[FROM_REV]`; `name` is that of its option or command.
+    fn for_value(value_name: &str, name: &str) -> Option<Self> {
+        let value_name = value_name.trim_matches(|c| matches!(c, '[' | ']' | '.' | '…'));
+        let kind = match value_name {
+            "DEPLOYMENT" => Self::Deployments,
+            "INDEX" => Self::Indexes,
+            "REV" | "REVISION" | "FROM_REV" | "COMMIT" => Self::Revisions,
+            "REMOTE" => Self::Remotes,
+            "REFSPEC" => Self::Refspecs,
+            // Only the packages to remove are known
+            "PKG" | "PACKAGE" if name == "uninstall" => Self::Packages,
+            _ => return None,
+        };
+        Some(kind)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Options => "options",
+            Self::Deployments => "deployments",
+            Self::Indexes => "indexes",
+            Self::Revisions => "revisions",
+            Self::Packages => "packages",
+            Self::Remotes => "remotes",
+            Self::Refspecs => "refspecs",
+        }
+    }
+}
+
+thread_local! {
+    /// Set while a C++ command is described; `parse_args()` stores the
+    /// definition of the Rust command it delegates to.
+    static DESCRIBING: RefCell<Option<Option<Command<'static>>>> = RefCell::new(None);
+}
+
+/// Parse the arguments of a Rust command dispatched from the C++ tables of
+/// commands.  While the command line is described for completion, this records
+/// the definition of the command instead, and fails so that it stops there.
+pub(crate) fn parse_args<T: Parser>(args: &[String]) -> Result<T> {
+    let describing = DESCRIBING.with(|d| match d.borrow_mut().as_mut() {
+        Some(slot) => {
+            *slot = Some(T::command());
+            true
+        }
+        None => false,
+    });
+    if describing {
+        return Err(anyhow!("Describing the command line"));
+    }
+    Ok(T::parse_from(args.iter()))
+}
+
+/// The definitions of the commands which are listed without a C++ function in
+/// `libmain.cxx`, and dispatched from `main.rs`.
+fn rust_command(name: &str) -> Option<Command<'static>> {
+    let cmd = match name {
+        "audit-log" => crate::builtins::audit_log::Opts::command(),
+        "completion" => Opts::command(),
+        "diff" => crate::deployment_diff::Opts::command(),
+        "pin" => crate::pin::PinOpts::command(),
+        "testdeploy" => crate::testdeploy::TestDeployOpts::command(),
+        "usroverlay" => crate::builtins::usroverlay::UsrOverlayOpts::command(),
+        "verify" => crate::verify::Opts::command(),
+        _ => return None,
+    };
+    Some(cmd)
+}
+
+/// An option of a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CliOpt {
+    /// The spellings, e.g. `-d` and `--deployment`
+    flags: Vec<String>,
+    description: String,
+    /// What its value is completed with
+    kind: Option<Kind>,
+}
+
+/// A command, named by the path of subcommands to it like
+/// `rpm-ostree__db__diff`.
+#[derive(Debug, Default)]
+struct Node {
+    path: String,
+    name: String,
+    description: String,
+    aliases: Vec<String>,
+    options: Vec<CliOpt>,
+    /// What the positional arguments are completed with
+    positional: Option<Kind>,
+    subcommands: Vec<Node>,
+}
+
+impl Node {
+    fn from_clap(path: String, name: &str, cmd: &Command) -> Self {
+        let options = cmd
+            .get_arguments()
+            .filter(|a| !a.is_positional() && !a.is_hide_set())
+            .map(|a| {
+                let short = a.get_short().map(|c| format!("-{}", c));
+                let long = a.get_long().map(|l| format!("--{}", l));
+                let kind = a
+                    .get_value_names()
+                    .and_then(|v| v.first())
+                    .and_then(|v| Kind::for_value(v, a.get_long().unwrap_or_default()));
+                CliOpt {
+                    flags: short.into_iter().chain(long).collect(),
+                    description: a.get_help().unwrap_or_default().to_string(),
+                    kind,
+                }
+            })
+            .collect();
+        let positional = cmd
+            .get_positionals()
+            .find_map(|a| Kind::for_value(a.get_value_names()?.first()?, name));
+        let subcommands = cmd
+            .get_subcommands()
+            .filter(|c| !c.is_hide_set())
+            .map(|c| Self::from_clap(format!("{}__{}", path, c.get_name()), c.get_name(), c))
+            .collect();
+        Self {
+            path,
+            name: name.to_string(),
+            description: cmd.get_about().unwrap_or_default().to_string(),
+            aliases: cmd.get_all_aliases().map(String::from).collect(),
+            options,
+            positional,
+            subcommands,
+        }
+    }
+
+    /// The subcommands of a C++ command are only named.
+    fn from_cxx(path: String, name: &str, cli: &crate::ffi::CliDescription) -> Self {
+        let options = cli
+            .options
+            .iter()
+            .map(|o| {
+                let short = Some(&o.short_name)
+                    .filter(|s| !s.is_empty())
+                    .map(|s| format!("-{}", s));
+                CliOpt {
+                    flags: short
+                        .into_iter()
+                        .chain(std::iter::once(format!("--{}", o.long_name)))
+                        .collect(),
+                    description: o.description.clone(),
+                    kind: Kind::for_value(&o.value_name, &o.long_name),
+                }
+            })
+            .collect();
+        let positional = cli
+            .parameters
+            .split_whitespace()
+            .next()
+            .and_then(|p| Kind::for_value(p, name));
+        let subcommands = cli
+            .subcommands
+            .iter()
+            .map(|c| Self {
+                path: format!("{}__{}", path, c.name),
+                name: c.name.clone(),
+                description: c.description.clone(),
+                ..Default::default()
+            })
+            .collect();
+        Self {
+            path,
+            name: name.to_string(),
+            options,
+            positional,
+            subcommands,
+            ..Default::default()
+        }
+    }
+
+    /// The options with completed values, and their spellings.
+    fn option_kinds(&self) -> impl Iterator<Item = (&[String], Kind)> {
+        self.options
+            .iter()
+            .filter_map(|o| Some((o.flags.as_slice(), o.kind?)))
+    }
+}
+
+fn command_path(words: &[&str]) -> String {
+    std::iter::once("rpm-ostree")
+        .chain(words.iter().copied())
+        .collect::<Vec<_>>()
+        .join("__")
+}
+
+/// Describe the command at `words`, e.g. `["db", "diff"]`.
+fn describe(words: &[&str]) -> Node {
+    let path = command_path(words);
+    let name = words.last().copied().unwrap_or("rpm-ostree");
+    if let Some(cmd) = words.first().and_then(|w| rust_command(w)) {
+        let mut cmd = &cmd;
+        for word in &words[1..] {
+            match cmd.find_subcommand(*word) {
+                Some(sub) => cmd = sub,
+                None => {
+                    return Node {
+                        path,
+                        name: name.to_string(),
+                        ..Default::default()
+                    }
+                }
+            }
+        }
+        return Node::from_clap(path, name, cmd);
+    }
+    DESCRIBING.with(|d| *d.borrow_mut() = Some(None));
+    let cli = crate::ffi::describe_command(words);
+    match DESCRIBING.with(|d| d.borrow_mut().take()).flatten() {
+        Some(cmd) => Node::from_clap(path, name, &cmd),
+        None => Node::from_cxx(path, name, &cli),
+    }
+}
+
+/// Describe the command at `words` and its subcommands, recursively.
+fn describe_tree(words: &[&str], describe: &impl Fn(&[&str]) -> Node) -> Node {
+    let mut node = describe(words);
+    node.subcommands = std::mem::take(&mut node.subcommands)
+        .into_iter()
+        .map(|sub| {
+            let mut words = words.to_vec();
+            words.push(&sub.name);
+            let full = describe_tree(&words, describe);
+            // The descriptions of C++ commands are those of the tables
+            Node {
+                options: full.options,
+                positional: full.positional,
+                subcommands: full.subcommands,
+                ..sub
+            }
+        })
+        .collect();
+    node
+}
+
+/// The whole command line.
+fn cli() -> Node {
+    describe_tree(&[], &describe)
+}
+
+fn collect_nodes<'a>(node: &'a Node, nodes: &mut Vec<&'a Node>) {
+    for sub in &node.subcommands {
+        collect_nodes(sub, nodes);
+    }
+    nodes.push(node);
+}
+
+fn nodes(cli: &Node) -> Vec<&Node> {
+    let mut nodes = Vec::new();
+    collect_nodes(cli, &mut nodes);
+    nodes.sort_by(|a, b| a.path.cmp(&b.path));
+    nodes
+}
+
+/// The transitions from a command to its subcommands, as `(path, word, subpath)`.
+fn transitions<'a>(nodes: &'a [&'a Node]) -> impl Iterator<Item = (&'a str, &'a str, &'a str)> {
+    nodes.iter().flat_map(|node| {
+        node.subcommands.iter().flat_map(move |sub| {
+            std::iter::once(&sub.name)
+                .chain(&sub.aliases)
+                .map(move |word| (node.path.as_str(), word.as_str(), sub.path.as_str()))
+        })
+    })
+}
+
+fn single_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r#"'\''"#))
+}
+
+fn bash(cli: &Node) -> String {
+    let nodes = &nodes(cli);
+    let mut r = String::new();
+    r.push_str(BASH_HEADER);
+    for (path, word, subpath) in transitions(nodes) {
+        writeln!(r, "            {},{}) cmdpath={} ;;", path, word, subpath).unwrap();
+    }
+    r.push_str(BASH_OPTIONS);
+    for node in nodes {
+        for (flags, kind) in node.option_kinds() {
+            let pattern: Vec<_> = flags
+                .iter()
+                .map(|f| format!("{},{}", node.path, f))
+                .collect();
+            writeln!(r, "        {}) kind={} ;;", pattern.join("|"), kind.name()).unwrap();
+        }
+    }
+    r.push_str(BASH_COMMANDS);
+    for node in nodes {
+        let subcommands: Vec<_> = node.subcommands.iter().map(|c| c.name.as_str()).collect();
+        if !subcommands.is_empty() {
+            writeln!(
+                r,
+                "            {}) COMPREPLY=( $(compgen -W {} -- \"${{cur}}\") ); return 0 ;;",
+                node.path,
+                single_quote(&subcommands.join(" "))
+            )
+            .unwrap();
+        } else if let Some(kind) = node.positional {
+            writeln!(r, "            {}) kind={} ;;", node.path, kind.name()).unwrap();
+        }
+    }
+    r.push_str(BASH_FOOTER);
+    r
+}
+
+const BASH_HEADER: &str = r#"# bash completion for rpm-ostree; generated by `rpm-ostree completion bash`
+
+_rpm_ostree_complete() {
+    rpm-ostree completion complete "$@" 2>/dev/null
+}
+
+_rpm_ostree() {
+    local cur prev cmdpath word kind i
+    COMPREPLY=()
+    cur="${COMP_WORDS[COMP_CWORD]}"
+    prev="${COMP_WORDS[COMP_CWORD-1]}"
+    # e.g. --deployment=0
+    if [ "${prev}" = "=" ]; then
+        prev="${COMP_WORDS[COMP_CWORD-2]}"
+    fi
+    cmdpath=rpm-ostree
+    for ((i = 1; i < COMP_CWORD; i++)); do
+        word="${COMP_WORDS[i]}"
+        case "${cmdpath},${word}" in
+"#;
+
+const BASH_OPTIONS: &str = r#"        esac
+    done
+    kind=
+    case "${cmdpath},${prev}" in
+"#;
+
+const BASH_COMMANDS: &str = r#"    esac
+    if [ -z "${kind}" ]; then
+        if [[ "${cur}" == -* ]]; then
+            COMPREPLY=( $(compgen -W "$(_rpm_ostree_complete options "${cmdpath}")" -- "${cur}") )
+            return 0
+        fi
+        case "${cmdpath}" in
+"#;
+
+const BASH_FOOTER: &str = r#"        esac
+    fi
+    if [ -n "${kind}" ]; then
+        COMPREPLY=( $(compgen -W "$(_rpm_ostree_complete "${kind}")" -- "${cur}") )
+        # A remote is followed by the ref
+        if [ "${kind}" = refspecs ]; then
+            compopt -o nospace
+        fi
+    fi
+    return 0
+}
+
+complete -o bashdefault -o default -F _rpm_ostree rpm-ostree
+"#;
+
+/// Escape the colons of a zsh `_describe` item name.
+fn zsh_describe_item(name: &str, description: &str) -> String {
+    let name = name.replace(':', r"\:");
+    single_quote(&format!("{}:{}", name, description))
+}
+
+fn zsh(cli: &Node) -> String {
+    let nodes = &nodes(cli);
+    let mut r = String::new();
+    r.push_str(ZSH_HEADER);
+    for (path, word, subpath) in transitions(nodes) {
+        writeln!(r, "            {},{}) cmdpath={} ;;", path, word, subpath).unwrap();
+    }
+    r.push_str(ZSH_OPTIONS);
+    for node in nodes {
+        for (flags, kind) in node.option_kinds() {
+            let pattern: Vec<_> = flags
+                .iter()
+                .map(|f| format!("{},{}", node.path, f))
+                .collect();
+            writeln!(r, "        {}) kind={} ;;", pattern.join("|"), kind.name()).unwrap();
+        }
+    }
+    r.push_str(ZSH_COMMANDS);
+    for node in nodes {
+        let subcommands: Vec<_> = node
+            .subcommands
+            .iter()
+            .map(|c| zsh_describe_item(&c.name, &c.description))
+            .collect();
+        if !subcommands.is_empty() {
+            writeln!(r, "            {})", node.path).unwrap();
+            writeln!(r, "                subcommands=(").unwrap();
+            for item in subcommands {
+                writeln!(r, "                    {}", item).unwrap();
+            }
+            writeln!(r, "                )").unwrap();
+            writeln!(
+                r,
+                "                _describe -t commands command subcommands; return ;;"
+            )
+            .unwrap();
+        } else if let Some(kind) = node.positional {
+            writeln!(r, "            {}) kind={} ;;", node.path, kind.name()).unwrap();
+        }
+    }
+    r.push_str(ZSH_FOOTER);
+    r
+}
+
+const ZSH_HEADER: &str = r#"#compdef rpm-ostree
+# zsh completion for rpm-ostree; generated by `rpm-ostree completion zsh`
+
+# Complete the candidates listed by rpm-ostree as `value<TAB>description`.
+_rpm_ostree_values() {
+    local tag=$1 line
+    local -a values suffix
+    shift
+    for line in ${(f)"$(rpm-ostree completion complete --describe "$@" 2>/dev/null)"}; do
+        values+=( "${${line%%$'\t'*}//:/\\:}:${line#*$'\t'}" )
+    done
+    # A remote is followed by the ref
+    if [[ $1 == refspecs ]]; then
+        suffix=( -S '' )
+    fi
+    _describe -t $tag $tag values $suffix
+}
+
+_rpm-ostree() {
+    local cmdpath=rpm-ostree word prev kind i
+    local -a subcommands
+    for ((i = 2; i < CURRENT; i++)); do
+        word=${words[i]}
+        case "${cmdpath},${word}" in
+"#;
+
+const ZSH_OPTIONS: &str = r#"        esac
+    done
+    prev=${words[CURRENT-1]}
+    case "${cmdpath},${prev}" in
+"#;
+
+const ZSH_COMMANDS: &str = r#"    esac
+    if [[ -z $kind ]]; then
+        if [[ ${words[CURRENT]} == -* ]]; then
+            _rpm_ostree_values option options $cmdpath
+            return
+        fi
+        case $cmdpath in
+"#;
+
+const ZSH_FOOTER: &str = r#"        esac
+    fi
+    if [[ -n $kind ]]; then
+        _rpm_ostree_values value $kind
+    else
+        _files
+    fi
+}
+
+if [ "$funcstack[1]" = "_rpm-ostree" ]; then
+    _rpm-ostree "$@"
+else
+    compdef _rpm-ostree rpm-ostree
+fi
+"#;
+
+fn fish_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\\', r"\\").replace('\'', r"\'"))
+}
+
+fn fish(cli: &Node) -> String {
+    let nodes = &nodes(cli);
+    let mut r = String::new();
+    r.push_str(FISH_HEADER);
+    for (path, word, subpath) in transitions(nodes) {
+        writeln!(
+            r,
+            "            case {}",
+            fish_quote(&format!("{},{}", path, word))
+        )
+        .unwrap();
+        writeln!(r, "                set cmdpath {}", subpath).unwrap();
+    }
+    r.push_str(FISH_FOOTER);
+    let values = |kind: Kind| {
+        format!(
+            "'(rpm-ostree completion complete --describe {} 2>/dev/null)'",
+            kind.name()
+        )
+    };
+    for node in nodes {
+        let at = format!("__rpm_ostree_at {}", node.path);
+        for sub in &node.subcommands {
+            write!(
+                r,
+                "complete -c rpm-ostree -f -n '{}; and not __rpm_ostree_option' -a {}",
+                at, sub.name
+            )
+            .unwrap();
+            if !sub.description.is_empty() {
+                write!(r, " -d {}", fish_quote(&sub.description)).unwrap();
+            }
+            r.push('\n');
+        }
+        for (flags, kind) in node.option_kinds() {
+            writeln!(
+                r,
+                "complete -c rpm-ostree -f -n '{}; and __rpm_ostree_prev_is {}' -a {}",
+                at,
+                flags.join(" "),
+                values(kind)
+            )
+            .unwrap();
+        }
+        if let Some(kind) = node.positional {
+            writeln!(
+                r,
+                "complete -c rpm-ostree -f -n '{}; and not __rpm_ostree_option' -a {}",
+                at,
+                values(kind)
+            )
+            .unwrap();
+        }
+    }
+    r
+}
+
+const FISH_HEADER: &str = r#"# fish completion for rpm-ostree; generated by `rpm-ostree completion fish`
+
+# The command being completed, e.g. rpm-ostree__db__diff
+function __rpm_ostree_path
+    set -l cmdpath rpm-ostree
+    set -l words (commandline -opc)
+    set -e words[1]
+    for word in $words
+        switch "$cmdpath,$word"
+"#;
+
+const FISH_FOOTER: &str = r#"        end
+    end
+    echo $cmdpath
+end
+
+function __rpm_ostree_at
+    test (__rpm_ostree_path) = $argv[1]
+end
+
+function __rpm_ostree_prev_is
+    contains -- (commandline -opc)[-1] $argv
+end
+
+function __rpm_ostree_option
+    string match -q -- '-*' (commandline -ct)
+end
+
+complete -c rpm-ostree -n __rpm_ostree_option -a '(rpm-ostree completion complete --describe options (__rpm_ostree_path) 2>/dev/null)'
+"#;
+
+/// The options of the command at `path`, e.g. `rpm-ostree__db__diff`.
+fn command_options(path: &str) -> Result<Vec<(String, String)>> {
+    let mut words = path.split("__");
+    if words.next() != Some("rpm-ostree") {
+        return Err(anyhow!("Invalid command path: {}", path));
+    }
+    let words: Vec<_> = words.collect();
+    let mut r = Vec::new();
+    for o in describe(&words).options {
+        r.extend(o.flags.into_iter().map(|f| (f, o.description.clone())));
+    }
+    r.push(("--help".to_string(), "Show help options".to_string()));
+    Ok(r)
+}
+
+fn commit_version(repo: &ostree::Repo, checksum: &str) -> Option<String> {
+    let commit = repo.load_commit(checksum).ok()?.0;
+    let meta = glib::VariantDict::new(Some(&commit.child_value(0)));
+    meta.lookup::<String>("version").ok().flatten()
+}
+
+/// The candidates of `kind` on the system, with their descriptions.
+fn candidates(kind: Kind, path: Option<&str>) -> Result<Vec<(String, String)>> {
+    if kind == Kind::Options {
+        let path = path.ok_or_else(|| anyhow!("A command path is required for options"))?;
+        return command_options(path);
+    }
+    let sysroot = &ostree::Sysroot::new_default();
+    sysroot.load(gio::NONE_CANCELLABLE)?;
+    let repo = &sysroot.repo().unwrap();
+    let booted = sysroot.booted_deployment();
+    let deployments = sysroot.deployments();
+    let describe = |d: &ostree::Deployment| {
+        let csum = d.csum();
+        let mut desc = match commit_version(repo, &csum) {
+            Some(version) => format!("{} ({:.10})", version, csum.as_str()),
+            None => format!("{:.10}", csum.as_str()),
+        };
+        if booted.as_ref().map(|b| b.equal(d)).unwrap_or_default() {
+            desc.push_str(", booted");
+        }
+        desc
+    };
+    let mut r = Vec::new();
+    match kind {
+        Kind::Options => unreachable!(),
+        Kind::Deployments | Kind::Indexes => {
+            for (i, d) in deployments.iter().enumerate() {
+                r.push((i.to_string(), describe(d)));
+            }
+            if kind == Kind::Deployments {
+                let (pending, rollback) = sysroot.query_deployments_for(None);
+                let named = [
+                    ("booted", booted.clone()),
+                    ("pending", pending),
+                    ("rollback", rollback),
+                ];
+                for (name, d) in named {
+                    if let Some(d) = d {
+                        r.push((name.to_string(), describe(&d)));
+                    }
+                }
+            }
+        }
+        Kind::Revisions => {
+            for (i, d) in deployments.iter().enumerate() {
+                r.push((
+                    d.csum().to_string(),
+                    format!("deployment {}: {}", i, describe(d)),
+                ));
+            }
+        }
+        Kind::Packages => {
+            let merge = match sysroot.merge_deployment(None) {
+                Some(d) => d,
+                None => return Ok(r),
+            };
+            let origin = merge
+                .origin()
+                .ok_or_else(|| anyhow!("Deployment {} has no origin", merge.csum()))?;
+            let tf = crate::origin::origin_to_treefile_inner(&origin)?;
+            let mut packages = BTreeSet::new();
+            packages.extend(tf.parsed.packages.iter().flatten().cloned());
+            packages.extend(
+                tf.parsed
+                    .derive
+                    .packages_local
+                    .iter()
+                    .flatten()
+                    .map(|(nevra, _)| nevra.clone()),
+            );
+            r.extend(packages.into_iter().map(|p| (p, String::new())));
+        }
+        Kind::Remotes | Kind::Refspecs => {
+            for remote in repo.remote_list() {
+                let url = repo
+                    .remote_get_url(&remote)
+                    .map(|u| u.to_string())
+                    .unwrap_or_default();
+                let remote = if kind == Kind::Refspecs {
+                    format!("{}:", remote)
+                } else {
+                    remote.to_string()
+                };
+                r.push((remote, url));
+            }
+            if kind == Kind::Refspecs {
+                let mut refspecs = BTreeMap::new();
+                for d in deployments.iter() {
+                    let refspec = d.origin().and_then(|o| o.string("origin", "refspec").ok());
+                    if let Some(refspec) = refspec {
+                        refspecs.entry(refspec.to_string()).or_insert_with(|| {
+                            format!("origin of {}", deployment_generate_id_impl(d))
+                        });
+                    }
+                }
+                r.extend(refspecs);
+            }
+        }
+    }
+    Ok(r)
+}
+
+/// Main entrypoint for `rpm-ostree completion`.
+pub fn entrypoint(args: &[&str]) -> Result<()> {
+    let opts = Opts::parse_from(args.iter().skip(1));
+    let script = match opts {
+        Opts::Bash => bash(&cli()),
+        Opts::Zsh => zsh(&cli()),
+        Opts::Fish => fish(&cli()),
+        Opts::Complete {
+            kind,
+            path,
+            describe,
+        } => {
+            for (value, description) in candidates(kind, path.as_deref())? {
+                if describe {
+                    println!("{}\t{}", value, description);
+                } else {
+                    println!("{}", value);
+                }
+            }
+            return Ok(());
+        }
+    };
+    print!("{}", script);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ffi::{CliCommand, CliDescription, CliOption};
+
+    fn command(name: &str, description: &str) -> CliCommand {
+        CliCommand {
+            name: name.into(),
+            description: description.into(),
+        }
+    }
+
+    fn option(long_name: &str, short_name: &str, value_name: &str) -> CliOption {
+        CliOption {
+            long_name: long_name.into(),
+            short_name: short_name.into(),
+            value_name: value_name.into(),
+            description: format!("The {}", long_name),
+        }
+    }
+
+    /// Stands for the C++ describe mode.
+    fn describe_fake(words: &[&str]) -> Node {
+        let path = command_path(words);
+        let name = words.last().copied().unwrap_or("rpm-ostree");
+        let cli = match words {
+            [] => CliDescription {
+                subcommands: vec![
+                    command("cleanup", "Clear cached/pending data"),
+                    command("db", "Commands to query the RPM database"),
+                    command("rebase", "Switch to a different tree"),
+                    command("uninstall", "Remove overlayed additional packages"),
+                    command("pin", "Pin a deployment, so that it isn't pruned"),
+                    command("completion", "Print a shell completion script"),
+                ],
+                options: vec![option("version", "", "")],
+                parameters: "COMMAND".into(),
+            },
+            ["db"] => CliDescription {
+                subcommands: vec![
+                    command("diff", "Show package changes between two commits"),
+                    command("verify", "Verify the files of packages"),
+                ],
+                parameters: "COMMAND".into(),
+                ..Default::default()
+            },
+            ["db", "diff"] => CliDescription {
+                parameters: "[FROM_REV] [TO_REV]".into(),
+                ..Default::default()
+            },
+            ["db", "verify"] => {
+                // Like a Rust command delegated to by C++
+                let cmd = Command::new("rpm-ostree db verify").arg(
+                    clap::Arg::new("deployment")
+                        .long("deployment")
+                        .short('d')
+                        .takes_value(true)
+                        .value_name("DEPLOYMENT"),
+                );
+                return Node::from_clap(path, name, &cmd);
+            }
+            ["rebase"] => CliDescription {
+                options: vec![option("remote", "m", "REMOTE"), option("reboot", "r", "")],
+                parameters: "REFSPEC [REVISION]".into(),
+                ..Default::default()
+            },
+            ["uninstall"] => CliDescription {
+                options: vec![option("install", "", "PKG")],
+                parameters: "PACKAGE [PACKAGE...]".into(),
+                ..Default::default()
+            },
+            // The Rust commands
+            _ => return describe(words),
+        };
+        Node::from_cxx(path, name, &cli)
+    }
+
+    #[test]
+    fn test_kind_for_value() {
+        assert_eq!(Kind::for_value("[FROM_REV]", "diff"), Some(Kind::Revisions));
+        assert_eq!(Kind::for_value("REV...", "list"), Some(Kind::Revisions));
+        assert_eq!(Kind::for_value("PKG", "uninstall"), Some(Kind::Packages));
+        assert_eq!(Kind::for_value("PKG", "install"), None);
+        assert_eq!(Kind::for_value("", "cleanup"), None);
+    }
+
+    #[test]
+    fn test_cli() {
+        let cli = &describe_tree(&[], &describe_fake);
+        let nodes = nodes(cli);
+        let find = |path: &str| *nodes.iter().find(|n| n.path == path).unwrap();
+        assert_eq!(
+            find("rpm-ostree__uninstall").positional,
+            Some(Kind::Packages)
+        );
+        assert_eq!(find("rpm-ostree__pin").positional, Some(Kind::Deployments));
+        assert_eq!(
+            find("rpm-ostree__db__diff").positional,
+            Some(Kind::Revisions)
+        );
+        assert_eq!(
+            find("rpm-ostree__db__verify")
+                .option_kinds()
+                .collect::<Vec<_>>(),
+            vec![(
+                &["-d".to_string(), "--deployment".to_string()][..],
+                Kind::Deployments
+            )]
+        );
+        // The description is that of the table of subcommands
+        assert_eq!(
+            find("rpm-ostree__db__verify").description,
+            "Verify the files of packages"
+        );
+        assert_eq!(find("rpm-ostree__uninstall").option_kinds().count(), 0);
+        assert!(find("rpm-ostree__completion__bash").subcommands.is_empty());
+        assert!(!nodes.iter().any(|n| n.path.ends_with("__complete")));
+    }
+
+    #[test]
+    fn test_scripts() {
+        let cli = &describe_tree(&[], &describe_fake);
+        let bash = bash(cli);
+        assert!(bash.contains("            rpm-ostree,db) cmdpath=rpm-ostree__db ;;\n"));
+        assert!(bash.contains("            rpm-ostree__db,diff) cmdpath=rpm-ostree__db__diff ;;\n"));
+        assert!(bash.contains(
+            "        rpm-ostree__db__verify,-d|rpm-ostree__db__verify,--deployment) kind=deployments ;;\n"
+        ));
+        assert!(bash.contains("            rpm-ostree__uninstall) kind=packages ;;\n"));
+        let zsh = zsh(cli);
+        assert!(zsh.contains("                    'cleanup:Clear cached/pending data'\n"));
+        let fish = fish(cli);
+        assert!(fish.contains(
+            "complete -c rpm-ostree -f -n '__rpm_ostree_at rpm-ostree__rebase; and __rpm_ostree_prev_is -m --remote' -a '(rpm-ostree completion complete --describe remotes 2>/dev/null)'\n"
+        ));
+        assert!(fish.contains("            case 'rpm-ostree,completion'\n"));
+    }
+}
@@ -11,7 +11,7 @@ use crate::cxxrsutil::*;
 use crate::ffi::BubblewrapMutability;
 use crate::ffiutil::{ffi_dirfd, ffi_view_openat_dir};
 use crate::normalization;
-use crate::passwd::PasswdDB;
+use crate::passwd::{PasswdDB, PasswdEntries};
 use crate::treefile::Treefile;
 use crate::{bwrap, importer};
 use anyhow::{anyhow, bail, format_err, Context, Result};
@@ -27,10 +27,10 @@ use fn_error_context::context;
 use gio::prelude::*;
 use gio::FileType;
 use openat_ext::OpenatDirExt;
-use ostree_ext::{gio, glib};
+use ostree_ext::{gio, glib, ostree};
 use rayon::prelude::*;
 use std::borrow::Cow;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::fmt::Write as FmtWrite;
 use std::io::{BufRead, BufReader, Read, Seek, Write};
 use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
@@ -450,6 +450,79 @@ fn compose_postprocess_add_files(rootfs_dfd: &openat::Dir, treefile: &mut Treefi
     Ok(())
 }
 
+/// Resolve an owner as `user[:group]`, by name or ID, against the composed
+/// users and groups; without a group, it's the primary group of the user.
+fn resolve_owner(db: &PasswdEntries, owner: &str) -> Result<(u32, u32)> {
+    let (user, group) = match owner.split_once(':') {
+        Some((u, g)) => (u, Some(g)),
+        None => (owner, None),
+    };
+    let uid = match user.parse::<u32>() {
+        Ok(uid) => uid,
+        Err(_) => db.lookup_user_id(user)?,
+    };
+    let gid = match group {
+        Some(g) => match g.parse::<u32>() {
+            Ok(gid) => gid,
+            Err(_) => db.lookup_group_id(g)?,
+        },
+        None if user.parse::<u32>().is_ok() => uid,
+        None => db.lookup_user_group_id(user)?,
+    };
+    Ok((uid, gid))
+}
+
+/// Implementation of the treefile `ownership` field; this runs after the
+/// passwd and group split, and before /var is converted to tmpfiles.d.
+#[context("Applying ownership")]
+fn postprocess_ownership(rootfs: &Dir, ownership: &BTreeMap<String, String>) -> Result<()> {
+    use nix::unistd::{fchownat, FchownatFlags, Gid, Uid};
+    let mut db = PasswdEntries::default();
+    for dir in ["usr/etc", "usr/lib"] {
+        if rootfs.try_exists(format!("{}/passwd", dir))? {
+            db.add_passwd_content(rootfs.as_raw_fd(), &format!("{}/passwd", dir))?;
+        }
+        if rootfs.try_exists(format!("{}/group", dir))? {
+            db.add_group_content(rootfs.as_raw_fd(), &format!("{}/group", dir))?;
+        }
+    }
+    for (path, owner) in ownership {
+        let relpath = path.trim_start_matches('/');
+        if !path.starts_with('/') || relpath.is_empty() {
+            bail!("Invalid ownership path: {}", path);
+        }
+        let relpath = if relpath == "etc" || relpath.starts_with("etc/") {
+            Cow::Owned(format!("usr/{}", relpath))
+        } else {
+            Cow::Borrowed(relpath)
+        };
+        let (uid, gid) =
+            resolve_owner(&db, owner).with_context(|| format!("Resolving owner of {}", path))?;
+        println!("Setting ownership of {} to {}:{}", path, uid, gid);
+        fchownat(
+            Some(rootfs.as_raw_fd()),
+            Path::new(&*relpath),
+            Some(Uid::from_raw(uid)),
+            Some(Gid::from_raw(gid)),
+            FchownatFlags::NoFollowSymlink,
+        )
+        .with_context(|| format!("Changing ownership of {}", path))?;
+    }
+    Ok(())
+}
+
+/// Apply the ownership declared in the treefile to the paths created by
+/// packages, `add-files` or postprocessing.
+pub fn compose_postprocess_ownership(rootfs_dfd: i32, treefile: &Treefile) -> CxxResult<()> {
+    let ownership = match treefile.parsed.base.ownership.as_ref() {
+        Some(o) if !o.is_empty() => o,
+        _ => return Ok(()),
+    };
+    let rootfs = unsafe { &crate::ffiutil::ffi_dirfd(rootfs_dfd)? };
+    postprocess_ownership(rootfs, ownership)?;
+    Ok(())
+}
+
 #[context("Symlinking {}", TRADITIONAL_RPMDB_LOCATION)]
 fn compose_postprocess_rpmdb(rootfs_dfd: &openat::Dir) -> Result<()> {
     /* This works around a potential issue with libsolv if we go down the
@@ -1028,6 +1101,93 @@ pub fn directory_size(
     Ok(directory_size_recurse(&dfd, cancellable)?)
 }
 
+/// Sign all regular files in the commit `rev` with the IMA private key `key`, returning
+/// the checksum of a new commit with the same metadata and parent, whose files carry
+/// the signatures in the `security.ima` xattr.
+#[context("IMA signing {}", rev)]
+pub fn compose_ima_sign(
+    repo: &crate::FFIOstreeRepo,
+    rev: &str,
+    key: &str,
+    algorithm: &str,
+) -> CxxResult<String> {
+    let repo = &repo.glib_reborrow();
+    let opts = ostree_ext::ima::ImaOpts {
+        algorithm: algorithm.to_string(),
+        key: key.into(),
+        overwrite: true,
+    };
+    Ok(ostree_ext::ima::ima_sign(repo, rev, &opts)?)
+}
+
+/// Error out if a regular file in the commit `rev` lacks an IMA signature.
+#[context("Verifying IMA signatures of {}", rev)]
+pub fn compose_ima_verify(repo: &crate::FFIOstreeRepo, rev: &str) -> CxxResult<()> {
+    let repo = &repo.glib_reborrow();
+    let cancellable = gio::NONE_CANCELLABLE;
+    let ima_xattr = b"security.ima\0";
+    let is_signed = |checksum: &str| -> Result<bool> {
+        let (_, _, xattrs) = repo.load_file(checksum, cancellable)?;
+        Ok(xattrs
+            .map(|xattrs| {
+                xattrs
+                    .iter()
+                    .any(|x| x.child_value(0).data_as_bytes().as_ref() == ima_xattr)
+            })
+            .unwrap_or_default())
+    };
+    fn walk(
+        dir: &gio::File,
+        path: &str,
+        is_signed: &dyn Fn(&str) -> Result<bool>,
+        checked: &mut HashSet<String>,
+        unsigned: &mut Vec<String>,
+    ) -> Result<()> {
+        let e = dir.enumerate_children(
+            "standard::name,standard::type",
+            gio::FileQueryInfoFlags::NOFOLLOW_SYMLINKS,
+            gio::NONE_CANCELLABLE,
+        )?;
+        for info in e {
+            let info = info?;
+            let name = info.name();
+            let name = name.to_str().unwrap_or_default();
+            let childpath = format!("{}/{}", path, name);
+            let child = dir.child(name);
+            match info.file_type() {
+                FileType::Directory => walk(&child, &childpath, is_signed, checked, unsigned)?,
+                FileType::Regular => {
+                    let child = child.downcast_ref::<ostree::RepoFile>().unwrap();
+                    child.ensure_resolved()?;
+                    let checksum = child.checksum().map(|s| s.to_string()).unwrap_or_default();
+                    if checked.contains(&checksum) {
+                        continue;
+                    }
+                    if is_signed(&checksum)? {
+                        checked.insert(checksum);
+                    } else {
+                        unsigned.push(childpath);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+    let (root, _) = repo.read_commit(rev, cancellable)?;
+    let mut unsigned = Vec::new();
+    walk(&root, "", &is_signed, &mut HashSet::new(), &mut unsigned)?;
+    if let Some(first) = unsigned.first() {
+        return Err(anyhow!(
+            "{} files without an IMA signature, e.g. {}",
+            unsigned.len(),
+            first
+        )
+        .into());
+    }
+    Ok(())
+}
+
 #[context("Hardlinking rpmdb to base location")]
 fn hardlink_rpmdb_base_location(
     rootfs: &openat::Dir,
@@ -1126,7 +1286,7 @@ pub(crate) fn rewrite_rpmdb_for_target(rootfs_dfd: i32, normalize: bool) -> CxxR
 ///
 /// Both directories must exist beforehand.
 #[context("Hardlinking /{} to /{}", source, target)]
-fn hardlink_hierarchy(
+pub(crate) fn hardlink_hierarchy(
     rootfs: &openat::Dir,
     source: &str,
     target: &str,
@@ -1247,6 +1407,27 @@ automount:  files sss
         assert_eq!(replaced2.as_str(), expected);
     }
 
+    #[test]
+    fn test_resolve_owner() -> Result<()> {
+        let rootfs = cap_tempfile::tempdir(cap_std::ambient_authority())?;
+        rootfs.create_dir_all("usr/lib")?;
+        rootfs.write(
+            "usr/lib/passwd",
+            "foo:x:990:985::/var/lib/foo:/sbin/nologin\n",
+        )?;
+        rootfs.write("usr/lib/group", "foo:x:985:\nbar:x:984:\n")?;
+        let mut db = PasswdEntries::default();
+        db.add_passwd_content(rootfs.as_raw_fd(), "usr/lib/passwd")?;
+        db.add_group_content(rootfs.as_raw_fd(), "usr/lib/group")?;
+        assert_eq!(resolve_owner(&db, "foo")?, (990, 985));
+        assert_eq!(resolve_owner(&db, "foo:bar")?, (990, 984));
+        assert_eq!(resolve_owner(&db, "foo:0")?, (990, 0));
+        assert_eq!(resolve_owner(&db, "1000")?, (1000, 1000));
+        assert!(resolve_owner(&db, "nosuchuser").is_err());
+        assert!(resolve_owner(&db, "foo:nosuchgroup").is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_mutate_os_release() {
         let orig = r##"NAME=Fedora
@@ -73,6 +73,27 @@ struct ContainerEncapsulateOpts {
     write_contentmeta_json: Option<Utf8PathBuf>,
 }
 
+#[derive(Debug, Parser)]
+struct DeploymentEncapsulateOpts {
+    /// Index of the deployment to export, as listed by `ostree admin status`;
+    /// defaults to the booted deployment
+    #[clap(long)]
+    index: Option<usize>,
+
+    /// Image reference, e.g. registry:quay.io/exampleos/exampleos:latest
+    /// or oci-archive:/path/to/image.ociarchive
+    #[clap(value_parser = ostree_ext::cli::parse_base_imgref)]
+    imgref: ImageReference,
+
+    /// Additional labels for the container
+    #[clap(name = "label", long, short)]
+    labels: Vec<String>,
+
+    /// Maximum number of container image layers
+    #[clap(long)]
+    max_layers: Option<NonZeroU32>,
+}
+
 #[derive(Debug)]
 struct MappingBuilder {
     /// Maps from package ID to metadata
@@ -212,6 +233,48 @@ pub async fn container_encapsulate(args: &[&str]) -> Result<()> {
     let args = args.iter().skip(1);
     let opt = ContainerEncapsulateOpts::parse_from(args);
     let repo = &ostree_ext::cli::parse_repo(opt.repo.as_str())?;
+    encapsulate_commit(repo, opt).await
+}
+
+/// Export a deployment, including any layered packages and overrides, as a
+/// bootable container image.
+pub async fn deployment_encapsulate(args: &[&str]) -> Result<()> {
+    let args = args.iter().skip(1);
+    let opt = DeploymentEncapsulateOpts::parse_from(args);
+    let sysroot = &ostree::Sysroot::new_default();
+    sysroot.load(gio::NONE_CANCELLABLE)?;
+    let deployment = match opt.index {
+        Some(i) => sysroot
+            .deployments()
+            .into_iter()
+            .nth(i)
+            .ok_or_else(|| anyhow::anyhow!("Invalid deployment index {}", i))?,
+        None => sysroot.require_booted_deployment()?,
+    };
+    let repo = &sysroot.repo().unwrap();
+    let mut labels = opt.labels;
+    // Client-side commits don't carry the bootable flag of their base
+    labels.push("ostree.bootable=true".to_string());
+    let opt = ContainerEncapsulateOpts {
+        repo: repo.path().unwrap().path().unwrap().try_into()?,
+        ostree_ref: deployment.csum().to_string(),
+        imgref: opt.imgref,
+        labels,
+        copy_meta_keys: Vec::new(),
+        cmd: None,
+        max_layers: opt.max_layers,
+        format_version: 1,
+        write_contentmeta_json: None,
+    };
+    println!(
+        "Exporting deployment {}.{}",
+        deployment.csum(),
+        deployment.deployserial()
+    );
+    encapsulate_commit(repo, opt).await
+}
+
+async fn encapsulate_commit(repo: &ostree::Repo, opt: ContainerEncapsulateOpts) -> Result<()> {
     let (root, rev) = repo.read_commit(opt.ostree_ref.as_str(), gio::NONE_CANCELLABLE)?;
     let pkglist = {
         let cancellable = gio::Cancellable::new();
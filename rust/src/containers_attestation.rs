@@ -0,0 +1,412 @@
+//! Verification of in-toto attestations attached to container images through
+//! the OCI referrers tag schema, according to a local policy.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::sysroot_upgrade::PullSettings;
+use anyhow::{anyhow, bail, Context, Result};
+use cap_std::fs::Dir;
+use cap_std_ext::cap_std;
+use cap_std_ext::dirext::CapStdExtDirExt;
+use openssl::hash::{hash, MessageDigest};
+use openssl::pkey::{PKey, Public};
+use openssl::sign::Verifier;
+use ostree_ext::container::{OstreeImageReference, Transport};
+use ostree_ext::containers_image_proxy::{ImageProxy, OpenedImage};
+use ostree_ext::glib;
+use serde_derive::Deserialize;
+use std::collections::HashMap;
+use std::process::Command;
+use tokio::io::AsyncReadExt;
+
+/// Path to the policy, relative to the root; attestations are only verified
+/// if it exists.
+const POLICY_PATH: &str = "etc/rpm-ostree/attestation-policy.json";
+
+pub(crate) const DSSE_ENVELOPE: &str = "application/vnd.dsse.envelope.v1+json";
+pub(crate) const IN_TOTO_PAYLOAD: &str = "application/vnd.in-toto+json";
+
+/// Requirements for the attestation of an image.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct AttestationPolicy {
+    /// The required in-toto predicate type, e.g. https://slsa.dev/provenance/v0.2
+    predicate_type: String,
+    /// PEM public key the attestation must be signed with
+    key_path: String,
+    /// If set, the builder recorded in the provenance must be one of these
+    #[serde(default)]
+    builder_ids: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Envelope {
+    payload_type: String,
+    payload: String,
+    signatures: Vec<EnvelopeSignature>,
+}
+
+#[derive(Deserialize, Debug)]
+struct EnvelopeSignature {
+    sig: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Statement {
+    subject: Vec<Subject>,
+    predicate_type: String,
+    #[serde(default)]
+    predicate: serde_json::Value,
+}
+
+#[derive(Deserialize, Debug)]
+struct Subject {
+    digest: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Descriptor {
+    #[serde(default)]
+    media_type: String,
+    digest: String,
+    #[serde(default)]
+    artifact_type: Option<String>,
+    #[serde(default)]
+    size: u64,
+}
+
+#[derive(Deserialize, Debug)]
+struct Index {
+    #[serde(default)]
+    manifests: Vec<Descriptor>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Manifest {
+    #[serde(default)]
+    layers: Vec<Descriptor>,
+}
+
+/// Strip the tag or digest from a docker image name.
+fn image_name_without_reference(name: &str) -> &str {
+    match name.split_once('@') {
+        Some((name, _)) => name,
+        None => match name.rsplit_once(':') {
+            Some((name, tag)) if !tag.contains('/') => name,
+            _ => name,
+        },
+    }
+}
+
+/// The registry of a docker image name, with the default for docker.io.
+pub(crate) fn image_registry(name: &str) -> String {
+    let name = image_name_without_reference(name);
+    match name.split_once('/') {
+        Some((registry, _))
+            if registry.contains('.') || registry.contains(':') || registry == "localhost" =>
+        {
+            registry.into()
+        }
+        _ => "docker.io".into(),
+    }
+}
+
+fn hex_encode(buf: &[u8]) -> String {
+    buf.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Fetch the raw manifest `reference` (a tag or a digest) of the repository
+/// `name` with `skopeo inspect`, returning `None` if it doesn't exist.
+async fn inspect_raw(mut skopeo: Command, name: &str, reference: &str) -> Result<Option<Vec<u8>>> {
+    let sep = if reference.contains(':') { '@' } else { ':' };
+    let imgref = format!("docker://{}{}{}", name, sep, reference);
+    skopeo.args(["inspect", "--raw", &imgref]);
+    let out = tokio::process::Command::from(skopeo)
+        .output()
+        .await
+        .context("Running skopeo")?;
+    if !out.status.success() {
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        // There's no better way to tell a missing manifest from the skopeo CLI
+        if stderr.contains("manifest unknown") {
+            return Ok(None);
+        }
+        bail!("skopeo inspect {}: {}", imgref, stderr.trim());
+    }
+    Ok(Some(out.stdout))
+}
+
+/// Fetch a blob of the repository of the opened image, checking its digest.
+async fn fetch_blob(
+    proxy: &ImageProxy,
+    img: &OpenedImage,
+    descriptor: &Descriptor,
+) -> Result<Vec<u8>> {
+    let digest = descriptor.digest.as_str();
+    let (mut blob, driver) = proxy.get_blob(img, digest, descriptor.size).await?;
+    let mut buf = Vec::new();
+    let (read, driver) = tokio::join!(blob.read_to_end(&mut buf), driver);
+    driver?;
+    read?;
+    let expected = digest
+        .strip_prefix("sha256:")
+        .ok_or_else(|| anyhow!("Unsupported digest: {}", digest))?;
+    let found = hex_encode(&hash(MessageDigest::sha256(), &buf)?);
+    if found != expected {
+        bail!("Corrupted blob {}: found sha256:{}", digest, found);
+    }
+    Ok(buf)
+}
+
+/// Fetch the DSSE envelopes attached to the image `name` with manifest
+/// `digest`, through the referrers tag schema.  Registries and their
+/// configuration (registries.conf, certificates, authentication, proxy)
+/// are handled by skopeo as for pulls.
+async fn fetch_attestations(
+    proxy: &ImageProxy,
+    skopeo: &dyn Fn() -> Command,
+    name: &str,
+    digest: &str,
+) -> Result<Vec<Vec<u8>>> {
+    let tag = digest.replacen(':', "-", 1);
+    let index: Index = match inspect_raw(skopeo(), name, &tag).await? {
+        Some(index) => serde_json::from_slice(&index).context("Parsing referrers")?,
+        None => return Ok(Vec::new()),
+    };
+    // Blobs are scoped to the repository, so we can fetch the envelopes
+    // through the image itself, which the proxy has already allowed.
+    let img = proxy
+        .open_image(&format!("docker://{}@{}", name, digest))
+        .await?;
+    let mut r = Vec::new();
+    for referrer in index.manifests {
+        if referrer.artifact_type.as_deref() != Some(DSSE_ENVELOPE) {
+            continue;
+        }
+        let manifest = inspect_raw(skopeo(), name, &referrer.digest)
+            .await?
+            .ok_or_else(|| anyhow!("Missing manifest {}", referrer.digest))?;
+        let manifest: Manifest = serde_json::from_slice(&manifest)
+            .with_context(|| format!("Parsing manifest {}", referrer.digest))?;
+        for layer in manifest.layers.iter() {
+            if layer.media_type == DSSE_ENVELOPE {
+                r.push(fetch_blob(proxy, &img, layer).await?);
+            }
+        }
+    }
+    proxy.close_image(&img).await?;
+    Ok(r)
+}
+
+/// The DSSE pre-authentication encoding which is signed.
+pub(crate) fn pae(payload_type: &str, payload: &[u8]) -> Vec<u8> {
+    let mut r = format!(
+        "DSSEv1 {} {} {} ",
+        payload_type.len(),
+        payload_type,
+        payload.len()
+    )
+    .into_bytes();
+    r.extend_from_slice(payload);
+    r
+}
+
+/// Check the signature of a DSSE envelope, and return the in-toto statement
+/// it contains.
+fn verify_envelope(key: &PKey<Public>, envelope: &[u8]) -> Result<Statement> {
+    let envelope: Envelope = serde_json::from_slice(envelope).context("Parsing envelope")?;
+    if envelope.payload_type != IN_TOTO_PAYLOAD {
+        bail!("Unexpected payload type: {}", envelope.payload_type);
+    }
+    let payload = glib::base64_decode(&envelope.payload);
+    let signed = pae(&envelope.payload_type, &payload);
+    let mut verified = false;
+    for signature in envelope.signatures.iter() {
+        let sig = glib::base64_decode(&signature.sig);
+        let mut verifier = Verifier::new(MessageDigest::sha256(), key)?;
+        if verifier.verify_oneshot(&sig, &signed).unwrap_or_default() {
+            verified = true;
+            break;
+        }
+    }
+    if !verified {
+        bail!("No valid signature");
+    }
+    Ok(serde_json::from_slice(&payload).context("Parsing statement")?)
+}
+
+/// Check that `statement` is about the image with manifest `digest`, and
+/// satisfies the policy.
+fn check_statement(policy: &AttestationPolicy, statement: &Statement, digest: &str) -> Result<()> {
+    let (algo, hex) = digest
+        .split_once(':')
+        .ok_or_else(|| anyhow!("Invalid digest: {}", digest))?;
+    if !statement
+        .subject
+        .iter()
+        .any(|s| s.digest.get(algo).map(|d| d.as_str()) == Some(hex))
+    {
+        bail!("Attestation is not for {}", digest);
+    }
+    if statement.predicate_type != policy.predicate_type {
+        bail!("Unexpected predicate type: {}", statement.predicate_type);
+    }
+    if !policy.builder_ids.is_empty() {
+        let p = &statement.predicate;
+        let builder = p["builder"]["id"]
+            .as_str()
+            .or_else(|| p["runDetails"]["builder"]["id"].as_str())
+            .ok_or_else(|| anyhow!("Missing builder in attestation"))?;
+        if !policy.builder_ids.iter().any(|b| b == builder) {
+            bail!("Untrusted builder: {}", builder);
+        }
+    }
+    Ok(())
+}
+
+/// If an attestation policy is configured, require the image `imgref` with
+/// manifest `digest` to have a valid attestation.  Returns whether one was
+/// verified.  The registry is accessed with the same `settings` and
+/// `authfile` as the pull.
+pub(crate) async fn verify_image_attestation(
+    imgref: &OstreeImageReference,
+    digest: &str,
+    settings: &PullSettings,
+    authfile: Option<&tempfile::NamedTempFile>,
+) -> Result<bool> {
+    let rootfs = Dir::open_ambient_dir("/", cap_std::ambient_authority())?;
+    let policy = match rootfs.open_optional(POLICY_PATH)? {
+        Some(f) => f,
+        None => return Ok(false),
+    };
+    let policy: AttestationPolicy = serde_json::from_reader(std::io::BufReader::new(policy))
+        .with_context(|| format!("Parsing /{}", POLICY_PATH))?;
+    if !matches!(imgref.imgref.transport, Transport::Registry) {
+        bail!(
+            "Attestations can only be verified for images in a registry: {}",
+            imgref
+        );
+    }
+    let key = rootfs
+        .read(policy.key_path.trim_start_matches('/'))
+        .with_context(|| format!("Reading {}", policy.key_path))?;
+    let key =
+        PKey::public_key_from_pem(&key).with_context(|| format!("Parsing {}", policy.key_path))?;
+
+    let name = image_name_without_reference(&imgref.imgref.name);
+    let proxy = ImageProxy::new_with_config(settings.proxy_config(authfile)).await?;
+    let skopeo = || settings.skopeo_command(authfile);
+    let envelopes = fetch_attestations(&proxy, &skopeo, name, digest)
+        .await
+        .with_context(|| format!("Fetching attestations for {}", imgref))?;
+    proxy.finalize().await?;
+    if envelopes.is_empty() {
+        bail!("No attestation found for {} ({})", imgref, digest);
+    }
+    let mut errors = Vec::new();
+    for envelope in envelopes {
+        let r = verify_envelope(&key, &envelope)
+            .and_then(|statement| check_statement(&policy, &statement, digest));
+        match r {
+            Ok(()) => return Ok(true),
+            Err(e) => errors.push(format!("{:#}", e)),
+        }
+    }
+    Err(anyhow!(
+        "No valid attestation for {} ({}): {}",
+        imgref,
+        digest,
+        errors.join("; ")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::nid::Nid;
+    use openssl::sign::Signer;
+
+    const DIGEST: &str = "sha256:4c3b9b3f2f6b7f5e3c1d0a8c5e3e2d6f7a9b0c1d2e3f4a5b6c7d8e9f0a1b2c3d";
+
+    fn policy() -> AttestationPolicy {
+        serde_json::from_str(
+            r#"{
+                "predicateType": "https://slsa.dev/provenance/v0.2",
+                "keyPath": "/etc/pki/rpm-ostree/attestation.pub",
+                "builderIds": ["https://github.com/example/os/.github/workflows/build.yml@refs/heads/main"]
+            }"#,
+        )
+        .unwrap()
+    }
+
+    fn statement(builder: &str) -> serde_json::Value {
+        serde_json::json!({
+            "_type": "https://in-toto.io/Statement/v0.1",
+            "subject": [{
+                "name": "quay.io/example/os",
+                "digest": { "sha256": DIGEST.strip_prefix("sha256:").unwrap() }
+            }],
+            "predicateType": "https://slsa.dev/provenance/v0.2",
+            "predicate": { "builder": { "id": builder } }
+        })
+    }
+
+    #[test]
+    fn test_image_registry() {
+        assert_eq!(
+            image_name_without_reference("localhost:5000/os:stable"),
+            "localhost:5000/os"
+        );
+        assert_eq!(
+            image_name_without_reference("localhost:5000/os"),
+            "localhost:5000/os"
+        );
+        assert_eq!(image_registry("quay.io/example/os:stable"), "quay.io");
+        assert_eq!(
+            image_registry("localhost:5000/os@sha256:abcd"),
+            "localhost:5000"
+        );
+        assert_eq!(image_registry("fedora:36"), "docker.io");
+    }
+
+    #[test]
+    fn test_check_statement() {
+        let policy = policy();
+        let good: Statement = serde_json::from_value(statement(&policy.builder_ids[0])).unwrap();
+        check_statement(&policy, &good, DIGEST).unwrap();
+        let other_digest = DIGEST.replace("4c3b", "0000");
+        assert!(check_statement(&policy, &good, &other_digest).is_err());
+        let bad: Statement =
+            serde_json::from_value(statement("https://example.com/untrusted")).unwrap();
+        assert!(check_statement(&policy, &bad, DIGEST).is_err());
+    }
+
+    #[test]
+    fn test_verify_envelope() -> Result<()> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+        let private = PKey::from_ec_key(EcKey::generate(&group)?)?;
+        let public = PKey::public_key_from_pem(&private.public_key_to_pem()?)?;
+        let payload = serde_json::to_vec(&statement("https://example.com/builder"))?;
+        let mut signer = Signer::new(MessageDigest::sha256(), &private)?;
+        let sig = signer.sign_oneshot_to_vec(&pae(IN_TOTO_PAYLOAD, &payload))?;
+        let envelope = |sig: &[u8]| {
+            serde_json::to_vec(&serde_json::json!({
+                "payloadType": IN_TOTO_PAYLOAD,
+                "payload": glib::base64_encode(&payload).as_str(),
+                "signatures": [{ "keyid": "", "sig": glib::base64_encode(sig).as_str() }]
+            }))
+            .unwrap()
+        };
+        let statement = verify_envelope(&public, &envelope(&sig))?;
+        assert_eq!(statement.predicate_type, "https://slsa.dev/provenance/v0.2");
+        let mut bad_sig = sig.clone();
+        let last = bad_sig.len() - 1;
+        bad_sig[last] ^= 0xff;
+        assert!(verify_envelope(&public, &envelope(&bad_sig)).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+//! Manage the registry credentials used to pull container images, stored in
+//! the auth.json(5) file read by ostree-rs-ext.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::cxxrsutil::*;
+use anyhow::{anyhow, Context, Result};
+use cap_std::fs::{Dir, Permissions};
+use cap_std_ext::cap_std;
+use cap_std_ext::dirext::CapStdExtDirExt;
+use ostree_ext::glib;
+use std::io::Read;
+use std::os::unix::fs::PermissionsExt;
+
+/// Persistent credentials, relative to the root.
+const AUTH_PATH: &str = "etc/ostree/auth.json";
+/// Credentials which go away on reboot, relative to the root.
+const RUNTIME_AUTH_PATH: &str = "run/ostree/auth.json";
+
+/// Add (or replace) the credentials for `registry` in the auth.json content `auth`.
+pub(crate) fn set_registry_auth(
+    auth: &mut serde_json::Value,
+    registry: &str,
+    username: &str,
+    password: &str,
+) -> Result<()> {
+    if registry.is_empty() || registry.contains("://") {
+        return Err(anyhow!("Invalid registry: {}", registry));
+    }
+    if username.is_empty() || username.contains(':') {
+        return Err(anyhow!("Invalid username: {}", username));
+    }
+    let encoded = glib::base64_encode(format!("{}:{}", username, password).as_bytes());
+    let auths = auth
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("Expected a JSON object"))?
+        .entry("auths")
+        .or_insert_with(|| serde_json::json!({}))
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("Expected \"auths\" to be an object"))?;
+    auths.insert(
+        registry.to_string(),
+        serde_json::json!({ "auth": encoded.as_str() }),
+    );
+    Ok(())
+}
+
+fn image_auth_add_impl(
+    rootfs: &Dir,
+    path: &str,
+    registry: &str,
+    username: &str,
+    password: &str,
+) -> Result<()> {
+    let mut auth = match rootfs.open_optional(path)? {
+        Some(mut f) => {
+            let mut buf = String::new();
+            f.read_to_string(&mut buf)?;
+            serde_json::from_str(&buf).with_context(|| format!("Parsing /{}", path))?
+        }
+        None => serde_json::json!({}),
+    };
+    set_registry_auth(&mut auth, registry, username, password)
+        .with_context(|| format!("Updating /{}", path))?;
+    let parent = std::path::Path::new(path).parent().unwrap();
+    rootfs.create_dir_all(parent)?;
+    let buf = serde_json::to_vec_pretty(&auth)?;
+    rootfs.atomic_write_with_perms(path, &buf, Permissions::from_mode(0o600))?;
+    Ok(())
+}
+
+/// Store credentials for `registry`, used by all subsequent container image
+/// pulls.  If `runtime` is set, they are only kept until the next reboot.
+pub(crate) fn image_auth_add(
+    registry: &str,
+    username: &str,
+    password: &str,
+    runtime: bool,
+) -> CxxResult<()> {
+    let rootfs = Dir::open_ambient_dir("/", cap_std::ambient_authority())?;
+    let path = if runtime {
+        RUNTIME_AUTH_PATH
+    } else {
+        AUTH_PATH
+    };
+    image_auth_add_impl(&rootfs, path, registry, username, password)?;
+    Ok(())
+}
+
+/// Load the auth.json used by default for container pulls: the runtime one
+/// if it exists, as ostree-rs-ext does.
+pub(crate) fn load_auth() -> Result<serde_json::Value> {
+    let rootfs = Dir::open_ambient_dir("/", cap_std::ambient_authority())?;
+    for path in [RUNTIME_AUTH_PATH, AUTH_PATH] {
+        if let Some(mut f) = rootfs.open_optional(path)? {
+            let mut buf = String::new();
+            f.read_to_string(&mut buf)?;
+            return serde_json::from_str(&buf).with_context(|| format!("Parsing /{}", path));
+        }
+    }
+    Ok(serde_json::json!({}))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_registry_auth() -> Result<()> {
+        let mut auth = serde_json::json!({
+            "auths": {
+                "quay.io": { "auth": "b2xkOm9sZA==" },
+                "registry.example.com": { "auth": "Zm9vOmJhcg==" }
+            },
+            "credHelpers": { "docker.io": "secretservice" }
+        });
+        set_registry_auth(&mut auth, "quay.io", "someuser", "s3cret:pw")?;
+        assert_eq!(
+            auth["auths"]["quay.io"]["auth"],
+            // someuser:s3cret:pw
+            "c29tZXVzZXI6czNjcmV0OnB3"
+        );
+        assert_eq!(
+            auth["auths"]["registry.example.com"]["auth"],
+            "Zm9vOmJhcg=="
+        );
+        assert_eq!(auth["credHelpers"]["docker.io"], "secretservice");
+
+        let mut auth = serde_json::json!({});
+        set_registry_auth(&mut auth, "quay.io/exampleos", "someuser", "pw")?;
+        assert!(auth["auths"]["quay.io/exampleos"].is_object());
+        assert!(set_registry_auth(&mut auth, "", "someuser", "pw").is_err());
+        assert!(set_registry_auth(&mut auth, "docker://quay.io", "someuser", "pw").is_err());
+        assert!(set_registry_auth(&mut auth, "quay.io", "some:user", "pw").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,386 @@
+//! Reading files from the layers of container images without pulling them:
+//! the layers are streamed through the image proxy from the newest one, and
+//! only as far as needed.  Registries and their configuration are handled by
+//! the proxy as for pulls.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::sysroot_upgrade::PullSettings;
+use anyhow::{anyhow, bail, Context, Result};
+use ostree_ext::container::OstreeImageReference;
+use ostree_ext::containers_image_proxy::{ImageProxy, OpenedImage};
+use serde_derive::Deserialize;
+use std::collections::HashMap;
+use std::io::Read;
+use std::os::unix::io::{FromRawFd, IntoRawFd};
+use std::process::{Command, Stdio};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// The prefix of whiteout entries in layers, marking deleted files.
+const WHITEOUT_PREFIX: &str = ".wh.";
+/// Marks a directory whose content in lower layers is hidden.
+const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+
+/// The tarball of a layer, as read in [`ImageLayers::scan_layer`].
+pub(crate) type LayerEntry<'a> = tar::Entry<'a, Box<dyn Read + Send>>;
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Layer {
+    #[serde(default)]
+    pub(crate) media_type: String,
+    pub(crate) digest: String,
+    pub(crate) size: u64,
+    #[serde(default)]
+    pub(crate) annotations: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Manifest {
+    #[serde(default)]
+    layers: Vec<Layer>,
+}
+
+/// An image opened through the proxy, for the platform of the system.
+pub(crate) struct ImageLayers {
+    proxy: ImageProxy,
+    img: OpenedImage,
+    /// The layers, from the base one.
+    pub(crate) layers: Vec<Layer>,
+    // Read by the proxy
+    _authfile: Option<tempfile::NamedTempFile>,
+}
+
+/// The path of a layer entry, without leading `./` or `/`.
+pub(crate) fn entry_path(entry: &LayerEntry) -> Result<String> {
+    let path = entry.path()?;
+    let path = path
+        .to_str()
+        .ok_or_else(|| anyhow!("Invalid path in layer: {:?}", path))?;
+    Ok(path
+        .trim_start_matches("./")
+        .trim_start_matches('/')
+        .to_string())
+}
+
+/// The decompressor for a layer of media type `media_type`, if it's compressed.
+fn decompressor(media_type: &str) -> Result<Option<&'static str>> {
+    // Both the OCI and Docker media types
+    if media_type.ends_with("gzip") {
+        Ok(Some("gzip"))
+    } else if media_type.ends_with("zstd") {
+        Ok(Some("zstd"))
+    } else if media_type.ends_with("tar") {
+        Ok(None)
+    } else {
+        bail!("Unsupported layer media type: {}", media_type)
+    }
+}
+
+/// Call `f` on the entries of the tarball read from `input`, to the end.
+fn scan_tarball<S, F>(
+    media_type: &str,
+    input: std::os::unix::net::UnixStream,
+    state: &mut S,
+    f: &mut F,
+) -> Result<()>
+where
+    F: FnMut(&mut S, &mut LayerEntry) -> Result<()>,
+{
+    let (reader, mut child): (Box<dyn Read + Send>, _) = match decompressor(media_type)? {
+        Some(cmd) => {
+            // SAFETY: We own the stream
+            let stdin = unsafe { Stdio::from_raw_fd(input.into_raw_fd()) };
+            let mut child = Command::new(cmd)
+                .arg("-dc")
+                .stdin(stdin)
+                .stdout(Stdio::piped())
+                .spawn()
+                .with_context(|| format!("Spawning {}", cmd))?;
+            (Box::new(child.stdout.take().unwrap()), Some(child))
+        }
+        None => (Box::new(input), None),
+    };
+    let mut archive = tar::Archive::new(reader);
+    let scan = || -> Result<()> {
+        for entry in archive.entries()? {
+            f(state, &mut entry?)?;
+        }
+        // Also read the end of the archive, so that the whole blob is verified
+        std::io::copy(&mut archive.into_inner(), &mut std::io::sink())?;
+        Ok(())
+    };
+    let r = scan();
+    if let Some(child) = child.as_mut() {
+        if r.is_err() {
+            let _ = child.kill();
+        }
+        let status = child.wait()?;
+        if r.is_ok() && !status.success() {
+            bail!("Decompressing layer failed: {:?}", status);
+        }
+    }
+    r
+}
+
+impl ImageLayers {
+    pub(crate) async fn open(imgref: &OstreeImageReference) -> Result<Self> {
+        let authfile = crate::credentials::registry_authfile(imgref)?;
+        let config = PullSettings::default().proxy_config(authfile.as_ref());
+        let proxy = ImageProxy::new_with_config(config).await?;
+        let img = proxy.open_image(&imgref.imgref.to_string()).await?;
+        let (_, manifest) = proxy.fetch_manifest(&img).await?;
+        let manifest: Manifest = serde_json::from_slice(&manifest).context("Parsing manifest")?;
+        Ok(Self {
+            proxy,
+            img,
+            layers: manifest.layers,
+            _authfile: authfile,
+        })
+    }
+
+    pub(crate) async fn close(self) -> Result<()> {
+        self.proxy.close_image(&self.img).await?;
+        self.proxy.finalize().await?;
+        Ok(())
+    }
+
+    /// Stream `layer`, calling `f` with `state` on each entry of its tarball in
+    /// a blocking thread, and return the state once the digest of the layer is
+    /// verified.  The layer isn't kept.
+    pub(crate) async fn scan_layer<S, F>(&self, layer: &Layer, state: S, mut f: F) -> Result<S>
+    where
+        S: Send + 'static,
+        F: FnMut(&mut S, &mut LayerEntry) -> Result<()> + Send + 'static,
+    {
+        let expected = layer
+            .digest
+            .strip_prefix("sha256:")
+            .ok_or_else(|| anyhow!("Unsupported digest: {}", layer.digest))?
+            .to_string();
+        let (mut tx, rx) = tokio::net::UnixStream::pair()?;
+        let rx = rx.into_std()?;
+        rx.set_nonblocking(false)?;
+        let media_type = layer.media_type.clone();
+        let scan = tokio::task::spawn_blocking(move || {
+            let mut state = state;
+            scan_tarball(&media_type, rx, &mut state, &mut f).map(|()| state)
+        });
+        let (mut blob, driver) = self
+            .proxy
+            .get_blob(&self.img, &layer.digest, layer.size)
+            .await?;
+        let copy = async move {
+            let mut hasher = openssl::sha::Sha256::new();
+            let mut buf = vec![0u8; 128 * 1024];
+            loop {
+                let n = blob.read(&mut buf).await?;
+                if n == 0 {
+                    break;
+                }
+                hasher.update(&buf[..n]);
+                tx.write_all(&buf[..n]).await?;
+            }
+            tx.shutdown().await?;
+            Ok::<_, anyhow::Error>(hasher.finish())
+        };
+        let (copied, driver, scanned) = tokio::join!(copy, driver, scan);
+        let state = scanned?.with_context(|| format!("Reading layer {}", layer.digest))?;
+        let found: String = copied?.iter().map(|b| format!("{:02x}", b)).collect();
+        driver?;
+        if found != expected {
+            bail!("Corrupted layer {}: found sha256:{}", layer.digest, found);
+        }
+        Ok(state)
+    }
+
+    /// Find regular files in the layers, from the newest.  Each file is given
+    /// by the paths where it may be; the first match in the newest layer which
+    /// has any of them, or deletes them, wins.  Hard links, as in ostree
+    /// encapsulated images, are resolved within their layer.
+    pub(crate) async fn find_files(&self, files: &[&[&str]]) -> Result<Vec<Option<Vec<u8>>>> {
+        let mut r: Vec<Option<Option<Vec<u8>>>> = vec![None; files.len()];
+        for layer in self.layers.iter().rev() {
+            let wanted: Vec<(usize, Vec<String>)> = r
+                .iter()
+                .enumerate()
+                .filter(|(_, f)| f.is_none())
+                .map(|(i, _)| (i, files[i].iter().map(|p| p.to_string()).collect()))
+                .collect();
+            if wanted.is_empty() {
+                break;
+            }
+            let mut state = self
+                .scan_layer(layer, LayerFiles::default(), move |state, entry| {
+                    state.add(&wanted, entry)
+                })
+                .await?;
+            // The targets of hard links may be anywhere in the layer
+            if !state.links.is_empty() {
+                let links = state.links.clone();
+                let targets = self
+                    .scan_layer(layer, HashMap::new(), move |found, entry| {
+                        let path = entry_path(entry)?;
+                        if links.values().any(|t| *t == path) {
+                            let mut buf = Vec::new();
+                            entry.read_to_end(&mut buf)?;
+                            found.insert(path, buf);
+                        }
+                        Ok(())
+                    })
+                    .await?;
+                for (i, target) in state.links.drain() {
+                    let content = targets
+                        .get(&target)
+                        .ok_or_else(|| anyhow!("Missing link target {}", target))?;
+                    state.found.insert(i, Some(content.clone()));
+                }
+            }
+            for (i, content) in state.found {
+                r[i] = Some(content);
+            }
+        }
+        Ok(r.into_iter().map(|f| f.flatten()).collect())
+    }
+}
+
+/// What a layer has of the files looked for by [`ImageLayers::find_files`].
+#[derive(Default)]
+struct LayerFiles {
+    /// By index: the content, or `None` if deleted.
+    found: HashMap<usize, Option<Vec<u8>>>,
+    /// By index: the targets of hard links.
+    links: HashMap<usize, String>,
+}
+
+impl LayerFiles {
+    fn add(&mut self, wanted: &[(usize, Vec<String>)], entry: &mut LayerEntry) -> Result<()> {
+        let path = entry_path(entry)?;
+        let (dir, name) = path.rsplit_once('/').unwrap_or(("", path.as_str()));
+        for (i, paths) in wanted {
+            if paths.contains(&path) {
+                match entry.header().entry_type() {
+                    tar::EntryType::Regular => {
+                        let mut buf = Vec::new();
+                        entry.read_to_end(&mut buf)?;
+                        self.found.insert(*i, Some(buf));
+                    }
+                    tar::EntryType::Link => {
+                        let target = entry
+                            .link_name()?
+                            .ok_or_else(|| anyhow!("Missing link target of {}", path))?;
+                        let target = target.to_string_lossy();
+                        let target = target.trim_start_matches("./").trim_start_matches('/');
+                        self.links.insert(*i, target.to_string());
+                    }
+                    _ => bail!("{} is not a regular file", path),
+                }
+                return Ok(());
+            }
+            // The files of the layer itself win over its whiteouts
+            if self.found.contains_key(i) || self.links.contains_key(i) {
+                continue;
+            }
+            let deleted = paths.iter().any(|p| {
+                let (pdir, pname) = p.rsplit_once('/').unwrap_or(("", p.as_str()));
+                let below = dir.is_empty() || p.starts_with(&format!("{}/", dir));
+                (pdir == dir && name.strip_prefix(WHITEOUT_PREFIX) == Some(pname))
+                    || (name == OPAQUE_WHITEOUT && below)
+            });
+            if deleted {
+                self.found.insert(*i, None);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tarball(entries: &[(&str, Option<&str>)]) -> Result<Vec<u8>> {
+        let mut b = tar::Builder::new(Vec::new());
+        for (path, content) in entries {
+            let mut h = tar::Header::new_gnu();
+            match content {
+                Some(content) => {
+                    h.set_size(content.len() as u64);
+                    h.set_entry_type(tar::EntryType::Regular);
+                    b.append_data(&mut h, path, content.as_bytes())?;
+                }
+                None => {
+                    h.set_size(0);
+                    h.set_entry_type(tar::EntryType::Directory);
+                    b.append_data(&mut h, path, std::io::empty())?;
+                }
+            }
+        }
+        Ok(b.into_inner()?)
+    }
+
+    fn scan(buf: Vec<u8>, wanted: &[(usize, Vec<String>)]) -> Result<LayerFiles> {
+        let (mut tx, rx) = std::os::unix::net::UnixStream::pair()?;
+        let writer = std::thread::spawn(move || std::io::Write::write_all(&mut tx, &buf));
+        let mut state = LayerFiles::default();
+        scan_tarball(
+            "application/vnd.oci.image.layer.v1.tar",
+            rx,
+            &mut state,
+            &mut |s, e| s.add(wanted, e),
+        )?;
+        writer.join().unwrap()?;
+        Ok(state)
+    }
+
+    #[test]
+    fn test_layer_files() -> Result<()> {
+        let wanted = vec![
+            (
+                0,
+                vec!["usr/etc/passwd".to_string(), "etc/passwd".to_string()],
+            ),
+            (
+                1,
+                vec!["usr/etc/group".to_string(), "etc/group".to_string()],
+            ),
+            (2, vec!["usr/etc/shadow".to_string()]),
+        ];
+        let buf = tarball(&[
+            ("./etc/", None),
+            ("./etc/passwd", Some("root:x:0:0::/root:/bin/bash\n")),
+            ("./etc/.wh.group", Some("")),
+            ("./usr/etc/.wh..wh..opq", Some("")),
+        ])?;
+        let state = scan(buf, &wanted)?;
+        assert_eq!(
+            state.found[&0].as_deref(),
+            Some(&b"root:x:0:0::/root:/bin/bash\n"[..])
+        );
+        assert_eq!(state.found[&1], None);
+        assert_eq!(state.found[&2], None);
+        assert!(state.links.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_decompressor() {
+        assert_eq!(
+            decompressor("application/vnd.oci.image.layer.v1.tar+gzip").unwrap(),
+            Some("gzip")
+        );
+        assert_eq!(
+            decompressor("application/vnd.docker.image.rootfs.diff.tar.gzip").unwrap(),
+            Some("gzip")
+        );
+        assert_eq!(
+            decompressor("application/vnd.oci.image.layer.v1.tar+zstd").unwrap(),
+            Some("zstd")
+        );
+        assert_eq!(
+            decompressor("application/vnd.oci.image.layer.v1.tar").unwrap(),
+            None
+        );
+        assert!(decompressor("application/vnd.oci.image.config.v1+json").is_err());
+    }
+}
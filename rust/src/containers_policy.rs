@@ -0,0 +1,822 @@
+//! Checks against the signature policy of containers-policy.json(5), used
+//! when container signature enforcement is requested, and the implementation
+//! of `rpm-ostree ex sigpolicy` to manage the policy for registry images.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use anyhow::{anyhow, bail, Context, Result};
+use camino::Utf8PathBuf;
+use cap_std::fs::{Dir, Permissions};
+use cap_std_ext::cap_std;
+use cap_std_ext::dirext::CapStdExtDirExt;
+use clap::Parser;
+use ostree_ext::container::{OstreeImageReference, SignatureSource, Transport};
+use serde_derive::Deserialize;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Read;
+use std::os::unix::fs::PermissionsExt;
+
+/// Path to the system policy, relative to the root.
+const POLICY_PATH: &str = "etc/containers/policy.json";
+/// Where keys given to `ex sigpolicy` are copied, relative to the root.
+const KEYS_DIR: &str = "etc/pki/containers";
+/// containers-registries.d(5), relative to the root.
+const REGISTRIES_D: &str = "etc/containers/registries.d";
+/// Enables sigstore attachments for the scopes which require a sigstore
+/// signature; written by `ex sigpolicy`, in `REGISTRIES_D`.
+const REGISTRIES_D_CONF: &str = "rpm-ostree-sigpolicy.yaml";
+/// OpenPGP packet tag of a public key.
+const PGP_TAG_PUBLIC_KEY: u8 = 6;
+
+/// A single policy requirement; all of them must be satisfied for an image.
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum PolicyRequirement {
+    InsecureAcceptAnything,
+    Reject,
+    SignedBy,
+    SigstoreSigned,
+    #[serde(other)]
+    Unknown,
+}
+
+impl PolicyRequirement {
+    fn requires_signature(&self) -> bool {
+        matches!(self, Self::SignedBy | Self::SigstoreSigned)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct Policy {
+    default: Vec<PolicyRequirement>,
+    #[serde(default)]
+    transports: HashMap<String, HashMap<String, Vec<PolicyRequirement>>>,
+}
+
+/// The scopes a docker image name matches, from most to least specific.
+fn docker_scopes(name: &str) -> Vec<String> {
+    let mut scopes = vec![name.to_string()];
+    let repo = match name.split_once('@') {
+        Some((repo, _)) => repo,
+        None => match name.rsplit_once(':') {
+            Some((repo, tag)) if !tag.contains('/') => repo,
+            _ => name,
+        },
+    };
+    let mut scope = repo;
+    loop {
+        if scope != name {
+            scopes.push(scope.to_string());
+        }
+        match scope.rsplit_once('/') {
+            Some((parent, _)) => scope = parent,
+            None => break,
+        }
+    }
+    // Wildcards for the subdomains of the registry host
+    let mut host = scope;
+    while let Some((_, rest)) = host.split_once('.') {
+        scopes.push(format!("*.{}", rest));
+        host = rest;
+    }
+    scopes
+}
+
+impl Policy {
+    /// Find the transport scope which applies to the given image, if any.
+    fn scope_for(&self, transport: &str, name: &str) -> Option<String> {
+        let scopes = self.transports.get(transport)?;
+        let candidates = if transport == "docker" {
+            docker_scopes(name)
+        } else {
+            vec![name.to_string()]
+        };
+        candidates
+            .into_iter()
+            .chain(std::iter::once(String::new()))
+            .find(|s| scopes.contains_key(s))
+    }
+
+    /// Find the requirements which apply to the given image.
+    fn requirements_for(&self, transport: &str, name: &str) -> &[PolicyRequirement] {
+        self.scope_for(transport, name)
+            .and_then(|scope| self.transports[transport].get(&scope))
+            .unwrap_or(&self.default)
+    }
+}
+
+fn transport_name(transport: &Transport) -> &'static str {
+    match transport {
+        Transport::Registry => "docker",
+        Transport::OciDir => "oci",
+        Transport::OciArchive => "oci-archive",
+        Transport::ContainerStorage => "containers-storage",
+    }
+}
+
+fn require_signed_image_impl(rootfs: &Dir, imgref: &OstreeImageReference) -> Result<()> {
+    match &imgref.sigverify {
+        SignatureSource::ContainerPolicy => {}
+        SignatureSource::OstreeRemote(_) | SignatureSource::ContainerPolicyAllowInsecure => {
+            return Err(anyhow!(
+                "Refusing to pull {}: container signature policy enforcement requires an ostree-image-signed: image reference",
+                imgref
+            ))
+        }
+    }
+    let policy = rootfs
+        .open_optional(POLICY_PATH)?
+        .ok_or_else(|| anyhow!("Missing /{}", POLICY_PATH))?;
+    let policy: Policy = serde_json::from_reader(std::io::BufReader::new(policy))
+        .with_context(|| format!("Parsing /{}", POLICY_PATH))?;
+    let transport = transport_name(&imgref.imgref.transport);
+    let reqs = policy.requirements_for(transport, &imgref.imgref.name);
+    if !reqs.iter().any(|r| r.requires_signature()) {
+        return Err(anyhow!(
+            "Refusing to pull {}: /{} does not require a signature (e.g. sigstoreSigned) for it",
+            imgref,
+            POLICY_PATH
+        ));
+    }
+    Ok(())
+}
+
+/// Error out unless pulling `imgref` will verify its signature according to the
+/// system containers-policy.json.
+pub(crate) fn require_signed_image(imgref: &OstreeImageReference) -> Result<()> {
+    let rootfs = Dir::open_ambient_dir("/", cap_std::ambient_authority())?;
+    require_signed_image_impl(&rootfs, imgref)
+}
+
+#[derive(Debug, Parser)]
+#[clap(name = "rpm-ostree ex sigpolicy")]
+#[clap(rename_all = "kebab-case")]
+enum Opt {
+    /// Show the signature requirements for registry images
+    Show(ShowOpts),
+    /// Require images in SCOPE to be signed with a sigstore (cosign) key; if a
+    /// key is already required, images signed with either key are accepted
+    AddSigstoreKey(KeyOpts),
+    /// Require images in SCOPE to be signed with a GPG key
+    RequireSignedBy(KeyOpts),
+    /// Remove the requirements for SCOPE, so that a less specific one applies
+    Remove(RemoveOpts),
+}
+
+#[derive(Debug, Parser)]
+struct ShowOpts {
+    /// Only show the requirements which apply to an image, e.g. quay.io/example/os:stable
+    #[clap(long)]
+    image: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+struct KeyOpts {
+    /// Scope in the registry, e.g. quay.io/example/os, quay.io/example or *.example.com
+    scope: String,
+    /// Path to the public key; it is copied to /etc/pki/containers
+    key: Utf8PathBuf,
+}
+
+#[derive(Debug, Parser)]
+struct RemoveOpts {
+    scope: String,
+}
+
+pub(crate) fn sigpolicy_entrypoint(args: &Vec<String>) -> Result<()> {
+    let opt = Opt::parse_from(args.iter());
+    if !matches!(opt, Opt::Show(_)) {
+        crate::ffi::client_require_root()?;
+    }
+    let rootfs = Dir::open_ambient_dir("/", cap_std::ambient_authority())?;
+    match opt {
+        Opt::Show(ref opts) => show(&rootfs, opts),
+        Opt::AddSigstoreKey(ref opts) => {
+            let key = install_key(&rootfs, opts, KeyKind::Sigstore)?;
+            update_policy(&rootfs, |p| add_sigstore_key(p, &opts.scope, &key))?;
+            println!("Images in {} must be signed with {}", opts.scope, key);
+            Ok(())
+        }
+        Opt::RequireSignedBy(ref opts) => {
+            let key = install_key(&rootfs, opts, KeyKind::Gpg)?;
+            update_policy(&rootfs, |p| require_signed_by(p, &opts.scope, &key))?;
+            println!("Images in {} must be signed with {}", opts.scope, key);
+            Ok(())
+        }
+        Opt::Remove(ref opts) => {
+            update_policy(&rootfs, |p| remove_scope(p, &opts.scope))?;
+            println!("Removed requirements for {}", opts.scope);
+            Ok(())
+        }
+    }
+}
+
+fn load_policy(rootfs: &Dir) -> Result<Value> {
+    let mut f = rootfs
+        .open_optional(POLICY_PATH)?
+        .ok_or_else(|| anyhow!("Missing /{}", POLICY_PATH))?;
+    let mut buf = String::new();
+    f.read_to_string(&mut buf)?;
+    serde_json::from_str(&buf).with_context(|| format!("Parsing /{}", POLICY_PATH))
+}
+
+/// Apply `f` to the policy, validate the result, and write it back along with
+/// the matching registries configuration.
+fn update_policy(rootfs: &Dir, f: impl FnOnce(&mut Value) -> Result<()>) -> Result<()> {
+    let mut policy = load_policy(rootfs)?;
+    f(&mut policy).with_context(|| format!("Updating /{}", POLICY_PATH))?;
+    serde_json::from_value::<Policy>(policy.clone())
+        .with_context(|| format!("Validating /{}", POLICY_PATH))?;
+    let perms = Permissions::from_mode(0o644);
+    let buf = serde_json::to_vec_pretty(&policy)?;
+    rootfs.atomic_write_with_perms(POLICY_PATH, &buf, perms.clone())?;
+    let existing = existing_registries_scopes(rootfs)?;
+    for scope in sigstore_scopes(&policy) {
+        if existing.get(scope) == Some(&false) {
+            println!(
+                "Note: {} is configured in /{} without use-sigstore-attachments",
+                scope, REGISTRIES_D
+            );
+        }
+    }
+    let conf = format!("{}/{}", REGISTRIES_D, REGISTRIES_D_CONF);
+    match registries_config(&policy, &existing)? {
+        Some(config) => {
+            rootfs.create_dir_all(REGISTRIES_D)?;
+            rootfs.atomic_write_with_perms(&conf, config, perms)?;
+        }
+        None => {
+            rootfs.remove_file_optional(&conf)?;
+        }
+    }
+    Ok(())
+}
+
+fn validate_scope(scope: &str) -> Result<()> {
+    if scope.is_empty() || scope.contains("://") || scope.contains(char::is_whitespace) {
+        bail!("Invalid scope: {:?}", scope);
+    }
+    match scope.strip_prefix("*.") {
+        Some(domain) if domain.is_empty() || domain.contains(['*', '/', ':', '@']) => {
+            bail!(
+                "Invalid wildcard scope {}; expected e.g. *.example.com",
+                scope
+            )
+        }
+        Some(_) => Ok(()),
+        None if scope.contains('*') => {
+            bail!(
+                "Invalid scope {}; wildcards are only supported as *.domain",
+                scope
+            )
+        }
+        None => Ok(()),
+    }
+}
+
+/// The scopes of the `docker` transport, i.e. registry images.
+fn docker_scopes_mut(policy: &mut Value) -> Result<&mut serde_json::Map<String, Value>> {
+    policy
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("Expected a JSON object"))?
+        .entry("transports")
+        .or_insert_with(|| json!({}))
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("Expected \"transports\" to be an object"))?
+        .entry("docker")
+        .or_insert_with(|| json!({}))
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("Expected \"transports.docker\" to be an object"))
+}
+
+fn add_sigstore_key(policy: &mut Value, scope: &str, key: &str) -> Result<()> {
+    validate_scope(scope)?;
+    let scopes = docker_scopes_mut(policy)?;
+    let existing = scopes
+        .get_mut(scope)
+        .and_then(|v| v.as_array_mut())
+        .and_then(|reqs| {
+            reqs.iter_mut()
+                .find(|r| r["type"] == "sigstoreSigned" && r.get("keyData").is_none())
+        })
+        .and_then(|r| r.as_object_mut());
+    let req = match existing {
+        Some(req) => req,
+        None => {
+            // Cosign signs the repository rather than a tag
+            scopes.insert(
+                scope.to_string(),
+                json!([{
+                    "type": "sigstoreSigned",
+                    "keyPath": key,
+                    "signedIdentity": {"type": "matchRepository"}
+                }]),
+            );
+            return Ok(());
+        }
+    };
+    let mut keys: Vec<Value> = match (req.remove("keyPath"), req.remove("keyPaths")) {
+        (Some(path), None) => vec![path],
+        (None, Some(Value::Array(paths))) => paths,
+        _ => bail!("Unexpected sigstoreSigned requirement for {}", scope),
+    };
+    if !keys.iter().any(|k| k == key) {
+        keys.push(key.into());
+    }
+    if keys.len() == 1 {
+        req.insert("keyPath".into(), keys.pop().unwrap());
+    } else {
+        req.insert("keyPaths".into(), keys.into());
+    }
+    Ok(())
+}
+
+fn require_signed_by(policy: &mut Value, scope: &str, key: &str) -> Result<()> {
+    validate_scope(scope)?;
+    docker_scopes_mut(policy)?.insert(
+        scope.to_string(),
+        json!([{"type": "signedBy", "keyType": "GPGKeys", "keyPath": key}]),
+    );
+    Ok(())
+}
+
+fn remove_scope(policy: &mut Value, scope: &str) -> Result<()> {
+    if docker_scopes_mut(policy)?.remove(scope).is_none() {
+        bail!("No requirements for scope {}", scope);
+    }
+    Ok(())
+}
+
+/// The scopes of the `docker` transport which require a sigstore signature.
+fn sigstore_scopes(policy: &Value) -> Vec<&str> {
+    let scopes = match policy["transports"]["docker"].as_object() {
+        Some(scopes) => scopes,
+        None => return Vec::new(),
+    };
+    scopes
+        .iter()
+        .filter(|(_, reqs)| {
+            reqs.as_array()
+                .map(|reqs| reqs.iter().any(|r| r["type"] == "sigstoreSigned"))
+                .unwrap_or_default()
+        })
+        .map(|(scope, _)| scope.as_str())
+        .collect()
+}
+
+/// The scopes configured in the containers-registries.d(5) files other than
+/// ours, along with whether they enable sigstore attachments.  Scopes can't be
+/// configured in multiple files.
+fn existing_registries_scopes(rootfs: &Dir) -> Result<BTreeMap<String, bool>> {
+    let mut r = BTreeMap::new();
+    let dir = match rootfs.open_dir_optional(REGISTRIES_D)? {
+        Some(d) => d,
+        None => return Ok(r),
+    };
+    for entry in dir.entries()? {
+        let name = entry?.file_name();
+        let name = name
+            .to_str()
+            .ok_or_else(|| anyhow!("Invalid filename: {:?}", name))?;
+        if !name.ends_with(".yaml") || name == REGISTRIES_D_CONF {
+            continue;
+        }
+        let buf = dir
+            .read_to_string(name)
+            .with_context(|| format!("Reading /{}/{}", REGISTRIES_D, name))?;
+        let config: serde_yaml::Value = serde_yaml::from_str(&buf)
+            .with_context(|| format!("Parsing /{}/{}", REGISTRIES_D, name))?;
+        if let Some(scopes) = config["docker"].as_mapping() {
+            for (scope, conf) in scopes {
+                if let Some(scope) = scope.as_str() {
+                    let sigstore = conf["use-sigstore-attachments"]
+                        .as_bool()
+                        .unwrap_or_default();
+                    r.insert(scope.to_string(), sigstore);
+                }
+            }
+        }
+    }
+    Ok(r)
+}
+
+/// The containers-registries.d(5) configuration enabling sigstore signatures
+/// for the scopes which require them, if any, except for those which are
+/// configured already in other files (`existing`).
+fn registries_config(policy: &Value, existing: &BTreeMap<String, bool>) -> Result<Option<String>> {
+    let docker: BTreeMap<&str, Value> = sigstore_scopes(policy)
+        .into_iter()
+        .filter(|scope| !existing.contains_key(*scope))
+        .map(|scope| (scope, json!({"use-sigstore-attachments": true})))
+        .collect();
+    if docker.is_empty() {
+        return Ok(None);
+    }
+    let config = serde_yaml::to_string(&BTreeMap::from([("docker", docker)]))?;
+    Ok(Some(format!(
+        "# Generated by rpm-ostree ex sigpolicy; edits will be overwritten\n{}",
+        config
+    )))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KeyKind {
+    Sigstore,
+    Gpg,
+}
+
+/// Decode an ASCII armored OpenPGP public key (RFC 4880 section 6.2).
+fn pgp_dearmor(text: &str) -> Result<Vec<u8>> {
+    let mut lines = text.lines().map(|l| l.trim()).skip_while(|l| l.is_empty());
+    if lines.next() != Some("-----BEGIN PGP PUBLIC KEY BLOCK-----") {
+        bail!("Missing armor header line");
+    }
+    // Skip the armor headers, up to an empty line
+    let lines = lines.skip_while(|l| !l.is_empty()).skip(1);
+    let mut data = String::new();
+    for line in lines {
+        if line == "-----END PGP PUBLIC KEY BLOCK-----" {
+            return openssl::base64::decode_block(&data).context("Decoding armored key");
+        }
+        // The checksum
+        if !line.starts_with('=') {
+            data.push_str(line);
+        }
+    }
+    bail!("Missing armor tail line")
+}
+
+/// Check that `buf` starts with a complete OpenPGP public key packet (RFC 4880
+/// section 4.2), as in exported keys.
+fn check_pgp_public_key(buf: &[u8]) -> Result<()> {
+    let be = |b: &[u8]| b.iter().fold(0usize, |n, &b| (n << 8) | b as usize);
+    let (&first, rest) = buf.split_first().ok_or_else(|| anyhow!("Empty key"))?;
+    if first & 0x80 == 0 {
+        bail!("Not an OpenPGP packet");
+    }
+    let (tag, len, body) = if first & 0x40 != 0 {
+        // New format; partial lengths aren't allowed for keys
+        let tag = first & 0x3f;
+        match rest {
+            [l, body @ ..] if *l < 192 => (tag, *l as usize, body),
+            [l1, l2, body @ ..] if *l1 < 224 => {
+                (tag, ((*l1 as usize - 192) << 8) + *l2 as usize + 192, body)
+            }
+            [255, l @ ..] if l.len() >= 4 => (tag, be(&l[..4]), &l[4..]),
+            _ => bail!("Invalid packet length"),
+        }
+    } else {
+        let n = match first & 0x03 {
+            0 => 1,
+            1 => 2,
+            2 => 4,
+            _ => bail!("Invalid packet length"),
+        };
+        let l = rest
+            .get(..n)
+            .ok_or_else(|| anyhow!("Truncated packet header"))?;
+        ((first >> 2) & 0x0f, be(l), &rest[n..])
+    };
+    if tag != PGP_TAG_PUBLIC_KEY {
+        bail!("Expected a public key packet, found tag {}", tag);
+    }
+    let body = body
+        .get(..len)
+        .ok_or_else(|| anyhow!("Truncated public key packet"))?;
+    match body.first() {
+        Some(4..=6) => Ok(()),
+        Some(v) => bail!("Unsupported key version {}", v),
+        None => bail!("Empty public key packet"),
+    }
+}
+
+fn validate_key(buf: &[u8], kind: KeyKind) -> Result<()> {
+    match kind {
+        KeyKind::Sigstore => {
+            openssl::pkey::PKey::public_key_from_pem(buf)
+                .context("Expected a PEM encoded public key")?;
+        }
+        // Either ASCII armored, or binary
+        KeyKind::Gpg => {
+            let armored = std::str::from_utf8(buf)
+                .ok()
+                .filter(|t| t.trim_start().starts_with("-----BEGIN PGP"));
+            let r = match armored {
+                Some(text) => pgp_dearmor(text).and_then(|buf| check_pgp_public_key(&buf)),
+                None => check_pgp_public_key(buf),
+            };
+            r.context("Expected a GPG public key")?;
+        }
+    }
+    Ok(())
+}
+
+/// Validate the key given on the command line and copy it to /etc/pki/containers,
+/// returning its absolute path there.
+fn install_key(rootfs: &Dir, opts: &KeyOpts, kind: KeyKind) -> Result<String> {
+    let buf = std::fs::read(&opts.key).with_context(|| format!("Reading {}", opts.key))?;
+    validate_key(&buf, kind).with_context(|| format!("Validating {}", opts.key))?;
+    let name = opts
+        .key
+        .file_name()
+        .ok_or_else(|| anyhow!("Invalid key path: {}", opts.key))?;
+    let path = format!("{}/{}", KEYS_DIR, name);
+    if let Some(mut f) = rootfs.open_optional(&path)? {
+        let mut existing = Vec::new();
+        f.read_to_end(&mut existing)?;
+        if existing != buf {
+            bail!("/{} already exists with different content", path);
+        }
+    } else {
+        rootfs.create_dir_all(KEYS_DIR)?;
+        rootfs.atomic_write_with_perms(&path, &buf, Permissions::from_mode(0o644))?;
+    }
+    Ok(format!("/{}", path))
+}
+
+/// A short description of a policy requirement.
+fn describe_requirement(req: &Value) -> String {
+    let kind = req["type"].as_str().unwrap_or("unknown");
+    let keys: Vec<&str> = match (&req["keyPath"], &req["keyPaths"]) {
+        (Value::String(path), _) => vec![path.as_str()],
+        (_, Value::Array(paths)) => paths.iter().filter_map(|p| p.as_str()).collect(),
+        _ if req.get("keyData").is_some() => vec!["inline key"],
+        _ => Vec::new(),
+    };
+    if keys.is_empty() {
+        kind.to_string()
+    } else {
+        format!("{} ({})", kind, keys.join(", "))
+    }
+}
+
+fn describe_requirements(reqs: &Value) -> String {
+    match reqs.as_array() {
+        Some(reqs) => reqs
+            .iter()
+            .map(describe_requirement)
+            .collect::<Vec<_>>()
+            .join(", "),
+        None => "invalid".to_string(),
+    }
+}
+
+fn show(rootfs: &Dir, opts: &ShowOpts) -> Result<()> {
+    let value = load_policy(rootfs)?;
+    let policy: Policy = serde_json::from_value(value.clone())
+        .with_context(|| format!("Parsing /{}", POLICY_PATH))?;
+    if let Some(image) = opts.image.as_deref() {
+        let scope = policy.scope_for("docker", image);
+        let (label, reqs) = match scope.as_deref() {
+            Some("") => ("default for registries", &value["transports"]["docker"][""]),
+            Some(scope) => (scope, &value["transports"]["docker"][scope]),
+            None => ("default", &value["default"]),
+        };
+        println!("{}: {}", label, describe_requirements(reqs));
+        let signed = policy
+            .requirements_for("docker", image)
+            .iter()
+            .any(|r| r.requires_signature());
+        if !signed {
+            println!("Note: no signature is required for {}", image);
+        }
+        return Ok(());
+    }
+    println!("default: {}", describe_requirements(&value["default"]));
+    if let Some(scopes) = value["transports"]["docker"].as_object() {
+        let scopes: BTreeMap<_, _> = scopes.iter().collect();
+        for (scope, reqs) in scopes {
+            let scope = if scope.is_empty() {
+                "(registries)"
+            } else {
+                scope
+            };
+            println!("{}: {}", scope, describe_requirements(reqs));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::pkey::PKey;
+
+    const POLICY: &str = r#"{
+        "default": [{"type": "insecureAcceptAnything"}],
+        "transports": {
+            "docker": {
+                "quay.io/example": [{"type": "sigstoreSigned", "keyPath": "/etc/pki/example.pub"}],
+                "quay.io/example/unsigned": [{"type": "insecureAcceptAnything"}],
+                "*.example.com": [{"type": "signedBy", "keyType": "GPGKeys", "keyPath": "/k"}],
+                "registry.local": [{"type": "reject"}]
+            },
+            "docker-daemon": {"": [{"type": "insecureAcceptAnything"}]}
+        }
+    }"#;
+
+    #[test]
+    fn test_docker_scopes() {
+        assert_eq!(
+            docker_scopes("quay.io/example/os:stable"),
+            vec![
+                "quay.io/example/os:stable",
+                "quay.io/example/os",
+                "quay.io/example",
+                "quay.io",
+                "*.io"
+            ]
+        );
+        assert_eq!(
+            docker_scopes("localhost:5000/os"),
+            vec!["localhost:5000/os", "localhost:5000"]
+        );
+    }
+
+    #[test]
+    fn test_requirements_for() {
+        let policy: Policy = serde_json::from_str(POLICY).unwrap();
+        let signed = |name: &str| {
+            policy
+                .requirements_for("docker", name)
+                .iter()
+                .any(|r| r.requires_signature())
+        };
+        assert!(signed("quay.io/example/os:stable"));
+        assert!(!signed("quay.io/example/unsigned:latest"));
+        assert!(signed("registry.example.com/os@sha256:abcd"));
+        assert!(!signed("registry.local/os"));
+        assert!(!signed("docker.io/library/busybox"));
+        assert_eq!(
+            policy.requirements_for("oci", "/srv/os"),
+            &[PolicyRequirement::InsecureAcceptAnything]
+        );
+    }
+
+    #[test]
+    fn test_scope_for() {
+        let policy: Policy = serde_json::from_str(POLICY).unwrap();
+        assert_eq!(
+            policy.scope_for("docker", "quay.io/example/os:stable"),
+            Some("quay.io/example".into())
+        );
+        assert_eq!(
+            policy.scope_for("docker", "docker.io/library/busybox"),
+            None
+        );
+        assert_eq!(
+            policy.scope_for("docker-daemon", "busybox"),
+            Some("".into())
+        );
+    }
+
+    #[test]
+    fn test_validate_scope() {
+        for scope in ["quay.io", "quay.io/example/os:stable", "*.example.com"] {
+            validate_scope(scope).unwrap();
+        }
+        for scope in [
+            "",
+            "docker://quay.io",
+            "*",
+            "*.",
+            "quay.*.io",
+            "*.example.com/os",
+        ] {
+            assert!(validate_scope(scope).is_err(), "{}", scope);
+        }
+    }
+
+    #[test]
+    fn test_edit_policy() -> Result<()> {
+        let mut policy: Value = serde_json::from_str(POLICY)?;
+        add_sigstore_key(&mut policy, "quay.io/other", "/etc/pki/containers/a.pub")?;
+        assert_eq!(
+            policy["transports"]["docker"]["quay.io/other"],
+            json!([{
+                "type": "sigstoreSigned",
+                "keyPath": "/etc/pki/containers/a.pub",
+                "signedIdentity": {"type": "matchRepository"}
+            }])
+        );
+        // Adding a key accepts either one, and is idempotent
+        add_sigstore_key(&mut policy, "quay.io/other", "/etc/pki/containers/b.pub")?;
+        add_sigstore_key(&mut policy, "quay.io/other", "/etc/pki/containers/b.pub")?;
+        let req = &policy["transports"]["docker"]["quay.io/other"][0];
+        assert!(req.get("keyPath").is_none());
+        assert_eq!(
+            req["keyPaths"],
+            json!(["/etc/pki/containers/a.pub", "/etc/pki/containers/b.pub"])
+        );
+        // Replaces insecureAcceptAnything
+        add_sigstore_key(&mut policy, "quay.io/example/unsigned", "/k.pub")?;
+        let parsed: Policy = serde_json::from_value(policy.clone())?;
+        assert_eq!(
+            parsed.requirements_for("docker", "quay.io/example/unsigned"),
+            &[PolicyRequirement::SigstoreSigned]
+        );
+
+        require_signed_by(&mut policy, "registry.local", "/etc/pki/containers/gpg.pub")?;
+        assert_eq!(
+            policy["transports"]["docker"]["registry.local"],
+            json!([{"type": "signedBy", "keyType": "GPGKeys", "keyPath": "/etc/pki/containers/gpg.pub"}])
+        );
+        assert!(require_signed_by(&mut policy, "docker://foo", "/k").is_err());
+
+        remove_scope(&mut policy, "registry.local")?;
+        assert!(remove_scope(&mut policy, "registry.local").is_err());
+        // Unrelated settings are kept
+        assert_eq!(
+            policy["transports"]["docker-daemon"],
+            json!({"": [{"type": "insecureAcceptAnything"}]})
+        );
+
+        let mut policy = json!({"default": [{"type": "reject"}]});
+        require_signed_by(&mut policy, "quay.io", "/k")?;
+        assert_eq!(
+            policy["transports"]["docker"]["quay.io"][0]["type"],
+            "signedBy"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_registries_config() -> Result<()> {
+        let policy: Value = serde_json::from_str(POLICY)?;
+        let config = registries_config(&policy, &BTreeMap::new())?.unwrap();
+        let parsed: serde_yaml::Value = serde_yaml::from_str(&config)?;
+        assert_eq!(
+            parsed["docker"]["quay.io/example"]["use-sigstore-attachments"],
+            serde_yaml::Value::Bool(true)
+        );
+        // signedBy signatures don't need sigstore attachments
+        assert!(parsed["docker"]["*.example.com"].is_null());
+        // Scopes configured elsewhere aren't duplicated
+        let existing = BTreeMap::from([("quay.io/example".to_string(), true)]);
+        assert!(registries_config(&policy, &existing)?.is_none());
+        let policy = json!({"default": [{"type": "insecureAcceptAnything"}]});
+        assert!(registries_config(&policy, &BTreeMap::new())?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_existing_registries_scopes() -> Result<()> {
+        let td = cap_tempfile::tempdir(cap_std::ambient_authority())?;
+        assert!(existing_registries_scopes(&td)?.is_empty());
+        td.create_dir_all(REGISTRIES_D)?;
+        td.write(
+            format!("{}/default.yaml", REGISTRIES_D),
+            "default-docker:\n  use-sigstore-attachments: true\ndocker:\n  quay.io/example:\n    use-sigstore-attachments: true\n  registry.local:\n    lookaside: https://sigs.local\n",
+        )?;
+        td.write(
+            format!("{}/{}", REGISTRIES_D, REGISTRIES_D_CONF),
+            "docker:\n  quay.io/other:\n    use-sigstore-attachments: true\n",
+        )?;
+        assert_eq!(
+            existing_registries_scopes(&td)?,
+            BTreeMap::from([
+                ("quay.io/example".to_string(), true),
+                ("registry.local".to_string(), false)
+            ])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_key() -> Result<()> {
+        use openssl::ec::{EcGroup, EcKey};
+        use openssl::nid::Nid;
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+        let pem = PKey::from_ec_key(EcKey::generate(&group)?)?.public_key_to_pem()?;
+        // Old and new format packet headers
+        let binary = [0x98, 0x04, 0x04, 0x00, 0x00, 0x00];
+        let binary_new = [0xc6, 0x02, 0x04, 0x00];
+        let armored = format!(
+            "-----BEGIN PGP PUBLIC KEY BLOCK-----\nComment: test\n\n{}\n=abcd\n-----END PGP PUBLIC KEY BLOCK-----\n",
+            openssl::base64::encode_block(&binary)
+        );
+        validate_key(&pem, KeyKind::Sigstore)?;
+        validate_key(armored.as_bytes(), KeyKind::Gpg)?;
+        validate_key(&binary, KeyKind::Gpg)?;
+        validate_key(&binary_new, KeyKind::Gpg)?;
+        assert!(validate_key(armored.as_bytes(), KeyKind::Sigstore).is_err());
+        assert!(validate_key(
+            b"-----BEGIN PUBLIC KEY-----\nMFkw\n-----END PUBLIC KEY-----\n",
+            KeyKind::Sigstore
+        )
+        .is_err());
+        assert!(validate_key(&pem, KeyKind::Gpg).is_err());
+        assert!(validate_key(b"", KeyKind::Gpg).is_err());
+        // Truncated
+        assert!(validate_key(&[0x99, 0x01, 0x0d], KeyKind::Gpg).is_err());
+        // A signature packet
+        assert!(validate_key(&[0x88, 0x01, 0x04], KeyKind::Gpg).is_err());
+        assert!(validate_key(
+            b"-----BEGIN PGP PUBLIC KEY BLOCK-----\n\nmQIN\n",
+            KeyKind::Gpg
+        )
+        .is_err());
+        Ok(())
+    }
+}
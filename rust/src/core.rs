@@ -17,9 +17,12 @@ use fn_error_context::context;
 use glib::prelude::StaticVariantType;
 use glib::translate::ToGlibPtr;
 use libdnf_sys::*;
-use ostree_ext::container::OstreeImageReference;
+use nix::sys::time::{TimeVal, TimeValLike};
+use ostree_ext::container::{OstreeImageReference, Transport};
 use ostree_ext::glib;
 use ostree_ext::ostree;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashSet};
 use std::fs::File;
 use std::io::{BufReader, Read};
 use std::os::unix::io::{AsRawFd, FromRawFd};
@@ -39,6 +42,19 @@ const SSS_CACHE_PATH: &str = "usr/sbin/sss_cache";
 // our wrapper script.  If we remember.
 const SYSTEMCTL_PATH: &str = "usr/bin/systemctl";
 const SYSTEMCTL_WRAPPER: &[u8] = include_bytes!("../../src/libpriv/systemctl-wrapper.sh");
+// Rebuilding the SELinux policy when layering packages which ship modules is
+// slow; the wrapper reuses the results cached in the directory bind-mounted
+// read-only at /run/rpmostree-semodule-cache, if any, and leaves new ones in
+// its `incoming` subdirectory, which is the only writable part.
+const SEMODULE_PATH: &str = "usr/sbin/semodule";
+const SEMODULE_WRAPPER: &[u8] = include_bytes!("../../src/libpriv/semodule-wrapper.sh");
+/// Number of policies kept in the semodule cache.
+const SEMODULE_CACHE_MAX_ENTRIES: usize = 8;
+/// The checksums of the cached policies, in the format of `sha256sum`; the
+/// wrapper ignores entries which don't match.
+const SEMODULE_CACHE_INDEX: &str = "SHA256SUMS";
+/// Where the wrapper leaves new policies, and marks the ones it used.
+const SEMODULE_CACHE_INCOMING: &str = "incoming";
 
 const RPMOSTREE_CORE_STAGED_RPMS_DIR: &str = "rpm-ostree/staged-rpms";
 
@@ -114,6 +130,85 @@ pub(crate) fn refspec_classify(refspec: &str) -> crate::ffi::RefspecType {
     }
 }
 
+/// Compare two `[epoch:]version-release` strings using librpm.
+pub(crate) fn evrcmp(a: &str, b: &str) -> Ordering {
+    match libdnf_sys::rpmver_cmp(a, b) {
+        Ok(r) => r.cmp(&0),
+        // Not an EVR; fall back to something stable
+        Err(_) => a.cmp(b),
+    }
+}
+
+/// Transports which reference a path on the local filesystem.
+fn is_local_transport(transport: &Transport) -> bool {
+    matches!(transport, Transport::OciDir | Transport::OciArchive)
+}
+
+/// For container image references using a local transport (`oci:` or `oci-archive:`),
+/// make the path absolute relative to `cwd`, since the daemon won't share our
+/// working directory.  Returns `None` if there is nothing to change, including
+/// for refspecs which aren't container image references.  Image references
+/// to containers storage are rejected.
+fn container_refspec_absolute_impl(refspec: &str, cwd: &Utf8Path) -> Result<Option<String>> {
+    let mut imgref = match OstreeImageReference::try_from(refspec) {
+        Ok(r) => r,
+        Err(_) if refspec.starts_with("ostree-") && refspec.contains(":dir:") => {
+            return Err(anyhow!(
+                "The dir: transport is not supported; use oci: for an OCI layout directory"
+            ));
+        }
+        Err(_) => return Ok(None),
+    };
+    if matches!(imgref.imgref.transport, Transport::ContainerStorage) {
+        // The daemon can't access application containers; see rpm-ostreed.service.
+        return Err(anyhow!(
+            "The containers-storage: transport is not supported; copy the image to an \
+             OCI archive first, e.g. skopeo copy {} oci-archive:/var/tmp/os.ociarchive",
+            imgref.imgref
+        ));
+    }
+    if !is_local_transport(&imgref.imgref.transport) {
+        return Ok(None);
+    }
+    let name = &imgref.imgref.name;
+    let (path, tag) = match name.split_once(':') {
+        Some((path, tag)) => (path, Some(tag)),
+        None => (name.as_str(), None),
+    };
+    if path.is_empty() {
+        return Err(anyhow!("Missing path in {}", refspec));
+    }
+    if path.starts_with('/') {
+        return Ok(None);
+    }
+    let path = cwd.join(path);
+    imgref.imgref.name = match tag {
+        Some(tag) => format!("{}:{}", path, tag),
+        None => path.into_string(),
+    };
+    Ok(Some(imgref.to_string()))
+}
+
+/// Canonicalize a refspec given on the command line; see
+/// `container_refspec_absolute_impl`.  Also verifies that local images exist.
+pub(crate) fn container_refspec_make_absolute(refspec: &str) -> CxxResult<String> {
+    let cwd = std::env::current_dir()?;
+    let cwd = Utf8Path::from_path(&cwd).ok_or_else(|| anyhow!("Non-UTF8 working directory"))?;
+    let refspec = match container_refspec_absolute_impl(refspec, cwd)? {
+        Some(r) => r,
+        None => refspec.to_string(),
+    };
+    if let Ok(imgref) = OstreeImageReference::try_from(refspec.as_str()) {
+        if is_local_transport(&imgref.imgref.transport) {
+            let path = imgref.imgref.name.split(':').next().unwrap();
+            if !Utf8Path::new(path).exists() {
+                return Err(anyhow!("No such file or directory: {}", path).into());
+            }
+        }
+    }
+    Ok(refspec)
+}
+
 /// Perform reversible filesystem transformations necessary before we execute scripts.
 pub(crate) struct FilesystemScriptPrep {
     rootfs: Dir,
@@ -132,8 +227,10 @@ pub(crate) fn log_treefile(tf: &crate::treefile::Treefile) {
 impl FilesystemScriptPrep {
     /// Filesystem paths that we rename out of the way if present
     const OPTIONAL_PATHS: &'static [&'static str] = &[SSS_CACHE_PATH];
-    const REPLACE_OPTIONAL_PATHS: &'static [(&'static str, &'static [u8])] =
-        &[(SYSTEMCTL_PATH, SYSTEMCTL_WRAPPER)];
+    const REPLACE_OPTIONAL_PATHS: &'static [(&'static str, &'static [u8])] = &[
+        (SYSTEMCTL_PATH, SYSTEMCTL_WRAPPER),
+        (SEMODULE_PATH, SEMODULE_WRAPPER),
+    ];
 
     fn saved_name(name: &str) -> String {
         format!("{}.rpmostreesave", name)
@@ -173,6 +270,139 @@ impl FilesystemScriptPrep {
     }
 }
 
+/// Parse the checksums of the cached policies, by name.
+fn semodule_cache_load_index(path: &Utf8Path) -> Result<BTreeMap<String, String>> {
+    let buf = match std::fs::read_to_string(path.join(SEMODULE_CACHE_INDEX)) {
+        Ok(buf) => buf,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(buf
+        .lines()
+        .filter_map(|l| l.split_once("  "))
+        .map(|(sum, name)| (name.to_string(), sum.to_string()))
+        .collect())
+}
+
+fn semodule_cache_store_index(path: &Utf8Path, index: &BTreeMap<String, String>) -> Result<()> {
+    let buf: String = index
+        .iter()
+        .map(|(name, sum)| format!("{}  {}\n", sum, name))
+        .collect();
+    crate::utils::write_file_atomic(path.join(SEMODULE_CACHE_INDEX), buf)
+}
+
+/// Whether `name` is that of a policy as written by the wrapper, i.e. a SHA-256
+/// key with a `.tar` suffix.
+fn semodule_cache_valid_name(name: &str) -> bool {
+    name.strip_suffix(".tar")
+        .map(|key| key.len() == 64 && key.bytes().all(|b| b.is_ascii_hexdigit()))
+        .unwrap_or_default()
+}
+
+/// Set up the cache of policies built by `semodule` in scriptlets at `path`,
+/// removing all entries if `clear` is set, and otherwise all but the most
+/// recently used ones, along with those which aren't in the index.
+pub(crate) fn semodule_cache_prepare(path: &str, clear: bool) -> CxxResult<()> {
+    semodule_cache_prepare_impl(Utf8Path::new(path), clear)
+        .with_context(|| format!("Preparing SELinux policy cache {}", path))?;
+    Ok(())
+}
+
+fn semodule_cache_prepare_impl(path: &Utf8Path, clear: bool) -> Result<()> {
+    std::fs::create_dir_all(path)?;
+    let mut index = semodule_cache_load_index(path)?;
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_str().unwrap_or_default();
+        if name == SEMODULE_CACHE_INDEX {
+            continue;
+        }
+        // Also drop leftovers from interrupted scriptlets
+        if entry.file_type()?.is_dir() {
+            std::fs::remove_dir_all(entry.path())?;
+            continue;
+        }
+        if clear || !index.contains_key(name) {
+            std::fs::remove_file(entry.path())?;
+            continue;
+        }
+        entries.push((entry.metadata()?.modified()?, name.to_string()));
+    }
+    entries.sort_by(|a, b| b.0.cmp(&a.0));
+    for (_, name) in entries.iter().skip(SEMODULE_CACHE_MAX_ENTRIES) {
+        std::fs::remove_file(path.join(name))?;
+    }
+    let kept: HashSet<_> = entries
+        .into_iter()
+        .take(SEMODULE_CACHE_MAX_ENTRIES)
+        .map(|(_, name)| name)
+        .collect();
+    index.retain(|name, _| kept.contains(name));
+    semodule_cache_store_index(path, &index)?;
+    std::fs::create_dir(path.join(SEMODULE_CACHE_INCOMING))?;
+    Ok(())
+}
+
+/// Move the policies which the wrapper left in the `incoming` directory of the
+/// cache at `path` into it, recording their checksums; called after each
+/// scriptlet.
+pub(crate) fn semodule_cache_import(path: &str) -> CxxResult<()> {
+    semodule_cache_import_impl(Utf8Path::new(path))
+        .with_context(|| format!("Updating SELinux policy cache {}", path))?;
+    Ok(())
+}
+
+fn semodule_cache_import_impl(path: &Utf8Path) -> Result<()> {
+    let incoming = &path.join(SEMODULE_CACHE_INCOMING);
+    let mut index = semodule_cache_load_index(path)?;
+    let mut changed = false;
+    for entry in std::fs::read_dir(incoming)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_str().unwrap_or_default();
+        // The wrapper marks the policies it used, for the LRU pruning
+        if let Some(used) = name.strip_suffix(".used") {
+            let used = path.join(format!("{}.tar", used));
+            if semodule_cache_valid_name(used.file_name().unwrap()) && used.exists() {
+                let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+                let now = TimeVal::seconds(now.as_secs() as i64);
+                nix::sys::stat::utimes(used.as_std_path(), &now, &now)?;
+            }
+        } else if semodule_cache_valid_name(name) && entry.file_type()?.is_file() {
+            let mut f = File::open(entry.path())?;
+            let mut hasher = openssl::sha::Sha256::new();
+            let mut buf = vec![0u8; 64 * 1024];
+            loop {
+                match f.read(&mut buf)? {
+                    0 => break,
+                    n => hasher.update(&buf[..n]),
+                }
+            }
+            let sum = hasher
+                .finish()
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect();
+            std::fs::rename(entry.path(), path.join(name))?;
+            index.insert(name.to_string(), sum);
+            changed = true;
+            continue;
+        }
+        if entry.file_type()?.is_dir() {
+            std::fs::remove_dir_all(entry.path())?;
+        } else {
+            std::fs::remove_file(entry.path())?;
+        }
+    }
+    if changed {
+        semodule_cache_store_index(path, &index)?;
+    }
+    Ok(())
+}
+
 /// Some Fedora/RHEL kernels ship .hmac files with absolute paths inside,
 /// which breaks when we relocate them into ostree/.  This function
 /// changes them to be relative.
@@ -316,7 +546,7 @@ fn stage_container_rpm_files(rpms: Vec<File>) -> CxxResult<Vec<String>> {
         run.create_dir_all(RPMOSTREE_CORE_STAGED_RPMS_DIR)?;
         let staged_rpms_dir = run.open_dir(RPMOSTREE_CORE_STAGED_RPMS_DIR)?;
         staged_rpms_dir.atomic_replace_with(&staged_fn, |f| -> std::io::Result<_> {
-            std::io::copy(&mut rpm, f)
+            crate::reflink::copy_file_data(&mut rpm, f.get_mut())
         })?;
         r.push(format!("{}:{}", digest, pkg.pin_mut().get_nevra()));
     }
@@ -328,6 +558,7 @@ mod test {
     use super::*;
     use crate::capstdext::dirbuilder_from_mode;
     use anyhow::Result;
+    use nix::sys::time::{TimeVal, TimeValLike};
 
     #[test]
     fn etcguard() -> Result<()> {
@@ -362,21 +593,77 @@ mod test {
         d.atomic_write_with_perms(super::SSS_CACHE_PATH, "sss binary", mode.clone())?;
         let original_systemctl = "original systemctl";
         d.atomic_write_with_perms(super::SYSTEMCTL_PATH, original_systemctl, mode.clone())?;
-        // Replaced systemctl
+        let original_semodule = "original semodule";
+        d.atomic_write_with_perms(super::SEMODULE_PATH, original_semodule, mode.clone())?;
+        // Replaced systemctl and semodule
         {
             assert!(d.try_exists(super::SSS_CACHE_PATH)?);
             let g = super::prepare_filesystem_script_prep(d.as_raw_fd())?;
             assert!(!d.try_exists(super::SSS_CACHE_PATH)?);
             let contents = d.read_to_string(super::SYSTEMCTL_PATH)?;
             assert_eq!(contents.as_bytes(), super::SYSTEMCTL_WRAPPER);
+            let contents = d.read_to_string(super::SEMODULE_PATH)?;
+            assert_eq!(contents.as_bytes(), super::SEMODULE_WRAPPER);
             g.undo()?;
             let contents = d.read_to_string(super::SYSTEMCTL_PATH)?;
             assert_eq!(contents, original_systemctl);
+            let contents = d.read_to_string(super::SEMODULE_PATH)?;
+            assert_eq!(contents, original_semodule);
             assert!(d.try_exists(super::SSS_CACHE_PATH)?);
         }
         Ok(())
     }
 
+    #[test]
+    fn semodule_cache() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        let path = Utf8Path::from_path(td.path()).unwrap().join("sepolicy");
+        let incoming = &path.join(super::SEMODULE_CACHE_INCOMING);
+        let key = |i: usize| format!("{:064x}.tar", i);
+        super::semodule_cache_prepare(path.as_str(), false)?;
+        let n = super::SEMODULE_CACHE_MAX_ENTRIES + 2;
+        for i in 0..n {
+            std::fs::write(incoming.join(key(i)), format!("policy {}", i))?;
+            super::semodule_cache_import(path.as_str())?;
+            let entry = path.join(key(i));
+            let mtime = TimeVal::seconds(i as i64 * 60);
+            nix::sys::stat::utimes(entry.as_std_path(), &mtime, &mtime)?;
+        }
+        let index = super::semodule_cache_load_index(&path)?;
+        assert_eq!(index.len(), n);
+        let sum: String = openssl::sha::sha256(b"policy 0")
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        assert_eq!(index[&key(0)], sum);
+        // Invalid names aren't imported
+        std::fs::write(incoming.join("foo.tar"), "")?;
+        super::semodule_cache_import(path.as_str())?;
+        assert!(!path.join("foo.tar").exists());
+        assert_eq!(std::fs::read_dir(incoming)?.count(), 0);
+        // Used policies are kept
+        std::fs::write(incoming.join(format!("{:064x}.used", 0)), "")?;
+        super::semodule_cache_import(path.as_str())?;
+        // Leftovers, and entries not in the index, are dropped
+        std::fs::write(incoming.join("foo.tar.tmp"), "")?;
+        std::fs::write(path.join(key(n)), "")?;
+        super::semodule_cache_prepare(path.as_str(), false)?;
+        assert_eq!(std::fs::read_dir(incoming)?.count(), 0);
+        assert!(!path.join(key(n)).exists());
+        assert!(path.join(key(0)).exists());
+        assert!(!path.join(key(1)).exists());
+        assert!(!path.join(key(2)).exists());
+        assert!(path.join(key(3)).exists());
+        assert!(path.join(key(n - 1)).exists());
+        let index = super::semodule_cache_load_index(&path)?;
+        assert_eq!(index.len(), super::SEMODULE_CACHE_MAX_ENTRIES);
+        assert!(!index.contains_key(&key(1)));
+        super::semodule_cache_prepare(path.as_str(), true)?;
+        assert_eq!(std::fs::read_dir(&path)?.count(), 2);
+        assert!(super::semodule_cache_load_index(&path)?.is_empty());
+        Ok(())
+    }
+
     #[test]
     fn test_refspecs() -> Result<()> {
         use super::is_container_image_reference;
@@ -425,6 +712,47 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_evrcmp() {
+        use Ordering::*;
+        assert_eq!(evrcmp("1.0-1", "1.0-1"), Equal);
+        assert_eq!(evrcmp("1:1.0-1", "2.0-1"), Greater);
+        assert_eq!(evrcmp("1.0-2.fc36", "1.0-10.fc36"), Less);
+        assert_eq!(evrcmp("1.0~rc1-1", "1.0-1"), Less);
+    }
+
+    #[test]
+    fn test_container_refspec_absolute() -> Result<()> {
+        use super::container_refspec_absolute_impl as absolute;
+        let cwd = Utf8Path::new("/srv/images");
+        assert_eq!(
+            absolute("ostree-unverified-image:oci-archive:os.ociarchive", cwd)?.unwrap(),
+            "ostree-unverified-image:oci-archive:/srv/images/os.ociarchive"
+        );
+        assert_eq!(
+            absolute("ostree-unverified-image:oci:build/os:stable", cwd)?.unwrap(),
+            "ostree-unverified-image:oci:/srv/images/build/os:stable"
+        );
+        for unchanged in [
+            "ostree-unverified-image:oci:/srv/os",
+            "ostree-unverified-registry:quay.io/example/os:stable",
+            "fedora:fedora/x86_64/coreos/stable",
+        ] {
+            assert!(absolute(unchanged, cwd)?.is_none());
+        }
+        let e = absolute("ostree-unverified-image:dir:/srv/os", cwd).unwrap_err();
+        assert!(e.to_string().contains("use oci:"));
+        let e = absolute(
+            "ostree-unverified-image:containers-storage:localhost/os:latest",
+            cwd,
+        )
+        .unwrap_err();
+        assert!(e
+            .to_string()
+            .contains("skopeo copy containers-storage:localhost/os:latest"));
+        Ok(())
+    }
+
     #[test]
     fn verify_hmac() -> Result<()> {
         let d = cap_tempfile::tempdir(cap_std::ambient_authority())?;
@@ -6,6 +6,7 @@ use anyhow::{bail, Context, Result};
 use cap_std_ext::rustix;
 use os_release::OsRelease;
 use std::path;
+use std::process::Command;
 
 use crate::core::OSTREE_BOOTED;
 
@@ -21,7 +22,6 @@ const DEFAULT_VARIANT_ID: &str = "unknown";
 /// Once this is fixed, we can switch to a HEAD request to reduce the footprint:
 /// let mut handle = Easy::new().nobody(true)?;
 fn send_countme(url: &str, ua: &str) -> Result<()> {
-    println!("Sending request to: {}", url);
     let handle = reqwest::blocking::ClientBuilder::new()
         .user_agent(ua)
         .build()?;
@@ -113,3 +113,34 @@ pub fn entrypoint(_args: &[&str]) -> Result<()> {
     }
     Ok(())
 }
+
+/// Count the system after a client operation (layering, `refresh-md`,
+/// automatic update checks) fetched the rpm-md of `repoids`, like DNF does
+/// when refreshing metadata.  The requests are left to
+/// `rpm-ostree-countme.service`, which runs unprivileged and shares the
+/// window with the timer; this only queues it, and never fails the operation.
+pub(crate) fn countme_rpmmd_fetched(repoids: &Vec<String>) {
+    if let Err(e) = countme_rpmmd_fetched_inner(repoids) {
+        eprintln!("warning: Count Me: {:#}", e);
+    }
+}
+
+fn countme_rpmmd_fetched_inner(repoids: &[String]) -> Result<()> {
+    if !path::Path::new(OSTREE_BOOTED).exists() {
+        return Ok(());
+    }
+    let counted = self::repo::all()?
+        .iter()
+        .any(|r| r.count_me() && repoids.iter().any(|id| id == r.id()));
+    if !counted {
+        return Ok(());
+    }
+    let status = Command::new("systemctl")
+        .args(["start", "--no-block", "rpm-ostree-countme.service"])
+        .status()
+        .context("Running systemctl")?;
+    if !status.success() {
+        bail!("Starting rpm-ostree-countme.service: {}", status);
+    }
+    Ok(())
+}
@@ -18,6 +18,7 @@ pub const YUM_REPOS_D: &str = "/etc/yum.repos.d";
 pub struct Repo {
     // Not needed right now
     // name: String,
+    id: String,
     enabled: bool,
     count_me: bool,
     meta_link: String,
@@ -59,7 +60,8 @@ fn parse_repo_file(path: &PathBuf) -> Result<Vec<Repo>> {
             None => {
                 continue;
             }
-            Some(_s) => Repo {
+            Some(s) => Repo {
+                id: s.to_string(),
                 enabled: false,
                 count_me: false,
                 meta_link: "".to_string(),
@@ -87,6 +89,11 @@ fn parse_repo_file(path: &PathBuf) -> Result<Vec<Repo>> {
 }
 
 impl Repo {
+    /// The repo ID, i.e. the name of its section
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
     /// Returns true if this repo is
     /// - enabled
     /// - configured for sending a Count Me request
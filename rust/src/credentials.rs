@@ -0,0 +1,182 @@
+//! Repository credentials encrypted with systemd-creds(1).
+//!
+//! Rather than keeping client certificates and passwords in plaintext in
+//! /etc, they can be stored as encrypted credentials under
+//! `/etc/rpm-ostree/credentials`:
+//!
+//! - `repos/<repo-id>/<option>.cred` for the `sslcacert`, `sslclientcert`,
+//!   `sslclientkey`, `username`, `password`, `proxy_username` and
+//!   `proxy_password` options of an rpm-md repo;
+//! - `registries/<registry>.cred` holding `username:password` for a container
+//!   registry.
+//!
+//! They are only decrypted by the daemon while a transaction needs them, into
+//! a private directory under /run which goes away with it, and they take
+//! precedence over the settings of the .repo file and of auth.json.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::cxxrsutil::*;
+use crate::ffi::StringMapping;
+use anyhow::{anyhow, bail, Context, Result};
+use ostree_ext::container::{OstreeImageReference, Transport};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const CREDENTIALS_DIR: &str = "/etc/rpm-ostree/credentials";
+/// Repo options naming a file, which get the path of the decrypted credential.
+const REPO_FILE_OPTIONS: &[&str] = &["sslcacert", "sslclientcert", "sslclientkey"];
+/// Repo options which get the content of the decrypted credential.
+const REPO_VALUE_OPTIONS: &[&str] = &["username", "password", "proxy_username", "proxy_password"];
+/// Where the decrypted auth.json for container pulls is written.
+const RUNTIME_DIR: &str = "/run/rpm-ostree";
+
+/// Refuse names which could escape the credentials directory.
+fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.starts_with('.') || name.contains('/') {
+        bail!("Invalid credential name: {}", name);
+    }
+    Ok(())
+}
+
+/// The encrypted credentials of the rpm-md repo `id` in `dir`, as (option, path).
+fn repo_credential_files(dir: &Path, id: &str) -> Result<Vec<(&'static str, PathBuf)>> {
+    validate_name(id)?;
+    let repodir = dir.join("repos").join(id);
+    let r = REPO_FILE_OPTIONS
+        .iter()
+        .chain(REPO_VALUE_OPTIONS)
+        .map(|&opt| (opt, repodir.join(format!("{}.cred", opt))))
+        .filter(|(_, path)| path.exists())
+        .collect();
+    Ok(r)
+}
+
+/// Decrypt the credential `src` to `dest`, or to stdout which is returned.
+/// systemd-creds checks that the name embedded in the credential matches the
+/// file name.
+fn decrypt(src: &Path, dest: Option<&Path>) -> Result<Vec<u8>> {
+    let mut cmd = Command::new("systemd-creds");
+    cmd.arg("decrypt").arg(src);
+    match dest {
+        Some(dest) => cmd.arg(dest),
+        None => cmd.arg("-"),
+    };
+    let out = cmd.output().context("Running systemd-creds")?;
+    if !out.status.success() {
+        bail!(
+            "Decrypting {}: {}",
+            src.display(),
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+    }
+    Ok(out.stdout)
+}
+
+fn decrypt_to_string(src: &Path) -> Result<String> {
+    let buf = decrypt(src, None)?;
+    let s = String::from_utf8(buf).with_context(|| format!("Decrypting {}", src.display()))?;
+    Ok(s.trim_end_matches('\n').to_string())
+}
+
+fn repo_credentials_impl(dir: &Path, id: &str, destdir: &Path) -> Result<Vec<StringMapping>> {
+    let mut r = Vec::new();
+    for (opt, path) in repo_credential_files(dir, id)? {
+        let v = if REPO_FILE_OPTIONS.contains(&opt) {
+            let dest = destdir.join(format!("{}-{}", id, opt));
+            decrypt(&path, Some(&dest))?;
+            dest.to_str()
+                .ok_or_else(|| anyhow!("Invalid path: {}", dest.display()))?
+                .to_string()
+        } else {
+            decrypt_to_string(&path)?
+        };
+        r.push(StringMapping {
+            k: opt.to_string(),
+            v,
+        });
+    }
+    Ok(r)
+}
+
+/// Decrypt the credentials of the rpm-md repo `id`, writing those which are
+/// files into the private directory `destdir`.  Returns the repo options to
+/// set, which must not be persisted.
+pub(crate) fn repo_credentials_decrypt(id: &str, destdir: &str) -> CxxResult<Vec<StringMapping>> {
+    let r = repo_credentials_impl(Path::new(CREDENTIALS_DIR), id, Path::new(destdir))
+        .with_context(|| format!("Loading credentials of repo '{}'", id))?;
+    Ok(r)
+}
+
+/// Decrypt the `username:password` credential for `registry`, if any.
+fn registry_credential_impl(dir: &Path, registry: &str) -> Result<Option<(String, String)>> {
+    validate_name(registry)?;
+    let path = dir.join("registries").join(format!("{}.cred", registry));
+    if !path.exists() {
+        return Ok(None);
+    }
+    let cred = decrypt_to_string(&path)?;
+    let (username, password) = cred
+        .split_once(':')
+        .ok_or_else(|| anyhow!("Expected username:password in {}", path.display()))?;
+    Ok(Some((username.to_string(), password.to_string())))
+}
+
+/// If there is an encrypted credential for the registry of `imgref`, write an
+/// auth.json with it added to the existing ones, to be passed to the image
+/// proxy.  The file is deleted when dropped.
+pub(crate) fn registry_authfile(
+    imgref: &OstreeImageReference,
+) -> Result<Option<tempfile::NamedTempFile>> {
+    if imgref.imgref.transport != Transport::Registry {
+        return Ok(None);
+    }
+    let registry = crate::containers_attestation::image_registry(&imgref.imgref.name);
+    let (username, password) =
+        match registry_credential_impl(Path::new(CREDENTIALS_DIR), &registry)? {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+    let mut auth = crate::containers_auth::load_auth()?;
+    crate::containers_auth::set_registry_auth(&mut auth, &registry, &username, &password)?;
+    std::fs::create_dir_all(RUNTIME_DIR)?;
+    // NamedTempFile is created with mode 0600
+    let mut f = tempfile::Builder::new()
+        .prefix("auth-")
+        .suffix(".json")
+        .tempfile_in(RUNTIME_DIR)?;
+    f.write_all(&serde_json::to_vec(&auth)?)?;
+    f.flush()?;
+    Ok(Some(f))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repo_credential_files() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        let repodir = td.path().join("repos/updates");
+        std::fs::create_dir_all(&repodir)?;
+        for f in [
+            "sslclientkey.cred",
+            "password.cred",
+            "gpgkey.cred",
+            "username",
+        ] {
+            std::fs::write(repodir.join(f), "")?;
+        }
+        let r = repo_credential_files(td.path(), "updates")?;
+        let opts: Vec<_> = r.iter().map(|(o, _)| *o).collect();
+        assert_eq!(opts, ["sslclientkey", "password"]);
+        assert_eq!(r[0].1, repodir.join("sslclientkey.cred"));
+        assert!(repo_credential_files(td.path(), "fedora")?.is_empty());
+        assert!(repo_credential_files(td.path(), "../repos").is_err());
+        assert!(repo_credential_files(td.path(), "").is_err());
+        assert_eq!(registry_credential_impl(td.path(), "quay.io")?, None);
+        assert!(registry_credential_impl(td.path(), "quay.io/exampleos").is_err());
+        Ok(())
+    }
+}
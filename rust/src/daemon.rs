@@ -14,15 +14,21 @@ use cap_std_ext::dirext::CapStdExtDirExt;
 use cap_std_ext::{cap_std, rustix};
 use fn_error_context::context;
 use glib::prelude::*;
+use ostree_ext::container::deploy::ORIGIN_CONTAINER;
+use ostree_ext::container::{
+    self as ostree_container, ImageReference, OstreeImageReference, SignatureSource,
+};
 use ostree_ext::{gio, glib, ostree};
 use rustix::fd::BorrowedFd;
 use rustix::fs::MetadataExt;
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::io::Read;
 use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
 
 const RPM_OSTREED_COMMIT_VERIFICATION_CACHE: &str = "rpm-ostree/gpgcheck-cache";
+/// Directory of admin-provided protected package lists; one package name per line.
+const PROTECTED_PACKAGES_DIR: &str = "etc/rpm-ostree/protected.d";
 
 /// Validate basic assumptions on daemon startup.
 pub(crate) fn daemon_sanitycheck_environment(sysroot: &crate::FFIOstreeSysroot) -> CxxResult<()> {
@@ -95,6 +101,11 @@ fn deployment_populate_variant_origin(
         "requested-local-fileoverride-packages",
         tf.derive.packages_local_fileoverride.as_ref(),
     );
+    vdict_insert_optset(
+        dict,
+        "unverified-local-packages",
+        tf.derive.packages_local_unverified.as_ref(),
+    );
     vdict_insert_optset(
         dict,
         "requested-base-removals",
@@ -118,15 +129,33 @@ fn deployment_populate_variant_origin(
         dict.insert("regenerate-initramfs", &initramfs.regenerate);
         vdict_insert_optvec(dict, "initramfs-args", initramfs.args.as_ref());
         vdict_insert_optset(dict, "initramfs-etc", initramfs.etc.as_ref());
+        for (k, v) in [
+            ("initramfs-add-modules", &initramfs.add_modules),
+            ("initramfs-omit-modules", &initramfs.omit_modules),
+            ("initramfs-add-drivers", &initramfs.add_drivers),
+        ] {
+            vdict_insert_optset(dict, k, v.as_ref());
+        }
+        if let Some(generator) = initramfs.generator.as_deref() {
+            dict.insert("initramfs-generator", &generator);
+        }
     } else {
         // This key is also always injected.
         dict.insert("regenerate-initramfs", &false);
     }
 
+    // Kernel arguments.
+    if let Some(profiles) = tf.derive.kargs.as_ref().and_then(|k| k.profiles.as_ref()) {
+        vdict_insert_strv(dict, "kargs-profiles", profiles.keys().map(|s| s.as_str()));
+    }
+
     // Other bits.
     if tf.cliwrap.unwrap_or_default() {
         dict.insert("cliwrap", &true);
     }
+    if tf.derive.fips.unwrap_or_default() {
+        dict.insert("fips", &true);
+    }
 
     Ok(())
 }
@@ -174,6 +203,13 @@ pub(crate) fn deployment_populate_variant(
     }
 
     dict.insert("pinned", &deployment.is_pinned());
+    crate::pin::deployment_populate_pin(deployment, &dict)?;
+    crate::rollback_retention::deployment_populate_retained(deployment, &dict)?;
+    crate::alternative::deployment_populate_alternative(deployment, &dict)?;
+    crate::testdeploy::deployment_populate_ephemeral(deployment, &dict)?;
+    if let Some(v) = crate::etc_conflicts::deployment_etc_conflicts_variant(deployment)? {
+        dict.insert_value("etc-conflicts", &v);
+    }
     let unlocked = deployment.unlocked();
     // Unwrap safety: This always returns a value
     dict.insert(
@@ -474,6 +510,101 @@ pub fn generate_baselayer_refs(
     Ok(())
 }
 
+/// The repository part of a container image name, i.e. without tag or digest.
+fn image_repository(name: &str) -> &str {
+    if let Some((repo, _)) = name.split_once('@') {
+        return repo;
+    }
+    match name.rsplit_once(':') {
+        Some((repo, tag)) if !tag.contains('/') => repo,
+        _ => name,
+    }
+}
+
+/// Given the pulled container images along with their timestamp, select the
+/// ones to remove: images used by a deployment are kept, along with the `keep`
+/// most recent other images of each image repository.
+fn select_images_to_prune<'a>(
+    images: &'a [(ImageReference, u64)],
+    deployed: &HashSet<String>,
+    keep: u32,
+) -> Vec<&'a ImageReference> {
+    let mut by_repository: BTreeMap<(String, &str), Vec<&(ImageReference, u64)>> = BTreeMap::new();
+    for image in images
+        .iter()
+        .filter(|(i, _)| !deployed.contains(&i.to_string()))
+    {
+        let key = (
+            image.0.transport.to_string(),
+            image_repository(&image.0.name),
+        );
+        by_repository.entry(key).or_default().push(image);
+    }
+    by_repository
+        .into_values()
+        .flat_map(|mut images| {
+            // Most recent first, then by name for stability
+            images.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.name.cmp(&b.0.name)));
+            images.into_iter().skip(keep as usize).map(|(i, _)| i)
+        })
+        .collect()
+}
+
+/// Remove pulled container images according to the retention policy (see
+/// `select_images_to_prune`), and the layers they no longer need.  The
+/// objects themselves are freed by a later prune.  Returns the number of
+/// removed images.
+pub(crate) fn prune_container_images(
+    sysroot: &crate::FFIOstreeSysroot,
+    repo: &crate::FFIOstreeRepo,
+    keep: u32,
+) -> CxxResult<u32> {
+    let sysroot = &sysroot.glib_reborrow();
+    let repo = &repo.glib_reborrow();
+    let mut deployed = HashSet::new();
+    for deployment in sysroot.deployments() {
+        let imgref = deployment
+            .origin()
+            .and_then(|o| o.string("origin", ORIGIN_CONTAINER).ok());
+        if let Some(imgref) = imgref {
+            deployed.insert(
+                OstreeImageReference::try_from(imgref.as_str())?
+                    .imgref
+                    .to_string(),
+            );
+        }
+    }
+    let mut images = Vec::new();
+    for image in ostree_container::store::list_images(repo)? {
+        let imgref = ImageReference::try_from(image.as_str())?;
+        let ostree_imgref = OstreeImageReference {
+            sigverify: SignatureSource::ContainerPolicyAllowInsecure,
+            imgref: imgref.clone(),
+        };
+        let timestamp = match ostree_container::store::query_image(repo, &ostree_imgref)? {
+            Some(state) => {
+                let commit = repo.load_commit(&state.merge_commit)?.0;
+                ostree::commit_get_timestamp(&commit)
+            }
+            None => 0,
+        };
+        images.push((imgref, timestamp));
+    }
+    let pruned = select_images_to_prune(&images, &deployed, keep);
+    if pruned.is_empty() {
+        return Ok(0);
+    }
+    for image in pruned.iter() {
+        crate::ffi::output_message(&format!("Removing container image: {}", image));
+    }
+    ostree_container::store::remove_images(repo, pruned.iter().copied())?;
+    let n_layers = ostree_container::store::gc_image_layers(repo)?;
+    if n_layers > 0 {
+        crate::ffi::output_message(&format!("Removed unreferenced image layers: {}", n_layers));
+    }
+    Ok(pruned.len() as u32)
+}
+
 /// Appends `part` to `base` in a way such that only characters that
 /// can be used in a D-Bus object path will be used. E.g. a character
 /// not in `[A-Z][a-z][0-9]_` will be escaped as `_HEX` where HEX is a
@@ -531,6 +662,55 @@ pub(crate) fn generate_object_path(base: &str, next_segment: &str) -> CxxResult<
     generate_object_path_impl(base, next_segment).map_err(Into::into)
 }
 
+/// Gather the packages which may not be overridden without `--allow-protected`;
+/// nothing is protected unless listed in `/etc/rpm-ostree/protected.d/*.conf`.
+#[context("Loading protected packages")]
+fn load_protected_packages(rootfs: &Dir) -> Result<BTreeSet<String>> {
+    let mut r = BTreeSet::new();
+    let d = match rootfs.open_dir_optional(PROTECTED_PACKAGES_DIR)? {
+        Some(d) => d,
+        None => return Ok(r),
+    };
+    for entry in d.entries()? {
+        let name = entry?.file_name();
+        let name = match name.to_str() {
+            Some(name) if name.ends_with(".conf") => name,
+            _ => continue,
+        };
+        let contents = d.read_to_string(name)?;
+        r.extend(
+            contents
+                .lines()
+                .map(str::trim)
+                .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                .map(ToOwned::to_owned),
+        );
+    }
+    Ok(r)
+}
+
+fn check_protected_packages_impl(rootfs: &Dir, pkgs: &[String]) -> Result<()> {
+    let protected = load_protected_packages(rootfs)?;
+    let found: Vec<_> = pkgs
+        .iter()
+        .filter(|p| protected.contains(p.as_str()))
+        .map(|p| p.as_str())
+        .collect();
+    if !found.is_empty() {
+        return Err(anyhow!(
+            "Refusing to override protected packages: {} (use --allow-protected)",
+            found.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+/// Error out if any of the package names in `pkgs` is protected.
+pub(crate) fn check_protected_packages(pkgs: &Vec<String>) -> CxxResult<()> {
+    let rootfs = Dir::open_ambient_dir("/", cap_std::ambient_authority())?;
+    check_protected_packages_impl(&rootfs, pkgs).map_err(Into::into)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -607,6 +787,24 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_protected_packages() -> Result<()> {
+        let td = &cap_tempfile::tempdir(cap_std::ambient_authority())?;
+        check_protected_packages_impl(td, &["foo".into(), "bar".into()])?;
+        // nothing is protected by default
+        check_protected_packages_impl(td, &["foo".into(), "kernel".into()])?;
+        td.create_dir_all(PROTECTED_PACKAGES_DIR)?;
+        td.write(
+            format!("{}/custom.conf", PROTECTED_PACKAGES_DIR),
+            "# comment\n\n  foo\n",
+        )?;
+        td.write(format!("{}/ignored.txt", PROTECTED_PACKAGES_DIR), "bar\n")?;
+        let e = check_protected_packages_impl(td, &["foo".into(), "bar".into()]).unwrap_err();
+        assert!(e.to_string().contains("packages: foo "));
+        check_protected_packages_impl(td, &["ostree".into(), "bar".into()])?;
+        Ok(())
+    }
+
     #[test]
     fn test_generate_object_path_impl() {
         assert!(generate_object_path_impl("/invalid", "").is_err());
@@ -619,4 +817,43 @@ mod test {
             "/base/first_21".to_string()
         );
     }
+
+    #[test]
+    fn test_select_images_to_prune() -> Result<()> {
+        let images = [
+            ("registry:quay.io/example/os:stable", 30),
+            ("registry:quay.io/example/os:testing", 20),
+            ("registry:quay.io/example/os@sha256:abcd", 10),
+            ("registry:quay.io/example/os:old", 5),
+            ("registry:localhost:5000/os:latest", 1),
+            ("oci:/srv/os", 40),
+        ];
+        let images = images
+            .iter()
+            .map(|(i, t)| Ok((ImageReference::try_from(*i)?, *t)))
+            .collect::<Result<Vec<_>>>()?;
+        let deployed = maplit::hashset! { "registry:quay.io/example/os:old".to_string() };
+        let pruned = |keep| {
+            let mut r = select_images_to_prune(&images, &deployed, keep)
+                .into_iter()
+                .map(|i| i.to_string())
+                .collect::<Vec<_>>();
+            r.sort();
+            r
+        };
+        assert_eq!(
+            pruned(0),
+            vec![
+                "oci:/srv/os",
+                "registry:localhost:5000/os:latest",
+                "registry:quay.io/example/os:stable",
+                "registry:quay.io/example/os:testing",
+                "registry:quay.io/example/os@sha256:abcd",
+            ]
+        );
+        assert_eq!(pruned(2), vec!["registry:quay.io/example/os@sha256:abcd"]);
+        assert!(pruned(3).is_empty());
+        assert_eq!(image_repository("localhost:5000/os"), "localhost:5000/os");
+        Ok(())
+    }
 }
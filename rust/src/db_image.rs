@@ -0,0 +1,254 @@
+//! Implementation of `rpm-ostree db diff --image`: fetch just enough of a
+//! container image to know its packages, without pulling or deploying it.
+//!
+//! For images derived with e.g. `dnf install`, the rpmdb is a regular file in
+//! the last layer which wrote it, so only the layers down to that one are
+//! streamed through the image proxy, keeping just the rpmdb.  For
+//! ostree-encapsulated images, the package list is in the metadata of the
+//! ostree commit, which is in the first layer; the layers with the content of
+//! packages (those with an `ostree.components` annotation) are never fetched.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::containers_layers::{entry_path, ImageLayers, LayerEntry};
+use crate::cxxrsutil::*;
+use anyhow::{anyhow, Result};
+use fn_error_context::context;
+use ostree_ext::container::OstreeImageReference;
+use ostree_ext::{gio, glib, ostree};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use tokio::runtime::Handle;
+
+/// Annotation of the layers of chunked ostree images with package content.
+const COMPONENTS_ANNOTATION: &str = "ostree.components";
+
+/// Where images keep their rpmdb, with the one we use first.
+const RPMDB_PATHS: &[&str] = &["usr/share/rpm", "usr/lib/sysimage/rpm", "var/lib/rpm"];
+/// The files identifying an rpmdb, in any of the formats.
+const RPMDB_MARKERS: &[&str] = &["rpmdb.sqlite", "Packages.db", "Packages"];
+
+const OSTREE_OBJECTS: &str = "sysroot/ostree/repo/objects/";
+const COMMIT_TYPE: &str = "(a{sv}aya(say)sstayay)";
+
+/// Where the packages of an image were found.
+#[derive(Debug, PartialEq, Eq)]
+enum LayerRpmdb {
+    /// The rpmdb directory, and its entries in the layer.
+    Rpmdb(&'static str, Vec<String>),
+    /// The path of the ostree commit object in the layer.
+    Commit(String),
+}
+
+/// The reference of `image`, either an ostree image reference or the name of
+/// an image in a registry, e.g. `quay.io/os:39`.
+fn image_reference(image: &str) -> Result<OstreeImageReference> {
+    if let Ok(r) = OstreeImageReference::try_from(image) {
+        return Ok(r);
+    }
+    let name = image.strip_prefix("docker://").unwrap_or(image);
+    OstreeImageReference::try_from(format!("ostree-unverified-registry:{}", name).as_str())
+}
+
+/// Find the rpmdb, or failing that the ostree commit, among the entries of a
+/// layer.
+fn find_layer_rpmdb(entries: &[String]) -> Option<LayerRpmdb> {
+    let entries: Vec<_> = entries.iter().map(|e| e.trim_start_matches("./")).collect();
+    for dir in RPMDB_PATHS {
+        let is_rpmdb = RPMDB_MARKERS
+            .iter()
+            .any(|m| entries.iter().any(|e| *e == format!("{}/{}", dir, m)));
+        if is_rpmdb {
+            let prefix = format!("{}/", dir);
+            let files = entries
+                .iter()
+                .filter(|e| e.starts_with(&prefix) && !e.ends_with('/'))
+                .map(|e| e.to_string())
+                .collect();
+            return Some(LayerRpmdb::Rpmdb(dir, files));
+        }
+    }
+    entries
+        .iter()
+        .find(|e| e.starts_with(OSTREE_OBJECTS) && e.ends_with(".commit"))
+        .map(|e| LayerRpmdb::Commit(e.to_string()))
+}
+
+/// The checksum of an ostree object from its path in the repo.
+fn object_checksum(path: &str) -> Result<String> {
+    let (dir, file) = path
+        .strip_prefix(OSTREE_OBJECTS)
+        .and_then(|p| p.split_once('/'))
+        .ok_or_else(|| anyhow!("Invalid object path: {}", path))?;
+    let file = file.strip_suffix(".commit").unwrap_or(file);
+    let checksum = format!("{}{}", dir, file);
+    ostree::validate_checksum_string(&checksum)?;
+    Ok(checksum)
+}
+
+/// What a layer has of the packages, as read by [`ImageLayers::scan_layer`].
+#[derive(Default)]
+struct LayerContent {
+    /// The regular files and directories of the layer.  In ostree images, the
+    /// rpmdb is hard links to objects, and we use the commit instead.
+    entries: Vec<String>,
+    /// The content of the ostree commit objects of the layer.
+    commits: HashMap<String, Vec<u8>>,
+}
+
+impl LayerContent {
+    /// Note `entry`, unpacking the files which may be part of an rpmdb into
+    /// `staging`, and keeping the commit objects.
+    fn add(&mut self, staging: &Path, entry: &mut LayerEntry) -> Result<()> {
+        let path = entry_path(entry)?;
+        match entry.header().entry_type() {
+            tar::EntryType::Regular => {
+                if RPMDB_PATHS
+                    .iter()
+                    .any(|d| path.starts_with(&format!("{}/", d)))
+                {
+                    entry.unpack_in(staging)?;
+                } else if path.starts_with(OSTREE_OBJECTS) && path.ends_with(".commit") {
+                    let mut buf = Vec::new();
+                    entry.read_to_end(&mut buf)?;
+                    self.commits.insert(path.clone(), buf);
+                }
+            }
+            tar::EntryType::Directory => {}
+            _ => return Ok(()),
+        }
+        self.entries.push(path);
+        Ok(())
+    }
+}
+
+/// Stream the layers of `image` from the newest, until one with the rpmdb or
+/// the ostree commit, and write the rpmdb to `usr/share/rpm` in `rootfs`, or
+/// the commit to `repo`.  Returns the checksum of the commit, if that's where
+/// the packages are.
+async fn fetch_rpmdb(
+    imgref: &OstreeImageReference,
+    repo: &ostree::Repo,
+    rootfs: &Path,
+) -> Result<Option<String>> {
+    let image = ImageLayers::open(imgref).await?;
+    let r = fetch_rpmdb_layers(&image, repo, rootfs).await;
+    image.close().await?;
+    r
+}
+
+async fn fetch_rpmdb_layers(
+    image: &ImageLayers,
+    repo: &ostree::Repo,
+    rootfs: &Path,
+) -> Result<Option<String>> {
+    for layer in image.layers.iter().rev() {
+        if layer.annotations.contains_key(COMPONENTS_ANNOTATION) {
+            continue;
+        }
+        let staging = tempfile::tempdir_in(rootfs)?;
+        let staging_path: PathBuf = staging.path().into();
+        let content = image
+            .scan_layer(layer, LayerContent::default(), move |content, entry| {
+                content.add(&staging_path, entry)
+            })
+            .await?;
+        match find_layer_rpmdb(&content.entries) {
+            Some(LayerRpmdb::Rpmdb(dir, _)) => {
+                let target = rootfs.join(crate::RPMOSTREE_RPMDB_LOCATION);
+                std::fs::create_dir_all(target.parent().unwrap())?;
+                std::fs::rename(staging.path().join(dir), &target)?;
+                return Ok(None);
+            }
+            Some(LayerRpmdb::Commit(path)) => {
+                let buf = content
+                    .commits
+                    .get(&path)
+                    .ok_or_else(|| anyhow!("Missing {}", path))?;
+                let checksum = object_checksum(&path)?;
+                let ty = glib::VariantTy::new(COMMIT_TYPE).unwrap();
+                let commit =
+                    glib::Variant::from_bytes_with_type(&glib::Bytes::from(buf.as_slice()), ty);
+                repo.write_metadata(
+                    ostree::ObjectType::Commit,
+                    Some(&checksum),
+                    &commit,
+                    gio::NONE_CANCELLABLE,
+                )?;
+                return Ok(Some(checksum));
+            }
+            None => continue,
+        }
+    }
+    Err(anyhow!("No rpmdb found in image"))
+}
+
+/// Fetch the packages of container image `image` for `db diff --image`: if
+/// they're only known from the image's ostree commit, it's written to `repo`
+/// and its checksum returned; otherwise the rpmdb is written to `rootfs_dfd`,
+/// and the returned string is empty.
+#[context("Fetching rpmdb of {}", image)]
+fn db_image_fetch_inner(repo: &ostree::Repo, image: &str, rootfs_dfd: i32) -> Result<String> {
+    let imgref = image_reference(image)?;
+    let rootfs = format!("/proc/self/fd/{}", rootfs_dfd);
+    let r = Handle::current().block_on(fetch_rpmdb(&imgref, repo, Path::new(&rootfs)))?;
+    Ok(r.unwrap_or_default())
+}
+
+pub(crate) fn db_image_fetch_rpmdb(
+    repo: &crate::FFIOstreeRepo,
+    image: &str,
+    rootfs_dfd: i32,
+) -> CxxResult<String> {
+    let repo = &repo.glib_reborrow();
+    Ok(db_image_fetch_inner(repo, image, rootfs_dfd)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_image_reference() -> Result<()> {
+        let r = image_reference("quay.io/os:39")?;
+        assert_eq!(r.to_string(), "ostree-unverified-registry:quay.io/os:39");
+        let r = image_reference("docker://quay.io/os:39")?;
+        assert_eq!(r.to_string(), "ostree-unverified-registry:quay.io/os:39");
+        let r = image_reference("ostree-unverified-image:oci:/var/tmp/os")?;
+        assert_eq!(r.to_string(), "ostree-unverified-image:oci:/var/tmp/os");
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_layer_rpmdb() -> Result<()> {
+        let entries = |e: &[&str]| e.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            find_layer_rpmdb(&entries(&[
+                "./usr/",
+                "./usr/bin/foo",
+                "./usr/lib/sysimage/rpm/",
+                "./usr/lib/sysimage/rpm/rpmdb.sqlite",
+                "./usr/lib/sysimage/rpm/rpmdb.sqlite-shm",
+            ])),
+            Some(LayerRpmdb::Rpmdb(
+                "usr/lib/sysimage/rpm",
+                entries(&[
+                    "usr/lib/sysimage/rpm/rpmdb.sqlite",
+                    "usr/lib/sysimage/rpm/rpmdb.sqlite-shm"
+                ])
+            ))
+        );
+        let commit = "sysroot/ostree/repo/objects/ab/cdef0123456789abcdef0123456789abcdef0123456789abcdef0123456789.commit";
+        assert_eq!(
+            find_layer_rpmdb(&entries(&["sysroot/ostree/repo/objects/ab/", commit])),
+            Some(LayerRpmdb::Commit(commit.to_string()))
+        );
+        assert_eq!(
+            object_checksum(commit)?,
+            "abcdef0123456789abcdef0123456789abcdef0123456789abcdef0123456789"
+        );
+        assert_eq!(find_layer_rpmdb(&entries(&["usr/bin/foo"])), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,516 @@
+//! Implementation of `rpm-ostree db verify`: like `rpm -V`, check the files of
+//! the packages of a deployment against the digests and modes in their
+//! headers, and cross-check them with the checksums of the ostree commit.
+//!
+//! A file in `/usr` which differs from the commit was tampered with, whatever
+//! its header says; one which is as in the commit but not as in its header was
+//! changed when composing, e.g. by a postprocess script.  Files in `/etc` are
+//! compared against their defaults in `/usr/etc`, which are themselves checked
+//! like the rest of `/usr`: local changes there are carried across upgrades by
+//! the `/etc` merge, so they're reported as expected, separately for `%config`
+//! files.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::deployment_generate_id_impl;
+use crate::verify::verify_checksum;
+use anyhow::{anyhow, bail, Context, Result};
+use clap::Parser;
+use openat_ext::OpenatDirExt;
+use openssl::hash::{Hasher, MessageDigest};
+use ostree_ext::{gio, ostree};
+use serde_derive::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::process::Command;
+
+/// The file attributes of the packages, one line per file.
+const HEADER_QUERYFORMAT: &str = "[%{NEVRA}\t%{FILEFLAGS}\t%{FILEMODES:octal}\t%{FILEDIGESTALGO}\t%{FILEDIGESTS}\t%{FILELINKTOS}\t%{FILENAMES}\n]";
+
+/// From `rpmfiles.h`.
+const RPMFILE_CONFIG: u32 = 1 << 0;
+const RPMFILE_GHOST: u32 = 1 << 6;
+
+/// From `rpmpgp.h`; packages without `FILEDIGESTALGO` use MD5.
+const PGPHASHALGO_MD5: u32 = 1;
+
+/// Verify the files of packages against their headers and the ostree commit
+#[derive(Debug, Parser)]
+#[clap(name = "rpm-ostree db verify", bin_name = "rpm-ostree db verify")]
+#[clap(rename_all = "kebab-case")]
+struct Opts {
+    /// The deployment to verify: its index, or booted, pending or rollback
+    #[clap(long, short = 'd', value_name = "DEPLOYMENT", default_value = "booted")]
+    deployment: String,
+
+    /// Output JSON
+    #[clap(long)]
+    json: bool,
+
+    /// Only verify the files of these packages
+    packages: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum Divergence {
+    /// The file differs from the commit.
+    Modified,
+    /// The file doesn't exist.
+    Missing,
+    /// A `%config` file in `/etc` was changed locally.
+    Config,
+    /// Another file in `/etc` was changed locally, and is kept by the `/etc` merge.
+    EtcMerge,
+    /// The file is as in the commit, but not as in its package.
+    Composed,
+}
+
+impl Divergence {
+    fn expected(self) -> bool {
+        !matches!(self, Divergence::Modified | Divergence::Missing)
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Divergence::Modified => "modified",
+            Divergence::Missing => "missing",
+            Divergence::Config => "config",
+            Divergence::EtcMerge => "etc-merge",
+            Divergence::Composed => "composed",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Finding {
+    path: String,
+    divergence: Divergence,
+    expected: bool,
+    /// The attributes which differ: `digest`, `mode`, `owner`, or
+    /// `ostree-checksum` for all of them plus the extended attributes; or
+    /// `removed` if the file was removed when composing or locally in `/etc`.
+    differs: Vec<&'static str>,
+    packages: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    deployment: String,
+    checked: usize,
+    findings: Vec<Finding>,
+}
+
+/// A file as described by the headers of the packages owning it.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct HeaderFile {
+    packages: BTreeSet<String>,
+    flags: u32,
+    mode: u32,
+    digest_algo: u32,
+    /// The digest of the content, or the target of a symbolic link.
+    digest: String,
+}
+
+impl HeaderFile {
+    fn is_config(&self) -> bool {
+        self.flags & RPMFILE_CONFIG > 0
+    }
+
+    fn is_ghost(&self) -> bool {
+        self.flags & RPMFILE_GHOST > 0
+    }
+}
+
+/// A file as found in the deployment.
+#[derive(Debug, PartialEq, Eq)]
+struct FileState {
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    /// The digest of the content, or the target of a symbolic link.
+    digest: String,
+}
+
+/// Parse the output of `rpm -q --qf HEADER_QUERYFORMAT`.  Files shared by
+/// several packages use the attributes of the first one which doesn't only
+/// own them as `%ghost`.
+fn parse_header_files(buf: &str) -> Result<BTreeMap<String, HeaderFile>> {
+    let mut r: BTreeMap<String, HeaderFile> = BTreeMap::new();
+    for line in buf.lines() {
+        let fields: Vec<_> = line.splitn(7, '\t').collect();
+        let (nevra, flags, mode, algo, digest, linkto, path) = match fields[..] {
+            [a, b, c, d, e, f, g] => (a, b, c, d, e, f, g),
+            _ => bail!("Invalid file attributes: {}", line),
+        };
+        if path.is_empty() || path == "(none)" {
+            continue;
+        }
+        let flags: u32 = flags
+            .parse()
+            .with_context(|| format!("Parsing flags of {}", path))?;
+        let mode =
+            u32::from_str_radix(mode, 8).with_context(|| format!("Parsing mode of {}", path))?;
+        let digest_algo = match algo {
+            "(none)" => PGPHASHALGO_MD5,
+            algo => algo.parse()?,
+        };
+        let digest = if mode & libc::S_IFMT == libc::S_IFLNK {
+            linkto
+        } else {
+            digest
+        };
+        let f = r.entry(path.to_string()).or_default();
+        if f.packages.is_empty() || (f.is_ghost() && flags & RPMFILE_GHOST == 0) {
+            // Keep %config from the other owners
+            f.flags = flags | (f.flags & RPMFILE_CONFIG);
+            f.mode = mode;
+            f.digest_algo = digest_algo;
+            f.digest = digest.to_string();
+        } else {
+            f.flags |= flags & RPMFILE_CONFIG;
+        }
+        f.packages.insert(nevra.to_string());
+    }
+    Ok(r)
+}
+
+/// The files of `packages`, or all of them, according to the rpmdb of `root`.
+fn header_files(root: &Path, packages: &[String]) -> Result<BTreeMap<String, HeaderFile>> {
+    let dbpath = root.join(crate::RPMOSTREE_RPMDB_LOCATION);
+    let mut cmd = Command::new("rpm");
+    cmd.arg(format!("--dbpath={}", dbpath.display()))
+        .args(["--qf", HEADER_QUERYFORMAT]);
+    if packages.is_empty() {
+        cmd.arg("-qa");
+    } else {
+        cmd.arg("-q").args(packages);
+    }
+    let out = cmd.output().context("Running rpm")?;
+    if !out.status.success() {
+        // e.g. "package foo is not installed" is on stdout
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        let msg: Vec<_> = stdout
+            .lines()
+            .filter(|l| !l.contains('\t'))
+            .chain(stderr.lines())
+            .collect();
+        bail!("Querying rpmdb: {}", msg.join("; "));
+    }
+    parse_header_files(&String::from_utf8_lossy(&out.stdout))
+}
+
+fn message_digest(algo: u32) -> Result<MessageDigest> {
+    let md = match algo {
+        1 => MessageDigest::md5(),
+        2 => MessageDigest::sha1(),
+        8 => MessageDigest::sha256(),
+        9 => MessageDigest::sha384(),
+        10 => MessageDigest::sha512(),
+        11 => MessageDigest::sha224(),
+        n => bail!("Unsupported file digest algorithm {}", n),
+    };
+    Ok(md)
+}
+
+/// The state of `path` in `root`, with the digest of its content in `algo`, if
+/// it exists.
+fn file_state(root: &openat::Dir, path: &str, algo: u32) -> Result<Option<FileState>> {
+    let meta = match root.metadata_optional(path)? {
+        Some(m) => m,
+        None => return Ok(None),
+    };
+    let st = meta.stat();
+    let digest = match st.st_mode & libc::S_IFMT {
+        libc::S_IFREG => {
+            let mut f = root.open_file(path)?;
+            let mut hasher = Hasher::new(message_digest(algo)?)?;
+            std::io::copy(&mut f, &mut hasher).with_context(|| format!("Reading {}", path))?;
+            hasher
+                .finish()?
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect()
+        }
+        libc::S_IFLNK => {
+            let target = root.read_link(path)?;
+            String::from_utf8_lossy(target.as_os_str().as_bytes()).into_owned()
+        }
+        _ => String::new(),
+    };
+    Ok(Some(FileState {
+        mode: st.st_mode,
+        uid: st.st_uid,
+        gid: st.st_gid,
+        digest,
+    }))
+}
+
+/// The attributes of `state` which differ from `expected`.
+fn state_differences(expected: &FileState, state: &FileState) -> Vec<&'static str> {
+    let mut r = Vec::new();
+    if expected.digest != state.digest {
+        r.push("digest");
+    }
+    if expected.mode != state.mode {
+        r.push("mode");
+    }
+    if (expected.uid, expected.gid) != (state.uid, state.gid) {
+        r.push("owner");
+    }
+    r
+}
+
+/// The attributes of `state` which differ from the header; ownership is by
+/// name in headers, so it's only checked against the commit.
+fn header_differences(header: &HeaderFile, state: &FileState) -> Vec<&'static str> {
+    let expected = FileState {
+        mode: header.mode,
+        uid: state.uid,
+        gid: state.gid,
+        digest: header.digest.clone(),
+    };
+    state_differences(&expected, state)
+}
+
+/// The checksum of the regular file or symbolic link `path` in the commit.
+fn commit_checksum(commit_root: &gio::File, path: &str) -> Result<Option<String>> {
+    let cancellable = gio::NONE_CANCELLABLE;
+    let f = commit_root.resolve_relative_path(path);
+    let f = f.downcast_ref::<ostree::RepoFile>().unwrap();
+    match f.query_file_type(gio::FileQueryInfoFlags::NOFOLLOW_SYMLINKS, cancellable) {
+        gio::FileType::Regular | gio::FileType::SymbolicLink => {}
+        _ => return Ok(None),
+    }
+    f.ensure_resolved()?;
+    Ok(f.checksum().map(|c| c.to_string()))
+}
+
+/// How a file diverges, and which of its attributes differ.
+type Check = Option<(Divergence, Vec<&'static str>)>;
+
+/// Check a file of `/usr` against the commit, and then its header if it's as
+/// in the commit.  Returns its state too, if it exists.
+fn verify_usr(
+    root: &openat::Dir,
+    commit_root: &gio::File,
+    path: &str,
+    header: &HeaderFile,
+) -> Result<(Option<FileState>, Check)> {
+    let checksum = commit_checksum(commit_root, path)?;
+    let state = file_state(root, path, header.digest_algo)?;
+    let r = match (checksum, &state) {
+        // Owned by a package, but removed when composing
+        (None, None) => Some((Divergence::Composed, vec!["removed"])),
+        (Some(_), None) => Some((Divergence::Missing, vec![])),
+        (None, Some(_)) => Some((Divergence::Modified, vec!["ostree-checksum"])),
+        (Some(checksum), Some(state)) => {
+            if !verify_checksum(root, path, &checksum)? {
+                Some((Divergence::Modified, vec!["ostree-checksum"]))
+            } else {
+                let differs = header_differences(header, state);
+                (!differs.is_empty()).then(|| (Divergence::Composed, differs))
+            }
+        }
+    };
+    Ok((state, r))
+}
+
+fn verify(
+    sysroot: &ostree::Sysroot,
+    deployment: &ostree::Deployment,
+    packages: &[String],
+) -> Result<Report> {
+    let repo = &sysroot.repo().unwrap();
+    let rootpath = sysroot.path().path().unwrap();
+    let rootpath = rootpath.join(sysroot.deployment_dirpath(deployment));
+    let root = &openat::Dir::open(&rootpath)?;
+    let (commit_root, _) = repo.read_commit(deployment.csum().as_str(), gio::NONE_CANCELLABLE)?;
+
+    let files = header_files(&rootpath, packages)?;
+    let mut checked = 0;
+    let mut findings = Vec::new();
+    let mut add = |path: &str, header: &HeaderFile, divergence, differs| {
+        findings.push(Finding {
+            path: path.to_string(),
+            divergence,
+            expected: Divergence::expected(divergence),
+            differs,
+            packages: header.packages.iter().cloned().collect(),
+        })
+    };
+    for (path, header) in files.iter() {
+        // Directories are checked by ostree fsck; %ghost files have no
+        // content in the header and are only created on the system.
+        if header.mode & libc::S_IFMT == libc::S_IFDIR || header.is_ghost() {
+            continue;
+        }
+        if let Some(etcpath) = path.strip_prefix("/etc/") {
+            checked += 1;
+            let etcpath = format!("etc/{}", etcpath);
+            let usretcpath = format!("usr/{}", etcpath);
+            let (defaults, r) = verify_usr(root, &commit_root, &usretcpath, header)?;
+            match r {
+                Some((Divergence::Composed, differs)) => {
+                    add(path, header, Divergence::Composed, differs)
+                }
+                Some((divergence, differs)) => {
+                    add(&format!("/{}", usretcpath), header, divergence, differs)
+                }
+                None => {}
+            }
+            let local = if header.is_config() {
+                Divergence::Config
+            } else {
+                Divergence::EtcMerge
+            };
+            match (defaults, file_state(root, &etcpath, header.digest_algo)?) {
+                (Some(_), None) => add(path, header, local, vec!["removed"]),
+                (Some(defaults), Some(state)) => {
+                    let differs = state_differences(&defaults, &state);
+                    if !differs.is_empty() {
+                        add(path, header, local, differs)
+                    }
+                }
+                (None, Some(state)) => {
+                    let differs = header_differences(header, &state);
+                    if !differs.is_empty() {
+                        add(path, header, local, differs)
+                    }
+                }
+                (None, None) => {}
+            }
+        } else if let Some(usrpath) = path.strip_prefix("/usr/") {
+            checked += 1;
+            let usrpath = format!("usr/{}", usrpath);
+            if let (_, Some((divergence, differs))) =
+                verify_usr(root, &commit_root, &usrpath, header)?
+            {
+                add(path, header, divergence, differs);
+            }
+        }
+        // Anything else, e.g. in /var, is only created on the system.
+    }
+    findings.sort_by(|a, b| (a.divergence, &a.path).cmp(&(b.divergence, &b.path)));
+    Ok(Report {
+        deployment: deployment_generate_id_impl(deployment),
+        checked,
+        findings,
+    })
+}
+
+fn print_findings(findings: &[&Finding]) {
+    for f in findings {
+        let differs = if f.differs.is_empty() {
+            String::new()
+        } else {
+            format!(" [{}]", f.differs.join(", "))
+        };
+        println!(
+            "  {:<9} {}{} ({})",
+            f.divergence.as_str(),
+            f.path,
+            differs,
+            f.packages.join(", ")
+        );
+    }
+}
+
+fn print_human(report: &Report) {
+    println!("Deployment: {}", report.deployment);
+    let (expected, tampered): (Vec<_>, Vec<_>) = report.findings.iter().partition(|f| f.expected);
+    if !tampered.is_empty() {
+        println!("Unexpected changes:");
+        print_findings(&tampered);
+    }
+    if !expected.is_empty() {
+        println!("Expected divergence:");
+        print_findings(&expected);
+    }
+    println!(
+        "Checked {} files: {} unexpected changes, {} expected divergences",
+        report.checked,
+        tampered.len(),
+        expected.len()
+    );
+}
+
+pub(crate) fn db_verify_entrypoint(args: &Vec<String>) -> Result<()> {
+    let opts = &crate::completion::parse_args::<Opts>(args)?;
+    // Some files are only readable by root
+    crate::ffi::client_require_root()?;
+    let sysroot = &ostree::Sysroot::new_default();
+    sysroot.load(gio::NONE_CANCELLABLE)?;
+    let deployment = &crate::pin::find_deployment(sysroot, &opts.deployment)?;
+    let report = verify(sysroot, deployment, &opts.packages)?;
+    if opts.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_human(&report);
+    }
+    let n = report.findings.iter().filter(|f| !f.expected).count();
+    if n > 0 {
+        return Err(anyhow!("{} files differ from the commit or are missing", n));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_header_files() -> Result<()> {
+        let buf = indoc::indoc! {"
+            bash-5.1.16-2.fc36.x86_64\t0\t100755\t8\tabcd\t\t/usr/bin/bash
+            bash-5.1.16-2.fc36.x86_64\t0\t120777\t8\t\tbash\t/usr/bin/sh
+            bash-5.1.16-2.fc36.x86_64\t17\t100644\t8\t0123\t\t/etc/skel/.bashrc
+            setup-2.13.10-1.fc36.noarch\t64\t100644\t8\t\t\t/etc/shadow
+            shadow-utils-4.11.1-2.fc36.x86_64\t0\t100000\t8\t4567\t\t/etc/shadow
+            filesystem-3.18-2.fc36.x86_64\t0\t40755\t8\t\t\t/usr/bin
+            gpg-pubkey-38ab71f4-60242b08\t(none)\t(none)\t(none)\t(none)\t(none)\t(none)
+        "};
+        let files = parse_header_files(buf)?;
+        assert_eq!(files.len(), 5);
+        assert_eq!(files["/usr/bin/sh"].digest, "bash");
+        assert_eq!(files["/usr/bin/bash"].mode, libc::S_IFREG | 0o755);
+        assert!(files["/etc/skel/.bashrc"].is_config());
+        let shadow = &files["/etc/shadow"];
+        assert!(!shadow.is_ghost());
+        assert_eq!(shadow.digest, "4567");
+        assert_eq!(shadow.packages.len(), 2);
+        assert!(parse_header_files("foo\t0\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_state() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        let root = openat::Dir::open(td.path())?;
+        root.write_file_contents("foo", 0o644, b"foo\n")?;
+        root.symlink("bar", "foo")?;
+        let foo = file_state(&root, "foo", 8)?.unwrap();
+        assert_eq!(
+            foo.digest,
+            "b5bb9d8014a0f9b1d61e21e796d78dccdf1352f23cd32812f4850b878ae4944c"
+        );
+        assert_eq!(foo.mode, libc::S_IFREG | 0o644);
+        assert_eq!(
+            file_state(&root, "foo", PGPHASHALGO_MD5)?.unwrap().digest,
+            "d3b07384d113edec49eaa6238ad5ff00"
+        );
+        assert_eq!(file_state(&root, "bar", 8)?.unwrap().digest, "foo");
+        assert_eq!(file_state(&root, "baz", 8)?, None);
+        assert!(file_state(&root, "foo", 3).is_err());
+
+        let header = HeaderFile {
+            mode: libc::S_IFREG | 0o600,
+            digest: foo.digest.clone(),
+            ..Default::default()
+        };
+        assert_eq!(header_differences(&header, &foo), ["mode"]);
+        Ok(())
+    }
+}
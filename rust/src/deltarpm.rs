@@ -0,0 +1,134 @@
+//! Downloading packages as DeltaRPMs.  When a repo provides a delta from the
+//! version of a package installed on the host to the one we want, we only
+//! download that delta (through librepo, with the repo's configuration) and
+//! reconstruct the full package from it and the files
+//! of the booted deployment with `applydeltarpm`.  This is a large win on
+//! metered or low-bandwidth links when updating layered packages.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::cxxrsutil::*;
+use anyhow::{anyhow, bail, Context, Result};
+use ostree_ext::glib;
+use std::io::Read;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+const APPLYDELTARPM: &str = "/usr/bin/applydeltarpm";
+
+/// Whether we can reconstruct packages from deltas on this host.
+pub(crate) fn deltarpm_available() -> bool {
+    Path::new(APPLYDELTARPM).exists()
+}
+
+/// Convert the output of `rpm -q --qf '%{EPOCH}:%{VERSION}-%{RELEASE}'` to the
+/// EVR format used in rpm-md, which omits a zero epoch.
+fn normalize_evr(evr: &str) -> &str {
+    evr.strip_prefix("(none):")
+        .or_else(|| evr.strip_prefix("0:"))
+        .unwrap_or(evr)
+}
+
+/// The EVR of the package `name`.`arch` installed on the host; empty if it
+/// isn't.  If multiple versions are installed (e.g. the kernel), this is the
+/// last one listed in the rpmdb.
+pub(crate) fn deltarpm_installed_evr(name: &str, arch: &str) -> CxxResult<String> {
+    let out = Command::new("rpm")
+        .args(["-q", "--qf", "%{EPOCH}:%{VERSION}-%{RELEASE}\\n"])
+        .arg(format!("{}.{}", name, arch))
+        .stderr(Stdio::null())
+        .output()?;
+    // Not installed
+    if !out.status.success() {
+        return Ok(String::new());
+    }
+    let out = String::from_utf8(out.stdout)?;
+    Ok(out
+        .lines()
+        .last()
+        .map(|evr| normalize_evr(evr.trim()).to_string())
+        .unwrap_or_default())
+}
+
+/// Verify that the file at `path` has the checksum `chksum_repr`, in the form
+/// `<type>:<hex>` as found in rpm-md.
+fn verify_checksum(path: &Path, chksum_repr: &str) -> Result<()> {
+    let (kind, expected) = chksum_repr
+        .split_once(':')
+        .ok_or_else(|| anyhow!("Invalid checksum: {}", chksum_repr))?;
+    let kind = match kind {
+        "sha256" => glib::ChecksumType::Sha256,
+        "sha512" => glib::ChecksumType::Sha512,
+        "sha1" | "sha" => glib::ChecksumType::Sha1,
+        "md5" => glib::ChecksumType::Md5,
+        o => bail!("Unsupported checksum type: {}", o),
+    };
+    let mut hasher = glib::Checksum::new(kind).unwrap();
+    let mut f = std::fs::File::open(path)?;
+    let mut buf = [0u8; 8192];
+    loop {
+        let n = f.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    let actual = hasher.string().expect("hash");
+    if actual != expected {
+        bail!("Checksum mismatch: expected {}, got {}", expected, actual);
+    }
+    Ok(())
+}
+
+/// Reconstruct the package `target` from the delta downloaded to `delta_fd`,
+/// verifying the result against `chksum_repr`.  On failure, `target` is left
+/// untouched and the caller should download the full package instead.
+pub(crate) fn deltarpm_apply(delta_fd: i32, target: &str, chksum_repr: &str) -> CxxResult<()> {
+    let target = Path::new(target);
+    let dir = target
+        .parent()
+        .ok_or_else(|| anyhow!("Invalid target: {}", target.display()))?;
+    let tmp_target = tempfile::NamedTempFile::new_in(dir)?;
+    let status = Command::new(APPLYDELTARPM)
+        // Not inherited; it's close-on-exec
+        .arg(format!("/proc/{}/fd/{}", std::process::id(), delta_fd))
+        .arg(tmp_target.path())
+        .stdout(Stdio::null())
+        .status()?;
+    if !status.success() {
+        return Err(anyhow!("{} failed: {:?}", APPLYDELTARPM, status).into());
+    }
+    verify_checksum(tmp_target.path(), chksum_repr)?;
+    tmp_target
+        .persist(target)
+        .with_context(|| format!("Writing {}", target.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_normalize_evr() {
+        assert_eq!(normalize_evr("(none):1.0-1.fc36"), "1.0-1.fc36");
+        assert_eq!(normalize_evr("0:1.0-1.fc36"), "1.0-1.fc36");
+        assert_eq!(normalize_evr("2:1.0-1.fc36"), "2:1.0-1.fc36");
+    }
+
+    #[test]
+    fn test_verify_checksum() -> Result<()> {
+        let mut f = tempfile::NamedTempFile::new()?;
+        f.write_all(b"hello world")?;
+        f.flush()?;
+        verify_checksum(
+            f.path(),
+            "sha256:b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9",
+        )?;
+        assert!(verify_checksum(f.path(), "sha256:00").is_err());
+        assert!(verify_checksum(f.path(), "blake2:00").is_err());
+        assert!(verify_checksum(f.path(), "garbage").is_err());
+        Ok(())
+    }
+}
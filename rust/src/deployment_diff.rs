@@ -0,0 +1,379 @@
+//! Implementation of `rpm-ostree diff`: compare two deployments.
+//!
+//! Deployments of container images are ostree commits too, so the packages
+//! and files are compared the same way for both kinds of origins.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::cxxrsutil::*;
+use crate::deployment_generate_id_impl;
+use crate::dirdiff::Diff;
+use crate::sysroot_upgrade::PackageDiff;
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use ostree_ext::{gio, glib, ostree};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+/// Compare two deployments
+#[derive(Debug, Parser)]
+#[clap(name = "rpm-ostree diff", bin_name = "rpm-ostree diff")]
+#[clap(rename_all = "kebab-case")]
+pub(crate) struct Opts {
+    /// The deployment to compare from: its index, or booted, pending or rollback
+    #[clap(value_name = "DEPLOYMENT")]
+    from: String,
+
+    /// The deployment to compare to
+    #[clap(value_name = "DEPLOYMENT")]
+    to: String,
+
+    /// Also list the files which differ
+    #[clap(long)]
+    files: bool,
+
+    /// Output JSON
+    #[clap(long)]
+    json: bool,
+}
+
+/// The packages of a commit, as (name, evr, arch).
+pub(crate) fn commit_packages(
+    repo: &ostree::Repo,
+    rev: &str,
+) -> Result<Vec<(String, String, String)>> {
+    let cancellable = gio::Cancellable::new();
+    let r = crate::ffi::package_variant_list_for_commit(
+        repo.reborrow_cxx(),
+        rev,
+        cancellable.reborrow_cxx(),
+    )?;
+    let pkglist: glib::Variant = unsafe { glib::translate::from_glib_full(r as *mut _) };
+    Ok(pkglist
+        .iter()
+        .map(|pkg| {
+            let field = |i| pkg.child_value(i).str().unwrap().to_string();
+            let (epoch, version, release) = (field(1), field(2), field(3));
+            let evr = if epoch == "0" {
+                format!("{}-{}", version, release)
+            } else {
+                format!("{}:{}-{}", epoch, version, release)
+            };
+            (field(0), evr, field(4))
+        })
+        .collect())
+}
+
+/// Key the packages of both commits by name, except the ones installed for
+/// several architectures in either (e.g. multilib), which are keyed by
+/// `name.arch` so that each architecture is compared separately.
+fn package_keys(from: &mut [(String, String, String)], to: &mut [(String, String, String)]) {
+    let mut counts = BTreeMap::<String, (usize, usize)>::new();
+    for (name, _, _) in from.iter() {
+        counts.entry(name.clone()).or_default().0 += 1;
+    }
+    for (name, _, _) in to.iter() {
+        counts.entry(name.clone()).or_default().1 += 1;
+    }
+    for (name, _, arch) in from.iter_mut().chain(to.iter_mut()) {
+        let (a, b) = counts[name.as_str()];
+        if a > 1 || b > 1 {
+            name.push('.');
+            name.push_str(arch);
+        }
+    }
+}
+
+/// The package name of a key from package_keys().
+fn key_name<'a>(key: &'a str, arch: &str) -> &'a str {
+    key.strip_suffix(arch)
+        .and_then(|k| k.strip_suffix('.'))
+        .unwrap_or(key)
+}
+
+/// The top-level keys of the origins of two deployments which differ, with
+/// their value in each.
+fn origin_diff(from: &Value, to: &Value) -> BTreeMap<String, (Value, Value)> {
+    let empty = serde_json::Map::new();
+    let from = from.as_object().unwrap_or(&empty);
+    let to = to.as_object().unwrap_or(&empty);
+    from.keys()
+        .chain(to.keys())
+        .filter_map(|k| {
+            let a = from.get(k).cloned().unwrap_or(Value::Null);
+            let b = to.get(k).cloned().unwrap_or(Value::Null);
+            (a != b).then(|| (k.clone(), (a, b)))
+        })
+        .collect()
+}
+
+fn deployment_origin(deployment: &ostree::Deployment) -> Result<Value> {
+    let origin = deployment
+        .origin()
+        .ok_or_else(|| anyhow!("Deployment {} has no origin", deployment.csum()))?;
+    let tf = crate::origin::origin_to_treefile_inner(&origin)?;
+    Ok(serde_json::to_value(&tf.parsed)?)
+}
+
+/// Record the differences between the commit trees `from` and `to` under `path`,
+/// skipping directories whose content checksum is the same.
+fn tree_diff(from: &gio::File, to: &gio::File, path: &str, diff: &mut Diff) -> Result<()> {
+    let cancellable = gio::NONE_CANCELLABLE;
+    let children = |dir: &gio::File| -> Result<BTreeMap<String, gio::FileType>> {
+        let e = dir.enumerate_children(
+            "standard::name,standard::type",
+            gio::FileQueryInfoFlags::NOFOLLOW_SYMLINKS,
+            cancellable,
+        )?;
+        let mut r = BTreeMap::new();
+        for info in e {
+            let info = info?;
+            if let Some(name) = info.name().to_str() {
+                r.insert(name.to_string(), info.file_type());
+            }
+        }
+        Ok(r)
+    };
+    let checksums = |f: &gio::File, is_dir: bool| -> Result<(Option<String>, Option<String>)> {
+        let f = f.downcast_ref::<ostree::RepoFile>().unwrap();
+        f.ensure_resolved()?;
+        Ok(if is_dir {
+            (
+                f.tree_get_contents_checksum().map(|s| s.to_string()),
+                f.tree_get_metadata_checksum().map(|s| s.to_string()),
+            )
+        } else {
+            (f.checksum().map(|s| s.to_string()), None)
+        })
+    };
+    let from_children = children(from)?;
+    let to_children = children(to)?;
+    let is_dir = |t: gio::FileType| t == gio::FileType::Directory;
+    for (name, &ftype) in from_children.iter() {
+        let childpath = format!("{}/{}", path, name);
+        match to_children.get(name) {
+            Some(&ttype) if is_dir(ftype) == is_dir(ttype) => {
+                let (a, b) = (from.child(name), to.child(name));
+                let (ca, cb) = (checksums(&a, is_dir(ftype))?, checksums(&b, is_dir(ftype))?);
+                if ca == cb {
+                    continue;
+                }
+                if is_dir(ftype) {
+                    if ca.1 != cb.1 {
+                        diff.changed_dirs.insert(childpath.clone());
+                    }
+                    tree_diff(&a, &b, &childpath, diff)?;
+                } else {
+                    diff.changed_files.insert(childpath);
+                }
+            }
+            // A directory replaced by a file or the other way around
+            Some(_) if is_dir(ftype) => {
+                diff.removed_dirs.insert(childpath.clone());
+                diff.added_files.insert(childpath);
+            }
+            Some(_) => {
+                diff.removed_files.insert(childpath.clone());
+                diff.added_dirs.insert(childpath);
+            }
+            None if is_dir(ftype) => {
+                diff.removed_dirs.insert(childpath);
+            }
+            None => {
+                diff.removed_files.insert(childpath);
+            }
+        }
+    }
+    for (name, &ttype) in to_children.iter() {
+        if from_children.contains_key(name) {
+            continue;
+        }
+        let childpath = format!("{}/{}", path, name);
+        if is_dir(ttype) {
+            diff.added_dirs.insert(childpath);
+        } else {
+            diff.added_files.insert(childpath);
+        }
+    }
+    Ok(())
+}
+
+fn print_human(
+    ids: (&str, &str),
+    origin: &BTreeMap<String, (Value, Value)>,
+    pkgs: &PackageDiff,
+    files: Option<&Diff>,
+) {
+    println!("{}", tr!("Deployments: {} → {}", ids.0, ids.1));
+    if !origin.is_empty() {
+        println!("{}", tr!("Origin:"));
+        for (k, (a, b)) in origin {
+            println!("  {}: {} → {}", k, a, b);
+        }
+    }
+    if pkgs.is_empty() {
+        println!("{}", tr!("No package changes."));
+    }
+    let sections = [
+        (tr!("Upgraded:"), &pkgs.upgraded),
+        (tr!("Downgraded:"), &pkgs.downgraded),
+    ];
+    for (title, changes) in sections {
+        if !changes.is_empty() {
+            println!("{}", title);
+            for (name, (old_evr, _), (new_evr, _)) in changes {
+                println!("  {} {} → {}", name, old_evr, new_evr);
+            }
+        }
+    }
+    for (title, pkgs) in [
+        (tr!("Removed:"), &pkgs.removed),
+        (tr!("Added:"), &pkgs.added),
+    ] {
+        if !pkgs.is_empty() {
+            println!("{}", title);
+            for (name, (evr, arch)) in pkgs {
+                println!("  {}-{}.{}", key_name(name, arch), evr, arch);
+            }
+        }
+    }
+    if let Some(files) = files {
+        println!("{}", tr!("Files: {}", files));
+        let sections = [
+            ("A", &files.added_dirs),
+            ("A", &files.added_files),
+            ("D", &files.removed_dirs),
+            ("D", &files.removed_files),
+            ("M", &files.changed_dirs),
+            ("M", &files.changed_files),
+        ];
+        for (kind, paths) in sections {
+            for path in paths {
+                println!("  {} {}", kind, path);
+            }
+        }
+    }
+}
+
+fn to_json(
+    ids: (&str, &str),
+    origin: &BTreeMap<String, (Value, Value)>,
+    pkgs: &PackageDiff,
+    files: Option<&Diff>,
+) -> Result<Value> {
+    let changed = |v: &[(&str, (&str, &str), (&str, &str))]| -> Vec<Value> {
+        v.iter()
+            .map(|(name, (old_evr, arch), (new_evr, _))| {
+                json!({"name": key_name(name, arch), "from": old_evr, "to": new_evr, "arch": arch})
+            })
+            .collect()
+    };
+    let single = |v: &[(&str, (&str, &str))]| -> Vec<Value> {
+        v.iter()
+            .map(|(name, (evr, arch))| json!({"name": key_name(name, arch), "evr": evr, "arch": arch}))
+            .collect()
+    };
+    let origin: serde_json::Map<String, Value> = origin
+        .iter()
+        .map(|(k, (a, b))| (k.clone(), json!({"from": a, "to": b})))
+        .collect();
+    let mut r = json!({
+        "from": ids.0,
+        "to": ids.1,
+        "origin": origin,
+        "packages": {
+            "upgraded": changed(&pkgs.upgraded),
+            "downgraded": changed(&pkgs.downgraded),
+            "removed": single(&pkgs.removed),
+            "added": single(&pkgs.added),
+        },
+    });
+    if let Some(files) = files {
+        r["files"] = serde_json::to_value(files)?;
+    }
+    Ok(r)
+}
+
+pub fn entrypoint(args: &[&str]) -> Result<()> {
+    let opts = &Opts::parse_from(args.iter().skip(1));
+    let sysroot = &ostree::Sysroot::new_default();
+    sysroot.load(gio::NONE_CANCELLABLE)?;
+    let repo = &sysroot.repo().unwrap();
+    let from = &crate::pin::find_deployment(sysroot, &opts.from)?;
+    let to = &crate::pin::find_deployment(sysroot, &opts.to)?;
+    let from_id = deployment_generate_id_impl(from);
+    let to_id = deployment_generate_id_impl(to);
+    let ids = (from_id.as_str(), to_id.as_str());
+
+    let origin = origin_diff(&deployment_origin(from)?, &deployment_origin(to)?);
+
+    let mut from_pkgs = commit_packages(repo, from.csum().as_str())?;
+    let mut to_pkgs = commit_packages(repo, to.csum().as_str())?;
+    package_keys(&mut from_pkgs, &mut to_pkgs);
+    let pkgmap = |pkgs: &'_ [(String, String, String)]| {
+        pkgs.iter()
+            .map(|(name, evr, arch)| (name.as_str(), (evr.as_str(), arch.as_str())))
+            .collect::<BTreeMap<_, _>>()
+    };
+    let pkgs = PackageDiff::new(&pkgmap(&from_pkgs), &pkgmap(&to_pkgs));
+
+    let files = if opts.files {
+        let (from_root, _) = repo.read_commit(from.csum().as_str(), gio::NONE_CANCELLABLE)?;
+        let (to_root, _) = repo.read_commit(to.csum().as_str(), gio::NONE_CANCELLABLE)?;
+        let mut diff = Diff::default();
+        tree_diff(&from_root, &to_root, "", &mut diff)?;
+        Some(diff)
+    } else {
+        None
+    };
+
+    if opts.json {
+        let r = to_json(ids, &origin, &pkgs, files.as_ref())?;
+        println!("{}", serde_json::to_string_pretty(&r)?);
+    } else {
+        print_human(ids, &origin, &pkgs, files.as_ref());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_package_keys() {
+        let pkg =
+            |name: &str, arch: &str| (name.to_string(), "1.0-1".to_string(), arch.to_string());
+        let mut from = vec![pkg("glibc", "x86_64"), pkg("bash", "x86_64")];
+        let mut to = vec![
+            pkg("glibc", "x86_64"),
+            pkg("glibc", "i686"),
+            pkg("bash", "noarch"),
+        ];
+        package_keys(&mut from, &mut to);
+        let keys = |v: &[(String, String, String)]| {
+            v.iter().map(|(k, _, _)| k.clone()).collect::<Vec<_>>()
+        };
+        assert_eq!(keys(&from), ["glibc.x86_64", "bash"]);
+        assert_eq!(keys(&to), ["glibc.x86_64", "glibc.i686", "bash"]);
+        assert_eq!(key_name("glibc.i686", "i686"), "glibc");
+        assert_eq!(key_name("bash", "noarch"), "bash");
+    }
+
+    #[test]
+    fn test_origin_diff() {
+        let a = json!({"packages": ["vim"], "container-image-reference": "a"});
+        let b = json!({"packages": ["vim", "strace"], "container-image-reference": "a"});
+        let diff = origin_diff(&a, &b);
+        assert_eq!(diff.len(), 1);
+        assert_eq!(diff["packages"], (json!(["vim"]), json!(["vim", "strace"])));
+        let c = json!({"refspec": "fedora:fedora/37/x86_64/silverblue"});
+        let diff = origin_diff(&a, &c);
+        assert_eq!(
+            diff.keys().collect::<Vec<_>>(),
+            ["container-image-reference", "packages", "refspec"]
+        );
+        assert_eq!(diff["refspec"].0, Value::Null);
+        assert!(origin_diff(&a, &a).is_empty());
+    }
+}
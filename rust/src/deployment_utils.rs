@@ -68,6 +68,22 @@ pub fn deployment_checksum_for_id(
     Ok(csum.to_string())
 }
 
+/// Serialize the origin of the deployment `deploy_id` as JSON, in its
+/// treefile representation.
+pub fn deployment_origin_json(
+    mut ffi_sysroot: Pin<&mut crate::ffi::OstreeSysroot>,
+    deploy_id: &str,
+) -> CxxResult<String> {
+    let sysroot = &ffi_sysroot.gobj_wrap();
+
+    let deployment = deployment_for_id_impl(sysroot, deploy_id)?;
+    let origin = deployment
+        .origin()
+        .ok_or_else(|| anyhow!("Deployment {} has no origin", deploy_id))?;
+    let tf = crate::origin::origin_to_treefile_inner(&origin)?;
+    Ok(tf.get_json_string())
+}
+
 pub fn deployment_get_base(
     mut ffi_sysroot: Pin<&mut crate::ffi::OstreeSysroot>,
     opt_deploy_id: &str,
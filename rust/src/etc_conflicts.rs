@@ -0,0 +1,332 @@
+//! Report the files of /etc where the three-way merge done when deploying
+//! kept a local modification although the new defaults (in /usr/etc) changed
+//! too, so that the admin can review the now possibly stale configuration.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::cxxrsutil::*;
+use crate::dirdiff;
+use anyhow::{anyhow, Context, Result};
+use fn_error_context::context;
+use glib::prelude::*;
+use ostree_ext::diff::FileTreeDiff;
+use ostree_ext::{gio, glib, ostree};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::path::Path;
+
+/// Where the conflicts of each deployment are recorded, by deployment ID.
+const STATE_DIR: &str = "/var/lib/rpm-ostree/etc-conflicts";
+/// Journal message ID for the summary logged when deploying.
+const ETC_CONFLICTS_MSG_ID: &str = "9c7d2f5a0e3b4b6c8a1d6e4f2b7c9a30";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum ConflictKind {
+    /// Modified locally, and changed or removed in the new defaults.
+    Modified,
+    /// Removed locally, and changed in the new defaults.
+    Removed,
+    /// Added locally, and also added in the new defaults.
+    Added,
+}
+
+impl ConflictKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            ConflictKind::Modified => "modified",
+            ConflictKind::Removed => "removed",
+            ConflictKind::Added => "added",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+struct EtcConflict {
+    /// Path relative to /etc.
+    path: String,
+    kind: ConflictKind,
+}
+
+/// Whether `path` or one of its parent directories is in `set`.
+fn contains_or_under(set: &BTreeSet<String>, path: &str) -> bool {
+    set.contains(path)
+        || path
+            .match_indices('/')
+            .any(|(i, _)| set.contains(&path[..i]))
+}
+
+/// Intersect the local changes of /etc against the old defaults with the
+/// changes of the defaults between the old and new commits.
+fn compute_conflicts(local: &dirdiff::Diff, defaults: &FileTreeDiff) -> Vec<EtcConflict> {
+    let strip = |s: &String| s.trim_start_matches('/').to_string();
+    let changed: BTreeSet<_> = defaults.changed_files.iter().map(strip).collect();
+    let removed: BTreeSet<_> = defaults
+        .removed_files
+        .iter()
+        .chain(defaults.removed_dirs.iter())
+        .map(strip)
+        .collect();
+    let added: BTreeSet<_> = defaults.added_files.iter().map(strip).collect();
+
+    let mut r = BTreeSet::new();
+    let mut push = |path: &str, kind| {
+        r.insert(EtcConflict {
+            path: path.to_string(),
+            kind,
+        });
+    };
+    for path in &local.changed_files {
+        if changed.contains(path) || contains_or_under(&removed, path) {
+            push(path, ConflictKind::Modified);
+        }
+    }
+    for path in &changed {
+        if contains_or_under(&local.removed_files, path)
+            || contains_or_under(&local.removed_dirs, path)
+        {
+            push(path, ConflictKind::Removed);
+        }
+    }
+    for path in &added {
+        if local.added_files.contains(path) || contains_or_under(&local.added_dirs, path) {
+            push(path, ConflictKind::Added);
+        }
+    }
+    r.into_iter().collect()
+}
+
+fn state_path(id: &str) -> String {
+    format!("{}/{}.json", STATE_DIR, id)
+}
+
+/// Records the merge deployment of a staged deployment, until it's finalized.
+fn pending_path(id: &str) -> String {
+    format!("{}/{}.pending", STATE_DIR, id)
+}
+
+fn read_conflicts(id: &str) -> Result<Vec<EtcConflict>> {
+    let path = state_path(id);
+    if !Path::new(&path).exists() {
+        return Ok(Vec::new());
+    }
+    let f = std::fs::File::open(&path).with_context(|| format!("Opening {}", path))?;
+    let r = serde_json::from_reader(std::io::BufReader::new(f))
+        .with_context(|| format!("Parsing {}", path))?;
+    Ok(r)
+}
+
+/// Compute the conflicts of the /etc of `merge` merged into `new`.
+#[context("Computing /etc merge conflicts")]
+fn compute_impl(
+    sysroot: &ostree::Sysroot,
+    merge: &ostree::Deployment,
+    new: &ostree::Deployment,
+) -> Result<Vec<EtcConflict>> {
+    let repo = &sysroot.repo().expect("repo");
+    let sysroot_path = sysroot.path();
+    let sysroot_path = sysroot_path
+        .path()
+        .ok_or_else(|| anyhow!("Sysroot has no path"))?;
+    let merge_dir = sysroot_path.join(sysroot.deployment_dirpath(merge).as_str());
+    let merge_dir = openat::Dir::open(&merge_dir)?;
+    let local = dirdiff::diff(&merge_dir.sub_dir("usr/etc")?, &merge_dir.sub_dir("etc")?)?;
+    let from = merge.csum().expect("csum");
+    let to = new.csum().expect("csum");
+    let defaults = ostree_ext::diff::diff(repo, &from, &to, Some("/usr/etc"))?;
+    Ok(compute_conflicts(&local, &defaults))
+}
+
+/// Drop the records of deployments which don't exist anymore, apart from `new`.
+fn prune_records(sysroot: &ostree::Sysroot, new: &ostree::Deployment) -> Result<()> {
+    let ids: BTreeSet<String> = sysroot
+        .deployments()
+        .iter()
+        .chain(std::iter::once(new))
+        .map(crate::deployment_generate_id_impl)
+        .collect();
+    std::fs::create_dir_all(STATE_DIR)?;
+    for entry in std::fs::read_dir(STATE_DIR)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        let id = name
+            .strip_suffix(".json")
+            .or_else(|| name.strip_suffix(".pending"));
+        match id {
+            Some(id) if ids.contains(id) => {}
+            _ => std::fs::remove_file(entry.path())?,
+        }
+    }
+    Ok(())
+}
+
+/// Record the conflicts of the deployment `id`, and log a summary.
+fn store_conflicts(id: &str, conflicts: &[EtcConflict]) -> Result<()> {
+    if conflicts.is_empty() {
+        return match std::fs::remove_file(state_path(id)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        };
+    }
+    crate::utils::write_file_atomic(Path::new(&state_path(id)), serde_json::to_vec(conflicts)?)?;
+    let paths: Vec<_> = conflicts.iter().map(|c| c.path.as_str()).collect();
+    let msg = format!(
+        "MESSAGE=Deployment {} keeps local changes to {} file(s) of /etc whose defaults changed: {}",
+        id,
+        conflicts.len(),
+        paths.join(", ")
+    );
+    systemd::journal::send(&[
+        msg.as_str(),
+        &format!("MESSAGE_ID={}", ETC_CONFLICTS_MSG_ID),
+        &format!("DEPLOYMENT_ID={}", id),
+        &format!("ETC_CONFLICTS={}", conflicts.len()),
+    ]);
+    Ok(())
+}
+
+/// Record the /etc merge conflicts of the `new` deployment, whose /etc is
+/// merged from `merge`, and log a summary.  For a staged deployment, /etc is
+/// only merged when it's finalized, so this just remembers `merge` for
+/// [`etc_conflicts_finalize`].
+pub(crate) fn etc_conflicts_record(
+    sysroot: &crate::FFIOstreeSysroot,
+    merge: &crate::FFIOstreeDeployment,
+    new: &crate::FFIOstreeDeployment,
+) -> CxxResult<()> {
+    let sysroot = &sysroot.glib_reborrow();
+    let merge = &merge.glib_reborrow();
+    let new = &new.glib_reborrow();
+    prune_records(sysroot, new)?;
+    let id = crate::deployment_generate_id_impl(new);
+    if new.is_staged() {
+        let merge_id = crate::deployment_generate_id_impl(merge);
+        crate::utils::write_file_atomic(Path::new(&pending_path(&id)), merge_id)?;
+        return Ok(());
+    }
+    let conflicts = compute_impl(sysroot, merge, new)?;
+    store_conflicts(&id, &conflicts)?;
+    Ok(())
+}
+
+fn finalize_impl(sysroot: &ostree::Sysroot) -> Result<()> {
+    let booted = match sysroot.booted_deployment() {
+        Some(d) => d,
+        None => return Ok(()),
+    };
+    let id = crate::deployment_generate_id_impl(&booted);
+    let pending = pending_path(&id);
+    let merge_id = match std::fs::read_to_string(&pending) {
+        Ok(s) => s,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("Reading {}", pending)),
+    };
+    // The merge deployment isn't booted anymore, so its /etc is still the one
+    // which was merged when finalizing.
+    let merge = sysroot
+        .deployments()
+        .into_iter()
+        .find(|d| crate::deployment_generate_id_impl(d) == merge_id.trim());
+    if let Some(merge) = merge {
+        let conflicts = compute_impl(sysroot, &merge, &booted)?;
+        store_conflicts(&id, &conflicts)?;
+    }
+    std::fs::remove_file(&pending).with_context(|| format!("Removing {}", pending))?;
+    Ok(())
+}
+
+/// Record the /etc merge conflicts of the booted deployment if it was staged
+/// and is now finalized.
+pub(crate) fn etc_conflicts_finalize(sysroot: &crate::FFIOstreeSysroot) -> CxxResult<()> {
+    let sysroot = &sysroot.glib_reborrow();
+    sysroot.load(gio::NONE_CANCELLABLE)?;
+    finalize_impl(sysroot)?;
+    Ok(())
+}
+
+/// The recorded /etc merge conflicts of `deployment`, as `a(ss)` of
+/// (path, kind), or `None` if there are none.
+pub(crate) fn deployment_etc_conflicts_variant(
+    deployment: &ostree::Deployment,
+) -> Result<Option<glib::Variant>> {
+    let conflicts = read_conflicts(&crate::deployment_generate_id_impl(deployment))?;
+    if conflicts.is_empty() {
+        return Ok(None);
+    }
+    let v: Vec<(String, String)> = conflicts
+        .into_iter()
+        .map(|c| (format!("/etc/{}", c.path), c.kind.as_str().to_string()))
+        .collect();
+    Ok(Some(v.to_variant()))
+}
+
+/// Render the /etc merge conflicts of `deployments`, the `Deployments`
+/// property of the sysroot, for `rpm-ostree status --etc-conflicts`; empty if
+/// there are none.
+pub(crate) fn deployments_etc_conflicts(deployments: &crate::FFIGVariant) -> CxxResult<String> {
+    let deployments = deployments.glib_reborrow();
+    let mut r = String::new();
+    for i in 0..deployments.n_children() {
+        let dict = glib::VariantDict::new(Some(&deployments.child_value(i)));
+        let conflicts = match dict.lookup_value("etc-conflicts", None) {
+            Some(v) => v.get::<Vec<(String, String)>>().unwrap_or_default(),
+            None => continue,
+        };
+        let id = dict
+            .lookup::<String>("id")
+            .map_err(anyhow::Error::msg)?
+            .unwrap_or_default();
+        writeln!(r, "{}:", id).unwrap();
+        for (path, kind) in conflicts {
+            writeln!(r, "  {:<8} {}", kind, path).unwrap();
+        }
+    }
+    Ok(r)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(v: &[&str]) -> BTreeSet<String> {
+        v.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_compute_conflicts() {
+        let local = dirdiff::Diff {
+            changed_files: set(&["ssh/sshd_config", "chrony.conf", "old.conf", "hosts"]),
+            removed_files: set(&["motd"]),
+            removed_dirs: set(&["dnf"]),
+            added_files: set(&["new.conf"]),
+            added_dirs: set(&["foo.d"]),
+            ..Default::default()
+        };
+        let defaults = FileTreeDiff {
+            subdir: Some("/usr/etc".to_string()),
+            changed_files: set(&["/ssh/sshd_config", "/motd", "/dnf/dnf.conf", "/issue"]),
+            removed_files: set(&["/old.conf"]),
+            removed_dirs: Default::default(),
+            added_files: set(&["/new.conf", "/foo.d/a.conf", "/other.conf"]),
+            added_dirs: Default::default(),
+            changed_dirs: Default::default(),
+        };
+        let r: Vec<_> = compute_conflicts(&local, &defaults)
+            .into_iter()
+            .map(|c| (c.path, c.kind))
+            .collect();
+        assert_eq!(
+            r,
+            [
+                ("dnf/dnf.conf".to_string(), ConflictKind::Removed),
+                ("foo.d/a.conf".to_string(), ConflictKind::Added),
+                ("motd".to_string(), ConflictKind::Removed),
+                ("new.conf".to_string(), ConflictKind::Added),
+                ("old.conf".to_string(), ConflictKind::Modified),
+                ("ssh/sshd_config".to_string(), ConflictKind::Modified),
+            ]
+        );
+    }
+}
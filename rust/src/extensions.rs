@@ -19,8 +19,8 @@ use std::os::unix::prelude::PermissionsExt;
 use crate::cxxrsutil::*;
 use crate::ffi::StringMapping;
 use crate::utils;
+use crate::TreeComposeConfig;
 use crate::Treefile;
-use crate::{BaseComposeConfigFields, TreeComposeConfig};
 
 const RPMOSTREE_EXTENSIONS_STATE_FILE: &str = ".rpm-ostree-state-chksum";
 
@@ -187,16 +187,11 @@ impl Extensions {
     pub(crate) fn generate_treefile(&self, src: &Treefile) -> CxxResult<Box<Treefile>> {
         let mut repos = src.parsed.base.repos.clone().unwrap_or_default();
         repos.extend(self.repos.iter().flatten().cloned());
-        let ret = TreeComposeConfig {
-            base: BaseComposeConfigFields {
-                repos: Some(repos),
-                releasever: src.parsed.base.releasever.clone(),
-                ..Default::default()
-            },
-            packages: Some(self.get_os_extension_packages().into_iter().collect()),
-            modules: self.modules.clone(),
-            ..Default::default()
-        };
+        let mut ret = TreeComposeConfig::default();
+        ret.base.repos = Some(repos);
+        ret.base.releasever = src.parsed.base.releasever.clone();
+        ret.packages = Some(self.get_os_extension_packages().into_iter().collect());
+        ret.modules = self.modules.clone();
         Ok(Box::new(Treefile::new_from_config(ret)?))
     }
 }
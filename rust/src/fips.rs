@@ -0,0 +1,150 @@
+//! Implementation of `rpm-ostree ex fips enable`, which enables FIPS mode in a
+//! new deployment: the packages it requires are layered if the base lacks
+//! them, the `fips` dracut module is added to the regenerated initramfs, and
+//! the `fips=1` and `boot=` kernel arguments are appended.  All of this is
+//! tracked in the origin, along with FIPS mode being enabled.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::cxxrsutil::*;
+use anyhow::{anyhow, bail, Context, Result};
+use clap::Parser;
+use gio::prelude::*;
+use glib::Variant;
+use ostree_ext::{gio, glib};
+use std::process::Command;
+
+use crate::utils::print_treepkg_diff;
+
+/// The packages FIPS mode requires: the tools of the FIPS crypto policy, and
+/// the HMAC tools the `fips` dracut module uses to check the kernel.
+const FIPS_PACKAGES: &[&str] = &["crypto-policies-scripts", "libkcapi-hmaccalc"];
+const FIPS_DRACUT_MODULE: &str = "fips";
+
+#[derive(Debug, Parser)]
+#[clap(name = "rpm-ostree ex fips")]
+#[clap(rename_all = "kebab-case")]
+enum Opt {
+    /// Enable FIPS mode in a new deployment
+    Enable(EnableOpts),
+}
+
+#[derive(Debug, Parser)]
+struct EnableOpts {
+    /// Initiate a reboot after the operation is complete
+    #[clap(long, short = 'r')]
+    reboot: bool,
+    #[clap(long)]
+    lock_finalization: bool,
+}
+
+pub(crate) fn fips_entrypoint(args: &Vec<String>) -> Result<()> {
+    match Opt::parse_from(args.iter()) {
+        Opt::Enable(ref opts) => enable(opts),
+    }
+}
+
+fn enable(opts: &EnableOpts) -> Result<()> {
+    let client = &mut crate::client::ClientConnection::new()?;
+    let previous_deployment = client
+        .get_os_proxy()
+        .cached_property("DefaultDeployment")
+        .ok_or_else(|| anyhow!("Failed to find default-deployment property"))?;
+    let modifiers = glib::VariantDict::new(None);
+    modifiers.insert("enable-fips", &true);
+    let options = glib::VariantDict::new(None);
+    options.insert("no-pull-base", &true);
+    options.insert("reboot", &opts.reboot);
+    options.insert("lock-finalization", &opts.lock_finalization);
+    let params = Variant::from_tuple(&[modifiers.end(), options.end()]);
+    let reply = &client.get_os_proxy().call_sync(
+        "UpdateDeployment",
+        Some(&params),
+        gio::DBusCallFlags::NONE,
+        -1,
+        gio::NONE_CANCELLABLE,
+    )?;
+    let reply = reply
+        .get::<(String,)>()
+        .ok_or_else(|| anyhow!("Invalid reply"))?;
+    client.transaction_connect_progress_sync(reply.0.as_str())?;
+    if !opts.reboot {
+        let new_deployment = client
+            .get_os_proxy()
+            .cached_property("DefaultDeployment")
+            .ok_or_else(|| anyhow!("Failed to find default-deployment property"))?;
+        if previous_deployment != new_deployment {
+            print_treepkg_diff("/");
+        }
+    }
+    Ok(())
+}
+
+/// The packages FIPS mode requires; those which the base provides are not layered.
+pub(crate) fn fips_packages() -> Vec<String> {
+    FIPS_PACKAGES.iter().map(|s| s.to_string()).collect()
+}
+
+/// The dracut module to add to the initramfs for FIPS mode.
+pub(crate) fn fips_dracut_module() -> String {
+    FIPS_DRACUT_MODULE.to_string()
+}
+
+/// The UUID of the filesystem mounted on `/boot`, if it's a separate one.
+fn boot_uuid() -> Result<Option<String>> {
+    let out = Command::new("findmnt")
+        .args(["--noheadings", "--output", "UUID", "--mountpoint", "/boot"])
+        .output()
+        .context("Running findmnt")?;
+    // findmnt fails if /boot is not a mount point
+    if !out.status.success() {
+        return Ok(None);
+    }
+    let uuid = String::from_utf8(out.stdout)?.trim().to_string();
+    if uuid.is_empty() {
+        bail!("Failed to find the UUID of the /boot filesystem");
+    }
+    Ok(Some(uuid))
+}
+
+/// The kernel arguments to append to `kargs` to enable FIPS mode.
+fn kargs_to_append(kargs: &str, boot_uuid: Option<&str>) -> Result<Vec<String>> {
+    let args: Vec<&str> = kargs.split_whitespace().collect();
+    let mut r = Vec::new();
+    match args.iter().rev().find(|a| a.starts_with("fips=")) {
+        Some(&"fips=1") => {}
+        Some(arg) => bail!("FIPS mode is explicitly disabled with {}", arg),
+        None => r.push("fips=1".to_string()),
+    }
+    // The fips dracut module checks the HMAC of the kernel on /boot, which it
+    // needs to mount if it's a separate filesystem.
+    if let Some(uuid) = boot_uuid {
+        if !args.iter().any(|a| a.starts_with("boot=")) {
+            r.push(format!("boot=UUID={}", uuid));
+        }
+    }
+    Ok(r)
+}
+
+/// The kernel arguments to append to the current ones, `kargs`, to enable FIPS mode.
+pub(crate) fn fips_kargs(kargs: &str) -> CxxResult<Vec<String>> {
+    Ok(kargs_to_append(kargs, boot_uuid()?.as_deref())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kargs_to_append() -> Result<()> {
+        let kargs = "root=UUID=1 rw quiet";
+        assert_eq!(
+            kargs_to_append(kargs, Some("2"))?,
+            ["fips=1", "boot=UUID=2"]
+        );
+        assert_eq!(kargs_to_append(kargs, None)?, ["fips=1"]);
+        assert!(kargs_to_append("root=UUID=1 fips=1 boot=/dev/vda2", Some("2"))?.is_empty());
+        assert!(kargs_to_append("root=UUID=1 fips=0", None).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,213 @@
+//! Coordinate automatic reboots across a fleet of machines through a lock
+//! server implementing the FleetLock protocol, like the one Zincati uses.
+//! If `FleetLockURL` is set in rpm-ostreed.conf, the daemon takes a slot of
+//! the reboot semaphore of its group before rebooting into an automatic
+//! update, and `rpm-ostree-fleet-lock-release.service` gives it back once the
+//! machine has booted.
+//!
+//! See https://coreos.github.io/zincati/development/fleetlock/protocol/
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::cxxrsutil::*;
+use anyhow::{anyhow, bail, Context, Result};
+use reqwest::header::CONTENT_TYPE;
+use serde_derive::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+
+/// Records the lock the slot was taken from, so it's released after the reboot
+/// even if the configuration changed meanwhile.
+const STATE_PATH: &str = "/var/lib/rpm-ostree/fleet-lock.json";
+/// The node ID sent to the server is derived from the machine ID with this
+/// application ID, so that the machine ID itself isn't disclosed.
+const APP_ID: &str = "7d2b6c1e9f3a4e58b0c4a1d6e2f8b935";
+const PROTOCOL_HEADER: &str = "fleet-lock-protocol";
+const REQUEST_TIMEOUT_SECS: u64 = 30;
+/// How long to wait between attempts to take a slot.
+const RETRY_SECS: u64 = 60;
+/// How long to keep trying to take a slot before giving up on rebooting.
+const MAX_WAIT_SECS: u64 = 30 * 60;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+struct FleetLock {
+    url: String,
+    group: String,
+}
+
+#[derive(Serialize)]
+struct ClientParams<'a> {
+    id: &'a str,
+    group: &'a str,
+}
+
+#[derive(Serialize)]
+struct LockRequest<'a> {
+    client_params: ClientParams<'a>,
+}
+
+/// The body of error responses.
+#[derive(Deserialize)]
+struct LockError {
+    kind: String,
+    value: String,
+}
+
+fn node_id() -> Result<String> {
+    crate::utils::machine_app_specific_id(APP_ID)
+}
+
+impl FleetLock {
+    fn new(url: &str, group: &str) -> Result<Self> {
+        let parsed = reqwest::Url::parse(url).with_context(|| format!("Invalid URL: {}", url))?;
+        if !matches!(parsed.scheme(), "http" | "https") {
+            bail!("Unsupported URL scheme: {}", url);
+        }
+        if group.is_empty()
+            || !group
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-')
+        {
+            bail!("Invalid group: {}", group);
+        }
+        Ok(Self {
+            url: url.trim_end_matches('/').to_string(),
+            group: group.to_string(),
+        })
+    }
+
+    fn endpoint(&self, name: &str) -> String {
+        format!("{}/v1/{}", self.url, name)
+    }
+
+    /// Send a request to the `name` endpoint on behalf of the node `id`.
+    async fn request(&self, name: &str, id: &str) -> Result<()> {
+        let body = serde_json::to_vec(&LockRequest {
+            client_params: ClientParams {
+                id,
+                group: &self.group,
+            },
+        })?;
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS))
+            .build()?;
+        let resp = client
+            .post(self.endpoint(name))
+            .header(PROTOCOL_HEADER, "true")
+            .header(CONTENT_TYPE, "application/json")
+            .body(body)
+            .send()
+            .await?;
+        let status = resp.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        let text = resp.text().await.unwrap_or_default();
+        match serde_json::from_str::<LockError>(&text) {
+            Ok(e) => Err(anyhow!("{} ({}): {}", status, e.kind, e.value)),
+            Err(_) => Err(anyhow!("{}", status)),
+        }
+    }
+
+    fn load() -> Result<Option<Self>> {
+        match std::fs::read_to_string(STATE_PATH) {
+            Ok(s) => Ok(Some(
+                serde_json::from_str(&s).with_context(|| format!("Parsing {}", STATE_PATH))?,
+            )),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Reading {}", STATE_PATH)),
+        }
+    }
+
+    fn store(&self) -> Result<()> {
+        let buf = serde_json::to_vec(self)?;
+        std::fs::write(STATE_PATH, buf).with_context(|| format!("Writing {}", STATE_PATH))
+    }
+}
+
+/// Check the `FleetLockURL` and `FleetLockGroup` settings.
+pub(crate) fn fleet_lock_validate(url: &str, group: &str) -> CxxResult<()> {
+    FleetLock::new(url, group)?;
+    Ok(())
+}
+
+/// Take a reboot slot from the lock server at `url` for `group`, retrying
+/// while the semaphore is full; fails if it can't be taken in time, or if
+/// `cancellable` is cancelled meanwhile.
+pub(crate) fn fleet_lock_pre_reboot(
+    url: &str,
+    group: &str,
+    cancellable: &crate::FFIGCancellable,
+) -> CxxResult<()> {
+    let cancellable = cancellable.glib_reborrow();
+    let lock = FleetLock::new(url, group)?;
+    let id = node_id()?;
+    // Recorded first: if the response gets lost the slot may still be ours,
+    // and releasing one we don't hold is harmless.
+    lock.store()?;
+    let start = Instant::now();
+    loop {
+        let e = match Handle::current().block_on(lock.request("pre-reboot", &id)) {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+        if start.elapsed() >= Duration::from_secs(MAX_WAIT_SECS) {
+            return Err(e.context("Taking reboot slot").into());
+        }
+        crate::ffi::output_message(&format!(
+            "Failed to take reboot slot: {:#}; retrying in {}s",
+            e, RETRY_SECS
+        ));
+        // Sleep in short steps, so that cancelling the transaction isn't
+        // held up until the next attempt.
+        for _ in 0..RETRY_SECS {
+            cancellable.set_error_if_cancelled()?;
+            std::thread::sleep(Duration::from_secs(1));
+        }
+    }
+}
+
+/// Main entrypoint, run once per boot: release the reboot slot, if one was
+/// taken before rebooting.
+pub fn entrypoint(_args: &[&str]) -> Result<()> {
+    let lock = match FleetLock::load()? {
+        Some(lock) => lock,
+        None => return Ok(()),
+    };
+    let id = node_id()?;
+    Handle::current()
+        .block_on(lock.request("steady-state", &id))
+        .with_context(|| format!("Releasing reboot slot at {}", lock.url))?;
+    std::fs::remove_file(STATE_PATH).with_context(|| format!("Removing {}", STATE_PATH))?;
+    println!(
+        "Released reboot slot at {} (group {})",
+        lock.url, lock.group
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new() -> Result<()> {
+        let lock = FleetLock::new("https://fleetlock.example.com:8443/", "workers")?;
+        assert_eq!(
+            lock.endpoint("pre-reboot"),
+            "https://fleetlock.example.com:8443/v1/pre-reboot"
+        );
+        assert!(FleetLock::new("fleetlock.example.com", "default").is_err());
+        assert!(FleetLock::new("file:///tmp/lock", "default").is_err());
+        assert!(FleetLock::new("http://fleetlock", "").is_err());
+        assert!(FleetLock::new("http://fleetlock", "my group").is_err());
+        let body = serde_json::to_string(&LockRequest {
+            client_params: ClientParams {
+                id: "abc",
+                group: "default",
+            },
+        })?;
+        assert_eq!(body, r#"{"client_params":{"id":"abc","group":"default"}}"#);
+        Ok(())
+    }
+}
@@ -0,0 +1,347 @@
+//! Enable and verify fs-verity on the file objects of ostree commits, so that
+//! the kernel checks the integrity of their content as it is read.  On the
+//! daemon side, ostree enables it itself as objects are written.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::cxxrsutil::*;
+use anyhow::{anyhow, bail, Context, Result};
+use clap::Parser;
+use openssl::sha::{sha256, Sha256};
+use ostree_ext::{gio, ostree};
+use rayon::prelude::*;
+use std::io::Read;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// `_IOW('f', 133, struct fsverity_enable_arg)`
+const FS_IOC_ENABLE_VERITY: libc::c_ulong = 0x40806685;
+/// `_IOWR('f', 134, struct fsverity_digest)`
+const FS_IOC_MEASURE_VERITY: libc::c_ulong = 0xc0046686;
+const FS_VERITY_HASH_ALG_SHA256: u32 = 1;
+const FS_VERITY_BLOCK_SIZE: u32 = 4096;
+const SHA256_DIGEST_SIZE: usize = 32;
+/// The ostree repository configuration making it enable fs-verity on the
+/// objects it writes.
+const OSTREE_FSVERITY_GROUP: &str = "ex-fsverity";
+const OSTREE_FSVERITY_REQUIRED: &str = "required";
+
+/// `struct fsverity_enable_arg` from `linux/fsverity.h`.
+#[repr(C)]
+#[derive(Default)]
+struct FsverityEnableArg {
+    version: u32,
+    hash_algorithm: u32,
+    block_size: u32,
+    salt_size: u32,
+    salt_ptr: u64,
+    sig_size: u32,
+    reserved1: u32,
+    sig_ptr: u64,
+    reserved2: [u64; 11],
+}
+
+/// `struct fsverity_digest` from `linux/fsverity.h`, for SHA-256.
+#[repr(C)]
+#[derive(Default)]
+struct FsverityDigest {
+    digest_algorithm: u16,
+    digest_size: u16,
+    digest: [u8; SHA256_DIGEST_SIZE],
+}
+
+#[derive(Debug, Parser)]
+#[clap(name = "rpm-ostree ex fsverity")]
+#[clap(rename_all = "kebab-case")]
+enum Opt {
+    /// Enable fs-verity on the objects of a commit
+    Enable(CommitOpts),
+    /// Check that fs-verity is enabled on all objects of a commit, and that
+    /// its digests match their content
+    Verify(CommitOpts),
+}
+
+#[derive(Debug, Parser)]
+struct CommitOpts {
+    /// Commit to operate on; defaults to the one of the booted deployment
+    commit: Option<String>,
+}
+
+pub(crate) fn fsverity_entrypoint(args: &Vec<String>) -> Result<()> {
+    let opt = Opt::parse_from(args.iter());
+    if matches!(opt, Opt::Enable(_)) {
+        crate::ffi::client_require_root()?;
+    }
+    let sysroot = &ostree::Sysroot::new_default();
+    sysroot.load(gio::NONE_CANCELLABLE)?;
+    let repo = &sysroot.repo().unwrap();
+    let commit = |opts: &CommitOpts| -> Result<String> {
+        match opts.commit.as_deref() {
+            Some(rev) => Ok(repo.require_rev(rev)?.to_string()),
+            None => Ok(sysroot.require_booted_deployment()?.csum().to_string()),
+        }
+    };
+    match opt {
+        Opt::Enable(ref opts) => {
+            let commit = commit(opts)?;
+            let n = enable_commit(repo, &commit)?;
+            println!("Enabled fs-verity on {} objects of {}", n, commit);
+        }
+        Opt::Verify(ref opts) => {
+            let commit = commit(opts)?;
+            let failed = verify_commit(repo, &commit)?;
+            if let Some((path, e)) = failed.first() {
+                bail!(
+                    "fs-verity verification failed for {} objects of {}, e.g. {}: {}",
+                    failed.len(),
+                    commit,
+                    path,
+                    e
+                );
+            }
+            println!(
+                "fs-verity is enabled and verified on all objects of {}",
+                commit
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Open the loose file objects of the commit `rev`; symbolic links are skipped,
+/// as fs-verity only applies to regular files.
+fn commit_objects(repo: &ostree::Repo, rev: &str) -> Result<(cap_std::fs::Dir, Vec<String>)> {
+    if repo.mode() == ostree::RepoMode::Archive {
+        bail!("fs-verity requires a bare repository");
+    }
+    let rev = repo.require_rev(rev)?;
+    let reachable = repo.traverse_commit(&rev, 0, gio::NONE_CANCELLABLE)?;
+    let repodir = unsafe { crate::ffiutil::ffi_dirfd(repo.dfd())? };
+    let objects = reachable
+        .into_iter()
+        .filter(|o| o.object_type() == ostree::ObjectType::File)
+        .map(|o| {
+            let checksum = o.checksum();
+            format!("objects/{}/{}.file", &checksum[..2], &checksum[2..])
+        })
+        .filter_map(|path| match repodir.symlink_metadata(&path) {
+            Ok(m) if m.is_file() => Some(Ok(path)),
+            Ok(_) => None,
+            Err(e) => Some(Err(e).with_context(|| format!("Querying {}", path))),
+        })
+        .collect::<Result<_>>()?;
+    Ok((repodir, objects))
+}
+
+/// Enable fs-verity on `f`, returning `false` if it already was.
+fn enable(f: &std::fs::File) -> Result<bool> {
+    let arg = FsverityEnableArg {
+        version: 1,
+        hash_algorithm: FS_VERITY_HASH_ALG_SHA256,
+        block_size: FS_VERITY_BLOCK_SIZE,
+        ..Default::default()
+    };
+    if unsafe { libc::ioctl(f.as_raw_fd(), FS_IOC_ENABLE_VERITY, &arg) } == 0 {
+        return Ok(true);
+    }
+    let e = std::io::Error::last_os_error();
+    match e.raw_os_error() {
+        Some(libc::EEXIST) => Ok(false),
+        Some(libc::ENOTTY) | Some(libc::EOPNOTSUPP) => {
+            bail!("fs-verity is not supported or not enabled on this filesystem")
+        }
+        _ => Err(e.into()),
+    }
+}
+
+/// The fs-verity digest of `f` as measured by the kernel, or `None` if fs-verity
+/// is not enabled on it.
+fn measure(f: &std::fs::File) -> Result<Option<[u8; SHA256_DIGEST_SIZE]>> {
+    let mut digest = FsverityDigest {
+        digest_size: SHA256_DIGEST_SIZE as u16,
+        ..Default::default()
+    };
+    if unsafe { libc::ioctl(f.as_raw_fd(), FS_IOC_MEASURE_VERITY, &mut digest) } < 0 {
+        let e = std::io::Error::last_os_error();
+        return match e.raw_os_error() {
+            Some(libc::ENODATA) => Ok(None),
+            _ => Err(e.into()),
+        };
+    }
+    if digest.digest_algorithm as u32 != FS_VERITY_HASH_ALG_SHA256 {
+        bail!("Unexpected digest algorithm {}", digest.digest_algorithm);
+    }
+    Ok(Some(digest.digest))
+}
+
+/// Compute the fs-verity digest of the content read from `r`, with SHA-256, 4K
+/// blocks and no salt, as in `Documentation/filesystems/fsverity.rst`.
+fn compute_digest(mut r: impl Read) -> Result<[u8; SHA256_DIGEST_SIZE]> {
+    let block_size = FS_VERITY_BLOCK_SIZE as usize;
+    // Hash the data blocks, zero-padding the last one
+    let mut hashes = Vec::new();
+    let mut size = 0u64;
+    let mut block = vec![0u8; block_size];
+    loop {
+        let mut n = 0;
+        while n < block_size {
+            match r.read(&mut block[n..])? {
+                0 => break,
+                m => n += m,
+            }
+        }
+        if n == 0 {
+            break;
+        }
+        size += n as u64;
+        block[n..].fill(0);
+        hashes.push(sha256(&block));
+        if n < block_size {
+            break;
+        }
+    }
+    // Then the levels of the Merkle tree, up to a single block
+    let root = match hashes.len() {
+        0 => [0u8; SHA256_DIGEST_SIZE],
+        1 => hashes[0],
+        _ => loop {
+            let level: Vec<_> = hashes
+                .chunks(block_size / SHA256_DIGEST_SIZE)
+                .map(|chunk| {
+                    let mut h = Sha256::new();
+                    chunk.iter().for_each(|c| h.update(c));
+                    h.update(&vec![0u8; block_size - chunk.len() * SHA256_DIGEST_SIZE]);
+                    h.finish()
+                })
+                .collect();
+            if level.len() == 1 {
+                break level[0];
+            }
+            hashes = level;
+        },
+    };
+    // The digest is that of `struct fsverity_descriptor`
+    let mut descriptor = [0u8; 256];
+    descriptor[0] = 1;
+    descriptor[1] = FS_VERITY_HASH_ALG_SHA256 as u8;
+    descriptor[2] = FS_VERITY_BLOCK_SIZE.trailing_zeros() as u8;
+    descriptor[8..16].copy_from_slice(&size.to_le_bytes());
+    descriptor[16..16 + SHA256_DIGEST_SIZE].copy_from_slice(&root);
+    Ok(sha256(&descriptor))
+}
+
+/// Check that fs-verity is enabled on `f`, and that the digest measured by the
+/// kernel matches its content.
+fn verify(f: &std::fs::File) -> Result<()> {
+    let measured = measure(f)?.ok_or_else(|| anyhow!("fs-verity is not enabled"))?;
+    let expected = compute_digest(f)?;
+    if measured != expected {
+        bail!(
+            "Expected fs-verity digest {}, found {}",
+            ostree::checksum_from_bytes(&expected),
+            ostree::checksum_from_bytes(&measured)
+        );
+    }
+    Ok(())
+}
+
+/// Enable fs-verity on all file objects of the commit `rev`, returning the number
+/// of objects on which it was not enabled yet.
+fn enable_commit(repo: &ostree::Repo, rev: &str) -> Result<u32> {
+    let (repodir, objects) = commit_objects(repo, rev)?;
+    let n = AtomicU32::new(0);
+    objects.par_iter().try_for_each(|path| -> Result<()> {
+        let f = repodir.open(path)?.into_std();
+        if enable(&f).with_context(|| format!("Enabling fs-verity on {}", path))? {
+            n.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(())
+    })?;
+    Ok(n.into_inner())
+}
+
+/// Return the file objects of the commit `rev` which fail fs-verity
+/// verification, along with the reason.
+fn verify_commit(repo: &ostree::Repo, rev: &str) -> Result<Vec<(String, String)>> {
+    let (repodir, objects) = commit_objects(repo, rev)?;
+    let failed = objects
+        .into_par_iter()
+        .map(|path| -> Result<_> {
+            let f = repodir.open(&path)?.into_std();
+            Ok(verify(&f).err().map(|e| (path, e.to_string())))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(failed.into_iter().flatten().collect())
+}
+
+/// Configure `repo` so that ostree enables fs-verity on the objects it writes;
+/// used when deploying if configured in the daemon.
+pub fn fsverity_require_repo(repo: &crate::FFIOstreeRepo) -> CxxResult<()> {
+    let repo = &repo.glib_reborrow();
+    let config = repo.copy_config();
+    if config
+        .boolean(OSTREE_FSVERITY_GROUP, OSTREE_FSVERITY_REQUIRED)
+        .unwrap_or_default()
+    {
+        return Ok(());
+    }
+    config.set_boolean(OSTREE_FSVERITY_GROUP, OSTREE_FSVERITY_REQUIRED, true);
+    repo.write_config(&config)?;
+    repo.reload_config(gio::NONE_CANCELLABLE)?;
+    Ok(())
+}
+
+/// Enable fs-verity on the objects of a commit; used at the end of composes.
+pub fn fsverity_enable_commit(repo: &crate::FFIOstreeRepo, rev: &str) -> CxxResult<()> {
+    let repo = &repo.glib_reborrow();
+    let n = enable_commit(repo, rev).context("Enabling fs-verity")?;
+    if n > 0 {
+        crate::ffi::output_message(&format!("Enabled fs-verity on {} objects", n));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enable_arg_size() {
+        // Encoded in FS_IOC_ENABLE_VERITY
+        assert_eq!(std::mem::size_of::<FsverityEnableArg>(), 0x80);
+        // Encoded in FS_IOC_MEASURE_VERITY
+        assert_eq!(
+            std::mem::size_of::<FsverityDigest>() - SHA256_DIGEST_SIZE,
+            4
+        );
+    }
+
+    #[test]
+    fn test_compute_digest() -> Result<()> {
+        let descriptor = |size: u64, root: &[u8]| {
+            let mut d = [0u8; 256];
+            d[..3].copy_from_slice(&[1, 1, 12]);
+            d[8..16].copy_from_slice(&size.to_le_bytes());
+            d[16..48].copy_from_slice(root);
+            sha256(&d)
+        };
+        assert_eq!(compute_digest(&b""[..])?, descriptor(0, &[0; 32]));
+        // A single block is its own root
+        let mut block = vec![0u8; 4096];
+        block[..3].copy_from_slice(b"foo");
+        assert_eq!(compute_digest(&b"foo"[..])?, descriptor(3, &sha256(&block)));
+        // Two blocks
+        let data = vec![b'x'; 4097];
+        let mut level = Vec::new();
+        level.extend_from_slice(&sha256(&data[..4096]));
+        let mut last = vec![0u8; 4096];
+        last[0] = b'x';
+        level.extend_from_slice(&sha256(&last));
+        level.resize(4096, 0);
+        assert_eq!(
+            compute_digest(&data[..])?,
+            descriptor(4097, &sha256(&level))
+        );
+        Ok(())
+    }
+}
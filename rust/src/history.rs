@@ -35,11 +35,12 @@
 
 use crate::cxxrsutil::*;
 use crate::ffi::HistoryEntry;
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 use cap_std::fs::{Dir, FileType};
 use cap_std_ext::cap_std;
 use fn_error_context::context;
-use std::collections::VecDeque;
+use ostree_ext::{glib, ostree};
+use std::collections::{BTreeSet, VecDeque};
 use std::ops::Deref;
 use std::path::Path;
 use systemd::journal::JournalRecord;
@@ -55,6 +56,21 @@ static OSTREE_BOOT_MSG: &str = "7170336a73ba4601bad31af888aa0df7";
 static RPMOSTREE_DEPLOY_MSG: &str = "9bddbda177cd44d891b1b561a8a0ce9e";
 
 static RPMOSTREE_HISTORY_DIR: &str = "/var/lib/rpm-ostree/history";
+/// The key of the deployment variant recording why it was automatically
+/// rolled back.
+static BOOT_FAILURE_KEY: &str = "boot-failure";
+
+/// The keys of the deployment variant holding client-side requests (layered
+/// packages, overrides, ...) whose changes are recorded in the history.
+static REQUEST_KEYS: &[&str] = &[
+    "requested-packages",
+    "requested-local-packages",
+    "requested-modules",
+    "requested-modules-enabled",
+    "requested-base-removals",
+    "requested-base-local-replacements",
+    "requested-base-remote-replacements",
+];
 
 /// Context object used to iterate through `HistoryEntry` events.
 // TODO use https://crates.io/crates/derivative to skip journal field
@@ -211,6 +227,45 @@ pub(crate) fn history_prune() -> CxxResult<()> {
     Ok(())
 }
 
+/// Find the `DEPLOYMENT_TIMESTAMP` of the most recent deploy message for the
+/// deployment at `path`; this is the name of its history GVariant.
+fn history_find_deployment_timestamp(path: &str) -> Result<Option<u64>> {
+    let mut journal = journal_open()?;
+    journal.seek(journal::JournalSeek::Tail)?;
+    journal.match_add("MESSAGE_ID", RPMOSTREE_DEPLOY_MSG)?;
+    while let Some(rec) = journal.previous_entry()? {
+        if rec.get("DEPLOYMENT_PATH").map(|p| p.as_str()) == Some(path) {
+            return Ok(map_to_u64(rec.get("DEPLOYMENT_TIMESTAMP")));
+        }
+    }
+    Ok(None)
+}
+
+/// Record `failure` in the history GVariant of `deployment`, so that
+/// `rpm-ostree history` shows why it was rolled back.  Deployments for which
+/// there is no history (e.g. created by a previous version) are skipped.
+pub(crate) fn history_record_boot_failure(
+    sysroot: &ostree::Sysroot,
+    deployment: &ostree::Deployment,
+    failure: &glib::Variant,
+) -> Result<()> {
+    let path = format!("/{}", sysroot.deployment_dirpath(deployment));
+    let timestamp = match history_find_deployment_timestamp(&path)? {
+        Some(ts) => ts,
+        None => return Ok(()),
+    };
+    let fn_ = Path::new(RPMOSTREE_HISTORY_DIR).join(timestamp.to_string());
+    let data = match std::fs::read(&fn_) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("Reading {}", fn_.display())),
+    };
+    let v = glib::Variant::from_bytes::<glib::VariantDict>(&glib::Bytes::from_owned(data));
+    let dict = glib::VariantDict::new(Some(&v));
+    dict.insert_value(BOOT_FAILURE_KEY, failure);
+    std::fs::write(&fn_, dict.end().data()).with_context(|| format!("Writing {}", fn_.display()))
+}
+
 pub(crate) fn history_ctx_new() -> CxxResult<Box<HistoryCtx>> {
     Ok(HistoryCtx::new_boxed()?)
 }
@@ -417,6 +472,75 @@ impl HistoryCtx {
     }
 }
 
+/// The requests added and removed from one deployment to the next.
+#[derive(Debug, PartialEq, Eq)]
+struct RequestsDiff {
+    added: Vec<String>,
+    removed: Vec<String>,
+}
+
+impl RequestsDiff {
+    fn new(old: &BTreeSet<String>, new: &BTreeSet<String>) -> Option<Self> {
+        let added: Vec<_> = new.difference(old).cloned().collect();
+        let removed: Vec<_> = old.difference(new).cloned().collect();
+        if added.is_empty() && removed.is_empty() {
+            return None;
+        }
+        Some(RequestsDiff { added, removed })
+    }
+}
+
+/// The requests under `key` in the deployment variant `dict`.  Remote overrides
+/// are flattened to `pkg (source)`.
+fn deployment_requests(dict: &glib::VariantDict, key: &str) -> Result<BTreeSet<String>> {
+    if key == "requested-base-remote-replacements" {
+        let overrides = dict
+            .lookup::<Vec<(String, Vec<String>)>>(key)
+            .map_err(anyhow::Error::msg)?
+            .unwrap_or_default();
+        return Ok(overrides
+            .into_iter()
+            .flat_map(|(from, pkgs)| pkgs.into_iter().map(move |p| format!("{} ({})", p, from)))
+            .collect());
+    }
+    Ok(dict
+        .lookup::<Vec<String>>(key)
+        .map_err(anyhow::Error::msg)?
+        .unwrap_or_default()
+        .into_iter()
+        .collect())
+}
+
+/// Insert into `dict`, as `requests`, the changes to client-side requests from
+/// the deployment variant `from` to `to`, if any.  For each changed request
+/// key, this holds the `added` and `removed` entries.
+pub(crate) fn history_add_requests_diff(
+    from: &crate::FFIGVariant,
+    to: &crate::FFIGVariant,
+    dict: &crate::FFIGVariantDict,
+) -> CxxResult<()> {
+    let from = glib::VariantDict::new(Some(&from.glib_reborrow()));
+    let to = glib::VariantDict::new(Some(&to.glib_reborrow()));
+    let dict = dict.glib_reborrow();
+    let requests = glib::VariantDict::new(None);
+    let mut changed = false;
+    for &key in REQUEST_KEYS {
+        let old = deployment_requests(&from, key)?;
+        let new = deployment_requests(&to, key)?;
+        if let Some(diff) = RequestsDiff::new(&old, &new) {
+            let d = glib::VariantDict::new(None);
+            d.insert("added", &diff.added);
+            d.insert("removed", &diff.removed);
+            requests.insert_value(key, &d.end());
+            changed = true;
+        }
+    }
+    if changed {
+        dict.insert_value("requests", &requests.end());
+    }
+    Ok(())
+}
+
 /// A minimal mock journal interface so we can unit test various code paths without adding
 /// stuff in the host journal; in fact without needing any system journal access at all.
 #[cfg(test)]
@@ -529,6 +653,20 @@ mod tests {
         }
     }
 
+    #[test]
+    fn requests_diff() {
+        let set = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<BTreeSet<_>>();
+        assert_eq!(RequestsDiff::new(&set(&[]), &set(&[])), None);
+        assert_eq!(RequestsDiff::new(&set(&["htop"]), &set(&["htop"])), None);
+        assert_eq!(
+            RequestsDiff::new(&set(&["htop", "vim"]), &set(&["htop", "strace", "tmux"])),
+            Some(RequestsDiff {
+                added: vec!["strace".into(), "tmux".into()],
+                removed: vec!["vim".into()],
+            })
+        );
+    }
+
     #[test]
     fn basic() {
         let mut ctx = HistoryCtx::new_boxed().unwrap();
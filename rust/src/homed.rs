@@ -0,0 +1,266 @@
+//! Implementation of the treefile `systemd-homed` field: configure the
+//! composed tree so that users managed by
+//! [systemd-homed](https://www.freedesktop.org/software/systemd/man/systemd-homed.service.html)
+//! can log in, i.e. NSS and PAM modules and the units.  Regular users are
+//! also kept out of the nss-altfiles split; see `migrate_passwd_except_root()`.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::cxxrsutil::*;
+use crate::ffiutil;
+use crate::treefile::Treefile;
+use anyhow::{Context, Result};
+use camino::Utf8Path;
+use cap_std::fs::{Dir, Permissions};
+use cap_std_ext::cap_std;
+use cap_std_ext::dirext::CapStdExtDirExt;
+use fn_error_context::context;
+use std::io::Write;
+use std::os::unix::prelude::PermissionsExt;
+
+const NSSWITCH: &str = "usr/etc/nsswitch.conf";
+const PAMDIR: &str = "usr/etc/pam.d";
+/// The PAM stacks used for local and remote logins.
+const PAM_STACKS: &[&str] = &["system-auth", "password-auth"];
+const PAM_MODULE: &str = "pam_systemd_home.so";
+const AUTHSELECT_CONF: &str = "usr/etc/authselect/authselect.conf";
+const AUTHSELECT_FEATURE: &str = "with-systemd-homed";
+const UNITDIR: &str = "usr/lib/systemd/system";
+/// The symlinks `systemctl enable systemd-homed.service` creates, relative to
+/// the unit directory, with the unit they require.
+const UNIT_LINKS: &[(&str, &str)] = &[
+    (
+        "multi-user.target.wants/systemd-homed.service",
+        "systemd-homed.service",
+    ),
+    (
+        "dbus-org.freedesktop.home1.service",
+        "systemd-homed.service",
+    ),
+    (
+        "systemd-homed.service.wants/systemd-homed-activate.service",
+        "systemd-homed-activate.service",
+    ),
+    (
+        "sockets.target.wants/systemd-userdbd.socket",
+        "systemd-userdbd.socket",
+    ),
+];
+
+/// Ensure `systemd` (i.e. nss-systemd, which resolves homed users) is in the
+/// `passwd:` and `group:` entries, after the other sources.
+pub(crate) fn add_nss_systemd(buf: &str) -> String {
+    let mut r = String::with_capacity(buf.len());
+    for line in buf.lines() {
+        r.push_str(line);
+        let is_db = line.starts_with("passwd:") || line.starts_with("group:");
+        if is_db && !line.split_whitespace().skip(1).any(|s| s == "systemd") {
+            r.push_str(" systemd");
+        }
+        r.push('\n');
+    }
+    r
+}
+
+/// Insert `pam_systemd_home.so` before `pam_unix.so` in each of the `auth`,
+/// `account`, `password` and `session` stacks; `None` if it's already there.
+fn add_pam_systemd_home(buf: &str) -> Option<String> {
+    if buf.contains(PAM_MODULE) {
+        return None;
+    }
+    let mut done = Vec::new();
+    let mut r = String::with_capacity(buf.len());
+    for line in buf.lines() {
+        let mut fields = line.split_whitespace();
+        let kind = fields.next().map(|k| k.trim_start_matches('-'));
+        let is_unix = fields.nth(1).map_or(false, |m| m == "pam_unix.so");
+        if let (Some(kind), true) = (kind, is_unix) {
+            if !done.contains(&kind) {
+                let control = if kind == "session" {
+                    "optional"
+                } else {
+                    "sufficient"
+                };
+                r.push_str(&format!("{:<12}{:<14}{}\n", kind, control, PAM_MODULE));
+                done.push(kind);
+            }
+        }
+        r.push_str(line);
+        r.push('\n');
+    }
+    Some(r)
+}
+
+/// The path in the rootfs which the file at `path` ends up at after following
+/// symlinks, e.g. in `/etc/authselect` for files managed by authselect.
+pub(crate) fn resolve_rootfs_link(rootfs: &Dir, path: &str) -> Result<String> {
+    let mut path = Utf8Path::new(path).to_path_buf();
+    // Bound the number of links we follow
+    for _ in 0..8 {
+        match rootfs.symlink_metadata_optional(&path)? {
+            Some(meta) if meta.file_type().is_symlink() => {}
+            _ => break,
+        }
+        let target = rootfs.read_link(&path)?;
+        let target = Utf8Path::from_path(&target)
+            .with_context(|| format!("Invalid link target in {}", path))?;
+        // /etc is still at /usr/etc while composing
+        path = match target.strip_prefix("/") {
+            Ok(abs) if abs.starts_with("etc") => Utf8Path::new("usr").join(abs),
+            Ok(abs) => abs.to_path_buf(),
+            Err(_) => path.parent().unwrap().join(target),
+        };
+    }
+    Ok(path.into_string())
+}
+
+fn replace_contents(rootfs: &Dir, path: &str, contents: &str) -> Result<()> {
+    let mode = rootfs.metadata(path)?.permissions().mode();
+    rootfs
+        .atomic_replace_with(path, |w| -> Result<()> {
+            w.get_mut()
+                .as_file_mut()
+                .set_permissions(Permissions::from_mode(mode))?;
+            w.write_all(contents.as_bytes())?;
+            Ok(())
+        })
+        .with_context(|| format!("Replacing {}", path))
+}
+
+#[context("Configuring NSS for systemd-homed")]
+fn configure_nss(rootfs: &Dir) -> Result<()> {
+    // If it's a symlink, then something else e.g. authselect must own it.
+    match rootfs.symlink_metadata_optional(NSSWITCH)? {
+        Some(meta) if !meta.file_type().is_symlink() => {}
+        _ => return Ok(()),
+    }
+    let buf = rootfs.read_to_string(NSSWITCH)?;
+    let new = add_nss_systemd(&buf);
+    if new != buf {
+        replace_contents(rootfs, NSSWITCH, &new)?;
+    }
+    Ok(())
+}
+
+#[context("Configuring PAM for systemd-homed")]
+fn configure_pam(rootfs: &Dir) -> Result<()> {
+    for name in PAM_STACKS {
+        let path = resolve_rootfs_link(rootfs, &format!("{}/{}", PAMDIR, name))?;
+        if !rootfs.try_exists(&path)? {
+            continue;
+        }
+        let buf = rootfs.read_to_string(&path)?;
+        if let Some(new) = add_pam_systemd_home(&buf) {
+            println!("Adding {} to /{}", PAM_MODULE, path);
+            replace_contents(rootfs, &path, &new)?;
+        }
+    }
+    // Also record the feature, so that regenerating the stacks keeps it.
+    if rootfs.try_exists(AUTHSELECT_CONF)? {
+        let buf = rootfs.read_to_string(AUTHSELECT_CONF)?;
+        if !buf.lines().any(|l| l.trim() == AUTHSELECT_FEATURE) {
+            let mut new = buf;
+            if !new.is_empty() && !new.ends_with('\n') {
+                new.push('\n');
+            }
+            new.push_str(AUTHSELECT_FEATURE);
+            new.push('\n');
+            replace_contents(rootfs, AUTHSELECT_CONF, &new)?;
+        }
+    }
+    Ok(())
+}
+
+#[context("Enabling systemd-homed")]
+fn enable_units(rootfs: &Dir) -> Result<()> {
+    let unitdir = rootfs.open_dir(UNITDIR)?;
+    for (link, unit) in UNIT_LINKS {
+        if !unitdir.try_exists(unit)? || unitdir.symlink_metadata_optional(link)?.is_some() {
+            continue;
+        }
+        let link = Utf8Path::new(link);
+        let target = match link.parent() {
+            Some(p) if !p.as_str().is_empty() => {
+                unitdir.create_dir_all(p)?;
+                format!("../{}", unit)
+            }
+            _ => unit.to_string(),
+        };
+        unitdir.symlink(target, link)?;
+    }
+    Ok(())
+}
+
+/// Configure NSS, PAM and the units for systemd-homed, if enabled in the
+/// treefile.
+pub(crate) fn compose_systemd_homed(rootfs_dfd: i32, treefile: &Treefile) -> CxxResult<()> {
+    if !treefile.get_systemd_homed() {
+        return Ok(());
+    }
+    let rootfs = unsafe { ffiutil::ffi_dirfd(rootfs_dfd)? };
+    configure_nss(&rootfs)?;
+    configure_pam(&rootfs)?;
+    enable_units(&rootfs)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    #[test]
+    fn test_add_nss_systemd() {
+        let orig = indoc! {"
+            # passwd: files
+            passwd:     files altfiles
+            shadow:     files
+            group:      files altfiles systemd
+            hosts:      files dns
+        "};
+        let expected = indoc! {"
+            # passwd: files
+            passwd:     files altfiles systemd
+            shadow:     files
+            group:      files altfiles systemd
+            hosts:      files dns
+        "};
+        assert_eq!(add_nss_systemd(orig), expected);
+        assert_eq!(add_nss_systemd(expected), expected);
+    }
+
+    #[test]
+    fn test_add_pam_systemd_home() {
+        let orig = indoc! {"
+            auth        required      pam_env.so
+            auth        sufficient    pam_unix.so try_first_pass nullok
+            auth        required      pam_deny.so
+
+            account     required      pam_unix.so
+
+            password    sufficient    pam_unix.so yescrypt shadow nullok use_authtok
+
+            -session    optional      pam_systemd.so
+            session     required      pam_unix.so
+        "};
+        let expected = indoc! {"
+            auth        required      pam_env.so
+            auth        sufficient    pam_systemd_home.so
+            auth        sufficient    pam_unix.so try_first_pass nullok
+            auth        required      pam_deny.so
+
+            account     sufficient    pam_systemd_home.so
+            account     required      pam_unix.so
+
+            password    sufficient    pam_systemd_home.so
+            password    sufficient    pam_unix.so yescrypt shadow nullok use_authtok
+
+            -session    optional      pam_systemd.so
+            session     optional      pam_systemd_home.so
+            session     required      pam_unix.so
+        "};
+        let new = add_pam_systemd_home(orig).unwrap();
+        assert_eq!(new, expected);
+        assert!(add_pam_systemd_home(&new).is_none());
+    }
+}
@@ -0,0 +1,232 @@
+//! Drop-ins in `/etc/rpm-ostree/hooks.d` run by the daemon around updates,
+//! e.g. to quiesce applications before an update is staged, or to run smoke
+//! tests on it before rebooting into it.  Each `*.hook` file is a key file
+//! like:
+//!
+//! ```ini
+//! [Hook]
+//! When=pre-stage
+//! Exec=/usr/local/bin/drain-node --timeout 60
+//! TimeoutSec=120
+//! OnFailure=abort
+//! ```
+//!
+//! Hooks of the same point run in the lexical order of their file names.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::cxxrsutil::*;
+use crate::origin::map_keyfile_optional;
+use anyhow::{anyhow, bail, Context, Result};
+use camino::Utf8Path;
+use ostree_ext::glib;
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+const HOOKS_DIR: &str = "/etc/rpm-ostree/hooks.d";
+const HOOK_SUFFIX: &str = ".hook";
+const HOOK_GROUP: &str = "Hook";
+const HOOK_POINTS: &[&str] = &["pre-stage", "post-stage", "pre-finalize"];
+const DEFAULT_TIMEOUT_SECS: u64 = 90;
+
+#[derive(Debug, PartialEq, Eq)]
+enum FailurePolicy {
+    /// Fail the operation
+    Abort,
+    /// Only warn about the failure
+    Ignore,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Hook {
+    name: String,
+    when: String,
+    argv: Vec<String>,
+    timeout: Duration,
+    on_failure: FailurePolicy,
+}
+
+impl Hook {
+    fn parse(name: &str, data: &str) -> Result<Self> {
+        let kf = glib::KeyFile::new();
+        kf.load_from_data(data, glib::KeyFileFlags::NONE)?;
+        let when = kf.string(HOOK_GROUP, "When")?.to_string();
+        if !HOOK_POINTS.contains(&when.as_str()) {
+            bail!("Invalid When: {}", when);
+        }
+        let exec = kf.string(HOOK_GROUP, "Exec")?;
+        let argv = glib::shell_parse_argv(exec.as_str())
+            .with_context(|| format!("Invalid Exec: {}", exec))?
+            .into_iter()
+            .map(|a| {
+                a.into_string()
+                    .map_err(|a| anyhow!("Invalid UTF-8 in Exec: {:?}", a))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if !Utf8Path::new(&argv[0]).is_absolute() {
+            bail!("Invalid Exec: {}: must be an absolute path", exec);
+        }
+        let timeout = map_keyfile_optional(kf.uint64(HOOK_GROUP, "TimeoutSec"))?
+            .unwrap_or(DEFAULT_TIMEOUT_SECS);
+        let on_failure = map_keyfile_optional(kf.string(HOOK_GROUP, "OnFailure"))?;
+        let on_failure = match on_failure.as_deref() {
+            None | Some("abort") => FailurePolicy::Abort,
+            Some("ignore") => FailurePolicy::Ignore,
+            Some(o) => bail!("Invalid OnFailure: {}", o),
+        };
+        Ok(Self {
+            name: name.to_string(),
+            when,
+            argv,
+            timeout: Duration::from_secs(timeout),
+            on_failure,
+        })
+    }
+
+    async fn run(&self, env: &[(&str, &str)]) -> Result<()> {
+        let mut child = tokio::process::Command::new(&self.argv[0])
+            .args(&self.argv[1..])
+            .envs(env.iter().copied())
+            .stdin(std::process::Stdio::null())
+            .kill_on_drop(true)
+            .spawn()?;
+        let status = tokio::time::timeout(self.timeout, child.wait())
+            .await
+            .map_err(|_| anyhow!("Timed out after {}s", self.timeout.as_secs()))??;
+        if !status.success() {
+            bail!("{}", status);
+        }
+        Ok(())
+    }
+}
+
+/// Load the hooks in `dir` which run at `point`, in the order to run them.
+fn load_hooks(dir: &Utf8Path, point: &str) -> Result<Vec<Hook>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Reading {}", dir)),
+    };
+    let mut hooks = Vec::new();
+    for entry in entries {
+        let name = entry?.file_name();
+        let name = match name.to_str() {
+            Some(name) if name.ends_with(HOOK_SUFFIX) => name,
+            _ => continue,
+        };
+        let path = dir.join(name);
+        let data = std::fs::read_to_string(&path).with_context(|| format!("Reading {}", path))?;
+        let hook = Hook::parse(name, &data).with_context(|| format!("Parsing {}", path))?;
+        if hook.when == point {
+            hooks.push(hook);
+        }
+    }
+    hooks.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(hooks)
+}
+
+/// Run the hooks of `point`, passing them `checksum`, the checksum of the
+/// deployment being updated to (empty if not known yet).  Fails on the first
+/// hook with `OnFailure=abort` which fails.
+pub(crate) fn run_update_hooks(
+    point: &str,
+    osname: &str,
+    checksum: &str,
+    automatic: bool,
+) -> CxxResult<()> {
+    let hooks = load_hooks(Utf8Path::new(HOOKS_DIR), point)?;
+    let mut env = vec![
+        ("RPMOSTREE_HOOK", point),
+        ("RPMOSTREE_OSNAME", osname),
+        ("RPMOSTREE_AUTOMATIC", if automatic { "1" } else { "0" }),
+    ];
+    if !checksum.is_empty() {
+        env.push(("RPMOSTREE_CHECKSUM", checksum));
+    }
+    for hook in hooks {
+        crate::ffi::output_message(&format!("Running {} hook {}", point, hook.name));
+        if let Err(e) = Handle::current().block_on(hook.run(&env)) {
+            match hook.on_failure {
+                FailurePolicy::Abort => {
+                    return Err(e.context(format!("{} hook {}", point, hook.name)).into())
+                }
+                FailurePolicy::Ignore => crate::ffi::output_message(&format!(
+                    "Ignoring failure of {} hook {}: {:#}",
+                    point, hook.name, e
+                )),
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        let hook = Hook::parse(
+            "10-drain.hook",
+            "[Hook]\nWhen=pre-stage\nExec=/usr/bin/drain --reason 'system update'\n",
+        )?;
+        assert_eq!(
+            hook,
+            Hook {
+                name: "10-drain.hook".into(),
+                when: "pre-stage".into(),
+                argv: vec![
+                    "/usr/bin/drain".into(),
+                    "--reason".into(),
+                    "system update".into()
+                ],
+                timeout: Duration::from_secs(DEFAULT_TIMEOUT_SECS),
+                on_failure: FailurePolicy::Abort,
+            }
+        );
+        let hook = Hook::parse(
+            "smoke.hook",
+            "[Hook]\nWhen=post-stage\nExec=/usr/bin/smoke\nTimeoutSec=5\nOnFailure=ignore\n",
+        )?;
+        assert_eq!(hook.timeout, Duration::from_secs(5));
+        assert_eq!(hook.on_failure, FailurePolicy::Ignore);
+        for invalid in [
+            "[Hook]\nExec=/usr/bin/smoke\n",
+            "[Hook]\nWhen=post-reboot\nExec=/usr/bin/smoke\n",
+            "[Hook]\nWhen=pre-stage\n",
+            "[Hook]\nWhen=pre-stage\nExec=smoke\n",
+            "[Hook]\nWhen=pre-stage\nExec=/usr/bin/smoke\nOnFailure=retry\n",
+            "[Hook]\nWhen=pre-stage\nExec=/usr/bin/smoke\nTimeoutSec=soon\n",
+        ] {
+            assert!(Hook::parse("invalid.hook", invalid).is_err(), "{}", invalid);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_hooks() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        let dir = Utf8Path::from_path(td.path()).unwrap();
+        assert!(load_hooks(&dir.join("missing"), "pre-stage")?.is_empty());
+        std::fs::write(
+            dir.join("20-b.hook"),
+            "[Hook]\nWhen=pre-stage\nExec=/usr/bin/b\n",
+        )?;
+        std::fs::write(
+            dir.join("10-a.hook"),
+            "[Hook]\nWhen=pre-stage\nExec=/usr/bin/a\n",
+        )?;
+        std::fs::write(
+            dir.join("15-c.hook"),
+            "[Hook]\nWhen=pre-finalize\nExec=/usr/bin/c\n",
+        )?;
+        std::fs::write(dir.join("README"), "not a hook")?;
+        let names: Vec<_> = load_hooks(dir, "pre-stage")?
+            .into_iter()
+            .map(|h| h.name)
+            .collect();
+        assert_eq!(names, ["10-a.hook", "20-b.hook"]);
+        assert_eq!(load_hooks(dir, "pre-finalize")?.len(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,162 @@
+//! Localization of client-facing messages.
+//!
+//! Messages are looked up with gettext(3) in the `rpm-ostree` text domain,
+//! which `early_main()` binds to the catalogs built from `po/`.  Mark
+//! translatable strings with [`tr!`], or [`ntr!`] for those depending on a
+//! count.  Only the client's own output should be translated: messages the
+//! daemon emits, e.g. through `output_message()` in a transaction, would be
+//! translated in the daemon's locale rather than the client's.  Since translations may need to reorder arguments, the templates
+//! are formatted at runtime: `{}` takes the next argument, `{N}` the N-th.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use std::ffi::{CStr, CString};
+use std::fmt::{Display, Write};
+use std::os::raw::{c_char, c_ulong};
+
+/// The gettext domain, `GETTEXT_PACKAGE` on the C++ side.
+const DOMAIN: &[u8] = b"rpm-ostree\0";
+
+extern "C" {
+    fn dgettext(domainname: *const c_char, msgid: *const c_char) -> *mut c_char;
+    fn dngettext(
+        domainname: *const c_char,
+        msgid: *const c_char,
+        msgid_plural: *const c_char,
+        n: c_ulong,
+    ) -> *mut c_char;
+}
+
+/// Convert the result of a gettext lookup, which is either one of the passed
+/// messages or points into the catalog that stays mapped until exit.
+fn lookup(f: impl FnOnce() -> *const c_char) -> String {
+    // SAFETY: see above; either way it's a valid NUL-terminated string.
+    unsafe { CStr::from_ptr(f()) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// The translation of `msgid`, or `msgid` itself if there is none.
+pub(crate) fn gettext(msgid: &str) -> String {
+    let c_msgid = match CString::new(msgid) {
+        Ok(s) => s,
+        Err(_) => return msgid.to_string(),
+    };
+    // SAFETY: both arguments are NUL-terminated strings.
+    lookup(|| unsafe { dgettext(DOMAIN.as_ptr().cast(), c_msgid.as_ptr()) })
+}
+
+/// The translation of `msgid` or `msgid_plural`, whichever the language
+/// uses for `n`.
+pub(crate) fn ngettext(msgid: &str, msgid_plural: &str, n: u64) -> String {
+    let (c_msgid, c_plural) = match (CString::new(msgid), CString::new(msgid_plural)) {
+        (Ok(a), Ok(b)) => (a, b),
+        _ => return if n == 1 { msgid } else { msgid_plural }.to_string(),
+    };
+    let n = c_ulong::try_from(n).unwrap_or(c_ulong::MAX);
+    // SAFETY: all strings are NUL-terminated.
+    lookup(|| unsafe {
+        dngettext(
+            DOMAIN.as_ptr().cast(),
+            c_msgid.as_ptr(),
+            c_plural.as_ptr(),
+            n,
+        )
+    })
+}
+
+/// Substitute `args` for the placeholders in `template`: `{}` is the next
+/// argument, `{N}` the N-th one, and `{{` and `}}` are literal braces.
+/// Placeholders without an argument are left as they are.
+pub(crate) fn format(template: &str, args: &[&dyn Display]) -> String {
+    let mut r = String::with_capacity(template.len());
+    let mut next = 0;
+    let mut rest = template;
+    while let Some(i) = rest.find(['{', '}']) {
+        r.push_str(&rest[..i]);
+        rest = &rest[i..];
+        if rest.starts_with("{{") || rest.starts_with("}}") {
+            r.push_str(&rest[..1]);
+            rest = &rest[2..];
+            continue;
+        }
+        let placeholder = Some(rest)
+            .filter(|s| s.starts_with('{'))
+            .and_then(|s| s[1..].find('}'))
+            .map(|end| &rest[1..end + 1])
+            .filter(|p| p.chars().all(|c| c.is_ascii_digit()));
+        let (arg, len) = match placeholder {
+            Some("") => {
+                next += 1;
+                (args.get(next - 1), 2)
+            }
+            Some(p) => (
+                p.parse::<usize>().ok().and_then(|i| args.get(i)),
+                p.len() + 2,
+            ),
+            None => (None, 1),
+        };
+        match arg {
+            Some(arg) => write!(r, "{}", arg).expect("write to String"),
+            None => r.push_str(&rest[..len]),
+        }
+        rest = &rest[len..];
+    }
+    r.push_str(rest);
+    r
+}
+
+/// Translate a message, then format it with the arguments (see the module
+/// documentation).  Like `_()` in C, the message must be a literal so that
+/// `xgettext` can extract it.
+macro_rules! tr {
+    ($msgid:literal) => {
+        $crate::i18n::gettext($msgid)
+    };
+    ($msgid:literal, $($arg:expr),+ $(,)?) => {
+        $crate::i18n::format(&$crate::i18n::gettext($msgid), &[$(&$arg),+])
+    };
+}
+
+/// Like [`tr!`], for a message whose form depends on the count `n`, which is
+/// not implicitly one of the arguments.
+macro_rules! ntr {
+    ($msgid:literal, $msgid_plural:literal, $n:expr $(, $arg:expr)* $(,)?) => {
+        $crate::i18n::format(
+            &$crate::i18n::ngettext($msgid, $msgid_plural, $n as u64),
+            &[$(&$arg),*],
+        )
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        let cases: &[(&str, &[&dyn Display], &str)] = &[
+            ("no placeholders", &[], "no placeholders"),
+            ("{} of {}", &[&1, &"two"], "1 of two"),
+            ("{1} before {0}", &[&"a", &"b"], "b before a"),
+            ("{0}, {}, {0}", &[&"x", &"y"], "x, x, x"),
+            ("{{literal}} {}", &[&3], "{literal} 3"),
+            ("missing {} {}", &[&1], "missing 1 {}"),
+            ("{5} {name} {", &[&1], "{5} {name} {"),
+        ];
+        for (template, args, expected) in cases {
+            assert_eq!(format(template, args), *expected, "{}", template);
+        }
+    }
+
+    #[test]
+    fn test_untranslated() {
+        // There are no catalogs in the test environment.
+        assert_eq!(
+            tr!("Deployment {} is now pinned", 2),
+            "Deployment 2 is now pinned"
+        );
+        assert_eq!(ntr!("{} package", "{} packages", 1, 1), "1 package");
+        assert_eq!(ntr!("{} package", "{} packages", 3, 3), "3 packages");
+    }
+}
@@ -0,0 +1,97 @@
+//! Whether the system is idle enough to run non-interactive operations, for
+//! `--when-idle`: the daemon refuses to start them while it isn't, and the
+//! client retries later, so they don't compete with the workload.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::cxxrsutil::*;
+use anyhow::{anyhow, Context, Result};
+
+/// The maximum 1-minute load average, per CPU.
+const MAX_LOAD_PER_CPU: f64 = 0.5;
+/// The maximum share of the last 10 seconds in which some task was stalled
+/// on I/O, in percent.
+const MAX_IO_PRESSURE: f64 = 10.0;
+
+/// Parse the 1-minute load average from `/proc/loadavg`.
+fn parse_loadavg(s: &str) -> Result<f64> {
+    let load = s
+        .split_whitespace()
+        .next()
+        .ok_or_else(|| anyhow!("Empty loadavg"))?;
+    Ok(load.parse()?)
+}
+
+/// Parse the 10-second average of the `some` line from `/proc/pressure/io`.
+fn parse_io_pressure(s: &str) -> Result<f64> {
+    let some = s
+        .lines()
+        .find_map(|l| l.strip_prefix("some "))
+        .ok_or_else(|| anyhow!("Missing 'some' in pressure"))?;
+    let avg10 = some
+        .split_whitespace()
+        .find_map(|f| f.strip_prefix("avg10="))
+        .ok_or_else(|| anyhow!("Missing avg10 in pressure"))?;
+    Ok(avg10.parse()?)
+}
+
+/// Describe why the system is busy, if it is.
+fn busy_reason(load: f64, ncpus: usize, io_pressure: Option<f64>) -> Option<String> {
+    let max_load = MAX_LOAD_PER_CPU * ncpus as f64;
+    if load > max_load {
+        return Some(format!("load average {:.2} above {:.2}", load, max_load));
+    }
+    match io_pressure {
+        Some(p) if p > MAX_IO_PRESSURE => Some(format!(
+            "I/O pressure {:.2}% above {:.2}%",
+            p, MAX_IO_PRESSURE
+        )),
+        _ => None,
+    }
+}
+
+/// Return why the system is too busy for non-interactive operations, or an
+/// empty string if it's idle.  I/O pressure is ignored if the kernel doesn't
+/// provide it.
+pub(crate) fn system_busy_reason() -> CxxResult<String> {
+    let loadavg = std::fs::read_to_string("/proc/loadavg").context("Reading loadavg")?;
+    let load = parse_loadavg(&loadavg).context("Parsing loadavg")?;
+    let ncpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+    // Missing without PSI support, and unreadable if booted with psi=0
+    let io_pressure = std::fs::read_to_string("/proc/pressure/io")
+        .ok()
+        .map(|s| parse_io_pressure(&s))
+        .transpose()
+        .context("Parsing I/O pressure")?;
+    Ok(busy_reason(load, ncpus, io_pressure).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        assert_eq!(parse_loadavg("0.52 0.58 0.59 1/1027 283470\n")?, 0.52);
+        assert!(parse_loadavg("").is_err());
+        let pressure = "some avg10=12.50 avg60=3.10 avg300=0.80 total=123456\n\
+                        full avg10=1.00 avg60=0.20 avg300=0.05 total=2345\n";
+        assert_eq!(parse_io_pressure(pressure)?, 12.5);
+        assert!(parse_io_pressure("full avg10=1.00\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_busy_reason() {
+        assert_eq!(busy_reason(1.5, 4, Some(2.0)), None);
+        assert_eq!(busy_reason(0.1, 1, None), None);
+        assert_eq!(
+            busy_reason(2.5, 4, None).unwrap(),
+            "load average 2.50 above 2.00"
+        );
+        assert_eq!(
+            busy_reason(0.1, 4, Some(25.0)).unwrap(),
+            "I/O pressure 25.00% above 10.00%"
+        );
+    }
+}
@@ -456,6 +456,85 @@ fn fix_tmpfiles_path(abs_path: Cow<str>) -> Cow<str> {
     utils::shellsafe_quote(tweaked_path)
 }
 
+/// Maximum number of concurrent imports into a repository on rotational
+/// storage, where more concurrent writes mostly add seeks.
+const ROTATIONAL_MAX_IMPORT_JOBS: u32 = 2;
+
+/// Whether the block device backing the directory `dfd` is rotational.
+fn dfd_is_rotational(dfd: i32) -> Result<bool> {
+    let st = nix::sys::stat::fstat(dfd)?;
+    let dev = format!(
+        "/sys/dev/block/{}:{}",
+        nix::sys::stat::major(st.st_dev),
+        nix::sys::stat::minor(st.st_dev)
+    );
+    // Partitions don't have a queue; the disk they're on does
+    for path in [
+        format!("{}/queue/rotational", dev),
+        format!("{}/../queue/rotational", dev),
+    ] {
+        match std::fs::read_to_string(&path) {
+            Ok(s) => return Ok(s.trim() == "1"),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    // E.g. on btrfs, tmpfs or overlayfs
+    Ok(false)
+}
+
+/// A rough upper bound of the memory used by importing a single package: this
+/// is dominated by the payload decompressor (e.g. xz with a 64 MiB dictionary),
+/// since file contents are streamed into the repository with bounded buffers.
+const IMPORT_JOB_MEMORY: u64 = 128 * 1024 * 1024;
+
+/// Parse the `MemAvailable` entry of `/proc/meminfo`, in bytes.
+fn parse_mem_available(meminfo: &str) -> Option<u64> {
+    meminfo.lines().find_map(|l| {
+        let v = l.strip_prefix("MemAvailable:")?.trim();
+        let kib = v.strip_suffix("kB").unwrap_or(v).trim();
+        kib.parse::<u64>().ok().map(|v| v * 1024)
+    })
+}
+
+fn mem_available() -> Option<u64> {
+    std::fs::read_to_string("/proc/meminfo")
+        .ok()
+        .and_then(|s| parse_mem_available(&s))
+}
+
+fn auto_import_jobs(n_cpus: u32, rotational: bool, mem_available: Option<u64>) -> u32 {
+    let n = if rotational {
+        n_cpus.min(ROTATIONAL_MAX_IMPORT_JOBS)
+    } else {
+        n_cpus
+    };
+    // Don't let concurrent imports push small build machines into swap
+    let n = match mem_available {
+        Some(mem) => n.min((mem / IMPORT_JOB_MEMORY).try_into().unwrap_or(u32::MAX)),
+        None => n,
+    };
+    n.max(1)
+}
+
+/// The number of packages to import concurrently into the repository
+/// `repo_dfd`.  Importing is mostly CPU bound, so by default this is the number
+/// of processors, unless the repository is on rotational storage or there isn't
+/// enough memory available; `configured` overrides that if nonzero.
+pub fn rpm_import_jobs(repo_dfd: i32, configured: u32) -> u32 {
+    if configured > 0 {
+        return configured;
+    }
+    let n_cpus = std::thread::available_parallelism()
+        .map(|n| n.get().try_into().unwrap_or(u32::MAX))
+        .unwrap_or(1);
+    let rotational = dfd_is_rotational(repo_dfd).unwrap_or_else(|e| {
+        tracing::debug!("Failed to query storage of repository: {}", e);
+        false
+    });
+    auto_import_jobs(n_cpus, rotational, mem_available())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -475,6 +554,33 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_import_jobs() {
+        use std::os::unix::io::AsRawFd;
+        assert_eq!(auto_import_jobs(8, false, None), 8);
+        assert_eq!(auto_import_jobs(8, true, None), ROTATIONAL_MAX_IMPORT_JOBS);
+        assert_eq!(auto_import_jobs(1, true, None), 1);
+        assert_eq!(auto_import_jobs(0, false, None), 1);
+        assert_eq!(auto_import_jobs(8, false, Some(4 * IMPORT_JOB_MEMORY)), 4);
+        assert_eq!(auto_import_jobs(8, false, Some(64 * IMPORT_JOB_MEMORY)), 8);
+        assert_eq!(auto_import_jobs(8, false, Some(0)), 1);
+        let td = tempfile::tempdir().unwrap();
+        let d = std::fs::File::open(td.path()).unwrap();
+        assert_eq!(rpm_import_jobs(d.as_raw_fd(), 3), 3);
+        assert!(rpm_import_jobs(d.as_raw_fd(), 0) >= 1);
+    }
+
+    #[test]
+    fn test_parse_mem_available() {
+        let meminfo = indoc::indoc! {"
+            MemTotal:        2030000 kB
+            MemFree:          100000 kB
+            MemAvailable:    1500000 kB
+        "};
+        assert_eq!(parse_mem_available(meminfo), Some(1500000 * 1024));
+        assert_eq!(parse_mem_available("MemTotal:        2030000 kB\n"), None);
+    }
+
     #[test]
     fn test_path_is_compliant() {
         let ostree_cases = &["/", "/usr", "/usr/share", "/bin/foo", "/usr/lib/opt/bar"];
@@ -1,21 +1,70 @@
-//! Generate an "overlay" initramfs image
+//! Generate an "overlay" initramfs image, and track the inputs of
+//! regenerating the initramfs on the client.
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 
 use crate::cxxrsutil::*;
+use crate::ffiutil::ffi_dirfd;
+use crate::treefile::DeriveInitramfs;
 use anyhow::{Context, Result};
 use camino::Utf8Path;
+use cap_std::fs::Dir;
+use cap_std::io_lifetimes::AsFilelike;
 use cap_std_ext::cap_std;
+use cap_std_ext::dirext::CapStdExtDirExt;
 use cap_std_ext::prelude::CapStdExtCommandExt;
+use cap_std_ext::rustix::fs::MetadataExt;
 use fn_error_context::context;
 use ostree_ext::{gio, glib, prelude::*};
-use std::collections::BTreeSet;
+use serde_derive::{Deserialize, Serialize};
 use std::collections::HashSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::io::prelude::*;
 use std::os::unix::io::IntoRawFd;
 use std::path::{Component, Path, PathBuf};
 use std::pin::Pin;
 use std::{fs, io};
 
+/// Written next to the kernel in `/usr/lib/modules/$kver` when the initramfs
+/// is regenerated on the client: the manifest of its inputs.
+const INPUTS_MANIFEST_NAME: &str = "rpmostree-initramfs-inputs.json";
+
+/// The dracut configuration generated from the modules and drivers configured
+/// with `rpm-ostree initramfs`.
+const DRACUT_CONF_PATH: &str = "usr/lib/dracut/dracut.conf.d/50-rpm-ostree.conf";
+
+/// The initramfs generator used unless another one is configured.
+pub(crate) const DEFAULT_GENERATOR: &str = "dracut";
+
+/// The directories of the definitions of the other initramfs generators,
+/// `NAME.conf` keyfiles, by precedence: the first one is read from the host,
+/// the second one from the new root filesystem.
+const GENERATOR_DIRS: [&str; 2] = [
+    "etc/rpm-ostree/initramfs-generators.d",
+    "usr/lib/rpm-ostree/initramfs-generators.d",
+];
+const GENERATOR_GROUP: &str = "initramfs-generator";
+
+/// The generators known without a definition, with their commands.
+const BUILTIN_GENERATORS: &[(&str, &str)] = &[(
+    "mkosi-initrd",
+    "mkosi-initrd --kernel-version \"$KVER\" --output-dir /tmp --output initramfs.img",
+)];
+
+/// The configuration of the host which `dracut` reads, besides the `/etc`
+/// paths given in its arguments.  Other changes in `/etc` are only picked up
+/// when the initramfs is explicitly regenerated.
+const HOST_INPUTS: &[&str] = &[
+    "etc/dracut.conf",
+    "etc/dracut.conf.d",
+    "etc/modprobe.d",
+    "etc/modules-load.d",
+    "etc/crypttab",
+    "etc/fstab",
+    "etc/vconsole.conf",
+    "etc/locale.conf",
+    "etc/cmdline.d",
+];
+
 fn list_files_recurse<P: glib::IsA<gio::Cancellable>>(
     d: &cap_std::fs::Dir,
     path: &str,
@@ -97,7 +146,7 @@ fn generate_initramfs_overlay<P: glib::IsA<gio::Cancellable>>(
     let mut cmd = std::process::Command::new("/bin/bash");
     cmd.args(&[
         "-c",
-        "set -euo pipefail; cpio --create --format newc --quiet --reproducible --null | gzip -1",
+        "set -euo pipefail; cpio --create --format newc --quiet --reproducible --null | gzip -1 -n",
     ]);
     cmd.cwd_dir(root.try_clone()?);
     let mut child = cmd
@@ -134,11 +183,377 @@ fn generate_initramfs_overlay_etc<P: glib::IsA<gio::Cancellable>>(
     generate_initramfs_overlay(root, files, cancellable)
 }
 
+/// Normalize a path to track with `initramfs-etc`; it may be a file or a
+/// directory, whose contents are included recursively.
+fn normalize_etc_path(path: &str) -> Result<String> {
+    let trimmed = path.trim_end_matches('/');
+    let rel = match trimmed.strip_prefix("/etc/") {
+        Some(p) if !p.is_empty() => p,
+        _ => anyhow::bail!("Path outside /etc forbidden: {}", path),
+    };
+    if rel.split('/').any(|c| matches!(c, "" | "." | "..")) {
+        anyhow::bail!("Invalid path {}: must be canonical", path);
+    }
+    Ok(trimmed.to_string())
+}
+
+/// Normalize the paths to track or untrack with `initramfs-etc`.
+pub(crate) fn initramfs_etc_normalize_paths(paths: Vec<String>) -> CxxResult<Vec<String>> {
+    Ok(paths
+        .iter()
+        .map(|p| normalize_etc_path(p))
+        .collect::<Result<_>>()?)
+}
+
 pub(crate) fn get_dracut_random_cpio() -> &'static [u8] {
     // Generated with: fakeroot /bin/sh -c 'cd dracut-urandom && find . -print0 | sort -z | (mknod dev/random c 1 8 && mknod dev/urandom c 1 9 && cpio -o --null -H newc -R 0:0 --reproducible --quiet -D . -O /tmp/dracut-urandom.cpio)'
     include_bytes!("../../src/libpriv/dracut-random.cpio.gz")
 }
 
+fn checksum_update_reader(h: &mut glib::Checksum, r: &mut impl Read) -> Result<()> {
+    let mut buf = vec![0u8; 128 * 1024];
+    loop {
+        let n = r.read(&mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        h.update(&buf[..n]);
+    }
+}
+
+/// The inputs of regenerating the initramfs on the client.  As the initramfs
+/// is reproducible, they determine it, so it is reused while they don't
+/// change.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+struct InputsManifest {
+    kver: String,
+    args: Vec<String>,
+    /// The checksum of the layered packages
+    packages: String,
+    /// The command of the generator, if not dracut
+    #[serde(skip_serializing_if = "Option::is_none")]
+    generator: Option<String>,
+    source_date_epoch: String,
+    /// The input files by absolute path: their mode in octal, followed by
+    /// the SHA-256 of regular files and the target of symlinks, or "missing".
+    files: BTreeMap<String, String>,
+}
+
+/// Add `path` in `d` to the input `files`, recursing into directories.  A
+/// missing path is recorded too.
+fn manifest_add_path(files: &mut BTreeMap<String, String>, d: &Dir, path: &Path) -> Result<()> {
+    let key = format!("/{}", path.display());
+    let meta = match d.symlink_metadata(path) {
+        Ok(m) => m,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            files.insert(key, "missing".to_string());
+            return Ok(());
+        }
+        Err(e) => return Err(e).with_context(|| format!("Querying {}", path.display())),
+    };
+    let mode = meta.mode();
+    if meta.is_dir() {
+        files.insert(key, format!("{:o}", mode));
+        let mut names = d
+            .read_dir(path)?
+            .map(|e| Ok(e?.file_name()))
+            .collect::<Result<Vec<_>>>()?;
+        names.sort();
+        for name in names.iter().filter(|n| *n != INPUTS_MANIFEST_NAME) {
+            manifest_add_path(files, d, &path.join(name))?;
+        }
+    } else if meta.is_symlink() {
+        // Unlike `Dir::read_link()`, this allows absolute targets.
+        let target = cap_primitives::fs::read_link_contents(&d.as_filelike_view(), path)?;
+        files.insert(key, format!("{:o} {}", mode, target.display()));
+    } else if meta.is_file() {
+        let mut h = glib::Checksum::new(glib::ChecksumType::Sha256).unwrap();
+        checksum_update_reader(&mut h, &mut d.open(path)?)?;
+        files.insert(key, format!("{:o} {}", mode, h.string().expect("hash")));
+    } else {
+        files.insert(key, format!("{:o}", mode));
+    }
+    Ok(())
+}
+
+/// The inputs of regenerating the initramfs of the kernel `kver` in `rootfs`:
+/// the arguments, the layered packages, the command of the `generator` if not
+/// dracut, `SOURCE_DATE_EPOCH`, the kernel with its modules and base
+/// initramfs, the dracut modules, and the configuration of the `host`.
+fn inputs_manifest(
+    rootfs: &Dir,
+    host: &Dir,
+    kver: &str,
+    args: &[&str],
+    packages: &str,
+    generator: &str,
+    source_date_epoch: &str,
+) -> Result<InputsManifest> {
+    let mut files = BTreeMap::new();
+    manifest_add_path(&mut files, rootfs, &Path::new("usr/lib/modules").join(kver))?;
+    manifest_add_path(&mut files, rootfs, Path::new("usr/lib/dracut"))?;
+    // Files from /etc given to e.g. `-I` or `--install=`
+    let arg_paths = args
+        .iter()
+        .flat_map(|a| a.split(|c: char| c == '=' || c.is_whitespace()))
+        .filter_map(|w| normalize_etc_path(w).ok())
+        .map(|p| p.trim_start_matches('/').to_string());
+    let host_paths: BTreeSet<String> = HOST_INPUTS
+        .iter()
+        .map(|p| p.to_string())
+        .chain(arg_paths)
+        .collect();
+    for path in host_paths {
+        manifest_add_path(&mut files, host, Path::new(&path))?;
+    }
+    Ok(InputsManifest {
+        kver: kver.to_string(),
+        args: args.iter().map(|s| s.to_string()).collect(),
+        packages: packages.to_string(),
+        generator: Some(generator)
+            .filter(|g| !g.is_empty())
+            .map(|g| g.to_string()),
+        source_date_epoch: source_date_epoch.to_string(),
+        files,
+    })
+}
+
+/// If the initramfs of the kernel `kver` in `deployment` was regenerated from
+/// the inputs `manifest`, open it.
+fn open_reusable_initramfs(
+    deployment: &Dir,
+    kver: &str,
+    manifest: &InputsManifest,
+) -> Result<Option<fs::File>> {
+    let moddir = Path::new("usr/lib/modules").join(kver);
+    let f = match deployment.open_optional(moddir.join(INPUTS_MANIFEST_NAME))? {
+        Some(f) => f,
+        None => return Ok(None),
+    };
+    // A manifest we can't parse, e.g. from a future version, is just not reused.
+    let recorded: InputsManifest = match serde_json::from_reader(io::BufReader::new(f)) {
+        Ok(m) => m,
+        Err(_) => return Ok(None),
+    };
+    if recorded != *manifest {
+        return Ok(None);
+    }
+    Ok(deployment
+        .open_optional(moddir.join("initramfs.img"))?
+        .map(|f| f.into_std()))
+}
+
+/// `SOURCE_DATE_EPOCH` when regenerating the initramfs on the client: the one
+/// of the daemon if set, else 0, like the timestamps of the files checked out
+/// by ostree.
+pub(crate) fn initramfs_source_date_epoch() -> String {
+    crate::normalization::source_date_epoch_raw().unwrap_or_else(|| "0".to_string())
+}
+
+/// The manifest of the inputs of regenerating the initramfs of the kernel
+/// `kver` in the root filesystem `rootfs_dfd` with the arguments `args`, in
+/// JSON; `packages` is the checksum of the layered packages, and `generator`
+/// the command of the generator, empty for dracut.
+pub(crate) fn initramfs_inputs_manifest(
+    rootfs_dfd: i32,
+    kver: &str,
+    args: &Vec<String>,
+    packages: &str,
+    generator: &str,
+) -> CxxResult<String> {
+    let rootfs = unsafe { &ffi_dirfd(rootfs_dfd)? };
+    let host = &Dir::open_ambient_dir("/", cap_std::ambient_authority())?;
+    let args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+    let epoch = initramfs_source_date_epoch();
+    let manifest = inputs_manifest(rootfs, host, kver, &args, packages, generator, &epoch)?;
+    Ok(serde_json::to_string_pretty(&manifest).map_err(anyhow::Error::msg)?)
+}
+
+/// Return a file descriptor for the initramfs of the kernel `kver` in the
+/// deployment `deployment_dfd` if it was regenerated from the inputs
+/// `manifest`, or -1.
+pub(crate) fn initramfs_reusable(
+    deployment_dfd: i32,
+    kver: &str,
+    manifest: &str,
+) -> CxxResult<i32> {
+    let deployment = unsafe { &ffi_dirfd(deployment_dfd)? };
+    let manifest: InputsManifest = serde_json::from_str(manifest).map_err(anyhow::Error::msg)?;
+    Ok(open_reusable_initramfs(deployment, kver, &manifest)?.map_or(-1, |f| f.into_raw_fd()))
+}
+
+/// Record that the initramfs of the kernel `kver` in the root filesystem
+/// `rootfs_dfd` was regenerated from the inputs `manifest`.
+pub(crate) fn initramfs_inputs_record(
+    rootfs_dfd: i32,
+    kver: &str,
+    manifest: &str,
+) -> CxxResult<()> {
+    let rootfs = unsafe { &ffi_dirfd(rootfs_dfd)? };
+    let path = Path::new("usr/lib/modules")
+        .join(kver)
+        .join(INPUTS_MANIFEST_NAME);
+    rootfs.atomic_write(&path, format!("{}\n", manifest))?;
+    Ok(())
+}
+
+/// The checksum of the overlay initramfs of `files`, as recorded by ostree in
+/// the deployments using it; this detects changes to the tracked files.
+#[context("Generating initramfs overlay")]
+pub(crate) fn initramfs_overlay_checksum(
+    files: &Vec<String>,
+    mut cancellable: Pin<&mut crate::FFIGCancellable>,
+) -> CxxResult<String> {
+    let cancellable = &cancellable.gobj_wrap();
+    let files: HashSet<String> = files.iter().cloned().collect();
+    let mut f = generate_initramfs_overlay_etc(&files, Some(cancellable))?;
+    let mut h = glib::Checksum::new(glib::ChecksumType::Sha256).unwrap();
+    checksum_update_reader(&mut h, &mut f)?;
+    Ok(h.string().expect("hash"))
+}
+
+fn validate_dracut_name(kind: &str, name: &str) -> Result<()> {
+    let valid = |c: char| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.');
+    if name.is_empty() || !name.chars().all(valid) {
+        anyhow::bail!("Invalid dracut {} name: {}", kind, name);
+    }
+    Ok(())
+}
+
+/// Add, omit or remove dracut modules and drivers; returns true if `initrd`
+/// changed.  A module is either added or omitted, the last request winning.
+pub(crate) fn dracut_config_update(
+    initrd: &mut DeriveInitramfs,
+    add_modules: &[String],
+    omit_modules: &[String],
+    add_drivers: &[String],
+    remove_modules: &[String],
+    remove_drivers: &[String],
+) -> Result<bool> {
+    for m in add_modules.iter().chain(omit_modules).chain(remove_modules) {
+        validate_dracut_name("module", m)?;
+    }
+    for d in add_drivers.iter().chain(remove_drivers) {
+        validate_dracut_name("driver", d)?;
+    }
+    if let Some(m) = add_modules.iter().find(|m| omit_modules.contains(m)) {
+        anyhow::bail!("Dracut module both added and omitted: {}", m);
+    }
+    let mut added = initrd.add_modules.clone().unwrap_or_default();
+    let mut omitted = initrd.omit_modules.clone().unwrap_or_default();
+    let mut drivers = initrd.add_drivers.clone().unwrap_or_default();
+    for m in remove_modules {
+        if !(added.remove(m) | omitted.remove(m)) {
+            anyhow::bail!("Dracut module not configured: {}", m);
+        }
+    }
+    for d in remove_drivers {
+        if !drivers.remove(d) {
+            anyhow::bail!("Driver not configured: {}", d);
+        }
+    }
+    for m in add_modules {
+        omitted.remove(m);
+        added.insert(m.clone());
+    }
+    for m in omit_modules {
+        added.remove(m);
+        omitted.insert(m.clone());
+    }
+    drivers.extend(add_drivers.iter().cloned());
+
+    let nonempty = |s: BTreeSet<String>| Some(s).filter(|s| !s.is_empty());
+    let mut updated = initrd.clone();
+    updated.add_modules = nonempty(added);
+    updated.omit_modules = nonempty(omitted);
+    updated.add_drivers = nonempty(drivers);
+    let changed = updated != *initrd;
+    *initrd = updated;
+    Ok(changed)
+}
+
+/// Render the dracut configuration for the modules and drivers of `initrd`;
+/// empty if there are none.
+pub(crate) fn dracut_conf_render(initrd: &DeriveInitramfs) -> String {
+    let mut buf = String::new();
+    for (key, values) in [
+        ("add_dracutmodules", &initrd.add_modules),
+        ("omit_dracutmodules", &initrd.omit_modules),
+        ("add_drivers", &initrd.add_drivers),
+    ] {
+        if let Some(values) = values.as_ref().filter(|v| !v.is_empty()) {
+            let values: Vec<&str> = values.iter().map(|s| s.as_str()).collect();
+            buf.push_str(&format!("{}+=\" {} \"\n", key, values.join(" ")));
+        }
+    }
+    if !buf.is_empty() {
+        buf.insert_str(
+            0,
+            "# Generated by rpm-ostree from `rpm-ostree initramfs`; do not edit\n",
+        );
+    }
+    buf
+}
+
+/// Write the dracut configuration `conf` rendered by `dracut_conf_render()`
+/// into the root filesystem `rootfs_dfd`, if not empty.
+pub(crate) fn initramfs_dracut_conf_write(rootfs_dfd: i32, conf: &str) -> CxxResult<()> {
+    if conf.is_empty() {
+        return Ok(());
+    }
+    let rootfs = unsafe { &ffi_dirfd(rootfs_dfd)? };
+    let path = Path::new(DRACUT_CONF_PATH);
+    // Unwrap safety: the path is constant, with a parent.
+    rootfs.create_dir_all(path.parent().unwrap())?;
+    rootfs.atomic_write(path, conf)?;
+    Ok(())
+}
+
+pub(crate) fn validate_generator_name(name: &str) -> Result<()> {
+    let valid = |c: char| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.');
+    if name.is_empty() || name.starts_with('.') || !name.chars().all(valid) {
+        anyhow::bail!("Invalid initramfs generator name: {}", name);
+    }
+    Ok(())
+}
+
+/// Find the command of the initramfs generator `name`, defined in the `host`
+/// or the new `rootfs`, or built in.
+fn generator_command(host: &Dir, rootfs: &Dir, name: &str) -> Result<String> {
+    validate_generator_name(name)?;
+    let filename = format!("{}.conf", name);
+    for (d, dir) in [host, rootfs].into_iter().zip(GENERATOR_DIRS) {
+        let path = Path::new(dir).join(&filename);
+        let mut f = match d.open_optional(&path)? {
+            Some(f) => f,
+            None => continue,
+        };
+        let mut buf = String::new();
+        f.read_to_string(&mut buf)?;
+        let kf = glib::KeyFile::new();
+        let command = kf
+            .load_from_data(&buf, glib::KeyFileFlags::NONE)
+            .and_then(|_| kf.string(GENERATOR_GROUP, "command"))
+            .with_context(|| format!("Parsing /{}", path.display()))?;
+        return Ok(command.to_string());
+    }
+    BUILTIN_GENERATORS
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, command)| command.to_string())
+        .ok_or_else(|| anyhow::anyhow!("Initramfs generator not found: {}", name))
+}
+
+/// The shell command of the initramfs generator `name` for the root
+/// filesystem `rootfs_dfd`.  It is given the initramfs arguments, and writes
+/// the initramfs of the kernel `$KVER` to `$INITRAMFS`.
+pub(crate) fn initramfs_generator_command(rootfs_dfd: i32, name: &str) -> CxxResult<String> {
+    let rootfs = unsafe { &ffi_dirfd(rootfs_dfd)? };
+    let host = &Dir::open_ambient_dir("/", cap_std::ambient_authority())?;
+    Ok(generator_command(host, rootfs, name)?)
+}
+
 /// cxx-rs entrypoint; we can't use generics and need to return a raw integer for fd
 #[context("Generating initramfs overlay")]
 pub(crate) fn initramfs_overlay_generate(
@@ -172,4 +587,252 @@ mod test {
         let _ = tmpd.metadata("initramfs").context("stat")?;
         Ok(())
     }
+
+    #[test]
+    fn test_gather_filelist_dir() -> Result<()> {
+        let cancellable = gio::NONE_CANCELLABLE;
+        let tmpd = cap_tempfile::tempdir(cap_std::ambient_authority())?;
+        tmpd.create_dir_all("cmdline.d/sub")?;
+        tmpd.write("cmdline.d/foo.conf", "foo")?;
+        tmpd.write("cmdline.d/sub/bar.conf", "bar")?;
+        tmpd.write("other.conf", "other")?;
+        let mut h = HashSet::new();
+        h.insert("/etc/cmdline.d".to_string());
+        let filelist = gather_filelist(&tmpd, &h, cancellable)?;
+        assert_eq!(
+            filelist.iter().map(|s| s.as_str()).collect::<Vec<_>>(),
+            [
+                "cmdline.d",
+                "cmdline.d/foo.conf",
+                "cmdline.d/sub",
+                "cmdline.d/sub/bar.conf"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_inputs_manifest() -> Result<()> {
+        let rootfs = &cap_tempfile::tempdir(cap_std::ambient_authority())?;
+        let host = &cap_tempfile::tempdir(cap_std::ambient_authority())?;
+        rootfs.create_dir_all("usr/lib/modules/5.8.0/kernel")?;
+        rootfs.write("usr/lib/modules/5.8.0/vmlinuz", "kernel")?;
+        rootfs.write("usr/lib/modules/5.8.0/initramfs.img", "initramfs")?;
+        rootfs.create_dir_all("usr/lib/dracut/modules.d")?;
+        host.create_dir_all("etc/dracut.conf.d")?;
+        host.write("etc/dracut.conf.d/foo.conf", "foo")?;
+        let args = ["--no-hostonly", "-I", "/etc/foo.key"];
+        let manifest = || inputs_manifest(rootfs, host, "5.8.0", &args, "packages", "", "0");
+        let orig = manifest()?;
+        assert_eq!(orig, manifest()?);
+        assert_eq!(orig.files["/etc/foo.key"], "missing");
+        assert!(orig.files["/usr/lib/modules/5.8.0/vmlinuz"]
+            .ends_with(" 6923dd1bc0460082c5d55a831908c24a282860b7f1cd6c2b79cf1bc8857c639c"));
+        // Recording the manifest doesn't change it
+        rootfs.write(
+            Path::new("usr/lib/modules/5.8.0").join(INPUTS_MANIFEST_NAME),
+            serde_json::to_string_pretty(&orig)?,
+        )?;
+        assert_eq!(orig, manifest()?);
+        for other in [
+            inputs_manifest(rootfs, host, "5.8.0", &args, "otherpackages", "", "0")?,
+            inputs_manifest(rootfs, host, "5.8.0", &args[..1], "packages", "", "0")?,
+            inputs_manifest(rootfs, host, "5.8.0", &args, "packages", "mkinitrd", "0")?,
+            inputs_manifest(rootfs, host, "5.8.0", &args, "packages", "", "1")?,
+        ] {
+            assert_ne!(orig, other);
+        }
+        // Files of the host given in the arguments
+        host.write("etc/foo.key", "key")?;
+        let with_key = manifest()?;
+        assert_ne!(orig, with_key);
+        host.write("etc/unrelated.conf", "bar")?;
+        assert_eq!(with_key, manifest()?);
+        host.write("etc/dracut.conf.d/foo.conf", "bar")?;
+        let with_conf = manifest()?;
+        assert_ne!(with_key, with_conf);
+        rootfs.write("usr/lib/modules/5.8.0/kernel/foo.ko", "module")?;
+        assert_ne!(with_conf, manifest()?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_reusable_initramfs() -> Result<()> {
+        let deployment = &cap_tempfile::tempdir(cap_std::ambient_authority())?;
+        let manifest = InputsManifest {
+            kver: "5.8.0".into(),
+            source_date_epoch: "0".into(),
+            ..Default::default()
+        };
+        assert!(open_reusable_initramfs(deployment, "5.8.0", &manifest)?.is_none());
+        deployment.create_dir_all("usr/lib/modules/5.8.0")?;
+        deployment.write("usr/lib/modules/5.8.0/initramfs.img", "initramfs")?;
+        assert!(open_reusable_initramfs(deployment, "5.8.0", &manifest)?.is_none());
+        let path = Path::new("usr/lib/modules/5.8.0").join(INPUTS_MANIFEST_NAME);
+        deployment.write(&path, "not a manifest")?;
+        assert!(open_reusable_initramfs(deployment, "5.8.0", &manifest)?.is_none());
+        deployment.write(&path, serde_json::to_string_pretty(&manifest)?)?;
+        let other = InputsManifest {
+            packages: "packages".into(),
+            ..Default::default()
+        };
+        assert!(open_reusable_initramfs(deployment, "5.8.0", &other)?.is_none());
+        let mut s = String::new();
+        open_reusable_initramfs(deployment, "5.8.0", &manifest)?
+            .unwrap()
+            .read_to_string(&mut s)?;
+        assert_eq!(s, "initramfs");
+        Ok(())
+    }
+
+    #[test]
+    fn test_dracut_config() -> Result<()> {
+        let v = |names: &[&str]| names.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let mut initrd = DeriveInitramfs::default();
+        assert_eq!(dracut_conf_render(&initrd), "");
+        assert!(dracut_config_update(
+            &mut initrd,
+            &v(&["crypt-ssh", "nfs"]),
+            &v(&["plymouth"]),
+            &v(&["nvme"]),
+            &[],
+            &[]
+        )?);
+        assert_eq!(
+            dracut_conf_render(&initrd),
+            "# Generated by rpm-ostree from `rpm-ostree initramfs`; do not edit
+add_dracutmodules+=\" crypt-ssh nfs \"
+omit_dracutmodules+=\" plymouth \"
+add_drivers+=\" nvme \"
+"
+        );
+        // Nothing changes when adding the same again
+        assert!(!dracut_config_update(
+            &mut initrd,
+            &v(&["nfs"]),
+            &[],
+            &v(&["nvme"]),
+            &[],
+            &[]
+        )?);
+        // Omitting an added module replaces it
+        assert!(dracut_config_update(
+            &mut initrd,
+            &[],
+            &v(&["nfs"]),
+            &[],
+            &[],
+            &[]
+        )?);
+        assert_eq!(initrd.add_modules.as_ref().unwrap().len(), 1);
+        assert_eq!(initrd.omit_modules.as_ref().unwrap().len(), 2);
+        assert!(dracut_config_update(
+            &mut initrd,
+            &[],
+            &[],
+            &[],
+            &v(&["crypt-ssh", "nfs", "plymouth"]),
+            &v(&["nvme"])
+        )?);
+        assert_eq!(initrd, DeriveInitramfs::default());
+        assert_eq!(dracut_conf_render(&initrd), "");
+
+        for (add, omit, remove, err) in [
+            (
+                &["foo bar"][..],
+                &[][..],
+                &[][..],
+                "Invalid dracut module name: foo bar",
+            ),
+            (&["\"foo"], &[], &[], "Invalid dracut module name: \"foo"),
+            (&[], &[""], &[], "Invalid dracut module name: "),
+            (
+                &["foo"],
+                &["foo"],
+                &[],
+                "Dracut module both added and omitted: foo",
+            ),
+            (&[], &[], &["foo"], "Dracut module not configured: foo"),
+        ] {
+            let e = dracut_config_update(&mut initrd, &v(add), &v(omit), &[], &v(remove), &[])
+                .unwrap_err();
+            assert_eq!(e.to_string(), err);
+        }
+        let e = dracut_config_update(&mut initrd, &[], &[], &[], &[], &v(&["nvme"])).unwrap_err();
+        assert_eq!(e.to_string(), "Driver not configured: nvme");
+        assert_eq!(initrd, DeriveInitramfs::default());
+        Ok(())
+    }
+
+    #[test]
+    fn test_generator_command() -> Result<()> {
+        let rootfs = &cap_tempfile::tempdir(cap_std::ambient_authority())?;
+        let host = &cap_tempfile::tempdir(cap_std::ambient_authority())?;
+        assert_eq!(
+            generator_command(host, rootfs, "mkinitrd")
+                .unwrap_err()
+                .to_string(),
+            "Initramfs generator not found: mkinitrd"
+        );
+        for name in ["", "../foo", ".foo", "foo bar"] {
+            assert_eq!(
+                generator_command(host, rootfs, name)
+                    .unwrap_err()
+                    .to_string(),
+                format!("Invalid initramfs generator name: {}", name)
+            );
+        }
+        assert!(generator_command(host, rootfs, "mkosi-initrd")?.starts_with("mkosi-initrd "));
+        rootfs.create_dir_all(GENERATOR_DIRS[1])?;
+        rootfs.write(
+            Path::new(GENERATOR_DIRS[1]).join("mkinitrd.conf"),
+            "[initramfs-generator]\ncommand=/usr/bin/mkinitrd $INITRAMFS $KVER\n",
+        )?;
+        assert_eq!(
+            generator_command(host, rootfs, "mkinitrd")?,
+            "/usr/bin/mkinitrd $INITRAMFS $KVER"
+        );
+        // The definitions of the host take precedence
+        host.create_dir_all(GENERATOR_DIRS[0])?;
+        host.write(
+            Path::new(GENERATOR_DIRS[0]).join("mkinitrd.conf"),
+            "[initramfs-generator]\ncommand=/usr/local/bin/mkinitrd\n",
+        )?;
+        assert_eq!(
+            generator_command(host, rootfs, "mkinitrd")?,
+            "/usr/local/bin/mkinitrd"
+        );
+        host.write(
+            Path::new(GENERATOR_DIRS[0]).join("mkosi-initrd.conf"),
+            "[initramfs-generator]\n",
+        )?;
+        assert!(generator_command(host, rootfs, "mkosi-initrd").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_normalize_etc_path() {
+        assert_eq!(
+            normalize_etc_path("/etc/cmdline.d/").unwrap(),
+            "/etc/cmdline.d"
+        );
+        assert_eq!(
+            normalize_etc_path("/etc/crypttab").unwrap(),
+            "/etc/crypttab"
+        );
+        for p in ["/", "/etc", "/etc/", "/var/foo", "etc/foo"] {
+            assert_eq!(
+                normalize_etc_path(p).unwrap_err().to_string(),
+                format!("Path outside /etc forbidden: {}", p)
+            );
+        }
+        for p in [
+            "/etc/foo/../bar",
+            "/etc/./foo",
+            "/etc/foo/./bar",
+            "/etc//foo",
+        ] {
+            assert!(normalize_etc_path(p).is_err(), "{}", p);
+        }
+    }
 }
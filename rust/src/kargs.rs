@@ -0,0 +1,936 @@
+//! Kernel arguments changed with `rpm-ostree kargs` are tracked in the origin,
+//! as the arguments appended and deleted relative to those of the base.  On
+//! every deployment, the arguments provided by the old base are replaced with
+//! those of the new one, and the tracked changes are reapplied on top.  The
+//! base provides them as bootc does, in `/usr/lib/bootc/kargs.d/*.toml`: each
+//! file has a `kargs` array, and optionally a `match-architectures` array
+//! restricting it to some architectures.
+//!
+//! Named kernel argument profiles are defined in `.kargs` files, which contain
+//! whitespace-separated kernel arguments, and `#` comments, in
+//! `/etc/rpm-ostree/kargs-profiles.d/<name>.kargs`, overriding those in
+//! `/usr/lib/rpm-ostree/kargs-profiles.d`, of the deployment being changed.  The enabled profiles are tracked in
+//! the origin along with the arguments they added, so that disabling one
+//! deletes exactly those, even if its definition changed since.
+//!
+//! Global kernel arguments, changed with `rpm-ostree kargs --global`, apply to
+//! all deployments rather than to a new one: they are changed in place in the
+//! boot entries of the existing deployments, and are recorded in
+//! `/var/lib/rpm-ostree/global.kargs`.  New deployments inherit them from the
+//! merge deployment like any other kernel argument.
+//!
+//! This also implements the text format of `rpm-ostree kargs --editor`: one
+//! kernel argument per line, which is validated before being applied.
+//!
+//! Whichever way they are changed, new kernel arguments are validated against
+//! mistakes which likely make the system fail to boot (e.g. `root=` given twice)
+//! before being written.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::cxxrsutil::*;
+use crate::ffi::{DeployKargs, EditedKargs, KargLayer, KargsValidation};
+use crate::treefile::DeriveKargs;
+use anyhow::{anyhow, bail, Context, Result};
+use cap_std::fs::Dir;
+use cap_std_ext::cap_std;
+use cap_std_ext::dirext::CapStdExtDirExt;
+use ostree_ext::prelude::*;
+use ostree_ext::{gio, ostree};
+use serde_derive::Deserialize;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::Path;
+
+const KARGS_D: &str = "usr/lib/bootc/kargs.d";
+const KARGS_D_SUFFIX: &str = ".toml";
+const KARGS_SUFFIX: &str = ".kargs";
+const GLOBAL_KARGS_PATH: &str = "/var/lib/rpm-ostree/global.kargs";
+/// The maximum length of the kernel command line, including the terminating
+/// NUL (`COMMAND_LINE_SIZE` on x86_64 and aarch64).
+const CMDLINE_MAX: usize = 2048;
+/// In order of precedence.
+const PROFILES_DIRS: &[&str] = &[
+    "etc/rpm-ostree/kargs-profiles.d",
+    "usr/lib/rpm-ostree/kargs-profiles.d",
+];
+
+fn split(kargs: &str) -> impl Iterator<Item = &str> {
+    kargs.split_whitespace()
+}
+
+fn key(arg: &str) -> &str {
+    arg.split_once('=').map_or(arg, |(k, _)| k)
+}
+
+/// Remove the first occurrence of `arg` from `kargs`; returns whether it was present.
+fn remove_one(kargs: &mut Vec<String>, arg: &str) -> bool {
+    match kargs.iter().position(|a| a == arg) {
+        Some(i) => {
+            kargs.remove(i);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Split kernel arguments on whitespace outside of double quotes; as in
+/// `key="a value"`.  Returns `None` if the quotes are unbalanced.
+fn split_quoted(kargs: &str) -> Option<Vec<&str>> {
+    let mut args = Vec::new();
+    let mut start = None;
+    let mut quoted = false;
+    for (i, c) in kargs.char_indices() {
+        if c == '"' {
+            quoted = !quoted;
+        }
+        if c.is_whitespace() && !quoted {
+            if let Some(s) = start.take() {
+                args.push(&kargs[s..i]);
+            }
+        } else if start.is_none() {
+            start = Some(i);
+        }
+    }
+    if quoted {
+        return None;
+    }
+    if let Some(s) = start {
+        args.push(&kargs[s..]);
+    }
+    Some(args)
+}
+
+/// A file of `kargs.d`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct KargsConfig {
+    kargs: Vec<String>,
+    match_architectures: Option<Vec<String>>,
+}
+
+/// Parse a file of `kargs.d`, returning its kernel arguments if they apply to
+/// the architecture `arch`.
+fn parse_kargs_config(s: &str, arch: &str) -> Result<Vec<String>> {
+    let config: KargsConfig = toml::from_str(s)?;
+    let applies = config
+        .match_architectures
+        .map_or(true, |archs| archs.iter().any(|a| a == arch));
+    Ok(if applies { config.kargs } else { Vec::new() })
+}
+
+/// Parse a `.kargs` file.
+fn parse_kargs_file(s: &str) -> impl Iterator<Item = &str> {
+    s.lines()
+        .map(|l| l.split_once('#').map_or(l, |(before, _)| before))
+        .flat_map(split)
+}
+
+/// Update the tracked changes `append` and `delete` after the kernel
+/// arguments were changed from `existing` to `new`.
+pub(crate) fn track(append: &mut Vec<String>, delete: &mut Vec<String>, existing: &str, new: &str) {
+    let mut added: Vec<String> = split(new).map(String::from).collect();
+    let mut removed = Vec::new();
+    for arg in split(existing) {
+        if !remove_one(&mut added, arg) {
+            removed.push(arg.to_string());
+        }
+    }
+    for arg in removed {
+        if !remove_one(append, &arg) && !delete.contains(&arg) {
+            delete.push(arg);
+        }
+    }
+    for arg in added {
+        if !remove_one(delete, &arg) && !append.contains(&arg) {
+            append.push(arg);
+        }
+    }
+}
+
+/// Compute the kernel arguments after deploying a base providing `new_base`
+/// over one providing `old_base`, given the `current` ones and the tracked
+/// changes.  Also returns the conflicts of the tracked changes with the
+/// arguments newly provided by the base; the tracked changes win.
+fn change_base(
+    current: &str,
+    old_base: &[String],
+    new_base: &[String],
+    append: &[String],
+    delete: &[String],
+) -> (Vec<String>, Vec<String>) {
+    let mut kargs: Vec<String> = split(current).map(String::from).collect();
+    for arg in old_base.iter().filter(|a| !new_base.contains(a)) {
+        remove_one(&mut kargs, arg);
+    }
+    for arg in new_base {
+        if !kargs.contains(arg) {
+            kargs.push(arg.clone());
+        }
+    }
+    let mut conflicts = Vec::new();
+    for arg in delete {
+        if new_base.contains(arg) && !old_base.contains(arg) {
+            conflicts.push(format!(
+                "{} is provided by the new base, but was deleted",
+                arg
+            ));
+        }
+        kargs.retain(|a| a != arg);
+    }
+    for arg in append {
+        for base_arg in new_base {
+            if base_arg != arg && key(base_arg) == key(arg) && !append.contains(base_arg) {
+                if !old_base.contains(base_arg) {
+                    conflicts.push(format!(
+                        "{} replaces {} provided by the new base",
+                        arg, base_arg
+                    ));
+                }
+                kargs.retain(|a| a != base_arg);
+            }
+        }
+        if !kargs.contains(arg) {
+            kargs.push(arg.clone());
+        }
+    }
+    (kargs, conflicts)
+}
+
+/// Compute the kernel arguments after replacing the tracked changes
+/// `old_append` and `old_delete` of `current` with `append` and `delete`, as
+/// for `rpm-ostree ex apply`.
+pub(crate) fn retrack(
+    current: &str,
+    old_append: &[String],
+    old_delete: &[String],
+    append: &[String],
+    delete: &[String],
+) -> Vec<String> {
+    let mut kargs: Vec<String> = split(current).map(String::from).collect();
+    for arg in old_append {
+        remove_one(&mut kargs, arg);
+    }
+    for arg in old_delete {
+        if !kargs.contains(arg) {
+            kargs.push(arg.clone());
+        }
+    }
+    for arg in delete {
+        kargs.retain(|a| a != arg);
+    }
+    for arg in append {
+        if !kargs.contains(arg) {
+            kargs.push(arg.clone());
+        }
+    }
+    kargs
+}
+
+/// Read the kernel arguments provided by the base commit `rev`.
+fn base_kargs(repo: &ostree::Repo, rev: &str) -> Result<Vec<String>> {
+    let cancellable = gio::NONE_CANCELLABLE;
+    let (root, _) = repo.read_commit(rev, cancellable)?;
+    let dir = root.resolve_relative_path(KARGS_D);
+    if !dir.query_exists(cancellable) {
+        return Ok(Vec::new());
+    }
+    let mut names = Vec::new();
+    let children =
+        dir.enumerate_children("standard::name", gio::FileQueryInfoFlags::NONE, cancellable)?;
+    for info in children {
+        let name = info?.name();
+        if let Some(name) = name.to_str().filter(|n| n.ends_with(KARGS_D_SUFFIX)) {
+            names.push(name.to_string());
+        }
+    }
+    names.sort();
+    let mut kargs = Vec::new();
+    for name in names {
+        let mut buf = String::new();
+        dir.child(&name)
+            .read(cancellable)?
+            .into_read()
+            .read_to_string(&mut buf)
+            .with_context(|| format!("Reading {}/{}", KARGS_D, name))?;
+        let args = parse_kargs_config(&buf, std::env::consts::ARCH)
+            .with_context(|| format!("Parsing {}/{}", KARGS_D, name))?;
+        kargs.extend(args);
+    }
+    Ok(kargs)
+}
+
+/// Compute the kernel arguments of a new deployment of `new_rev` over one of
+/// `old_rev`, given the `current` ones and the tracked changes.
+pub(crate) fn kargs_for_deploy(
+    repo: &crate::ffi::OstreeRepo,
+    current: &str,
+    old_rev: &str,
+    new_rev: &str,
+    append: Vec<String>,
+    delete: Vec<String>,
+) -> CxxResult<DeployKargs> {
+    let repo = &repo.glib_reborrow();
+    let old_base = base_kargs(repo, old_rev).context("Reading kernel arguments of the old base")?;
+    let new_base = base_kargs(repo, new_rev).context("Reading kernel arguments of the new base")?;
+    let (kargs, conflicts) = change_base(current, &old_base, &new_base, &append, &delete);
+    Ok(DeployKargs { kargs, conflicts })
+}
+
+fn validate_profile_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid {
+        bail!("Invalid kernel argument profile name: {}", name);
+    }
+    Ok(())
+}
+
+/// Read the kernel arguments of the profile `name` from `root`.
+fn load_profile(root: &Dir, name: &str) -> Result<Vec<String>> {
+    validate_profile_name(name)?;
+    let filename = format!("{}{}", name, KARGS_SUFFIX);
+    for dir in PROFILES_DIRS {
+        let path = format!("{}/{}", dir, filename);
+        if let Some(mut f) = root.open_optional(&path)? {
+            let mut buf = String::new();
+            f.read_to_string(&mut buf)
+                .with_context(|| format!("Reading /{}", path))?;
+            return Ok(parse_kargs_file(&buf).map(String::from).collect());
+        }
+    }
+    bail!("Kernel argument profile not found: {}", name)
+}
+
+/// Disable the profiles `disable`, deleting the kernel arguments they added,
+/// then enable the profiles `enable`, appending those of their arguments which
+/// are missing.  `profiles` maps the enabled profiles to the arguments they
+/// added.
+fn toggle_profiles(
+    kargs: &str,
+    profiles: &mut BTreeMap<String, Vec<String>>,
+    enable: Vec<(String, Vec<String>)>,
+    disable: &[String],
+) -> Result<Vec<String>> {
+    let mut kargs: Vec<String> = split(kargs).map(String::from).collect();
+    for name in disable {
+        let added = profiles
+            .remove(name)
+            .ok_or_else(|| anyhow!("Kernel argument profile is not enabled: {}", name))?;
+        for arg in added {
+            remove_one(&mut kargs, &arg);
+        }
+    }
+    for (name, args) in enable {
+        if profiles.contains_key(&name) {
+            bail!("Kernel argument profile is already enabled: {}", name);
+        }
+        let mut added = Vec::new();
+        for arg in args {
+            if !kargs.contains(&arg) {
+                kargs.push(arg.clone());
+                added.push(arg);
+            }
+        }
+        profiles.insert(name, added);
+    }
+    Ok(kargs)
+}
+
+/// Enable and disable kernel argument profiles, as defined in the deployment
+/// `root`, tracking them in `derive`; returns the new kernel arguments.
+pub(crate) fn apply_profiles(
+    derive: &mut Option<DeriveKargs>,
+    root: &Dir,
+    kargs: &str,
+    enable: &[String],
+    disable: &[String],
+) -> Result<String> {
+    let enable = enable
+        .iter()
+        .map(|name| Ok((name.clone(), load_profile(root, name)?)))
+        .collect::<Result<Vec<_>>>()?;
+    let mut d = derive.take().unwrap_or_default();
+    let mut profiles = d.profiles.take().unwrap_or_default();
+    let r = toggle_profiles(kargs, &mut profiles, enable, disable);
+    d.profiles = Some(profiles).filter(|p| !p.is_empty());
+    *derive = Some(d).filter(|d| d != &DeriveKargs::default());
+    Ok(r?.join(" "))
+}
+
+fn load_global(path: &Path) -> Result<Vec<String>> {
+    match std::fs::read_to_string(path) {
+        Ok(s) => Ok(parse_kargs_file(&s).map(String::from).collect()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e).with_context(|| format!("Reading {}", path.display())),
+    }
+}
+
+fn store_global(path: &Path, kargs: &[String]) -> Result<()> {
+    if kargs.is_empty() {
+        return match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("Removing {}", path.display()))
+            }
+            _ => Ok(()),
+        };
+    }
+    std::fs::write(path, format!("{}\n", kargs.join(" ")))
+        .with_context(|| format!("Writing {}", path.display()))
+}
+
+/// The global kernel arguments, which apply to all deployments.
+pub(crate) fn kargs_global() -> CxxResult<Vec<String>> {
+    Ok(load_global(Path::new(GLOBAL_KARGS_PATH))?)
+}
+
+/// Record the global kernel arguments, once they were applied to all deployments.
+pub(crate) fn kargs_global_store(kargs: &Vec<String>) -> CxxResult<()> {
+    Ok(store_global(Path::new(GLOBAL_KARGS_PATH), kargs)?)
+}
+
+/// Compute the global kernel arguments after deleting `delete` from the `old`
+/// ones, then appending those of `append` which are missing.
+pub(crate) fn kargs_global_change(
+    old: &Vec<String>,
+    append: Vec<String>,
+    delete: Vec<String>,
+) -> CxxResult<Vec<String>> {
+    let mut r = old.clone();
+    for arg in delete {
+        if !remove_one(&mut r, &arg) {
+            return Err(anyhow!("Kernel argument is not global: {}", arg).into());
+        }
+    }
+    for arg in append {
+        if !r.contains(&arg) {
+            r.push(arg);
+        }
+    }
+    Ok(r)
+}
+
+/// Compute the kernel arguments of a deployment when the global ones change
+/// from `old` to `new`: the arguments deleted from them are deleted, and the
+/// missing ones are appended.
+pub(crate) fn kargs_apply_global(kargs: &str, old: &Vec<String>, new: &Vec<String>) -> String {
+    let mut r: Vec<String> = split(kargs).map(String::from).collect();
+    for arg in old.iter().filter(|a| !new.contains(a)) {
+        remove_one(&mut r, arg);
+    }
+    for arg in new {
+        if !r.contains(arg) {
+            r.push(arg.clone());
+        }
+    }
+    r.join(" ")
+}
+
+/// Attribute each of the kernel arguments `kargs` to the layer it comes from;
+/// in order of precedence: `ostree`, `global`, `profile <name>`, `local` (the
+/// changes tracked in the origin), `base` (`kargs.d` of the commit `base`),
+/// and otherwise `install`, for the arguments set when the system was
+/// installed or first booted, which aren't tracked.
+fn layers(
+    kargs: &str,
+    global: &[String],
+    base: &[String],
+    derive: Option<&DeriveKargs>,
+) -> Vec<KargLayer> {
+    let append = derive.and_then(|d| d.append.as_deref()).unwrap_or_default();
+    let profiles = derive.and_then(|d| d.profiles.as_ref());
+    let args = split_quoted(kargs).unwrap_or_else(|| split(kargs).collect());
+    args.into_iter()
+        .map(|arg| {
+            let owned = arg.to_string();
+            let profile = profiles
+                .into_iter()
+                .flatten()
+                .find(|(_, args)| args.contains(&owned));
+            let layer = if key(arg) == "ostree" {
+                "ostree".to_string()
+            } else if global.contains(&owned) {
+                "global".to_string()
+            } else if let Some((name, _)) = profile {
+                format!("profile {}", name)
+            } else if append.contains(&owned) {
+                "local".to_string()
+            } else if base.contains(&owned) {
+                "base".to_string()
+            } else {
+                "install".to_string()
+            };
+            KargLayer { arg: owned, layer }
+        })
+        .collect()
+}
+
+/// Attribute each of the kernel arguments `kargs` of a deployment of the
+/// commit `rev` to the layer it comes from, given its tracked changes `derive`.
+pub(crate) fn kargs_layers(
+    repo: &ostree::Repo,
+    rev: &str,
+    kargs: &str,
+    derive: Option<&DeriveKargs>,
+) -> Result<Vec<KargLayer>> {
+    let global = load_global(Path::new(GLOBAL_KARGS_PATH))?;
+    let base = base_kargs(repo, rev).context("Reading kernel arguments of the base")?;
+    Ok(layers(kargs, &global, &base, derive))
+}
+
+/// Format the kernel arguments `kargs` for `rpm-ostree kargs --editor`, one
+/// per line.
+pub(crate) fn kargs_editor_format(kargs: &str) -> String {
+    let args = split_quoted(kargs).unwrap_or_else(|| split(kargs).collect());
+    args.iter().map(|a| format!("{}\n", a)).collect()
+}
+
+/// Parse the result of `rpm-ostree kargs --editor`, ignoring empty lines and
+/// lines starting with `#`.  Returns the arguments, and warnings.
+fn parse_edited(buf: &str) -> Result<(Vec<&str>, Vec<String>)> {
+    let mut args: Vec<&str> = Vec::new();
+    for (i, line) in buf.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line_args =
+            split_quoted(line).ok_or_else(|| anyhow!("Unbalanced quotes on line {}", i + 1))?;
+        for arg in line_args {
+            if args.contains(&arg) {
+                bail!("Kernel argument {} is given twice, on line {}", arg, i + 1);
+            }
+            args.push(arg);
+        }
+    }
+    if args.is_empty() {
+        bail!("The kernel arguments can not be empty");
+    }
+    let mut warnings = Vec::new();
+    for (i, arg) in args.iter().enumerate() {
+        let k = key(arg);
+        let first = args.iter().position(|a| key(a) == k);
+        if first == Some(i) && args[i + 1..].iter().any(|a| key(a) == k) {
+            warnings.push(format!(
+                "Key {} is given multiple times with different values",
+                k
+            ));
+        }
+    }
+    Ok((args, warnings))
+}
+
+/// Validate the result of `rpm-ostree kargs --editor`.  Unbalanced quotes and
+/// arguments given twice are errors; keys given multiple times with different
+/// values (which is valid, as for `console=`) are warned about.
+pub(crate) fn kargs_editor_parse(buf: &str) -> CxxResult<EditedKargs> {
+    let (args, warnings) = parse_edited(buf)?;
+    Ok(EditedKargs {
+        kargs: args.join(" "),
+        warnings,
+    })
+}
+
+/// The changes from the kernel arguments `old` to `new`, as lines prefixed
+/// with `-` and `+`.
+pub(crate) fn kargs_diff(old: &str, new: &str) -> Vec<String> {
+    let old = split_quoted(old).unwrap_or_else(|| split(old).collect());
+    let mut added = split_quoted(new).unwrap_or_else(|| split(new).collect());
+    let mut r = Vec::new();
+    for arg in old {
+        match added.iter().position(|a| *a == arg) {
+            Some(i) => {
+                added.remove(i);
+            }
+            None => r.push(format!("-{}", arg)),
+        }
+    }
+    r.extend(added.into_iter().map(|a| format!("+{}", a)));
+    r
+}
+
+/// The device of a `console=` argument, e.g. `ttyS0` for `console=ttyS0,115200n8`.
+fn console_device(arg: &str) -> Option<&str> {
+    let value = arg.strip_prefix("console=")?;
+    Some(value.split_once(',').map_or(value, |(dev, _)| dev))
+}
+
+/// The console which `/dev/console` refers to, i.e. the last one given.
+fn primary_console(kargs: &str) -> Option<&str> {
+    split(kargs).filter_map(console_device).last()
+}
+
+/// The problems of the kernel command line `kargs` which likely make the
+/// system fail to boot, or not as intended, keyed by what they are about.
+fn problems(kargs: &str) -> Vec<(String, String)> {
+    let args = split_quoted(kargs).unwrap_or_else(|| split(kargs).collect());
+    let mut r = Vec::new();
+    let roots: Vec<&str> = args.iter().copied().filter(|a| key(a) == "root").collect();
+    if roots.len() > 1 {
+        r.push((
+            "root".to_string(),
+            format!("root= is given multiple times: {}", roots.join(" ")),
+        ));
+    }
+    let mut seen: Vec<&str> = Vec::new();
+    for dev in args.iter().filter_map(|a| console_device(a)) {
+        if seen.contains(&dev) {
+            continue;
+        }
+        seen.push(dev);
+        let mut consoles: Vec<&str> = args
+            .iter()
+            .copied()
+            .filter(|a| console_device(a) == Some(dev))
+            .collect();
+        consoles.dedup();
+        if consoles.len() > 1 {
+            r.push((
+                format!("console {}", dev),
+                format!(
+                    "Console {} is given multiple times with different options: {}",
+                    dev,
+                    consoles.join(" ")
+                ),
+            ));
+        }
+    }
+    if kargs.len() >= CMDLINE_MAX {
+        r.push((
+            "length".to_string(),
+            format!(
+                "The kernel command line is {} bytes long, exceeding the limit of {}",
+                kargs.len(),
+                CMDLINE_MAX - 1
+            ),
+        ));
+    }
+    r
+}
+
+/// Validate the kernel arguments `new`, which replace `old`.  The problems
+/// which `old` doesn't have are errors; the other ones are only warned about,
+/// so that the kernel arguments can still be fixed.  A change of the primary
+/// console (the last `console=` argument) is also warned about.
+pub(crate) fn kargs_validate(old: &str, new: &str) -> KargsValidation {
+    let old_problems = problems(old);
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+    for (what, problem) in problems(new) {
+        if old_problems.iter().any(|(w, _)| w == &what) {
+            warnings.push(problem);
+        } else {
+            errors.push(problem);
+        }
+    }
+    if let (Some(old), Some(new)) = (primary_console(old), primary_console(new)) {
+        if old != new {
+            warnings.push(format!(
+                "The primary console changes from {} to {}",
+                old, new
+            ));
+        }
+    }
+    KargsValidation { errors, warnings }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strs(s: &str) -> Vec<String> {
+        split(s).map(String::from).collect()
+    }
+
+    #[test]
+    fn test_parse_kargs_file() {
+        let kargs: Vec<_> =
+            parse_kargs_file("# Serial console\nconsole=ttyS0,115200n8 # for the cloud\n\nnosmt\n")
+                .collect();
+        assert_eq!(kargs, ["console=ttyS0,115200n8", "nosmt"]);
+    }
+
+    #[test]
+    fn test_parse_kargs_config() {
+        let kargs = parse_kargs_config(r#"kargs = ["console=ttyS0,115200n8", "nosmt"]"#, "x86_64");
+        assert_eq!(kargs.unwrap(), ["console=ttyS0,115200n8", "nosmt"]);
+        let config = indoc::indoc! {r#"
+            kargs = ["nosmt"]
+            match-architectures = ["x86_64", "aarch64"]
+        "#};
+        assert_eq!(parse_kargs_config(config, "aarch64").unwrap(), ["nosmt"]);
+        assert!(parse_kargs_config(config, "s390x").unwrap().is_empty());
+        assert!(parse_kargs_config("kargs = \"nosmt\"", "x86_64").is_err());
+        assert!(parse_kargs_config("kargs = []\nfoo = 1", "x86_64").is_err());
+    }
+
+    #[test]
+    fn test_track() {
+        let (mut append, mut delete) = (Vec::new(), Vec::new());
+        track(
+            &mut append,
+            &mut delete,
+            "root=UUID=1 rw quiet",
+            "root=UUID=1 rw foo=1",
+        );
+        assert_eq!((&append, &delete), (&strs("foo=1"), &strs("quiet")));
+        // Replacing an appended argument
+        track(
+            &mut append,
+            &mut delete,
+            "root=UUID=1 rw foo=1",
+            "root=UUID=1 rw foo=2",
+        );
+        assert_eq!((&append, &delete), (&strs("foo=2"), &strs("quiet")));
+        // Undoing the changes
+        track(
+            &mut append,
+            &mut delete,
+            "root=UUID=1 rw foo=2",
+            "root=UUID=1 rw quiet",
+        );
+        assert!(append.is_empty() && delete.is_empty());
+    }
+
+    #[test]
+    fn test_retrack() {
+        let kargs = retrack(
+            "root=UUID=1 rw foo=1 nosmt",
+            &strs("foo=1 nosmt"),
+            &strs("quiet"),
+            &strs("nosmt bar"),
+            &strs("rw"),
+        );
+        assert_eq!(kargs, strs("root=UUID=1 quiet nosmt bar"));
+        // Same changes
+        let kargs = retrack(
+            "root=UUID=1 foo=1",
+            &strs("foo=1"),
+            &strs(""),
+            &strs("foo=1"),
+            &strs(""),
+        );
+        assert_eq!(kargs, strs("root=UUID=1 foo=1"));
+    }
+
+    #[test]
+    fn test_change_base() {
+        let (kargs, conflicts) = change_base(
+            "root=UUID=1 rw mitigations=off nosmt console=tty0",
+            &strs("mitigations=off nosmt"),
+            &strs("mitigations=auto quiet"),
+            &strs("console=tty0"),
+            &strs(""),
+        );
+        assert_eq!(
+            kargs,
+            strs("root=UUID=1 rw console=tty0 mitigations=auto quiet")
+        );
+        assert!(conflicts.is_empty());
+
+        let (kargs, conflicts) = change_base(
+            "root=UUID=1 rw mitigations=off",
+            &strs(""),
+            &strs("mitigations=auto quiet"),
+            &strs("mitigations=off"),
+            &strs("quiet"),
+        );
+        assert_eq!(kargs, strs("root=UUID=1 rw mitigations=off"));
+        assert_eq!(
+            conflicts,
+            [
+                "quiet is provided by the new base, but was deleted",
+                "mitigations=off replaces mitigations=auto provided by the new base"
+            ]
+        );
+        // The same base, as when upgrading to a commit with the same kargs.d
+        let (kargs, conflicts) = change_base(
+            "root=UUID=1 mitigations=off rw",
+            &strs("mitigations=auto quiet"),
+            &strs("mitigations=auto quiet"),
+            &strs("mitigations=off"),
+            &strs("quiet"),
+        );
+        assert_eq!(kargs, strs("root=UUID=1 mitigations=off rw"));
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn test_split_quoted() {
+        assert_eq!(
+            split_quoted("  root=UUID=1 foo=\"a b\"\tquiet ").unwrap(),
+            ["root=UUID=1", "foo=\"a b\"", "quiet"]
+        );
+        assert!(split_quoted("foo=\"a b").is_none());
+    }
+
+    #[test]
+    fn test_kargs_editor() -> Result<()> {
+        let kargs = "root=UUID=1 rw foo=\"a b\"";
+        let formatted = kargs_editor_format(kargs);
+        assert_eq!(formatted, "root=UUID=1\nrw\nfoo=\"a b\"\n");
+        let edited = kargs_editor_parse(&format!("# Comment\n\n{}  quiet\n", formatted))?;
+        assert_eq!(edited.kargs, "root=UUID=1 rw foo=\"a b\" quiet");
+        assert!(edited.warnings.is_empty());
+
+        let edited = kargs_editor_parse("console=tty0\nconsole=ttyS0\nrw\n")?;
+        assert_eq!(edited.kargs, "console=tty0 console=ttyS0 rw");
+        assert_eq!(
+            edited.warnings,
+            ["Key console is given multiple times with different values"]
+        );
+
+        let e = parse_edited("rw\nfoo=\"a b\n").unwrap_err();
+        assert_eq!(e.to_string(), "Unbalanced quotes on line 2");
+        let e = parse_edited("rw\nquiet\n\nrw\n").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Kernel argument rw is given twice, on line 4"
+        );
+        assert!(parse_edited("# Nothing\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_kargs_diff() {
+        assert_eq!(
+            kargs_diff("root=UUID=1 rw quiet foo=1", "root=UUID=1 rw foo=2 bar"),
+            ["-quiet", "-foo=1", "+foo=2", "+bar"]
+        );
+        assert!(kargs_diff("rw quiet", "quiet rw").is_empty());
+    }
+
+    #[test]
+    fn test_load_profile() -> Result<()> {
+        let td = cap_tempfile::tempdir(cap_std::ambient_authority())?;
+        td.create_dir_all(PROFILES_DIRS[0])?;
+        td.create_dir_all(PROFILES_DIRS[1])?;
+        td.write(
+            format!("{}/debug.kargs", PROFILES_DIRS[1]),
+            "# Verbose boot\nsystemd.log_level=debug rd.debug\n",
+        )?;
+        td.write(format!("{}/fips.kargs", PROFILES_DIRS[1]), "fips=1\n")?;
+        td.write(
+            format!("{}/fips.kargs", PROFILES_DIRS[0]),
+            "fips=1 boot=UUID=1\n",
+        )?;
+        assert_eq!(
+            load_profile(&td, "debug")?,
+            strs("systemd.log_level=debug rd.debug")
+        );
+        assert_eq!(load_profile(&td, "fips")?, strs("fips=1 boot=UUID=1"));
+        assert!(load_profile(&td, "nomodeset").is_err());
+        assert!(load_profile(&td, "../debug").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_toggle_profiles() -> Result<()> {
+        let mut profiles = BTreeMap::new();
+        let kargs = toggle_profiles(
+            "root=UUID=1 rw rd.debug",
+            &mut profiles,
+            vec![("debug".into(), strs("systemd.log_level=debug rd.debug"))],
+            &[],
+        )?;
+        assert_eq!(
+            kargs,
+            strs("root=UUID=1 rw rd.debug systemd.log_level=debug")
+        );
+        assert_eq!(profiles["debug"], strs("systemd.log_level=debug"));
+        assert!(toggle_profiles("", &mut profiles, vec![("debug".into(), vec![])], &[]).is_err());
+        assert!(toggle_profiles("", &mut profiles, vec![], &strs("fips")).is_err());
+        // Arguments which were already present are kept
+        let kargs = toggle_profiles(&kargs.join(" "), &mut profiles, vec![], &strs("debug"))?;
+        assert_eq!(kargs, strs("root=UUID=1 rw rd.debug"));
+        assert!(profiles.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_global() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        let path = &td.path().join("global.kargs");
+        assert!(load_global(path)?.is_empty());
+        let old = kargs_global_change(&vec![], strs("quiet mitigations=off"), vec![])?;
+        store_global(path, &old)?;
+        assert_eq!(load_global(path)?, old);
+        let new = kargs_global_change(&old, strs("nosmt quiet"), strs("mitigations=off"))?;
+        assert_eq!(new, strs("quiet nosmt"));
+        assert!(kargs_global_change(&new, vec![], strs("rw")).is_err());
+        assert_eq!(
+            kargs_apply_global("root=UUID=1 mitigations=off rw quiet", &old, &new),
+            "root=UUID=1 rw quiet nosmt"
+        );
+        store_global(path, &[])?;
+        assert!(!path.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_layers() {
+        let mut derive = DeriveKargs::default();
+        derive.append = Some(strs("console=ttyS0 systemd.log_level=debug"));
+        derive.profiles = Some(BTreeMap::from([(
+            "debug".to_string(),
+            strs("systemd.log_level=debug"),
+        )]));
+        let layers = layers(
+            "root=UUID=1 ostree=/ostree/boot.1/fedora/1/0 mitigations=auto console=ttyS0 systemd.log_level=debug quiet",
+            &strs("quiet"),
+            &strs("mitigations=auto"),
+            Some(&derive),
+        );
+        let layers: Vec<_> = layers.iter().map(|l| l.layer.as_str()).collect();
+        assert_eq!(
+            layers,
+            [
+                "install",
+                "ostree",
+                "base",
+                "local",
+                "profile debug",
+                "global"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_kargs_validate() {
+        let old = "root=UUID=1 rw console=tty0 console=ttyS0,115200n8";
+        let v = kargs_validate(old, &format!("{} quiet", old));
+        assert!(v.errors.is_empty() && v.warnings.is_empty());
+
+        let v = kargs_validate(
+            old,
+            "root=UUID=1 rw console=ttyS0,115200n8 console=tty0 root=UUID=2",
+        );
+        assert_eq!(
+            v.errors,
+            ["root= is given multiple times: root=UUID=1 root=UUID=2"]
+        );
+        assert_eq!(
+            v.warnings,
+            ["The primary console changes from ttyS0 to tty0"]
+        );
+
+        let v = kargs_validate(old, &format!("{} console=ttyS0,9600", old));
+        assert_eq!(
+            v.errors,
+            ["Console ttyS0 is given multiple times with different options: console=ttyS0,115200n8 console=ttyS0,9600"]
+        );
+        assert!(v.warnings.is_empty());
+
+        let long = format!("{} foo={}", old, "x".repeat(CMDLINE_MAX));
+        let v = kargs_validate(old, &long);
+        assert_eq!(v.errors.len(), 1);
+        assert!(v.errors[0].starts_with("The kernel command line is"));
+        // Problems which were already present are only warned about
+        let v = kargs_validate(&long, &format!("{} quiet", long));
+        assert!(v.errors.is_empty());
+        assert_eq!(v.warnings.len(), 1);
+    }
+}
@@ -0,0 +1,185 @@
+//! Run the `kernel-install` plugins of a new root filesystem when its kernel
+//! changed on the client, i.e. when overriding or layering the kernel, so that
+//! tools hooking into them (e.g. akmods) work on rpm-ostree systems too.  As
+//! rpm-ostree owns depmod, the initramfs and the boot loader entries, only the
+//! `add` command is run, with the `other` layout, and the plugins doing those
+//! are skipped.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::cxxrsutil::*;
+use crate::ffi::BubblewrapMutability;
+use crate::ffiutil::ffi_dirfd;
+use anyhow::{anyhow, Result};
+use cap_std::fs::Dir;
+use cap_std::io_lifetimes::AsFilelike;
+use cap_std_ext::cap_std;
+use cap_std_ext::dirext::CapStdExtDirExt;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::Path;
+
+/// The directories of the plugins, by precedence; `/etc` is the one of the
+/// new root filesystem, like for RPM scripts.
+const PLUGIN_DIRS: &[&str] = &["etc/kernel/install.d", "usr/lib/kernel/install.d"];
+
+/// Plugins doing what rpm-ostree owns.  `00-rpmostree-skip.install` is added
+/// by composes to suppress running `kernel-install` from the kernel `%posttrans`.
+const SKIPPED_PLUGINS: &[&str] = &[
+    "00-rpmostree-skip.install",
+    "20-grubby.install",
+    "50-depmod.install",
+    "50-dracut.install",
+    "51-dracut-rescue.install",
+    "60-ukify.install",
+    "90-loaderentry.install",
+    "90-uki-copy.install",
+    "92-crashkernel.install",
+    "92-tuned.install",
+    "99-grub-mkconfig.install",
+];
+
+/// Runs the plugins given as arguments like `kernel-install add`: exit code
+/// 77 stops without error, and others fail.
+const RUN_PLUGINS_SCRIPT: &str = r#"set -euo pipefail
+kver=$1; shift
+mkdir -p "$KERNEL_INSTALL_STAGING_AREA" /tmp/kernel-install-entry
+for plugin in "$@"; do
+    test -x "$plugin" || continue
+    rc=0
+    "$plugin" add "$kver" /tmp/kernel-install-entry "/usr/lib/modules/$kver/vmlinuz" || rc=$?
+    case $rc in
+        0) ;;
+        77) exit 0 ;;
+        *) echo "kernel-install plugin $plugin failed with exit code $rc" 1>&2; exit $rc ;;
+    esac
+done
+"#;
+
+/// Whether `path` in `d` is a symlink to `/dev/null`.
+fn is_masked(d: &Dir, path: &Path) -> Result<bool> {
+    if !d.symlink_metadata(path)?.is_symlink() {
+        return Ok(false);
+    }
+    // Unlike `Dir::read_link()`, this allows absolute targets.
+    let target = cap_primitives::fs::read_link_contents(&d.as_filelike_view(), path)?;
+    Ok(target == Path::new("/dev/null"))
+}
+
+/// The paths of the plugins in `rootfs` to run, ordered by name.  A plugin in
+/// `/etc` overrides the one of the same name in `/usr/lib`, or masks it if
+/// it's a symlink to `/dev/null`.
+fn find_plugins(rootfs: &Dir) -> Result<Vec<String>> {
+    let mut plugins = BTreeMap::new();
+    for dir in PLUGIN_DIRS.iter().rev() {
+        let d = match rootfs.open_dir_optional(dir)? {
+            Some(d) => d,
+            None => continue,
+        };
+        for entry in d.entries()? {
+            let name = entry?.file_name();
+            let name = name
+                .to_str()
+                .ok_or_else(|| anyhow!("Invalid plugin name: {:?}", name))?;
+            if !name.ends_with(".install") {
+                continue;
+            }
+            if is_masked(&d, Path::new(name))? {
+                plugins.remove(name);
+            } else {
+                plugins.insert(name.to_string(), format!("/{}/{}", dir, name));
+            }
+        }
+    }
+    Ok(plugins
+        .into_iter()
+        .filter(|(name, _)| !SKIPPED_PLUGINS.contains(&name.as_str()))
+        .map(|(_, path)| path)
+        .collect())
+}
+
+fn machine_id() -> Result<Option<String>> {
+    let root = Dir::open_ambient_dir("/", cap_std::ambient_authority())?;
+    let mut buf = String::new();
+    match root.open_optional("etc/machine-id")? {
+        Some(mut f) => f.read_to_string(&mut buf)?,
+        None => return Ok(None),
+    };
+    Ok(Some(buf.trim().to_string()).filter(|s| !s.is_empty()))
+}
+
+/// Run the `kernel-install` plugins of the root filesystem `rootfs_dfd` for
+/// the new kernel `kver`.  This is called before depmod and regenerating the
+/// initramfs, as plugins may add modules.
+pub(crate) fn kernel_install_add(rootfs_dfd: i32, kver: &str) -> CxxResult<()> {
+    let tempetc = crate::core::prepare_tempetc_guard(rootfs_dfd)?;
+    let rootfs = unsafe { &ffi_dirfd(rootfs_dfd)? };
+    let plugins = find_plugins(rootfs)?;
+    if plugins.is_empty() {
+        tempetc.undo()?;
+        return Ok(());
+    }
+    let names: Vec<&str> = plugins
+        .iter()
+        .filter_map(|p| p.rsplit_once('/').map(|(_, name)| name))
+        .collect();
+    crate::ffi::output_message(&format!(
+        "Running kernel-install plugins: {}",
+        names.join(", ")
+    ));
+
+    let rootfs_fd = crate::ffiutil::ffi_view_openat_dir(rootfs_dfd);
+    let mut bwrap =
+        crate::bwrap::Bubblewrap::new_with_mutability(&rootfs_fd, BubblewrapMutability::RoFiles)?;
+    bwrap.setup_compat_var()?;
+    bwrap.setenv("KERNEL_INSTALL_LAYOUT", "other");
+    bwrap.setenv("KERNEL_INSTALL_IMAGE_TYPE", "linux");
+    bwrap.setenv("KERNEL_INSTALL_STAGING_AREA", "/tmp/kernel-install-staging");
+    bwrap.setenv("KERNEL_INSTALL_VERBOSE", "0");
+    if let Some(machine_id) = machine_id()? {
+        bwrap.setenv("KERNEL_INSTALL_MACHINE_ID", &machine_id);
+        bwrap.setenv("KERNEL_INSTALL_ENTRY_TOKEN", &machine_id);
+    }
+    bwrap.append_child_argv(["bash", "-c", RUN_PLUGINS_SCRIPT, "kernel-install", kver]);
+    bwrap.append_child_argv(plugins.iter().map(|p| p.as_str()));
+    bwrap.run_inner(None)?;
+    tempetc.undo()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_find_plugins() -> Result<()> {
+        let rootfs = &cap_tempfile::tempdir(cap_std::ambient_authority())?;
+        assert!(find_plugins(rootfs)?.is_empty());
+        rootfs.create_dir_all(PLUGIN_DIRS[0])?;
+        rootfs.create_dir_all(PLUGIN_DIRS[1])?;
+        let usr = rootfs.open_dir(PLUGIN_DIRS[1])?;
+        let etc = rootfs.open_dir(PLUGIN_DIRS[0])?;
+        for name in [
+            "00-rpmostree-skip.install",
+            "50-dracut.install",
+            "90-akmods.install",
+            "95-kernel-hooks.install",
+            "96-masked.install",
+            "README",
+        ] {
+            usr.write(name, "#!/bin/sh\n")?;
+        }
+        etc.write("10-custom.install", "#!/bin/sh\n")?;
+        etc.write("95-kernel-hooks.install", "#!/bin/sh\n")?;
+        etc.symlink("/dev/null", "96-masked.install")?;
+        assert_eq!(
+            find_plugins(rootfs)?,
+            [
+                "/etc/kernel/install.d/10-custom.install",
+                "/usr/lib/kernel/install.d/90-akmods.install",
+                "/etc/kernel/install.d/95-kernel-hooks.install",
+            ]
+        );
+        Ok(())
+    }
+}
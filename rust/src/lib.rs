@@ -15,6 +15,8 @@
 
 // pub(crate) utilities
 mod cxxrsutil;
+#[macro_use]
+mod i18n;
 mod ffiutil;
 pub(crate) mod ffiwrappers;
 pub(crate) use cxxrsutil::*;
@@ -76,6 +78,33 @@ pub mod ffi {
         v: String,
     }
 
+    /// A subcommand of a C++ command, for the shell completion.
+    #[derive(Clone, Debug, Default)]
+    struct CliCommand {
+        name: String,
+        description: String,
+    }
+
+    /// An option of a C++ command, for the shell completion.
+    #[derive(Clone, Debug, Default)]
+    struct CliOption {
+        long_name: String,
+        /// Empty if there's none
+        short_name: String,
+        /// The placeholder of the value, e.g. `PKG`; empty if there's none
+        value_name: String,
+        description: String,
+    }
+
+    /// A C++ command, as recorded from its option context; see `completion.rs`.
+    #[derive(Clone, Debug, Default)]
+    struct CliDescription {
+        subcommands: Vec<CliCommand>,
+        options: Vec<CliOption>,
+        /// The description of the arguments, e.g. `REFSPEC [REVISION]`
+        parameters: String,
+    }
+
     /// Classify the running system.
     #[derive(Clone, Debug)]
     enum SystemHostType {
@@ -84,6 +113,110 @@ pub mod ffi {
         Unknown,
     }
 
+    /// What the conditions of the system restrict an automatic update to.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    enum UpdateRestriction {
+        None,
+        CheckOnly,
+        DownloadOnly,
+    }
+
+    /// A failure of the automatic update policy, as recorded to back off.
+    #[derive(Debug)]
+    pub(crate) struct AutoUpdateFailure {
+        /// "network", "gpg", "depsolve", or "other"
+        pub category: String,
+        pub message: String,
+        /// The number of consecutive failures; 0 if there's none
+        pub count: u32,
+        /// Unix timestamp of the last failure
+        pub timestamp: i64,
+        /// Unix timestamp after which automatic updates are attempted again
+        pub next_attempt: i64,
+        pub backoff_secs: u64,
+    }
+
+    /// A package of a cached depsolve result.
+    #[derive(Debug)]
+    pub(crate) struct SolvedPackage {
+        pub nevra: String,
+        /// The rpm-md repo (or e.g. `@commandline`) it comes from
+        pub repo: String,
+    }
+
+    /// The kernel arguments of a new deployment, with the tracked changes reapplied.
+    #[derive(Debug)]
+    pub(crate) struct DeployKargs {
+        pub kargs: Vec<String>,
+        /// Conflicts of the tracked changes with the kernel arguments of the new base
+        pub conflicts: Vec<String>,
+    }
+
+    /// The kernel arguments resulting from `rpm-ostree kargs --editor`.
+    #[derive(Debug)]
+    pub(crate) struct EditedKargs {
+        pub kargs: String,
+        pub warnings: Vec<String>,
+    }
+
+    /// The changes made to an origin by `rpm-ostree ex apply`.
+    #[derive(Debug, Default)]
+    pub(crate) struct AppliedState {
+        pub changed: bool,
+        /// The new kernel arguments, if `kargs_changed`
+        pub kargs: Vec<String>,
+        pub kargs_changed: bool,
+    }
+
+    /// A kernel argument, and the layer it comes from (e.g. `global`, `base`).
+    #[derive(Debug)]
+    pub(crate) struct KargLayer {
+        pub arg: String,
+        pub layer: String,
+    }
+
+    /// The problems found when validating new kernel arguments.
+    #[derive(Debug)]
+    pub(crate) struct KargsValidation {
+        /// Problems which are refused unless forced
+        pub errors: Vec<String>,
+        pub warnings: Vec<String>,
+    }
+
+    // alternative.rs
+    extern "Rust" {
+        fn alternative_pin(sysroot: &OstreeSysroot, deployment: &OstreeDeployment) -> Result<()>;
+        fn alternative_deployment_index(sysroot: &OstreeSysroot) -> Result<i32>;
+        fn release_alternative_pin(sysroot: &OstreeSysroot, force: bool) -> Result<()>;
+    }
+
+    // apply_state.rs
+    extern "Rust" {
+        fn apply_state_entrypoint(args: &Vec<String>) -> Result<()>;
+    }
+
+    // autoupdate_failure.rs
+    extern "Rust" {
+        fn autoupdate_record_failure(category: &str, message: &str) -> Result<AutoUpdateFailure>;
+        fn autoupdate_record_success() -> Result<()>;
+        fn autoupdate_backoff_reason() -> Result<String>;
+        fn autoupdate_last_failure() -> Result<AutoUpdateFailure>;
+        fn autoupdate_failure_describe(
+            category: &str,
+            message: &str,
+            count: u32,
+            timestamp: i64,
+            next_attempt: i64,
+        ) -> String;
+    }
+
+    // bootc.rs
+    extern "Rust" {
+        fn bootc_is_managed() -> Result<bool>;
+        fn bootc_delegate_upgrade(reboot: bool) -> Result<bool>;
+        fn bootc_delegate_switch(imgref: &str, reboot: bool) -> Result<bool>;
+    }
+
     // client.rs
     extern "Rust" {
         fn is_bare_split_xattrs() -> Result<bool>;
@@ -94,7 +227,12 @@ pub mod ffi {
         fn is_rpm_arg(arg: &str) -> bool;
         fn client_start_daemon() -> Result<()>;
         fn client_handle_fd_argument(arg: &str, arch: &str, is_replace: bool) -> Result<Vec<i32>>;
+        fn client_pin_url_checksums(
+            pkgs: &Vec<String>,
+            checksums: &Vec<String>,
+        ) -> Result<Vec<String>>;
         fn client_render_download_progress(progress: &GVariant) -> String;
+        fn client_render_update_diff_json(diff: &GVariant) -> Result<String>;
         fn running_in_container() -> bool;
     }
 
@@ -127,6 +265,7 @@ pub mod ffi {
         fn append_bwrap_arg(&mut self, arg: &str);
         fn append_child_arg(&mut self, arg: &str);
         fn setenv(&mut self, k: &str, v: &str);
+        fn set_reproducible(&mut self, source_date_epoch: &str) -> Result<()>;
         fn take_fd(&mut self, source_fd: i32, target_fd: i32);
         fn set_inherit_stdin(&mut self);
         fn take_stdin_fd(&mut self, source_fd: i32);
@@ -152,6 +291,17 @@ pub mod ffi {
         fn composeutil_legacy_prep_dev_and_run(rootfs_dfd: i32) -> Result<()>;
         fn print_ostree_txn_stats(stats: Pin<&mut OstreeRepoTransactionStats>);
         fn write_commit_id(target_path: &str, revision: &str) -> Result<()>;
+        fn compose_build_derived_image_entrypoint(args: &Vec<String>) -> Result<()>;
+        fn compose_diskimage_entrypoint(args: &Vec<String>) -> Result<()>;
+        fn compose_passwd_snapshot_entrypoint(args: &Vec<String>) -> Result<()>;
+        fn compose_pin_ids_entrypoint(args: &Vec<String>) -> Result<()>;
+        fn compose_schema_entrypoint(args: &Vec<String>) -> Result<()>;
+    }
+
+    // builtins/usroverlay.rs
+    extern "Rust" {
+        fn usroverlay_mode() -> Result<String>;
+        fn usroverlay_changes() -> Result<Vec<String>>;
     }
 
     // cliwrap.rs
@@ -171,17 +321,79 @@ pub mod ffi {
         pub image_digest: String,
     }
 
+    /// Settings for pulling container images from the daemon configuration;
+    /// an empty `proxy` and a zero `retries`/`timeout` mean unset.
+    #[derive(Debug)]
+    pub(crate) struct ContainerPullConfig {
+        pub proxy: String,
+        pub tls_verify: bool,
+        pub retries: u32,
+        pub timeout: u64,
+    }
+
     // sysroot_upgrade.rs
     extern "Rust" {
         fn pull_container(
             repo: &OstreeRepo,
             cancellable: &GCancellable,
             imgref: &str,
+            enforce_sigpolicy: bool,
+            bypass_attestation: bool,
+            origin: &GKeyFile,
+            config: &ContainerPullConfig,
         ) -> Result<Box<ContainerImageState>>;
         fn query_container_image(
             repo: &OstreeRepo,
             imgref: &str,
         ) -> Result<Box<ContainerImageState>>;
+        fn container_image_populate_variant(
+            repo: &OstreeRepo,
+            imgref: &str,
+            dict: &GVariantDict,
+        ) -> Result<()>;
+        fn preview_container_rebase(imgref: &str) -> Result<()>;
+        fn list_container_tags(
+            imgref: &str,
+            origin: &GKeyFile,
+            config: &ContainerPullConfig,
+            skip: u32,
+            count: u32,
+        ) -> Result<()>;
+        fn container_update_populate_variant(
+            repo: &OstreeRepo,
+            cancellable: &GCancellable,
+            imgref: &str,
+            origin: &GKeyFile,
+            config: &ContainerPullConfig,
+            dict: &GVariantDict,
+        ) -> Result<bool>;
+        fn container_update_available(
+            repo: &OstreeRepo,
+            cancellable: &GCancellable,
+            imgref: &str,
+            origin: &GKeyFile,
+            config: &ContainerPullConfig,
+        ) -> Result<bool>;
+    }
+
+    // containers_auth.rs
+    extern "Rust" {
+        fn image_auth_add(
+            registry: &str,
+            username: &str,
+            password: &str,
+            runtime: bool,
+        ) -> Result<()>;
+    }
+
+    // countme.rs
+    extern "Rust" {
+        fn countme_rpmmd_fetched(repoids: &Vec<String>);
+    }
+
+    // credentials.rs
+    extern "Rust" {
+        fn repo_credentials_decrypt(id: &str, destdir: &str) -> Result<Vec<StringMapping>>;
     }
 
     // core.rs
@@ -202,6 +414,8 @@ pub mod ffi {
 
         fn prepare_filesystem_script_prep(rootfs: i32) -> Result<Box<FilesystemScriptPrep>>;
         fn undo(self: &FilesystemScriptPrep) -> Result<()>;
+        fn semodule_cache_prepare(path: &str, clear: bool) -> Result<()>;
+        fn semodule_cache_import(path: &str) -> Result<()>;
 
         fn run_depmod(rootfs_dfd: i32, kver: &str, unified_core: bool) -> Result<()>;
 
@@ -209,6 +423,7 @@ pub mod ffi {
 
         fn is_container_image_reference(refspec: &str) -> bool;
         fn refspec_classify(refspec: &str) -> RefspecType;
+        fn container_refspec_make_absolute(refspec: &str) -> Result<String>;
 
         fn verify_kernel_hmac(rootfs: i32, moddir: &str) -> Result<()>;
 
@@ -236,6 +451,7 @@ pub mod ffi {
             unified_core: bool,
         ) -> Result<()>;
         fn compose_postprocess_final(rootfs_dfd: i32) -> Result<()>;
+        fn compose_postprocess_ownership(rootfs_dfd: i32, treefile: &Treefile) -> Result<()>;
         fn convert_var_to_tmpfiles_d(rootfs_dfd: i32, cancellable: &GCancellable) -> Result<()>;
         fn rootfs_prepare_links(rootfs_dfd: i32) -> Result<()>;
         fn workaround_selinux_cross_labeling(
@@ -250,6 +466,30 @@ pub mod ffi {
         fn postprocess_cleanup_rpmdb(rootfs_dfd: i32) -> Result<()>;
         fn rewrite_rpmdb_for_target(rootfs_dfd: i32, normalize: bool) -> Result<()>;
         fn directory_size(dfd: i32, mut cancellable: Pin<&mut GCancellable>) -> Result<u64>;
+        fn compose_ima_sign(
+            repo: &OstreeRepo,
+            rev: &str,
+            key: &str,
+            algorithm: &str,
+        ) -> Result<String>;
+        fn compose_ima_verify(repo: &OstreeRepo, rev: &str) -> Result<()>;
+    }
+
+    // db_image.rs
+    extern "Rust" {
+        fn db_image_fetch_rpmdb(repo: &OstreeRepo, image: &str, rootfs_dfd: i32) -> Result<String>;
+    }
+
+    // db_verify.rs
+    extern "Rust" {
+        fn db_verify_entrypoint(args: &Vec<String>) -> Result<()>;
+    }
+
+    // deltarpm.rs
+    extern "Rust" {
+        fn deltarpm_available() -> bool;
+        fn deltarpm_installed_evr(name: &str, arch: &str) -> Result<String>;
+        fn deltarpm_apply(delta_fd: i32, target: &str, chksum_repr: &str) -> Result<()>;
     }
 
     // deployment_utils.rs
@@ -262,6 +502,10 @@ pub mod ffi {
             sysroot: Pin<&mut OstreeSysroot>,
             deploy_id: &str,
         ) -> Result<String>;
+        fn deployment_origin_json(
+            sysroot: Pin<&mut OstreeSysroot>,
+            deploy_id: &str,
+        ) -> Result<String>;
         fn deployment_get_base(
             sysroot: Pin<&mut OstreeSysroot>,
             opt_deploy_id: &str,
@@ -311,6 +555,11 @@ pub mod ffi {
             repo: &OstreeRepo,
             cancellable: &GCancellable,
         ) -> Result<()>;
+        fn prune_container_images(
+            sysroot: &OstreeSysroot,
+            repo: &OstreeRepo,
+            keep: u32,
+        ) -> Result<u32>;
         fn variant_add_remote_status(
             repo: &OstreeRepo,
             refspec: &str,
@@ -328,6 +577,18 @@ pub mod ffi {
         fn parse_override_source(source: &str) -> Result<OverrideReplacementSource>;
         fn parse_revision(source: &str) -> Result<ParsedRevision>;
         fn generate_object_path(base: &str, next_segment: &str) -> Result<String>;
+        fn check_protected_packages(pkgs: &Vec<String>) -> Result<()>;
+    }
+
+    // etc_conflicts.rs
+    extern "Rust" {
+        fn etc_conflicts_record(
+            sysroot: &OstreeSysroot,
+            merge: &OstreeDeployment,
+            new: &OstreeDeployment,
+        ) -> Result<()>;
+        fn etc_conflicts_finalize(sysroot: &OstreeSysroot) -> Result<()>;
+        fn deployments_etc_conflicts(deployments: &GVariant) -> Result<String>;
     }
 
     // failpoint_bridge.rs
@@ -335,6 +596,14 @@ pub mod ffi {
         fn failpoint(p: &str) -> Result<()>;
     }
 
+    // fips.rs
+    extern "Rust" {
+        fn fips_entrypoint(args: &Vec<String>) -> Result<()>;
+        fn fips_packages() -> Vec<String>;
+        fn fips_dracut_module() -> String;
+        fn fips_kargs(kargs: &str) -> Result<Vec<String>>;
+    }
+
     // importer.rs
     extern "Rust" {
         type RpmImporterFlags;
@@ -374,15 +643,63 @@ pub mod ffi {
             username: &str,
             groupname: &str,
         ) -> Result<String>;
+
+        fn rpm_import_jobs(repo_dfd: i32, configured: u32) -> u32;
     }
 
     // initramfs.rs
     extern "Rust" {
+        fn initramfs_etc_normalize_paths(paths: Vec<String>) -> Result<Vec<String>>;
         fn get_dracut_random_cpio() -> &'static [u8];
         fn initramfs_overlay_generate(
             files: &Vec<String>,
             cancellable: Pin<&mut GCancellable>,
         ) -> Result<i32>;
+        fn initramfs_overlay_checksum(
+            files: &Vec<String>,
+            cancellable: Pin<&mut GCancellable>,
+        ) -> Result<String>;
+        fn initramfs_inputs_manifest(
+            rootfs_dfd: i32,
+            kver: &str,
+            args: &Vec<String>,
+            packages: &str,
+            generator: &str,
+        ) -> Result<String>;
+        fn initramfs_reusable(deployment_dfd: i32, kver: &str, manifest: &str) -> Result<i32>;
+        fn initramfs_inputs_record(rootfs_dfd: i32, kver: &str, manifest: &str) -> Result<()>;
+        fn initramfs_source_date_epoch() -> String;
+        fn initramfs_dracut_conf_write(rootfs_dfd: i32, conf: &str) -> Result<()>;
+        fn initramfs_generator_command(rootfs_dfd: i32, name: &str) -> Result<String>;
+    }
+
+    // kargs.rs
+    extern "Rust" {
+        fn kargs_for_deploy(
+            repo: &OstreeRepo,
+            current: &str,
+            old_rev: &str,
+            new_rev: &str,
+            append: Vec<String>,
+            delete: Vec<String>,
+        ) -> Result<DeployKargs>;
+        fn kargs_editor_format(kargs: &str) -> String;
+        fn kargs_editor_parse(buf: &str) -> Result<EditedKargs>;
+        fn kargs_diff(old: &str, new: &str) -> Vec<String>;
+        fn kargs_validate(old: &str, new: &str) -> KargsValidation;
+        fn kargs_global() -> Result<Vec<String>>;
+        fn kargs_global_store(kargs: &Vec<String>) -> Result<()>;
+        fn kargs_global_change(
+            old: &Vec<String>,
+            append: Vec<String>,
+            delete: Vec<String>,
+        ) -> Result<Vec<String>>;
+        fn kargs_apply_global(kargs: &str, old: &Vec<String>, new: &Vec<String>) -> String;
+    }
+
+    // kernel_install.rs
+    extern "Rust" {
+        fn kernel_install_add(rootfs_dfd: i32, kver: &str) -> Result<()>;
     }
 
     // journal.rs
@@ -401,6 +718,11 @@ pub mod ffi {
         fn console_progress_end(suffix: &str);
     }
 
+    // homed.rs
+    extern "Rust" {
+        fn compose_systemd_homed(rootfs_dfd: i32, treefile: &Treefile) -> Result<()>;
+    }
+
     // history.rs
 
     /// A history entry in the journal. It may represent multiple consecutive boots
@@ -427,6 +749,11 @@ pub mod ffi {
         fn history_ctx_new() -> Result<Box<HistoryCtx>>;
         fn next_entry(&mut self) -> Result<HistoryEntry>;
         fn history_prune() -> Result<()>;
+        fn history_add_requests_diff(
+            from: &GVariant,
+            to: &GVariant,
+            dict: &GVariantDict,
+        ) -> Result<()>;
     }
 
     // modularity.rs
@@ -434,6 +761,156 @@ pub mod ffi {
         fn modularity_entrypoint(args: &Vec<String>) -> Result<()>;
     }
 
+    // offline_update.rs
+    extern "Rust" {
+        fn offline_update_entrypoint(args: &Vec<String>) -> Result<()>;
+    }
+
+    // containers_policy.rs
+    extern "Rust" {
+        fn sigpolicy_entrypoint(args: &Vec<String>) -> Result<()>;
+    }
+
+    // fsverity.rs
+    extern "Rust" {
+        fn fsverity_entrypoint(args: &Vec<String>) -> Result<()>;
+        fn fsverity_enable_commit(repo: &OstreeRepo, rev: &str) -> Result<()>;
+        fn fsverity_require_repo(repo: &OstreeRepo) -> Result<()>;
+    }
+
+    // metrics.rs
+    extern "Rust" {
+        fn metrics_record_transaction(
+            method: &str,
+            duration_secs: f64,
+            success: bool,
+            downloaded_bytes: u64,
+        ) -> Result<()>;
+        fn metrics_record_update_check() -> Result<()>;
+        fn metrics_write(path: &str, deployments: &GVariant) -> Result<()>;
+    }
+
+    // fleet_lock.rs
+    extern "Rust" {
+        fn fleet_lock_validate(url: &str, group: &str) -> Result<()>;
+        fn fleet_lock_pre_reboot(url: &str, group: &str, cancellable: &GCancellable) -> Result<()>;
+    }
+
+    // provenance.rs
+    extern "Rust" {
+        fn provenance_validate(sink: &str, key_path: &str) -> Result<()>;
+        fn provenance_emit(
+            repo: &OstreeRepo,
+            base: &str,
+            commit: &str,
+            origin: &str,
+            packages: &Vec<StringMapping>,
+            sink: &str,
+            key_path: &str,
+        ) -> Result<()>;
+    }
+
+    // rebase_targets.rs
+    extern "Rust" {
+        fn rebase_list_targets(source: &str, page: u32) -> Result<()>;
+        fn rebase_pick_target(source: &str) -> Result<String>;
+    }
+
+    // repo_keys.rs
+    extern "Rust" {
+        fn repo_keys_entrypoint(args: &Vec<String>) -> Result<()>;
+        fn repo_keys_validate();
+        fn repo_keys_check_rpmmd(id: &str, gpgcheck: bool, keys: &Vec<String>) -> Result<()>;
+        fn repo_keys_rpmmd_pinned(id: &str) -> Result<bool>;
+        fn repo_keys_check_signer(id: &str, keyid: &str, keys: &Vec<String>) -> Result<()>;
+        fn repo_keys_verify_commit(repo: &OstreeRepo, remote: &str, commit: &str) -> Result<()>;
+    }
+
+    // hooks.rs
+    extern "Rust" {
+        fn run_update_hooks(
+            point: &str,
+            osname: &str,
+            checksum: &str,
+            automatic: bool,
+        ) -> Result<()>;
+    }
+
+    // idle.rs
+    extern "Rust" {
+        fn system_busy_reason() -> Result<String>;
+    }
+
+    // rollout.rs
+    extern "Rust" {
+        fn rollout_deferral(commit: &GVariant) -> Result<String>;
+    }
+
+    // update_conditions.rs
+    extern "Rust" {
+        fn nm_metered(value: u32) -> bool;
+        fn update_restriction(
+            metered: bool,
+            on_battery: bool,
+            cache_only: bool,
+            checking_only: bool,
+            downloading_only: bool,
+        ) -> UpdateRestriction;
+    }
+
+    // update_graph.rs
+    extern "Rust" {
+        fn update_graph_target(url: &str, current: &str) -> Result<String>;
+    }
+
+    // system_update.rs
+    extern "Rust" {
+        fn system_update_prepare() -> Result<()>;
+        fn system_update_cancel() -> Result<bool>;
+    }
+
+    // update_window.rs
+    extern "Rust" {
+        fn update_window_validate(spec: &str) -> Result<()>;
+        fn update_window_is_open(spec: &str) -> Result<bool>;
+        fn update_window_describe(spec: &str) -> Result<String>;
+    }
+
+    // status.rs
+    extern "Rust" {
+        fn status_format(status: &str, format: &str, schema_version: u32) -> Result<String>;
+        fn status_query(status: &str, query: &str) -> Result<String>;
+        fn deployments_reboot_reason(deployments: &GVariant) -> Result<String>;
+    }
+
+    /// A progress update from the daemon; counts which are unknown are zero,
+    /// and a negative `percentage` is unknown.  An empty `stage` is reported
+    /// as `other`.
+    #[derive(Debug)]
+    pub(crate) struct ProgressEvent {
+        pub stage: String,
+        pub text: String,
+        pub items_done: u64,
+        pub items_total: u64,
+        pub bytes_done: u64,
+        pub bytes_total: u64,
+        pub percentage: i32,
+        pub elapsed_secs: u64,
+    }
+
+    // transaction_progress.rs
+    extern "Rust" {
+        fn progress_event_to_variant(event: &ProgressEvent) -> *mut GVariant;
+        fn client_render_progress_json(progress: &GVariant) -> Result<String>;
+    }
+
+    // timings.rs
+    extern "Rust" {
+        fn timings_enable();
+        fn timings_describe(key: &str, size: u64) -> String;
+        fn timings_record(key: &str, size: u64, millis: u64);
+    }
+
     // tokio_ffi.rs
     extern "Rust" {
         type TokioHandle;
@@ -448,6 +925,21 @@ pub mod ffi {
         fn script_is_ignored(pkg: &str, script: &str) -> bool;
     }
 
+    // scan.rs
+    extern "Rust" {
+        fn scan_entrypoint(args: &Vec<String>) -> Result<()>;
+    }
+
+    // secureboot.rs
+    extern "Rust" {
+        fn secureboot_audit(rootfs_dfd: i32, treefile: &Treefile) -> Result<()>;
+    }
+
+    // sysusers.rs
+    extern "Rust" {
+        fn compose_generate_sysusers(rootfs_dfd: i32, treefile: &Treefile) -> Result<()>;
+    }
+
     // testutils.rs
     extern "Rust" {
         fn testutils_entrypoint(argv: Vec<String>) -> Result<()>;
@@ -481,6 +973,12 @@ pub mod ffi {
         packages: Vec<String>,
     }
 
+    #[derive(Debug, PartialEq, Eq)]
+    struct RepoPackage {
+        repo: String,
+        packages: Vec<String>,
+    }
+
     extern "Rust" {
         type Treefile;
 
@@ -510,6 +1008,7 @@ pub mod ffi {
             packages: Vec<String>,
             allow_existing: bool,
         ) -> Result<bool>;
+        fn set_local_package_verified(&mut self, nevra: &str, verified: bool);
         fn get_local_fileoverride_packages(&self) -> Vec<String>;
         fn add_local_fileoverride_packages(
             &mut self,
@@ -517,6 +1016,7 @@ pub mod ffi {
             allow_existing: bool,
         ) -> Result<bool>;
         fn remove_packages(&mut self, packages: Vec<String>, allow_noent: bool) -> Result<bool>;
+        fn set_packages_transient(&mut self, packages: Vec<String>, boots: u32) -> Result<bool>;
         fn get_packages_override_replace(&self) -> Vec<OverrideReplacement>;
         fn has_packages_override_replace(&self) -> bool;
         fn add_packages_override_replace(&mut self, replacement: OverrideReplacement) -> bool;
@@ -532,8 +1032,11 @@ pub mod ffi {
         fn get_modules_enable(&self) -> Vec<String>;
         fn has_modules_enable(&self) -> bool;
         fn get_modules_install(&self) -> Vec<String>;
-        fn add_modules(&mut self, modules: Vec<String>, enable_only: bool) -> bool;
+        fn add_modules(&mut self, modules: Vec<String>, enable_only: bool) -> Result<bool>;
         fn remove_modules(&mut self, modules: Vec<String>, enable_only: bool) -> bool;
+        fn disable_modules(&mut self, modules: Vec<String>) -> Result<bool>;
+        fn reset_modules(&mut self, names: Vec<String>) -> Result<bool>;
+        fn switch_module_streams(&mut self, modules: Vec<String>) -> Result<bool>;
         fn remove_all_packages(&mut self) -> bool;
         fn get_exclude_packages(&self) -> Vec<String>;
         fn get_platform_module(&self) -> String;
@@ -555,6 +1058,13 @@ pub mod ffi {
         fn get_etc_group_members(&self) -> Vec<String>;
         fn get_boot_location_is_modules(&self) -> bool;
         fn get_ima(&self) -> bool;
+        fn get_systemd_homed(&self) -> bool;
+        fn get_nss_altfiles(&self) -> bool;
+        fn get_ima_sign_key(&self) -> String;
+        fn get_ima_sign_algorithm(&self) -> String;
+        fn get_ima_verify(&self) -> bool;
+        fn get_rpm_signing_keys(&self) -> Vec<String>;
+        fn get_fsverity(&self) -> bool;
         fn get_releasever(&self) -> String;
         fn get_repo_metadata_target(&self) -> RepoMetadataTarget;
         fn rpmdb_backend_is_target(&self) -> bool;
@@ -562,7 +1072,7 @@ pub mod ffi {
         fn get_files_remove_regex(&self, package: &str) -> Vec<String>;
         fn get_checksum(&self, repo: &OstreeRepo) -> Result<String>;
         fn get_ostree_ref(&self) -> String;
-        fn get_repo_packages(&self) -> &[RepoPackage];
+        fn get_repo_packages(&self) -> Vec<RepoPackage>;
         fn clear_repo_packages(&mut self);
         fn prettyprint_json_stdout(&self);
         fn print_deprecation_warnings(&self);
@@ -591,18 +1101,46 @@ pub mod ffi {
         fn get_initramfs_regenerate(&self) -> bool;
         fn get_initramfs_args(&self) -> Vec<String>;
         fn set_initramfs_regenerate(&mut self, enabled: bool, args: Vec<String>);
+        fn get_initramfs_dracut_conf(&self) -> String;
+        fn get_initramfs_generator(&self) -> String;
+        fn set_initramfs_generator(&mut self, name: &str) -> Result<bool>;
+        fn get_fips(&self) -> bool;
+        fn set_fips(&mut self, enabled: bool);
+        fn initramfs_dracut_config_update(
+            &mut self,
+            add_modules: Vec<String>,
+            omit_modules: Vec<String>,
+            add_drivers: Vec<String>,
+            remove_modules: Vec<String>,
+            remove_drivers: Vec<String>,
+        ) -> Result<bool>;
+        fn get_kargs_append(&self) -> Vec<String>;
+        fn get_kargs_delete(&self) -> Vec<String>;
+        fn kargs_track(&mut self, existing: &str, new: &str);
+        fn kargs_profiles_apply(
+            &mut self,
+            rootfs_dfd: i32,
+            kargs: &str,
+            enable: Vec<String>,
+            disable: Vec<String>,
+        ) -> Result<String>;
+        fn kargs_layers(&self, repo: &OstreeRepo, rev: &str, kargs: &str)
+            -> Result<Vec<KargLayer>>;
         fn get_unconfigured_state(&self) -> String;
         fn may_require_local_assembly(&self) -> bool;
         fn has_any_packages(&self) -> bool;
         fn merge_treefile(&mut self, treefile: &str) -> Result<bool>;
+        fn apply_state(
+            &mut self,
+            state: &str,
+            kargs: &str,
+            allow_protected: bool,
+        ) -> Result<AppliedState>;
     }
 
-    // treefile.rs (split out from above to make &self nice to use)
+    // userdb.rs
     extern "Rust" {
-        type RepoPackage;
-
-        fn get_repo(&self) -> &str;
-        fn get_packages(&self) -> Vec<String>;
+        fn compose_userdb(rootfs_dfd: i32, treefile: &Treefile) -> Result<()>;
     }
 
     // utils.rs
@@ -643,13 +1181,39 @@ pub mod ffi {
         fn transaction_apply_live(sysroot: &OstreeSysroot, target: &GVariant) -> Result<()>;
     }
 
+    // live_restarts.rs
+    extern "Rust" {
+        fn live_restarts_needed() -> Result<Vec<String>>;
+    }
+
+    // ownership_audit.rs
+    extern "Rust" {
+        fn compose_ownership_audit(rootfs_dfd: i32, treefile: &Treefile) -> Result<()>;
+    }
+
+    #[derive(Debug)]
+    struct PolicyPackage {
+        nevra: String,
+        name: String,
+        license: String,
+    }
+
+    // package_policy.rs
+    extern "Rust" {
+        fn package_policy_check(treefile: &Treefile, packages: &Vec<PolicyPackage>) -> Result<()>;
+    }
+
     // passwd.rs
     extern "Rust" {
         fn prepare_rpm_layering(rootfs: i32, merge_passwd_dir: &str) -> Result<bool>;
         fn complete_rpm_layering(rootfs: i32) -> Result<()>;
         fn passwd_cleanup(rootfs: i32) -> Result<()>;
-        fn migrate_group_except_root(rootfs: i32, preserved_groups: &Vec<String>) -> Result<()>;
-        fn migrate_passwd_except_root(rootfs: i32) -> Result<()>;
+        fn migrate_group_except_root(
+            rootfs: i32,
+            preserved_groups: &Vec<String>,
+            keep_regular: bool,
+        ) -> Result<()>;
+        fn migrate_passwd_except_root(rootfs: i32, keep_regular: bool) -> Result<()>;
         fn passwd_compose_prep(rootfs: i32, treefile: &mut Treefile) -> Result<()>;
         fn passwd_compose_prep_repo(
             rootfs: i32,
@@ -727,6 +1291,66 @@ pub mod ffi {
         fn origin_to_treefile(kf: &GKeyFile) -> Result<Box<Treefile>>;
         fn treefile_to_origin(tf: &Treefile) -> Result<*mut GKeyFile>;
         fn origin_validate_roundtrip(kf: &GKeyFile);
+        fn origin_is_container_rebase_with_same_layering(
+            from: &GKeyFile,
+            to: &GKeyFile,
+        ) -> Result<bool>;
+        fn origin_is_package_addition(from: &GKeyFile, to: &GKeyFile) -> Result<bool>;
+    }
+
+    // pin.rs
+    extern "Rust" {
+        fn release_stale_pins(sysroot: &OstreeSysroot) -> Result<()>;
+        fn pin_deployment(
+            sysroot: &OstreeSysroot,
+            spec: &str,
+            unpin: bool,
+            reason: &str,
+            expires: i64,
+        ) -> Result<()>;
+    }
+
+    // rollback_retention.rs
+    extern "Rust" {
+        fn retention_pin_rollbacks(sysroot: &OstreeSysroot, keep: u32) -> Result<()>;
+        fn release_retention_pins(sysroot: &OstreeSysroot, force: bool) -> Result<()>;
+    }
+
+    // testdeploy.rs
+    extern "Rust" {
+        fn testdeploy_mark(sysroot: &OstreeSysroot, deployment: &OstreeDeployment) -> Result<()>;
+        fn testdeploy_cleanup(sysroot: &OstreeSysroot) -> Result<bool>;
+    }
+
+    // reflink.rs
+    extern "Rust" {
+        fn fs_supports_reflink(dfd: i32) -> bool;
+    }
+
+    // rpmdb_convert.rs
+    extern "Rust" {
+        fn rpmdb_convert_for_target(rootfs_dfd: i32, treefile: &Treefile) -> Result<()>;
+    }
+
+    // rpmdb_update.rs
+    extern "Rust" {
+        fn rpmdb_snapshot(rootfs_dfd: i32) -> Result<bool>;
+        fn rpmdb_update_for_target(
+            rootfs_dfd: i32,
+            added: &Vec<String>,
+            added_headers_fd: i32,
+            removed: &Vec<String>,
+        ) -> Result<bool>;
+    }
+
+    // solvecache.rs
+    extern "Rust" {
+        fn solve_cache_lookup(cachedir: &str, key: &str) -> Result<Vec<SolvedPackage>>;
+        fn solve_cache_store(
+            cachedir: &str,
+            key: &str,
+            packages: &Vec<SolvedPackage>,
+        ) -> Result<()>;
     }
 
     // rpmutils.rs
@@ -758,6 +1382,7 @@ pub mod ffi {
         include!("rpmostreemain.h");
         fn early_main();
         fn rpmostree_main(args: &[&str]) -> Result<i32>;
+        fn describe_command(path: &[&str]) -> CliDescription;
         fn rpmostree_process_global_teardown();
         fn c_unit_tests() -> Result<()>;
     }
@@ -855,12 +1480,46 @@ pub mod ffi {
             cancellable: &GCancellable,
         ) -> Result<*mut GVariant>;
     }
+
+    // rpmostree-bench.h
+    unsafe extern "C++" {
+        include!("rpmostree-bench.h");
+        fn bench_import_rpm(repo: &OstreeRepo, path: &str) -> Result<()>;
+        fn bench_depsolve(
+            repomd: &str,
+            primary: &str,
+            cachedir: &str,
+            pkgs: &Vec<String>,
+        ) -> Result<u32>;
+    }
+
+    // rpmostree-scan.h
+    unsafe extern "C++" {
+        include!("rpmostree-scan.h");
+        fn scan_updateinfo(repo: &OstreeRepo, rev: &str, cachedir: &str) -> Result<*mut GVariant>;
+    }
 }
 
+mod alternative;
+pub(crate) use self::alternative::*;
+mod apply_state;
+pub(crate) use self::apply_state::*;
+mod autoupdate_failure;
+pub(crate) use self::autoupdate_failure::*;
+mod bench;
+pub mod boot_trial;
+mod bootc;
+pub(crate) use self::bootc::*;
 pub mod builtins;
 pub(crate) use crate::builtins::apply_live::*;
 pub(crate) use crate::builtins::compose::commit::*;
+pub(crate) use crate::builtins::compose::derived_image::*;
+pub(crate) use crate::builtins::compose::diskimage::*;
+pub(crate) use crate::builtins::compose::passwd_snapshot::*;
+pub(crate) use crate::builtins::compose::pin_ids::*;
+pub(crate) use crate::builtins::compose::schema::*;
 pub(crate) use crate::builtins::compose::*;
+pub(crate) use crate::builtins::usroverlay::{usroverlay_changes, usroverlay_mode};
 mod bwrap;
 pub(crate) use bwrap::*;
 mod client;
@@ -868,25 +1527,56 @@ pub(crate) use client::*;
 pub mod cliwrap;
 pub mod container;
 pub use cliwrap::*;
+pub mod completion;
 mod composepost;
+mod containers_auth;
+pub(crate) use containers_auth::*;
+mod containers_attestation;
+mod containers_layers;
+mod containers_policy;
+pub(crate) use containers_policy::sigpolicy_entrypoint;
 pub mod countme;
+pub(crate) use countme::countme_rpmmd_fetched;
+mod credentials;
+pub(crate) use credentials::*;
 pub(crate) use composepost::*;
 mod core;
 use crate::core::*;
 mod capstdext;
 mod daemon;
 pub(crate) use daemon::*;
+mod db_image;
+pub(crate) use db_image::*;
+mod db_verify;
+pub(crate) use db_verify::*;
+mod deltarpm;
+pub(crate) use self::deltarpm::*;
+pub mod deployment_diff;
 mod deployment_utils;
 pub(crate) use deployment_utils::*;
 mod dirdiff;
+mod etc_conflicts;
+pub(crate) use etc_conflicts::*;
 pub mod failpoint_bridge;
 use failpoint_bridge::*;
 mod extensions;
 pub(crate) use extensions::*;
 #[cfg(feature = "fedora-integration")]
 mod fedora_integration;
+mod fips;
+pub(crate) use self::fips::*;
+pub mod fleet_lock;
+pub(crate) use self::fleet_lock::*;
+mod fsverity;
+pub(crate) use self::fsverity::*;
 mod history;
 pub use self::history::*;
+mod homed;
+pub(crate) use self::homed::*;
+mod hooks;
+pub(crate) use self::hooks::*;
+mod idle;
+pub(crate) use self::idle::*;
 mod importer;
 pub(crate) use importer::*;
 mod initramfs;
@@ -894,33 +1584,94 @@ pub(crate) use self::initramfs::*;
 mod isolation;
 mod journal;
 pub(crate) use self::journal::*;
+mod kargs;
+pub(crate) use self::kargs::*;
+mod kernel_install;
+pub(crate) use self::kernel_install::*;
 mod lockfile;
 pub(crate) use self::lockfile::*;
 mod live;
 pub(crate) use self::live::*;
+mod live_restarts;
+pub(crate) use self::live_restarts::*;
+mod metrics;
+pub(crate) use self::metrics::*;
 pub mod modularity;
 pub(crate) use self::modularity::*;
 mod nameservice;
 mod normalization;
+mod offline_update;
+pub(crate) use self::offline_update::*;
 mod origin;
 pub(crate) use self::origin::*;
+mod ownership_audit;
+pub(crate) use self::ownership_audit::*;
+mod package_policy;
+pub(crate) use self::package_policy::*;
 mod passwd;
 use passwd::*;
+pub mod pin;
+pub(crate) use self::pin::*;
+mod pinned_ids;
 mod console_progress;
 pub(crate) use self::console_progress::*;
 mod progress;
+mod provenance;
+pub(crate) use self::provenance::*;
 mod tokio_ffi;
 pub(crate) use self::tokio_ffi::*;
+mod transaction_progress;
+pub(crate) use self::transaction_progress::*;
 mod scripts;
 pub(crate) use self::scripts::*;
+mod scan;
+pub(crate) use self::scan::*;
+mod secureboot;
+pub(crate) use self::secureboot::*;
+pub mod soft_reboot;
+mod status;
+pub(crate) use self::status::*;
 mod sysroot_upgrade;
 pub(crate) use crate::sysroot_upgrade::*;
+pub mod system_update;
+pub(crate) use self::system_update::*;
+mod rollback_retention;
+pub(crate) use self::rollback_retention::*;
+mod rollout;
+pub(crate) use self::rollout::*;
+mod rebase_targets;
+pub(crate) use self::rebase_targets::*;
+mod reflink;
+pub(crate) use self::reflink::*;
+mod repo_keys;
+pub(crate) use self::repo_keys::*;
+mod rpmdb_convert;
+pub(crate) use self::rpmdb_convert::*;
+mod rpmdb_update;
+pub(crate) use self::rpmdb_update::*;
 mod rpmutils;
 pub(crate) use self::rpmutils::*;
+mod solvecache;
+pub(crate) use self::solvecache::*;
+pub mod testdeploy;
+pub(crate) use self::testdeploy::*;
 mod testutils;
 pub(crate) use self::testutils::*;
+mod timings;
+pub(crate) use self::timings::*;
+pub mod transient;
 mod treefile;
 pub use self::treefile::*;
+mod update_conditions;
+pub(crate) use self::update_conditions::*;
+mod update_graph;
+pub(crate) use self::update_graph::*;
+pub mod update_notify;
+mod update_window;
+pub(crate) use self::update_window::*;
+mod userdb;
+pub(crate) use self::userdb::*;
 mod utils;
 pub use self::utils::*;
 mod variant_utils;
+pub mod verify;
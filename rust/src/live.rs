@@ -27,8 +27,12 @@ use std::path::{Path, PathBuf};
 
 /// GVariant `s`: Choose a specific commit
 pub(crate) const OPT_TARGET: &str = "target";
-/// GVariant `b`: Enable changing or removing packages(+files).
+/// GVariant `b`: Revert the live-applied changes, back to the booted commit.
+pub(crate) const OPT_RESET: &str = "reset";
+/// GVariant `b`: Remove files even if running processes hold them.
 pub(crate) const OPT_REPLACE: &str = "replace";
+/// GVariant `s`: Space-separated services to restart if they use replaced files.
+pub(crate) const OPT_RESTART_SERVICES: &str = "restart-services";
 
 /// The directory where ostree stores transient per-deployment state.
 /// This is currently semi-private to ostree; we should add an API to
@@ -45,7 +49,7 @@ const LIVE_REF_INPROGRESS: &str = "rpmostree/live-apply-inprogress";
 
 /// Get the transient state directory for a deployment; TODO
 /// upstream this into libostree.
-fn get_runstate_dir(deploy: &ostree::Deployment) -> PathBuf {
+pub(crate) fn get_runstate_dir(deploy: &ostree::Deployment) -> PathBuf {
     format!(
         "{}/{}.{}",
         OSTREE_RUNSTATE_DIR,
@@ -120,11 +124,45 @@ fn subpath(diff: &FileTreeDiff, p: &Path) -> Option<PathBuf> {
     }
 }
 
+/// Update the ownership and mode of the directory `target` in `destdir` to
+/// those of `path` in `commit`; as directories changed by the diff, e.g. when
+/// downgrading a package, are not checked out again.
+fn update_dir_metadata(
+    repo: &ostree::Repo,
+    commit: &str,
+    path: &Path,
+    destdir: &Dir,
+    target: &Path,
+) -> Result<()> {
+    use nix::sys::stat::{fchmodat, FchmodatFlags, Mode};
+    use nix::unistd::{fchownat, FchownatFlags, Gid, Uid};
+    let cancellable = gio::NONE_CANCELLABLE;
+    let (root, _) = repo.read_commit(commit, cancellable)?;
+    let info = root
+        .resolve_relative_path(path.strip_prefix("/").unwrap_or(path))
+        .query_info(
+            "unix::mode,unix::uid,unix::gid",
+            gio::FileQueryInfoFlags::NOFOLLOW_SYMLINKS,
+            cancellable,
+        )?;
+    let target = Some(target)
+        .filter(|t| !t.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let dfd = Some(destdir.as_raw_fd());
+    fchownat(
+        dfd,
+        target,
+        Some(Uid::from_raw(info.attribute_uint32("unix::uid"))),
+        Some(Gid::from_raw(info.attribute_uint32("unix::gid"))),
+        FchownatFlags::NoFollowSymlink,
+    )?;
+    let mode = Mode::from_bits_truncate(info.attribute_uint32("unix::mode") & 0o7777);
+    fchmodat(dfd, target, mode, FchmodatFlags::FollowSymlink)?;
+    Ok(())
+}
+
 /// Given a diff, apply it to the target directory, which should be a checkout of the source commit.
 fn apply_diff(repo: &ostree::Repo, diff: &FileTreeDiff, commit: &str, destdir: &Dir) -> Result<()> {
-    if !diff.changed_dirs.is_empty() {
-        anyhow::bail!("Changed directories are not supported yet");
-    }
     let cancellable = gio::NONE_CANCELLABLE;
     // This applies to all added/changed content, we just
     // overwrite `subpath` in each run.
@@ -163,7 +201,12 @@ fn apply_diff(repo: &ostree::Repo, diff: &FileTreeDiff, commit: &str, destdir: &
         )
         .with_context(|| format!("Checking out changed file {:?}", d))?;
     }
-    assert!(diff.changed_dirs.is_empty());
+    // And changed directories
+    for d in diff.changed_dirs.iter().map(Path::new) {
+        let path = subpath(diff, d).expect("subpath");
+        update_dir_metadata(repo, commit, &path, destdir, d.strip_prefix("/")?)
+            .with_context(|| format!("Updating changed dir {:?}", d))?;
+    }
 
     // Finally clean up removed directories and files together.  We use
     // rayon here just because we can.
@@ -218,9 +261,6 @@ fn update_etc(
     // The generic apply_diff() above in theory could work anywhere.
     // But this code is only designed for /etc.
     assert_eq!(diff.subdir.as_ref().expect("subpath"), expected_subpath);
-    if !diff.changed_dirs.is_empty() {
-        anyhow::bail!("Changed directories are not supported yet");
-    }
 
     let cancellable = gio::NONE_CANCELLABLE;
     // This applies to all added/changed content, we just
@@ -276,7 +316,12 @@ fn update_etc(
             )
         })?;
     }
-    assert!(diff.changed_dirs.is_empty());
+    // And changed directories
+    for (subpath, target) in diff.changed_dirs.iter().filter_map(filtermap_paths) {
+        let subpath = subpath.expect("subpath");
+        update_dir_metadata(repo, commit, &subpath, destdir, &target)
+            .with_context(|| format!("Updating changed /etc dir {:?}", target))?;
+    }
 
     // And finally clean up removed files and directories.
     diff.removed_files
@@ -344,6 +389,17 @@ pub(crate) fn transaction_apply_live(
         .lookup(OPT_REPLACE)
         .map_err(anyhow::Error::msg)?
         .unwrap_or_default();
+    let restart_services = options
+        .lookup::<String>(OPT_RESTART_SERVICES)
+        .map_err(anyhow::Error::msg)?
+        .unwrap_or_default();
+    let reset: bool = options
+        .lookup(OPT_RESET)
+        .map_err(anyhow::Error::msg)?
+        .unwrap_or_default();
+    if reset && target.is_some() {
+        return Err(anyhow!("Cannot specify both a target commit and reset").into());
+    }
     let repo = &sysroot.repo().expect("repo");
 
     let booted = sysroot.require_booted_deployment()?;
@@ -351,8 +407,17 @@ pub(crate) fn transaction_apply_live(
     let booted_commit = booted.csum().expect("csum");
     let booted_commit = booted_commit.as_str();
 
+    let state = get_live_state(repo, &booted)?;
     let target_commit = if let Some(t) = target {
         Cow::Borrowed(t)
+    } else if reset {
+        if !state
+            .as_ref()
+            .map_or(false, |s| !(s.commit.is_empty() && s.inprogress.is_empty()))
+        {
+            return Err(anyhow!("No live-applied changes to reset").into());
+        }
+        Cow::Borrowed(booted_commit)
     } else {
         match sysroot.query_deployments_for(Some(osname.as_str())) {
             (Some(pending), _) => {
@@ -366,7 +431,6 @@ pub(crate) fn transaction_apply_live(
         }
     };
 
-    let state = get_live_state(repo, &booted)?;
     if state.is_none() {
         match booted.unlocked() {
             DeploymentUnlockedState::None => {
@@ -403,7 +467,8 @@ pub(crate) fn transaction_apply_live(
         )?;
     }
 
-    if let Some(ref state) = state {
+    // Resetting is always possible, even after an interruption
+    if let Some(state) = state.as_ref().filter(|_| !reset) {
         if !state.inprogress.is_empty() && state.inprogress.as_str() != target_commit.as_str() {
             return Err(anyhow::anyhow!(
                 "Previously interrupted while targeting commit {}, cannot change target to {}",
@@ -414,9 +479,16 @@ pub(crate) fn transaction_apply_live(
         }
     }
 
+    // When resetting after an interrupted update, revert what was partially applied
     let source_commit = state
         .as_ref()
-        .map(|s| s.commit.as_str())
+        .map(|s| {
+            if reset && !s.inprogress.is_empty() && s.inprogress.as_str() != booted_commit {
+                s.inprogress.as_str()
+            } else {
+                s.commit.as_str()
+            }
+        })
         .filter(|s| !s.is_empty())
         .unwrap_or(booted_commit);
     // Compute the filesystem-level diff
@@ -428,18 +500,17 @@ pub(crate) fn transaction_apply_live(
         crate::ffi::rpmdb_diff(repo.reborrow_cxx(), &from, &to, false)
             .map_err(anyhow::Error::msg)?
     };
+    // Packages can be removed and changed (e.g. downgraded) too; running
+    // processes keep using the replaced files, but those holding removed
+    // ones may still need them.
     if !allow_replacement {
-        if pkgdiff.n_removed() > 0 {
-            return Err(anyhow!(
-                "packages would be removed: {}, enable replacement to override",
-                pkgdiff.n_removed()
-            )
-            .into());
-        }
-        if pkgdiff.n_modified() > 0 {
+        let holding = progress_task("Finding processes holding files to remove", || {
+            crate::live_restarts::processes_holding_removed(&diff)
+        })?;
+        if !holding.is_empty() {
             return Err(anyhow!(
-                "packages would be changed: {}, enable replacement to override",
-                pkgdiff.n_modified()
+                "Files to remove are held by running processes: {}; stop them, or enable replacement to override",
+                holding.join("; ")
             )
             .into());
         }
@@ -484,11 +555,25 @@ pub(crate) fn transaction_apply_live(
     })?;
     progress_task("Running systemd-tmpfiles for /run and /var", rerun_tmpfiles)?;
 
-    // Success! Update the recorded state.
-    state.commit = target_commit.to_string();
+    // Success! Update the recorded state; after a reset, there is no live
+    // state anymore.
+    state.commit = if reset {
+        "".to_string()
+    } else {
+        target_commit.to_string()
+    };
     state.inprogress = "".to_string();
     write_live_state(repo, &booted, &state)?;
 
+    // And report the services still running replaced code
+    let run = Dir::open_ambient_dir("/run", cap_std::ambient_authority())?;
+    if let Some(rundir) = run.open_dir_optional(&get_runstate_dir(&booted))? {
+        let allowlist: Vec<_> = restart_services.split_ascii_whitespace().collect();
+        if let Err(e) = crate::live_restarts::update_restarts_needed(&rundir, &diff, &allowlist) {
+            eprintln!("warning: Failed to find services needing restart: {:#}", e);
+        }
+    }
+
     Ok(())
 }
 
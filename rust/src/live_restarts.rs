@@ -0,0 +1,346 @@
+//! After `apply-live` replaced files in `/usr`, the processes which mapped
+//! the old versions of them (executables and libraries) keep running the old
+//! code until they're restarted.  We find them via `/proc/<pid>/maps`, map
+//! them to their systemd services via `/proc/<pid>/cgroup`, and record the
+//! services so that `rpm-ostree status --restarts-needed` can list those
+//! which haven't been restarted since.
+//!
+//! Removed files are different, as the processes holding them (mapped or
+//! open) may still need them later, e.g. to load a plugin; so before
+//! `apply-live` removes files, it refuses to do so if any process holds them.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::cxxrsutil::*;
+use anyhow::{Context, Result};
+use cap_std::fs::Dir;
+use cap_std_ext::cap_std;
+use cap_std_ext::dirext::CapStdExtDirExt;
+use nix::time::{clock_gettime, ClockId};
+use ostree_ext::diff::FileTreeDiff;
+use ostree_ext::{gio, ostree};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Stored in the transient state directory of the booted deployment.
+const RESTARTS_STATE_NAME: &str = "rpmostree-live-restarts.json";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RestartsState {
+    /// Services needing a restart, with the `CLOCK_MONOTONIC` time in
+    /// microseconds of the live apply which made them need it.
+    units: BTreeMap<String, u64>,
+}
+
+impl RestartsState {
+    fn load(rundir: &Dir) -> Result<Self> {
+        match rundir.open_optional(RESTARTS_STATE_NAME)? {
+            Some(f) => serde_json::from_reader(std::io::BufReader::new(f))
+                .with_context(|| format!("Parsing {}", RESTARTS_STATE_NAME)),
+            None => Ok(Default::default()),
+        }
+    }
+
+    fn write(&self, rundir: &Dir) -> Result<()> {
+        rundir.atomic_write(RESTARTS_STATE_NAME, serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+
+    /// Drop the services which were stopped or restarted since.
+    fn prune(&mut self) {
+        self.units
+            .retain(|unit, applied| !unit_restarted_since(unit, *applied).unwrap_or(true));
+    }
+}
+
+fn monotonic_usec() -> Result<u64> {
+    let now = clock_gettime(ClockId::CLOCK_MONOTONIC)?;
+    Ok(now.tv_sec() as u64 * 1_000_000 + now.tv_nsec() as u64 / 1_000)
+}
+
+/// The path relative to `/usr` of `path`, as in a diff of `/usr`.
+fn usr_relative(path: &str) -> Option<&str> {
+    path.strip_prefix("/usr").filter(|p| p.starts_with('/'))
+}
+
+/// Whether `path` was removed by `diff`, a diff of `/usr`.
+fn is_removed(diff: &FileTreeDiff, path: &str) -> bool {
+    let path = match usr_relative(path) {
+        Some(p) => p,
+        None => return false,
+    };
+    diff.removed_files.contains(path)
+        || diff.removed_dirs.iter().any(|d| {
+            path.strip_prefix(d.as_str())
+                .map_or(false, |rest| rest.starts_with('/'))
+        })
+}
+
+/// Whether `path` was changed or removed by `diff`, a diff of `/usr`.
+fn is_replaced(diff: &FileTreeDiff, path: &str) -> bool {
+    usr_relative(path).map_or(false, |p| diff.changed_files.contains(p)) || is_removed(diff, path)
+}
+
+/// Parse the paths of the files mapped in `/proc/<pid>/maps`.
+fn parse_mapped_paths(maps: &str) -> impl Iterator<Item = &str> {
+    maps.lines().filter_map(|line| {
+        // The address, perms, offset, dev and inode are separated by single
+        // spaces, and the path is padded.
+        let path = line.splitn(6, ' ').nth(5)?.trim_start();
+        let path = path.strip_suffix(" (deleted)").unwrap_or(path);
+        Some(path).filter(|p| p.starts_with('/'))
+    })
+}
+
+/// Parse the system service of a process from its `/proc/<pid>/cgroup`.
+fn parse_cgroup_service(cgroup: &str) -> Option<&str> {
+    let path = cgroup.lines().find_map(|line| {
+        let mut parts = line.splitn(3, ':');
+        let (hierarchy, controllers) = (parts.next()?, parts.next()?);
+        let unified = hierarchy == "0" && controllers.is_empty();
+        Some(parts.next()?).filter(|_| unified || controllers == "name=systemd")
+    })?;
+    let mut components = path.trim_start_matches('/').split('/');
+    if components.next() != Some("system.slice") {
+        return None;
+    }
+    components.find(|c| c.ends_with(".service"))
+}
+
+/// The `/proc/<pid>` directories of the running processes.
+fn processes() -> Result<Vec<PathBuf>> {
+    let mut r = Vec::new();
+    for entry in std::fs::read_dir("/proc")? {
+        let entry = entry?;
+        let pid = entry.file_name();
+        if pid
+            .to_str()
+            .map_or(false, |p| p.bytes().all(|b| b.is_ascii_digit()))
+        {
+            r.push(entry.path());
+        }
+    }
+    Ok(r)
+}
+
+/// The paths of the files a process holds: those it maps (including its
+/// executable), and those it has open.
+fn held_paths(proc_pid: &Path) -> Vec<String> {
+    // Processes can exit at any time, and kernel threads don't map files.
+    let mut r: Vec<String> = std::fs::read_to_string(proc_pid.join("maps"))
+        .map(|maps| parse_mapped_paths(&maps).map(String::from).collect())
+        .unwrap_or_default();
+    if let Ok(fds) = std::fs::read_dir(proc_pid.join("fd")) {
+        for fd in fds.flatten() {
+            if let Ok(target) = std::fs::read_link(fd.path()) {
+                if let Some(target) = target.to_str() {
+                    let target = target.strip_suffix(" (deleted)").unwrap_or(target);
+                    r.push(target.to_string());
+                }
+            }
+        }
+    }
+    r
+}
+
+/// Describe the processes holding files which `diff`, a diff of `/usr`,
+/// removes, e.g. `1234 (foo): /usr/bin/foo`.
+pub(crate) fn processes_holding_removed(diff: &FileTreeDiff) -> Result<Vec<String>> {
+    let mut r = Vec::new();
+    for proc_pid in processes()? {
+        let mut removed: Vec<String> = held_paths(&proc_pid)
+            .into_iter()
+            .filter(|p| is_removed(diff, p))
+            .collect();
+        if removed.is_empty() {
+            continue;
+        }
+        removed.sort();
+        removed.dedup();
+        let pid = proc_pid.file_name().and_then(|p| p.to_str()).unwrap_or("?");
+        let comm = std::fs::read_to_string(proc_pid.join("comm")).unwrap_or_default();
+        r.push(format!("{} ({}): {}", pid, comm.trim(), removed.join(", ")));
+    }
+    Ok(r)
+}
+
+/// Find the services with processes which map files replaced by `diff`.
+fn find_services_using(diff: &FileTreeDiff) -> Result<BTreeSet<String>> {
+    let mut services = BTreeSet::new();
+    for proc_pid in processes()? {
+        // Processes can exit at any time, and kernel threads don't map files.
+        let maps = match std::fs::read_to_string(proc_pid.join("maps")) {
+            Ok(maps) => maps,
+            Err(_) => continue,
+        };
+        if !parse_mapped_paths(&maps).any(|p| is_replaced(diff, p)) {
+            continue;
+        }
+        if let Ok(cgroup) = std::fs::read_to_string(proc_pid.join("cgroup")) {
+            if let Some(service) = parse_cgroup_service(&cgroup) {
+                services.insert(service.to_string());
+            }
+        }
+    }
+    Ok(services)
+}
+
+/// Whether `unit` is no longer active, or was (re)started after `usec`.
+fn unit_restarted_since(unit: &str, usec: u64) -> Result<bool> {
+    let out = Command::new("systemctl")
+        .args(&[
+            "show",
+            "--property=ActiveState,ActiveEnterTimestampMonotonic",
+            "--",
+            unit,
+        ])
+        .output()?;
+    if !out.status.success() {
+        anyhow::bail!("systemctl show {} failed: {}", unit, out.status);
+    }
+    let out = String::from_utf8(out.stdout)?;
+    let (mut active, mut entered) = (false, 0);
+    for line in out.lines() {
+        match line.split_once('=') {
+            Some(("ActiveState", v)) => active = v == "active",
+            Some(("ActiveEnterTimestampMonotonic", v)) => entered = v.parse()?,
+            _ => {}
+        }
+    }
+    Ok(!active || entered > usec)
+}
+
+/// Called after applying `diff` live to the booted deployment, whose
+/// transient state directory is `rundir`: print the services still using
+/// replaced files, restarting those in `allowlist`, and record the others.
+pub(crate) fn update_restarts_needed(
+    rundir: &Dir,
+    diff: &FileTreeDiff,
+    allowlist: &[&str],
+) -> Result<()> {
+    let applied = monotonic_usec()?;
+    let services = find_services_using(diff)?;
+    let (restart, mut others): (Vec<_>, Vec<_>) = services
+        .into_iter()
+        .partition(|s| allowlist.contains(&s.as_str()));
+    if !restart.is_empty() {
+        let status = Command::new("systemctl")
+            .arg("try-restart")
+            .arg("--")
+            .args(&restart)
+            .status()?;
+        if status.success() {
+            crate::ffi::output_message(&format!("Restarted services: {}", restart.join(", ")));
+        } else {
+            crate::ffi::output_message(&format!(
+                "Failed to restart services: {}",
+                restart.join(", ")
+            ));
+            others.extend(restart);
+        }
+    }
+
+    let mut state = RestartsState::load(rundir)?;
+    state.prune();
+    for service in others {
+        state.units.insert(service, applied);
+    }
+    if !state.units.is_empty() {
+        let units: Vec<_> = state.units.keys().map(|s| s.as_str()).collect();
+        crate::ffi::output_message(&format!(
+            "Services using replaced files, which need a restart: {}",
+            units.join(", ")
+        ));
+    }
+    state.write(rundir)?;
+    Ok(())
+}
+
+/// Implementation of `rpm-ostree status --restarts-needed`: the services
+/// which need a restart after live applies to the booted deployment.
+pub(crate) fn live_restarts_needed() -> CxxResult<Vec<String>> {
+    let sysroot = &ostree::Sysroot::new_default();
+    sysroot.load(gio::NONE_CANCELLABLE)?;
+    let booted = match sysroot.booted_deployment() {
+        Some(b) => b,
+        None => return Ok(Vec::new()),
+    };
+    let run = Dir::open_ambient_dir("/run", cap_std::ambient_authority())?;
+    let rundir = match run.open_dir_optional(&crate::live::get_runstate_dir(&booted))? {
+        Some(d) => d,
+        None => return Ok(Vec::new()),
+    };
+    let mut state = RestartsState::load(&rundir)?;
+    state.prune();
+    Ok(state.units.into_keys().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_replaced() {
+        let mut diff = FileTreeDiff {
+            subdir: Some("/usr".to_string()),
+            ..Default::default()
+        };
+        diff.changed_files.insert("/lib64/libfoo.so.1".into());
+        diff.removed_files.insert("/bin/bar".into());
+        diff.removed_dirs.insert("/lib64/baz".into());
+        assert!(is_replaced(&diff, "/usr/lib64/libfoo.so.1"));
+        assert!(is_replaced(&diff, "/usr/bin/bar"));
+        assert!(is_replaced(&diff, "/usr/lib64/baz/plugin.so"));
+        assert!(!is_replaced(&diff, "/usr/lib64/bazz/plugin.so"));
+        assert!(!is_replaced(&diff, "/usr/lib64/libfoo.so.2"));
+        assert!(!is_replaced(&diff, "/lib64/libfoo.so.1"));
+        assert!(!is_replaced(&diff, "/usrbin/bar"));
+        assert!(!is_removed(&diff, "/usr/lib64/libfoo.so.1"));
+        assert!(is_removed(&diff, "/usr/lib64/baz/plugin.so"));
+    }
+
+    #[test]
+    fn test_parse_mapped_paths() {
+        let maps = "\
+55d0c0a00000-55d0c0a02000 r--p 00000000 fd:00 1234                       /usr/bin/foo
+7f1c2a000000-7f1c2a021000 rw-p 00000000 00:00 0
+7f1c2a200000-7f1c2a228000 r--p 00000000 fd:00 5678                       /usr/lib64/libc.so.6 (deleted)
+7f1c2a400000-7f1c2a401000 r--p 00000000 fd:00 91011                      /var/lib/my data
+7ffd5e3c1000-7ffd5e3e2000 rw-p 00000000 00:00 0                          [stack]
+";
+        let paths: Vec<_> = parse_mapped_paths(maps).collect();
+        assert_eq!(
+            paths,
+            ["/usr/bin/foo", "/usr/lib64/libc.so.6", "/var/lib/my data"]
+        );
+    }
+
+    #[test]
+    fn test_parse_cgroup_service() {
+        assert_eq!(
+            parse_cgroup_service("0::/system.slice/sshd.service\n"),
+            Some("sshd.service")
+        );
+        assert_eq!(
+            parse_cgroup_service("0::/system.slice/system-getty.slice/getty@tty1.service\n"),
+            Some("getty@tty1.service")
+        );
+        assert_eq!(
+            parse_cgroup_service("0::/system.slice/podman.service/payload\n"),
+            Some("podman.service")
+        );
+        assert_eq!(
+            parse_cgroup_service(
+                "12:memory:/system.slice\n1:name=systemd:/system.slice/crond.service\n"
+            ),
+            Some("crond.service")
+        );
+        assert_eq!(
+            parse_cgroup_service("0::/user.slice/user-1000.slice/user@1000.service/app.slice\n"),
+            None
+        );
+        assert_eq!(parse_cgroup_service("0::/init.scope\n"), None);
+    }
+}
@@ -32,6 +32,10 @@ async fn inner_async_main(args: Vec<String>) -> Result<i32> {
             rpmostree_rust::container::container_encapsulate(&args_borrowed).await?;
             return Ok(0);
         }
+        "deployment-encapsulate" => {
+            rpmostree_rust::container::deployment_encapsulate(&args_borrowed).await?;
+            return Ok(0);
+        }
         _ => {}
     }
     // Everything below here is a blocking API, and run on a worker thread so
@@ -42,8 +46,23 @@ async fn inner_async_main(args: Vec<String>) -> Result<i32> {
         if let Some(arg) = args.get(1) {
             match *arg {
                 // Add custom Rust commands here, and also in `libmain.cxx` if user-visible.
+                "audit-log" => builtins::audit_log::entrypoint(args).map(|_| 0),
+                "boot-trial" => rpmostree_rust::boot_trial::entrypoint(args).map(|_| 0),
                 "countme" => rpmostree_rust::countme::entrypoint(args).map(|_| 0),
                 "cliwrap" => rpmostree_rust::cliwrap::entrypoint(args).map(|_| 0),
+                "completion" => rpmostree_rust::completion::entrypoint(args).map(|_| 0),
+                "diff" => rpmostree_rust::deployment_diff::entrypoint(args).map(|_| 0),
+                "fleet-lock-release" => rpmostree_rust::fleet_lock::entrypoint(args).map(|_| 0),
+                "pin" => rpmostree_rust::pin::entrypoint(args).map(|_| 0),
+                "soft-reboot" => rpmostree_rust::soft_reboot::entrypoint(args).map(|_| 0),
+                "system-update" => rpmostree_rust::system_update::entrypoint(args).map(|_| 0),
+                "testdeploy" => rpmostree_rust::testdeploy::entrypoint(args).map(|_| 0),
+                "testdeploy-cleanup" => {
+                    rpmostree_rust::testdeploy::cleanup_entrypoint(args).map(|_| 0)
+                }
+                "transient-reset" => rpmostree_rust::transient::entrypoint(args).map(|_| 0),
+                "update-notify" => rpmostree_rust::update_notify::entrypoint(args).map(|_| 0),
+                "verify" => rpmostree_rust::verify::entrypoint(args).map(|_| 0),
                 // The `unlock` is a hidden alias for "ostree CLI compatibility"
                 "usroverlay" | "unlock" => builtins::usroverlay::entrypoint(args).map(|_| 0),
                 // C++ main
@@ -0,0 +1,272 @@
+//! Metrics about the state of the system and the activity of the daemon,
+//! written in the Prometheus text format if `MetricsFile` is set in
+//! rpm-ostreed.conf, e.g. for the textfile collector of node_exporter.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::cxxrsutil::*;
+use anyhow::{Context, Result};
+use cap_std::fs::{Dir, Permissions};
+use cap_std_ext::cap_std;
+use cap_std_ext::dirext::CapStdExtDirExt;
+use ostree_ext::glib;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io::Read;
+use std::os::unix::fs::PermissionsExt;
+
+/// The counters, which are kept across daemon restarts; relative to the root.
+const STATE_PATH: &str = "var/lib/rpm-ostree/metrics.json";
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+struct TransactionStats {
+    count: u64,
+    failed: u64,
+    duration_seconds: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+struct MetricsState {
+    /// Unix timestamp of the last check for updates
+    last_update_check: Option<i64>,
+    downloaded_bytes: u64,
+    /// By D-Bus method of the transaction
+    transactions: BTreeMap<String, TransactionStats>,
+}
+
+/// What the metrics report about the deployments.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct DeploymentStats {
+    staged: bool,
+    rollbacks: u64,
+}
+
+impl MetricsState {
+    fn load(rootfs: &Dir) -> Result<Self> {
+        match rootfs.open_optional(STATE_PATH)? {
+            Some(mut f) => {
+                let mut buf = String::new();
+                f.read_to_string(&mut buf)?;
+                serde_json::from_str(&buf).with_context(|| format!("Parsing /{}", STATE_PATH))
+            }
+            None => Ok(Self::default()),
+        }
+    }
+
+    fn store(&self, rootfs: &Dir) -> Result<()> {
+        let parent = std::path::Path::new(STATE_PATH).parent().unwrap();
+        rootfs.create_dir_all(parent)?;
+        let buf = serde_json::to_vec(self)?;
+        rootfs
+            .atomic_write_with_perms(STATE_PATH, &buf, Permissions::from_mode(0o644))
+            .with_context(|| format!("Writing /{}", STATE_PATH))?;
+        Ok(())
+    }
+
+    fn update(f: impl FnOnce(&mut Self)) -> Result<()> {
+        let rootfs = Dir::open_ambient_dir("/", cap_std::ambient_authority())?;
+        let mut state = Self::load(&rootfs)?;
+        f(&mut state);
+        state.store(&rootfs)
+    }
+}
+
+impl DeploymentStats {
+    /// Summarize `deployments`, the `Deployments` property of the sysroot.
+    fn from_variant(deployments: &glib::Variant) -> Result<Self> {
+        let mut stats = Self::default();
+        let mut booted_seen = false;
+        for i in 0..deployments.n_children() {
+            let dict = glib::VariantDict::new(Some(&deployments.child_value(i)));
+            let flag = |k: &str| -> Result<bool> {
+                Ok(dict
+                    .lookup::<bool>(k)
+                    .map_err(anyhow::Error::msg)?
+                    .unwrap_or_default())
+            };
+            if flag("staged")? {
+                stats.staged = true;
+            }
+            if booted_seen {
+                stats.rollbacks += 1;
+            } else if flag("booted")? {
+                booted_seen = true;
+            }
+        }
+        Ok(stats)
+    }
+}
+
+fn render_metric(
+    buf: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    values: impl IntoIterator<Item = (String, String)>,
+) {
+    writeln!(buf, "# HELP {} {}", name, help).unwrap();
+    writeln!(buf, "# TYPE {} {}", name, kind).unwrap();
+    for (suffix, value) in values {
+        writeln!(buf, "{}{} {}", name, suffix, value).unwrap();
+    }
+}
+
+/// Render the metrics in the Prometheus text format.
+fn render(state: &MetricsState, deployments: &DeploymentStats) -> String {
+    let mut buf = String::new();
+    let per_method = |f: &dyn Fn(&TransactionStats) -> String, suffix: &str| {
+        state
+            .transactions
+            .iter()
+            .map(|(method, stats)| (format!("{}{{method=\"{}\"}}", suffix, method), f(stats)))
+            .collect::<Vec<_>>()
+    };
+    if let Some(t) = state.last_update_check {
+        render_metric(
+            &mut buf,
+            "rpm_ostree_last_update_check_timestamp_seconds",
+            "gauge",
+            "Time of the last check for updates.",
+            [(String::new(), t.to_string())],
+        );
+    }
+    render_metric(
+        &mut buf,
+        "rpm_ostree_update_staged",
+        "gauge",
+        "Whether a staged deployment is waiting for a reboot.",
+        [(String::new(), (deployments.staged as u8).to_string())],
+    );
+    render_metric(
+        &mut buf,
+        "rpm_ostree_rollback_deployments",
+        "gauge",
+        "Number of deployments available for rollback.",
+        [(String::new(), deployments.rollbacks.to_string())],
+    );
+    let mut durations = per_method(&|s| s.duration_seconds.to_string(), "_sum");
+    durations.extend(per_method(&|s| s.count.to_string(), "_count"));
+    render_metric(
+        &mut buf,
+        "rpm_ostree_transaction_duration_seconds",
+        "summary",
+        "Duration of transactions, by D-Bus method.",
+        durations,
+    );
+    render_metric(
+        &mut buf,
+        "rpm_ostree_transactions_failed_total",
+        "counter",
+        "Number of failed transactions, by D-Bus method.",
+        per_method(&|s| s.failed.to_string(), ""),
+    );
+    render_metric(
+        &mut buf,
+        "rpm_ostree_downloaded_bytes_total",
+        "counter",
+        "Bytes of ostree content downloaded by transactions.",
+        [(String::new(), state.downloaded_bytes.to_string())],
+    );
+    buf
+}
+
+/// Record that a transaction for D-Bus method `method` finished.
+pub(crate) fn metrics_record_transaction(
+    method: &str,
+    duration_secs: f64,
+    success: bool,
+    downloaded_bytes: u64,
+) -> CxxResult<()> {
+    MetricsState::update(|state| {
+        let stats = state.transactions.entry(method.to_string()).or_default();
+        stats.count += 1;
+        stats.failed += (!success) as u64;
+        stats.duration_seconds += duration_secs;
+        state.downloaded_bytes += downloaded_bytes;
+    })?;
+    Ok(())
+}
+
+/// Record that we just checked for updates.
+pub(crate) fn metrics_record_update_check() -> CxxResult<()> {
+    let now = chrono::Utc::now().timestamp();
+    MetricsState::update(|state| state.last_update_check = Some(now))?;
+    Ok(())
+}
+
+/// Write the metrics to `path`, given `deployments`, the `Deployments`
+/// property of the sysroot.
+pub(crate) fn metrics_write(path: &str, deployments: &crate::FFIGVariant) -> CxxResult<()> {
+    let deployments = DeploymentStats::from_variant(&deployments.glib_reborrow())?;
+    let rootfs = Dir::open_ambient_dir("/", cap_std::ambient_authority())?;
+    let state = MetricsState::load(&rootfs)?;
+    let path = path.trim_start_matches('/');
+    rootfs
+        .atomic_write_with_perms(
+            path,
+            render(&state, &deployments),
+            Permissions::from_mode(0o644),
+        )
+        .with_context(|| format!("Writing /{}", path))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let mut state = MetricsState::default();
+        let deployments = DeploymentStats::default();
+        let r = render(&state, &deployments);
+        assert!(!r.contains("rpm_ostree_last_update_check_timestamp_seconds"));
+        assert!(r.contains("\nrpm_ostree_update_staged 0\n"));
+        assert!(r.contains("# TYPE rpm_ostree_transaction_duration_seconds summary\n"));
+        assert!(r.ends_with("\nrpm_ostree_downloaded_bytes_total 0\n"));
+
+        state.last_update_check = Some(1665900000);
+        state.downloaded_bytes = 4096;
+        state.transactions.insert(
+            "UpdateDeployment".into(),
+            TransactionStats {
+                count: 3,
+                failed: 1,
+                duration_seconds: 42.5,
+            },
+        );
+        let deployments = DeploymentStats {
+            staged: true,
+            rollbacks: 1,
+        };
+        let r = render(&state, &deployments);
+        for line in [
+            "rpm_ostree_last_update_check_timestamp_seconds 1665900000",
+            "rpm_ostree_update_staged 1",
+            "rpm_ostree_rollback_deployments 1",
+            "rpm_ostree_transaction_duration_seconds_sum{method=\"UpdateDeployment\"} 42.5",
+            "rpm_ostree_transaction_duration_seconds_count{method=\"UpdateDeployment\"} 3",
+            "rpm_ostree_transactions_failed_total{method=\"UpdateDeployment\"} 1",
+            "rpm_ostree_downloaded_bytes_total 4096",
+        ] {
+            assert!(r.lines().any(|l| l == line), "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_state_roundtrip() -> Result<()> {
+        let td = &cap_tempfile::tempdir(cap_std::ambient_authority())?;
+        assert_eq!(MetricsState::load(td)?, MetricsState::default());
+        let mut state = MetricsState::default();
+        state.last_update_check = Some(1665900000);
+        state
+            .transactions
+            .insert("Rebase".into(), TransactionStats::default());
+        state.store(td)?;
+        assert_eq!(MetricsState::load(td)?, state);
+        Ok(())
+    }
+}
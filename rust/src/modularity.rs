@@ -22,6 +22,10 @@ enum Opt {
     Install(InstallOpts),
     /// Uninstall a module
     Uninstall(InstallOpts),
+    /// Reset a module, dropping all enabled streams and installed profiles
+    Reset(InstallOpts),
+    /// Switch an enabled or installed module to a different stream
+    SwitchTo(InstallOpts),
 }
 
 #[derive(Debug, Parser)]
@@ -40,6 +44,8 @@ const OPT_KEY_ENABLE_MODULES: &str = "enable-modules";
 const OPT_KEY_DISABLE_MODULES: &str = "disable-modules";
 const OPT_KEY_INSTALL_MODULES: &str = "install-modules";
 const OPT_KEY_UNINSTALL_MODULES: &str = "uninstall-modules";
+const OPT_KEY_RESET_MODULES: &str = "reset-modules";
+const OPT_KEY_SWITCH_MODULES: &str = "switch-modules";
 
 pub(crate) fn modularity_entrypoint(args: &Vec<String>) -> Result<()> {
     match Opt::parse_from(args.iter()) {
@@ -47,6 +53,8 @@ pub(crate) fn modularity_entrypoint(args: &Vec<String>) -> Result<()> {
         Opt::Disable(ref opts) => disable(opts),
         Opt::Install(ref opts) => install(opts),
         Opt::Uninstall(ref opts) => uninstall(opts),
+        Opt::Reset(ref opts) => reset(opts),
+        Opt::SwitchTo(ref opts) => switch_to(opts),
     }
 }
 
@@ -84,6 +92,31 @@ fn uninstall(opts: &InstallOpts) -> Result<()> {
     modules_impl(OPT_KEY_UNINSTALL_MODULES, opts)
 }
 
+fn reset(opts: &InstallOpts) -> Result<()> {
+    if let Some(m) = opts
+        .modules
+        .iter()
+        .find(|m| m.contains(':') || m.contains('/'))
+    {
+        bail!(
+            "Module reset takes module names only, not streams or profiles: {}",
+            m
+        );
+    }
+    modules_impl(OPT_KEY_RESET_MODULES, opts)
+}
+
+fn switch_to(opts: &InstallOpts) -> Result<()> {
+    if let Some(m) = opts
+        .modules
+        .iter()
+        .find(|m| !m.contains(':') || m.contains('/'))
+    {
+        bail!("Expected NAME:STREAM to switch to, found: {}", m);
+    }
+    modules_impl(OPT_KEY_SWITCH_MODULES, opts)
+}
+
 fn modules_impl(key: &str, opts: &InstallOpts) -> Result<()> {
     if opts.modules.is_empty() {
         bail!("At least one module must be specified");
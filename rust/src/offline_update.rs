@@ -0,0 +1,259 @@
+//! Implementation of `rpm-ostree ex offline-update`: carry an update of a
+//! container image based system to machines without network access.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use anyhow::{anyhow, bail, Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use clap::Parser;
+use gio::prelude::*;
+use glib::Variant;
+use ostree_ext::container::{ImageReference, OstreeImageReference, SignatureSource, Transport};
+use ostree_ext::{gio, glib};
+use serde_derive::{Deserialize, Serialize};
+use std::process::Command;
+
+/// Where imported updates are stored; the deployment origin refers to the
+/// image in there, so importing a newer archive makes it the next upgrade.
+const OFFLINE_DIR: &str = "/var/lib/rpm-ostree/offline";
+const METADATA: &str = "metadata.json";
+const IMAGE: &str = "image.ociarchive";
+
+#[derive(Debug, Parser)]
+#[clap(name = "rpm-ostree ex offline-update")]
+#[clap(rename_all = "kebab-case")]
+enum Opt {
+    /// Export a container image to an archive, on a machine with network access
+    Export(ExportOpts),
+    /// Import an archive written by `export` and deploy it
+    Import(ImportOpts),
+}
+
+#[derive(Debug, Parser)]
+struct ExportOpts {
+    /// Image reference, e.g. ostree-remote-registry:fedora:quay.io/fedora/coreos:stable
+    imgref: String,
+    /// Path of the archive to write
+    output: Utf8PathBuf,
+}
+
+#[derive(Debug, Parser)]
+struct ImportOpts {
+    /// Path of the archive
+    archive: Utf8PathBuf,
+    /// Initiate a reboot after the update is deployed
+    #[clap(long, short)]
+    reboot: bool,
+}
+
+/// Describes the image in an archive.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+struct OfflineUpdateMetadata {
+    /// The image reference the image was exported from
+    source: String,
+    /// Digest of the image manifest
+    digest: String,
+    version: Option<String>,
+    created: Option<String>,
+}
+
+pub(crate) fn offline_update_entrypoint(args: &Vec<String>) -> Result<()> {
+    match Opt::parse_from(args.iter()) {
+        Opt::Export(ref opts) => export(opts),
+        Opt::Import(ref opts) => import(opts),
+    }
+}
+
+/// Return the image reference to deploy the image stored at `path`, keeping
+/// the signature verification of the `source` image.
+fn offline_imgref(source: &str, path: &Utf8Path) -> Result<OstreeImageReference> {
+    let source = OstreeImageReference::try_from(source)?;
+    if matches!(source.sigverify, SignatureSource::ContainerPolicy) {
+        bail!(
+            "Signatures verified through containers-policy.json cannot be carried in an archive; use an ostree-remote-registry: image reference to verify the signature embedded in the image"
+        );
+    }
+    Ok(OstreeImageReference {
+        sigverify: source.sigverify,
+        imgref: ImageReference {
+            transport: Transport::OciArchive,
+            name: path.to_string(),
+        },
+    })
+}
+
+fn run(cmd: &mut Command) -> Result<()> {
+    let status = cmd.status().with_context(|| format!("Running {:?}", cmd))?;
+    if !status.success() {
+        bail!("{:?} failed: {:?}", cmd, status);
+    }
+    Ok(())
+}
+
+/// Gather the metadata of the image in the OCI archive at `path`.
+fn inspect_image(source: &str, path: &Utf8Path) -> Result<OfflineUpdateMetadata> {
+    let out = Command::new("skopeo")
+        .args(["inspect", &format!("oci-archive:{}", path)])
+        .output()
+        .context("Running skopeo")?;
+    if !out.status.success() {
+        bail!(
+            "skopeo inspect failed: {}",
+            String::from_utf8_lossy(&out.stderr)
+        );
+    }
+    let info: serde_json::Value = serde_json::from_slice(&out.stdout)?;
+    let field = |v: &serde_json::Value| v.as_str().map(|s| s.to_string());
+    Ok(OfflineUpdateMetadata {
+        source: source.to_string(),
+        digest: field(&info["Digest"]).ok_or_else(|| anyhow!("Missing image digest"))?,
+        version: field(&info["Labels"]["org.opencontainers.image.version"])
+            .or_else(|| field(&info["Labels"]["version"])),
+        created: field(&info["Created"]),
+    })
+}
+
+fn export(opts: &ExportOpts) -> Result<()> {
+    let source = OstreeImageReference::try_from(opts.imgref.as_str())?;
+    // Fail early if the signature can't be verified on import
+    offline_imgref(&opts.imgref, Utf8Path::new(IMAGE))?;
+
+    let tmpdir = tempfile::tempdir_in("/var/tmp")?;
+    let tmpdir: &Utf8Path = tmpdir.path().try_into()?;
+    let image = tmpdir.join(IMAGE);
+    println!("Exporting {}", source);
+    // The digest must be preserved for the signature embedded in the image
+    // and the digest tracked by the deployment to match.
+    run(Command::new("skopeo")
+        .args(["copy", "--preserve-digests"])
+        .arg(source.imgref.to_string())
+        .arg(format!("oci-archive:{}", image)))?;
+    let metadata = inspect_image(&opts.imgref, &image)?;
+    std::fs::write(tmpdir.join(METADATA), serde_json::to_vec_pretty(&metadata)?)?;
+    run(Command::new("tar")
+        .arg("-cf")
+        .arg(opts.output.as_str())
+        .args(["-C", tmpdir.as_str(), METADATA, IMAGE]))?;
+    println!("Wrote {} (digest: {})", opts.output, metadata.digest);
+    Ok(())
+}
+
+fn import(opts: &ImportOpts) -> Result<()> {
+    let staging = Utf8PathBuf::from(format!("{}.tmp", OFFLINE_DIR));
+    if staging.exists() {
+        std::fs::remove_dir_all(&staging)?;
+    }
+    std::fs::create_dir_all(&staging).with_context(|| format!("Creating {}", staging))?;
+    run(Command::new("tar")
+        .arg("-xf")
+        .arg(opts.archive.as_str())
+        .args(["-C", staging.as_str(), METADATA, IMAGE]))?;
+    let metadata: OfflineUpdateMetadata =
+        serde_json::from_slice(&std::fs::read(staging.join(METADATA))?)
+            .with_context(|| format!("Parsing {}", METADATA))?;
+    let found = inspect_image(&metadata.source, &staging.join(IMAGE))?;
+    if found.digest != metadata.digest {
+        bail!(
+            "Image digest mismatch: expected {}, found {}",
+            metadata.digest,
+            found.digest
+        );
+    }
+    // Keep the previous image until the new one is deployed, so that a failed
+    // import leaves the system as it was.
+    let offline_dir = Utf8Path::new(OFFLINE_DIR);
+    let previous = Utf8PathBuf::from(format!("{}.old", OFFLINE_DIR));
+    if previous.exists() {
+        std::fs::remove_dir_all(&previous)?;
+    }
+    if offline_dir.exists() {
+        std::fs::rename(offline_dir, &previous)?;
+    }
+    std::fs::rename(&staging, offline_dir)?;
+    match deploy(&metadata, offline_dir, opts.reboot) {
+        Ok(()) => {
+            if previous.exists() {
+                std::fs::remove_dir_all(&previous)?;
+            }
+            Ok(())
+        }
+        Err(e) => {
+            std::fs::remove_dir_all(offline_dir)?;
+            if previous.exists() {
+                std::fs::rename(&previous, offline_dir)?;
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Deploy the image imported into `offline_dir`.
+fn deploy(metadata: &OfflineUpdateMetadata, offline_dir: &Utf8Path, reboot: bool) -> Result<()> {
+    let imgref = offline_imgref(&metadata.source, &offline_dir.join(IMAGE))?;
+    println!(
+        "Imported {} (version: {}, digest: {})",
+        metadata.source,
+        metadata.version.as_deref().unwrap_or("none"),
+        metadata.digest
+    );
+    match &imgref.sigverify {
+        SignatureSource::OstreeRemote(remote) => {
+            println!(
+                "The signature will be verified with ostree remote {}",
+                remote
+            )
+        }
+        _ => println!("Note: the image is not signature verified"),
+    }
+
+    let client = &mut crate::client::ClientConnection::new()?;
+    let modifiers = glib::VariantDict::new(None);
+    modifiers.insert("set-refspec", &imgref.to_string());
+    let options = glib::VariantDict::new(None);
+    options.insert("reboot", &reboot);
+    let params = Variant::from_tuple(&[modifiers.end(), options.end()]);
+    let reply = &client.get_os_proxy().call_sync(
+        "UpdateDeployment",
+        Some(&params),
+        gio::DBusCallFlags::NONE,
+        -1,
+        gio::NONE_CANCELLABLE,
+    )?;
+    let reply = reply
+        .get::<(String,)>()
+        .ok_or_else(|| anyhow!("Invalid reply"))?;
+    client.transaction_connect_progress_sync(reply.0.as_str())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offline_imgref() -> Result<()> {
+        let path = Utf8Path::new("/var/lib/rpm-ostree/offline/image.ociarchive");
+        let imgref = offline_imgref(
+            "ostree-remote-registry:fedora:quay.io/fedora/coreos:stable",
+            path,
+        )?;
+        assert_eq!(
+            imgref.sigverify,
+            SignatureSource::OstreeRemote("fedora".into())
+        );
+        assert_eq!(imgref.imgref.transport, Transport::OciArchive);
+        assert_eq!(imgref.imgref.name, path.as_str());
+        let imgref = offline_imgref(
+            "ostree-unverified-registry:quay.io/fedora/coreos:stable",
+            path,
+        )?;
+        assert_eq!(
+            imgref.sigverify,
+            SignatureSource::ContainerPolicyAllowInsecure
+        );
+        assert!(offline_imgref("ostree-image-signed:docker://quay.io/os", path).is_err());
+        assert!(offline_imgref("fedora:fedora/36/x86_64/silverblue", path).is_err());
+        Ok(())
+    }
+}
@@ -24,12 +24,17 @@ const RPMOSTREE: &str = "rpmostree";
 const PACKAGES: &str = "packages";
 const MODULES: &str = "modules";
 const OVERRIDES: &str = "overrides";
+const CONTAINER_PULL: &str = "container-pull";
+const KARGS_PROFILES: &str = "kargs-profiles";
+const BOOTC: &str = "bootc";
 
 /// The set of keys that we parse as BTreeMap and need to ignore ordering changes.
 static UNORDERED_LIST_KEYS: phf::Set<&'static str> = phf::phf_set! {
     "packages/requested",
     "packages/local",
     "packages/local-fileoverride",
+    "packages/transient",
+    "packages/local-unverified",
     "modules/enable",
     "modules/install",
     "overrides/remove",
@@ -62,13 +67,15 @@ pub(crate) fn origin_to_treefile_inner(kf: &KeyFile) -> Result<Box<Treefile>> {
     cfg.derive.packages_local = parse_localpkglist(kf, PACKAGES, "requested-local")?;
     cfg.derive.packages_local_fileoverride =
         parse_localpkglist(kf, PACKAGES, "requested-local-fileoverride")?;
+    cfg.derive.packages_local_unverified = parse_stringlist(kf, PACKAGES, "local-unverified")?;
+    cfg.derive.packages_transient = parse_transientpkglist(kf, PACKAGES, "transient")?;
     let modules_enable = parse_stringlist(kf, MODULES, "enable")?;
     let modules_install = parse_stringlist(kf, MODULES, "install")?;
     if modules_enable.is_some() || modules_install.is_some() {
-        cfg.modules = Some(crate::treefile::ModulesConfig {
-            enable: modules_enable,
-            install: modules_install,
-        });
+        cfg.modules = Some(crate::treefile::ModulesConfig::new(
+            modules_enable,
+            modules_install,
+        ));
     }
     cfg.derive.override_remove = parse_stringlist(kf, OVERRIDES, "remove")?;
     cfg.derive.override_replace_local = parse_localpkglist(kf, OVERRIDES, "replace-local")?;
@@ -88,10 +95,10 @@ pub(crate) fn origin_to_treefile_inner(kf: &KeyFile) -> Result<Box<Treefile>> {
                 }
                 _ => bail!("Unknown repo replacement source: {}", from),
             };
-            override_replace.push(crate::treefile::RemoteOverrideReplace {
-                from: source,
-                packages: split.map(|s| s.to_string()).collect(),
-            });
+            override_replace.push(crate::treefile::RemoteOverrideReplace::new(
+                source,
+                split.map(|s| s.to_string()).collect(),
+            ));
         }
         cfg.derive.override_replace = Some(override_replace);
     }
@@ -101,18 +108,73 @@ pub(crate) fn origin_to_treefile_inner(kf: &KeyFile) -> Result<Box<Treefile>> {
         .unwrap_or_default();
     let initramfs_etc = parse_stringlist(kf, RPMOSTREE, "initramfs-etc")?;
     let initramfs_args = parse_stringlist(kf, RPMOSTREE, "initramfs-args")?;
-    if regenerate_initramfs || initramfs_etc.is_some() || initramfs_args.is_some() {
-        let initramfs = crate::treefile::DeriveInitramfs {
-            regenerate: regenerate_initramfs,
-            etc: initramfs_etc,
-            args: initramfs_args,
-        };
+    let add_modules = parse_stringlist(kf, RPMOSTREE, "initramfs-add-modules")?;
+    let omit_modules = parse_stringlist(kf, RPMOSTREE, "initramfs-omit-modules")?;
+    let add_drivers = parse_stringlist(kf, RPMOSTREE, "initramfs-add-drivers")?;
+    let generator = keyfile_get_optional_string(kf, RPMOSTREE, "initramfs-generator")?;
+    if regenerate_initramfs
+        || initramfs_etc.is_some()
+        || initramfs_args.is_some()
+        || add_modules.is_some()
+        || omit_modules.is_some()
+        || add_drivers.is_some()
+        || generator.is_some()
+    {
+        let mut initramfs = crate::treefile::DeriveInitramfs::default();
+        initramfs.regenerate = regenerate_initramfs;
+        initramfs.etc = initramfs_etc;
+        initramfs.args = initramfs_args;
+        initramfs.add_modules = add_modules;
+        initramfs.omit_modules = omit_modules;
+        initramfs.add_drivers = add_drivers;
+        initramfs.generator = generator;
         cfg.derive.initramfs = Some(initramfs);
     }
 
+    let kargs_append = parse_stringlist(kf, RPMOSTREE, "kargs-append")?;
+    let kargs_delete = parse_stringlist(kf, RPMOSTREE, "kargs-delete")?;
+    let mut kargs_profiles = BTreeMap::new();
+    if kf.has_group(KARGS_PROFILES) {
+        for name in kf.keys(KARGS_PROFILES)?.0.iter() {
+            let args = parse_stringlist(kf, KARGS_PROFILES, name)?.unwrap_or_default();
+            kargs_profiles.insert(name.to_string(), args);
+        }
+    }
+    let kargs_profiles = Some(kargs_profiles).filter(|p| !p.is_empty());
+    if kargs_append.is_some() || kargs_delete.is_some() || kargs_profiles.is_some() {
+        let mut kargs = crate::treefile::DeriveKargs::default();
+        kargs.append = kargs_append;
+        kargs.delete = kargs_delete;
+        kargs.profiles = kargs_profiles;
+        cfg.derive.kargs = Some(kargs);
+    }
+
+    let mut pull = crate::treefile::DeriveContainerPull::default();
+    pull.proxy = keyfile_get_optional_string(kf, CONTAINER_PULL, "proxy")?;
+    pull.tls_verify = map_keyfile_optional(kf.boolean(CONTAINER_PULL, "tls-verify"))?;
+    pull.retries = map_keyfile_optional(kf.uint64(CONTAINER_PULL, "retries"))?
+        .map(u32::try_from)
+        .transpose()?;
+    pull.timeout = map_keyfile_optional(kf.uint64(CONTAINER_PULL, "timeout"))?;
+    if pull != Default::default() {
+        cfg.derive.container_pull = Some(pull);
+    }
+
+    if kf.has_group(BOOTC) {
+        let mut bootc = BTreeMap::new();
+        for k in kf.keys(BOOTC)?.0.iter() {
+            bootc.insert(k.to_string(), kf.value(BOOTC, k)?.to_string());
+        }
+        cfg.derive.bootc = Some(bootc);
+    }
+
     if let Some(url) = keyfile_get_optional_string(kf, ORIGIN, "custom-url")? {
         let description = keyfile_get_optional_string(kf, ORIGIN, "custom-description")?;
-        cfg.derive.custom = Some(crate::treefile::DeriveCustom { url, description })
+        cfg.derive.custom = Some(crate::treefile::DeriveCustom::new(url, description))
+    }
+
+    if map_keyfile_optional(kf.boolean(RPMOSTREE, "fips"))?.unwrap_or_default() {
+        cfg.derive.fips = Some(true)
     }
 
     if map_keyfile_optional(kf.boolean(RPMOSTREE, "ex-cliwrap"))?.unwrap_or_default() {
@@ -140,6 +202,70 @@ pub(crate) fn treefile_to_origin(tf: &Treefile) -> Result<*mut crate::FFIGKeyFil
 }
 
 /// Set a keyfile value to a string list.
+fn is_container_rebase_with_same_layering(from: &KeyFile, to: &KeyFile) -> Result<bool> {
+    let from = origin_to_treefile_inner(from)?.parsed;
+    let to = origin_to_treefile_inner(to)?.parsed;
+    match (
+        from.derive.container_image_reference.as_ref(),
+        to.derive.container_image_reference.as_ref(),
+    ) {
+        (Some(a), Some(b)) if a != b => {}
+        _ => return Ok(false),
+    }
+    // Modules and remote overrides are resolved against rpm-md every time
+    if to.modules.is_some() || to.derive.override_replace.is_some() {
+        return Ok(false);
+    }
+    Ok(from.packages == to.packages
+        && from.derive.packages_local == to.derive.packages_local
+        && from.derive.packages_local_fileoverride == to.derive.packages_local_fileoverride
+        && from.derive.override_remove == to.derive.override_remove
+        && from.derive.override_replace_local == to.derive.override_replace_local)
+}
+
+/// Whether going from origin `from` to `to` only switches the base container
+/// image, leaving the layered packages and overrides as they are.  In that case
+/// the packages can be resolved again from the package cache, without rpm-md.
+pub(crate) fn origin_is_container_rebase_with_same_layering(
+    from: &crate::ffi::GKeyFile,
+    to: &crate::ffi::GKeyFile,
+) -> CxxResult<bool> {
+    Ok(is_container_rebase_with_same_layering(
+        &from.glib_reborrow(),
+        &to.glib_reborrow(),
+    )?)
+}
+
+fn is_package_addition(from: &KeyFile, to: &KeyFile) -> Result<bool> {
+    let mut from = origin_to_treefile_inner(from)?.parsed;
+    let mut to = origin_to_treefile_inner(to)?.parsed;
+    // Overrides and modules can't be applied on top of a tree which already has them
+    if to.modules.is_some()
+        || to.derive.override_remove.is_some()
+        || to.derive.override_replace.is_some()
+        || to.derive.override_replace_local.is_some()
+        || to.derive.packages_local.is_some()
+        || to.derive.packages_local_fileoverride.is_some()
+    {
+        return Ok(false);
+    }
+    let from_pkgs = from.packages.take().unwrap_or_default();
+    let to_pkgs = to.packages.take().unwrap_or_default();
+    Ok(from_pkgs.len() < to_pkgs.len() && from_pkgs.is_subset(&to_pkgs) && from == to)
+}
+
+/// Whether going from origin `from` to `to` only requests additional packages.
+/// In that case they can be layered on top of the tree built for `from`.
+pub(crate) fn origin_is_package_addition(
+    from: &crate::ffi::GKeyFile,
+    to: &crate::ffi::GKeyFile,
+) -> CxxResult<bool> {
+    Ok(is_package_addition(
+        &from.glib_reborrow(),
+        &to.glib_reborrow(),
+    )?)
+}
+
 fn kf_set_string_list_optional<'a>(
     kf: &glib::KeyFile,
     group: impl AsRef<str>,
@@ -201,6 +327,31 @@ fn treefile_to_origin_inner(tf: &Treefile) -> Result<glib::KeyFile> {
     if let Some(pkgs) = tf.derive.packages_local_fileoverride.as_ref() {
         set_sha256_nevra_pkgs(&kf, PACKAGES, "requested-local-fileoverride", pkgs)
     }
+    if let Some(pkgs) = tf.derive.packages_local_unverified.as_ref() {
+        // Only keep track of local packages which are still requested
+        let pkgs = pkgs
+            .iter()
+            .filter(|nevra| {
+                [
+                    &tf.derive.packages_local,
+                    &tf.derive.packages_local_fileoverride,
+                    &tf.derive.override_replace_local,
+                ]
+                .into_iter()
+                .filter_map(Option::as_ref)
+                .any(|m| m.contains_key(*nevra))
+            })
+            .map(|s| s.as_str());
+        kf_set_string_list_optional(&kf, PACKAGES, "local-unverified", pkgs)
+    }
+    if let Some(pkgs) = tf.derive.packages_transient.as_ref() {
+        let pkgs: Vec<_> = pkgs
+            .iter()
+            .map(|(pkg, boots)| format!("{}:{}", pkg, boots))
+            .collect();
+        let pkgs = pkgs.iter().map(|s| s.as_str());
+        kf_set_string_list_optional(&kf, PACKAGES, "transient", pkgs)
+    }
     if let Some(pkgs) = tf.derive.override_remove.as_ref() {
         let pkgs = pkgs.iter().map(|s| s.as_str());
         kf_set_string_list_optional(&kf, OVERRIDES, "remove", pkgs)
@@ -246,6 +397,55 @@ fn treefile_to_origin_inner(tf: &Treefile) -> Result<glib::KeyFile> {
             let args = args.iter().map(|s| s.as_str());
             kf_set_string_list_optional(&kf, RPMOSTREE, "initramfs-args", args)
         }
+        for (key, names) in [
+            ("initramfs-add-modules", &initramfs.add_modules),
+            ("initramfs-omit-modules", &initramfs.omit_modules),
+            ("initramfs-add-drivers", &initramfs.add_drivers),
+        ] {
+            if let Some(names) = names.as_ref() {
+                let names = names.iter().map(|s| s.as_str());
+                kf_set_string_list_optional(&kf, RPMOSTREE, key, names)
+            }
+        }
+        if let Some(generator) = initramfs.generator.as_deref() {
+            kf.set_string(RPMOSTREE, "initramfs-generator", generator);
+        }
+    }
+
+    if let Some(kargs) = tf.derive.kargs.as_ref() {
+        if let Some(args) = kargs.append.as_deref() {
+            let args = args.iter().map(|s| s.as_str());
+            kf_set_string_list_optional(&kf, RPMOSTREE, "kargs-append", args)
+        }
+        if let Some(args) = kargs.delete.as_deref() {
+            let args = args.iter().map(|s| s.as_str());
+            kf_set_string_list_optional(&kf, RPMOSTREE, "kargs-delete", args)
+        }
+        // Written even if empty, unlike the lists above
+        for (name, args) in kargs.profiles.iter().flatten() {
+            let v: String = args.iter().map(|a| format!("{};", a)).collect();
+            kf.set_value(KARGS_PROFILES, name, &v);
+        }
+    }
+
+    if let Some(pull) = tf.derive.container_pull.as_ref() {
+        if let Some(proxy) = pull.proxy.as_deref() {
+            kf.set_string(CONTAINER_PULL, "proxy", proxy);
+        }
+        if let Some(v) = pull.tls_verify {
+            kf.set_boolean(CONTAINER_PULL, "tls-verify", v);
+        }
+        if let Some(v) = pull.retries {
+            kf.set_uint64(CONTAINER_PULL, "retries", v.into());
+        }
+        if let Some(v) = pull.timeout {
+            kf.set_uint64(CONTAINER_PULL, "timeout", v);
+        }
+    }
+
+    // Verbatim, as this is bootc's
+    for (k, v) in tf.derive.bootc.iter().flatten() {
+        kf.set_value(BOOTC, k, v);
     }
 
     // Custom origin
@@ -256,6 +456,10 @@ fn treefile_to_origin_inner(tf: &Treefile) -> Result<glib::KeyFile> {
         }
     }
 
+    if tf.derive.fips.unwrap_or_default() {
+        kf.set_boolean(RPMOSTREE, "fips", true)
+    }
+
     if tf.cliwrap.unwrap_or_default() {
         kf.set_boolean(RPMOSTREE, "ex-cliwrap", true)
     }
@@ -346,7 +550,9 @@ pub(crate) fn origin_validate_roundtrip(kf: &crate::ffi::GKeyFile) {
     }
 }
 
-fn map_keyfile_optional<T>(res: StdResult<T, glib::Error>) -> StdResult<Option<T>, glib::Error> {
+pub(crate) fn map_keyfile_optional<T>(
+    res: StdResult<T, glib::Error>,
+) -> StdResult<Option<T>, glib::Error> {
     match res {
         Ok(v) => Ok(Some(v)),
         Err(e) => {
@@ -388,6 +594,29 @@ fn parse_localpkglist(
     }
 }
 
+/// Parse a list of `PKG:BOOTS` entries.
+fn parse_transientpkglist(
+    kf: &KeyFile,
+    group: &str,
+    key: &str,
+) -> Result<Option<BTreeMap<String, u32>>> {
+    if let Some(v) = map_keyfile_optional(kf.string_list(group, key))? {
+        let mut r = BTreeMap::new();
+        for s in v {
+            let (pkg, boots) = s
+                .rsplit_once(':')
+                .ok_or_else(|| anyhow!("Invalid transient package entry: {}", s))?;
+            let boots: u32 = boots
+                .parse()
+                .map_err(|e| anyhow!("Invalid boot count in transient package {}: {}", s, e))?;
+            r.insert(pkg.to_string(), boots);
+        }
+        Ok(Some(r))
+    } else {
+        Ok(None)
+    }
+}
+
 fn keyfile_get_optional_string(kf: &KeyFile, group: &str, key: &str) -> Result<Option<String>> {
     Ok(map_keyfile_optional(kf.string(group, key))?.map(|v| v.to_string()))
 }
@@ -424,7 +653,9 @@ pub(crate) mod test {
 
     [packages]
     requested=libvirt;fish;
+    transient=fish:2;
     requested-local=4ed748ba060fce4571e7ef19f3f5ed6209f67dbac8327af0d38ea70b96d2f723:foo-1.2-3.x86_64;
+    local-unverified=foo-1.2-3.x86_64;
 
     [modules]
     enable=foo:2.0;bar:rolling;
@@ -492,39 +723,229 @@ pub(crate) mod test {
             tf.parsed.derive.override_commit.unwrap(),
             "41af286dc0b172ed2f1ca934fd2278de4a1192302ffa07087cea2682e7d372e3"
         );
+        assert_eq!(
+            tf.parsed.derive.packages_transient,
+            Some(maplit::btreemap!("fish".into() => 2))
+        );
+        assert_eq!(
+            tf.parsed.derive.packages_local_unverified,
+            Some(maplit::btreeset!("foo-1.2-3.x86_64".into()))
+        );
         assert_eq!(
             tf.parsed.modules,
-            Some(crate::treefile::ModulesConfig {
-                enable: Some(maplit::btreeset!("foo:2.0".into(), "bar:rolling".into(),)),
-                install: Some(maplit::btreeset!("baz:next/development".into())),
-            })
+            Some(crate::treefile::ModulesConfig::new(
+                Some(maplit::btreeset!("foo:2.0".into(), "bar:rolling".into(),)),
+                Some(maplit::btreeset!("baz:next/development".into())),
+            ))
         );
         assert_eq!(
             tf.parsed.derive.override_replace,
             Some(vec![
-                crate::treefile::RemoteOverrideReplace {
-                    from: crate::treefile::RemoteOverrideReplaceFrom::Repo("foobar".into()),
-                    packages: maplit::btreeset!("systemd".into()),
-                },
-                crate::treefile::RemoteOverrideReplace {
-                    from: crate::treefile::RemoteOverrideReplaceFrom::Repo("bazboo".into()),
-                    packages: maplit::btreeset!(
+                crate::treefile::RemoteOverrideReplace::new(
+                    crate::treefile::RemoteOverrideReplaceFrom::Repo("foobar".into()),
+                    maplit::btreeset!("systemd".into()),
+                ),
+                crate::treefile::RemoteOverrideReplace::new(
+                    crate::treefile::RemoteOverrideReplaceFrom::Repo("bazboo".into()),
+                    maplit::btreeset!(
                         "kernel".into(),
                         "kernel-core".into(),
                         "kernel-modules".into()
                     ),
-                }
+                )
             ])
         );
         Ok(())
     }
 
+    #[test]
+    fn test_container_rebase_with_same_layering() -> Result<()> {
+        let image = |img: &str, pkgs: &str| {
+            kf_from_str(&format!(
+                "[origin]\ncontainer-image-reference={}\n\n[packages]\nrequested={}\n",
+                img, pkgs
+            ))
+        };
+        let a = image(
+            "ostree-unverified-registry:quay.io/example/os:36",
+            "vim;strace;",
+        )?;
+        let b = image(
+            "ostree-unverified-registry:quay.io/example/os:37",
+            "strace;vim;",
+        )?;
+        assert!(is_container_rebase_with_same_layering(&a, &b)?);
+        // Not a rebase
+        assert!(!is_container_rebase_with_same_layering(&a, &a)?);
+        // Changed packages
+        let c = image("ostree-unverified-registry:quay.io/example/os:37", "vim;")?;
+        assert!(!is_container_rebase_with_same_layering(&a, &c)?);
+        // Not container images
+        let kf = kf_from_str(BASE)?;
+        assert!(!is_container_rebase_with_same_layering(&kf, &b)?);
+        // Remote overrides always need rpm-md
+        a.set_string_list(OVERRIDES, "replace", &["repo=foobar,systemd"]);
+        b.set_string_list(OVERRIDES, "replace", &["repo=foobar,systemd"]);
+        assert!(!is_container_rebase_with_same_layering(&a, &b)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_package_addition() -> Result<()> {
+        let pkgs = |pkgs: &str| kf_from_str(&format!("{}\n[packages]\nrequested={}\n", BASE, pkgs));
+        let a = pkgs("vim;")?;
+        let b = pkgs("strace;vim;")?;
+        assert!(is_package_addition(&a, &b)?);
+        assert!(!is_package_addition(&b, &a)?);
+        assert!(!is_package_addition(&a, &a)?);
+        assert!(is_package_addition(&kf_from_str(BASE)?, &a)?);
+        // Not only packages changed
+        b.set_string(ORIGIN, "refspec", "foo:bar/x86_64/other");
+        assert!(!is_package_addition(&a, &b)?);
+        // Overrides can't be amended
+        let c = pkgs("strace;vim;")?;
+        assert!(is_package_addition(&a, &c)?);
+        a.set_string_list(OVERRIDES, "remove", &["nano"]);
+        c.set_string_list(OVERRIDES, "remove", &["nano"]);
+        assert!(!is_package_addition(&a, &c)?);
+        Ok(())
+    }
+
     #[test]
     fn test_origin_roundtrip() -> Result<()> {
         let kf = kf_from_str(BASE)?;
         origin_validate_roundtrip_inner(&kf).expect("validating BASE");
         let kf = kf_from_str(COMPLEX)?;
         origin_validate_roundtrip_inner(&kf).expect("validating COMPLEX");
+        let kf = kf_from_str(indoc! {"
+            [origin]
+            container-image-reference=ostree-unverified-registry:quay.io/example/os:stable
+
+            [container-pull]
+            proxy=http://proxy.example.com:3128
+            tls-verify=false
+            retries=3
+        "})?;
+        origin_validate_roundtrip_inner(&kf).expect("validating container-pull");
+        let tf = origin_to_treefile_inner(&kf)?;
+        let pull = tf.parsed.derive.container_pull.as_ref().unwrap();
+        assert_eq!(pull.tls_verify, Some(false));
+        assert_eq!(pull.retries, Some(3));
+        assert!(pull.timeout.is_none());
+        let kf = kf_from_str(indoc! {"
+            [origin]
+            container-image-reference=ostree-unverified-registry:quay.io/example/os:stable
+
+            [bootc]
+            backend=ostree
+            pinned=1
+        "})?;
+        origin_validate_roundtrip_inner(&kf).expect("validating bootc");
+        let tf = origin_to_treefile_inner(&kf)?;
+        let bootc = tf.parsed.derive.bootc.as_ref().unwrap();
+        assert_eq!(bootc["backend"], "ostree");
+        let kf = kf_from_str(indoc! {"
+            [origin]
+            refspec=fedora:fedora/36/x86_64/silverblue
+
+            [rpmostree]
+            kargs-append=console=ttyS0,115200n8;mitigations=off;
+            kargs-delete=quiet;
+        "})?;
+        origin_validate_roundtrip_inner(&kf).expect("validating kargs");
+        let tf = origin_to_treefile_inner(&kf)?;
+        assert_eq!(
+            tf.get_kargs_append(),
+            ["console=ttyS0,115200n8", "mitigations=off"]
+        );
+        assert_eq!(tf.get_kargs_delete(), ["quiet"]);
+        let kf = kf_from_str(indoc! {"
+            [origin]
+            refspec=fedora:fedora/36/x86_64/silverblue
+
+            [rpmostree]
+            kargs-append=systemd.log_level=debug;
+
+            [kargs-profiles]
+            debug=systemd.log_level=debug;
+            nomodeset=
+        "})?;
+        origin_validate_roundtrip_inner(&kf).expect("validating kargs profiles");
+        let tf = origin_to_treefile_inner(&kf)?;
+        let profiles = tf.parsed.derive.kargs.as_ref().unwrap().profiles.as_ref();
+        assert_eq!(
+            profiles.unwrap().keys().collect::<Vec<_>>(),
+            ["debug", "nomodeset"]
+        );
+        let kf = kf_from_str(indoc! {"
+            [origin]
+            refspec=fedora:fedora/36/x86_64/silverblue
+
+            [rpmostree]
+            regenerate-initramfs=true
+            initramfs-add-modules=crypt-ssh;nfs;
+            initramfs-omit-modules=plymouth;
+            initramfs-add-drivers=nvme;
+        "})?;
+        origin_validate_roundtrip_inner(&kf).expect("validating dracut config");
+        let tf = origin_to_treefile_inner(&kf)?;
+        assert!(tf
+            .get_initramfs_dracut_conf()
+            .contains("add_dracutmodules+=\" crypt-ssh nfs \"\n"));
+        let kf = kf_from_str(indoc! {"
+            [origin]
+            refspec=fedora:fedora/36/x86_64/silverblue
+
+            [rpmostree]
+            regenerate-initramfs=true
+            initramfs-generator=mkosi-initrd
+        "})?;
+        origin_validate_roundtrip_inner(&kf).expect("validating initramfs generator");
+        let tf = origin_to_treefile_inner(&kf)?;
+        assert_eq!(tf.get_initramfs_generator(), "mkosi-initrd");
+        let kf = kf_from_str(indoc! {"
+            [origin]
+            refspec=fedora:fedora/36/x86_64/silverblue
+
+            [packages]
+            requested=libkcapi-hmaccalc;
+
+            [rpmostree]
+            regenerate-initramfs=true
+            initramfs-add-modules=fips;
+            kargs-append=fips=1;
+            fips=true
+        "})?;
+        origin_validate_roundtrip_inner(&kf).expect("validating fips");
+        let tf = origin_to_treefile_inner(&kf)?;
+        assert!(tf.get_fips());
+        Ok(())
+    }
+
+    #[test]
+    fn test_local_unverified() -> Result<()> {
+        let mut tf = origin_to_treefile_inner(&kf_from_str(COMPLEX)?)?;
+        tf.set_local_package_verified("rpm-ostree-2021.1-2.fc33.x86_64", false);
+        tf.set_local_package_verified("foo-1.2-3.x86_64", true);
+        let kf = treefile_to_origin_inner(&tf)?;
+        assert_eq!(
+            kf.value(PACKAGES, "local-unverified")?.as_str(),
+            "rpm-ostree-2021.1-2.fc33.x86_64;"
+        );
+        // Entries for packages which are no longer requested are dropped
+        tf.remove_package_override_replace_local("rpm-ostree-2021.1-2.fc33.x86_64");
+        let kf = treefile_to_origin_inner(&tf)?;
+        assert!(kf.value(PACKAGES, "local-unverified").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_origin_json() -> Result<()> {
+        let tf = origin_to_treefile_inner(&kf_from_str(COMPLEX)?)?;
+        let v: serde_json::Value = serde_json::from_str(&tf.get_json_string())?;
+        assert_eq!(v["base-refspec"], "fedora:fedora/34/x86_64/silverblue");
+        assert_eq!(v["override-remove"], serde_json::json!(["docker"]));
+        assert_eq!(v["packages-transient"]["fish"], 2);
         Ok(())
     }
 }
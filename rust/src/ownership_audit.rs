@@ -0,0 +1,240 @@
+//! Implementation of the treefile `ownership-audit` field: at the end of a
+//! compose, check that the owner and group of every file of the tree resolve
+//! to entries of the composed passwd and group databases (including the
+//! nss-altfiles ones in /usr/lib), and report the files which don't along with
+//! the packages shipping them.  Such files typically come from packages
+//! relying on users created outside of sysusers or `%pre` scripts.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::cxxrsutil::*;
+use crate::passwd::PasswdDB;
+use crate::treefile::Treefile;
+use anyhow::{anyhow, Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use cap_std::fs::Dir;
+use cap_std_ext::cap_std;
+use cap_std_ext::rustix::fs::MetadataExt;
+use serde_derive::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FileOwner {
+    /// Path relative to the rootfs.
+    path: Utf8PathBuf,
+    uid: u32,
+    gid: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Unresolved {
+    User,
+    Group,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+struct Violation {
+    /// Absolute path as installed, i.e. with /usr/etc as /etc.
+    path: String,
+    uid: u32,
+    gid: u32,
+    unresolved: Vec<Unresolved>,
+    packages: Vec<String>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+struct AuditReport {
+    checked: usize,
+    violations: Vec<Violation>,
+}
+
+/// The path of `path`, relative to the rootfs, on a deployed system.
+fn installed_path(path: &Utf8Path) -> String {
+    match path.strip_prefix("usr/etc") {
+        Ok(p) => Utf8Path::new("/etc").join(p).into_string(),
+        Err(_) => Utf8Path::new("/").join(path).into_string(),
+    }
+}
+
+/// Recursively collect the owners of the entries under `path`, without
+/// following symlinks.
+fn collect_owners(rootfs: &Dir, path: &Utf8Path, owners: &mut Vec<FileOwner>) -> Result<()> {
+    let dir = if path.as_str().is_empty() {
+        rootfs.try_clone()?
+    } else {
+        rootfs.open_dir(path)?
+    };
+    for entry in dir.entries()? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name
+            .to_str()
+            .ok_or_else(|| anyhow!("Invalid UTF-8 filename in /{}", path))?;
+        let child = path.join(name);
+        let meta = entry.metadata()?;
+        owners.push(FileOwner {
+            path: child.clone(),
+            uid: meta.uid(),
+            gid: meta.gid(),
+        });
+        if meta.is_dir() {
+            collect_owners(rootfs, &child, owners)?;
+        }
+    }
+    Ok(())
+}
+
+/// Check `files` against `db`; `packages` are the packages owning each
+/// installed path.
+fn audit(
+    files: &[FileOwner],
+    db: &PasswdDB,
+    packages: &BTreeMap<String, BTreeSet<String>>,
+) -> AuditReport {
+    let mut report = AuditReport {
+        checked: files.len(),
+        ..Default::default()
+    };
+    for f in files {
+        let mut unresolved = Vec::new();
+        if db.lookup_user(f.uid).is_err() {
+            unresolved.push(Unresolved::User);
+        }
+        if db.lookup_group(f.gid).is_err() {
+            unresolved.push(Unresolved::Group);
+        }
+        if unresolved.is_empty() {
+            continue;
+        }
+        let path = installed_path(&f.path);
+        let packages = packages
+            .get(&path)
+            .map(|p| p.iter().cloned().collect())
+            .unwrap_or_default();
+        report.violations.push(Violation {
+            path,
+            uid: f.uid,
+            gid: f.gid,
+            unresolved,
+            packages,
+        });
+    }
+    report
+}
+
+fn audit_rootfs(rootfs_dfd: i32, rootfs: &Dir) -> Result<AuditReport> {
+    let db = PasswdDB::populate_new(rootfs)?;
+    let mut files = Vec::new();
+    collect_owners(rootfs, Utf8Path::new(""), &mut files)?;
+    // Only query the rpmdb if there is something to attribute
+    let no_packages = BTreeMap::new();
+    let report = audit(&files, &db, &no_packages);
+    if report.violations.is_empty() || !rootfs.try_exists(crate::RPMOSTREE_RPMDB_LOCATION)? {
+        return Ok(report);
+    }
+    let root = format!("/proc/self/fd/{}", rootfs_dfd);
+    let packages = crate::verify::file_owners(Path::new(&root))?;
+    Ok(audit(&files, &db, &packages))
+}
+
+/// Check that the owners of all files of the tree resolve, if
+/// `ownership-audit` is set.
+pub(crate) fn compose_ownership_audit(rootfs_dfd: i32, treefile: &Treefile) -> CxxResult<()> {
+    let config = match treefile.parsed.base.ownership_audit.as_ref() {
+        Some(c) => c,
+        None => return Ok(()),
+    };
+    let rootfs = unsafe { crate::ffiutil::ffi_dirfd(rootfs_dfd)? };
+    println!("Auditing file ownership");
+    let report = audit_rootfs(rootfs_dfd, &rootfs).context("Auditing file ownership")?;
+    for v in report.violations.iter() {
+        let packages = if v.packages.is_empty() {
+            "no package".to_string()
+        } else {
+            v.packages.join(", ")
+        };
+        println!("  {}:{} {} ({})", v.uid, v.gid, v.path, packages);
+    }
+    println!(
+        "Checked ownership of {} files: {} with an unresolvable owner",
+        report.checked,
+        report.violations.len()
+    );
+    if let Some(path) = config.report.as_deref() {
+        let path = Utf8Path::new(treefile.get_workdir()).join(path);
+        let mut buf = serde_json::to_vec_pretty(&report)?;
+        buf.push(b'\n');
+        std::fs::write(&path, buf).with_context(|| format!("Writing {}", path))?;
+        println!("Wrote ownership audit report to {}", path);
+    }
+    if config.fatal.unwrap_or(false) && !report.violations.is_empty() {
+        return Err(anyhow!(
+            "ownership-audit: {} files with an unresolvable owner, e.g. {}",
+            report.violations.len(),
+            report.violations[0].path
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audit() -> Result<()> {
+        let td = cap_tempfile::tempdir(cap_std::ambient_authority())?;
+        td.create_dir_all("usr/etc")?;
+        td.write("usr/etc/passwd", "root:x:0:0:root:/root:/bin/bash\n")?;
+        td.write("usr/etc/group", "root:x:0:\n")?;
+        td.create_dir_all("usr/lib")?;
+        td.write(
+            "usr/lib/passwd",
+            "chrony:x:991:987::/var/lib/chrony:/sbin/nologin\n",
+        )?;
+        td.write("usr/lib/group", "chrony:x:987:\n")?;
+        let db = PasswdDB::populate_new(&td)?;
+
+        let file = |path: &str, uid, gid| FileOwner {
+            path: path.into(),
+            uid,
+            gid,
+        };
+        let files = [
+            file("usr/bin/bash", 0, 0),
+            file("var/lib/chrony", 991, 987),
+            file("usr/etc/foo.conf", 0, 990),
+            file("usr/libexec/bar", 1234, 1234),
+        ];
+        let mut packages: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        packages
+            .entry("/etc/foo.conf".into())
+            .or_default()
+            .insert("foo-1.0-1.x86_64".into());
+        let report = audit(&files, &db, &packages);
+        assert_eq!(report.checked, 4);
+        assert_eq!(
+            report.violations,
+            [
+                Violation {
+                    path: "/etc/foo.conf".into(),
+                    uid: 0,
+                    gid: 990,
+                    unresolved: vec![Unresolved::Group],
+                    packages: vec!["foo-1.0-1.x86_64".into()],
+                },
+                Violation {
+                    path: "/usr/libexec/bar".into(),
+                    uid: 1234,
+                    gid: 1234,
+                    unresolved: vec![Unresolved::User, Unresolved::Group],
+                    packages: vec![],
+                },
+            ]
+        );
+        Ok(())
+    }
+}
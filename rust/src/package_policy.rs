@@ -0,0 +1,352 @@
+//! Implementation of the treefile `forbidden-licenses` and
+//! `forbidden-packages` fields: after depsolving, check the packages of the
+//! transaction against them, and fail the compose with a report of all the
+//! violations.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::cxxrsutil::*;
+use crate::ffi::PolicyPackage;
+use crate::treefile::Treefile;
+use anyhow::{anyhow, bail, Result};
+use regex::Regex;
+use std::fmt::Write;
+
+/// A parsed license expression, e.g. `GPL-2.0-or-later AND (MIT OR BSD-3-Clause)`,
+/// or in the older Fedora style `GPLv2+ and (MIT or BSD)`.
+#[derive(Debug, PartialEq, Eq)]
+enum LicenseExpr {
+    /// A license, with its exception if any.  Old-style names may contain
+    /// spaces, e.g. `Public Domain`.
+    License(String, Option<String>),
+    And(Vec<LicenseExpr>),
+    Or(Vec<LicenseExpr>),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    And,
+    Or,
+    With,
+    Open,
+    Close,
+}
+
+fn tokenize(s: &str) -> Vec<Token> {
+    let mut r = Vec::new();
+    for word in s.replace('(', " ( ").replace(')', " ) ").split_whitespace() {
+        let t = match word {
+            "(" => Token::Open,
+            ")" => Token::Close,
+            w if w.eq_ignore_ascii_case("and") => Token::And,
+            w if w.eq_ignore_ascii_case("or") => Token::Or,
+            w if w.eq_ignore_ascii_case("with") => Token::With,
+            w => Token::Word(w.to_string()),
+        };
+        r.push(t);
+    }
+    r
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next_if(&mut self, t: Token) -> bool {
+        if self.peek() == Some(&t) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Consecutive words form a single name.
+    fn name(&mut self) -> Result<String> {
+        let mut words = Vec::new();
+        while let Some(Token::Word(w)) = self.peek() {
+            words.push(w.clone());
+            self.pos += 1;
+        }
+        if words.is_empty() {
+            bail!("Expected license name");
+        }
+        Ok(words.join(" "))
+    }
+
+    fn atom(&mut self) -> Result<LicenseExpr> {
+        if self.next_if(Token::Open) {
+            let e = self.or()?;
+            if !self.next_if(Token::Close) {
+                bail!("Unbalanced parentheses");
+            }
+            return Ok(e);
+        }
+        let name = self.name()?;
+        let exception = if self.next_if(Token::With) {
+            Some(self.name()?)
+        } else {
+            None
+        };
+        Ok(LicenseExpr::License(name, exception))
+    }
+
+    fn and(&mut self) -> Result<LicenseExpr> {
+        let mut terms = vec![self.atom()?];
+        while self.next_if(Token::And) {
+            terms.push(self.atom()?);
+        }
+        Ok(if terms.len() == 1 {
+            terms.pop().unwrap()
+        } else {
+            LicenseExpr::And(terms)
+        })
+    }
+
+    fn or(&mut self) -> Result<LicenseExpr> {
+        let mut terms = vec![self.and()?];
+        while self.next_if(Token::Or) {
+            terms.push(self.and()?);
+        }
+        Ok(if terms.len() == 1 {
+            terms.pop().unwrap()
+        } else {
+            LicenseExpr::Or(terms)
+        })
+    }
+}
+
+impl LicenseExpr {
+    fn parse(s: &str) -> Result<Self> {
+        let mut p = Parser {
+            tokens: tokenize(s),
+            pos: 0,
+        };
+        let e = p.or()?;
+        if p.pos != p.tokens.len() {
+            bail!("Unexpected token in license: {}", s);
+        }
+        Ok(e)
+    }
+
+    /// The forbidden licenses this expression can't be satisfied without,
+    /// i.e. for `A OR B` only those required by both alternatives.
+    fn required_forbidden<'a>(&'a self, forbidden: &[Regex], r: &mut Vec<&'a str>) {
+        match self {
+            LicenseExpr::License(name, _) => {
+                if forbidden.iter().any(|re| re.is_match(name)) {
+                    r.push(name);
+                }
+            }
+            LicenseExpr::And(terms) => {
+                for t in terms {
+                    t.required_forbidden(forbidden, r);
+                }
+            }
+            LicenseExpr::Or(terms) => {
+                let mut alternatives = terms.iter().map(|t| {
+                    let mut v = Vec::new();
+                    t.required_forbidden(forbidden, &mut v);
+                    v
+                });
+                // Any alternative without a forbidden license makes it acceptable
+                if let Some(first) = alternatives.next() {
+                    if !first.is_empty() {
+                        let mut all = first;
+                        for v in alternatives {
+                            if v.is_empty() {
+                                return;
+                            }
+                            all.extend(v);
+                        }
+                        r.extend(all);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The forbidden licenses required by the `license` of a package.  If it
+/// can't be parsed, any forbidden license appearing in it counts.
+fn forbidden_licenses_of(license: &str, forbidden: &[Regex]) -> Vec<String> {
+    let mut r: Vec<String> = match LicenseExpr::parse(license) {
+        Ok(e) => {
+            let mut v = Vec::new();
+            e.required_forbidden(forbidden, &mut v);
+            v.into_iter().map(|s| s.to_string()).collect()
+        }
+        Err(_) => tokenize(license)
+            .into_iter()
+            .filter_map(|t| match t {
+                Token::Word(w) if forbidden.iter().any(|re| re.is_match(&w)) => Some(w),
+                _ => None,
+            })
+            .collect(),
+    };
+    r.sort();
+    r.dedup();
+    r
+}
+
+/// Convert a pattern where `*` matches any sequence of characters; license
+/// identifiers are case-insensitive.
+fn glob_to_regex(pattern: &str, case_insensitive: bool) -> Result<Regex> {
+    let re = regex::escape(pattern).replace(r"\*", ".*");
+    let flags = if case_insensitive { "(?i)" } else { "" };
+    Ok(Regex::new(&format!("{}^{}$", flags, re))?)
+}
+
+fn check_packages(
+    forbidden_licenses: &[String],
+    forbidden_packages: &[String],
+    packages: &[PolicyPackage],
+) -> Result<Option<String>> {
+    let licenses = forbidden_licenses
+        .iter()
+        .map(|p| glob_to_regex(p, true))
+        .collect::<Result<Vec<_>>>()?;
+    let names = forbidden_packages
+        .iter()
+        .map(|p| glob_to_regex(p, false))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut report = String::new();
+    let mut n = 0;
+    for pkg in packages {
+        if names.iter().any(|re| re.is_match(&pkg.name)) {
+            writeln!(report, "  {}: forbidden package", pkg.nevra)?;
+            n += 1;
+        }
+        let bad = forbidden_licenses_of(&pkg.license, &licenses);
+        if !bad.is_empty() {
+            writeln!(
+                report,
+                "  {}: forbidden license {} (License: {})",
+                pkg.nevra,
+                bad.join(", "),
+                pkg.license
+            )?;
+            n += 1;
+        }
+    }
+    if n == 0 {
+        return Ok(None);
+    }
+    Ok(Some(format!(
+        "{} package policy violation(s):\n{}",
+        n,
+        report.trim_end()
+    )))
+}
+
+/// Check the packages of the transaction against the forbidden licenses and
+/// packages of the treefile.
+pub(crate) fn package_policy_check(
+    treefile: &Treefile,
+    packages: &Vec<PolicyPackage>,
+) -> CxxResult<()> {
+    let base = &treefile.parsed.base;
+    let forbidden_licenses = base.forbidden_licenses.as_deref().unwrap_or_default();
+    let forbidden_packages = base.forbidden_packages.as_deref().unwrap_or_default();
+    if forbidden_licenses.is_empty() && forbidden_packages.is_empty() {
+        return Ok(());
+    }
+    if let Some(report) = check_packages(forbidden_licenses, forbidden_packages, packages)? {
+        return Err(anyhow!("{}", report).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pkg(name: &str, license: &str) -> PolicyPackage {
+        PolicyPackage {
+            nevra: format!("{}-1.0-1.x86_64", name),
+            name: name.to_string(),
+            license: license.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        use LicenseExpr::*;
+        let l = |s: &str| License(s.to_string(), None);
+        assert_eq!(LicenseExpr::parse("MIT")?, l("MIT"));
+        assert_eq!(
+            LicenseExpr::parse("GPLv2+ and (LGPLv2 or Public Domain)")?,
+            And(vec![l("GPLv2+"), Or(vec![l("LGPLv2"), l("Public Domain")])])
+        );
+        assert_eq!(
+            LicenseExpr::parse("GPL-2.0-only WITH Classpath-exception-2.0 OR MIT")?,
+            Or(vec![
+                License(
+                    "GPL-2.0-only".into(),
+                    Some("Classpath-exception-2.0".into())
+                ),
+                l("MIT")
+            ])
+        );
+        assert!(LicenseExpr::parse("(MIT").is_err());
+        assert!(LicenseExpr::parse("MIT)").is_err());
+        assert!(LicenseExpr::parse("MIT and").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_forbidden_licenses_of() -> Result<()> {
+        let forbidden = [
+            glob_to_regex("AGPL*", true)?,
+            glob_to_regex("SSPL-1.0", true)?,
+        ];
+        let cases: &[(&str, &[&str])] = &[
+            ("MIT", &[]),
+            ("AGPL-3.0-only", &["AGPL-3.0-only"]),
+            ("agplv3+", &["agplv3+"]),
+            ("MIT AND AGPL-3.0-or-later", &["AGPL-3.0-or-later"]),
+            ("MIT OR AGPL-3.0-or-later", &[]),
+            ("SSPL-1.0 OR AGPL-3.0-only", &["AGPL-3.0-only", "SSPL-1.0"]),
+            ("(SSPL-1.0 OR MIT) AND AGPLv3", &["AGPLv3"]),
+            ("GPLv2 and (MIT or AGPLv3", &["AGPLv3"]),
+        ];
+        for (license, expected) in cases {
+            assert_eq!(
+                forbidden_licenses_of(license, &forbidden),
+                *expected,
+                "{}",
+                license
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_packages() -> Result<()> {
+        let packages = [
+            pkg("bash", "GPL-3.0-or-later"),
+            pkg("mongodb-server", "SSPL-1.0"),
+            pkg("telnet-server", "BSD"),
+            pkg("telnet", "BSD"),
+        ];
+        let licenses = ["sspl-*".to_string()];
+        let names = ["telnet-*".to_string()];
+        assert_eq!(check_packages(&[], &[], &packages)?, None);
+        assert_eq!(
+            check_packages(&licenses, &names, &packages)?.unwrap(),
+            indoc::indoc! {"
+                2 package policy violation(s):
+                  mongodb-server-1.0-1.x86_64: forbidden license SSPL-1.0 (License: SSPL-1.0)
+                  telnet-server-1.0-1.x86_64: forbidden package"}
+        );
+        Ok(())
+    }
+}
@@ -2,11 +2,12 @@
 //! handling the "nss-altfiles" split into `/usr/lib/{passwd,group}`.
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 
+use crate::containers_layers::ImageLayers;
 use crate::cxxrsutil::*;
 use crate::ffiutil;
 use crate::nameservice;
 use crate::normalization;
-use crate::treefile::{CheckGroups, CheckPasswd, Treefile};
+use crate::treefile::{CheckCommit, CheckContainer, CheckGroups, CheckPasswd, Treefile};
 use anyhow::{anyhow, Context, Result};
 use cap_std::fs::Dir;
 use cap_std::fs::OpenOptions;
@@ -19,13 +20,18 @@ use fn_error_context::context;
 use gio::prelude::*;
 use nix::unistd::{Gid, Uid};
 use once_cell::sync::Lazy;
+use ostree_ext::container::OstreeImageReference;
+use ostree_ext::glib::{self, ToVariant};
 use ostree_ext::{gio, ostree};
+use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
-use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
 use std::os::unix::fs::DirBuilderExt;
 use std::os::unix::io::AsRawFd;
 use std::os::unix::prelude::PermissionsExt;
 use std::path::PathBuf;
+use std::sync::Mutex;
+use tokio::runtime::Handle;
 
 const DEFAULT_MODE: u32 = 0o644;
 static DEFAULT_PERMS: Lazy<Permissions> = Lazy::new(|| Permissions::from_mode(DEFAULT_MODE));
@@ -100,14 +106,22 @@ pub fn passwd_cleanup(rootfs_dfd: i32) -> Result<()> {
     Ok(())
 }
 
+/// The range of IDs of regular (human) users and their groups, as in systemd.
+const REGULAR_IDS: std::ops::RangeInclusive<u32> = 1000..=60000;
+
+/// Whether `id` is in the range of regular users and their groups.
+pub(crate) fn is_regular_id(id: u32) -> bool {
+    REGULAR_IDS.contains(&id)
+}
+
 /// Passwd splitting logic.
 ///
 /// This function is taking the /etc/passwd generated in the install root (really
 /// in /usr/etc at this point), and splitting it into two streams: a new
-/// /etc/passwd that just contains the root entry, and /usr/lib/passwd which
-/// contains everything else.
+/// /etc/passwd that just contains the root entry (and regular users if
+/// `keep_regular` is set), and /usr/lib/passwd which contains everything else.
 #[context("Migrating 'passwd' to /usr/lib")]
-pub fn migrate_passwd_except_root(rootfs_dfd: i32) -> CxxResult<()> {
+pub fn migrate_passwd_except_root(rootfs_dfd: i32, keep_regular: bool) -> CxxResult<()> {
     static ETCSRC_PATH: &str = "usr/etc/passwd";
     static USRDEST_PATH: &str = "usr/lib/passwd";
 
@@ -115,7 +129,9 @@ pub fn migrate_passwd_except_root(rootfs_dfd: i32) -> CxxResult<()> {
     let (roots, others): (Vec<_>, Vec<_>) = {
         let src_rd = rootfs.open(ETCSRC_PATH).map(BufReader::new)?;
         let entries = nameservice::passwd::parse_passwd_content(src_rd)?;
-        entries.into_iter().partition(|e| e.uid == 0)
+        entries
+            .into_iter()
+            .partition(|e| e.uid == 0 || (keep_regular && REGULAR_IDS.contains(&e.uid)))
     };
 
     {
@@ -151,10 +167,15 @@ pub fn migrate_passwd_except_root(rootfs_dfd: i32) -> CxxResult<()> {
 ///
 /// This function is taking the /etc/group generated in the install root (really
 /// in /usr/etc at this point), and splitting it into two streams: a new
-/// /etc/group that just contains roots and preserved entries, and /usr/lib/group
-/// which contains everything else.
+/// /etc/group that just contains roots and preserved entries (and the groups of
+/// regular users if `keep_regular` is set), and /usr/lib/group which contains
+/// everything else.
 #[context("Migrating 'group' to /usr/lib")]
-pub fn migrate_group_except_root(rootfs_dfd: i32, preserved_groups: &Vec<String>) -> CxxResult<()> {
+pub fn migrate_group_except_root(
+    rootfs_dfd: i32,
+    preserved_groups: &Vec<String>,
+    keep_regular: bool,
+) -> CxxResult<()> {
     static ETCSRC_PATH: &str = "usr/etc/group";
     static USRDEST_PATH: &str = "usr/lib/group";
 
@@ -162,7 +183,9 @@ pub fn migrate_group_except_root(rootfs_dfd: i32, preserved_groups: &Vec<String>
     let (mut roots_preserved, others): (Vec<_>, Vec<_>) = {
         let src_rd = rootfs.open(ETCSRC_PATH).map(BufReader::new)?;
         let entries = nameservice::group::parse_group_content(src_rd)?;
-        entries.into_iter().partition(|e| e.gid == 0)
+        entries
+            .into_iter()
+            .partition(|e| e.gid == 0 || (keep_regular && REGULAR_IDS.contains(&e.gid)))
     };
 
     {
@@ -355,21 +378,10 @@ fn data_from_json(
 
     let target_etc_filename = format!("{}{}", dest_path, target);
 
-    // Migrate the check data from the specified file to /etc.
-    let mut src_file = if target == "passwd" {
-        let check_passwd_file = match treefile.parsed.get_check_passwd() {
-            CheckPasswd::File(cfg) => cfg,
-            _ => return Ok(false),
-        };
-        treefile.externals.passwd_file_mut(check_passwd_file)?
-    } else if target == "group" {
-        let check_groups_file = match treefile.parsed.get_check_groups() {
-            CheckGroups::File(cfg) => cfg,
-            _ => return Ok(false),
-        };
-        treefile.externals.group_file_mut(check_groups_file)?
-    } else {
-        unreachable!("impossible merge target '{}'", target);
+    // Migrate the check data from the specified reference to /etc.
+    let content = match reference_content(treefile, target)? {
+        Some(c) => c,
+        None => return Ok(false),
     };
 
     let mut seen_names = HashSet::new();
@@ -379,7 +391,7 @@ fn data_from_json(
                 .get_mut()
                 .as_file_mut()
                 .set_permissions(DEFAULT_PERMS.clone())?;
-            let mut buf_rd = BufReader::new(&mut src_file);
+            let mut buf_rd = BufReader::new(content.as_slice());
             append_unique_entries(&mut buf_rd, &mut seen_names, dest_bufwr)
                 .with_context(|| format!("failed to process '{}' content from JSON", &target))?;
             Ok(())
@@ -389,6 +401,183 @@ fn data_from_json(
     Ok(true)
 }
 
+/// The users and groups of a published artifact, which `check-passwd` and
+/// `check-groups` can reference; the `snapshot` type reads them from JSON, as
+/// written by `rpm-ostree compose passwd-snapshot`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct PasswdSnapshot {
+    pub(crate) passwd: Vec<String>,
+    pub(crate) group: Vec<String>,
+}
+
+/// Commits and container images fetched while composing, so that they are
+/// only fetched once for both the passwd and group files.
+static REFERENCES: Lazy<Mutex<HashMap<String, PasswdSnapshot>>> = Lazy::new(Default::default);
+
+impl PasswdSnapshot {
+    /// Collect the entries of `usr/etc` and `usr/lib` in the tree at `root`,
+    /// deduplicated by name like for the previous commit.
+    pub(crate) fn from_root(root: &gio::File) -> Result<Self> {
+        let mut r = Self::default();
+        for (target, dest) in [("passwd", &mut r.passwd), ("group", &mut r.group)] {
+            let mut seen = HashSet::new();
+            for dir in ["usr/etc", "usr/lib"] {
+                let path = root.resolve_relative_path(format!("{}/{}", dir, target));
+                if !path.query_exists(gio::NONE_CANCELLABLE) {
+                    continue;
+                }
+                let stream = path.read(gio::NONE_CANCELLABLE)?.into_read();
+                Self::push_entries(dest, &mut seen, BufReader::new(stream))?;
+            }
+        }
+        Ok(r)
+    }
+
+    fn push_entries(
+        dest: &mut Vec<String>,
+        seen: &mut HashSet<String>,
+        content: impl BufRead,
+    ) -> Result<()> {
+        for line in content.lines() {
+            let line = line?;
+            let name = line.split(':').next().unwrap_or_default();
+            if name.is_empty() || name.starts_with('#') {
+                continue;
+            }
+            if seen.insert(name.to_string()) {
+                dest.push(line);
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn content(&self, target: &str) -> Vec<u8> {
+        let lines = if target == "passwd" {
+            &self.passwd
+        } else {
+            &self.group
+        };
+        let mut r = Vec::new();
+        for l in lines {
+            r.extend_from_slice(l.as_bytes());
+            r.push(b'\n');
+        }
+        r
+    }
+}
+
+/// Pull just the passwd and group files of the commit into a temporary repo.
+#[context("Fetching passwd reference {} from {}", cfg.rev, cfg.repo)]
+fn fetch_commit_reference(cfg: &CheckCommit) -> Result<PasswdSnapshot> {
+    let td = tempfile::tempdir_in("/var/tmp")?;
+    let repo = ostree::Repo::new_for_path(td.path());
+    repo.create(ostree::RepoMode::Archive, gio::NONE_CANCELLABLE)?;
+    let subdirs: Vec<String> = ["usr/etc", "usr/lib"]
+        .iter()
+        .flat_map(|d| {
+            USRLIB_PWGRP_FILES
+                .iter()
+                .map(move |f| format!("/{}/{}", d, f))
+        })
+        .collect();
+    let subdirs: Vec<&str> = subdirs.iter().map(|s| s.as_str()).collect();
+    let options = glib::VariantDict::new(None);
+    options.insert_value("refs", &vec![cfg.rev.as_str()].to_variant());
+    options.insert_value("subdirs", &subdirs.to_variant());
+    repo.pull_with_options(&cfg.repo, &options.end(), None, gio::NONE_CANCELLABLE)?;
+    let rev = repo.require_rev(&cfg.rev)?;
+    let (root, _) = repo.read_commit(&rev, gio::NONE_CANCELLABLE)?;
+    PasswdSnapshot::from_root(&root)
+}
+
+/// Read just the passwd and group files from the layers of the image.
+#[context("Fetching passwd reference {}", cfg.image)]
+fn fetch_container_reference(cfg: &CheckContainer) -> Result<PasswdSnapshot> {
+    let imgref = OstreeImageReference::try_from(cfg.image.as_str())?;
+    // Non-ostree images have them in /etc
+    let files: &[&[&str]] = &[
+        &["usr/etc/passwd", "etc/passwd"],
+        &["usr/lib/passwd"],
+        &["usr/etc/group", "etc/group"],
+        &["usr/lib/group"],
+    ];
+    let found = Handle::current().block_on(async {
+        let image = ImageLayers::open(&imgref).await?;
+        let found = image.find_files(files).await?;
+        image.close().await?;
+        Ok::<_, anyhow::Error>(found)
+    })?;
+    let mut r = PasswdSnapshot::default();
+    let mut found = found.into_iter();
+    for dest in [&mut r.passwd, &mut r.group] {
+        let mut seen = HashSet::new();
+        for content in found.by_ref().take(2).flatten() {
+            PasswdSnapshot::push_entries(dest, &mut seen, content.as_slice())?;
+        }
+    }
+    Ok(r)
+}
+
+fn cached_reference(
+    key: String,
+    fetch: impl FnOnce() -> Result<PasswdSnapshot>,
+) -> Result<PasswdSnapshot> {
+    if let Some(r) = REFERENCES.lock().unwrap().get(&key) {
+        return Ok(r.clone());
+    }
+    let r = fetch()?;
+    REFERENCES.lock().unwrap().insert(key, r.clone());
+    Ok(r)
+}
+
+/// The `target` ("passwd" or "group") entries that `check-passwd` or
+/// `check-groups` reference, if they are of type file, snapshot, commit or
+/// container.
+fn reference_content(treefile: &mut Treefile, target: &str) -> Result<Option<Vec<u8>>> {
+    let Treefile {
+        parsed, externals, ..
+    } = treefile;
+    let snapshot = |f: &mut std::fs::File| -> Result<PasswdSnapshot> {
+        serde_json::from_reader(BufReader::new(f)).context("Parsing passwd snapshot")
+    };
+    let commit = |c: &CheckCommit| {
+        cached_reference(format!("commit:{}:{}", c.repo, c.rev), || {
+            fetch_commit_reference(c)
+        })
+    };
+    let container = |c: &CheckContainer| {
+        cached_reference(format!("container:{}", c.image), || {
+            fetch_container_reference(c)
+        })
+    };
+    let r = match target {
+        "passwd" => match parsed.get_check_passwd() {
+            CheckPasswd::File(f) => {
+                let mut buf = Vec::new();
+                externals.passwd_file_mut(f)?.read_to_end(&mut buf)?;
+                buf
+            }
+            CheckPasswd::Snapshot(f) => snapshot(externals.passwd_file_mut(f)?)?.content(target),
+            CheckPasswd::Commit(c) => commit(c)?.content(target),
+            CheckPasswd::Container(c) => container(c)?.content(target),
+            _ => return Ok(None),
+        },
+        "group" => match parsed.get_check_groups() {
+            CheckGroups::File(f) => {
+                let mut buf = Vec::new();
+                externals.group_file_mut(f)?.read_to_end(&mut buf)?;
+                buf
+            }
+            CheckGroups::Snapshot(f) => snapshot(externals.group_file_mut(f)?)?.content(target),
+            CheckGroups::Commit(c) => commit(c)?.content(target),
+            CheckGroups::Container(c) => container(c)?.content(target),
+            _ => return Ok(None),
+        },
+        x => anyhow::bail!("invalid reference target '{}'", x),
+    };
+    Ok(Some(r))
+}
+
 /// Merge and deduplicate entries from /usr/etc and /usr/lib.
 fn concat_fs_content(rootfs: &Dir, repo: &ostree::Repo, previous_checksum: &str) -> Result<()> {
     anyhow::ensure!(!previous_checksum.is_empty(), "missing previous reference");
@@ -555,10 +744,16 @@ pub fn check_passwd_group_entries(
         repo_previous_rev = Some((repo.as_ref(), previous_rev));
     }
 
-    // Parse entries in the upcoming commit content.
+    // Parse entries in the upcoming commit content; without nss-altfiles,
+    // they all stay in /etc.
+    let dir = if has_usrlib_passwd(&rootfs)? {
+        "usr/lib"
+    } else {
+        "usr/etc"
+    };
     let mut new_entities = PasswdEntries::default();
-    new_entities.add_passwd_content(rootfs.as_raw_fd(), "usr/lib/passwd")?;
-    new_entities.add_group_content(rootfs.as_raw_fd(), "usr/lib/group")?;
+    new_entities.add_passwd_content(rootfs.as_raw_fd(), &format!("{}/passwd", dir))?;
+    new_entities.add_group_content(rootfs.as_raw_fd(), &format!("{}/group", dir))?;
 
     // Fetch entries from treefile and previous commit, according to config.
     // These are used as ground-truth by the validation steps below.
@@ -582,6 +777,8 @@ pub fn check_passwd_group_entries(
         &treefile.parsed.base.ignore_removed_groups,
     )?;
 
+    crate::pinned_ids::check_pinned_ids(&rootfs, treefile)?;
+
     Ok(())
 }
 
@@ -606,9 +803,12 @@ impl PasswdDB {
     pub(crate) fn populate_new(rootfs: &Dir) -> Result<Self> {
         let mut db = Self::default();
         db.add_passwd_content(rootfs.as_raw_fd(), "usr/etc/passwd")?;
-        db.add_passwd_content(rootfs.as_raw_fd(), "usr/lib/passwd")?;
         db.add_group_content(rootfs.as_raw_fd(), "usr/etc/group")?;
-        db.add_group_content(rootfs.as_raw_fd(), "usr/lib/group")?;
+        // Not there if the treefile retires nss-altfiles
+        if has_usrlib_passwd(rootfs)? {
+            db.add_passwd_content(rootfs.as_raw_fd(), "usr/lib/passwd")?;
+            db.add_group_content(rootfs.as_raw_fd(), "usr/lib/group")?;
+        }
         Ok(db)
     }
 
@@ -723,6 +923,16 @@ impl PasswdEntries {
         Ok(username)
     }
 
+    /// Lookup the primary group ID of a user by name.
+    pub fn lookup_user_group_id(&self, username: &str) -> CxxResult<u32> {
+        let gid = self
+            .users
+            .get(username)
+            .map(|user| user.1.as_raw())
+            .ok_or_else(|| anyhow!("failed to find user '{}'", username))?;
+        Ok(gid)
+    }
+
     /// Lookup group ID by name.
     pub fn lookup_group_id(&self, groupname: &str) -> CxxResult<u32> {
         let groupname = self
@@ -739,21 +949,21 @@ impl PasswdEntries {
         treefile: &mut Treefile,
         repo_previous_rev: &Option<(&ostree::Repo, &str)>,
     ) -> Result<()> {
+        if let Some(content) = reference_content(treefile, "passwd")? {
+            let entries = nameservice::passwd::parse_passwd_content(content.as_slice())?;
+            for user in entries {
+                self.users.insert(
+                    user.name,
+                    (Uid::from_raw(user.uid), Gid::from_raw(user.gid)),
+                );
+            }
+            return Ok(());
+        }
+
         let config = treefile.parsed.get_check_passwd();
 
         match config {
             CheckPasswd::None => {}
-            CheckPasswd::File(f) => {
-                let fp = treefile.externals.passwd_file_mut(f)?;
-                let buf_rd = BufReader::new(fp);
-                let entries = nameservice::passwd::parse_passwd_content(buf_rd)?;
-                for user in entries {
-                    self.users.insert(
-                        user.name,
-                        (Uid::from_raw(user.uid), Gid::from_raw(user.gid)),
-                    );
-                }
-            }
             CheckPasswd::Previous => {
                 // This logic short-circuits if there is no previous commit, or if
                 // it doesn't contain a passwd file. Nothing to validate in that case.
@@ -777,10 +987,17 @@ impl PasswdEntries {
                 }
             }
             CheckPasswd::Data(data) => {
-                for user in &data.entries {
-                    self.users.insert(user.0.clone(), user.1.ids());
+                for (name, entry) in &data.entries {
+                    let (uid, gid) = entry.ids();
+                    self.users
+                        .insert(name.clone(), (Uid::from_raw(uid), Gid::from_raw(gid)));
                 }
             }
+            // Handled by reference_content() above
+            CheckPasswd::File(_)
+            | CheckPasswd::Snapshot(_)
+            | CheckPasswd::Commit(_)
+            | CheckPasswd::Container(_) => {}
         };
 
         Ok(())
@@ -792,18 +1009,18 @@ impl PasswdEntries {
         treefile: &mut Treefile,
         repo_previous_rev: &Option<(&ostree::Repo, &str)>,
     ) -> Result<()> {
+        if let Some(content) = reference_content(treefile, "group")? {
+            let entries = nameservice::group::parse_group_content(content.as_slice())?;
+            for group in entries {
+                self.groups.insert(group.name, Gid::from_raw(group.gid));
+            }
+            return Ok(());
+        }
+
         let config = treefile.parsed.get_check_groups();
 
         match config {
             CheckGroups::None => {}
-            CheckGroups::File(f) => {
-                let fp = treefile.externals.group_file_mut(f)?;
-                let buf_rd = BufReader::new(fp);
-                let entries = nameservice::group::parse_group_content(buf_rd)?;
-                for group in entries {
-                    self.groups.insert(group.name, Gid::from_raw(group.gid));
-                }
-            }
             CheckGroups::Previous => {
                 // This logic short-circuits if there is no previous commit, or if
                 // it doesn't contain a group file. Nothing to validate in that case.
@@ -829,6 +1046,11 @@ impl PasswdEntries {
                     self.groups.insert(groupname.clone(), id);
                 }
             }
+            // Handled by reference_content() above
+            CheckGroups::File(_)
+            | CheckGroups::Snapshot(_)
+            | CheckGroups::Commit(_)
+            | CheckGroups::Container(_) => {}
         };
 
         Ok(())
@@ -0,0 +1,362 @@
+//! Pinning deployments with `rpm-ostree pin`.
+//!
+//! This is like `ostree admin pin`, additionally recording why a deployment
+//! was pinned and optionally until when, so that the pins of a fleet document
+//! themselves.  Pinning is done by the daemon, in a transaction, so that it is
+//! authorized like other changes of the deployments.  Both are shown by `rpm-ostree status`.  An expired pin is
+//! released by the daemon the next time it prunes deployments, which is the
+//! only time a pin makes a difference.
+//!
+//! The daemon also pins rollback deployments kept per `KeepRollbackDeployments`
+//! and the alternative to the staged deployment, but records them separately;
+//! see `rollback_retention.rs` and `alternative.rs`.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::cxxrsutil::*;
+use crate::deployment_generate_id_impl;
+use anyhow::{anyhow, Context, Result};
+use chrono::prelude::*;
+use clap::Parser;
+use ostree_ext::prelude::*;
+use ostree_ext::{gio, glib, ostree};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+const STATE_PATH: &str = "/var/lib/rpm-ostree/pins.json";
+/// The origin group in which libostree stores the pinned state.
+const ORIGIN_TRANSIENT_GROUP: &str = "libostree-transient";
+
+/// Metadata recorded for a pinned deployment.
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct PinInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    /// Unix timestamp after which the pin is released
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires: Option<i64>,
+}
+
+/// The metadata of pins, by deployment ID.
+type PinState = BTreeMap<String, PinInfo>;
+
+fn load_state(path: &Path) -> Result<PinState> {
+    match std::fs::read_to_string(path) {
+        Ok(s) => serde_json::from_str(&s).with_context(|| format!("Parsing {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(PinState::new()),
+        Err(e) => Err(e).with_context(|| format!("Reading {}", path.display())),
+    }
+}
+
+fn store_state(path: &Path, state: &PinState) -> Result<()> {
+    if state.is_empty() {
+        return match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("Removing {}", path.display()))
+            }
+            _ => Ok(()),
+        };
+    }
+    let buf = serde_json::to_vec(state)?;
+    crate::utils::write_file_atomic(path, buf)
+}
+
+/// Parse a duration like `30d` into seconds; the units are `m`, `h`, `d` and `w`.
+fn parse_duration(s: &str) -> Result<i64> {
+    let err = || anyhow!("Invalid duration {:?}; expected e.g. 12h, 30d or 2w", s);
+    let units = [
+        ("m", 60),
+        ("h", 60 * 60),
+        ("d", 24 * 60 * 60),
+        ("w", 7 * 24 * 60 * 60),
+    ];
+    let (n, unit) = units
+        .iter()
+        .find_map(|(suffix, unit)| s.strip_suffix(suffix).map(|n| (n, *unit)))
+        .ok_or_else(err)?;
+    let n: i64 = n.parse().ok().filter(|n| *n > 0).ok_or_else(err)?;
+    n.checked_mul(unit).ok_or_else(err)
+}
+
+fn format_timestamp(t: i64) -> String {
+    Local
+        .timestamp(t, 0)
+        .format("%a %Y-%m-%d %H:%M %:z")
+        .to_string()
+}
+
+/// Pin a deployment, so that it isn't pruned
+#[derive(Debug, Parser)]
+#[clap(name = "rpm-ostree pin", bin_name = "rpm-ostree pin")]
+#[clap(rename_all = "kebab-case")]
+pub(crate) struct PinOpts {
+    /// The deployment: its index in `rpm-ostree status`, or booted, pending or rollback
+    #[clap(value_name = "DEPLOYMENT")]
+    deployment: String,
+
+    /// Unpin the deployment instead
+    #[clap(long, conflicts_with_all = &["reason", "expires"])]
+    unpin: bool,
+
+    /// Record why the deployment is pinned
+    #[clap(long)]
+    reason: Option<String>,
+
+    /// Release the pin after this duration, e.g. 12h, 30d or 2w
+    #[clap(long, value_parser = parse_duration)]
+    expires: Option<i64>,
+}
+
+pub(crate) fn find_deployment(sysroot: &ostree::Sysroot, spec: &str) -> Result<ostree::Deployment> {
+    let found = match spec {
+        "booted" => sysroot.booted_deployment(),
+        "pending" => sysroot.query_deployments_for(None).0,
+        "rollback" => sysroot.query_deployments_for(None).1,
+        _ => {
+            let index: usize = spec.parse().map_err(|_| {
+                anyhow!(
+                    "Invalid deployment {:?}; expected an index, booted, pending or rollback",
+                    spec
+                )
+            })?;
+            sysroot.deployments().into_iter().nth(index)
+        }
+    };
+    found.ok_or_else(|| anyhow!("Deployment not found: {}", spec))
+}
+
+fn pin(
+    sysroot: &ostree::Sysroot,
+    spec: &str,
+    unpin: bool,
+    reason: Option<String>,
+    expires: Option<i64>,
+    path: &Path,
+    now: i64,
+) -> Result<()> {
+    let deployment = &find_deployment(sysroot, spec)?;
+    let id = deployment_generate_id_impl(deployment);
+    let mut state = load_state(path)?;
+    // Either way, it's not pinned by the daemon to keep it anymore
+    crate::rollback_retention::forget_retained(deployment)?;
+    crate::alternative::forget_alternative(deployment)?;
+    if unpin {
+        if deployment.is_pinned() {
+            set_pinned_now(sysroot, deployment, false)?;
+            crate::ffi::output_message(&format!("Deployment {} is now unpinned", id));
+        } else {
+            crate::ffi::output_message(&format!("Deployment {} is not pinned", id));
+        }
+        state.remove(&id);
+    } else {
+        if !deployment.is_pinned() {
+            set_pinned_now(sysroot, deployment, true)?;
+        }
+        // Pinning again replaces the reason and expiry
+        let info = PinInfo {
+            reason,
+            expires: expires.map(|d| now + d),
+            ..Default::default()
+        };
+        let msg = match info.expires {
+            Some(t) => format!(
+                "Deployment {} is now pinned until {}",
+                id,
+                format_timestamp(t)
+            ),
+            None => format!("Deployment {} is now pinned", id),
+        };
+        crate::ffi::output_message(&msg);
+        if info == PinInfo::default() {
+            state.remove(&id);
+        } else {
+            state.insert(id, info);
+        }
+    }
+    store_state(path, &state)
+}
+
+/// Pin or unpin the deployment `spec`, as for `rpm-ostree pin`; an empty
+/// `reason` and an `expires` of 0 mean none.  This must be called with the
+/// sysroot lock held.
+pub(crate) fn pin_deployment(
+    sysroot: &crate::ffi::OstreeSysroot,
+    spec: &str,
+    unpin: bool,
+    reason: &str,
+    expires: i64,
+) -> CxxResult<()> {
+    let sysroot = &sysroot.glib_reborrow();
+    let reason = Some(reason.to_string()).filter(|r| !r.is_empty());
+    let expires = Some(expires).filter(|d| *d > 0);
+    pin(
+        sysroot,
+        spec,
+        unpin,
+        reason,
+        expires,
+        Path::new(STATE_PATH),
+        Utc::now().timestamp(),
+    )?;
+    Ok(())
+}
+
+/// Main entrypoint for `rpm-ostree pin`.
+pub fn entrypoint(args: &[&str]) -> Result<()> {
+    let opts = &PinOpts::parse_from(args.iter().skip(1));
+    let client = &mut crate::client::ClientConnection::new()?;
+    let options = glib::VariantDict::new(None);
+    if opts.unpin {
+        options.insert("unpin", &true);
+    }
+    if let Some(reason) = opts.reason.as_deref() {
+        options.insert("reason", &reason);
+    }
+    if let Some(expires) = opts.expires {
+        options.insert("expires", &expires);
+    }
+    let params = glib::Variant::from_tuple(&[opts.deployment.to_variant(), options.end()]);
+    let reply = &client.get_os_proxy().call_sync(
+        "PinDeployment",
+        Some(&params),
+        gio::DBusCallFlags::NONE,
+        -1,
+        gio::NONE_CANCELLABLE,
+    )?;
+    let reply = reply
+        .get::<(String,)>()
+        .ok_or_else(|| anyhow!("Invalid reply"))?;
+    client.transaction_connect_progress_sync(reply.0.as_str())
+}
+
+/// Add the reason and expiry of the pin of `deployment` to `dict`, if any.
+pub(crate) fn deployment_populate_pin(
+    deployment: &ostree::Deployment,
+    dict: &glib::VariantDict,
+) -> Result<()> {
+    if !deployment.is_pinned() {
+        return Ok(());
+    }
+    let mut state = load_state(Path::new(STATE_PATH))?;
+    if let Some(info) = state.remove(&deployment_generate_id_impl(deployment)) {
+        if let Some(reason) = info.reason {
+            dict.insert("pin-reason", &reason.as_str());
+        }
+        if let Some(expires) = info.expires {
+            dict.insert("pin-expires", &expires);
+        }
+    }
+    Ok(())
+}
+
+/// Pin or unpin `deployment`.  The pinned state is read from the origin, which
+/// libostree doesn't reload; update it too, so that pruning deployments sees
+/// the change right away.
+pub(crate) fn set_pinned_now(
+    sysroot: &ostree::Sysroot,
+    deployment: &ostree::Deployment,
+    pinned: bool,
+) -> Result<()> {
+    sysroot.deployment_set_pinned(deployment, pinned)?;
+    if let Some(origin) = deployment.origin() {
+        origin.set_boolean(ORIGIN_TRANSIENT_GROUP, "pinned", pinned);
+    }
+    Ok(())
+}
+
+fn release_stale_pins_impl(sysroot: &ostree::Sysroot, path: &Path, now: i64) -> Result<()> {
+    let state = load_state(path)?;
+    if state.is_empty() {
+        return Ok(());
+    }
+    let pinned: BTreeMap<_, _> = sysroot
+        .deployments()
+        .into_iter()
+        .filter(|d| d.is_pinned())
+        .map(|d| (deployment_generate_id_impl(&d), d))
+        .collect();
+    let n_pins = state.len();
+    let mut new_state = PinState::new();
+    for (id, info) in state {
+        // Forget about deployments which were unpinned or removed otherwise
+        let deployment = match pinned.get(&id) {
+            Some(d) => d,
+            None => continue,
+        };
+        let expired = info.expires.map_or(false, |t| t <= now);
+        if !expired {
+            new_state.insert(id, info);
+            continue;
+        }
+        set_pinned_now(sysroot, deployment, false)?;
+        crate::ffi::output_message(&format!("Released expired pin of deployment {}", id));
+    }
+    if new_state.len() != n_pins {
+        store_state(path, &new_state)?;
+    }
+    Ok(())
+}
+
+/// Release the pins which expired before pruning deployments.  This must be
+/// called with the sysroot lock held.
+pub(crate) fn release_stale_pins(sysroot: &crate::ffi::OstreeSysroot) -> CxxResult<()> {
+    let sysroot = &sysroot.glib_reborrow();
+    release_stale_pins_impl(sysroot, Path::new(STATE_PATH), Utc::now().timestamp())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90m").unwrap(), 90 * 60);
+        assert_eq!(parse_duration("12h").unwrap(), 12 * 60 * 60);
+        assert_eq!(parse_duration("30d").unwrap(), 30 * 24 * 60 * 60);
+        assert_eq!(parse_duration("2w").unwrap(), 14 * 24 * 60 * 60);
+        for v in [
+            "",
+            "d",
+            "30",
+            "30s",
+            "-1d",
+            "0d",
+            "1.5d",
+            "30 d",
+            "99999999999999999w",
+        ] {
+            assert!(parse_duration(v).is_err(), "{}", v);
+        }
+    }
+
+    #[test]
+    fn test_state() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        let path = &td.path().join("pins.json");
+        assert!(load_state(path)?.is_empty());
+        let mut state = PinState::new();
+        state.insert(
+            "fedora-abc.0".into(),
+            PinInfo {
+                reason: Some("known-good".into()),
+                ..Default::default()
+            },
+        );
+        state.insert(
+            "fedora-def.0".into(),
+            PinInfo {
+                expires: Some(42),
+                ..Default::default()
+            },
+        );
+        store_state(path, &state)?;
+        assert_eq!(load_state(path)?, state);
+        store_state(path, &PinState::new())?;
+        assert!(!path.exists());
+        // Removing it again is fine
+        store_state(path, &PinState::new())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,182 @@
+//! Implementation of the treefile `pinned-ids` field: a JSON mapping of user
+//! and group names to the IDs they were first allocated, which composes are
+//! checked against so that system accounts keep their numbering even across
+//! removals.  The mapping is maintained with `rpm-ostree compose pin-ids`.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::nameservice::group::parse_group_content;
+use crate::nameservice::passwd::parse_passwd_content;
+use crate::passwd::PasswdSnapshot;
+use crate::treefile::Treefile;
+use anyhow::{bail, Context, Result};
+use cap_std::fs::Dir;
+use cap_std_ext::cap_std;
+use cap_std_ext::dirext::CapStdExtDirExt;
+use fn_error_context::context;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::BufReader;
+
+/// The IDs of users and groups, by name.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct PinnedIds {
+    #[serde(default)]
+    pub(crate) users: BTreeMap<String, u32>,
+    #[serde(default)]
+    pub(crate) groups: BTreeMap<String, u32>,
+}
+
+/// Compare the allocated IDs of one kind of entries against the pinned ones:
+/// both a name getting another ID and an ID being reused by another name are
+/// drift.
+fn drift_of(
+    kind: &str,
+    id_kind: &str,
+    pinned: &BTreeMap<String, u32>,
+    allocated: &BTreeMap<String, u32>,
+    r: &mut Vec<String>,
+) {
+    let owners: BTreeMap<u32, &str> = pinned.iter().map(|(n, id)| (*id, n.as_str())).collect();
+    for (name, id) in allocated {
+        match pinned.get(name) {
+            Some(pinned_id) if pinned_id != id => r.push(format!(
+                "{} {}: pinned {} {}, allocated {}",
+                kind, name, id_kind, pinned_id, id
+            )),
+            Some(_) => {}
+            None => {
+                if let Some(owner) = owners.get(id) {
+                    r.push(format!(
+                        "{} {}: {} {} is pinned to {}",
+                        kind, name, id_kind, id, owner
+                    ));
+                }
+            }
+        }
+    }
+}
+
+impl PinnedIds {
+    /// Read the IDs allocated in the tree at `rootfs`, from both `usr/etc`
+    /// and `usr/lib`.
+    #[context("Reading allocated IDs")]
+    pub(crate) fn from_rootfs(rootfs: &Dir) -> Result<Self> {
+        let mut r = Self::default();
+        for dir in ["usr/etc", "usr/lib"] {
+            let path = format!("{}/passwd", dir);
+            if let Some(f) = rootfs.open_optional(&path)? {
+                let entries = parse_passwd_content(BufReader::new(f))
+                    .with_context(|| format!("Parsing {}", path))?;
+                for e in entries {
+                    r.users.entry(e.name).or_insert(e.uid);
+                }
+            }
+            let path = format!("{}/group", dir);
+            if let Some(f) = rootfs.open_optional(&path)? {
+                let entries = parse_group_content(BufReader::new(f))
+                    .with_context(|| format!("Parsing {}", path))?;
+                for e in entries {
+                    r.groups.entry(e.name).or_insert(e.gid);
+                }
+            }
+        }
+        Ok(r)
+    }
+
+    /// The IDs allocated in a snapshot of the users and groups of a commit.
+    pub(crate) fn from_snapshot(snapshot: &PasswdSnapshot) -> Result<Self> {
+        let mut r = Self::default();
+        for e in parse_passwd_content(snapshot.content("passwd").as_slice())? {
+            r.users.insert(e.name, e.uid);
+        }
+        for e in parse_group_content(snapshot.content("group").as_slice())? {
+            r.groups.insert(e.name, e.gid);
+        }
+        Ok(r)
+    }
+
+    /// The differences of the `allocated` IDs from the pinned ones.
+    pub(crate) fn drift(&self, allocated: &PinnedIds) -> Vec<String> {
+        let mut r = Vec::new();
+        drift_of("user", "UID", &self.users, &allocated.users, &mut r);
+        drift_of("group", "GID", &self.groups, &allocated.groups, &mut r);
+        r
+    }
+
+    /// Pin the `allocated` IDs of the names not pinned yet, failing on drift.
+    /// Returns the number of new entries.
+    pub(crate) fn update(&mut self, allocated: &PinnedIds) -> Result<usize> {
+        check_drift(self, allocated)?;
+        let mut n = 0;
+        for (name, id) in &allocated.users {
+            if self.users.insert(name.clone(), *id).is_none() {
+                n += 1;
+            }
+        }
+        for (name, id) in &allocated.groups {
+            if self.groups.insert(name.clone(), *id).is_none() {
+                n += 1;
+            }
+        }
+        Ok(n)
+    }
+}
+
+fn check_drift(pinned: &PinnedIds, allocated: &PinnedIds) -> Result<()> {
+    let drift = pinned.drift(allocated);
+    if !drift.is_empty() {
+        bail!(
+            "Allocated IDs drifted from the pinned ones:\n  {}",
+            drift.join("\n  ")
+        );
+    }
+    Ok(())
+}
+
+/// Check the users and groups of the composed tree against the treefile's
+/// `pinned-ids`, if any.
+#[context("Checking pinned IDs")]
+pub(crate) fn check_pinned_ids(rootfs: &Dir, treefile: &mut Treefile) -> Result<()> {
+    let f = match treefile.externals.pinned_ids_file_mut()? {
+        Some(f) => f,
+        None => return Ok(()),
+    };
+    let pinned: PinnedIds = serde_json::from_reader(BufReader::new(f))?;
+    check_drift(&pinned, &PinnedIds::from_rootfs(rootfs)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(users: &[(&str, u32)], groups: &[(&str, u32)]) -> PinnedIds {
+        let map = |v: &[(&str, u32)]| v.iter().map(|(n, id)| (n.to_string(), *id)).collect();
+        PinnedIds {
+            users: map(users),
+            groups: map(groups),
+        }
+    }
+
+    #[test]
+    fn test_drift() -> Result<()> {
+        let mut pinned = ids(&[("chrony", 991), ("old", 992)], &[("chrony", 987)]);
+        let allocated = ids(&[("chrony", 991), ("dnsmasq", 990)], &[("chrony", 987)]);
+        assert!(pinned.drift(&allocated).is_empty());
+        assert_eq!(pinned.update(&allocated)?, 1);
+        assert_eq!(pinned.users["dnsmasq"], 990);
+        assert_eq!(pinned.users["old"], 992);
+
+        let allocated = ids(&[("chrony", 990), ("new", 992)], &[("chrony", 988)]);
+        assert_eq!(
+            pinned.drift(&allocated),
+            [
+                "user chrony: pinned UID 991, allocated 990",
+                "user new: UID 992 is pinned to old",
+                "group chrony: pinned GID 987, allocated 988"
+            ]
+        );
+        assert!(pinned.update(&allocated).is_err());
+        Ok(())
+    }
+}
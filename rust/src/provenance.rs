@@ -0,0 +1,240 @@
+//! Provenance of client-side deployments.  If `ProvenanceSink` is set in
+//! rpm-ostreed.conf, each new deployment gets an in-toto statement with a SLSA
+//! provenance predicate recording what it was produced from (the base commit
+//! or container image, the layered packages and the origin), signed with
+//! `ProvenanceSigningKey` in a DSSE envelope, and written to a directory or
+//! posted to an HTTP(S) endpoint.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::containers_attestation::{pae, DSSE_ENVELOPE, IN_TOTO_PAYLOAD};
+use crate::cxxrsutil::*;
+use crate::ffi::StringMapping;
+use anyhow::{anyhow, bail, Context, Result};
+use openssl::hash::MessageDigest;
+use openssl::pkey::{Id, PKey, Private};
+use openssl::sign::Signer;
+use ostree_ext::glib;
+use reqwest::header::CONTENT_TYPE;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+const STATEMENT_TYPE: &str = "https://in-toto.io/Statement/v0.1";
+const PREDICATE_TYPE: &str = "https://slsa.dev/provenance/v0.2";
+const BUILD_TYPE: &str = "https://coreos.github.io/rpm-ostree/provenance/deployment/v1";
+/// The builder ID is derived from the machine ID with this application ID, like
+/// the node ID used for FleetLock.
+const APP_ID: &str = "c4e1a2f7d85b4b0e9a3f6d21b7e8c590";
+/// Set by ostree-ext on the commits of container images.
+const MANIFEST_DIGEST_KEY: &str = "ostree.manifest-digest";
+const REQUEST_TIMEOUT_SECS: u64 = 30;
+
+enum Sink {
+    Dir(String),
+    Url(reqwest::Url),
+}
+
+impl Sink {
+    fn new(sink: &str) -> Result<Self> {
+        if sink.starts_with('/') {
+            return Ok(Self::Dir(sink.to_string()));
+        }
+        let url = reqwest::Url::parse(sink).with_context(|| format!("Invalid URL: {}", sink))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            bail!(
+                "Unsupported sink (not an absolute path or HTTP(S) URL): {}",
+                sink
+            );
+        }
+        Ok(Self::Url(url))
+    }
+}
+
+/// Check the `ProvenanceSink` and `ProvenanceSigningKey` settings.
+pub(crate) fn provenance_validate(sink: &str, key_path: &str) -> CxxResult<()> {
+    Sink::new(sink)?;
+    if key_path.is_empty() {
+        return Err(anyhow!("ProvenanceSink requires ProvenanceSigningKey").into());
+    }
+    Ok(())
+}
+
+/// Split a checksum as formatted by `get_repodata_chksum_repr()`, e.g.
+/// `sha256:...`, into a digest set.
+fn digest_set(checksum: &str) -> Result<BTreeMap<String, String>> {
+    let (algo, hex) = checksum
+        .split_once(':')
+        .ok_or_else(|| anyhow!("Invalid checksum: {}", checksum))?;
+    Ok([(algo.to_lowercase(), hex.to_string())].into())
+}
+
+fn base_material(
+    repo: &ostree_ext::ostree::Repo,
+    base: &str,
+    origin: &glib::KeyFile,
+) -> Result<serde_json::Value> {
+    let commit = repo.load_commit(base)?.0;
+    let commitmeta = commit.child_value(0);
+    let meta = &glib::VariantDict::new(Some(&commitmeta));
+    if let Ok(imgref) = origin.string("origin", "container-image-reference") {
+        let digest = meta
+            .lookup::<String>(MANIFEST_DIGEST_KEY)
+            .map_err(anyhow::Error::msg)?
+            .ok_or_else(|| anyhow!("Missing {} in {}", MANIFEST_DIGEST_KEY, base))?;
+        return Ok(json!({ "uri": imgref.as_str(), "digest": digest_set(&digest)? }));
+    }
+    let refspec = origin
+        .string("origin", "refspec")
+        .or_else(|_| origin.string("origin", "baserefspec"))
+        .map(|s| s.to_string())
+        .unwrap_or_default();
+    Ok(json!({ "uri": format!("ostree:{}", refspec), "digest": { "sha256": base } }))
+}
+
+/// Build the in-toto statement for the deployment of `commit`.
+fn statement(
+    repo: &ostree_ext::ostree::Repo,
+    base: &str,
+    commit: &str,
+    origin: &str,
+    packages: &[StringMapping],
+) -> Result<serde_json::Value> {
+    let kf = glib::KeyFile::new();
+    kf.load_from_data(origin, glib::KeyFileFlags::NONE)
+        .context("Parsing origin")?;
+    let mut materials = vec![base_material(repo, base, &kf).context("Describing base")?];
+    for pkg in packages {
+        materials.push(json!({ "uri": format!("rpm:{}", pkg.k), "digest": digest_set(&pkg.v)? }));
+    }
+    let builder_id = format!(
+        "rpm-ostree://{}",
+        crate::utils::machine_app_specific_id(APP_ID)?
+    );
+    Ok(json!({
+        "_type": STATEMENT_TYPE,
+        "subject": [{ "name": format!("ostree:{}", commit), "digest": { "sha256": commit } }],
+        "predicateType": PREDICATE_TYPE,
+        "predicate": {
+            "builder": { "id": builder_id },
+            "buildType": BUILD_TYPE,
+            "invocation": {
+                "parameters": { "origin": origin },
+                "environment": { "rpm-ostree": env!("CARGO_PKG_VERSION") },
+            },
+            "metadata": {
+                "buildFinishedOn": chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+                "completeness": { "parameters": true, "environment": false, "materials": true },
+                "reproducible": false,
+            },
+            "materials": materials,
+        },
+    }))
+}
+
+/// Sign `payload` into a DSSE envelope.
+fn sign_envelope(key: &PKey<Private>, payload: &[u8]) -> Result<Vec<u8>> {
+    let signed = pae(IN_TOTO_PAYLOAD, payload);
+    let mut signer = if key.id() == Id::ED25519 {
+        Signer::new_without_digest(key)?
+    } else {
+        Signer::new(MessageDigest::sha256(), key)?
+    };
+    let sig = signer.sign_oneshot_to_vec(&signed)?;
+    let envelope = json!({
+        "payloadType": IN_TOTO_PAYLOAD,
+        "payload": glib::base64_encode(payload).as_str(),
+        "signatures": [{ "keyid": "", "sig": glib::base64_encode(&sig).as_str() }],
+    });
+    Ok(serde_json::to_vec(&envelope)?)
+}
+
+fn emit(sink: &Sink, commit: &str, envelope: Vec<u8>) -> Result<()> {
+    match sink {
+        Sink::Dir(dir) => {
+            std::fs::create_dir_all(dir)?;
+            let name = format!("{}-{}.dsse.json", chrono::Utc::now().timestamp(), commit);
+            let path = std::path::Path::new(dir).join(name);
+            std::fs::write(&path, envelope).with_context(|| format!("Writing {:?}", path))
+        }
+        Sink::Url(url) => Handle::current().block_on(async {
+            let client = reqwest::Client::builder()
+                .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS))
+                .build()?;
+            client
+                .post(url.clone())
+                .header(CONTENT_TYPE, DSSE_ENVELOPE)
+                .body(envelope)
+                .send()
+                .await?
+                .error_for_status()?;
+            Ok(())
+        }),
+    }
+}
+
+/// Write the provenance of the deployment of `commit` to `sink`; `base` is the
+/// commit it was produced from, and `packages` maps the NEVRAs of the packages
+/// layered or overridden to their checksums.
+pub(crate) fn provenance_emit(
+    repo: &crate::FFIOstreeRepo,
+    base: &str,
+    commit: &str,
+    origin: &str,
+    packages: &Vec<StringMapping>,
+    sink: &str,
+    key_path: &str,
+) -> CxxResult<()> {
+    let repo = &repo.glib_reborrow();
+    let sink = Sink::new(sink)?;
+    let key = std::fs::read(key_path).with_context(|| format!("Reading {}", key_path))?;
+    let key = PKey::private_key_from_pem(&key).with_context(|| format!("Parsing {}", key_path))?;
+    let statement = statement(repo, base, commit, origin, packages)?;
+    let envelope = sign_envelope(&key, &serde_json::to_vec(&statement)?)?;
+    emit(&sink, commit, envelope).context("Writing provenance")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::nid::Nid;
+    use openssl::sign::Verifier;
+
+    #[test]
+    fn test_sink() {
+        assert!(matches!(Sink::new("/var/lib/provenance"), Ok(Sink::Dir(_))));
+        assert!(matches!(
+            Sink::new("https://provenance.example.com/v1/deployments"),
+            Ok(Sink::Url(_))
+        ));
+        assert!(Sink::new("relative/dir").is_err());
+        assert!(Sink::new("ftp://example.com").is_err());
+        assert!(provenance_validate("/var/lib/provenance", "").is_err());
+    }
+
+    #[test]
+    fn test_digest_set() -> Result<()> {
+        let d = digest_set("SHA256:abcd")?;
+        assert_eq!(d.get("sha256").map(|s| s.as_str()), Some("abcd"));
+        assert!(digest_set("abcd").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sign_envelope() -> Result<()> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+        let key = PKey::from_ec_key(EcKey::generate(&group)?)?;
+        let payload = br#"{"_type":"https://in-toto.io/Statement/v0.1"}"#;
+        let envelope: serde_json::Value = serde_json::from_slice(&sign_envelope(&key, payload)?)?;
+        assert_eq!(envelope["payloadType"], IN_TOTO_PAYLOAD);
+        let decoded = glib::base64_decode(envelope["payload"].as_str().unwrap());
+        assert_eq!(decoded.as_slice(), payload);
+        let sig = glib::base64_decode(envelope["signatures"][0]["sig"].as_str().unwrap());
+        let mut verifier = Verifier::new(MessageDigest::sha256(), &key)?;
+        assert!(verifier.verify_oneshot(&sig, &pae(IN_TOTO_PAYLOAD, payload))?);
+        Ok(())
+    }
+}
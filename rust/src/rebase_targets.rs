@@ -0,0 +1,325 @@
+//! Implementation of `rpm-ostree rebase --list` and `--interactive`, which
+//! show the targets one can rebase to: the refs of the configured ostree
+//! remotes, with the version and date of their commits as recorded in the
+//! summary, or the tags of the repository of a container image.  The tags
+//! are listed by the daemon, as for `rpm-ostree deploy --list`, so that the
+//! registry is accessed with the pull settings and credentials of the system.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::cxxrsutil::*;
+use crate::sysroot_upgrade::split_image_tag;
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::prelude::*;
+use gio::prelude::*;
+use ostree_ext::container::OstreeImageReference;
+use ostree_ext::{gio, glib, ostree};
+use std::io::{BufRead, Write};
+
+/// The GVariant type of an ostree summary file.
+const SUMMARY_TYPE: &str = "(a(s(taya{sv}))a{sv})";
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct RebaseTarget {
+    refspec: String,
+    version: Option<String>,
+    timestamp: Option<String>,
+    /// Whether this is what the system currently follows
+    current: bool,
+}
+
+fn format_timestamp(t: u64) -> String {
+    Utc.timestamp(t as i64, 0)
+        .format("%Y-%m-%dT%H:%M:%SZ")
+        .to_string()
+}
+
+/// Parse the refs of the summary `summary` of remote `remote`.
+fn parse_summary(
+    remote: &str,
+    summary: &glib::Variant,
+    current: Option<&str>,
+) -> Vec<RebaseTarget> {
+    let refs = summary.child_value(0);
+    let mut r = Vec::new();
+    for i in 0..refs.n_children() {
+        let entry = refs.child_value(i);
+        let name = entry.child_value(0);
+        let name = name.str().unwrap_or_default();
+        let meta = glib::VariantDict::new(Some(&entry.child_value(1).child_value(2)));
+        let refspec = format!("{}:{}", remote, name);
+        // The timestamp is stored in big-endian, as in commits
+        let timestamp = meta
+            .lookup::<u64>("ostree.commit.timestamp")
+            .ok()
+            .flatten()
+            .map(|t| format_timestamp(u64::from_be(t)));
+        r.push(RebaseTarget {
+            current: current == Some(refspec.as_str()),
+            refspec,
+            version: meta
+                .lookup::<String>("ostree.commit.version")
+                .ok()
+                .flatten(),
+            timestamp,
+        });
+    }
+    r
+}
+
+/// The refs of the ostree remote `remote`.
+fn remote_targets(
+    repo: &ostree::Repo,
+    remote: &str,
+    current: Option<&str>,
+) -> Result<Vec<RebaseTarget>> {
+    let (summary, _) = repo
+        .remote_fetch_summary(remote, gio::NONE_CANCELLABLE)
+        .with_context(|| format!("Fetching summary of remote {}", remote))?;
+    let ty = glib::VariantTy::new(SUMMARY_TYPE).unwrap();
+    let summary = glib::Variant::from_bytes_with_type(&summary, ty);
+    Ok(parse_summary(remote, &summary, current))
+}
+
+/// Have the daemon output page `page` of the tags of the repository of the
+/// container image `imgref`.
+fn list_image_tags(imgref: &str, page: u32) -> Result<()> {
+    let client = &mut crate::client::ClientConnection::new()?;
+    let options = glib::VariantDict::new(None);
+    options.insert("page", &page);
+    options.insert("refspec", &imgref);
+    let params = glib::Variant::from_tuple(&[options.end()]);
+    let reply = &client.get_os_proxy().call_sync(
+        "ListRevisions",
+        Some(&params),
+        gio::DBusCallFlags::NONE,
+        -1,
+        gio::NONE_CANCELLABLE,
+    )?;
+    let (address,) = reply
+        .get::<(String,)>()
+        .ok_or_else(|| anyhow!("Invalid reply"))?;
+    client.transaction_connect_progress_sync(address.as_str())
+}
+
+/// The reference to the image tagged `tag` in the repository of the container
+/// image `imgref`.
+fn image_with_tag(imgref: &str, tag: &str) -> Result<String> {
+    if tag.contains(|c: char| c.is_whitespace() || matches!(c, ':' | '/' | '@')) {
+        bail!("Invalid tag: {}", tag);
+    }
+    let mut imgref = OstreeImageReference::try_from(imgref)?;
+    let (repo, _) = split_image_tag(&imgref.imgref.name);
+    imgref.imgref.name = format!("{}:{}", repo, tag);
+    Ok(imgref.to_string())
+}
+
+#[derive(Debug)]
+enum Targets {
+    /// Refs of ostree remotes
+    Refs(Vec<RebaseTarget>),
+    /// The tags of the repository of this container image, listed by the daemon
+    ImageTags(String),
+}
+
+/// The targets from `source`, which is either the name of a remote or a
+/// container image reference.  If it's empty, the targets are the tags of the
+/// image followed by the booted deployment, or else the refs of all remotes.
+fn targets(source: &str) -> Result<Targets> {
+    let sysroot = &ostree::Sysroot::new_default();
+    sysroot.load(gio::NONE_CANCELLABLE)?;
+    let repo = &sysroot.repo().unwrap();
+    let current = match sysroot.booted_deployment() {
+        Some(booted) => {
+            let origin = booted
+                .origin()
+                .ok_or_else(|| anyhow!("No origin for booted deployment"))?;
+            let tf = crate::origin::origin_to_treefile_inner(&origin)?;
+            tf.parsed
+                .derive
+                .container_image_reference
+                .or(tf.parsed.derive.base_refspec)
+        }
+        None => None,
+    };
+    let current = current.as_deref();
+    let remotes = repo.remote_list();
+
+    if source.is_empty() {
+        if let Some(imgref) = current.filter(|c| OstreeImageReference::try_from(*c).is_ok()) {
+            return Ok(Targets::ImageTags(imgref.to_string()));
+        }
+        if remotes.is_empty() {
+            bail!("No remotes configured");
+        }
+        let mut r = Vec::new();
+        for remote in remotes.iter() {
+            r.extend(remote_targets(repo, remote, current)?);
+        }
+        Ok(Targets::Refs(r))
+    } else if remotes.iter().any(|r| r.as_str() == source) {
+        Ok(Targets::Refs(remote_targets(repo, source, current)?))
+    } else if OstreeImageReference::try_from(source).is_ok() {
+        Ok(Targets::ImageTags(source.to_string()))
+    } else {
+        bail!("Not a remote or a container image reference: {}", source)
+    }
+}
+
+fn format_target(target: &RebaseTarget) -> String {
+    format!(
+        "{} {:<40} {:<20} {}{}",
+        if target.current { "●" } else { " " },
+        target.refspec,
+        target.version.as_deref().unwrap_or("(no version)"),
+        target.timestamp.as_deref().unwrap_or_default(),
+        if target.current { " (current)" } else { "" }
+    )
+}
+
+/// Print the targets from `source`, as for `rpm-ostree rebase --list`; `page`
+/// is the page of the tags of a container image, or 0 if not specified.
+pub(crate) fn rebase_list_targets(source: &str, page: u32) -> CxxResult<()> {
+    let targets = match targets(source)? {
+        Targets::ImageTags(imgref) => return Ok(list_image_tags(&imgref, page.max(1))?),
+        Targets::Refs(_) if page > 0 => {
+            return Err(anyhow!("--list-page is only supported for container images").into())
+        }
+        Targets::Refs(targets) => targets,
+    };
+    if targets.is_empty() {
+        crate::ffi::output_message("No targets found.");
+    }
+    for target in targets.iter() {
+        println!("{}", format_target(target));
+    }
+    Ok(())
+}
+
+/// Parse the answer `answer` to the prompt for one of `n` targets; returns
+/// `None` if no target was picked.
+fn parse_choice(answer: &str, n: usize) -> Result<Option<usize>> {
+    let answer = answer.trim();
+    if answer.is_empty() {
+        return Ok(None);
+    }
+    match answer.parse::<usize>() {
+        Ok(i) if (1..=n).contains(&i) => Ok(Some(i - 1)),
+        _ => bail!("Invalid choice: {}", answer),
+    }
+}
+
+fn read_answer(prompt: &str) -> Result<String> {
+    eprint!("{}", prompt);
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    Ok(answer)
+}
+
+/// Print the targets from `source` and ask which one to rebase to, as for
+/// `rpm-ostree rebase --interactive`; returns an empty string if none was
+/// picked.  The tags of a container image are picked by name.
+pub(crate) fn rebase_pick_target(source: &str) -> CxxResult<String> {
+    if !nix::unistd::isatty(libc::STDIN_FILENO)? {
+        return Err(anyhow!("--interactive requires a terminal").into());
+    }
+    let targets = match targets(source)? {
+        Targets::ImageTags(imgref) => {
+            list_image_tags(&imgref, 1)?;
+            let answer = read_answer("Rebase to tag [empty to cancel]: ")?;
+            let tag = answer.trim();
+            if tag.is_empty() {
+                return Ok(String::new());
+            }
+            return Ok(image_with_tag(&imgref, tag)?);
+        }
+        Targets::Refs(targets) => targets,
+    };
+    if targets.is_empty() {
+        return Err(anyhow!("No targets found").into());
+    }
+    for (i, target) in targets.iter().enumerate() {
+        println!("{:>3}) {}", i + 1, format_target(target));
+    }
+    let answer = read_answer(&format!(
+        "Rebase to [1-{}, empty to cancel]: ",
+        targets.len()
+    ))?;
+    let choice = parse_choice(&answer, targets.len())?;
+    Ok(choice
+        .map(|i| targets[i].refspec.clone())
+        .unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glib::{ToVariant, Variant};
+    use std::collections::HashMap;
+
+    fn summary_entry(name: &str, meta: &glib::VariantDict) -> Variant {
+        let commit =
+            Variant::from_tuple(&[0u64.to_variant(), vec![0u8; 32].to_variant(), meta.end()]);
+        Variant::from_tuple(&[name.to_variant(), commit])
+    }
+
+    #[test]
+    fn test_parse_summary() {
+        let meta = glib::VariantDict::new(None);
+        meta.insert("ostree.commit.timestamp", &u64::to_be(1650000000));
+        meta.insert("ostree.commit.version", &"36.20220415.0");
+        let refs = Variant::from_array::<(&str, (u64, &[u8], HashMap<&str, Variant>))>(&[
+            summary_entry("fedora/36/x86_64/silverblue", &meta),
+            summary_entry("fedora/37/x86_64/silverblue", &glib::VariantDict::new(None)),
+        ]);
+        let summary = Variant::from_tuple(&[refs, glib::VariantDict::new(None).end()]);
+        assert_eq!(summary.type_().as_str(), SUMMARY_TYPE);
+        let targets = parse_summary(
+            "fedora",
+            &summary,
+            Some("fedora:fedora/37/x86_64/silverblue"),
+        );
+        assert_eq!(
+            targets,
+            vec![
+                RebaseTarget {
+                    refspec: "fedora:fedora/36/x86_64/silverblue".into(),
+                    version: Some("36.20220415.0".into()),
+                    timestamp: Some("2022-04-15T05:20:00Z".into()),
+                    current: false,
+                },
+                RebaseTarget {
+                    refspec: "fedora:fedora/37/x86_64/silverblue".into(),
+                    current: true,
+                    ..Default::default()
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_choice() {
+        assert_eq!(parse_choice("\n", 3).unwrap(), None);
+        assert_eq!(parse_choice(" 2\n", 3).unwrap(), Some(1));
+        assert!(parse_choice("0", 3).is_err());
+        assert!(parse_choice("4", 3).is_err());
+        assert!(parse_choice("foo", 3).is_err());
+    }
+
+    #[test]
+    fn test_image_with_tag() -> Result<()> {
+        let imgref = "ostree-unverified-registry:quay.io/exampleos/os:stable";
+        assert_eq!(
+            image_with_tag(imgref, "testing")?,
+            "ostree-unverified-registry:quay.io/exampleos/os:testing"
+        );
+        assert_eq!(
+            image_with_tag("ostree-unverified-registry:localhost:5000/os", "36")?,
+            "ostree-unverified-registry:localhost:5000/os:36"
+        );
+        assert!(image_with_tag(imgref, "foo bar").is_err());
+        assert!(image_with_tag(imgref, "foo:bar").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,147 @@
+//! Cheap copies on filesystems which support reflinks (btrfs, and XFS when
+//! created with `reflink=1`).  Cloning a file there shares its extents instead
+//! of writing out the data again, which matters on edge devices where disk
+//! writes are slow and wear out the storage.  Elsewhere, we still copy in the
+//! kernel with `copy_file_range()` rather than through userspace buffers.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use cap_std_ext::rustix;
+use once_cell::sync::Lazy;
+use rustix::fd::BorrowedFd;
+use rustix::fs::{Mode, OFlags};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Seek, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::Mutex;
+
+/// From `linux/fs.h`: `_IOW(0x94, 9, int)`.
+const FICLONE: libc::c_ulong = 0x40049409;
+const BTRFS_SUPER_MAGIC: u32 = 0x9123683e;
+const XFS_SUPER_MAGIC: u32 = 0x58465342;
+
+/// Whether reflinks work, per device.
+static SUPPORTED: Lazy<Mutex<HashMap<u64, bool>>> = Lazy::new(Default::default);
+
+fn ficlone(src: &File, dest: &File) -> std::io::Result<()> {
+    let r = unsafe { libc::ioctl(dest.as_raw_fd(), FICLONE, src.as_raw_fd()) };
+    if r < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Whether the filesystem of `fd` is one which may support reflinks at all.
+fn fs_may_reflink(fd: RawFd) -> bool {
+    let mut stbuf: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstatfs(fd, &mut stbuf) } < 0 {
+        return false;
+    }
+    matches!(stbuf.f_type as u32, BTRFS_SUPER_MAGIC | XFS_SUPER_MAGIC)
+}
+
+/// Try to clone a file within the directory `dfd`.  XFS only supports it when
+/// created with `reflink=1`, so the filesystem type alone doesn't tell.
+fn probe(dfd: RawFd) -> std::io::Result<bool> {
+    let dfd = unsafe { BorrowedFd::borrow_raw(dfd) };
+    let tmpfile = || -> std::io::Result<File> {
+        let flags = OFlags::TMPFILE | OFlags::RDWR | OFlags::CLOEXEC;
+        let fd = rustix::fs::openat(&dfd, ".", flags, Mode::from_bits_truncate(0o600))?;
+        Ok(File::from(fd))
+    };
+    let mut src = tmpfile()?;
+    src.write_all(&[0u8; 4096])?;
+    let dest = tmpfile()?;
+    Ok(ficlone(&src, &dest).is_ok())
+}
+
+/// Whether files in the directory `dfd` can be cloned with reflinks.
+pub(crate) fn fs_supports_reflink(dfd: i32) -> bool {
+    let dev = match rustix::fs::fstat(unsafe { BorrowedFd::borrow_raw(dfd) }) {
+        Ok(st) => st.st_dev,
+        Err(_) => return false,
+    };
+    let mut supported = SUPPORTED.lock().unwrap();
+    *supported.entry(dev).or_insert_with(|| {
+        let r = fs_may_reflink(dfd) && probe(dfd).unwrap_or(false);
+        tracing::debug!("reflinks supported on device {}: {}", dev, r);
+        r
+    })
+}
+
+/// Copy the contents of `src` to `dest`, sharing extents if the filesystem
+/// supports it, otherwise copying in the kernel when possible.  Both files are
+/// copied from and to their current offsets, except when cloning them whole.
+pub(crate) fn copy_file_data(src: &mut File, dest: &mut File) -> std::io::Result<u64> {
+    if src.stream_position()? == 0 && dest.stream_position()? == 0 && ficlone(src, dest).is_ok() {
+        let len = src.metadata()?.len();
+        dest.seek(std::io::SeekFrom::End(0))?;
+        return Ok(len);
+    }
+    let mut copied = 0u64;
+    loop {
+        let r = unsafe {
+            libc::copy_file_range(
+                src.as_raw_fd(),
+                std::ptr::null_mut(),
+                dest.as_raw_fd(),
+                std::ptr::null_mut(),
+                1 << 30,
+                0,
+            )
+        };
+        match r {
+            0 => return Ok(copied),
+            n if n > 0 => copied += n as u64,
+            _ => {
+                let e = std::io::Error::last_os_error();
+                // Not supported between these files; fall back to a plain copy
+                if copied == 0
+                    && matches!(
+                        e.raw_os_error(),
+                        Some(libc::EXDEV | libc::ENOSYS | libc::EOPNOTSUPP | libc::EINVAL)
+                    )
+                {
+                    return std::io::copy(src, dest);
+                }
+                return Err(e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_copy_file_data() -> std::io::Result<()> {
+        let td = tempfile::tempdir()?;
+        let mut src = tempfile::tempfile_in(td.path())?;
+        src.write_all(b"hello world")?;
+        src.rewind()?;
+        let mut dest = tempfile::tempfile_in(td.path())?;
+        assert_eq!(copy_file_data(&mut src, &mut dest)?, 11);
+        dest.rewind()?;
+        let mut buf = String::new();
+        dest.read_to_string(&mut buf)?;
+        assert_eq!(buf, "hello world");
+
+        // Copies from the current offset
+        src.seek(std::io::SeekFrom::Start(6))?;
+        let mut dest = tempfile::tempfile_in(td.path())?;
+        assert_eq!(copy_file_data(&mut src, &mut dest)?, 5);
+        dest.rewind()?;
+        buf.clear();
+        dest.read_to_string(&mut buf)?;
+        assert_eq!(buf, "world");
+
+        let dir = File::open(td.path())?;
+        // Either answer is fine, but it must be stable
+        let supported = fs_supports_reflink(dir.as_raw_fd());
+        assert_eq!(fs_supports_reflink(dir.as_raw_fd()), supported);
+        Ok(())
+    }
+}
@@ -0,0 +1,759 @@
+//! Pinning the signing keys of rpm-md repos and ostree remotes.
+//!
+//! The fingerprints of the keys allowed to sign the packages of an rpm-md repo
+//! are listed by repo ID in the `[RepoKeys]` group of rpm-ostreed.conf, and
+//! those allowed to sign the commits of an ostree remote by remote name in
+//! `[RemoteKeys]`.  A pinned repo which doesn't check signatures or whose
+//! local `gpgkey=` files hold any other key is refused when fetching its
+//! metadata, and its packages need a valid signature by a pinned key (or one of
+//! its subkeys); a commit pulled from a pinned remote needs a valid signature by
+//! a pinned key.
+//!
+//! `rpm-ostree ex repo-keys` manages the pins.  Rotating a key is done in two
+//! steps: `rotate` pins the new key alongside the old ones if it's certified
+//! by one of them, and once the repo or remote is configured with the new key,
+//! `rotate --finish` drops the pins of the keys it isn't configured with
+//! anymore.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::cxxrsutil::*;
+use anyhow::{anyhow, bail, Context, Result};
+use clap::Parser;
+use ini::Ini;
+use ostree_ext::{gio, glib, ostree};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const CONFIG_PATH: &str = "/etc/rpm-ostreed.conf";
+const YUM_REPOS_D: &str = "/etc/yum.repos.d";
+/// The keyrings libostree trusts for all remotes.
+const OSTREE_TRUSTED_KEYRINGS: &[&str] = &[
+    "/etc/ostree/trusted.gpg.d",
+    "/usr/share/ostree/trusted.gpg.d",
+];
+/// Indexes in the signature variants of `OstreeGpgVerifyResult`.
+const GPG_SIGNATURE_ATTR_VALID: usize = 0;
+const GPG_SIGNATURE_ATTR_FINGERPRINT: usize = 5;
+const GPG_SIGNATURE_ATTR_FINGERPRINT_PRIMARY: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Repo,
+    Remote,
+}
+
+impl Kind {
+    fn group(self) -> &'static str {
+        match self {
+            Kind::Repo => "RepoKeys",
+            Kind::Remote => "RemoteKeys",
+        }
+    }
+
+    fn flag(self) -> &'static str {
+        match self {
+            Kind::Repo => "repo",
+            Kind::Remote => "remote",
+        }
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            Kind::Repo => "rpm-md repo",
+            Kind::Remote => "ostree remote",
+        }
+    }
+}
+
+#[derive(Debug, clap::Args)]
+struct Target {
+    /// The ID of an rpm-md repo
+    #[clap(long, conflicts_with = "remote", required_unless_present = "remote")]
+    repo: Option<String>,
+
+    /// The name of an ostree remote
+    #[clap(long)]
+    remote: Option<String>,
+}
+
+impl Target {
+    fn get(&self) -> (Kind, &str) {
+        match (self.repo.as_deref(), self.remote.as_deref()) {
+            (Some(repo), _) => (Kind::Repo, repo),
+            (None, Some(remote)) => (Kind::Remote, remote),
+            (None, None) => unreachable!(),
+        }
+    }
+}
+
+/// Pin the signing keys of rpm-md repos and ostree remotes
+#[derive(Debug, Parser)]
+#[clap(name = "rpm-ostree ex repo-keys", rename_all = "kebab-case")]
+enum Opts {
+    /// List the pinned keys, and whether the configured keys match them
+    List,
+    /// Pin the keys of a repo or remote; by default, those it's configured with
+    Pin {
+        #[clap(flatten)]
+        target: Target,
+
+        /// The fingerprints of the keys to pin
+        fingerprints: Vec<String>,
+    },
+    /// Remove the pins of a repo or remote
+    Unpin {
+        #[clap(flatten)]
+        target: Target,
+    },
+    /// Pin a new key of a repo or remote, if it's certified by a pinned key
+    Rotate {
+        #[clap(flatten)]
+        target: Target,
+
+        /// The file holding the new public key
+        #[clap(required_unless_present = "finish")]
+        key: Option<String>,
+
+        /// Drop the pins of the keys the repo or remote isn't configured with anymore
+        #[clap(long, conflicts_with = "key")]
+        finish: bool,
+    },
+}
+
+/// The pinned fingerprints, by repo ID or remote name.
+type Pins = BTreeMap<String, BTreeSet<String>>;
+
+/// Normalize an OpenPGP v4 or v5 fingerprint to uppercase hex without spaces.
+fn parse_fingerprint(s: &str) -> Result<String> {
+    let fpr: String = s
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_ascii_uppercase();
+    if !matches!(fpr.len(), 40 | 64) || !fpr.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!("Invalid key fingerprint: {}", s);
+    }
+    Ok(fpr)
+}
+
+fn load_config(path: &Path) -> Result<glib::KeyFile> {
+    let kf = glib::KeyFile::new();
+    if let Err(e) = kf.load_from_file(path, glib::KeyFileFlags::KEEP_COMMENTS) {
+        if !e.matches(glib::FileError::Noent) {
+            return Err(e).with_context(|| format!("Loading {}", path.display()));
+        }
+    }
+    Ok(kf)
+}
+
+fn load_pins(kf: &glib::KeyFile, kind: Kind) -> Result<Pins> {
+    let mut pins = Pins::new();
+    if !kf.has_group(kind.group()) {
+        return Ok(pins);
+    }
+    for name in kf.keys(kind.group())?.0.iter() {
+        let fprs = kf
+            .string_list(kind.group(), name)?
+            .iter()
+            .filter(|s| !s.is_empty())
+            .map(|s| parse_fingerprint(s))
+            .collect::<Result<BTreeSet<_>>>()
+            .with_context(|| format!("Parsing {} pins of {}", kind.describe(), name))?;
+        if fprs.is_empty() {
+            bail!("No keys pinned for {} {}", kind.describe(), name);
+        }
+        pins.insert(name.to_string(), fprs);
+    }
+    Ok(pins)
+}
+
+fn store_pins(kf: &glib::KeyFile, kind: Kind, pins: &Pins) -> Result<()> {
+    if kf.has_group(kind.group()) {
+        kf.remove_group(kind.group())?;
+    }
+    for (name, fprs) in pins.iter() {
+        let fprs: Vec<&str> = fprs.iter().map(|s| s.as_str()).collect();
+        kf.set_string_list(kind.group(), name, &fprs);
+    }
+    Ok(())
+}
+
+fn store_config(path: &Path, kf: &glib::KeyFile) -> Result<()> {
+    crate::utils::write_file_atomic(path, kf.to_data().as_bytes())
+        .with_context(|| format!("Writing {}", path.display()))
+}
+
+/// Run gpg with a throwaway home directory, returning its stdout.
+fn gpg(home: &Path, args: &[&str], files: &[PathBuf]) -> Result<String> {
+    let out = Command::new("gpg")
+        .arg("--homedir")
+        .arg(home)
+        .args(["--batch", "--quiet", "--with-colons"])
+        .args(args)
+        .args(files)
+        .output()
+        .context("Running gpg")?;
+    if !out.status.success() {
+        bail!(
+            "gpg failed: {}",
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+/// The fingerprints of the primary keys in a `--with-colons` key listing.
+fn parse_primary_fingerprints(listing: &str) -> BTreeSet<String> {
+    let mut r = BTreeSet::new();
+    let mut in_primary = false;
+    for line in listing.lines() {
+        let fields: Vec<&str> = line.split(':').collect();
+        match fields[0] {
+            "pub" => in_primary = true,
+            "fpr" if in_primary => {
+                if let Some(fpr) = fields.get(9) {
+                    r.insert(fpr.to_ascii_uppercase());
+                }
+                in_primary = false;
+            }
+            "sub" | "uid" => in_primary = false,
+            _ => {}
+        }
+    }
+    r
+}
+
+/// The fingerprints of the subkeys of the `pinned` primary keys in a
+/// `--with-colons` key listing.
+fn pinned_subkey_fingerprints(listing: &str, pinned: &BTreeSet<String>) -> BTreeSet<String> {
+    let mut r = BTreeSet::new();
+    let mut primary: Option<String> = None;
+    let mut in_subkey = false;
+    for line in listing.lines() {
+        let fields: Vec<&str> = line.split(':').collect();
+        let fpr = fields.get(9).map(|f| f.to_ascii_uppercase());
+        match fields[0] {
+            "pub" => {
+                primary = None;
+                in_subkey = false;
+            }
+            "sub" => in_subkey = true,
+            "fpr" if !in_subkey && primary.is_none() => primary = fpr,
+            "fpr" if in_subkey => {
+                if primary.as_ref().map(|p| pinned.contains(p)) == Some(true) {
+                    r.extend(fpr);
+                }
+                in_subkey = false;
+            }
+            _ => {}
+        }
+    }
+    r
+}
+
+/// The pinned keys with a valid certification of the user IDs of a key, from
+/// its `--check-signatures` listing.
+fn certifying_keys(listing: &str, pinned: &BTreeSet<String>) -> BTreeSet<String> {
+    listing
+        .lines()
+        .map(|line| line.split(':').collect::<Vec<_>>())
+        .filter(|fields| fields[0] == "sig" && fields.get(1) == Some(&"!"))
+        // Certifications of user IDs are of class 0x10 to 0x13
+        .filter(|fields| fields.get(10).map(|c| c.starts_with('1')) == Some(true))
+        .filter_map(|fields| {
+            let keyid = fields.get(4)?.to_ascii_uppercase();
+            pinned.iter().find(|fpr| fpr.ends_with(&keyid)).cloned()
+        })
+        .collect()
+}
+
+fn key_fingerprints(files: &[PathBuf]) -> Result<BTreeSet<String>> {
+    if files.is_empty() {
+        return Ok(BTreeSet::new());
+    }
+    let home = tempfile::tempdir()?;
+    let listing = gpg(home.path(), &["--show-keys"], files)?;
+    Ok(parse_primary_fingerprints(&listing))
+}
+
+/// Resolve a `gpgkey=` URL to a local path.
+fn repo_keyfile(url: &str, releasever: &str) -> Result<PathBuf> {
+    let url = url
+        .replace("$releasever", releasever)
+        .replace("$basearch", &crate::utils::get_rpm_basearch());
+    match url.strip_prefix("file://") {
+        Some(path) => Ok(PathBuf::from(path)),
+        None if url.starts_with('/') => Ok(PathBuf::from(url)),
+        None => bail!(
+            "Key {} is not local; pass the fingerprints of the keys to pin explicitly",
+            url
+        ),
+    }
+}
+
+/// The key files of the rpm-md repo `id`, from its `gpgkey=`.
+fn repo_keyfiles(id: &str) -> Result<Vec<PathBuf>> {
+    let releasever = os_release::OsRelease::new()
+        .map(|r| r.version_id)
+        .unwrap_or_default();
+    let mut entries = std::fs::read_dir(YUM_REPOS_D)
+        .with_context(|| format!("Reading {}", YUM_REPOS_D))?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort();
+    for path in entries {
+        if path.extension().map(|e| e != "repo").unwrap_or(true) {
+            continue;
+        }
+        let ini = Ini::load_from_file(&path).with_context(|| format!("Parsing {:?}", path))?;
+        if let Some(section) = ini.section(Some(id)) {
+            return section
+                .get("gpgkey")
+                .unwrap_or_default()
+                .split(|c: char| c.is_whitespace() || c == ',')
+                .filter(|s| !s.is_empty())
+                .map(|url| repo_keyfile(url, &releasever))
+                .collect();
+        }
+    }
+    Err(anyhow!("Unknown rpm-md repo: {}", id))
+}
+
+/// The keys in a keyring file, or the files of a keyring directory.
+fn keyring_files(path: &Path) -> Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut r = std::fs::read_dir(path)
+        .with_context(|| format!("Reading {}", path.display()))?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    r.sort();
+    Ok(r)
+}
+
+/// The keyrings trusted for the commits of the ostree remote `remote`.
+fn remote_keyfiles(repo: &ostree::Repo, remote: &str) -> Result<Vec<PathBuf>> {
+    if !repo.remote_list().iter().any(|r| r.as_str() == remote) {
+        bail!("Unknown ostree remote: {}", remote);
+    }
+    let mut r = Vec::new();
+    let repo_path = repo.path().unwrap().path().unwrap();
+    let keyring = repo_path.join(format!("{}.trustedkeys.gpg", remote));
+    if keyring.exists() {
+        r.push(keyring);
+    }
+    let keypath = repo.remote_get_option(remote, "gpgkeypath", Some(""))?;
+    for p in keypath
+        .split(|c| c == ';' || c == ',')
+        .filter(|s| !s.is_empty())
+    {
+        r.extend(keyring_files(Path::new(p))?);
+    }
+    for p in OSTREE_TRUSTED_KEYRINGS.iter().map(Path::new) {
+        if p.exists() {
+            r.extend(keyring_files(p)?);
+        }
+    }
+    Ok(r)
+}
+
+fn configured_keyfiles(kind: Kind, name: &str, repo: &ostree::Repo) -> Result<Vec<PathBuf>> {
+    match kind {
+        Kind::Repo => repo_keyfiles(name),
+        Kind::Remote => remote_keyfiles(repo, name),
+    }
+}
+
+fn configured_keys(kind: Kind, name: &str, repo: &ostree::Repo) -> Result<BTreeSet<String>> {
+    key_fingerprints(&configured_keyfiles(kind, name, repo)?)
+}
+
+fn list(kf: &glib::KeyFile, repo: &ostree::Repo) -> Result<()> {
+    for kind in [Kind::Repo, Kind::Remote] {
+        let pins = load_pins(kf, kind)?;
+        for (name, pinned) in pins.iter() {
+            println!("{} {}:", kind.describe(), name);
+            let configured = configured_keys(kind, name, repo);
+            for fpr in pinned.iter() {
+                match configured.as_ref() {
+                    Ok(c) if !c.contains(fpr) => println!("  {} (not configured)", fpr),
+                    _ => println!("  {}", fpr),
+                }
+            }
+            match configured {
+                Ok(c) => {
+                    for fpr in c.difference(pinned) {
+                        println!("  {} (configured but not pinned)", fpr);
+                    }
+                }
+                Err(e) => println!("  error: {:#}", e),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Pin the keys certified by a pinned key among those of `keyfile`.
+fn rotate(
+    kind: Kind,
+    name: &str,
+    keyfile: &Path,
+    pinned: &BTreeSet<String>,
+    repo: &ostree::Repo,
+) -> Result<BTreeSet<String>> {
+    let home = tempfile::tempdir()?;
+    let new = parse_primary_fingerprints(&gpg(home.path(), &["--show-keys"], &[keyfile.into()])?);
+    let new: BTreeSet<String> = new.difference(pinned).cloned().collect();
+    if new.is_empty() {
+        bail!("No new keys in {}", keyfile.display());
+    }
+    // The certifications can only be checked with the pinned keys in the keyring
+    let mut files = configured_keyfiles(kind, name, repo)?;
+    files.push(keyfile.to_path_buf());
+    gpg(home.path(), &["--import"], &files)?;
+    for fpr in new.iter() {
+        let listing = gpg(home.path(), &["--check-signatures", fpr.as_str()], &[])?;
+        let certifiers = certifying_keys(&listing, pinned);
+        if certifiers.is_empty() {
+            bail!(
+                "Key {} is not certified by any key pinned for {} {}",
+                fpr,
+                kind.describe(),
+                name
+            );
+        }
+        println!(
+            "Key {} is certified by {}",
+            fpr,
+            certifiers.into_iter().collect::<Vec<_>>().join(", ")
+        );
+    }
+    Ok(new)
+}
+
+pub(crate) fn repo_keys_entrypoint(args: &Vec<String>) -> Result<()> {
+    let opts = Opts::parse_from(args.iter());
+    if !matches!(opts, Opts::List) {
+        crate::ffi::client_require_root()?;
+    }
+    let path = Path::new(CONFIG_PATH);
+    let kf = load_config(path)?;
+    let sysroot = &ostree::Sysroot::new_default();
+    sysroot.load(gio::NONE_CANCELLABLE)?;
+    let repo = &sysroot.repo().unwrap();
+    let (kind, name, fprs) = match opts {
+        Opts::List => return list(&kf, repo),
+        Opts::Pin {
+            target,
+            fingerprints,
+        } => {
+            let (kind, name) = target.get();
+            let fprs = if fingerprints.is_empty() {
+                configured_keys(kind, name, repo)?
+            } else {
+                fingerprints
+                    .iter()
+                    .map(|s| parse_fingerprint(s))
+                    .collect::<Result<_>>()?
+            };
+            if fprs.is_empty() {
+                bail!("No keys configured for {} {}", kind.describe(), name);
+            }
+            (kind, name.to_string(), Some(fprs))
+        }
+        Opts::Unpin { target } => {
+            let (kind, name) = target.get();
+            (kind, name.to_string(), None)
+        }
+        Opts::Rotate {
+            target,
+            key,
+            finish,
+        } => {
+            let (kind, name) = target.get();
+            let mut pins = load_pins(&kf, kind)?;
+            let pinned = pins
+                .remove(name)
+                .ok_or_else(|| anyhow!("No keys pinned for {} {}", kind.describe(), name))?;
+            let fprs = if finish {
+                let configured = configured_keys(kind, name, repo)?;
+                if let Some(fpr) = configured.difference(&pinned).next() {
+                    bail!("Configured key {} is not pinned", fpr);
+                }
+                if configured.is_empty() {
+                    bail!("No keys configured for {}", name);
+                }
+                for fpr in pinned.difference(&configured) {
+                    println!("Unpinning {}", fpr);
+                }
+                configured
+            } else {
+                let new = rotate(
+                    kind,
+                    name,
+                    Path::new(key.as_deref().unwrap()),
+                    &pinned,
+                    repo,
+                )?;
+                println!(
+                    "Configure {} {} with the new key, then run `rpm-ostree ex repo-keys rotate --{} {} --finish`.",
+                    kind.describe(),
+                    name,
+                    kind.flag(),
+                    name
+                );
+                pinned.union(&new).cloned().collect()
+            };
+            (kind, name.to_string(), Some(fprs))
+        }
+    };
+    let mut pins = load_pins(&kf, kind)?;
+    match fprs {
+        Some(fprs) => {
+            for fpr in fprs.iter() {
+                println!("Pinned {} for {} {}", fpr, kind.describe(), name);
+            }
+            pins.insert(name, fprs);
+        }
+        None => {
+            if pins.remove(&name).is_none() {
+                bail!("No keys pinned for {} {}", kind.describe(), name);
+            }
+        }
+    }
+    store_pins(&kf, kind, &pins)?;
+    store_config(path, &kf)?;
+    Ok(())
+}
+
+/// Check the pins of rpm-ostreed.conf, on reload.  Errors are only warned
+/// about, since the pins are read again when used, and refuse the operations
+/// they cover as long as they're invalid.
+pub(crate) fn repo_keys_validate() {
+    let r = load_config(Path::new(CONFIG_PATH)).and_then(|kf| {
+        load_pins(&kf, Kind::Repo)?;
+        load_pins(&kf, Kind::Remote)
+    });
+    if let Err(e) = r {
+        systemd::journal::print(4, &format!("Invalid key pins: {:#}", e));
+    }
+}
+
+fn check_rpmmd(pins: &Pins, id: &str, gpgcheck: bool, keys: &[PathBuf]) -> Result<()> {
+    let pinned = match pins.get(id) {
+        Some(p) => p,
+        None => return Ok(()),
+    };
+    if !gpgcheck {
+        bail!(
+            "rpm-md repo {} has pinned keys but gpgcheck is disabled",
+            id
+        );
+    }
+    if keys.is_empty() {
+        bail!("rpm-md repo {} has pinned keys but no keys configured", id);
+    }
+    // Remote keys are only fetched when checking packages; the signer is
+    // checked against the pins then anyways.
+    let local: Vec<_> = keys.iter().filter(|k| k.exists()).cloned().collect();
+    let configured = key_fingerprints(&local)?;
+    if let Some(fpr) = configured.difference(pinned).next() {
+        bail!(
+            "rpm-md repo {} is configured with key {} which is not pinned",
+            id,
+            fpr
+        );
+    }
+    Ok(())
+}
+
+/// Refuse the rpm-md repo `id` if it's pinned and could accept packages signed
+/// by other keys.  `keys` are the local paths of its keys, as returned by
+/// libdnf.
+pub(crate) fn repo_keys_check_rpmmd(id: &str, gpgcheck: bool, keys: &Vec<String>) -> CxxResult<()> {
+    let kf = load_config(Path::new(CONFIG_PATH))?;
+    let pins = load_pins(&kf, Kind::Repo)?;
+    let keys = keys.iter().map(PathBuf::from).collect::<Vec<_>>();
+    Ok(check_rpmmd(&pins, id, gpgcheck, &keys)?)
+}
+
+/// Whether the rpm-md repo `id` has pinned keys.
+pub(crate) fn repo_keys_rpmmd_pinned(id: &str) -> CxxResult<bool> {
+    let kf = load_config(Path::new(CONFIG_PATH))?;
+    Ok(load_pins(&kf, Kind::Repo)?.contains_key(id))
+}
+
+fn check_signer(pins: &Pins, id: &str, keyid: &str, keys: &[PathBuf]) -> Result<()> {
+    let pinned = match pins.get(id) {
+        Some(p) => p,
+        None => return Ok(()),
+    };
+    let keyid = keyid.trim().to_ascii_uppercase();
+    if pinned.iter().any(|fpr| fpr.ends_with(&keyid)) {
+        return Ok(());
+    }
+    if !keys.is_empty() {
+        let home = tempfile::tempdir()?;
+        let listing = gpg(home.path(), &["--show-keys"], keys)?;
+        let subkeys = pinned_subkey_fingerprints(&listing, pinned);
+        if subkeys.iter().any(|fpr| fpr.ends_with(&keyid)) {
+            return Ok(());
+        }
+    }
+    bail!(
+        "Signed by key {} which is not pinned for rpm-md repo {}",
+        keyid,
+        id
+    )
+}
+
+/// Require that `keyid`, the ID of the key which signed a package of the
+/// rpm-md repo `id`, is that of a pinned key or one of its subkeys in `keys`.
+pub(crate) fn repo_keys_check_signer(id: &str, keyid: &str, keys: &Vec<String>) -> CxxResult<()> {
+    let kf = load_config(Path::new(CONFIG_PATH))?;
+    let pins = load_pins(&kf, Kind::Repo)?;
+    let keys = keys.iter().map(PathBuf::from).collect::<Vec<_>>();
+    Ok(check_signer(&pins, id, keyid, &keys)?)
+}
+
+/// Require a valid signature by a pinned key on `commit` if `remote` is pinned.
+pub(crate) fn repo_keys_verify_commit(
+    repo: &crate::ffi::OstreeRepo,
+    remote: &str,
+    commit: &str,
+) -> CxxResult<()> {
+    let repo = &repo.glib_reborrow();
+    let kf = load_config(Path::new(CONFIG_PATH))?;
+    let pins = load_pins(&kf, Kind::Remote)?;
+    let pinned = match pins.get(remote) {
+        Some(p) => p,
+        None => return Ok(()),
+    };
+    let result = repo
+        .verify_commit_for_remote(commit, remote, gio::NONE_CANCELLABLE)
+        .with_context(|| format!("Verifying commit {} from remote {}", commit, remote))?;
+    for i in 0..result.count_all() {
+        let sig = result.all(i);
+        let valid = sig
+            .child_value(GPG_SIGNATURE_ATTR_VALID)
+            .get::<bool>()
+            .unwrap_or_default();
+        let fprs = [
+            GPG_SIGNATURE_ATTR_FINGERPRINT_PRIMARY,
+            GPG_SIGNATURE_ATTR_FINGERPRINT,
+        ]
+        .map(|attr| {
+            sig.child_value(attr)
+                .str()
+                .unwrap_or_default()
+                .to_ascii_uppercase()
+        });
+        if valid && fprs.iter().any(|fpr| pinned.contains(fpr)) {
+            return Ok(());
+        }
+    }
+    Err(anyhow!(
+        "Commit {} from remote {} is not signed by a pinned key",
+        commit,
+        remote
+    )
+    .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FPR_OLD: &str = "115DF9AEF857853EE8445D0A0727707EA15B79CC";
+    const FPR_NEW: &str = "E8F23996F23218640CB44CBE75CF5AC418B8E74C";
+
+    #[test]
+    fn test_parse_fingerprint() {
+        assert_eq!(
+            parse_fingerprint("115d f9ae f857 853e e844  5d0a 0727 707e a15b 79cc").unwrap(),
+            FPR_OLD
+        );
+        assert!(parse_fingerprint("0727707EA15B79CC").is_err());
+        assert!(parse_fingerprint(&FPR_OLD.replace('C', "X")).is_err());
+    }
+
+    #[test]
+    fn test_pins_roundtrip() -> Result<()> {
+        let kf = glib::KeyFile::new();
+        kf.load_from_data(
+            &format!(
+                "[Daemon]\nAutomaticUpdatePolicy=stage\n\n[RepoKeys]\nfedora={};{}\n",
+                FPR_OLD,
+                FPR_NEW.to_ascii_lowercase()
+            ),
+            glib::KeyFileFlags::KEEP_COMMENTS,
+        )?;
+        let mut pins = load_pins(&kf, Kind::Repo)?;
+        assert_eq!(pins["fedora"].len(), 2);
+        assert!(pins["fedora"].contains(FPR_NEW));
+        assert!(load_pins(&kf, Kind::Remote)?.is_empty());
+        pins.insert(
+            "updates".into(),
+            [FPR_NEW.to_string()].into_iter().collect(),
+        );
+        store_pins(&kf, Kind::Repo, &pins)?;
+        assert_eq!(load_pins(&kf, Kind::Repo)?, pins);
+        assert_eq!(
+            kf.string("Daemon", "AutomaticUpdatePolicy")?.as_str(),
+            "stage"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_listings() {
+        let listing = format!(
+            "pub:-:4096:1:0727707EA15B79CC:1586276747:::-:::scSC::::::23::0:\n\
+             fpr:::::::::{}:\n\
+             uid:-::::1586276747::ABC::Fedora (33) <fedora-33@fedoraproject.org>::::::::::0:\n\
+             sig:!::1:0727707EA15B79CC:1586276747::::Fedora (33):13x:::::8:\n\
+             sig:!::1:75CF5AC418B8E74C:1600000000::::Fedora (34):10x:::::8:\n\
+             sig:-::1:0000000000000001:1600000000::::Someone:10x:::::8:\n\
+             sub:-:4096:1:1111111111111111:1586276747::::::e::::::23:\n\
+             fpr:::::::::2222222222222222222222222222222222222222:\n",
+            FPR_OLD
+        );
+        assert_eq!(
+            parse_primary_fingerprints(&listing),
+            [FPR_OLD.to_string()].into_iter().collect()
+        );
+        let pinned: BTreeSet<String> = [FPR_NEW.to_string()].into_iter().collect();
+        assert_eq!(certifying_keys(&listing, &pinned), pinned);
+        assert!(certifying_keys(&listing, &BTreeSet::new()).is_empty());
+        let primary: BTreeSet<String> = [FPR_OLD.to_string()].into_iter().collect();
+        assert_eq!(
+            pinned_subkey_fingerprints(&listing, &primary),
+            ["2222222222222222222222222222222222222222".to_string()]
+                .into_iter()
+                .collect()
+        );
+        assert!(pinned_subkey_fingerprints(&listing, &pinned).is_empty());
+    }
+
+    #[test]
+    fn test_check_rpmmd() {
+        let pins: Pins = [(
+            "fedora".to_string(),
+            [FPR_OLD.to_string()].into_iter().collect(),
+        )]
+        .into_iter()
+        .collect();
+        assert!(check_rpmmd(&pins, "other", false, &[]).is_ok());
+        assert!(check_rpmmd(&pins, "fedora", false, &[]).is_err());
+        assert!(check_rpmmd(&pins, "fedora", true, &[]).is_err());
+        // Not downloaded yet
+        let remote = PathBuf::from("/nonexistent/RPM-GPG-KEY-fedora");
+        assert!(check_rpmmd(&pins, "fedora", true, &[remote]).is_ok());
+        assert!(check_signer(&pins, "other", "0000000000000001", &[]).is_ok());
+        assert!(check_signer(&pins, "fedora", "0727707ea15b79cc", &[]).is_ok());
+        assert!(check_signer(&pins, "fedora", "75CF5AC418B8E74C", &[]).is_err());
+    }
+}
@@ -0,0 +1,161 @@
+//! Keeping rollback deployments per `KeepRollbackDeployments` across the
+//! finalization of a staged deployment.
+//!
+//! libostree drops all the rollback deployments but the booted one when
+//! finalizing a staged deployment, unless they're pinned.  So after staging,
+//! the daemon pins the rollbacks to keep itself, and records them in
+//! `/var/lib/rpm-ostree/retained-rollbacks.json`, apart from the pins of
+//! `rpm-ostree pin`; these pins are released again once there is no staged
+//! deployment anymore.  `rpm-ostree status` shows the deployments as retained
+//! rather than pinned.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::cxxrsutil::*;
+use crate::deployment_generate_id_impl;
+use crate::pin::set_pinned_now;
+use anyhow::{Context, Result};
+use ostree_ext::{glib, ostree};
+use std::collections::BTreeSet;
+use std::path::Path;
+
+const STATE_PATH: &str = "/var/lib/rpm-ostree/retained-rollbacks.json";
+
+/// The IDs of the deployments pinned to keep them.
+type RetentionState = BTreeSet<String>;
+
+fn load_state(path: &Path) -> Result<RetentionState> {
+    match std::fs::read_to_string(path) {
+        Ok(s) => serde_json::from_str(&s).with_context(|| format!("Parsing {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(RetentionState::new()),
+        Err(e) => Err(e).with_context(|| format!("Reading {}", path.display())),
+    }
+}
+
+fn store_state(path: &Path, state: &RetentionState) -> Result<()> {
+    if state.is_empty() {
+        return match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("Removing {}", path.display()))
+            }
+            _ => Ok(()),
+        };
+    }
+    let buf = serde_json::to_vec(state)?;
+    crate::utils::write_file_atomic(path, buf)
+}
+
+fn retention_pin_rollbacks_impl(sysroot: &ostree::Sysroot, path: &Path, keep: u32) -> Result<()> {
+    let booted = match sysroot.booted_deployment() {
+        Some(b) => b,
+        None => return Ok(()),
+    };
+    let mut state = load_state(path)?;
+    let deployments = sysroot.deployments();
+    // Start over from the current deployments
+    for d in deployments.iter() {
+        if state.remove(&deployment_generate_id_impl(d)) && d.is_pinned() {
+            set_pinned_now(sysroot, d, false)?;
+        }
+    }
+    // Once finalized, the booted deployment is the first rollback; keep the
+    // next most recent ones.  Pinned deployments don't count.
+    let osname = booted.osname().expect("osname");
+    let rollbacks = deployments
+        .iter()
+        .skip_while(|d| !d.equal(&booted))
+        .skip(1)
+        .filter(|d| d.osname().as_ref() == Some(&osname) && !d.is_pinned())
+        .take(keep.saturating_sub(1) as usize);
+    for d in rollbacks {
+        set_pinned_now(sysroot, d, true)?;
+        state.insert(deployment_generate_id_impl(d));
+    }
+    store_state(path, &state)
+}
+
+/// Pin the rollback deployments to keep per `KeepRollbackDeployments=keep`
+/// through the finalization of the staged deployment.  This must be called with
+/// the sysroot lock held, after staging.
+pub(crate) fn retention_pin_rollbacks(
+    sysroot: &crate::ffi::OstreeSysroot,
+    keep: u32,
+) -> CxxResult<()> {
+    let sysroot = &sysroot.glib_reborrow();
+    retention_pin_rollbacks_impl(sysroot, Path::new(STATE_PATH), keep)?;
+    Ok(())
+}
+
+fn release_retention_pins_impl(sysroot: &ostree::Sysroot, path: &Path, force: bool) -> Result<()> {
+    let mut state = load_state(path)?;
+    if state.is_empty() || !(force || sysroot.staged_deployment().is_none()) {
+        return Ok(());
+    }
+    for d in sysroot.deployments() {
+        if state.contains(&deployment_generate_id_impl(&d)) && d.is_pinned() {
+            set_pinned_now(sysroot, &d, false)?;
+        }
+    }
+    state.clear();
+    store_state(path, &state)
+}
+
+/// Release the retention pins if there is no staged deployment anymore, or if
+/// `force` is set.  This must be called with the sysroot lock held.
+pub(crate) fn release_retention_pins(
+    sysroot: &crate::ffi::OstreeSysroot,
+    force: bool,
+) -> CxxResult<()> {
+    let sysroot = &sysroot.glib_reborrow();
+    release_retention_pins_impl(sysroot, Path::new(STATE_PATH), force)?;
+    Ok(())
+}
+
+/// Forget that `deployment` was pinned to keep it, e.g. because it is pinned
+/// or unpinned explicitly.  This must be called with the sysroot lock held.
+pub(crate) fn forget_retained(deployment: &ostree::Deployment) -> Result<()> {
+    let path = Path::new(STATE_PATH);
+    let mut state = load_state(path)?;
+    if state.remove(&deployment_generate_id_impl(deployment)) {
+        store_state(path, &state)?;
+    }
+    Ok(())
+}
+
+/// Mark `deployment` as retained in `dict` if it is pinned to keep it.
+pub(crate) fn deployment_populate_retained(
+    deployment: &ostree::Deployment,
+    dict: &glib::VariantDict,
+) -> Result<()> {
+    if !deployment.is_pinned() {
+        return Ok(());
+    }
+    let state = load_state(Path::new(STATE_PATH))?;
+    if state.contains(&deployment_generate_id_impl(deployment)) {
+        dict.insert("retained", &true);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        let path = &td.path().join("retained-rollbacks.json");
+        assert!(load_state(path)?.is_empty());
+        let state: RetentionState = ["fedora-abc.0", "fedora-def.1"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        store_state(path, &state)?;
+        assert_eq!(load_state(path)?, state);
+        store_state(path, &RetentionState::new())?;
+        assert!(!path.exists());
+        // Removing it again is fine
+        store_state(path, &RetentionState::new())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,196 @@
+//! Phased rollouts: an update commit may be rolled out to an increasing share
+//! of machines over time, so that problems are found before they reach the
+//! whole fleet.  Each machine gets a stable bucket derived from its machine ID,
+//! and automatic updates are deferred until the rollout reaches it.  This is
+//! described in the commit metadata:
+//!
+//! - `rpmostree.rollout.duration` (`t`): how many seconds the rollout takes to
+//!   reach all machines; commits without it aren't phased
+//! - `rpmostree.rollout.start` (`t`): when the rollout starts, as a Unix
+//!   timestamp; defaults to the commit timestamp
+//! - `rpmostree.rollout.start-percentage` (`u`): the share of machines the
+//!   update is rolled out to when it starts; defaults to 0
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::cxxrsutil::*;
+use anyhow::{bail, Result};
+use chrono::prelude::*;
+use ostree_ext::{glib, ostree};
+
+const DURATION: &str = "rpmostree.rollout.duration";
+const START: &str = "rpmostree.rollout.start";
+const START_PERCENTAGE: &str = "rpmostree.rollout.start-percentage";
+/// The bucket is derived from the machine ID with this application ID, so
+/// that it's independent from other IDs derived from it.
+const APP_ID: &str = "4f0b8e3a2c6d4b9e8a1f5c7d3e9b2a60";
+
+#[derive(Debug, PartialEq, Eq)]
+struct Rollout {
+    start: i64,
+    start_percentage: u32,
+    duration: u64,
+}
+
+impl Rollout {
+    /// Parse the rollout from the metadata of a commit made at `timestamp`.
+    fn from_metadata(commitmeta: &glib::VariantDict, timestamp: u64) -> Result<Option<Self>> {
+        let lookup_u64 = |key| commitmeta.lookup::<u64>(key).map_err(anyhow::Error::msg);
+        let duration = match lookup_u64(DURATION)? {
+            Some(d) => d,
+            None => return Ok(None),
+        };
+        let start = lookup_u64(START)?.unwrap_or(timestamp);
+        let start_percentage = commitmeta
+            .lookup::<u32>(START_PERCENTAGE)
+            .map_err(anyhow::Error::msg)?
+            .unwrap_or(0);
+        if start_percentage > 100 {
+            bail!("Invalid {}: {}", START_PERCENTAGE, start_percentage);
+        }
+        Ok(Some(Self {
+            start: start.try_into()?,
+            start_percentage,
+            duration,
+        }))
+    }
+
+    /// The share of machines the update is rolled out to at `t`, in percent.
+    fn percentage(&self, t: i64) -> f64 {
+        if t < self.start {
+            return 0.0;
+        }
+        let start = self.start_percentage as f64;
+        if self.duration == 0 {
+            return 100.0;
+        }
+        let progress = (t - self.start) as f64 / self.duration as f64;
+        (start + (100.0 - start) * progress).min(100.0)
+    }
+
+    /// When the rollout reaches `bucket`.
+    fn reaches(&self, bucket: f64) -> i64 {
+        let start = self.start_percentage as f64;
+        if bucket < start {
+            return self.start;
+        }
+        let progress = (bucket - start) / (100.0 - start);
+        self.start + (progress * self.duration as f64).ceil() as i64
+    }
+
+    /// Describe why the update is deferred at `t` for a machine in `bucket`,
+    /// if it is.
+    fn deferral(&self, bucket: f64, t: i64) -> Option<String> {
+        let percentage = self.percentage(t);
+        if bucket < percentage {
+            return None;
+        }
+        let reaches = Local
+            .timestamp(self.reaches(bucket), 0)
+            .format("%a %Y-%m-%d %H:%M %:z");
+        Some(format!(
+            "rolled out to {:.0}% of machines, this one is at {:.0}%; expected {}",
+            percentage.floor(),
+            bucket.floor(),
+            reaches
+        ))
+    }
+}
+
+/// The bucket of this machine, in percent: the rollout reaches it once it's
+/// rolled out to more than that share of machines.
+fn bucket() -> Result<f64> {
+    let id = crate::utils::machine_app_specific_id(APP_ID)?;
+    let v = u32::from_str_radix(&id[..8], 16)?;
+    Ok(v as f64 * 100.0 / (u32::MAX as f64 + 1.0))
+}
+
+/// Return why `commit` isn't rolled out to this machine yet, or an empty string
+/// if it is, or if it isn't phased.
+pub(crate) fn rollout_deferral(commit: &crate::FFIGVariant) -> CxxResult<String> {
+    let commit = commit.glib_reborrow();
+    let commitmeta = &glib::VariantDict::new(Some(&commit.child_value(0)));
+    let timestamp = ostree::commit_get_timestamp(&commit);
+    let rollout = match Rollout::from_metadata(commitmeta, timestamp)? {
+        Some(r) => r,
+        None => return Ok(String::new()),
+    };
+    Ok(rollout
+        .deferral(bucket()?, Utc::now().timestamp())
+        .unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glib::ToVariant;
+
+    fn metadata(meta: &[(&str, glib::Variant)]) -> glib::VariantDict {
+        let dict = glib::VariantDict::new(None);
+        for (k, v) in meta {
+            dict.insert_value(k, v);
+        }
+        dict
+    }
+
+    #[test]
+    fn test_from_metadata() -> Result<()> {
+        assert_eq!(Rollout::from_metadata(&metadata(&[]), 1000)?, None);
+        let meta = metadata(&[(DURATION, 3600u64.to_variant())]);
+        let r = Rollout::from_metadata(&meta, 1000)?.unwrap();
+        assert_eq!(
+            r,
+            Rollout {
+                start: 1000,
+                start_percentage: 0,
+                duration: 3600
+            }
+        );
+        let meta = metadata(&[
+            (DURATION, 3600u64.to_variant()),
+            (START, 5000u64.to_variant()),
+            (START_PERCENTAGE, 10u32.to_variant()),
+        ]);
+        let r = Rollout::from_metadata(&meta, 1000)?.unwrap();
+        assert_eq!((r.start, r.start_percentage), (5000, 10));
+        let meta = metadata(&[
+            (DURATION, 3600u64.to_variant()),
+            (START_PERCENTAGE, 101u32.to_variant()),
+        ]);
+        assert!(Rollout::from_metadata(&meta, 1000).is_err());
+        // Wrong type
+        let meta = metadata(&[(DURATION, "1h".to_variant())]);
+        assert!(Rollout::from_metadata(&meta, 1000).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rollout() {
+        let r = Rollout {
+            start: 1000,
+            start_percentage: 20,
+            duration: 800,
+        };
+        assert_eq!(r.percentage(999), 0.0);
+        assert_eq!(r.percentage(1000), 20.0);
+        assert_eq!(r.percentage(1400), 60.0);
+        assert_eq!(r.percentage(5000), 100.0);
+        assert_eq!(r.reaches(10.0), 1000);
+        assert_eq!(r.reaches(60.0), 1400);
+        assert!(r.deferral(59.5, 1400).is_none());
+        let msg = r.deferral(60.0, 1400).unwrap();
+        assert!(
+            msg.starts_with("rolled out to 60% of machines, this one is at 60%; expected "),
+            "{}",
+            msg
+        );
+        assert!(r.deferral(99.9, 1800).is_none());
+        let immediate = Rollout {
+            start: 1000,
+            start_percentage: 0,
+            duration: 0,
+        };
+        assert!(immediate.deferral(99.9, 1000).is_none());
+        assert!(immediate.deferral(0.0, 999).is_some());
+    }
+}
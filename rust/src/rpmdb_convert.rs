@@ -0,0 +1,207 @@
+//! Conversion of the rpmdb between the bdb, ndb and sqlite backends.
+//!
+//! The rpmdb is normally written in the format the target's rpm defaults to.
+//! But it can end up in another one, e.g. when the base was composed before
+//! the target switched to sqlite and the layered packages are added
+//! incrementally to it, or when a treefile asks for a specific format with
+//! `rpmdb-format`.  The conversion exports all the headers and imports them
+//! again with the target's rpm, and then checks that the same headers are
+//! found in the new rpmdb as in the old one before replacing it.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::bwrap::Bubblewrap;
+use crate::composepost::RPMOSTREE_RPMDB_LOCATION;
+use crate::cxxrsutil::*;
+use crate::ffi::BubblewrapMutability;
+use crate::ffiutil::ffi_view_openat_dir;
+use crate::rpmdb_update::{dbpath_arg, query_packages};
+use crate::treefile::{RpmdbFormat, Treefile};
+use anyhow::{bail, Context, Result};
+use fn_error_context::context;
+use openat_ext::OpenatDirExt;
+use std::io::{Seek, Write};
+use std::os::unix::io::IntoRawFd;
+
+/// Where the converted rpmdb is written before replacing the original.
+const RPMDB_CONVERTED: &str = "usr/share/rpm.rpmostree-convert";
+
+/// Mapping of the treefile `rpmdb-format` values to what rpm calls them.
+trait RpmdbFormatExt: Sized {
+    /// The value of the `_db_backend` macro.
+    fn backend(self) -> &'static str;
+    fn from_backend(backend: &str) -> Option<Self>;
+    /// The file identifying an rpmdb in this format.
+    fn marker(self) -> &'static str;
+}
+
+impl RpmdbFormatExt for RpmdbFormat {
+    fn backend(self) -> &'static str {
+        match self {
+            RpmdbFormat::Bdb => "bdb",
+            RpmdbFormat::Ndb => "ndb",
+            RpmdbFormat::Sqlite => "sqlite",
+        }
+    }
+
+    fn from_backend(backend: &str) -> Option<Self> {
+        match backend {
+            "bdb" | "bdb_ro" => Some(RpmdbFormat::Bdb),
+            "ndb" => Some(RpmdbFormat::Ndb),
+            "sqlite" => Some(RpmdbFormat::Sqlite),
+            _ => None,
+        }
+    }
+
+    fn marker(self) -> &'static str {
+        match self {
+            RpmdbFormat::Bdb => "Packages",
+            RpmdbFormat::Ndb => "Packages.db",
+            RpmdbFormat::Sqlite => "rpmdb.sqlite",
+        }
+    }
+}
+
+/// The format of the rpmdb at `path`, if any.
+fn detect_format(rootfs: &openat::Dir, path: &str) -> Result<Option<RpmdbFormat>> {
+    for format in [RpmdbFormat::Sqlite, RpmdbFormat::Ndb, RpmdbFormat::Bdb] {
+        if rootfs.exists(format!("{}/{}", path, format.marker()).as_str())? {
+            return Ok(Some(format));
+        }
+    }
+    Ok(None)
+}
+
+/// The format the target's rpm writes by default.
+fn target_format(rootfs: &openat::Dir) -> Result<Option<RpmdbFormat>> {
+    let mut bwrap = Bubblewrap::new_with_mutability(rootfs, BubblewrapMutability::Immutable)?;
+    bwrap.append_child_argv(["rpm", "--eval", "%{_db_backend}"]);
+    let out = bwrap.run_captured(None)?;
+    let out = String::from_utf8(out.to_vec())?;
+    Ok(RpmdbFormat::from_backend(out.trim()))
+}
+
+/// Write the rpmdb at `RPMOSTREE_RPMDB_LOCATION` in `format` to
+/// `RPMDB_CONVERTED`, and check that it has the same headers.
+fn convert_to(rootfs: &openat::Dir, format: RpmdbFormat) -> Result<usize> {
+    let mut bwrap = Bubblewrap::new_with_mutability(rootfs, BubblewrapMutability::Immutable)?;
+    let dbpath = dbpath_arg(RPMOSTREE_RPMDB_LOCATION);
+    bwrap.append_child_argv(["rpmdb", dbpath.as_str(), "--exportdb"]);
+    let headers = bwrap
+        .run_captured(None)
+        .context("Failed to run rpmdb --exportdb")?;
+    let mut dbfd = memfd::MemfdOptions::default().create("rpmdb")?.into_file();
+    dbfd.write_all(&headers)?;
+    dbfd.seek(std::io::SeekFrom::Start(0))?;
+
+    rootfs.remove_all(RPMDB_CONVERTED)?;
+    rootfs.create_dir(RPMDB_CONVERTED, 0o755)?;
+    let dbpath = dbpath_arg(RPMDB_CONVERTED);
+    let define = format!("_db_backend {}", format.backend());
+    let mut bwrap = Bubblewrap::new_with_mutability(rootfs, BubblewrapMutability::RoFiles)?;
+    bwrap.append_child_argv([
+        "rpmdb",
+        dbpath.as_str(),
+        "--define",
+        define.as_str(),
+        "--importdb",
+    ]);
+    bwrap.take_stdin_fd(dbfd.into_raw_fd());
+    bwrap
+        .run_inner(None)
+        .context("Failed to run rpmdb --importdb")?;
+
+    if detect_format(rootfs, RPMDB_CONVERTED)? != Some(format) {
+        bail!(
+            "The target's rpm doesn't support writing {}",
+            format.backend()
+        );
+    }
+    let expected = query_packages(rootfs, RPMOSTREE_RPMDB_LOCATION, true)?;
+    let found = query_packages(rootfs, RPMDB_CONVERTED, true)?;
+    if expected != found {
+        let missing: Vec<_> = expected.difference(&found).collect();
+        let extra: Vec<_> = found.difference(&expected).collect();
+        bail!(
+            "Verification failed: missing {:?}, unexpected {:?}",
+            missing,
+            extra
+        );
+    }
+    Ok(found.len())
+}
+
+#[context("Converting rpmdb to {}", format.backend())]
+fn convert_rpmdb(rootfs: &openat::Dir, format: RpmdbFormat) -> Result<()> {
+    let n = match convert_to(rootfs, format) {
+        Ok(n) => n,
+        Err(e) => {
+            rootfs.remove_all(RPMDB_CONVERTED)?;
+            return Err(e);
+        }
+    };
+    rootfs.remove_all(RPMOSTREE_RPMDB_LOCATION)?;
+    rootfs.local_rename(RPMDB_CONVERTED, RPMOSTREE_RPMDB_LOCATION)?;
+    println!("Verified converted rpmdb: {} packages", n);
+    Ok(())
+}
+
+fn convert_for_target(rootfs: &openat::Dir, wanted: Option<RpmdbFormat>) -> Result<()> {
+    let current = match detect_format(rootfs, RPMOSTREE_RPMDB_LOCATION)? {
+        Some(f) => f,
+        None => return Ok(()),
+    };
+    let wanted = match wanted {
+        Some(f) => f,
+        None => match target_format(rootfs)? {
+            Some(f) => f,
+            None => return Ok(()),
+        },
+    };
+    if current == wanted {
+        return Ok(());
+    }
+    println!(
+        "Converting rpmdb from {} to {}",
+        current.backend(),
+        wanted.backend()
+    );
+    convert_rpmdb(rootfs, wanted)
+}
+
+/// Convert the rpmdb of `rootfs_dfd` to the format requested by the treefile
+/// with `rpmdb-format`, or else to the one the target's rpm writes, if it's
+/// in another one.
+pub(crate) fn rpmdb_convert_for_target(rootfs_dfd: i32, treefile: &Treefile) -> CxxResult<()> {
+    let rootfs = &ffi_view_openat_dir(rootfs_dfd);
+    let tempetc = crate::core::prepare_tempetc_guard(rootfs_dfd)?;
+    let r = convert_for_target(rootfs, treefile.parsed.base.rpmdb_format);
+    tempetc.undo()?;
+    Ok(r?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_format() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        let rootfs = openat::Dir::open(td.path())?;
+        rootfs.ensure_dir_all(RPMOSTREE_RPMDB_LOCATION, 0o755)?;
+        assert_eq!(detect_format(&rootfs, RPMOSTREE_RPMDB_LOCATION)?, None);
+        rootfs.write_file_contents("usr/share/rpm/Packages", 0o644, b"")?;
+        assert_eq!(
+            detect_format(&rootfs, RPMOSTREE_RPMDB_LOCATION)?,
+            Some(RpmdbFormat::Bdb)
+        );
+        rootfs.write_file_contents("usr/share/rpm/rpmdb.sqlite", 0o644, b"")?;
+        assert_eq!(
+            detect_format(&rootfs, RPMOSTREE_RPMDB_LOCATION)?,
+            Some(RpmdbFormat::Sqlite)
+        );
+        assert_eq!(RpmdbFormat::from_backend("bdb_ro"), Some(RpmdbFormat::Bdb));
+        assert_eq!(RpmdbFormat::from_backend("dummy"), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,208 @@
+//! Incremental updates of the rpmdb in the target's native format.
+//!
+//! When layering, librpm on the host adds the layered packages to the rpmdb of
+//! the base.  Since the host's librpm may use another format than the target,
+//! the rpmdb is then regenerated with the target's rpm from an export of all
+//! the headers (see `rewrite_rpmdb_for_target`), which dominates the time of
+//! small transactions.  Instead, the rpmdb of the base is snapshotted before
+//! librpm writes to it, and only the packages which were added or removed are
+//! applied to that snapshot with the target's rpm, importing the headers of
+//! the added packages which the caller exported with librpm.  If that fails,
+//! the caller falls back to regenerating the rpmdb.
+//!
+//! Set `RPMOSTREE_RPMDB_VERIFY` to check that the result has the same packages
+//! as the rpmdb written by the host.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::bwrap::Bubblewrap;
+use crate::composepost::RPMOSTREE_RPMDB_LOCATION;
+use crate::cxxrsutil::*;
+use crate::ffi::BubblewrapMutability;
+use crate::ffiutil::ffi_view_openat_dir;
+use anyhow::{bail, Context, Result};
+use fn_error_context::context;
+use openat_ext::OpenatDirExt;
+use std::collections::BTreeSet;
+use std::os::unix::io::{AsRawFd, IntoRawFd};
+use std::process::{Command, Stdio};
+
+/// The rpmdb before librpm on the host changed it; hardlinked.
+const RPMDB_SNAPSHOT: &str = "usr/share/rpm.rpmostree-snapshot";
+/// The rpmdb written by librpm on the host, while updating the snapshot.
+const RPMDB_HOST: &str = "usr/share/rpm.rpmostree-host";
+/// Environment variable enabling the verification of the result.
+const VERIFY_ENV: &str = "RPMOSTREE_RPMDB_VERIFY";
+
+pub(crate) fn dbpath_arg(path: &str) -> String {
+    format!("--dbpath=/proc/self/cwd/{}", path)
+}
+
+/// Snapshot the rpmdb of `rootfs_dfd`, if any, for `rpmdb_update_for_target()`.
+/// This must be called before the hardlinks of the rpmdb are broken for librpm
+/// on the host to write to it.
+pub(crate) fn rpmdb_snapshot(rootfs_dfd: i32) -> CxxResult<bool> {
+    let rootfs = &ffi_view_openat_dir(rootfs_dfd);
+    if !rootfs.exists(RPMOSTREE_RPMDB_LOCATION)? {
+        return Ok(false);
+    }
+    rootfs.remove_all(RPMDB_SNAPSHOT)?;
+    rootfs.create_dir(RPMDB_SNAPSHOT, 0o755)?;
+    crate::composepost::hardlink_hierarchy(rootfs, RPMOSTREE_RPMDB_LOCATION, RPMDB_SNAPSHOT, None)?;
+    Ok(true)
+}
+
+/// Copy the files which are hardlinked, e.g. to the repository, before they
+/// are changed.
+fn break_hardlinks(rootfs: &openat::Dir, path: &str) -> Result<()> {
+    for ent in rootfs.list_dir(path)? {
+        let ent = ent?;
+        let name = format!("{}/{}", path, ent.file_name().to_str().unwrap());
+        let meta = rootfs.metadata(name.as_str())?;
+        if meta.simple_type() != openat::SimpleType::File || meta.stat().st_nlink < 2 {
+            continue;
+        }
+        let tmp = format!("{}.tmp", name);
+        rootfs.copy_file(name.as_str(), tmp.as_str())?;
+        rootfs.local_rename(tmp.as_str(), name.as_str())?;
+    }
+    Ok(())
+}
+
+/// The packages in the rpmdb at `path`, queried with the target's rpm if
+/// `target`, or else with the host's.
+pub(crate) fn query_packages(
+    rootfs: &openat::Dir,
+    path: &str,
+    target: bool,
+) -> Result<BTreeSet<String>> {
+    let args = [
+        dbpath_arg(path),
+        "-qa".to_string(),
+        "--qf".to_string(),
+        "%{NEVRA} %{SHA256HEADER}\\n".to_string(),
+    ];
+    let out = if target {
+        let mut bwrap = Bubblewrap::new_with_mutability(rootfs, BubblewrapMutability::Immutable)?;
+        bwrap.append_child_argv(std::iter::once("rpm").chain(args.iter().map(|s| s.as_str())));
+        let out = bwrap.run_captured(None)?;
+        String::from_utf8(out.to_vec())?
+    } else {
+        let out = Command::new("rpm")
+            .args(&args)
+            .current_dir(format!("/proc/self/fd/{}", rootfs.as_raw_fd()))
+            .stderr(Stdio::inherit())
+            .output()?;
+        if !out.status.success() {
+            bail!("Failed to execute rpm -qa: {:?}", out.status);
+        }
+        String::from_utf8(out.stdout)?
+    };
+    Ok(out.lines().map(|l| l.to_string()).collect())
+}
+
+/// Apply the packages `added` to and `removed` from the host's rpmdb to the
+/// snapshot, with the target's rpm; all are given as name-version-release.arch,
+/// and the headers of the added ones are read from `added_headers_fd`.
+fn update_snapshot(
+    rootfs: &openat::Dir,
+    added: &[String],
+    added_headers_fd: i32,
+    removed: &[String],
+) -> Result<()> {
+    let dbpath = dbpath_arg(RPMOSTREE_RPMDB_LOCATION);
+
+    if !removed.is_empty() {
+        let mut bwrap = Bubblewrap::new_with_mutability(rootfs, BubblewrapMutability::RoFiles)?;
+        bwrap.append_child_argv([
+            "rpm",
+            dbpath.as_str(),
+            "-e",
+            "--justdb",
+            "--nodeps",
+            "--noscripts",
+            "--notriggers",
+        ]);
+        bwrap.append_child_argv(removed.iter().map(|s| s.as_str()));
+        bwrap
+            .run_inner(None)
+            .context("Failed to run rpm -e --justdb")?;
+    }
+
+    if !added.is_empty() {
+        if added_headers_fd < 0 {
+            bail!("Missing the headers of the added packages");
+        }
+        let added_headers_fd = unsafe { rustix::fd::BorrowedFd::borrow_raw(added_headers_fd) };
+        let headers = rustix::io::fcntl_dupfd_cloexec(&added_headers_fd, 0)?;
+        let mut bwrap = Bubblewrap::new_with_mutability(rootfs, BubblewrapMutability::RoFiles)?;
+        bwrap.append_child_argv(["rpmdb", dbpath.as_str(), "--importdb"]);
+        bwrap.take_stdin_fd(headers.into_raw_fd());
+        bwrap
+            .run_inner(None)
+            .context("Failed to run rpmdb --importdb")?;
+    }
+    Ok(())
+}
+
+#[context("Updating rpmdb for target native format")]
+fn rpmdb_update_for_target_inner(
+    rootfs: &openat::Dir,
+    added: &[String],
+    added_headers_fd: i32,
+    removed: &[String],
+) -> Result<()> {
+    rootfs.local_rename(RPMOSTREE_RPMDB_LOCATION, RPMDB_HOST)?;
+    rootfs.local_rename(RPMDB_SNAPSHOT, RPMOSTREE_RPMDB_LOCATION)?;
+    break_hardlinks(rootfs, RPMOSTREE_RPMDB_LOCATION)?;
+    let tempetc = crate::core::prepare_tempetc_guard(rootfs.as_raw_fd())?;
+    update_snapshot(rootfs, added, added_headers_fd, removed)?;
+    if std::env::var_os(VERIFY_ENV).is_some() {
+        let expected = query_packages(rootfs, RPMDB_HOST, false)?;
+        let found = query_packages(rootfs, RPMOSTREE_RPMDB_LOCATION, true)?;
+        if expected != found {
+            let missing: Vec<_> = expected.difference(&found).collect();
+            let extra: Vec<_> = found.difference(&expected).collect();
+            bail!(
+                "Verification failed: missing {:?}, unexpected {:?}",
+                missing,
+                extra
+            );
+        }
+        crate::ffi::output_message(&format!(
+            "Verified rpmdb for target: {} packages",
+            found.len()
+        ));
+    }
+    tempetc.undo()?;
+    Ok(())
+}
+
+/// Update the rpmdb snapshotted by `rpmdb_snapshot()` in the target's native
+/// format, given the packages (as name-version-release.arch) which librpm on
+/// the host has since `added` and `removed`, and the headers of the added ones
+/// in `added_headers_fd` (or -1 if they couldn't be exported).  Returns false
+/// if that failed, leaving the host's rpmdb in place to be regenerated instead.
+pub(crate) fn rpmdb_update_for_target(
+    rootfs_dfd: i32,
+    added: &Vec<String>,
+    added_headers_fd: i32,
+    removed: &Vec<String>,
+) -> CxxResult<bool> {
+    let rootfs = &ffi_view_openat_dir(rootfs_dfd);
+    match rpmdb_update_for_target_inner(rootfs, added, added_headers_fd, removed) {
+        Ok(()) => {
+            rootfs.remove_all(RPMDB_HOST)?;
+            Ok(true)
+        }
+        Err(e) => {
+            crate::ffi::output_message(&format!("{:#}; regenerating instead", e));
+            if rootfs.exists(RPMDB_HOST)? {
+                rootfs.remove_all(RPMOSTREE_RPMDB_LOCATION)?;
+                rootfs.local_rename(RPMDB_HOST, RPMOSTREE_RPMDB_LOCATION)?;
+            }
+            rootfs.remove_all(RPMDB_SNAPSHOT)?;
+            Ok(false)
+        }
+    }
+}
@@ -0,0 +1,528 @@
+//! Implementation of `rpm-ostree ex scan`: match the packages of a deployment
+//! against security advisories, from the updateinfo of the rpm-md repos cached
+//! by the last refresh and from OSV records, without network access.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::core::evrcmp;
+use crate::cxxrsutil::*;
+use crate::deployment_diff::commit_packages;
+use crate::deployment_generate_id_impl;
+use anyhow::{Context, Result};
+use clap::Parser;
+use ostree_ext::{gio, glib, ostree};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// See `RPMOSTREE_CORE_CACHEDIR`.
+const CACHEDIR: &str = "/var/cache/rpm-ostree";
+/// The version of the OSV schema of the exported records.
+const OSV_SCHEMA_VERSION: &str = "1.3.0";
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    /// A human-readable list
+    Text,
+    /// A JSON report of the affected packages
+    Json,
+    /// A JSON array of OSV records
+    Osv,
+}
+
+/// Match the packages of a deployment against security advisories
+#[derive(Debug, Parser)]
+#[clap(name = "rpm-ostree ex scan")]
+#[clap(rename_all = "kebab-case")]
+struct Opts {
+    /// The deployment to scan: its index, or booted, pending or rollback
+    #[clap(default_value = "booted")]
+    deployment: String,
+
+    /// Also match against the OSV records in this JSON file or directory
+    #[clap(long, value_name = "PATH")]
+    osv: Vec<String>,
+
+    /// Only use the OSV records for this ecosystem (e.g. `AlmaLinux:9`); also
+    /// the ecosystem of the exported records
+    #[clap(long)]
+    ecosystem: Option<String>,
+
+    /// Don't use the updateinfo of the cached rpm-md repos
+    #[clap(long)]
+    no_updateinfo: bool,
+
+    /// Directory of the rpm-md cache
+    #[clap(long, default_value = CACHEDIR, hide = true)]
+    cachedir: String,
+
+    /// Output format
+    #[clap(long, value_enum, default_value = "text")]
+    format: Format,
+}
+
+/// An installed package affected by a vulnerability.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+struct Finding {
+    name: String,
+    evr: String,
+    arch: String,
+    /// The advisory or OSV ID
+    id: String,
+    aliases: Vec<String>,
+    severity: Option<String>,
+    /// The first version which isn't affected, if any
+    fixed: Option<String>,
+    /// `updateinfo` or the OSV file
+    source: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct OsvRecord {
+    id: String,
+    #[serde(default)]
+    aliases: Vec<String>,
+    #[serde(default)]
+    severity: Vec<OsvSeverity>,
+    #[serde(default)]
+    affected: Vec<OsvAffected>,
+    #[serde(default)]
+    database_specific: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OsvSeverity {
+    score: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct OsvAffected {
+    package: OsvPackage,
+    #[serde(default)]
+    ranges: Vec<OsvRange>,
+    #[serde(default)]
+    versions: Vec<String>,
+    #[serde(default)]
+    ecosystem_specific: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OsvPackage {
+    ecosystem: String,
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct OsvRange {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    events: Vec<OsvEvent>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OsvEvent {
+    introduced: Option<String>,
+    fixed: Option<String>,
+    last_affected: Option<String>,
+}
+
+impl OsvEvent {
+    fn version(&self) -> &str {
+        self.introduced
+            .as_deref()
+            .or(self.fixed.as_deref())
+            .or(self.last_affected.as_deref())
+            .unwrap_or_default()
+    }
+}
+
+/// Compare versions where `0` is the lowest, as in OSV `introduced` events.
+fn osv_cmp(a: &str, b: &str) -> Ordering {
+    match (a, b) {
+        ("0", "0") => Ordering::Equal,
+        ("0", _) => Ordering::Less,
+        (_, "0") => Ordering::Greater,
+        _ => evrcmp(a, b),
+    }
+}
+
+/// Whether `evr` is in the `ECOSYSTEM` range, and if so the version fixing it.
+fn range_affects(range: &OsvRange, evr: &str) -> (bool, Option<String>) {
+    let mut events: Vec<&OsvEvent> = range.events.iter().collect();
+    events.sort_by(|a, b| osv_cmp(a.version(), b.version()));
+    let mut affected = false;
+    for event in events {
+        if let Some(v) = event.introduced.as_deref() {
+            if osv_cmp(evr, v) != Ordering::Less {
+                affected = true;
+            }
+        } else if let Some(v) = event.fixed.as_deref() {
+            if osv_cmp(evr, v) != Ordering::Less {
+                affected = false;
+            } else if affected {
+                return (true, Some(v.to_string()));
+            }
+        } else if let Some(v) = event.last_affected.as_deref() {
+            if osv_cmp(evr, v) == Ordering::Greater {
+                affected = false;
+            }
+        }
+    }
+    (affected, None)
+}
+
+/// The severity of an OSV record for a package: the distribution's rating if
+/// there is one, else the first score (e.g. a CVSS vector).
+fn osv_severity(record: &OsvRecord, affected: &OsvAffected) -> Option<String> {
+    let rating = |v: &Option<Value>| {
+        v.as_ref()
+            .and_then(|v| v.get("severity"))
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string())
+    };
+    rating(&affected.ecosystem_specific)
+        .or_else(|| rating(&record.database_specific))
+        .or_else(|| record.severity.first().map(|s| s.score.clone()))
+}
+
+/// Match the packages against an OSV record.
+fn osv_match(
+    record: &OsvRecord,
+    packages: &[(String, String, String)],
+    ecosystem: Option<&str>,
+    source: &str,
+) -> Vec<Finding> {
+    let mut r = Vec::new();
+    for affected in record.affected.iter() {
+        if let Some(ecosystem) = ecosystem {
+            if affected.package.ecosystem != ecosystem {
+                continue;
+            }
+        }
+        for (name, evr, arch) in packages.iter() {
+            if *name != affected.package.name {
+                continue;
+            }
+            let listed = affected
+                .versions
+                .iter()
+                .any(|v| evrcmp(v, evr) == Ordering::Equal);
+            let in_range = affected
+                .ranges
+                .iter()
+                .filter(|range| range.kind == "ECOSYSTEM")
+                .map(|range| range_affects(range, evr))
+                .find(|(affected, _)| *affected);
+            if !listed && in_range.is_none() {
+                continue;
+            }
+            r.push(Finding {
+                name: name.clone(),
+                evr: evr.clone(),
+                arch: arch.clone(),
+                id: record.id.clone(),
+                aliases: record.aliases.clone(),
+                severity: osv_severity(record, affected),
+                fixed: in_range.and_then(|(_, fixed)| fixed),
+                source: source.to_string(),
+            });
+        }
+    }
+    r
+}
+
+/// Load the OSV records of a file, which holds either one or an array of them,
+/// or of the `.json` files in a directory.
+fn load_osv(path: &Path) -> Result<Vec<(String, OsvRecord)>> {
+    let mut files = Vec::new();
+    if path.is_dir() {
+        for entry in std::fs::read_dir(path).with_context(|| format!("Reading {:?}", path))? {
+            let entry = entry?.path();
+            if entry.extension().map(|e| e == "json").unwrap_or_default() {
+                files.push(entry);
+            }
+        }
+        files.sort();
+    } else {
+        files.push(path.to_path_buf());
+    }
+    let mut r = Vec::new();
+    for file in files {
+        let source = file.to_string_lossy().to_string();
+        let data = std::fs::read(&file).with_context(|| format!("Reading {}", source))?;
+        let value: Value =
+            serde_json::from_slice(&data).with_context(|| format!("Parsing {}", source))?;
+        let records = match value {
+            Value::Array(records) => records,
+            record => vec![record],
+        };
+        for record in records {
+            let record = serde_json::from_value(record)
+                .with_context(|| format!("Parsing OSV record in {}", source))?;
+            r.push((source.clone(), record));
+        }
+    }
+    Ok(r)
+}
+
+/// Match the packages of `rev` against the updateinfo of the cached repos.
+fn updateinfo_findings(repo: &ostree::Repo, rev: &str, cachedir: &str) -> Result<Vec<Finding>> {
+    let r = crate::ffi::scan_updateinfo(repo.reborrow_cxx(), rev, cachedir)?;
+    let matches: glib::Variant = unsafe { glib::translate::from_glib_full(r as *mut _) };
+    Ok(matches
+        .iter()
+        .map(|m| {
+            let field = |i| m.child_value(i).str().unwrap().to_string();
+            let severity = Some(field(4)).filter(|s| !s.is_empty());
+            Finding {
+                name: field(0),
+                evr: field(1),
+                arch: field(2),
+                id: field(3),
+                aliases: m.child_value(6).get::<Vec<String>>().unwrap(),
+                severity,
+                fixed: Some(field(5)),
+                source: "updateinfo".to_string(),
+            }
+        })
+        .collect())
+}
+
+/// The OSV ecosystem of a commit, from the `NAME` and `VERSION_ID` of its
+/// os-release, e.g. `AlmaLinux:9`.
+fn commit_ecosystem(repo: &ostree::Repo, rev: &str) -> Result<String> {
+    let (root, _) = repo.read_commit(rev, gio::NONE_CANCELLABLE)?;
+    let f = root.resolve_relative_path("usr/lib/os-release");
+    let (contents, _) = f.load_contents(gio::NONE_CANCELLABLE)?;
+    let contents = String::from_utf8_lossy(&contents);
+    let field = |key: &str| {
+        contents
+            .lines()
+            .filter_map(|l| l.split_once('='))
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v.trim_matches(|c| c == '"' || c == '\'').to_string())
+            .unwrap_or_default()
+    };
+    Ok(format!("{}:{}", field("NAME"), field("VERSION_ID")))
+}
+
+/// Export the findings as OSV records, one per vulnerability.
+fn to_osv(findings: &[Finding], ecosystem: &str) -> Value {
+    let mut records = BTreeMap::<&str, Vec<&Finding>>::new();
+    for finding in findings {
+        records
+            .entry(finding.id.as_str())
+            .or_default()
+            .push(finding);
+    }
+    let modified = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+    let records: Vec<Value> = records
+        .into_iter()
+        .map(|(id, findings)| {
+            let affected: Vec<Value> = findings
+                .iter()
+                .map(|f| {
+                    let mut events = vec![json!({"introduced": "0"})];
+                    if let Some(fixed) = f.fixed.as_deref() {
+                        events.push(json!({ "fixed": fixed }));
+                    }
+                    json!({
+                        "package": { "ecosystem": ecosystem, "name": f.name },
+                        "versions": [f.evr],
+                        "ranges": [{ "type": "ECOSYSTEM", "events": events }],
+                        "database_specific": { "arch": f.arch, "severity": f.severity },
+                    })
+                })
+                .collect();
+            json!({
+                "schema_version": OSV_SCHEMA_VERSION,
+                "id": id,
+                "modified": modified,
+                "aliases": findings[0].aliases,
+                "affected": affected,
+            })
+        })
+        .collect();
+    Value::Array(records)
+}
+
+fn print_human(id: &str, findings: &[Finding]) {
+    println!("Deployment: {}", id);
+    if findings.is_empty() {
+        println!("No known vulnerabilities.");
+        return;
+    }
+    let mut pkgs = findings
+        .iter()
+        .map(|f| (&f.name, &f.arch))
+        .collect::<Vec<_>>();
+    pkgs.dedup();
+    println!(
+        "{} vulnerabilities affecting {} packages:",
+        findings.len(),
+        pkgs.len()
+    );
+    for f in findings {
+        print!("  {}-{}.{}: {}", f.name, f.evr, f.arch, f.id);
+        if let Some(severity) = f.severity.as_deref() {
+            print!(" ({})", severity);
+        }
+        if !f.aliases.is_empty() {
+            print!(" [{}]", f.aliases.join(", "));
+        }
+        match f.fixed.as_deref() {
+            Some(fixed) => println!("; fixed in {}", fixed),
+            None => println!("; no fix available"),
+        }
+    }
+}
+
+pub(crate) fn scan_entrypoint(args: &Vec<String>) -> Result<()> {
+    let opts = &Opts::parse_from(args.iter());
+    let sysroot = &ostree::Sysroot::new_default();
+    sysroot.load(gio::NONE_CANCELLABLE)?;
+    let repo = &sysroot.repo().unwrap();
+    let deployment = &crate::pin::find_deployment(sysroot, &opts.deployment)?;
+    let id = deployment_generate_id_impl(deployment);
+    let rev = deployment.csum();
+    let packages = commit_packages(repo, rev.as_str())?;
+
+    let mut findings = Vec::new();
+    if !opts.no_updateinfo {
+        findings.extend(updateinfo_findings(repo, rev.as_str(), &opts.cachedir)?);
+    }
+    for path in opts.osv.iter() {
+        for (source, record) in load_osv(Path::new(path))? {
+            findings.extend(osv_match(
+                &record,
+                &packages,
+                opts.ecosystem.as_deref(),
+                &source,
+            ));
+        }
+    }
+    findings.sort();
+    findings.dedup_by(|a, b| (&a.name, &a.arch, &a.id) == (&b.name, &b.arch, &b.id));
+
+    match opts.format {
+        Format::Text => print_human(&id, &findings),
+        Format::Json => {
+            let r = json!({
+                "deployment": id,
+                "checksum": rev.as_str(),
+                "vulnerabilities": findings,
+            });
+            println!("{}", serde_json::to_string_pretty(&r)?);
+        }
+        Format::Osv => {
+            let ecosystem = match opts.ecosystem.as_deref() {
+                Some(e) => e.to_string(),
+                None => commit_ecosystem(repo, rev.as_str())?,
+            };
+            let r = to_osv(&findings, &ecosystem);
+            println!("{}", serde_json::to_string_pretty(&r)?);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record() -> OsvRecord {
+        serde_json::from_value(json!({
+            "id": "ALSA-2023:0001",
+            "aliases": ["CVE-2023-0286"],
+            "affected": [{
+                "package": { "ecosystem": "AlmaLinux:9", "name": "openssl" },
+                "ranges": [{
+                    "type": "ECOSYSTEM",
+                    "events": [{ "introduced": "0" }, { "fixed": "1:3.0.1-47.el9_1" }],
+                }],
+                "ecosystem_specific": { "severity": "Important" },
+            }],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_range_affects() {
+        let range: OsvRange = serde_json::from_value(json!({
+            "type": "ECOSYSTEM",
+            "events": [
+                { "fixed": "2.0-1" },
+                { "introduced": "1.5-1" },
+                { "introduced": "2.5-1" },
+                { "last_affected": "2.6-1" },
+                { "introduced": "1:0.1-1" },
+            ],
+        }))
+        .unwrap();
+        let cases = [
+            ("1.0-1", false, None),
+            ("1.5-1", true, Some("2.0-1")),
+            ("1.9-3", true, Some("2.0-1")),
+            ("2.0-1", false, None),
+            ("2.5-2", true, None),
+            ("2.6-1", true, None),
+            ("2.7-1", false, None),
+            ("2.0~rc1-1", true, Some("2.0-1")),
+            ("1:0.1-1", true, None),
+        ];
+        for (evr, affected, fixed) in cases {
+            assert_eq!(
+                range_affects(&range, evr),
+                (affected, fixed.map(|s| s.to_string())),
+                "{}",
+                evr
+            );
+        }
+    }
+
+    #[test]
+    fn test_osv_match() {
+        let record = record();
+        let pkgs = |evr: &str| {
+            vec![
+                ("openssl".to_string(), evr.to_string(), "x86_64".to_string()),
+                (
+                    "bash".to_string(),
+                    "5.1-1".to_string(),
+                    "x86_64".to_string(),
+                ),
+            ]
+        };
+        let r = osv_match(&record, &pkgs("1:3.0.1-43.el9_0"), None, "test.json");
+        assert_eq!(r.len(), 1);
+        assert_eq!(r[0].name, "openssl");
+        assert_eq!(r[0].severity.as_deref(), Some("Important"));
+        assert_eq!(r[0].fixed.as_deref(), Some("1:3.0.1-47.el9_1"));
+        assert!(osv_match(&record, &pkgs("1:3.0.1-47.el9_1"), None, "test.json").is_empty());
+        assert!(osv_match(
+            &record,
+            &pkgs("1:3.0.1-43.el9_0"),
+            Some("Rocky Linux:9"),
+            ""
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn test_to_osv() {
+        let record = record();
+        let pkgs = vec![(
+            "openssl".to_string(),
+            "1:3.0.1-43.el9_0".to_string(),
+            "x86_64".to_string(),
+        )];
+        let findings = osv_match(&record, &pkgs, None, "test.json");
+        let r = to_osv(&findings, "AlmaLinux:9");
+        let exported: OsvRecord = serde_json::from_value(r[0].clone()).unwrap();
+        assert_eq!(exported.id, "ALSA-2023:0001");
+        assert_eq!(osv_match(&exported, &pkgs, None, "export.json").len(), 1);
+    }
+}
@@ -0,0 +1,370 @@
+//! Implementation of the treefile `secureboot-audit` field: at the end of a
+//! compose, check that the kernel and all its modules are signed with one of
+//! the configured certificates, so that e.g. unsigned out-of-tree modules are
+//! caught before the image ships.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::cxxrsutil::*;
+use crate::treefile::Treefile;
+use anyhow::{anyhow, bail, Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use cap_std::fs::Dir;
+use cap_std_ext::cap_std;
+use cap_std_ext::prelude::CapStdExtDirExt;
+use openssl::pkcs7::{Pkcs7, Pkcs7Flags};
+use openssl::stack::Stack;
+use openssl::x509::store::X509StoreBuilder;
+use openssl::x509::X509;
+use rayon::prelude::*;
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+
+const MODULES_DIR: &str = "usr/lib/modules";
+/// Marks an appended signature; used for modules, and for the kernel on
+/// architectures without PE images (e.g. s390x).
+const MODULE_SIG_MAGIC: &[u8] = b"~Module signature appended~\n";
+/// Size of `struct module_signature`, which precedes the magic.
+const MODULE_SIG_INFO_SIZE: usize = 12;
+/// `PKEY_ID_PKCS7` in `struct module_signature`.
+const PKEY_ID_PKCS7: u8 = 2;
+/// Suffixes of kernel modules, and the decompressor to use if any.
+const MODULE_SUFFIXES: &[(&str, Option<&str>)] = &[
+    (".ko", None),
+    (".ko.xz", Some("xz")),
+    (".ko.zst", Some("zstd")),
+    (".ko.gz", Some("gzip")),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SigStatus {
+    Valid,
+    Unsigned,
+    /// Signed, but not by one of the configured certificates, or corrupted.
+    Invalid,
+}
+
+impl SigStatus {
+    fn describe(self) -> &'static str {
+        match self {
+            SigStatus::Valid => "valid",
+            SigStatus::Unsigned => "unsigned",
+            SigStatus::Invalid => "invalid",
+        }
+    }
+}
+
+/// Load the certificates at `paths`, in PEM (possibly several per file) or DER.
+fn load_certificates(paths: &[Utf8PathBuf]) -> Result<Vec<X509>> {
+    let mut certs = Vec::new();
+    for path in paths {
+        let buf = std::fs::read(path).with_context(|| format!("Reading {}", path))?;
+        let loaded = if buf.starts_with(b"-----BEGIN") {
+            X509::stack_from_pem(&buf)
+        } else {
+            X509::from_der(&buf).map(|c| vec![c])
+        };
+        let loaded = loaded.with_context(|| format!("Parsing certificate {}", path))?;
+        if loaded.is_empty() {
+            bail!("No certificate found in {}", path);
+        }
+        certs.extend(loaded);
+    }
+    Ok(certs)
+}
+
+/// Check the signature appended to `data` (in the format of `scripts/sign-file`
+/// from the kernel) against `certs`.
+fn verify_appended_signature(data: &[u8], certs: &[X509]) -> Result<SigStatus> {
+    let data = match data.strip_suffix(MODULE_SIG_MAGIC) {
+        Some(data) => data,
+        None => return Ok(SigStatus::Unsigned),
+    };
+    if data.len() < MODULE_SIG_INFO_SIZE {
+        return Ok(SigStatus::Invalid);
+    }
+    let (data, info) = data.split_at(data.len() - MODULE_SIG_INFO_SIZE);
+    if info[2] != PKEY_ID_PKCS7 {
+        return Ok(SigStatus::Invalid);
+    }
+    let sig_len = u32::from_be_bytes(info[8..12].try_into().unwrap()) as usize;
+    if data.len() < sig_len {
+        return Ok(SigStatus::Invalid);
+    }
+    let (content, sig) = data.split_at(data.len() - sig_len);
+    let sig = match Pkcs7::from_der(sig) {
+        Ok(sig) => sig,
+        Err(_) => return Ok(SigStatus::Invalid),
+    };
+    // The signer has to be one of the configured certificates; there is no
+    // chain to verify.
+    let mut signers = Stack::new()?;
+    for cert in certs {
+        signers.push(cert.clone())?;
+    }
+    let store = X509StoreBuilder::new()?.build();
+    let flags = Pkcs7Flags::BINARY | Pkcs7Flags::NOINTERN | Pkcs7Flags::NOVERIFY;
+    match sig.verify(&signers, &store, Some(content), None, flags) {
+        Ok(()) => Ok(SigStatus::Valid),
+        Err(_) => Ok(SigStatus::Invalid),
+    }
+}
+
+/// Check the Authenticode signature of the PE image `data` using `sbverify`.
+fn verify_pe_signature(data: &[u8], certs: &[X509]) -> Result<SigStatus> {
+    let mut image = tempfile::NamedTempFile::new()?;
+    image.write_all(data)?;
+    let path = image.path();
+    let listed = Command::new("sbverify")
+        .arg("--list")
+        .arg(path)
+        .output()
+        .context("Running sbverify (from sbsigntools)")?;
+    if !listed.status.success() || String::from_utf8_lossy(&listed.stdout).contains("No signature")
+    {
+        return Ok(SigStatus::Unsigned);
+    }
+    for cert in certs {
+        let mut certfile = tempfile::NamedTempFile::new()?;
+        certfile.write_all(&cert.to_pem()?)?;
+        let status = Command::new("sbverify")
+            .arg("--cert")
+            .arg(certfile.path())
+            .arg(path)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()?;
+        if status.success() {
+            return Ok(SigStatus::Valid);
+        }
+    }
+    Ok(SigStatus::Invalid)
+}
+
+fn verify_kernel(rootfs: &Dir, path: &Utf8Path, certs: &[X509]) -> Result<SigStatus> {
+    let data = rootfs.read(path)?;
+    if data.ends_with(MODULE_SIG_MAGIC) {
+        verify_appended_signature(&data, certs)
+    } else if data.starts_with(b"MZ") {
+        verify_pe_signature(&data, certs)
+    } else {
+        Ok(SigStatus::Unsigned)
+    }
+}
+
+fn read_module(rootfs: &Dir, path: &Utf8Path, decompressor: Option<&str>) -> Result<Vec<u8>> {
+    let f = rootfs.open(path)?.into_std();
+    match decompressor {
+        None => {
+            let mut buf = Vec::new();
+            std::io::BufReader::new(f).read_to_end(&mut buf)?;
+            Ok(buf)
+        }
+        Some(decompressor) => {
+            let out = Command::new(decompressor)
+                .arg("-dc")
+                .stdin(Stdio::from(f))
+                .stderr(Stdio::inherit())
+                .output()
+                .with_context(|| format!("Running {}", decompressor))?;
+            if !out.status.success() {
+                bail!("{} failed: {:?}", decompressor, out.status);
+            }
+            Ok(out.stdout)
+        }
+    }
+}
+
+/// Recursively collect the kernel modules under `path`.
+fn find_modules(
+    rootfs: &Dir,
+    path: &Utf8Path,
+    modules: &mut Vec<(Utf8PathBuf, Option<&'static str>)>,
+) -> Result<()> {
+    for entry in rootfs.read_dir(path)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name
+            .to_str()
+            .ok_or_else(|| anyhow!("Invalid UTF-8 filename in {}", path))?;
+        let child = path.join(name);
+        let ty = entry.file_type()?;
+        if ty.is_dir() {
+            find_modules(rootfs, &child, modules)?;
+        } else if ty.is_file() {
+            if let Some((_, d)) = MODULE_SUFFIXES.iter().find(|(s, _)| name.ends_with(s)) {
+                modules.push((child, *d));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+struct AuditReport {
+    checked: usize,
+    failures: Vec<(Utf8PathBuf, SigStatus)>,
+}
+
+impl AuditReport {
+    fn add(&mut self, path: Utf8PathBuf, status: SigStatus) {
+        self.checked += 1;
+        if status != SigStatus::Valid {
+            self.failures.push((path, status));
+        }
+    }
+}
+
+fn audit(rootfs: &Dir, certs: &[X509]) -> Result<AuditReport> {
+    let mut report = AuditReport::default();
+    let mut modules = Vec::new();
+    for entry in rootfs.read_dir(MODULES_DIR)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let name = entry.file_name();
+        let kver = name
+            .to_str()
+            .ok_or_else(|| anyhow!("Invalid UTF-8 filename in {}", MODULES_DIR))?;
+        let kdir = Utf8Path::new(MODULES_DIR).join(kver);
+        let vmlinuz = kdir.join("vmlinuz");
+        if rootfs.try_exists(&vmlinuz)? {
+            let status = verify_kernel(rootfs, &vmlinuz, certs)
+                .with_context(|| format!("Checking {}", vmlinuz))?;
+            report.add(vmlinuz, status);
+        }
+        find_modules(rootfs, &kdir, &mut modules)
+            .with_context(|| format!("Finding modules in {}", kdir))?;
+    }
+    let results = modules
+        .into_par_iter()
+        .map(|(path, decompressor)| {
+            let status = read_module(rootfs, &path, decompressor)
+                .and_then(|data| verify_appended_signature(&data, certs))
+                .with_context(|| format!("Checking {}", path))?;
+            Ok((path, status))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    for (path, status) in results {
+        report.add(path, status);
+    }
+    Ok(report)
+}
+
+/// Audit the signatures of the kernel and modules if `secureboot-audit` is set.
+pub(crate) fn secureboot_audit(rootfs_dfd: i32, treefile: &Treefile) -> CxxResult<()> {
+    let config = match treefile.parsed.base.secureboot_audit.as_ref() {
+        Some(c) => c,
+        None => return Ok(()),
+    };
+    let rootfs = unsafe { crate::ffiutil::ffi_dirfd(rootfs_dfd)? };
+    if !rootfs.try_exists(MODULES_DIR)? {
+        return Ok(());
+    }
+    let workdir = Utf8Path::new(treefile.get_workdir());
+    let paths: Vec<_> = config
+        .certificates
+        .iter()
+        .map(|c| workdir.join(c))
+        .collect();
+    let certs = load_certificates(&paths)?;
+    if certs.is_empty() {
+        return Err(anyhow!("secureboot-audit: No certificates configured").into());
+    }
+    println!("Auditing kernel and module signatures");
+    let report = audit(&rootfs, &certs).context("Auditing kernel and module signatures")?;
+    for (path, status) in report.failures.iter() {
+        println!("  {}: {}", status.describe(), path);
+    }
+    println!(
+        "Checked signatures of {} files: {} without a valid signature",
+        report.checked,
+        report.failures.len()
+    );
+    if config.fatal.unwrap_or(false) && !report.failures.is_empty() {
+        return Err(anyhow!(
+            "secureboot-audit: {} files without a valid signature, e.g. {}",
+            report.failures.len(),
+            report.failures[0].0
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::asn1::Asn1Time;
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::{PKey, Private};
+    use openssl::rsa::Rsa;
+    use openssl::x509::X509NameBuilder;
+
+    fn new_cert(cn: &str) -> Result<(X509, PKey<Private>)> {
+        let key = PKey::from_rsa(Rsa::generate(2048)?)?;
+        let mut name = X509NameBuilder::new()?;
+        name.append_entry_by_text("CN", cn)?;
+        let name = name.build();
+        let mut builder = X509::builder()?;
+        builder.set_version(2)?;
+        builder.set_subject_name(&name)?;
+        builder.set_issuer_name(&name)?;
+        builder.set_pubkey(&key)?;
+        builder.set_not_before(&Asn1Time::days_from_now(0)?)?;
+        builder.set_not_after(&Asn1Time::days_from_now(1)?)?;
+        builder.sign(&key, MessageDigest::sha256())?;
+        Ok((builder.build(), key))
+    }
+
+    /// Sign `content` like `scripts/sign-file` does.
+    fn sign_module(content: &[u8], cert: &X509, key: &PKey<Private>) -> Result<Vec<u8>> {
+        let flags = Pkcs7Flags::BINARY
+            | Pkcs7Flags::DETACHED
+            | Pkcs7Flags::NOCERTS
+            | Pkcs7Flags::NOATTR
+            | Pkcs7Flags::NOSMIMECAP;
+        let sig = Pkcs7::sign(cert, key, &Stack::new()?, content, flags)?.to_der()?;
+        let mut r = content.to_vec();
+        r.extend_from_slice(&sig);
+        r.extend_from_slice(&[0, 0, PKEY_ID_PKCS7, 0, 0, 0, 0, 0]);
+        r.extend_from_slice(&(sig.len() as u32).to_be_bytes());
+        r.extend_from_slice(MODULE_SIG_MAGIC);
+        Ok(r)
+    }
+
+    #[test]
+    fn test_verify_appended_signature() -> Result<()> {
+        let (cert, key) = new_cert("trusted")?;
+        let (other_cert, other_key) = new_cert("other")?;
+        let certs = [cert.clone()];
+        let content = b"\x7fELF module content";
+
+        let signed = sign_module(content, &cert, &key)?;
+        assert_eq!(
+            verify_appended_signature(&signed, &certs)?,
+            SigStatus::Valid
+        );
+        assert_eq!(
+            verify_appended_signature(content, &certs)?,
+            SigStatus::Unsigned
+        );
+        let untrusted = sign_module(content, &other_cert, &other_key)?;
+        assert_eq!(
+            verify_appended_signature(&untrusted, &certs)?,
+            SigStatus::Invalid
+        );
+        let mut tampered = signed.clone();
+        tampered[0] = b'!';
+        assert_eq!(
+            verify_appended_signature(&tampered, &certs)?,
+            SigStatus::Invalid
+        );
+        assert_eq!(
+            verify_appended_signature(MODULE_SIG_MAGIC, &certs)?,
+            SigStatus::Invalid
+        );
+        Ok(())
+    }
+}
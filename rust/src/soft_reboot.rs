@@ -0,0 +1,65 @@
+//! Soft-reboot into the staged deployment, for `rpm-ostree finalize
+//! --soft-reboot`.
+//!
+//! If the staged deployment has the same kernel, initramfs and kernel
+//! arguments as the booted one, only userspace needs restarting: the daemon
+//! unlocks it and starts `rpm-ostree-soft-reboot.service`, which runs this
+//! outside of the daemon's mount namespace.  It finalizes the deployment, has
+//! `ostree admin prepare-soft-reboot` set up its root in `/run/nextroot` the
+//! same way `ostree-prepare-root` would at boot, composefs included, and has
+//! systemd switch into it with `systemctl soft-reboot`.  The kernel keeps
+//! running, so this saves the firmware, bootloader and initramfs stages of a
+//! reboot.
+//!
+//! If this fails, it falls back to a full reboot; the deployment is the
+//! default one either way.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use anyhow::{anyhow, bail, Context, Result};
+use ostree_ext::{gio, ostree};
+use std::process::Command;
+
+fn run(cmd: &mut Command) -> Result<()> {
+    let status = cmd.status().with_context(|| format!("Running {:?}", cmd))?;
+    if !status.success() {
+        bail!("{:?} failed: {:?}", cmd, status);
+    }
+    Ok(())
+}
+
+/// Finalize the staged deployment and set up its root in `/run/nextroot`.
+fn prepare() -> Result<()> {
+    let sysroot = &ostree::Sysroot::new_default();
+    sysroot.load(gio::NONE_CANCELLABLE)?;
+    if sysroot.staged_deployment().is_some() {
+        run(Command::new("ostree").args(["admin", "finalize-staged"]))?;
+        sysroot.load(gio::NONE_CANCELLABLE)?;
+    }
+    let booted = sysroot.require_booted_deployment()?;
+    let target = sysroot
+        .deployments()
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("No deployments found"))?;
+    if target.equal(&booted) {
+        bail!("The default deployment is already booted");
+    }
+    if target.bootcsum() != booted.bootcsum() {
+        bail!("The default deployment has a different kernel or initramfs");
+    }
+    // The default deployment is the first one
+    run(Command::new("ostree").args(["admin", "prepare-soft-reboot", "0"]))
+}
+
+/// Main entrypoint, run by `rpm-ostree-soft-reboot.service`.
+pub fn entrypoint(_args: &[&str]) -> Result<()> {
+    match prepare() {
+        Ok(()) => run(Command::new("systemctl").arg("soft-reboot")),
+        Err(e) => {
+            eprintln!("Failed to prepare soft-reboot: {:#}; rebooting instead", e);
+            let _ = run(Command::new("ostree").args(["admin", "prepare-soft-reboot", "--reset"]));
+            run(Command::new("systemctl").arg("reboot"))
+        }
+    }
+}
@@ -0,0 +1,182 @@
+//! A persistent cache of depsolve results.  Repeating the same package
+//! operation against the same rpm-md metadata and base (which is common when
+//! retrying in CI or kickstarts) would otherwise redo the full depsolve every
+//! time.  The core computes a key covering everything which can influence the
+//! solution; here we only store the resulting package set under that key.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::cxxrsutil::*;
+use crate::ffi::SolvedPackage;
+use anyhow::{anyhow, Context, Result};
+use serde_derive::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Bump this when changing how the key is computed or what is stored.
+const CACHE_VERSION: u32 = 1;
+/// The number of solutions we keep around; the oldest ones are pruned.
+const MAX_ENTRIES: usize = 16;
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct CachedPackage {
+    nevra: String,
+    repo: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct CacheEntry {
+    version: u32,
+    packages: Vec<CachedPackage>,
+}
+
+fn entry_path(cachedir: &Path, key: &str) -> Result<PathBuf> {
+    if key.is_empty() || !key.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow!("Invalid solve cache key: {}", key));
+    }
+    Ok(cachedir.join(format!("{}.json", key)))
+}
+
+fn lookup(cachedir: &Path, key: &str) -> Result<Option<CacheEntry>> {
+    let path = entry_path(cachedir, key)?;
+    let buf = match std::fs::read(&path) {
+        Ok(b) => b,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Reading {}", path.display())),
+    };
+    match serde_json::from_slice::<CacheEntry>(&buf) {
+        Ok(entry) if entry.version == CACHE_VERSION && !entry.packages.is_empty() => {
+            Ok(Some(entry))
+        }
+        // Stale or corrupted entries are simply dropped
+        _ => {
+            let _ = std::fs::remove_file(&path);
+            Ok(None)
+        }
+    }
+}
+
+fn store(cachedir: &Path, key: &str, entry: &CacheEntry) -> Result<()> {
+    let path = entry_path(cachedir, key)?;
+    std::fs::create_dir_all(cachedir)
+        .with_context(|| format!("Creating {}", cachedir.display()))?;
+    let mut tmp = tempfile::NamedTempFile::new_in(cachedir)?;
+    serde_json::to_writer(&mut tmp, entry)?;
+    tmp.flush()?;
+    tmp.persist(&path)
+        .with_context(|| format!("Writing {}", path.display()))?;
+    prune(cachedir, MAX_ENTRIES)
+}
+
+/// Remove the least recently stored entries beyond `keep`.
+fn prune(cachedir: &Path, keep: usize) -> Result<()> {
+    let mut entries = Vec::new();
+    for ent in std::fs::read_dir(cachedir)? {
+        let ent = ent?;
+        let path = ent.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+        entries.push((ent.metadata()?.modified()?, path));
+    }
+    if entries.len() <= keep {
+        return Ok(());
+    }
+    entries.sort();
+    for (_, path) in entries.iter().take(entries.len() - keep) {
+        std::fs::remove_file(path).with_context(|| format!("Removing {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Find the solution previously stored under `key` in `cachedir`; empty if
+/// there is none.
+pub(crate) fn solve_cache_lookup(cachedir: &str, key: &str) -> CxxResult<Vec<SolvedPackage>> {
+    let entry = match lookup(Path::new(cachedir), key)? {
+        Some(e) => e,
+        None => return Ok(Vec::new()),
+    };
+    Ok(entry
+        .packages
+        .into_iter()
+        .map(|p| SolvedPackage {
+            nevra: p.nevra,
+            repo: p.repo,
+        })
+        .collect())
+}
+
+/// Store the solution `packages` under `key` in `cachedir`.
+pub(crate) fn solve_cache_store(
+    cachedir: &str,
+    key: &str,
+    packages: &Vec<SolvedPackage>,
+) -> CxxResult<()> {
+    let entry = CacheEntry {
+        version: CACHE_VERSION,
+        packages: packages
+            .iter()
+            .map(|p| CachedPackage {
+                nevra: p.nevra.clone(),
+                repo: p.repo.clone(),
+            })
+            .collect(),
+    };
+    Ok(store(Path::new(cachedir), key, &entry)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(nevras: &[&str]) -> CacheEntry {
+        CacheEntry {
+            version: CACHE_VERSION,
+            packages: nevras
+                .iter()
+                .map(|n| CachedPackage {
+                    nevra: n.to_string(),
+                    repo: "fedora".to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_solve_cache() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        let cachedir = &td.path().join("depsolve");
+        assert!(lookup(cachedir, "abcd")?.is_none());
+        assert!(entry_path(cachedir, "../abcd").is_err());
+        assert!(entry_path(cachedir, "").is_err());
+
+        let e = entry(&["foo-1.0-1.x86_64", "bar-2.0-1.noarch"]);
+        store(cachedir, "abcd", &e)?;
+        assert_eq!(lookup(cachedir, "abcd")?.as_ref(), Some(&e));
+        assert!(lookup(cachedir, "abce")?.is_none());
+
+        // Entries from another version of the cache are dropped
+        let mut old = entry(&["foo-1.0-1.x86_64"]);
+        old.version = CACHE_VERSION + 1;
+        store(cachedir, "0123", &old)?;
+        assert!(lookup(cachedir, "0123")?.is_none());
+        assert!(!cachedir.join("0123.json").exists());
+
+        std::fs::write(cachedir.join("4567.json"), "not json")?;
+        assert!(lookup(cachedir, "4567")?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_solve_cache_prune() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        let cachedir = td.path();
+        for i in 0..(MAX_ENTRIES + 4) {
+            let key = format!("{:04x}", i);
+            store(cachedir, &key, &entry(&["foo-1.0-1.x86_64"]))?;
+        }
+        assert_eq!(std::fs::read_dir(cachedir)?.count(), MAX_ENTRIES);
+        assert!(lookup(cachedir, &format!("{:04x}", MAX_ENTRIES + 3))?.is_some());
+        Ok(())
+    }
+}
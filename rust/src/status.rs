@@ -0,0 +1,641 @@
+//! Implementation of `rpm-ostree status --format`: unlike `--json`, which
+//! directly serializes the D-Bus API and hence gains and loses keys over time,
+//! the output follows an explicitly versioned schema.  Also determines whether
+//! a reboot is needed, for `--needs-reboot` and the `NeedsReboot` property,
+//! and evaluates the paths of `--query`.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::cxxrsutil::*;
+use anyhow::{anyhow, bail, Result};
+use ostree_ext::glib;
+use serde_derive::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// The schema used if no version is requested.
+const LATEST_SCHEMA_VERSION: u32 = 1;
+
+/// Version 1 of the status schema.
+///
+/// Within a version, fields are never removed, renamed or changed in type,
+/// so tooling can rely on them; anything else requires a new version.  Unset
+/// optional fields are serialized as `null`, and lists are always present.
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+struct StatusV1 {
+    /// Always 1
+    schema_version: u32,
+    /// In boot order; the first deployment is the default one
+    deployments: Vec<DeploymentV1>,
+    /// The transaction in progress
+    transaction: Option<TransactionV1>,
+    /// An update found by `upgrade --check` or the automatic update policy
+    cached_update: Option<CachedUpdateV1>,
+    /// The agent registered to drive updates
+    update_driver: Option<UpdateDriverV1>,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+struct DeploymentV1 {
+    id: String,
+    osname: String,
+    /// Checksum of the deployed ostree commit
+    checksum: String,
+    serial: i64,
+    booted: bool,
+    staged: bool,
+    pinned: bool,
+    /// Whether the deployment will not be finalized on shutdown
+    finalization_locked: bool,
+    /// The ostree unlocked state: none, development, hotfix or transient
+    unlocked: String,
+    /// The ostree refspec, checksum or container image reference deployed
+    origin: Option<String>,
+    version: Option<String>,
+    /// Creation time of the commit, in seconds since the epoch
+    timestamp: Option<u64>,
+    /// Checksum of the base commit, if the deployment has local changes
+    base_checksum: Option<String>,
+    /// Set for deployments of a container image
+    container_image: Option<ContainerImageV1>,
+    /// Checksum of the commit applied live on top of the deployment
+    live_replaced: Option<String>,
+    /// Packages requested to be layered
+    requested_packages: Vec<String>,
+    /// NEVRAs of local packages requested to be layered
+    requested_local_packages: Vec<String>,
+    /// Names of base packages requested to be removed
+    requested_base_removals: Vec<String>,
+    /// NEVRAs of local packages requested to replace base packages
+    requested_base_local_replacements: Vec<String>,
+    /// Packages layered in the deployed commit
+    packages: Vec<String>,
+    regenerate_initramfs: bool,
+    initramfs_args: Vec<String>,
+    /// Size in bytes of the objects not shared with other deployments
+    unique_size: Option<u64>,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+struct ContainerImageV1 {
+    reference: String,
+    /// Digest of the image manifest
+    digest: Option<String>,
+    /// Creation date of the image, in RFC 3339 format
+    created: Option<String>,
+    labels: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+struct TransactionV1 {
+    /// The D-Bus method which started the transaction, e.g. `Upgrade`
+    method: String,
+    /// The D-Bus name of the client
+    sender: String,
+    path: String,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+struct CachedUpdateV1 {
+    /// Checksum of the ostree commit, or digest of the container image
+    checksum: String,
+    origin: Option<String>,
+    version: Option<String>,
+    /// Creation time, in seconds since the epoch
+    timestamp: Option<u64>,
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+struct UpdateDriverV1 {
+    name: String,
+    /// The systemd unit of the driver
+    sd_unit: String,
+}
+
+fn opt_string(v: &Value, k: &str) -> Option<String> {
+    v[k].as_str().map(|s| s.to_string())
+}
+
+fn string(v: &Value, k: &str) -> Result<String> {
+    opt_string(v, k).ok_or_else(|| anyhow!("Missing {}", k))
+}
+
+fn strv(v: &Value, k: &str) -> Vec<String> {
+    v[k].as_array()
+        .map(|a| {
+            a.iter()
+                .filter_map(|s| s.as_str().map(|s| s.to_string()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn flag(v: &Value, k: &str) -> bool {
+    v[k].as_bool().unwrap_or_default()
+}
+
+impl DeploymentV1 {
+    fn new(d: &Value) -> Result<Self> {
+        let container_image = opt_string(d, "container-image-reference").map(|reference| {
+            let labels = d["container-image-labels"]
+                .as_object()
+                .map(|l| {
+                    l.iter()
+                        .filter_map(|(k, v)| v.as_str().map(|v| (k.clone(), v.to_string())))
+                        .collect()
+                })
+                .unwrap_or_default();
+            ContainerImageV1 {
+                reference,
+                digest: opt_string(d, "container-image-reference-digest"),
+                created: opt_string(d, "container-image-created"),
+                labels,
+            }
+        });
+        let origin = opt_string(d, "origin")
+            .or_else(|| container_image.as_ref().map(|i| i.reference.clone()));
+        Ok(Self {
+            id: string(d, "id")?,
+            osname: string(d, "osname")?,
+            checksum: string(d, "checksum")?,
+            serial: d["serial"].as_i64().unwrap_or_default(),
+            booted: flag(d, "booted"),
+            staged: flag(d, "staged"),
+            pinned: flag(d, "pinned"),
+            finalization_locked: flag(d, "finalization-locked"),
+            unlocked: opt_string(d, "unlocked").unwrap_or_else(|| "none".into()),
+            origin,
+            version: opt_string(d, "version"),
+            timestamp: d["timestamp"].as_u64(),
+            base_checksum: opt_string(d, "base-checksum"),
+            container_image,
+            live_replaced: opt_string(d, "live-replaced"),
+            requested_packages: strv(d, "requested-packages"),
+            requested_local_packages: strv(d, "requested-local-packages"),
+            requested_base_removals: strv(d, "requested-base-removals"),
+            requested_base_local_replacements: strv(d, "requested-base-local-replacements"),
+            packages: strv(d, "packages"),
+            regenerate_initramfs: flag(d, "regenerate-initramfs"),
+            initramfs_args: strv(d, "initramfs-args"),
+            unique_size: d["unique-size"].as_u64(),
+        })
+    }
+}
+
+impl StatusV1 {
+    /// Convert the `--json` status output.
+    fn new(status: &Value) -> Result<Self> {
+        let deployments = status["deployments"]
+            .as_array()
+            .ok_or_else(|| anyhow!("Missing deployments"))?
+            .iter()
+            .map(DeploymentV1::new)
+            .collect::<Result<_>>()?;
+        let transaction = match status["transaction"].as_array().map(|t| t.as_slice()) {
+            Some([method, sender, path]) => Some(TransactionV1 {
+                method: method.as_str().unwrap_or_default().to_string(),
+                sender: sender.as_str().unwrap_or_default().to_string(),
+                path: path.as_str().unwrap_or_default().to_string(),
+            }),
+            _ => None,
+        };
+        let u = &status["cached-update"];
+        let cached_update = opt_string(u, "checksum").map(|checksum| CachedUpdateV1 {
+            checksum,
+            origin: opt_string(u, "origin"),
+            version: opt_string(u, "version"),
+            timestamp: u["timestamp"].as_u64(),
+        });
+        let d = &status["update-driver"];
+        let update_driver = opt_string(d, "driver-name").map(|name| UpdateDriverV1 {
+            name,
+            sd_unit: opt_string(d, "driver-sd-unit").unwrap_or_default(),
+        });
+        Ok(Self {
+            schema_version: 1,
+            deployments,
+            transaction,
+            cached_update,
+            update_driver,
+        })
+    }
+}
+
+fn serialize<T: serde::Serialize>(v: &T, format: &str) -> Result<String> {
+    match format {
+        "json" => Ok(serde_json::to_string_pretty(v)? + "\n"),
+        "yaml" => Ok(serde_yaml::to_string(v)?),
+        o => bail!("Unknown format: {} (expected json or yaml)", o),
+    }
+}
+
+fn status_format_impl(status: &Value, format: &str, schema_version: u32) -> Result<String> {
+    match schema_version {
+        1 => serialize(&StatusV1::new(status)?, format),
+        n => bail!(
+            "Unsupported schema version: {} (latest: {})",
+            n,
+            LATEST_SCHEMA_VERSION
+        ),
+    }
+}
+
+/// Convert the `--json` status output `status` to the schema `schema_version`
+/// (0 for the latest), serialized in `format`: json or yaml.
+pub(crate) fn status_format(status: &str, format: &str, schema_version: u32) -> CxxResult<String> {
+    let status: Value = serde_json::from_str(status)?;
+    let schema_version = match schema_version {
+        0 => LATEST_SCHEMA_VERSION,
+        n => n,
+    };
+    Ok(status_format_impl(&status, format, schema_version)?)
+}
+
+/// A component of a `--query` path.
+#[derive(Debug, PartialEq, Eq)]
+enum QueryStep {
+    Key(String),
+    /// Negative indices count from the end
+    Index(i64),
+}
+
+/// Parse a path like `.deployments[0].base-commit-meta["ostree.linux"]`.
+/// Unlike in jq, keys may contain `-`; keys with other special characters
+/// can be given in brackets.
+fn parse_query(query: &str) -> Result<Vec<QueryStep>> {
+    let rest = query
+        .strip_prefix('.')
+        .ok_or_else(|| anyhow!("Query must start with '.': {}", query))?;
+    let mut steps = Vec::new();
+    let mut chars = rest.chars().peekable();
+    // The leading '.' may be directly followed by a key
+    let mut want_key = true;
+    while let Some(&c) = chars.peek() {
+        match c {
+            '[' => {
+                chars.next();
+                let mut inner = String::new();
+                let quoted = chars.peek() == Some(&'"');
+                if quoted {
+                    chars.next();
+                }
+                loop {
+                    match chars.next() {
+                        Some('"') if quoted => break,
+                        Some(']') if !quoted => break,
+                        Some(c) => inner.push(c),
+                        None => bail!("Unterminated '[' in query: {}", query),
+                    }
+                }
+                if quoted {
+                    if chars.next() != Some(']') {
+                        bail!("Expected ']' after key in query: {}", query);
+                    }
+                    steps.push(QueryStep::Key(inner));
+                } else {
+                    let i = inner
+                        .trim()
+                        .parse()
+                        .map_err(|_| anyhow!("Invalid index '{}' in query: {}", inner, query))?;
+                    steps.push(QueryStep::Index(i));
+                }
+                want_key = false;
+            }
+            '.' if !want_key => {
+                chars.next();
+                want_key = true;
+            }
+            _ if want_key => {
+                let mut key = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c == '.' || c == '[' {
+                        break;
+                    }
+                    key.push(c);
+                    chars.next();
+                }
+                if key.is_empty() {
+                    bail!("Empty key in query: {}", query);
+                }
+                steps.push(QueryStep::Key(key));
+                want_key = false;
+            }
+            _ => bail!("Unexpected '{}' in query: {}", c, query),
+        }
+    }
+    if want_key && !steps.is_empty() {
+        bail!("Query must not end with '.': {}", query);
+    }
+    Ok(steps)
+}
+
+fn status_query_impl(status: &Value, query: &str) -> Result<String> {
+    let mut v = status;
+    for step in parse_query(query)? {
+        let next = match (&step, v) {
+            (QueryStep::Key(k), Value::Object(o)) => o.get(k),
+            (QueryStep::Index(i), Value::Array(a)) => {
+                let i = if *i < 0 { a.len() as i64 + i } else { *i };
+                usize::try_from(i).ok().and_then(|i| a.get(i))
+            }
+            _ => None,
+        };
+        v = next.ok_or_else(|| anyhow!("No value at {}", query))?;
+    }
+    // Like `jq -r`, so scripts don't need to unquote strings
+    Ok(match v {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Object(_) | Value::Array(_) => serde_json::to_string_pretty(v)?,
+        v => v.to_string(),
+    })
+}
+
+/// Get the value at the path `query` of the `--json` status output `status`:
+/// strings are unquoted, null is empty, and objects and arrays are JSON.
+/// Missing values are an error, so scripts notice typos.
+pub(crate) fn status_query(status: &str, query: &str) -> CxxResult<String> {
+    let status: Value = serde_json::from_str(status)?;
+    Ok(status_query_impl(&status, query)?)
+}
+
+/// The state of a deployment which matters for whether a reboot is needed.
+#[derive(Debug, Default)]
+struct RebootState {
+    id: String,
+    osname: String,
+    checksum: String,
+    booted: bool,
+    staged: bool,
+    live_inprogress: bool,
+    live_replaced: Option<String>,
+}
+
+impl RebootState {
+    fn from_variant(v: &glib::Variant) -> Result<Self> {
+        let dict = glib::VariantDict::new(Some(v));
+        let string = |k: &str| -> Result<String> {
+            dict.lookup::<String>(k)
+                .map_err(anyhow::Error::msg)?
+                .ok_or_else(|| anyhow!("Missing {} in deployment", k))
+        };
+        let flag = |k: &str| -> Result<bool> {
+            Ok(dict
+                .lookup::<bool>(k)
+                .map_err(anyhow::Error::msg)?
+                .unwrap_or_default())
+        };
+        Ok(Self {
+            id: string("id")?,
+            osname: string("osname")?,
+            checksum: string("checksum")?,
+            booted: flag("booted")?,
+            staged: flag("staged")?,
+            live_inprogress: dict.contains("live-inprogress"),
+            live_replaced: dict
+                .lookup::<String>("live-replaced")
+                .map_err(anyhow::Error::msg)?,
+        })
+    }
+}
+
+/// Why rebooting is needed to get the system into the state described by
+/// `deployments`, in boot order; `None` if it already is.
+fn reboot_reason(deployments: &[RebootState]) -> Option<String> {
+    // Not booted into a deployment, e.g. while provisioning
+    let booted = deployments.iter().find(|d| d.booted)?;
+    if booted.live_inprogress {
+        return Some(tr!("A live update was interrupted"));
+    }
+    let default = deployments.first()?;
+    if default.booted {
+        return None;
+    }
+    // Changes applied live are already in effect
+    if default.osname == booted.osname
+        && booted.live_replaced.as_deref() == Some(default.checksum.as_str())
+    {
+        return None;
+    }
+    let r = match (default.osname != booted.osname, default.staged) {
+        (true, true) => tr!(
+            "Deployment {} of stateroot {} is staged",
+            default.id,
+            default.osname
+        ),
+        (true, false) => tr!(
+            "Deployment {} of stateroot {} is pending",
+            default.id,
+            default.osname
+        ),
+        (false, true) => tr!("Deployment {} is staged", default.id),
+        (false, false) => tr!("Deployment {} is pending", default.id),
+    };
+    Some(r)
+}
+
+/// Why rebooting is needed to get into the state described by `deployments`,
+/// the `Deployments` property of the sysroot; empty if it isn't.
+pub(crate) fn deployments_reboot_reason(deployments: &crate::FFIGVariant) -> CxxResult<String> {
+    let deployments = deployments.glib_reborrow();
+    let deployments = (0..deployments.n_children())
+        .map(|i| RebootState::from_variant(&deployments.child_value(i)))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(reboot_reason(&deployments).unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status() -> Value {
+        serde_json::json!({
+            "deployments": [
+                {
+                    "id": "fedora-4d2b1f0c7e.0",
+                    "osname": "fedora",
+                    "serial": 0,
+                    "checksum": "4d2b1f0c7e",
+                    "booted": false,
+                    "staged": true,
+                    "finalization-locked": true,
+                    "pinned": false,
+                    "unlocked": "none",
+                    "version": "37.20221016.0",
+                    "timestamp": 1665900000u64,
+                    "container-image-reference": "ostree-unverified-registry:quay.io/fedora/fedora-coreos:stable",
+                    "container-image-reference-digest": "sha256:0123",
+                    "container-image-labels": { "org.opencontainers.image.version": "37.20221016.0" },
+                    "requested-packages": [],
+                    "packages": [],
+                    "regenerate-initramfs": false,
+                    "some-future-key": 42
+                },
+                {
+                    "id": "fedora-9a8b7c6d5e.0",
+                    "osname": "fedora",
+                    "serial": 0,
+                    "checksum": "9a8b7c6d5e",
+                    "base-checksum": "1f2e3d4c5b",
+                    "booted": true,
+                    "staged": false,
+                    "pinned": true,
+                    "unlocked": "none",
+                    "origin": "fedora:fedora/37/x86_64/silverblue",
+                    "version": "37.20221001.0",
+                    "timestamp": 1664600000u64,
+                    "requested-packages": ["htop", "vim-enhanced"],
+                    "requested-base-removals": ["firefox"],
+                    "packages": ["htop", "vim-enhanced"],
+                    "regenerate-initramfs": true,
+                    "initramfs-args": ["-I", "/etc/crypttab"],
+                    "unique-size": 52428800u64
+                }
+            ],
+            "transaction": ["Upgrade", ":1.42", "/org/projectatomic/rpmostree1/fedora"],
+            "cached-update": null,
+            "update-driver": { "driver-name": "zincati", "driver-sd-unit": "zincati.service" }
+        })
+    }
+
+    #[test]
+    fn test_status_v1() -> Result<()> {
+        let s = StatusV1::new(&status())?;
+        assert_eq!(s.schema_version, 1);
+        assert_eq!(s.deployments.len(), 2);
+        let staged = &s.deployments[0];
+        assert!(staged.staged && staged.finalization_locked && !staged.booted);
+        let image = staged.container_image.as_ref().unwrap();
+        assert_eq!(image.digest.as_deref(), Some("sha256:0123"));
+        assert_eq!(staged.origin.as_deref(), Some(image.reference.as_str()));
+        assert_eq!(
+            image.labels["org.opencontainers.image.version"],
+            "37.20221016.0"
+        );
+        let booted = &s.deployments[1];
+        assert!(booted.booted && booted.pinned);
+        assert!(booted.container_image.is_none());
+        assert_eq!(booted.base_checksum.as_deref(), Some("1f2e3d4c5b"));
+        assert_eq!(booted.requested_base_removals, ["firefox"]);
+        assert_eq!(booted.initramfs_args, ["-I", "/etc/crypttab"]);
+        assert!(booted.requested_local_packages.is_empty());
+        assert_eq!(booted.unique_size, Some(52428800));
+        assert_eq!(staged.unique_size, None);
+        assert_eq!(s.transaction.as_ref().unwrap().method, "Upgrade");
+        assert!(s.cached_update.is_none());
+        assert_eq!(s.update_driver.as_ref().unwrap().sd_unit, "zincati.service");
+        Ok(())
+    }
+
+    #[test]
+    fn test_status_format() -> Result<()> {
+        let json: Value = serde_json::from_str(&status_format_impl(&status(), "json", 1)?)?;
+        assert_eq!(json["schema-version"], 1);
+        // Unknown keys are not passed through, and unset fields are explicit
+        assert!(json["deployments"][0].get("some-future-key").is_none());
+        assert_eq!(json["deployments"][1]["live-replaced"], Value::Null);
+        assert_eq!(json["cached-update"], Value::Null);
+        let yaml: serde_yaml::Value =
+            serde_yaml::from_str(&status_format_impl(&status(), "yaml", 1)?)?;
+        assert_eq!(yaml["deployments"][1]["requested-packages"][0], "htop");
+        assert!(status_format_impl(&status(), "xml", 1).is_err());
+        assert!(status_format_impl(&status(), "json", 2).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_status_query() -> Result<()> {
+        let s = status();
+        let q = |query| status_query_impl(&s, query);
+        assert_eq!(q(".deployments[0].booted")?, "false");
+        assert_eq!(q(".deployments[1].checksum")?, "9a8b7c6d5e");
+        assert_eq!(q(".deployments[-1].requested-packages[0]")?, "htop");
+        assert_eq!(q(".update-driver[\"driver-name\"]")?, "zincati");
+        assert_eq!(q(".transaction[0]")?, "Upgrade");
+        assert_eq!(q(".cached-update")?, "");
+        let all: Value = serde_json::from_str(&q(".")?)?;
+        assert_eq!(all, s);
+        let pkgs: Value = serde_json::from_str(&q(".deployments[1].packages")?)?;
+        assert_eq!(pkgs[0], "htop");
+        for missing in [
+            ".deployments[2]",
+            ".deployments[-3]",
+            ".nosuchkey",
+            ".cached-update.version",
+            ".deployments.checksum",
+        ] {
+            assert!(q(missing).is_err(), "{}", missing);
+        }
+        for invalid in [
+            "deployments",
+            ".deployments[",
+            ".deployments[x]",
+            ".a..b",
+            ".a.",
+        ] {
+            assert!(parse_query(invalid).is_err(), "{}", invalid);
+        }
+        assert_eq!(
+            parse_query(".base-commit-meta[\"ostree.linux\"][1]")?,
+            [
+                QueryStep::Key("base-commit-meta".into()),
+                QueryStep::Key("ostree.linux".into()),
+                QueryStep::Index(1)
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_reboot_reason() {
+        let deployment = |id: &str, checksum: &str, booted: bool| RebootState {
+            id: id.into(),
+            osname: "fedora".into(),
+            checksum: checksum.into(),
+            booted,
+            ..Default::default()
+        };
+        let booted = deployment("fedora-b.0", "b", true);
+        assert_eq!(reboot_reason(&[]), None);
+        assert_eq!(reboot_reason(&[deployment("fedora-b.0", "b", true)]), None);
+        // Rollback deployment only
+        let d = [booted, deployment("fedora-a.0", "a", false)];
+        assert_eq!(reboot_reason(&d), None);
+
+        let [booted, rollback] = d;
+        let pending = RebootState {
+            staged: true,
+            ..deployment("fedora-c.0", "c", false)
+        };
+        let mut d = [pending, booted, rollback];
+        assert_eq!(
+            reboot_reason(&d).as_deref(),
+            Some("Deployment fedora-c.0 is staged")
+        );
+        d[1].live_replaced = Some("c".into());
+        assert_eq!(reboot_reason(&d), None);
+        d[1].live_inprogress = true;
+        assert_eq!(
+            reboot_reason(&d).as_deref(),
+            Some("A live update was interrupted")
+        );
+
+        let other = RebootState {
+            osname: "rhcos".into(),
+            ..deployment("rhcos-d.0", "d", false)
+        };
+        let d = [other, deployment("fedora-b.0", "b", true)];
+        assert_eq!(
+            reboot_reason(&d).as_deref(),
+            Some("Deployment rhcos-d.0 of stateroot rhcos is pending")
+        );
+        // Not booted into a deployment
+        assert_eq!(reboot_reason(&[deployment("fedora-c.0", "c", false)]), None);
+    }
+}
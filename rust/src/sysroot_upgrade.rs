@@ -2,16 +2,24 @@
 
 // SPDX-License-Identifier: Apache-2.0 OR MIT
 
+use crate::core::evrcmp;
 use crate::cxxrsutil::*;
-use crate::ffi::{output_message, ContainerImageState};
-use anyhow::Result;
-use ostree::glib;
+use crate::ffi::{output_message, ContainerImageState, ContainerPullConfig};
+use crate::treefile::DeriveContainerPull;
+use anyhow::{anyhow, Context, Result};
+use glib::prelude::*;
+use ostree::{gio, glib};
 use ostree_container::store::ImageImporter;
 use ostree_container::store::PrepareResult;
-use ostree_container::OstreeImageReference;
+use ostree_container::{OstreeImageReference, Transport};
 use ostree_ext::container as ostree_container;
 use ostree_ext::container::store::{ImportProgress, ManifestLayerState};
+use ostree_ext::containers_image_proxy::{ImageProxy, ImageProxyConfig};
+use ostree_ext::oci_spec::image::{ImageConfiguration, ImageManifest};
 use ostree_ext::ostree;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::time::Duration;
 use tokio::runtime::Handle;
 use tokio::sync::mpsc::Receiver;
 
@@ -48,14 +56,114 @@ async fn layer_progress_print(mut r: Receiver<ImportProgress>) {
     }
 }
 
+/// Pull settings, combining the daemon configuration with the origin.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct PullSettings {
+    proxy: Option<String>,
+    tls_verify: bool,
+    retries: u32,
+    timeout: Option<Duration>,
+}
+
+impl Default for PullSettings {
+    /// The defaults of the image proxy and skopeo.
+    fn default() -> Self {
+        Self {
+            proxy: None,
+            tls_verify: true,
+            retries: 0,
+            timeout: None,
+        }
+    }
+}
+
+impl PullSettings {
+    /// Settings from `origin` take precedence over the daemon `config`.
+    fn new(config: &ContainerPullConfig, origin: Option<&DeriveContainerPull>) -> Self {
+        let origin = origin.cloned().unwrap_or_default();
+        let proxy = origin
+            .proxy
+            .or_else(|| Some(config.proxy.clone()))
+            .filter(|p| !p.is_empty());
+        let timeout = Some(origin.timeout.unwrap_or(config.timeout))
+            .filter(|&t| t > 0)
+            .map(Duration::from_secs);
+        Self {
+            proxy,
+            tls_verify: origin.tls_verify.unwrap_or(config.tls_verify),
+            retries: origin.retries.unwrap_or(config.retries),
+            timeout,
+        }
+    }
+
+    /// The image proxy configuration; `authfile` is the decrypted auth.json from
+    /// [`crate::credentials::registry_authfile`], if any.
+    pub(crate) fn proxy_config(
+        &self,
+        authfile: Option<&tempfile::NamedTempFile>,
+    ) -> ImageProxyConfig {
+        let mut config = ImageProxyConfig::default();
+        config.authfile = authfile.map(|f| f.path().to_owned());
+        if !self.tls_verify {
+            config.insecure_skip_tls_verification = Some(true);
+        }
+        if let Some(proxy) = self.proxy.as_deref() {
+            let mut cmd = std::process::Command::new("skopeo");
+            cmd.env("HTTP_PROXY", proxy).env("HTTPS_PROXY", proxy);
+            config.skopeo_cmd = Some(cmd);
+        }
+        config
+    }
+
+    /// A `skopeo` command with the same settings as the image proxy, for
+    /// the operations the proxy doesn't provide; add the subcommand.
+    pub(crate) fn skopeo_command(
+        &self,
+        authfile: Option<&tempfile::NamedTempFile>,
+    ) -> std::process::Command {
+        let mut cmd = std::process::Command::new("skopeo");
+        if let Some(proxy) = self.proxy.as_deref() {
+            cmd.env("HTTP_PROXY", proxy).env("HTTPS_PROXY", proxy);
+        }
+        if let Some(authfile) = authfile {
+            cmd.arg("--authfile").arg(authfile.path());
+        }
+        if !self.tls_verify {
+            cmd.arg("--tls-verify=false");
+        }
+        cmd
+    }
+}
+
 async fn pull_container_async(
     repo: &ostree::Repo,
     imgref: &OstreeImageReference,
+    settings: &PullSettings,
+    verify_attestation: bool,
 ) -> Result<ContainerImageState> {
     output_message(&format!("Pulling manifest: {}", &imgref));
-    let mut imp = ImageImporter::new(repo, imgref, Default::default()).await?;
+    let authfile = crate::credentials::registry_authfile(imgref)?;
+    let proxy_config = settings.proxy_config(authfile.as_ref());
+    let mut imp = ImageImporter::new(repo, imgref, proxy_config).await?;
     let layer_progress = imp.request_progress();
-    let prep = match imp.prepare().await? {
+    let prep = imp.prepare().await?;
+    if verify_attestation {
+        let digest = match &prep {
+            PrepareResult::AlreadyPresent(r) => r.manifest_digest.as_str(),
+            PrepareResult::Ready(r) => r.manifest_digest.as_str(),
+        };
+        let verified = crate::containers_attestation::verify_image_attestation(
+            imgref,
+            digest,
+            settings,
+            authfile.as_ref(),
+        )
+        .await?;
+        if verified {
+            output_message(&format!("Verified attestation for {}", digest));
+        }
+    }
+    let prep = match prep {
         PrepareResult::AlreadyPresent(r) => return Ok(r.into()),
         PrepareResult::Ready(r) => r,
     };
@@ -81,25 +189,96 @@ async fn pull_container_async(
             "custom layers stored: {stored} needed: {n_to_fetch} ({size})"
         ));
     }
+    let (size_stored, size_total) = prep
+        .ostree_layers
+        .iter()
+        .chain(std::iter::once(&prep.ostree_commit_layer))
+        .chain(prep.layers.iter())
+        .fold((0u64, 0u64), |(stored, total), l| {
+            let stored = stored + if l.commit.is_some() { l.size() } else { 0 };
+            (stored, total + l.size())
+        });
     let import = imp.import(prep).await;
     let _ = progress_printer.await;
     // TODO log the discarded bits from import
-    Ok(import?.into())
+    let import = import?;
+    if size_stored > 0 {
+        let saved = glib::format_size(size_stored);
+        let total = glib::format_size(size_total);
+        output_message(&format!(
+            "Reused stored layers: {saved} of {total} not downloaded"
+        ));
+    }
+    Ok(import.into())
+}
+
+/// Pull the image, retrying on failure and bounding each attempt by the
+/// timeout according to `settings`.
+async fn pull_container_with_retries(
+    repo: &ostree::Repo,
+    imgref: &OstreeImageReference,
+    settings: &PullSettings,
+    verify_attestation: bool,
+) -> Result<ContainerImageState> {
+    let mut attempt = 0;
+    loop {
+        let pull = pull_container_async(repo, imgref, settings, verify_attestation);
+        let r = match settings.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, pull)
+                .await
+                .unwrap_or_else(|_| {
+                    Err(anyhow!(
+                        "Timed out after {}s pulling {}",
+                        timeout.as_secs(),
+                        imgref
+                    ))
+                }),
+            None => pull.await,
+        };
+        match r {
+            Err(e) if attempt < settings.retries => {
+                attempt += 1;
+                output_message(&format!(
+                    "Pull failed ({:#}); retrying ({}/{})",
+                    e, attempt, settings.retries
+                ));
+            }
+            r => return r,
+        }
+    }
 }
 
 /// Import ostree commit in container image using ostree-rs-ext's API.
+/// If `enforce_sigpolicy` is set, the image must be signed according to the
+/// system containers-policy.json.  Unless `bypass_attestation` is set, the
+/// image must have an attestation satisfying the attestation policy, if one
+/// is configured.  The proxy, TLS verification, retries and timeout come from
+/// the daemon `config`, unless set in the `origin`.
 pub(crate) fn pull_container(
     repo: &crate::FFIOstreeRepo,
     cancellable: &crate::FFIGCancellable,
     imgref: &str,
+    enforce_sigpolicy: bool,
+    bypass_attestation: bool,
+    origin: &crate::FFIGKeyFile,
+    config: &ContainerPullConfig,
 ) -> CxxResult<Box<ContainerImageState>> {
     let repo = &repo.glib_reborrow();
     let cancellable = cancellable.glib_reborrow();
     let imgref = &OstreeImageReference::try_from(imgref)?;
+    if enforce_sigpolicy {
+        crate::containers_policy::require_signed_image(imgref)?;
+    }
+    if bypass_attestation {
+        output_message("Skipping attestation verification");
+    }
+    let verify_attestation = !bypass_attestation;
+    let origin = crate::origin::origin_to_treefile_inner(&origin.glib_reborrow())?;
+    let settings = &PullSettings::new(config, origin.parsed.derive.container_pull.as_ref());
 
     let r = Handle::current().block_on(async {
         crate::utils::run_with_cancellable(
-            async { pull_container_async(repo, imgref).await },
+            async { pull_container_with_retries(repo, imgref, settings, verify_attestation).await },
             &cancellable,
         )
         .await
@@ -118,3 +297,641 @@ pub(crate) fn query_container_image(
         .ok_or_else(|| anyhow::anyhow!("Failed to find image {}", imgref))?;
     Ok(Box::new(state.into()))
 }
+
+/// Add the digest, labels and creation date of the pulled image `imgref` to
+/// the status `dict` of a deployment.
+pub(crate) fn container_image_populate_variant(
+    repo: &crate::FFIOstreeRepo,
+    imgref: &str,
+    dict: &crate::FFIGVariantDict,
+) -> CxxResult<()> {
+    let repo = &repo.glib_reborrow();
+    let dict = &dict.glib_reborrow();
+    let imgref = &OstreeImageReference::try_from(imgref)?;
+    let state = ostree_container::store::query_image(repo, imgref)?
+        .ok_or_else(|| anyhow::anyhow!("Failed to find image {}", imgref))?;
+    dict.insert("container-image-reference-digest", &state.manifest_digest);
+    let config = state.configuration.as_ref();
+    let labels: HashMap<String, String> = config_labels(config)
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    dict.insert_value("container-image-labels", &labels.to_variant());
+    if let Some(created) = config.and_then(|c| c.created().as_deref()) {
+        dict.insert("container-image-created", &created);
+    }
+    Ok(())
+}
+
+/// Pair each layer digest of an image with a description of its content:
+/// for chunked images, this is the list of components in the layer.
+fn layer_descriptions(
+    manifest: &ImageManifest,
+    config: Option<&ImageConfiguration>,
+) -> Vec<(String, String)> {
+    let history = config
+        .map(|c| c.history().as_slice())
+        .unwrap_or_default()
+        .iter()
+        .filter(|h| !matches!(h.empty_layer(), Some(true)))
+        .map(|h| h.created_by().as_deref().unwrap_or_default());
+    manifest
+        .layers()
+        .iter()
+        .map(|l| l.digest().to_string())
+        .zip(history.map(Some).chain(std::iter::repeat(None)))
+        .map(|(digest, desc)| {
+            let desc = desc.filter(|s| !s.is_empty()).unwrap_or(digest.as_str());
+            let desc = desc.to_string();
+            (digest, desc)
+        })
+        .collect()
+}
+
+fn config_labels(config: Option<&ImageConfiguration>) -> BTreeMap<&str, &str> {
+    config
+        .and_then(|c| c.config().as_ref())
+        .and_then(|c| c.labels().as_ref())
+        .map(|l| l.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect())
+        .unwrap_or_default()
+}
+
+/// Return the labels which differ between two images, as (label, old, new).
+fn diff_labels<'a>(
+    old: &BTreeMap<&'a str, &'a str>,
+    new: &BTreeMap<&'a str, &'a str>,
+) -> Vec<(&'a str, Option<&'a str>, Option<&'a str>)> {
+    let keys: BTreeSet<_> = old.keys().chain(new.keys()).collect();
+    keys.into_iter()
+        .filter_map(|k| {
+            let (o, n) = (old.get(k).copied(), new.get(k).copied());
+            if o != n {
+                Some((*k, o, n))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Print a summary of what rebasing to the container image `imgref` would
+/// change compared to the booted deployment, fetching only its manifest and
+/// configuration.
+pub(crate) fn preview_container_rebase(imgref: &str) -> CxxResult<()> {
+    let imgref = &OstreeImageReference::try_from(imgref)?;
+    let sysroot = &ostree::Sysroot::new_default();
+    sysroot.load(gio::NONE_CANCELLABLE)?;
+    let repo = &sysroot.repo().unwrap();
+    let booted = sysroot.require_booted_deployment()?;
+    let origin = booted
+        .origin()
+        .ok_or_else(|| anyhow!("No origin for booted deployment"))?;
+    let origin = crate::origin::origin_to_treefile_inner(&origin)?;
+    let deployed = match origin.parsed.derive.container_image_reference.as_deref() {
+        Some(r) => ostree_container::store::query_image(repo, &OstreeImageReference::try_from(r)?)?,
+        None => None,
+    };
+
+    println!("Fetching manifest: {}", imgref);
+    let (digest, manifest, config, (stored, (n_to_fetch, size_to_fetch))) = Handle::current()
+        .block_on(async {
+            let mut imp = ImageImporter::new(repo, imgref, Default::default()).await?;
+            let r = match imp.prepare().await? {
+                PrepareResult::AlreadyPresent(s) => {
+                    let n = s.manifest.layers().len() as u32;
+                    (s.manifest_digest, s.manifest, s.configuration, (n, (0, 0)))
+                }
+                PrepareResult::Ready(p) => {
+                    let layers = p
+                        .ostree_layers
+                        .iter()
+                        .chain(std::iter::once(&p.ostree_commit_layer))
+                        .chain(p.layers.iter());
+                    let counts = layer_counts(layers);
+                    (p.manifest_digest, p.manifest, Some(p.config), counts)
+                }
+            };
+            Ok::<_, anyhow::Error>(r)
+        })?;
+    println!("Digest: {}", digest);
+    if let Some(deployed) = deployed.as_ref() {
+        if deployed.manifest_digest == digest {
+            println!("The booted deployment already uses this image.");
+            return Ok(());
+        }
+        println!("Deployed digest: {}", deployed.manifest_digest);
+    } else {
+        println!("The booted deployment is not based on a container image.");
+    }
+
+    let new_layers = layer_descriptions(&manifest, config.as_ref());
+    let (old_layers, old_labels) = match deployed.as_ref() {
+        Some(d) => (
+            layer_descriptions(&d.manifest, d.configuration.as_ref()),
+            config_labels(d.configuration.as_ref()),
+        ),
+        None => (Vec::new(), BTreeMap::new()),
+    };
+    let old_digests: HashSet<&str> = old_layers.iter().map(|(d, _)| d.as_str()).collect();
+    let new_digests: HashSet<&str> = new_layers.iter().map(|(d, _)| d.as_str()).collect();
+    let added: Vec<&str> = new_layers
+        .iter()
+        .filter(|(d, _)| !old_digests.contains(d.as_str()))
+        .map(|(_, desc)| desc.as_str())
+        .collect();
+    let removed = old_digests.difference(&new_digests).count();
+    println!(
+        "Layers: {} total, {} changed, {} removed",
+        new_layers.len(),
+        added.len(),
+        removed
+    );
+    println!(
+        "Download: {} layers ({}); {} already stored",
+        n_to_fetch,
+        glib::format_size(size_to_fetch),
+        stored
+    );
+    if !added.is_empty() {
+        println!("Changed components:");
+        for desc in added {
+            println!("  {}", desc);
+        }
+    }
+    let new_labels = config_labels(config.as_ref());
+    let labels = diff_labels(&old_labels, &new_labels);
+    if !labels.is_empty() {
+        println!("Changed labels:");
+        for (k, old, new) in labels {
+            match (old, new) {
+                (Some(old), Some(new)) => println!("  {}: {} → {}", k, old, new),
+                (None, Some(new)) => println!("  {}: (added) {}", k, new),
+                (Some(old), None) => println!("  {}: (removed) {}", k, old),
+                (None, None) => unreachable!(),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Split a registry image name like `quay.io/fedora/coreos:stable` into the
+/// repository and the tag, if any.  Digested references have no tag.
+pub(crate) fn split_image_tag(name: &str) -> (&str, Option<&str>) {
+    if let Some((repo, _digest)) = name.split_once('@') {
+        return (repo, None);
+    }
+    match name.rsplit_once(':') {
+        Some((repo, tag)) if !tag.contains('/') => (repo, Some(tag)),
+        _ => (name, None),
+    }
+}
+
+fn skopeo_json(mut cmd: std::process::Command, args: &[&str]) -> Result<serde_json::Value> {
+    let out = cmd.args(args).output().context("Running skopeo")?;
+    if !out.status.success() {
+        return Err(anyhow!(
+            "skopeo {} failed: {}",
+            args[0],
+            String::from_utf8_lossy(&out.stderr)
+        ));
+    }
+    Ok(serde_json::from_slice(&out.stdout)?)
+}
+
+/// The tags of the repository of the registry image `imgref`, highest version
+/// first as compared by librpm, along with the repository as a `docker://`
+/// reference and the tag of `imgref`, if any.  `authfile` is as for
+/// [`PullSettings::skopeo_command`].
+pub(crate) fn registry_image_tags(
+    imgref: &OstreeImageReference,
+    settings: &PullSettings,
+    authfile: Option<&tempfile::NamedTempFile>,
+) -> Result<(String, Option<String>, Vec<String>)> {
+    if imgref.imgref.transport != Transport::Registry {
+        return Err(anyhow!(
+            "Cannot list tags of {}: only registry images have tags",
+            imgref.imgref
+        ));
+    }
+    let (repo, current) = split_image_tag(&imgref.imgref.name);
+    let repo = format!("docker://{}", repo);
+    let tags = skopeo_json(settings.skopeo_command(authfile), &["list-tags", &repo])?;
+    let mut tags: Vec<String> = tags["Tags"]
+        .as_array()
+        .map(|a| {
+            a.iter()
+                .filter_map(|t| t.as_str().map(String::from))
+                .collect()
+        })
+        .unwrap_or_default();
+    tags.sort_unstable_by(|a, b| evrcmp(b, a));
+    Ok((repo, current.map(String::from), tags))
+}
+
+/// Return the version label and the creation date of the image tagged `tag`
+/// in the `docker://` repository `repo`.
+pub(crate) fn registry_image_tag_info(
+    repo: &str,
+    tag: &str,
+    settings: &PullSettings,
+    authfile: Option<&tempfile::NamedTempFile>,
+) -> Result<(String, String)> {
+    let imgref = format!("{}:{}", repo, tag);
+    let info = skopeo_json(
+        settings.skopeo_command(authfile),
+        &["inspect", "--no-tags", &imgref],
+    )?;
+    let labels = &info["Labels"];
+    let version = labels["org.opencontainers.image.version"]
+        .as_str()
+        .or_else(|| labels["version"].as_str())
+        .unwrap_or("(no version)");
+    let created = info["Created"].as_str().unwrap_or_default();
+    Ok((version.to_string(), created.to_string()))
+}
+
+/// Output `count` tags of the registry image `imgref` after skipping `skip`,
+/// highest version first, along with the version label and creation date of
+/// the image of each.  The registry is accessed with the same settings and
+/// credentials as when pulling per `origin` and `config`.
+pub(crate) fn list_container_tags(
+    imgref: &str,
+    origin: &crate::FFIGKeyFile,
+    config: &ContainerPullConfig,
+    skip: u32,
+    count: u32,
+) -> CxxResult<()> {
+    let imgref = &OstreeImageReference::try_from(imgref)?;
+    let origin = crate::origin::origin_to_treefile_inner(&origin.glib_reborrow())?;
+    let settings = &PullSettings::new(config, origin.parsed.derive.container_pull.as_ref());
+    let authfile = crate::credentials::registry_authfile(imgref)?;
+    let (repo, current, tags) = registry_image_tags(imgref, settings, authfile.as_ref())?;
+    let page: Vec<&String> = tags
+        .iter()
+        .skip(skip as usize)
+        .take(count as usize)
+        .collect();
+    if page.is_empty() {
+        output_message("No tags found on this page.");
+        return Ok(());
+    }
+    for tag in page.iter() {
+        let (version, created) = registry_image_tag_info(&repo, tag, settings, authfile.as_ref())?;
+        let tracked = current.as_deref() == Some(tag.as_str());
+        output_message(&format!(
+            "{} {:<20} {:<20} {}{}",
+            if tracked { "●" } else { " " },
+            tag,
+            version,
+            created,
+            if tracked { " (tracked)" } else { "" }
+        ));
+    }
+    if tags.len() > skip as usize + page.len() {
+        let next = skip / count + 2;
+        output_message(&format!("Use --list-page={} to show further tags.", next));
+    }
+    Ok(())
+}
+
+/// Split a package NEVRA like `bash-5.1.16-2.fc36.x86_64` into (name, evr, arch).
+/// Returns `None` for words which aren't NEVRAs.
+fn parse_nevra(s: &str) -> Option<(&str, &str, &str)> {
+    let nevra = libdnf_sys::hy_split_nevra(s).ok()?;
+    let arch = &nevra.arch;
+    if !arch.starts_with(|c: char| c.is_ascii_alphabetic())
+        || !arch.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return None;
+    }
+    let name = s.get(..nevra.name.len())?;
+    let evr = s.get(nevra.name.len() + 1..s.len() - arch.len() - 1)?;
+    Some((name, evr, &s[s.len() - arch.len()..]))
+}
+
+/// Gather the packages of a chunked image from the components recorded in
+/// the history of its configuration, as name → (evr, arch).
+fn image_packages(config: Option<&ImageConfiguration>) -> BTreeMap<&str, (&str, &str)> {
+    config
+        .map(|c| c.history().as_slice())
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|h| h.created_by().as_deref())
+        .flat_map(|s| s.split(|c: char| c == ',' || c.is_whitespace()))
+        .filter_map(parse_nevra)
+        .map(|(name, evr, arch)| (name, (evr, arch)))
+        .collect()
+}
+
+/// `RPM_OSTREE_PKG_TYPE_BASE`: all packages of an image are base packages.
+const PKG_TYPE_BASE: u32 = 0;
+
+pub(crate) type PackageChange<'a> = (&'a str, (&'a str, &'a str), (&'a str, &'a str));
+
+/// The package changes between two images, as (upgraded, downgraded, removed, added).
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct PackageDiff<'a> {
+    pub(crate) upgraded: Vec<PackageChange<'a>>,
+    pub(crate) downgraded: Vec<PackageChange<'a>>,
+    pub(crate) removed: Vec<(&'a str, (&'a str, &'a str))>,
+    pub(crate) added: Vec<(&'a str, (&'a str, &'a str))>,
+}
+
+impl<'a> PackageDiff<'a> {
+    pub(crate) fn new(
+        old: &BTreeMap<&'a str, (&'a str, &'a str)>,
+        new: &BTreeMap<&'a str, (&'a str, &'a str)>,
+    ) -> Self {
+        let mut diff = Self::default();
+        for (&name, &old_pkg) in old {
+            match new.get(name) {
+                Some(&new_pkg) if new_pkg != old_pkg => {
+                    if evrcmp(new_pkg.0, old_pkg.0) == Ordering::Less {
+                        diff.downgraded.push((name, old_pkg, new_pkg));
+                    } else {
+                        diff.upgraded.push((name, old_pkg, new_pkg));
+                    }
+                }
+                Some(_) => {}
+                None => diff.removed.push((name, old_pkg)),
+            }
+        }
+        for (&name, &new_pkg) in new {
+            if !old.contains_key(name) {
+                diff.added.push((name, new_pkg));
+            }
+        }
+        diff
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.upgraded.is_empty()
+            && self.downgraded.is_empty()
+            && self.removed.is_empty()
+            && self.added.is_empty()
+    }
+
+    /// Serialize in the `rpm-diff` format of the `CachedUpdate` D-Bus property.
+    fn to_variant(&self) -> glib::Variant {
+        let pkg = |(evr, arch): (&str, &str)| (evr.to_string(), arch.to_string());
+        let changed = |v: &[PackageChange]| {
+            v.iter()
+                .map(|&(name, old, new)| (PKG_TYPE_BASE, name.to_string(), pkg(old), pkg(new)))
+                .collect::<Vec<_>>()
+                .to_variant()
+        };
+        let single = |v: &[(&str, (&str, &str))]| {
+            v.iter()
+                .map(|&(name, (evr, arch))| {
+                    (
+                        PKG_TYPE_BASE,
+                        name.to_string(),
+                        evr.to_string(),
+                        arch.to_string(),
+                    )
+                })
+                .collect::<Vec<_>>()
+                .to_variant()
+        };
+        let dict = glib::VariantDict::new(None);
+        dict.insert_value("upgraded", &changed(&self.upgraded));
+        dict.insert_value("downgraded", &changed(&self.downgraded));
+        dict.insert_value("removed", &single(&self.removed));
+        dict.insert_value("added", &single(&self.added));
+        dict.end()
+    }
+}
+
+/// Check whether the tag followed by the container image `imgref` now points
+/// to a different image, fetching only its manifest and configuration.  If so,
+/// populate `dict` with the details of the update in the format of the
+/// `CachedUpdate` D-Bus property, and return true.
+pub(crate) fn container_update_populate_variant(
+    repo: &crate::FFIOstreeRepo,
+    cancellable: &crate::FFIGCancellable,
+    imgref: &str,
+    origin: &crate::FFIGKeyFile,
+    config: &ContainerPullConfig,
+    dict: &crate::FFIGVariantDict,
+) -> CxxResult<bool> {
+    let repo = &repo.glib_reborrow();
+    let cancellable = cancellable.glib_reborrow();
+    let dict = &dict.glib_reborrow();
+    let imgref = &OstreeImageReference::try_from(imgref)?;
+    let origin = crate::origin::origin_to_treefile_inner(&origin.glib_reborrow())?;
+    let settings = PullSettings::new(config, origin.parsed.derive.container_pull.as_ref());
+    let current = ostree_container::store::query_image(repo, imgref)?;
+
+    let prep = Handle::current().block_on(async {
+        crate::utils::run_with_cancellable(
+            async {
+                let authfile = crate::credentials::registry_authfile(imgref)?;
+                let proxy_config = settings.proxy_config(authfile.as_ref());
+                let mut imp = ImageImporter::new(repo, imgref, proxy_config).await?;
+                imp.prepare().await
+            },
+            &cancellable,
+        )
+        .await
+    })?;
+    let prep = match prep {
+        PrepareResult::AlreadyPresent(_) => return Ok(false),
+        PrepareResult::Ready(p) => p,
+    };
+
+    let digest = prep.manifest_digest.as_str();
+    dict.insert("checksum", &digest);
+    dict.insert("container-image-reference-digest", &digest);
+    dict.insert("origin", &imgref.to_string());
+    dict.insert("ref-has-new-commit", &true);
+    let labels = config_labels(Some(&prep.config));
+    if let Some(version) = labels
+        .get("org.opencontainers.image.version")
+        .or_else(|| labels.get("version"))
+    {
+        dict.insert("version", version);
+    }
+    let created = prep.config.created().as_deref();
+    if let Some(t) = created.and_then(|t| chrono::DateTime::parse_from_rfc3339(t).ok()) {
+        dict.insert("timestamp", &(t.timestamp() as u64));
+    }
+    // The package diff is only meaningful if both images are chunked.
+    let old_packages = image_packages(current.as_ref().and_then(|c| c.configuration.as_ref()));
+    let new_packages = image_packages(Some(&prep.config));
+    if !old_packages.is_empty() && !new_packages.is_empty() {
+        let diff = PackageDiff::new(&old_packages, &new_packages);
+        if !diff.is_empty() {
+            dict.insert_value("rpm-diff", &diff.to_variant());
+        }
+    }
+    Ok(true)
+}
+
+/// Return whether the image `imgref` differs from the one we have, fetching only its
+/// manifest; this is the fast path of quick update checks.
+pub(crate) fn container_update_available(
+    repo: &crate::FFIOstreeRepo,
+    cancellable: &crate::FFIGCancellable,
+    imgref: &str,
+    origin: &crate::FFIGKeyFile,
+    config: &ContainerPullConfig,
+) -> CxxResult<bool> {
+    let repo = &repo.glib_reborrow();
+    let cancellable = cancellable.glib_reborrow();
+    let imgref = &OstreeImageReference::try_from(imgref)?;
+    let origin = crate::origin::origin_to_treefile_inner(&origin.glib_reborrow())?;
+    let settings = PullSettings::new(config, origin.parsed.derive.container_pull.as_ref());
+    let current = ostree_container::store::query_image(repo, imgref)?;
+
+    let authfile = crate::credentials::registry_authfile(imgref)?;
+    let mut proxy_config = settings.proxy_config(authfile.as_ref());
+    ostree_container::merge_default_container_proxy_opts(&mut proxy_config)?;
+    let digest = Handle::current().block_on(async {
+        crate::utils::run_with_cancellable(
+            async {
+                let proxy = ImageProxy::new_with_config(proxy_config).await?;
+                let img = proxy.open_image(&imgref.imgref.to_string()).await?;
+                let (digest, _) = proxy.fetch_manifest(&img).await?;
+                proxy.close_image(&img).await?;
+                proxy.finalize().await?;
+                Ok(digest)
+            },
+            &cancellable,
+        )
+        .await
+    })?;
+    Ok(current.map(|c| c.manifest_digest) != Some(digest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_image_tag() {
+        let cases = [
+            (
+                "quay.io/fedora/coreos:stable",
+                ("quay.io/fedora/coreos", Some("stable")),
+            ),
+            ("localhost:5000/os", ("localhost:5000/os", None)),
+            ("localhost:5000/os:39", ("localhost:5000/os", Some("39"))),
+            ("quay.io/os@sha256:abcd", ("quay.io/os", None)),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(split_image_tag(name), expected);
+        }
+    }
+
+    #[test]
+    fn test_diff_labels() {
+        let old = maplit::btreemap! {
+            "version" => "36.20220505.3.2",
+            "ostree.bootable" => "true",
+            "ostree.linux" => "5.17.5-300.fc36.x86_64",
+        };
+        let new = maplit::btreemap! {
+            "version" => "36.20220520.3.0",
+            "ostree.bootable" => "true",
+            "org.opencontainers.image.source" => "https://example.com/os",
+        };
+        assert_eq!(
+            diff_labels(&old, &new),
+            vec![
+                (
+                    "org.opencontainers.image.source",
+                    None,
+                    Some("https://example.com/os")
+                ),
+                ("ostree.linux", Some("5.17.5-300.fc36.x86_64"), None),
+                ("version", Some("36.20220505.3.2"), Some("36.20220520.3.0")),
+            ]
+        );
+        assert!(diff_labels(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn test_parse_nevra() {
+        assert_eq!(
+            parse_nevra("bash-5.1.16-2.fc36.x86_64"),
+            Some(("bash", "5.1.16-2.fc36", "x86_64"))
+        );
+        assert_eq!(
+            parse_nevra("python3-libs-1:3.10.4-1.fc36.noarch"),
+            Some(("python3-libs", "1:3.10.4-1.fc36", "noarch"))
+        );
+        assert_eq!(parse_nevra("ostree_commit"), None);
+        assert_eq!(parse_nevra("bash"), None);
+        assert_eq!(parse_nevra("foo-1.2"), None);
+        assert_eq!(parse_nevra("and-4-more.2"), None);
+    }
+
+    #[test]
+    fn test_package_diff() {
+        let old = maplit::btreemap! {
+            "bash" => ("5.1.16-2.fc36", "x86_64"),
+            "kernel" => ("5.17.5-300.fc36", "x86_64"),
+            "nano" => ("6.0-2.fc36", "x86_64"),
+            "vim-minimal" => ("2:8.2.4975-1.fc36", "x86_64"),
+        };
+        let new = maplit::btreemap! {
+            "bash" => ("5.1.16-2.fc36", "x86_64"),
+            "kernel" => ("5.17.8-300.fc36", "x86_64"),
+            "strace" => ("5.18-1.fc36", "x86_64"),
+            "vim-minimal" => ("2:8.2.4950-1.fc36", "x86_64"),
+        };
+        let diff = PackageDiff::new(&old, &new);
+        assert_eq!(
+            diff,
+            PackageDiff {
+                upgraded: vec![(
+                    "kernel",
+                    ("5.17.5-300.fc36", "x86_64"),
+                    ("5.17.8-300.fc36", "x86_64")
+                )],
+                downgraded: vec![(
+                    "vim-minimal",
+                    ("2:8.2.4975-1.fc36", "x86_64"),
+                    ("2:8.2.4950-1.fc36", "x86_64")
+                )],
+                removed: vec![("nano", ("6.0-2.fc36", "x86_64"))],
+                added: vec![("strace", ("5.18-1.fc36", "x86_64"))],
+            }
+        );
+        assert!(PackageDiff::new(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn test_pull_settings() {
+        let config = ContainerPullConfig {
+            proxy: "http://proxy.example.com:3128".into(),
+            tls_verify: true,
+            retries: 2,
+            timeout: 0,
+        };
+        let settings = PullSettings::new(&config, None);
+        assert_eq!(
+            settings,
+            PullSettings {
+                proxy: Some("http://proxy.example.com:3128".into()),
+                tls_verify: true,
+                retries: 2,
+                timeout: None,
+            }
+        );
+        let mut origin = DeriveContainerPull::default();
+        origin.proxy = Some("".into());
+        origin.tls_verify = Some(false);
+        origin.timeout = Some(600);
+        let settings = PullSettings::new(&config, Some(&origin));
+        assert_eq!(
+            settings,
+            PullSettings {
+                proxy: None,
+                tls_verify: false,
+                retries: 2,
+                timeout: Some(Duration::from_secs(600)),
+            }
+        );
+    }
+}
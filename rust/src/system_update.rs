@@ -0,0 +1,159 @@
+//! Offline updates integrated with `system-update.target`, like PackageKit's;
+//! see `systemd.offline-updates(7)`.  `rpm-ostree upgrade --offline` only
+//! downloads the update, and points `/etc/system-update` to our marker so that
+//! the next boot is a dedicated offline update boot.  There,
+//! `rpm-ostree-system-update.service` deploys the update from the cache and
+//! reboots, finalizing it while no other services are running.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::cxxrsutil::*;
+use anyhow::{anyhow, bail, Context, Result};
+use glib::Variant;
+use ostree_ext::{gio, glib};
+use std::os::unix::fs::symlink;
+use std::path::Path;
+use std::process::Command;
+
+/// Read by systemd-system-update-generator to boot into `system-update.target`.
+/// It also checks `/system-update`, but that's in the read-only deployment
+/// root here, while `/etc` is that of the booted system and writable.
+const SYSTEM_UPDATE_LINK: &str = "/etc/system-update";
+/// Where `/etc/system-update` points to for our offline updates.
+const MARKER_PATH: &str = "/var/lib/rpm-ostree/system-update";
+
+/// Whether the offline update boot is ours, rather than e.g. PackageKit's.
+fn link_is_ours(link: &Path, marker: &Path) -> Result<bool> {
+    match std::fs::read_link(link) {
+        Ok(target) => Ok(target == marker),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e).with_context(|| format!("Reading {}", link.display())),
+    }
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match std::fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e).with_context(|| format!("Removing {}", path.display())),
+    }
+}
+
+fn prepare(link: &Path, marker: &Path) -> Result<()> {
+    if !link_is_ours(link, marker)? && link.symlink_metadata().is_ok() {
+        bail!(
+            "Another offline update is pending: {} exists",
+            link.display()
+        );
+    }
+    std::fs::write(marker, b"").with_context(|| format!("Writing {}", marker.display()))?;
+    remove_if_exists(link)?;
+    symlink(marker, link).with_context(|| format!("Creating {}", link.display()))?;
+    Ok(())
+}
+
+fn cancel(link: &Path, marker: &Path) -> Result<bool> {
+    let pending = link_is_ours(link, marker)?;
+    if pending {
+        remove_if_exists(link)?;
+    }
+    remove_if_exists(marker)?;
+    Ok(pending)
+}
+
+/// Make the next boot an offline update boot applying the downloaded update.
+pub(crate) fn system_update_prepare() -> CxxResult<()> {
+    prepare(Path::new(SYSTEM_UPDATE_LINK), Path::new(MARKER_PATH))?;
+    Ok(())
+}
+
+/// Cancel a pending offline update; returns whether there was one.
+pub(crate) fn system_update_cancel() -> CxxResult<bool> {
+    Ok(cancel(
+        Path::new(SYSTEM_UPDATE_LINK),
+        Path::new(MARKER_PATH),
+    )?)
+}
+
+/// Show the progress of the update on the boot splash, if any.
+fn plymouth(args: &[&str]) {
+    // Failures are fine, e.g. plymouth isn't installed or there's no splash.
+    let _ = Command::new("plymouth").args(args).status();
+}
+
+fn deploy_from_cache() -> Result<()> {
+    let client = &mut crate::client::ClientConnection::new()?;
+    let options = glib::VariantDict::new(None);
+    options.insert("cache-only", &true);
+    options.insert("initiating-command-line", &"offline update");
+    let params = Variant::from_tuple(&[options.end()]);
+    let reply = &client.get_os_proxy().call_sync(
+        "Upgrade",
+        Some(&params),
+        gio::DBusCallFlags::NONE,
+        -1,
+        gio::NONE_CANCELLABLE,
+    )?;
+    let reply = reply
+        .get::<(String,)>()
+        .ok_or_else(|| anyhow!("Invalid reply"))?;
+    client.transaction_connect_progress_sync(reply.0.as_str())?;
+    Ok(())
+}
+
+/// Main entrypoint, run by `rpm-ostree-system-update.service` in the offline
+/// update boot.
+pub fn entrypoint(_args: &[&str]) -> Result<()> {
+    let link = Path::new(SYSTEM_UPDATE_LINK);
+    let marker = Path::new(MARKER_PATH);
+    if !link_is_ours(link, marker)? {
+        println!("{} isn't ours; nothing to do", link.display());
+        return Ok(());
+    }
+    // Removed first so that we don't end up in a loop of offline update boots.
+    cancel(link, marker)?;
+
+    plymouth(&["display-message", "--text=Applying system update..."]);
+    plymouth(&["system-update", "--progress=0"]);
+    let r = deploy_from_cache();
+    plymouth(&["system-update", "--progress=100"]);
+    if let Err(e) = &r {
+        eprintln!("Failed to apply update: {:#}", e);
+        plymouth(&["display-message", "--text=Failed to apply system update"]);
+    }
+    // Whether it failed or not, boot normally again; into the update if it succeeded.
+    let status = Command::new("systemctl")
+        .arg("reboot")
+        .status()
+        .context("Running systemctl reboot")?;
+    if !status.success() {
+        bail!("systemctl reboot failed: {:?}", status);
+    }
+    r
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prepare_cancel() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        let link = &td.path().join("system-update");
+        let marker = &td.path().join("marker");
+        assert!(!link_is_ours(link, marker)?);
+        assert!(!cancel(link, marker)?);
+        prepare(link, marker)?;
+        assert!(link_is_ours(link, marker)?);
+        // Preparing again is fine
+        prepare(link, marker)?;
+        assert!(cancel(link, marker)?);
+        assert!(!link.exists() && !marker.exists());
+        // But not if another offline update is pending
+        symlink(td.path(), link)?;
+        assert!(prepare(link, marker).is_err());
+        assert!(!cancel(link, marker)?);
+        assert!(link.symlink_metadata().is_ok());
+        Ok(())
+    }
+}
@@ -0,0 +1,367 @@
+//! Implementation of the treefile `generate-sysusers` field: at the end of a
+//! compose, write [sysusers.d](https://www.freedesktop.org/software/systemd/man/sysusers.d.html)
+//! entries for the users and groups which packages created imperatively (e.g.
+//! with `useradd` in scriptlets), so that they can be recreated on the host
+//! without relying on the nss-altfiles copies in `/usr/lib/{passwd,group}`.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::cxxrsutil::*;
+use crate::ffiutil;
+use crate::nameservice::group::{parse_group_content, GroupEntry};
+use crate::nameservice::passwd::{parse_passwd_content, PasswdEntry};
+use crate::treefile::Treefile;
+use anyhow::{anyhow, bail, Context, Result};
+use cap_std::fs::{Dir, Permissions};
+use cap_std_ext::cap_std;
+use cap_std_ext::dirext::CapStdExtDirExt;
+use fn_error_context::context;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{BufReader, Write};
+use std::os::unix::prelude::PermissionsExt;
+
+const SYSUSERS_DIR: &str = "usr/lib/sysusers.d";
+const GENERATED_CONF: &str = "rpm-ostree-generated.conf";
+
+/// The users, groups and memberships declared by sysusers.d fragments, with
+/// their fixed IDs if any.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Declared {
+    users: BTreeMap<String, (Option<u32>, Option<u32>)>,
+    groups: BTreeMap<String, Option<u32>>,
+    members: BTreeSet<(String, String)>,
+}
+
+/// Split a sysusers.d line into fields, handling double quotes.
+fn split_fields(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.peek().map_or(false, |c| c.is_whitespace()) {
+            chars.next();
+        }
+        let mut field = String::new();
+        match chars.peek() {
+            None => break,
+            Some('"') => {
+                chars.next();
+                for c in chars.by_ref() {
+                    if c == '"' {
+                        break;
+                    }
+                    field.push(c);
+                }
+            }
+            Some(_) => {
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    field.push(c);
+                }
+            }
+        }
+        fields.push(field);
+    }
+    fields
+}
+
+/// Parse the ID field of a `u` line: `-`, `UID`, `UID:GID`, `UID:group` or
+/// a path; only numeric IDs are returned.
+fn parse_user_id(id: &str) -> (Option<u32>, Option<u32>) {
+    let (uid, gid) = match id.split_once(':') {
+        Some((u, g)) => (u, Some(g)),
+        None => (id, None),
+    };
+    let uid = uid.parse().ok();
+    let gid = gid.and_then(|g| g.parse().ok()).or(uid);
+    (uid, gid)
+}
+
+impl Declared {
+    fn parse(&mut self, buf: &str) {
+        for line in buf.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields = split_fields(line);
+            let field = |i: usize| fields.get(i).map(String::as_str).unwrap_or("-");
+            // The first declaration wins, like in systemd-sysusers.
+            match field(0) {
+                "u" | "u!" => {
+                    let ids = parse_user_id(field(2));
+                    self.users.entry(field(1).to_string()).or_insert(ids);
+                    // The primary group is implicitly created too
+                    self.groups
+                        .entry(field(1).to_string())
+                        .or_insert(ids.1.or(ids.0));
+                }
+                "g" => {
+                    let gid = field(2).parse().ok();
+                    self.groups.entry(field(1).to_string()).or_insert(gid);
+                }
+                "m" => {
+                    self.members
+                        .insert((field(1).to_string(), field(2).to_string()));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    #[context("Loading sysusers.d")]
+    fn load(rootfs: &Dir) -> Result<Self> {
+        let mut r = Self::default();
+        let dir = match rootfs.open_dir_optional(SYSUSERS_DIR)? {
+            Some(d) => d,
+            None => return Ok(r),
+        };
+        let mut names = Vec::new();
+        for entry in dir.entries()? {
+            let name = entry?.file_name();
+            let name = name
+                .to_str()
+                .ok_or_else(|| anyhow!("Invalid filename: {:?}", name))?
+                .to_string();
+            if name.ends_with(".conf") && name != GENERATED_CONF {
+                names.push(name);
+            }
+        }
+        names.sort();
+        for name in names {
+            let buf = dir
+                .read_to_string(&name)
+                .with_context(|| format!("Reading {}", name))?;
+            r.parse(&buf);
+        }
+        Ok(r)
+    }
+}
+
+/// Check the IDs declared in sysusers.d against the ones actually allocated.
+fn check_consistency(
+    declared: &Declared,
+    users: &[PasswdEntry],
+    groups: &[GroupEntry],
+) -> Vec<String> {
+    let mut errors = Vec::new();
+    for u in users {
+        if let Some((uid, gid)) = declared.users.get(&u.name) {
+            if uid.map_or(false, |uid| uid != u.uid) || gid.map_or(false, |gid| gid != u.gid) {
+                errors.push(format!(
+                    "user {}: sysusers.d declares {}:{}, passwd has {}:{}",
+                    u.name,
+                    uid.map_or("-".to_string(), |v| v.to_string()),
+                    gid.map_or("-".to_string(), |v| v.to_string()),
+                    u.uid,
+                    u.gid
+                ));
+            }
+        }
+    }
+    for g in groups {
+        if let Some(Some(gid)) = declared.groups.get(&g.name) {
+            if *gid != g.gid {
+                errors.push(format!(
+                    "group {}: sysusers.d declares {}, group has {}",
+                    g.name, gid, g.gid
+                ));
+            }
+        }
+    }
+    errors
+}
+
+fn quoted_or_dash(s: &str) -> String {
+    if s.is_empty() {
+        "-".to_string()
+    } else {
+        format!("\"{}\"", s)
+    }
+}
+
+/// Write sysusers.d entries for the users, groups and memberships not already
+/// declared.  Returns whether anything was written.
+fn write_entries(
+    w: &mut impl Write,
+    declared: &Declared,
+    users: &[PasswdEntry],
+    groups: &[GroupEntry],
+) -> Result<bool> {
+    let mut n = 0;
+    writeln!(
+        w,
+        "# Generated by rpm-ostree from the users and groups created by packages"
+    )?;
+    for g in groups
+        .iter()
+        .filter(|g| !declared.groups.contains_key(&g.name))
+    {
+        writeln!(w, "g {} {}", g.name, g.gid)?;
+        n += 1;
+    }
+    for u in users
+        .iter()
+        .filter(|u| !declared.users.contains_key(&u.name))
+    {
+        writeln!(
+            w,
+            "u {} {}:{} {} {} {}",
+            u.name,
+            u.uid,
+            u.gid,
+            quoted_or_dash(&u.gecos),
+            quoted_or_dash(&u.home_dir),
+            quoted_or_dash(&u.shell)
+        )?;
+        n += 1;
+    }
+    for g in groups {
+        for m in g.users.iter().filter(|m| !m.is_empty()) {
+            if !declared.members.contains(&(m.clone(), g.name.clone())) {
+                writeln!(w, "m {} {}", m, g.name)?;
+                n += 1;
+            }
+        }
+    }
+    Ok(n > 0)
+}
+
+fn read_entries<T>(
+    rootfs: &Dir,
+    name: &str,
+    parse: impl Fn(BufReader<cap_std::fs::File>) -> Result<Vec<T>>,
+) -> Result<Vec<T>> {
+    let mut r = Vec::new();
+    for dir in ["usr/etc", "usr/lib"] {
+        let path = format!("{}/{}", dir, name);
+        if let Some(f) = rootfs.open_optional(&path)? {
+            r.extend(parse(BufReader::new(f)).with_context(|| format!("Parsing {}", path))?);
+        }
+    }
+    Ok(r)
+}
+
+#[context("Generating sysusers.d entries")]
+fn generate_sysusers(rootfs: &Dir) -> Result<()> {
+    let declared = Declared::load(rootfs)?;
+    let users = read_entries(rootfs, "passwd", |r| parse_passwd_content(r))?;
+    let groups = read_entries(rootfs, "group", |r| parse_group_content(r))?;
+
+    let errors = check_consistency(&declared, &users, &groups);
+    if !errors.is_empty() {
+        bail!(
+            "sysusers.d is inconsistent with the allocated IDs:\n  {}",
+            errors.join("\n  ")
+        );
+    }
+
+    let mut buf = Vec::new();
+    if !write_entries(&mut buf, &declared, &users, &groups)? {
+        return Ok(());
+    }
+    let path = format!("{}/{}", SYSUSERS_DIR, GENERATED_CONF);
+    println!("Writing /{}", path);
+    rootfs.create_dir_all(SYSUSERS_DIR)?;
+    rootfs.atomic_replace_with(&path, |w| -> Result<()> {
+        w.get_mut()
+            .as_file_mut()
+            .set_permissions(Permissions::from_mode(0o644))?;
+        w.write_all(&buf)?;
+        Ok(())
+    })?;
+    Ok(())
+}
+
+/// Write sysusers.d entries for the users and groups created by packages, if
+/// enabled in the treefile.  Must run before they are split out of /usr/etc.
+pub(crate) fn compose_generate_sysusers(rootfs_dfd: i32, treefile: &Treefile) -> CxxResult<()> {
+    if !treefile.parsed.base.generate_sysusers.unwrap_or_default() {
+        return Ok(());
+    }
+    let rootfs = unsafe { ffiutil::ffi_dirfd(rootfs_dfd)? };
+    generate_sysusers(&rootfs)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    #[test]
+    fn test_split_fields() {
+        assert_eq!(
+            split_fields(r#"u  dbus 81 "System message bus"  - /sbin/nologin"#),
+            [
+                "u",
+                "dbus",
+                "81",
+                "System message bus",
+                "-",
+                "/sbin/nologin"
+            ]
+        );
+        assert!(split_fields("   ").is_empty());
+    }
+
+    #[test]
+    fn test_generate() -> Result<()> {
+        let mut declared = Declared::default();
+        declared.parse(indoc! {r#"
+            # comment
+            g wheel 10
+            u root 0 "Super User" /root /bin/bash
+            u dbus 81:81 "System message bus"
+            u systemd-oom - "systemd Userspace OOM Killer"
+            u root 1
+            m root wheel
+        "#});
+        assert_eq!(declared.users["root"], (Some(0), Some(0)));
+        assert_eq!(declared.users["systemd-oom"], (None, None));
+        assert_eq!(declared.groups["dbus"], Some(81));
+
+        let users = parse_passwd_content(
+            indoc! {"
+                root:x:0:0:Super User:/root:/bin/bash
+                dbus:x:81:81:System message bus:/:/sbin/nologin
+                systemd-oom:x:990:990:systemd Userspace OOM Killer:/:/usr/sbin/nologin
+                chrony:x:991:987::/var/lib/chrony:/sbin/nologin
+            "}
+            .as_bytes(),
+        )?;
+        let groups = parse_group_content(
+            indoc! {"
+                root:x:0:
+                wheel:x:10:root,chrony
+                dbus:x:81:
+                chrony:x:987:
+            "}
+            .as_bytes(),
+        )?;
+        assert!(check_consistency(&declared, &users, &groups).is_empty());
+
+        let mut buf = Vec::new();
+        assert!(write_entries(&mut buf, &declared, &users, &groups)?);
+        assert_eq!(
+            String::from_utf8(buf)?,
+            indoc! {r#"
+                # Generated by rpm-ostree from the users and groups created by packages
+                g chrony 987
+                u chrony 991:987 - "/var/lib/chrony" "/sbin/nologin"
+                m chrony wheel
+            "#}
+        );
+
+        let mut users = users;
+        users[1].uid = 82;
+        let mut groups = groups;
+        groups[1].gid = 11;
+        assert_eq!(
+            check_consistency(&declared, &users, &groups),
+            [
+                "user dbus: sysusers.d declares 81:81, passwd has 82:81",
+                "group wheel: sysusers.d declares 10, group has 11"
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,351 @@
+//! Ephemeral test deployments, for `rpm-ostree testdeploy`.
+//!
+//! This stages a rebase like `rpm-ostree rebase`, but the daemon records the
+//! new deployment as ephemeral, along with the default deployment it
+//! replaces.  The test deployment is booted once: during that boot,
+//! `rpm-ostree-testdeploy.service` makes the previous default the default
+//! again, and on the following boot it removes the test deployment.  It does
+//! so through the daemon, as the `testdeploy` cleanup type, which isn't
+//! exposed by `rpm-ostree cleanup`.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::cxxrsutil::*;
+use crate::deployment_generate_id_impl;
+use crate::ffi::output_message;
+use anyhow::{anyhow, Context, Result};
+use clap::Parser;
+use glib::prelude::*;
+use ostree_ext::{gio, glib, ostree};
+use serde_derive::{Deserialize, Serialize};
+use std::path::Path;
+
+/// File recording the test deployment, shared with other daemon state.
+const STATE_PATH: &str = "/var/lib/rpm-ostree/testdeploy.json";
+
+/// Stage a deployment which is booted once, then removed
+#[derive(Debug, Parser)]
+#[clap(name = "rpm-ostree testdeploy", bin_name = "rpm-ostree testdeploy")]
+#[clap(rename_all = "kebab-case")]
+pub(crate) struct TestDeployOpts {
+    /// The ref or container image to test, like for `rpm-ostree rebase`
+    #[clap(value_name = "REFSPEC")]
+    target: String,
+
+    /// Initiate a reboot after the operation is complete
+    #[clap(long, short = 'r')]
+    reboot: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+struct TestDeployState {
+    /// The ID of the test deployment.
+    deployment: String,
+    /// The ID of the default deployment it replaced, which is restored.
+    previous: Option<String>,
+    /// Whether the previous default was restored already.
+    #[serde(default)]
+    restored: bool,
+}
+
+/// What to do at boot about the test deployment.
+#[derive(Debug, PartialEq, Eq)]
+enum Action {
+    /// It wasn't finalized yet.
+    Wait,
+    /// It was removed otherwise; forget about it.
+    Forget,
+    /// It's booted; reorder the deployments by the given indices, so that the
+    /// next boot is into the previous default again.
+    Restore(Vec<usize>),
+    /// It's booted and the previous default is the default already.
+    Booted,
+    /// It was made the default again after the restore; keep it.
+    Keep,
+    /// It isn't booted anymore; remove the deployment at the given index.
+    Remove(usize),
+}
+
+/// A deployment, for planning: its ID, and whether it's staged or booted.
+struct DeploymentInfo {
+    id: String,
+    staged: bool,
+    booted: bool,
+}
+
+fn plan(state: &TestDeployState, deployments: &[DeploymentInfo]) -> Action {
+    let index = match deployments.iter().position(|d| d.id == state.deployment) {
+        Some(i) => i,
+        None => return Action::Forget,
+    };
+    let test = &deployments[index];
+    if test.staged {
+        return Action::Wait;
+    }
+    if !test.booted {
+        return Action::Remove(index);
+    }
+    if state.restored {
+        // `rpm-ostree rollback` makes it the default again
+        return if index == 0 {
+            Action::Keep
+        } else {
+            Action::Booted
+        };
+    }
+    // Fall back to the deployment after it if the previous default is gone
+    let previous = state
+        .previous
+        .as_ref()
+        .and_then(|p| deployments.iter().position(|d| &d.id == p))
+        .or_else(|| Some(index + 1).filter(|&i| i < deployments.len()));
+    match previous {
+        Some(previous) if previous > index => {
+            let mut order: Vec<usize> = (0..deployments.len()).collect();
+            order.remove(previous);
+            order.insert(index, previous);
+            Action::Restore(order)
+        }
+        _ => Action::Booted,
+    }
+}
+
+fn load_state(path: &Path) -> Result<Option<TestDeployState>> {
+    match std::fs::read_to_string(path) {
+        Ok(s) => Ok(Some(
+            serde_json::from_str(&s).with_context(|| format!("Parsing {}", path.display()))?,
+        )),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("Reading {}", path.display())),
+    }
+}
+
+fn store_state(path: &Path, state: Option<&TestDeployState>) -> Result<()> {
+    match state {
+        Some(state) => {
+            let buf = serde_json::to_vec(state)?;
+            crate::utils::write_file_atomic(path, buf)
+        }
+        None => match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("Removing {}", path.display()))
+            }
+            _ => Ok(()),
+        },
+    }
+}
+
+/// Record `deployment`, which was just staged, as the test deployment.  This
+/// must be called with the sysroot lock held.
+pub(crate) fn testdeploy_mark(
+    sysroot: &crate::ffi::OstreeSysroot,
+    deployment: &crate::ffi::OstreeDeployment,
+) -> CxxResult<()> {
+    let sysroot = &sysroot.glib_reborrow();
+    let deployment = &deployment.glib_reborrow();
+    let osname = deployment.osname().expect("osname");
+    let previous = sysroot
+        .deployments()
+        .into_iter()
+        .find(|d| !d.is_staged() && !d.equal(deployment) && d.osname() == Some(osname.clone()))
+        .map(|d| deployment_generate_id_impl(&d));
+    let state = TestDeployState {
+        deployment: deployment_generate_id_impl(deployment),
+        previous,
+        restored: false,
+    };
+    store_state(Path::new(STATE_PATH), Some(&state))?;
+    Ok(())
+}
+
+/// Add whether `deployment` is the test deployment to `dict`.
+pub(crate) fn deployment_populate_ephemeral(
+    deployment: &ostree::Deployment,
+    dict: &glib::VariantDict,
+) -> Result<()> {
+    if let Some(state) = load_state(Path::new(STATE_PATH))? {
+        if state.deployment == deployment_generate_id_impl(deployment) {
+            dict.insert("ephemeral", &true);
+        }
+    }
+    Ok(())
+}
+
+fn cleanup(sysroot: &ostree::Sysroot, path: &Path) -> Result<bool> {
+    let mut state = match load_state(path)? {
+        Some(s) => s,
+        None => return Ok(false),
+    };
+    let deployments = sysroot.deployments();
+    let booted = sysroot.booted_deployment();
+    let infos: Vec<_> = deployments
+        .iter()
+        .map(|d| DeploymentInfo {
+            id: deployment_generate_id_impl(d),
+            staged: d.is_staged(),
+            booted: booted.as_ref().map_or(false, |b| b.equal(d)),
+        })
+        .collect();
+    match plan(&state, &infos) {
+        Action::Wait | Action::Booted => Ok(false),
+        Action::Forget => {
+            store_state(path, None)?;
+            Ok(false)
+        }
+        Action::Keep => {
+            output_message(&format!("Keeping test deployment {}", state.deployment));
+            store_state(path, None)?;
+            Ok(true)
+        }
+        Action::Restore(order) => {
+            let new: Vec<_> = order.into_iter().map(|i| deployments[i].clone()).collect();
+            sysroot.write_deployments(&new, gio::NONE_CANCELLABLE)?;
+            output_message(&format!(
+                "Booted test deployment {}; the next boot returns to the previous default",
+                state.deployment
+            ));
+            state.restored = true;
+            store_state(path, Some(&state))?;
+            Ok(true)
+        }
+        Action::Remove(index) => {
+            let mut new = deployments;
+            new.remove(index);
+            sysroot.write_deployments(&new, gio::NONE_CANCELLABLE)?;
+            output_message(&format!("Removed test deployment {}", state.deployment));
+            store_state(path, None)?;
+            Ok(true)
+        }
+    }
+}
+
+/// Restore the previous default deployment or remove the test deployment,
+/// as due; returns whether the deployments changed.  This is run by the
+/// daemon for the `testdeploy` cleanup type, with the sysroot lock held.
+pub(crate) fn testdeploy_cleanup(sysroot: &crate::ffi::OstreeSysroot) -> CxxResult<bool> {
+    let sysroot = &sysroot.glib_reborrow();
+    Ok(cleanup(sysroot, Path::new(STATE_PATH))?)
+}
+
+/// Entrypoint for `rpm-ostree-testdeploy.service`, run once per boot.
+pub fn cleanup_entrypoint(_args: &[&str]) -> Result<()> {
+    let client = &mut crate::client::ClientConnection::new()?;
+    let params = glib::Variant::from_tuple(&[vec!["testdeploy"].to_variant()]);
+    let reply = &client.get_os_proxy().call_sync(
+        "Cleanup",
+        Some(&params),
+        gio::DBusCallFlags::NONE,
+        -1,
+        gio::NONE_CANCELLABLE,
+    )?;
+    let reply = reply
+        .get::<(String,)>()
+        .ok_or_else(|| anyhow!("Invalid reply"))?;
+    client.transaction_connect_progress_sync(reply.0.as_str())
+}
+
+/// Main entrypoint for `rpm-ostree testdeploy`.
+pub fn entrypoint(args: &[&str]) -> Result<()> {
+    let opts = TestDeployOpts::parse_from(args.iter().skip(1));
+    let client = &mut crate::client::ClientConnection::new()?;
+    let options = glib::VariantDict::new(None);
+    options.insert("ephemeral", &true);
+    // The previous ref is needed again once the test deployment is removed
+    options.insert("skip-purge", &true);
+    options.insert("reboot", &opts.reboot);
+    let command_line = format!("rpm-ostree testdeploy {}", opts.target);
+    options.insert("initiating-command-line", &command_line.as_str());
+    let packages: Vec<String> = Vec::new();
+    let params = glib::Variant::from_tuple(&[
+        options.end(),
+        opts.target.to_variant(),
+        packages.to_variant(),
+    ]);
+    let reply = &client.get_os_proxy().call_sync(
+        "Rebase",
+        Some(&params),
+        gio::DBusCallFlags::NONE,
+        -1,
+        gio::NONE_CANCELLABLE,
+    )?;
+    let reply = reply
+        .get::<(String,)>()
+        .ok_or_else(|| anyhow!("Invalid reply"))?;
+    client.transaction_connect_progress_sync(reply.0.as_str())?;
+    if !opts.reboot {
+        println!("Test deployment staged; it is booted once, then removed again.");
+        println!("Run \"systemctl reboot\" to start a reboot");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn infos(ids: &[&str], staged: Option<&str>, booted: &str) -> Vec<DeploymentInfo> {
+        ids.iter()
+            .map(|id| DeploymentInfo {
+                id: id.to_string(),
+                staged: Some(*id) == staged,
+                booted: *id == booted,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_plan() {
+        let mut state = TestDeployState {
+            deployment: "test".into(),
+            previous: Some("prev".into()),
+            restored: false,
+        };
+        let staged = infos(&["test", "prev", "old"], Some("test"), "prev");
+        assert_eq!(plan(&state, &staged), Action::Wait);
+        // The first boot of the test deployment restores the previous default
+        let booted = infos(&["test", "prev", "old"], None, "test");
+        assert_eq!(plan(&state, &booted), Action::Restore(vec![1, 0, 2]));
+        state.restored = true;
+        let restored = infos(&["prev", "test", "old"], None, "test");
+        assert_eq!(plan(&state, &restored), Action::Booted);
+        // Unless it's rolled back to before the next boot
+        assert_eq!(plan(&state, &booted), Action::Keep);
+        // The next one removes it
+        let next = infos(&["prev", "test", "old"], None, "prev");
+        assert_eq!(plan(&state, &next), Action::Remove(1));
+        // Also if it wasn't booted, e.g. it failed and the previous default was picked
+        let failed = infos(&["test", "prev", "old"], None, "prev");
+        assert_eq!(plan(&state, &failed), Action::Remove(0));
+        let gone = infos(&["prev", "old"], None, "prev");
+        assert_eq!(plan(&state, &gone), Action::Forget);
+
+        // Without the previous default, the following deployment becomes the default
+        let state = TestDeployState {
+            deployment: "test".into(),
+            previous: None,
+            restored: false,
+        };
+        let booted = infos(&["test", "old"], None, "test");
+        assert_eq!(plan(&state, &booted), Action::Restore(vec![1, 0]));
+        let only = infos(&["test"], None, "test");
+        assert_eq!(plan(&state, &only), Action::Booted);
+    }
+
+    #[test]
+    fn test_state() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        let path = &td.path().join("testdeploy.json");
+        assert_eq!(load_state(path)?, None);
+        let state = TestDeployState {
+            deployment: "fedora-abc.0".into(),
+            previous: Some("fedora-def.0".into()),
+            restored: true,
+        };
+        store_state(path, Some(&state))?;
+        assert_eq!(load_state(path)?, Some(state));
+        store_state(path, None)?;
+        assert!(!path.exists());
+        store_state(path, None)?;
+        Ok(())
+    }
+}
@@ -59,6 +59,8 @@ enum Opt {
     CUnits,
     /// Test that we can 🐄
     Moo,
+    /// Benchmark core code paths against synthetic fixtures
+    Bench(crate::bench::BenchOpts),
 }
 
 /// Returns `true` if a file is ELF; see https://en.wikipedia.org/wiki/Executable_and_Linkable_Format
@@ -273,6 +275,7 @@ pub(crate) fn testutils_entrypoint(args: Vec<String>) -> CxxResult<()> {
         Opt::IntegrationReadOnly => integration_read_only()?,
         Opt::CUnits => crate::ffi::c_unit_tests()?,
         Opt::Moo => test_moo()?,
+        Opt::Bench(ref opts) => crate::bench::run(opts)?,
     };
     Ok(())
 }
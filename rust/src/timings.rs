@@ -0,0 +1,173 @@
+//! A database of how long operations of transactions took in the past, such as
+//! downloading or importing packages, so that progress output can show how long
+//! they'll likely take instead of just what is being done.  Durations are
+//! recorded along with the size of the operation (e.g. the number of packages),
+//! and scaled by it for estimates.  This is only enabled in the system daemon.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+const STATE_PATH: &str = "/var/lib/rpm-ostree/timings.json";
+/// The number of durations we keep per operation; older ones are dropped.
+const MAX_SAMPLES: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Sample {
+    size: u64,
+    millis: u64,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct TimingDb {
+    operations: BTreeMap<String, Vec<Sample>>,
+}
+
+/// The loaded database; `None` unless enabled.
+static TIMINGS: Lazy<Mutex<Option<TimingDb>>> = Lazy::new(|| Mutex::new(None));
+
+impl TimingDb {
+    fn load(path: &Path) -> Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(s) => {
+                serde_json::from_str(&s).with_context(|| format!("Parsing {}", path.display()))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("Reading {}", path.display())),
+        }
+    }
+
+    fn store(&self, path: &Path) -> Result<()> {
+        let buf = serde_json::to_vec(self)?;
+        crate::utils::write_file_atomic(path, buf)
+    }
+
+    fn record(&mut self, key: &str, size: u64, millis: u64) {
+        let samples = self.operations.entry(key.to_string()).or_default();
+        samples.push(Sample { size, millis });
+        if samples.len() > MAX_SAMPLES {
+            samples.drain(..samples.len() - MAX_SAMPLES);
+        }
+    }
+
+    /// The estimated duration in milliseconds of the operation `key` of `size`:
+    /// the median rate of earlier ones scaled by `size`, or if the size doesn't
+    /// apply, the median of their durations.
+    fn estimate(&self, key: &str, size: u64) -> Option<u64> {
+        let samples = self.operations.get(key)?;
+        if size > 0 {
+            let rates = samples
+                .iter()
+                .filter(|s| s.size > 0)
+                .map(|s| s.millis as f64 / s.size as f64)
+                .collect();
+            median(rates).map(|r| (r * size as f64) as u64)
+        } else {
+            median(samples.iter().map(|s| s.millis as f64).collect()).map(|m| m as u64)
+        }
+    }
+}
+
+fn median(mut v: Vec<f64>) -> Option<f64> {
+    if v.is_empty() {
+        return None;
+    }
+    v.sort_by(|a, b| a.total_cmp(b));
+    let mid = v.len() / 2;
+    if v.len() % 2 == 0 {
+        Some((v[mid - 1] + v[mid]) / 2.0)
+    } else {
+        Some(v[mid])
+    }
+}
+
+/// Format an estimate in seconds, e.g. `45s` or `3m 5s`.
+fn format_estimate(secs: u64) -> String {
+    match (secs / 60, secs % 60) {
+        (0, s) => format!("{}s", s),
+        (m, 0) => format!("{}m", m),
+        (m, s) => format!("{}m {}s", m, s),
+    }
+}
+
+/// Start recording durations, and using them for estimates.
+pub(crate) fn timings_enable() {
+    let db = TimingDb::load(Path::new(STATE_PATH)).unwrap_or_else(|e| {
+        tracing::warn!("{:#}", e);
+        TimingDb::default()
+    });
+    *TIMINGS.lock().unwrap() = Some(db);
+}
+
+/// Describe how long the operation `key` of `size` will likely take, e.g.
+/// `est. 45s`; empty if there's no estimate, or it's under a second.
+pub(crate) fn timings_describe(key: &str, size: u64) -> String {
+    let lock = TIMINGS.lock().unwrap();
+    lock.as_ref()
+        .and_then(|db| db.estimate(key, size))
+        .map(|millis| (millis + 500) / 1000)
+        .filter(|&secs| secs > 0)
+        .map(|secs| format!("est. {}", format_estimate(secs)))
+        .unwrap_or_default()
+}
+
+/// Record that the operation `key` of `size` took `millis`.
+pub(crate) fn timings_record(key: &str, size: u64, millis: u64) {
+    let mut lock = TIMINGS.lock().unwrap();
+    if let Some(db) = lock.as_mut() {
+        db.record(key, size, millis);
+        if let Err(e) = db.store(Path::new(STATE_PATH)) {
+            tracing::warn!("{:#}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate() {
+        let mut db = TimingDb::default();
+        assert_eq!(db.estimate("import", 10), None);
+        db.record("import", 10, 1000);
+        db.record("import", 20, 3000);
+        db.record("import", 10, 1200);
+        // The median rate is 120ms per package
+        assert_eq!(db.estimate("import", 100), Some(12000));
+        db.record("checkout", 0, 4000);
+        db.record("checkout", 0, 6000);
+        assert_eq!(db.estimate("checkout", 0), Some(5000));
+        assert_eq!(db.estimate("download", 0), None);
+
+        for i in 0..(MAX_SAMPLES as u64 + 5) {
+            db.record("checkout", 0, i);
+        }
+        assert_eq!(db.operations["checkout"].len(), MAX_SAMPLES);
+        assert_eq!(db.operations["checkout"][0].millis, 5);
+    }
+
+    #[test]
+    fn test_store() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        let path = &td.path().join("timings.json");
+        assert_eq!(TimingDb::load(path)?, TimingDb::default());
+        let mut db = TimingDb::default();
+        db.record("import", 10, 1000);
+        db.store(path)?;
+        assert_eq!(TimingDb::load(path)?, db);
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_estimate() {
+        assert_eq!(format_estimate(45), "45s");
+        assert_eq!(format_estimate(120), "2m");
+        assert_eq!(format_estimate(185), "3m 5s");
+    }
+}
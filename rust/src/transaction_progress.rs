@@ -0,0 +1,206 @@
+//! Structured progress of transactions: the daemon emits it as the `Progress`
+//! signal alongside the human-readable progress signals, and the client can
+//! write it as JSON lines to `--json-progress-fd` for use by other programs.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::cxxrsutil::*;
+use crate::ffi::ProgressEvent;
+use anyhow::Result;
+use glib::translate::*;
+use ostree_ext::glib;
+use serde_derive::Serialize;
+
+/// A progress update, as carried by the `Progress` signal and written to
+/// `--json-progress-fd`.  Clients should accept stages they don't know about.
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+struct ProgressRecord {
+    stage: String,
+    text: String,
+    items_done: Option<u64>,
+    items_total: Option<u64>,
+    bytes_done: Option<u64>,
+    bytes_total: Option<u64>,
+    percentage: Option<u32>,
+    /// Estimated time remaining
+    eta_seconds: Option<u64>,
+}
+
+fn eta(done: u64, total: u64, elapsed_secs: u64) -> Option<u64> {
+    (done > 0 && total > done && elapsed_secs > 0).then(|| elapsed_secs * (total - done) / done)
+}
+
+fn percentage(done: u64, total: u64) -> u32 {
+    (done.min(total) * 100 / total) as u32
+}
+
+impl ProgressRecord {
+    fn new(event: &ProgressEvent) -> Self {
+        let stage = if event.stage.is_empty() {
+            "other".to_string()
+        } else {
+            event.stage.clone()
+        };
+        let items = Some((event.items_done, event.items_total)).filter(|i| i.1 > 0);
+        let bytes_total = Some(event.bytes_total).filter(|&t| t > 0);
+        let bytes_done = Some(event.bytes_done).filter(|&d| d > 0 || bytes_total.is_some());
+        let (percentage, eta_seconds) = if let Some(total) = bytes_total {
+            (
+                Some(percentage(event.bytes_done, total)),
+                eta(event.bytes_done, total, event.elapsed_secs),
+            )
+        } else if let Some((done, total)) = items {
+            (
+                Some(percentage(done, total)),
+                eta(done, total, event.elapsed_secs),
+            )
+        } else if event.percentage >= 0 {
+            let p = event.percentage.min(100) as u64;
+            (Some(p as u32), eta(p, 100, event.elapsed_secs))
+        } else {
+            (None, None)
+        };
+        Self {
+            stage,
+            text: event.text.clone(),
+            items_done: items.map(|i| i.0),
+            items_total: items.map(|i| i.1),
+            bytes_done,
+            bytes_total,
+            percentage,
+            eta_seconds,
+        }
+    }
+
+    fn to_variant(&self) -> glib::Variant {
+        let dict = glib::VariantDict::new(None);
+        dict.insert("stage", &self.stage);
+        dict.insert("text", &self.text);
+        let counts = [
+            ("items-done", self.items_done),
+            ("items-total", self.items_total),
+            ("bytes-done", self.bytes_done),
+            ("bytes-total", self.bytes_total),
+            ("eta-seconds", self.eta_seconds),
+        ];
+        for (k, v) in counts {
+            if let Some(v) = v {
+                dict.insert(k, &v);
+            }
+        }
+        if let Some(p) = self.percentage {
+            dict.insert("percentage", &p);
+        }
+        dict.end()
+    }
+
+    fn from_variant(v: &glib::Variant) -> Result<Self> {
+        let dict = glib::VariantDict::new(Some(v));
+        let lookup_u64 = |k: &str| dict.lookup::<u64>(k).map_err(anyhow::Error::msg);
+        Ok(Self {
+            stage: dict
+                .lookup("stage")
+                .map_err(anyhow::Error::msg)?
+                .unwrap_or_default(),
+            text: dict
+                .lookup("text")
+                .map_err(anyhow::Error::msg)?
+                .unwrap_or_default(),
+            items_done: lookup_u64("items-done")?,
+            items_total: lookup_u64("items-total")?,
+            bytes_done: lookup_u64("bytes-done")?,
+            bytes_total: lookup_u64("bytes-total")?,
+            percentage: dict.lookup("percentage").map_err(anyhow::Error::msg)?,
+            eta_seconds: lookup_u64("eta-seconds")?,
+        })
+    }
+}
+
+/// Build the parameter of the `Progress` signal for `event`.
+pub(crate) fn progress_event_to_variant(event: &ProgressEvent) -> *mut crate::FFIGVariant {
+    let v = ProgressRecord::new(event).to_variant();
+    v.to_glib_full() as *mut _
+}
+
+/// Render the parameter of the `Progress` signal as a line of JSON.
+pub(crate) fn client_render_progress_json(progress: &crate::FFIGVariant) -> CxxResult<String> {
+    let progress = progress.glib_reborrow();
+    let record = ProgressRecord::from_variant(&progress.child_value(0))?;
+    Ok(serde_json::to_string(&record)? + "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(text: &str) -> ProgressEvent {
+        ProgressEvent {
+            stage: String::new(),
+            text: text.to_string(),
+            items_done: 0,
+            items_total: 0,
+            bytes_done: 0,
+            bytes_total: 0,
+            percentage: -1,
+            elapsed_secs: 0,
+        }
+    }
+
+    #[test]
+    fn test_progress_record() {
+        let r = ProgressRecord::new(&event("Computing /etc diff"));
+        assert_eq!(r.stage, "other");
+        assert_eq!(r.percentage, None);
+        assert_eq!(r.eta_seconds, None);
+
+        let r = ProgressRecord::new(&ProgressEvent {
+            stage: "assemble".into(),
+            items_done: 25,
+            items_total: 100,
+            elapsed_secs: 10,
+            ..event("Checking out packages")
+        });
+        assert_eq!(r.stage, "assemble");
+        assert_eq!((r.items_done, r.items_total), (Some(25), Some(100)));
+        assert_eq!(r.bytes_done, None);
+        assert_eq!(r.percentage, Some(25));
+        assert_eq!(r.eta_seconds, Some(30));
+
+        // Bytes take precedence for the percentage and ETA
+        let r = ProgressRecord::new(&ProgressEvent {
+            stage: "download".into(),
+            items_done: 1,
+            items_total: 10,
+            bytes_done: 300,
+            bytes_total: 400,
+            elapsed_secs: 6,
+            ..event("Receiving delta parts")
+        });
+        assert_eq!(r.percentage, Some(75));
+        assert_eq!(r.eta_seconds, Some(2));
+
+        let r = ProgressRecord::new(&ProgressEvent {
+            percentage: 40,
+            ..event("Generating initramfs")
+        });
+        assert_eq!(r.percentage, Some(40));
+        assert_eq!(r.items_total, None);
+    }
+
+    #[test]
+    fn test_progress_variant_roundtrip() -> Result<()> {
+        let r = ProgressRecord::new(&ProgressEvent {
+            bytes_done: 1024,
+            elapsed_secs: 3,
+            ..event("Receiving objects")
+        });
+        assert_eq!(r.bytes_total, None);
+        assert_eq!(ProgressRecord::from_variant(&r.to_variant())?, r);
+        let json: serde_json::Value = serde_json::to_value(&r)?;
+        assert_eq!(json["stage"], "download");
+        assert_eq!(json["bytes-done"], 1024);
+        assert_eq!(json["items-total"], serde_json::Value::Null);
+        Ok(())
+    }
+}
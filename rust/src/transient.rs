@@ -0,0 +1,148 @@
+//! Automatically reset package requests made with `install --transient-boots`.
+//!
+//! The booted deployment's origin records, for each transient package, the
+//! number of boots it should stay layered for.  This is run once per boot
+//! (see `rpm-ostree-transient-reset.service`); it records the current boot
+//! for each transient package and asks the daemon to uninstall the ones
+//! which used up their budget.  The removal then takes effect on the next boot.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use anyhow::{anyhow, Context, Result};
+use cap_std::fs::Dir;
+use cap_std_ext::cap_std;
+use cap_std_ext::dirext::CapStdExtDirExt;
+use glib::prelude::*;
+use ostree_ext::{gio, glib, ostree};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Read;
+
+/// State directory, shared with other daemon state.
+const STATE_DIR: &str = "/var/lib/rpm-ostree";
+/// File recording the boots observed for each transient package.
+const STATE_FILE: &str = "transient-boots.json";
+/// The kernel-provided identifier for the current boot.
+const BOOT_ID_PATH: &str = "/proc/sys/kernel/random/boot_id";
+
+/// The boots we have seen for each transient package request.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+struct TransientState {
+    boots: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl TransientState {
+    /// Record `boot_id` for each of the `transient` packages (a mapping from
+    /// package to number of boots), forget packages which are no longer
+    /// transient, and return the packages whose budget is used up.
+    fn record_boot(&mut self, transient: &BTreeMap<String, u32>, boot_id: &str) -> Vec<String> {
+        self.boots.retain(|pkg, _| transient.contains_key(pkg));
+        let mut expired = Vec::new();
+        for (pkg, &n_boots) in transient.iter() {
+            let seen = self.boots.entry(pkg.clone()).or_default();
+            seen.insert(boot_id.to_string());
+            if seen.len() >= n_boots as usize {
+                expired.push(pkg.clone());
+            }
+        }
+        expired
+    }
+}
+
+fn load_state(statedir: &Dir) -> Result<TransientState> {
+    let mut content = String::new();
+    match statedir.open_optional(STATE_FILE)? {
+        Some(mut f) => f.read_to_string(&mut content)?,
+        None => return Ok(Default::default()),
+    };
+    match serde_json::from_str(&content) {
+        Ok(s) => Ok(s),
+        Err(e) => {
+            eprintln!("Ignoring invalid {}: {}", STATE_FILE, e);
+            Ok(Default::default())
+        }
+    }
+}
+
+/// Ask the daemon to uninstall the given packages from the default deployment.
+fn reset_packages(pkgs: &[String]) -> Result<()> {
+    let client = &mut crate::client::ClientConnection::new()?;
+    let modifiers = glib::VariantDict::new(None);
+    modifiers.insert_value("uninstall-packages", &pkgs.to_variant());
+    let options = glib::VariantDict::new(None);
+    options.insert("no-pull-base", &true);
+    options.insert("cache-only", &true);
+    options.insert("idempotent-layering", &true);
+    options.insert("initiating-command-line", &"rpm-ostree transient-reset");
+    let params = glib::Variant::from_tuple(&[modifiers.end(), options.end()]);
+    let reply = &client.get_os_proxy().call_sync(
+        "UpdateDeployment",
+        Some(&params),
+        gio::DBusCallFlags::NONE,
+        -1,
+        gio::NONE_CANCELLABLE,
+    )?;
+    let reply = reply
+        .get::<(String,)>()
+        .ok_or_else(|| anyhow!("Invalid reply"))?;
+    client.transaction_connect_progress_sync(reply.0.as_str())
+}
+
+/// Main entrypoint, run once per boot.
+pub fn entrypoint(_args: &[&str]) -> Result<()> {
+    let sysroot = &ostree::Sysroot::new_default();
+    sysroot.load(gio::NONE_CANCELLABLE)?;
+    let booted = sysroot.require_booted_deployment()?;
+    let origin = booted
+        .origin()
+        .ok_or_else(|| anyhow!("No origin for booted deployment"))?;
+    let tf = crate::origin::origin_to_treefile_inner(&origin)?;
+    let transient = tf.get_packages_transient();
+
+    let boot_id = std::fs::read_to_string(BOOT_ID_PATH)
+        .with_context(|| format!("Reading {}", BOOT_ID_PATH))?;
+    let statedir = Dir::open_ambient_dir(STATE_DIR, cap_std::ambient_authority())?;
+    let mut state = load_state(&statedir)?;
+    let expired = state.record_boot(&transient, boot_id.trim());
+    statedir.atomic_replace_with(STATE_FILE, |w| -> Result<_> {
+        Ok(serde_json::to_writer(w, &state)?)
+    })?;
+
+    if expired.is_empty() {
+        return Ok(());
+    }
+    println!(
+        "Resetting expired transient packages: {}",
+        expired.join(", ")
+    );
+    reset_packages(&expired)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_boot() {
+        let mut state = TransientState::default();
+        let transient = maplit::btreemap! {
+            "strace".to_string() => 2,
+            "gdb".to_string() => 1,
+        };
+        assert_eq!(state.record_boot(&transient, "boot1"), vec!["gdb"]);
+        // Recording the same boot twice doesn't count it twice
+        assert_eq!(state.record_boot(&transient, "boot1"), vec!["gdb"]);
+        assert_eq!(
+            state.record_boot(&transient, "boot2"),
+            vec!["gdb", "strace"]
+        );
+        // Packages which are no longer transient are forgotten
+        let transient = maplit::btreemap! { "strace".to_string() => 2 };
+        state.record_boot(&transient, "boot3");
+        assert_eq!(state.boots.len(), 1);
+        assert!(state.boots.contains_key("strace"));
+        let transient = BTreeMap::new();
+        assert!(state.record_boot(&transient, "boot4").is_empty());
+        assert!(state.boots.is_empty());
+    }
+}
@@ -11,32 +11,21 @@
 //! file is used for that.  See also
 //! [this issue](https://github.com/coreos/rpm-ostree/issues/2326).
 
-/*
- * HACKING: In order to add a entry to the treefile, be sure to:
- * - Update docs/treefile.md
- * - Add it to the struct
- * - Add a merge entry to `treefile_merge()`
- * - Add a test case in tests/compose
- */
+// The treefile types themselves live in the rpmostree-treefile crate
+// (rust/treefile), see the HACKING note there for adding new entries.
 
 use crate::cxxrsutil::*;
 use anyhow::{anyhow, bail, Context, Result};
 use camino::{Utf8Path, Utf8PathBuf};
 use cap_std_ext::cap_std::fs::Dir;
 use cap_std_ext::prelude::CapStdExtDirExt;
-use nix::unistd::{Gid, Uid};
-use once_cell::sync::Lazy;
 use ostree_ext::{glib, ostree};
-use regex::Regex;
-use serde_derive::{Deserialize, Serialize};
-use std::collections::btree_map::Entry;
-use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs::{read_dir, File};
 use std::io::prelude::*;
-use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::os::unix::fs::PermissionsExt;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::path::{Path, PathBuf};
-use std::str::FromStr;
 use std::{fs, io};
 use tracing::{event, instrument, Level};
 
@@ -45,8 +34,6 @@ use crate::importer::RpmImporterFlags;
 use crate::utils;
 use crate::utils::OptionExtGetOrInsertDefault;
 
-const INCLUDE_MAXDEPTH: u32 = 50;
-
 /// Path to the flattened JSON serialization of the treefile, installed on the target (client)
 /// filesystem.  Nothing actually parses this by default client side today,
 /// it's intended to be informative.
@@ -63,6 +50,7 @@ pub(crate) struct TreefileExternals {
     add_files: BTreeMap<String, fs::File>,
     passwd: Option<fs::File>,
     group: Option<fs::File>,
+    pinned_ids: Option<fs::File>,
 }
 
 // This type name is exposed through ffi.
@@ -79,168 +67,6 @@ struct ConfigAndExternals {
     externals: TreefileExternals,
 }
 
-/// Parse a YAML treefile definition using base architecture `basearch`.
-/// This does not open the externals.
-fn treefile_parse_stream<R: io::Read>(
-    fmt: utils::InputFormat,
-    input: &mut R,
-    basearch: Option<&str>,
-) -> Result<TreeComposeConfig> {
-    let mut treefile: TreeComposeConfig = utils::parse_stream(&fmt, input)?;
-
-    treefile.base.basearch = match (treefile.base.basearch, basearch) {
-        (Some(treearch), Some(arch)) => {
-            if treearch != arch {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    format!(
-                        "Invalid basearch {} on {}: cross-composes are not supported",
-                        treearch, arch
-                    ),
-                )
-                .into());
-            } else {
-                Some(treearch)
-            }
-        }
-        (None, Some(arch)) => Some(arch.into()),
-        // really, only for tests do we not specify a basearch. let's just canonicalize to None
-        (_, None) => None,
-    };
-
-    if let Some(ref vars) = treefile.base.variables {
-        for reserved in ["releasever", "basearch"] {
-            if vars.contains_key(reserved) {
-                bail!("cannot define reserved variable '{}'", reserved);
-            }
-        }
-    }
-
-    // auto-mirror basearch and releasever to variables
-    if let Some(ref mut basearch) = treefile.base.basearch {
-        treefile
-            .base
-            .variables
-            .get_or_insert_with(BTreeMap::new)
-            .insert("basearch".into(), VarValue::String(basearch.clone()));
-    }
-    if let Some(ref mut releasever) = treefile.base.releasever {
-        treefile
-            .base
-            .variables
-            .get_or_insert_with(BTreeMap::new)
-            .insert("releasever".into(), releasever.clone().into());
-    }
-
-    // remove from packages-${arch} keys from the extra keys
-    let mut archful_pkgs: Option<BTreeSet<String>> = take_archful_pkgs(basearch, &mut treefile)?;
-
-    if fmt == utils::InputFormat::YAML && !treefile.base.extra.is_empty() {
-        let keys: Vec<&str> = treefile.base.extra.keys().map(|k| k.as_str()).collect();
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidInput,
-            format!("Unknown fields: {}", keys.join(", ")),
-        )
-        .into());
-    }
-
-    // Special handling for packages, since we allow whitespace within items.
-    // We also canonicalize bootstrap_packages to packages here so it's
-    // easier to append the basearch packages after.
-    let pkgs = {
-        let mut pkgs: BTreeSet<String> = BTreeSet::new();
-        if let Some(base_pkgs) = treefile.packages.take() {
-            pkgs.append(&mut whitespace_split_packages(&base_pkgs)?);
-        }
-        if let Some(bootstrap_pkgs) = treefile.base.bootstrap_packages.take() {
-            pkgs.append(&mut whitespace_split_packages(&bootstrap_pkgs)?);
-        }
-        if let Some(archful_pkgs) = archful_pkgs.take() {
-            pkgs.append(&mut whitespace_split_packages(&archful_pkgs)?);
-        }
-        pkgs
-    };
-
-    // to be consistent, we also support whitespace-separated modules
-    if let Some(mut modules) = treefile.modules.take() {
-        if let Some(enable) = modules.enable.take() {
-            modules.enable = Some(whitespace_split_packages(&enable)?);
-        }
-        if let Some(install) = modules.install.take() {
-            modules.install = Some(whitespace_split_packages(&install)?);
-        }
-        treefile.modules = Some(modules);
-    }
-
-    // Whitespace split repo packages
-    if let Some(repo_packages) = treefile.repo_packages.as_mut() {
-        for rp in repo_packages {
-            rp.packages = whitespace_split_packages(&rp.packages)?;
-        }
-    }
-    if let Some(repo_packages) = treefile.derive.override_replace.as_mut() {
-        for rp in repo_packages {
-            rp.packages = whitespace_split_packages(&rp.packages)?;
-        }
-    }
-
-    treefile.packages = Some(pkgs);
-    treefile = treefile.migrate_legacy_fields()?;
-    Ok(treefile)
-}
-
-/// Sanity checks that the packages-${basearch} entries are well-formed, and returns the ones
-/// matching the current basearch.
-fn take_archful_pkgs(
-    basearch: Option<&str>,
-    treefile: &mut TreeComposeConfig,
-) -> Result<Option<BTreeSet<String>>> {
-    let mut archful_pkgs: Option<BTreeSet<String>> = None;
-
-    for key in treefile
-        .base
-        .extra
-        .keys()
-        .filter(|k| k.starts_with("packages-"))
-    {
-        if !treefile.base.extra[key].is_array()
-            || treefile.base.extra[key]
-                .as_array()
-                .unwrap()
-                .iter()
-                .any(|v| !v.is_string())
-        {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                format!("Invalid field {}: expected array of strings", key),
-            )
-            .into());
-        }
-
-        if let Some(basearch) = basearch {
-            if basearch == &key["packages-".len()..] {
-                assert!(archful_pkgs == None);
-                archful_pkgs = Some(
-                    treefile.base.extra[key]
-                        .as_array()
-                        .unwrap()
-                        .iter()
-                        .map(|v| v.as_str().unwrap().into())
-                        .collect(),
-                );
-            }
-        }
-    }
-
-    // and drop it from the map
-    treefile
-        .base
-        .extra
-        .retain(|k, _| !k.starts_with("packages-"));
-
-    Ok(archful_pkgs)
-}
-
 /// If a passwd/group file is provided explicitly, load it as a fd.
 fn load_passwd_file<P: AsRef<Path>>(basedir: P, cfg: &CheckFile) -> Result<Option<fs::File>> {
     let basedir = basedir.as_ref();
@@ -248,36 +74,9 @@ fn load_passwd_file<P: AsRef<Path>>(basedir: P, cfg: &CheckFile) -> Result<Optio
     Ok(Some(file))
 }
 
-type IncludeMap = BTreeMap<(u64, u64), String>;
-
-/// Given a treefile filename and an architecture, parse it and also
-/// open its external files.
-fn treefile_parse<P: AsRef<Path>>(
-    filename: P,
-    basearch: Option<&str>,
-    seen_includes: &mut IncludeMap,
-) -> Result<ConfigAndExternals> {
-    let filename = filename.as_ref();
-    let f = utils::open_file(filename)?;
-    let meta = f.metadata()?;
-    let devino = (meta.dev(), meta.ino());
-    match seen_includes.entry(devino) {
-        Entry::Occupied(_) => bail!(
-            "Include loop detected; {} was already included",
-            filename.to_str().unwrap()
-        ),
-        Entry::Vacant(e) => {
-            e.insert(filename.to_str().unwrap().to_string());
-        }
-    };
-    let mut f = io::BufReader::new(f);
-    let fmt = utils::InputFormat::detect_from_filename(filename)?;
-    let tf = treefile_parse_stream(fmt, &mut f, basearch).map_err(|e| {
-        io::Error::new(
-            io::ErrorKind::InvalidInput,
-            format!("Parsing {}: {}", filename.to_string_lossy(), e.to_string()),
-        )
-    })?;
+/// Open the external files referenced by the treefile `filename` (but not by
+/// the files it includes), whose content is `tf`.
+fn treefile_open_externals(filename: &Path, tf: &TreeComposeConfig) -> Result<TreefileExternals> {
     let postprocess_script = if let Some(ref postprocess) = tf.base.postprocess_script.as_ref() {
         Some(utils::open_file(filename.with_file_name(postprocess))?)
     } else {
@@ -294,198 +93,26 @@ fn treefile_parse<P: AsRef<Path>>(
     }
     let parent = utils::parent_dir(filename).unwrap();
     let passwd = match tf.get_check_passwd() {
-        CheckPasswd::File(ref f) => load_passwd_file(&parent, f)?,
+        CheckPasswd::File(ref f) | CheckPasswd::Snapshot(ref f) => load_passwd_file(&parent, f)?,
         _ => None,
     };
     let group = match tf.get_check_groups() {
-        CheckGroups::File(ref f) => load_passwd_file(&parent, f)?,
+        CheckGroups::File(ref f) | CheckGroups::Snapshot(ref f) => load_passwd_file(&parent, f)?,
         _ => None,
     };
+    let pinned_ids = if let Some(ref name) = tf.base.pinned_ids.as_ref() {
+        Some(utils::open_file(parent.join(name))?)
+    } else {
+        None
+    };
 
-    Ok(ConfigAndExternals {
-        config: tf,
-        externals: TreefileExternals {
-            postprocess_script,
-            add_files,
-            passwd,
-            group,
-        },
-    })
-}
-
-/// Merge a "basic" or non-array field. The semantics originally defined for
-/// these are that first-one wins.
-fn merge_basic_field<T>(dest: &mut Option<T>, src: &mut Option<T>) {
-    if dest.is_some() {
-        return;
-    }
-    *dest = src.take()
-}
-
-/// Merge a vector field by appending. This semantic was originally designed for
-/// the `packages` key.
-fn merge_vec_field<T>(dest: &mut Option<Vec<T>>, src: &mut Option<Vec<T>>) {
-    if let Some(mut srcv) = src.take() {
-        if let Some(ref mut destv) = dest {
-            srcv.append(destv);
-        }
-        *dest = Some(srcv);
-    }
-}
-
-/// Merge a map field similarly to Python's `dict.update()`. In case of
-/// duplicate keys, `dest` wins (`src` is the "included" config).
-fn merge_map_field<T>(
-    dest: &mut Option<BTreeMap<String, T>>,
-    src: &mut Option<BTreeMap<String, T>>,
-) {
-    if let Some(mut srcv) = src.take() {
-        if let Some(mut destv) = dest.take() {
-            srcv.append(&mut destv);
-        }
-        *dest = Some(srcv);
-    }
-}
-
-/// Merge an hashset field by extending.
-fn merge_hashset_field<T: Eq + std::hash::Hash + std::cmp::Ord>(
-    dest: &mut Option<BTreeSet<T>>,
-    src: &mut Option<BTreeSet<T>>,
-) {
-    if let Some(mut srcv) = src.take() {
-        if let Some(mut destv) = dest.take() {
-            srcv.append(&mut destv);
-        }
-        *dest = Some(srcv);
-    }
-}
-
-/// Merge modules fields.
-pub(crate) fn merge_modules(dest: &mut Option<ModulesConfig>, src: &mut Option<ModulesConfig>) {
-    if let Some(mut srcv) = src.take() {
-        if let Some(mut destv) = dest.take() {
-            merge_hashset_field(&mut destv.enable, &mut srcv.enable);
-            merge_hashset_field(&mut destv.install, &mut srcv.install);
-            srcv = destv;
-        }
-        *dest = Some(srcv);
-    }
-}
-
-/// Given two configs, merge them.
-fn treefile_merge(dest: &mut TreeComposeConfig, src: &mut TreeComposeConfig) {
-    macro_rules! merge_basics {
-        ( $($field:ident),* ) => {{
-            $( merge_basic_field(&mut dest.base.$field, &mut src.base.$field); )*
-        }};
-    }
-    macro_rules! merge_hashsets {
-        ( $($field:ident),* ) => {{
-            $( merge_hashset_field(&mut dest.base.$field, &mut src.base.$field); )*
-        }};
-    }
-    macro_rules! merge_maps {
-        ( $($field:ident),* ) => {{
-            $( merge_map_field(&mut dest.base.$field, &mut src.base.$field); )*
-        }};
-    }
-    macro_rules! merge_vecs {
-        ( $($field:ident),* ) => {{
-            $( merge_vec_field(&mut dest.base.$field, &mut src.base.$field); )*
-        }};
-    }
-
-    merge_basics!(
-        treeref,
-        basearch,
-        rojig,
-        selinux,
-        ima,
-        gpg_key,
-        include,
-        container,
-        recommends,
-        readonly_executables,
-        container_cmd,
-        documentation,
-        boot_location,
-        tmp_is_dir,
-        default_target,
-        machineid_compat,
-        releasever,
-        automatic_version_prefix,
-        automatic_version_suffix,
-        rpmdb,
-        mutate_os_release,
-        preserve_passwd,
-        check_passwd,
-        check_groups,
+    Ok(TreefileExternals {
         postprocess_script,
-        rpmdb_normalize
-    );
-    merge_hashsets!(ignore_removed_groups, ignore_removed_users);
-    merge_maps!(add_commit_metadata, variables);
-    merge_vecs!(
-        repos,
-        lockfile_repos,
-        exclude_packages,
-        ostree_layers,
-        ostree_override_layers,
-        install_langs,
-        initramfs_args,
-        units,
-        etc_group_members,
-        postprocess,
         add_files,
-        remove_files,
-        remove_from_packages
-    );
-
-    merge_hashset_field(&mut dest.bootstrap_packages, &mut src.bootstrap_packages);
-    merge_hashset_field(&mut dest.packages, &mut src.packages);
-    merge_vec_field(&mut dest.repo_packages, &mut src.repo_packages);
-    dest.handle_repo_packages_overrides();
-    merge_basic_field(&mut dest.cliwrap, &mut src.cliwrap);
-    merge_modules(&mut dest.modules, &mut src.modules);
-
-    merge_basic_field(&mut dest.derive.base_refspec, &mut src.derive.base_refspec);
-    merge_basic_field(
-        &mut dest.derive.container_image_reference,
-        &mut src.derive.container_image_reference,
-    );
-    merge_map_field(
-        &mut dest.derive.packages_local,
-        &mut src.derive.packages_local,
-    );
-    merge_map_field(
-        &mut dest.derive.packages_local_fileoverride,
-        &mut src.derive.packages_local_fileoverride,
-    );
-    merge_hashset_field(
-        &mut dest.derive.override_remove,
-        &mut src.derive.override_remove,
-    );
-    merge_vec_field(
-        &mut dest.derive.override_replace,
-        &mut src.derive.override_replace,
-    );
-    dest.handle_repo_packages_override_replacements();
-    merge_map_field(
-        &mut dest.derive.override_replace_local,
-        &mut src.derive.override_replace_local,
-    );
-    // XXX: should merge args
-    merge_basic_field(&mut dest.derive.initramfs, &mut src.derive.initramfs);
-    // no fancy merging for this
-    merge_basic_field(&mut dest.derive.custom, &mut src.derive.custom);
-    merge_basic_field(
-        &mut dest.derive.override_commit,
-        &mut src.derive.override_commit,
-    );
-    merge_basic_field(
-        &mut dest.derive.unconfigured_state,
-        &mut src.derive.unconfigured_state,
-    );
+        passwd,
+        group,
+        pinned_ids,
+    })
 }
 
 /// Merge the treefile externals. There are currently only two keys that
@@ -506,77 +133,9 @@ fn treefile_merge_externals(dest: &mut TreefileExternals, src: &mut TreefileExte
     if dest.group.is_none() {
         dest.group = src.group.take();
     }
-}
-
-/// Recursively parse a treefile, merging along the way.
-fn treefile_parse_recurse<P: AsRef<Path>>(
-    filename: P,
-    basearch: Option<&str>,
-    depth: u32,
-    seen_includes: &mut IncludeMap,
-    variables: &mut BTreeMap<String, VarValue>,
-) -> Result<ConfigAndExternals> {
-    let filename = filename.as_ref();
-    let mut parsed = treefile_parse(filename, basearch, seen_includes)?;
-    let include = parsed
-        .config
-        .base
-        .include
-        .take()
-        .unwrap_or_else(|| Include::Multiple(Vec::new()));
-    let mut includes = match include {
-        Include::Single(v) => vec![v],
-        Include::Multiple(v) => v,
-    };
-    // fold in all new variables
-    if let Some(mut new_vars) = parsed.config.base.variables.take() {
-        new_vars.append(variables);
-        *variables = new_vars;
-    }
-    if let Some(mut arch_includes) = parsed.config.base.arch_include.take() {
-        if let Some(basearch) = basearch {
-            if let Some(arch_include_value) = arch_includes.remove(basearch) {
-                match arch_include_value {
-                    Include::Single(v) => includes.push(v),
-                    Include::Multiple(v) => includes.extend(v),
-                }
-            }
-        }
+    if dest.pinned_ids.is_none() {
+        dest.pinned_ids = src.pinned_ids.take();
     }
-    if let Some(conditional_includes) = parsed.config.base.conditional_include.take() {
-        for conditional_include in conditional_includes {
-            let matches = match conditional_include.condition {
-                IncludeConditions::Single(c) => c.evaluate(variables)?,
-                IncludeConditions::Multiple(v) => v
-                    .iter()
-                    // note we always evaluate because we always want to report semantic errors
-                    // like type mismatches, even if the condition already would've evaluated false
-                    .try_fold(true, |prev, c| c.evaluate(variables).map(|b| prev && b))?,
-            };
-            if matches {
-                match conditional_include.include {
-                    Include::Single(v) => includes.push(v),
-                    Include::Multiple(v) => includes.extend(v),
-                }
-            }
-        }
-    }
-    for include_path in includes.iter() {
-        if depth == INCLUDE_MAXDEPTH {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                format!("Reached maximum include depth {}", INCLUDE_MAXDEPTH),
-            )
-            .into());
-        }
-        let parent = utils::parent_dir(filename).unwrap();
-        let include_path = parent.join(include_path);
-        let mut included =
-            treefile_parse_recurse(include_path, basearch, depth + 1, seen_includes, variables)?;
-        treefile_merge(&mut parsed.config, &mut included.config);
-        treefile_merge_externals(&mut parsed.externals, &mut included.externals);
-    }
-    Ok(parsed)
 }
 
 /// Recursively parse a treefile, merging along the way, and finally postprocess it (e.g. collapse
@@ -585,29 +144,14 @@ fn treefile_parse_and_process<P: AsRef<Path>>(
     filename: P,
     basearch: Option<&str>,
 ) -> Result<ConfigAndExternals> {
-    let mut seen_includes = BTreeMap::new();
-    let mut variables = BTreeMap::new();
-    let mut parsed =
-        treefile_parse_recurse(filename, basearch, 0, &mut seen_includes, &mut variables)?;
+    let mut externals = TreefileExternals::default();
+    let config = rpmostree_treefile::parse_path_with(filename, basearch, |filename, tf| {
+        let mut new = treefile_open_externals(filename, tf)?;
+        treefile_merge_externals(&mut externals, &mut new);
+        Ok(())
+    })?;
     event!(Level::DEBUG, "parsed successfully");
-    if !variables.is_empty() {
-        parsed.config.base.variables = Some(variables);
-    }
-    parsed.config = parsed.config.substitute_vars()?;
-    Treefile::validate_config(&parsed.config)?;
-    Ok(parsed)
-}
-
-// Similar to the importer check but just checks for prefixes since
-// they're files, and also allows /etc since it's before conversion
-fn add_files_path_is_valid(path: &str) -> bool {
-    let path = path.trim_start_matches('/');
-    (path.starts_with("usr/") && !path.starts_with("usr/local/"))
-        || path.starts_with("etc/")
-        || path.starts_with("bin/")
-        || path.starts_with("sbin/")
-        || path.starts_with("lib/")
-        || path.starts_with("lib64/")
+    Ok(ConfigAndExternals { config, externals })
 }
 
 impl Treefile {
@@ -627,7 +171,7 @@ impl Treefile {
     }
 
     pub(crate) fn new_from_string(fmt: utils::InputFormat, buf: &str) -> Result<Box<Self>> {
-        let mut treefile = treefile_parse_stream(fmt, &mut buf.as_bytes(), None)?;
+        let mut treefile = rpmostree_treefile::parse_stream(fmt, &mut buf.as_bytes(), None)?;
         treefile = treefile.substitute_vars()?;
         Ok(Box::new(Treefile {
             directory: None,
@@ -784,6 +328,45 @@ impl Treefile {
         Ok(set.len() != n)
     }
 
+    /// Mark already requested packages as transient; they will be reset after
+    /// the given number of boots.
+    pub(crate) fn set_packages_transient(
+        &mut self,
+        packages: Vec<String>,
+        boots: u32,
+    ) -> Result<bool> {
+        if boots == 0 {
+            bail!("Number of transient boots must be greater than zero");
+        }
+        let requested = self.parsed.packages.as_ref();
+        if let Some(pkg) = packages
+            .iter()
+            .find(|p| !requested.map(|r| r.contains(*p)).unwrap_or_default())
+        {
+            bail!("Package '{}' is not currently requested", pkg);
+        }
+        let map = self
+            .parsed
+            .derive
+            .packages_transient
+            .ext_get_or_insert_default();
+        let mut changed = false;
+        for pkg in packages {
+            changed |= map.insert(pkg, boots) != Some(boots);
+        }
+        Ok(changed)
+    }
+
+    /// Returns the transient package requests, mapped to the number of boots
+    /// after which they are reset.
+    pub(crate) fn get_packages_transient(&self) -> BTreeMap<String, u32> {
+        self.parsed
+            .derive
+            .packages_transient
+            .clone()
+            .unwrap_or_default()
+    }
+
     pub(crate) fn get_local_packages(&self) -> Vec<String> {
         self.parsed
             .derive
@@ -817,6 +400,19 @@ impl Treefile {
         add_sha256_nevra_to_map(map, packages)
     }
 
+    /// Record whether the signature of the local package `nevra` was verified when it was
+    /// imported.
+    pub(crate) fn set_local_package_verified(&mut self, nevra: &str, verified: bool) {
+        let unverified = &mut self.parsed.derive.packages_local_unverified;
+        if verified {
+            if let Some(set) = unverified.as_mut() {
+                set.remove(nevra);
+            }
+        } else {
+            unverified.ext_get_or_insert_default().insert(nevra.into());
+        }
+    }
+
     pub(crate) fn get_local_fileoverride_packages(&self) -> Vec<String> {
         self.parsed
             .derive
@@ -868,6 +464,9 @@ impl Treefile {
                 .unwrap_or_default()
             {
                 changed = true;
+                if let Some(m) = self.parsed.derive.packages_transient.as_mut() {
+                    m.remove(&package);
+                }
             } else if self
                 .parsed
                 .derive
@@ -964,8 +563,34 @@ impl Treefile {
             .collect()
     }
 
-    pub(crate) fn add_modules(&mut self, modules: Vec<String>, enable_only: bool) -> bool {
+    /// Add module specs to be enabled or installed.  It is an error to request
+    /// a stream of a module for which a different stream is already requested;
+    /// use `switch_module_streams()` for that.
+    pub(crate) fn add_modules(&mut self, modules: Vec<String>, enable_only: bool) -> Result<bool> {
         let modules_cfg = self.parsed.modules.ext_get_or_insert_default();
+        let existing = modules_cfg
+            .enable
+            .iter()
+            .chain(modules_cfg.install.iter())
+            .flatten();
+        for existing in existing {
+            let (existing_name, existing_stream) = module_spec_name_stream(existing);
+            for module in modules.iter() {
+                let (name, stream) = module_spec_name_stream(module);
+                if name != existing_name {
+                    continue;
+                }
+                if let (Some(stream), Some(existing_stream)) = (stream, existing_stream) {
+                    if stream != existing_stream {
+                        bail!(
+                            "Module '{}' conflicts with requested '{}'; use `module switch-to` to change streams",
+                            module,
+                            existing
+                        );
+                    }
+                }
+            }
+        }
         let map = if enable_only {
             modules_cfg.enable.ext_get_or_insert_default()
         } else {
@@ -973,7 +598,7 @@ impl Treefile {
         };
         let n = map.len();
         map.extend(modules);
-        n != map.len()
+        Ok(n != map.len())
     }
 
     pub(crate) fn remove_modules(&mut self, modules: Vec<String>, enable_only: bool) -> bool {
@@ -989,6 +614,98 @@ impl Treefile {
         n != map.len()
     }
 
+    /// Disable module streams, i.e. stop enabling them.  This fails if packages
+    /// from the module are still layered via an installed profile, since
+    /// disabling the stream would leave those packages without a source.
+    pub(crate) fn disable_modules(&mut self, modules: Vec<String>) -> Result<bool> {
+        for module in modules.iter() {
+            let (name, stream) = module_spec_name_stream(module);
+            let installed = self.get_modules_install().into_iter().find(|installed| {
+                let (installed_name, installed_stream) = module_spec_name_stream(installed);
+                installed_name == name && (stream.is_none() || installed_stream == stream)
+            });
+            if let Some(installed) = installed {
+                bail!(
+                    "Cannot disable module '{}': packages are layered from '{}'; use `module uninstall` or `module reset` first",
+                    module,
+                    installed
+                );
+            }
+        }
+        Ok(self.remove_modules(modules, true))
+    }
+
+    /// Reset modules to their default state by dropping all enable and install
+    /// requests for them, regardless of stream or profile.  Modules are specified
+    /// by name only.
+    pub(crate) fn reset_modules(&mut self, names: Vec<String>) -> Result<bool> {
+        let modules_cfg = self.parsed.modules.ext_get_or_insert_default();
+        let mut changed = false;
+        for name in names.iter() {
+            if name.contains(':') || name.contains('/') {
+                bail!(
+                    "Invalid module name '{}': expected a name without stream or profile",
+                    name
+                );
+            }
+            let mut found = false;
+            for map in [&mut modules_cfg.enable, &mut modules_cfg.install]
+                .into_iter()
+                .flatten()
+            {
+                let n = map.len();
+                map.retain(|module| module_spec_name_stream(module).0 != name);
+                found |= n != map.len();
+            }
+            if !found {
+                bail!("Module '{}' is not enabled or installed", name);
+            }
+            changed = true;
+        }
+        Ok(changed)
+    }
+
+    /// Switch already requested modules to a different stream.  Each argument
+    /// must be of the form `NAME:STREAM`; the profiles of installed modules are
+    /// carried over to the new stream.
+    pub(crate) fn switch_module_streams(&mut self, modules: Vec<String>) -> Result<bool> {
+        let modules_cfg = self.parsed.modules.ext_get_or_insert_default();
+        let mut changed = false;
+        for module in modules.iter() {
+            let (name, stream) = match module_spec_name_stream(module) {
+                (name, Some(stream)) if !module.contains('/') => (name, stream),
+                _ => bail!("Invalid module stream '{}': expected NAME:STREAM", module),
+            };
+            let mut found = false;
+            for map in [&mut modules_cfg.enable, &mut modules_cfg.install]
+                .into_iter()
+                .flatten()
+            {
+                let switched: BTreeSet<String> = map
+                    .iter()
+                    .map(|existing| {
+                        if module_spec_name_stream(existing).0 != name {
+                            return existing.clone();
+                        }
+                        found = true;
+                        match existing.split_once('/') {
+                            Some((_, profile)) => format!("{}:{}/{}", name, stream, profile),
+                            None => format!("{}:{}", name, stream),
+                        }
+                    })
+                    .collect();
+                if *map != switched {
+                    *map = switched;
+                    changed = true;
+                }
+            }
+            if !found {
+                bail!("Module '{}' is not enabled or installed", name);
+            }
+        }
+        Ok(changed)
+    }
+
     pub(crate) fn get_packages_override_remove(&self) -> Vec<String> {
         self.parsed
             .derive
@@ -1194,6 +911,7 @@ impl Treefile {
             .map(|x| !x.is_empty())
             .unwrap_or_default()
             || changed;
+        self.parsed.derive.packages_transient.take();
         changed = self
             .parsed
             .modules
@@ -1311,6 +1029,52 @@ impl Treefile {
         self.parsed.base.ima.unwrap_or(false)
     }
 
+    pub(crate) fn get_systemd_homed(&self) -> bool {
+        self.parsed.base.systemd_homed.unwrap_or(false)
+    }
+
+    pub(crate) fn get_nss_altfiles(&self) -> bool {
+        self.parsed.base.nss_altfiles.unwrap_or(true)
+    }
+
+    /// The path to the private key to sign files with, if any; relative paths
+    /// are resolved against the directory of the treefile.
+    pub(crate) fn get_ima_sign_key(&self) -> String {
+        match self.parsed.base.ima_sign_key.as_deref() {
+            Some(key) => Utf8Path::new(self.get_workdir()).join(key).into_string(),
+            None => String::new(),
+        }
+    }
+
+    /// The paths to the GPG keys all RPMs must be signed with, if any; relative
+    /// paths are resolved against the directory of the treefile.
+    pub(crate) fn get_rpm_signing_keys(&self) -> Vec<String> {
+        let workdir = Utf8Path::new(self.get_workdir());
+        self.parsed
+            .base
+            .rpm_signing_keys
+            .iter()
+            .flatten()
+            .map(|k| workdir.join(k).into_string())
+            .collect()
+    }
+
+    pub(crate) fn get_ima_sign_algorithm(&self) -> String {
+        self.parsed
+            .base
+            .ima_sign_algorithm
+            .clone()
+            .unwrap_or_else(|| "sha256".to_string())
+    }
+
+    pub(crate) fn get_ima_verify(&self) -> bool {
+        self.parsed.base.ima_verify.unwrap_or(false)
+    }
+
+    pub(crate) fn get_fsverity(&self) -> bool {
+        self.parsed.base.fsverity.unwrap_or(false)
+    }
+
     pub(crate) fn get_releasever(&self) -> String {
         self.parsed
             .base
@@ -1353,43 +1117,19 @@ impl Treefile {
         files_to_remove
     }
 
-    pub(crate) fn get_repo_packages(&self) -> &[RepoPackage] {
-        self.parsed.repo_packages.as_deref().unwrap_or_default()
+    pub(crate) fn get_repo_packages(&self) -> Vec<crate::ffi::RepoPackage> {
+        self.parsed
+            .repo_packages
+            .iter()
+            .flatten()
+            .map(|rp| rp.clone().into())
+            .collect()
     }
 
     pub(crate) fn clear_repo_packages(&mut self) {
         self.parsed.repo_packages.take();
     }
 
-    /// Do some upfront semantic checks we can do beyond just the type safety serde provides.
-    fn validate_config(config: &TreeComposeConfig) -> Result<()> {
-        // check add-files
-        if let Some(files) = &config.base.add_files {
-            for (_, dest) in files.iter() {
-                if !add_files_path_is_valid(dest) {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidInput,
-                        format!("Unsupported path in add-files: {}", dest),
-                    )
-                    .into());
-                }
-            }
-        }
-        if let Some(version_suffix) = config.base.automatic_version_suffix.as_ref() {
-            if !(version_suffix.len() == 1 && version_suffix.is_ascii()) {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    format!(
-                        "Invalid automatic-version-suffix, must be exactly one ASCII character: {}",
-                        version_suffix
-                    ),
-                )
-                .into());
-            }
-        }
-        Ok(())
-    }
-
     /// Do some upfront semantic checks we can do beyond just the type safety serde provides.
     fn validate_base_config(config: &TreeComposeConfig) -> Result<()> {
         if config.base.repos.is_none() && config.base.lockfile_repos.is_none() {
@@ -1465,7 +1205,9 @@ impl Treefile {
         // this further by using a custom `serialize_with` for Vecs where ordering doesn't matter
         // (or just sort the Vecs).
         let mut hasher = glib::Checksum::new(glib::ChecksumType::Sha256).unwrap();
-        self.parsed.hasher_update(&mut hasher)?;
+        // don't use pretty mode to increase the chances of a stable serialization
+        // https://github.com/projectatomic/rpm-ostree/pull/1865
+        hasher.update(serde_json::to_vec(&self.parsed)?.as_slice());
         self.externals.hasher_update(&mut hasher)?;
 
         let it = self.parsed.base.ostree_layers.iter().flat_map(|x| x.iter());
@@ -1535,6 +1277,7 @@ impl Treefile {
         let mut clone = self.parsed.derive.clone();
         // neuter everything we *do* support
         clone.packages_local.take();
+        clone.packages_local_unverified.take();
         clone.override_replace.take();
         clone.override_remove.take();
         clone.override_replace_local.take();
@@ -1599,14 +1342,10 @@ impl Treefile {
         if custom_origin_url.is_empty() {
             self.parsed.derive.custom = None;
         } else {
-            self.parsed.derive.custom = Some(DeriveCustom {
-                url: custom_origin_url.to_string(),
-                description: if custom_origin_description.is_empty() {
-                    None
-                } else {
-                    Some(custom_origin_description.to_string())
-                },
-            });
+            let description = Some(custom_origin_description)
+                .filter(|d| !d.is_empty())
+                .map(|d| d.to_string());
+            self.parsed.derive.custom = Some(DeriveCustom::new(custom_origin_url, description));
         }
     }
 
@@ -1733,6 +1472,10 @@ impl Treefile {
             if let Some(ref mut initrd) = self.parsed.derive.initramfs {
                 initrd.regenerate = false;
                 initrd.args = None;
+                initrd.add_modules = None;
+                initrd.omit_modules = None;
+                initrd.add_drivers = None;
+                initrd.generator = None;
             }
         } else {
             let initrd = self.parsed.derive.initramfs.ext_get_or_insert_default();
@@ -1741,6 +1484,137 @@ impl Treefile {
         }
     }
 
+    /// The dracut configuration drop-in generated from the modules and drivers
+    /// configured with `rpm-ostree initramfs`; empty if there are none.
+    pub(crate) fn get_initramfs_dracut_conf(&self) -> String {
+        self.parsed
+            .derive
+            .initramfs
+            .as_ref()
+            .map(crate::initramfs::dracut_conf_render)
+            .unwrap_or_default()
+    }
+
+    /// The generator regenerating the initramfs on the client; empty for dracut.
+    pub(crate) fn get_initramfs_generator(&self) -> String {
+        self.parsed
+            .derive
+            .initramfs
+            .as_ref()
+            .and_then(|i| i.generator.clone())
+            .unwrap_or_default()
+    }
+
+    /// Set the generator regenerating the initramfs on the client; returns true
+    /// if it changed.
+    pub(crate) fn set_initramfs_generator(&mut self, name: &str) -> CxxResult<bool> {
+        crate::initramfs::validate_generator_name(name)?;
+        let generator = Some(name)
+            .filter(|n| *n != crate::initramfs::DEFAULT_GENERATOR)
+            .map(|n| n.to_string());
+        let initrd = self.parsed.derive.initramfs.ext_get_or_insert_default();
+        let changed = initrd.generator != generator;
+        initrd.generator = generator;
+        Ok(changed)
+    }
+
+    /// Update the dracut modules and drivers configured with `rpm-ostree
+    /// initramfs`; returns true if they changed.
+    pub(crate) fn initramfs_dracut_config_update(
+        &mut self,
+        add_modules: Vec<String>,
+        omit_modules: Vec<String>,
+        add_drivers: Vec<String>,
+        remove_modules: Vec<String>,
+        remove_drivers: Vec<String>,
+    ) -> CxxResult<bool> {
+        let initrd = self.parsed.derive.initramfs.ext_get_or_insert_default();
+        Ok(crate::initramfs::dracut_config_update(
+            initrd,
+            &add_modules,
+            &omit_modules,
+            &add_drivers,
+            &remove_modules,
+            &remove_drivers,
+        )?)
+    }
+
+    /// Whether FIPS mode was enabled with `rpm-ostree ex fips enable`.
+    pub(crate) fn get_fips(&self) -> bool {
+        self.parsed.derive.fips.unwrap_or_default()
+    }
+
+    pub(crate) fn set_fips(&mut self, enabled: bool) {
+        self.parsed.derive.fips = Some(enabled).filter(|e| *e);
+    }
+
+    pub(crate) fn get_kargs_append(&self) -> Vec<String> {
+        self.parsed
+            .derive
+            .kargs
+            .as_ref()
+            .and_then(|k| k.append.clone())
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn get_kargs_delete(&self) -> Vec<String> {
+        self.parsed
+            .derive
+            .kargs
+            .as_ref()
+            .and_then(|k| k.delete.clone())
+            .unwrap_or_default()
+    }
+
+    /// Track the changes of the kernel arguments from `existing` to `new`.
+    pub(crate) fn kargs_track(&mut self, existing: &str, new: &str) {
+        let mut append = self.get_kargs_append();
+        let mut delete = self.get_kargs_delete();
+        crate::kargs::track(&mut append, &mut delete, existing, new);
+        let profiles = self.parsed.derive.kargs.take().and_then(|k| k.profiles);
+        let mut kargs = DeriveKargs::default();
+        kargs.append = Some(append).filter(|v| !v.is_empty());
+        kargs.delete = Some(delete).filter(|v| !v.is_empty());
+        kargs.profiles = profiles;
+        self.parsed.derive.kargs = Some(kargs).filter(|k| k != &DeriveKargs::default());
+    }
+
+    /// Enable and disable the named kernel argument profiles, as defined in
+    /// the deployment root `rootfs_dfd`, given the current kernel arguments
+    /// `kargs`; returns the new ones.
+    pub(crate) fn kargs_profiles_apply(
+        &mut self,
+        rootfs_dfd: i32,
+        kargs: &str,
+        enable: Vec<String>,
+        disable: Vec<String>,
+    ) -> CxxResult<String> {
+        let root = unsafe { &crate::ffiutil::ffi_dirfd(rootfs_dfd)? };
+        Ok(crate::kargs::apply_profiles(
+            &mut self.parsed.derive.kargs,
+            root,
+            kargs,
+            &enable,
+            &disable,
+        )?)
+    }
+
+    /// Attribute each of the kernel arguments `kargs` of a deployment of the
+    /// commit `rev` to the layer it comes from.
+    pub(crate) fn kargs_layers(
+        &self,
+        repo: &crate::ffi::OstreeRepo,
+        rev: &str,
+        kargs: &str,
+    ) -> CxxResult<Vec<crate::ffi::KargLayer>> {
+        Ok(crate::kargs::kargs_layers(
+            &repo.glib_reborrow(),
+            rev,
+            kargs,
+            self.parsed.derive.kargs.as_ref(),
+        )?)
+    }
+
     pub(crate) fn get_unconfigured_state(&self) -> String {
         self.parsed
             .derive
@@ -1755,62 +1629,12 @@ impl Treefile {
     ///    false --> definitely does not require local assembly
     ///    true  --> maybe requires assembly, need to investigate further by doing work
     pub(crate) fn may_require_local_assembly(&self) -> bool {
-        self.parsed.cliwrap.unwrap_or_default() ||
-            self.get_initramfs_regenerate() ||
-            self.has_initramfs_etc_files() ||
-            self.has_any_packages() ||
-            // Technically, alone it doesn't require require assembly, but it still
-            // requires fetching repo metadata to validate (remember: modules are a
-            // pure rpmmd concept). This means we may pay the cost of an unneeded
-            // tree checkout, but it's not worth trying to optimize for it.
-            self.has_modules_enable()
+        self.parsed.may_require_local_assembly()
     }
 
     /// Returns true if this origin contains overlay or override packages.
     pub(crate) fn has_any_packages(&self) -> bool {
-        // XXX: make a generic helper for querying optional vecs
-        self.has_packages()
-            || self
-                .parsed
-                .derive
-                .packages_local
-                .as_ref()
-                .map(|m| !m.is_empty())
-                .unwrap_or_default()
-            || self
-                .parsed
-                .derive
-                .packages_local_fileoverride
-                .as_ref()
-                .map(|m| !m.is_empty())
-                .unwrap_or_default()
-            || self
-                .parsed
-                .derive
-                .override_replace
-                .as_ref()
-                .map(|v| v.iter().any(|ovr| !ovr.is_empty()))
-                .unwrap_or_default()
-            || self
-                .parsed
-                .derive
-                .override_replace_local
-                .as_ref()
-                .map(|m| !m.is_empty())
-                .unwrap_or_default()
-            || self
-                .parsed
-                .derive
-                .override_remove
-                .as_ref()
-                .map(|m| !m.is_empty())
-                .unwrap_or_default()
-            || self
-                .parsed
-                .modules
-                .as_ref()
-                .and_then(|m| m.install.as_ref().map(|i| !i.is_empty()))
-                .unwrap_or_default()
+        self.parsed.has_any_packages()
     }
 
     /// Derive RPM importer flags for a given package and treefile settings.
@@ -1841,11 +1665,27 @@ impl Treefile {
 
     pub(crate) fn merge_treefile(&mut self, treefile: &str) -> Result<bool> {
         let mut treefile = std::io::Cursor::new(treefile);
-        let mut treefile = treefile_parse_stream(utils::InputFormat::JSON, &mut treefile, None)?;
-        let prev_checksum = self.parsed.get_checksum()?;
-        treefile_merge(&mut self.parsed, &mut treefile);
-        let new_checksum = self.parsed.get_checksum()?;
-        Ok(prev_checksum != new_checksum)
+        let mut treefile =
+            rpmostree_treefile::parse_stream(utils::InputFormat::JSON, &mut treefile, None)?;
+        let prev = serde_json::to_vec(&self.parsed)?;
+        rpmostree_treefile::merge(&mut self.parsed, &mut treefile);
+        Ok(prev != serde_json::to_vec(&self.parsed)?)
+    }
+
+    /// Converge to the desired state of `rpm-ostree ex apply`, given the
+    /// current kernel arguments `kargs`.
+    pub(crate) fn apply_state(
+        &mut self,
+        state: &str,
+        kargs: &str,
+        allow_protected: bool,
+    ) -> CxxResult<crate::ffi::AppliedState> {
+        Ok(crate::apply_state::apply(
+            &mut self.parsed,
+            state,
+            kargs,
+            allow_protected,
+        )?)
     }
 }
 
@@ -1877,16 +1717,6 @@ fn print_experimental_notice(print: bool, key: &str) {
     }
 }
 
-impl RepoPackage {
-    pub(crate) fn get_repo(&self) -> &str {
-        self.repo.as_str()
-    }
-
-    pub(crate) fn get_packages(&self) -> Vec<String> {
-        self.packages.iter().map(|k| k.to_string()).collect()
-    }
-}
-
 fn hash_file(hasher: &mut glib::Checksum, mut f: &fs::File) -> Result<()> {
     let mut reader = io::BufReader::with_capacity(128 * 1024, f);
     loop {
@@ -1924,6 +1754,16 @@ impl TreefileExternals {
         Ok(passwd_file)
     }
 
+    pub(crate) fn pinned_ids_file_mut(&mut self) -> Result<Option<&mut fs::File>> {
+        match self.pinned_ids.as_mut() {
+            Some(f) => {
+                f.seek(io::SeekFrom::Start(0))?;
+                Ok(Some(f))
+            }
+            None => Ok(None),
+        }
+    }
+
     fn hasher_update(&self, hasher: &mut glib::Checksum) -> Result<()> {
         if let Some(ref f) = self.postprocess_script {
             hash_file(hasher, f)?;
@@ -1934,369 +1774,33 @@ impl TreefileExternals {
         if let Some(ref f) = self.group {
             hash_file(hasher, f)?;
         }
-        for f in self.add_files.values() {
-            hash_file(hasher, f)?;
-        }
-        Ok(())
-    }
-
-    // Panic if there is externally referenced data.
-    fn assert_empty(&self) {
-        // can't use the Default trick here because we can't auto-derive Eq because of `File`
-        assert!(self.postprocess_script.is_none());
-        assert!(self.add_files.is_empty());
-        assert!(self.passwd.is_none());
-        assert!(self.group.is_none());
-    }
-}
-
-/// For increased readability in YAML/JSON, we support whitespace in individual
-/// array elements.
-fn whitespace_split_packages<'a>(pkgs: &BTreeSet<String>) -> Result<BTreeSet<String>> {
-    let mut ret = BTreeSet::new();
-    for element in pkgs {
-        ret.extend(split_whitespace_unless_quoted(element)?.map(String::from));
-    }
-
-    Ok(ret)
-}
-
-// Helper for whitespace_split_packages().
-// Splits a String by whitespace unless substring is wrapped between single quotes
-// and returns split &str in an Iterator.
-fn split_whitespace_unless_quoted(element: &str) -> Result<impl Iterator<Item = &str>> {
-    let mut ret = vec![];
-    let mut start_index = 0;
-    let mut looping_over_quoted_pkg = false;
-    for (i, c) in element.chars().enumerate() {
-        if c == '\'' {
-            if looping_over_quoted_pkg {
-                ret.push(&element[start_index..i]);
-                looping_over_quoted_pkg = false;
-            } else {
-                ret.extend((&element[start_index..i]).split_whitespace());
-                looping_over_quoted_pkg = true;
-            }
-            start_index = i + 1;
-        }
-        if i == element.len() - 1 {
-            if looping_over_quoted_pkg {
-                bail!("Missing terminating quote: {}", element);
-            }
-            ret.extend((&element[start_index..]).split_whitespace());
-        }
-    }
-
-    Ok(ret.into_iter())
-}
-
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Copy, Clone)]
-#[serde(rename_all = "kebab-case")]
-pub(crate) enum BootLocation {
-    New,
-    Modules,
-}
-
-impl Default for BootLocation {
-    fn default() -> Self {
-        BootLocation::New
-    }
-}
-
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
-#[serde(rename_all = "kebab-case")]
-#[serde(tag = "type")]
-pub(crate) enum CheckGroups {
-    None,
-    Previous,
-    File(CheckFile),
-    Data(CheckGroupsData),
-}
-
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
-pub(crate) struct CheckFile {
-    filename: String,
-}
-
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
-pub(crate) struct CheckGroupsData {
-    pub(crate) entries: BTreeMap<String, u32>,
-}
-
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
-#[serde(rename_all = "kebab-case")]
-#[serde(tag = "type")]
-pub(crate) enum CheckPasswd {
-    None,
-    Previous,
-    File(CheckFile),
-    Data(CheckPasswdData),
-}
-
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
-pub(crate) struct CheckPasswdData {
-    pub(crate) entries: BTreeMap<String, CheckPasswdDataEntries>,
-}
-
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
-#[serde(untagged)]
-pub(crate) enum CheckPasswdDataEntries {
-    IdValue(u32),
-    IdTuple([u32; 1]),
-    UidGid((u32, u32)),
-}
-
-impl CheckPasswdDataEntries {
-    /// Return IDs for user and group.
-    pub fn ids(&self) -> (Uid, Gid) {
-        let (user, group) = match self {
-            CheckPasswdDataEntries::IdValue(v) => (*v, *v),
-            CheckPasswdDataEntries::IdTuple([v]) => (*v, *v),
-            CheckPasswdDataEntries::UidGid(v) => *v,
-        };
-        (Uid::from_raw(user), Gid::from_raw(group))
-    }
-}
-
-impl From<u32> for CheckPasswdDataEntries {
-    fn from(item: u32) -> Self {
-        Self::IdValue(item)
-    }
-}
-
-impl From<[u32; 1]> for CheckPasswdDataEntries {
-    fn from(item: [u32; 1]) -> Self {
-        Self::IdTuple(item)
-    }
-}
-
-impl From<(u32, u32)> for CheckPasswdDataEntries {
-    fn from(item: (u32, u32)) -> Self {
-        Self::UidGid(item)
-    }
-}
-
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
-pub(crate) struct Rojig {
-    pub(crate) name: String,
-    pub(crate) summary: String,
-    pub(crate) license: String,
-    pub(crate) description: Option<String>,
-}
-
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
-#[serde(untagged)]
-pub(crate) enum Include {
-    Single(String),
-    Multiple(Vec<String>),
-}
-
-#[derive(Deserialize, Debug, PartialEq, Eq)]
-pub(crate) struct ConditionalInclude {
-    #[serde(rename = "if")]
-    pub(crate) condition: IncludeConditions,
-    pub(crate) include: Include,
-}
-
-#[derive(Deserialize, Debug, PartialEq, Eq)]
-#[serde(untagged)]
-pub(crate) enum IncludeConditions {
-    Single(IncludeCondition),
-    Multiple(Vec<IncludeCondition>),
-}
-
-#[derive(Debug, PartialEq, Eq)]
-pub(crate) struct IncludeCondition {
-    pub(crate) variable: String,
-    pub(crate) op: IncludeConditionOp,
-    pub(crate) value: VarValue,
-}
-
-#[derive(Debug, PartialEq, Eq)]
-pub(crate) enum IncludeConditionOp {
-    Equal,
-    NotEqual,
-    Greater,
-    GreaterOrEqual,
-    Less,
-    LessOrEqual,
-}
-
-impl IncludeCondition {
-    fn evaluate(&self, vars: &BTreeMap<String, VarValue>) -> Result<bool> {
-        let val = vars
-            .get(&self.variable)
-            .ok_or_else(|| anyhow::anyhow!("undefined variable '{}'", &self.variable))?;
-        if !val.type_matches(&self.value) {
-            bail!("{} is not same type as {}", &self.variable, &self.value);
-        }
-        Ok(match &self.op {
-            IncludeConditionOp::Equal => val == &self.value,
-            IncludeConditionOp::NotEqual => val != &self.value,
-            numeric_op => match (val, &self.value) {
-                (VarValue::Number(a), VarValue::Number(b)) => match numeric_op {
-                    IncludeConditionOp::Greater => a > b,
-                    IncludeConditionOp::GreaterOrEqual => a >= b,
-                    IncludeConditionOp::Less => a < b,
-                    IncludeConditionOp::LessOrEqual => a <= b,
-                    _ => unreachable!(),
-                },
-                // we know they're the same type from type_matches(), and both invalid
-                _ => bail!("invalid op for values '{}' and '{}'", val, self.value),
-            },
-        })
-    }
-}
-
-impl TryFrom<&str> for IncludeConditionOp {
-    type Error = anyhow::Error;
-
-    fn try_from(s: &str) -> Result<Self, Self::Error> {
-        Ok(match s {
-            "==" => Self::Equal,
-            "!=" => Self::NotEqual,
-            ">" => Self::Greater,
-            ">=" => Self::GreaterOrEqual,
-            "<" => Self::Less,
-            "<=" => Self::LessOrEqual,
-            x => bail!("expected one of ==, !=, >, >=, <, <= but got {}", x),
-        })
-    }
-}
-
-impl<'de> serde::de::Deserialize<'de> for IncludeCondition {
-    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
-    where
-        D: serde::de::Deserializer<'de>,
-    {
-        use serde::de::Error;
-
-        static COND: Lazy<Regex> = Lazy::new(|| {
-            Regex::new(
-                r#"(?x)^
-                \s*
-                (?P<var>[[:alnum:]_]+)     # variable name
-                \s*
-                (?P<op>\S+)                # operator
-                \s*
-                ((?P<true>true)|           # value; we let regex do all the hard work here
-                 (?P<false>false)|         # and use named groups to know which one matched
-                 (?P<number>[[:digit:]]+)|
-                 "(?P<string>.*)")
-                \s*
-                $"#,
-            )
-            .unwrap()
-        });
-        let s: String = String::deserialize(deserializer)?;
-        if let Some(caps) = COND.captures(&s) {
-            let value = {
-                if caps.name("true").is_some() {
-                    VarValue::Bool(true)
-                } else if caps.name("false").is_some() {
-                    VarValue::Bool(false)
-                } else if let Some(number) = caps.name("number") {
-                    VarValue::Number(u64::from_str(number.as_str()).map_err(D::Error::custom)?)
-                } else if let Some(s) = caps.name("string") {
-                    VarValue::String(s.as_str().into())
-                } else {
-                    unreachable!()
-                }
-            };
-            return Ok(IncludeCondition {
-                variable: caps.name("var").unwrap().as_str().into(),
-                op: caps
-                    .name("op")
-                    .unwrap()
-                    .as_str()
-                    .try_into()
-                    .map_err(D::Error::custom)?,
-                value,
-            });
-        }
-        Err(D::Error::custom(format!("invalid condition: {}", &s)))
-    }
-}
-
-// this is like a subset of serde_json::Value
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
-#[serde(untagged)]
-pub(crate) enum VarValue {
-    Bool(bool),
-    Number(u64),
-    String(String),
-}
-
-impl std::fmt::Display for VarValue {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            VarValue::Bool(b) => write!(f, "{}", b),
-            VarValue::Number(u) => write!(f, "{}", u),
-            VarValue::String(s) => write!(f, "{}", s),
-        }
-    }
-}
-
-impl VarValue {
-    fn type_matches(&self, other: &Self) -> bool {
-        matches!(
-            (self, other),
-            (VarValue::Bool(_), VarValue::Bool(_))
-                | (VarValue::Number(_), VarValue::Number(_))
-                | (VarValue::String(_), VarValue::String(_))
-        )
-    }
-}
-
-// Ughh awkward; this is *almost* VarValue, but we don't want to support `bool` in this
-// case. Really tempting to use derive_more for #[derive(Display)]...
-
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
-#[serde(untagged)]
-pub(crate) enum ReleaseVer {
-    Number(u64),
-    String(String),
-}
-
-impl std::fmt::Display for ReleaseVer {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            ReleaseVer::Number(u) => write!(f, "{}", u),
-            ReleaseVer::String(s) => write!(f, "{}", s),
-        }
-    }
-}
-
-impl From<ReleaseVer> for VarValue {
-    fn from(r: ReleaseVer) -> Self {
-        match r {
-            ReleaseVer::Number(u) => VarValue::Number(u),
-            ReleaseVer::String(s) => VarValue::String(s),
+        if let Some(ref f) = self.pinned_ids {
+            hash_file(hasher, f)?;
         }
+        for f in self.add_files.values() {
+            hash_file(hasher, f)?;
+        }
+        Ok(())
     }
-}
-
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
-#[serde(rename_all = "kebab-case")]
-pub(crate) enum RepoMetadataTarget {
-    Inline,
-    Detached,
-    Disabled,
-}
-
-impl RepoMetadataTarget {
-    const DEFAULT: Self = Self::Inline;
 
-    fn is_default(value: &Self) -> bool {
-        *value == Self::DEFAULT
+    // Panic if there is externally referenced data.
+    fn assert_empty(&self) {
+        // can't use the Default trick here because we can't auto-derive Eq because of `File`
+        assert!(self.postprocess_script.is_none());
+        assert!(self.add_files.is_empty());
+        assert!(self.passwd.is_none());
+        assert!(self.group.is_none());
+        assert!(self.pinned_ids.is_none());
     }
 }
 
-impl Default for RepoMetadataTarget {
-    fn default() -> Self {
-        Self::DEFAULT
-    }
-}
+pub(crate) use rpmostree_treefile::{
+    merge_modules, module_spec_name_stream, BaseComposeConfigFields, BootLocation, CheckCommit,
+    CheckContainer, CheckFile, CheckGroups, CheckPasswd, DeriveContainerPull, DeriveCustom,
+    DeriveInitramfs, DeriveKargs, DiskImage, DiskImageFilesystem, DiskImageFormat, ModulesConfig,
+    RemoteOverrideReplace, RemoteOverrideReplaceFrom, RepoMetadataTarget, RepoPackage,
+    RpmdbBackend, RpmdbFormat, TreeComposeConfig,
+};
 
 impl From<RepoMetadataTarget> for crate::ffi::RepoMetadataTarget {
     fn from(target: RepoMetadataTarget) -> Self {
@@ -2308,273 +1812,11 @@ impl From<RepoMetadataTarget> for crate::ffi::RepoMetadataTarget {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
-#[serde(rename_all = "kebab-case")]
-/// The database backend; see https://github.com/coreos/fedora-coreos-tracker/issues/609
-/// and https://fedoraproject.org/wiki/Changes/Sqlite_Rpmdb
-pub(crate) enum RpmdbBackend {
-    // We messed up the name originally, allow this for backcompat.  xref https://github.com/openshift/os/issues/552
-    #[serde(alias = "b-d-b")]
-    Bdb,
-    Sqlite,
-    Ndb,
-    Target,
-    Host,
-}
-
-// Because of how we handle includes, *everything* here has to be
-// Option<T>.  The defaults live in the code (e.g. machineid-compat defaults
-// to `true`).
-/// Things that live *directly* in this struct are in common to both the base compose and derive
-/// cases. Everything else is specific to either case and so lives in their respective flattened
-/// field.
-#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
-#[serde(rename_all = "kebab-case")]
-pub(crate) struct TreeComposeConfig {
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) packages: Option<BTreeSet<String>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) repo_packages: Option<Vec<RepoPackage>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) modules: Option<ModulesConfig>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) cliwrap: Option<bool>,
-
-    #[serde(flatten)]
-    pub(crate) derive: DeriveConfigFields,
-
-    #[serde(flatten)]
-    pub(crate) base: BaseComposeConfigFields,
-}
-
-impl std::ops::Deref for TreeComposeConfig {
-    type Target = BaseComposeConfigFields;
-
-    fn deref(&self) -> &Self::Target {
-        &self.base
-    }
-}
-
-impl std::ops::DerefMut for TreeComposeConfig {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.base
-    }
-}
-
-/// These fields are only useful when composing a new ostree commit.
-#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
-#[serde(rename_all = "kebab-case")]
-pub(crate) struct BaseComposeConfigFields {
-    // Compose controls
-    #[serde(rename = "ref")]
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) treeref: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) basearch: Option<String>,
-    #[serde(skip_serializing)]
-    pub(crate) variables: Option<BTreeMap<String, VarValue>>,
-    // Optional rojig data
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) rojig: Option<Rojig>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) repos: Option<Vec<String>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) lockfile_repos: Option<Vec<String>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) selinux: Option<bool>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) ima: Option<bool>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) gpg_key: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) include: Option<Include>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) arch_include: Option<BTreeMap<String, Include>>,
-    // Skip serializing for now; it's consumed during parsing anyway. It does
-    // mean though that we can't construct a treefile with it and render it to
-    // disk, which is unlikely we'll ever need. Still, for consistency we
-    // should either implement serialization for this or also skip serializing
-    // the other include knobs above.
-    #[serde(skip_serializing)]
-    pub(crate) conditional_include: Option<Vec<ConditionalInclude>>,
-
-    // Core content
-    // Deprecated option
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) bootstrap_packages: Option<BTreeSet<String>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) ostree_layers: Option<Vec<String>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) ostree_override_layers: Option<Vec<String>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) exclude_packages: Option<Vec<String>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) platform_module: Option<String>,
-
-    // Content installation opts
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) container: Option<bool>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) recommends: Option<bool>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) documentation: Option<bool>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) install_langs: Option<Vec<String>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) initramfs_args: Option<Vec<String>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) readonly_executables: Option<bool>,
-
-    // Tree layout options
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) boot_location: Option<BootLocation>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) tmp_is_dir: Option<bool>,
-
-    // systemd
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) units: Option<Vec<String>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) default_target: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    // Defaults to `true`
-    pub(crate) machineid_compat: Option<bool>,
-
-    // versioning
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) releasever: Option<ReleaseVer>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) automatic_version_prefix: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) automatic_version_suffix: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) mutate_os_release: Option<String>,
-
-    // passwd-related bits
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) etc_group_members: Option<Vec<String>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) preserve_passwd: Option<bool>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) check_passwd: Option<CheckPasswd>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) check_groups: Option<CheckGroups>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) ignore_removed_users: Option<BTreeSet<String>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) ignore_removed_groups: Option<BTreeSet<String>>,
-
-    // Content manipulation
-    #[serde(skip_serializing_if = "Option::is_none")]
-    // This one references an external filename
-    pub(crate) postprocess_script: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    // This one is inline, and supports multiple (hence is useful for inheritance)
-    pub(crate) postprocess: Option<Vec<String>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) add_files: Option<Vec<(String, String)>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) remove_files: Option<Vec<String>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) remove_from_packages: Option<Vec<Vec<String>>>,
-    // The BTreeMap here is on purpose; it ensures we always re-serialize in sorted order so that
-    // checksumming is deterministic across runs. (And serde itself uses BTreeMap for child objects
-    // as well).
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) add_commit_metadata: Option<BTreeMap<String, serde_json::Value>>,
-    #[serde(default, skip_serializing_if = "RepoMetadataTarget::is_default")]
-    pub(crate) repo_metadata: RepoMetadataTarget,
-    // The database backend
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) rpmdb: Option<RpmdbBackend>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) rpmdb_normalize: Option<bool>,
-
-    // Container related bits
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) container_cmd: Option<Vec<String>>,
-
-    #[serde(flatten)]
-    pub(crate) legacy_fields: LegacyTreeComposeConfigFields,
-
-    // This is used to support `packages-${arch}` keys. For YAML files, any other keys cause an
-    // error. For JSON files, unknown keys are silently ignored.
-    #[serde(flatten)]
-    pub(crate) extra: HashMap<String, serde_json::Value>,
-}
-
-#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
-pub(crate) struct RepoPackage {
-    pub(crate) repo: String,
-    pub(crate) packages: BTreeSet<String>,
-}
-
-#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq, Clone)]
-pub(crate) struct ModulesConfig {
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) enable: Option<BTreeSet<String>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) install: Option<BTreeSet<String>>,
-}
-
-#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
-pub(crate) struct LegacyTreeComposeConfigFields {
-    #[serde(skip_serializing)]
-    pub(crate) gpg_key: Option<String>,
-    #[serde(skip_serializing)]
-    pub(crate) boot_location: Option<BootLocation>,
-    #[serde(skip_serializing)]
-    pub(crate) default_target: Option<String>,
-    #[serde(skip_serializing)]
-    pub(crate) automatic_version_prefix: Option<String>,
-}
-
-#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq, Clone)]
-#[serde(rename_all = "kebab-case")]
-pub(crate) struct DeriveCustom {
-    pub(crate) url: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) description: Option<String>,
-}
-
-#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq, Clone)]
-#[serde(rename_all = "kebab-case")]
-pub(crate) struct DeriveInitramfs {
-    pub(crate) regenerate: bool,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) etc: Option<BTreeSet<String>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) args: Option<Vec<String>>,
-}
-
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
-#[serde(rename_all = "kebab-case")]
-pub(crate) enum RemoteOverrideReplaceFrom {
-    Repo(String),
-}
-
-impl std::fmt::Display for RemoteOverrideReplaceFrom {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match self {
-            RemoteOverrideReplaceFrom::Repo(r) => write!(f, "repo={}", r),
-        }
-    }
-}
-
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
-#[serde(rename_all = "kebab-case")]
-pub(crate) struct RemoteOverrideReplace {
-    pub(crate) from: RemoteOverrideReplaceFrom,
-    // In the future, some `from`s could support not specifying packages.
-    // See discussions in https://github.com/coreos/rpm-ostree/issues/1265.
-    // Update `is_empty` below as necessary in that case.
-    pub(crate) packages: BTreeSet<String>,
-}
-
-impl RemoteOverrideReplace {
-    fn is_empty(&self) -> bool {
-        match self.from {
-            RemoteOverrideReplaceFrom::Repo(_) => self.packages.is_empty(),
+impl From<RepoPackage> for crate::ffi::RepoPackage {
+    fn from(rp: RepoPackage) -> Self {
+        Self {
+            repo: rp.repo,
+            packages: rp.packages.into_iter().collect(),
         }
     }
 }
@@ -2600,219 +1842,7 @@ impl From<crate::ffi::OverrideReplacement> for RemoteOverrideReplace {
             // there are no other kinds the C++ side could create, but Rust doesn't know that
             _ => unreachable!(),
         };
-        RemoteOverrideReplace { from, packages }
-    }
-}
-
-/// These fields are only useful when deriving from a prior ostree commit;
-/// at the moment we only use them when translating an origin file
-/// to a treefile for client side assembly.
-#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq, Clone)]
-#[serde(rename_all = "kebab-case")]
-pub(crate) struct DeriveConfigFields {
-    // this is used for ref types ostree/checksum
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) base_refspec: Option<String>,
-    // this is used for ref types ostree/checksum
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) container_image_reference: Option<String>,
-
-    // Packages
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) packages_local: Option<BTreeMap<String, String>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) packages_local_fileoverride: Option<BTreeMap<String, String>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) override_remove: Option<BTreeSet<String>>,
-    #[serde(rename = "ex-override-replace")]
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) override_replace: Option<Vec<RemoteOverrideReplace>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) override_replace_local: Option<BTreeMap<String, String>>,
-
-    // Initramfs
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) initramfs: Option<DeriveInitramfs>,
-
-    // Custom origin
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) custom: Option<DeriveCustom>,
-
-    // Misc
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) override_commit: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub(crate) unconfigured_state: Option<String>,
-}
-
-impl BaseComposeConfigFields {
-    pub(crate) fn error_if_nonempty(&self) -> Result<()> {
-        // Exemption for `basearch`, which we set to the current arch during parsing. Also mask
-        // `variables`, which can have `basearch` be auto-added but doesn't matter in this context
-        // since it's not used during the actual compose, only during treefile processing itself.
-        let s = Self {
-            basearch: self.basearch.clone(),
-            variables: self.variables.clone(),
-            ..Default::default()
-        };
-        if &s != self {
-            let j = serde_json::to_string_pretty(self)?;
-            bail!("the following base fields are not supported:\n{}", j);
-        }
-        Ok(())
-    }
-}
-
-impl DeriveConfigFields {
-    pub(crate) fn error_if_nonempty(&self) -> Result<()> {
-        if &Self::default() != self {
-            let j = serde_json::to_string_pretty(self)?;
-            bail!("the following derivation fields are not supported:\n{}", j);
-        }
-        Ok(())
-    }
-}
-
-fn substitute_string(vars: &HashMap<String, String>, s: &mut String) -> Result<()> {
-    if envsubst::is_templated(&s) {
-        *s = envsubst::substitute(s.clone(), vars).map_err(anyhow::Error::msg)?
-    }
-    Ok(())
-}
-
-fn substitute_string_option(
-    vars: &HashMap<String, String>,
-    field: &mut Option<String>,
-) -> Result<()> {
-    if let Some(ref mut s) = field {
-        substitute_string(vars, s)?;
-    }
-    Ok(())
-}
-
-impl TreeComposeConfig {
-    /// Look for use of legacy/renamed fields and migrate them to the new field.
-    fn migrate_legacy_fields(mut self) -> Result<Self> {
-        macro_rules! migrate_field {
-            ( $field:ident ) => {{
-                if self.base.legacy_fields.$field.is_some() && self.base.$field.is_some() {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidInput,
-                        format!("Cannot use new and legacy forms of {}", stringify!($field)),
-                    )
-                    .into());
-                }
-                self.base.$field = self.base.$field.or(self.base.legacy_fields.$field.take());
-            }};
-        }
-
-        migrate_field!(gpg_key);
-        migrate_field!(boot_location);
-        migrate_field!(default_target);
-        migrate_field!(automatic_version_prefix);
-
-        Ok(self)
-    }
-
-    /// Look for use of ${variable} and replace it by its proper value
-    fn substitute_vars(mut self) -> Result<Self> {
-        // convert to strings for envsubst
-        let substvars: HashMap<String, String> = self
-            .base
-            .variables
-            .iter()
-            .flatten()
-            .map(|(k, v)| (k.clone(), v.to_string()))
-            .collect();
-        envsubst::validate_vars(&substvars)?;
-
-        substitute_string_option(&substvars, &mut self.base.treeref)?;
-        if let Some(ref mut rojig) = self.base.rojig {
-            substitute_string(&substvars, &mut rojig.name)?;
-            substitute_string(&substvars, &mut rojig.summary)?;
-            substitute_string_option(&substvars, &mut rojig.description)?;
-        }
-        if let Some(ref mut add_commit_metadata) = self.base.add_commit_metadata {
-            for val in add_commit_metadata.values_mut() {
-                if let serde_json::Value::String(ref mut s) = val {
-                    substitute_string(&substvars, s)?;
-                }
-            }
-        }
-        substitute_string_option(&substvars, &mut self.base.automatic_version_prefix)?;
-        substitute_string_option(&substvars, &mut self.base.mutate_os_release)?;
-        substitute_string_option(&substvars, &mut self.base.platform_module)?;
-
-        Ok(self)
-    }
-
-    fn get_checksum(&self) -> Result<String> {
-        let mut hasher = glib::Checksum::new(glib::ChecksumType::Sha256).unwrap();
-        self.hasher_update(&mut hasher)?;
-        Ok(hasher.string().expect("hash"))
-    }
-
-    fn hasher_update(&self, hasher: &mut glib::Checksum) -> Result<()> {
-        // don't use pretty mode to increase the chances of a stable serialization
-        // https://github.com/projectatomic/rpm-ostree/pull/1865
-        hasher.update(serde_json::to_vec(self)?.as_slice());
-        Ok(())
-    }
-
-    pub(crate) fn get_check_passwd(&self) -> &CheckPasswd {
-        static DEFAULT: CheckPasswd = CheckPasswd::Previous;
-        self.base.check_passwd.as_ref().unwrap_or(&DEFAULT)
-    }
-
-    pub(crate) fn get_check_groups(&self) -> &CheckGroups {
-        static DEFAULT: CheckGroups = CheckGroups::Previous;
-        self.base.check_groups.as_ref().unwrap_or(&DEFAULT)
-    }
-
-    // we need to ensure that appended repo packages override earlier ones
-    // XXX: collapse elements with the same repo to a single element
-    fn handle_repo_packages_overrides(&mut self) {
-        if let Some(repo_packages) = self.repo_packages.as_mut() {
-            let mut seen_pkgs: HashSet<String> = HashSet::new();
-            // Create a temporary new filtered vec; see
-            // https://doc.rust-lang.org/std/iter/struct.Map.html#notes-about-side-effects for why
-            // the reverse and re-reverse due to the side effect during `map()`.
-            let mut v: Vec<_> = repo_packages
-                .drain(..)
-                .rev()
-                .map(|mut rp| {
-                    rp.packages.retain(|p| seen_pkgs.insert(p.into()));
-                    rp
-                })
-                .filter(|rp| !rp.packages.is_empty())
-                .collect();
-            // Now replace the original, re-reversing.
-            v.reverse();
-            *repo_packages = v;
-        }
-    }
-
-    // we need to ensure that appended replacements override earlier ones
-    // XXX: collapse elements with the same source to a single element
-    fn handle_repo_packages_override_replacements(&mut self) {
-        if let Some(override_replace) = self.derive.override_replace.as_mut() {
-            let mut seen_pkgs: HashSet<String> = HashSet::new();
-            // Create a temporary new filtered vec; see
-            // https://doc.rust-lang.org/std/iter/struct.Map.html#notes-about-side-effects for why
-            // the reverse and re-reverse due to the side effect during `map()`.
-            let mut v: Vec<_> = override_replace
-                .drain(..)
-                .rev()
-                .map(|mut ovr| {
-                    ovr.packages.retain(|p| seen_pkgs.insert(p.into()));
-                    ovr
-                })
-                .filter(|ovr| !ovr.is_empty())
-                .collect();
-            // Now replace the original, re-reversing.
-            v.reverse();
-            *override_replace = v;
-        }
+        RemoteOverrideReplace::new(from, packages)
     }
 }
 
@@ -2820,6 +1850,7 @@ impl TreeComposeConfig {
 pub(crate) mod tests {
     use super::*;
     use indoc::indoc;
+    use rpmostree_treefile::{CheckGroupsData, CheckPasswdData, ReleaseVer, VarValue};
     use std::io::Cursor;
     use tempfile;
 
@@ -2879,17 +1910,21 @@ pub(crate) mod tests {
     #[test]
     fn basic_valid() {
         let mut input = Cursor::new(VALID_PRELUDE);
-        let mut treefile =
-            treefile_parse_stream(utils::InputFormat::YAML, &mut input, Some(ARCH_X86_64)).unwrap();
+        let mut treefile = rpmostree_treefile::parse_stream(
+            utils::InputFormat::YAML,
+            &mut input,
+            Some(ARCH_X86_64),
+        )
+        .unwrap();
         treefile = treefile.substitute_vars().unwrap();
         assert!(treefile.base.treeref.unwrap() == "exampleos/x86_64/blah");
         assert!(treefile.packages.unwrap().len() == 7);
         assert_eq!(
             treefile.repo_packages,
-            Some(vec![RepoPackage {
-                repo: "baserepo".into(),
-                packages: maplit::btreeset!("blah".into(), "bloo".into()),
-            }])
+            Some(vec![RepoPackage::new(
+                "baserepo",
+                maplit::btreeset!("blah".into(), "bloo".into()),
+            )])
         );
     }
 
@@ -2907,8 +1942,12 @@ pub(crate) mod tests {
              - bar
         "#});
         let mut input = Cursor::new(buf);
-        let treefile =
-            treefile_parse_stream(utils::InputFormat::YAML, &mut input, Some(ARCH_X86_64)).unwrap();
+        let treefile = rpmostree_treefile::parse_stream(
+            utils::InputFormat::YAML,
+            &mut input,
+            Some(ARCH_X86_64),
+        )
+        .unwrap();
         assert!(treefile.base.add_files.unwrap().len() == 2);
         assert!(treefile.base.remove_files.unwrap().len() == 2);
     }
@@ -2939,8 +1978,12 @@ pub(crate) mod tests {
     #[test]
     fn basic_js_valid() {
         let mut input = Cursor::new(VALID_PRELUDE_JS);
-        let mut treefile =
-            treefile_parse_stream(utils::InputFormat::JSON, &mut input, Some(ARCH_X86_64)).unwrap();
+        let mut treefile = rpmostree_treefile::parse_stream(
+            utils::InputFormat::JSON,
+            &mut input,
+            Some(ARCH_X86_64),
+        )
+        .unwrap();
         treefile = treefile.substitute_vars().unwrap();
         assert!(treefile.base.treeref.unwrap() == "exampleos/x86_64/blah");
         assert!(treefile.packages.unwrap().len() == 5);
@@ -2950,7 +1993,7 @@ pub(crate) mod tests {
     fn basic_valid_noarch() {
         let mut input = Cursor::new(VALID_PRELUDE);
         let mut treefile =
-            treefile_parse_stream(utils::InputFormat::YAML, &mut input, None).unwrap();
+            rpmostree_treefile::parse_stream(utils::InputFormat::YAML, &mut input, None).unwrap();
         treefile = treefile.substitute_vars().unwrap();
         assert!(treefile.base.treeref.unwrap() == "exampleos/x86_64/blah");
         assert!(treefile.packages.unwrap().len() == 5);
@@ -2959,15 +2002,23 @@ pub(crate) mod tests {
     fn append_and_parse(append: &'static str) -> TreeComposeConfig {
         let buf = VALID_PRELUDE.to_string() + append;
         let mut input = Cursor::new(buf);
-        let treefile =
-            treefile_parse_stream(utils::InputFormat::YAML, &mut input, Some(ARCH_X86_64)).unwrap();
+        let treefile = rpmostree_treefile::parse_stream(
+            utils::InputFormat::YAML,
+            &mut input,
+            Some(ARCH_X86_64),
+        )
+        .unwrap();
         treefile.substitute_vars().unwrap()
     }
 
     fn test_invalid(data: &'static str) {
         let buf = VALID_PRELUDE.to_string() + data;
         let mut input = Cursor::new(buf);
-        match treefile_parse_stream(utils::InputFormat::YAML, &mut input, Some(ARCH_X86_64)) {
+        match rpmostree_treefile::parse_stream(
+            utils::InputFormat::YAML,
+            &mut input,
+            Some(ARCH_X86_64),
+        ) {
             Err(ref e) => match e.downcast_ref::<io::Error>() {
                 Some(ref ioe) if ioe.kind() == io::ErrorKind::InvalidInput => {}
                 _ => panic!("Expected invalid treefile, not {}", e.to_string()),
@@ -2985,8 +2036,12 @@ pub(crate) mod tests {
             mutate-os-release: ${releasever}
         "#};
         let mut input = Cursor::new(buf);
-        let mut treefile =
-            treefile_parse_stream(utils::InputFormat::YAML, &mut input, Some(ARCH_X86_64)).unwrap();
+        let mut treefile = rpmostree_treefile::parse_stream(
+            utils::InputFormat::YAML,
+            &mut input,
+            Some(ARCH_X86_64),
+        )
+        .unwrap();
         treefile = treefile.substitute_vars().unwrap();
         assert!(treefile.base.treeref.unwrap() == "exampleos/x86_64/30");
         assert!(treefile.base.releasever.unwrap() == ReleaseVer::String("30".into()));
@@ -3004,8 +2059,12 @@ pub(crate) mod tests {
             mutate-os-release: ${releasever}
         "#};
         let mut input = Cursor::new(buf);
-        let mut treefile =
-            treefile_parse_stream(utils::InputFormat::YAML, &mut input, Some(ARCH_X86_64)).unwrap();
+        let mut treefile = rpmostree_treefile::parse_stream(
+            utils::InputFormat::YAML,
+            &mut input,
+            Some(ARCH_X86_64),
+        )
+        .unwrap();
         treefile = treefile.substitute_vars().unwrap();
         assert!(treefile.base.treeref.unwrap() == "exampleos/x86_64/30");
         assert!(treefile.base.releasever.unwrap() == ReleaseVer::Number(30));
@@ -3034,8 +2093,12 @@ pub(crate) mod tests {
             mutate-os-release: ${releasever}
         "#};
         let mut input = Cursor::new(buf);
-        let mut treefile =
-            treefile_parse_stream(utils::InputFormat::YAML, &mut input, Some(ARCH_X86_64)).unwrap();
+        let mut treefile = rpmostree_treefile::parse_stream(
+            utils::InputFormat::YAML,
+            &mut input,
+            Some(ARCH_X86_64),
+        )
+        .unwrap();
         treefile = treefile.substitute_vars().unwrap();
         assert!(treefile.base.treeref.unwrap() == "exampleos/x86_64/30/bar/5/false");
         assert!(treefile.base.releasever.unwrap() == ReleaseVer::String("30".into()));
@@ -3048,17 +2111,23 @@ pub(crate) mod tests {
             releasever: 30
         "#};
         let mut input = Cursor::new(buf);
-        assert!(
-            treefile_parse_stream(utils::InputFormat::YAML, &mut input, Some(ARCH_X86_64)).is_err()
-        );
+        assert!(rpmostree_treefile::parse_stream(
+            utils::InputFormat::YAML,
+            &mut input,
+            Some(ARCH_X86_64)
+        )
+        .is_err());
         let buf = indoc! {r#"
             variables:
                 basearch: foo
         "#};
         let mut input = Cursor::new(buf);
-        assert!(
-            treefile_parse_stream(utils::InputFormat::YAML, &mut input, Some(ARCH_X86_64)).is_err()
-        );
+        assert!(rpmostree_treefile::parse_stream(
+            utils::InputFormat::YAML,
+            &mut input,
+            Some(ARCH_X86_64)
+        )
+        .is_err());
     }
 
     #[test]
@@ -3177,6 +2246,50 @@ pub(crate) mod tests {
         assert!(tf.parsed.base.machineid_compat.is_none());
     }
 
+    #[test]
+    fn test_treefile_ima_sign() -> Result<()> {
+        let workdir = tempfile::tempdir()?;
+        let workdir: &Utf8Path = workdir.path().try_into().unwrap();
+        let tf = new_test_treefile(workdir, VALID_PRELUDE, None)?;
+        assert_eq!(tf.get_ima_sign_key(), "");
+        assert_eq!(tf.get_ima_sign_algorithm(), "sha256");
+        assert!(!tf.get_ima_verify());
+        let mut buf = VALID_PRELUDE.to_string();
+        buf.push_str(indoc! {"
+            ima-sign-key: keys/ima.pem
+            ima-sign-algorithm: sha512
+            ima-verify: true
+        "});
+        let tf = new_test_treefile(workdir, buf.as_str(), None)?;
+        assert_eq!(tf.get_ima_sign_key(), workdir.join("keys/ima.pem").as_str());
+        assert_eq!(tf.get_ima_sign_algorithm(), "sha512");
+        assert!(tf.get_ima_verify());
+        Ok(())
+    }
+
+    #[test]
+    fn test_treefile_rpm_signing_keys() -> Result<()> {
+        let workdir = tempfile::tempdir()?;
+        let workdir: &Utf8Path = workdir.path().try_into().unwrap();
+        let tf = new_test_treefile(workdir, VALID_PRELUDE, None)?;
+        assert!(tf.get_rpm_signing_keys().is_empty());
+        let mut buf = VALID_PRELUDE.to_string();
+        buf.push_str(indoc! {"
+            rpm-signing-keys:
+              - keys/fedora.asc
+              - /etc/pki/rpm-gpg/RPM-GPG-KEY-extras
+        "});
+        let tf = new_test_treefile(workdir, buf.as_str(), None)?;
+        assert_eq!(
+            tf.get_rpm_signing_keys(),
+            [
+                workdir.join("keys/fedora.asc").as_str(),
+                "/etc/pki/rpm-gpg/RPM-GPG-KEY-extras"
+            ]
+        );
+        Ok(())
+    }
+
     const ROJIG_YAML: &'static str = indoc! {r#"
         releasever: "35"
         rojig:
@@ -3238,27 +2351,21 @@ pub(crate) mod tests {
         assert_eq!(
             tf.parsed.repo_packages,
             Some(vec![
-                RepoPackage {
-                    repo: "foo2".into(),
-                    packages: maplit::btreeset!("qwert".into()),
-                },
-                RepoPackage {
-                    repo: "baserepo".into(),
-                    packages: maplit::btreeset!("blah".into(), "bloo".into()),
-                }
+                RepoPackage::new("foo2", maplit::btreeset!("qwert".into())),
+                RepoPackage::new("baserepo", maplit::btreeset!("blah".into(), "bloo".into()))
             ])
         );
         assert_eq!(
             tf.parsed.modules,
-            Some(ModulesConfig {
-                enable: Some(maplit::btreeset!("dodo".into(), "foobar:2.0".into())),
-                install: Some(maplit::btreeset!(
+            Some(ModulesConfig::new(
+                Some(maplit::btreeset!("dodo".into(), "foobar:2.0".into())),
+                Some(maplit::btreeset!(
                     "bazboo".into(),
                     "nodejs:15".into(),
                     "swig:3.0/complete".into(),
                     "sway:rolling".into(),
                 ))
-            },)
+            ))
         );
         Ok(())
     }
@@ -3554,16 +2661,18 @@ conditional-include:
             .as_bytes(),
         );
         let mut mid =
-            treefile_parse_stream(utils::InputFormat::YAML, &mut mid_input, basearch).unwrap();
+            rpmostree_treefile::parse_stream(utils::InputFormat::YAML, &mut mid_input, basearch)
+                .unwrap();
         mid = mid.substitute_vars().unwrap();
         let mut top_input = Cursor::new(ROJIG_YAML);
         let mut top =
-            treefile_parse_stream(utils::InputFormat::YAML, &mut top_input, basearch).unwrap();
+            rpmostree_treefile::parse_stream(utils::InputFormat::YAML, &mut top_input, basearch)
+                .unwrap();
         top = top.substitute_vars().unwrap();
         assert!(top.base.add_commit_metadata.is_none());
-        treefile_merge(&mut mid, &mut base);
+        rpmostree_treefile::merge(&mut mid, &mut base);
         assert_eq!(mid.base.releasever, Some(ReleaseVer::Number(1)));
-        treefile_merge(&mut top, &mut mid);
+        rpmostree_treefile::merge(&mut top, &mut mid);
         let tf = &top;
         assert!(tf.packages.as_ref().unwrap().len() == 10);
         assert!(tf.base.etc_group_members.as_ref().unwrap().len() == 2);
@@ -3601,65 +2710,6 @@ conditional-include:
         assert_eq!(data.get("mybool_override").unwrap(), &VarValue::Bool(true));
     }
 
-    #[test]
-    fn test_split_whitespace_unless_quoted() -> Result<()> {
-        // test single quoted package
-        let single_quoted_pkg = "'foobar >= 1.0'";
-        let pkgs: Vec<_> = split_whitespace_unless_quoted(&single_quoted_pkg)?.collect();
-        assert_eq!("foobar >= 1.0", pkgs[0]);
-
-        // test multiple quoted packages
-        let mult_quoted_pkg = "'foobar >= 1.0' 'quuz < 0.5' 'corge > 2'";
-        let pkgs: Vec<_> = split_whitespace_unless_quoted(&mult_quoted_pkg)?.collect();
-        assert_eq!("foobar >= 1.0", pkgs[0]);
-        assert_eq!("quuz < 0.5", pkgs[1]);
-        assert_eq!("corge > 2", pkgs[2]);
-
-        // test single unquoted package
-        let single_unquoted_pkg = "foobar";
-        let pkgs: Vec<_> = split_whitespace_unless_quoted(&single_unquoted_pkg)?.collect();
-        assert_eq!("foobar", pkgs[0]);
-
-        // test multiple unquoted packages
-        let mult_unquoted_pkg = "foobar quuz corge";
-        let pkgs: Vec<_> = split_whitespace_unless_quoted(&mult_unquoted_pkg)?.collect();
-        assert_eq!("foobar", pkgs[0]);
-        assert_eq!("quuz", pkgs[1]);
-        assert_eq!("corge", pkgs[2]);
-
-        // test different orderings of mixed quoted and unquoted packages
-        let mix_quoted_unquoted_pkgs = "'foobar >= 1.1' baz-package 'corge < 0.5'";
-        let pkgs: Vec<_> = split_whitespace_unless_quoted(&mix_quoted_unquoted_pkgs)?.collect();
-        assert_eq!("foobar >= 1.1", pkgs[0]);
-        assert_eq!("baz-package", pkgs[1]);
-        assert_eq!("corge < 0.5", pkgs[2]);
-        let mix_quoted_unquoted_pkgs = "corge 'foobar >= 1.1' baz-package";
-        let pkgs: Vec<_> = split_whitespace_unless_quoted(&mix_quoted_unquoted_pkgs)?.collect();
-        assert_eq!("corge", pkgs[0]);
-        assert_eq!("foobar >= 1.1", pkgs[1]);
-        assert_eq!("baz-package", pkgs[2]);
-        let mix_quoted_unquoted_pkgs = "corge 'foobar >= 1.1' baz-package 'quuz < 0.0.1'";
-        let pkgs: Vec<_> = split_whitespace_unless_quoted(&mix_quoted_unquoted_pkgs)?.collect();
-        assert_eq!("corge", pkgs[0]);
-        assert_eq!("foobar >= 1.1", pkgs[1]);
-        assert_eq!("baz-package", pkgs[2]);
-        assert_eq!("quuz < 0.0.1", pkgs[3]);
-
-        // test missing quotes around packages using version qualifiers
-        let missing_quotes = "foobar >= 1.0 quuz";
-        let pkgs: Vec<_> = split_whitespace_unless_quoted(&missing_quotes)?.collect();
-        assert_ne!("foobar >= 1.0", pkgs[0]);
-        assert_eq!(">=", pkgs[1]);
-        let missing_leading_quote = "foobar >= 1.0'";
-        assert!(split_whitespace_unless_quoted(&missing_leading_quote).is_err());
-        let missing_trailing_quote = "'foobar >= 1.0 baz-package";
-        assert!(split_whitespace_unless_quoted(&missing_trailing_quote).is_err());
-        let stray_quote = "'foobar >= 1.0' quuz' corge";
-        assert!(split_whitespace_unless_quoted(&stray_quote).is_err());
-
-        Ok(())
-    }
-
     #[test]
     fn test_write_json() -> Result<()> {
         let rootdir = cap_tempfile::tempdir(cap_std::ambient_authority())?;
@@ -3672,7 +2722,7 @@ conditional-include:
         let mut src = rootdir
             .open(COMPOSE_JSON_PATH)
             .map(std::io::BufReader::new)?;
-        let cfg = treefile_parse_stream(utils::InputFormat::JSON, &mut src, None)?;
+        let cfg = rpmostree_treefile::parse_stream(utils::InputFormat::JSON, &mut src, None)?;
         assert_eq!(cfg.base.treeref.unwrap(), "exampleos/x86_64/blah");
         Ok(())
     }
@@ -3706,21 +2756,15 @@ conditional-include:
                 x => panic!("unexpected variant {:?}", x),
             };
             assert_eq!(
-                data,
-                &CheckPasswdData {
-                    entries: maplit::btreemap!(
-                        "adm".into() => (3, 4).into(),
-                        "bin".into() => 1.into(),
-                        "foo".into() => [2].into(),
-                    ),
-                }
+                data.entries,
+                maplit::btreemap!(
+                    "adm".into() => (3, 4).into(),
+                    "bin".into() => 1.into(),
+                    "foo".into() => [2].into(),
+                )
             );
             let ids: Vec<_> = data.entries.iter().map(|(_k, v)| v.ids()).collect();
-            let expected = vec![
-                (Uid::from_raw(3), Gid::from_raw(4)),
-                (Uid::from_raw(1), Gid::from_raw(1)),
-                (Uid::from_raw(2), Gid::from_raw(2)),
-            ];
+            let expected = vec![(3, 4), (1, 1), (2, 2)];
             assert_eq!(ids, expected);
         }
         {
@@ -3730,13 +2774,33 @@ conditional-include:
             let workdir: &Utf8Path = workdir.path().try_into().unwrap();
             std::fs::write(workdir.join("local-file"), "").unwrap();
             let tf = new_test_treefile(workdir, &input, None).unwrap();
-            let custom_cfg = tf.parsed.get_check_passwd();
-            assert_eq!(
-                custom_cfg,
-                &CheckPasswd::File(CheckFile {
-                    filename: "local-file".to_string()
-                })
-            );
+            match tf.parsed.get_check_passwd() {
+                CheckPasswd::File(f) => assert_eq!(f.filename, "local-file"),
+                x => panic!("unexpected variant {:?}", x),
+            }
+        }
+        {
+            let input = VALID_PRELUDE.to_string()
+                + r#"check-passwd: { "type": "commit", "repo": "https://example.com/repo", "ref": "exampleos/x86_64/stable" }"#;
+            let workdir = tempfile::tempdir().unwrap();
+            let workdir: &Utf8Path = workdir.path().try_into().unwrap();
+            let tf = new_test_treefile(workdir, &input, None).unwrap();
+            match tf.parsed.get_check_passwd() {
+                CheckPasswd::Commit(c) => {
+                    assert_eq!(c.repo, "https://example.com/repo");
+                    assert_eq!(c.rev, "exampleos/x86_64/stable");
+                }
+                x => panic!("unexpected variant {:?}", x),
+            }
+        }
+        {
+            let input = VALID_PRELUDE.to_string()
+                + r#"check-passwd: { "type": "snapshot", "filename": "passwd.json" }"#;
+            let workdir = tempfile::tempdir().unwrap();
+            let workdir: &Utf8Path = workdir.path().try_into().unwrap();
+            std::fs::write(workdir.join("passwd.json"), "{}").unwrap();
+            let mut tf = new_test_treefile(workdir, &input, None).unwrap();
+            assert!(tf.get_passwd_fd() >= 0);
         }
     }
 
@@ -3763,15 +2827,12 @@ conditional-include:
             let workdir = tempfile::tempdir().unwrap();
             let workdir: &Utf8Path = workdir.path().try_into().unwrap();
             let tf = new_test_treefile(workdir, &input, None).unwrap();
-            let custom_cfg = tf.parsed.get_check_groups();
-            assert_eq!(
-                custom_cfg,
-                &CheckGroups::Data(CheckGroupsData {
-                    entries: maplit::btreemap!(
-                        "bin".into() => 1,
-                    ),
-                })
-            );
+            match tf.parsed.get_check_groups() {
+                CheckGroups::Data(d) => {
+                    assert_eq!(d.entries, maplit::btreemap!("bin".into() => 1))
+                }
+                x => panic!("unexpected variant {:?}", x),
+            }
         }
         {
             let input = VALID_PRELUDE.to_string()
@@ -3780,16 +2841,38 @@ conditional-include:
             let workdir: &Utf8Path = workdir.path().try_into().unwrap();
             std::fs::write(workdir.join("local-file"), "").unwrap();
             let tf = new_test_treefile(workdir, &input, None).unwrap();
-            let custom_cfg = tf.parsed.get_check_groups();
-            assert_eq!(
-                custom_cfg,
-                &CheckGroups::File(CheckFile {
-                    filename: "local-file".to_string()
-                })
-            );
+            match tf.parsed.get_check_groups() {
+                CheckGroups::File(f) => assert_eq!(f.filename, "local-file"),
+                x => panic!("unexpected variant {:?}", x),
+            }
+        }
+        {
+            let input = VALID_PRELUDE.to_string()
+                + r#"check-groups: { "type": "container", "image": "ostree-unverified-registry:quay.io/exampleos/os:stable" }"#;
+            let workdir = tempfile::tempdir().unwrap();
+            let workdir: &Utf8Path = workdir.path().try_into().unwrap();
+            let tf = new_test_treefile(workdir, &input, None).unwrap();
+            match tf.parsed.get_check_groups() {
+                CheckGroups::Container(c) => assert_eq!(
+                    c.image,
+                    "ostree-unverified-registry:quay.io/exampleos/os:stable"
+                ),
+                x => panic!("unexpected variant {:?}", x),
+            }
         }
     }
 
+    #[test]
+    fn test_module_spec_name_stream() {
+        assert_eq!(module_spec_name_stream("nodejs"), ("nodejs", None));
+        assert_eq!(module_spec_name_stream("nodejs:18"), ("nodejs", Some("18")));
+        assert_eq!(
+            module_spec_name_stream("nodejs:18/minimal"),
+            ("nodejs", Some("18"))
+        );
+        assert_eq!(module_spec_name_stream("nodejs/minimal"), ("nodejs", None));
+    }
+
     #[test]
     fn test_derivation() {
         let buf = indoc! {"
@@ -3828,10 +2911,17 @@ conditional-include:
         assert_eq!(treefile.get_packages(), &["foobar"]);
         assert!(treefile.has_modules_enable());
         assert_eq!(treefile.get_modules_enable(), &["nodejs:latest"]);
-        assert!(treefile.add_modules(vec!["foo:bar".into()], true));
-        assert!(!treefile.add_modules(vec!["foo:bar".into()], true));
-        assert!(treefile.add_modules(vec!["baz:boo/minimal".into()], false));
-        assert!(!treefile.add_modules(vec!["baz:boo/minimal".into()], false));
+        assert!(treefile.add_modules(vec!["foo:bar".into()], true).unwrap());
+        assert!(!treefile.add_modules(vec!["foo:bar".into()], true).unwrap());
+        assert!(treefile
+            .add_modules(vec!["baz:boo/minimal".into()], false)
+            .unwrap());
+        assert!(!treefile
+            .add_modules(vec!["baz:boo/minimal".into()], false)
+            .unwrap());
+        assert!(treefile
+            .add_modules(vec!["foo:other".into()], true)
+            .is_err());
         assert_eq!(treefile.get_modules_enable(), &["foo:bar", "nodejs:latest"]);
         assert_eq!(treefile.get_modules_install(), &["baz:boo/minimal"]);
         assert!(treefile.remove_modules(vec!["foo:bar".into()], true));
@@ -3840,6 +2930,37 @@ conditional-include:
         assert!(!treefile.remove_modules(vec!["baz:boo/minimal".into()], false));
         assert_eq!(treefile.get_modules_enable(), &["nodejs:latest"]);
         assert!(treefile.get_modules_install().is_empty());
+        // module lifecycle: disable, switch, reset
+        assert!(treefile
+            .add_modules(vec!["nodejs:latest/default".into()], false)
+            .unwrap());
+        assert!(treefile
+            .disable_modules(vec!["nodejs:latest".into()])
+            .is_err());
+        assert!(treefile
+            .switch_module_streams(vec!["nodejs:18".into()])
+            .unwrap());
+        assert!(!treefile
+            .switch_module_streams(vec!["nodejs:18".into()])
+            .unwrap());
+        assert_eq!(treefile.get_modules_enable(), &["nodejs:18"]);
+        assert_eq!(treefile.get_modules_install(), &["nodejs:18/default"]);
+        assert!(treefile
+            .switch_module_streams(vec!["nodejs:18/default".into()])
+            .is_err());
+        assert!(treefile
+            .switch_module_streams(vec!["enoent:1".into()])
+            .is_err());
+        assert!(treefile.reset_modules(vec!["nodejs".into()]).unwrap());
+        assert!(treefile.reset_modules(vec!["nodejs".into()]).is_err());
+        assert!(treefile.get_modules_enable().is_empty());
+        assert!(treefile.get_modules_install().is_empty());
+        assert!(treefile.add_modules(vec!["foo:bar".into()], true).unwrap());
+        assert!(treefile.disable_modules(vec!["foo:bar".into()]).unwrap());
+        assert!(treefile.get_modules_enable().is_empty());
+        assert!(treefile
+            .add_modules(vec!["nodejs:latest".into()], true)
+            .unwrap());
         assert!(treefile.remove_all_packages());
         assert!(treefile.has_packages_override_remove_name("glibc"));
         assert!(!treefile.has_packages_override_remove_name("enoent"));
@@ -4164,6 +3285,36 @@ conditional-include:
             .unwrap());
     }
 
+    #[test]
+    fn test_packages_transient() {
+        let buf = indoc! {"
+            packages:
+              - strace
+              - gdb
+        "};
+        let mut treefile = Treefile::new_from_string(utils::InputFormat::YAML, buf).unwrap();
+        assert!(treefile
+            .set_packages_transient(vec!["strace".into()], 0)
+            .is_err());
+        assert!(treefile
+            .set_packages_transient(vec!["foobar".into()], 2)
+            .is_err());
+        assert!(treefile
+            .set_packages_transient(vec!["strace".into()], 2)
+            .unwrap());
+        assert!(!treefile
+            .set_packages_transient(vec!["strace".into()], 2)
+            .unwrap());
+        assert_eq!(
+            treefile.get_packages_transient(),
+            maplit::btreemap!("strace".into() => 2)
+        );
+        assert!(treefile
+            .remove_packages(vec!["strace".into()], false)
+            .unwrap());
+        assert!(treefile.get_packages_transient().is_empty());
+    }
+
     #[test]
     fn test_override_replace() {
         let buf = indoc! {"
@@ -4182,15 +3333,10 @@ conditional-include:
         assert_eq!(replacements.len(), 1);
         assert_eq!(
             replacements[0],
-            RemoteOverrideReplace {
-                from: RemoteOverrideReplaceFrom::Repo("foobar".into()),
-                packages: maplit::btreeset![
-                    "foo".into(),
-                    "bar".into(),
-                    "baz".into(),
-                    "blah".into()
-                ],
-            }
+            RemoteOverrideReplace::new(
+                RemoteOverrideReplaceFrom::Repo("foobar".into()),
+                maplit::btreeset!["foo".into(), "bar".into(), "baz".into(), "blah".into()],
+            )
         );
 
         // test canonicalization
@@ -4204,7 +3350,7 @@ conditional-include:
         "};
         let mut treefile_top =
             Treefile::new_from_string(utils::InputFormat::YAML, buf_top).unwrap();
-        treefile_merge(&mut treefile_top.parsed, &mut treefile.parsed);
+        rpmostree_treefile::merge(&mut treefile_top.parsed, &mut treefile.parsed);
         assert!(treefile_top.parsed.derive.override_replace.is_some());
         let replacements = treefile_top
             .parsed
@@ -4215,17 +3361,17 @@ conditional-include:
         assert_eq!(replacements.len(), 2);
         assert_eq!(
             replacements[0],
-            RemoteOverrideReplace {
-                from: RemoteOverrideReplaceFrom::Repo("foobar".into()),
-                packages: maplit::btreeset!["bar".into(), "baz".into(), "blah".into(),],
-            }
+            RemoteOverrideReplace::new(
+                RemoteOverrideReplaceFrom::Repo("foobar".into()),
+                maplit::btreeset!["bar".into(), "baz".into(), "blah".into(),],
+            )
         );
         assert_eq!(
             replacements[1],
-            RemoteOverrideReplace {
-                from: RemoteOverrideReplaceFrom::Repo("other-repo".into()),
-                packages: maplit::btreeset!["foo".into(), "newfoo".into(),],
-            }
+            RemoteOverrideReplace::new(
+                RemoteOverrideReplaceFrom::Repo("other-repo".into()),
+                maplit::btreeset!["foo".into(), "newfoo".into(),],
+            )
         );
         assert!(treefile_top.has_any_packages());
         treefile_top.parsed.derive.override_replace = Some(Vec::new());
@@ -4329,7 +3475,7 @@ pub(crate) fn treefile_new_client_from_etc(basearch: &str) -> CxxResult<Box<Tree
         new_cfg.config.base.error_if_nonempty()?;
         new_cfg.externals.assert_empty();
         let mut new_cfg = new_cfg.config;
-        treefile_merge(&mut new_cfg, &mut cfg);
+        rpmostree_treefile::merge(&mut new_cfg, &mut cfg);
         cfg = new_cfg;
     }
     let r = Treefile::new_from_config(cfg)?;
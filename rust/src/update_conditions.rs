@@ -0,0 +1,88 @@
+//! Conditions of the system which automatic updates honor unless allowed in
+//! `rpm-ostreed.conf`: whether the network connection is metered, per
+//! NetworkManager, and whether the system is on battery power, per UPower.
+//! The daemon queries these services asynchronously; without them, the
+//! connection isn't considered metered, nor the system on battery power.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::ffi::UpdateRestriction;
+
+/// Whether an `NMMetered` value means that the connection is metered.
+pub(crate) fn nm_metered(value: u32) -> bool {
+    // NM_METERED_YES and NM_METERED_GUESS_YES
+    matches!(value, 1 | 3)
+}
+
+/// What an automatic update is restricted to by the conditions of the system:
+/// on metered connections, it only checks for updates, and on battery power,
+/// it only downloads them, or only checks for them if they were to be
+/// deployed from the cache.  `checking_only` and `downloading_only` describe
+/// the update as it would otherwise be run.
+pub(crate) fn update_restriction(
+    metered: bool,
+    on_battery: bool,
+    cache_only: bool,
+    checking_only: bool,
+    downloading_only: bool,
+) -> UpdateRestriction {
+    if metered && !cache_only && !checking_only {
+        UpdateRestriction::CheckOnly
+    } else if on_battery && cache_only {
+        UpdateRestriction::CheckOnly
+    } else if on_battery && !checking_only && !downloading_only {
+        UpdateRestriction::DownloadOnly
+    } else {
+        UpdateRestriction::None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nm_metered() {
+        // Unknown, yes, no, guess yes, guess no
+        let metered: Vec<_> = (0..5).map(nm_metered).collect();
+        assert_eq!(metered, [false, true, false, true, false]);
+    }
+
+    #[test]
+    fn test_update_restriction() {
+        use UpdateRestriction::*;
+        // (metered, on_battery, cache_only, checking_only, downloading_only)
+        for (conditions, expected) in [
+            ((false, false, false, false, false), None),
+            ((false, false, true, false, false), None),
+            // Metered connections only allow checking, unless not downloading anyway
+            ((true, false, false, false, false), CheckOnly),
+            ((true, false, false, false, true), CheckOnly),
+            ((true, false, false, true, false), None),
+            ((true, false, true, false, false), None),
+            // Battery power only allows downloading
+            ((false, true, false, false, false), DownloadOnly),
+            ((false, true, false, false, true), None),
+            ((false, true, false, true, false), None),
+            ((false, true, true, false, false), CheckOnly),
+            // Both
+            ((true, true, false, false, false), CheckOnly),
+            ((true, true, true, false, false), CheckOnly),
+            ((true, true, false, true, false), None),
+        ] {
+            let (metered, on_battery, cache_only, checking_only, downloading_only) = conditions;
+            assert_eq!(
+                update_restriction(
+                    metered,
+                    on_battery,
+                    cache_only,
+                    checking_only,
+                    downloading_only
+                ),
+                expected,
+                "{:?}",
+                conditions
+            );
+        }
+    }
+}
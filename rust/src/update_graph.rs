@@ -0,0 +1,219 @@
+//! Choose upgrade targets from an update graph, as served by Cincinnati, rather
+//! than always deploying the tip of the ref.  This is enabled per remote by
+//! setting `update-graph-url` in its configuration.  Nodes are identified by
+//! the commit checksum in their `payload`, and the server only provides edges
+//! for safe upgrades; e.g. everything older than an update barrier only has an
+//! edge to the barrier, which must be deployed before anything newer.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::cxxrsutil::*;
+use crate::ffi::output_message;
+use anyhow::{anyhow, bail, Context, Result};
+use reqwest::header::ACCEPT;
+use serde_derive::Deserialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::time::Duration;
+use tokio::runtime::Handle;
+
+const REQUEST_TIMEOUT_SECS: u64 = 30;
+
+// Node metadata, as used by Fedora CoreOS
+const DEADEND: &str = "org.fedoraproject.coreos.updates.deadend";
+const DEADEND_REASON: &str = "org.fedoraproject.coreos.updates.deadend_reason";
+const AGE_INDEX: &str = "org.fedoraproject.coreos.releases.age_index";
+
+#[derive(Deserialize, Debug)]
+struct Node {
+    version: String,
+    payload: String,
+    #[serde(default)]
+    metadata: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Graph {
+    nodes: Vec<Node>,
+    edges: Vec<(usize, usize)>,
+}
+
+impl Node {
+    /// Releases which shouldn't be upgraded to, since nothing can be upgraded
+    /// from them.
+    fn is_deadend(&self) -> bool {
+        self.metadata.get(DEADEND).map(|v| v.as_str()) == Some("true")
+    }
+}
+
+impl Graph {
+    fn parse(s: &str) -> Result<Self> {
+        let graph: Self = serde_json::from_str(s)?;
+        let n = graph.nodes.len();
+        if let Some(e) = graph.edges.iter().find(|(a, b)| *a >= n || *b >= n) {
+            bail!("Invalid edge: {:?}", e);
+        }
+        Ok(graph)
+    }
+
+    fn find(&self, checksum: &str) -> Option<usize> {
+        self.nodes.iter().position(|n| n.payload == checksum)
+    }
+
+    /// How recent node `i` is: by age index if provided, and otherwise by its
+    /// position, as servers list nodes in release order.
+    fn age(&self, i: usize) -> (Option<u64>, usize) {
+        let age_index = self.nodes[i].metadata.get(AGE_INDEX);
+        (age_index.and_then(|v| v.parse().ok()), i)
+    }
+
+    /// The path from `current` to the most recent release reachable from it,
+    /// skipping dead ends; its second node is the next upgrade.  `None` if
+    /// there's no upgrade.
+    fn upgrade_path(&self, current: usize) -> Option<Vec<usize>> {
+        // Breadth-first, so that the path is the shortest one
+        let mut parent: Vec<Option<usize>> = vec![None; self.nodes.len()];
+        let mut queue = VecDeque::from([current]);
+        while let Some(i) = queue.pop_front() {
+            for &(_, to) in self.edges.iter().filter(|(from, _)| *from == i) {
+                if to != current && parent[to].is_none() && !self.nodes[to].is_deadend() {
+                    parent[to] = Some(i);
+                    queue.push_back(to);
+                }
+            }
+        }
+        let latest = (0..self.nodes.len())
+            .filter(|&i| parent[i].is_some())
+            .max_by_key(|&i| self.age(i))?;
+        let mut path = vec![latest];
+        while let Some(p) = parent[*path.last().unwrap()] {
+            path.push(p);
+        }
+        path.reverse();
+        Some(path)
+    }
+
+    /// Explain the upgrade along `path`.
+    fn describe(&self, path: &[usize]) -> Vec<String> {
+        let versions: Vec<&str> = path
+            .iter()
+            .map(|&i| self.nodes[i].version.as_str())
+            .collect();
+        let mut msgs = vec![format!("Update graph path: {}", versions.join(" -> "))];
+        if versions.len() > 2 {
+            msgs.push(format!(
+                "Upgrading to {} first; it must be deployed before {}",
+                versions[1],
+                versions[versions.len() - 1]
+            ));
+        }
+        msgs
+    }
+}
+
+async fn fetch_graph(url: &str) -> Result<Graph> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS))
+        .build()?;
+    let text = client
+        .get(url)
+        .header(ACCEPT, "application/json")
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    Graph::parse(&text).context("Parsing update graph")
+}
+
+/// Return the commit to upgrade to from `current` according to the update
+/// graph at `url`, or an empty string if there's none.  The chosen path is
+/// explained in the transaction output.
+pub(crate) fn update_graph_target(url: &str, current: &str) -> CxxResult<String> {
+    let graph = Handle::current()
+        .block_on(fetch_graph(url))
+        .with_context(|| format!("Fetching update graph from {}", url))?;
+    let node = graph
+        .find(current)
+        .ok_or_else(|| anyhow!("Commit {} not found in update graph at {}", current, url))?;
+    let current_node = &graph.nodes[node];
+    if current_node.is_deadend() {
+        let reason = current_node
+            .metadata
+            .get(DEADEND_REASON)
+            .map(|r| format!(": {}", r))
+            .unwrap_or_default();
+        output_message(&format!(
+            "Warning: {} is a dead end{}",
+            current_node.version, reason
+        ));
+    }
+    let path = match graph.upgrade_path(node) {
+        Some(path) => path,
+        None => {
+            output_message(&format!(
+                "No upgrade from {} in update graph",
+                current_node.version
+            ));
+            return Ok(String::new());
+        }
+    };
+    for msg in graph.describe(&path) {
+        output_message(&msg);
+    }
+    Ok(graph.nodes[path[1]].payload.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 36.2 is a barrier and 36.4 a dead end
+    const GRAPH: &str = r#"{
+        "nodes": [
+            { "version": "36.1", "payload": "a1", "metadata": {} },
+            { "version": "36.2", "payload": "b2", "metadata": {} },
+            { "version": "36.3", "payload": "c3",
+              "metadata": { "org.fedoraproject.coreos.releases.age_index": "2" } },
+            { "version": "36.4", "payload": "d4",
+              "metadata": { "org.fedoraproject.coreos.updates.deadend": "true",
+                            "org.fedoraproject.coreos.updates.deadend_reason": "broken boot" } },
+            { "version": "36.5", "payload": "e5",
+              "metadata": { "org.fedoraproject.coreos.releases.age_index": "5" } }
+        ],
+        "edges": [[0, 1], [1, 2], [1, 3], [1, 4], [2, 4]]
+    }"#;
+
+    #[test]
+    fn test_upgrade_path() -> Result<()> {
+        let graph = Graph::parse(GRAPH)?;
+        assert_eq!(graph.find("b2"), Some(1));
+        assert_eq!(graph.find("ff"), None);
+        assert!(graph.nodes[3].is_deadend());
+        // Through the barrier
+        let path = graph.upgrade_path(0).unwrap();
+        assert_eq!(path, vec![0, 1, 4]);
+        assert_eq!(
+            graph.describe(&path),
+            vec![
+                "Update graph path: 36.1 -> 36.2 -> 36.5",
+                "Upgrading to 36.2 first; it must be deployed before 36.5"
+            ]
+        );
+        // Directly to the newest, and never to the dead end
+        assert_eq!(graph.upgrade_path(1).unwrap(), vec![1, 4]);
+        assert_eq!(graph.upgrade_path(2).unwrap(), vec![2, 4]);
+        assert_eq!(
+            graph.describe(&[2, 4]),
+            vec!["Update graph path: 36.3 -> 36.5"]
+        );
+        assert_eq!(graph.upgrade_path(3), None);
+        assert_eq!(graph.upgrade_path(4), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!(Graph::parse(r#"{ "nodes": [], "edges": [[0, 1]] }"#).is_err());
+        assert!(Graph::parse(r#"{ "nodes": [] }"#).is_err());
+    }
+}
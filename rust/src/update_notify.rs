@@ -0,0 +1,89 @@
+//! Forward the `UpdateNotification` signal of the daemon, emitted if
+//! `UpdateNotifications` is enabled in rpm-ostreed.conf, as desktop
+//! notifications.  This runs in the user session, see
+//! `rpm-ostree-update-notify.service`.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use anyhow::Result;
+use glib::prelude::*;
+use ostree_ext::{gio, glib};
+
+/// The well-known bus name.
+const BUS_NAME: &str = "org.projectatomic.rpmostree1";
+/// The global sysroot path
+const SYSROOT_PATH: &str = "/org/projectatomic/rpmostree1/Sysroot";
+const SYSROOT_INTERFACE: &str = "org.projectatomic.rpmostree1.Sysroot";
+
+const NOTIFICATIONS_NAME: &str = "org.freedesktop.Notifications";
+const NOTIFICATIONS_PATH: &str = "/org/freedesktop/Notifications";
+/// Icon from the freedesktop icon naming specification
+const NOTIFICATION_ICON: &str = "system-software-update";
+
+/// Build the parameters of the `Notify` method of the notification server.
+fn notify_params(summary: &str, body: &str) -> glib::Variant {
+    let actions: Vec<String> = Vec::new();
+    let hints = glib::VariantDict::new(None);
+    glib::Variant::from_tuple(&[
+        "rpm-ostree".to_variant(),
+        0u32.to_variant(),
+        NOTIFICATION_ICON.to_variant(),
+        summary.to_variant(),
+        body.to_variant(),
+        actions.to_variant(),
+        hints.end(),
+        0i32.to_variant(),
+    ])
+}
+
+fn notify(session: &gio::DBusConnection, params: &glib::Variant) -> Result<()> {
+    let (summary, body) = params
+        .get::<(String, String)>()
+        .ok_or_else(|| anyhow::anyhow!("Invalid UpdateNotification signal"))?;
+    session.call_sync(
+        Some(NOTIFICATIONS_NAME),
+        NOTIFICATIONS_PATH,
+        NOTIFICATIONS_NAME,
+        "Notify",
+        Some(&notify_params(&summary, &body)),
+        Some(glib::VariantTy::new("(u)").unwrap()),
+        gio::DBusCallFlags::NONE,
+        -1,
+        gio::NONE_CANCELLABLE,
+    )?;
+    Ok(())
+}
+
+/// Main entrypoint; runs until the session ends.
+pub fn entrypoint(_args: &[&str]) -> Result<()> {
+    let system = gio::bus_get_sync(gio::BusType::System, gio::NONE_CANCELLABLE)?;
+    let session = gio::bus_get_sync(gio::BusType::Session, gio::NONE_CANCELLABLE)?;
+    // The daemon isn't started for this; it's running whenever it stages updates.
+    let _subscription = system.signal_subscribe(
+        Some(BUS_NAME),
+        Some(SYSROOT_INTERFACE),
+        Some("UpdateNotification"),
+        Some(SYSROOT_PATH),
+        None,
+        gio::DBusSignalFlags::NONE,
+        move |_, _, _, _, _, params| {
+            if let Err(e) = notify(&session, params) {
+                eprintln!("Failed to send notification: {:#}", e);
+            }
+        },
+    );
+    glib::MainLoop::new(None, false).run();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notify_params() {
+        let params = notify_params("System update ready", "Reboot to apply it.");
+        assert_eq!(params.type_().as_str(), "(susssasa{sv}i)");
+        assert_eq!(params.child_value(3).str(), Some("System update ready"));
+    }
+}
@@ -0,0 +1,259 @@
+//! Maintenance windows for automatic updates, from `UpdateWindow` in
+//! rpm-ostreed.conf: outside of them, the policies which deploy updates only
+//! check for updates.  A window is e.g. `Sat,Sun 02:00-05:00`, or
+//! `Mon..Fri 22:00-02:00 UTC`; several can be given, separated by `;`.
+//! `DownloadWindow`, which restricts when automatic updates are downloaded,
+//! uses the same format.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::cxxrsutil::*;
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::prelude::*;
+use chrono::Duration;
+
+#[derive(Debug, PartialEq, Eq)]
+struct UpdateWindow {
+    /// Indexed by `Weekday::num_days_from_monday()`; the days the window starts on
+    days: [bool; 7],
+    /// In minutes since midnight
+    start: u32,
+    /// In minutes since midnight; if not after `start`, the window ends on the next day
+    end: u32,
+    /// Whether times are in UTC rather than in the local time zone
+    utc: bool,
+}
+
+fn parse_day(s: &str) -> Result<Weekday> {
+    s.parse().map_err(|_| anyhow!("Invalid day: {}", s))
+}
+
+/// Parse e.g. `Sat,Sun` or `Mon..Fri`.
+fn parse_days(s: &str) -> Result<[bool; 7]> {
+    let mut days = [false; 7];
+    for part in s.split(',') {
+        if let Some((first, last)) = part.split_once("..") {
+            let mut day = parse_day(first)?;
+            let last = parse_day(last)?;
+            loop {
+                days[day.num_days_from_monday() as usize] = true;
+                if day == last {
+                    break;
+                }
+                day = day.succ();
+            }
+        } else {
+            days[parse_day(part)?.num_days_from_monday() as usize] = true;
+        }
+    }
+    Ok(days)
+}
+
+fn parse_time(s: &str) -> Result<u32> {
+    let t =
+        NaiveTime::parse_from_str(s, "%H:%M").with_context(|| format!("Invalid time: {}", s))?;
+    Ok(t.hour() * 60 + t.minute())
+}
+
+impl UpdateWindow {
+    fn parse(s: &str) -> Result<Self> {
+        let mut words: Vec<&str> = s.split_whitespace().collect();
+        let utc = words.last().map(|w| w.eq_ignore_ascii_case("UTC")) == Some(true);
+        if utc {
+            words.pop();
+        }
+        let (days, times) = match words.as_slice() {
+            [times] => ([true; 7], *times),
+            [days, times] => (parse_days(days)?, *times),
+            _ => bail!("Expected [DAYS] HH:MM-HH:MM [UTC]"),
+        };
+        let (start, end) = times
+            .split_once('-')
+            .ok_or_else(|| anyhow!("Invalid time range: {}", times))?;
+        let (start, end) = (parse_time(start)?, parse_time(end)?);
+        if start == end {
+            bail!("Empty time range: {}", times);
+        }
+        Ok(Self {
+            days,
+            start,
+            end,
+            utc,
+        })
+    }
+
+    fn starts_on(&self, date: NaiveDate) -> bool {
+        self.days[date.weekday().num_days_from_monday() as usize]
+    }
+
+    /// The current time in the time zone of the window.
+    fn now(&self) -> NaiveDateTime {
+        if self.utc {
+            Utc::now().naive_utc()
+        } else {
+            Local::now().naive_local()
+        }
+    }
+
+    fn contains(&self, t: NaiveDateTime) -> bool {
+        let minutes = t.hour() * 60 + t.minute();
+        let date = t.date();
+        if self.start < self.end {
+            self.starts_on(date) && (self.start..self.end).contains(&minutes)
+        } else {
+            (self.starts_on(date) && minutes >= self.start)
+                || (self.starts_on(date.pred()) && minutes < self.end)
+        }
+    }
+
+    /// When the window next opens after `t`.
+    fn next_start(&self, t: NaiveDateTime) -> Option<NaiveDateTime> {
+        let start = NaiveTime::from_hms(self.start / 60, self.start % 60, 0);
+        (0..=7)
+            .map(|d| (t.date() + Duration::days(d)).and_time(start))
+            .find(|s| *s > t && self.starts_on(s.date()))
+    }
+
+    /// When the window which contains `t` closes.
+    fn end_after(&self, t: NaiveDateTime) -> NaiveDateTime {
+        let end = NaiveTime::from_hms(self.end / 60, self.end % 60, 0);
+        let mut end = t.date().and_time(end);
+        if end <= t {
+            end += Duration::days(1);
+        }
+        end
+    }
+
+    /// Convert `t`, in the time zone of the window, to local time.
+    fn to_local(&self, t: NaiveDateTime) -> DateTime<Local> {
+        if self.utc {
+            Utc.from_utc_datetime(&t).with_timezone(&Local)
+        } else {
+            // In a DST gap, this is when the clock moved forward
+            Local
+                .from_local_datetime(&t)
+                .earliest()
+                .unwrap_or_else(|| Local.from_utc_datetime(&t))
+        }
+    }
+}
+
+fn parse_windows(spec: &str) -> Result<Vec<UpdateWindow>> {
+    spec.split(';')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| UpdateWindow::parse(s).with_context(|| format!("Parsing update window '{}'", s)))
+        .collect()
+}
+
+/// Describe the state of `windows` for `rpm-ostree status`.
+fn describe(windows: &[UpdateWindow]) -> String {
+    const FORMAT: &str = "%a %Y-%m-%d %H:%M %:z";
+    let open = windows.iter().find(|w| w.contains(w.now()));
+    if let Some(w) = open {
+        let end = w.to_local(w.end_after(w.now()));
+        return format!("open until {}", end.format(FORMAT));
+    }
+    windows
+        .iter()
+        .filter_map(|w| w.next_start(w.now()).map(|s| w.to_local(s)))
+        .min()
+        .map(|s| format!("next {}", s.format(FORMAT)))
+        .unwrap_or_default()
+}
+
+/// Check that the `UpdateWindow` setting `spec` is valid.
+pub(crate) fn update_window_validate(spec: &str) -> CxxResult<()> {
+    parse_windows(spec)?;
+    Ok(())
+}
+
+/// Whether automatic updates may be staged now according to the `UpdateWindow`
+/// setting `spec`; always the case if it's empty.
+pub(crate) fn update_window_is_open(spec: &str) -> CxxResult<bool> {
+    let windows = parse_windows(spec)?;
+    Ok(windows.is_empty() || windows.iter().any(|w| w.contains(w.now())))
+}
+
+/// Describe when the windows of the `UpdateWindow` setting `spec` next open,
+/// or until when one is open.
+pub(crate) fn update_window_describe(spec: &str) -> CxxResult<String> {
+    Ok(describe(&parse_windows(spec)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        let w = UpdateWindow::parse("Sat,Sun 02:00-05:00")?;
+        assert_eq!(w.days, [false, false, false, false, false, true, true]);
+        assert_eq!((w.start, w.end, w.utc), (120, 300, false));
+        let w = UpdateWindow::parse("Fri..Mon 22:30-01:00 UTC")?;
+        assert_eq!(w.days, [true, false, false, false, true, true, true]);
+        assert_eq!((w.start, w.end, w.utc), (1350, 60, true));
+        let w = UpdateWindow::parse("  03:00-04:00 ")?;
+        assert_eq!(w.days, [true; 7]);
+        assert_eq!(parse_windows("Sat 02:00-05:00; Wed 12:00-13:00;")?.len(), 2);
+        assert!(parse_windows("")?.is_empty());
+        for invalid in [
+            "Sat,Sun",
+            "Caturday 02:00-05:00",
+            "Sat 02:00",
+            "Sat 25:00-26:00",
+            "Sat 02:00-02:00",
+            "Sat Sun 02:00-05:00",
+        ] {
+            assert!(UpdateWindow::parse(invalid).is_err(), "{}", invalid);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_window() -> Result<()> {
+        // 2022-10-15 is a Saturday
+        let w = UpdateWindow::parse("Sat,Sun 02:00-05:00")?;
+        assert!(w.contains(at("2022-10-15 02:00")));
+        assert!(w.contains(at("2022-10-16 04:59")));
+        assert!(!w.contains(at("2022-10-15 05:00")));
+        assert!(!w.contains(at("2022-10-14 03:00")));
+        assert_eq!(
+            w.next_start(at("2022-10-15 02:00")),
+            Some(at("2022-10-16 02:00"))
+        );
+        assert_eq!(
+            w.next_start(at("2022-10-16 12:00")),
+            Some(at("2022-10-22 02:00"))
+        );
+        assert_eq!(w.end_after(at("2022-10-15 03:00")), at("2022-10-15 05:00"));
+
+        // Windows which end on the next day
+        let w = UpdateWindow::parse("Fri 22:00-02:00")?;
+        assert!(w.contains(at("2022-10-14 23:00")));
+        assert!(w.contains(at("2022-10-15 01:00")));
+        assert!(!w.contains(at("2022-10-14 01:00")));
+        assert!(!w.contains(at("2022-10-15 23:00")));
+        assert_eq!(w.end_after(at("2022-10-14 23:00")), at("2022-10-15 02:00"));
+        assert_eq!(w.end_after(at("2022-10-15 01:00")), at("2022-10-15 02:00"));
+        assert_eq!(
+            w.next_start(at("2022-10-14 23:00")),
+            Some(at("2022-10-21 22:00"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_describe() -> Result<()> {
+        assert_eq!(describe(&[]), "");
+        // Always open
+        let windows = parse_windows("Mon..Sun 00:00-23:59; Mon..Sun 23:59-00:00")?;
+        assert!(describe(&windows).starts_with("open until "));
+        assert!(update_window_is_open("").unwrap());
+        Ok(())
+    }
+}
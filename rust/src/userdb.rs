@@ -0,0 +1,278 @@
+//! Implementation of the treefile `nss-altfiles: false` setting: instead of
+//! splitting the users and groups created while composing into
+//! `/usr/lib/{passwd,group}` for nss-altfiles, keep them in the standard files
+//! in `/etc`, and also write them as systemd
+//! [JSON user records](https://systemd.io/USER_RECORD/) in `/usr/lib/userdb`,
+//! which nss-systemd resolves, so that users added by later updates resolve
+//! even though `/etc/passwd` isn't updated.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::cxxrsutil::*;
+use crate::ffiutil;
+use crate::homed::{add_nss_systemd, resolve_rootfs_link};
+use crate::nameservice::group::{parse_group_content, GroupEntry};
+use crate::nameservice::passwd::{parse_passwd_content, PasswdEntry};
+use crate::treefile::Treefile;
+use anyhow::{bail, Context, Result};
+use cap_std::fs::{Dir, DirBuilder, Permissions};
+use cap_std_ext::cap_std;
+use cap_std_ext::dirext::CapStdExtDirExt;
+use fn_error_context::context;
+use serde_json::json;
+use std::io::{BufReader, Write};
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::prelude::PermissionsExt;
+
+const USERDB_DIR: &str = "usr/lib/userdb";
+const NSSWITCH: &str = "usr/etc/nsswitch.conf";
+
+/// The JSON user record of a passwd entry.
+fn user_record(u: &PasswdEntry) -> serde_json::Value {
+    let mut r = json!({
+        "userName": u.name,
+        "uid": u.uid,
+        "gid": u.gid,
+        "disposition": if crate::passwd::is_regular_id(u.uid) { "regular" } else { "system" },
+    });
+    let obj = r.as_object_mut().unwrap();
+    for (k, v) in [
+        ("realName", &u.gecos),
+        ("homeDirectory", &u.home_dir),
+        ("shell", &u.shell),
+    ] {
+        if !v.is_empty() {
+            obj.insert(k.to_string(), json!(v));
+        }
+    }
+    r
+}
+
+/// The JSON group record of a group entry.
+fn group_record(g: &GroupEntry) -> serde_json::Value {
+    let mut r = json!({
+        "groupName": g.name,
+        "gid": g.gid,
+        "disposition": if crate::passwd::is_regular_id(g.gid) { "regular" } else { "system" },
+    });
+    let members: Vec<_> = g.users.iter().filter(|m| !m.is_empty()).collect();
+    if !members.is_empty() {
+        r.as_object_mut()
+            .unwrap()
+            .insert("members".to_string(), json!(members));
+    }
+    r
+}
+
+/// Remove `altfiles` from, and ensure `files` and `systemd` are in, the
+/// `passwd:` and `group:` entries.
+fn retire_altfiles(buf: &str) -> String {
+    let mut r = String::with_capacity(buf.len());
+    for line in buf.lines() {
+        let (db, rest) = match line.split_once(':') {
+            Some((db, rest)) if db == "passwd" || db == "group" => (db, rest),
+            _ => {
+                r.push_str(line);
+                r.push('\n');
+                continue;
+            }
+        };
+        let mut sources: Vec<_> = rest
+            .split_whitespace()
+            .filter(|s| *s != "altfiles")
+            .collect();
+        if !sources.contains(&"files") {
+            sources.insert(0, "files");
+        }
+        r.push_str(&format!("{}: {}\n", db, sources.join(" ")));
+    }
+    add_nss_systemd(&r)
+}
+
+/// Whether the `passwd:` and `group:` entries resolve from the files in /etc
+/// and the user records.
+fn nsswitch_resolves(buf: &str) -> bool {
+    ["passwd", "group"].iter().all(|db| {
+        buf.lines().any(|l| match l.split_once(':') {
+            Some((d, rest)) if d == *db => {
+                let sources: Vec<_> = rest.split_whitespace().collect();
+                sources.contains(&"files")
+                    && sources.contains(&"systemd")
+                    && !sources.contains(&"altfiles")
+            }
+            _ => false,
+        })
+    })
+}
+
+fn write_record(dir: &Dir, name: &str, value: &serde_json::Value) -> Result<()> {
+    let mut buf = serde_json::to_vec_pretty(value)?;
+    buf.push(b'\n');
+    dir.atomic_replace_with(name, |w| -> Result<()> {
+        w.get_mut()
+            .as_file_mut()
+            .set_permissions(Permissions::from_mode(0o644))?;
+        w.write_all(&buf)?;
+        Ok(())
+    })
+    .with_context(|| format!("Writing {}", name))
+}
+
+/// Check that every entry resolves by name and by ID from the records.
+fn verify_records(dir: &Dir, users: &[PasswdEntry], groups: &[GroupEntry]) -> Result<()> {
+    let mut errors = Vec::new();
+    let lookup = |file: &str, field: &str| -> Result<Option<serde_json::Value>> {
+        let f = match dir.open_optional(file)? {
+            Some(f) => f,
+            None => return Ok(None),
+        };
+        let v: serde_json::Value = serde_json::from_reader(BufReader::new(f))
+            .with_context(|| format!("Parsing {}", file))?;
+        Ok(v.get(field).cloned())
+    };
+    for u in users {
+        if lookup(&format!("{}.user", u.name), "uid")? != Some(json!(u.uid))
+            || lookup(&format!("{}.user", u.uid), "userName")? != Some(json!(u.name))
+        {
+            errors.push(format!("user {} ({})", u.name, u.uid));
+        }
+    }
+    for g in groups {
+        if lookup(&format!("{}.group", g.name), "gid")? != Some(json!(g.gid))
+            || lookup(&format!("{}.group", g.gid), "groupName")? != Some(json!(g.name))
+        {
+            errors.push(format!("group {} ({})", g.name, g.gid));
+        }
+    }
+    if !errors.is_empty() {
+        bail!("Failed to resolve from user records: {}", errors.join(", "));
+    }
+    Ok(())
+}
+
+#[context("Writing user records")]
+fn write_userdb(rootfs: &Dir) -> Result<()> {
+    let users = parse_passwd_content(BufReader::new(rootfs.open("usr/etc/passwd")?))?;
+    let groups = parse_group_content(BufReader::new(rootfs.open("usr/etc/group")?))?;
+    // root is always resolved by nss-systemd itself
+    let users: Vec<_> = users.into_iter().filter(|u| u.uid != 0).collect();
+    let groups: Vec<_> = groups.into_iter().filter(|g| g.gid != 0).collect();
+
+    rootfs.ensure_dir_with(USERDB_DIR, DirBuilder::new().mode(0o755))?;
+    let dir = rootfs.open_dir(USERDB_DIR)?;
+    for u in &users {
+        let name = format!("{}.user", u.name);
+        write_record(&dir, &name, &user_record(u))?;
+        dir.remove_file_optional(format!("{}.user", u.uid))?;
+        dir.symlink(&name, format!("{}.user", u.uid))?;
+    }
+    for g in &groups {
+        let name = format!("{}.group", g.name);
+        write_record(&dir, &name, &group_record(g))?;
+        dir.remove_file_optional(format!("{}.group", g.gid))?;
+        dir.symlink(&name, format!("{}.group", g.gid))?;
+    }
+    println!(
+        "Wrote {} user and {} group records to /{}",
+        users.len(),
+        groups.len(),
+        USERDB_DIR
+    );
+    verify_records(&dir, &users, &groups)
+}
+
+#[context("Retiring altfiles from /etc/nsswitch.conf")]
+fn update_nsswitch(rootfs: &Dir) -> Result<()> {
+    let path = resolve_rootfs_link(rootfs, NSSWITCH)?;
+    if !rootfs.try_exists(&path)? {
+        return Ok(());
+    }
+    let buf = rootfs.read_to_string(&path)?;
+    let new = retire_altfiles(&buf);
+    if new != buf {
+        let mode = rootfs.metadata(&path)?.permissions().mode();
+        rootfs.atomic_write_with_perms(&path, new.as_bytes(), Permissions::from_mode(mode))?;
+    }
+    if !nsswitch_resolves(&new) {
+        bail!(
+            "/{} doesn't resolve users and groups from files and systemd",
+            path
+        );
+    }
+    Ok(())
+}
+
+/// If the treefile retires nss-altfiles, write the users and groups of the
+/// tree as user records and update nsswitch.conf for them.
+pub(crate) fn compose_userdb(rootfs_dfd: i32, treefile: &Treefile) -> CxxResult<()> {
+    if treefile.get_nss_altfiles() {
+        return Ok(());
+    }
+    let rootfs = unsafe { ffiutil::ffi_dirfd(rootfs_dfd)? };
+    write_userdb(&rootfs)?;
+    update_nsswitch(&rootfs)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    #[test]
+    fn test_retire_altfiles() {
+        let orig = indoc! {"
+            # passwd: files altfiles
+            passwd:     sss files altfiles systemd
+            shadow:     files
+            group:      altfiles
+            hosts:      files dns
+        "};
+        let expected = indoc! {"
+            # passwd: files altfiles
+            passwd: sss files systemd
+            shadow:     files
+            group: files systemd
+            hosts:      files dns
+        "};
+        let new = retire_altfiles(orig);
+        assert_eq!(new, expected);
+        assert_eq!(retire_altfiles(&new), expected);
+        assert!(nsswitch_resolves(&new));
+        assert!(!nsswitch_resolves(orig));
+    }
+
+    #[test]
+    fn test_records() -> Result<()> {
+        let users =
+            parse_passwd_content("chrony:x:991:987::/var/lib/chrony:/sbin/nologin\n".as_bytes())?;
+        let groups = parse_group_content("wheel:x:10:chrony\nchrony:x:987:\n".as_bytes())?;
+        assert_eq!(
+            user_record(&users[0]),
+            json!({
+                "userName": "chrony",
+                "uid": 991,
+                "gid": 987,
+                "disposition": "system",
+                "homeDirectory": "/var/lib/chrony",
+                "shell": "/sbin/nologin",
+            })
+        );
+        assert_eq!(
+            group_record(&groups[0]),
+            json!({
+                "groupName": "wheel",
+                "gid": 10,
+                "disposition": "system",
+                "members": ["chrony"],
+            })
+        );
+
+        let td = cap_tempfile::tempdir(cap_std::ambient_authority())?;
+        write_record(&td, "chrony.user", &user_record(&users[0]))?;
+        td.symlink("chrony.user", "991.user")?;
+        assert!(verify_records(&td, &users, &[]).is_ok());
+        assert!(verify_records(&td, &[], &groups).is_err());
+        Ok(())
+    }
+}
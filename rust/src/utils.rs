@@ -11,9 +11,13 @@ use crate::cxxrsutil::*;
 use crate::variant_utils;
 use anyhow::{bail, Context, Result};
 use cap_std_ext::cap_std;
+use cap_std_ext::dirext::CapStdExtDirExt;
 use glib::translate::ToGlibPtr;
 use glib::Variant;
 use once_cell::sync::Lazy;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
 use ostree_ext::prelude::*;
 use ostree_ext::{glib, ostree};
 use regex::Regex;
@@ -24,55 +28,8 @@ use std::os::unix::io::{AsRawFd, IntoRawFd};
 use std::path::Path;
 use std::{fs, io};
 
-#[derive(Debug, PartialEq)]
-/// Supported config serialization used by treefile and lockfile
-pub enum InputFormat {
-    YAML,
-    JSON,
-}
-
-impl InputFormat {
-    pub fn detect_from_filename<P: AsRef<Path>>(filename: P) -> Result<Self> {
-        let filename = filename.as_ref();
-        let basename = filename
-            .file_name()
-            .map(|s| s.to_string_lossy())
-            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Expected a filename"))?;
-        if basename.ends_with(".yaml") || basename.ends_with(".yml") {
-            Ok(Self::YAML)
-        } else {
-            Ok(Self::JSON)
-        }
-    }
-}
-
-/// Given a lockfile/treefile config definition, parse it
-pub fn parse_stream<T, R: io::Read>(fmt: &InputFormat, input: &mut R) -> Result<T>
-where
-    T: serde::de::DeserializeOwned,
-{
-    let parsed: T = match fmt {
-        InputFormat::JSON => {
-            let pf: T = serde_json::from_reader(input).map_err(|e| {
-                io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    format!("serde-json: {}", e.to_string()),
-                )
-            })?;
-            pf
-        }
-        InputFormat::YAML => {
-            let pf: T = serde_yaml::from_reader(input).map_err(|e| {
-                io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    format!("serde-yaml: {}", e.to_string()),
-                )
-            })?;
-            pf
-        }
-    };
-    Ok(parsed)
-}
+/// Config parsing is shared with the treefile crate.
+pub use rpmostree_treefile::{from_reader as parse_stream, InputFormat};
 
 /// Given a URL, download it to an O_TMPFILE (temporary file descriptor).
 /// This is a thin wrapper for `download_urls_to_tmpfiles`.
@@ -155,6 +112,20 @@ pub fn parent_dir(filename: &Path) -> Option<&Path> {
         .map(|p| if p.as_os_str() == "" { ".".as_ref() } else { p })
 }
 
+/// Write `contents` to `path` atomically: readers see either the previous
+/// contents or the new ones, even if we crash midway.
+pub(crate) fn write_file_atomic(path: &Path, contents: impl AsRef<[u8]>) -> Result<()> {
+    let (dir, name) = match (parent_dir(path), path.file_name()) {
+        (Some(dir), Some(name)) => (dir, name),
+        _ => bail!("Invalid path {}", path.display()),
+    };
+    let dir = cap_std::fs::Dir::open_ambient_dir(dir, cap_std::ambient_authority())
+        .with_context(|| format!("Opening {}", dir.display()))?;
+    dir.atomic_write(name, contents)
+        .with_context(|| format!("Writing {}", path.display()))?;
+    Ok(())
+}
+
 /// Call a faillible future, while monitoring `cancellable` and return an error if cancelled.
 pub(crate) async fn run_with_cancellable<F, R>(
     f: F,
@@ -312,10 +283,54 @@ pub(crate) fn translate_path_for_ostree_impl(path: &str) -> Option<String> {
     return None;
 }
 
+const MACHINE_ID_PATH: &str = "/etc/machine-id";
+
+/// Parse a 128-bit ID in the format of `/etc/machine-id`.
+fn parse_id128(s: &str) -> Result<[u8; 16]> {
+    let s = s.trim();
+    if s.len() != 32 || !s.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!("Invalid ID: {}", s);
+    }
+    Ok(u128::from_str_radix(s, 16)?.to_be_bytes())
+}
+
+/// Derive an ID from `machine_id` like `sd_id128_get_machine_app_specific()`.
+fn app_specific_id(machine_id: &str, app_id: &str) -> Result<String> {
+    let key = PKey::hmac(&parse_id128(machine_id)?)?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    signer.update(&parse_id128(app_id)?)?;
+    let mut id = [0u8; 16];
+    id.copy_from_slice(&signer.sign_to_vec()?[..16]);
+    // Make it a valid v4 UUID
+    id[6] = (id[6] & 0x0F) | 0x40;
+    id[8] = (id[8] & 0x3F) | 0x80;
+    Ok(format!("{:032x}", u128::from_be_bytes(id)))
+}
+
+/// Derive an ID for `app_id` from the ID of this machine.
+pub(crate) fn machine_app_specific_id(app_id: &str) -> Result<String> {
+    let machine_id = fs::read_to_string(MACHINE_ID_PATH)
+        .with_context(|| format!("Reading {}", MACHINE_ID_PATH))?;
+    app_specific_id(&machine_id, app_id)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_app_specific_id() -> Result<()> {
+        // Cross-checked with `systemd-id128 machine-id --app-specific=`
+        let app_id = "7d2b6c1e9f3a4e58b0c4a1d6e2f8b935";
+        assert_eq!(
+            app_specific_id("0123456789abcdef0123456789abcdef\n", app_id)?,
+            "bc3fda6c362644eaa799b27bff6d7899"
+        );
+        assert!(app_specific_id("0123456789abcdef", app_id).is_err());
+        assert!(app_specific_id("+123456789abcdef0123456789abcdef", app_id).is_err());
+        Ok(())
+    }
+
     fn subs() -> HashMap<String, String> {
         let mut h = HashMap::new();
         h.insert("basearch".to_string(), "ppc64le".to_string());
@@ -407,6 +422,17 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_write_file_atomic() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        let path = &td.path().join("state.json");
+        write_file_atomic(path, "{}")?;
+        write_file_atomic(path, "{\"a\": 1}")?;
+        assert_eq!(std::fs::read_to_string(path)?, "{\"a\": 1}");
+        assert!(write_file_atomic(&td.path().join("nonexistent/state.json"), "{}").is_err());
+        Ok(())
+    }
+
     // Note this is testing C++ code defined in rpmostree-util.cxx
     #[test]
     fn test_next_version() {
@@ -0,0 +1,367 @@
+//! Implementation of `rpm-ostree verify`: check the files of a deployment
+//! against its commit, like `rpm -Va` but using the ostree checksums.
+//!
+//! Files are attributed to the packages owning them according to the rpmdb of
+//! the deployment.  `/usr/etc` isn't checked, as `/etc` is expected to be
+//! modified locally.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::deployment_generate_id_impl;
+use anyhow::{anyhow, bail, Context, Result};
+use clap::Parser;
+use ostree_ext::{gio, ostree};
+use rayon::prelude::*;
+use serde_derive::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::process::Command;
+
+/// Check the files of a deployment against its commit and rpmdb
+#[derive(Debug, Parser)]
+#[clap(name = "rpm-ostree verify", bin_name = "rpm-ostree verify")]
+#[clap(rename_all = "kebab-case")]
+pub(crate) struct Opts {
+    /// The deployment to verify: its index, or booted, pending or rollback
+    #[clap(value_name = "DEPLOYMENT", default_value = "booted")]
+    deployment: String,
+
+    /// Only verify the files of this package (may be specified multiple times)
+    #[clap(long = "package", short = 'p')]
+    packages: Vec<String>,
+
+    /// Output JSON
+    #[clap(long)]
+    json: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Problem {
+    /// The content, mode or ownership differs from the commit.
+    Modified,
+    /// The file doesn't exist.
+    Missing,
+    /// The SELinux label differs from the one the policy of the deployment assigns.
+    Unlabeled,
+}
+
+impl Problem {
+    fn code(self) -> char {
+        match self {
+            Problem::Modified => 'M',
+            Problem::Missing => 'D',
+            Problem::Unlabeled => 'L',
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Failure {
+    path: String,
+    problem: Problem,
+    packages: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    deployment: String,
+    checked: usize,
+    failures: Vec<Failure>,
+}
+
+/// List the regular files and symbolic links of a commit tree under `path`,
+/// with their checksums.
+fn commit_files(dir: &gio::File, path: &str, files: &mut Vec<(String, String)>) -> Result<()> {
+    let cancellable = gio::NONE_CANCELLABLE;
+    let e = dir.enumerate_children(
+        "standard::name,standard::type",
+        gio::FileQueryInfoFlags::NOFOLLOW_SYMLINKS,
+        cancellable,
+    )?;
+    for info in e {
+        let info = info?;
+        let name = info.name();
+        let name = name
+            .to_str()
+            .ok_or_else(|| anyhow!("Invalid filename in {}", path))?;
+        let childpath = format!("{}/{}", path, name);
+        if childpath == "/usr/etc" {
+            continue;
+        }
+        let child = dir.child(name);
+        if info.file_type() == gio::FileType::Directory {
+            commit_files(&child, &childpath, files)?;
+        } else {
+            let f = child.downcast_ref::<ostree::RepoFile>().unwrap();
+            f.ensure_resolved()?;
+            let checksum = f
+                .checksum()
+                .ok_or_else(|| anyhow!("No checksum for {}", childpath))?;
+            files.push((childpath, checksum.to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// Parse `rpm -qa --qf '[%{NEVRA}\t%{FILENAMES}\n]'` into the packages owning each path.
+pub(crate) fn parse_file_owners(buf: &str) -> BTreeMap<String, BTreeSet<String>> {
+    let mut r: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for line in buf.lines() {
+        if let Some((nevra, path)) = line.split_once('\t') {
+            if path.is_empty() || path == "(none)" {
+                continue;
+            }
+            r.entry(path.to_string())
+                .or_default()
+                .insert(nevra.to_string());
+        }
+    }
+    r
+}
+
+/// The packages owning each path, according to the rpmdb of `root`.
+pub(crate) fn file_owners(root: &Path) -> Result<BTreeMap<String, BTreeSet<String>>> {
+    let dbpath = root.join(crate::RPMOSTREE_RPMDB_LOCATION);
+    let out = Command::new("rpm")
+        .arg(format!("--dbpath={}", dbpath.display()))
+        .args(["-qa", "--qf", "[%{NEVRA}\t%{FILENAMES}\n]"])
+        .output()
+        .context("Running rpm")?;
+    if !out.status.success() {
+        bail!(
+            "Querying rpmdb: {}",
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+    }
+    Ok(parse_file_owners(&String::from_utf8_lossy(&out.stdout)))
+}
+
+/// The `security.selinux` extended attribute of `path`, without the trailing NUL.
+fn selinux_label(path: &Path) -> Result<Option<Vec<u8>>> {
+    let cpath = CString::new(path.as_os_str().as_bytes())?;
+    let name = b"security.selinux\0";
+    let mut buf = vec![0u8; 256];
+    loop {
+        let n = unsafe {
+            libc::lgetxattr(
+                cpath.as_ptr(),
+                name.as_ptr() as *const libc::c_char,
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+            )
+        };
+        if n >= 0 {
+            buf.truncate(n as usize);
+            if buf.last() == Some(&0) {
+                buf.pop();
+            }
+            return Ok(Some(buf));
+        }
+        let e = std::io::Error::last_os_error();
+        match e.raw_os_error() {
+            Some(libc::ENODATA) | Some(libc::ENOTSUP) => return Ok(None),
+            Some(libc::ERANGE) => buf.resize(buf.len() * 4, 0),
+            _ => return Err(e).with_context(|| format!("Reading label of {}", path.display())),
+        }
+    }
+}
+
+/// Check that the file `path` of the deployment at `root` exists and has the
+/// SELinux label its policy assigns.
+fn verify_metadata(
+    root: &openat::Dir,
+    rootpath: &Path,
+    sepolicy: &ostree::SePolicy,
+    path: &str,
+) -> Result<Option<Problem>> {
+    let relpath = path.trim_start_matches('/');
+    let mode = match root.metadata(relpath) {
+        Ok(m) => m.stat().st_mode,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Some(Problem::Missing)),
+        Err(e) => return Err(e).with_context(|| format!("Querying {}", path)),
+    };
+    if sepolicy.name().is_some() {
+        let expected = sepolicy.label(path, mode, gio::NONE_CANCELLABLE)?;
+        let actual = selinux_label(&rootpath.join(relpath))?;
+        if expected.as_ref().map(|l| l.as_bytes()) != actual.as_deref() {
+            return Ok(Some(Problem::Unlabeled));
+        }
+    }
+    Ok(None)
+}
+
+/// Check the content, mode, ownership and extended attributes of the file
+/// `path` of the deployment at `root` against its `checksum`.
+pub(crate) fn verify_checksum(root: &openat::Dir, path: &str, checksum: &str) -> Result<bool> {
+    let actual = ostree::checksum_file_at(
+        root.as_raw_fd(),
+        Path::new(path.trim_start_matches('/')),
+        None,
+        ostree::ObjectType::File,
+        ostree::ChecksumFlags::NONE,
+        gio::NONE_CANCELLABLE,
+    )
+    .with_context(|| format!("Checksumming {}", path))?;
+    Ok(actual.as_str() == checksum)
+}
+
+fn verify(
+    sysroot: &ostree::Sysroot,
+    deployment: &ostree::Deployment,
+    packages: &[String],
+) -> Result<Report> {
+    let repo = &sysroot.repo().unwrap();
+    let rootpath = sysroot.path().path().unwrap();
+    let rootpath = rootpath.join(sysroot.deployment_dirpath(deployment));
+    let root = openat::Dir::open(&rootpath)?;
+    let sepolicy = ostree::SePolicy::new_at(root.as_raw_fd(), gio::NONE_CANCELLABLE)?;
+
+    let (commit_root, _) = repo.read_commit(deployment.csum().as_str(), gio::NONE_CANCELLABLE)?;
+    let mut files = Vec::new();
+    commit_files(&commit_root.child("usr"), "/usr", &mut files)?;
+
+    let owners = file_owners(&rootpath)?;
+    if !packages.is_empty() {
+        let names: BTreeSet<_> = packages.iter().map(|s| s.as_str()).collect();
+        let owned_by = |nevras: &BTreeSet<String>| {
+            nevras
+                .iter()
+                .any(|n| names.contains(n.as_str()) || names.contains(nevra_name(n)))
+        };
+        files.retain(|(path, _)| owners.get(path).map(owned_by).unwrap_or(false));
+        if files.is_empty() {
+            bail!("No files found for packages: {}", packages.join(", "));
+        }
+    }
+
+    // The label is part of the checksum, so the content of files which are
+    // missing or mislabeled isn't checked.
+    let mut problems = BTreeMap::new();
+    let mut to_checksum = Vec::new();
+    for (path, checksum) in files.iter() {
+        match verify_metadata(&root, &rootpath, &sepolicy, path)? {
+            Some(problem) => {
+                problems.insert(path.as_str(), problem);
+            }
+            None => to_checksum.push((path.as_str(), checksum.as_str())),
+        }
+    }
+    let modified = to_checksum
+        .par_iter()
+        .filter_map(
+            |&(path, checksum)| match verify_checksum(&root, path, checksum) {
+                Ok(true) => None,
+                Ok(false) => Some(Ok(path)),
+                Err(e) => Some(Err(e)),
+            },
+        )
+        .collect::<Result<Vec<_>>>()?;
+    problems.extend(modified.into_iter().map(|p| (p, Problem::Modified)));
+
+    let failures = problems
+        .into_iter()
+        .map(|(path, problem)| Failure {
+            path: path.to_string(),
+            problem,
+            packages: owners
+                .get(path)
+                .map(|s| s.iter().cloned().collect())
+                .unwrap_or_default(),
+        })
+        .collect();
+    Ok(Report {
+        deployment: deployment_generate_id_impl(deployment),
+        checked: files.len(),
+        failures,
+    })
+}
+
+/// The name of a package from its NEVRA.
+fn nevra_name(nevra: &str) -> &str {
+    let nevr = nevra.rsplit_once('.').map_or(nevra, |(n, _)| n);
+    let nev = nevr.rsplit_once('-').map_or(nevr, |(n, _)| n);
+    nev.rsplit_once('-').map_or(nev, |(n, _)| n)
+}
+
+fn print_human(report: &Report) {
+    println!("Deployment: {}", report.deployment);
+    for f in report.failures.iter() {
+        if f.packages.is_empty() {
+            println!("  {} {}", f.problem.code(), f.path);
+        } else {
+            println!(
+                "  {} {} ({})",
+                f.problem.code(),
+                f.path,
+                f.packages.join(", ")
+            );
+        }
+    }
+    let count = |p| report.failures.iter().filter(|f| f.problem == p).count();
+    println!(
+        "Checked {} files: {} modified, {} missing, {} unlabeled",
+        report.checked,
+        count(Problem::Modified),
+        count(Problem::Missing),
+        count(Problem::Unlabeled)
+    );
+}
+
+pub fn entrypoint(args: &[&str]) -> Result<()> {
+    let opts = &Opts::parse_from(args.iter().skip(1));
+    // Some files are only readable by root
+    crate::ffi::client_require_root()?;
+    let sysroot = &ostree::Sysroot::new_default();
+    sysroot.load(gio::NONE_CANCELLABLE)?;
+    let deployment = &crate::pin::find_deployment(sysroot, &opts.deployment)?;
+    let report = verify(sysroot, deployment, &opts.packages)?;
+    if opts.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_human(&report);
+    }
+    if !report.failures.is_empty() {
+        bail!("{} files failed verification", report.failures.len());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_file_owners() {
+        let buf = indoc::indoc! {"
+            bash-5.1.16-2.fc36.x86_64\t/usr/bin/bash
+            bash-5.1.16-2.fc36.x86_64\t/usr/bin/sh
+            filesystem-3.18-2.fc36.x86_64\t/usr/bin
+            setup-2.13.10-1.fc36.noarch\t/usr/bin
+            gpg-pubkey-38ab71f4-60242b08\t(none)
+        "};
+        let owners = parse_file_owners(buf);
+        assert_eq!(owners.len(), 3);
+        assert_eq!(
+            owners["/usr/bin"].iter().collect::<Vec<_>>(),
+            [
+                "filesystem-3.18-2.fc36.x86_64",
+                "setup-2.13.10-1.fc36.noarch"
+            ]
+        );
+        assert!(owners["/usr/bin/sh"].contains("bash-5.1.16-2.fc36.x86_64"));
+    }
+
+    #[test]
+    fn test_nevra_name() {
+        assert_eq!(nevra_name("bash-5.1.16-2.fc36.x86_64"), "bash");
+        assert_eq!(
+            nevra_name("python3-libs-3.10.4-1.fc36.x86_64"),
+            "python3-libs"
+        );
+        assert_eq!(nevra_name("shim-x64-1:15.4-5.x86_64"), "shim-x64");
+    }
+}
@@ -0,0 +1,1338 @@
+//! The data model of treefiles, as deserialized by serde.
+
+/*
+ * HACKING: In order to add a entry to the treefile, be sure to:
+ * - Update docs/treefile.md
+ * - Add it to the struct
+ * - Add a merge entry to `merge()` in parse.rs
+ * - Add a test case in tests/compose
+ *
+ * The JSON schema printed by `rpm-ostree compose schema` is derived from
+ * these types; mark defaults with `#[schemars(default = ...)]` and deprecated
+ * fields with `#[schemars(with = "Deprecated<...>")]`.
+ */
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use anyhow::{anyhow, bail, Result};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::schema::{InstanceType, Metadata, RootSchema, Schema, SchemaObject};
+use schemars::JsonSchema;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::io;
+use std::marker::PhantomData;
+use std::str::FromStr;
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq, Copy, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub enum BootLocation {
+    New,
+    Modules,
+}
+
+impl Default for BootLocation {
+    fn default() -> Self {
+        BootLocation::New
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+#[serde(tag = "type")]
+pub enum CheckGroups {
+    None,
+    Previous,
+    File(CheckFile),
+    Data(CheckGroupsData),
+    Snapshot(CheckFile),
+    Commit(CheckCommit),
+    Container(CheckContainer),
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct CheckFile {
+    pub filename: String,
+}
+
+/// A commit in a (possibly remote) OSTree repository.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq, Clone)]
+#[non_exhaustive]
+pub struct CheckCommit {
+    /// URL of the repository, `file://` for a local one.
+    pub repo: String,
+    /// Ref or checksum of the commit.
+    #[serde(rename = "ref")]
+    pub rev: String,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq, Clone)]
+#[non_exhaustive]
+pub struct CheckContainer {
+    /// An ostree image reference, e.g. `ostree-unverified-registry:quay.io/exampleos/os:stable`.
+    pub image: String,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct CheckGroupsData {
+    pub entries: BTreeMap<String, u32>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+#[serde(tag = "type")]
+pub enum CheckPasswd {
+    None,
+    Previous,
+    File(CheckFile),
+    Data(CheckPasswdData),
+    Snapshot(CheckFile),
+    Commit(CheckCommit),
+    Container(CheckContainer),
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct CheckPasswdData {
+    pub entries: BTreeMap<String, CheckPasswdDataEntries>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum CheckPasswdDataEntries {
+    IdValue(u32),
+    IdTuple([u32; 1]),
+    UidGid((u32, u32)),
+}
+
+impl CheckPasswdDataEntries {
+    /// Return IDs for user and group.
+    pub fn ids(&self) -> (u32, u32) {
+        match self {
+            CheckPasswdDataEntries::IdValue(v) => (*v, *v),
+            CheckPasswdDataEntries::IdTuple([v]) => (*v, *v),
+            CheckPasswdDataEntries::UidGid(v) => *v,
+        }
+    }
+}
+
+impl From<u32> for CheckPasswdDataEntries {
+    fn from(item: u32) -> Self {
+        Self::IdValue(item)
+    }
+}
+
+impl From<[u32; 1]> for CheckPasswdDataEntries {
+    fn from(item: [u32; 1]) -> Self {
+        Self::IdTuple(item)
+    }
+}
+
+impl From<(u32, u32)> for CheckPasswdDataEntries {
+    fn from(item: (u32, u32)) -> Self {
+        Self::UidGid(item)
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Rojig {
+    pub name: String,
+    pub summary: String,
+    pub license: String,
+    pub description: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub struct SecurebootAudit {
+    /// Certificates (PEM or DER) the kernel and modules must be signed with;
+    /// relative paths are resolved against the directory of the treefile.
+    pub certificates: Vec<String>,
+    /// Fail the compose if a file is not signed with one of them. Defaults to
+    /// `false`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(default = "default_false")]
+    pub fatal: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub struct OwnershipAudit {
+    /// Where to write the JSON report of the files whose owner doesn't
+    /// resolve; relative paths are resolved against the directory of the
+    /// treefile.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub report: Option<String>,
+    /// Fail the compose if there is such a file. Defaults to `false`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(default = "default_false")]
+    pub fatal: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub struct DiskImage {
+    /// Size of the image, in bytes or with a `K`, `M`, `G` or `T` suffix
+    /// (powers of 1024), e.g. `10G`.
+    pub size: String,
+    /// Defaults to `qcow2`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(default = "default_disk_image_format")]
+    pub format: Option<DiskImageFormat>,
+    /// Filesystem of the root partition. Defaults to `xfs`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(default = "default_disk_image_filesystem")]
+    pub filesystem: Option<DiskImageFilesystem>,
+    /// The ostree stateroot; by default, the `ID` of the os-release of the
+    /// commit.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub osname: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kargs: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub luks: Option<DiskImageLuks>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ignition: Option<DiskImageIgnition>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DiskImageFormat {
+    Qcow2,
+    Raw,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DiskImageFilesystem {
+    Xfs,
+    Ext4,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub struct DiskImageLuks {
+    /// File holding the passphrase the root partition is encrypted with;
+    /// relative paths are resolved against the directory of the treefile.
+    pub key_file: String,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub struct DiskImageIgnition {
+    /// The `ignition.platform.id` karg; by default, `qemu` for qcow2 images
+    /// and `metal` for raw ones.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub platform: Option<String>,
+    /// Config to embed in the image; relative paths are resolved against the
+    /// directory of the treefile.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Include {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+#[derive(Deserialize, JsonSchema, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ConditionalInclude {
+    #[serde(rename = "if")]
+    pub condition: IncludeConditions,
+    pub include: Include,
+}
+
+#[derive(Deserialize, JsonSchema, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum IncludeConditions {
+    Single(IncludeCondition),
+    Multiple(Vec<IncludeCondition>),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct IncludeCondition {
+    pub variable: String,
+    pub op: IncludeConditionOp,
+    pub value: VarValue,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum IncludeConditionOp {
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+}
+
+impl IncludeCondition {
+    pub fn evaluate(&self, vars: &BTreeMap<String, VarValue>) -> Result<bool> {
+        let val = vars
+            .get(&self.variable)
+            .ok_or_else(|| anyhow::anyhow!("undefined variable '{}'", &self.variable))?;
+        if !val.type_matches(&self.value) {
+            bail!("{} is not same type as {}", &self.variable, &self.value);
+        }
+        Ok(match &self.op {
+            IncludeConditionOp::Equal => val == &self.value,
+            IncludeConditionOp::NotEqual => val != &self.value,
+            numeric_op => match (val, &self.value) {
+                (VarValue::Number(a), VarValue::Number(b)) => match numeric_op {
+                    IncludeConditionOp::Greater => a > b,
+                    IncludeConditionOp::GreaterOrEqual => a >= b,
+                    IncludeConditionOp::Less => a < b,
+                    IncludeConditionOp::LessOrEqual => a <= b,
+                    _ => unreachable!(),
+                },
+                // we know they're the same type from type_matches(), and both invalid
+                _ => bail!("invalid op for values '{}' and '{}'", val, self.value),
+            },
+        })
+    }
+}
+
+impl TryFrom<&str> for IncludeConditionOp {
+    type Error = anyhow::Error;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        Ok(match s {
+            "==" => Self::Equal,
+            "!=" => Self::NotEqual,
+            ">" => Self::Greater,
+            ">=" => Self::GreaterOrEqual,
+            "<" => Self::Less,
+            "<=" => Self::LessOrEqual,
+            x => bail!("expected one of ==, !=, >, >=, <, <= but got {}", x),
+        })
+    }
+}
+
+impl<'de> serde::de::Deserialize<'de> for IncludeCondition {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::de::Deserializer<'de>,
+    {
+        use serde::de::Error;
+
+        static COND: Lazy<Regex> = Lazy::new(|| {
+            Regex::new(
+                r#"(?x)^
+                \s*
+                (?P<var>[[:alnum:]_]+)     # variable name
+                \s*
+                (?P<op>\S+)                # operator
+                \s*
+                ((?P<true>true)|           # value; we let regex do all the hard work here
+                 (?P<false>false)|         # and use named groups to know which one matched
+                 (?P<number>[[:digit:]]+)|
+                 "(?P<string>.*)")
+                \s*
+                $"#,
+            )
+            .unwrap()
+        });
+        let s: String = String::deserialize(deserializer)?;
+        if let Some(caps) = COND.captures(&s) {
+            let value = {
+                if caps.name("true").is_some() {
+                    VarValue::Bool(true)
+                } else if caps.name("false").is_some() {
+                    VarValue::Bool(false)
+                } else if let Some(number) = caps.name("number") {
+                    VarValue::Number(u64::from_str(number.as_str()).map_err(D::Error::custom)?)
+                } else if let Some(s) = caps.name("string") {
+                    VarValue::String(s.as_str().into())
+                } else {
+                    unreachable!()
+                }
+            };
+            return Ok(IncludeCondition {
+                variable: caps.name("var").unwrap().as_str().into(),
+                op: caps
+                    .name("op")
+                    .unwrap()
+                    .as_str()
+                    .try_into()
+                    .map_err(D::Error::custom)?,
+                value,
+            });
+        }
+        Err(D::Error::custom(format!("invalid condition: {}", &s)))
+    }
+}
+
+impl JsonSchema for IncludeCondition {
+    fn schema_name() -> String {
+        "IncludeCondition".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        let description = "A condition of the form `VARIABLE OP VALUE`, where OP is one of \
+                           `==`, `!=`, `>`, `>=`, `<` or `<=`";
+        SchemaObject {
+            instance_type: Some(InstanceType::String.into()),
+            metadata: Some(Box::new(Metadata {
+                description: Some(description.into()),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+// this is like a subset of serde_json::Value
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq, Clone)]
+#[serde(untagged)]
+pub enum VarValue {
+    Bool(bool),
+    Number(u64),
+    String(String),
+}
+
+impl std::fmt::Display for VarValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VarValue::Bool(b) => write!(f, "{}", b),
+            VarValue::Number(u) => write!(f, "{}", u),
+            VarValue::String(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl VarValue {
+    fn type_matches(&self, other: &Self) -> bool {
+        matches!(
+            (self, other),
+            (VarValue::Bool(_), VarValue::Bool(_))
+                | (VarValue::Number(_), VarValue::Number(_))
+                | (VarValue::String(_), VarValue::String(_))
+        )
+    }
+}
+
+// Ughh awkward; this is *almost* VarValue, but we don't want to support `bool` in this
+// case. Really tempting to use derive_more for #[derive(Display)]...
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq, Clone)]
+#[serde(untagged)]
+pub enum ReleaseVer {
+    Number(u64),
+    String(String),
+}
+
+impl std::fmt::Display for ReleaseVer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReleaseVer::Number(u) => write!(f, "{}", u),
+            ReleaseVer::String(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl From<ReleaseVer> for VarValue {
+    fn from(r: ReleaseVer) -> Self {
+        match r {
+            ReleaseVer::Number(u) => VarValue::Number(u),
+            ReleaseVer::String(s) => VarValue::String(s),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum RepoMetadataTarget {
+    Inline,
+    Detached,
+    Disabled,
+}
+
+impl RepoMetadataTarget {
+    const DEFAULT: Self = Self::Inline;
+
+    fn is_default(value: &Self) -> bool {
+        *value == Self::DEFAULT
+    }
+}
+
+impl Default for RepoMetadataTarget {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+/// The database backend; see https://github.com/coreos/fedora-coreos-tracker/issues/609
+/// and https://fedoraproject.org/wiki/Changes/Sqlite_Rpmdb
+pub enum RpmdbBackend {
+    // We messed up the name originally, allow this for backcompat.  xref https://github.com/openshift/os/issues/552
+    /// Deprecated; treated as `target`.
+    #[serde(alias = "b-d-b")]
+    Bdb,
+    /// Deprecated; treated as `target`.
+    Sqlite,
+    /// Deprecated; treated as `target`.
+    Ndb,
+    Target,
+    Host,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+/// The on-disk format of the rpmdb, which it's converted to if needed.
+pub enum RpmdbFormat {
+    Bdb,
+    Ndb,
+    Sqlite,
+}
+
+// Because of how we handle includes, *everything* here has to be
+// Option<T>.  The defaults live in the code (e.g. machineid-compat defaults
+// to `true`).
+/// Things that live *directly* in this struct are in common to both the base compose and derive
+/// cases. Everything else is specific to either case and so lives in their respective flattened
+/// field.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+#[schemars(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct TreeComposeConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub packages: Option<BTreeSet<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repo_packages: Option<Vec<RepoPackage>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modules: Option<ModulesConfig>,
+    /// Defaults to `false`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(default = "default_false")]
+    pub cliwrap: Option<bool>,
+
+    #[serde(flatten)]
+    pub derive: DeriveConfigFields,
+
+    #[serde(flatten)]
+    pub base: BaseComposeConfigFields,
+}
+
+impl std::ops::Deref for TreeComposeConfig {
+    type Target = BaseComposeConfigFields;
+
+    fn deref(&self) -> &Self::Target {
+        &self.base
+    }
+}
+
+impl std::ops::DerefMut for TreeComposeConfig {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.base
+    }
+}
+
+/// These fields are only useful when composing a new ostree commit.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+#[schemars(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct BaseComposeConfigFields {
+    // Compose controls
+    #[serde(rename = "ref")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub treeref: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub basearch: Option<String>,
+    #[serde(skip_serializing)]
+    pub variables: Option<BTreeMap<String, VarValue>>,
+    // Optional rojig data
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rojig: Option<Rojig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repos: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lockfile_repos: Option<Vec<String>>,
+    /// Defaults to `true`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(default = "default_true")]
+    pub selinux: Option<bool>,
+    /// Defaults to `false`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(default = "default_false")]
+    pub ima: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ima_sign_key: Option<String>,
+    /// Defaults to `sha256`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(default = "default_ima_sign_algorithm")]
+    pub ima_sign_algorithm: Option<String>,
+    /// Defaults to `false`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(default = "default_false")]
+    pub ima_verify: Option<bool>,
+    /// Defaults to `false`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(default = "default_false")]
+    pub fsverity: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secureboot_audit: Option<SecurebootAudit>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rpm_signing_keys: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forbidden_licenses: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forbidden_packages: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gpg_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include: Option<Include>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arch_include: Option<BTreeMap<String, Include>>,
+    // Skip serializing for now; it's consumed during parsing anyway. It does
+    // mean though that we can't construct a treefile with it and render it to
+    // disk, which is unlikely we'll ever need. Still, for consistency we
+    // should either implement serialization for this or also skip serializing
+    // the other include knobs above.
+    #[serde(skip_serializing)]
+    pub conditional_include: Option<Vec<ConditionalInclude>>,
+
+    // Core content
+    /// Deprecated; include these in `packages` instead.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Deprecated<Option<BTreeSet<String>>>")]
+    pub bootstrap_packages: Option<BTreeSet<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ostree_layers: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ostree_override_layers: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exclude_packages: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub platform_module: Option<String>,
+
+    // Content installation opts
+    /// Defaults to `false`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(default = "default_false")]
+    pub container: Option<bool>,
+    /// Defaults to `true`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(default = "default_true")]
+    pub recommends: Option<bool>,
+    /// Defaults to `true`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(default = "default_true")]
+    pub documentation: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub install_langs: Option<Vec<String>>,
+    /// Deprecated; use a drop-in in `/etc/dracut.conf.d` instead.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Deprecated<Option<Vec<String>>>")]
+    pub initramfs_args: Option<Vec<String>>,
+    /// Defaults to `false`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(default = "default_false")]
+    pub readonly_executables: Option<bool>,
+
+    // Tree layout options
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub boot_location: Option<BootLocation>,
+    /// Defaults to `false`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(default = "default_false")]
+    pub tmp_is_dir: Option<bool>,
+
+    // systemd
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub units: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_target: Option<String>,
+    /// Defaults to `true`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(default = "default_true")]
+    pub machineid_compat: Option<bool>,
+
+    // versioning
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub releasever: Option<ReleaseVer>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub automatic_version_prefix: Option<String>,
+    /// Defaults to `.`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(default = "default_automatic_version_suffix")]
+    pub automatic_version_suffix: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mutate_os_release: Option<String>,
+
+    // passwd-related bits
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub etc_group_members: Option<Vec<String>>,
+    /// Defaults to `true`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(default = "default_true")]
+    pub preserve_passwd: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub check_passwd: Option<CheckPasswd>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub check_groups: Option<CheckGroups>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ignore_removed_users: Option<BTreeSet<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ignore_removed_groups: Option<BTreeSet<String>>,
+    /// Defaults to `false`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(default = "default_false")]
+    pub generate_sysusers: Option<bool>,
+    /// Defaults to `false`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(default = "default_false")]
+    pub systemd_homed: Option<bool>,
+    /// Defaults to `true`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(default = "default_true")]
+    pub nss_altfiles: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    // This one references an external filename
+    pub pinned_ids: Option<String>,
+
+    // Content manipulation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    // This one references an external filename
+    pub postprocess_script: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    // This one is inline, and supports multiple (hence is useful for inheritance)
+    pub postprocess: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub add_files: Option<Vec<(String, String)>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remove_files: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ownership: Option<BTreeMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ownership_audit: Option<OwnershipAudit>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remove_from_packages: Option<Vec<Vec<String>>>,
+    // The BTreeMap here is on purpose; it ensures we always re-serialize in sorted order so that
+    // checksumming is deterministic across runs. (And serde itself uses BTreeMap for child objects
+    // as well).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub add_commit_metadata: Option<BTreeMap<String, serde_json::Value>>,
+    /// Defaults to `inline`.
+    #[serde(default, skip_serializing_if = "RepoMetadataTarget::is_default")]
+    pub repo_metadata: RepoMetadataTarget,
+    /// Defaults to `target`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(default = "default_rpmdb")]
+    pub rpmdb: Option<RpmdbBackend>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rpmdb_format: Option<RpmdbFormat>,
+    /// Defaults to `false`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(default = "default_false")]
+    pub rpmdb_normalize: Option<bool>,
+
+    // Container related bits
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container_cmd: Option<Vec<String>>,
+
+    // Disk images
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disk_image: Option<DiskImage>,
+
+    #[serde(flatten)]
+    pub legacy_fields: LegacyTreeComposeConfigFields,
+
+    // This is used to support `packages-${arch}` keys. For YAML files, any other keys cause an
+    // error. For JSON files, unknown keys are silently ignored.
+    #[serde(flatten)]
+    #[schemars(schema_with = "packages_arch_schema")]
+    pub extra: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RepoPackage {
+    pub repo: String,
+    pub packages: BTreeSet<String>,
+}
+
+impl RepoPackage {
+    pub fn new(repo: impl Into<String>, packages: BTreeSet<String>) -> Self {
+        Self {
+            repo: repo.into(),
+            packages,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Default, PartialEq, Eq, Clone)]
+#[non_exhaustive]
+pub struct ModulesConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enable: Option<BTreeSet<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub install: Option<BTreeSet<String>>,
+}
+
+impl ModulesConfig {
+    pub fn new(enable: Option<BTreeSet<String>>, install: Option<BTreeSet<String>>) -> Self {
+        Self { enable, install }
+    }
+}
+
+/// Split a module spec of the form `NAME[:STREAM][/PROFILE]` into its
+/// name and (optional) stream.
+pub fn module_spec_name_stream(spec: &str) -> (&str, Option<&str>) {
+    let spec = spec.split_once('/').map(|(s, _)| s).unwrap_or(spec);
+    match spec.split_once(':') {
+        Some((name, stream)) => (name, Some(stream)),
+        None => (spec, None),
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Default, PartialEq, Eq)]
+#[schemars(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct LegacyTreeComposeConfigFields {
+    /// Deprecated; use `gpg-key` instead.
+    #[serde(skip_serializing)]
+    #[schemars(with = "Deprecated<Option<String>>")]
+    pub gpg_key: Option<String>,
+    /// Deprecated; use `boot-location` instead.
+    #[serde(skip_serializing)]
+    #[schemars(with = "Deprecated<Option<BootLocation>>")]
+    pub boot_location: Option<BootLocation>,
+    /// Deprecated; use `default-target` instead.
+    #[serde(skip_serializing)]
+    #[schemars(with = "Deprecated<Option<String>>")]
+    pub default_target: Option<String>,
+    /// Deprecated; use `automatic-version-prefix` instead.
+    #[serde(skip_serializing)]
+    #[schemars(with = "Deprecated<Option<String>>")]
+    pub automatic_version_prefix: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Default, PartialEq, Eq, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub struct DeriveCustom {
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+impl DeriveCustom {
+    pub fn new(url: impl Into<String>, description: Option<String>) -> Self {
+        Self {
+            url: url.into(),
+            description,
+        }
+    }
+}
+
+/// Settings for pulling the container image, overriding the daemon configuration.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Default, PartialEq, Eq, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub struct DeriveContainerPull {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_verify: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+    /// In seconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Default, PartialEq, Eq, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub struct DeriveInitramfs {
+    pub regenerate: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub etc: Option<BTreeSet<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub args: Option<Vec<String>>,
+    /// Dracut modules to include, written to a configuration drop-in
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub add_modules: Option<BTreeSet<String>>,
+    /// Dracut modules to omit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub omit_modules: Option<BTreeSet<String>>,
+    /// Kernel drivers to include
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub add_drivers: Option<BTreeSet<String>>,
+    /// The generator to use instead of dracut
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generator: Option<String>,
+}
+
+/// Changes of the kernel arguments relative to those of the base, reapplied when rebasing.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Default, PartialEq, Eq, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub struct DeriveKargs {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub append: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delete: Option<Vec<String>>,
+    /// The enabled profiles, with the kernel arguments they added.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profiles: Option<BTreeMap<String, Vec<String>>>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub enum RemoteOverrideReplaceFrom {
+    Repo(String),
+}
+
+impl std::fmt::Display for RemoteOverrideReplaceFrom {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RemoteOverrideReplaceFrom::Repo(r) => write!(f, "repo={}", r),
+        }
+    }
+}
+
+impl FromStr for RemoteOverrideReplaceFrom {
+    type Err = anyhow::Error;
+
+    /// Parse a source in `KIND=NAME` format, e.g. `repo=updates-testing`.
+    fn from_str(s: &str) -> Result<Self> {
+        let (kind, name) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Not in KIND=NAME format: {}", s))?;
+        if name.is_empty() {
+            bail!("Empty name: {}", s);
+        }
+        match kind {
+            "repo" => Ok(Self::Repo(name.to_string())),
+            x => bail!("Invalid kind '{}'", x),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub struct RemoteOverrideReplace {
+    pub from: RemoteOverrideReplaceFrom,
+    // In the future, some `from`s could support not specifying packages.
+    // See discussions in https://github.com/coreos/rpm-ostree/issues/1265.
+    // Update `is_empty` below as necessary in that case.
+    pub packages: BTreeSet<String>,
+}
+
+impl RemoteOverrideReplace {
+    pub fn new(from: RemoteOverrideReplaceFrom, packages: BTreeSet<String>) -> Self {
+        Self { from, packages }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match self.from {
+            RemoteOverrideReplaceFrom::Repo(_) => self.packages.is_empty(),
+        }
+    }
+}
+
+/// These fields are only useful when deriving from a prior ostree commit;
+/// at the moment we only use them when translating an origin file
+/// to a treefile for client side assembly.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Default, PartialEq, Eq, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[schemars(deny_unknown_fields)]
+#[non_exhaustive]
+pub struct DeriveConfigFields {
+    // this is used for ref types ostree/checksum
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_refspec: Option<String>,
+    // this is used for ref types ostree/checksum
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container_image_reference: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container_pull: Option<DeriveContainerPull>,
+    /// The `[bootc]` group of the origin on hosts managed by bootc; we don't
+    /// interpret it, only carry it over so bootc keeps recognizing deployments
+    /// written by us.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bootc: Option<BTreeMap<String, String>>,
+
+    // Packages
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub packages_local: Option<BTreeMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub packages_local_fileoverride: Option<BTreeMap<String, String>>,
+    /// NEVRAs of local packages whose signature could not be verified on import.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub packages_local_unverified: Option<BTreeSet<String>>,
+    /// Package requests which are automatically reset after a number of boots.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub packages_transient: Option<BTreeMap<String, u32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub override_remove: Option<BTreeSet<String>>,
+    #[serde(rename = "ex-override-replace")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub override_replace: Option<Vec<RemoteOverrideReplace>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub override_replace_local: Option<BTreeMap<String, String>>,
+
+    // Initramfs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub initramfs: Option<DeriveInitramfs>,
+
+    // Kernel arguments
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kargs: Option<DeriveKargs>,
+
+    // FIPS mode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fips: Option<bool>,
+
+    // Custom origin
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub custom: Option<DeriveCustom>,
+
+    // Misc
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub override_commit: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unconfigured_state: Option<String>,
+}
+
+/// Marks a field as deprecated in the JSON schema; the schema is otherwise that of `T`.
+struct Deprecated<T>(PhantomData<T>);
+
+impl<T: JsonSchema> JsonSchema for Deprecated<T> {
+    fn is_referenceable() -> bool {
+        false
+    }
+
+    fn schema_name() -> String {
+        T::schema_name()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        let mut schema = gen.subschema_for::<T>().into_object();
+        schema.metadata().deprecated = true;
+        schema.into()
+    }
+
+    // Keeps `Option` fields out of `required`
+    fn _schemars_private_is_option() -> bool {
+        T::_schemars_private_is_option()
+    }
+}
+
+/// The schema of `extra`: `packages-${arch}` keys, and nothing else.
+fn packages_arch_schema(gen: &mut SchemaGenerator) -> Schema {
+    let mut schema = SchemaObject {
+        instance_type: Some(InstanceType::Object.into()),
+        ..Default::default()
+    };
+    let object = schema.object();
+    object
+        .pattern_properties
+        .insert("^packages-.+$".into(), gen.subschema_for::<Vec<String>>());
+    object.additional_properties = Some(Box::new(false.into()));
+    schema.into()
+}
+
+// The defaults shown in the JSON schema; the code applies them where the
+// fields are used.
+fn default_true() -> Option<bool> {
+    Some(true)
+}
+
+fn default_false() -> Option<bool> {
+    Some(false)
+}
+
+fn default_ima_sign_algorithm() -> Option<String> {
+    Some("sha256".into())
+}
+
+fn default_automatic_version_suffix() -> Option<String> {
+    Some(".".into())
+}
+
+fn default_disk_image_format() -> Option<DiskImageFormat> {
+    Some(DiskImageFormat::Qcow2)
+}
+
+fn default_disk_image_filesystem() -> Option<DiskImageFilesystem> {
+    Some(DiskImageFilesystem::Xfs)
+}
+
+fn default_rpmdb() -> Option<RpmdbBackend> {
+    Some(RpmdbBackend::Target)
+}
+
+/// The JSON schema of treefiles.
+pub fn json_schema() -> RootSchema {
+    SchemaSettings::draft07()
+        .with(|s| s.option_add_null_type = false)
+        .into_generator()
+        .into_root_schema_for::<TreeComposeConfig>()
+}
+
+impl BaseComposeConfigFields {
+    pub fn error_if_nonempty(&self) -> Result<()> {
+        // Exemption for `basearch`, which we set to the current arch during parsing. Also mask
+        // `variables`, which can have `basearch` be auto-added but doesn't matter in this context
+        // since it's not used during the actual compose, only during treefile processing itself.
+        let s = Self {
+            basearch: self.basearch.clone(),
+            variables: self.variables.clone(),
+            ..Default::default()
+        };
+        if &s != self {
+            let j = serde_json::to_string_pretty(self)?;
+            bail!("the following base fields are not supported:\n{}", j);
+        }
+        Ok(())
+    }
+}
+
+impl DeriveConfigFields {
+    pub fn error_if_nonempty(&self) -> Result<()> {
+        if &Self::default() != self {
+            let j = serde_json::to_string_pretty(self)?;
+            bail!("the following derivation fields are not supported:\n{}", j);
+        }
+        Ok(())
+    }
+}
+
+fn substitute_string(vars: &HashMap<String, String>, s: &mut String) -> Result<()> {
+    if envsubst::is_templated(&s) {
+        *s = envsubst::substitute(s.clone(), vars).map_err(anyhow::Error::msg)?
+    }
+    Ok(())
+}
+
+fn substitute_string_option(
+    vars: &HashMap<String, String>,
+    field: &mut Option<String>,
+) -> Result<()> {
+    if let Some(ref mut s) = field {
+        substitute_string(vars, s)?;
+    }
+    Ok(())
+}
+
+impl TreeComposeConfig {
+    /// Look for use of legacy/renamed fields and migrate them to the new field.
+    pub(crate) fn migrate_legacy_fields(mut self) -> Result<Self> {
+        macro_rules! migrate_field {
+            ( $field:ident ) => {{
+                if self.base.legacy_fields.$field.is_some() && self.base.$field.is_some() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Cannot use new and legacy forms of {}", stringify!($field)),
+                    )
+                    .into());
+                }
+                self.base.$field = self.base.$field.or(self.base.legacy_fields.$field.take());
+            }};
+        }
+
+        migrate_field!(gpg_key);
+        migrate_field!(boot_location);
+        migrate_field!(default_target);
+        migrate_field!(automatic_version_prefix);
+
+        Ok(self)
+    }
+
+    /// Look for use of ${variable} and replace it by its proper value
+    pub fn substitute_vars(mut self) -> Result<Self> {
+        // convert to strings for envsubst
+        let substvars: HashMap<String, String> = self
+            .base
+            .variables
+            .iter()
+            .flatten()
+            .map(|(k, v)| (k.clone(), v.to_string()))
+            .collect();
+        envsubst::validate_vars(&substvars)?;
+
+        substitute_string_option(&substvars, &mut self.base.treeref)?;
+        if let Some(ref mut rojig) = self.base.rojig {
+            substitute_string(&substvars, &mut rojig.name)?;
+            substitute_string(&substvars, &mut rojig.summary)?;
+            substitute_string_option(&substvars, &mut rojig.description)?;
+        }
+        if let Some(ref mut add_commit_metadata) = self.base.add_commit_metadata {
+            for val in add_commit_metadata.values_mut() {
+                if let serde_json::Value::String(ref mut s) = val {
+                    substitute_string(&substvars, s)?;
+                }
+            }
+        }
+        substitute_string_option(&substvars, &mut self.base.automatic_version_prefix)?;
+        substitute_string_option(&substvars, &mut self.base.mutate_os_release)?;
+        substitute_string_option(&substvars, &mut self.base.platform_module)?;
+
+        Ok(self)
+    }
+
+    pub fn get_check_passwd(&self) -> &CheckPasswd {
+        static DEFAULT: CheckPasswd = CheckPasswd::Previous;
+        self.base.check_passwd.as_ref().unwrap_or(&DEFAULT)
+    }
+
+    pub fn get_check_groups(&self) -> &CheckGroups {
+        static DEFAULT: CheckGroups = CheckGroups::Previous;
+        self.base.check_groups.as_ref().unwrap_or(&DEFAULT)
+    }
+
+    // we need to ensure that appended repo packages override earlier ones
+    // XXX: collapse elements with the same repo to a single element
+    pub fn handle_repo_packages_overrides(&mut self) {
+        if let Some(repo_packages) = self.repo_packages.as_mut() {
+            let mut seen_pkgs: HashSet<String> = HashSet::new();
+            // Create a temporary new filtered vec; see
+            // https://doc.rust-lang.org/std/iter/struct.Map.html#notes-about-side-effects for why
+            // the reverse and re-reverse due to the side effect during `map()`.
+            let mut v: Vec<_> = repo_packages
+                .drain(..)
+                .rev()
+                .map(|mut rp| {
+                    rp.packages.retain(|p| seen_pkgs.insert(p.into()));
+                    rp
+                })
+                .filter(|rp| !rp.packages.is_empty())
+                .collect();
+            // Now replace the original, re-reversing.
+            v.reverse();
+            *repo_packages = v;
+        }
+    }
+
+    // we need to ensure that appended replacements override earlier ones
+    // XXX: collapse elements with the same source to a single element
+    pub fn handle_repo_packages_override_replacements(&mut self) {
+        if let Some(override_replace) = self.derive.override_replace.as_mut() {
+            let mut seen_pkgs: HashSet<String> = HashSet::new();
+            // Create a temporary new filtered vec; see
+            // https://doc.rust-lang.org/std/iter/struct.Map.html#notes-about-side-effects for why
+            // the reverse and re-reverse due to the side effect during `map()`.
+            let mut v: Vec<_> = override_replace
+                .drain(..)
+                .rev()
+                .map(|mut ovr| {
+                    ovr.packages.retain(|p| seen_pkgs.insert(p.into()));
+                    ovr
+                })
+                .filter(|ovr| !ovr.is_empty())
+                .collect();
+            // Now replace the original, re-reversing.
+            v.reverse();
+            *override_replace = v;
+        }
+    }
+
+    /// Do some upfront semantic checks we can do beyond just the type safety serde provides.
+    pub fn validate(&self) -> Result<()> {
+        // check add-files
+        if let Some(files) = &self.base.add_files {
+            for (_, dest) in files.iter() {
+                if !add_files_path_is_valid(dest) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Unsupported path in add-files: {}", dest),
+                    )
+                    .into());
+                }
+            }
+        }
+        if let Some(version_suffix) = self.base.automatic_version_suffix.as_ref() {
+            if !(version_suffix.len() == 1 && version_suffix.is_ascii()) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Invalid automatic-version-suffix, must be exactly one ASCII character: {}",
+                        version_suffix
+                    ),
+                )
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Determines whether the origin hints at local assembly being required. In some
+    /// cases, no assembly might actually be required (e.g. if requested packages are
+    /// already in the base). IOW:
+    ///    false --> definitely does not require local assembly
+    ///    true  --> maybe requires assembly, need to investigate further by doing work
+    pub fn may_require_local_assembly(&self) -> bool {
+        self.cliwrap.unwrap_or_default() ||
+            self.derive.initramfs.as_ref().map(|i| i.regenerate).unwrap_or_default() ||
+            self.derive
+                .initramfs
+                .as_ref()
+                .and_then(|i| i.etc.as_ref())
+                .map(|v| !v.is_empty())
+                .unwrap_or_default() ||
+            self.has_any_packages() ||
+            // Technically, alone it doesn't require require assembly, but it still
+            // requires fetching repo metadata to validate (remember: modules are a
+            // pure rpmmd concept). This means we may pay the cost of an unneeded
+            // tree checkout, but it's not worth trying to optimize for it.
+            self.modules
+                .as_ref()
+                .and_then(|m| m.enable.as_ref())
+                .map(|e| !e.is_empty())
+                .unwrap_or_default()
+    }
+
+    /// Returns true if this origin contains overlay or override packages.
+    pub fn has_any_packages(&self) -> bool {
+        fn nonempty<T: IntoIterator>(v: Option<T>) -> bool {
+            v.map(|v| v.into_iter().next().is_some())
+                .unwrap_or_default()
+        }
+        nonempty(self.packages.as_ref())
+            || nonempty(self.derive.packages_local.as_ref())
+            || nonempty(self.derive.packages_local_fileoverride.as_ref())
+            || self
+                .derive
+                .override_replace
+                .as_ref()
+                .map(|v| v.iter().any(|ovr| !ovr.is_empty()))
+                .unwrap_or_default()
+            || nonempty(self.derive.override_replace_local.as_ref())
+            || nonempty(self.derive.override_remove.as_ref())
+            || nonempty(self.modules.as_ref().and_then(|m| m.install.as_ref()))
+    }
+}
+
+// Similar to the importer check but just checks for prefixes since
+// they're files, and also allows /etc since it's before conversion
+fn add_files_path_is_valid(path: &str) -> bool {
+    let path = path.trim_start_matches('/');
+    (path.starts_with("usr/") && !path.starts_with("usr/local/"))
+        || path.starts_with("etc/")
+        || path.starts_with("bin/")
+        || path.starts_with("sbin/")
+        || path.starts_with("lib/")
+        || path.starts_with("lib64/")
+}
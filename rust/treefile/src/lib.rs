@@ -0,0 +1,27 @@
+//! Parsing, validation and serialization of rpm-ostree treefiles and origins,
+//! without the rest of rpm-ostree.
+//!
+//! A treefile is the declarative YAML or JSON manifest `rpm-ostree compose`
+//! builds an OSTree commit from; see
+//! [the documentation](https://coreos.github.io/rpm-ostree/treefile/).  The
+//! same data model describes the packages, overrides etc. layered on a
+//! deployment, which are stored in its "origin" key file.
+//!
+//! ```no_run
+//! # fn main() -> anyhow::Result<()> {
+//! let tf = rpmostree_treefile::parse_path("fedora-coreos.yaml", Some("x86_64"))?;
+//! println!("{}", serde_json::to_string_pretty(&tf)?);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The files a treefile references (e.g. `postprocess-script`) aren't read;
+//! use [`parse_path_with`] to resolve them.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+mod config;
+pub use config::*;
+mod parse;
+pub use parse::*;
+pub mod origin;
@@ -0,0 +1,760 @@
+//! An "origin" declares how a deployment was generated: the base it follows
+//! and the packages, overrides, kernel arguments etc. applied on top of it.
+//! Origins are stored as GLib key files; this converts them to and from the
+//! derivation fields of a treefile, for tools which inspect origins without
+//! GLib.  rpm-ostree itself reads and writes them with `GKeyFile`.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::config::*;
+use anyhow::{anyhow, bail, Result};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+pub const ORIGIN: &str = "origin";
+pub const RPMOSTREE: &str = "rpmostree";
+pub const PACKAGES: &str = "packages";
+pub const MODULES: &str = "modules";
+pub const OVERRIDES: &str = "overrides";
+pub const CONTAINER_PULL: &str = "container-pull";
+pub const KARGS_PROFILES: &str = "kargs-profiles";
+pub const BOOTC: &str = "bootc";
+
+/// The key of the container image reference in the `[origin]` group.
+pub const ORIGIN_CONTAINER: &str = "container-image-reference";
+
+/// A key file, in the subset of the format of GLib's `GKeyFile` which origins
+/// use: groups of `key=value` lines, where string values are escaped and
+/// lists are terminated by `;`.  Comments aren't preserved.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct KeyFile {
+    groups: Vec<(String, Vec<(String, String)>)>,
+}
+
+fn unescape(v: &str) -> Result<String> {
+    let mut r = String::with_capacity(v.len());
+    let mut chars = v.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            r.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => r.push(' '),
+            Some('n') => r.push('\n'),
+            Some('t') => r.push('\t'),
+            Some('r') => r.push('\r'),
+            Some('\\') => r.push('\\'),
+            Some(';') => r.push(';'),
+            _ => bail!("Invalid escape sequence in value: {}", v),
+        }
+    }
+    Ok(r)
+}
+
+fn escape(v: &str) -> String {
+    let mut r = String::with_capacity(v.len());
+    for (i, c) in v.chars().enumerate() {
+        match c {
+            ' ' if i == 0 => r.push_str("\\s"),
+            '\n' => r.push_str("\\n"),
+            '\t' => r.push_str("\\t"),
+            '\r' => r.push_str("\\r"),
+            '\\' => r.push_str("\\\\"),
+            c => r.push(c),
+        }
+    }
+    r
+}
+
+impl KeyFile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn group(&self, group: &str) -> Option<&[(String, String)]> {
+        self.groups
+            .iter()
+            .find(|(name, _)| name == group)
+            .map(|(_, entries)| entries.as_slice())
+    }
+
+    /// The names of the groups, in order.
+    pub fn groups(&self) -> impl Iterator<Item = &str> {
+        self.groups.iter().map(|(name, _)| name.as_str())
+    }
+
+    pub fn has_group(&self, group: &str) -> bool {
+        self.group(group).is_some()
+    }
+
+    /// The keys of `group`, in order; none if there's no such group.
+    pub fn keys<'a>(&'a self, group: &str) -> impl Iterator<Item = &'a str> {
+        self.group(group)
+            .unwrap_or_default()
+            .iter()
+            .map(|(k, _)| k.as_str())
+    }
+
+    /// The raw value of `key` in `group`.
+    pub fn value(&self, group: &str, key: &str) -> Option<&str> {
+        self.group(group)?
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// The value of `key` in `group`, unescaped.
+    pub fn string(&self, group: &str, key: &str) -> Result<Option<String>> {
+        self.value(group, key).map(unescape).transpose()
+    }
+
+    /// The `;`-separated list in `key` of `group`, unescaped.
+    pub fn string_list(&self, group: &str, key: &str) -> Result<Option<Vec<String>>> {
+        let v = match self.value(group, key) {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        let mut r = Vec::new();
+        let mut elt = String::new();
+        let mut escaped = false;
+        for c in v.chars() {
+            if escaped {
+                elt.push('\\');
+                elt.push(c);
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == ';' {
+                r.push(unescape(&elt)?);
+                elt.clear();
+            } else {
+                elt.push(c);
+            }
+        }
+        if escaped {
+            bail!("Invalid escape sequence in value: {}", v);
+        }
+        if !elt.is_empty() {
+            r.push(unescape(&elt)?);
+        }
+        Ok(Some(r))
+    }
+
+    pub fn boolean(&self, group: &str, key: &str) -> Result<Option<bool>> {
+        self.value(group, key)
+            .map(|v| match v.trim_end() {
+                "true" | "1" => Ok(true),
+                "false" | "0" => Ok(false),
+                _ => Err(anyhow!("Invalid boolean for {}/{}: {}", group, key, v)),
+            })
+            .transpose()
+    }
+
+    pub fn uint64(&self, group: &str, key: &str) -> Result<Option<u64>> {
+        self.value(group, key)
+            .map(|v| {
+                v.trim_end()
+                    .parse()
+                    .map_err(|e| anyhow!("Invalid integer for {}/{}: {}: {}", group, key, v, e))
+            })
+            .transpose()
+    }
+
+    /// Set the raw value of `key` in `group`, creating the group if needed.
+    pub fn set_value(&mut self, group: &str, key: &str, value: &str) {
+        let entries = match self.groups.iter().position(|(name, _)| name == group) {
+            Some(i) => &mut self.groups[i].1,
+            None => {
+                self.groups.push((group.to_string(), Vec::new()));
+                &mut self.groups.last_mut().unwrap().1
+            }
+        };
+        match entries.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => *v = value.to_string(),
+            None => entries.push((key.to_string(), value.to_string())),
+        }
+    }
+
+    pub fn set_string(&mut self, group: &str, key: &str, value: &str) {
+        self.set_value(group, key, &escape(value))
+    }
+
+    /// Set `key` of `group` to a list, or leave it unset if `values` is empty.
+    pub fn set_string_list<'a>(
+        &mut self,
+        group: &str,
+        key: &str,
+        values: impl IntoIterator<Item = &'a str>,
+    ) {
+        let mut v = String::new();
+        for elt in values {
+            v.push_str(&escape(elt).replace(';', "\\;"));
+            v.push(';');
+        }
+        if !v.is_empty() {
+            self.set_value(group, key, &v)
+        }
+    }
+
+    pub fn set_boolean(&mut self, group: &str, key: &str, value: bool) {
+        self.set_value(group, key, if value { "true" } else { "false" })
+    }
+
+    pub fn set_uint64(&mut self, group: &str, key: &str, value: u64) {
+        self.set_value(group, key, &value.to_string())
+    }
+
+    /// Remove `group`; returns whether it existed.
+    pub fn remove_group(&mut self, group: &str) -> bool {
+        let n = self.groups.len();
+        self.groups.retain(|(name, _)| name != group);
+        self.groups.len() != n
+    }
+}
+
+impl std::str::FromStr for KeyFile {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut r = KeyFile::new();
+        let mut group: Option<String> = None;
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim_start();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[') {
+                let name = name
+                    .trim_end()
+                    .strip_suffix(']')
+                    .ok_or_else(|| anyhow!("Invalid group on line {}: {}", i + 1, line))?;
+                if !r.has_group(name) {
+                    r.groups.push((name.to_string(), Vec::new()));
+                }
+                group = Some(name.to_string());
+                continue;
+            }
+            let group = group
+                .as_deref()
+                .ok_or_else(|| anyhow!("Key file does not start with a group"))?;
+            let (k, v) = line
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Invalid line {}: {}", i + 1, line))?;
+            r.set_value(group, k.trim_end(), v.trim_start());
+        }
+        Ok(r)
+    }
+}
+
+impl std::fmt::Display for KeyFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, (name, entries)) in self.groups.iter().enumerate() {
+            if i > 0 {
+                f.write_char('\n')?;
+            }
+            writeln!(f, "[{}]", name)?;
+            for (k, v) in entries {
+                writeln!(f, "{}={}", k, v)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parse the 2-tuple `<sha256>:<nevra>` string into a tuple of (nevra, sha256).
+fn decompose_sha256_nevra(v: &str) -> Result<(&str, &str)> {
+    let (sha256, nevra) = v
+        .split_once(':')
+        .ok_or_else(|| anyhow!("Missing : in {}", v))?;
+    if sha256.len() != 64
+        || !sha256
+            .bytes()
+            .all(|c| matches!(c, b'0'..=b'9' | b'a'..=b'f'))
+    {
+        bail!("Invalid checksum in {}", v);
+    }
+    Ok((nevra, sha256))
+}
+
+fn parse_stringlist<T>(kf: &KeyFile, group: &str, key: &str) -> Result<Option<T>>
+where
+    T: std::iter::FromIterator<String>,
+{
+    Ok(kf.string_list(group, key)?.map(|v| v.into_iter().collect()))
+}
+
+fn parse_localpkglist(
+    kf: &KeyFile,
+    group: &str,
+    key: &str,
+) -> Result<Option<BTreeMap<String, String>>> {
+    if let Some(v) = kf.string_list(group, key)? {
+        let mut r = BTreeMap::new();
+        for s in v {
+            let (nevra, sha256) = decompose_sha256_nevra(s.as_str())?;
+            r.insert(nevra.to_string(), sha256.to_string());
+        }
+        Ok(Some(r))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Parse a list of `PKG:BOOTS` entries.
+fn parse_transientpkglist(
+    kf: &KeyFile,
+    group: &str,
+    key: &str,
+) -> Result<Option<BTreeMap<String, u32>>> {
+    if let Some(v) = kf.string_list(group, key)? {
+        let mut r = BTreeMap::new();
+        for s in v {
+            let (pkg, boots) = s
+                .rsplit_once(':')
+                .ok_or_else(|| anyhow!("Invalid transient package entry: {}", s))?;
+            let boots: u32 = boots
+                .parse()
+                .map_err(|e| anyhow!("Invalid boot count in transient package {}: {}", s, e))?;
+            r.insert(pkg.to_string(), boots);
+        }
+        Ok(Some(r))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Convert an origin key file to a treefile config.
+///
+/// For historical reasons, rpm-ostree has two file formats to represent
+/// state.  This bridges parts of an origin file to a treefile that
+/// is understood by the core.
+pub fn to_config(kf: &KeyFile) -> Result<TreeComposeConfig> {
+    let mut cfg = TreeComposeConfig::default();
+    let base_refspec = if let Some(r) = kf.string(ORIGIN, "refspec")? {
+        Some(r)
+    } else {
+        kf.string(ORIGIN, "baserefspec")?
+    };
+
+    let container_image_reference = kf.string(ORIGIN, ORIGIN_CONTAINER)?;
+
+    match (base_refspec, container_image_reference) {
+        (Some(_), Some(_)) => bail!("Found both refspec/baserefspec and {}", ORIGIN_CONTAINER),
+        (None, None) => bail!(
+            "Failed to find refspec/baserefspec/{} in origin",
+            ORIGIN_CONTAINER
+        ),
+        (Some(s), None) => cfg.derive.base_refspec = Some(s),
+        (None, Some(s)) => cfg.derive.container_image_reference = Some(s),
+    }
+    cfg.packages = parse_stringlist(kf, PACKAGES, "requested")?;
+    cfg.derive.packages_local = parse_localpkglist(kf, PACKAGES, "requested-local")?;
+    cfg.derive.packages_local_fileoverride =
+        parse_localpkglist(kf, PACKAGES, "requested-local-fileoverride")?;
+    cfg.derive.packages_local_unverified = parse_stringlist(kf, PACKAGES, "local-unverified")?;
+    cfg.derive.packages_transient = parse_transientpkglist(kf, PACKAGES, "transient")?;
+    let modules_enable = parse_stringlist(kf, MODULES, "enable")?;
+    let modules_install = parse_stringlist(kf, MODULES, "install")?;
+    if modules_enable.is_some() || modules_install.is_some() {
+        cfg.modules = Some(ModulesConfig {
+            enable: modules_enable,
+            install: modules_install,
+        });
+    }
+    cfg.derive.override_remove = parse_stringlist(kf, OVERRIDES, "remove")?;
+    cfg.derive.override_replace_local = parse_localpkglist(kf, OVERRIDES, "replace-local")?;
+    cfg.derive.unconfigured_state = kf.string(ORIGIN, "unconfigured-state")?;
+
+    if let Some(strv) = parse_stringlist::<Vec<String>>(kf, OVERRIDES, "replace")? {
+        let mut override_replace = Vec::new();
+        for s in strv {
+            let mut split = s.split(',');
+            let from = split
+                .next()
+                .ok_or_else(|| anyhow!("Invalid repo replacement: {}", s))?;
+            override_replace.push(RemoteOverrideReplace {
+                from: from.parse()?,
+                packages: split.map(|s| s.to_string()).collect(),
+            });
+        }
+        cfg.derive.override_replace = Some(override_replace);
+    }
+
+    let regenerate_initramfs = kf
+        .boolean(RPMOSTREE, "regenerate-initramfs")
+        .unwrap_or_default()
+        .unwrap_or_default();
+    let initramfs_etc = parse_stringlist(kf, RPMOSTREE, "initramfs-etc")?;
+    let initramfs_args = parse_stringlist(kf, RPMOSTREE, "initramfs-args")?;
+    let add_modules = parse_stringlist(kf, RPMOSTREE, "initramfs-add-modules")?;
+    let omit_modules = parse_stringlist(kf, RPMOSTREE, "initramfs-omit-modules")?;
+    let add_drivers = parse_stringlist(kf, RPMOSTREE, "initramfs-add-drivers")?;
+    let generator = kf.string(RPMOSTREE, "initramfs-generator")?;
+    if regenerate_initramfs
+        || initramfs_etc.is_some()
+        || initramfs_args.is_some()
+        || add_modules.is_some()
+        || omit_modules.is_some()
+        || add_drivers.is_some()
+        || generator.is_some()
+    {
+        cfg.derive.initramfs = Some(DeriveInitramfs {
+            regenerate: regenerate_initramfs,
+            etc: initramfs_etc,
+            args: initramfs_args,
+            add_modules,
+            omit_modules,
+            add_drivers,
+            generator,
+        });
+    }
+
+    let kargs_append = parse_stringlist(kf, RPMOSTREE, "kargs-append")?;
+    let kargs_delete = parse_stringlist(kf, RPMOSTREE, "kargs-delete")?;
+    let mut kargs_profiles = BTreeMap::new();
+    for name in kf.keys(KARGS_PROFILES) {
+        let args = parse_stringlist(kf, KARGS_PROFILES, name)?.unwrap_or_default();
+        kargs_profiles.insert(name.to_string(), args);
+    }
+    let kargs_profiles = Some(kargs_profiles).filter(|p| !p.is_empty());
+    if kargs_append.is_some() || kargs_delete.is_some() || kargs_profiles.is_some() {
+        cfg.derive.kargs = Some(DeriveKargs {
+            append: kargs_append,
+            delete: kargs_delete,
+            profiles: kargs_profiles,
+        });
+    }
+
+    let pull = DeriveContainerPull {
+        proxy: kf.string(CONTAINER_PULL, "proxy")?,
+        tls_verify: kf.boolean(CONTAINER_PULL, "tls-verify")?,
+        retries: kf
+            .uint64(CONTAINER_PULL, "retries")?
+            .map(u32::try_from)
+            .transpose()?,
+        timeout: kf.uint64(CONTAINER_PULL, "timeout")?,
+    };
+    if pull != Default::default() {
+        cfg.derive.container_pull = Some(pull);
+    }
+
+    if kf.has_group(BOOTC) {
+        let bootc = kf
+            .keys(BOOTC)
+            .map(|k| (k.to_string(), kf.value(BOOTC, k).unwrap().to_string()))
+            .collect();
+        cfg.derive.bootc = Some(bootc);
+    }
+
+    if let Some(url) = kf.string(ORIGIN, "custom-url")? {
+        let description = kf.string(ORIGIN, "custom-description")?;
+        cfg.derive.custom = Some(DeriveCustom { url, description })
+    }
+
+    if kf.boolean(RPMOSTREE, "fips")?.unwrap_or_default() {
+        cfg.derive.fips = Some(true)
+    }
+
+    if kf.boolean(RPMOSTREE, "ex-cliwrap")?.unwrap_or_default() {
+        cfg.cliwrap = Some(true)
+    }
+
+    cfg.derive.override_commit = kf.string(ORIGIN, "override-commit")?;
+
+    Ok(cfg)
+}
+
+fn set_sha256_nevra_pkgs(kf: &mut KeyFile, group: &str, k: &str, pkgs: &BTreeMap<String, String>) {
+    let pkgs: Vec<_> = pkgs
+        .iter()
+        .map(|(nevra, sha256)| format!("{}:{}", sha256, nevra))
+        .collect();
+    kf.set_string_list(group, k, pkgs.iter().map(|s| s.as_str()))
+}
+
+/// Convert the derivation fields of a treefile config to an origin key file.
+pub fn from_config(tf: &TreeComposeConfig) -> Result<KeyFile> {
+    let mut kf = KeyFile::new();
+
+    if let Some(r) = tf.derive.base_refspec.as_deref() {
+        let k = if tf.may_require_local_assembly() {
+            "baserefspec"
+        } else {
+            "refspec"
+        };
+        kf.set_string(ORIGIN, k, r);
+    } else if let Some(r) = tf.derive.container_image_reference.as_deref() {
+        kf.set_string(ORIGIN, ORIGIN_CONTAINER, r);
+    } else {
+        bail!("Neither base-refspec nor container-image-reference is set");
+    }
+
+    // Packages
+    if let Some(pkgs) = tf.packages.as_ref() {
+        let pkgs = pkgs.iter().map(|s| s.as_str());
+        kf.set_string_list(PACKAGES, "requested", pkgs)
+    }
+    if let Some(pkgs) = tf.derive.packages_local.as_ref() {
+        set_sha256_nevra_pkgs(&mut kf, PACKAGES, "requested-local", pkgs)
+    }
+    if let Some(pkgs) = tf.derive.packages_local_fileoverride.as_ref() {
+        set_sha256_nevra_pkgs(&mut kf, PACKAGES, "requested-local-fileoverride", pkgs)
+    }
+    if let Some(pkgs) = tf.derive.packages_local_unverified.as_ref() {
+        // Only keep track of local packages which are still requested
+        let pkgs = pkgs
+            .iter()
+            .filter(|nevra| {
+                [
+                    &tf.derive.packages_local,
+                    &tf.derive.packages_local_fileoverride,
+                    &tf.derive.override_replace_local,
+                ]
+                .into_iter()
+                .filter_map(Option::as_ref)
+                .any(|m| m.contains_key(*nevra))
+            })
+            .map(|s| s.as_str());
+        kf.set_string_list(PACKAGES, "local-unverified", pkgs)
+    }
+    if let Some(pkgs) = tf.derive.packages_transient.as_ref() {
+        let pkgs: Vec<_> = pkgs
+            .iter()
+            .map(|(pkg, boots)| format!("{}:{}", pkg, boots))
+            .collect();
+        kf.set_string_list(PACKAGES, "transient", pkgs.iter().map(|s| s.as_str()))
+    }
+    if let Some(pkgs) = tf.derive.override_remove.as_ref() {
+        let pkgs = pkgs.iter().map(|s| s.as_str());
+        kf.set_string_list(OVERRIDES, "remove", pkgs)
+    }
+    if let Some(pkgs) = tf.derive.override_replace_local.as_ref() {
+        set_sha256_nevra_pkgs(&mut kf, OVERRIDES, "replace-local", pkgs)
+    }
+    if let Some(v) = tf.derive.override_replace.as_ref() {
+        let pkgs: Vec<String> = v
+            .iter()
+            .map(|ovr| {
+                let src = ovr.from.to_string();
+                let mut v = vec![src.as_str()];
+                v.extend(ovr.packages.iter().map(|s| s.as_str()));
+                v.join(",")
+            })
+            .collect();
+        kf.set_string_list(OVERRIDES, "replace", pkgs.iter().map(|s| s.as_str()));
+    }
+
+    if let Some(ref modcfg) = tf.modules {
+        if let Some(modules) = modcfg.enable.as_ref() {
+            let modules = modules.iter().map(|s| s.as_str());
+            kf.set_string_list(MODULES, "enable", modules)
+        }
+        if let Some(modules) = modcfg.install.as_ref() {
+            let modules = modules.iter().map(|s| s.as_str());
+            kf.set_string_list(MODULES, "install", modules)
+        }
+    }
+
+    // Initramfs bits
+    if let Some(initramfs) = tf.derive.initramfs.as_ref() {
+        if initramfs.regenerate {
+            kf.set_boolean(RPMOSTREE, "regenerate-initramfs", true);
+        }
+        if let Some(etc) = initramfs.etc.as_ref() {
+            let etc = etc.iter().map(|s| s.as_str());
+            kf.set_string_list(RPMOSTREE, "initramfs-etc", etc)
+        }
+        if let Some(args) = initramfs.args.as_deref() {
+            let args = args.iter().map(|s| s.as_str());
+            kf.set_string_list(RPMOSTREE, "initramfs-args", args)
+        }
+        for (key, names) in [
+            ("initramfs-add-modules", &initramfs.add_modules),
+            ("initramfs-omit-modules", &initramfs.omit_modules),
+            ("initramfs-add-drivers", &initramfs.add_drivers),
+        ] {
+            if let Some(names) = names.as_ref() {
+                let names = names.iter().map(|s| s.as_str());
+                kf.set_string_list(RPMOSTREE, key, names)
+            }
+        }
+        if let Some(generator) = initramfs.generator.as_deref() {
+            kf.set_string(RPMOSTREE, "initramfs-generator", generator);
+        }
+    }
+
+    if let Some(kargs) = tf.derive.kargs.as_ref() {
+        if let Some(args) = kargs.append.as_deref() {
+            let args = args.iter().map(|s| s.as_str());
+            kf.set_string_list(RPMOSTREE, "kargs-append", args)
+        }
+        if let Some(args) = kargs.delete.as_deref() {
+            let args = args.iter().map(|s| s.as_str());
+            kf.set_string_list(RPMOSTREE, "kargs-delete", args)
+        }
+        // Written even if empty, unlike the lists above
+        for (name, args) in kargs.profiles.iter().flatten() {
+            let v: String = args.iter().map(|a| format!("{};", a)).collect();
+            kf.set_value(KARGS_PROFILES, name, &v);
+        }
+    }
+
+    if let Some(pull) = tf.derive.container_pull.as_ref() {
+        if let Some(proxy) = pull.proxy.as_deref() {
+            kf.set_string(CONTAINER_PULL, "proxy", proxy);
+        }
+        if let Some(v) = pull.tls_verify {
+            kf.set_boolean(CONTAINER_PULL, "tls-verify", v);
+        }
+        if let Some(v) = pull.retries {
+            kf.set_uint64(CONTAINER_PULL, "retries", v.into());
+        }
+        if let Some(v) = pull.timeout {
+            kf.set_uint64(CONTAINER_PULL, "timeout", v);
+        }
+    }
+
+    // Verbatim, as this is bootc's
+    for (k, v) in tf.derive.bootc.iter().flatten() {
+        kf.set_value(BOOTC, k, v);
+    }
+
+    // Custom origin
+    if let Some(custom) = tf.derive.custom.as_ref() {
+        kf.set_string(ORIGIN, "custom-url", custom.url.as_str());
+        if let Some(desc) = custom.description.as_deref() {
+            kf.set_string(ORIGIN, "custom-description", desc);
+        }
+    }
+
+    if tf.derive.fips.unwrap_or_default() {
+        kf.set_boolean(RPMOSTREE, "fips", true)
+    }
+
+    if tf.cliwrap.unwrap_or_default() {
+        kf.set_boolean(RPMOSTREE, "ex-cliwrap", true)
+    }
+
+    if let Some(c) = tf.derive.override_commit.as_deref() {
+        kf.set_string(ORIGIN, "override-commit", c);
+    }
+
+    Ok(kf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    #[test]
+    fn test_keyfile() -> Result<()> {
+        let kf: KeyFile = indoc! {r"
+            # A comment
+            [origin]
+            refspec = fedora:fedora/36/x86_64/silverblue
+            custom-description=\sFrom\tsomewhere\\
+
+            [packages]
+            requested=vim;semi\;colon;
+            empty=
+
+            [rpmostree]
+            fips=true
+            regenerate-initramfs=0
+            bad-bool=yes
+        "}
+        .parse()?;
+        assert_eq!(
+            kf.groups().collect::<Vec<_>>(),
+            [ORIGIN, PACKAGES, RPMOSTREE]
+        );
+        assert_eq!(
+            kf.string(ORIGIN, "refspec")?.unwrap(),
+            "fedora:fedora/36/x86_64/silverblue"
+        );
+        assert_eq!(
+            kf.string(ORIGIN, "custom-description")?.unwrap(),
+            " From\tsomewhere\\"
+        );
+        assert_eq!(
+            kf.string_list(PACKAGES, "requested")?.unwrap(),
+            ["vim", "semi;colon"]
+        );
+        assert!(kf.string_list(PACKAGES, "empty")?.unwrap().is_empty());
+        assert_eq!(kf.string(PACKAGES, "missing")?, None);
+        assert_eq!(kf.boolean(RPMOSTREE, "fips")?, Some(true));
+        assert_eq!(kf.boolean(RPMOSTREE, "regenerate-initramfs")?, Some(false));
+        assert!(kf.boolean(RPMOSTREE, "bad-bool").is_err());
+        assert!("refspec=foo\n".parse::<KeyFile>().is_err());
+
+        let mut kf2 = KeyFile::new();
+        kf2.set_string(ORIGIN, "custom-description", " From\tsomewhere\\");
+        assert_eq!(
+            kf2.value(ORIGIN, "custom-description"),
+            kf.value(ORIGIN, "custom-description")
+        );
+        kf2.set_string_list(PACKAGES, "requested", ["vim", "semi;colon"]);
+        kf2.set_string_list(PACKAGES, "none", std::iter::empty());
+        assert_eq!(
+            kf2.to_string(),
+            "[origin]\ncustom-description=\\sFrom\\tsomewhere\\\\\n\n\
+             [packages]\nrequested=vim;semi\\;colon;\n"
+        );
+        assert_eq!(kf2.to_string().parse::<KeyFile>()?, kf2);
+        assert!(kf2.remove_group(PACKAGES));
+        assert!(!kf2.has_group(PACKAGES));
+        Ok(())
+    }
+
+    #[test]
+    fn test_origin_roundtrip() -> Result<()> {
+        let kf: KeyFile = indoc! {"
+            [origin]
+            baserefspec=fedora:fedora/36/x86_64/silverblue
+
+            [packages]
+            requested=fish;libvirt;
+            transient=fish:2;
+            requested-local=4ed748ba060fce4571e7ef19f3f5ed6209f67dbac8327af0d38ea70b96d2f723:foo-1.2-3.x86_64;
+
+            [overrides]
+            replace=repo=foobar,systemd;
+
+            [rpmostree]
+            kargs-append=console=ttyS0,115200n8;
+        "}
+        .parse()?;
+        let cfg = to_config(&kf)?;
+        assert_eq!(
+            cfg.derive.packages_local.as_ref().unwrap()["foo-1.2-3.x86_64"],
+            "4ed748ba060fce4571e7ef19f3f5ed6209f67dbac8327af0d38ea70b96d2f723"
+        );
+        assert_eq!(
+            cfg.derive.override_replace.as_ref().unwrap()[0].from,
+            RemoteOverrideReplaceFrom::Repo("foobar".into())
+        );
+        let newkf = from_config(&cfg)?;
+        for group in kf.groups() {
+            for key in kf.keys(group) {
+                assert_eq!(kf.value(group, key), newkf.value(group, key));
+            }
+        }
+        assert_eq!(to_config(&newkf)?, cfg);
+
+        // Without layering, it's a plain refspec
+        let kf: KeyFile = "[origin]\nbaserefspec=fedora:fedora/36/x86_64/silverblue\n".parse()?;
+        let newkf = from_config(&to_config(&kf)?)?;
+        assert_eq!(
+            newkf.value(ORIGIN, "refspec"),
+            Some("fedora:fedora/36/x86_64/silverblue")
+        );
+
+        let kf: KeyFile = "[origin]\nrefspec=foo\n[packages]\nrequested-local=foo;\n".parse()?;
+        assert!(to_config(&kf).is_err());
+        assert!(to_config(&KeyFile::new()).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,759 @@
+//! Parsing treefiles from YAML or JSON, including the files they include.
+
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+
+use crate::config::*;
+use anyhow::{bail, Context, Result};
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+const INCLUDE_MAXDEPTH: u32 = 50;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+/// Supported config serialization used by treefile and lockfile
+pub enum InputFormat {
+    YAML,
+    JSON,
+}
+
+impl InputFormat {
+    /// YAML for files ending in `.yaml` or `.yml`, JSON otherwise.
+    pub fn detect_from_filename<P: AsRef<Path>>(filename: P) -> Result<Self> {
+        let filename = filename.as_ref();
+        let basename = filename
+            .file_name()
+            .map(|s| s.to_string_lossy())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Expected a filename"))?;
+        if basename.ends_with(".yaml") || basename.ends_with(".yml") {
+            Ok(Self::YAML)
+        } else {
+            Ok(Self::JSON)
+        }
+    }
+}
+
+/// Given a lockfile/treefile config definition, parse it
+pub fn from_reader<T, R: io::Read>(fmt: &InputFormat, input: &mut R) -> Result<T>
+where
+    T: serde::de::DeserializeOwned,
+{
+    let parsed: T = match fmt {
+        InputFormat::JSON => {
+            let pf: T = serde_json::from_reader(input).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("serde-json: {}", e.to_string()),
+                )
+            })?;
+            pf
+        }
+        InputFormat::YAML => {
+            let pf: T = serde_yaml::from_reader(input).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("serde-yaml: {}", e.to_string()),
+                )
+            })?;
+            pf
+        }
+    };
+    Ok(parsed)
+}
+
+/// Parse a YAML or JSON treefile definition using base architecture `basearch`.
+/// This neither processes includes nor substitutes variables.
+pub fn parse_stream<R: io::Read>(
+    fmt: InputFormat,
+    input: &mut R,
+    basearch: Option<&str>,
+) -> Result<TreeComposeConfig> {
+    let mut treefile: TreeComposeConfig = from_reader(&fmt, input)?;
+
+    treefile.base.basearch = match (treefile.base.basearch, basearch) {
+        (Some(treearch), Some(arch)) => {
+            if treearch != arch {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Invalid basearch {} on {}: cross-composes are not supported",
+                        treearch, arch
+                    ),
+                )
+                .into());
+            } else {
+                Some(treearch)
+            }
+        }
+        (None, Some(arch)) => Some(arch.into()),
+        // really, only for tests do we not specify a basearch. let's just canonicalize to None
+        (_, None) => None,
+    };
+
+    if let Some(ref vars) = treefile.base.variables {
+        for reserved in ["releasever", "basearch"] {
+            if vars.contains_key(reserved) {
+                bail!("cannot define reserved variable '{}'", reserved);
+            }
+        }
+    }
+
+    // auto-mirror basearch and releasever to variables
+    if let Some(ref mut basearch) = treefile.base.basearch {
+        treefile
+            .base
+            .variables
+            .get_or_insert_with(BTreeMap::new)
+            .insert("basearch".into(), VarValue::String(basearch.clone()));
+    }
+    if let Some(ref mut releasever) = treefile.base.releasever {
+        treefile
+            .base
+            .variables
+            .get_or_insert_with(BTreeMap::new)
+            .insert("releasever".into(), releasever.clone().into());
+    }
+
+    // remove from packages-${arch} keys from the extra keys
+    let mut archful_pkgs: Option<BTreeSet<String>> = take_archful_pkgs(basearch, &mut treefile)?;
+
+    if fmt == InputFormat::YAML && !treefile.base.extra.is_empty() {
+        let keys: Vec<&str> = treefile.base.extra.keys().map(|k| k.as_str()).collect();
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Unknown fields: {}", keys.join(", ")),
+        )
+        .into());
+    }
+
+    // Special handling for packages, since we allow whitespace within items.
+    // We also canonicalize bootstrap_packages to packages here so it's
+    // easier to append the basearch packages after.
+    let pkgs = {
+        let mut pkgs: BTreeSet<String> = BTreeSet::new();
+        if let Some(base_pkgs) = treefile.packages.take() {
+            pkgs.append(&mut whitespace_split_packages(&base_pkgs)?);
+        }
+        if let Some(bootstrap_pkgs) = treefile.base.bootstrap_packages.take() {
+            pkgs.append(&mut whitespace_split_packages(&bootstrap_pkgs)?);
+        }
+        if let Some(archful_pkgs) = archful_pkgs.take() {
+            pkgs.append(&mut whitespace_split_packages(&archful_pkgs)?);
+        }
+        pkgs
+    };
+
+    // to be consistent, we also support whitespace-separated modules
+    if let Some(mut modules) = treefile.modules.take() {
+        if let Some(enable) = modules.enable.take() {
+            modules.enable = Some(whitespace_split_packages(&enable)?);
+        }
+        if let Some(install) = modules.install.take() {
+            modules.install = Some(whitespace_split_packages(&install)?);
+        }
+        treefile.modules = Some(modules);
+    }
+
+    // Whitespace split repo packages
+    if let Some(repo_packages) = treefile.repo_packages.as_mut() {
+        for rp in repo_packages {
+            rp.packages = whitespace_split_packages(&rp.packages)?;
+        }
+    }
+    if let Some(repo_packages) = treefile.derive.override_replace.as_mut() {
+        for rp in repo_packages {
+            rp.packages = whitespace_split_packages(&rp.packages)?;
+        }
+    }
+
+    treefile.packages = Some(pkgs);
+    treefile = treefile.migrate_legacy_fields()?;
+    Ok(treefile)
+}
+
+/// Sanity checks that the packages-${basearch} entries are well-formed, and returns the ones
+/// matching the current basearch.
+fn take_archful_pkgs(
+    basearch: Option<&str>,
+    treefile: &mut TreeComposeConfig,
+) -> Result<Option<BTreeSet<String>>> {
+    let mut archful_pkgs: Option<BTreeSet<String>> = None;
+
+    for key in treefile
+        .base
+        .extra
+        .keys()
+        .filter(|k| k.starts_with("packages-"))
+    {
+        if !treefile.base.extra[key].is_array()
+            || treefile.base.extra[key]
+                .as_array()
+                .unwrap()
+                .iter()
+                .any(|v| !v.is_string())
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid field {}: expected array of strings", key),
+            )
+            .into());
+        }
+
+        if let Some(basearch) = basearch {
+            if basearch == &key["packages-".len()..] {
+                assert!(archful_pkgs == None);
+                archful_pkgs = Some(
+                    treefile.base.extra[key]
+                        .as_array()
+                        .unwrap()
+                        .iter()
+                        .map(|v| v.as_str().unwrap().into())
+                        .collect(),
+                );
+            }
+        }
+    }
+
+    // and drop it from the map
+    treefile
+        .base
+        .extra
+        .retain(|k, _| !k.starts_with("packages-"));
+
+    Ok(archful_pkgs)
+}
+
+/// Merge a "basic" or non-array field. The semantics originally defined for
+/// these are that first-one wins.
+fn merge_basic_field<T>(dest: &mut Option<T>, src: &mut Option<T>) {
+    if dest.is_some() {
+        return;
+    }
+    *dest = src.take()
+}
+
+/// Merge a vector field by appending. This semantic was originally designed for
+/// the `packages` key.
+fn merge_vec_field<T>(dest: &mut Option<Vec<T>>, src: &mut Option<Vec<T>>) {
+    if let Some(mut srcv) = src.take() {
+        if let Some(ref mut destv) = dest {
+            srcv.append(destv);
+        }
+        *dest = Some(srcv);
+    }
+}
+
+/// Merge a map field similarly to Python's `dict.update()`. In case of
+/// duplicate keys, `dest` wins (`src` is the "included" config).
+fn merge_map_field<T>(
+    dest: &mut Option<BTreeMap<String, T>>,
+    src: &mut Option<BTreeMap<String, T>>,
+) {
+    if let Some(mut srcv) = src.take() {
+        if let Some(mut destv) = dest.take() {
+            srcv.append(&mut destv);
+        }
+        *dest = Some(srcv);
+    }
+}
+
+/// Merge an hashset field by extending.
+fn merge_hashset_field<T: Eq + std::hash::Hash + std::cmp::Ord>(
+    dest: &mut Option<BTreeSet<T>>,
+    src: &mut Option<BTreeSet<T>>,
+) {
+    if let Some(mut srcv) = src.take() {
+        if let Some(mut destv) = dest.take() {
+            srcv.append(&mut destv);
+        }
+        *dest = Some(srcv);
+    }
+}
+
+/// Merge modules fields.
+pub fn merge_modules(dest: &mut Option<ModulesConfig>, src: &mut Option<ModulesConfig>) {
+    if let Some(mut srcv) = src.take() {
+        if let Some(mut destv) = dest.take() {
+            merge_hashset_field(&mut destv.enable, &mut srcv.enable);
+            merge_hashset_field(&mut destv.install, &mut srcv.install);
+            srcv = destv;
+        }
+        *dest = Some(srcv);
+    }
+}
+
+/// Given two configs, merge them; `src` is the included config, and `dest`
+/// wins for fields which can't be combined.
+pub fn merge(dest: &mut TreeComposeConfig, src: &mut TreeComposeConfig) {
+    macro_rules! merge_basics {
+        ( $($field:ident),* ) => {{
+            $( merge_basic_field(&mut dest.base.$field, &mut src.base.$field); )*
+        }};
+    }
+    macro_rules! merge_hashsets {
+        ( $($field:ident),* ) => {{
+            $( merge_hashset_field(&mut dest.base.$field, &mut src.base.$field); )*
+        }};
+    }
+    macro_rules! merge_maps {
+        ( $($field:ident),* ) => {{
+            $( merge_map_field(&mut dest.base.$field, &mut src.base.$field); )*
+        }};
+    }
+    macro_rules! merge_vecs {
+        ( $($field:ident),* ) => {{
+            $( merge_vec_field(&mut dest.base.$field, &mut src.base.$field); )*
+        }};
+    }
+
+    merge_basics!(
+        treeref,
+        basearch,
+        rojig,
+        selinux,
+        ima,
+        ima_sign_key,
+        ima_sign_algorithm,
+        ima_verify,
+        fsverity,
+        secureboot_audit,
+        gpg_key,
+        include,
+        container,
+        recommends,
+        readonly_executables,
+        container_cmd,
+        disk_image,
+        documentation,
+        boot_location,
+        tmp_is_dir,
+        default_target,
+        machineid_compat,
+        releasever,
+        automatic_version_prefix,
+        automatic_version_suffix,
+        rpmdb,
+        rpmdb_format,
+        mutate_os_release,
+        preserve_passwd,
+        check_passwd,
+        check_groups,
+        generate_sysusers,
+        systemd_homed,
+        nss_altfiles,
+        pinned_ids,
+        ownership_audit,
+        postprocess_script,
+        rpmdb_normalize
+    );
+    merge_hashsets!(ignore_removed_groups, ignore_removed_users);
+    merge_maps!(add_commit_metadata, variables, ownership);
+    merge_vecs!(
+        repos,
+        lockfile_repos,
+        rpm_signing_keys,
+        forbidden_licenses,
+        forbidden_packages,
+        exclude_packages,
+        ostree_layers,
+        ostree_override_layers,
+        install_langs,
+        initramfs_args,
+        units,
+        etc_group_members,
+        postprocess,
+        add_files,
+        remove_files,
+        remove_from_packages
+    );
+
+    merge_hashset_field(&mut dest.bootstrap_packages, &mut src.bootstrap_packages);
+    merge_hashset_field(&mut dest.packages, &mut src.packages);
+    merge_vec_field(&mut dest.repo_packages, &mut src.repo_packages);
+    dest.handle_repo_packages_overrides();
+    merge_basic_field(&mut dest.cliwrap, &mut src.cliwrap);
+    merge_modules(&mut dest.modules, &mut src.modules);
+
+    merge_basic_field(&mut dest.derive.base_refspec, &mut src.derive.base_refspec);
+    merge_basic_field(
+        &mut dest.derive.container_image_reference,
+        &mut src.derive.container_image_reference,
+    );
+    merge_map_field(
+        &mut dest.derive.packages_local,
+        &mut src.derive.packages_local,
+    );
+    merge_map_field(
+        &mut dest.derive.packages_local_fileoverride,
+        &mut src.derive.packages_local_fileoverride,
+    );
+    merge_hashset_field(
+        &mut dest.derive.packages_local_unverified,
+        &mut src.derive.packages_local_unverified,
+    );
+    merge_map_field(
+        &mut dest.derive.packages_transient,
+        &mut src.derive.packages_transient,
+    );
+    merge_hashset_field(
+        &mut dest.derive.override_remove,
+        &mut src.derive.override_remove,
+    );
+    merge_vec_field(
+        &mut dest.derive.override_replace,
+        &mut src.derive.override_replace,
+    );
+    dest.handle_repo_packages_override_replacements();
+    merge_map_field(
+        &mut dest.derive.override_replace_local,
+        &mut src.derive.override_replace_local,
+    );
+    // XXX: should merge args
+    merge_basic_field(&mut dest.derive.initramfs, &mut src.derive.initramfs);
+    merge_basic_field(&mut dest.derive.kargs, &mut src.derive.kargs);
+    // no fancy merging for this
+    merge_basic_field(&mut dest.derive.custom, &mut src.derive.custom);
+    merge_basic_field(
+        &mut dest.derive.container_pull,
+        &mut src.derive.container_pull,
+    );
+    merge_basic_field(
+        &mut dest.derive.override_commit,
+        &mut src.derive.override_commit,
+    );
+    merge_basic_field(
+        &mut dest.derive.unconfigured_state,
+        &mut src.derive.unconfigured_state,
+    );
+}
+
+/// For increased readability in YAML/JSON, we support whitespace in individual
+/// array elements.
+fn whitespace_split_packages(pkgs: &BTreeSet<String>) -> Result<BTreeSet<String>> {
+    let mut ret = BTreeSet::new();
+    for element in pkgs {
+        ret.extend(split_whitespace_unless_quoted(element)?.map(String::from));
+    }
+
+    Ok(ret)
+}
+
+// Helper for whitespace_split_packages().
+// Splits a String by whitespace unless substring is wrapped between single quotes
+// and returns split &str in an Iterator.
+fn split_whitespace_unless_quoted(element: &str) -> Result<impl Iterator<Item = &str>> {
+    let mut ret = vec![];
+    let mut start_index = 0;
+    let mut looping_over_quoted_pkg = false;
+    for (i, c) in element.chars().enumerate() {
+        if c == '\'' {
+            if looping_over_quoted_pkg {
+                ret.push(&element[start_index..i]);
+                looping_over_quoted_pkg = false;
+            } else {
+                ret.extend((&element[start_index..i]).split_whitespace());
+                looping_over_quoted_pkg = true;
+            }
+            start_index = i + 1;
+        }
+        if i == element.len() - 1 {
+            if looping_over_quoted_pkg {
+                bail!("Missing terminating quote: {}", element);
+            }
+            ret.extend((&element[start_index..]).split_whitespace());
+        }
+    }
+
+    Ok(ret.into_iter())
+}
+
+/// Open file for reading and provide context containing filename on failures.
+fn open_file(filename: &Path) -> Result<fs::File> {
+    fs::File::open(filename)
+        .with_context(|| format!("Can't open file {:?} for reading", filename.display()))
+}
+
+// parent() returns a slice of its buffer, so doesn't handle going up relative
+// paths well: https://github.com/rust-lang/rust/issues/36861
+fn parent_dir(filename: &Path) -> &Path {
+    match filename.parent() {
+        Some(p) if p.as_os_str() != "" => p,
+        _ => ".".as_ref(),
+    }
+}
+
+type IncludeMap = BTreeMap<(u64, u64), String>;
+
+/// Parse a single treefile, without its includes.
+fn parse_one(
+    filename: &Path,
+    basearch: Option<&str>,
+    seen_includes: &mut IncludeMap,
+) -> Result<TreeComposeConfig> {
+    let f = open_file(filename)?;
+    let meta = f.metadata()?;
+    let devino = (meta.dev(), meta.ino());
+    match seen_includes.entry(devino) {
+        Entry::Occupied(_) => bail!(
+            "Include loop detected; {} was already included",
+            filename.display()
+        ),
+        Entry::Vacant(e) => {
+            e.insert(filename.display().to_string());
+        }
+    };
+    let mut f = io::BufReader::new(f);
+    let fmt = InputFormat::detect_from_filename(filename)?;
+    let tf = parse_stream(fmt, &mut f, basearch).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Parsing {}: {}", filename.to_string_lossy(), e.to_string()),
+        )
+    })?;
+    Ok(tf)
+}
+
+/// Recursively parse a treefile, merging along the way.
+fn parse_recurse<F>(
+    filename: &Path,
+    basearch: Option<&str>,
+    depth: u32,
+    seen_includes: &mut IncludeMap,
+    variables: &mut BTreeMap<String, VarValue>,
+    visit: &mut F,
+) -> Result<TreeComposeConfig>
+where
+    F: FnMut(&Path, &TreeComposeConfig) -> Result<()>,
+{
+    let mut parsed = parse_one(filename, basearch, seen_includes)?;
+    visit(filename, &parsed)?;
+    let include = parsed
+        .base
+        .include
+        .take()
+        .unwrap_or_else(|| Include::Multiple(Vec::new()));
+    let mut includes = match include {
+        Include::Single(v) => vec![v],
+        Include::Multiple(v) => v,
+    };
+    // fold in all new variables
+    if let Some(mut new_vars) = parsed.base.variables.take() {
+        new_vars.append(variables);
+        *variables = new_vars;
+    }
+    if let Some(mut arch_includes) = parsed.base.arch_include.take() {
+        if let Some(basearch) = basearch {
+            if let Some(arch_include_value) = arch_includes.remove(basearch) {
+                match arch_include_value {
+                    Include::Single(v) => includes.push(v),
+                    Include::Multiple(v) => includes.extend(v),
+                }
+            }
+        }
+    }
+    if let Some(conditional_includes) = parsed.base.conditional_include.take() {
+        for conditional_include in conditional_includes {
+            let matches = match conditional_include.condition {
+                IncludeConditions::Single(c) => c.evaluate(variables)?,
+                IncludeConditions::Multiple(v) => v
+                    .iter()
+                    // note we always evaluate because we always want to report semantic errors
+                    // like type mismatches, even if the condition already would've evaluated false
+                    .try_fold(true, |prev, c| c.evaluate(variables).map(|b| prev && b))?,
+            };
+            if matches {
+                match conditional_include.include {
+                    Include::Single(v) => includes.push(v),
+                    Include::Multiple(v) => includes.extend(v),
+                }
+            }
+        }
+    }
+    for include_path in includes.iter() {
+        if depth == INCLUDE_MAXDEPTH {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Reached maximum include depth {}", INCLUDE_MAXDEPTH),
+            )
+            .into());
+        }
+        let include_path = parent_dir(filename).join(include_path);
+        let mut included = parse_recurse(
+            &include_path,
+            basearch,
+            depth + 1,
+            seen_includes,
+            variables,
+            visit,
+        )?;
+        merge(&mut parsed, &mut included);
+    }
+    Ok(parsed)
+}
+
+/// Parse the treefile `filename` for base architecture `basearch` (`None`
+/// meaning any), along with the files it includes, and finally postprocess
+/// it (substitute variables, validate the config).
+pub fn parse_path<P: AsRef<Path>>(
+    filename: P,
+    basearch: Option<&str>,
+) -> Result<TreeComposeConfig> {
+    parse_path_with(filename, basearch, |_, _| Ok(()))
+}
+
+/// Like [`parse_path`], but also call `visit` with the path and the content of
+/// each file as it's parsed, i.e. before its includes are merged into it.
+/// Files are visited in include order, starting with `filename`; this is useful
+/// to resolve the files referenced by a treefile (e.g. `postprocess-script`),
+/// which are relative to the file referencing them.
+pub fn parse_path_with<P, F>(
+    filename: P,
+    basearch: Option<&str>,
+    mut visit: F,
+) -> Result<TreeComposeConfig>
+where
+    P: AsRef<Path>,
+    F: FnMut(&Path, &TreeComposeConfig) -> Result<()>,
+{
+    let mut seen_includes = BTreeMap::new();
+    let mut variables = BTreeMap::new();
+    let mut parsed = parse_recurse(
+        filename.as_ref(),
+        basearch,
+        0,
+        &mut seen_includes,
+        &mut variables,
+        &mut visit,
+    )?;
+    if !variables.is_empty() {
+        parsed.base.variables = Some(variables);
+    }
+    let parsed = parsed.substitute_vars()?;
+    parsed.validate()?;
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_split_whitespace_unless_quoted() -> Result<()> {
+        // test single quoted package
+        let single_quoted_pkg = "'foobar >= 1.0'";
+        let pkgs: Vec<_> = split_whitespace_unless_quoted(&single_quoted_pkg)?.collect();
+        assert_eq!("foobar >= 1.0", pkgs[0]);
+
+        // test multiple quoted packages
+        let mult_quoted_pkg = "'foobar >= 1.0' 'quuz < 0.5' 'corge > 2'";
+        let pkgs: Vec<_> = split_whitespace_unless_quoted(&mult_quoted_pkg)?.collect();
+        assert_eq!("foobar >= 1.0", pkgs[0]);
+        assert_eq!("quuz < 0.5", pkgs[1]);
+        assert_eq!("corge > 2", pkgs[2]);
+
+        // test single unquoted package
+        let single_unquoted_pkg = "foobar";
+        let pkgs: Vec<_> = split_whitespace_unless_quoted(&single_unquoted_pkg)?.collect();
+        assert_eq!("foobar", pkgs[0]);
+
+        // test multiple unquoted packages
+        let mult_unquoted_pkg = "foobar quuz corge";
+        let pkgs: Vec<_> = split_whitespace_unless_quoted(&mult_unquoted_pkg)?.collect();
+        assert_eq!("foobar", pkgs[0]);
+        assert_eq!("quuz", pkgs[1]);
+        assert_eq!("corge", pkgs[2]);
+
+        // test different orderings of mixed quoted and unquoted packages
+        let mix_quoted_unquoted_pkgs = "'foobar >= 1.1' baz-package 'corge < 0.5'";
+        let pkgs: Vec<_> = split_whitespace_unless_quoted(&mix_quoted_unquoted_pkgs)?.collect();
+        assert_eq!("foobar >= 1.1", pkgs[0]);
+        assert_eq!("baz-package", pkgs[1]);
+        assert_eq!("corge < 0.5", pkgs[2]);
+        let mix_quoted_unquoted_pkgs = "corge 'foobar >= 1.1' baz-package";
+        let pkgs: Vec<_> = split_whitespace_unless_quoted(&mix_quoted_unquoted_pkgs)?.collect();
+        assert_eq!("corge", pkgs[0]);
+        assert_eq!("foobar >= 1.1", pkgs[1]);
+        assert_eq!("baz-package", pkgs[2]);
+        let mix_quoted_unquoted_pkgs = "corge 'foobar >= 1.1' baz-package 'quuz < 0.0.1'";
+        let pkgs: Vec<_> = split_whitespace_unless_quoted(&mix_quoted_unquoted_pkgs)?.collect();
+        assert_eq!("corge", pkgs[0]);
+        assert_eq!("foobar >= 1.1", pkgs[1]);
+        assert_eq!("baz-package", pkgs[2]);
+        assert_eq!("quuz < 0.0.1", pkgs[3]);
+
+        // test missing quotes around packages using version qualifiers
+        let missing_quotes = "foobar >= 1.0 quuz";
+        let pkgs: Vec<_> = split_whitespace_unless_quoted(&missing_quotes)?.collect();
+        assert_ne!("foobar >= 1.0", pkgs[0]);
+        assert_eq!(">=", pkgs[1]);
+        let missing_leading_quote = "foobar >= 1.0'";
+        assert!(split_whitespace_unless_quoted(&missing_leading_quote).is_err());
+        let missing_trailing_quote = "'foobar >= 1.0 baz-package";
+        assert!(split_whitespace_unless_quoted(&missing_trailing_quote).is_err());
+        let stray_quote = "'foobar >= 1.0' quuz' corge";
+        assert!(split_whitespace_unless_quoted(&stray_quote).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_path_with() -> Result<()> {
+        let td = tempfile::tempdir()?;
+        let td = td.path();
+        fs::create_dir(td.join("common"))?;
+        fs::write(
+            td.join("common/base.yaml"),
+            indoc! {"
+                repos:
+                  - fedora
+                packages:
+                  - systemd
+                postprocess-script: base-post.sh
+            "},
+        )?;
+        fs::write(
+            td.join("common/x86_64.yaml"),
+            indoc! {"
+                packages:
+                  - grub2-efi-x64
+            "},
+        )?;
+        fs::write(
+            td.join("host.yaml"),
+            indoc! {"
+                ref: exampleos/${basearch}/host
+                include: common/base.yaml
+                arch-include:
+                  x86_64: common/x86_64.yaml
+                packages:
+                  - podman
+            "},
+        )?;
+        let mut visited = Vec::new();
+        let cfg = parse_path_with(td.join("host.yaml"), Some("x86_64"), |path, cfg| {
+            let script = cfg.base.postprocess_script.clone();
+            visited.push((path.strip_prefix(td)?.to_path_buf(), script));
+            Ok(())
+        })?;
+        assert_eq!(cfg.base.treeref.as_deref(), Some("exampleos/x86_64/host"));
+        let pkgs: Vec<_> = cfg.packages.iter().flatten().map(|s| s.as_str()).collect();
+        assert_eq!(pkgs, ["grub2-efi-x64", "podman", "systemd"]);
+        let expected: Vec<(PathBuf, Option<String>)> = vec![
+            ("host.yaml".into(), None),
+            ("common/base.yaml".into(), Some("base-post.sh".into())),
+            ("common/x86_64.yaml".into(), None),
+        ];
+        assert_eq!(visited, expected);
+
+        // Includes are relative to the including file, and loops are caught
+        fs::write(td.join("common/x86_64.yaml"), "include: ../host.yaml\n")?;
+        let e = parse_path(td.join("host.yaml"), Some("x86_64")).unwrap_err();
+        assert!(e.to_string().contains("Include loop detected"), "{}", e);
+        Ok(())
+    }
+}
@@ -22,6 +22,7 @@
 
 #include <gio/gio.h>
 #include <glib-unix.h>
+#include <glib/gi18n.h>
 
 #include <errno.h>
 #include <exception>
@@ -77,15 +78,34 @@ static RpmOstreeCommand commands[] = {
     "Remove all mutations", rpmostree_builtin_reset },
   { "refresh-md", static_cast<RpmOstreeBuiltinFlags> (0), "Generate rpm repo metadata",
     rpmostree_builtin_refresh_md },
+  { "remote", static_cast<RpmOstreeBuiltinFlags> (0), "Manage remote repository settings",
+    rpmostree_builtin_remote },
   { "kargs", static_cast<RpmOstreeBuiltinFlags> (0), "Query or modify kernel arguments",
     rpmostree_builtin_kargs },
   { "initramfs-etc", (RpmOstreeBuiltinFlags)0, "Track initramfs configuration files",
     rpmostree_builtin_initramfs_etc },
+  { "apply-live", static_cast<RpmOstreeBuiltinFlags> (0),
+    "Apply pending deployment changes to booted deployment", rpmostree_builtin_apply_live },
+  { "finalize", static_cast<RpmOstreeBuiltinFlags> (0),
+    "Finalize the staged deployment and reboot into it", rpmostree_builtin_finalize_deployment },
   /* Rust-implemented commands; they're here so that they show up in `rpm-ostree
    * --help` alongside the other commands, but the command itself is fully
-   *  handled Rust side. */
+   *  handled Rust side.  Their definitions for the shell completion are listed
+   *  in `rust_command()` of completion.rs. */
   { "usroverlay", static_cast<RpmOstreeBuiltinFlags> (RPM_OSTREE_BUILTIN_FLAG_REQUIRES_ROOT),
     "Apply a transient overlayfs to /usr", NULL },
+  { "audit-log", static_cast<RpmOstreeBuiltinFlags> (RPM_OSTREE_BUILTIN_FLAG_LOCAL_CMD),
+    "Show the transactions which modified the system", NULL },
+  { "completion", static_cast<RpmOstreeBuiltinFlags> (RPM_OSTREE_BUILTIN_FLAG_LOCAL_CMD),
+    "Print a shell completion script", NULL },
+  { "diff", static_cast<RpmOstreeBuiltinFlags> (RPM_OSTREE_BUILTIN_FLAG_LOCAL_CMD),
+    "Compare the packages, files and origins of two deployments", NULL },
+  { "pin", static_cast<RpmOstreeBuiltinFlags> (RPM_OSTREE_BUILTIN_FLAG_REQUIRES_ROOT),
+    "Pin a deployment, so that it isn't pruned", NULL },
+  { "testdeploy", static_cast<RpmOstreeBuiltinFlags> (RPM_OSTREE_BUILTIN_FLAG_REQUIRES_ROOT),
+    "Stage a deployment which is booted once, then removed", NULL },
+  { "verify", static_cast<RpmOstreeBuiltinFlags> (RPM_OSTREE_BUILTIN_FLAG_REQUIRES_ROOT),
+    "Check the files of a deployment against its commit", NULL },
   /* Legacy aliases */
   { "pkg-add", static_cast<RpmOstreeBuiltinFlags> (RPM_OSTREE_BUILTIN_FLAG_HIDDEN), NULL,
     rpmostree_builtin_install },
@@ -126,6 +146,7 @@ static RpmOstreeCommand commands[] = {
 static gboolean opt_version;
 static gboolean opt_force_peer;
 static char *opt_sysroot;
+static int opt_json_progress_fd = -1;
 static gchar **opt_install;
 static gchar **opt_uninstall;
 
@@ -138,6 +159,8 @@ static GOptionEntry daemon_entries[]
           "Use system root SYSROOT (default: /)", "SYSROOT" },
         { "peer", 0, 0, G_OPTION_ARG_NONE, &opt_force_peer,
           "Force a peer-to-peer connection instead of using the system message bus", NULL },
+        { "json-progress-fd", 0, 0, G_OPTION_ARG_INT, &opt_json_progress_fd,
+          "Write transaction progress as JSON lines to file descriptor FD", "FD" },
         { NULL } };
 
 static GOptionEntry pkg_entries[]
@@ -147,6 +170,42 @@ static GOptionEntry pkg_entries[]
           "Remove overlayed additional package", "PKG" },
         { NULL } };
 
+/* Set while describing the command line for the shell completion, see
+ * describe_command(); the parameters, options and subcommands are recorded
+ * as they're added to the option contexts, and the parsing fails instead.
+ */
+static rpmostreecxx::CliDescription *describing;
+
+GOptionContext *
+rpmostree_option_context_new (const char *parameter_string)
+{
+  if (describing && parameter_string)
+    describing->parameters = parameter_string;
+  return g_option_context_new (parameter_string);
+}
+
+void
+rpmostree_option_context_add_entries (GOptionContext *context, const GOptionEntry *entries)
+{
+  for (const GOptionEntry *entry = entries; describing && entry->long_name != NULL; entry++)
+    {
+      /* G_OPTION_REMAINING has no name */
+      if ((entry->flags & G_OPTION_FLAG_HIDDEN) > 0 || *entry->long_name == '\0')
+        continue;
+      rpmostreecxx::CliOption option;
+      option.long_name = entry->long_name;
+      if (entry->short_name)
+        option.short_name = std::string (1, entry->short_name);
+      bool takes_value
+          = entry->arg != G_OPTION_ARG_NONE && (entry->flags & G_OPTION_FLAG_NO_ARG) == 0;
+      if (takes_value)
+        option.value_name = entry->arg_description ?: "VALUE";
+      option.description = entry->description ?: "";
+      describing->options.push_back (option);
+    }
+  g_option_context_add_main_entries (context, entries, NULL);
+}
+
 static GOptionContext *
 option_context_new_with_commands (RpmOstreeCommandInvocation *invocation,
                                   RpmOstreeCommand *commands)
@@ -172,6 +231,13 @@ option_context_new_with_commands (RpmOstreeCommandInvocation *invocation,
           g_string_append_printf (summary, "\n  %-17s", command->name);
           if (command->description != NULL)
             g_string_append_printf (summary, "%s", command->description);
+          if (describing)
+            {
+              rpmostreecxx::CliCommand subcommand;
+              subcommand.name = command->name;
+              subcommand.description = command->description ?: "";
+              describing->subcommands.push_back (subcommand);
+            }
         }
     }
 
@@ -230,15 +296,18 @@ rpmostree_option_context_parse (GOptionContext *context, const GOptionEntry *mai
     }
 
   if (main_entries != NULL)
-    g_option_context_add_main_entries (context, main_entries, NULL);
+    rpmostree_option_context_add_entries (context, main_entries);
 
   if (use_daemon)
-    g_option_context_add_main_entries (context, daemon_entries, NULL);
+    rpmostree_option_context_add_entries (context, daemon_entries);
 
   if ((flags & RPM_OSTREE_BUILTIN_FLAG_SUPPORTS_PKG_INSTALLS) > 0)
-    g_option_context_add_main_entries (context, pkg_entries, NULL);
+    rpmostree_option_context_add_entries (context, pkg_entries);
 
-  g_option_context_add_main_entries (context, global_entries, NULL);
+  rpmostree_option_context_add_entries (context, global_entries);
+
+  if (describing)
+    return glnx_throw (error, "Describing the command line");
 
   if (!g_option_context_parse (context, argc, argv, error))
     return FALSE;
@@ -286,7 +355,9 @@ rpmostree_option_context_parse (GOptionContext *context, const GOptionEntry *mai
         /* ignore errors; we print out a warning if we fail to spawn pkttyagent */
         (void)rpmostree_polkit_agent_open ();
 
-      if (!rpmostree_load_sysroot (opt_sysroot, cancellable, out_sysroot_proxy, error))
+      rpmostree_set_json_progress_fd (opt_json_progress_fd);
+      if (!rpmostree_load_sysroot (opt_sysroot, opt_force_peer, cancellable, out_sysroot_proxy,
+                                   error))
         return FALSE;
     }
 
@@ -378,8 +449,11 @@ rpmostree_handle_subcommand (int argc, char **argv, RpmOstreeCommand *subcommand
                        invocation->command->name, subcommand_name);
         }
 
-      g_autofree char *help = g_option_context_get_help (context, FALSE, NULL);
-      g_printerr ("%s", help);
+      if (!describing)
+        {
+          g_autofree char *help = g_option_context_get_help (context, FALSE, NULL);
+          g_printerr ("%s", help);
+        }
       return FALSE;
     }
 
@@ -431,6 +505,9 @@ early_main (void)
     g_assert (g_setenv ("GSETTINGS_BACKEND", "memory", TRUE));
 
   setlocale (LC_ALL, "");
+  /* Shared with the Rust side, see i18n.rs */
+  bindtextdomain (GETTEXT_PACKAGE, LOCALEDIR);
+  bind_textdomain_codeset (GETTEXT_PACKAGE, "UTF-8");
 
   /* We don't support /etc/dnf/dnf.conf, so tell libdnf to not look for it. The function
    * name here is misleading; it's not attached to a `DnfContext` object, but instead
@@ -478,8 +555,11 @@ rpmostree_main (const rust::Slice<const rust::Str> args)
       /* This will not return for some options (e.g. --version). */
       (void)rpmostree_option_context_parse (context, NULL, &argc, &argv, NULL, NULL, NULL, NULL,
                                             NULL, NULL);
-      g_autofree char *help = g_option_context_get_help (context, FALSE, NULL);
-      g_printerr ("%s", help);
+      if (!describing)
+        {
+          g_autofree char *help = g_option_context_get_help (context, FALSE, NULL);
+          g_printerr ("%s", help);
+        }
       if (command_name == NULL)
         throw std::runtime_error ("No command specified");
       else
@@ -513,6 +593,27 @@ rpmostree_main (const rust::Slice<const rust::Str> args)
     }
 }
 
+// Describe the command at `path` (e.g. `["db", "diff"]`) for the shell
+// completion, by running it up to the parsing of its options; see completion.rs.
+CliDescription
+describe_command (rust::Slice<const rust::Str> path)
+{
+  CliDescription description;
+  std::vector<rust::Str> args = { "rpm-ostree" };
+  args.insert (args.end (), path.begin (), path.end ());
+  describing = &description;
+  try
+    {
+      (void)rpmostree_main (rust::Slice<const rust::Str> (args.data (), args.size ()));
+    }
+  catch (const std::exception &)
+    {
+      /* Expected; the parsing fails once the options are recorded */
+    }
+  describing = NULL;
+  return description;
+}
+
 // Tear down any process global state */
 void
 rpmostree_process_global_teardown ()
@@ -23,6 +23,7 @@
 #include <glib-unix.h>
 #include <string.h>
 
+#include "rpmostree-builtins.h"
 #include "rpmostree-clientlib.h"
 #include "rpmostree-cxxrs.h"
 #include "rpmostree-ex-builtins.h"
@@ -33,6 +34,15 @@
 gboolean
 rpmostree_ex_builtin_apply_live (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
                                  GCancellable *cancellable, GError **error)
+{
+  g_printerr ("NOTICE: This command is now stable and no longer requires `ex`.\n");
+  g_printerr ("NOTICE: Please update scripts; support for this alias will be removed.\n");
+  return rpmostree_builtin_apply_live (argc, argv, invocation, cancellable, error);
+}
+
+gboolean
+rpmostree_builtin_apply_live (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
+                              GCancellable *cancellable, GError **error)
 {
   rust::Vec<rust::String> rustargv;
   for (int i = 0; i < argc; i++)
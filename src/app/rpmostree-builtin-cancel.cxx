@@ -54,7 +54,7 @@ gboolean
 rpmostree_builtin_cancel (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
                           GCancellable *cancellable, GError **error)
 {
-  g_autoptr (GOptionContext) context = g_option_context_new ("");
+  g_autoptr (GOptionContext) context = rpmostree_option_context_new ("");
   glnx_unref_object RPMOSTreeSysroot *sysroot_proxy = NULL;
 
   if (!rpmostree_option_context_parse (context, option_entries, &argc, &argv, invocation,
@@ -36,6 +36,8 @@ static gboolean opt_base;
 static gboolean opt_pending;
 static gboolean opt_rollback;
 static gboolean opt_repomd;
+static gboolean opt_container_images;
+static gboolean opt_retention;
 
 static GOptionEntry option_entries[] = {
   { "os", 0, 0, G_OPTION_ARG_STRING, &opt_osname, "Operate on provided OSNAME", "OSNAME" },
@@ -44,6 +46,10 @@ static GOptionEntry option_entries[] = {
   { "pending", 'p', 0, G_OPTION_ARG_NONE, &opt_pending, "Remove pending deployment", NULL },
   { "rollback", 'r', 0, G_OPTION_ARG_NONE, &opt_rollback, "Remove rollback deployment", NULL },
   { "repomd", 'm', 0, G_OPTION_ARG_NONE, &opt_repomd, "Delete cached rpm repo metadata", NULL },
+  { "container-images", 'i', 0, G_OPTION_ARG_NONE, &opt_container_images,
+    "Remove container images not used by a deployment", NULL },
+  { "retention", 0, 0, G_OPTION_ARG_NONE, &opt_retention,
+    "Remove deployments per the retention policy of the daemon", NULL },
   { NULL }
 };
 
@@ -51,7 +57,7 @@ gboolean
 rpmostree_builtin_cleanup (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
                            GCancellable *cancellable, GError **error)
 {
-  g_autoptr (GOptionContext) context = g_option_context_new ("");
+  g_autoptr (GOptionContext) context = rpmostree_option_context_new ("");
   g_autoptr (GPtrArray) cleanup_types = g_ptr_array_new ();
   glnx_unref_object RPMOSTreeOS *os_proxy = NULL;
   glnx_unref_object RPMOSTreeSysroot *sysroot_proxy = NULL;
@@ -75,6 +81,10 @@ rpmostree_builtin_cleanup (int argc, char **argv, RpmOstreeCommandInvocation *in
     g_ptr_array_add (cleanup_types, (char *)"rollback-deploy");
   if (opt_repomd)
     g_ptr_array_add (cleanup_types, (char *)"repomd");
+  if (opt_container_images)
+    g_ptr_array_add (cleanup_types, (char *)"container-images");
+  if (opt_retention)
+    g_ptr_array_add (cleanup_types, (char *)"retention");
   if (cleanup_types->len == 0)
     {
       glnx_throw (error, "At least one cleanup option must be specified");
@@ -48,6 +48,22 @@ static RpmOstreeCommand compose_subcommands[]
         { "extensions", RPM_OSTREE_BUILTIN_FLAG_LOCAL_CMD,
           "Download RPM packages guaranteed to depsolve with a base OSTree",
           rpmostree_compose_builtin_extensions },
+        { "build-derived-image", RPM_OSTREE_BUILTIN_FLAG_LOCAL_CMD,
+          "Build a container image from the client-side changes of the booted deployment",
+          rpmostree_compose_builtin_build_derived_image },
+        { "diskimage",
+          (RpmOstreeBuiltinFlags)(RPM_OSTREE_BUILTIN_FLAG_LOCAL_CMD
+                                  | RPM_OSTREE_BUILTIN_FLAG_REQUIRES_ROOT),
+          "Install a commit into a bootable disk image described by a treefile",
+          rpmostree_compose_builtin_diskimage },
+        { "passwd-snapshot", RPM_OSTREE_BUILTIN_FLAG_LOCAL_CMD,
+          "Export the users and groups of a commit for check-passwd and check-groups",
+          rpmostree_compose_builtin_passwd_snapshot },
+        { "pin-ids", RPM_OSTREE_BUILTIN_FLAG_LOCAL_CMD,
+          "Add the user and group IDs of a commit to a pinned-ids mapping",
+          rpmostree_compose_builtin_pin_ids },
+        { "schema", RPM_OSTREE_BUILTIN_FLAG_LOCAL_CMD, "Print the schema of treefiles",
+          rpmostree_compose_builtin_schema },
         { NULL, (RpmOstreeBuiltinFlags)0, NULL, NULL } };
 
 gboolean
@@ -26,10 +26,28 @@
 static RpmOstreeCommand rpm_subcommands[]
     = { { "diff", RPM_OSTREE_BUILTIN_FLAG_LOCAL_CMD, "Show package changes between two commits",
           rpmostree_db_builtin_diff },
+        { "changelogs", RPM_OSTREE_BUILTIN_FLAG_LOCAL_CMD,
+          "Show the changelogs of the packages changed in the pending update",
+          rpmostree_db_builtin_changelogs },
+        { "compare-manifest", RPM_OSTREE_BUILTIN_FLAG_LOCAL_CMD,
+          "Compare the packages of a commit against a manifest",
+          rpmostree_db_builtin_compare_manifest },
+        { "export-manifest", RPM_OSTREE_BUILTIN_FLAG_LOCAL_CMD,
+          "Export a manifest of the packages of a commit", rpmostree_db_builtin_export_manifest },
+        { "history", RPM_OSTREE_BUILTIN_FLAG_LOCAL_CMD,
+          "Show when a package changed across deployments", rpmostree_db_builtin_history },
         { "list", RPM_OSTREE_BUILTIN_FLAG_LOCAL_CMD, "List packages within commits",
           rpmostree_db_builtin_list },
+        { "search", RPM_OSTREE_BUILTIN_FLAG_LOCAL_CMD,
+          "Search packages by name or provide across deployments", rpmostree_db_builtin_search },
+        { "verify", RPM_OSTREE_BUILTIN_FLAG_LOCAL_CMD,
+          "Verify the files of packages against their headers and the commit",
+          rpmostree_db_builtin_verify },
         { "version", RPM_OSTREE_BUILTIN_FLAG_LOCAL_CMD,
           "Show rpmdb version of packages within the commits", rpmostree_db_builtin_version },
+        { "whatprovides", RPM_OSTREE_BUILTIN_FLAG_LOCAL_CMD,
+          "Show which packages of a deployment own a file or provide a capability",
+          rpmostree_db_builtin_whatprovides },
         { NULL, (RpmOstreeBuiltinFlags)0, NULL, NULL } };
 
 static char *opt_repo;
@@ -47,7 +65,7 @@ rpmostree_db_option_context_parse (GOptionContext *context, const GOptionEntry *
   /* Entries are listed in --help output in the order added.  We add the
    * main entries ourselves so that we can add the --repo entry first. */
 
-  g_option_context_add_main_entries (context, global_entries, NULL);
+  rpmostree_option_context_add_entries (context, global_entries);
 
   if (!rpmostree_option_context_parse (context, main_entries, argc, argv, invocation, cancellable,
                                        NULL, NULL, NULL, error))
@@ -34,10 +34,14 @@ static gboolean opt_preview;
 static gboolean opt_cache_only;
 static gboolean opt_download_only;
 static gboolean opt_lock_finalization;
+static gboolean opt_force_policy_rebuild;
+static gboolean opt_alternative;
 static gboolean opt_disallow_downgrade;
 static gboolean opt_unchanged_exit_77;
 static gboolean opt_bypass_driver;
 static gboolean opt_skip_branch_check;
+static gboolean opt_list;
+static int opt_list_page;
 static char *opt_ex_cliwrap;
 
 static GOptionEntry option_entries[]
@@ -50,6 +54,10 @@ static GOptionEntry option_entries[]
          *     deprecate --check-diff. */
         { "preview", 0, 0, G_OPTION_ARG_NONE, &opt_preview, "Just preview package differences",
           NULL },
+        { "list", 0, 0, G_OPTION_ARG_NONE, &opt_list,
+          "List the available versions, or the image tags for container images", NULL },
+        { "list-page", 0, 0, G_OPTION_ARG_INT, &opt_list_page,
+          "Show page PAGE of the versions listed by --list (implies --list)", "PAGE" },
         { "cache-only", 'C', 0, G_OPTION_ARG_NONE, &opt_cache_only,
           "Do not download latest ostree and RPM data", NULL },
         { "download-only", 0, 0, G_OPTION_ARG_NONE, &opt_download_only,
@@ -58,6 +66,11 @@ static GOptionEntry option_entries[]
           "Do not check if commit belongs on the same branch", NULL },
         { "lock-finalization", 0, G_OPTION_FLAG_HIDDEN, G_OPTION_ARG_NONE, &opt_lock_finalization,
           "Prevent automatic deployment finalization on shutdown", NULL },
+        { "alternative", 0, 0, G_OPTION_ARG_NONE, &opt_alternative,
+          "Write the new deployment as the alternative to the staged one instead of replacing it",
+          NULL },
+        { "force-policy-rebuild", 0, 0, G_OPTION_ARG_NONE, &opt_force_policy_rebuild,
+          "Don't reuse the SELinux policy cached from previous layering operations", NULL },
         { "disallow-downgrade", 0, 0, G_OPTION_ARG_NONE, &opt_disallow_downgrade,
           "Forbid deployment of chronologically older trees", NULL },
         { "unchanged-exit-77", 0, 0, G_OPTION_ARG_NONE, &opt_unchanged_exit_77,
@@ -72,6 +85,25 @@ static GOptionEntry option_entries[]
           "Enable or disable wrapping binaries like /usr/bin/rpm", NULL },
         { NULL } };
 
+/* Print the commits available on the tracked ref, or the tags of the tracked image */
+static gboolean
+list_revisions (RPMOSTreeSysroot *sysroot_proxy, RPMOSTreeOS *os_proxy, GCancellable *cancellable,
+                GError **error)
+{
+  GVariantDict dict;
+  g_variant_dict_init (&dict, NULL);
+  g_variant_dict_insert (&dict, "page", "u", (guint32)(opt_list_page ?: 1));
+  g_autoptr (GVariant) options = g_variant_ref_sink (g_variant_dict_end (&dict));
+
+  g_autofree char *transaction_address = NULL;
+  if (!rpmostree_os_call_list_revisions_sync (os_proxy, options, &transaction_address,
+                                              cancellable, error))
+    return FALSE;
+
+  return rpmostree_transaction_get_response_sync (sysroot_proxy, transaction_address,
+                                                  cancellable, error);
+}
+
 gboolean
 rpmostree_builtin_deploy (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
                           GCancellable *cancellable, GError **error)
@@ -85,13 +117,28 @@ rpmostree_builtin_deploy (int argc, char **argv, RpmOstreeCommandInvocation *inv
   const char *const *install_pkgs = NULL;
   const char *const *uninstall_pkgs = NULL;
 
-  context = g_option_context_new ("REVISION");
+  context = rpmostree_option_context_new ("REVISION");
 
   if (!rpmostree_option_context_parse (context, option_entries, &argc, &argv, invocation,
                                        cancellable, &install_pkgs, &uninstall_pkgs, &sysroot_proxy,
                                        error))
     return FALSE;
 
+  if (opt_list_page < 0)
+    return glnx_throw (error, "Invalid --list-page: %d", opt_list_page);
+  if (opt_list || opt_list_page > 0)
+    {
+      if (argc >= 2 || opt_preview || install_pkgs != NULL || uninstall_pkgs != NULL)
+        {
+          rpmostree_usage_error (
+              context, "--list cannot be combined with REVISION or other operations", error);
+          return FALSE;
+        }
+      if (!rpmostree_load_os_proxy (sysroot_proxy, opt_osname, cancellable, &os_proxy, error))
+        return FALSE;
+      return list_revisions (sysroot_proxy, os_proxy, cancellable, error);
+    }
+
   const gboolean arg_specified = argc >= 2;
   // If using --ex-cliwrap or --register-driver, we don't usually
   // expect them to be performing another operation.
@@ -149,6 +196,10 @@ rpmostree_builtin_deploy (int argc, char **argv, RpmOstreeCommandInvocation *inv
       g_variant_dict_insert (&dict, "download-only", "b", opt_download_only);
       g_variant_dict_insert (&dict, "skip-branch-check", "b", opt_skip_branch_check);
       g_variant_dict_insert (&dict, "lock-finalization", "b", opt_lock_finalization);
+      if (opt_alternative)
+        g_variant_dict_insert (&dict, "alternative", "b", TRUE);
+      if (opt_force_policy_rebuild)
+        g_variant_dict_insert (&dict, "force-policy-rebuild", "b", TRUE);
       g_variant_dict_insert (&dict, "initiating-command-line", "s", invocation->command_line);
       if (opt_register_driver)
         g_variant_dict_insert (&dict, "register-driver", "s", opt_register_driver);
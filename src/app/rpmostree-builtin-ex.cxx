@@ -27,6 +27,13 @@ static RpmOstreeCommand ex_subcommands[] = {
     "Apply pending deployment changes to booted deployment", rpmostree_ex_builtin_apply_live },
   { "apply-live", (RpmOstreeBuiltinFlags)0, "Apply pending deployment changes to booted deployment",
     rpmostree_ex_builtin_apply_live },
+  { "apply", static_cast<RpmOstreeBuiltinFlags> (0),
+    "Converge the system to a declarative description of its desired state",
+    rpmostree_ex_builtin_apply },
+  { "fips", static_cast<RpmOstreeBuiltinFlags> (0), "Enable FIPS mode",
+    rpmostree_ex_builtin_fips },
+  { "fsverity", static_cast<RpmOstreeBuiltinFlags> (RPM_OSTREE_BUILTIN_FLAG_LOCAL_CMD),
+    "Enable or verify fs-verity on the objects of a commit", rpmostree_ex_builtin_fsverity },
   { "history", (RpmOstreeBuiltinFlags)RPM_OSTREE_BUILTIN_FLAG_LOCAL_CMD,
     "Inspect rpm-ostree history of the system", rpmostree_ex_builtin_history },
   { "initramfs-etc", (RpmOstreeBuiltinFlags)0, "Track initramfs configuration files",
@@ -41,6 +48,15 @@ static RpmOstreeCommand ex_subcommands[] = {
    * https://github.com/coreos/rpm-ostree/pull/3078 */
   { "module", static_cast<RpmOstreeBuiltinFlags> (0), "Commands to install/uninstall modules",
     rpmostree_ex_builtin_module },
+  { "offline-update", static_cast<RpmOstreeBuiltinFlags> (0),
+    "Carry container image updates to machines without network access",
+    rpmostree_ex_builtin_offline_update },
+  { "repo-keys", static_cast<RpmOstreeBuiltinFlags> (RPM_OSTREE_BUILTIN_FLAG_LOCAL_CMD),
+    "Pin the signing keys of rpm-md repos and ostree remotes", rpmostree_ex_builtin_repo_keys },
+  { "scan", static_cast<RpmOstreeBuiltinFlags> (RPM_OSTREE_BUILTIN_FLAG_LOCAL_CMD),
+    "Match the packages of a deployment against security advisories", rpmostree_ex_builtin_scan },
+  { "sigpolicy", static_cast<RpmOstreeBuiltinFlags> (RPM_OSTREE_BUILTIN_FLAG_LOCAL_CMD),
+    "Manage the signature policy for container images", rpmostree_ex_builtin_sigpolicy },
   { NULL, (RpmOstreeBuiltinFlags)0, NULL, NULL }
 };
 
@@ -70,3 +86,80 @@ rpmostree_ex_builtin_module (int argc, char **argv, RpmOstreeCommandInvocation *
   ROSCXX_TRY (modularity_entrypoint (rustargv), error);
   return TRUE;
 }
+
+gboolean
+rpmostree_ex_builtin_apply (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
+                            GCancellable *cancellable, GError **error)
+{
+  rust::Vec<rust::String> rustargv;
+  for (int i = 0; i < argc; i++)
+    rustargv.push_back (std::string (argv[i]));
+  ROSCXX_TRY (apply_state_entrypoint (rustargv), error);
+  return TRUE;
+}
+
+gboolean
+rpmostree_ex_builtin_fips (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
+                           GCancellable *cancellable, GError **error)
+{
+  rust::Vec<rust::String> rustargv;
+  for (int i = 0; i < argc; i++)
+    rustargv.push_back (std::string (argv[i]));
+  ROSCXX_TRY (fips_entrypoint (rustargv), error);
+  return TRUE;
+}
+
+gboolean
+rpmostree_ex_builtin_fsverity (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
+                               GCancellable *cancellable, GError **error)
+{
+  rust::Vec<rust::String> rustargv;
+  for (int i = 0; i < argc; i++)
+    rustargv.push_back (std::string (argv[i]));
+  ROSCXX_TRY (fsverity_entrypoint (rustargv), error);
+  return TRUE;
+}
+
+gboolean
+rpmostree_ex_builtin_offline_update (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
+                                     GCancellable *cancellable, GError **error)
+{
+  rust::Vec<rust::String> rustargv;
+  for (int i = 0; i < argc; i++)
+    rustargv.push_back (std::string (argv[i]));
+  ROSCXX_TRY (offline_update_entrypoint (rustargv), error);
+  return TRUE;
+}
+
+gboolean
+rpmostree_ex_builtin_sigpolicy (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
+                                GCancellable *cancellable, GError **error)
+{
+  rust::Vec<rust::String> rustargv;
+  for (int i = 0; i < argc; i++)
+    rustargv.push_back (std::string (argv[i]));
+  ROSCXX_TRY (sigpolicy_entrypoint (rustargv), error);
+  return TRUE;
+}
+
+gboolean
+rpmostree_ex_builtin_scan (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
+                           GCancellable *cancellable, GError **error)
+{
+  rust::Vec<rust::String> rustargv;
+  for (int i = 0; i < argc; i++)
+    rustargv.push_back (std::string (argv[i]));
+  ROSCXX_TRY (scan_entrypoint (rustargv), error);
+  return TRUE;
+}
+
+gboolean
+rpmostree_ex_builtin_repo_keys (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
+                                GCancellable *cancellable, GError **error)
+{
+  rust::Vec<rust::String> rustargv;
+  for (int i = 0; i < argc; i++)
+    rustargv.push_back (std::string (argv[i]));
+  ROSCXX_TRY (repo_keys_entrypoint (rustargv), error);
+  return TRUE;
+}
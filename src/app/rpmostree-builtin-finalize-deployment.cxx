@@ -26,6 +26,8 @@
 static char *opt_osname;
 static gboolean opt_allow_unlocked;
 static gboolean opt_allow_missing;
+static gboolean opt_soft_reboot;
+static gboolean opt_alternative;
 
 static GOptionEntry option_entries[] = {
   /* though there can only be one staged deployment at a time, this could still
@@ -35,6 +37,12 @@ static GOptionEntry option_entries[] = {
     "Don't error out if no expected checksum is provided", NULL },
   { "allow-unlocked", 0, 0, G_OPTION_ARG_NONE, &opt_allow_unlocked,
     "Don't error out if staged deployment wasn't locked", NULL },
+  { "soft-reboot", 0, 0, G_OPTION_ARG_NONE, &opt_soft_reboot,
+    "Soft-reboot into the deployment; it must have the same kernel, initramfs and kernel "
+    "arguments as the booted one",
+    NULL },
+  { "alternative", 0, 0, G_OPTION_ARG_NONE, &opt_alternative,
+    "Make the alternative to the staged deployment the default instead", NULL },
   { NULL }
 };
 
@@ -43,13 +51,22 @@ rpmostree_builtin_finalize_deployment (int argc, char **argv,
                                        RpmOstreeCommandInvocation *invocation,
                                        GCancellable *cancellable, GError **error)
 {
-  g_autoptr (GOptionContext) context = g_option_context_new ("CHECKSUM");
+  g_autoptr (GOptionContext) context = rpmostree_option_context_new ("CHECKSUM");
 
   glnx_unref_object RPMOSTreeSysroot *sysroot_proxy = NULL;
   if (!rpmostree_option_context_parse (context, option_entries, &argc, &argv, invocation,
                                        cancellable, NULL, NULL, &sysroot_proxy, error))
     return FALSE;
 
+  /* `finalize` is the interactive variant of `finalize-deployment`: the staged deployment
+   * needn't be locked, and the checksum is optional */
+  if (g_str_equal (invocation->command->name, "finalize"))
+    {
+      opt_allow_unlocked = TRUE;
+      if (argc < 2)
+        opt_allow_missing = TRUE;
+    }
+
   const char *checksum = NULL;
   if (argc > 2)
     {
@@ -81,6 +98,9 @@ rpmostree_builtin_finalize_deployment (int argc, char **argv,
     g_variant_dict_insert (&dict, "checksum", "s", checksum);
   g_variant_dict_insert (&dict, "allow-missing-checksum", "b", opt_allow_missing);
   g_variant_dict_insert (&dict, "allow-unlocked", "b", opt_allow_unlocked);
+  g_variant_dict_insert (&dict, "soft-reboot", "b", opt_soft_reboot);
+  if (opt_alternative)
+    g_variant_dict_insert (&dict, "alternative", "b", TRUE);
   g_variant_dict_insert (&dict, "initiating-command-line", "s", invocation->command_line);
   g_autoptr (GVariant) options = g_variant_ref_sink (g_variant_dict_end (&dict));
 
@@ -42,9 +42,11 @@ static GOptionEntry option_entries[] = {
   { "os", 0, 0, G_OPTION_ARG_STRING, &opt_osname, "Operate on provided OSNAME", "OSNAME" },
   { "force-sync", 0, 0, G_OPTION_ARG_NONE, &opt_force_sync,
     "Deploy a new tree with the latest tracked /etc files", NULL },
-  { "track", 0, 0, G_OPTION_ARG_STRING_ARRAY, &opt_track, "Track root /etc file", "FILE" },
-  { "untrack", 0, 0, G_OPTION_ARG_STRING_ARRAY, &opt_untrack, "Untrack root /etc file", "FILE" },
-  { "untrack-all", 0, 0, G_OPTION_ARG_NONE, &opt_untrack_all, "Untrack all root /etc files", NULL },
+  { "track", 0, 0, G_OPTION_ARG_STRING_ARRAY, &opt_track, "Track root /etc file or directory",
+    "PATH" },
+  { "untrack", 0, 0, G_OPTION_ARG_STRING_ARRAY, &opt_untrack,
+    "Untrack root /etc file or directory", "PATH" },
+  { "untrack-all", 0, 0, G_OPTION_ARG_NONE, &opt_untrack_all, "Untrack all root /etc paths", NULL },
   { "reboot", 'r', 0, G_OPTION_ARG_NONE, &opt_reboot,
     "Initiate a reboot after operation is complete", NULL },
   { "lock-finalization", 0, G_OPTION_FLAG_HIDDEN, G_OPTION_ARG_NONE, &opt_lock_finalization,
@@ -68,7 +70,7 @@ gboolean
 rpmostree_builtin_initramfs_etc (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
                                  GCancellable *cancellable, GError **error)
 {
-  g_autoptr (GOptionContext) context = g_option_context_new ("");
+  g_autoptr (GOptionContext) context = rpmostree_option_context_new ("");
 
   glnx_unref_object RPMOSTreeSysroot *sysroot_proxy = NULL;
   if (!rpmostree_option_context_parse (context, option_entries, &argc, &argv, invocation,
@@ -105,7 +107,14 @@ rpmostree_builtin_initramfs_etc (int argc, char **argv, RpmOstreeCommandInvocati
         {
           g_print ("Tracked files:\n");
           for (char **it = files; it && *it; it++)
-            g_print ("  %s\n", *it);
+            {
+              /* Like when generating the initramfs, symlinks aren't followed */
+              if (g_file_test (*it, G_FILE_TEST_IS_DIR)
+                  && !g_file_test (*it, G_FILE_TEST_IS_SYMLINK))
+                g_print ("  %s/ (directory)\n", *it);
+              else
+                g_print ("  %s\n", *it);
+            }
         }
 
       return TRUE; /* note early return */
@@ -33,6 +33,12 @@ static char *opt_osname;
 static gboolean opt_enable;
 static gboolean opt_disable;
 static char **opt_add_arg;
+static char *opt_generator;
+static char **opt_add_module;
+static char **opt_omit_module;
+static char **opt_add_driver;
+static char **opt_remove_module;
+static char **opt_remove_driver;
 static gboolean opt_reboot;
 static gboolean opt_lock_finalization;
 
@@ -42,6 +48,18 @@ static GOptionEntry option_entries[]
           NULL },
         { "arg", 0, 0, G_OPTION_ARG_STRING_ARRAY, &opt_add_arg,
           "Append ARG to the dracut arguments", "ARG" },
+        { "generator", 0, 0, G_OPTION_ARG_STRING, &opt_generator,
+          "Generate the initramfs with NAME instead of dracut", "NAME" },
+        { "add-module", 0, 0, G_OPTION_ARG_STRING_ARRAY, &opt_add_module,
+          "Include the dracut module NAME (implies --enable)", "NAME" },
+        { "omit-module", 0, 0, G_OPTION_ARG_STRING_ARRAY, &opt_omit_module,
+          "Omit the dracut module NAME (implies --enable)", "NAME" },
+        { "add-driver", 0, 0, G_OPTION_ARG_STRING_ARRAY, &opt_add_driver,
+          "Include the kernel driver NAME (implies --enable)", "NAME" },
+        { "remove-module", 0, 0, G_OPTION_ARG_STRING_ARRAY, &opt_remove_module,
+          "Stop including or omitting the dracut module NAME", "NAME" },
+        { "remove-driver", 0, 0, G_OPTION_ARG_STRING_ARRAY, &opt_remove_driver,
+          "Stop including the kernel driver NAME", "NAME" },
         { "disable", 0, 0, G_OPTION_ARG_NONE, &opt_disable,
           "Disable regenerating initramfs locally", NULL },
         { "reboot", 'r', 0, G_OPTION_ARG_NONE, &opt_reboot,
@@ -54,7 +72,7 @@ gboolean
 rpmostree_builtin_initramfs (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
                              GCancellable *cancellable, GError **error)
 {
-  g_autoptr (GOptionContext) context = g_option_context_new ("");
+  g_autoptr (GOptionContext) context = rpmostree_option_context_new ("");
 
   glnx_unref_object RPMOSTreeSysroot *sysroot_proxy = NULL;
   if (!rpmostree_option_context_parse (context, option_entries, &argc, &argv, invocation,
@@ -65,11 +83,23 @@ rpmostree_builtin_initramfs (int argc, char **argv, RpmOstreeCommandInvocation *
   if (!rpmostree_load_os_proxy (sysroot_proxy, opt_osname, cancellable, &os_proxy, error))
     return FALSE;
 
+  const gboolean have_dracut_config = opt_add_module || opt_omit_module || opt_add_driver
+                                      || opt_remove_module || opt_remove_driver;
+  if (have_dracut_config && opt_disable)
+    return glnx_throw (error, "Cannot simultaneously specify --disable and modules or drivers");
+  /* The modules and drivers only matter when regenerating */
+  if (have_dracut_config)
+    opt_enable = TRUE;
+
   if (!(opt_enable || opt_disable))
     {
       g_autoptr (GVariant) deployments = rpmostree_sysroot_dup_deployments (sysroot_proxy);
       gboolean cur_regenerate = FALSE;
       g_autofree char **initramfs_args = NULL;
+      g_autofree char **add_modules = NULL;
+      g_autofree char **omit_modules = NULL;
+      g_autofree char **add_drivers = NULL;
+      const char *generator = NULL;
 
       if (opt_reboot)
         {
@@ -83,6 +113,8 @@ rpmostree_builtin_initramfs (int argc, char **argv, RpmOstreeCommandInvocation *
                                "--arg must be used with --enable");
           return FALSE;
         }
+      if (opt_generator)
+        return glnx_throw (error, "--generator must be used with --enable");
 
       if (g_variant_n_children (deployments) > 1)
         {
@@ -95,10 +127,16 @@ rpmostree_builtin_initramfs (int argc, char **argv, RpmOstreeCommandInvocation *
           if (cur_regenerate)
             {
               g_variant_dict_lookup (&dict, "initramfs-args", "^a&s", &initramfs_args);
+              g_variant_dict_lookup (&dict, "initramfs-add-modules", "^a&s", &add_modules);
+              g_variant_dict_lookup (&dict, "initramfs-omit-modules", "^a&s", &omit_modules);
+              g_variant_dict_lookup (&dict, "initramfs-add-drivers", "^a&s", &add_drivers);
+              g_variant_dict_lookup (&dict, "initramfs-generator", "&s", &generator);
             }
         }
 
       g_print ("Initramfs regeneration: %s\n", cur_regenerate ? "enabled" : "disabled");
+      if (generator && *generator)
+        g_print ("Initramfs generator: %s\n", generator);
       if (initramfs_args)
         {
           g_print ("Initramfs args: ");
@@ -106,6 +144,21 @@ rpmostree_builtin_initramfs (int argc, char **argv, RpmOstreeCommandInvocation *
             g_print ("%s ", *iter);
           g_print ("\n");
         }
+      if (add_modules && *add_modules)
+        {
+          g_autofree char *modules = g_strjoinv (" ", add_modules);
+          g_print ("Initramfs added modules: %s\n", modules);
+        }
+      if (omit_modules && *omit_modules)
+        {
+          g_autofree char *modules = g_strjoinv (" ", omit_modules);
+          g_print ("Initramfs omitted modules: %s\n", modules);
+        }
+      if (add_drivers && *add_drivers)
+        {
+          g_autofree char *drivers = g_strjoinv (" ", add_drivers);
+          g_print ("Initramfs added drivers: %s\n", drivers);
+        }
     }
   else if (opt_enable && opt_disable)
     {
@@ -122,6 +175,8 @@ rpmostree_builtin_initramfs (int argc, char **argv, RpmOstreeCommandInvocation *
                        "Cannot simultaenously specify --disable and --arg");
           return FALSE;
         }
+      if (opt_disable && opt_generator)
+        return glnx_throw (error, "Cannot simultaneously specify --disable and --generator");
       if (!opt_add_arg)
         opt_add_arg = empty_strv;
 
@@ -130,6 +185,18 @@ rpmostree_builtin_initramfs (int argc, char **argv, RpmOstreeCommandInvocation *
       g_variant_dict_insert (&dict, "reboot", "b", opt_reboot);
       g_variant_dict_insert (&dict, "initiating-command-line", "s", invocation->command_line);
       g_variant_dict_insert (&dict, "lock-finalization", "b", opt_lock_finalization);
+      if (opt_add_module)
+        g_variant_dict_insert (&dict, "add-modules", "^as", opt_add_module);
+      if (opt_omit_module)
+        g_variant_dict_insert (&dict, "omit-modules", "^as", opt_omit_module);
+      if (opt_add_driver)
+        g_variant_dict_insert (&dict, "add-drivers", "^as", opt_add_driver);
+      if (opt_remove_module)
+        g_variant_dict_insert (&dict, "remove-modules", "^as", opt_remove_module);
+      if (opt_remove_driver)
+        g_variant_dict_insert (&dict, "remove-drivers", "^as", opt_remove_driver);
+      if (opt_generator)
+        g_variant_dict_insert (&dict, "generator", "s", opt_generator);
       g_autoptr (GVariant) options = g_variant_ref_sink (g_variant_dict_end (&dict));
 
       g_autofree char *transaction_address = NULL;
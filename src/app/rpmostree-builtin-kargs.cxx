@@ -21,6 +21,7 @@
 #include "config.h"
 
 #include "rpmostree-clientlib.h"
+#include "rpmostree-cxxrs.h"
 #include "rpmostree-editor.h"
 #include "rpmostree-ex-builtins.h"
 #include "rpmostree-libbuiltin.h"
@@ -35,10 +36,15 @@ static char **opt_kernel_append_strings;
 static char **opt_kernel_delete_if_present_strings;
 static char **opt_kernel_append_if_missing_strings;
 static char **opt_kernel_replace_strings;
+static char **opt_enable_profiles;
+static char **opt_disable_profiles;
 static char *opt_osname;
 static char *opt_deploy_index;
 static gboolean opt_lock_finalization;
 static gboolean opt_unchanged_exit_77;
+static gboolean opt_force;
+static gboolean opt_global;
+static gboolean opt_show_layers;
 
 static GOptionEntry option_entries[] = {
   { "os", 0, 0, G_OPTION_ARG_STRING, &opt_osname, "Operation on provided OSNAME", "OSNAME" },
@@ -64,8 +70,18 @@ static GOptionEntry option_entries[] = {
     "Like --append, but does nothing if the key is already present", "KEY=VALUE" },
   { "delete-if-present", 0, 0, G_OPTION_ARG_STRING_ARRAY, &opt_kernel_delete_if_present_strings,
     "Like --delete, but does nothing if the key is already missing", "KEY=VALUE" },
+  { "enable-profile", 0, 0, G_OPTION_ARG_STRING_ARRAY, &opt_enable_profiles,
+    "Enable a named kernel argument profile, appending its arguments", "NAME" },
+  { "disable-profile", 0, 0, G_OPTION_ARG_STRING_ARRAY, &opt_disable_profiles,
+    "Disable a named kernel argument profile, deleting the arguments it appended", "NAME" },
   { "unchanged-exit-77", 0, 0, G_OPTION_ARG_NONE, &opt_unchanged_exit_77,
     "If no kernel args changed, exit 77", NULL },
+  { "force", 0, 0, G_OPTION_ARG_NONE, &opt_force,
+    "Apply the kernel arguments even if they fail validation", NULL },
+  { "global", 0, 0, G_OPTION_ARG_NONE, &opt_global,
+    "Show or change the global kernel arguments, which apply to all deployments, in place", NULL },
+  { "show-layers", 0, 0, G_OPTION_ARG_NONE, &opt_show_layers,
+    "Show which layer each kernel argument comes from", NULL },
   { "import-proc-cmdline", 0, 0, G_OPTION_ARG_NONE, &opt_import_proc_cmdline,
     "Instead of modifying old kernel arguments, we modify args from current /proc/cmdline (the "
     "booted deployment)",
@@ -105,16 +121,17 @@ kernel_arg_handle_editor (const char *input_kernel_arg, const char **out_kernel_
     }
   g_autofree char *filtered_input = ostree_kernel_args_to_string (temp_kargs);
 
+  auto formatted_input = rpmostreecxx::kargs_editor_format (filtered_input);
   g_autofree char *input_string = g_strdup_printf (
-      "# Current kernel arguments are shown below, and can be directly edited.\n"
-      "# Empty or commented lines (starting with '#') will be ignored.\n"
-      "# Individual kernel arguments should be separated by spaces, and the order \n"
-      "# is relevant.\n"
+      "# Current kernel arguments are shown below, one per line, and can be directly\n"
+      "# edited. Empty or commented lines (starting with '#') will be ignored.\n"
+      "# The order of the arguments is relevant, and values containing spaces must\n"
+      "# be quoted, as in key=\"some value\".\n"
       "# Also, please note that any changes to the 'ostree=' argument will not be \n"
       "# effective as they are usually regenerated when bootconfig changes.\n"
       "\n"
       "%s",
-      filtered_input);
+      formatted_input.c_str ());
 
   /* Note: the repo here is NULL, as we don't really require it
    * for the edtior process
@@ -123,35 +140,13 @@ kernel_arg_handle_editor (const char *input_kernel_arg, const char **out_kernel_
   if (out_editor_string == NULL)
     return FALSE;
 
-  /* We now process the editor output, we ignore empty lines and lines
-   * starting with '#'
+  /* We now validate the editor output (ignoring empty lines and lines
+   * starting with '#'), and join the arguments back into one line.
    */
-  g_auto (GStrv) lines = g_strsplit (out_editor_string, "\n", -1);
-  guint num_lines = g_strv_length (lines);
-  g_autoptr (GString) kernel_arg_buf = g_string_new ("");
-  for (guint i = 0; i < num_lines; i++)
-    {
-      char *line = lines[i];
-      /* Remove leading and trailing spaces */
-      g_strstrip (line);
-
-      /* Comments and blank lines are skipped */
-      if (*line == '#')
-        continue;
-      if (*line == '\0')
-        continue;
-      g_string_append (kernel_arg_buf, line);
-
-      /* We append a space to every line except the last
-       * because there could be a case where user have
-       * args that are in multiple lines.
-       */
-      g_string_append (kernel_arg_buf, " ");
-    }
-
-  /* Transfer the ownership to a new string, so we can remove the trailing spaces */
-  g_autofree char *kernel_args_str = g_string_free (util::move_nullify (kernel_arg_buf), FALSE);
-  g_strchomp (kernel_args_str);
+  CXX_TRY_VAR (edited, rpmostreecxx::kargs_editor_parse (out_editor_string), error);
+  for (auto &warning : edited.warnings)
+    g_printerr ("warning: %s\n", warning.c_str ());
+  g_autofree char *kernel_args_str = g_strdup (edited.kargs.c_str ());
 
   /* Compare input and user content, in order to signal whether there was any real change */
   *out_kargs_changed = !g_str_equal (filtered_input, kernel_args_str);
@@ -165,14 +160,6 @@ kernel_arg_handle_editor (const char *input_kernel_arg, const char **out_kernel_
       return FALSE;
     }
 
-  /* We do not allow empty kernel arg string */
-  if (g_str_equal (kernel_args_str, ""))
-    {
-      g_set_error (error, G_IO_ERROR, G_IO_ERROR_INVALID_ARGUMENT,
-                   "The kernel arguments can not be empty");
-      return FALSE;
-    }
-
   *out_kernel_arg = util::move_nullify (kernel_args_str);
 
   return TRUE;
@@ -183,7 +170,7 @@ rpmostree_builtin_kargs (int argc, char **argv, RpmOstreeCommandInvocation *invo
                          GCancellable *cancellable, GError **error)
 {
   glnx_unref_object RPMOSTreeSysroot *sysroot_proxy = NULL;
-  g_autoptr (GOptionContext) context = g_option_context_new ("");
+  g_autoptr (GOptionContext) context = rpmostree_option_context_new ("");
   gboolean display_kernel_args = FALSE;
   if (!rpmostree_option_context_parse (context, option_entries, &argc, &argv, invocation,
                                        cancellable, NULL, NULL, &sysroot_proxy, error))
@@ -191,14 +178,16 @@ rpmostree_builtin_kargs (int argc, char **argv, RpmOstreeCommandInvocation *invo
 
   if (opt_editor
       && (opt_kernel_delete_strings || opt_kernel_replace_strings || opt_kernel_append_strings
-          || opt_kernel_delete_if_present_strings || opt_kernel_append_if_missing_strings))
+          || opt_kernel_delete_if_present_strings || opt_kernel_append_if_missing_strings
+          || opt_enable_profiles || opt_disable_profiles))
     {
       /* We want editor command to achieve all these functionalities
        * Thus erroring out ahead of time when these strings exist
        */
       g_set_error (error, G_IO_ERROR, G_IO_ERROR_INVALID_ARGUMENT,
                    "Cannot specify --editor with --replace, --delete, --append, "
-                   "--delete-if-present or --append-if-missing");
+                   "--delete-if-present, --append-if-missing, --enable-profile or "
+                   "--disable-profile");
       return FALSE;
     }
 
@@ -222,7 +211,8 @@ rpmostree_builtin_kargs (int argc, char **argv, RpmOstreeCommandInvocation *invo
     }
   if (!(opt_kernel_delete_strings) && !(opt_kernel_append_strings) && !(opt_kernel_replace_strings)
       && !(opt_editor) && !(opt_kernel_delete_if_present_strings)
-      && !(opt_kernel_append_if_missing_strings))
+      && !(opt_kernel_append_if_missing_strings) && !(opt_enable_profiles)
+      && !(opt_disable_profiles))
     display_kernel_args = TRUE;
 
   if (opt_reboot && display_kernel_args)
@@ -231,6 +221,32 @@ rpmostree_builtin_kargs (int argc, char **argv, RpmOstreeCommandInvocation *invo
                    "Cannot reboot when kernel arguments not changed");
       return FALSE;
     }
+  if (opt_show_layers && !display_kernel_args)
+    {
+      g_set_error (error, G_IO_ERROR, G_IO_ERROR_INVALID_ARGUMENT,
+                   "Cannot specify --show-layers when changing kernel arguments");
+      return FALSE;
+    }
+  if (opt_global
+      && (opt_editor || opt_kernel_replace_strings || opt_kernel_delete_if_present_strings
+          || opt_kernel_append_if_missing_strings || opt_enable_profiles || opt_disable_profiles
+          || opt_deploy_index || opt_import_proc_cmdline || opt_show_layers))
+    {
+      g_set_error (error, G_IO_ERROR, G_IO_ERROR_INVALID_ARGUMENT,
+                   "Only --append and --delete can be specified with --global");
+      return FALSE;
+    }
+
+  /* The global kernel arguments are recorded on the host */
+  if (opt_global && display_kernel_args)
+    {
+      CXX_TRY_VAR (global_kargs, rpmostreecxx::kargs_global (), error);
+      g_autoptr (GString) buf = g_string_new ("");
+      for (auto &arg : global_kargs)
+        g_string_append_printf (buf, "%s%s", buf->len ? " " : "", arg.c_str ());
+      g_print ("%s\n", buf->str);
+      return TRUE;
+    }
 
   glnx_unref_object RPMOSTreeOS *os_proxy = NULL;
   if (!rpmostree_load_os_proxy (sysroot_proxy, opt_osname, cancellable, &os_proxy, error))
@@ -259,7 +275,25 @@ rpmostree_builtin_kargs (int argc, char **argv, RpmOstreeCommandInvocation *invo
   if (!g_variant_lookup (boot_config, "options", "&s", &old_kernel_arg_string))
     return FALSE;
 
-  if (display_kernel_args)
+  if (display_kernel_args && opt_show_layers)
+    {
+      g_autoptr (GVariant) layers
+          = g_variant_lookup_value (boot_config, "options-layers", G_VARIANT_TYPE ("a(ss)"));
+      if (!layers)
+        return glnx_throw (error, "The daemon doesn't support --show-layers");
+      gsize max_len = 0;
+      GVariantIter iter;
+      const char *arg;
+      const char *layer;
+      g_variant_iter_init (&iter, layers);
+      while (g_variant_iter_next (&iter, "(&s&s)", &arg, &layer))
+        max_len = MAX (max_len, strlen (layer));
+      g_variant_iter_init (&iter, layers);
+      while (g_variant_iter_next (&iter, "(&s&s)", &arg, &layer))
+        g_print ("%-*s  %s\n", (int)max_len, layer, arg);
+      return TRUE;
+    }
+  else if (display_kernel_args)
     {
       g_print ("%s\n", old_kernel_arg_string);
       return TRUE;
@@ -275,6 +309,8 @@ rpmostree_builtin_kargs (int argc, char **argv, RpmOstreeCommandInvocation *invo
   g_variant_dict_insert (&dict, "reboot", "b", opt_reboot);
   g_variant_dict_insert (&dict, "initiating-command-line", "s", invocation->command_line);
   g_variant_dict_insert (&dict, "lock-finalization", "b", opt_lock_finalization);
+  g_variant_dict_insert (&dict, "force", "b", opt_force);
+  g_variant_dict_insert (&dict, "global", "b", opt_global);
   g_autoptr (GVariant) options = NULL;
 
   if (opt_editor)
@@ -355,6 +391,10 @@ rpmostree_builtin_kargs (int argc, char **argv, RpmOstreeCommandInvocation *invo
       if (opt_kernel_delete_if_present_strings && *opt_kernel_delete_if_present_strings)
         g_variant_dict_insert (&dict, "delete-if-present", "^as",
                                opt_kernel_delete_if_present_strings);
+      if (opt_enable_profiles && *opt_enable_profiles)
+        g_variant_dict_insert (&dict, "enable-profiles", "^as", opt_enable_profiles);
+      if (opt_disable_profiles && *opt_disable_profiles)
+        g_variant_dict_insert (&dict, "disable-profiles", "^as", opt_disable_profiles);
       options = g_variant_ref_sink (g_variant_dict_end (&dict));
 
       /* call the generated dbus-function */
@@ -34,6 +34,9 @@ static RpmOstreeCommand override_subcommands[]
           "Remove packages from the base layer", rpmostree_override_builtin_remove },
         { "reset", RPM_OSTREE_BUILTIN_FLAG_SUPPORTS_PKG_INSTALLS,
           "Reset currently active package overrides", rpmostree_override_builtin_reset },
+        { "replace-kernel", RPM_OSTREE_BUILTIN_FLAG_NONE,
+          "Replace the kernel packages in the base layer as a unit",
+          rpmostree_override_builtin_replace_kernel },
         { NULL, (RpmOstreeBuiltinFlags)0, NULL, NULL } };
 
 gboolean
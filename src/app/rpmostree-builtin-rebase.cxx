@@ -43,7 +43,15 @@ static gboolean opt_download_only;
 static gboolean opt_experimental;
 static gboolean opt_disallow_downgrade;
 static gboolean opt_lock_finalization;
+static gboolean opt_alternative;
 static gboolean opt_bypass_driver;
+static gboolean opt_enforce_container_sigpolicy;
+static gboolean opt_bypass_attestation;
+static gboolean opt_force_policy_rebuild;
+static gboolean opt_preview;
+static gboolean opt_list;
+static int opt_list_page;
+static gboolean opt_interactive;
 
 static GOptionEntry option_entries[]
     = { { "os", 0, 0, G_OPTION_ARG_STRING, &opt_osname, "Operate on provided OSNAME", "OSNAME" },
@@ -70,8 +78,29 @@ static GOptionEntry option_entries[]
           "Forbid deployment of chronologically older trees", NULL },
         { "lock-finalization", 0, G_OPTION_FLAG_HIDDEN, G_OPTION_ARG_NONE, &opt_lock_finalization,
           "Prevent automatic deployment finalization on shutdown", NULL },
+        { "alternative", 0, 0, G_OPTION_ARG_NONE, &opt_alternative,
+          "Write the new deployment as the alternative to the staged one instead of replacing it",
+          NULL },
         { "bypass-driver", 0, 0, G_OPTION_ARG_NONE, &opt_bypass_driver,
           "Force a rebase even if an updates driver is registered", NULL },
+        { "enforce-container-sigpolicy", 0, 0, G_OPTION_ARG_NONE, &opt_enforce_container_sigpolicy,
+          "Refuse container images which are not signed according to containers-policy.json",
+          NULL },
+        { "bypass-attestation", 0, 0, G_OPTION_ARG_NONE, &opt_bypass_attestation,
+          "Deploy the container image even if it has no attestation satisfying the policy", NULL },
+        { "force-policy-rebuild", 0, 0, G_OPTION_ARG_NONE, &opt_force_policy_rebuild,
+          "Don't reuse the SELinux policy cached from previous layering operations", NULL },
+        { "preview", 0, 0, G_OPTION_ARG_NONE, &opt_preview,
+          "Just show what would change when rebasing to a container image, without pulling it",
+          NULL },
+        { "list", 0, 0, G_OPTION_ARG_NONE, &opt_list,
+          "List the refs of the configured remotes, or the tags of a container image, with their "
+          "versions and dates",
+          NULL },
+        { "list-page", 0, 0, G_OPTION_ARG_INT, &opt_list_page,
+          "Show page PAGE of the image tags listed by --list (implies --list)", "PAGE" },
+        { "interactive", 'i', 0, G_OPTION_ARG_NONE, &opt_interactive,
+          "Pick the refspec from the list shown by --list", NULL },
         { NULL } };
 
 gboolean
@@ -85,7 +114,7 @@ rpmostree_builtin_rebase (int argc, char **argv, RpmOstreeCommandInvocation *inv
   /* forced blank for now */
   const char *packages[] = { NULL };
 
-  g_autoptr (GOptionContext) context = g_option_context_new ("REFSPEC [REVISION]");
+  g_autoptr (GOptionContext) context = rpmostree_option_context_new ("REFSPEC [REVISION]");
   glnx_unref_object RPMOSTreeOS *os_proxy = NULL;
   g_autofree char *transaction_address = NULL;
   glnx_unref_object RPMOSTreeSysroot *sysroot_proxy = NULL;
@@ -103,6 +132,30 @@ rpmostree_builtin_rebase (int argc, char **argv, RpmOstreeCommandInvocation *inv
       return FALSE;
     }
 
+  /* With --list and --interactive, the argument is the remote or the container image
+   * whose targets to list */
+  if (opt_list_page < 0)
+    return glnx_throw (error, "Invalid --list-page: %d", opt_list_page);
+  if (opt_list_page > 0)
+    opt_list = TRUE;
+  if (opt_list || opt_interactive)
+    {
+      if (opt_list && opt_interactive)
+        return rpmostree_usage_error (context, "Cannot specify both --list and --interactive",
+                                      error),
+               FALSE;
+      if (argc > 2 || opt_branch || opt_remote)
+        return rpmostree_usage_error (
+                   context, "--list and --interactive only take a REMOTE or an IMGREF", error),
+               FALSE;
+    }
+  if (opt_list)
+    {
+      CXX_TRY (rpmostreecxx::rebase_list_targets (argc == 2 ? argv[1] : "", opt_list_page),
+               error);
+      return TRUE;
+    }
+
   if (!opt_bypass_driver)
     if (!error_if_driver_registered (sysroot_proxy, cancellable, error))
       return FALSE;
@@ -110,7 +163,17 @@ rpmostree_builtin_rebase (int argc, char **argv, RpmOstreeCommandInvocation *inv
   if (!rpmostree_load_os_proxy (sysroot_proxy, opt_osname, cancellable, &os_proxy, error))
     return FALSE;
 
-  if (argc < 2 && !(opt_branch || opt_remote))
+  if (opt_interactive)
+    {
+      CXX_TRY_VAR (picked, rpmostreecxx::rebase_pick_target (argc == 2 ? argv[1] : ""), error);
+      if (picked.empty ())
+        {
+          g_print ("No target picked; not rebasing.\n");
+          return TRUE;
+        }
+      new_provided_refspec = new_refspec_owned = g_strdup (picked.c_str ());
+    }
+  else if (argc < 2 && !(opt_branch || opt_remote))
     {
       return rpmostree_usage_error (context, "Must specify refspec, or -b branch or -m remote",
                                     error),
@@ -136,12 +199,26 @@ rpmostree_builtin_rebase (int argc, char **argv, RpmOstreeCommandInvocation *inv
     }
   (void)new_refspec_owned; /* Pacify static analysis */
 
+  /* Paths to local container images are resolved by the daemon, which doesn't share our
+   * working directory */
+  CXX_TRY_VAR (canonical_refspec,
+               rpmostreecxx::container_refspec_make_absolute (new_provided_refspec), error);
+  new_provided_refspec = canonical_refspec.c_str ();
+
   auto refspectype = rpmostreecxx::refspec_classify (new_provided_refspec);
 
   /* catch empty refspec now; we'd error out much later in the daemon otherwise */
   if (strlen (new_provided_refspec) == 0)
     return glnx_throw (error, "Refspec is empty");
 
+  if (opt_preview)
+    {
+      if (refspectype != rpmostreecxx::RefspecType::Container)
+        return glnx_throw (error, "--preview is only supported for container image references");
+      CXX_TRY (rpmostreecxx::preview_container_rebase (new_provided_refspec), error);
+      return TRUE;
+    }
+
   if (refspectype == rpmostreecxx::RefspecType::Container)
     {
       if (!opt_experimental)
@@ -153,6 +230,21 @@ rpmostree_builtin_rebase (int argc, char **argv, RpmOstreeCommandInvocation *inv
         return glnx_throw (error, "Unexpected ostree revision alongside container refspec type");
     }
 
+  /* On hosts managed by bootc, plain rebases to container images may be handed off to it;
+   * NULL with an older daemon */
+  const char *bootc_interop = rpmostree_sysroot_get_bootc_interop (sysroot_proxy);
+  if (refspectype == rpmostreecxx::RefspecType::Container
+      && g_strcmp0 (bootc_interop, "delegate") == 0 && !opt_osname
+      && !(opt_cache_only || opt_download_only || opt_disallow_downgrade || opt_lock_finalization
+           || opt_alternative || opt_custom_origin_url || opt_enforce_container_sigpolicy
+           || opt_bypass_attestation || install_pkgs != NULL || uninstall_pkgs != NULL))
+    {
+      CXX_TRY_VAR (delegated,
+                   rpmostreecxx::bootc_delegate_switch (new_provided_refspec, opt_reboot), error);
+      if (delegated)
+        return TRUE;
+    }
+
   /* Check if remote refers to a local repo */
   g_autofree char *local_repo_remote = NULL;
   if (refspectype == rpmostreecxx::RefspecType::Ostree
@@ -182,6 +274,14 @@ rpmostree_builtin_rebase (int argc, char **argv, RpmOstreeCommandInvocation *inv
   g_variant_dict_insert (&dict, "skip-purge", "b", opt_skip_purge);
   g_variant_dict_insert (&dict, "initiating-command-line", "s", invocation->command_line);
   g_variant_dict_insert (&dict, "lock-finalization", "b", opt_lock_finalization);
+  if (opt_alternative)
+    g_variant_dict_insert (&dict, "alternative", "b", TRUE);
+  if (opt_enforce_container_sigpolicy)
+    g_variant_dict_insert (&dict, "enforce-container-sigpolicy", "b", TRUE);
+  if (opt_bypass_attestation)
+    g_variant_dict_insert (&dict, "bypass-attestation", "b", TRUE);
+  if (opt_force_policy_rebuild)
+    g_variant_dict_insert (&dict, "force-policy-rebuild", "b", TRUE);
   if (opt_custom_origin_url)
     {
       if (!opt_custom_origin_description)
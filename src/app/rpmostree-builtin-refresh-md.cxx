@@ -30,10 +30,13 @@
 
 static char *opt_osname;
 static char *opt_force;
+static gboolean opt_when_idle;
 
 static GOptionEntry option_entries[]
     = { { "os", 0, 0, G_OPTION_ARG_STRING, &opt_osname, "Operate on provided OSNAME", "OSNAME" },
         { "force", 'f', 0, G_OPTION_ARG_NONE, &opt_force, "Expire current cache", NULL },
+        { "when-idle", 0, 0, G_OPTION_ARG_NONE, &opt_when_idle,
+          "Wait until the system is idle (for up to an hour)", NULL },
         { NULL } };
 
 static GVariant *
@@ -42,6 +45,7 @@ get_args_variant (void)
   GVariantDict dict;
   g_variant_dict_init (&dict, NULL);
   g_variant_dict_insert (&dict, "force", "b", opt_force);
+  g_variant_dict_insert (&dict, "when-idle", "b", opt_when_idle);
   return g_variant_dict_end (&dict);
 }
 
@@ -49,7 +53,7 @@ gboolean
 rpmostree_builtin_refresh_md (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
                               GCancellable *cancellable, GError **error)
 {
-  g_autoptr (GOptionContext) context = g_option_context_new ("");
+  g_autoptr (GOptionContext) context = rpmostree_option_context_new ("");
   glnx_unref_object RPMOSTreeOS *os_proxy = NULL;
   glnx_unref_object RPMOSTreeSysroot *sysroot_proxy = NULL;
   g_autofree char *transaction_address = NULL;
@@ -67,9 +71,16 @@ rpmostree_builtin_refresh_md (int argc, char **argv, RpmOstreeCommandInvocation
   if (!rpmostree_load_os_proxy (sysroot_proxy, opt_osname, cancellable, &os_proxy, error))
     return FALSE;
 
-  if (!rpmostree_os_call_refresh_md_sync (os_proxy, get_args_variant (), &transaction_address,
-                                          cancellable, error))
-    return FALSE;
+  guint idle_waited_secs = 0;
+  while (TRUE)
+    {
+      g_autoptr (GError) local_error = NULL;
+      if (rpmostree_os_call_refresh_md_sync (os_proxy, get_args_variant (), &transaction_address,
+                                             cancellable, &local_error))
+        break;
+      if (!rpmostree_retry_when_idle (&local_error, &opt_when_idle, &idle_waited_secs, error))
+        return FALSE;
+    }
 
   if (!rpmostree_transaction_get_response_sync (sysroot_proxy, transaction_address, cancellable,
                                                 error))
@@ -0,0 +1,105 @@
+/* Copyright (C) 2022 Red Hat, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Lesser General Public License as published
+ * by the Free Software Foundation; either version 2 of the licence or (at
+ * your option) any later version.
+ *
+ * This library is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ * Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General
+ * Public License along with this library; if not, write to the
+ * Free Software Foundation, Inc., 59 Temple Place, Suite 330,
+ * Boston, MA 02111-1307, USA.
+ */
+
+#include "config.h"
+
+#include <string.h>
+#include <unistd.h>
+
+#include "rpmostree-builtins.h"
+#include "rpmostree-libbuiltin.h"
+
+#include <libglnx.h>
+
+static char *opt_osname;
+static char *opt_username;
+static gboolean opt_password_stdin;
+static gboolean opt_runtime;
+
+static GOptionEntry add_image_auth_option_entries[]
+    = { { "os", 0, 0, G_OPTION_ARG_STRING, &opt_osname, "Operate on provided OSNAME", "OSNAME" },
+        { "username", 'u', 0, G_OPTION_ARG_STRING, &opt_username, "Registry user name",
+          "USERNAME" },
+        { "password-stdin", 0, 0, G_OPTION_ARG_NONE, &opt_password_stdin,
+          "Read the password from standard input", NULL },
+        { "runtime", 0, 0, G_OPTION_ARG_NONE, &opt_runtime,
+          "Only keep the credentials until the next reboot", NULL },
+        { NULL } };
+
+static gboolean
+rpmostree_remote_builtin_add_image_auth (int argc, char **argv,
+                                         RpmOstreeCommandInvocation *invocation,
+                                         GCancellable *cancellable, GError **error)
+{
+  g_autoptr (GOptionContext) context = rpmostree_option_context_new ("REGISTRY");
+  glnx_unref_object RPMOSTreeOS *os_proxy = NULL;
+  glnx_unref_object RPMOSTreeSysroot *sysroot_proxy = NULL;
+
+  if (!rpmostree_option_context_parse (context, add_image_auth_option_entries, &argc, &argv,
+                                       invocation, cancellable, NULL, NULL, &sysroot_proxy, error))
+    return FALSE;
+
+  if (argc != 2)
+    {
+      rpmostree_usage_error (context, "REGISTRY must be specified", error);
+      return FALSE;
+    }
+  const char *registry = argv[1];
+
+  if (!opt_username)
+    return glnx_throw (error, "--username must be specified");
+  /* Don't accept passwords on the command line, where they'd be visible to all users */
+  if (!opt_password_stdin)
+    return glnx_throw (error, "--password-stdin must be specified");
+  g_autofree char *password = glnx_fd_readall_utf8 (STDIN_FILENO, NULL, cancellable, error);
+  if (!password)
+    return glnx_prefix_error (error, "Reading password");
+  g_strchomp (password);
+  if (!*password)
+    return glnx_throw (error, "Password is empty");
+
+  if (!rpmostree_load_os_proxy (sysroot_proxy, opt_osname, cancellable, &os_proxy, error))
+    return FALSE;
+
+  GVariantDict dict;
+  g_variant_dict_init (&dict, NULL);
+  g_variant_dict_insert (&dict, "username", "s", opt_username);
+  g_variant_dict_insert (&dict, "password", "s", password);
+  g_variant_dict_insert (&dict, "runtime", "b", opt_runtime);
+  g_autoptr (GVariant) options = g_variant_ref_sink (g_variant_dict_end (&dict));
+
+  if (!rpmostree_os_call_add_image_auth_sync (os_proxy, registry, options, cancellable, error))
+    return FALSE;
+
+  g_print ("Stored credentials for %s\n", registry);
+  return TRUE;
+}
+
+static RpmOstreeCommand remote_subcommands[]
+    = { { "add-image-auth", RPM_OSTREE_BUILTIN_FLAG_NONE,
+          "Store credentials for a container image registry",
+          rpmostree_remote_builtin_add_image_auth },
+        { NULL, (RpmOstreeBuiltinFlags)0, NULL, NULL } };
+
+gboolean
+rpmostree_builtin_remote (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
+                          GCancellable *cancellable, GError **error)
+{
+  return rpmostree_handle_subcommand (argc, argv, remote_subcommands, invocation, cancellable,
+                                      error);
+}
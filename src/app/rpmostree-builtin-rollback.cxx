@@ -30,10 +30,15 @@
 #include <libglnx.h>
 
 static gboolean opt_reboot;
+static char *opt_to;
 
-static GOptionEntry option_entries[] = { { "reboot", 'r', 0, G_OPTION_ARG_NONE, &opt_reboot,
-                                           "Initiate a reboot after operation is complete", NULL },
-                                         { NULL } };
+static GOptionEntry option_entries[]
+    = { { "reboot", 'r', 0, G_OPTION_ARG_NONE, &opt_reboot,
+          "Initiate a reboot after operation is complete", NULL },
+        { "to", 0, 0, G_OPTION_ARG_STRING, &opt_to,
+          "Make the deployment with this index, checksum (prefix) or version the default",
+          "DEPLOYMENT" },
+        { NULL } };
 
 static GVariant *
 get_args_variant (void)
@@ -42,6 +47,8 @@ get_args_variant (void)
 
   g_variant_dict_init (&dict, NULL);
   g_variant_dict_insert (&dict, "reboot", "b", opt_reboot);
+  if (opt_to)
+    g_variant_dict_insert (&dict, "to", "s", opt_to);
 
   return g_variant_dict_end (&dict);
 }
@@ -50,7 +57,7 @@ gboolean
 rpmostree_builtin_rollback (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
                             GCancellable *cancellable, GError **error)
 {
-  GOptionContext *context = g_option_context_new ("");
+  GOptionContext *context = rpmostree_option_context_new ("");
   glnx_unref_object RPMOSTreeOS *os_proxy = NULL;
   glnx_unref_object RPMOSTreeSysroot *sysroot_proxy = NULL;
   g_autofree char *transaction_address = NULL;
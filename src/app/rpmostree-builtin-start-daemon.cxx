@@ -35,6 +35,7 @@
 #include "rpmostree-libbuiltin.h"
 #include "rpmostree-util.h"
 #include "rpmostreed-daemon.h"
+#include "rpmostreed-sysroot.h"
 
 typedef enum
 {
@@ -47,11 +48,15 @@ typedef enum
 static AppState appstate = APPSTATE_STARTING;
 static gboolean opt_debug = FALSE;
 static char *opt_sysroot = NULL;
-static GOptionEntry opt_entries[] = { { "debug", 'd', 0, G_OPTION_ARG_NONE, &opt_debug,
-                                        "Print debug information on stderr", NULL },
-                                      { "sysroot", 0, 0, G_OPTION_ARG_STRING, &opt_sysroot,
-                                        "Use system root SYSROOT (default: /)", "SYSROOT" },
-                                      { NULL } };
+static int opt_dbus_peer = -1;
+static GOptionEntry opt_entries[]
+    = { { "debug", 'd', 0, G_OPTION_ARG_NONE, &opt_debug, "Print debug information on stderr",
+          NULL },
+        { "sysroot", 0, 0, G_OPTION_ARG_STRING, &opt_sysroot,
+          "Use system root SYSROOT (default: /)", "SYSROOT" },
+        { "dbus-peer", 0, G_OPTION_FLAG_HIDDEN, G_OPTION_ARG_INT, &opt_dbus_peer,
+          "Serve a peer-to-peer connection on FD instead of the system bus", "FD" },
+        { NULL } };
 
 static RpmostreedDaemon *rpm_ostree_daemon = NULL;
 
@@ -74,11 +79,38 @@ start_daemon (GDBusConnection *connection, GError **error)
                                             connection, "sysroot-path", opt_sysroot ?: "/", NULL);
   if (rpm_ostree_daemon == NULL)
     return FALSE;
-  (void)g_bus_own_name_on_connection (connection, DBUS_NAME, G_BUS_NAME_OWNER_FLAGS_NONE, NULL,
-                                      NULL, NULL, NULL);
+  if (!rpmostreed_daemon_is_private (rpm_ostree_daemon))
+    (void)g_bus_own_name_on_connection (connection, DBUS_NAME, G_BUS_NAME_OWNER_FLAGS_NONE, NULL,
+                                        NULL, NULL, NULL);
   return TRUE;
 }
 
+/* Set up the connection to the client which spawned us, over the socket @fd.  Message
+ * processing starts once the daemon is ready. */
+static GDBusConnection *
+peer_connection_new (int fd, GError **error)
+{
+  g_autoptr (GSocket) socket = g_socket_new_from_fd (fd, error);
+  if (!socket)
+    return (GDBusConnection *)glnx_prefix_error_null (error, "Invalid --dbus-peer");
+  g_autoptr (GSocketConnection) stream = g_socket_connection_factory_create_connection (socket);
+  g_autofree char *guid = g_dbus_generate_guid ();
+  return g_dbus_connection_new_sync (
+      G_IO_STREAM (stream), guid,
+      (GDBusConnectionFlags)(G_DBUS_CONNECTION_FLAGS_AUTHENTICATION_SERVER
+                             | G_DBUS_CONNECTION_FLAGS_DELAY_MESSAGE_PROCESSING),
+      NULL, NULL, error);
+}
+
+static void
+on_peer_closed (GDBusConnection *connection, gboolean remote_peer_vanished, GError *error,
+                gpointer user_data)
+{
+  sd_journal_print (LOG_INFO, "%s", "peer connection closed");
+  if (appstate < APPSTATE_FLUSHING)
+    state_transition (APPSTATE_FLUSHING);
+}
+
 static void
 on_bus_name_released (GDBusConnection *connection, GAsyncResult *result, void *user_data)
 {
@@ -243,10 +275,23 @@ rpmostree_builtin_start_daemon (int argc, char **argv, RpmOstreeCommandInvocatio
   g_unix_signal_add (SIGTERM, on_sigint, NULL);
 
   /* Get an explicit ref to the bus so we can use it later */
-  g_autoptr (GDBusConnection) bus = g_bus_get_sync (G_BUS_TYPE_SYSTEM, NULL, error);
-  if (!bus)
-    return FALSE;
-  if (!start_daemon (bus, error))
+  g_autoptr (GDBusConnection) bus = NULL;
+  g_autoptr (GDBusConnection) connection = NULL;
+  if (opt_dbus_peer >= 0)
+    {
+      connection = peer_connection_new (opt_dbus_peer, error);
+      if (!connection)
+        return FALSE;
+      g_signal_connect (connection, "closed", G_CALLBACK (on_peer_closed), NULL);
+    }
+  else
+    {
+      bus = g_bus_get_sync (G_BUS_TYPE_SYSTEM, NULL, error);
+      if (!bus)
+        return FALSE;
+      connection = (GDBusConnection *)g_object_ref (bus);
+    }
+  if (!start_daemon (connection, error))
     {
       if (*error)
         sd_notifyf (0, "STATUS=error: %s", (*error)->message);
@@ -258,6 +303,9 @@ rpmostree_builtin_start_daemon (int argc, char **argv, RpmOstreeCommandInvocatio
   g_debug ("Entering main event loop");
   rpmostreed_daemon_run_until_idle_exit (rpm_ostree_daemon);
 
+  /* If we're stopped in the middle of a transaction, let it wind down cleanly first */
+  rpmostreed_sysroot_cancel_txn_sync (rpmostreed_sysroot_get ());
+
   if (bus)
     {
       /* We first tell systemd we're stopping, so it knows to activate a new instance
@@ -280,6 +328,11 @@ rpmostree_builtin_start_daemon (int argc, char **argv, RpmOstreeCommandInvocatio
           "ReleaseName", g_variant_new ("(s)", DBUS_NAME), G_VARIANT_TYPE ("(u)"),
           G_DBUS_CALL_FLAGS_NONE, -1, NULL, (GAsyncReadyCallback)on_bus_name_released, NULL);
     }
+  else if (appstate < APPSTATE_EXITING)
+    {
+      /* No bus name to release on a peer-to-peer connection */
+      state_transition (APPSTATE_EXITING);
+    }
 
   /* Waiting 🛌 for the name to be released */
   g_autoptr (GMainContext) mainctx = g_main_context_default ();
@@ -59,7 +59,14 @@ static gboolean opt_verbose_advisories;
 static gboolean opt_json;
 static gboolean opt_only_booted;
 static const char *opt_jsonpath;
+static const char *opt_format;
+static const char *opt_query;
+static int opt_schema_version;
 static gboolean opt_pending_exit_77;
+static gboolean opt_needs_reboot;
+static gboolean opt_restarts_needed;
+static gboolean opt_etc_conflicts;
+static gboolean opt_recommendations;
 
 static GOptionEntry option_entries[]
     = { { "pretty", 'p', G_OPTION_FLAG_HIDDEN, G_OPTION_ARG_NONE, &opt_pretty,
@@ -71,10 +78,25 @@ static GOptionEntry option_entries[]
         { "json", 0, 0, G_OPTION_ARG_NONE, &opt_json, "Output JSON", NULL },
         { "jsonpath", 'J', 0, G_OPTION_ARG_STRING, &opt_jsonpath, "Filter JSONPath expression",
           "EXPRESSION" },
+        { "query", 'q', 0, G_OPTION_ARG_STRING, &opt_query,
+          "Print the value at PATH, e.g. .deployments[0].version", "PATH" },
+        { "format", 0, 0, G_OPTION_ARG_STRING, &opt_format,
+          "Output in a versioned, stable schema; FORMAT is json or yaml", "FORMAT" },
+        { "schema-version", 0, 0, G_OPTION_ARG_INT, &opt_schema_version,
+          "Schema version to use with --format (default: latest)", "N" },
         { "booted", 'b', 0, G_OPTION_ARG_NONE, &opt_only_booted, "Only print the booted deployment",
           NULL },
         { "pending-exit-77", 'b', 0, G_OPTION_ARG_NONE, &opt_pending_exit_77,
           "If pending deployment available, exit 77", NULL },
+        { "needs-reboot", 0, 0, G_OPTION_ARG_NONE, &opt_needs_reboot,
+          "Only print why a reboot is needed, if it is, and exit 77 then", NULL },
+        { "restarts-needed", 0, 0, G_OPTION_ARG_NONE, &opt_restarts_needed,
+          "Only print the services using files replaced by apply-live, and exit 77 if any",
+          NULL },
+        { "etc-conflicts", 0, 0, G_OPTION_ARG_NONE, &opt_etc_conflicts,
+          "Only print the locally modified files of /etc whose defaults changed on upgrade", NULL },
+        { "recommendations", 0, 0, G_OPTION_ARG_NONE, &opt_recommendations,
+          "Print weak dependencies of layered packages which are not installed", NULL },
         { NULL } };
 
 /* return space available for printing value side of kv */
@@ -464,6 +486,58 @@ print_daemon_state (RPMOSTreeSysroot *sysroot_proxy, GCancellable *cancellable,
             g_assert_not_reached ();
           }
         }
+
+      /* NULL with an older daemon */
+      const char *update_window = rpmostree_sysroot_get_update_window (sysroot_proxy);
+      if (!g_str_equal (policy, "check") && update_window && *update_window)
+        {
+          CXX_TRY_VAR (state, rpmostreecxx::update_window_describe (update_window), error);
+          g_print ("  UpdateWindow: %s; %s\n", update_window, state.c_str ());
+        }
+      const char *download_window = rpmostree_sysroot_get_download_window (sysroot_proxy);
+      if (!g_str_equal (policy, "check") && download_window && *download_window)
+        {
+          CXX_TRY_VAR (state, rpmostreecxx::update_window_describe (download_window), error);
+          g_print ("  DownloadWindow: %s; %s\n", download_window, state.c_str ());
+        }
+      /* NULL with an older daemon */
+      GVariant *last_failure = rpmostree_sysroot_get_automatic_update_last_failure (sysroot_proxy);
+      g_auto (GVariantDict) last_failure_dict;
+      g_variant_dict_init (&last_failure_dict, last_failure);
+      const char *category, *message;
+      guint32 count;
+      gint64 timestamp, next_attempt;
+      if (g_variant_dict_lookup (&last_failure_dict, "category", "&s", &category)
+          && g_variant_dict_lookup (&last_failure_dict, "message", "&s", &message)
+          && g_variant_dict_lookup (&last_failure_dict, "count", "u", &count)
+          && g_variant_dict_lookup (&last_failure_dict, "timestamp", "x", &timestamp)
+          && g_variant_dict_lookup (&last_failure_dict, "next-attempt", "x", &next_attempt))
+        {
+          auto desc = rpmostreecxx::autoupdate_failure_describe (category, message, count,
+                                                                  timestamp, next_attempt);
+          g_print ("  LastFailure: %s%s%s%s%s\n", get_red_start (), get_bold_start (),
+                   desc.c_str (), get_bold_end (), get_red_end ());
+        }
+    }
+
+  /* NULL with an older daemon, and empty unless the host is managed by bootc */
+  const char *bootc_interop = rpmostree_sysroot_get_bootc_interop (sysroot_proxy);
+  if (bootc_interop && *bootc_interop)
+    {
+      if (g_str_equal (bootc_interop, "delegate"))
+        g_print ("Bootc: managed; upgrades and rebases are delegated to bootc\n");
+      else
+        g_print ("Bootc: managed\n");
+    }
+
+  /* The global kernel arguments apply to all deployments, so they're not shown per deployment */
+  CXX_TRY_VAR (global_kargs, rpmostreecxx::kargs_global (), error);
+  if (!global_kargs.empty ())
+    {
+      g_autoptr (GString) buf = g_string_new ("");
+      for (auto &arg : global_kargs)
+        g_string_append_printf (buf, "%s%s", buf->len ? " " : "", arg.c_str ());
+      g_print ("GlobalKernelArgs: %s\n", buf->str);
     }
 
   if (txn_proxy)
@@ -480,6 +554,37 @@ print_daemon_state (RPMOSTreeSysroot *sysroot_proxy, GCancellable *cancellable,
   return TRUE;
 }
 
+/* Print the OCI labels which relate a container image to its build pipeline; the
+ * version is only printed if it differs from the one of the commit. */
+static void
+print_container_image_metadata (GVariantDict *dict, const char *version_string, guint max_key_len)
+{
+  g_autoptr (GVariant) labels
+      = g_variant_dict_lookup_value (dict, "container-image-labels", G_VARIANT_TYPE ("a{ss}"));
+  if (!labels)
+    return;
+
+  const char *image_version = NULL;
+  if (!g_variant_lookup (labels, "org.opencontainers.image.version", "&s", &image_version))
+    g_variant_lookup (labels, "version", "&s", &image_version);
+  if (image_version && g_strcmp0 (image_version, version_string) != 0)
+    rpmostree_print_kv ("ImageVersion", max_key_len, image_version);
+
+  const char *created = NULL;
+  if (!g_variant_lookup (labels, "org.opencontainers.image.created", "&s", &created))
+    g_variant_dict_lookup (dict, "container-image-created", "&s", &created);
+  if (created)
+    rpmostree_print_kv ("ImageCreated", max_key_len, created);
+
+  const char *source = NULL;
+  if (g_variant_lookup (labels, "org.opencontainers.image.source", "&s", &source))
+    rpmostree_print_kv ("ImageSource", max_key_len, source);
+
+  const char *revision = NULL;
+  if (g_variant_lookup (labels, "org.opencontainers.image.revision", "&s", &revision))
+    rpmostree_print_kv ("ImageRevision", max_key_len, revision);
+}
+
 /* Print the commit checksum, and optionally the rpm repodata injected from
  * rpmostree_context_get_rpmmd_repo_commit_metadata() */
 static void
@@ -608,6 +713,7 @@ print_one_deployment (RPMOSTreeSysroot *sysroot_proxy, GVariant *child, gint ind
   g_autofree const gchar **origin_requested_local_fileoverride_packages = NULL;
   g_autofree const gchar **origin_requested_base_removals = NULL;
   g_autofree const gchar **origin_requested_base_local_replacements = NULL;
+  g_autofree const gchar **unverified_local_packages = NULL;
   g_autoptr (GVariant) origin_requested_base_remote_replacements = NULL;
   /* these come from commit metadata; they represent what *actually* happened */
   g_autofree const gchar **packages = NULL;
@@ -637,6 +743,7 @@ print_one_deployment (RPMOSTreeSysroot *sysroot_proxy, GVariant *child, gint ind
           = lookup_array_and_canonicalize (dict, "requested-base-removals");
       origin_requested_base_local_replacements
           = lookup_array_and_canonicalize (dict, "requested-base-local-replacements");
+      unverified_local_packages = lookup_array_and_canonicalize (dict, "unverified-local-packages");
       origin_requested_base_remote_replacements = g_variant_dict_lookup_value (
           dict, "requested-base-remote-replacements", G_VARIANT_TYPE ("a(sas)"));
     }
@@ -705,7 +812,10 @@ print_one_deployment (RPMOSTreeSysroot *sysroot_proxy, GVariant *child, gint ind
   g_print ("\n");
 
   if (container_image_reference_digest)
-    rpmostree_print_kv ("Digest", max_key_len, container_image_reference_digest);
+    {
+      rpmostree_print_kv ("Digest", max_key_len, container_image_reference_digest);
+      print_container_image_metadata (dict, version_string, max_key_len);
+    }
   else if (custom_origin_description)
     rpmostree_print_kv ("CustomOrigin", max_key_len, custom_origin_description);
 
@@ -810,6 +920,14 @@ print_one_deployment (RPMOSTreeSysroot *sysroot_proxy, GVariant *child, gint ind
   if (opt_verbose || have_multiple_stateroots)
     rpmostree_print_kv ("StateRoot", max_key_len, os_name);
 
+  /* The space removing the deployment would free */
+  guint64 unique_size;
+  if (opt_verbose && g_variant_dict_lookup (dict, "unique-size", "t", &unique_size))
+    {
+      g_autofree char *unique_size_str = g_format_size (unique_size);
+      rpmostree_print_kv ("UniqueSize", max_key_len, unique_size_str);
+    }
+
   gboolean gpg_enabled;
   if (!g_variant_dict_lookup (dict, "gpg-enabled", "b", &gpg_enabled))
     gpg_enabled = FALSE;
@@ -829,19 +947,34 @@ print_one_deployment (RPMOSTreeSysroot *sysroot_proxy, GVariant *child, gint ind
           = g_variant_dict_lookup_value (&dict, "rpm-diff", G_VARIANT_TYPE ("a{sv}"));
       g_autoptr (GVariant) advisories
           = g_variant_dict_lookup_value (&dict, "advisories", G_VARIANT_TYPE ("a(suuasa{sv})"));
-      if (!rpmostree_print_diff_advisories (rpm_diff, advisories, opt_verbose,
-                                            opt_verbose_advisories, max_key_len, error))
+      g_autoptr (GVariant) layered_advisories = g_variant_dict_lookup_value (
+          &dict, "layered-advisories", G_VARIANT_TYPE ("a(suuasa{sv})"));
+      if (!rpmostree_print_diff_advisories (rpm_diff, advisories, layered_advisories,
+                                            opt_verbose, opt_verbose_advisories, max_key_len,
+                                            error))
         return FALSE;
       if (out_printed_cached_update)
         *out_printed_cached_update = TRUE;
     }
-  else if (is_pending_deployment && sysroot_proxy)
+  else if (is_pending_deployment)
     {
-      /* No cached update, but we can still print a diff summary */
-      const char *sysroot_path = rpmostree_sysroot_get_path (sysroot_proxy);
-      ROSCXX_TRY (print_treepkg_diff_from_sysroot_path (rust::Str (sysroot_path), diff_format,
-                                                        max_key_len, NULL),
-                  error);
+      /* No cached update, but we can still print a diff summary; the daemon caches
+       * it, but fall back to computing it ourselves for older ones */
+      g_autoptr (GVariant) pending_diff
+          = g_variant_dict_lookup_value (dict, "pending-rpm-diff", G_VARIANT_TYPE ("a{sv}"));
+      if (pending_diff)
+        {
+          if (!rpmostree_print_diff_advisories (pending_diff, NULL, NULL, opt_verbose, FALSE,
+                                                max_key_len, error))
+            return FALSE;
+        }
+      else if (sysroot_proxy)
+        {
+          const char *sysroot_path = rpmostree_sysroot_get_path (sysroot_proxy);
+          ROSCXX_TRY (print_treepkg_diff_from_sysroot_path (rust::Str (sysroot_path),
+                                                            diff_format, max_key_len, NULL),
+                      error);
+        }
     }
 
   /* print base overrides before overlays */
@@ -1036,11 +1169,31 @@ print_one_deployment (RPMOSTreeSysroot *sysroot_proxy, GVariant *child, gint ind
     print_values ("EnabledModules", max_key_len, origin_requested_modules_enabled, NULL, TRUE,
                   NULL);
 
+  g_autoptr (GVariant) weakdeps
+      = g_variant_dict_lookup_value (dict, "unsatisfied-weak-deps", G_VARIANT_TYPE ("a(sss)"));
+  if (opt_recommendations && weakdeps)
+    {
+      g_autoptr (GPtrArray) values = g_ptr_array_new_with_free_func (g_free);
+      GVariantIter iter;
+      g_variant_iter_init (&iter, weakdeps);
+      const char *pkgname, *kind, *dep;
+      while (g_variant_iter_next (&iter, "(&s&s&s)", &pkgname, &kind, &dep))
+        g_ptr_array_add (values, g_strdup_printf ("%s (%s by %s)", dep,
+                                                  g_str_equal (kind, "recommends") ? "recommended"
+                                                                                  : "suggested",
+                                                  pkgname));
+      g_ptr_array_add (values, NULL);
+      print_values ("MissingWeakDeps", max_key_len, (const char *const *)values->pdata, NULL, FALSE,
+                    "Not installed:");
+    }
+
   if (origin_requested_local_packages)
     print_values ("LocalPackages", max_key_len, origin_requested_local_packages, NULL, TRUE, NULL);
   if (origin_requested_local_fileoverride_packages)
     print_values ("LocalForcedPackages", max_key_len, origin_requested_local_fileoverride_packages,
                   NULL, TRUE, NULL);
+  if (unverified_local_packages)
+    print_values ("UnverifiedPackages", max_key_len, unverified_local_packages, NULL, TRUE, NULL);
 
   if (regenerate_initramfs)
     {
@@ -1059,12 +1212,44 @@ print_one_deployment (RPMOSTreeSysroot *sysroot_proxy, GVariant *child, gint ind
       if (buf->len == 0)
         g_string_append (buf, "regenerate");
       rpmostree_print_kv ("Initramfs", max_key_len, buf->str);
+
+      const char *generator = NULL;
+      if (g_variant_dict_lookup (dict, "initramfs-generator", "&s", &generator))
+        rpmostree_print_kv ("InitramfsGenerator", max_key_len, generator);
+
+      const struct
+      {
+        const char *key;
+        const char *name;
+      } dracut_config[] = {
+        { "initramfs-add-modules", "InitramfsAddModules" },
+        { "initramfs-omit-modules", "InitramfsOmitModules" },
+        { "initramfs-add-drivers", "InitramfsAddDrivers" },
+      };
+      for (guint i = 0; i < G_N_ELEMENTS (dracut_config); i++)
+        {
+          g_autofree char **values = NULL;
+          g_variant_dict_lookup (dict, dracut_config[i].key, "^a&s", &values);
+          if (values && *values)
+            print_values (dracut_config[i].name, max_key_len, (const char **)values, NULL, TRUE,
+                          NULL);
+        }
     }
 
+  g_autofree char **kargs_profiles = NULL;
+  g_variant_dict_lookup (dict, "kargs-profiles", "^a&s", &kargs_profiles);
+  if (kargs_profiles && *kargs_profiles)
+    print_values ("KernelArgProfiles", max_key_len, (const char **)kargs_profiles, NULL, TRUE,
+                  NULL);
+
   gboolean cliwrap = FALSE;
   if (g_variant_dict_lookup (dict, "cliwrap", "b", &cliwrap) && cliwrap)
     rpmostree_print_kv ("Cliwrap", max_key_len, "enabled");
 
+  gboolean fips = FALSE;
+  if (g_variant_dict_lookup (dict, "fips", "b", &fips) && fips)
+    rpmostree_print_kv ("FIPS", max_key_len, "enabled");
+
   g_autofree char **initramfs_etc_files = NULL;
   g_variant_dict_lookup (dict, "initramfs-etc", "^a&s", &initramfs_etc_files);
   if (initramfs_etc_files && *initramfs_etc_files)
@@ -1073,8 +1258,43 @@ print_one_deployment (RPMOSTreeSysroot *sysroot_proxy, GVariant *child, gint ind
 
   gboolean pinned = FALSE;
   g_variant_dict_lookup (dict, "pinned", "b", &pinned);
-  if (pinned)
-    rpmostree_print_kv ("Pinned", max_key_len, "yes");
+  gboolean retained = FALSE;
+  g_variant_dict_lookup (dict, "retained", "b", &retained);
+  gboolean alternative = FALSE;
+  g_variant_dict_lookup (dict, "alternative", "b", &alternative);
+  if (retained)
+    rpmostree_print_kv ("Retained", max_key_len, "yes; rollback kept per KeepRollbackDeployments");
+  else if (alternative)
+    rpmostree_print_kv ("Alternative", max_key_len,
+                        "yes; pick with \"rpm-ostree finalize --alternative\"");
+  else if (pinned)
+    {
+      g_autoptr (GString) buf = g_string_new ("yes");
+      const char *pin_reason = NULL;
+      if (g_variant_dict_lookup (dict, "pin-reason", "&s", &pin_reason))
+        g_string_append_printf (buf, "; %s", pin_reason);
+      gint64 pin_expires = 0;
+      if (g_variant_dict_lookup (dict, "pin-expires", "x", &pin_expires))
+        {
+          g_autofree char *ts = rpmostree_timestamp_str_from_unix_utc (pin_expires);
+          g_string_append_printf (buf, " (until %s)", ts);
+        }
+      rpmostree_print_kv ("Pinned", max_key_len, buf->str);
+    }
+
+  gboolean ephemeral = FALSE;
+  if (g_variant_dict_lookup (dict, "ephemeral", "b", &ephemeral) && ephemeral)
+    rpmostree_print_kv ("Ephemeral", max_key_len, "yes; removed after its first boot");
+
+  g_autoptr (GVariant) etc_conflicts
+      = g_variant_dict_lookup_value (dict, "etc-conflicts", G_VARIANT_TYPE ("a(ss)"));
+  if (etc_conflicts && g_variant_n_children (etc_conflicts) > 0)
+    {
+      g_autofree char *buf
+          = g_strdup_printf ("%zu file(s); see rpm-ostree status --etc-conflicts",
+                             g_variant_n_children (etc_conflicts));
+      rpmostree_print_kv ("EtcConflicts", max_key_len, buf);
+    }
 
   if (unlocked && g_strcmp0 (unlocked, "none") != 0)
     {
@@ -1082,6 +1302,25 @@ print_one_deployment (RPMOSTreeSysroot *sysroot_proxy, GVariant *child, gint ind
       rpmostree_print_kv ("Unlocked", max_key_len, unlocked);
       g_print ("%s%s", get_bold_end (), get_red_end ());
     }
+  if (is_booted)
+    {
+      CXX_TRY_VAR (usroverlay, rpmostreecxx::usroverlay_mode (), error);
+      /* Only root can read the changes */
+      if (!usroverlay.empty () && getuid () == 0)
+        {
+          CXX_TRY_VAR (changes, rpmostreecxx::usroverlay_changes (), error);
+          g_autofree char *buf
+              = g_strdup_printf ("%s; %zu file(s) changed", usroverlay.c_str (), changes.size ());
+          rpmostree_print_kv ("UsrOverlay", max_key_len, buf);
+          if (opt_verbose)
+            {
+              for (auto &change : changes)
+                rpmostree_print_kv ("", max_key_len, change.c_str ());
+            }
+        }
+      else if (!usroverlay.empty ())
+        rpmostree_print_kv ("UsrOverlay", max_key_len, usroverlay.c_str ());
+    }
   const char *end_of_life_string = NULL;
   /* look for endoflife attribute in the deployment */
   g_variant_dict_lookup (dict, "endoflife", "&s", &end_of_life_string);
@@ -1179,11 +1418,47 @@ print_deployments (RPMOSTreeSysroot *sysroot_proxy, GVariant *deployments, GVari
   return TRUE;
 }
 
+/* Add the "unique-size" of each deployment to @deployments.  The daemon only computes them
+ * when asked for, since it traverses all deployments. */
+static gboolean
+add_unique_sizes (RPMOSTreeSysroot *sysroot_proxy, GVariant **deployments,
+                  GCancellable *cancellable, GError **error)
+{
+  g_autoptr (GVariant) sizes = NULL;
+  if (!rpmostree_sysroot_call_get_deployments_unique_sizes_sync (sysroot_proxy, &sizes,
+                                                                 cancellable, error))
+    return glnx_prefix_error (error, "Computing deployment sizes");
+
+  GVariantBuilder builder;
+  g_variant_builder_init (&builder, G_VARIANT_TYPE ("aa{sv}"));
+  GVariantIter iter;
+  g_variant_iter_init (&iter, *deployments);
+  while (TRUE)
+    {
+      g_autoptr (GVariant) child = g_variant_iter_next_value (&iter);
+      if (child == NULL)
+        break;
+
+      g_auto (GVariantDict) dict;
+      g_variant_dict_init (&dict, child);
+      const char *id;
+      guint64 unique_size;
+      if (g_variant_dict_lookup (&dict, "id", "&s", &id)
+          && g_variant_lookup (sizes, id, "t", &unique_size))
+        g_variant_dict_insert (&dict, "unique-size", "t", unique_size);
+      g_variant_builder_add_value (&builder, g_variant_dict_end (&dict));
+    }
+
+  g_variant_unref (*deployments);
+  *deployments = g_variant_ref_sink (g_variant_builder_end (&builder));
+  return TRUE;
+}
+
 gboolean
 rpmostree_builtin_status (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
                           GCancellable *cancellable, GError **error)
 {
-  g_autoptr (GOptionContext) context = g_option_context_new ("");
+  g_autoptr (GOptionContext) context = rpmostree_option_context_new ("");
   glnx_unref_object RPMOSTreeOS *os_proxy = NULL;
   glnx_unref_object RPMOSTreeSysroot *sysroot_proxy = NULL;
 
@@ -1197,12 +1472,65 @@ rpmostree_builtin_status (int argc, char **argv, RpmOstreeCommandInvocation *inv
                    "Cannot specify both --json and --jsonpath");
       return FALSE;
     }
+  if (opt_format && (opt_json || opt_jsonpath))
+    return glnx_throw (error, "Cannot specify --format with --json or --jsonpath");
+  if (opt_query && (opt_json || opt_jsonpath || opt_format))
+    return glnx_throw (error, "Cannot specify --query with --json, --jsonpath or --format");
+  if (opt_schema_version && !opt_format)
+    return glnx_throw (error, "--schema-version requires --format");
+  if (opt_schema_version < 0)
+    return glnx_throw (error, "Invalid schema version: %d", opt_schema_version);
+  if (opt_needs_reboot
+      && (opt_json || opt_jsonpath || opt_format || opt_query || opt_pending_exit_77))
+    return glnx_throw (error, "Cannot specify --needs-reboot with other output options");
+  if (opt_restarts_needed
+      && (opt_json || opt_jsonpath || opt_format || opt_query || opt_pending_exit_77
+          || opt_needs_reboot))
+    return glnx_throw (error, "Cannot specify --restarts-needed with other output options");
+  if (opt_etc_conflicts
+      && (opt_json || opt_jsonpath || opt_format || opt_query || opt_pending_exit_77
+          || opt_needs_reboot || opt_restarts_needed))
+    return glnx_throw (error, "Cannot specify --etc-conflicts with other output options");
+
+  if (opt_needs_reboot)
+    {
+      g_autoptr (GVariant) deployments = rpmostree_sysroot_dup_deployments (sysroot_proxy);
+      g_assert (deployments);
+      CXX_TRY_VAR (reason, rpmostreecxx::deployments_reboot_reason (*deployments), error);
+      if (!reason.empty ())
+        {
+          g_print ("%s\n", reason.c_str ());
+          invocation->exit_code = RPM_OSTREE_EXIT_PENDING;
+        }
+      return TRUE; /* Note early return */
+    }
+
+  if (opt_restarts_needed)
+    {
+      CXX_TRY_VAR (services, rpmostreecxx::live_restarts_needed (), error);
+      for (auto &service : services)
+        g_print ("%s\n", service.c_str ());
+      if (!services.empty ())
+        invocation->exit_code = RPM_OSTREE_EXIT_PENDING;
+      return TRUE; /* Note early return */
+    }
+
+  if (opt_etc_conflicts)
+    {
+      g_autoptr (GVariant) deployments = rpmostree_sysroot_dup_deployments (sysroot_proxy);
+      g_assert (deployments);
+      CXX_TRY_VAR (conflicts, rpmostreecxx::deployments_etc_conflicts (*deployments), error);
+      g_print ("%s", conflicts.c_str ());
+      return TRUE; /* Note early return */
+    }
 
   if (!rpmostree_load_os_proxy (sysroot_proxy, NULL, cancellable, &os_proxy, error))
     return FALSE;
 
   g_autoptr (GVariant) deployments = rpmostree_sysroot_dup_deployments (sysroot_proxy);
   g_assert (deployments);
+  if (opt_verbose && !add_unique_sizes (sysroot_proxy, &deployments, cancellable, error))
+    return FALSE;
   g_autoptr (GVariant) cached_update = NULL;
   if (rpmostree_os_get_has_cached_update_rpm_diff (os_proxy))
     cached_update = rpmostree_os_dup_cached_update (os_proxy);
@@ -1210,7 +1538,7 @@ rpmostree_builtin_status (int argc, char **argv, RpmOstreeCommandInvocation *inv
   if (!get_driver_g_variant (&driver_info, error))
     return FALSE;
 
-  if (opt_json || opt_jsonpath)
+  if (opt_json || opt_jsonpath || opt_format || opt_query)
     {
       glnx_unref_object JsonBuilder *builder = json_builder_new ();
       json_builder_begin_object (builder);
@@ -1232,32 +1560,58 @@ rpmostree_builtin_status (int argc, char **argv, RpmOstreeCommandInvocation *inv
       JsonNode *update_driver_node
           = driver_info ? json_gvariant_serialize (driver_info) : json_node_new (JSON_NODE_NULL);
       json_builder_add_value (builder, update_driver_node);
+      const char *bootc_interop = rpmostree_sysroot_get_bootc_interop (sysroot_proxy);
+      json_builder_set_member_name (builder, "bootc-interop");
+      if (bootc_interop && *bootc_interop)
+        json_builder_add_string_value (builder, bootc_interop);
+      else
+        json_builder_add_null_value (builder);
       json_builder_end_object (builder);
 
       JsonNode *json_root = json_builder_get_root (builder);
-      glnx_unref_object JsonGenerator *generator = json_generator_new ();
-      json_generator_set_pretty (generator, TRUE);
-
-      if (opt_json)
-        json_generator_set_root (generator, json_root);
+      if (opt_format)
+        {
+          /* The versioned schema is derived from the D-Bus API serialization */
+          g_autofree char *status_json = json_to_string (json_root, FALSE);
+          json_node_free (json_root);
+          CXX_TRY_VAR (out,
+                       rpmostreecxx::status_format (status_json, opt_format, opt_schema_version),
+                       error);
+          g_print ("%s", out.c_str ());
+        }
+      else if (opt_query)
+        {
+          g_autofree char *status_json = json_to_string (json_root, FALSE);
+          json_node_free (json_root);
+          CXX_TRY_VAR (out, rpmostreecxx::status_query (status_json, opt_query), error);
+          g_print ("%s\n", out.c_str ());
+        }
       else
         {
-          JsonNode *result = json_path_query (opt_jsonpath, json_root, error);
-          if (!result)
+          glnx_unref_object JsonGenerator *generator = json_generator_new ();
+          json_generator_set_pretty (generator, TRUE);
+
+          if (opt_json)
+            json_generator_set_root (generator, json_root);
+          else
             {
-              g_prefix_error (error, "While compiling jsonpath: ");
-              return FALSE;
+              JsonNode *result = json_path_query (opt_jsonpath, json_root, error);
+              if (!result)
+                {
+                  g_prefix_error (error, "While compiling jsonpath: ");
+                  return FALSE;
+                }
+              json_generator_set_root (generator, result);
+              json_node_free (result);
             }
-          json_generator_set_root (generator, result);
-          json_node_free (result);
-        }
-      json_node_free (json_root);
+          json_node_free (json_root);
 
-      glnx_unref_object GOutputStream *stdout_gio = g_unix_output_stream_new (1, FALSE);
-      /* NB: watch out for the misleading API docs */
-      if (json_generator_to_stream (generator, stdout_gio, NULL, error) <= 0
-          || (error != NULL && *error != NULL))
-        return FALSE;
+          glnx_unref_object GOutputStream *stdout_gio = g_unix_output_stream_new (1, FALSE);
+          /* NB: watch out for the misleading API docs */
+          if (json_generator_to_stream (generator, stdout_gio, NULL, error) <= 0
+              || (error != NULL && *error != NULL))
+            return FALSE;
+        }
     }
   else
     {
@@ -1355,6 +1709,70 @@ print_timestamp_and_relative (const char *key, guint64 t)
   g_print ("%s (%s)\n", ts, time_rel);
 }
 
+/* Labels for the changes of requests in history diffs */
+static const struct
+{
+  const char *key;
+  const char *label;
+} history_request_labels[] = {
+  { "requested-packages", "LayeredPackages" },
+  { "requested-local-packages", "LocalPackages" },
+  { "requested-modules", "LayeredModules" },
+  { "requested-modules-enabled", "EnabledModules" },
+  { "requested-base-removals", "RemovedBasePackages" },
+  { "requested-base-local-replacements", "ReplacedBasePackages" },
+  { "requested-base-remote-replacements", "ReplacedBasePackages" },
+};
+
+/* Print the "history-diff" of a history entry: what changed from the deployment
+ * it was created from */
+static gboolean
+print_history_diff (GVariant *diff, GError **error)
+{
+  /* same as in print_one_deployment() */
+  const guint max_key_len
+      = MAX (strlen ("InactiveRemoteOverrides"), strlen ("InterruptedLiveCommit"));
+
+  g_auto (GVariantDict) dict;
+  g_variant_dict_init (&dict, diff);
+
+  const char *checksum;
+  if (g_variant_dict_lookup (&dict, "checksum", "&s", &checksum))
+    rpmostree_print_kv ("PreviousCommit", max_key_len, checksum);
+
+  g_autoptr (GVariant) rpm_diff
+      = g_variant_dict_lookup_value (&dict, "rpm-diff", G_VARIANT_TYPE ("a{sv}"));
+  if (!rpmostree_print_diff_advisories (rpm_diff, NULL, NULL, opt_verbose, FALSE, max_key_len,
+                                        error))
+    return FALSE;
+
+  g_autoptr (GVariant) requests
+      = g_variant_dict_lookup_value (&dict, "requests", G_VARIANT_TYPE ("a{sv}"));
+  for (guint i = 0; requests && i < G_N_ELEMENTS (history_request_labels); i++)
+    {
+      g_autoptr (GVariant) changes = g_variant_lookup_value (
+          requests, history_request_labels[i].key, G_VARIANT_TYPE ("a{sv}"));
+      if (!changes)
+        continue;
+
+      g_autoptr (GPtrArray) values = g_ptr_array_new_with_free_func (g_free);
+      const char *prefixes[][2] = { { "added", "+" }, { "removed", "-" } };
+      for (guint j = 0; j < G_N_ELEMENTS (prefixes); j++)
+        {
+          g_autofree const char **pkgs = NULL;
+          if (!g_variant_lookup (changes, prefixes[j][0], "^a&s", &pkgs))
+            continue;
+          for (const char **it = pkgs; it && *it; it++)
+            g_ptr_array_add (values, g_strconcat (prefixes[j][1], *it, NULL));
+        }
+      g_ptr_array_add (values, NULL);
+      print_values (history_request_labels[i].label, max_key_len,
+                    (const char *const *)values->pdata, NULL, FALSE, NULL);
+    }
+
+  return TRUE;
+}
+
 static gboolean
 print_history_entry (const rpmostreecxx::HistoryEntry &entry, GError **error)
 {
@@ -1362,6 +1780,21 @@ print_history_entry (const rpmostreecxx::HistoryEntry &entry, GError **error)
   if (!fetch_history_deployment_gvariant (entry, &deployment, error))
     return FALSE;
 
+  /* split out what changed from the previous deployment and why it was rolled back
+   * automatically, if recorded */
+  g_autoptr (GVariant) diff = NULL;
+  g_autoptr (GVariant) boot_failure = NULL;
+  if (deployment)
+    {
+      g_autoptr (GVariantDict) dict = g_variant_dict_new (deployment);
+      diff = g_variant_dict_lookup_value (dict, "history-diff", G_VARIANT_TYPE ("a{sv}"));
+      g_variant_dict_remove (dict, "history-diff");
+      boot_failure = g_variant_dict_lookup_value (dict, "boot-failure", G_VARIANT_TYPE ("a{sv}"));
+      g_variant_dict_remove (dict, "boot-failure");
+      g_clear_pointer (&deployment, g_variant_unref);
+      deployment = g_variant_ref_sink (g_variant_dict_end (dict));
+    }
+
   if (!opt_json)
     {
       print_timestamp_and_relative ("BootTimestamp", entry.last_boot_timestamp);
@@ -1380,6 +1813,9 @@ print_history_entry (const rpmostreecxx::HistoryEntry &entry, GError **error)
           g_print ("CreateCommand: %s%s%s\n", get_bold_start (), cmdline_copy.c_str (),
                    get_bold_end ());
         }
+      const char *boot_failure_reason = NULL;
+      if (boot_failure && g_variant_lookup (boot_failure, "reason", "&s", &boot_failure_reason))
+        g_print ("BootFailure: %s%s%s\n", get_red_start (), boot_failure_reason, get_red_end ());
       if (!deployment)
         /* somehow we're missing an entry? XXX: just fallback to checksum, version, refspec
          * from journal entry in this case */
@@ -1389,6 +1825,9 @@ print_history_entry (const rpmostreecxx::HistoryEntry &entry, GError **error)
       else if (!print_one_deployment (NULL, deployment, -1, FALSE, FALSE, NULL, NULL, NULL, NULL,
                                       error))
         return FALSE;
+
+      if (diff && !print_history_diff (diff, error))
+        return FALSE;
     }
   else
     {
@@ -1402,6 +1841,16 @@ print_history_entry (const rpmostreecxx::HistoryEntry &entry, GError **error)
           json_builder_set_member_name (builder, "deployment");
           json_builder_add_value (builder, json_gvariant_serialize (deployment));
         }
+      if (diff)
+        {
+          json_builder_set_member_name (builder, "diff");
+          json_builder_add_value (builder, json_gvariant_serialize (diff));
+        }
+      if (boot_failure)
+        {
+          json_builder_set_member_name (builder, "boot-failure");
+          json_builder_add_value (builder, json_gvariant_serialize (boot_failure));
+        }
 
       json_builder_set_member_name (builder, "deployment-create-timestamp");
       json_builder_add_int_value (builder, entry.deploy_timestamp);
@@ -1440,7 +1889,7 @@ gboolean
 rpmostree_ex_builtin_history (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
                               GCancellable *cancellable, GError **error)
 {
-  g_autoptr (GOptionContext) context = g_option_context_new ("");
+  g_autoptr (GOptionContext) context = rpmostree_option_context_new ("");
   if (!rpmostree_option_context_parse (context, history_option_entries, &argc, &argv, invocation,
                                        cancellable, NULL, NULL, NULL, error))
     return FALSE;
@@ -155,6 +155,7 @@ rpmostree_testutils_builtin_script_shell (int argc, char **argv,
   if (!glnx_opendirat (AT_FDCWD, rootpath, TRUE, &rootfs_dfd, error))
     return FALSE;
 
-  return rpmostree_run_script_in_bwrap_container (rootfs_dfd, NULL, TRUE, "testscript", NULL, NULL,
-                                                  NULL, NULL, STDIN_FILENO, cancellable, error);
+  return rpmostree_run_script_in_bwrap_container (rootfs_dfd, NULL, TRUE, NULL, "testscript", NULL,
+                                                  NULL, NULL, NULL, STDIN_FILENO, cancellable,
+                                                  error);
 }
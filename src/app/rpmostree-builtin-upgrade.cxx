@@ -23,6 +23,7 @@
 #include <gio/gio.h>
 #include <glib-unix.h>
 #include <string.h>
+#include <unistd.h>
 
 #include "rpmostree-builtins.h"
 #include "rpmostree-clientlib.h"
@@ -35,6 +36,7 @@ static char *opt_osname;
 static gboolean opt_reboot;
 static gboolean opt_allow_downgrade;
 static gboolean opt_preview;
+static gboolean opt_preview_diff;
 static gboolean opt_check;
 static gboolean opt_upgrade_unchanged_exit_77;
 static gboolean opt_unchanged_exit_77;
@@ -42,7 +44,14 @@ static gboolean opt_cache_only;
 static gboolean opt_download_only;
 static char *opt_automatic;
 static gboolean opt_lock_finalization;
+static gboolean opt_force_policy_rebuild;
+static gboolean opt_alternative;
 static gboolean opt_bypass_driver;
+static gboolean opt_when_idle;
+static gboolean opt_quick;
+static gboolean opt_offline;
+static gboolean opt_from_local_rpms;
+static gboolean opt_changelogs;
 
 /* "check-diff" is deprecated, replaced by "preview" */
 static GOptionEntry option_entries[]
@@ -55,6 +64,10 @@ static GOptionEntry option_entries[]
           "Check for upgrades and print package diff only", NULL },
         { "preview", 0, 0, G_OPTION_ARG_NONE, &opt_preview,
           "Just preview package differences (implies --unchanged-exit-77)", NULL },
+        { "preview-diff", 0, 0, G_OPTION_ARG_NONE, &opt_preview_diff,
+          "Just download the package database of the update and print its package and file "
+          "differences as JSON",
+          NULL },
         { "check", 0, 0, G_OPTION_ARG_NONE, &opt_check,
           "Just check if an upgrade is available (implies --unchanged-exit-77)", NULL },
         { "cache-only", 'C', 0, G_OPTION_ARG_NONE, &opt_cache_only,
@@ -70,15 +83,67 @@ static GOptionEntry option_entries[]
           &opt_automatic, "For automated use only; triggered by automatic timer", NULL },
         { "lock-finalization", 0, G_OPTION_FLAG_HIDDEN, G_OPTION_ARG_NONE, &opt_lock_finalization,
           "Prevent automatic deployment finalization on shutdown", NULL },
+        { "alternative", 0, 0, G_OPTION_ARG_NONE, &opt_alternative,
+          "Write the new deployment as the alternative to the staged one instead of replacing it",
+          NULL },
+        { "force-policy-rebuild", 0, 0, G_OPTION_ARG_NONE, &opt_force_policy_rebuild,
+          "Don't reuse the SELinux policy cached from previous layering operations", NULL },
         { "bypass-driver", 0, 0, G_OPTION_ARG_NONE, &opt_bypass_driver,
           "Force an upgrade even if an updates driver is registered", NULL },
+        { "when-idle", 0, 0, G_OPTION_ARG_NONE, &opt_when_idle,
+          "With --check or --preview, wait until the system is idle (for up to an hour)", NULL },
+        { "quick", 0, 0, G_OPTION_ARG_NONE, &opt_quick,
+          "With --check, only check the base with a single small fetch, ignoring layered packages",
+          NULL },
+        { "offline", 0, 0, G_OPTION_ARG_NONE, &opt_offline,
+          "Download the update, and apply it in a dedicated offline update boot", NULL },
+        { "from-local-rpms", 0, 0, G_OPTION_ARG_NONE, &opt_from_local_rpms,
+          "Reconstruct the update from locally available packages where possible, and only "
+          "download the rest",
+          NULL },
+        { "changelogs", 0, 0, G_OPTION_ARG_NONE, &opt_changelogs,
+          "Print the RPM changelogs of the upgraded packages", NULL },
         { NULL } };
 
+/* Implements --preview-diff: fetch the rpmdb of the update without deploying
+ * it, and print the diff with the default deployment as JSON on stdout. */
+static gboolean
+preview_diff (RPMOSTreeSysroot *sysroot_proxy, RPMOSTreeOS *os_proxy, GCancellable *cancellable,
+              GError **error)
+{
+  g_autofree char *transaction_address = NULL;
+  if (!rpmostree_os_call_download_update_rpm_diff_sync (os_proxy, &transaction_address,
+                                                        cancellable, error))
+    return FALSE;
+
+  /* Keep the transaction output (including progress bars) out of the JSON by
+   * sending it to stderr */
+  fflush (stdout);
+  glnx_autofd int stdout_fd = dup (STDOUT_FILENO);
+  if (stdout_fd < 0 || dup2 (STDERR_FILENO, STDOUT_FILENO) < 0)
+    return glnx_throw_errno_prefix (error, "Redirecting stdout");
+  gboolean ok = rpmostree_transaction_get_response_sync (sysroot_proxy, transaction_address,
+                                                         cancellable, error);
+  fflush (stdout);
+  if (dup2 (stdout_fd, STDOUT_FILENO) < 0)
+    return glnx_throw_errno_prefix (error, "Restoring stdout");
+  if (!ok)
+    return FALSE;
+
+  g_autoptr (GVariant) diff = NULL;
+  if (!rpmostree_os_call_get_cached_update_diff_sync (os_proxy, "", &diff, cancellable, error))
+    return FALSE;
+
+  CXX_TRY_VAR (json, rpmostreecxx::client_render_update_diff_json (*diff), error);
+  g_print ("%s", json.c_str ());
+  return TRUE;
+}
+
 gboolean
 rpmostree_builtin_upgrade (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
                            GCancellable *cancellable, GError **error)
 {
-  g_autoptr (GOptionContext) context = g_option_context_new ("");
+  g_autoptr (GOptionContext) context = rpmostree_option_context_new ("");
   glnx_unref_object RPMOSTreeOS *os_proxy = NULL;
   glnx_unref_object RPMOSTreeSysroot *sysroot_proxy = NULL;
   g_autofree char *transaction_address = NULL;
@@ -111,6 +176,36 @@ rpmostree_builtin_upgrade (int argc, char **argv, RpmOstreeCommandInvocation *in
       return FALSE;
     }
 
+  if (opt_preview_diff
+      && (opt_reboot || opt_preview || opt_check || install_pkgs != NULL
+          || uninstall_pkgs != NULL))
+    {
+      g_set_error (error, G_IO_ERROR, G_IO_ERROR_INVALID_ARGUMENT,
+                   "Cannot specify --preview-diff with --reboot, --preview, --check or "
+                   "--install/--uninstall");
+      return FALSE;
+    }
+
+  if (opt_when_idle && !(opt_automatic || opt_check || opt_preview))
+    return glnx_throw (error, "--when-idle requires --check or --preview");
+
+  if (opt_quick && !opt_check)
+    return glnx_throw (error, "--quick requires --check");
+
+  if (opt_offline
+      && (opt_check || opt_preview || opt_preview_diff || opt_cache_only || opt_download_only
+          || install_pkgs != NULL || uninstall_pkgs != NULL))
+    return glnx_throw (error, "Cannot specify --offline with --check, --preview, --preview-diff, "
+                              "--cache-only, --download-only or --install/--uninstall");
+
+  if (opt_from_local_rpms && (opt_check || opt_preview || opt_preview_diff || opt_cache_only))
+    return glnx_throw (error, "Cannot specify --from-local-rpms with --check, --preview, "
+                              "--preview-diff or --cache-only");
+
+  if (opt_changelogs && (opt_reboot || opt_check || opt_preview || opt_preview_diff))
+    return glnx_throw (error, "Cannot specify --changelogs with --reboot, --check, --preview or "
+                              "--preview-diff");
+
   /* If both --check and --preview were passed, --preview overrides. */
   if (opt_preview)
     opt_check = FALSE;
@@ -126,7 +221,7 @@ rpmostree_builtin_upgrade (int argc, char **argv, RpmOstreeCommandInvocation *in
   if (!opt_automatic)
     {
       const char *policy = rpmostree_sysroot_get_automatic_update_policy (sysroot_proxy);
-      if (policy && g_str_equal (policy, "stage"))
+      if (policy && (g_str_equal (policy, "stage") || g_str_equal (policy, "apply-live")))
         g_print ("note: automatic updates (%s) are enabled\n", policy);
     }
 
@@ -134,25 +229,64 @@ rpmostree_builtin_upgrade (int argc, char **argv, RpmOstreeCommandInvocation *in
     if (!error_if_driver_registered (sysroot_proxy, cancellable, error))
       return FALSE;
 
+  if (opt_preview_diff)
+    return preview_diff (sysroot_proxy, os_proxy, cancellable, error);
+
+  /* On hosts managed by bootc, plain upgrades may be handed off to it; NULL with an older
+   * daemon */
+  const char *bootc_interop = rpmostree_sysroot_get_bootc_interop (sysroot_proxy);
+  if (g_strcmp0 (bootc_interop, "delegate") == 0 && !opt_osname
+      && !(opt_automatic || opt_check || opt_preview || opt_cache_only || opt_download_only
+           || opt_allow_downgrade || opt_lock_finalization || opt_alternative || opt_offline
+           || opt_from_local_rpms || install_pkgs != NULL || uninstall_pkgs != NULL))
+    {
+      CXX_TRY_VAR (delegated, rpmostreecxx::bootc_delegate_upgrade (opt_reboot), error);
+      if (delegated)
+        return TRUE;
+    }
+
   g_autoptr (GVariant) previous_deployment = rpmostree_os_dup_default_deployment (os_proxy);
 
   const gboolean check_or_preview = (opt_check || opt_preview);
   if (opt_automatic || check_or_preview)
     {
-      GVariantDict dict;
-      g_variant_dict_init (&dict, NULL);
-      g_variant_dict_insert (&dict, "mode", "s", check_or_preview ? "check" : "auto");
-      g_variant_dict_insert (&dict, "initiating-command-line", "s", invocation->command_line);
-      /* override default of TRUE if we're handling --check/--preview for backcompat,
-       * or we're *are* handling --trigger-automatic-update-policy, but on a tty */
-      if (check_or_preview || glnx_stdout_is_tty ())
-        g_variant_dict_insert (&dict, "output-to-self", "b", FALSE);
-      g_autoptr (GVariant) options = g_variant_ref_sink (g_variant_dict_end (&dict));
-
       gboolean auto_updates_enabled;
-      if (!rpmostree_os_call_automatic_update_trigger_sync (
-              os_proxy, options, &auto_updates_enabled, &transaction_address, cancellable, error))
-        return FALSE;
+      guint idle_waited_secs = 0;
+      while (TRUE)
+        {
+          GVariantDict dict;
+          g_variant_dict_init (&dict, NULL);
+          g_variant_dict_insert (&dict, "mode", "s", check_or_preview ? "check" : "auto");
+          g_variant_dict_insert (&dict, "initiating-command-line", "s", invocation->command_line);
+          /* override default of TRUE if we're handling --check/--preview for backcompat,
+           * or we're *are* handling --trigger-automatic-update-policy, but on a tty */
+          if (check_or_preview || glnx_stdout_is_tty ())
+            g_variant_dict_insert (&dict, "output-to-self", "b", FALSE);
+          g_variant_dict_insert (&dict, "when-idle", "b", opt_when_idle);
+          if (opt_quick)
+            g_variant_dict_insert (&dict, "quick", "b", TRUE);
+          g_autoptr (GVariant) options = g_variant_ref_sink (g_variant_dict_end (&dict));
+
+          g_autoptr (GError) local_error = NULL;
+          if (rpmostree_os_call_automatic_update_trigger_sync (os_proxy, options,
+                                                               &auto_updates_enabled,
+                                                               &transaction_address, cancellable,
+                                                               &local_error))
+            break;
+          /* not a failure; the policy is backing off after failing previously */
+          if (g_dbus_error_is_remote_error (local_error))
+            {
+              g_autofree char *remote_err = g_dbus_error_get_remote_error (local_error);
+              if (g_str_equal (remote_err, "org.projectatomic.rpmostreed.Error.BackingOff"))
+                {
+                  g_dbus_error_strip_remote_error (local_error);
+                  g_print ("%s\n", local_error->message);
+                  return TRUE; /* Note early return */
+                }
+            }
+          if (!rpmostree_retry_when_idle (&local_error, &opt_when_idle, &idle_waited_secs, error))
+            return FALSE;
+        }
 
       if (!auto_updates_enabled)
         {
@@ -169,7 +303,15 @@ rpmostree_builtin_upgrade (int argc, char **argv, RpmOstreeCommandInvocation *in
       g_variant_dict_insert (&dict, "allow-downgrade", "b", opt_allow_downgrade);
       g_variant_dict_insert (&dict, "cache-only", "b", opt_cache_only);
       g_variant_dict_insert (&dict, "download-only", "b", opt_download_only);
+      if (opt_offline)
+        g_variant_dict_insert (&dict, "offline", "b", TRUE);
       g_variant_dict_insert (&dict, "lock-finalization", "b", opt_lock_finalization);
+      if (opt_alternative)
+        g_variant_dict_insert (&dict, "alternative", "b", TRUE);
+      if (opt_from_local_rpms)
+        g_variant_dict_insert (&dict, "from-local-rpms", "b", TRUE);
+      if (opt_force_policy_rebuild)
+        g_variant_dict_insert (&dict, "force-policy-rebuild", "b", TRUE);
       g_variant_dict_insert (&dict, "initiating-command-line", "s", invocation->command_line);
       g_autoptr (GVariant) options = g_variant_ref_sink (g_variant_dict_end (&dict));
 
@@ -222,6 +364,18 @@ rpmostree_builtin_upgrade (int argc, char **argv, RpmOstreeCommandInvocation *in
     }
   else if (!opt_reboot)
     {
+      const char *sysroot_path = rpmostree_sysroot_get_path (sysroot_proxy);
+      g_autoptr (GFile) sysroot_file = g_file_new_for_path (sysroot_path);
+      g_autoptr (OstreeSysroot) sysroot = ostree_sysroot_new (sysroot_file);
+
+      /* With --download-only, print what was downloaded */
+      if (opt_changelogs && opt_download_only)
+        {
+          if (!ostree_sysroot_load (sysroot, cancellable, error))
+            return FALSE;
+          return rpmostree_print_pending_changelogs (sysroot, cancellable, error);
+        }
+
       if (!rpmostree_has_new_default_deployment (os_proxy, previous_deployment))
         {
           if (opt_upgrade_unchanged_exit_77 || opt_unchanged_exit_77)
@@ -229,12 +383,21 @@ rpmostree_builtin_upgrade (int argc, char **argv, RpmOstreeCommandInvocation *in
           return TRUE;
         }
 
-      /* do diff without dbus: https://github.com/projectatomic/rpm-ostree/pull/116 */
-      const char *sysroot_path = rpmostree_sysroot_get_path (sysroot_proxy);
-      ROSCXX_TRY (print_treepkg_diff_from_sysroot_path (rust::Str (sysroot_path),
-                                                        RPMOSTREE_DIFF_PRINT_FORMAT_FULL_MULTILINE,
-                                                        0, cancellable),
-                  error);
+      if (opt_changelogs)
+        {
+          if (!ostree_sysroot_load (sysroot, cancellable, error))
+            return FALSE;
+          if (!rpmostree_print_pending_changelogs (sysroot, cancellable, error))
+            return FALSE;
+        }
+      else
+        {
+          /* do diff without dbus: https://github.com/projectatomic/rpm-ostree/pull/116 */
+          ROSCXX_TRY (print_treepkg_diff_from_sysroot_path (
+                          rust::Str (sysroot_path), RPMOSTREE_DIFF_PRINT_FORMAT_FULL_MULTILINE, 0,
+                          cancellable),
+                      error);
+        }
 
       g_print ("Run \"systemctl reboot\" to start a reboot\n");
     }
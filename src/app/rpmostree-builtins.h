@@ -44,6 +44,7 @@ BUILTINPROTO (rollback);
 BUILTINPROTO (initramfs);
 BUILTINPROTO (status);
 BUILTINPROTO (refresh_md);
+BUILTINPROTO (remote);
 BUILTINPROTO (db);
 BUILTINPROTO (internals);
 BUILTINPROTO (container);
@@ -59,9 +60,14 @@ BUILTINPROTO (testutils);
 BUILTINPROTO (ex);
 BUILTINPROTO (finalize_deployment);
 BUILTINPROTO (initramfs_etc);
+BUILTINPROTO (apply_live);
 
 #undef BUILTINPROTO
 
+GOptionContext *rpmostree_option_context_new (const char *parameter_string);
+
+void rpmostree_option_context_add_entries (GOptionContext *context, const GOptionEntry *entries);
+
 gboolean rpmostree_option_context_parse (GOptionContext *context, const GOptionEntry *main_entries,
                                          int *argc, char ***argv,
                                          RpmOstreeCommandInvocation *invocation,
@@ -25,6 +25,7 @@
 #include <sys/prctl.h>
 #include <sys/socket.h>
 #include <systemd/sd-login.h>
+#include <stdexcept>
 #include <unistd.h>
 #include <utility>
 
@@ -45,6 +46,9 @@ static gboolean impl_transaction_get_response_sync (GDBusConnection *connection,
                                                     GCancellable *cancellable, GError **error);
 
 #define RPMOSTREE_CLI_ID "cli"
+/* How often to retry, and for how long, while the daemon says the system is busy */
+#define WHEN_IDLE_RETRY_SECS 60
+#define WHEN_IDLE_MAX_WAIT_SECS (60 * 60)
 
 /* Used to close race conditions by ensuring the daemon status is up-to-date */
 static void
@@ -66,16 +70,96 @@ await_reload_sync (RPMOSTreeSysroot *sysroot_proxy)
     g_main_context_iteration (NULL, TRUE);
 }
 
+/* Whether @sysroot is the one of the booted system, which the system daemon manages */
+static gboolean
+sysroot_is_booted (const char *sysroot, gboolean *out_is_booted, GError **error)
+{
+  if (!glnx_fstatat_allow_noent (AT_FDCWD, "/run/ostree-booted", NULL, 0, error))
+    return FALSE;
+  if (errno == ENOENT)
+    {
+      *out_is_booted = FALSE;
+      return TRUE;
+    }
+
+  struct stat booted_stbuf;
+  if (!glnx_fstatat (AT_FDCWD, "/ostree/repo", &booted_stbuf, 0, error))
+    return FALSE;
+  g_autofree char *repo_path = g_build_filename (sysroot, "ostree/repo", NULL);
+  struct stat stbuf;
+  if (!glnx_fstatat (AT_FDCWD, repo_path, &stbuf, 0, error))
+    return glnx_prefix_error (error, "Invalid sysroot %s", sysroot);
+  *out_is_booted = (stbuf.st_dev == booted_stbuf.st_dev && stbuf.st_ino == booted_stbuf.st_ino);
+  return TRUE;
+}
+
+static void
+private_daemon_child_setup (gpointer user_data)
+{
+  /* Make sure the daemon goes away when we do */
+  if (prctl (PR_SET_PDEATHSIG, SIGTERM) < 0)
+    err (EXIT_FAILURE, "prctl");
+}
+
+/* Spawn a private daemon operating on @sysroot which only serves us, over a socket pair, and
+ * connect to it.  This is used for sysroots we're not booted into, e.g. by provisioning
+ * tools building disk images offline.  Like the system daemon, it holds the ostree sysroot
+ * lock during transactions, so that they are serialized with any other daemon operating on
+ * the same sysroot.
+ */
+static GDBusConnection *
+private_daemon_connect (const char *sysroot, GCancellable *cancellable, GError **error)
+{
+  GLNX_AUTO_PREFIX_ERROR ("Starting private daemon", error);
+  int pair[2];
+  if (socketpair (AF_UNIX, SOCK_STREAM | SOCK_CLOEXEC, 0, pair) < 0)
+    return (GDBusConnection *)glnx_null_throw_errno_prefix (error, "socketpair");
+  glnx_autofd int client_fd = pair[0];
+  glnx_autofd int daemon_fd = pair[1];
+
+  g_autoptr (GSubprocessLauncher) launcher = g_subprocess_launcher_new (G_SUBPROCESS_FLAGS_NONE);
+  g_subprocess_launcher_take_fd (launcher, glnx_steal_fd (&daemon_fd), 3);
+  g_subprocess_launcher_set_child_setup (launcher, private_daemon_child_setup, NULL, NULL);
+  g_autoptr (GSubprocess) daemon = g_subprocess_launcher_spawn (
+      launcher, error, "rpm-ostree", "start-daemon", "--sysroot", sysroot, "--dbus-peer=3", NULL);
+  if (!daemon)
+    return NULL;
+
+  g_autoptr (GSocket) socket = g_socket_new_from_fd (client_fd, error);
+  if (!socket)
+    return NULL;
+  client_fd = -1; /* Now owned by the socket */
+  g_autoptr (GSocketConnection) stream = g_socket_connection_factory_create_connection (socket);
+  return g_dbus_connection_new_sync (G_IO_STREAM (stream), NULL,
+                                     G_DBUS_CONNECTION_FLAGS_AUTHENTICATION_CLIENT, NULL,
+                                     cancellable, error);
+}
+
 /* Connect via DBus and register as a client to rpm-ostreed,
  * with a retry loop in case the daemon
  * is in the process of auto-exiting.
  */
 static gboolean
-app_load_sysroot_impl (const char *sysroot, GCancellable *cancellable, GDBusConnection **out_conn,
-                       GError **error)
+app_load_sysroot_impl (const char *sysroot, gboolean force_peer, GCancellable *cancellable,
+                       GDBusConnection **out_conn, GError **error)
 {
   const char *bus_name = NULL;
 
+  gboolean use_peer = force_peer;
+  if (sysroot != NULL && !use_peer)
+    {
+      gboolean is_booted = FALSE;
+      if (!sysroot_is_booted (sysroot, &is_booted, error))
+        return FALSE;
+      use_peer = !is_booted;
+    }
+  if (use_peer)
+    {
+      /* There's nothing to register with; the daemon exits when we close the connection */
+      *out_conn = private_daemon_connect (sysroot ?: "/", cancellable, error);
+      return *out_conn != NULL;
+    }
+
   ROSCXX_TRY (client_start_daemon (), error);
 
   g_autoptr (GDBusConnection) connection = g_bus_get_sync (G_BUS_TYPE_SYSTEM, cancellable, error);
@@ -151,7 +235,7 @@ new_client_connection ()
   g_autoptr (GError) local_error = NULL;
   GDBusConnection *conn = NULL;
 
-  if (!app_load_sysroot_impl ("/", NULL, &conn, &local_error))
+  if (!app_load_sysroot_impl (NULL, FALSE, NULL, &conn, &local_error))
     util::throw_gerror (local_error);
   return std::make_unique<ClientConnection> (conn);
 }
@@ -178,6 +262,9 @@ ClientConnection::transaction_connect_progress_sync (const rust::Str address) co
 
 /**
  * rpmostree_load_sysroot
+ * @sysroot: (allow-none): Path of the sysroot; a private daemon is spawned for sysroots
+ *   other than the one of the booted system
+ * @force_peer: Spawn a private daemon even for the sysroot of the booted system
  * @cancellable: A GCancellable
  * @out_sysroot: (out) Return location for sysroot
  * @error: A pointer to a GError pointer.
@@ -185,12 +272,12 @@ ClientConnection::transaction_connect_progress_sync (const rust::Str address) co
  * Returns: True on success
  **/
 gboolean
-rpmostree_load_sysroot (const char *sysroot, GCancellable *cancellable,
+rpmostree_load_sysroot (const char *sysroot, gboolean force_peer, GCancellable *cancellable,
                         RPMOSTreeSysroot **out_sysroot_proxy, GError **error)
 {
   g_autoptr (GDBusConnection) connection = NULL;
 
-  if (!app_load_sysroot_impl (sysroot, cancellable, &connection, error))
+  if (!app_load_sysroot_impl (sysroot, force_peer, cancellable, &connection, error))
     return FALSE;
 
   const char *bus_name = NULL;
@@ -218,6 +305,9 @@ rpmostree_load_os_proxies (RPMOSTreeSysroot *sysroot_proxy, const char *opt_osna
   g_autofree char *os_object_path = NULL;
   if (opt_osname == NULL)
     os_object_path = rpmostree_sysroot_dup_booted (sysroot_proxy);
+  /* "/" means we're not booted into the sysroot; the daemon picks the default OS then */
+  if (g_strcmp0 (os_object_path, "/") == 0)
+    g_clear_pointer (&os_object_path, g_free);
   if (os_object_path == NULL)
     {
       /* Usually if opt_osname is null and the property isn't
@@ -266,6 +356,35 @@ rpmostree_load_os_proxy (RPMOSTreeSysroot *sysroot_proxy, gchar *opt_osname,
                                     error);
 }
 
+/* Where to write the Progress signals as JSON lines, see --json-progress-fd */
+static int json_progress_fd = -1;
+
+void
+rpmostree_set_json_progress_fd (int fd)
+{
+  json_progress_fd = fd;
+}
+
+/* Write a Progress signal to json_progress_fd; on failure, warn and stop writing. */
+static void
+write_json_progress (GVariant *parameters)
+{
+  if (json_progress_fd < 0)
+    return;
+  try
+    {
+      auto line = rpmostreecxx::client_render_progress_json (*parameters);
+      if (glnx_loop_write (json_progress_fd, line.data (), line.size ()) < 0)
+        throw std::runtime_error (g_strerror (errno));
+    }
+  catch (std::exception &e)
+    {
+      g_printerr ("warning: Failed to write progress to fd %d: %s\n", json_progress_fd,
+                  e.what ());
+      json_progress_fd = -1;
+    }
+}
+
 typedef struct
 {
   gboolean progress;
@@ -377,6 +496,10 @@ on_transaction_progress (GDBusProxy *proxy, gchar *sender_name, gchar *signal_na
       else
         rpmostreecxx::console_progress_set_message (line.c_str ());
     }
+  else if (g_strcmp0 (signal_name, "Progress") == 0)
+    {
+      write_json_progress (parameters);
+    }
   else if (g_strcmp0 (signal_name, "Finished") == 0)
     {
       if (tp->error == NULL)
@@ -1048,8 +1171,8 @@ severity_to_str (RpmOstreeAdvisorySeverity severity)
     }
 }
 
-void
-rpmostree_print_advisories (GVariant *advisories, gboolean verbose, guint max_key_len)
+static void
+print_advisories (const char *key, GVariant *advisories, gboolean verbose, guint max_key_len)
 {
   /* counters for none/unknown, low, moderate, important, critical advisories */
   guint n_sev[RPM_OSTREE_ADVISORY_SEVERITY_LAST] = {
@@ -1099,9 +1222,9 @@ rpmostree_print_advisories (GVariant *advisories, gboolean verbose, guint max_ke
 
   /* this signals to just print leftmost */
   if (max_key_len == 0)
-    g_print ("SecAdvisories:\n");
+    g_print ("%s:\n", key);
   else
-    rpmostree_print_kv_no_newline ("SecAdvisories", max_key_len, "");
+    rpmostree_print_kv_no_newline (key, max_key_len, "");
 
   if (!verbose)
     {
@@ -1146,7 +1269,7 @@ rpmostree_print_advisories (GVariant *advisories, gboolean verbose, guint max_ke
           const char *nevra;
           g_variant_get_child (pkgs, j, "&s", &nevra);
 
-          if (i == 0 && j == 0 && max_key_len > 0) /* we're on the same line as SecAdvisories */
+          if (i == 0 && j == 0 && max_key_len > 0) /* we're on the same line as the key */
             g_print ("%-*s  %-*s  %s\n", max_id_len, id, max_sev_len, severity_str, nevra);
           else
             g_print ("  %*s  %-*s  %-*s  %s\n", max_key_len, "", max_id_len, id, max_sev_len,
@@ -1175,9 +1298,16 @@ rpmostree_print_advisories (GVariant *advisories, gboolean verbose, guint max_ke
     }
 }
 
-/* print "rpm-diff" and "advisories" GVariants from a cached update */
+void
+rpmostree_print_advisories (GVariant *advisories, gboolean verbose, guint max_key_len)
+{
+  print_advisories ("SecAdvisories", advisories, verbose, max_key_len);
+}
+
+/* print "rpm-diff", "advisories" and "layered-advisories" GVariants from a cached update */
 gboolean
-rpmostree_print_diff_advisories (GVariant *rpm_diff, GVariant *advisories, gboolean verbose,
+rpmostree_print_diff_advisories (GVariant *rpm_diff, GVariant *advisories,
+                                 GVariant *layered_advisories, gboolean verbose,
                                  gboolean verbose_advisories, guint max_key_len, GError **error)
 {
   if (!rpm_diff)
@@ -1185,6 +1315,10 @@ rpmostree_print_diff_advisories (GVariant *rpm_diff, GVariant *advisories, gbool
 
   if (advisories)
     rpmostree_print_advisories (advisories, verbose || verbose_advisories, max_key_len);
+  /* "advisories" includes these too; just summarize which ones are about pkgs layered or
+   * overridden client-side, since they may need attention of their own */
+  if (layered_advisories)
+    print_advisories ("LayeredSecAdvisories", layered_advisories, FALSE, max_key_len);
 
   g_auto (GVariantDict) rpm_diff_dict;
   g_variant_dict_init (&rpm_diff_dict, rpm_diff);
@@ -1262,18 +1396,24 @@ rpmostree_print_cached_update (GVariant *cached_update, gboolean verbose,
   gboolean is_new_checksum;
   g_assert (g_variant_dict_lookup (&dict, "ref-has-new-commit", "b", &is_new_checksum));
 
+  const char *rollout_deferred;
+  if (!g_variant_dict_lookup (&dict, "rollout-deferred", "&s", &rollout_deferred))
+    rollout_deferred = NULL;
+
   g_autoptr (GVariant) rpm_diff
       = g_variant_dict_lookup_value (&dict, "rpm-diff", G_VARIANT_TYPE ("a{sv}"));
 
   g_autoptr (GVariant) advisories
       = g_variant_dict_lookup_value (&dict, "advisories", G_VARIANT_TYPE ("a(suuasa{sv})"));
+  g_autoptr (GVariant) layered_advisories = g_variant_dict_lookup_value (
+      &dict, "layered-advisories", G_VARIANT_TYPE ("a(suuasa{sv})"));
 
   /* and now we can print 🖨️ things! */
 
   g_print ("AvailableUpdate:\n");
 
   /* add the long keys here */
-  const guint max_key_len = MAX (strlen ("SecAdvisories"), strlen ("GPGSignature"));
+  const guint max_key_len = MAX (strlen ("LayeredSecAdvisories"), strlen ("GPGSignature"));
 
   if (is_new_checksum)
     {
@@ -1281,10 +1421,15 @@ rpmostree_print_cached_update (GVariant *cached_update, gboolean verbose,
       rpmostree_print_kv ("Commit", max_key_len, checksum);
       if (gpg_enabled)
         rpmostree_print_gpg_info (signatures, verbose, max_key_len);
+      if (rollout_deferred)
+        {
+          g_autofree char *rollout = g_strdup_printf ("deferred; %s", rollout_deferred);
+          rpmostree_print_kv ("Rollout", max_key_len, rollout);
+        }
     }
 
-  if (!rpmostree_print_diff_advisories (rpm_diff, advisories, verbose, verbose_advisories,
-                                        max_key_len, error))
+  if (!rpmostree_print_diff_advisories (rpm_diff, advisories, layered_advisories, verbose,
+                                        verbose_advisories, max_key_len, error))
     return FALSE;
 
   return TRUE;
@@ -1455,3 +1600,39 @@ error_if_driver_registered (RPMOSTreeSysroot *sysroot_proxy, GCancellable *cance
 
   return TRUE;
 }
+
+/* Implements --when-idle: with the "when-idle" option, the daemon refuses to start
+ * non-interactive operations while the system is busy.  If the call which failed with
+ * @local_error was refused for that, wait and return TRUE to retry it; once we've waited for
+ * long enough, unset @when_idle so that the retry goes ahead regardless.  Otherwise propagate
+ * the error and return FALSE. */
+gboolean
+rpmostree_retry_when_idle (GError **local_error, gboolean *when_idle, guint *waited_secs,
+                           GError **error)
+{
+  g_autofree char *remote_err = NULL;
+  if (g_dbus_error_is_remote_error (*local_error))
+    remote_err = g_dbus_error_get_remote_error (*local_error);
+  if (!*when_idle || g_strcmp0 (remote_err, "org.projectatomic.rpmostreed.Error.SystemBusy") != 0)
+    {
+      g_propagate_error (error, util::move_nullify (*local_error));
+      return FALSE;
+    }
+
+  g_dbus_error_strip_remote_error (*local_error);
+  if (*waited_secs >= WHEN_IDLE_MAX_WAIT_SECS)
+    {
+      g_print ("%s; proceeding after waiting for %u minutes\n", (*local_error)->message,
+               *waited_secs / 60);
+      *when_idle = FALSE;
+    }
+  else
+    {
+      if (*waited_secs == 0)
+        g_print ("%s; waiting until it is idle\n", (*local_error)->message);
+      sleep (WHEN_IDLE_RETRY_SECS);
+      *waited_secs += WHEN_IDLE_RETRY_SECS;
+    }
+  g_clear_error (local_error);
+  return TRUE;
+}
@@ -64,8 +64,11 @@ G_BEGIN_DECLS
 
 #define BUS_NAME "org.projectatomic.rpmostree1"
 
-gboolean rpmostree_load_sysroot (const char *sysroot, GCancellable *cancellable,
-                                 RPMOSTreeSysroot **out_sysroot_proxy, GError **error);
+void rpmostree_set_json_progress_fd (int fd);
+
+gboolean rpmostree_load_sysroot (const char *sysroot, gboolean force_peer,
+                                 GCancellable *cancellable, RPMOSTreeSysroot **out_sysroot_proxy,
+                                 GError **error);
 
 gboolean rpmostree_load_os_proxy (RPMOSTreeSysroot *sysroot_proxy, gchar *opt_osname,
                                   GCancellable *cancellable, RPMOSTreeOS **out_os_proxy,
@@ -108,8 +111,9 @@ gboolean rpmostree_update_deployment (
     char **out_transaction_address, GCancellable *cancellable, GError **error);
 
 gboolean rpmostree_print_diff_advisories (GVariant *rpm_diff, GVariant *advisories,
-                                          gboolean verbose, gboolean verbose_advisories,
-                                          guint max_key_len, GError **error);
+                                          GVariant *layered_advisories, gboolean verbose,
+                                          gboolean verbose_advisories, guint max_key_len,
+                                          GError **error);
 
 gboolean rpmostree_print_cached_update (GVariant *cached_update, gboolean verbose,
                                         gboolean verbose_advisories, GCancellable *cancellable,
@@ -124,4 +128,7 @@ gboolean get_sd_unit_objpath (GDBusConnection *connection, const char *method_na
 gboolean error_if_driver_registered (RPMOSTreeSysroot *sysroot_proxy, GCancellable *cancellable,
                                      GError **error);
 
+gboolean rpmostree_retry_when_idle (GError **local_error, gboolean *when_idle, guint *waited_secs,
+                                    GError **error);
+
 G_END_DECLS
@@ -75,6 +75,7 @@ static char *opt_write_lockfile_to;
 static char **opt_lockfiles;
 static gboolean opt_lockfile_strict;
 static char *opt_parent;
+static int opt_generate_static_deltas;
 
 static char *opt_extensions_output_dir;
 static char *opt_extensions_base_rev;
@@ -136,6 +137,8 @@ static GOptionEntry commit_option_entries[] = {
     "Write JSON to FILE containing information about the compose run", "FILE" },
   { "no-parent", 0, 0, G_OPTION_ARG_NONE, &opt_no_parent, "Always commit without a parent", NULL },
   { "parent", 0, 0, G_OPTION_ARG_STRING, &opt_parent, "Commit with specific parent", "REV" },
+  { "generate-static-deltas", 0, 0, G_OPTION_ARG_INT, &opt_generate_static_deltas,
+    "Generate static deltas to the new commit from its last N ancestors", "N" },
   { NULL }
 };
 
@@ -842,6 +845,26 @@ inject_advisories (RpmOstreeTreeComposeContext *self, GCancellable *cancellable,
   return TRUE;
 }
 
+/* Record the keys the packages were verified against, if rpm-signing-keys is set */
+static gboolean
+inject_rpm_signatures (RpmOstreeTreeComposeContext *self, GError **error)
+{
+  g_autoptr (GVariant) keys = NULL;
+  g_autoptr (GVariant) signatures = NULL;
+  if (!rpmostree_context_get_rpm_signatures (self->corectx, &keys, &signatures, error))
+    return FALSE;
+
+  if (keys)
+    {
+      g_hash_table_insert (self->metadata, g_strdup ("rpmostree.rpm-signing-keys"),
+                           g_steal_pointer (&keys));
+      g_hash_table_insert (self->metadata, g_strdup ("rpmostree.rpm-signatures"),
+                           g_steal_pointer (&signatures));
+    }
+
+  return TRUE;
+}
+
 static gboolean
 impl_install_tree (RpmOstreeTreeComposeContext *self, gboolean *out_changed,
                    GCancellable *cancellable, GError **error)
@@ -885,6 +908,10 @@ impl_install_tree (RpmOstreeTreeComposeContext *self, gboolean *out_changed,
   if (!opt_unified_core && json_object_has_member (self->treefile, "modules"))
     return glnx_throw (error, "Composing with modules requires --unified-core");
 
+  /* Similarly, packages are only verified against rpm-signing-keys when importing them */
+  if (!opt_unified_core && !(*self->treefile_rs)->get_rpm_signing_keys ().empty ())
+    return glnx_throw (error, "rpm-signing-keys requires --unified-core");
+
   /* Read the previous commit. Note we don't actually *need* the full commit; really, only
    * if one uses `check-passwd: { "type": "previous" }`. There are a few other optimizations
    * too, e.g. using the previous SELinux policy in unified core. Also, we might need the
@@ -1014,6 +1041,9 @@ impl_install_tree (RpmOstreeTreeComposeContext *self, gboolean *out_changed,
   if (!inject_advisories (self, cancellable, error))
     return FALSE;
 
+  if (!inject_rpm_signatures (self, error))
+    return FALSE;
+
   /* Destroy this now so the libdnf stack won't have any references
    * into the filesystem before we manipulate it.
    */
@@ -1106,11 +1136,50 @@ pull_local_into_target_repo (OstreeRepo *src_repo, OstreeRepo *dest_repo, const
   return TRUE;
 }
 
+/* Generate static deltas to @to_rev from up to @n_ancestors of its ancestors, so that
+ * clients updating from any of them pull as little as possible. */
+static gboolean
+generate_static_deltas (OstreeRepo *repo, const char *to_rev, guint n_ancestors,
+                        GCancellable *cancellable, GError **error)
+{
+  g_autoptr (GVariant) params = g_variant_ref_sink (g_variant_new ("a{sv}", NULL));
+  g_autofree char *from_rev = g_strdup (to_rev);
+  for (guint i = 0; i < n_ancestors; i++)
+    {
+      g_autoptr (GVariant) commit = NULL;
+      OstreeRepoCommitState state;
+      if (!ostree_repo_load_commit (repo, from_rev, &commit, &state, error))
+        return FALSE;
+      g_autofree char *parent_rev = ostree_commit_get_parent (commit);
+      if (!parent_rev)
+        break;
+      /* The history may have been pruned, or only the commit object pulled */
+      g_autoptr (GVariant) parent_commit = NULL;
+      OstreeRepoCommitState parent_state;
+      if (!ostree_repo_load_commit (repo, parent_rev, &parent_commit, &parent_state, NULL)
+          || (parent_state & OSTREE_REPO_COMMIT_STATE_PARTIAL))
+        break;
+
+      g_print ("Generating static delta: %s-%s\n", parent_rev, to_rev);
+      if (!ostree_repo_static_delta_generate (repo, OSTREE_STATIC_DELTA_GENERATE_OPT_MAJOR,
+                                              parent_rev, to_rev, NULL, params, cancellable,
+                                              error))
+        return glnx_prefix_error (error, "Generating static delta from %s", parent_rev);
+
+      g_free (from_rev);
+      from_rev = util::move_nullify (parent_rev);
+    }
+
+  return TRUE;
+}
+
 /* Perform required postprocessing, and invoke rpmostree_compose_commit(). */
 static gboolean
 impl_commit_tree (RpmOstreeTreeComposeContext *self, GCancellable *cancellable, GError **error)
 {
   auto gpgkey = (*self->treefile_rs)->get_gpg_key ();
+  auto ima_sign_key = (*self->treefile_rs)->get_ima_sign_key ();
+  auto ima_sign_algorithm = (*self->treefile_rs)->get_ima_sign_algorithm ();
   auto selinux = (*self->treefile_rs)->get_selinux ();
 
   /* pick up any initramfs regeneration args to shove into the metadata */
@@ -1186,12 +1255,19 @@ impl_commit_tree (RpmOstreeTreeComposeContext *self, GCancellable *cancellable,
   const char *gpgkey_c = NULL;
   if (!gpgkey.empty ())
     gpgkey_c = gpgkey.c_str ();
+  const char *ima_sign_key_c = NULL;
+  if (!ima_sign_key.empty ())
+    ima_sign_key_c = ima_sign_key.c_str ();
   if (!rpmostree_compose_commit (self->rootfs_dfd, self->build_repo, parent_revision, metadata,
-                                 detached_metadata, gpgkey_c, selinux, self->devino_cache,
+                                 detached_metadata, gpgkey_c, ima_sign_key_c,
+                                 ima_sign_algorithm.c_str (), selinux, self->devino_cache,
                                  &new_revision, cancellable, error))
     return glnx_prefix_error (error, "Writing commit");
   g_assert (new_revision != NULL);
 
+  if ((*self->treefile_rs)->get_ima_verify ())
+    ROSCXX_TRY (compose_ima_verify (*self->build_repo, new_revision), error);
+
   OstreeRepoTransactionStats stats = {
     0,
   };
@@ -1217,6 +1293,9 @@ impl_commit_tree (RpmOstreeTreeComposeContext *self, GCancellable *cancellable,
         return glnx_prefix_error (error, "Copying final commit from build repo into target repo");
     }
 
+  if ((*self->treefile_rs)->get_fsverity ())
+    ROSCXX_TRY (fsverity_enable_commit (*self->repo, new_revision), error);
+
   g_autoptr (GVariant) new_commit = NULL;
   if (!ostree_repo_load_commit (self->repo, new_revision, &new_commit, NULL, error))
     return FALSE;
@@ -1234,6 +1313,13 @@ impl_commit_tree (RpmOstreeTreeComposeContext *self, GCancellable *cancellable,
   else
     g_print ("Wrote commit: %s\n", new_revision);
 
+  if (opt_generate_static_deltas > 0)
+    {
+      if (!generate_static_deltas (self->repo, new_revision, opt_generate_static_deltas,
+                                   cancellable, error))
+        return FALSE;
+    }
+
   /* Optionally write a JSON summary of this compose-commit run */
   if (opt_write_composejson_to)
     if (!rpmostree_composeutil_write_composejson (self->repo, opt_write_composejson_to, statsp,
@@ -1251,9 +1337,9 @@ gboolean
 rpmostree_compose_builtin_install (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
                                    GCancellable *cancellable, GError **error)
 {
-  g_autoptr (GOptionContext) context = g_option_context_new ("TREEFILE DESTDIR");
-  g_option_context_add_main_entries (context, common_option_entries, NULL);
-  g_option_context_add_main_entries (context, repo_option_entries, NULL);
+  g_autoptr (GOptionContext) context = rpmostree_option_context_new ("TREEFILE DESTDIR");
+  rpmostree_option_context_add_entries (context, common_option_entries);
+  rpmostree_option_context_add_entries (context, repo_option_entries);
 
   if (!rpmostree_option_context_parse (context, install_option_entries, &argc, &argv, invocation,
                                        cancellable, NULL, NULL, NULL, error))
@@ -1319,8 +1405,8 @@ rpmostree_compose_builtin_postprocess (int argc, char **argv,
                                        RpmOstreeCommandInvocation *invocation,
                                        GCancellable *cancellable, GError **error)
 {
-  g_autoptr (GOptionContext) context = g_option_context_new ("ROOTFS [TREEFILE]");
-  g_option_context_add_main_entries (context, common_option_entries, NULL);
+  g_autoptr (GOptionContext) context = rpmostree_option_context_new ("ROOTFS [TREEFILE]");
+  rpmostree_option_context_add_entries (context, common_option_entries);
 
   if (!rpmostree_option_context_parse (context, postprocess_option_entries, &argc, &argv,
                                        invocation, cancellable, NULL, NULL, NULL, error))
@@ -1372,9 +1458,9 @@ gboolean
 rpmostree_compose_builtin_commit (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
                                   GCancellable *cancellable, GError **error)
 {
-  g_autoptr (GOptionContext) context = g_option_context_new ("TREEFILE ROOTFS");
-  g_option_context_add_main_entries (context, common_option_entries, NULL);
-  g_option_context_add_main_entries (context, repo_option_entries, NULL);
+  g_autoptr (GOptionContext) context = rpmostree_option_context_new ("TREEFILE ROOTFS");
+  rpmostree_option_context_add_entries (context, common_option_entries);
+  rpmostree_option_context_add_entries (context, repo_option_entries);
 
   if (!rpmostree_option_context_parse (context, commit_option_entries, &argc, &argv, invocation,
                                        cancellable, NULL, NULL, NULL, error))
@@ -1392,6 +1478,12 @@ rpmostree_compose_builtin_commit (int argc, char **argv, RpmOstreeCommandInvocat
       return FALSE;
     }
 
+  if (opt_generate_static_deltas < 0)
+    {
+      rpmostree_usage_error (context, "--generate-static-deltas must not be negative", error);
+      return FALSE;
+    }
+
   const char *treefile_path = argv[1];
   const char *rootfs_path = argv[2];
   auto basearch = rpmostreecxx::get_rpm_basearch ();
@@ -1410,11 +1502,11 @@ gboolean
 rpmostree_compose_builtin_tree (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
                                 GCancellable *cancellable, GError **error)
 {
-  g_autoptr (GOptionContext) context = g_option_context_new ("TREEFILE");
-  g_option_context_add_main_entries (context, common_option_entries, NULL);
-  g_option_context_add_main_entries (context, repo_option_entries, NULL);
-  g_option_context_add_main_entries (context, install_option_entries, NULL);
-  g_option_context_add_main_entries (context, postprocess_option_entries, NULL);
+  g_autoptr (GOptionContext) context = rpmostree_option_context_new ("TREEFILE");
+  rpmostree_option_context_add_entries (context, common_option_entries);
+  rpmostree_option_context_add_entries (context, repo_option_entries);
+  rpmostree_option_context_add_entries (context, install_option_entries);
+  rpmostree_option_context_add_entries (context, postprocess_option_entries);
 
   if (!rpmostree_option_context_parse (context, commit_option_entries, &argc, &argv, invocation,
                                        cancellable, NULL, NULL, NULL, error))
@@ -1442,6 +1534,12 @@ rpmostree_compose_builtin_tree (int argc, char **argv, RpmOstreeCommandInvocatio
       return FALSE;
     }
 
+  if (opt_generate_static_deltas < 0)
+    {
+      rpmostree_usage_error (context, "--generate-static-deltas must not be negative", error);
+      return FALSE;
+    }
+
   g_autoptr (RpmOstreeTreeComposeContext) self = NULL;
   if (!rpm_ostree_compose_context_new (treefile_path, basearch.c_str (), &self, cancellable, error))
     return FALSE;
@@ -1472,10 +1570,10 @@ gboolean
 rpmostree_compose_builtin_extensions (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
                                       GCancellable *cancellable, GError **error)
 {
-  g_autoptr (GOptionContext) context = g_option_context_new ("TREEFILE EXTYAML");
-  g_option_context_add_main_entries (context, common_option_entries, NULL);
-  g_option_context_add_main_entries (context, repo_option_entries, NULL);
-  g_option_context_add_main_entries (context, extensions_option_entries, NULL);
+  g_autoptr (GOptionContext) context = rpmostree_option_context_new ("TREEFILE EXTYAML");
+  rpmostree_option_context_add_entries (context, common_option_entries);
+  rpmostree_option_context_add_entries (context, repo_option_entries);
+  rpmostree_option_context_add_entries (context, extensions_option_entries);
 
   if (!rpmostree_option_context_parse (context, NULL, &argc, &argv, invocation, cancellable, NULL,
                                        NULL, NULL, error))
@@ -1731,3 +1829,60 @@ rpmostree_compose_builtin_extensions (int argc, char **argv, RpmOstreeCommandInv
 
   return TRUE;
 }
+
+gboolean
+rpmostree_compose_builtin_build_derived_image (int argc, char **argv,
+                                               RpmOstreeCommandInvocation *invocation,
+                                               GCancellable *cancellable, GError **error)
+{
+  rust::Vec<rust::String> rustargv;
+  for (int i = 0; i < argc; i++)
+    rustargv.push_back (std::string (argv[i]));
+  ROSCXX_TRY (compose_build_derived_image_entrypoint (rustargv), error);
+  return TRUE;
+}
+
+gboolean
+rpmostree_compose_builtin_diskimage (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
+                                     GCancellable *cancellable, GError **error)
+{
+  rust::Vec<rust::String> rustargv;
+  for (int i = 0; i < argc; i++)
+    rustargv.push_back (std::string (argv[i]));
+  ROSCXX_TRY (compose_diskimage_entrypoint (rustargv), error);
+  return TRUE;
+}
+
+gboolean
+rpmostree_compose_builtin_passwd_snapshot (int argc, char **argv,
+                                           RpmOstreeCommandInvocation *invocation,
+                                           GCancellable *cancellable, GError **error)
+{
+  rust::Vec<rust::String> rustargv;
+  for (int i = 0; i < argc; i++)
+    rustargv.push_back (std::string (argv[i]));
+  ROSCXX_TRY (compose_passwd_snapshot_entrypoint (rustargv), error);
+  return TRUE;
+}
+
+gboolean
+rpmostree_compose_builtin_pin_ids (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
+                                   GCancellable *cancellable, GError **error)
+{
+  rust::Vec<rust::String> rustargv;
+  for (int i = 0; i < argc; i++)
+    rustargv.push_back (std::string (argv[i]));
+  ROSCXX_TRY (compose_pin_ids_entrypoint (rustargv), error);
+  return TRUE;
+}
+
+gboolean
+rpmostree_compose_builtin_schema (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
+                                  GCancellable *cancellable, GError **error)
+{
+  rust::Vec<rust::String> rustargv;
+  for (int i = 0; i < argc; i++)
+    rustargv.push_back (std::string (argv[i]));
+  ROSCXX_TRY (compose_schema_entrypoint (rustargv), error);
+  return TRUE;
+}
@@ -41,5 +41,20 @@ gboolean rpmostree_compose_builtin_commit (int argc, char **argv,
 gboolean rpmostree_compose_builtin_extensions (int argc, char **argv,
                                                RpmOstreeCommandInvocation *invocation,
                                                GCancellable *cancellable, GError **error);
+gboolean rpmostree_compose_builtin_build_derived_image (int argc, char **argv,
+                                                        RpmOstreeCommandInvocation *invocation,
+                                                        GCancellable *cancellable, GError **error);
+gboolean rpmostree_compose_builtin_diskimage (int argc, char **argv,
+                                              RpmOstreeCommandInvocation *invocation,
+                                              GCancellable *cancellable, GError **error);
+gboolean rpmostree_compose_builtin_passwd_snapshot (int argc, char **argv,
+                                                    RpmOstreeCommandInvocation *invocation,
+                                                    GCancellable *cancellable, GError **error);
+gboolean rpmostree_compose_builtin_pin_ids (int argc, char **argv,
+                                            RpmOstreeCommandInvocation *invocation,
+                                            GCancellable *cancellable, GError **error);
+gboolean rpmostree_compose_builtin_schema (int argc, char **argv,
+                                           RpmOstreeCommandInvocation *invocation,
+                                           GCancellable *cancellable, GError **error);
 
 G_END_DECLS
@@ -0,0 +1,58 @@
+/* -*- mode: C; c-file-style: "gnu"; indent-tabs-mode: nil; -*-
+ *
+ * Copyright (C) 2026 Red Hat, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Lesser General Public License as published
+ * by the Free Software Foundation; either version 2 of the licence or (at
+ * your option) any later version.
+ *
+ * This library is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ * Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General
+ * Public License along with this library; if not, write to the
+ * Free Software Foundation, Inc., 59 Temple Place, Suite 330,
+ * Boston, MA 02111-1307, USA.
+ */
+
+#include "config.h"
+
+#include "rpmostree-db-builtins.h"
+#include "rpmostree-libbuiltin.h"
+
+static char *opt_sysroot;
+
+static GOptionEntry option_entries[]
+    = { { "sysroot", 0, 0, G_OPTION_ARG_STRING, &opt_sysroot,
+          "Use system root SYSROOT (default: /)", "SYSROOT" },
+        { NULL } };
+
+gboolean
+rpmostree_db_builtin_changelogs (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
+                                 GCancellable *cancellable, GError **error)
+{
+  g_autoptr (GOptionContext) context = rpmostree_option_context_new ("");
+
+  g_autoptr (OstreeRepo) repo = NULL;
+  if (!rpmostree_db_option_context_parse (context, option_entries, &argc, &argv, invocation, &repo,
+                                          cancellable, error))
+    return FALSE;
+
+  if (argc > 1)
+    {
+      rpmostree_usage_error (context, "Too many arguments; use `db diff --changelogs` to compare "
+                                      "commits", error);
+      return FALSE;
+    }
+
+  const char *sysroot_path = opt_sysroot ?: "/";
+  g_autoptr (GFile) sysroot_file = g_file_new_for_path (sysroot_path);
+  g_autoptr (OstreeSysroot) sysroot = ostree_sysroot_new (sysroot_file);
+  if (!ostree_sysroot_load (sysroot, cancellable, error))
+    return FALSE;
+
+  return rpmostree_print_pending_changelogs (sysroot, cancellable, error);
+}
@@ -35,6 +35,7 @@ static gboolean opt_changelogs;
 static char *opt_sysroot;
 static gboolean opt_base;
 static gboolean opt_advisories;
+static char **opt_images;
 
 static GOptionEntry option_entries[] = {
   { "format", 'F', 0, G_OPTION_ARG_STRING, &opt_format,
@@ -45,6 +46,8 @@ static GOptionEntry option_entries[] = {
   { "base", 0, 0, G_OPTION_ARG_NONE, &opt_base,
     "Diff against deployments' base, not layered commits", NULL },
   { "advisories", 'a', 0, G_OPTION_ARG_NONE, &opt_advisories, "Also output new advisories", NULL },
+  { "image", 0, 0, G_OPTION_ARG_STRING_ARRAY, &opt_images,
+    "Diff container images instead of commits, fetching only their rpmdb (pass twice)", "IMAGE" },
   { NULL }
 };
 
@@ -142,11 +145,50 @@ print_deployment_diff (OstreeRepo *repo, const char *from_desc, OstreeDeployment
   return print_diff (repo, from_desc, from_checksum, to_desc, to_checksum, cancellable, error);
 }
 
+/* Fetch the packages of container image @image into @repo, returning a commit
+ * with them: either the image's own ostree commit, or a new one with only its
+ * rpmdb. */
+static gboolean
+fetch_image_commit (OstreeRepo *repo, const char *image, char **out_checksum,
+                    GCancellable *cancellable, GError **error)
+{
+  g_auto (GLnxTmpDir) rootfs = {
+    0,
+  };
+  if (!glnx_mkdtemp ("rpmostree-db-image-XXXXXX", 0700, &rootfs, error))
+    return FALSE;
+
+  CXX_TRY_VAR (image_commit, rpmostreecxx::db_image_fetch_rpmdb (*repo, image, rootfs.fd), error);
+  if (!image_commit.empty ())
+    {
+      *out_checksum = g_strdup (std::string (image_commit).c_str ());
+      return TRUE;
+    }
+
+  g_autoptr (GVariant) pkglist = NULL;
+  if (!rpmostree_create_rpmdb_pkglist_variant (rootfs.fd, ".", &pkglist, cancellable, error))
+    return FALSE;
+  g_autoptr (GVariantDict) meta = g_variant_dict_new (NULL);
+  g_variant_dict_insert_value (meta, "rpmostree.rpmdb.pkglist", pkglist);
+  g_autoptr (GVariant) metav = g_variant_ref_sink (g_variant_dict_end (meta));
+
+  g_autoptr (OstreeMutableTree) mtree = ostree_mutable_tree_new ();
+  g_autoptr (OstreeRepoCommitModifier) modifier = ostree_repo_commit_modifier_new (
+      OSTREE_REPO_COMMIT_MODIFIER_FLAGS_CANONICAL_PERMISSIONS, NULL, NULL, NULL);
+  if (!ostree_repo_write_dfd_to_mtree (repo, rootfs.fd, ".", mtree, modifier, cancellable, error))
+    return FALSE;
+  g_autoptr (GFile) root = NULL;
+  if (!ostree_repo_write_mtree (repo, mtree, &root, cancellable, error))
+    return FALSE;
+  return ostree_repo_write_commit (repo, NULL, image, NULL, metav, OSTREE_REPO_FILE (root),
+                                   out_checksum, cancellable, error);
+}
+
 gboolean
 rpmostree_db_builtin_diff (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
                            GCancellable *cancellable, GError **error)
 {
-  g_autoptr (GOptionContext) context = g_option_context_new ("[FROM_REV] [TO_REV]");
+  g_autoptr (GOptionContext) context = rpmostree_option_context_new ("[FROM_REV] [TO_REV]");
 
   g_autoptr (OstreeRepo) repo = NULL;
   if (!rpmostree_db_option_context_parse (context, option_entries, &argc, &argv, invocation, &repo,
@@ -164,12 +206,6 @@ rpmostree_db_builtin_diff (int argc, char **argv, RpmOstreeCommandInvocation *in
   if (!opt_format)
     opt_format = g_strdup ("block");
 
-  if (g_str_equal (opt_format, "json") && opt_changelogs)
-    {
-      rpmostree_usage_error (context, "json format and --changelogs not supported", error);
-      return FALSE;
-    }
-
   if (g_str_equal (opt_format, "diff") && opt_advisories)
     {
       rpmostree_usage_error (context, "diff format and --advisories not supported", error);
@@ -188,8 +224,40 @@ rpmostree_db_builtin_diff (int argc, char **argv, RpmOstreeCommandInvocation *in
   g_autofree char *from_checksum = NULL;
   const char *to_desc = NULL;
   g_autofree char *to_checksum = NULL;
+  /* Where the packages of images are written */
+  g_auto (GLnxTmpDir) image_tmpdir = {
+    0,
+  };
 
-  if (argc < 3)
+  if (opt_images)
+    {
+      if (g_strv_length (opt_images) != 2 || argc > 1)
+        {
+          rpmostree_usage_error (context, "--image must be passed exactly twice, without revs",
+                                 error);
+          return FALSE;
+        }
+
+      if (!glnx_mkdtemp ("rpmostree-db-images-XXXXXX", 0700, &image_tmpdir, error))
+        return FALSE;
+      g_clear_object (&repo);
+      repo = ostree_repo_create_at (image_tmpdir.fd, "repo", OSTREE_REPO_MODE_BARE_USER_ONLY, NULL,
+                                    cancellable, error);
+      if (!repo)
+        return FALSE;
+
+      if (!ostree_repo_prepare_transaction (repo, NULL, cancellable, error))
+        return FALSE;
+      from_desc = opt_images[0];
+      if (!fetch_image_commit (repo, from_desc, &from_checksum, cancellable, error))
+        return FALSE;
+      to_desc = opt_images[1];
+      if (!fetch_image_commit (repo, to_desc, &to_checksum, cancellable, error))
+        return FALSE;
+      if (!ostree_repo_commit_transaction (repo, NULL, cancellable, error))
+        return FALSE;
+    }
+  else if (argc < 3)
     {
       /* find booted deployment */
       const char *sysroot_path = opt_sysroot ?: "/";
@@ -261,6 +329,21 @@ rpmostree_db_builtin_diff (int argc, char **argv, RpmOstreeCommandInvocation *in
                    rpmostreecxx::calculate_advisories_diff (*repo, from_checksum, to_checksum),
                    error);
       g_variant_builder_add (&builder, "{sv}", "advisories", adv_diff);
+      if (opt_changelogs)
+        {
+          g_autoptr (RpmRevisionData) rpmrev1
+              = rpmrev_new (repo, from_checksum, NULL, cancellable, error);
+          if (!rpmrev1)
+            return FALSE;
+          g_autoptr (RpmRevisionData) rpmrev2
+              = rpmrev_new (repo, to_checksum, NULL, cancellable, error);
+          if (!rpmrev2)
+            return FALSE;
+
+          g_autoptr (GVariant) changelogs = rpmhdrs_diff_changelogs_variant (
+              rpmhdrs_diff (rpmrev_get_headers (rpmrev1), rpmrev_get_headers (rpmrev2)));
+          g_variant_builder_add (&builder, "{sv}", "changelogs", changelogs);
+        }
       g_autoptr (GVariant) metadata = g_variant_builder_end (&builder);
 
       JsonNode *node = json_gvariant_serialize (metadata);
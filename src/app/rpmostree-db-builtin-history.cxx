@@ -0,0 +1,284 @@
+/* -*- mode: C; c-file-style: "gnu"; indent-tabs-mode: nil; -*-
+ *
+ * Copyright (C) 2026 Red Hat, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Lesser General Public License as published
+ * by the Free Software Foundation; either version 2 of the licence or (at
+ * your option) any later version.
+ *
+ * This library is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ * Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General
+ * Public License along with this library; if not, write to the
+ * Free Software Foundation, Inc., 59 Temple Place, Suite 330,
+ * Boston, MA 02111-1307, USA.
+ */
+
+#include "config.h"
+
+#include "rpmostree-core.h"
+#include "rpmostree-cxxrs.h"
+#include "rpmostree-db-builtins.h"
+#include "rpmostree-libbuiltin.h"
+#include "rpmostree-rpm-util.h"
+#include "rpmostree-util.h"
+
+static char *opt_sysroot;
+
+static GOptionEntry option_entries[]
+    = { { "sysroot", 0, 0, G_OPTION_ARG_STRING, &opt_sysroot,
+          "Use system root SYSROOT (default: /)", "SYSROOT" },
+        { NULL } };
+
+/* A deployment created on this system, either still around or only known
+ * from the history journal. */
+typedef struct
+{
+  guint64 deploy_timestamp;
+  char *checksum;
+  char *desc;
+  char *cmdline;
+} HistoryPoint;
+
+static void
+history_point_free (HistoryPoint *point)
+{
+  g_free (point->checksum);
+  g_free (point->desc);
+  g_free (point->cmdline);
+  g_free (point);
+}
+
+static gint
+history_point_compare (gconstpointer a, gconstpointer b)
+{
+  auto pa = *(HistoryPoint **)a;
+  auto pb = *(HistoryPoint **)b;
+  if (pa->deploy_timestamp == pb->deploy_timestamp)
+    return 0;
+  return pa->deploy_timestamp < pb->deploy_timestamp ? -1 : 1;
+}
+
+static void
+add_seen (GHashTable *seen, guint64 ts)
+{
+  auto key = g_new (guint64, 1);
+  *key = ts;
+  g_hash_table_add (seen, key);
+}
+
+/* Same as the names of the files in the history directory */
+static gboolean
+get_deploy_timestamp (OstreeSysroot *sysroot, OstreeDeployment *deployment, guint64 *out_timestamp,
+                      GError **error)
+{
+  g_autofree char *path = ostree_sysroot_get_deployment_dirpath (sysroot, deployment);
+  struct stat stbuf;
+  if (!glnx_fstatat (ostree_sysroot_get_fd (sysroot), path, &stbuf, 0, error))
+    return FALSE;
+  *out_timestamp = stbuf.st_ctime;
+  return TRUE;
+}
+
+/* Add the deployments recorded in the history journal and which aren't
+ * already in @points. */
+static gboolean
+add_journal_points (GPtrArray *points, GHashTable *seen, GError **error)
+{
+  CXX_TRY_VAR (history_ctx, rpmostreecxx::history_ctx_new (), error);
+  while (TRUE)
+    {
+      CXX_TRY_VAR (entry, history_ctx->next_entry (), error);
+      if (entry.eof)
+        break;
+
+      guint64 ts = entry.deploy_timestamp;
+      if (g_hash_table_contains (seen, &ts))
+        continue;
+
+      g_autofree char *fn = g_strdup_printf ("%s/%" PRIu64, RPMOSTREE_HISTORY_DIR, ts);
+      glnx_autofd int fd = -1;
+      g_autoptr (GError) local_error = NULL;
+      if (!glnx_openat_rdonly (AT_FDCWD, fn, TRUE, &fd, &local_error))
+        {
+          if (!g_error_matches (local_error, G_IO_ERROR, G_IO_ERROR_NOT_FOUND))
+            return g_propagate_error (error, util::move_nullify (local_error)), FALSE;
+          continue; /* pruned */
+        }
+      g_autoptr (GBytes) data = glnx_fd_readall_bytes (fd, NULL, error);
+      if (!data)
+        return FALSE;
+      g_autoptr (GVariant) deployment
+          = g_variant_ref_sink (g_variant_new_from_bytes (G_VARIANT_TYPE_VARDICT, data, FALSE));
+
+      const char *checksum;
+      if (!g_variant_lookup (deployment, "checksum", "&s", &checksum))
+        continue;
+      const char *version = NULL;
+      g_variant_lookup (deployment, "version", "&s", &version);
+
+      auto point = g_new0 (HistoryPoint, 1);
+      point->deploy_timestamp = ts;
+      point->checksum = g_strdup (checksum);
+      point->desc = version ? g_strdup_printf ("commit %.10s (%s)", checksum, version)
+                            : g_strdup_printf ("commit %.10s", checksum);
+      if (entry.deploy_cmdline.length () > 0)
+        point->cmdline = g_strdup (std::string (entry.deploy_cmdline).c_str ());
+      g_ptr_array_add (points, point);
+      add_seen (seen, ts);
+    }
+
+  return TRUE;
+}
+
+/* Find the packages named @name in @checksum.  Returns %FALSE in @out_available
+ * if the commit was pruned; otherwise the newest matching header (or %NULL if
+ * there are none) is in @out_header and all their NEVRAs in @out_nevras. */
+static gboolean
+query_commit (OstreeRepo *repo, const char *checksum, const char *name, gboolean *out_available,
+              Header *out_header, char **out_nevras, GCancellable *cancellable, GError **error)
+{
+  *out_header = NULL;
+  *out_nevras = NULL;
+
+  g_autoptr (GVariant) commit = NULL;
+  if (!ostree_repo_load_variant_if_exists (repo, OSTREE_OBJECT_TYPE_COMMIT, checksum, &commit,
+                                           error))
+    return FALSE;
+  *out_available = (commit != NULL);
+  if (!commit)
+    return TRUE;
+
+  g_autoptr (RpmRevisionData) rpmrev = rpmrev_new (repo, checksum, NULL, cancellable, error);
+  if (!rpmrev)
+    return FALSE;
+
+  GPtrArray *hs = rpmrev_get_headers (rpmrev)->hs;
+  Header newest = NULL;
+  g_autoptr (GPtrArray) nevras = g_ptr_array_new_with_free_func (g_free);
+  for (guint i = 0; i < hs->len; i++)
+    {
+      auto h = static_cast<Header> (hs->pdata[i]);
+      if (!g_str_equal (headerGetString (h, RPMTAG_NAME), name))
+        continue;
+      g_ptr_array_add (nevras, headerGetAsString (h, RPMTAG_NEVRA));
+      if (!newest || rpmVersionCompare (h, newest) > 0)
+        newest = h;
+    }
+
+  if (newest)
+    {
+      g_ptr_array_add (nevras, NULL);
+      *out_header = headerLink (newest);
+      *out_nevras = g_strjoinv (", ", (char **)nevras->pdata);
+    }
+  return TRUE;
+}
+
+gboolean
+rpmostree_db_builtin_history (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
+                              GCancellable *cancellable, GError **error)
+{
+  g_autoptr (GOptionContext) context = rpmostree_option_context_new ("PACKAGE");
+
+  g_autoptr (OstreeRepo) repo = NULL;
+  if (!rpmostree_db_option_context_parse (context, option_entries, &argc, &argv, invocation, &repo,
+                                          cancellable, error))
+    return FALSE;
+
+  if (argc != 2)
+    {
+      rpmostree_usage_error (context, "A package name is required", error);
+      return FALSE;
+    }
+  const char *name = argv[1];
+
+  const char *sysroot_path = opt_sysroot ?: "/";
+  g_autoptr (GFile) sysroot_file = g_file_new_for_path (sysroot_path);
+  g_autoptr (OstreeSysroot) sysroot = ostree_sysroot_new (sysroot_file);
+  if (!ostree_sysroot_load (sysroot, cancellable, error))
+    return FALSE;
+
+  g_autoptr (GPtrArray) points = g_ptr_array_new_with_free_func ((GDestroyNotify)history_point_free);
+  g_autoptr (GHashTable) seen = g_hash_table_new_full (g_int64_hash, g_int64_equal, g_free, NULL);
+
+  OstreeDeployment *booted = ostree_sysroot_get_booted_deployment (sysroot);
+  g_autoptr (GPtrArray) deployments = ostree_sysroot_get_deployments (sysroot);
+  for (guint i = 0; i < deployments->len; i++)
+    {
+      auto deployment = static_cast<OstreeDeployment *> (deployments->pdata[i]);
+      guint64 ts;
+      if (!get_deploy_timestamp (sysroot, deployment, &ts, error))
+        return FALSE;
+      const char *checksum = ostree_deployment_get_csum (deployment);
+      auto point = g_new0 (HistoryPoint, 1);
+      point->deploy_timestamp = ts;
+      point->checksum = g_strdup (checksum);
+      point->desc = g_strdup_printf ("deployment %u (%.10s%s)", i, checksum,
+                                     deployment == booted ? ", booted" : "");
+      g_ptr_array_add (points, point);
+      add_seen (seen, ts);
+    }
+
+  if (!add_journal_points (points, seen, error))
+    return glnx_prefix_error (error, "Reading history");
+
+  g_ptr_array_sort (points, history_point_compare);
+
+  gboolean found = FALSE;
+  gboolean prev_known = FALSE;
+  Header prev_h = NULL;
+  g_autofree char *prev_nevras = NULL;
+  for (guint i = 0; i < points->len; i++)
+    {
+      auto point = static_cast<HistoryPoint *> (points->pdata[i]);
+      gboolean available;
+      Header h;
+      g_autofree char *nevras = NULL;
+      if (!query_commit (repo, point->checksum, name, &available, &h, &nevras, cancellable, error))
+        return glnx_prefix_error (error, "Querying %s", point->desc);
+
+      g_autofree char *ts = rpmostree_timestamp_str_from_unix_utc (point->deploy_timestamp);
+      g_autofree char *change = NULL;
+      if (!available)
+        change = g_strdup ("unknown (commit no longer in the repo)");
+      else if (!prev_known)
+        change = h ? g_strdup_printf ("present %s", nevras) : NULL;
+      else if (!prev_h && h)
+        change = g_strdup_printf ("introduced %s", nevras);
+      else if (prev_h && !h)
+        change = g_strdup_printf ("removed %s", prev_nevras);
+      else if (prev_h && !g_str_equal (prev_nevras, nevras))
+        {
+          int cmp = rpmVersionCompare (h, prev_h);
+          const char *verb = cmp > 0 ? "upgraded" : cmp < 0 ? "downgraded" : "changed";
+          change = g_strdup_printf ("%s %s -> %s", verb, prev_nevras, nevras);
+        }
+
+      if (change)
+        {
+          g_print ("%s %s: %s\n", ts, point->desc, change);
+          if (point->cmdline)
+            g_print ("  %s\n", point->cmdline);
+        }
+      found = found || h != NULL;
+
+      if (prev_h)
+        headerFree (prev_h);
+      prev_h = h;
+      g_free (prev_nevras);
+      prev_nevras = util::move_nullify (nevras);
+      prev_known = available;
+    }
+  if (prev_h)
+    headerFree (prev_h);
+
+  if (!found)
+    return glnx_throw (error, "Package '%s' not found in any deployment", name);
+
+  return TRUE;
+}
@@ -21,12 +21,16 @@
 #include "config.h"
 
 #include "rpmostree-db-builtins.h"
+#include "rpmostree-libbuiltin.h"
 #include "rpmostree-rpm-util.h"
 
 static gboolean opt_advisories;
+static char *opt_queryformat;
 
 static GOptionEntry option_entries[]
     = { { "advisories", 'a', 0, G_OPTION_ARG_NONE, &opt_advisories, "Also list advisories", NULL },
+        { "queryformat", 0, 0, G_OPTION_ARG_STRING, &opt_queryformat,
+          "Print packages with an rpm query format, e.g. '%{NAME} %{VERSION}\\n'", "FORMAT" },
         { NULL } };
 
 static gboolean
@@ -41,6 +45,19 @@ _builtin_db_list (OstreeRepo *repo, GPtrArray *revs, const GPtrArray *patterns,
       if (!ostree_repo_resolve_rev (repo, rev, FALSE, &checksum, error))
         return FALSE;
 
+      /* With a query format, the output is entirely up to the caller */
+      if (opt_queryformat)
+        {
+          g_autoptr (RpmRevisionData) rpmrev
+              = rpmrev_new (repo, checksum, patterns, cancellable, error);
+          if (!rpmrev)
+            return FALSE;
+
+          if (!rpmhdrs_list_queryformat (rpmrev_get_headers (rpmrev), opt_queryformat, error))
+            return FALSE;
+          continue;
+        }
+
       if (!g_str_equal (rev, checksum))
         printf ("ostree commit: %s (%s)\n", rev, checksum);
       else
@@ -97,13 +114,19 @@ gboolean
 rpmostree_db_builtin_list (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
                            GCancellable *cancellable, GError **error)
 {
-  g_autoptr (GOptionContext) context = g_option_context_new ("REV... [PREFIX-PKGNAME...]");
+  g_autoptr (GOptionContext) context = rpmostree_option_context_new ("REV... [PREFIX-PKGNAME...]");
 
   g_autoptr (OstreeRepo) repo = NULL;
   if (!rpmostree_db_option_context_parse (context, option_entries, &argc, &argv, invocation, &repo,
                                           cancellable, error))
     return FALSE;
 
+  if (opt_queryformat && opt_advisories)
+    {
+      rpmostree_usage_error (context, "--queryformat and --advisories not supported", error);
+      return FALSE;
+    }
+
   /* Iterate over all arguments. When we see the first argument which
    * appears to be an OSTree commit, take all other arguments to be
    * patterns.
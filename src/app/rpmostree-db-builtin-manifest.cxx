@@ -0,0 +1,243 @@
+/* -*- mode: C; c-file-style: "gnu"; indent-tabs-mode: nil; -*-
+ *
+ * Copyright (C) 2026 Red Hat, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Lesser General Public License as published
+ * by the Free Software Foundation; either version 2 of the licence or (at
+ * your option) any later version.
+ *
+ * This library is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ * Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General
+ * Public License along with this library; if not, write to the
+ * Free Software Foundation, Inc., 59 Temple Place, Suite 330,
+ * Boston, MA 02111-1307, USA.
+ */
+
+#include "config.h"
+
+#include "rpmostree-db-builtins.h"
+#include "rpmostree-libbuiltin.h"
+#include "rpmostree-rpm-util.h"
+#include "rpmostree-util.h"
+
+/* A manifest lists one package per line as "NEVRA SHA256HEADER", sorted by
+ * NEVRA, after a header of comments describing where it comes from.  Since it
+ * only depends on the packages, it's stable and can be signed detached, e.g.
+ * with `gpg --detach-sign`, and compared against systems with the same
+ * packages but another commit. */
+#define MANIFEST_HEADER "# rpm-ostree package manifest v1\n"
+
+static char *opt_sysroot;
+static char *opt_output;
+
+static GOptionEntry export_option_entries[]
+    = { { "sysroot", 0, 0, G_OPTION_ARG_STRING, &opt_sysroot,
+          "Use system root SYSROOT (default: /)", "SYSROOT" },
+        { "output", 'o', 0, G_OPTION_ARG_STRING, &opt_output,
+          "Write the manifest to FILE instead of stdout", "FILE" },
+        { NULL } };
+
+static GOptionEntry compare_option_entries[]
+    = { { "sysroot", 0, 0, G_OPTION_ARG_STRING, &opt_sysroot,
+          "Use system root SYSROOT (default: /)", "SYSROOT" },
+        { NULL } };
+
+/* Resolve @rev, or the booted deployment if %NULL. */
+static gboolean
+resolve_commit (OstreeRepo *repo, const char *rev, char **out_checksum, GCancellable *cancellable,
+                GError **error)
+{
+  if (rev)
+    return ostree_repo_resolve_rev (repo, rev, FALSE, out_checksum, error);
+
+  const char *sysroot_path = opt_sysroot ?: "/";
+  g_autoptr (GFile) sysroot_file = g_file_new_for_path (sysroot_path);
+  g_autoptr (OstreeSysroot) sysroot = ostree_sysroot_new (sysroot_file);
+  if (!ostree_sysroot_load (sysroot, cancellable, error))
+    return FALSE;
+
+  OstreeDeployment *booted = ostree_sysroot_get_booted_deployment (sysroot);
+  if (!booted)
+    return glnx_throw (error, "Not booted into any deployment");
+  *out_checksum = g_strdup (ostree_deployment_get_csum (booted));
+  return TRUE;
+}
+
+static const char *
+header_digest (Header h)
+{
+  const char *digest = headerGetString (h, RPMTAG_SHA256HEADER);
+  if (!digest)
+    digest = headerGetString (h, RPMTAG_SHA1HEADER);
+  /* e.g. gpg-pubkey */
+  return digest ?: "none";
+}
+
+/* Returns a map of NEVRA to header digest of the packages in @checksum. */
+static GHashTable *
+commit_packages (OstreeRepo *repo, const char *checksum, GCancellable *cancellable, GError **error)
+{
+  g_autoptr (RpmRevisionData) rpmrev = rpmrev_new (repo, checksum, NULL, cancellable, error);
+  if (!rpmrev)
+    return NULL;
+
+  g_autoptr (GHashTable) packages = g_hash_table_new_full (g_str_hash, g_str_equal, g_free, g_free);
+  GPtrArray *hs = rpmrev_get_headers (rpmrev)->hs;
+  for (guint i = 0; i < hs->len; i++)
+    {
+      auto h = static_cast<Header> (hs->pdata[i]);
+      g_hash_table_insert (packages, headerGetAsString (h, RPMTAG_NEVRA),
+                           g_strdup (header_digest (h)));
+    }
+  return util::move_nullify (packages);
+}
+
+static GHashTable *
+parse_manifest (const char *path, GError **error)
+{
+  g_autofree char *contents = glnx_file_get_contents_utf8_at (AT_FDCWD, path, NULL, NULL, error);
+  if (!contents)
+    return NULL;
+  if (!g_str_has_prefix (contents, MANIFEST_HEADER))
+    return (GHashTable *)glnx_null_throw (error, "%s: Not an rpm-ostree package manifest", path);
+
+  g_autoptr (GHashTable) packages = g_hash_table_new_full (g_str_hash, g_str_equal, g_free, g_free);
+  g_auto (GStrv) lines = g_strsplit (contents, "\n", -1);
+  for (guint i = 0; lines[i]; i++)
+    {
+      const char *line = lines[i];
+      if (*line == '\0' || *line == '#')
+        continue;
+      g_auto (GStrv) fields = g_strsplit (line, " ", -1);
+      if (g_strv_length (fields) != 2)
+        return (GHashTable *)glnx_null_throw (error, "%s:%u: Invalid line: %s", path, i + 1, line);
+      g_hash_table_insert (packages, g_strdup (fields[0]), g_strdup (fields[1]));
+    }
+  return util::move_nullify (packages);
+}
+
+gboolean
+rpmostree_db_builtin_export_manifest (int argc, char **argv,
+                                      RpmOstreeCommandInvocation *invocation,
+                                      GCancellable *cancellable, GError **error)
+{
+  g_autoptr (GOptionContext) context = rpmostree_option_context_new ("[REV]");
+
+  g_autoptr (OstreeRepo) repo = NULL;
+  if (!rpmostree_db_option_context_parse (context, export_option_entries, &argc, &argv, invocation,
+                                          &repo, cancellable, error))
+    return FALSE;
+
+  if (argc > 2)
+    {
+      rpmostree_usage_error (context, "At most one revision may be specified", error);
+      return FALSE;
+    }
+
+  g_autofree char *checksum = NULL;
+  if (!resolve_commit (repo, argc == 2 ? argv[1] : NULL, &checksum, cancellable, error))
+    return FALSE;
+
+  g_autoptr (GVariant) commit = NULL;
+  if (!ostree_repo_load_commit (repo, checksum, &commit, NULL, error))
+    return FALSE;
+  g_autofree char *version = rpmostree_checksum_version (commit);
+
+  g_autoptr (GHashTable) packages = commit_packages (repo, checksum, cancellable, error);
+  if (!packages)
+    return FALSE;
+
+  g_autoptr (GString) buf = g_string_new (MANIFEST_HEADER);
+  g_string_append_printf (buf, "# commit: %s\n", checksum);
+  if (version)
+    g_string_append_printf (buf, "# version: %s\n", version);
+  g_autoptr (GList) nevras = g_list_sort (g_hash_table_get_keys (packages), (GCompareFunc)strcmp);
+  for (GList *l = nevras; l; l = l->next)
+    {
+      auto nevra = static_cast<const char *> (l->data);
+      g_string_append_printf (buf, "%s %s\n", nevra,
+                              static_cast<const char *> (g_hash_table_lookup (packages, nevra)));
+    }
+
+  if (!opt_output)
+    {
+      g_print ("%s", buf->str);
+      return TRUE;
+    }
+
+  if (!glnx_file_replace_contents_at (AT_FDCWD, opt_output, (guint8 *)buf->str, buf->len,
+                                      static_cast<GLnxFileReplaceFlags> (0), cancellable, error))
+    return FALSE;
+  g_print ("Wrote manifest of %u packages to %s\n", g_hash_table_size (packages), opt_output);
+  return TRUE;
+}
+
+gboolean
+rpmostree_db_builtin_compare_manifest (int argc, char **argv,
+                                       RpmOstreeCommandInvocation *invocation,
+                                       GCancellable *cancellable, GError **error)
+{
+  g_autoptr (GOptionContext) context = rpmostree_option_context_new ("MANIFEST [REV]");
+
+  g_autoptr (OstreeRepo) repo = NULL;
+  if (!rpmostree_db_option_context_parse (context, compare_option_entries, &argc, &argv,
+                                          invocation, &repo, cancellable, error))
+    return FALSE;
+
+  if (argc < 2 || argc > 3)
+    {
+      rpmostree_usage_error (context, "A manifest and at most one revision must be specified",
+                             error);
+      return FALSE;
+    }
+
+  g_autoptr (GHashTable) expected = parse_manifest (argv[1], error);
+  if (!expected)
+    return FALSE;
+
+  g_autofree char *checksum = NULL;
+  if (!resolve_commit (repo, argc == 3 ? argv[2] : NULL, &checksum, cancellable, error))
+    return FALSE;
+  g_autoptr (GHashTable) found = commit_packages (repo, checksum, cancellable, error);
+  if (!found)
+    return FALSE;
+
+  /* Same markers as `db diff --format=diff`, with `!` for a different build
+   * of the same NEVRA */
+  guint n_differences = 0;
+  g_autoptr (GList) expected_nevras
+      = g_list_sort (g_hash_table_get_keys (expected), (GCompareFunc)strcmp);
+  for (GList *l = expected_nevras; l; l = l->next)
+    {
+      auto nevra = static_cast<const char *> (l->data);
+      auto digest = static_cast<const char *> (g_hash_table_lookup (found, nevra));
+      if (!digest)
+        g_print ("-%s\n", nevra);
+      else if (!g_str_equal (digest, g_hash_table_lookup (expected, nevra)))
+        g_print ("!%s\n", nevra);
+      else
+        continue;
+      n_differences++;
+    }
+  g_autoptr (GList) found_nevras = g_list_sort (g_hash_table_get_keys (found), (GCompareFunc)strcmp);
+  for (GList *l = found_nevras; l; l = l->next)
+    {
+      auto nevra = static_cast<const char *> (l->data);
+      if (g_hash_table_contains (expected, nevra))
+        continue;
+      g_print ("+%s\n", nevra);
+      n_differences++;
+    }
+
+  if (n_differences > 0)
+    return glnx_throw (error, "Commit %s differs from manifest: %u packages", checksum,
+                       n_differences);
+
+  g_print ("Commit %s matches manifest: %u packages\n", checksum, g_hash_table_size (found));
+  return TRUE;
+}
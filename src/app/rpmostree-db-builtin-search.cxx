@@ -0,0 +1,168 @@
+/* -*- mode: C; c-file-style: "gnu"; indent-tabs-mode: nil; -*-
+ *
+ * Copyright (C) 2026 Red Hat, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Lesser General Public License as published
+ * by the Free Software Foundation; either version 2 of the licence or (at
+ * your option) any later version.
+ *
+ * This library is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ * Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General
+ * Public License along with this library; if not, write to the
+ * Free Software Foundation, Inc., 59 Temple Place, Suite 330,
+ * Boston, MA 02111-1307, USA.
+ */
+
+#include "config.h"
+
+#include <fnmatch.h>
+
+#include "rpmostree-db-builtins.h"
+#include "rpmostree-libbuiltin.h"
+#include "rpmostree-rpm-util.h"
+
+static char *opt_sysroot;
+static gboolean opt_refs;
+
+static GOptionEntry option_entries[]
+    = { { "sysroot", 0, 0, G_OPTION_ARG_STRING, &opt_sysroot,
+          "Use system root SYSROOT (default: /)", "SYSROOT" },
+        { "refs", 0, 0, G_OPTION_ARG_NONE, &opt_refs,
+          "Also search the commits of refs which aren't deployed, e.g. downloaded updates", NULL },
+        { NULL } };
+
+/* Whether the name or one of the provides of @h matches @pattern. */
+static gboolean
+header_matches (Header h, const char *pattern)
+{
+  if (fnmatch (pattern, headerGetString (h, RPMTAG_NAME), 0) == 0)
+    return TRUE;
+
+  struct rpmtd_s provides_s;
+  _cleanup_rpmtddata_ rpmtd provides = &provides_s;
+  if (!headerGet (h, RPMTAG_PROVIDENAME, provides, HEADERGET_MINMEM))
+    return FALSE;
+  const char *provide;
+  while ((provide = rpmtdNextString (provides)) != NULL)
+    {
+      if (fnmatch (pattern, provide, 0) == 0)
+        return TRUE;
+    }
+  return FALSE;
+}
+
+/* Add the packages of @checksum matching @pattern to @results, a map of NEVRA
+ * to the descriptions of the commits containing it. */
+static gboolean
+search_commit (OstreeRepo *repo, const char *checksum, const char *desc, const char *pattern,
+               GHashTable *results, GCancellable *cancellable, GError **error)
+{
+  g_autoptr (RpmRevisionData) rpmrev = rpmrev_new (repo, checksum, NULL, cancellable, error);
+  if (!rpmrev)
+    return FALSE;
+
+  GPtrArray *hs = rpmrev_get_headers (rpmrev)->hs;
+  for (guint i = 0; i < hs->len; i++)
+    {
+      auto h = static_cast<Header> (hs->pdata[i]);
+      if (!header_matches (h, pattern))
+        continue;
+
+      g_autofree char *nevra = headerGetAsString (h, RPMTAG_NEVRA);
+      auto descs = static_cast<GPtrArray *> (g_hash_table_lookup (results, nevra));
+      if (!descs)
+        {
+          descs = g_ptr_array_new_with_free_func (g_free);
+          g_hash_table_insert (results, g_strdup (nevra), descs);
+        }
+      g_ptr_array_add (descs, g_strdup (desc));
+    }
+
+  return TRUE;
+}
+
+gboolean
+rpmostree_db_builtin_search (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
+                             GCancellable *cancellable, GError **error)
+{
+  g_autoptr (GOptionContext) context = rpmostree_option_context_new ("PATTERN");
+
+  g_autoptr (OstreeRepo) repo = NULL;
+  if (!rpmostree_db_option_context_parse (context, option_entries, &argc, &argv, invocation, &repo,
+                                          cancellable, error))
+    return FALSE;
+
+  if (argc != 2)
+    {
+      rpmostree_usage_error (context, "A package name or provide pattern is required", error);
+      return FALSE;
+    }
+  const char *pattern = argv[1];
+
+  const char *sysroot_path = opt_sysroot ?: "/";
+  g_autoptr (GFile) sysroot_file = g_file_new_for_path (sysroot_path);
+  g_autoptr (OstreeSysroot) sysroot = ostree_sysroot_new (sysroot_file);
+  if (!ostree_sysroot_load (sysroot, cancellable, error))
+    return FALSE;
+
+  g_autoptr (GHashTable) results
+      = g_hash_table_new_full (g_str_hash, g_str_equal, g_free, (GDestroyNotify)g_ptr_array_unref);
+  /* The commits searched already, as multiple deployments or refs can share one */
+  g_autoptr (GHashTable) searched = g_hash_table_new_full (g_str_hash, g_str_equal, g_free, NULL);
+
+  OstreeDeployment *booted = ostree_sysroot_get_booted_deployment (sysroot);
+  g_autoptr (GPtrArray) deployments = ostree_sysroot_get_deployments (sysroot);
+  for (guint i = 0; i < deployments->len; i++)
+    {
+      auto deployment = static_cast<OstreeDeployment *> (deployments->pdata[i]);
+      const char *checksum = ostree_deployment_get_csum (deployment);
+      g_autofree char *desc = g_strdup_printf ("deployment %u (%.10s%s)", i, checksum,
+                                               deployment == booted ? ", booted" : "");
+      g_hash_table_add (searched, g_strdup (checksum));
+      if (!search_commit (repo, checksum, desc, pattern, results, cancellable, error))
+        return glnx_prefix_error (error, "Searching deployment %u", i);
+    }
+
+  if (opt_refs)
+    {
+      g_autoptr (GHashTable) refs = NULL;
+      if (!ostree_repo_list_refs (repo, NULL, &refs, cancellable, error))
+        return FALSE;
+
+      g_autoptr (GList) names = g_list_sort (g_hash_table_get_keys (refs), (GCompareFunc)strcmp);
+      for (GList *l = names; l; l = l->next)
+        {
+          auto ref = static_cast<const char *> (l->data);
+          auto checksum = static_cast<const char *> (g_hash_table_lookup (refs, ref));
+          if (g_hash_table_contains (searched, checksum))
+            continue;
+          g_hash_table_add (searched, g_strdup (checksum));
+
+          /* Not all refs point to commits with an rpmdb, e.g. container image layers */
+          g_autoptr (GError) local_error = NULL;
+          g_autofree char *desc = g_strdup_printf ("ref %s (%.10s)", ref, checksum);
+          if (!search_commit (repo, checksum, desc, pattern, results, cancellable, &local_error))
+            g_debug ("Skipping ref %s: %s", ref, local_error->message);
+        }
+    }
+
+  if (g_hash_table_size (results) == 0)
+    return glnx_throw (error, "No packages matching '%s'", pattern);
+
+  g_autoptr (GList) nevras = g_list_sort (g_hash_table_get_keys (results), (GCompareFunc)strcmp);
+  for (GList *l = nevras; l; l = l->next)
+    {
+      auto nevra = static_cast<const char *> (l->data);
+      auto descs = static_cast<GPtrArray *> (g_hash_table_lookup (results, nevra));
+      g_print ("%s\n", nevra);
+      for (guint i = 0; i < descs->len; i++)
+        g_print ("  %s\n", (char *)descs->pdata[i]);
+    }
+
+  return TRUE;
+}
@@ -55,7 +55,7 @@ gboolean
 rpmostree_db_builtin_version (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
                               GCancellable *cancellable, GError **error)
 {
-  g_autoptr (GOptionContext) context = g_option_context_new ("COMMIT...");
+  g_autoptr (GOptionContext) context = rpmostree_option_context_new ("COMMIT...");
 
   g_autoptr (OstreeRepo) repo = NULL;
   if (!rpmostree_db_option_context_parse (context, db_version_entries, &argc, &argv, invocation,
@@ -0,0 +1,139 @@
+/* -*- mode: C; c-file-style: "gnu"; indent-tabs-mode: nil; -*-
+ *
+ * Copyright (C) 2026 Red Hat, Inc.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Lesser General Public License as published
+ * by the Free Software Foundation; either version 2 of the licence or (at
+ * your option) any later version.
+ *
+ * This library is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ * Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General
+ * Public License along with this library; if not, write to the
+ * Free Software Foundation, Inc., 59 Temple Place, Suite 330,
+ * Boston, MA 02111-1307, USA.
+ */
+
+#include "config.h"
+
+#include "rpmostree-db-builtins.h"
+#include "rpmostree-libbuiltin.h"
+#include "rpmostree-rpm-util.h"
+
+static char *opt_sysroot;
+static int opt_deployment = -1;
+
+static GOptionEntry option_entries[]
+    = { { "sysroot", 0, 0, G_OPTION_ARG_STRING, &opt_sysroot,
+          "Use system root SYSROOT (default: /)", "SYSROOT" },
+        { "deployment", 'd', 0, G_OPTION_ARG_INT, &opt_deployment,
+          "Query deployment INDEX as listed by status (default: booted)", "INDEX" },
+        { NULL } };
+
+/* The directories which are symlinks into /usr since the UsrMove */
+static const char *usrmove_dirs[] = { "/bin/", "/sbin/", "/lib/", "/lib64/" };
+
+/* Add the NEVRAs of the packages owning file @path, or else providing @path, to
+ * @nevras. */
+static void
+query_provides (rpmts ts, const char *path, GPtrArray *nevras)
+{
+  g_auto (rpmdbMatchIterator) mi = rpmtsInitIterator (ts, RPMDBI_INSTFILENAMES, path, 0);
+  if (mi == NULL)
+    mi = rpmtsInitIterator (ts, RPMDBI_PROVIDENAME, path, 0);
+  if (mi == NULL)
+    return;
+
+  Header h;
+  while ((h = rpmdbNextIterator (mi)) != NULL)
+    g_ptr_array_add (nevras, headerGetAsString (h, RPMTAG_NEVRA));
+}
+
+gboolean
+rpmostree_db_builtin_whatprovides (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
+                                   GCancellable *cancellable, GError **error)
+{
+  g_autoptr (GOptionContext) context = rpmostree_option_context_new ("PATH|CAPABILITY...");
+
+  g_autoptr (OstreeRepo) repo = NULL;
+  if (!rpmostree_db_option_context_parse (context, option_entries, &argc, &argv, invocation, &repo,
+                                          cancellable, error))
+    return FALSE;
+
+  if (argc < 2)
+    {
+      rpmostree_usage_error (context, "At least one path or capability is required", error);
+      return FALSE;
+    }
+
+  const char *sysroot_path = opt_sysroot ?: "/";
+  g_autoptr (GFile) sysroot_file = g_file_new_for_path (sysroot_path);
+  g_autoptr (OstreeSysroot) sysroot = ostree_sysroot_new (sysroot_file);
+  if (!ostree_sysroot_load (sysroot, cancellable, error))
+    return FALSE;
+
+  OstreeDeployment *deployment = NULL;
+  if (opt_deployment >= 0)
+    {
+      g_autoptr (GPtrArray) deployments = ostree_sysroot_get_deployments (sysroot);
+      if ((guint)opt_deployment >= deployments->len)
+        return glnx_throw (error, "Invalid deployment index %d", opt_deployment);
+      deployment = static_cast<OstreeDeployment *> (deployments->pdata[opt_deployment]);
+    }
+  else
+    {
+      deployment = ostree_sysroot_get_booted_deployment (sysroot);
+      if (!deployment)
+        return glnx_throw (error, "Not booted into any deployment; use --deployment");
+    }
+
+  /* The deployment's commit includes the layered packages in its rpmdb */
+  const char *checksum = ostree_deployment_get_csum (deployment);
+  g_autoptr (RpmOstreeRefTs) refts = NULL;
+  if (!rpmostree_get_refts_for_commit (repo, checksum, &refts, cancellable, error))
+    return FALSE;
+
+  guint n_unowned = 0;
+  for (int i = 1; i < argc; i++)
+    {
+      const char *path = argv[i];
+      g_autoptr (GPtrArray) nevras = g_ptr_array_new_with_free_func (g_free);
+      query_provides (refts->ts, path, nevras);
+      /* e.g. /bin/sh is owned as /usr/bin/sh */
+      for (guint j = 0; nevras->len == 0 && j < G_N_ELEMENTS (usrmove_dirs); j++)
+        {
+          if (!g_str_has_prefix (path, usrmove_dirs[j]))
+            continue;
+          g_autofree char *usr_path = g_strconcat ("/usr", path, NULL);
+          query_provides (refts->ts, usr_path, nevras);
+        }
+
+      if (nevras->len == 0)
+        {
+          if (*path == '/')
+            g_printerr ("file %s is not owned by any package\n", path);
+          else
+            g_printerr ("no package provides %s\n", path);
+          n_unowned++;
+          continue;
+        }
+
+      for (guint j = 0; j < nevras->len; j++)
+        {
+          if (argc > 2)
+            g_print ("%s: %s\n", path, (char *)nevras->pdata[j]);
+          else
+            g_print ("%s\n", (char *)nevras->pdata[j]);
+        }
+    }
+
+  if (n_unowned > 0)
+    return glnx_throw (error, "%u of %d queries not resolved in deployment %.10s", n_unowned,
+                       argc - 1, checksum);
+
+  return TRUE;
+}
@@ -28,11 +28,30 @@ G_BEGIN_DECLS
 
 gboolean rpmostree_db_builtin_diff (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
                                     GCancellable *cancellable, GError **error);
+gboolean rpmostree_db_builtin_changelogs (int argc, char **argv,
+                                          RpmOstreeCommandInvocation *invocation,
+                                          GCancellable *cancellable, GError **error);
+gboolean rpmostree_db_builtin_compare_manifest (int argc, char **argv,
+                                                RpmOstreeCommandInvocation *invocation,
+                                                GCancellable *cancellable, GError **error);
+gboolean rpmostree_db_builtin_export_manifest (int argc, char **argv,
+                                               RpmOstreeCommandInvocation *invocation,
+                                               GCancellable *cancellable, GError **error);
+gboolean rpmostree_db_builtin_history (int argc, char **argv,
+                                       RpmOstreeCommandInvocation *invocation,
+                                       GCancellable *cancellable, GError **error);
 gboolean rpmostree_db_builtin_list (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
                                     GCancellable *cancellable, GError **error);
+gboolean rpmostree_db_builtin_search (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
+                                      GCancellable *cancellable, GError **error);
+gboolean rpmostree_db_builtin_verify (int argc, char **argv, RpmOstreeCommandInvocation *invocation,
+                                      GCancellable *cancellable, GError **error);
 gboolean rpmostree_db_builtin_version (int argc, char **argv,
                                        RpmOstreeCommandInvocation *invocation,
                                        GCancellable *cancellable, GError **error);
+gboolean rpmostree_db_builtin_whatprovides (int argc, char **argv,
+                                            RpmOstreeCommandInvocation *invocation,
+                                            GCancellable *cancellable, GError **error);
 
 gboolean rpmostree_db_option_context_parse (GOptionContext *context,
                                             const GOptionEntry *main_entries, int *argc,
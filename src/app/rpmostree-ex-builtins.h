@@ -32,11 +32,18 @@ G_BEGIN_DECLS
                                         GCancellable *cancellable, GError **error)
 
 BUILTINPROTO (unpack);
+BUILTINPROTO (apply);
 BUILTINPROTO (apply_live);
+BUILTINPROTO (fips);
+BUILTINPROTO (fsverity);
 BUILTINPROTO (history);
 BUILTINPROTO (initramfs_etc);
 BUILTINPROTO (module);
+BUILTINPROTO (offline_update);
 BUILTINPROTO (rebuild);
+BUILTINPROTO (repo_keys);
+BUILTINPROTO (scan);
+BUILTINPROTO (sigpolicy);
 
 #undef BUILTINPROTO
 
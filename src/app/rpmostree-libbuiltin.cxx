@@ -23,6 +23,8 @@
 #include "string.h"
 
 #include "rpmostree-libbuiltin.h"
+#include "rpmostree-origin.h"
+#include "rpmostree-rpm-util.h"
 #include "rpmostree-util.h"
 #include "rpmostree.h"
 
@@ -121,6 +123,117 @@ print_treepkg_diff_from_sysroot_path (rust::Str sysroot_path, RpmOstreeDiffPrint
 
 } /* namespace */
 
+/* Find the update of the booted deployment of @sysroot: the pending
+ * deployment, or else the commit its ref points to if it was downloaded
+ * since, e.g. with `upgrade --download-only`.  Returns %FALSE in @out_found if
+ * there is none. */
+static gboolean
+resolve_pending_update (OstreeSysroot *sysroot, OstreeRepo *repo, gboolean *out_found,
+                        char **out_from_rev, char **out_to_rev, char **out_to_desc,
+                        GError **error)
+{
+  *out_found = FALSE;
+
+  OstreeDeployment *booted = ostree_sysroot_get_booted_deployment (sysroot);
+  if (!booted)
+    return glnx_throw (error, "Not booted into any deployment");
+
+  g_autoptr (OstreeDeployment) pending = NULL;
+  ostree_sysroot_query_deployments_for (sysroot, ostree_deployment_get_osname (booted), &pending,
+                                        NULL);
+  if (pending)
+    {
+      *out_found = TRUE;
+      *out_from_rev = g_strdup (ostree_deployment_get_csum (booted));
+      *out_to_rev = g_strdup (ostree_deployment_get_csum (pending));
+      *out_to_desc = g_strdup ("pending deployment");
+      return TRUE;
+    }
+
+  /* Only the base can have been downloaded; layered packages are only
+   * resolved when deploying */
+  g_autoptr (RpmOstreeOrigin) origin = rpmostree_origin_parse_deployment (booted, error);
+  if (!origin)
+    return FALSE;
+  auto refspec = rpmostree_origin_get_refspec (origin);
+  if (refspec.kind != rpmostreecxx::RefspecType::Ostree)
+    return TRUE;
+  std::string refname (refspec.refspec);
+  if (rpmostree_origin_get_override_commit (origin).length () > 0)
+    return TRUE;
+
+  g_autofree char *base = NULL;
+  if (!rpmostree_deployment_get_base_layer (repo, booted, &base, error))
+    return FALSE;
+  if (!base)
+    base = g_strdup (ostree_deployment_get_csum (booted));
+  g_autofree char *rev = NULL;
+  if (!ostree_repo_resolve_rev (repo, refname.c_str (), TRUE, &rev, error))
+    return FALSE;
+  if (!rev || g_str_equal (rev, base))
+    return TRUE;
+
+  *out_found = TRUE;
+  *out_from_rev = util::move_nullify (base);
+  *out_to_rev = util::move_nullify (rev);
+  *out_to_desc = g_strdup_printf ("downloaded update of %s", refname.c_str ());
+  return TRUE;
+}
+
+/* Print the packages which change in the pending update of the booted
+ * deployment of @sysroot, with the %changelog entries of the upgraded ones.
+ * Only the locally available package databases are used; if the one of the
+ * update wasn't downloaded, e.g. after `upgrade --check`, the packages are
+ * listed from the commit metadata instead, without changelogs. */
+gboolean
+rpmostree_print_pending_changelogs (OstreeSysroot *sysroot, GCancellable *cancellable,
+                                    GError **error)
+{
+  g_autoptr (OstreeRepo) repo = NULL;
+  if (!ostree_sysroot_get_repo (sysroot, &repo, cancellable, error))
+    return FALSE;
+
+  gboolean found;
+  g_autofree char *from_rev = NULL;
+  g_autofree char *to_rev = NULL;
+  g_autofree char *to_desc = NULL;
+  if (!resolve_pending_update (sysroot, repo, &found, &from_rev, &to_rev, &to_desc, error))
+    return FALSE;
+  if (!found)
+    {
+      g_print ("No pending update.\n");
+      return TRUE;
+    }
+
+  g_print ("Changes from booted deployment (%.10s) to %s (%.10s):\n", from_rev, to_desc, to_rev);
+
+  g_autoptr (GError) local_error = NULL;
+  g_autoptr (RpmRevisionData) rpmrev_from
+      = rpmrev_new (repo, from_rev, NULL, cancellable, error);
+  if (!rpmrev_from)
+    return FALSE;
+  g_autoptr (RpmRevisionData) rpmrev_to
+      = rpmrev_new (repo, to_rev, NULL, cancellable, &local_error);
+  if (rpmrev_to)
+    {
+      rpmhdrs_diff_prnt_block (
+          TRUE, rpmhdrs_diff (rpmrev_get_headers (rpmrev_from), rpmrev_get_headers (rpmrev_to)));
+      return TRUE;
+    }
+
+  g_print ("Changelogs unavailable: %s\n", local_error->message);
+  g_autoptr (GPtrArray) removed = NULL;
+  g_autoptr (GPtrArray) added = NULL;
+  g_autoptr (GPtrArray) modified_old = NULL;
+  g_autoptr (GPtrArray) modified_new = NULL;
+  if (!rpm_ostree_db_diff (repo, from_rev, to_rev, &removed, &added, &modified_old, &modified_new,
+                           cancellable, error))
+    return FALSE;
+  rpmostree_diff_print_formatted (RPMOSTREE_DIFF_PRINT_FORMAT_FULL_MULTILINE, NULL, 0, removed,
+                                  added, modified_old, modified_new);
+  return TRUE;
+}
+
 void
 rpmostree_print_timestamp_version (const char *version_string, const char *timestamp_string,
                                    guint max_key_len)
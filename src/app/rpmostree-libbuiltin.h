@@ -61,6 +61,9 @@ void print_treepkg_diff_from_sysroot_path (rust::Str sysroot_path, RpmOstreeDiff
                                            guint32 max_key_len, GCancellable *cancellable);
 }
 
+gboolean rpmostree_print_pending_changelogs (OstreeSysroot *sysroot, GCancellable *cancellable,
+                                            GError **error);
+
 void rpmostree_print_timestamp_version (const char *version_string, const char *timestamp_string,
                                         guint max_key_len);
 
@@ -24,6 +24,7 @@
 #include "rpmostree-core.h"
 #include "rpmostree-libbuiltin.h"
 #include "rpmostree-override-builtins.h"
+#include "rpmostree-rpm-util.h"
 
 #include <libglnx.h>
 
@@ -38,8 +39,13 @@ static const char *const *install_pkgs;
 static const char *const *uninstall_pkgs;
 static const gchar *opt_from;
 static gboolean opt_lock_finalization;
+static gboolean opt_force_policy_rebuild;
+static gboolean opt_bypass_layering_policy;
 static gboolean opt_experimental;
 static gboolean opt_freeze;
+static gboolean opt_allow_protected;
+static gboolean opt_allow_unverified_local;
+static char **opt_require_kargs;
 
 static GOptionEntry option_entries[]
     = { { "os", 0, 0, G_OPTION_ARG_STRING, &opt_osname, "Operate on provided OSNAME", "OSNAME" },
@@ -51,6 +57,11 @@ static GOptionEntry option_entries[]
           "Prevent automatic deployment finalization on shutdown", NULL },
         { "cache-only", 'C', 0, G_OPTION_ARG_NONE, &opt_cache_only, "Only operate on cached data",
           NULL },
+        { "force-policy-rebuild", 0, 0, G_OPTION_ARG_NONE, &opt_force_policy_rebuild,
+          "Don't reuse the SELinux policy cached from previous layering operations", NULL },
+        { "bypass-layering-policy", 0, 0, G_OPTION_ARG_NONE, &opt_bypass_layering_policy,
+          "Allow packages forbidden by RestrictLayering (requires additional authorization)",
+          NULL },
         { NULL } };
 
 static GOptionEntry reset_option_entries[]
@@ -65,11 +76,29 @@ static GOptionEntry replace_option_entries[]
           "Pin packages to versions found", NULL },
         { "from", 'r', G_OPTION_FLAG_HIDDEN, G_OPTION_ARG_STRING, &opt_from, "Source of override",
           "KIND=NAME" },
+        { "allow-protected", 0, 0, G_OPTION_ARG_NONE, &opt_allow_protected,
+          "Allow overriding protected packages", NULL },
+        { "allow-unverified-local", 0, 0, G_OPTION_ARG_NONE, &opt_allow_unverified_local,
+          "Allow local packages which can't be verified against the host's GPG keys", NULL },
+        { NULL } };
+
+static GOptionEntry remove_option_entries[]
+    = { { "replace", 0, 0, G_OPTION_ARG_STRING_ARRAY, &opt_replace_pkgs, "Replace a package", "RPM" },
+        { "allow-protected", 0, 0, G_OPTION_ARG_NONE, &opt_allow_protected,
+          "Allow overriding protected packages", NULL },
+        { NULL } };
+
+static GOptionEntry replace_kernel_option_entries[]
+    = { { "require-karg", 0, 0, G_OPTION_ARG_STRING_ARRAY, &opt_require_kargs,
+          "Fail if KARG is not set for the new deployment", "KARG" },
+        { "allow-unverified-local", 0, 0, G_OPTION_ARG_NONE, &opt_allow_unverified_local,
+          "Allow local packages which can't be verified against the host's GPG keys", NULL },
+        { "allow-protected", 0, 0, G_OPTION_ARG_NONE, &opt_allow_protected,
+          "Allow overriding protected packages", NULL },
         { NULL } };
 
-static GOptionEntry remove_option_entries[] = { { "replace", 0, 0, G_OPTION_ARG_STRING_ARRAY,
-                                                  &opt_replace_pkgs, "Replace a package", "RPM" },
-                                                { NULL } };
+/* The packages which make up a bootable kernel; these must always be replaced together. */
+static const char *const kernel_unit_pkgs[] = { "kernel", "kernel-core", "kernel-modules", NULL };
 
 static gboolean
 sort_replacements (RPMOSTreeOSExperimental *osexperimental_proxy,
@@ -146,7 +175,8 @@ sort_replacements (RPMOSTreeOSExperimental *osexperimental_proxy,
 static gboolean
 handle_override (RPMOSTreeSysroot *sysroot_proxy, RpmOstreeCommandInvocation *invocation,
                  const char *const *override_remove, const char *const *override_replace,
-                 const char *const *override_reset, GCancellable *cancellable, GError **error)
+                 const char *const *override_reset, gboolean allow_protected,
+                 GCancellable *cancellable, GError **error)
 {
   CXX_TRY_VAR (is_ostree_container, rpmostreecxx::is_ostree_container (), error);
 
@@ -222,6 +252,14 @@ handle_override (RPMOSTreeSysroot *sysroot_proxy, RpmOstreeCommandInvocation *in
   g_variant_dict_insert (&dict, "no-overrides", "b", opt_reset_all);
   g_variant_dict_insert (&dict, "initiating-command-line", "s", invocation->command_line);
   g_variant_dict_insert (&dict, "lock-finalization", "b", opt_lock_finalization);
+  if (allow_protected)
+    g_variant_dict_insert (&dict, "allow-protected", "b", allow_protected);
+  if (opt_allow_unverified_local)
+    g_variant_dict_insert (&dict, "allow-unverified-local", "b", opt_allow_unverified_local);
+  if (opt_force_policy_rebuild)
+    g_variant_dict_insert (&dict, "force-policy-rebuild", "b", TRUE);
+  if (opt_bypass_layering_policy)
+    g_variant_dict_insert (&dict, "bypass-layering-policy", "b", TRUE);
   g_autoptr (GVariant) options = g_variant_ref_sink (g_variant_dict_end (&dict));
 
   g_autoptr (GVariant) previous_deployment = rpmostree_os_dup_default_deployment (os_proxy);
@@ -279,9 +317,9 @@ rpmostree_override_builtin_replace (int argc, char **argv, RpmOstreeCommandInvoc
   GOptionContext *context;
   glnx_unref_object RPMOSTreeSysroot *sysroot_proxy = NULL;
 
-  context = g_option_context_new ("PACKAGE [PACKAGE...]");
+  context = rpmostree_option_context_new ("PACKAGE [PACKAGE...]");
 
-  g_option_context_add_main_entries (context, replace_option_entries, NULL);
+  rpmostree_option_context_add_entries (context, replace_option_entries);
 
   if (!rpmostree_option_context_parse (context, option_entries, &argc, &argv, invocation,
                                        cancellable, &install_pkgs, &uninstall_pkgs, &sysroot_proxy,
@@ -301,7 +339,7 @@ rpmostree_override_builtin_replace (int argc, char **argv, RpmOstreeCommandInvoc
   argv[argc] = NULL;
 
   return handle_override (sysroot_proxy, invocation, opt_remove_pkgs, (const char *const *)argv,
-                          NULL, cancellable, error);
+                          NULL, opt_allow_protected, cancellable, error);
 }
 
 gboolean
@@ -311,9 +349,9 @@ rpmostree_override_builtin_remove (int argc, char **argv, RpmOstreeCommandInvoca
   GOptionContext *context;
   glnx_unref_object RPMOSTreeSysroot *sysroot_proxy = NULL;
 
-  context = g_option_context_new ("PACKAGE [PACKAGE...]");
+  context = rpmostree_option_context_new ("PACKAGE [PACKAGE...]");
 
-  g_option_context_add_main_entries (context, remove_option_entries, NULL);
+  rpmostree_option_context_add_entries (context, remove_option_entries);
 
   if (!rpmostree_option_context_parse (context, option_entries, &argc, &argv, invocation,
                                        cancellable, &install_pkgs, &uninstall_pkgs, &sysroot_proxy,
@@ -333,7 +371,7 @@ rpmostree_override_builtin_remove (int argc, char **argv, RpmOstreeCommandInvoca
   argv[argc] = NULL;
 
   return handle_override (sysroot_proxy, invocation, (const char *const *)argv, opt_replace_pkgs,
-                          NULL, cancellable, error);
+                          NULL, opt_allow_protected, cancellable, error);
 }
 
 gboolean
@@ -343,9 +381,9 @@ rpmostree_override_builtin_reset (int argc, char **argv, RpmOstreeCommandInvocat
   GOptionContext *context;
   glnx_unref_object RPMOSTreeSysroot *sysroot_proxy = NULL;
 
-  context = g_option_context_new ("PACKAGE [PACKAGE...]");
+  context = rpmostree_option_context_new ("PACKAGE [PACKAGE...]");
 
-  g_option_context_add_main_entries (context, reset_option_entries, NULL);
+  rpmostree_option_context_add_entries (context, reset_option_entries);
 
   if (!rpmostree_option_context_parse (context, option_entries, &argc, &argv, invocation,
                                        cancellable, &install_pkgs, &uninstall_pkgs, &sysroot_proxy,
@@ -370,5 +408,177 @@ rpmostree_override_builtin_reset (int argc, char **argv, RpmOstreeCommandInvocat
   argv[argc] = NULL;
 
   return handle_override (sysroot_proxy, invocation, NULL, NULL, (const char *const *)argv,
-                          cancellable, error);
+                          FALSE, cancellable, error);
+}
+
+/* Expand directory arguments into the kernel RPMs they contain. */
+static gboolean
+expand_kernel_sources (const char *const *sources, GPtrArray *out_pkgs, GError **error)
+{
+  for (const char *const *it = sources; it && *it; it++)
+    {
+      auto source = *it;
+      struct stat stbuf;
+      if (!glnx_fstatat_allow_noent (AT_FDCWD, source, &stbuf, 0, error))
+        return FALSE;
+      if (errno == ENOENT || !S_ISDIR (stbuf.st_mode))
+        {
+          g_ptr_array_add (out_pkgs, g_strdup (source));
+          continue;
+        }
+
+      g_auto (GLnxDirFdIterator) dfd_iter = {
+        FALSE,
+      };
+      if (!glnx_dirfd_iterator_init_at (AT_FDCWD, source, TRUE, &dfd_iter, error))
+        return FALSE;
+      while (TRUE)
+        {
+          struct dirent *dent = NULL;
+          if (!glnx_dirfd_iterator_next_dent_ensure_dtype (&dfd_iter, &dent, NULL, error))
+            return FALSE;
+          if (dent == NULL)
+            break;
+          if (dent->d_type == DT_REG && g_str_has_prefix (dent->d_name, "kernel")
+              && g_str_has_suffix (dent->d_name, ".rpm")
+              && !g_str_has_suffix (dent->d_name, ".src.rpm"))
+            g_ptr_array_add (out_pkgs, g_build_filename (source, dent->d_name, NULL));
+        }
+    }
+  return TRUE;
+}
+
+/* Check that the replacement packages are a consistent kernel unit. We can only
+ * inspect files and plain URLs; e.g. Koji build URLs are filtered server-side. */
+static gboolean
+validate_kernel_replacement (const char *const *pkgs, GError **error)
+{
+  g_autoptr (GHashTable) names = g_hash_table_new_full (g_str_hash, g_str_equal, g_free, NULL);
+  g_autofree char *unit_evr = NULL;
+  for (const char *const *it = pkgs; it && *it; it++)
+    {
+      g_autofree char *nevra = g_path_get_basename (*it);
+      if (!g_str_has_suffix (nevra, ".rpm"))
+        continue;
+      nevra[strlen (nevra) - strlen (".rpm")] = '\0';
+
+      g_autofree char *name = NULL;
+      g_autofree char *version = NULL;
+      g_autofree char *release = NULL;
+      if (!rpmostree_decompose_nevra (nevra, &name, NULL, &version, &release, NULL, error))
+        return glnx_prefix_error (error, "Parsing %s", *it);
+      if (!g_str_has_prefix (name, "kernel"))
+        return glnx_throw (error, "%s is not a kernel package", *it);
+
+      g_autofree char *evr = g_strdup_printf ("%s-%s", version, release);
+      if (unit_evr == NULL)
+        unit_evr = util::move_nullify (evr);
+      else if (!g_str_equal (unit_evr, evr))
+        return glnx_throw (error, "Mismatched kernel package versions: %s and %s", unit_evr, evr);
+      g_hash_table_add (names, util::move_nullify (name));
+    }
+
+  if (unit_evr == NULL)
+    return TRUE;
+
+  for (const char *const *it = kernel_unit_pkgs; it && *it; it++)
+    {
+      if (!g_hash_table_contains (names, *it))
+        return glnx_throw (error, "Missing %s-%s; the kernel must be replaced as a unit", *it,
+                           unit_evr);
+    }
+  return TRUE;
+}
+
+static gboolean
+get_pending_kargs (RPMOSTreeOS *os_proxy, char ***out_kargs, GCancellable *cancellable,
+                   GError **error)
+{
+  g_autoptr (GVariant) boot_config = NULL;
+  if (!rpmostree_os_call_get_deployment_boot_config_sync (os_proxy, "", TRUE, &boot_config,
+                                                          cancellable, error))
+    return FALSE;
+  const char *options = NULL;
+  if (!g_variant_lookup (boot_config, "options", "&s", &options))
+    return glnx_throw (error, "Missing kernel arguments in boot configuration");
+  g_autoptr (OstreeKernelArgs) kargs = ostree_kernel_args_from_string (options);
+  *out_kargs = ostree_kernel_args_to_strv (kargs);
+  return TRUE;
+}
+
+gboolean
+rpmostree_override_builtin_replace_kernel (int argc, char **argv,
+                                           RpmOstreeCommandInvocation *invocation,
+                                           GCancellable *cancellable, GError **error)
+{
+  GOptionContext *context;
+  glnx_unref_object RPMOSTreeSysroot *sysroot_proxy = NULL;
+
+  context = rpmostree_option_context_new ("SOURCE [SOURCE...]");
+
+  rpmostree_option_context_add_entries (context, replace_kernel_option_entries);
+
+  if (!rpmostree_option_context_parse (context, option_entries, &argc, &argv, invocation,
+                                       cancellable, NULL, NULL, &sysroot_proxy, error))
+    return FALSE;
+
+  if (argc < 2)
+    {
+      rpmostree_usage_error (context, "At least one SOURCE must be specified", error);
+      return FALSE;
+    }
+
+  /* We verify the kernel arguments of the new deployment before it is booted. */
+  if (opt_reboot)
+    {
+      rpmostree_usage_error (context, "Cannot use --reboot with replace-kernel", error);
+      return FALSE;
+    }
+
+  /* shift to first source and ensure it's a proper strv (previous parsing
+   * might have moved args around) */
+  argv++;
+  argc--;
+  argv[argc] = NULL;
+
+  g_autoptr (GPtrArray) pkgs = g_ptr_array_new_with_free_func (g_free);
+  if (!expand_kernel_sources ((const char *const *)argv, pkgs, error))
+    return FALSE;
+  if (pkgs->len == 0)
+    return glnx_throw (error, "No kernel packages found");
+  g_ptr_array_add (pkgs, NULL);
+  auto kernel_pkgs = (const char *const *)pkgs->pdata;
+  if (!validate_kernel_replacement (kernel_pkgs, error))
+    return FALSE;
+
+  glnx_unref_object RPMOSTreeOS *os_proxy = NULL;
+  if (!rpmostree_load_os_proxy (sysroot_proxy, opt_osname, cancellable, &os_proxy, error))
+    return FALSE;
+  if (!handle_override (sysroot_proxy, invocation, NULL, kernel_pkgs, NULL, opt_allow_protected,
+                        cancellable, error))
+    return FALSE;
+  if (opt_dry_run || !opt_require_kargs)
+    return TRUE;
+
+  g_auto (GStrv) new_kargs_strv = NULL;
+  if (!get_pending_kargs (os_proxy, &new_kargs_strv, cancellable, error))
+    return FALSE;
+  auto new_kargs = (const char *const *)new_kargs_strv;
+  g_autoptr (GPtrArray) missing = g_ptr_array_new ();
+  for (char **it = opt_require_kargs; it && *it; it++)
+    {
+      if (!g_strv_contains (new_kargs, *it))
+        g_ptr_array_add (missing, *it);
+    }
+  if (missing->len > 0)
+    {
+      g_ptr_array_add (missing, NULL);
+      g_autofree char *missing_str = g_strjoinv (" ", (char **)missing->pdata);
+      return glnx_throw (error,
+                         "Kernel arguments missing from the new deployment: %s; use \"rpm-ostree "
+                         "kargs\" to fix them up before rebooting",
+                         missing_str);
+    }
+
+  return TRUE;
 }
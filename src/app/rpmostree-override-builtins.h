@@ -35,5 +35,8 @@ gboolean rpmostree_override_builtin_remove (int argc, char **argv,
 gboolean rpmostree_override_builtin_reset (int argc, char **argv,
                                            RpmOstreeCommandInvocation *invocation,
                                            GCancellable *cancellable, GError **error);
+gboolean rpmostree_override_builtin_replace_kernel (int argc, char **argv,
+                                                    RpmOstreeCommandInvocation *invocation,
+                                                    GCancellable *cancellable, GError **error);
 
 G_END_DECLS
@@ -44,7 +44,12 @@ static gboolean opt_allow_inactive;
 static gboolean opt_uninstall_all;
 static gboolean opt_unchanged_exit_77;
 static gboolean opt_lock_finalization;
+static gboolean opt_force_policy_rebuild;
+static gboolean opt_bypass_layering_policy;
 static gboolean opt_force_replacefiles;
+static gboolean opt_allow_unverified_local;
+static int opt_transient_boots;
+static char **opt_sha256;
 
 static GOptionEntry option_entries[]
     = { { "os", 0, 0, G_OPTION_ARG_STRING, &opt_osname, "Operate on provided OSNAME", "OSNAME" },
@@ -60,6 +65,11 @@ static GOptionEntry option_entries[]
           "If no overlays were changed, exit 77", NULL },
         { "lock-finalization", 0, G_OPTION_FLAG_HIDDEN, G_OPTION_ARG_NONE, &opt_lock_finalization,
           "Prevent automatic deployment finalization on shutdown", NULL },
+        { "force-policy-rebuild", 0, 0, G_OPTION_ARG_NONE, &opt_force_policy_rebuild,
+          "Don't reuse the SELinux policy cached from previous layering operations", NULL },
+        { "bypass-layering-policy", 0, 0, G_OPTION_ARG_NONE, &opt_bypass_layering_policy,
+          "Allow packages forbidden by RestrictLayering (requires additional authorization)",
+          NULL },
         { NULL } };
 
 static GOptionEntry uninstall_option_entry[]
@@ -67,6 +77,8 @@ static GOptionEntry uninstall_option_entry[]
           "PKG" },
         { "all", 0, 0, G_OPTION_ARG_NONE, &opt_uninstall_all,
           "Remove all overlayed additional packages", NULL },
+        { "apply-live", 'A', 0, G_OPTION_ARG_NONE, &opt_apply_live,
+          "Apply changes to both pending deployment and running filesystem tree", NULL },
         { NULL } };
 
 static GOptionEntry install_option_entry[]
@@ -80,6 +92,12 @@ static GOptionEntry install_option_entry[]
           "Apply changes to both pending deployment and running filesystem tree", NULL },
         { "force-replacefiles", 0, 0, G_OPTION_ARG_NONE, &opt_force_replacefiles,
           "Allow package to replace files from other packages", NULL },
+        { "transient-boots", 0, 0, G_OPTION_ARG_INT, &opt_transient_boots,
+          "Automatically remove the packages after they have been layered for N boots", "N" },
+        { "sha256", 0, 0, G_OPTION_ARG_STRING_ARRAY, &opt_sha256,
+          "Expected SHA-256 of a package given by URL (once per URL, in order)", "CHECKSUM" },
+        { "allow-unverified-local", 0, 0, G_OPTION_ARG_NONE, &opt_allow_unverified_local,
+          "Allow local packages which can't be verified against the host's GPG keys", NULL },
         { NULL } };
 
 static gboolean
@@ -119,6 +137,14 @@ pkg_change (RpmOstreeCommandInvocation *invocation, RPMOSTreeSysroot *sysroot_pr
   g_variant_dict_insert (&dict, "lock-finalization", "b", opt_lock_finalization);
   if (opt_apply_live)
     g_variant_dict_insert (&dict, "apply-live", "b", opt_apply_live);
+  if (opt_transient_boots > 0)
+    g_variant_dict_insert (&dict, "transient-boots", "u", (guint32)opt_transient_boots);
+  if (opt_allow_unverified_local)
+    g_variant_dict_insert (&dict, "allow-unverified-local", "b", opt_allow_unverified_local);
+  if (opt_force_policy_rebuild)
+    g_variant_dict_insert (&dict, "force-policy-rebuild", "b", TRUE);
+  if (opt_bypass_layering_policy)
+    g_variant_dict_insert (&dict, "bypass-layering-policy", "b", TRUE);
   g_autoptr (GVariant) options = g_variant_ref_sink (g_variant_dict_end (&dict));
 
   gboolean met_local_pkg = FALSE;
@@ -160,9 +186,9 @@ rpmostree_builtin_install (int argc, char **argv, RpmOstreeCommandInvocation *in
   GOptionContext *context;
   glnx_unref_object RPMOSTreeSysroot *sysroot_proxy = NULL;
 
-  context = g_option_context_new ("PACKAGE [PACKAGE...]");
+  context = rpmostree_option_context_new ("PACKAGE [PACKAGE...]");
 
-  g_option_context_add_main_entries (context, install_option_entry, NULL);
+  rpmostree_option_context_add_entries (context, install_option_entry);
 
   if (!rpmostree_option_context_parse (context, option_entries, &argc, &argv, invocation,
                                        cancellable, NULL, NULL, &sysroot_proxy, error))
@@ -174,12 +200,30 @@ rpmostree_builtin_install (int argc, char **argv, RpmOstreeCommandInvocation *in
       return FALSE;
     }
 
+  if (opt_transient_boots < 0)
+    {
+      rpmostree_usage_error (context, "--transient-boots must be a positive number", error);
+      return FALSE;
+    }
+
   /* shift to first pkgspec and ensure it's a proper strv (previous parsing
    * might have moved args around) */
   argv++;
   argc--;
   argv[argc] = NULL;
 
+  g_auto (GStrv) pinned_argv = NULL;
+  if (opt_sha256)
+    {
+      CXX_TRY_VAR (pinned,
+                   rpmostreecxx::client_pin_url_checksums (
+                       util::rust_stringvec_from_strv (argv),
+                       util::rust_stringvec_from_strv (opt_sha256)),
+                   error);
+      pinned_argv = rpmostree_cxx_string_vec_to_strv (pinned);
+      argv = pinned_argv;
+    }
+
   CXX_TRY_VAR (is_ostree_container, rpmostreecxx::is_ostree_container (), error);
   if (is_ostree_container)
     {
@@ -216,9 +260,9 @@ rpmostree_builtin_uninstall (int argc, char **argv, RpmOstreeCommandInvocation *
   GOptionContext *context;
   glnx_unref_object RPMOSTreeSysroot *sysroot_proxy = NULL;
 
-  context = g_option_context_new ("PACKAGE [PACKAGE...]");
+  context = rpmostree_option_context_new ("PACKAGE [PACKAGE...]");
 
-  g_option_context_add_main_entries (context, uninstall_option_entry, NULL);
+  rpmostree_option_context_add_entries (context, uninstall_option_entry);
 
   if (!rpmostree_option_context_parse (context, option_entries, &argc, &argv, invocation,
                                        cancellable, NULL, NULL, &sysroot_proxy, error))
@@ -5,9 +5,12 @@
 namespace rpmostreecxx
 {
 
+struct CliDescription;
+
 void early_main ();
 void rpmostree_process_global_teardown ();
 int rpmostree_main (rust::Slice<const rust::Str> args);
+CliDescription describe_command (rust::Slice<const rust::Str> path);
 
 void c_unit_tests ();
 
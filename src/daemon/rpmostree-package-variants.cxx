@@ -22,8 +22,11 @@
 
 #include "rpmostree-package-priv.h"
 #include "rpmostree-package-variants.h"
+#include "rpmostree-rpm-util.h"
 #include "rpmostree-util.h"
 #include <libglnx.h>
+#include <rpm/rpmfi.h>
+#include <rpm/rpmts.h>
 #include <rpmostree.h>
 
 /**
@@ -188,6 +191,116 @@ rpm_ostree_db_diff_variant (OstreeRepo *repo, const char *from_rev, const char *
   return TRUE;
 }
 
+/* Gather the files of all packages in the rpmdb of @rev as a map of path to digest;
+ * symlinks are represented by their target, and directories and %ghost files are
+ * skipped since they don't have content. */
+static GHashTable *
+rpmdb_file_digests (OstreeRepo *repo, const char *rev, GCancellable *cancellable, GError **error)
+{
+  g_autofree char *commit = NULL;
+  if (!ostree_repo_resolve_rev (repo, rev, FALSE, &commit, error))
+    return NULL;
+
+  g_autoptr (RpmOstreeRefTs) refts = NULL;
+  if (!rpmostree_get_refts_for_commit (repo, commit, &refts, cancellable, error))
+    return NULL;
+
+  g_autoptr (GHashTable) files = g_hash_table_new_full (g_str_hash, g_str_equal, g_free, g_free);
+  g_auto (rpmdbMatchIterator) it = rpmtsInitIterator (refts->ts, RPMDBI_PACKAGES, NULL, 0);
+  Header h;
+  while ((h = rpmdbNextIterator (it)) != NULL)
+    {
+      g_auto (rpmfi) fi = rpmfiNew (refts->ts, h, RPMTAG_BASENAMES, RPMFI_FLAGS_QUERY);
+      fi = rpmfiInit (fi, 0);
+      while (rpmfiNext (fi) >= 0)
+        {
+          if (rpmfiFFlags (fi) & RPMFILE_GHOST)
+            continue;
+          mode_t mode = rpmfiFMode (fi);
+          if (S_ISDIR (mode))
+            continue;
+
+          char *digest = NULL;
+          if (S_ISLNK (mode))
+            digest = g_strconcat ("symlink:", rpmfiFLink (fi), NULL);
+          else
+            {
+              char *hex = rpmfiFDigestHex (fi, NULL);
+              digest = g_strdup (hex ?: "");
+              free (hex);
+            }
+          g_hash_table_replace (files, g_strdup (rpmfiFN (fi)), digest);
+        }
+    }
+
+  return util::move_nullify (files);
+}
+
+static int
+compare_paths (gconstpointer a, gconstpointer b)
+{
+  return strcmp (*(const char *const *)a, *(const char *const *)b);
+}
+
+static GVariant *
+sorted_strv_variant (GPtrArray *paths)
+{
+  g_ptr_array_sort (paths, compare_paths);
+  return g_variant_new_strv ((const char *const *)paths->pdata, paths->len);
+}
+
+/**
+ * rpm_ostree_db_file_diff_variant
+ * @repo: A OstreeRepo
+ * @from_rev: First ref to diff
+ * @to_rev: Second ref to diff
+ * @out_variant: a{sv} with the paths of the files which were "added", "removed" and
+ *   "modified" between the rpm databases on the given refs.
+ * GCancellable: A GCancellable
+ * GError: **error
+ *
+ * Unlike rpm_ostree_db_diff_variant(), this requires the full rpmdb of both refs.
+ *
+ * Returns: %TRUE on success, %FALSE on failure
+ */
+gboolean
+rpm_ostree_db_file_diff_variant (OstreeRepo *repo, const char *from_rev, const char *to_rev,
+                                 GVariant **out_variant, GCancellable *cancellable,
+                                 GError **error)
+{
+  g_autoptr (GHashTable) from_files = rpmdb_file_digests (repo, from_rev, cancellable, error);
+  if (!from_files)
+    return FALSE;
+  g_autoptr (GHashTable) to_files = rpmdb_file_digests (repo, to_rev, cancellable, error);
+  if (!to_files)
+    return FALSE;
+
+  g_autoptr (GPtrArray) added = g_ptr_array_new ();
+  g_autoptr (GPtrArray) removed = g_ptr_array_new ();
+  g_autoptr (GPtrArray) modified = g_ptr_array_new ();
+  GLNX_HASH_TABLE_FOREACH_KV (to_files, const char *, path, const char *, digest)
+    {
+      auto from_digest = static_cast<const char *> (g_hash_table_lookup (from_files, path));
+      if (!from_digest)
+        g_ptr_array_add (added, (gpointer)path);
+      else if (!g_str_equal (from_digest, digest))
+        g_ptr_array_add (modified, (gpointer)path);
+    }
+  GLNX_HASH_TABLE_FOREACH (from_files, const char *, path)
+    {
+      if (!g_hash_table_contains (to_files, path))
+        g_ptr_array_add (removed, (gpointer)path);
+    }
+
+  g_auto (GVariantDict) dict;
+  g_variant_dict_init (&dict, NULL);
+  g_variant_dict_insert_value (&dict, "added", sorted_strv_variant (added));
+  g_variant_dict_insert_value (&dict, "removed", sorted_strv_variant (removed));
+  g_variant_dict_insert_value (&dict, "modified", sorted_strv_variant (modified));
+  *out_variant = g_variant_ref_sink (g_variant_dict_end (&dict));
+  return TRUE;
+}
+
 namespace rpmostreecxx
 {
 GVariant *
@@ -39,6 +39,10 @@ gboolean rpm_ostree_db_diff_variant (OstreeRepo *repo, const char *from_rev, con
                                      gboolean allow_noent, GVariant **out_variant,
                                      GCancellable *cancellable, GError **error);
 
+gboolean rpm_ostree_db_file_diff_variant (OstreeRepo *repo, const char *from_rev,
+                                          const char *to_rev, GVariant **out_variant,
+                                          GCancellable *cancellable, GError **error);
+
 G_END_DECLS
 
 #ifdef __cplusplus
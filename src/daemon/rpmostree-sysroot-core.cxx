@@ -36,6 +36,7 @@
 #include "rpmostree-rpm-util.h"
 #include "rpmostree-sysroot-core.h"
 #include "rpmostree-sysroot-upgrader.h"
+#include "rpmostreed-daemon.h"
 
 #include "ostree-repo.h"
 
@@ -77,6 +78,68 @@ add_package_refs_to_set (RpmOstreeRefSack *rsack, GHashTable *referenced_pkgs,
   return TRUE;
 }
 
+/* Load the pkgcache refs pinned by download-only operations into @pins. */
+static gboolean
+load_pkgcache_pins (GHashTable *pins, GError **error)
+{
+  g_autoptr (GError) local_error = NULL;
+  g_autofree char *contents = glnx_file_get_contents_utf8_at (
+      AT_FDCWD, RPMOSTREE_PKGCACHE_PINS_FILE, NULL, NULL, &local_error);
+  if (!contents)
+    {
+      if (g_error_matches (local_error, G_IO_ERROR, G_IO_ERROR_NOT_FOUND))
+        return TRUE;
+      g_propagate_error (error, util::move_nullify (local_error));
+      return FALSE;
+    }
+
+  g_auto (GStrv) lines = g_strsplit (contents, "\n", -1);
+  for (char **it = lines; it && *it; it++)
+    {
+      if (**it)
+        g_hash_table_add (pins, g_strdup (*it));
+    }
+  return TRUE;
+}
+
+static gboolean
+write_pkgcache_pins (GHashTable *pins, GCancellable *cancellable, GError **error)
+{
+  if (g_hash_table_size (pins) == 0)
+    return glnx_shutil_rm_rf_at (AT_FDCWD, RPMOSTREE_PKGCACHE_PINS_FILE, cancellable, error);
+
+  if (!glnx_shutil_mkdir_p_at (AT_FDCWD, RPMOSTREE_CORE_CACHEDIR, 0775, cancellable, error))
+    return FALSE;
+
+  g_autoptr (GString) contents = g_string_new ("");
+  GLNX_HASH_TABLE_FOREACH (pins, const char *, ref)
+    g_string_append_printf (contents, "%s\n", ref);
+  return glnx_file_replace_contents_at (AT_FDCWD, RPMOSTREE_PKGCACHE_PINS_FILE,
+                                        (const guint8 *)contents->str, contents->len,
+                                        static_cast<GLnxFileReplaceFlags> (0), cancellable, error);
+}
+
+/* Protect the pkgcache refs of @packages from garbage collection until a
+ * deployment references them. This is used by download-only operations so
+ * that a later cache-only operation can complete offline.
+ */
+gboolean
+rpmostree_syscore_pin_pkgcache_refs (GPtrArray *packages, GCancellable *cancellable,
+                                     GError **error)
+{
+  g_autoptr (GHashTable) pins = g_hash_table_new_full (g_str_hash, g_str_equal, g_free, NULL);
+  if (!load_pkgcache_pins (pins, error))
+    return FALSE;
+
+  for (guint i = 0; i < packages->len; i++)
+    {
+      auto pkg = static_cast<DnfPackage *> (packages->pdata[i]);
+      g_hash_table_add (pins, rpmostree_get_cache_branch_pkg (pkg));
+    }
+
+  return write_pkgcache_pins (pins, cancellable, error);
+}
+
 /* Loop over all deployments, gathering all referenced NEVRAs for
  * layered packages.  Then delete any cached pkg refs that aren't in
  * that set.
@@ -137,6 +200,28 @@ generate_pkgcache_refs (OstreeSysroot *sysroot, OstreeRepo *repo, guint *out_n_f
         }
     }
 
+  /* Keep packages downloaded for a later cache-only operation; drop the pins
+   * which are now held by a deployment. */
+  g_autoptr (GHashTable) pins = g_hash_table_new_full (g_str_hash, g_str_equal, g_free, NULL);
+  if (!load_pkgcache_pins (pins, error))
+    return FALSE;
+  const guint n_pins = g_hash_table_size (pins);
+  GHashTableIter pins_iter;
+  gpointer pin;
+  g_hash_table_iter_init (&pins_iter, pins);
+  while (g_hash_table_iter_next (&pins_iter, &pin, NULL))
+    {
+      if (g_hash_table_contains (referenced_pkgs, pin))
+        g_hash_table_iter_remove (&pins_iter);
+    }
+  if (g_hash_table_size (pins) != n_pins)
+    {
+      if (!write_pkgcache_pins (pins, cancellable, error))
+        return FALSE;
+    }
+  GLNX_HASH_TABLE_FOREACH (pins, const char *, ref)
+    g_hash_table_add (referenced_pkgs, g_strdup (ref));
+
   guint n_freed = 0;
   /* Loop over layered refs */
   g_autoptr (GHashTable) pkg_refs = NULL;
@@ -206,6 +291,11 @@ rpmostree_syscore_cleanup (OstreeSysroot *sysroot, OstreeRepo *repo, GCancellabl
   /* also delete extra history entries */
   ROSCXX_TRY (history_prune (), error);
 
+  /* And pulled container images, if configured */
+  gint image_retention = rpmostreed_get_container_image_retention (rpmostreed_daemon_get ());
+  if (image_retention >= 0)
+    ROSCXX_TRY (prune_container_images (*sysroot, *repo, image_retention), error);
+
   /* Regenerate all refs */
   guint n_pkgcache_freed = 0;
   if (!syscore_regenerate_refs (sysroot, repo, &n_pkgcache_freed, cancellable, error))
@@ -321,6 +411,174 @@ rpmostree_syscore_filter_deployments (OstreeSysroot *sysroot, const char *osname
   return util::move_nullify (new_deployments);
 }
 
+/* The root of a deployment is immutable, so its mtime is when it was created */
+static gboolean
+get_deployment_created (OstreeSysroot *sysroot, OstreeDeployment *deployment, gint64 *out_created,
+                        GError **error)
+{
+  g_autofree char *path = ostree_sysroot_get_deployment_dirpath (sysroot, deployment);
+  struct stat stbuf;
+  if (!glnx_fstatat (ostree_sysroot_get_fd (sysroot), path, &stbuf, 0, error))
+    return FALSE;
+  *out_created = stbuf.st_mtime;
+  return TRUE;
+}
+
+typedef struct
+{
+  OstreeDeployment *deployment;
+  gint64 created;
+} RollbackDeployment;
+
+static gint
+compare_rollback_recency (gconstpointer a, gconstpointer b)
+{
+  auto ra = static_cast<const RollbackDeployment *> (a);
+  auto rb = static_cast<const RollbackDeployment *> (b);
+  return (ra->created < rb->created) - (ra->created > rb->created);
+}
+
+/* Returns in @out_deployments the deployments to keep per the retention policy, or NULL if
+ * they're unchanged. For @osname, this drops the rollback deployments beyond the
+ * KeepRollbackDeployments most recent ones, as well as the pending and rollback
+ * deployments created more than AutoCleanupAfterDays days ago, except for the most recent
+ * rollback deployment. The booted and pinned deployments are always kept. The rollback
+ * deployments are also reordered from the most recent to the oldest, which is the order
+ * they're counted in, and shown by `rpm-ostree status`; e.g. rolling back to an older
+ * deployment otherwise leaves it out of order.
+ */
+gboolean
+rpmostree_syscore_filter_deployments_retention (OstreeSysroot *sysroot, const char *osname,
+                                                GPtrArray **out_deployments, GError **error)
+{
+  RpmostreedDaemon *daemon = rpmostreed_daemon_get ();
+  const gint keep_rollback = rpmostreed_get_keep_rollback_deployments (daemon);
+  const gint max_days = rpmostreed_get_auto_cleanup_after_days (daemon);
+  OstreeDeployment *booted_deployment = ostree_sysroot_get_booted_deployment (sysroot);
+
+  *out_deployments = NULL;
+  /* without a booted deployment, pending and rollback deployments can't be told apart */
+  if (!booted_deployment || (keep_rollback < 0 && max_days < 0))
+    return TRUE;
+
+  g_autoptr (GPtrArray) deployments = ostree_sysroot_get_deployments (sysroot);
+  g_autoptr (GPtrArray) new_deployments = g_ptr_array_new_with_free_func (g_object_unref);
+  g_autoptr (GArray) rollbacks = g_array_new (FALSE, FALSE, sizeof (RollbackDeployment));
+  g_autoptr (GPtrArray) other_rollbacks = g_ptr_array_new ();
+  const gint64 max_age = (gint64)max_days * 24 * 60 * 60;
+  const gint64 now = g_get_real_time () / G_USEC_PER_SEC;
+  gboolean found_booted = FALSE;
+
+  for (guint i = 0; i < deployments->len; i++)
+    {
+      auto deployment = static_cast<OstreeDeployment *> (deployments->pdata[i]);
+
+      if (ostree_deployment_equal (deployment, booted_deployment))
+        {
+          found_booted = TRUE;
+          g_ptr_array_add (new_deployments, g_object_ref (deployment));
+          continue;
+        }
+
+      if (strcmp (ostree_deployment_get_osname (deployment), osname) != 0)
+        {
+          if (found_booted)
+            g_ptr_array_add (other_rollbacks, deployment);
+          else
+            g_ptr_array_add (new_deployments, g_object_ref (deployment));
+          continue;
+        }
+
+      RollbackDeployment rollback = { deployment, 0 };
+      if (!get_deployment_created (sysroot, deployment, &rollback.created, error))
+        return FALSE;
+
+      if (!found_booted)
+        {
+          if (ostree_deployment_is_pinned (deployment) || max_days < 0
+              || now - rollback.created <= max_age)
+            g_ptr_array_add (new_deployments, g_object_ref (deployment));
+          continue;
+        }
+
+      g_array_append_val (rollbacks, rollback);
+    }
+
+  g_array_sort (rollbacks, compare_rollback_recency);
+  gint n_rollback = 0;
+  for (guint i = 0; i < rollbacks->len; i++)
+    {
+      auto rollback = &g_array_index (rollbacks, RollbackDeployment, i);
+      if (ostree_deployment_is_pinned (rollback->deployment))
+        {
+          g_ptr_array_add (new_deployments, g_object_ref (rollback->deployment));
+          continue;
+        }
+
+      n_rollback++;
+      const gboolean expired = max_days >= 0 && now - rollback->created > max_age;
+      if ((keep_rollback < 0 || n_rollback <= keep_rollback) && (n_rollback == 1 || !expired))
+        g_ptr_array_add (new_deployments, g_object_ref (rollback->deployment));
+    }
+  for (guint i = 0; i < other_rollbacks->len; i++)
+    g_ptr_array_add (new_deployments, g_object_ref (other_rollbacks->pdata[i]));
+
+  gboolean changed = new_deployments->len != deployments->len;
+  for (guint i = 0; !changed && i < deployments->len; i++)
+    {
+      auto deployment = static_cast<OstreeDeployment *> (deployments->pdata[i]);
+      auto new_deployment = static_cast<OstreeDeployment *> (new_deployments->pdata[i]);
+      changed = !ostree_deployment_equal (deployment, new_deployment);
+    }
+  if (changed)
+    *out_deployments = util::move_nullify (new_deployments);
+  return TRUE;
+}
+
+/* Drop the rollback deployments of @new_deployment's osname beyond the @keep most recent
+ * container image deployments (counting the booted one); see ContainerDeploymentRetention.
+ * Other rollback deployments are dropped, as ostree does by default. */
+static gboolean
+prune_container_deployments (OstreeSysroot *sysroot, OstreeDeployment *new_deployment, guint keep,
+                             GCancellable *cancellable, GError **error)
+{
+  g_autoptr (GPtrArray) deployments = ostree_sysroot_get_deployments (sysroot);
+  g_autoptr (GPtrArray) new_deployments = g_ptr_array_new_with_free_func (g_object_unref);
+  OstreeDeployment *booted_deployment = ostree_sysroot_get_booted_deployment (sysroot);
+  const char *osname = ostree_deployment_get_osname (new_deployment);
+  guint n_container = 0;
+
+  for (guint i = 0; i < deployments->len; i++)
+    {
+      auto deployment = static_cast<OstreeDeployment *> (deployments->pdata[i]);
+      const gboolean is_booted
+          = booted_deployment != NULL && ostree_deployment_equal (deployment, booted_deployment);
+
+      if (!is_booted
+          && (ostree_deployment_equal (deployment, new_deployment)
+              || strcmp (ostree_deployment_get_osname (deployment), osname) != 0
+              || ostree_deployment_is_pinned (deployment)))
+        {
+          g_ptr_array_add (new_deployments, g_object_ref (deployment));
+          continue;
+        }
+
+      g_autoptr (RpmOstreeOrigin) origin = rpmostree_origin_parse_deployment (deployment, error);
+      if (!origin)
+        return FALSE;
+      const gboolean is_container
+          = rpmostree_origin_get_refspec (origin).kind == rpmostreecxx::RefspecType::Container;
+      if (is_booted || (is_container && n_container < keep))
+        g_ptr_array_add (new_deployments, g_object_ref (deployment));
+      if (is_container)
+        n_container++;
+    }
+
+  if (new_deployments->len == deployments->len)
+    return TRUE;
+  return ostree_sysroot_write_deployments (sysroot, new_deployments, cancellable, error);
+}
+
 /* A wrapper around ostree_sysroot_simple_write_deployment() that makes it easy to push
  * livefs rollbacks as well as retain them afterwards */
 gboolean
@@ -334,6 +592,10 @@ rpmostree_syscore_write_deployment (OstreeSysroot *sysroot, OstreeDeployment *ne
   auto flags = static_cast<OstreeSysrootSimpleWriteDeploymentFlags> (
       OSTREE_SYSROOT_SIMPLE_WRITE_DEPLOYMENT_FLAGS_NO_CLEAN);
 
+  /* if set, we prune rollback container deployments ourselves */
+  gint container_retention = -1;
+  /* and likewise per KeepRollbackDeployments */
+  gboolean keep_rollback = FALSE;
   if (pushing_rollback)
     flags = static_cast<OstreeSysrootSimpleWriteDeploymentFlags> (
         flags | OSTREE_SYSROOT_SIMPLE_WRITE_DEPLOYMENT_FLAGS_NOT_DEFAULT
@@ -341,21 +603,94 @@ rpmostree_syscore_write_deployment (OstreeSysroot *sysroot, OstreeDeployment *ne
   else
     {
       /* make sure rollbacks of live deployments aren't pruned */
+      gboolean is_live = FALSE;
       OstreeDeployment *booted = ostree_sysroot_get_booted_deployment (sysroot);
       if (booted)
         {
-          CXX_TRY_VAR (is_live, rpmostreecxx::has_live_apply_state (*sysroot, *booted), error);
-          if (is_live)
-            flags = static_cast<OstreeSysrootSimpleWriteDeploymentFlags> (
-                flags | OSTREE_SYSROOT_SIMPLE_WRITE_DEPLOYMENT_FLAGS_RETAIN_ROLLBACK);
+          CXX_TRY_VAR (is_live_res, rpmostreecxx::has_live_apply_state (*sysroot, *booted),
+                       error);
+          is_live = is_live_res;
+        }
+      if (!is_live)
+        {
+          RpmostreedDaemon *daemon = rpmostreed_daemon_get ();
+          container_retention = rpmostreed_get_container_deployment_retention (daemon);
+          keep_rollback = rpmostreed_get_keep_rollback_deployments (daemon) >= 0;
         }
+      if (is_live || container_retention >= 0 || keep_rollback)
+        flags = static_cast<OstreeSysrootSimpleWriteDeploymentFlags> (
+            flags | OSTREE_SYSROOT_SIMPLE_WRITE_DEPLOYMENT_FLAGS_RETAIN_ROLLBACK);
     }
 
+  ROSCXX_TRY (release_stale_pins (*sysroot), error);
+  ROSCXX_TRY (release_retention_pins (*sysroot, FALSE), error);
+  ROSCXX_TRY (release_alternative_pin (*sysroot, FALSE), error);
+
   const char *osname = ostree_deployment_get_osname (new_deployment);
   if (!ostree_sysroot_simple_write_deployment (sysroot, osname, new_deployment, merge_deployment,
                                                flags, cancellable, error))
     return FALSE;
 
+  if (container_retention >= 0
+      && !prune_container_deployments (sysroot, new_deployment, container_retention, cancellable,
+                                       error))
+    return FALSE;
+
+  if (keep_rollback)
+    {
+      g_autoptr (GPtrArray) new_deployments = NULL;
+      if (!rpmostree_syscore_filter_deployments_retention (sysroot, osname, &new_deployments,
+                                                           error))
+        return FALSE;
+      if (new_deployments
+          && !ostree_sysroot_write_deployments (sysroot, new_deployments, cancellable, error))
+        return FALSE;
+    }
+
+  if (!rpmostree_syscore_cleanup (sysroot, repo, cancellable, error))
+    return FALSE;
+
+  return TRUE;
+}
+
+/* Write @new_deployment as the alternative to the staged deployment: right after the booted
+ * deployment, replacing the previous alternative, if any, and pinned so that it survives the
+ * finalization of the staged deployment. All other deployments are kept. */
+gboolean
+rpmostree_syscore_write_alternative_deployment (OstreeSysroot *sysroot,
+                                                OstreeDeployment *new_deployment,
+                                                GCancellable *cancellable, GError **error)
+{
+  OstreeRepo *repo = ostree_sysroot_repo (sysroot);
+  OstreeDeployment *booted_deployment = ostree_sysroot_get_booted_deployment (sysroot);
+  g_assert (booted_deployment);
+
+  /* Before releasing stale pins, which include that of the previous alternative if nothing
+   * is staged */
+  CXX_TRY_VAR (prev_index, rpmostreecxx::alternative_deployment_index (*sysroot), error);
+  ROSCXX_TRY (release_stale_pins (*sysroot), error);
+  ROSCXX_TRY (release_retention_pins (*sysroot, FALSE), error);
+  ROSCXX_TRY (release_alternative_pin (*sysroot, FALSE), error);
+
+  g_autoptr (GPtrArray) deployments = ostree_sysroot_get_deployments (sysroot);
+  g_autoptr (GPtrArray) new_deployments = g_ptr_array_new_with_free_func (g_object_unref);
+  for (guint i = 0; i < deployments->len; i++)
+    {
+      auto deployment = static_cast<OstreeDeployment *> (deployments->pdata[i]);
+      if ((gint)i == prev_index)
+        continue;
+      g_ptr_array_add (new_deployments, g_object_ref (deployment));
+      if (ostree_deployment_equal (deployment, booted_deployment))
+        g_ptr_array_add (new_deployments, g_object_ref (new_deployment));
+    }
+
+  OstreeSysrootWriteDeploymentsOpts write_opts = { .do_postclean = FALSE };
+  if (!ostree_sysroot_write_deployments_with_options (sysroot, new_deployments, &write_opts,
+                                                      cancellable, error))
+    return FALSE;
+
+  ROSCXX_TRY (alternative_pin (*sysroot, *new_deployment), error);
+
   if (!rpmostree_syscore_cleanup (sysroot, repo, cancellable, error))
     return FALSE;
 
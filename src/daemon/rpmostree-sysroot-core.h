@@ -40,6 +40,9 @@ G_BEGIN_DECLS
 gboolean rpmostree_syscore_cleanup (OstreeSysroot *sysroot, OstreeRepo *repo,
                                     GCancellable *cancellable, GError **error);
 
+gboolean rpmostree_syscore_pin_pkgcache_refs (GPtrArray *packages, GCancellable *cancellable,
+                                              GError **error);
+
 OstreeDeployment *rpmostree_syscore_get_origin_merge_deployment (OstreeSysroot *self,
                                                                  const char *osname);
 
@@ -48,10 +51,20 @@ gboolean rpmostree_syscore_bump_mtime (OstreeSysroot *self, GError **error);
 GPtrArray *rpmostree_syscore_filter_deployments (OstreeSysroot *sysroot, const char *osname,
                                                  gboolean remove_pending, gboolean remove_rollback);
 
+gboolean rpmostree_syscore_filter_deployments_retention (OstreeSysroot *sysroot,
+                                                         const char *osname,
+                                                         GPtrArray **out_deployments,
+                                                         GError **error);
+
 gboolean rpmostree_syscore_write_deployment (OstreeSysroot *sysroot,
                                              OstreeDeployment *new_deployment,
                                              OstreeDeployment *merge_deployment,
                                              gboolean pushing_rollback, GCancellable *cancellable,
                                              GError **error);
 
+gboolean rpmostree_syscore_write_alternative_deployment (OstreeSysroot *sysroot,
+                                                         OstreeDeployment *new_deployment,
+                                                         GCancellable *cancellable,
+                                                         GError **error);
+
 G_END_DECLS
@@ -115,6 +115,10 @@ struct RpmOstreeSysrootUpgrader
   gboolean pkgs_imported;    /* Whether pkgs to be layered have been downloaded & imported */
   char *base_revision;       /* Non-layered replicated commit */
   char *final_revision;      /* Computed by layering; if NULL, only using base_revision */
+  char *amend_revision;      /* Layered commit of the staged deployment we build on, if any */
+  char *pull_target;         /* Commit to pull instead of the tip of the ref, if any */
+  GHashTable *provenance_pkgs; /* NEVRA -> checksum of layered packages, if recording provenance */
+  gboolean provenance_incomplete; /* Some of the checksums are missing */
 
   char **kargs_strv; /* Kernel argument list to be written into deployment  */
 };
@@ -179,9 +183,27 @@ rpmostree_sysroot_upgrader_initable_init (GInitable *initable, GCancellable *can
   if (!ostree_sysroot_get_repo (self->sysroot, &self->repo, cancellable, error))
     return FALSE;
 
-  self->cfg_merge_deployment = ostree_sysroot_get_merge_deployment (self->sysroot, self->osname);
-  self->origin_merge_deployment
-      = rpmostree_syscore_get_origin_merge_deployment (self->sysroot, self->osname);
+  /* ostree then enables fs-verity on the objects as they are written */
+  if (self->flags & RPMOSTREE_SYSROOT_UPGRADER_FLAGS_FSVERITY)
+    ROSCXX_TRY (fsverity_require_repo (*self->repo), error);
+
+  /* An alternative deployment is a variant of the booted one, not of the staged one */
+  if (self->flags & RPMOSTREE_SYSROOT_UPGRADER_FLAGS_ALTERNATIVE)
+    {
+      if (booted_deployment == NULL)
+        return glnx_throw (error, "Alternative deployments require a booted system");
+      if (!g_str_equal (ostree_deployment_get_osname (booted_deployment), self->osname))
+        return glnx_throw (error, "Alternative deployments must be for the booted OS");
+      self->cfg_merge_deployment = (OstreeDeployment *)g_object_ref (booted_deployment);
+      self->origin_merge_deployment = (OstreeDeployment *)g_object_ref (booted_deployment);
+    }
+  else
+    {
+      self->cfg_merge_deployment
+          = ostree_sysroot_get_merge_deployment (self->sysroot, self->osname);
+      self->origin_merge_deployment
+          = rpmostree_syscore_get_origin_merge_deployment (self->sysroot, self->osname);
+    }
   if (self->cfg_merge_deployment == NULL || self->origin_merge_deployment == NULL)
     return glnx_throw (error, "No previous deployment for OS '%s'", self->osname);
 
@@ -243,6 +265,9 @@ rpmostree_sysroot_upgrader_finalize (GObject *object)
   g_clear_pointer (&self->computed_origin, (GDestroyNotify)rpmostree_origin_unref);
   g_free (self->base_revision);
   g_free (self->final_revision);
+  g_free (self->amend_revision);
+  g_free (self->pull_target);
+  g_clear_pointer (&self->provenance_pkgs, g_hash_table_unref);
   g_strfreev (self->kargs_strv);
 
   G_OBJECT_CLASS (rpmostree_sysroot_upgrader_parent_class)->finalize (object);
@@ -401,6 +426,146 @@ rpmostree_sysroot_upgrader_get_sack (RpmOstreeSysrootUpgrader *self, GError **er
   return self->rpmmd_sack;
 }
 
+/* Pull @origin_ref from @origin_remote, or @override_commit if set.  If
+ * @dir_to_pull is set, only that subdirectory of the commit is pulled. */
+static gboolean
+pull_from_remote (RpmOstreeSysrootUpgrader *self, const char *origin_remote, char *origin_ref,
+                  const char *override_commit, const char *dir_to_pull, OstreeRepoPullFlags flags,
+                  gboolean timestamp_check, OstreeAsyncProgress *progress,
+                  GCancellable *cancellable, GError **error)
+{
+  g_autoptr (GVariantBuilder) optbuilder = g_variant_builder_new (G_VARIANT_TYPE ("a{sv}"));
+  if (dir_to_pull && *dir_to_pull)
+    g_variant_builder_add (optbuilder, "{s@v}", "subdir",
+                           g_variant_new_variant (g_variant_new_string (dir_to_pull)));
+  g_variant_builder_add (optbuilder, "{s@v}", "flags",
+                         g_variant_new_variant (g_variant_new_int32 (flags)));
+  gint retries = rpmostreed_get_retry_count (rpmostreed_daemon_get ());
+  if (retries >= 0)
+    g_variant_builder_add (optbuilder, "{s@v}", "n-network-retries",
+                           g_variant_new_variant (g_variant_new_uint32 (retries)));
+  /* A static delta would contain all the files, including those we
+   * reconstructed from packages */
+  if (self->flags & RPMOSTREE_SYSROOT_UPGRADER_FLAGS_FROM_LOCAL_RPMS)
+    g_variant_builder_add (optbuilder, "{s@v}", "disable-static-deltas",
+                           g_variant_new_variant (g_variant_new_boolean (TRUE)));
+  /* Add the timestamp check, unless disabled. The option was added in
+   * libostree v2017.11 */
+  if (timestamp_check)
+    {
+      g_variant_builder_add (optbuilder, "{s@v}", "timestamp-check",
+                             g_variant_new_variant (g_variant_new_boolean (TRUE)));
+      /* XXX: Short-term hack until we switch to timestamp-check-from-rev:
+       * https://github.com/coreos/rpm-ostree/pull/2094. This ensures that
+       * timestamp-check is comparing against our deployment csum's timestamp, not
+       * whatever the ref is pointing to.
+       */
+      if (override_commit
+          && !ostree_repo_set_ref_immediate (self->repo, origin_remote, origin_ref,
+                                             self->base_revision, cancellable, error))
+        return FALSE;
+    }
+  g_variant_builder_add (
+      optbuilder, "{s@v}", "refs",
+      g_variant_new_variant (g_variant_new_strv ((const char *const *)&origin_ref, 1)));
+  if (override_commit)
+    g_variant_builder_add (
+        optbuilder, "{s@v}", "override-commit-ids",
+        g_variant_new_variant (g_variant_new_strv ((const char *const *)&override_commit, 1)));
+
+  g_autoptr (GVariant) opts = g_variant_ref_sink (g_variant_builder_end (optbuilder));
+  if (!ostree_repo_pull_with_options (self->repo, origin_remote, opts, progress, cancellable,
+                                      error))
+    return glnx_prefix_error (error, "While pulling %s", override_commit ?: origin_ref);
+
+  if (progress)
+    ostree_async_progress_finish (progress);
+
+  return TRUE;
+}
+
+/* Pull just the new commit for @refspec and its /etc, and import the packages
+ * it was composed from which we have locally, so that pulling the rest of the
+ * commit only fetches the files which couldn't be reconstructed from them.
+ * This is a large win with a local mirror of the rpm-md repos. */
+static gboolean
+import_local_pkgs_for_update (RpmOstreeSysrootUpgrader *self, const char *origin_remote,
+                              char *origin_ref, const char *override_commit, const char *refspec,
+                              GCancellable *cancellable, GError **error)
+{
+  const gboolean allow_older = (self->flags & RPMOSTREE_SYSROOT_UPGRADER_FLAGS_ALLOW_OLDER) > 0;
+  if (!pull_from_remote (self, origin_remote, origin_ref, override_commit, NULL,
+                         OSTREE_REPO_PULL_FLAGS_COMMIT_ONLY, !allow_older, NULL, cancellable,
+                         error))
+    return FALSE;
+
+  g_autofree char *rev = g_strdup (override_commit);
+  if (!rev && !ostree_repo_resolve_rev (self->repo, refspec, FALSE, &rev, error))
+    return FALSE;
+  if (g_str_equal (rev, self->base_revision))
+    return TRUE; /* Note early return */
+
+  GLNX_AUTO_PREFIX_ERROR ("Importing local packages", error);
+
+  /* We need the SELinux policy of the update to label the files the same way.  The
+   * rest of /etc would be pulled afterwards anyway. */
+  if (!pull_from_remote (self, origin_remote, origin_ref, rev, "/usr/etc",
+                         OSTREE_REPO_PULL_FLAGS_NONE, FALSE, NULL, cancellable, error))
+    return FALSE;
+
+  g_autoptr (GFile) root = NULL;
+  if (!ostree_repo_read_commit (self->repo, rev, &root, NULL, cancellable, error))
+    return FALSE;
+  g_autoptr (GFile) selinux_dir = g_file_resolve_relative_path (root, "usr/etc/selinux");
+
+  g_auto (GLnxTmpDir) tmpdir = {
+    0,
+  };
+  if (!glnx_mkdtempat (ostree_repo_get_dfd (self->repo), "tmp/rpm-ostree-sepolicy.XXXXXX", 0700,
+                       &tmpdir, error))
+    return FALSE;
+  if (g_file_query_exists (selinux_dir, cancellable))
+    {
+      if (!glnx_shutil_mkdir_p_at (tmpdir.fd, "usr/etc", 0755, cancellable, error))
+        return FALSE;
+      OstreeRepoCheckoutAtOptions checkout_options = { .subpath = "/usr/etc/selinux" };
+      if (!ostree_repo_checkout_at (self->repo, &checkout_options, tmpdir.fd, "usr/etc/selinux",
+                                    rev, cancellable, error))
+        return FALSE;
+    }
+  g_autoptr (OstreeSePolicy) sepolicy = ostree_sepolicy_new_at (tmpdir.fd, cancellable, error);
+  if (!sepolicy)
+    return FALSE;
+
+  g_autoptr (RpmOstreeContext) ctx = rpmostree_context_new_client (self->repo);
+  g_autofree char *source_root
+      = rpmostree_get_deployment_root (self->sysroot, self->cfg_merge_deployment);
+  rpmostree_context_set_sepolicy (ctx, sepolicy);
+  if (!rpmostree_context_setup (ctx, NULL, source_root, cancellable, error))
+    return FALSE;
+  rpmostree_context_configure_from_deployment (ctx, self->sysroot, self->cfg_merge_deployment);
+  if (!rpmostree_context_download_metadata (
+          ctx,
+          (DnfContextSetupSackFlags)(DNF_CONTEXT_SETUP_SACK_FLAG_SKIP_RPMDB
+                                     | DNF_CONTEXT_SETUP_SACK_FLAG_SKIP_FILELISTS),
+          cancellable, error))
+    return FALSE;
+
+  g_autoptr (GVariant) commit = NULL;
+  if (!ostree_repo_load_commit (self->repo, rev, &commit, NULL, error))
+    return FALSE;
+  guint n_pkgs = 0;
+  if (!rpmostree_context_import_local_pkgs (ctx, commit, &n_pkgs, cancellable, error))
+    return FALSE;
+  if (n_pkgs > 0)
+    rpmostree_output_message ("Reassembling update from %u local package%s", n_pkgs,
+                              _NS (n_pkgs));
+  else
+    rpmostree_output_message ("No packages of the update found locally");
+
+  return TRUE;
+}
+
 /*
  * Like ostree_sysroot_upgrader_pull(), but also handles the `baserefspec` we
  * use when doing layered packages.
@@ -413,15 +578,21 @@ rpmostree_sysroot_upgrader_pull_base (RpmOstreeSysrootUpgrader *self, const char
 {
   g_assert (cancellable);
 
+  rpmostree_output_set_stage (RPMOSTREE_OUTPUT_STAGE_DOWNLOAD);
+
   const gboolean allow_older = (self->flags & RPMOSTREE_SYSROOT_UPGRADER_FLAGS_ALLOW_OLDER) > 0;
   const gboolean synthetic = (self->flags & RPMOSTREE_SYSROOT_UPGRADER_FLAGS_SYNTHETIC_PULL) > 0;
+  const gboolean from_local_rpms
+      = (self->flags & RPMOSTREE_SYSROOT_UPGRADER_FLAGS_FROM_LOCAL_RPMS) > 0;
+
+  auto r = rpmostree_origin_get_refspec (self->computed_origin);
 
   auto override_commit_s = rpmostree_origin_get_override_commit (self->computed_origin);
   const char *override_commit = NULL;
   if (!override_commit_s.empty ())
     override_commit = override_commit_s.c_str ();
-
-  auto r = rpmostree_origin_get_refspec (self->computed_origin);
+  else if (r.kind == rpmostreecxx::RefspecType::Ostree)
+    override_commit = self->pull_target;
 
   g_autofree char *new_base_rev = NULL;
 
@@ -433,8 +604,17 @@ rpmostree_sysroot_upgrader_pull_base (RpmOstreeSysrootUpgrader *self, const char
           return glnx_throw (error, "Specifying commit overrides for container-image-reference "
                                     "type refspecs is not supported");
 
+        const gboolean enforce_sigpolicy
+            = (self->flags & RPMOSTREE_SYSROOT_UPGRADER_FLAGS_ENFORCE_CONTAINER_SIGPOLICY) > 0;
+        const gboolean bypass_attestation
+            = (self->flags & RPMOSTREE_SYSROOT_UPGRADER_FLAGS_BYPASS_ATTESTATION) > 0;
+        g_autoptr (GKeyFile) origin_kf = rpmostree_origin_dup_keyfile (self->computed_origin);
+        auto pull_config
+            = rpmostreecxx::rpmostreed_get_container_pull_config (rpmostreed_daemon_get ());
         CXX_TRY_VAR (import,
-                     rpmostreecxx::pull_container (*self->repo, *cancellable, r.refspec.c_str ()),
+                     rpmostreecxx::pull_container (*self->repo, *cancellable, r.refspec.c_str (),
+                                                   enforce_sigpolicy, bypass_attestation,
+                                                   *origin_kf, pull_config),
                      error);
         // Note this duplicates
         // https://github.com/ostreedev/ostree-rs-ext/blob/22a663f64e733e7ba8382f11f853ce4202652254/lib/src/container/store.rs#L64
@@ -457,44 +637,18 @@ rpmostree_sysroot_upgrader_pull_base (RpmOstreeSysrootUpgrader *self, const char
         g_assert (self->origin_merge_deployment);
         if (origin_remote && !synthetic && !is_commit)
           {
-            g_autoptr (GVariantBuilder) optbuilder
-                = g_variant_builder_new (G_VARIANT_TYPE ("a{sv}"));
-            if (dir_to_pull && *dir_to_pull)
-              g_variant_builder_add (optbuilder, "{s@v}", "subdir",
-                                     g_variant_new_variant (g_variant_new_string (dir_to_pull)));
-            g_variant_builder_add (optbuilder, "{s@v}", "flags",
-                                   g_variant_new_variant (g_variant_new_int32 (flags)));
-            /* Add the timestamp check, unless disabled. The option was added in
-             * libostree v2017.11 */
-            if (!allow_older)
+            if (from_local_rpms && !(flags & OSTREE_REPO_PULL_FLAGS_COMMIT_ONLY)
+                && !(dir_to_pull && *dir_to_pull))
               {
-                g_variant_builder_add (optbuilder, "{s@v}", "timestamp-check",
-                                       g_variant_new_variant (g_variant_new_boolean (TRUE)));
-                /* XXX: Short-term hack until we switch to timestamp-check-from-rev:
-                 * https://github.com/coreos/rpm-ostree/pull/2094. This ensures that
-                 * timestamp-check is comparing against our deployment csum's timestamp, not
-                 * whatever the ref is pointing to.
-                 */
-                if (override_commit
-                    && !ostree_repo_set_ref_immediate (self->repo, origin_remote, origin_ref,
-                                                       self->base_revision, cancellable, error))
+                if (!import_local_pkgs_for_update (self, origin_remote, origin_ref,
+                                                   override_commit, r.refspec.c_str (),
+                                                   cancellable, error))
                   return FALSE;
               }
-            g_variant_builder_add (
-                optbuilder, "{s@v}", "refs",
-                g_variant_new_variant (g_variant_new_strv ((const char *const *)&origin_ref, 1)));
-            if (override_commit)
-              g_variant_builder_add (optbuilder, "{s@v}", "override-commit-ids",
-                                     g_variant_new_variant (g_variant_new_strv (
-                                         (const char *const *)&override_commit, 1)));
-
-            g_autoptr (GVariant) opts = g_variant_ref_sink (g_variant_builder_end (optbuilder));
-            if (!ostree_repo_pull_with_options (self->repo, origin_remote, opts, progress,
-                                                cancellable, error))
-              return glnx_prefix_error (error, "While pulling %s", override_commit ?: origin_ref);
 
-            if (progress)
-              ostree_async_progress_finish (progress);
+            if (!pull_from_remote (self, origin_remote, origin_ref, override_commit, dir_to_pull,
+                                   flags, !allow_older, progress, cancellable, error))
+              return FALSE;
           }
 
         if (override_commit)
@@ -505,6 +659,11 @@ rpmostree_sysroot_upgrader_pull_base (RpmOstreeSysrootUpgrader *self, const char
                                           error))
               return FALSE;
           }
+
+        /* Require a signature by a key pinned in rpm-ostreed.conf, if any */
+        if (origin_remote && !synthetic)
+          CXX_TRY (rpmostreecxx::repo_keys_verify_commit (*self->repo, origin_remote, new_base_rev),
+                   error);
       }
       break;
     }
@@ -535,9 +694,12 @@ checkout_base_tree (RpmOstreeSysrootUpgrader *self, GCancellable *cancellable, G
   if (self->tmprootfs_dfd != -1)
     return TRUE; /* already checked out! */
 
+  /* When amending a staged deployment, we start from its tree instead of the base */
+  const char *revision = self->amend_revision ?: self->base_revision;
+
   /* let's give the user some feedback so they don't think we're blocked */
-  auto msg = g_strdup_printf ("Checking out tree %.7s", self->base_revision);
-  auto task = rpmostreecxx::progress_begin_task (msg);
+  auto msg = g_strdup_printf ("Checking out tree %.7s", revision);
+  auto task = rpmostreecxx::progress_begin_task_timed (msg, "checkout");
 
   int repo_dfd = ostree_repo_get_dfd (self->repo); /* borrowed */
   /* Always delete this */
@@ -564,12 +726,13 @@ checkout_base_tree (RpmOstreeSysrootUpgrader *self, GCancellable *cancellable, G
   self->devino_cache = ostree_repo_devino_cache_new ();
   OstreeRepoCheckoutAtOptions checkout_options = { .devino_to_csum_cache = self->devino_cache };
   if (!ostree_repo_checkout_at (self->repo, &checkout_options, repo_dfd, RPMOSTREE_TMP_ROOTFS_DIR,
-                                self->base_revision, cancellable, error))
+                                revision, cancellable, error))
     return FALSE;
 
   if (!glnx_opendirat (repo_dfd, RPMOSTREE_TMP_ROOTFS_DIR, FALSE, &self->tmprootfs_dfd, error))
     return FALSE;
 
+  task->end ("");
   return TRUE;
 }
 
@@ -886,7 +1049,7 @@ finalize_overlays (RpmOstreeSysrootUpgrader *self, GCancellable *cancellable, GE
 
 static gboolean
 prepare_context_for_assembly (RpmOstreeSysrootUpgrader *self, const char *tmprootfs,
-                              GCancellable *cancellable, GError **error)
+                              gboolean pkgcache_only, GCancellable *cancellable, GError **error)
 {
   /* make sure yum repos and passwd used are from our cfg merge */
   rpmostree_context_configure_from_deployment (self->ctx, self->sysroot,
@@ -899,36 +1062,50 @@ prepare_context_for_assembly (RpmOstreeSysrootUpgrader *self, const char *tmproo
 
   rpmostree_context_set_sepolicy (self->ctx, sepolicy);
 
-  if (self->flags & RPMOSTREE_SYSROOT_UPGRADER_FLAGS_PKGCACHE_ONLY)
+  if (pkgcache_only || (self->flags & RPMOSTREE_SYSROOT_UPGRADER_FLAGS_PKGCACHE_ONLY))
     rpmostree_context_set_pkgcache_only (self->ctx, TRUE);
 
   return TRUE;
 }
 
-/* Initialize libdnf context from our configuration */
-static gboolean
-prep_local_assembly (RpmOstreeSysrootUpgrader *self, GCancellable *cancellable, GError **error)
+/* Let the user know about optional functionality which won't be available */
+static void
+print_unsatisfied_weakdeps (GVariant *weakdeps)
 {
-  g_assert (!self->ctx);
-
-  /* before doing any serious work; do some basic sanity checks that the origin is valid */
-
-  /* If initramfs regeneration is enabled, it's silly to support /etc overlays on top of
-   * that. Just point users at dracut's -I instead. I guess we could auto-convert
-   * ourselves? */
-  if (rpmostree_origin_get_regenerate_initramfs (self->computed_origin)
-      && rpmostree_origin_has_initramfs_etc_files (self->computed_origin))
-    return glnx_throw (
-        error, "initramfs regeneration and /etc overlay not compatible; use dracut arg -I instead");
+  if (!weakdeps || g_variant_n_children (weakdeps) == 0)
+    return;
+
+  rpmostree_output_message ("Unsatisfied weak dependencies:");
+  GVariantIter iter;
+  g_variant_iter_init (&iter, weakdeps);
+  const char *pkgname, *kind, *dep;
+  while (g_variant_iter_next (&iter, "(&s&s&s)", &pkgname, &kind, &dep))
+    rpmostree_output_message ("  %s %s %s", pkgname, kind, dep);
+}
 
-  if (!checkout_base_tree (self, cancellable, error))
-    return FALSE;
+/* Set up the libdnf context for the computed origin and resolve the packages; if
+ * @pkgcache_only is set, only the package cache is used. */
+static gboolean
+prep_layering_context (RpmOstreeSysrootUpgrader *self, gboolean pkgcache_only,
+                       GCancellable *cancellable, GError **error)
+{
+  const gboolean restrict_layering
+      = (self->flags & RPMOSTREE_SYSROOT_UPGRADER_FLAGS_RESTRICT_LAYERING) > 0;
+  /* This covers packages layered before the policy was enabled too */
+  if (restrict_layering
+      && (!rpmostree_origin_get_local_packages (self->computed_origin).empty ()
+          || !rpmostree_origin_get_local_fileoverride_packages (self->computed_origin).empty ()
+          || !rpmostree_origin_get_overrides_local_replace (self->computed_origin).empty ()))
+    return glnx_throw (error, "Local packages are forbidden by RestrictLayering; use "
+                              "--bypass-layering-policy to override");
 
   self->ctx = rpmostree_context_new_client (self->repo);
+  rpmostree_context_set_require_gpgcheck (self->ctx, restrict_layering);
+  rpmostree_context_set_find_weakdeps (self->ctx, TRUE);
 
   g_autofree char *tmprootfs_abspath = glnx_fdrel_abspath (self->tmprootfs_dfd, ".");
 
-  if (!prepare_context_for_assembly (self, tmprootfs_abspath, cancellable, error))
+  if (!prepare_context_for_assembly (self, tmprootfs_abspath, pkgcache_only, cancellable, error))
     return FALSE;
 
   {
@@ -947,6 +1124,7 @@ prep_local_assembly (RpmOstreeSysrootUpgrader *self, GCancellable *cancellable,
       if (!rpmostree_context_prepare (self->ctx, cancellable, error))
         return FALSE;
       self->layering_type = RPMOSTREE_SYSROOT_UPGRADER_LAYERING_RPMMD_REPOS;
+      print_unsatisfied_weakdeps (rpmostree_context_get_unsatisfied_weakdeps (self->ctx));
 
       /* keep a ref on it in case the level higher up needs it */
       self->rpmmd_sack
@@ -965,6 +1143,92 @@ prep_local_assembly (RpmOstreeSysrootUpgrader *self, GCancellable *cancellable,
       self->layering_type = RPMOSTREE_SYSROOT_UPGRADER_LAYERING_LOCAL;
     }
 
+  return TRUE;
+}
+
+/* If the only change to the staged deployment is additional packages on the same base, set
+ * self->amend_revision so that we layer them on top of its tree: the packages it already
+ * has are then installed in the rpmdb, and only the new ones get resolved and assembled. */
+static gboolean
+check_amend_staged (RpmOstreeSysrootUpgrader *self, GError **error)
+{
+  OstreeDeployment *staged = self->origin_merge_deployment;
+  /* The tree may already be checked out for the base rpmdb */
+  if (!ostree_deployment_is_staged (staged) || !self->final_revision || self->tmprootfs_dfd != -1)
+    return TRUE;
+
+  CXX_TRY_VAR (layeredmeta, rpmostreecxx::deployment_layeredmeta_load (*self->repo, *staged),
+               error);
+  /* We need the layered packages to be recorded the way the new commit will */
+  if (!layeredmeta.is_layered || layeredmeta.clientlayer_version < 6
+      || !g_str_equal (layeredmeta.base_commit.c_str (), self->base_revision))
+    return TRUE;
+
+  GKeyFile *prev_origin = ostree_deployment_get_origin (staged);
+  g_autoptr (GKeyFile) new_origin = rpmostree_origin_dup_keyfile (self->original_origin);
+  CXX_TRY_VAR (is_addition, rpmostreecxx::origin_is_package_addition (*prev_origin, *new_origin),
+               error);
+  if (!is_addition)
+    return TRUE;
+
+  rpmostree_output_message ("Amending staged deployment");
+  self->amend_revision = g_strdup (self->final_revision);
+  return TRUE;
+}
+
+/* Initialize libdnf context from our configuration */
+static gboolean
+prep_local_assembly (RpmOstreeSysrootUpgrader *self, GCancellable *cancellable, GError **error)
+{
+  g_assert (!self->ctx);
+
+  /* before doing any serious work; do some basic sanity checks that the origin is valid */
+
+  /* If initramfs regeneration is enabled, it's silly to support /etc overlays on top of
+   * that. Just point users at dracut's -I instead. I guess we could auto-convert
+   * ourselves? */
+  if (rpmostree_origin_get_regenerate_initramfs (self->computed_origin)
+      && rpmostree_origin_has_initramfs_etc_files (self->computed_origin))
+    return glnx_throw (
+        error, "initramfs regeneration and /etc overlay not compatible; use dracut arg -I instead");
+
+  if (!(self->flags & RPMOSTREE_SYSROOT_UPGRADER_FLAGS_DRY_RUN)
+      && !check_amend_staged (self, error))
+    return FALSE;
+
+  if (!checkout_base_tree (self, cancellable, error))
+    return FALSE;
+
+  /* When switching between container images without changing the layered packages, first
+   * try to resolve them again from the package cache, which avoids fetching rpm-md and
+   * downloading packages again. */
+  gboolean reuse_pkgcache = FALSE;
+  if (!(self->flags & RPMOSTREE_SYSROOT_UPGRADER_FLAGS_PKGCACHE_ONLY)
+      && rpmostree_origin_has_any_packages (self->computed_origin))
+    {
+      GKeyFile *prev_origin = ostree_deployment_get_origin (self->origin_merge_deployment);
+      g_autoptr (GKeyFile) new_origin = rpmostree_origin_dup_keyfile (self->original_origin);
+      CXX_TRY_VAR (same_layering,
+                   rpmostreecxx::origin_is_container_rebase_with_same_layering (*prev_origin,
+                                                                               *new_origin),
+                   error);
+      reuse_pkgcache = same_layering;
+    }
+
+  if (reuse_pkgcache)
+    {
+      rpmostree_output_message ("Layered packages unchanged; reusing cached packages");
+      g_autoptr (GError) local_error = NULL;
+      if (!prep_layering_context (self, TRUE, cancellable, &local_error))
+        {
+          rpmostree_output_message ("Cannot reuse cached packages: %s", local_error->message);
+          g_clear_object (&self->ctx);
+          reuse_pkgcache = FALSE;
+        }
+    }
+  if (!reuse_pkgcache && !prep_layering_context (self, FALSE, cancellable, error))
+    return FALSE;
+
   if (self->flags & RPMOSTREE_SYSROOT_UPGRADER_FLAGS_DRY_RUN)
     {
       if (rpmostree_origin_has_any_packages (self->computed_origin))
@@ -1001,6 +1265,42 @@ prep_local_assembly (RpmOstreeSysrootUpgrader *self, GCancellable *cancellable,
   return TRUE;
 }
 
+/* If the initramfs of the merge deployment was regenerated from the same inputs,
+ * @inputs_manifest, copy it into @out_tmpf rather than running dracut again.
+ * Leaves @out_tmpf uninitialized otherwise.
+ */
+static gboolean
+reuse_merge_initramfs (RpmOstreeSysrootUpgrader *self, const char *kver,
+                       const rust::String &inputs_manifest, GLnxTmpfile *out_tmpf,
+                       GError **error)
+{
+  g_autofree char *deployment_path
+      = ostree_sysroot_get_deployment_dirpath (self->sysroot, self->origin_merge_deployment);
+  glnx_autofd int deployment_dfd = -1;
+  if (!glnx_opendirat (ostree_sysroot_get_fd (self->sysroot), deployment_path, TRUE,
+                       &deployment_dfd, error))
+    return FALSE;
+
+  CXX_TRY_VAR (fdv, rpmostreecxx::initramfs_reusable (deployment_dfd, kver, inputs_manifest),
+               error);
+  glnx_autofd int fd = fdv;
+  if (fd < 0)
+    return TRUE;
+
+  g_auto (GLnxTmpfile) tmpf = {
+    0,
+  };
+  if (!glnx_open_tmpfile_linkable_at (self->tmprootfs_dfd, ".", O_RDWR | O_CLOEXEC, &tmpf, error))
+    return FALSE;
+  if (glnx_regfile_copy_bytes (fd, tmpf.fd, (off_t)-1) < 0)
+    return glnx_throw_errno_prefix (error, "Copying initramfs");
+
+  rpmostree_output_message ("Initramfs inputs unchanged; reusing initramfs of merge deployment");
+  *out_tmpf = tmpf;
+  tmpf.initialized = FALSE; /* Transfer ownership */
+  return TRUE;
+}
+
 /* Overlay pkgs, run scripts, and commit final rootfs to ostree */
 static gboolean
 perform_local_assembly (RpmOstreeSysrootUpgrader *self, GCancellable *cancellable, GError **error)
@@ -1014,6 +1314,9 @@ perform_local_assembly (RpmOstreeSysrootUpgrader *self, GCancellable *cancellabl
 
   rpmostree_context_set_devino_cache (self->ctx, self->devino_cache);
   rpmostree_context_set_tmprootfs_dfd (self->ctx, self->tmprootfs_dfd);
+  rpmostree_context_set_semodule_cache (
+      self->ctx, RPMOSTREE_CORE_CACHEDIR "sepolicy",
+      (self->flags & RPMOSTREE_SYSROOT_UPGRADER_FLAGS_FORCE_POLICY_REBUILD) > 0);
 
   if (self->layering_type == RPMOSTREE_SYSROOT_UPGRADER_LAYERING_RPMMD_REPOS)
     {
@@ -1022,6 +1325,35 @@ perform_local_assembly (RpmOstreeSysrootUpgrader *self, GCancellable *cancellabl
       /* --- override/overlay --- */
       if (!rpmostree_context_assemble (self->ctx, cancellable, error))
         return FALSE;
+
+      /* Remember the packages for the provenance; the context is gone by the time we deploy */
+      if (rpmostreed_get_provenance_sink (rpmostreed_daemon_get ()))
+        {
+          g_clear_pointer (&self->provenance_pkgs, g_hash_table_unref);
+          self->provenance_incomplete = FALSE;
+          self->provenance_pkgs = g_hash_table_new_full (g_str_hash, g_str_equal, g_free, g_free);
+          g_autoptr (GPtrArray) pkgs = rpmostree_context_get_packages (self->ctx);
+          for (guint i = 0; i < pkgs->len; i++)
+            {
+              auto pkg = static_cast<DnfPackage *> (pkgs->pdata[i]);
+              /* Failing to record the provenance shouldn't fail the deployment; it's
+               * skipped in emit_provenance() instead */
+              try
+                {
+                  auto chksum = rpmostreecxx::get_repodata_chksum_repr (*pkg);
+                  g_hash_table_insert (self->provenance_pkgs,
+                                       g_strdup (dnf_package_get_nevra (pkg)),
+                                       g_strdup (chksum.c_str ()));
+                }
+              catch (std::exception &e)
+                {
+                  rpmostree_output_message ("warning: Failed to get the checksum of %s: %s",
+                                            dnf_package_get_nevra (pkg), e.what ());
+                  self->provenance_incomplete = TRUE;
+                  break;
+                }
+            }
+        }
     }
   else
     {
@@ -1090,6 +1422,8 @@ perform_local_assembly (RpmOstreeSysrootUpgrader *self, GCancellable *cancellabl
   if (rpmostree_context_get_kernel_changed (self->ctx))
     {
       g_assert (kernel_state && kver);
+      /* Before depmod, as plugins (e.g. akmods) may add modules */
+      ROSCXX_TRY (kernel_install_add (self->tmprootfs_dfd, kver), error);
       ROSCXX_TRY (run_depmod (self->tmprootfs_dfd, kver, true), error);
     }
 
@@ -1105,26 +1439,84 @@ perform_local_assembly (RpmOstreeSysrootUpgrader *self, GCancellable *cancellabl
         g_ptr_array_add (initramfs_args, g_strdup (arg.c_str ()));
       g_ptr_array_add (initramfs_args, NULL);
 
-      auto task = rpmostreecxx::progress_begin_task ("Generating initramfs");
-
       g_assert (kernel_state && kernel_path);
 
+      const gboolean regenerate = rpmostree_origin_get_regenerate_initramfs (self->computed_origin);
       g_auto (GLnxTmpfile) initramfs_tmpf = {
         0,
       };
-      /* NB: We only use the real root's /etc if initramfs regeneration is explicitly
-       * requested. IOW, just replacing the kernel still gets use stock settings, like the
-       * server side. */
-      if (!rpmostree_run_dracut (self->tmprootfs_dfd, (const char *const *)initramfs_args->pdata,
-                                 kver, initramfs_path,
-                                 rpmostree_origin_get_regenerate_initramfs (self->computed_origin),
-                                 NULL, &initramfs_tmpf, cancellable, error))
-        return FALSE;
+      /* The command of the generator to use instead of dracut, if any; it only gets the
+       * arguments from the origin, not the default ones of dracut. */
+      rust::String generator_command;
+      g_autoptr (GPtrArray) generator_args = g_ptr_array_new_with_free_func (g_free);
+      if (regenerate)
+        {
+          auto generator = rpmostree_origin_get_initramfs_generator (self->computed_origin);
+          if (!generator.empty ())
+            {
+              CXX_TRY_VAR (command,
+                           rpmostreecxx::initramfs_generator_command (self->tmprootfs_dfd,
+                                                                      generator),
+                           error);
+              generator_command = std::move (command);
+              for (auto &arg : add_dracut_argv)
+                g_ptr_array_add (generator_args, g_strdup (arg.c_str ()));
+            }
+        }
+      g_ptr_array_add (generator_args, NULL);
+      GPtrArray *args = generator_command.empty () ? initramfs_args : generator_args;
+
+      rust::String inputs_manifest;
+      if (regenerate)
+        {
+          /* The modules and drivers configured with `rpm-ostree initramfs` */
+          auto dracut_conf = rpmostree_origin_get_initramfs_dracut_conf (self->computed_origin);
+          ROSCXX_TRY (initramfs_dracut_conf_write (self->tmprootfs_dfd, dracut_conf), error);
+
+          g_autofree char *packages_sha512 = NULL;
+          if (!rpmostree_context_get_packages_sha512 (self->ctx, &packages_sha512, error))
+            return FALSE;
+          auto argsv = util::rust_stringvec_from_strv ((const char *const *)args->pdata);
+          CXX_TRY_VAR (manifest,
+                       rpmostreecxx::initramfs_inputs_manifest (self->tmprootfs_dfd, kver, argsv,
+                                                                packages_sha512, generator_command),
+                       error);
+          inputs_manifest = std::move (manifest);
+          if (!(self->flags & RPMOSTREE_SYSROOT_UPGRADER_FLAGS_INITRAMFS_REGENERATE)
+              && !reuse_merge_initramfs (self, kver, inputs_manifest, &initramfs_tmpf, error))
+            return FALSE;
+        }
+
+      if (!initramfs_tmpf.initialized)
+        {
+          auto task = rpmostreecxx::progress_begin_task ("Generating initramfs");
+          if (!generator_command.empty ())
+            {
+              if (!rpmostree_run_initramfs_generator (
+                      self->tmprootfs_dfd, generator_command.c_str (),
+                      (const char *const *)args->pdata, kver, &initramfs_tmpf, cancellable, error))
+                return FALSE;
+            }
+          else
+            {
+              /* NB: We only use the real root's /etc if initramfs regeneration is explicitly
+               * requested. IOW, just replacing the kernel still gets use stock settings, like
+               * the server side. */
+              if (!rpmostree_run_dracut (self->tmprootfs_dfd,
+                                         (const char *const *)initramfs_args->pdata, kver,
+                                         initramfs_path, regenerate, TRUE, NULL, &initramfs_tmpf,
+                                         cancellable, error))
+                return FALSE;
+            }
+        }
 
       if (!rpmostree_finalize_kernel (self->tmprootfs_dfd, bootdir, kver, kernel_path,
                                       &initramfs_tmpf, RPMOSTREE_FINALIZE_KERNEL_AUTO, cancellable,
                                       error))
         return glnx_prefix_error (error, "Finalizing kernel");
+
+      if (regenerate)
+        ROSCXX_TRY (initramfs_inputs_record (self->tmprootfs_dfd, kver, inputs_manifest), error);
     }
 
   if (!rpmostree_context_commit (self->ctx, self->base_revision,
@@ -1221,6 +1613,26 @@ rpmostree_sysroot_upgrader_import_pkgs (RpmOstreeSysrootUpgrader *self, GCancell
   return TRUE;
 }
 
+/**
+ * rpmostree_sysroot_upgrader_pin_pkgs:
+ * @self: Self
+ *
+ * Keep the imported packages in the pkgcache until a deployment uses them,
+ * so that a download-only operation can be completed later in cache-only mode.
+ */
+gboolean
+rpmostree_sysroot_upgrader_pin_pkgs (RpmOstreeSysrootUpgrader *self, GCancellable *cancellable,
+                                     GError **error)
+{
+  g_assert (self->pkgs_imported);
+
+  if (self->layering_type != RPMOSTREE_SYSROOT_UPGRADER_LAYERING_RPMMD_REPOS)
+    return TRUE;
+
+  g_autoptr (GPtrArray) pkgs = rpmostree_context_get_packages (self->ctx);
+  return rpmostree_syscore_pin_pkgcache_refs (pkgs, cancellable, error);
+}
+
 /**
  * rpmostree_sysroot_upgrader_set_kargs:
  * @self: Self
@@ -1235,15 +1647,50 @@ rpmostree_sysroot_upgrader_set_kargs (RpmOstreeSysrootUpgrader *self, char **ker
   self->kargs_strv = g_strdupv (kernel_args);
 }
 
+/**
+ * rpmostree_sysroot_upgrader_set_pull_target:
+ * @self: Self
+ * @commit: (allow-none): Commit to pull
+ *
+ * Pull @commit instead of the tip of the ref, unless the origin overrides the commit.
+ * Unlike an override, this isn't written to the origin of the new deployment.
+ */
+void
+rpmostree_sysroot_upgrader_set_pull_target (RpmOstreeSysrootUpgrader *self, const char *commit)
+{
+  g_free (self->pull_target);
+  self->pull_target = g_strdup (commit);
+}
+
 static gboolean
 write_history (RpmOstreeSysrootUpgrader *self, OstreeDeployment *new_deployment,
                GCancellable *cancellable, GError **error)
 {
+  /* The history is that of the host; don't mix in deployments of a sysroot we're not booted
+   * into, e.g. a disk image being provisioned */
+  if (ostree_sysroot_get_booted_deployment (self->sysroot) == NULL)
+    return TRUE;
+
   g_autoptr (GVariant) deployment_variant = NULL;
   if (!rpmostreed_deployment_generate_variant (self->sysroot, new_deployment, NULL, self->repo,
                                                FALSE, &deployment_variant, error))
     return FALSE;
 
+  if (self->origin_merge_deployment)
+    {
+      g_autoptr (GVariant) diff = NULL;
+      if (!rpmostreed_deployment_generate_history_diff (self->sysroot, self->repo,
+                                                        self->origin_merge_deployment,
+                                                        new_deployment, deployment_variant, &diff,
+                                                        cancellable, error))
+        return glnx_prefix_error (error, "Generating history diff");
+
+      g_autoptr (GVariantDict) dict = g_variant_dict_new (deployment_variant);
+      g_variant_dict_insert (dict, "history-diff", "@a{sv}", diff);
+      g_clear_pointer (&deployment_variant, g_variant_unref);
+      deployment_variant = g_variant_ref_sink (g_variant_dict_end (dict));
+    }
+
   g_autofree char *deployment_dirpath
       = ostree_sysroot_get_deployment_dirpath (self->sysroot, new_deployment);
   struct stat stbuf;
@@ -1293,6 +1740,34 @@ write_history (RpmOstreeSysrootUpgrader *self, OstreeDeployment *new_deployment,
   return TRUE;
 }
 
+/* Write the signed provenance of the new deployment to the configured sink */
+static gboolean
+emit_provenance (RpmOstreeSysrootUpgrader *self, GKeyFile *origin, const char *target_revision,
+                 GError **error)
+{
+  RpmostreedDaemon *daemon = rpmostreed_daemon_get ();
+  const char *sink = rpmostreed_get_provenance_sink (daemon);
+  const char *signing_key = rpmostreed_get_provenance_signing_key (daemon);
+  g_assert (sink && signing_key);
+
+  if (self->provenance_incomplete)
+    return glnx_throw (error, "Missing the checksums of the layered packages");
+
+  rust::Vec<rpmostreecxx::StringMapping> pkgs;
+  if (self->final_revision && self->provenance_pkgs)
+    {
+      GLNX_HASH_TABLE_FOREACH_KV (self->provenance_pkgs, const char *, nevra, const char *,
+                                  chksum)
+        pkgs.push_back (rpmostreecxx::StringMapping{ nevra, chksum });
+    }
+
+  g_autofree char *origin_data = g_key_file_to_data (origin, NULL, NULL);
+  CXX_TRY (rpmostreecxx::provenance_emit (*self->repo, self->base_revision, target_revision,
+                                          origin_data, pkgs, sink, signing_key),
+           error);
+  return TRUE;
+}
+
 /**
  * rpmostree_sysroot_upgrader_deploy:
  * @self: Self
@@ -1335,12 +1810,17 @@ rpmostree_sysroot_upgrader_deploy (RpmOstreeSysrootUpgrader *self,
   if (!perform_local_assembly (self, cancellable, error))
     return FALSE;
 
+  rpmostree_output_set_stage (RPMOSTREE_OUTPUT_STAGE_DEPLOY);
+
   /* make sure we have a known target to deploy */
   const char *target_revision = self->final_revision ?: self->base_revision;
   g_assert (target_revision);
 
-  /* Use staging only if we're booted into the target root. */
-  const gboolean use_staging = (ostree_sysroot_get_booted_deployment (self->sysroot) != NULL);
+  /* Use staging only if we're booted into the target root; the alternative to the staged
+   * deployment is written out right away instead. */
+  const gboolean alternative = (self->flags & RPMOSTREE_SYSROOT_UPGRADER_FLAGS_ALTERNATIVE) > 0;
+  const gboolean use_staging
+      = (ostree_sysroot_get_booted_deployment (self->sysroot) != NULL) && !alternative;
 
   /* Fix for https://github.com/projectatomic/rpm-ostree/issues/1392,
    * when kargs_strv is empty, we port those directly from pending
@@ -1400,6 +1880,12 @@ rpmostree_sysroot_upgrader_deploy (RpmOstreeSysrootUpgrader *self,
                                                    origin, self->cfg_merge_deployment, &opts,
                                                    &new_deployment, cancellable, error))
         return FALSE;
+
+      /* Finalization drops the rollback deployments except the booted one, unless pinned */
+      const gint keep_rollback
+          = rpmostreed_get_keep_rollback_deployments (rpmostreed_daemon_get ());
+      if (keep_rollback > 1)
+        ROSCXX_TRY (retention_pin_rollbacks (*self->sysroot, keep_rollback), error);
     }
   else
     {
@@ -1412,6 +1898,31 @@ rpmostree_sysroot_upgrader_deploy (RpmOstreeSysrootUpgrader *self,
   if (!write_history (self, new_deployment, cancellable, error))
     return FALSE;
 
+  /* The /etc merge conflicts are purely informational; don't fail the deployment.
+   * For a staged deployment, they're recorded once it's finalized. */
+  if (self->cfg_merge_deployment)
+    {
+      try
+        {
+          rpmostreecxx::etc_conflicts_record (*self->sysroot, *self->cfg_merge_deployment,
+                                              *new_deployment);
+        }
+      catch (std::exception &e)
+        {
+          rpmostree_output_message ("warning: Failed to record /etc merge conflicts: %s",
+                                    e.what ());
+        }
+    }
+
+  /* Failing to record the provenance shouldn't fail the deployment */
+  if (rpmostreed_get_provenance_sink (rpmostreed_daemon_get ()))
+    {
+      g_autoptr (GError) local_error = NULL;
+      if (!emit_provenance (self, origin, target_revision, &local_error))
+        rpmostree_output_message ("warning: Failed to record provenance: %s",
+                                  local_error->message);
+    }
+
   /* Also do a sanitycheck even if there's no local mutation; it's basically free
    * and might save someone in the future.  The RPMOSTREE_SKIP_SANITYCHECK
    * environment variable is just used by test-basic.sh currently.
@@ -1450,6 +1961,12 @@ rpmostree_sysroot_upgrader_deploy (RpmOstreeSysrootUpgrader *self,
       if (!rpmostree_syscore_cleanup (self->sysroot, self->repo, cancellable, error))
         return FALSE;
     }
+  else if (alternative)
+    {
+      if (!rpmostree_syscore_write_alternative_deployment (self->sysroot, new_deployment,
+                                                           cancellable, error))
+        return FALSE;
+    }
   else
     {
       if (!rpmostree_syscore_write_deployment (
@@ -1482,6 +1999,23 @@ rpmostree_sysroot_upgrader_flags_get_type (void)
           "RPMOSTREE_SYSROOT_UPGRADER_FLAGS_SYNTHETIC_PULL", "synthetic-pull" },
         { RPMOSTREE_SYSROOT_UPGRADER_FLAGS_LOCK_FINALIZATION,
           "RPMOSTREE_SYSROOT_UPGRADER_FLAGS_LOCK_FINALIZATION", "lock-finalization" },
+        { RPMOSTREE_SYSROOT_UPGRADER_FLAGS_ENFORCE_CONTAINER_SIGPOLICY,
+          "RPMOSTREE_SYSROOT_UPGRADER_FLAGS_ENFORCE_CONTAINER_SIGPOLICY",
+          "enforce-container-sigpolicy" },
+        { RPMOSTREE_SYSROOT_UPGRADER_FLAGS_BYPASS_ATTESTATION,
+          "RPMOSTREE_SYSROOT_UPGRADER_FLAGS_BYPASS_ATTESTATION", "bypass-attestation" },
+        { RPMOSTREE_SYSROOT_UPGRADER_FLAGS_INITRAMFS_REGENERATE,
+          "RPMOSTREE_SYSROOT_UPGRADER_FLAGS_INITRAMFS_REGENERATE", "initramfs-regenerate" },
+        { RPMOSTREE_SYSROOT_UPGRADER_FLAGS_ALTERNATIVE,
+          "RPMOSTREE_SYSROOT_UPGRADER_FLAGS_ALTERNATIVE", "alternative" },
+        { RPMOSTREE_SYSROOT_UPGRADER_FLAGS_FROM_LOCAL_RPMS,
+          "RPMOSTREE_SYSROOT_UPGRADER_FLAGS_FROM_LOCAL_RPMS", "from-local-rpms" },
+        { RPMOSTREE_SYSROOT_UPGRADER_FLAGS_FSVERITY, "RPMOSTREE_SYSROOT_UPGRADER_FLAGS_FSVERITY",
+          "fsverity" },
+        { RPMOSTREE_SYSROOT_UPGRADER_FLAGS_FORCE_POLICY_REBUILD,
+          "RPMOSTREE_SYSROOT_UPGRADER_FLAGS_FORCE_POLICY_REBUILD", "force-policy-rebuild" },
+        { RPMOSTREE_SYSROOT_UPGRADER_FLAGS_RESTRICT_LAYERING,
+          "RPMOSTREE_SYSROOT_UPGRADER_FLAGS_RESTRICT_LAYERING", "restrict-layering" },
       };
       GType g_define_type_id = g_flags_register_static (
           g_intern_static_string ("RpmOstreeSysrootUpgraderFlags"), values);
@@ -48,6 +48,21 @@ G_DEFINE_AUTOPTR_CLEANUP_FUNC (RpmOstreeSysrootUpgrader, g_object_unref)
  * @RPMOSTREE_SYSROOT_UPGRADER_FLAGS_SYNTHETIC_PULL: Don't actually pull, just resolve ref and
  * timestamp check
  * @RPMOSTREE_SYSROOT_UPGRADER_FLAGS_LOCK_FINALIZATION: Prevent deployment finalization on shutdown
+ * @RPMOSTREE_SYSROOT_UPGRADER_FLAGS_ENFORCE_CONTAINER_SIGPOLICY: Require container images to be
+ * signed according to containers-policy.json
+ * @RPMOSTREE_SYSROOT_UPGRADER_FLAGS_BYPASS_ATTESTATION: Don't require container images to have
+ * an attestation satisfying the attestation policy
+ * @RPMOSTREE_SYSROOT_UPGRADER_FLAGS_INITRAMFS_REGENERATE: Always run dracut when regenerating the
+ * initramfs, rather than reusing that of the merge deployment if its inputs are unchanged
+ * @RPMOSTREE_SYSROOT_UPGRADER_FLAGS_ALTERNATIVE: Build on the booted deployment, and write the
+ * result as the alternative to the staged deployment rather than staging it
+ * @RPMOSTREE_SYSROOT_UPGRADER_FLAGS_FROM_LOCAL_RPMS: Before pulling the base, reconstruct what we
+ * can of it from the packages available locally
+ * @RPMOSTREE_SYSROOT_UPGRADER_FLAGS_FSVERITY: Have ostree enable fs-verity on the objects it writes
+ * @RPMOSTREE_SYSROOT_UPGRADER_FLAGS_FORCE_POLICY_REBUILD: Discard the SELinux policies cached from
+ * previous package layering operations
+ * @RPMOSTREE_SYSROOT_UPGRADER_FLAGS_RESTRICT_LAYERING: Refuse local packages, and packages from
+ * repos which don't check signatures
  *
  * Flags controlling operation of an #RpmOstreeSysrootUpgrader.
  */
@@ -61,6 +76,14 @@ typedef enum
   RPMOSTREE_SYSROOT_UPGRADER_FLAGS_PKGCACHE_ONLY = (1 << 4),
   RPMOSTREE_SYSROOT_UPGRADER_FLAGS_SYNTHETIC_PULL = (1 << 5),
   RPMOSTREE_SYSROOT_UPGRADER_FLAGS_LOCK_FINALIZATION = (1 << 6),
+  RPMOSTREE_SYSROOT_UPGRADER_FLAGS_ENFORCE_CONTAINER_SIGPOLICY = (1 << 7),
+  RPMOSTREE_SYSROOT_UPGRADER_FLAGS_BYPASS_ATTESTATION = (1 << 8),
+  RPMOSTREE_SYSROOT_UPGRADER_FLAGS_INITRAMFS_REGENERATE = (1 << 9),
+  RPMOSTREE_SYSROOT_UPGRADER_FLAGS_ALTERNATIVE = (1 << 10),
+  RPMOSTREE_SYSROOT_UPGRADER_FLAGS_FROM_LOCAL_RPMS = (1 << 11),
+  RPMOSTREE_SYSROOT_UPGRADER_FLAGS_FSVERITY = (1 << 12),
+  RPMOSTREE_SYSROOT_UPGRADER_FLAGS_FORCE_POLICY_REBUILD = (1 << 13),
+  RPMOSTREE_SYSROOT_UPGRADER_FLAGS_RESTRICT_LAYERING = (1 << 14),
 } RpmOstreeSysrootUpgraderFlags;
 
 /* _NONE means we're doing pure ostree, no client-side computation.
@@ -110,6 +133,9 @@ gboolean rpmostree_sysroot_upgrader_prep_layering (
 gboolean rpmostree_sysroot_upgrader_import_pkgs (RpmOstreeSysrootUpgrader *self,
                                                  GCancellable *cancellable, GError **error);
 
+gboolean rpmostree_sysroot_upgrader_pin_pkgs (RpmOstreeSysrootUpgrader *self,
+                                              GCancellable *cancellable, GError **error);
+
 gboolean rpmostree_sysroot_upgrader_pull_repos (RpmOstreeSysrootUpgrader *self,
                                                 const char *dir_to_pull, OstreeRepoPullFlags flags,
                                                 OstreeAsyncProgress *progress,
@@ -121,4 +147,8 @@ gboolean rpmostree_sysroot_upgrader_deploy (RpmOstreeSysrootUpgrader *self,
                                             GCancellable *cancellable, GError **error);
 
 void rpmostree_sysroot_upgrader_set_kargs (RpmOstreeSysrootUpgrader *self, char **kernel_args);
+
+void rpmostree_sysroot_upgrader_set_pull_target (RpmOstreeSysrootUpgrader *self,
+                                                 const char *commit);
+
 G_END_DECLS
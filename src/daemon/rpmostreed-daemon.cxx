@@ -18,10 +18,12 @@
 
 #include "config.h"
 
+#include "rpmostree-core.h"
 #include "rpmostree-origin.h"
 #include "rpmostree-util.h"
 #include "rpmostreed-daemon.h"
 #include "rpmostreed-sysroot.h"
+#include "rpmostreed-transaction.h"
 #include "rpmostreed-types.h"
 #include "rpmostreed-utils.h"
 
@@ -75,6 +77,37 @@ struct _RpmostreedDaemon
   /* Settings from the config file */
   guint idle_exit_timeout;
   RpmostreedAutomaticUpdatePolicy auto_update_policy;
+  gboolean enforce_container_sigpolicy;
+  gboolean verify_local_packages;
+  gboolean enable_fsverity;
+  gboolean restrict_layering;
+  gint container_image_retention;
+  gint container_deployment_retention;
+  gint keep_rollback_deployments;
+  gint auto_cleanup_after_days;
+  char *container_proxy;
+  gboolean container_tls_verify;
+  guint container_pull_retries;
+  guint64 container_pull_timeout;
+  char *metrics_file;
+  gint retry_count;
+  guint64 bandwidth_limit;
+  gint64 automatic_update_bandwidth_limit;
+  gboolean update_notifications;
+  char *update_window;
+  gboolean update_window_reboot;
+  char *download_window;
+  gboolean prefetch_updates;
+  guint64 prefetch_bandwidth_limit;
+  gboolean allow_metered_downloads;
+  gboolean allow_staging_on_battery;
+  char *fleet_lock_url;
+  char *fleet_lock_group;
+  RpmOstreeAdvisorySeverity security_min_severity;
+  char *live_restart_services;
+  char *provenance_sink;
+  char *provenance_signing_key;
+  char *bootc_interop;
 
   GDBusConnection *connection;
   GDBusObjectManagerServer *object_manager;
@@ -176,6 +209,16 @@ daemon_finalize (GObject *object)
     g_source_remove (self->rerender_status_id);
 
   g_free (self->sysroot_path);
+  g_free (self->container_proxy);
+  g_free (self->metrics_file);
+  g_free (self->update_window);
+  g_free (self->download_window);
+  g_free (self->fleet_lock_url);
+  g_free (self->fleet_lock_group);
+  g_free (self->live_restart_services);
+  g_free (self->provenance_sink);
+  g_free (self->provenance_signing_key);
+  g_free (self->bootc_interop);
   G_OBJECT_CLASS (rpmostreed_daemon_parent_class)->finalize (object);
 
   _daemon_instance = NULL;
@@ -256,14 +299,33 @@ on_active_txn_changed (GObject *object, GParamSpec *pspec, gpointer user_data)
           if (!clientdata)
             clientdata = clientdata_owned = client_new (self, sender, NULL);
           g_autofree char *client_str = rpmostree_client_to_string (clientdata);
-          g_autofree char *client_data_msg = NULL;
+          g_autofree char *client_uid = NULL;
           if (clientdata->uid_valid)
-            client_data_msg = g_strdup_printf ("CLIENT_UID=%u", clientdata->uid);
-          /* TODO: convert other client fields to structured log */
-          sd_journal_send ("MESSAGE_ID=" SD_ID128_FORMAT_STR,
-                           SD_ID128_FORMAT_VAL (RPMOSTREE_MESSAGE_TRANSACTION_STARTED),
-                           "MESSAGE=Initiated txn %s for %s: %s", method, client_str, path,
-                           "BUS_ADDRESS=%s", sender, client_data_msg ?: NULL, NULL);
+            client_uid = g_strdup_printf ("%u", clientdata->uid);
+          g_autofree char *client_pid = NULL;
+          if (clientdata->pid_valid)
+            client_pid = g_strdup_printf ("%u", (guint32)clientdata->pid);
+          /* The transaction address and arguments tie this to the record logged when the
+           * transaction finishes; `rpm-ostree audit-log` joins them. */
+          auto txn = rpmostreed_sysroot_get_txn (rpmostreed_sysroot_get ());
+          const char *txn_address = "";
+          g_autofree char *txn_args = NULL;
+          if (txn)
+            {
+              GDBusMethodInvocation *invocation = rpmostreed_transaction_get_invocation (txn);
+              txn_address = rpmostreed_transaction_get_client_address (txn);
+              txn_args
+                  = g_variant_print (g_dbus_method_invocation_get_parameters (invocation), FALSE);
+            }
+          /* Like for deployments, empty fields are fine for the unknown ones */
+          sd_journal_send (
+              "MESSAGE_ID=" SD_ID128_FORMAT_STR,
+              SD_ID128_FORMAT_VAL (RPMOSTREE_MESSAGE_TRANSACTION_STARTED),
+              "MESSAGE=Initiated txn %s for %s: %s", method, client_str, path, "BUS_ADDRESS=%s",
+              sender, "CLIENT_UID=%s", client_uid ?: "", "CLIENT_PID=%s", client_pid ?: "",
+              "CLIENT_ID=%s", clientdata->id ?: "", "CLIENT_SD_UNIT=%s", clientdata->sd_unit ?: "",
+              "TRANSACTION_METHOD=%s", method, "TRANSACTION_PATH=%s", path,
+              "TRANSACTION_ADDRESS=%s", txn_address, "TRANSACTION_ARGS=%s", txn_args ?: "", NULL);
           rpmostree_client_free (clientdata_owned);
         }
     }
@@ -296,16 +358,25 @@ rpmostreed_daemon_initable_init (GInitable *initable, GCancellable *cancellable,
   if (!rpmostreed_sysroot_populate (rpmostreed_sysroot_get (), cancellable, error))
     return glnx_prefix_error (error, "Error setting up sysroot");
 
+  /* Only the system daemon keeps a history of how long operations take */
+  if (rpmostreed_sysroot_is_system_daemon (rpmostreed_sysroot_get ()))
+    rpmostreecxx::timings_enable ();
+
   g_signal_connect (rpmostreed_sysroot_get (), "notify::active-transaction",
                     G_CALLBACK (on_active_txn_changed), self);
 
-  self->bus_proxy = g_dbus_proxy_new_sync (
-      self->connection,
-      (GDBusProxyFlags)(G_DBUS_PROXY_FLAGS_DO_NOT_LOAD_PROPERTIES
-                        | G_DBUS_PROXY_FLAGS_DO_NOT_CONNECT_SIGNALS),
-      NULL, "org.freedesktop.DBus", "/org/freedesktop/DBus", "org.freedesktop.DBus", NULL, error);
-  if (!self->bus_proxy)
-    return FALSE;
+  /* There is no bus to ask about clients on a peer-to-peer connection */
+  if (!rpmostreed_daemon_is_private (self))
+    {
+      self->bus_proxy = g_dbus_proxy_new_sync (
+          self->connection,
+          (GDBusProxyFlags)(G_DBUS_PROXY_FLAGS_DO_NOT_LOAD_PROPERTIES
+                            | G_DBUS_PROXY_FLAGS_DO_NOT_CONNECT_SIGNALS),
+          NULL, "org.freedesktop.DBus", "/org/freedesktop/DBus", "org.freedesktop.DBus", NULL,
+          error);
+      if (!self->bus_proxy)
+        return FALSE;
+    }
 
   rpmostreed_daemon_publish (self, path.c_str (), FALSE, self->sysroot);
   g_dbus_connection_start_message_processing (self->connection);
@@ -366,6 +437,22 @@ get_config_uint64 (GKeyFile *keyfile, const char *key, guint64 default_val)
   return default_val;
 }
 
+static gboolean
+get_config_bool (GKeyFile *keyfile, const char *key, gboolean default_val)
+{
+  if (keyfile && g_key_file_has_key (keyfile, DAEMON_CONFIG_GROUP, key, NULL))
+    {
+      g_autoptr (GError) local_error = NULL;
+      gboolean r = g_key_file_get_boolean (keyfile, DAEMON_CONFIG_GROUP, key, &local_error);
+      if (!local_error)
+        return r;
+      if (g_error_matches (local_error, G_KEY_FILE_ERROR, G_KEY_FILE_ERROR_INVALID_VALUE))
+        sd_journal_print (LOG_WARNING, "Bad boolean for '%s': %s; using compiled defaults", key,
+                          local_error->message);
+    }
+  return default_val;
+}
+
 namespace rpmostreecxx
 {
 rust::Box<TokioEnterGuard>
@@ -373,6 +460,17 @@ rpmostreed_daemon_tokio_enter (RpmostreedDaemon *self)
 {
   return (*self->tokio_handle)->enter ();
 }
+
+ContainerPullConfig
+rpmostreed_get_container_pull_config (RpmostreedDaemon *self)
+{
+  return ContainerPullConfig{
+    .proxy = self->container_proxy ?: "",
+    .tls_verify = (bool)self->container_tls_verify,
+    .retries = self->container_pull_retries,
+    .timeout = self->container_pull_timeout,
+  };
+}
 }
 
 RpmostreedAutomaticUpdatePolicy
@@ -381,6 +479,203 @@ rpmostreed_get_automatic_update_policy (RpmostreedDaemon *self)
   return self->auto_update_policy;
 }
 
+gboolean
+rpmostreed_get_enforce_container_sigpolicy (RpmostreedDaemon *self)
+{
+  return self->enforce_container_sigpolicy;
+}
+
+gboolean
+rpmostreed_get_verify_local_packages (RpmostreedDaemon *self)
+{
+  return self->verify_local_packages;
+}
+
+gboolean
+rpmostreed_get_enable_fsverity (RpmostreedDaemon *self)
+{
+  return self->enable_fsverity;
+}
+
+/* Returns whether layering local packages and packages from repos which don't check
+ * signatures is forbidden, unless bypassed with the dedicated polkit action. */
+gboolean
+rpmostreed_get_restrict_layering (RpmostreedDaemon *self)
+{
+  return self->restrict_layering;
+}
+
+/* Returns the number of container images to keep per image repository besides
+ * the deployed ones, or -1 if none should be removed. */
+gint
+rpmostreed_get_container_image_retention (RpmostreedDaemon *self)
+{
+  return self->container_image_retention;
+}
+
+/* Returns the number of container image deployments to keep for rollback, counting
+ * the booted one, or -1 to follow the default deployment retention. */
+gint
+rpmostreed_get_container_deployment_retention (RpmostreedDaemon *self)
+{
+  return self->container_deployment_retention;
+}
+
+/* Returns the number of rollback deployments to keep, or -1 to follow the default
+ * deployment retention. */
+gint
+rpmostreed_get_keep_rollback_deployments (RpmostreedDaemon *self)
+{
+  return self->keep_rollback_deployments;
+}
+
+/* Returns the number of days after which pending and rollback deployments are removed,
+ * or -1 if they're kept regardless of their age. */
+gint
+rpmostreed_get_auto_cleanup_after_days (RpmostreedDaemon *self)
+{
+  return self->auto_cleanup_after_days;
+}
+
+/* Returns the number of times to retry failed downloads, or -1 for the defaults. */
+gint
+rpmostreed_get_retry_count (RpmostreedDaemon *self)
+{
+  return self->retry_count;
+}
+
+/* Returns the maximum download rate for packages in KiB/s, 0 meaning no limit. */
+guint64
+rpmostreed_get_bandwidth_limit (RpmostreedDaemon *self)
+{
+  return self->bandwidth_limit;
+}
+
+/* Returns the maximum download rate for packages of automatic updates in KiB/s, 0 meaning
+ * no limit, or -1 if it's the same as for other downloads. */
+gint64
+rpmostreed_get_automatic_update_bandwidth_limit (RpmostreedDaemon *self)
+{
+  return self->automatic_update_bandwidth_limit;
+}
+
+/* Returns the path of the file to write metrics to, or NULL if disabled. */
+const char *
+rpmostreed_get_metrics_file (RpmostreedDaemon *self)
+{
+  return self->metrics_file;
+}
+
+/* Returns whether to emit UpdateNotification when a reboot becomes needed. */
+gboolean
+rpmostreed_get_update_notifications (RpmostreedDaemon *self)
+{
+  return self->update_notifications;
+}
+
+/* Returns the maintenance windows for automatic updates, or NULL if unrestricted. */
+const char *
+rpmostreed_get_update_window (RpmostreedDaemon *self)
+{
+  return self->update_window;
+}
+
+/* Returns whether to reboot after staging an automatic update in a maintenance window. */
+gboolean
+rpmostreed_get_update_window_reboot (RpmostreedDaemon *self)
+{
+  return self->update_window_reboot;
+}
+
+/* Returns the windows in which automatic updates are downloaded, or NULL if unrestricted. */
+const char *
+rpmostreed_get_download_window (RpmostreedDaemon *self)
+{
+  return self->download_window;
+}
+
+/* Returns whether automatic updates are downloaded slowly in the background outside of
+ * maintenance windows. */
+gboolean
+rpmostreed_get_prefetch_updates (RpmostreedDaemon *self)
+{
+  return self->prefetch_updates;
+}
+
+/* Returns the maximum download rate when prefetching updates in KiB/s, 0 meaning no limit. */
+guint64
+rpmostreed_get_prefetch_bandwidth_limit (RpmostreedDaemon *self)
+{
+  return self->prefetch_bandwidth_limit;
+}
+
+/* Returns whether automatic updates are downloaded on metered connections. */
+gboolean
+rpmostreed_get_allow_metered_downloads (RpmostreedDaemon *self)
+{
+  return self->allow_metered_downloads;
+}
+
+/* Returns whether automatic updates are staged on battery power. */
+gboolean
+rpmostreed_get_allow_staging_on_battery (RpmostreedDaemon *self)
+{
+  return self->allow_staging_on_battery;
+}
+
+/* Returns the FleetLock server coordinating automatic reboots, or NULL if unset. */
+const char *
+rpmostreed_get_fleet_lock_url (RpmostreedDaemon *self)
+{
+  return self->fleet_lock_url;
+}
+
+/* Returns the group of the reboot semaphore on the FleetLock server. */
+const char *
+rpmostreed_get_fleet_lock_group (RpmostreedDaemon *self)
+{
+  return self->fleet_lock_group;
+}
+
+/* Returns the directory or HTTP(S) URL to which the provenance of new deployments is
+ * written, or NULL if unset. */
+const char *
+rpmostreed_get_provenance_sink (RpmostreedDaemon *self)
+{
+  return self->provenance_sink;
+}
+
+/* Returns the path to the PEM private key signing the provenance of new deployments. */
+const char *
+rpmostreed_get_provenance_signing_key (RpmostreedDaemon *self)
+{
+  return self->provenance_signing_key;
+}
+
+/* Returns the minimum severity of the security advisories an update must address to be
+ * staged by the security automatic update policy. */
+RpmOstreeAdvisorySeverity
+rpmostreed_get_security_min_severity (RpmostreedDaemon *self)
+{
+  return self->security_min_severity;
+}
+
+/* Returns the space-separated services to restart after applying updates live if they use
+ * replaced files, or NULL if unset. */
+const char *
+rpmostreed_get_live_restart_services (RpmostreedDaemon *self)
+{
+  return self->live_restart_services;
+}
+
+/* Returns how we interoperate with bootc on hosts managed by it: "coordinate", "delegate"
+ * or "off". */
+const char *
+rpmostreed_get_bootc_interop (RpmostreedDaemon *self)
+{
+  return self->bootc_interop;
+}
+
 /* in-place version of g_ascii_strdown */
 static inline void
 ascii_strdown_inplace (char *str)
@@ -411,15 +706,197 @@ rpmostreed_daemon_reload_config (RpmostreedDaemon *self, gboolean *out_changed,
         return FALSE;
     }
 
+  /* default to staging updates addressing any security advisory */
+  RpmOstreeAdvisorySeverity security_min_severity = RPM_OSTREE_ADVISORY_SEVERITY_NONE;
+  g_autofree char *severity_str = get_config_str (config, "SecurityMinSeverity", NULL);
+  if (severity_str)
+    {
+      ascii_strdown_inplace (severity_str);
+      if (g_str_equal (severity_str, "any"))
+        security_min_severity = RPM_OSTREE_ADVISORY_SEVERITY_NONE;
+      else if (g_str_equal (severity_str, "low"))
+        security_min_severity = RPM_OSTREE_ADVISORY_SEVERITY_LOW;
+      else if (g_str_equal (severity_str, "moderate"))
+        security_min_severity = RPM_OSTREE_ADVISORY_SEVERITY_MODERATE;
+      else if (g_str_equal (severity_str, "important"))
+        security_min_severity = RPM_OSTREE_ADVISORY_SEVERITY_IMPORTANT;
+      else if (g_str_equal (severity_str, "critical"))
+        security_min_severity = RPM_OSTREE_ADVISORY_SEVERITY_CRITICAL;
+      else
+        return glnx_throw (error, "Invalid SecurityMinSeverity: %s", severity_str);
+    }
+
+  /* default to keeping all pulled container images */
+  gint container_image_retention = -1;
+  g_autofree char *retention_str = get_config_str (config, "ContainerImageRetention", NULL);
+  if (retention_str)
+    {
+      ascii_strdown_inplace (retention_str);
+      if (g_str_equal (retention_str, "deployments"))
+        container_image_retention = 0;
+      else if (!g_str_equal (retention_str, "all"))
+        {
+          guint64 keep = 0;
+          if (!g_ascii_string_to_unsigned (retention_str, 10, 0, G_MAXINT, &keep, NULL))
+            return glnx_throw (error, "Invalid ContainerImageRetention: %s", retention_str);
+          container_image_retention = keep;
+        }
+    }
+
+  /* default to keeping just the booted deployment for rollback, like for other deployments */
+  gint container_deployment_retention = -1;
+  g_autofree char *deployment_retention_str
+      = get_config_str (config, "ContainerDeploymentRetention", NULL);
+  if (deployment_retention_str)
+    {
+      guint64 keep = 0;
+      if (!g_ascii_string_to_unsigned (deployment_retention_str, 10, 1, G_MAXINT, &keep, NULL))
+        return glnx_throw (error, "Invalid ContainerDeploymentRetention: %s",
+                           deployment_retention_str);
+      container_deployment_retention = keep;
+    }
+
+  g_autofree char *metrics_file = get_config_str (config, "MetricsFile", NULL);
+  if (metrics_file && !g_path_is_absolute (metrics_file))
+    return glnx_throw (error, "Invalid MetricsFile: %s: must be an absolute path", metrics_file);
+
+  g_autofree char *update_window = get_config_str (config, "UpdateWindow", NULL);
+  if (update_window)
+    CXX_TRY (rpmostreecxx::update_window_validate (update_window), error);
+  gboolean update_window_reboot = get_config_bool (config, "UpdateWindowReboot", FALSE);
+  if (update_window_reboot && !update_window)
+    return glnx_throw (error, "UpdateWindowReboot requires UpdateWindow");
+  g_autofree char *download_window = get_config_str (config, "DownloadWindow", NULL);
+  if (download_window)
+    CXX_TRY (rpmostreecxx::update_window_validate (download_window), error);
+  gboolean prefetch_updates = get_config_bool (config, "PrefetchUpdates", FALSE);
+  if (prefetch_updates && !update_window)
+    return glnx_throw (error, "PrefetchUpdates requires UpdateWindow");
+
+  g_autofree char *fleet_lock_url = get_config_str (config, "FleetLockURL", NULL);
+  g_autofree char *fleet_lock_group = get_config_str (config, "FleetLockGroup", "default");
+  if (fleet_lock_url)
+    CXX_TRY (rpmostreecxx::fleet_lock_validate (fleet_lock_url, fleet_lock_group), error);
+
+  g_autofree char *provenance_sink = get_config_str (config, "ProvenanceSink", NULL);
+  g_autofree char *provenance_signing_key = get_config_str (config, "ProvenanceSigningKey", NULL);
+  if (provenance_sink)
+    CXX_TRY (rpmostreecxx::provenance_validate (provenance_sink, provenance_signing_key ?: ""),
+             error);
+
+  g_autofree char *bootc_interop = get_config_str (config, "BootcInterop", "coordinate");
+  ascii_strdown_inplace (bootc_interop);
+  if (!(g_str_equal (bootc_interop, "coordinate") || g_str_equal (bootc_interop, "delegate")
+        || g_str_equal (bootc_interop, "off")))
+    return glnx_throw (error, "Invalid BootcInterop: %s", bootc_interop);
+
+  /* The pins themselves are read when used, so they apply without a reload; this only
+   * warns about invalid ones */
+  rpmostreecxx::repo_keys_validate ();
+
+  g_autofree char *live_restart_services = get_config_str (config, "LiveRestartServices", NULL);
+  if (live_restart_services)
+    {
+      g_auto (GStrv) services = g_strsplit (live_restart_services, " ", -1);
+      for (char **it = services; it && *it; it++)
+        {
+          if (**it && !g_str_has_suffix (*it, ".service"))
+            return glnx_throw (error, "Invalid LiveRestartServices: %s: not a service", *it);
+        }
+    }
+
+  /* libdnf doesn't allow more than 20 */
+  guint64 parallel_downloads = get_config_uint64 (config, "ParallelDownloads", 0);
+  if (parallel_downloads > 20)
+    return glnx_throw (error,
+                       "Invalid ParallelDownloads: %" G_GUINT64_FORMAT ": must be at most 20",
+                       parallel_downloads);
+  guint64 bandwidth_limit = get_config_uint64 (config, "BandwidthLimitKBps", 0);
+  guint64 import_jobs = get_config_uint64 (config, "ImportJobs", 0);
+  guint64 automatic_update_bandwidth_limit
+      = get_config_uint64 (config, "AutomaticUpdateBandwidthLimitKBps", G_MAXUINT64);
+  guint64 retry_count = get_config_uint64 (config, "RetryCount", G_MAXUINT64);
+  guint64 keep_rollback_deployments
+      = get_config_uint64 (config, "KeepRollbackDeployments", G_MAXUINT64);
+  guint64 auto_cleanup_after_days = get_config_uint64 (config, "AutoCleanupAfterDays", 0);
+
   /* don't update changed for this; it's contained to RpmostreedDaemon so no other objects
    * need to be reloaded if it changes */
   self->idle_exit_timeout = idle_exit_timeout;
+  /* same here; this is only read when starting a transaction */
+  self->enforce_container_sigpolicy = get_config_bool (config, "EnforceContainerSigpolicy", FALSE);
+  /* and this when importing local packages */
+  self->verify_local_packages = get_config_bool (config, "VerifyLocalPackages", TRUE);
+  /* and this when deploying */
+  self->enable_fsverity = get_config_bool (config, "EnableFsVerity", FALSE);
+  self->restrict_layering = get_config_bool (config, "RestrictLayering", FALSE);
+  /* and this is only read when cleaning up */
+  self->container_image_retention = container_image_retention;
+  /* and these when writing deployments */
+  self->container_deployment_retention = container_deployment_retention;
+  self->keep_rollback_deployments
+      = keep_rollback_deployments <= G_MAXINT ? (gint)keep_rollback_deployments : -1;
+  g_free (self->provenance_sink);
+  self->provenance_sink = util::move_nullify (provenance_sink);
+  g_free (self->provenance_signing_key);
+  self->provenance_signing_key = util::move_nullify (provenance_signing_key);
+  /* and this when cleaning up per the retention policy */
+  self->auto_cleanup_after_days
+      = auto_cleanup_after_days > 0 ? (gint)MIN (auto_cleanup_after_days, G_MAXINT) : -1;
+  /* and these when pulling container images */
+  g_free (self->container_proxy);
+  self->container_proxy = get_config_str (config, "ContainerProxy", NULL);
+  self->container_tls_verify = get_config_bool (config, "ContainerTlsVerify", TRUE);
+  self->container_pull_retries = get_config_uint64 (config, "ContainerPullRetries", 0);
+  self->container_pull_timeout = get_config_uint64 (config, "ContainerPullTimeout", 0);
+  /* and this after transactions and when deployments change */
+  g_free (self->metrics_file);
+  self->metrics_file = util::move_nullify (metrics_file);
+  /* and these when downloading */
+  self->retry_count = retry_count <= G_MAXINT ? (gint)retry_count : -1;
+  self->bandwidth_limit = bandwidth_limit;
+  rpmostree_set_download_config (parallel_downloads, bandwidth_limit, self->retry_count);
+  rpmostree_set_deltarpm (get_config_bool (config, "DeltaRPM", FALSE));
+  rpmostree_set_countme (get_config_bool (config, "CountMe", TRUE));
+  /* and this when importing */
+  rpmostree_set_import_jobs ((guint)MIN (import_jobs, G_MAXUINT));
+  /* and this when downloading automatic updates */
+  self->automatic_update_bandwidth_limit = automatic_update_bandwidth_limit <= G_MAXINT64
+                                               ? (gint64)automatic_update_bandwidth_limit
+                                               : -1;
+  /* and this when deployments change */
+  self->update_notifications = get_config_bool (config, "UpdateNotifications", FALSE);
+  /* and these when rebooting into automatic updates */
+  g_free (self->fleet_lock_url);
+  self->fleet_lock_url = util::move_nullify (fleet_lock_url);
+  g_free (self->fleet_lock_group);
+  self->fleet_lock_group = util::move_nullify (fleet_lock_group);
+  /* and these when triggering automatic updates */
+  self->prefetch_updates = prefetch_updates;
+  self->prefetch_bandwidth_limit = get_config_uint64 (config, "PrefetchBandwidthLimitKBps", 256);
+  self->allow_metered_downloads = get_config_bool (config, "AllowMeteredDownloads", FALSE);
+  self->allow_staging_on_battery = get_config_bool (config, "AllowStagingOnBattery", FALSE);
+  /* and this when checking automatic updates */
+  self->security_min_severity = security_min_severity;
+  /* and this when applying updates live */
+  g_free (self->live_restart_services);
+  self->live_restart_services = util::move_nullify (live_restart_services);
 
   gboolean changed = FALSE;
 
   changed = changed || (self->auto_update_policy != auto_update_policy);
+  changed = changed || (g_strcmp0 (self->update_window, update_window) != 0);
+  changed = changed || (g_strcmp0 (self->download_window, download_window) != 0);
+  changed = changed || (g_strcmp0 (self->bootc_interop, bootc_interop) != 0);
 
   self->auto_update_policy = auto_update_policy;
+  g_free (self->update_window);
+  self->update_window = util::move_nullify (update_window);
+  self->update_window_reboot = update_window_reboot;
+  g_free (self->download_window);
+  self->download_window = util::move_nullify (download_window);
+  g_free (self->bootc_interop);
+  self->bootc_interop = util::move_nullify (bootc_interop);
 
   if (out_changed)
     *out_changed = changed;
@@ -550,7 +1027,9 @@ update_status (RpmostreedDaemon *self)
         have_active_txn = TRUE;
     }
 
-  if (!getenv ("RPMOSTREE_DEBUG_DISABLE_DAEMON_IDLE_EXIT") && self->idle_exit_timeout > 0)
+  /* A private daemon exits when its client closes the connection instead */
+  if (!getenv ("RPMOSTREE_DEBUG_DISABLE_DAEMON_IDLE_EXIT") && self->idle_exit_timeout > 0
+      && !rpmostreed_daemon_is_private (self))
     currently_idle = !have_active_txn && n_clients == 0;
 
   if (currently_idle && !self->idle_exit_source)
@@ -599,6 +1078,9 @@ update_status (RpmostreedDaemon *self)
 gboolean
 rpmostreed_get_client_uid (RpmostreedDaemon *self, const char *client, uid_t *out_uid)
 {
+  if (!self->bus_proxy)
+    return FALSE;
+
   g_autoptr (GError) local_error = NULL;
   g_autoptr (GVariant) uidcall = g_dbus_proxy_call_sync (
       self->bus_proxy, "GetConnectionUnixUser", g_variant_new ("(s)", client),
@@ -617,6 +1099,9 @@ rpmostreed_get_client_uid (RpmostreedDaemon *self, const char *client, uid_t *ou
 static gboolean
 get_client_pid (RpmostreedDaemon *self, const char *client, pid_t *out_pid)
 {
+  if (!self->bus_proxy)
+    return FALSE;
+
   g_autoptr (GError) local_error = NULL;
   g_autoptr (GVariant) all = g_dbus_proxy_call_sync (
       self->bus_proxy, "GetConnectionUnixProcessID", g_variant_new ("(s)", client),
@@ -744,14 +1229,19 @@ rpmostreed_daemon_exit_now (RpmostreedDaemon *self)
 }
 
 static gboolean
-idle_initiate_reboot (void *_unused)
+idle_initiate_reboot (void *soft)
 {
-  sd_journal_print (LOG_INFO, "Initiating reboot requested from transaction");
+  sd_journal_print (LOG_INFO, "Initiating %s requested from transaction",
+                    soft ? "soft-reboot" : "reboot");
 
   /* Note that we synchronously spawn this command, but the command just queues the request and
    * returns.
    */
-  const char *child_argv[] = { "systemctl", "reboot", NULL };
+  const char *reboot_argv[] = { "systemctl", "reboot", NULL };
+  /* The new root for the soft-reboot needs to be set up outside of our mount namespace */
+  const char *soft_reboot_argv[]
+      = { "systemctl", "start", "--no-block", "rpm-ostree-soft-reboot.service", NULL };
+  const char **child_argv = soft ? soft_reboot_argv : reboot_argv;
   g_autoptr (GError) local_error = NULL;
   if (!g_spawn_sync (NULL, (char **)child_argv, NULL,
                      (GSpawnFlags)(G_SPAWN_CHILD_INHERITS_STDIN | G_SPAWN_SEARCH_PATH), NULL, NULL,
@@ -766,17 +1256,37 @@ idle_initiate_reboot (void *_unused)
   return FALSE;
 }
 
-void
-rpmostreed_daemon_reboot (RpmostreedDaemon *self)
+static void
+initiate_reboot (RpmostreedDaemon *self, gboolean soft)
 {
   g_assert (!self->rebooting);
+  /* The host isn't running the sysroot a private daemon operates on */
+  if (rpmostreed_daemon_is_private (self))
+    {
+      sd_journal_print (LOG_INFO, "Not rebooting for a transaction of a private daemon");
+      return;
+    }
   self->rebooting = TRUE;
   /* Queue actually starting the reboot until we return to the client, so
    * that they get a success message for the transaction.  Otherwise
    * if the daemon gets killed via SIGTERM they just see the bus connection
    * broken and may spuriously error out.
    */
-  g_idle_add_full (G_PRIORITY_LOW, idle_initiate_reboot, NULL, NULL);
+  g_idle_add_full (G_PRIORITY_LOW, idle_initiate_reboot, GINT_TO_POINTER (soft), NULL);
+}
+
+void
+rpmostreed_daemon_reboot (RpmostreedDaemon *self)
+{
+  initiate_reboot (self, FALSE);
+}
+
+/* Like rpmostreed_daemon_reboot(), but soft-reboot into the default deployment; it must
+ * have the same kernel, initramfs and kernel arguments as the booted one. */
+void
+rpmostreed_daemon_soft_reboot (RpmostreedDaemon *self)
+{
+  initiate_reboot (self, TRUE);
 }
 
 gboolean
@@ -792,6 +1302,14 @@ rpmostreed_daemon_connection (void)
   return _daemon_instance->connection;
 }
 
+/* Whether the daemon serves a single client on a peer-to-peer connection rather than the
+ * system bus, i.e. it was spawned by the client to operate on another sysroot */
+gboolean
+rpmostreed_daemon_is_private (RpmostreedDaemon *self)
+{
+  return g_dbus_connection_get_unique_name (self->connection) == NULL;
+}
+
 void
 rpmostreed_daemon_run_until_idle_exit (RpmostreedDaemon *self)
 {
@@ -35,12 +35,14 @@ G_BEGIN_DECLS
 /* Update driver info */
 #define RPMOSTREE_RUN_DIR "/run/rpm-ostree/"
 #define RPMOSTREE_DRIVER_STATE RPMOSTREE_RUN_DIR "update-driver.gv"
+#define RPMOSTREE_STATE_CACHE RPMOSTREE_RUN_DIR "state-cache.gv"
 #define RPMOSTREE_DRIVER_SD_UNIT "driver-sd-unit"
 #define RPMOSTREE_DRIVER_NAME "driver-name"
 
 GType rpmostreed_daemon_get_type (void) G_GNUC_CONST;
 RpmostreedDaemon *rpmostreed_daemon_get (void);
 GDBusConnection *rpmostreed_daemon_connection (void);
+gboolean rpmostreed_daemon_is_private (RpmostreedDaemon *self);
 gboolean rpmostreed_get_client_uid (RpmostreedDaemon *self, const char *client, uid_t *out_uid);
 void rpmostreed_daemon_add_client (RpmostreedDaemon *self, const char *client,
                                    const char *client_id);
@@ -50,6 +52,7 @@ char *rpmostreed_daemon_client_get_agent_id (RpmostreedDaemon *self, const char
 char *rpmostreed_daemon_client_get_sd_unit (RpmostreedDaemon *self, const char *client);
 void rpmostreed_daemon_exit_now (RpmostreedDaemon *self);
 void rpmostreed_daemon_reboot (RpmostreedDaemon *self);
+void rpmostreed_daemon_soft_reboot (RpmostreedDaemon *self);
 gboolean rpmostreed_daemon_is_rebooting (RpmostreedDaemon *self);
 void rpmostreed_daemon_run_until_idle_exit (RpmostreedDaemon *self);
 void rpmostreed_daemon_publish (RpmostreedDaemon *self, const gchar *path, gboolean uniquely,
@@ -61,10 +64,38 @@ gboolean rpmostreed_daemon_reload_config (RpmostreedDaemon *self, gboolean *out_
 gboolean rpmostreed_authorize_method_for_uid0 (GDBusMethodInvocation *invocation);
 
 RpmostreedAutomaticUpdatePolicy rpmostreed_get_automatic_update_policy (RpmostreedDaemon *self);
+gboolean rpmostreed_get_enforce_container_sigpolicy (RpmostreedDaemon *self);
+gboolean rpmostreed_get_verify_local_packages (RpmostreedDaemon *self);
+gboolean rpmostreed_get_enable_fsverity (RpmostreedDaemon *self);
+gboolean rpmostreed_get_restrict_layering (RpmostreedDaemon *self);
+gint rpmostreed_get_container_image_retention (RpmostreedDaemon *self);
+gint rpmostreed_get_container_deployment_retention (RpmostreedDaemon *self);
+gint rpmostreed_get_keep_rollback_deployments (RpmostreedDaemon *self);
+gint rpmostreed_get_auto_cleanup_after_days (RpmostreedDaemon *self);
+gint rpmostreed_get_retry_count (RpmostreedDaemon *self);
+guint64 rpmostreed_get_bandwidth_limit (RpmostreedDaemon *self);
+gint64 rpmostreed_get_automatic_update_bandwidth_limit (RpmostreedDaemon *self);
+const char *rpmostreed_get_metrics_file (RpmostreedDaemon *self);
+gboolean rpmostreed_get_update_notifications (RpmostreedDaemon *self);
+const char *rpmostreed_get_update_window (RpmostreedDaemon *self);
+gboolean rpmostreed_get_update_window_reboot (RpmostreedDaemon *self);
+const char *rpmostreed_get_download_window (RpmostreedDaemon *self);
+gboolean rpmostreed_get_prefetch_updates (RpmostreedDaemon *self);
+guint64 rpmostreed_get_prefetch_bandwidth_limit (RpmostreedDaemon *self);
+gboolean rpmostreed_get_allow_metered_downloads (RpmostreedDaemon *self);
+gboolean rpmostreed_get_allow_staging_on_battery (RpmostreedDaemon *self);
+const char *rpmostreed_get_fleet_lock_url (RpmostreedDaemon *self);
+const char *rpmostreed_get_fleet_lock_group (RpmostreedDaemon *self);
+const char *rpmostreed_get_provenance_sink (RpmostreedDaemon *self);
+const char *rpmostreed_get_provenance_signing_key (RpmostreedDaemon *self);
+RpmOstreeAdvisorySeverity rpmostreed_get_security_min_severity (RpmostreedDaemon *self);
+const char *rpmostreed_get_live_restart_services (RpmostreedDaemon *self);
+const char *rpmostreed_get_bootc_interop (RpmostreedDaemon *self);
 
 G_END_DECLS
 
 namespace rpmostreecxx
 {
 rust::Box<TokioEnterGuard> rpmostreed_daemon_tokio_enter (RpmostreedDaemon *self);
+ContainerPullConfig rpmostreed_get_container_pull_config (RpmostreedDaemon *self);
 }
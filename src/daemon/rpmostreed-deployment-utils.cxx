@@ -29,6 +29,7 @@
 #include "rpmostree-sysroot-core.h"
 #include "rpmostree-types.h"
 #include "rpmostree-util.h"
+#include "rpmostreed-daemon.h"
 #include "rpmostreed-deployment-utils.h"
 #include "rpmostreed-errors.h"
 #include "rpmostreed-utils.h"
@@ -137,6 +138,8 @@ filter_commit_meta (GVariant *commit_meta)
   g_variant_dict_remove (&dict, "rpmostree.replaced-base-packages"); /* 'base-local-replacements' */
   g_variant_dict_remove (
       &dict, "rpmostree.replaced-base-remote-packages"); /* 'base-remote-replacements' */
+  g_variant_dict_remove (&dict,
+                         "rpmostree.unsatisfied-weakdeps"); /* 'unsatisfied-weak-deps' */
   return g_variant_dict_end (&dict);
 }
 
@@ -189,6 +192,10 @@ rpmostreed_deployment_generate_variant (OstreeSysroot *sysroot, OstreeDeployment
 
       /* See below for base commit metadata */
       g_autoptr (GVariant) layered_metadata = g_variant_get_child_value (commit, 0);
+      g_autoptr (GVariant) weakdeps = g_variant_lookup_value (
+          layered_metadata, "rpmostree.unsatisfied-weakdeps", G_VARIANT_TYPE ("a(sss)"));
+      if (weakdeps)
+        g_variant_dict_insert_value (dict, "unsatisfied-weak-deps", weakdeps);
       g_variant_dict_insert (dict, "layered-commit-meta", "@a{sv}",
                              filter ? filter_commit_meta (layered_metadata) : layered_metadata);
     }
@@ -214,9 +221,7 @@ rpmostreed_deployment_generate_variant (OstreeSysroot *sysroot, OstreeDeployment
     case rpmostreecxx::RefspecType::Container:
       {
         g_variant_dict_insert (dict, "container-image-reference", "s", refspec);
-        CXX_TRY_VAR (state, rpmostreecxx::query_container_image (*repo, refspec), error);
-        g_variant_dict_insert (dict, "container-image-reference-digest", "s",
-                               state->image_digest.c_str ());
+        CXX_TRY (rpmostreecxx::container_image_populate_variant (*repo, refspec, *dict), error);
       }
       break;
     case rpmostreecxx::RefspecType::Checksum:
@@ -421,8 +426,19 @@ modified_dnfpkg_variant_new (RpmOstreePkgTypes type, RpmOstreePackage *pkg_old,
                      dnf_package_get_arch (pkg_new)));
 }
 
+static RpmOstreePkgTypes
+pkg_type (RpmOstreePkgTypes type, GHashTable *layered_nevras, RpmOstreePackage *pkg)
+{
+  if (layered_nevras && g_hash_table_contains (layered_nevras, rpm_ostree_package_get_nevra (pkg)))
+    return RPM_OSTREE_PKG_TYPE_LAYER;
+  return type;
+}
+
+/* Adds the diff between @old_checksum and @new_checksum to @diff, as pkgs of @type, or
+ * as layered pkgs for those in @layered_nevras. */
 static gboolean
 rpm_diff_add_db_diff (RpmDiff *diff, OstreeRepo *repo, RpmOstreePkgTypes type,
+                      GHashTable *layered_nevras, /* allow-none */
                       const char *old_checksum, const char *new_checksum,
                       GPtrArray **out_modified_new, GCancellable *cancellable, GError **error)
 {
@@ -443,19 +459,27 @@ rpm_diff_add_db_diff (RpmDiff *diff, OstreeRepo *repo, RpmOstreePkgTypes type,
 
   g_assert_cmpuint (modified_old->len, ==, modified_new->len);
   for (guint i = 0; i < removed->len; i++)
-    g_ptr_array_add (diff->removed, single_pkg_variant_new (
-                                        type, static_cast<RpmOstreePackage *> (removed->pdata[i])));
+    {
+      auto pkg = static_cast<RpmOstreePackage *> (removed->pdata[i]);
+      RpmOstreePkgTypes t = pkg_type (type, layered_nevras, pkg);
+      g_ptr_array_add (diff->removed, single_pkg_variant_new (t, pkg));
+    }
   for (guint i = 0; i < added->len; i++)
-    g_ptr_array_add (diff->added, single_pkg_variant_new (
-                                      type, static_cast<RpmOstreePackage *> (added->pdata[i])));
+    {
+      auto pkg = static_cast<RpmOstreePackage *> (added->pdata[i]);
+      RpmOstreePkgTypes t = pkg_type (type, layered_nevras, pkg);
+      g_ptr_array_add (diff->added, single_pkg_variant_new (t, pkg));
+    }
   for (guint i = 0; i < modified_old->len; i++)
     {
       auto old_pkg = static_cast<RpmOstreePackage *> (modified_old->pdata[i]);
       auto new_pkg = static_cast<RpmOstreePackage *> (modified_new->pdata[i]);
-      if (rpm_ostree_package_cmp (old_pkg, new_pkg) < 0)
-        g_ptr_array_add (diff->upgraded, modified_pkg_variant_new (type, old_pkg, new_pkg));
-      else
-        g_ptr_array_add (diff->downgraded, modified_pkg_variant_new (type, old_pkg, new_pkg));
+      RpmOstreePkgTypes modified_type = pkg_type (type, layered_nevras, old_pkg);
+      if (modified_type != RPM_OSTREE_PKG_TYPE_LAYER)
+        modified_type = pkg_type (type, layered_nevras, new_pkg);
+      GPtrArray *modified
+          = rpm_ostree_package_cmp (old_pkg, new_pkg) < 0 ? diff->upgraded : diff->downgraded;
+      g_ptr_array_add (modified, modified_pkg_variant_new (modified_type, old_pkg, new_pkg));
     }
 
   if (out_modified_new)
@@ -606,6 +630,66 @@ rpmmd_diff_guess (OstreeRepo *repo, const char *base_checksum, const char *layer
   return TRUE;
 }
 
+/* For all base pkgs overridden in @layered_checksum, check if there are newer versions in
+ * the rpmmd. These aren't part of the diff since local overrides don't follow the repos,
+ * but we still want to know about advisories affecting them. */
+static gboolean
+rpmmd_find_newer_overrides (OstreeRepo *repo, const char *base_checksum,
+                            const char *layered_checksum, DnfSack *sack,
+                            GPtrArray **out_newer_packages, GError **error)
+{
+  g_autoptr (GPtrArray) overridden_pkgs = NULL;
+  RpmOstreeDbDiffExtFlags flags = RPM_OSTREE_DB_DIFF_EXT_ALLOW_NOENT;
+  if (!rpm_ostree_db_diff_ext (repo, base_checksum, layered_checksum, flags, NULL, NULL, NULL,
+                               &overridden_pkgs, NULL, error))
+    return FALSE;
+
+  g_autoptr (GPtrArray) newer_packages
+      = g_ptr_array_new_with_free_func ((GDestroyNotify)g_object_unref);
+  for (guint i = 0; overridden_pkgs && i < overridden_pkgs->len; i++)
+    {
+      auto pkg = static_cast<RpmOstreePackage *> (overridden_pkgs->pdata[i]);
+      g_autoptr (DnfPackage) newer_pkg = find_package (sack, TRUE, pkg);
+      if (newer_pkg)
+        g_ptr_array_add (newer_packages, g_object_ref (newer_pkg));
+    }
+
+  /* canonicalize to NULL if there's nothing new */
+  if (newer_packages->len == 0)
+    g_clear_pointer (&newer_packages, (GDestroyNotify)g_ptr_array_unref);
+
+  *out_newer_packages = util::move_nullify (newer_packages);
+  return TRUE;
+}
+
+/* Returns the NEVRAs of the pkgs layered or overridden in @checksum on top of its base
+ * @base_checksum. */
+static gboolean
+get_layered_nevras (OstreeRepo *repo, const char *base_checksum, const char *checksum,
+                    GHashTable **out_nevras, GCancellable *cancellable, GError **error)
+{
+  g_autoptr (GPtrArray) added = NULL;
+  g_autoptr (GPtrArray) modified_new = NULL;
+  RpmOstreeDbDiffExtFlags flags = RPM_OSTREE_DB_DIFF_EXT_ALLOW_NOENT;
+  if (!rpm_ostree_db_diff_ext (repo, base_checksum, checksum, flags, NULL, &added, NULL,
+                               &modified_new, cancellable, error))
+    return FALSE;
+
+  g_autoptr (GHashTable) nevras = g_hash_table_new_full (g_str_hash, g_str_equal, g_free, NULL);
+  GPtrArray *pkgs_arrays[] = { added, modified_new };
+  for (guint i = 0; i < G_N_ELEMENTS (pkgs_arrays); i++)
+    {
+      for (guint j = 0; pkgs_arrays[i] && j < pkgs_arrays[i]->len; j++)
+        {
+          auto pkg = static_cast<RpmOstreePackage *> (pkgs_arrays[i]->pdata[j]);
+          g_hash_table_add (nevras, g_strdup (rpm_ostree_package_get_nevra (pkg)));
+        }
+    }
+
+  *out_nevras = util::move_nullify (nevras);
+  return TRUE;
+}
+
 /* try to find the exact same RpmOstreePackage pkgs in the sack */
 static GPtrArray *
 rpm_ostree_pkgs_to_dnf (DnfSack *sack, GPtrArray *rpm_ostree_pkgs)
@@ -630,18 +714,52 @@ rpm_ostree_pkgs_to_dnf (DnfSack *sack, GPtrArray *rpm_ostree_pkgs)
   return util::move_nullify (dnf_pkgs);
 }
 
+/* For container image origins, we don't pull anything to check for updates; instead we
+ * resolve the tag in the registry and compare the digest to the one we have, deriving the
+ * package diff from the image metadata. */
+static gboolean
+container_update_generate_variant (OstreeDeployment *booted_deployment, RpmOstreeOrigin *origin,
+                                   OstreeRepo *repo, const char *imgref, GVariant **out_update,
+                                   GCancellable *cancellable, GError **error)
+{
+  g_assert (cancellable);
+
+  g_autoptr (GVariantDict) dict = g_variant_dict_new (NULL);
+  g_variant_dict_insert (dict, "osname", "s", ostree_deployment_get_osname (booted_deployment));
+
+  g_autoptr (GKeyFile) origin_kf = rpmostree_origin_dup_keyfile (origin);
+  auto pull_config = rpmostreecxx::rpmostreed_get_container_pull_config (rpmostreed_daemon_get ());
+  CXX_TRY_VAR (has_update,
+               rpmostreecxx::container_update_populate_variant (*repo, *cancellable, imgref,
+                                                                *origin_kf, pull_config, *dict),
+               error);
+  if (!has_update)
+    {
+      *out_update = NULL;
+      return TRUE;
+    }
+
+  /* see the comment in rpmostreed_update_generate_variant() */
+  const char *current_checksum = ostree_deployment_get_csum (booted_deployment);
+  g_variant_dict_insert (dict, "update-sha256", "s", current_checksum);
+  *out_update = g_variant_ref_sink (g_variant_dict_end (dict));
+  return TRUE;
+}
+
 /* The variant returned by this function is backwards compatible with the one returned by
  * rpmostreed_commit_generate_cached_details_variant(). However, it also includes a base
  * tree db diff, layered pkgs diff, state, advisories, etc... Also, it will happily return
  * %NULL if no updates are available.
  *
  * If @staged_deployment is %NULL, update details are based on latest downloaded ostree
- * rpmmd metadata. If @staged_deployment is not %NULL, then the update describes the diff
- * between @booted_deployment and @staged_deployment. */
+ * rpmmd metadata, and on @target_checksum rather than the tip of the ref if it's not %NULL
+ * (e.g. as chosen from an update graph). If @staged_deployment is not %NULL, then the
+ * update describes the diff between @booted_deployment and @staged_deployment. */
 gboolean
 rpmostreed_update_generate_variant (OstreeDeployment *booted_deployment,
-                                    OstreeDeployment *staged_deployment, OstreeRepo *repo,
-                                    DnfSack *sack, /* allow-none */
+                                    OstreeDeployment *staged_deployment,
+                                    const char *target_checksum, /* allow-none */
+                                    OstreeRepo *repo, DnfSack *sack, /* allow-none */
                                     GVariant **out_update, GCancellable *cancellable,
                                     GError **error)
 {
@@ -653,6 +771,10 @@ rpmostreed_update_generate_variant (OstreeDeployment *booted_deployment,
 
   auto r = rpmostree_origin_get_refspec (origin);
 
+  if (!staged_deployment && r.kind == rpmostreecxx::RefspecType::Container)
+    return container_update_generate_variant (booted_deployment, origin, repo, r.refspec.c_str (),
+                                              out_update, cancellable, error);
+
   /* let's start with the ostree side of things */
 
   const char *current_checksum = ostree_deployment_get_csum (booted_deployment);
@@ -674,6 +796,11 @@ rpmostreed_update_generate_variant (OstreeDeployment *booted_deployment,
         return FALSE;
       new_base_checksum = new_base_checksum_owned ?: new_checksum;
     }
+  else if (target_checksum)
+    {
+      new_base_checksum = target_checksum;
+      is_new_layered = (current_base_checksum_owned != NULL);
+    }
   else
     {
       if (!ostree_repo_resolve_rev_ext (repo, r.refspec.c_str (), TRUE,
@@ -709,6 +836,14 @@ rpmostreed_update_generate_variant (OstreeDeployment *booted_deployment,
    * it easier to consume for UIs like GNOME Software and Cockpit. */
   g_variant_dict_insert (dict, "ref-has-new-commit", "b", is_new_checksum);
 
+  /* And if the update is phased and hasn't reached us yet, why it won't be staged */
+  if (!staged_deployment && is_new_checksum)
+    {
+      CXX_TRY_VAR (deferral, rpmostreecxx::rollout_deferral (*commit), error);
+      if (!deferral.empty ())
+        g_variant_dict_insert (dict, "rollout-deferred", "s", deferral.c_str ());
+    }
+
   g_auto (RpmDiff) rpm_diff = {
     0,
   };
@@ -717,14 +852,14 @@ rpmostreed_update_generate_variant (OstreeDeployment *booted_deployment,
   /* we'll need these later for advisories, so just keep them around */
   g_autoptr (GPtrArray) ostree_modified_new = NULL;
   g_autoptr (GPtrArray) rpmmd_modified_new = NULL;
+  g_autoptr (GPtrArray) rpmmd_overrides_new = NULL;
 
   if (staged_deployment)
     {
       /* ok we have a staged deployment; we just need to do a simple diff and BOOM done! */
-      /* XXX: we're marking all pkgs as BASE right now even though there could be layered
-       * pkgs too -- we can tease those out in the future if needed */
-      if (!rpm_diff_add_db_diff (&rpm_diff, repo, RPM_OSTREE_PKG_TYPE_BASE, current_checksum,
-                                 new_checksum, &ostree_modified_new, cancellable, error))
+      if (!rpm_diff_add_db_diff (&rpm_diff, repo, RPM_OSTREE_PKG_TYPE_BASE, NULL,
+                                 current_checksum, new_checksum, &ostree_modified_new,
+                                 cancellable, error))
         return FALSE;
     }
   else
@@ -735,7 +870,7 @@ rpmostreed_update_generate_variant (OstreeDeployment *booted_deployment,
        */
       if (is_new_checksum)
         {
-          if (!rpm_diff_add_db_diff (&rpm_diff, repo, RPM_OSTREE_PKG_TYPE_BASE,
+          if (!rpm_diff_add_db_diff (&rpm_diff, repo, RPM_OSTREE_PKG_TYPE_BASE, NULL,
                                      current_base_checksum, new_base_checksum, &ostree_modified_new,
                                      cancellable, error))
             return FALSE;
@@ -750,6 +885,13 @@ rpmostreed_update_generate_variant (OstreeDeployment *booted_deployment,
                                  &rpmmd_modified_new, error))
             return FALSE;
         }
+
+      if (sack && is_new_layered)
+        {
+          if (!rpmmd_find_newer_overrides (repo, current_base_checksum, current_checksum, sack,
+                                           &rpmmd_overrides_new, error))
+            return FALSE;
+        }
     }
 
   /* don't bother inserting if there's nothing new */
@@ -758,30 +900,69 @@ rpmostreed_update_generate_variant (OstreeDeployment *booted_deployment,
 
   /* now we look for advisories */
 
-  if (sack && (ostree_modified_new || rpmmd_modified_new))
+  if (sack && (ostree_modified_new || rpmmd_modified_new || rpmmd_overrides_new))
     {
-      /* let's just merge the two now for convenience */
+      /* let's just merge them now for convenience */
       g_autoptr (GPtrArray) new_packages
           = g_ptr_array_new_with_free_func ((GDestroyNotify)g_object_unref);
+      /* and keep track of those which are layered or overridden to flag their advisories */
+      g_autoptr (GPtrArray) layered_new_packages
+          = g_ptr_array_new_with_free_func ((GDestroyNotify)g_object_unref);
 
       if (ostree_modified_new)
         {
+          /* when diffing against a staged deployment, pkgs layered or overridden in it are
+           * mixed in with the base ones; tease them out */
+          g_autoptr (GHashTable) layered_nevras = NULL;
+          if (staged_deployment && !g_str_equal (new_base_checksum, new_checksum))
+            {
+              if (!get_layered_nevras (repo, new_base_checksum, new_checksum, &layered_nevras,
+                                       cancellable, error))
+                return FALSE;
+            }
+
+          g_autoptr (GPtrArray) layered_modified_new = g_ptr_array_new ();
+          for (guint i = 0; layered_nevras && i < ostree_modified_new->len; i++)
+            {
+              auto pkg = static_cast<RpmOstreePackage *> (ostree_modified_new->pdata[i]);
+              if (g_hash_table_contains (layered_nevras, rpm_ostree_package_get_nevra (pkg)))
+                g_ptr_array_add (layered_modified_new, pkg);
+            }
+
           /* recall that @ostree_modified_new is an array of RpmOstreePackage; try to find
            * the same pkg in the rpmmd so that we can search for advisories afterwards */
           g_autoptr (GPtrArray) pkgs = rpm_ostree_pkgs_to_dnf (sack, ostree_modified_new);
           for (guint i = 0; i < pkgs->len; i++)
             g_ptr_array_add (new_packages, g_object_ref (pkgs->pdata[i]));
+
+          g_autoptr (GPtrArray) layered_pkgs = rpm_ostree_pkgs_to_dnf (sack, layered_modified_new);
+          for (guint i = 0; i < layered_pkgs->len; i++)
+            g_ptr_array_add (layered_new_packages, g_object_ref (layered_pkgs->pdata[i]));
         }
 
-      if (rpmmd_modified_new)
+      GPtrArray *rpmmd_new[] = { rpmmd_modified_new, rpmmd_overrides_new };
+      for (guint i = 0; i < G_N_ELEMENTS (rpmmd_new); i++)
         {
-          for (guint i = 0; i < rpmmd_modified_new->len; i++)
-            g_ptr_array_add (new_packages, g_object_ref (rpmmd_modified_new->pdata[i]));
+          for (guint j = 0; rpmmd_new[i] && j < rpmmd_new[i]->len; j++)
+            {
+              g_ptr_array_add (new_packages, g_object_ref (rpmmd_new[i]->pdata[j]));
+              g_ptr_array_add (layered_new_packages, g_object_ref (rpmmd_new[i]->pdata[j]));
+            }
         }
 
+      /* "advisories" covers all pkgs for backwards compatibility */
       g_autoptr (GVariant) advisories = rpmostree_advisories_variant (sack, new_packages);
       if (advisories)
         g_variant_dict_insert (dict, "advisories", "@a(suuasa{sv})", advisories);
+
+      if (layered_new_packages->len > 0)
+        {
+          g_autoptr (GVariant) layered_advisories
+              = rpmostree_advisories_variant (sack, layered_new_packages);
+          if (layered_advisories)
+            g_variant_dict_insert (dict, "layered-advisories", "@a(suuasa{sv})",
+                                   layered_advisories);
+        }
     }
 
   if (staged_deployment)
@@ -804,3 +985,216 @@ rpmostreed_update_generate_variant (OstreeDeployment *booted_deployment,
 
   return TRUE;
 }
+
+/* Checks whether the base of @booted_deployment has an update with a single small fetch:
+ * the summary of the remote for ostree refs, or the manifest for container images. Unlike
+ * rpmostreed_update_generate_variant(), this doesn't look at layered packages. */
+gboolean
+rpmostreed_update_check_quick (OstreeDeployment *booted_deployment, OstreeRepo *repo,
+                               gboolean *out_available, GCancellable *cancellable, GError **error)
+{
+  GLNX_AUTO_PREFIX_ERROR ("Quick update check", error);
+
+  g_autoptr (RpmOstreeOrigin) origin = rpmostree_origin_parse_deployment (booted_deployment, error);
+  if (!origin)
+    return FALSE;
+
+  auto r = rpmostree_origin_get_refspec (origin);
+  switch (r.kind)
+    {
+    case rpmostreecxx::RefspecType::Container:
+      {
+        g_autoptr (GKeyFile) origin_kf = rpmostree_origin_dup_keyfile (origin);
+        auto pull_config
+            = rpmostreecxx::rpmostreed_get_container_pull_config (rpmostreed_daemon_get ());
+        CXX_TRY_VAR (available,
+                     rpmostreecxx::container_update_available (*repo, *cancellable,
+                                                               r.refspec.c_str (), *origin_kf,
+                                                               pull_config),
+                     error);
+        *out_available = available;
+        return TRUE;
+      }
+    case rpmostreecxx::RefspecType::Checksum:
+      /* pinned; there's nothing to update to */
+      *out_available = FALSE;
+      return TRUE;
+    case rpmostreecxx::RefspecType::Ostree:
+      break;
+    }
+
+  g_autofree char *remote = NULL;
+  g_autofree char *ref = NULL;
+  if (!ostree_parse_refspec (r.refspec.c_str (), &remote, &ref, error))
+    return FALSE;
+
+  g_autofree char *new_checksum = NULL;
+  if (remote)
+    {
+      g_autoptr (GHashTable) refs = NULL;
+      if (!ostree_repo_remote_list_refs (repo, remote, &refs, cancellable, error))
+        return FALSE;
+      new_checksum = g_strdup (static_cast<const char *> (g_hash_table_lookup (refs, ref)));
+      if (!new_checksum)
+        return glnx_throw (error, "Ref %s not found in the summary of remote %s", ref, remote);
+    }
+  else if (!ostree_repo_resolve_rev (repo, ref, FALSE, &new_checksum, error))
+    return FALSE;
+
+  g_autofree char *base_checksum = NULL;
+  if (!rpmostree_deployment_get_base_layer (repo, booted_deployment, &base_checksum, error))
+    return FALSE;
+  const char *current_checksum = base_checksum ?: ostree_deployment_get_csum (booted_deployment);
+
+  *out_available = !g_str_equal (new_checksum, current_checksum);
+  return TRUE;
+}
+
+/* Generates the diff recorded in the history for @new_deployment, described by
+ * @new_variant, relative to @from_deployment, the deployment it was created from:
+ *  - checksum: the checksum of @from_deployment
+ *  - rpm-diff: the pkg changes, in the same format as for cached updates (if any)
+ *  - requests: the changes to layered pkgs, overrides, etc... (if any)
+ */
+gboolean
+rpmostreed_deployment_generate_history_diff (OstreeSysroot *sysroot, OstreeRepo *repo,
+                                             OstreeDeployment *from_deployment,
+                                             OstreeDeployment *new_deployment,
+                                             GVariant *new_variant, GVariant **out_diff,
+                                             GCancellable *cancellable, GError **error)
+{
+  g_autoptr (GVariant) from_variant = NULL;
+  if (!rpmostreed_deployment_generate_variant (sysroot, from_deployment, NULL, repo, TRUE,
+                                               &from_variant, error))
+    return FALSE;
+
+  const char *from_checksum = ostree_deployment_get_csum (from_deployment);
+  const char *new_checksum = ostree_deployment_get_csum (new_deployment);
+  g_autofree char *from_base_checksum = NULL;
+  if (!rpmostree_deployment_get_base_layer (repo, from_deployment, &from_base_checksum, error))
+    return FALSE;
+  g_autofree char *new_base_checksum = NULL;
+  if (!rpmostree_deployment_get_base_layer (repo, new_deployment, &new_base_checksum, error))
+    return FALSE;
+
+  /* flag the pkgs layered or overridden on either side */
+  g_autoptr (GHashTable) layered_nevras
+      = g_hash_table_new_full (g_str_hash, g_str_equal, g_free, NULL);
+  const char *layers[][2] = { { from_base_checksum, from_checksum },
+                              { new_base_checksum, new_checksum } };
+  for (guint i = 0; i < G_N_ELEMENTS (layers); i++)
+    {
+      if (!layers[i][0])
+        continue;
+      g_autoptr (GHashTable) nevras = NULL;
+      if (!get_layered_nevras (repo, layers[i][0], layers[i][1], &nevras, cancellable, error))
+        return FALSE;
+      GLNX_HASH_TABLE_FOREACH (nevras, const char *, nevra)
+        g_hash_table_add (layered_nevras, g_strdup (nevra));
+    }
+
+  g_auto (RpmDiff) rpm_diff = {
+    0,
+  };
+  rpm_diff_init (&rpm_diff);
+  if (!rpm_diff_add_db_diff (&rpm_diff, repo, RPM_OSTREE_PKG_TYPE_BASE, layered_nevras,
+                             from_checksum, new_checksum, NULL, cancellable, error))
+    return FALSE;
+
+  g_autoptr (GVariantDict) dict = g_variant_dict_new (NULL);
+  g_variant_dict_insert (dict, "checksum", "s", from_checksum);
+  if (!rpm_diff_is_empty (&rpm_diff))
+    g_variant_dict_insert (dict, "rpm-diff", "@a{sv}", rpm_diff_variant_new (&rpm_diff));
+  ROSCXX_TRY (history_add_requests_diff (*from_variant, *new_variant, *dict), error);
+
+  *out_diff = g_variant_ref_sink (g_variant_dict_end (dict));
+  return TRUE;
+}
+
+/* Generates the diff of the packages from @from_deployment to @to_deployment, in the
+ * "rpm-diff" format of cached updates.  This is empty if either has no rpmdb data.
+ */
+gboolean
+rpmostreed_deployment_generate_rpm_diff (OstreeRepo *repo, OstreeDeployment *from_deployment,
+                                         OstreeDeployment *to_deployment, GVariant **out_diff,
+                                         GCancellable *cancellable, GError **error)
+{
+  g_auto (RpmDiff) rpm_diff = {
+    0,
+  };
+  rpm_diff_init (&rpm_diff);
+  if (!rpm_diff_add_db_diff (&rpm_diff, repo, RPM_OSTREE_PKG_TYPE_BASE, NULL,
+                             ostree_deployment_get_csum (from_deployment),
+                             ostree_deployment_get_csum (to_deployment), NULL, cancellable, error))
+    return FALSE;
+
+  *out_diff = g_variant_ref_sink (rpm_diff_variant_new (&rpm_diff));
+  return TRUE;
+}
+
+/* Computes for each of @deployments the size of the objects it doesn't share with any
+ * other, i.e. the space removing it frees unless something else, like a ref, still
+ * holds on to them.  The base commit of layered deployments counts as part of them.
+ * @out_sizes must have room for all deployments.
+ */
+gboolean
+rpmostreed_deployments_compute_unique_sizes (OstreeRepo *repo, GPtrArray *deployments,
+                                             guint64 *out_sizes, GCancellable *cancellable,
+                                             GError **error)
+{
+  g_autoptr (GPtrArray) reachable_sets
+      = g_ptr_array_new_with_free_func ((GDestroyNotify)g_hash_table_unref);
+  /* Number of deployments reaching each object; keys are owned by reachable_sets */
+  g_autoptr (GHashTable) counts = g_hash_table_new (ostree_hash_object_name, g_variant_equal);
+  for (guint i = 0; i < deployments->len; i++)
+    {
+      auto deployment = static_cast<OstreeDeployment *> (deployments->pdata[i]);
+      g_autoptr (GHashTable) reachable = ostree_repo_traverse_new_reachable ();
+      if (!ostree_repo_traverse_commit_union (repo, ostree_deployment_get_csum (deployment), 0,
+                                              reachable, cancellable, error))
+        return FALSE;
+
+      g_autofree char *base_checksum = NULL;
+      if (!rpmostree_deployment_get_layered_info (repo, deployment, NULL, NULL, &base_checksum,
+                                                  NULL, NULL, NULL, NULL, NULL, error))
+        return FALSE;
+      gboolean have_base = FALSE;
+      if (base_checksum
+          && !ostree_repo_has_object (repo, OSTREE_OBJECT_TYPE_COMMIT, base_checksum, &have_base,
+                                      cancellable, error))
+        return FALSE;
+      if (have_base
+          && !ostree_repo_traverse_commit_union (repo, base_checksum, 0, reachable, cancellable,
+                                                 error))
+        return FALSE;
+
+      GLNX_HASH_TABLE_FOREACH (reachable, GVariant *, object)
+        {
+          guint count = GPOINTER_TO_UINT (g_hash_table_lookup (counts, object));
+          g_hash_table_replace (counts, object, GUINT_TO_POINTER (count + 1));
+        }
+      g_ptr_array_add (reachable_sets, util::move_nullify (reachable));
+    }
+
+  for (guint i = 0; i < deployments->len; i++)
+    {
+      auto reachable = static_cast<GHashTable *> (reachable_sets->pdata[i]);
+      guint64 size = 0;
+      GLNX_HASH_TABLE_FOREACH (reachable, GVariant *, object)
+        {
+          if (GPOINTER_TO_UINT (g_hash_table_lookup (counts, object)) > 1)
+            continue;
+          const char *checksum;
+          OstreeObjectType objtype;
+          ostree_object_name_deserialize (object, &checksum, &objtype);
+          guint64 object_size = 0;
+          if (!ostree_repo_query_object_storage_size (repo, objtype, checksum, &object_size,
+                                                      cancellable, error))
+            return FALSE;
+          size += object_size;
+        }
+      out_sizes[i] = size;
+    }
+
+  return TRUE;
+}
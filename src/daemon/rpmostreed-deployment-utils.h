@@ -43,8 +43,28 @@ GVariant *rpmostreed_commit_generate_cached_details_variant (OstreeDeployment *d
                                                              const char *checksum, GError **error);
 
 gboolean rpmostreed_update_generate_variant (OstreeDeployment *booted_deployment,
-                                             OstreeDeployment *staged_deployment, OstreeRepo *repo,
+                                             OstreeDeployment *staged_deployment,
+                                             const char *target_checksum, OstreeRepo *repo,
                                              DnfSack *sack, GVariant **out_update,
                                              GCancellable *cancellable, GError **error);
 
+gboolean rpmostreed_update_check_quick (OstreeDeployment *booted_deployment, OstreeRepo *repo,
+                                        gboolean *out_available, GCancellable *cancellable,
+                                        GError **error);
+
+gboolean rpmostreed_deployment_generate_history_diff (
+    OstreeSysroot *sysroot, OstreeRepo *repo, OstreeDeployment *from_deployment,
+    OstreeDeployment *new_deployment, GVariant *new_variant, GVariant **out_diff,
+    GCancellable *cancellable, GError **error);
+
+gboolean rpmostreed_deployment_generate_rpm_diff (OstreeRepo *repo,
+                                                  OstreeDeployment *from_deployment,
+                                                  OstreeDeployment *to_deployment,
+                                                  GVariant **out_diff, GCancellable *cancellable,
+                                                  GError **error);
+
+gboolean rpmostreed_deployments_compute_unique_sizes (OstreeRepo *repo, GPtrArray *deployments,
+                                                      guint64 *out_sizes,
+                                                      GCancellable *cancellable, GError **error);
+
 G_END_DECLS
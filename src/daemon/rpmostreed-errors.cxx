@@ -27,6 +27,8 @@ static const GDBusErrorEntry dbus_error_entries[] = {
   { RPM_OSTREED_ERROR_NOT_AUTHORIZED, "org.projectatomic.rpmostreed.Error.NotAuthorized" },
   { RPM_OSTREED_ERROR_UPDATE_IN_PROGRESS, "org.projectatomic.rpmostreed.Error.UpdateInProgress" },
   { RPM_OSTREED_ERROR_INVALID_REFSPEC, "org.projectatomic.rpmostreed.Error.InvalidRefspec" },
+  { RPM_OSTREED_ERROR_SYSTEM_BUSY, "org.projectatomic.rpmostreed.Error.SystemBusy" },
+  { RPM_OSTREED_ERROR_BACKING_OFF, "org.projectatomic.rpmostreed.Error.BackingOff" },
 };
 
 GQuark
@@ -31,6 +31,8 @@ typedef enum
   RPM_OSTREED_ERROR_NOT_AUTHORIZED,
   RPM_OSTREED_ERROR_UPDATE_IN_PROGRESS,
   RPM_OSTREED_ERROR_INVALID_REFSPEC,
+  RPM_OSTREED_ERROR_SYSTEM_BUSY,
+  RPM_OSTREED_ERROR_BACKING_OFF,
   RPM_OSTREED_ERROR_NUM_ENTRIES,
 } RpmOstreedError;
 
@@ -76,6 +76,40 @@ sysroot_changed (RpmostreedSysroot *sysroot, gpointer user_data)
     g_warning ("%s", local_error->message);
 }
 
+/* Checking for updates is its own action, so that it can be allowed without also allowing
+ * to deploy them. */
+static const char *
+automatic_update_trigger_action (GVariant *parameters)
+{
+  g_autoptr (GVariant) options = g_variant_get_child_value (parameters, 0);
+  g_auto (GVariantDict) dict;
+  g_variant_dict_init (&dict, options);
+  auto mode = static_cast<const char *> (vardict_lookup_ptr (&dict, "mode", "&s") ?: "auto");
+
+  RpmostreedAutomaticUpdatePolicy autoupdate_policy;
+  if (g_str_equal (mode, "auto"))
+    autoupdate_policy = rpmostreed_get_automatic_update_policy (rpmostreed_daemon_get ());
+  else if (!rpmostree_str_to_auto_update_policy (mode, &autoupdate_policy, NULL))
+    autoupdate_policy = RPMOSTREED_AUTOMATIC_UPDATE_POLICY_STAGE;
+
+  if (autoupdate_policy == RPMOSTREED_AUTOMATIC_UPDATE_POLICY_STAGE
+      || autoupdate_policy == RPMOSTREED_AUTOMATIC_UPDATE_POLICY_APPLY_LIVE
+      || autoupdate_policy == RPMOSTREED_AUTOMATIC_UPDATE_POLICY_LAYERED
+      || autoupdate_policy == RPMOSTREED_AUTOMATIC_UPDATE_POLICY_SECURITY)
+    return "org.projectatomic.rpmostree1.upgrade";
+  return "org.projectatomic.rpmostree1.upgrade-check";
+}
+
+/* Rebasing to a container image has its own action, so that it can be allowed separately
+ * from rebasing to an arbitrary ostree ref (and vice versa). */
+static const char *
+rebase_action (const char *refspec)
+{
+  if (rpmostreecxx::refspec_classify (refspec) == rpmostreecxx::RefspecType::Container)
+    return "org.projectatomic.rpmostree1.rebase-container";
+  return "org.projectatomic.rpmostree1.rebase";
+}
+
 static gboolean
 os_authorize_method (GDBusInterfaceSkeleton *interface, GDBusMethodInvocation *invocation)
 {
@@ -93,19 +127,28 @@ os_authorize_method (GDBusInterfaceSkeleton *interface, GDBusMethodInvocation *i
       return TRUE;
     }
 
+  if (rpmostreed_daemon_is_private (rpmostreed_daemon_get ()))
+    {
+      /* A private daemon only serves the process which spawned it, with its privileges */
+      return TRUE;
+    }
+
   if (g_strcmp0 (method_name, "GetDeploymentsRpmDiff") == 0
       || g_strcmp0 (method_name, "GetCachedDeployRpmDiff") == 0
       || g_strcmp0 (method_name, "DownloadDeployRpmDiff") == 0
       || g_strcmp0 (method_name, "GetCachedUpdateRpmDiff") == 0
+      || g_strcmp0 (method_name, "GetCachedUpdateDiff") == 0
       || g_strcmp0 (method_name, "DownloadUpdateRpmDiff") == 0
       || g_strcmp0 (method_name, "GetCachedRebaseRpmDiff") == 0
       || g_strcmp0 (method_name, "DownloadRebaseRpmDiff") == 0
-      || g_strcmp0 (method_name, "RefreshMd") == 0)
+      || g_strcmp0 (method_name, "RefreshMd") == 0
+      || g_strcmp0 (method_name, "ListRevisions") == 0)
 
     {
       g_ptr_array_add (actions, (void *)"org.projectatomic.rpmostree1.repo-refresh");
     }
-  else if (g_strcmp0 (method_name, "ModifyYumRepo") == 0)
+  else if (g_strcmp0 (method_name, "ModifyYumRepo") == 0
+           || g_strcmp0 (method_name, "AddImageAuth") == 0)
     {
       g_ptr_array_add (actions, (void *)"org.projectatomic.rpmostree1.repo-modify");
     }
@@ -113,17 +156,22 @@ os_authorize_method (GDBusInterfaceSkeleton *interface, GDBusMethodInvocation *i
     {
       g_ptr_array_add (actions, (void *)"org.projectatomic.rpmostree1.deploy");
     }
-  /* unite these for now; it could make sense at least to make "check" its own action */
-  else if (g_strcmp0 (method_name, "Upgrade") == 0
-           || g_strcmp0 (method_name, "AutomaticUpdateTrigger") == 0)
+  else if (g_strcmp0 (method_name, "Upgrade") == 0)
     {
       g_ptr_array_add (actions, (void *)"org.projectatomic.rpmostree1.upgrade");
     }
+  else if (g_strcmp0 (method_name, "AutomaticUpdateTrigger") == 0)
+    {
+      g_ptr_array_add (actions, (void *)automatic_update_trigger_action (parameters));
+    }
   else if (g_strcmp0 (method_name, "Rebase") == 0)
     {
-      g_ptr_array_add (actions, (void *)"org.projectatomic.rpmostree1.rebase");
+      const char *refspec = NULL;
+      g_variant_get_child (parameters, 1, "&s", &refspec);
+      g_ptr_array_add (actions, (void *)rebase_action (refspec));
     }
-  else if (g_strcmp0 (method_name, "GetDeploymentBootConfig") == 0)
+  else if (g_strcmp0 (method_name, "GetDeploymentBootConfig") == 0
+           || g_strcmp0 (method_name, "GetDeploymentOrigin") == 0)
     {
       /* Note: early return here because no need authentication
        * for this method
@@ -131,15 +179,22 @@ os_authorize_method (GDBusInterfaceSkeleton *interface, GDBusMethodInvocation *i
       return TRUE;
     }
   else if (g_strcmp0 (method_name, "SetInitramfsState") == 0
-           || g_strcmp0 (method_name, "KernelArgs") == 0
            || g_strcmp0 (method_name, "InitramfsEtc") == 0)
     {
       g_ptr_array_add (actions, (void *)"org.projectatomic.rpmostree1.bootconfig");
     }
+  else if (g_strcmp0 (method_name, "KernelArgs") == 0)
+    {
+      g_ptr_array_add (actions, (void *)"org.projectatomic.rpmostree1.kargs");
+    }
   else if (g_strcmp0 (method_name, "Cleanup") == 0)
     {
       g_ptr_array_add (actions, (void *)"org.projectatomic.rpmostree1.cleanup");
     }
+  else if (g_strcmp0 (method_name, "PinDeployment") == 0)
+    {
+      g_ptr_array_add (actions, (void *)"org.projectatomic.rpmostree1.pin");
+    }
   else if (g_strcmp0 (method_name, "Rollback") == 0
            || g_strcmp0 (method_name, "ClearRollbackTarget") == 0)
     {
@@ -169,6 +224,8 @@ os_authorize_method (GDBusInterfaceSkeleton *interface, GDBusMethodInvocation *i
       g_autofree char **install_modules = vardict_lookup_strv (&modifiers_dict, "install-modules");
       g_autofree char **uninstall_modules
           = vardict_lookup_strv (&modifiers_dict, "uninstall-modules");
+      g_autofree char **reset_modules = vardict_lookup_strv (&modifiers_dict, "reset-modules");
+      g_autofree char **switch_modules = vardict_lookup_strv (&modifiers_dict, "switch-modules");
       g_autofree const char *const *override_replace_pkgs
           = vardict_lookup_strv (&modifiers_dict, "override-replace-packages");
       g_autofree const char *const *override_remove_pkgs
@@ -189,7 +246,7 @@ os_authorize_method (GDBusInterfaceSkeleton *interface, GDBusMethodInvocation *i
         g_ptr_array_add (actions, (void *)"org.projectatomic.rpmostree1.bootconfig");
 
       if (refspec != NULL)
-        g_ptr_array_add (actions, (void *)"org.projectatomic.rpmostree1.rebase");
+        g_ptr_array_add (actions, (void *)rebase_action (refspec));
       else if (revision != NULL)
         g_ptr_array_add (actions, (void *)"org.projectatomic.rpmostree1.deploy");
       else if (!no_pull_base)
@@ -197,7 +254,7 @@ os_authorize_method (GDBusInterfaceSkeleton *interface, GDBusMethodInvocation *i
 
       if (install_pkgs != NULL || uninstall_pkgs != NULL || enable_modules != NULL
           || disable_modules != NULL || install_modules != NULL || uninstall_modules != NULL
-          || no_layering)
+          || reset_modules != NULL || switch_modules != NULL || no_layering)
         g_ptr_array_add (actions,
                          (void *)"org.projectatomic.rpmostree1.install-uninstall-packages");
 
@@ -206,12 +263,39 @@ os_authorize_method (GDBusInterfaceSkeleton *interface, GDBusMethodInvocation *i
               && g_variant_n_children (install_local_fileoverride_pkgs) > 0))
         g_ptr_array_add (actions, (void *)"org.projectatomic.rpmostree1.install-local-packages");
 
-      if (override_replace_pkgs != NULL || override_remove_pkgs != NULL
-          || override_reset_pkgs != NULL
+      if (override_replace_pkgs != NULL
           || (override_replace_local_pkgs != NULL
-              && g_variant_n_children (override_replace_local_pkgs) > 0)
-          || no_overrides)
+              && g_variant_n_children (override_replace_local_pkgs) > 0))
+        g_ptr_array_add (actions, (void *)"org.projectatomic.rpmostree1.override-replace");
+      if (override_remove_pkgs != NULL || override_reset_pkgs != NULL || no_overrides)
         g_ptr_array_add (actions, (void *)"org.projectatomic.rpmostree1.override");
+      if (vardict_lookup_bool (&options_dict, "allow-protected", FALSE))
+        g_ptr_array_add (actions, (void *)"org.projectatomic.rpmostree1.override-protected");
+      if (vardict_lookup_bool (&options_dict, "allow-unverified-local", FALSE))
+        g_ptr_array_add (actions,
+                         (void *)"org.projectatomic.rpmostree1.install-unverified-local-packages");
+      if (vardict_lookup_bool (&options_dict, "bypass-layering-policy", FALSE))
+        g_ptr_array_add (actions, (void *)"org.projectatomic.rpmostree1.bypass-layering-policy");
+      if (vardict_lookup_bool (&options_dict, "bypass-attestation", FALSE))
+        g_ptr_array_add (actions, (void *)"org.projectatomic.rpmostree1.bypass-attestation");
+      /* Enabling FIPS mode layers packages, and changes the initramfs and kernel arguments */
+      if (vardict_lookup_bool (&modifiers_dict, "enable-fips", FALSE))
+        {
+          g_ptr_array_add (actions,
+                           (void *)"org.projectatomic.rpmostree1.install-uninstall-packages");
+          g_ptr_array_add (actions, (void *)"org.projectatomic.rpmostree1.bootconfig");
+          g_ptr_array_add (actions, (void *)"org.projectatomic.rpmostree1.kargs");
+        }
+      /* A desired state may change any of the packages, overrides, initramfs and kernel
+       * arguments */
+      if (vardict_lookup_ptr (&modifiers_dict, "ex-apply-state", "&s") != NULL)
+        {
+          g_ptr_array_add (actions,
+                           (void *)"org.projectatomic.rpmostree1.install-uninstall-packages");
+          g_ptr_array_add (actions, (void *)"org.projectatomic.rpmostree1.override");
+          g_ptr_array_add (actions, (void *)"org.projectatomic.rpmostree1.bootconfig");
+          g_ptr_array_add (actions, (void *)"org.projectatomic.rpmostree1.kargs");
+        }
       /* If we couldn't figure out what's going on, count it as an override.  This occurs
        * right now with `deploy --ex-cliwrap=true`.
        */
@@ -388,9 +472,27 @@ os_handle_get_deployments_rpm_diff (RPMOSTreeOS *interface, GDBusMethodInvocatio
   return TRUE;
 }
 
+static gboolean
+os_handle_get_deployment_origin (RPMOSTreeOS *interface, GDBusMethodInvocation *invocation,
+                                 const char *arg_deployid)
+{
+  GError *local_error = NULL;
+  OstreeSysroot *ot_sysroot = rpmostreed_sysroot_get_root (rpmostreed_sysroot_get ());
+
+  rust::Str deploy_id (arg_deployid ?: "");
+  auto origin = ROSCXX_VAL (deployment_origin_json (*ot_sysroot, deploy_id), &local_error);
+  if (!origin)
+    return os_throw_dbus_invocation_error (invocation, &local_error);
+
+  rpmostree_os_complete_get_deployment_origin (interface, invocation, origin->c_str ());
+  return TRUE;
+}
+
+/* @out_file_diff is optional, since computing it requires reading all the files of both
+ * package databases. */
 static gboolean
 get_cached_update_rpm_diff (const gchar *name, const char *arg_deployid, GVariant **out_value,
-                            GVariant **out_details, GError **error)
+                            GVariant **out_details, GVariant **out_file_diff, GError **error)
 {
   RpmostreedSysroot *global_sysroot;
   g_autoptr (RpmOstreeOrigin) origin = NULL;
@@ -425,6 +527,13 @@ get_cached_update_rpm_diff (const gchar *name, const char *arg_deployid, GVarian
   if (!details)
     return FALSE;
 
+  if (out_file_diff)
+    {
+      if (!rpm_ostree_db_file_diff_variant (ot_repo, ostree_deployment_get_csum (base_deployment),
+                                            r.refspec.c_str (), out_file_diff, cancellable, error))
+        return FALSE;
+    }
+
   *out_value = util::move_nullify (value);
   *out_details = util::move_nullify (details);
   return TRUE;
@@ -440,7 +549,8 @@ os_handle_get_cached_update_rpm_diff (RPMOSTreeOS *interface, GDBusMethodInvocat
 
   const gchar *name = rpmostree_os_get_name (interface);
 
-  gboolean is_ok = get_cached_update_rpm_diff (name, arg_deployid, &value, &details, &local_error);
+  gboolean is_ok
+      = get_cached_update_rpm_diff (name, arg_deployid, &value, &details, NULL, &local_error);
   if (!is_ok)
     return os_throw_dbus_invocation_error (invocation, &local_error);
 
@@ -451,6 +561,31 @@ os_handle_get_cached_update_rpm_diff (RPMOSTreeOS *interface, GDBusMethodInvocat
   return TRUE;
 }
 
+static gboolean
+os_handle_get_cached_update_diff (RPMOSTreeOS *interface, GDBusMethodInvocation *invocation,
+                                  const char *arg_deployid)
+{
+  GError *local_error = NULL;
+  g_autoptr (GVariant) value = NULL;
+  g_autoptr (GVariant) details = NULL;
+  g_autoptr (GVariant) file_diff = NULL;
+
+  const gchar *name = rpmostree_os_get_name (interface);
+
+  if (!get_cached_update_rpm_diff (name, arg_deployid, &value, &details, &file_diff,
+                                   &local_error))
+    return os_throw_dbus_invocation_error (invocation, &local_error);
+
+  g_auto (GVariantDict) dict;
+  g_variant_dict_init (&dict, NULL);
+  g_variant_dict_insert_value (&dict, "packages", value);
+  g_variant_dict_insert_value (&dict, "files", file_diff);
+  g_variant_dict_insert_value (&dict, "details", details);
+  rpmostree_os_complete_get_cached_update_diff (interface, invocation,
+                                                g_variant_dict_end (&dict));
+  return TRUE;
+}
+
 static gboolean refresh_cached_update (RpmostreedOS *, GError **error);
 
 static void
@@ -669,6 +804,54 @@ os_handle_update_deployment (RPMOSTreeOS *interface, GDBusMethodInvocation *invo
       modifiers, fd_list, rpmostree_os_complete_update_deployment);
 }
 
+/* With the "when-idle" option, non-interactive operations are refused while the system is
+ * busy, rather than competing with its workload; the client retries later. We check before
+ * starting the transaction, so that waiting doesn't block interactive operations. */
+static gboolean
+check_when_idle (GVariantDict *dict, GError **error)
+{
+  if (!vardict_lookup_bool (dict, "when-idle", FALSE))
+    return TRUE;
+  CXX_TRY_VAR (reason, rpmostreecxx::system_busy_reason (), error);
+  if (!reason.empty ())
+    {
+      g_set_error (error, RPM_OSTREED_ERROR, RPM_OSTREED_ERROR_SYSTEM_BUSY, "System is busy: %s",
+                   reason.c_str ());
+      return FALSE;
+    }
+  return TRUE;
+}
+
+/* After failures of the automatic update policy, it backs off exponentially; refuse to start
+ * it until the backoff is over. */
+static gboolean
+check_autoupdate_backoff (GError **error)
+{
+  CXX_TRY_VAR (reason, rpmostreecxx::autoupdate_backoff_reason (), error);
+  if (!reason.empty ())
+    {
+      g_set_error (error, RPM_OSTREED_ERROR, RPM_OSTREED_ERROR_BACKING_OFF, "%s",
+                   reason.c_str ());
+      return FALSE;
+    }
+  return TRUE;
+}
+
+/* Whether @windows, as in UpdateWindow or DownloadWindow, are open now; always the case if
+ * unset. */
+static gboolean
+window_is_open (const char *windows, gboolean *out_open, GError **error)
+{
+  if (!windows)
+    {
+      *out_open = TRUE;
+      return TRUE;
+    }
+  CXX_TRY_VAR (open, rpmostreecxx::update_window_is_open (windows), error);
+  *out_open = open;
+  return TRUE;
+}
+
 /* compat shim for call completer */
 static void
 automatic_update_trigger_completer (RPMOSTreeOS *os, GDBusMethodInvocation *invocation,
@@ -677,6 +860,187 @@ automatic_update_trigger_completer (RPMOSTreeOS *os, GDBusMethodInvocation *invo
   rpmostree_os_complete_automatic_update_trigger (os, invocation, TRUE, address);
 }
 
+#define NM_NAME "org.freedesktop.NetworkManager"
+#define NM_PATH "/org/freedesktop/NetworkManager"
+#define UPOWER_NAME "org.freedesktop.UPower"
+#define UPOWER_PATH "/org/freedesktop/UPower"
+/* These services answer quickly, if at all */
+#define UPDATE_CONDITION_TIMEOUT_MS 5000
+
+/* An AutomaticUpdateTrigger call, while we find out about the conditions of the system */
+typedef struct
+{
+  RPMOSTreeOS *interface;
+  GDBusMethodInvocation *invocation;
+  GVariant *options;
+  RpmOstreeTransactionDeployFlags dfault;
+  gboolean reboot;
+  gboolean cache_only;
+  guint n_pending; /* Outstanding queries */
+  gboolean metered;
+  gboolean on_battery;
+  GError *error;
+} AutoUpdateTrigger;
+
+static void
+auto_update_trigger_free (AutoUpdateTrigger *trigger)
+{
+  g_object_unref (trigger->interface);
+  g_object_unref (trigger->invocation);
+  g_variant_unref (trigger->options);
+  g_clear_error (&trigger->error);
+  g_free (trigger);
+}
+
+/* Finish a Properties.Get call; @out_value is set to %NULL if the service isn't running. */
+static gboolean
+get_property_finish (GObject *src, GAsyncResult *res, const GVariantType *type,
+                     GVariant **out_value, GError **error)
+{
+  g_autoptr (GError) local_error = NULL;
+  g_autoptr (GVariant) reply
+      = g_dbus_connection_call_finish (G_DBUS_CONNECTION (src), res, &local_error);
+  if (!reply)
+    {
+      if (g_error_matches (local_error, G_DBUS_ERROR, G_DBUS_ERROR_SERVICE_UNKNOWN)
+          || g_error_matches (local_error, G_DBUS_ERROR, G_DBUS_ERROR_NAME_HAS_NO_OWNER))
+        {
+          *out_value = NULL;
+          return TRUE;
+        }
+      g_propagate_error (error, util::move_nullify (local_error));
+      return FALSE;
+    }
+  g_autoptr (GVariant) value = NULL;
+  g_variant_get (reply, "(v)", &value);
+  if (!g_variant_is_of_type (value, type))
+    return glnx_throw (error, "Invalid property type %s", g_variant_get_type_string (value));
+  *out_value = util::move_nullify (value);
+  return TRUE;
+}
+
+static void automatic_update_trigger_start (AutoUpdateTrigger *trigger);
+
+static void
+auto_update_trigger_query_done (AutoUpdateTrigger *trigger)
+{
+  g_assert_cmpuint (trigger->n_pending, >, 0);
+  if (--trigger->n_pending == 0)
+    automatic_update_trigger_start (trigger);
+}
+
+static void
+on_metered_reply (GObject *src, GAsyncResult *res, gpointer user_data)
+{
+  auto trigger = static_cast<AutoUpdateTrigger *> (user_data);
+  g_autoptr (GVariant) value = NULL;
+  g_autoptr (GError) local_error = NULL;
+  if (!get_property_finish (src, res, G_VARIANT_TYPE_UINT32, &value, &local_error))
+    {
+      g_prefix_error (&local_error, "Querying %s: ", NM_NAME);
+      if (!trigger->error)
+        trigger->error = util::move_nullify (local_error);
+    }
+  else if (value)
+    trigger->metered = rpmostreecxx::nm_metered (g_variant_get_uint32 (value));
+  auto_update_trigger_query_done (trigger);
+}
+
+static void
+on_on_battery_reply (GObject *src, GAsyncResult *res, gpointer user_data)
+{
+  auto trigger = static_cast<AutoUpdateTrigger *> (user_data);
+  g_autoptr (GVariant) value = NULL;
+  g_autoptr (GError) local_error = NULL;
+  if (!get_property_finish (src, res, G_VARIANT_TYPE_BOOLEAN, &value, &local_error))
+    {
+      g_prefix_error (&local_error, "Querying %s: ", UPOWER_NAME);
+      if (!trigger->error)
+        trigger->error = util::move_nullify (local_error);
+    }
+  else if (value)
+    trigger->on_battery = g_variant_get_boolean (value);
+  auto_update_trigger_query_done (trigger);
+}
+
+/* Restrict @dfault to only checking for updates, keeping the flags which say what to check
+ * for, e.g. only security fixes. */
+static RpmOstreeTransactionDeployFlags
+deploy_flags_check_only (RpmOstreeTransactionDeployFlags dfault)
+{
+  const auto deploying = RPMOSTREE_TRANSACTION_DEPLOY_FLAG_DOWNLOAD_ONLY
+                         | RPMOSTREE_TRANSACTION_DEPLOY_FLAG_PREFETCH
+                         | RPMOSTREE_TRANSACTION_DEPLOY_FLAG_APPLY_LIVE_IF_SAFE
+                         | RPMOSTREE_TRANSACTION_DEPLOY_FLAG_AUTOMATIC_REBOOT;
+  return static_cast<RpmOstreeTransactionDeployFlags> (
+      (dfault & ~deploying) | RPMOSTREE_TRANSACTION_DEPLOY_FLAG_DOWNLOAD_METADATA_ONLY);
+}
+
+/* Now that we know the conditions of the system, start the automatic update transaction,
+ * restricted accordingly. Consumes @trigger. */
+static void
+automatic_update_trigger_start (AutoUpdateTrigger *trigger)
+{
+  RPMOSTreeOS *interface = trigger->interface;
+  GDBusMethodInvocation *invocation = trigger->invocation;
+  if (trigger->error)
+    {
+      g_dbus_method_invocation_return_gerror (invocation, trigger->error);
+      auto_update_trigger_free (trigger);
+      return;
+    }
+
+  RpmOstreeTransactionDeployFlags dfault = trigger->dfault;
+  gboolean reboot = trigger->reboot;
+  gboolean cache_only = trigger->cache_only;
+  auto restriction = rpmostreecxx::update_restriction (
+      trigger->metered, trigger->on_battery, cache_only,
+      (dfault & RPMOSTREE_TRANSACTION_DEPLOY_FLAG_DOWNLOAD_METADATA_ONLY) > 0,
+      (dfault & RPMOSTREE_TRANSACTION_DEPLOY_FLAG_DOWNLOAD_ONLY) > 0);
+  switch (restriction)
+    {
+    case rpmostreecxx::UpdateRestriction::None:
+      break;
+    case rpmostreecxx::UpdateRestriction::CheckOnly:
+      sd_journal_print (LOG_INFO, "%s; only checking for updates",
+                        trigger->metered ? "On a metered connection" : "On battery power");
+      dfault = deploy_flags_check_only (dfault);
+      reboot = FALSE;
+      cache_only = FALSE;
+      break;
+    case rpmostreecxx::UpdateRestriction::DownloadOnly:
+      sd_journal_print (LOG_INFO, "On battery power; only downloading updates");
+      dfault = static_cast<RpmOstreeTransactionDeployFlags> (
+          (dfault & ~RPMOSTREE_TRANSACTION_DEPLOY_FLAG_AUTOMATIC_REBOOT)
+          | RPMOSTREE_TRANSACTION_DEPLOY_FLAG_DOWNLOAD_ONLY);
+      reboot = FALSE;
+      break;
+    default:
+      g_assert_not_reached ();
+    }
+
+  /* if output-to-self is not explicitly set, default to TRUE */
+  g_auto (GVariantDict) dict;
+  g_variant_dict_init (&dict, trigger->options);
+  if (!g_variant_dict_contains (&dict, "output-to-self"))
+    g_variant_dict_insert (&dict, "output-to-self", "b", TRUE);
+  if (reboot)
+    g_variant_dict_insert (&dict, "reboot", "b", TRUE);
+  if (cache_only)
+    g_variant_dict_insert (&dict, "cache-only", "b", TRUE);
+  g_autoptr (GVariant) options = g_variant_ref_sink (g_variant_dict_end (&dict));
+
+  /* Unlike manual upgrades, automatic ones wait for phased rollouts to reach us, and may have
+   * their own bandwidth limit */
+  dfault = static_cast<RpmOstreeTransactionDeployFlags> (
+      dfault | RPMOSTREE_TRANSACTION_DEPLOY_FLAG_PHASED_ROLLOUT
+      | RPMOSTREE_TRANSACTION_DEPLOY_FLAG_AUTOMATIC_BANDWIDTH_LIMIT);
+
+  (void)os_merge_or_start_deployment_txn (interface, invocation, dfault, options, NULL, NULL,
+                                          automatic_update_trigger_completer);
+  auto_update_trigger_free (trigger);
+}
+
 /* we make this a separate method to keep the D-Bus API clean, but the actual
  * implementation is done by our dear friend deploy_transaction_execute(). ❤️
  */
@@ -737,21 +1101,149 @@ os_handle_automatic_update_trigger (RPMOSTreeOS *interface, GDBusMethodInvocatio
       break;
     case RPMOSTREED_AUTOMATIC_UPDATE_POLICY_STAGE:
       break;
+    case RPMOSTREED_AUTOMATIC_UPDATE_POLICY_APPLY_LIVE:
+      dfault = RPMOSTREE_TRANSACTION_DEPLOY_FLAG_APPLY_LIVE_IF_SAFE;
+      break;
+    case RPMOSTREED_AUTOMATIC_UPDATE_POLICY_LAYERED:
+      /* Stay on the current base, only picking up updates of layered packages */
+      dfault = static_cast<RpmOstreeTransactionDeployFlags> (
+          RPMOSTREE_TRANSACTION_DEPLOY_FLAG_NO_PULL_BASE
+          | RPMOSTREE_TRANSACTION_DEPLOY_FLAG_REFRESH_LAYERED);
+      break;
+    case RPMOSTREED_AUTOMATIC_UPDATE_POLICY_SECURITY:
+      dfault = RPMOSTREE_TRANSACTION_DEPLOY_FLAG_SECURITY_ONLY;
+      break;
     default:
       g_assert_not_reached ();
     }
 
-  /* if output-to-self is not explicitly set, default to TRUE */
-  g_autoptr (GVariant) arg_options_owned = NULL;
-  if (!g_variant_dict_contains (&dict, "output-to-self"))
+  /* Outside of maintenance windows, the policies which deploy updates only check for
+   * updates, unless they're in a download window, where they download them to be deployed
+   * later. Outside of download windows, they only deploy updates which were already
+   * downloaded. This only applies to the configured policy; an explicit mode is a manual
+   * request. */
+  gboolean reboot = FALSE;
+  gboolean cache_only = FALSE;
+  if (g_str_equal (mode, "auto") && autoupdate_policy != RPMOSTREED_AUTOMATIC_UPDATE_POLICY_CHECK)
     {
-      g_variant_dict_insert (&dict, "output-to-self", "b", TRUE);
-      arg_options = arg_options_owned = g_variant_ref_sink (g_variant_dict_end (&dict));
+      RpmostreedDaemon *daemon = rpmostreed_daemon_get ();
+      const char *update_window = rpmostreed_get_update_window (daemon);
+      const char *download_window = rpmostreed_get_download_window (daemon);
+      gboolean update_window_open = TRUE;
+      gboolean download_window_open = TRUE;
+      if (!window_is_open (update_window, &update_window_open, error)
+          || !window_is_open (download_window, &download_window_open, error))
+        {
+          g_dbus_method_invocation_take_error (invocation, util::move_nullify (local_error));
+          return TRUE;
+        }
+      if (!update_window_open && download_window && download_window_open)
+        {
+          sd_journal_print (LOG_INFO, "Outside of UpdateWindow %s; only downloading updates",
+                            update_window);
+          dfault = static_cast<RpmOstreeTransactionDeployFlags> (
+              dfault | RPMOSTREE_TRANSACTION_DEPLOY_FLAG_DOWNLOAD_ONLY);
+        }
+      else if (!update_window_open && !download_window
+               && rpmostreed_get_prefetch_updates (daemon))
+        {
+          sd_journal_print (LOG_INFO, "Outside of UpdateWindow %s; prefetching updates",
+                            update_window);
+          dfault = static_cast<RpmOstreeTransactionDeployFlags> (
+              dfault | RPMOSTREE_TRANSACTION_DEPLOY_FLAG_DOWNLOAD_ONLY
+              | RPMOSTREE_TRANSACTION_DEPLOY_FLAG_PREFETCH);
+        }
+      else if (!update_window_open)
+        {
+          sd_journal_print (LOG_INFO, "Outside of UpdateWindow %s; only checking for updates",
+                            update_window);
+          dfault = deploy_flags_check_only (dfault);
+        }
+      else
+        {
+          if (!download_window_open)
+            {
+              sd_journal_print (LOG_INFO,
+                                "Outside of DownloadWindow %s; only deploying downloaded updates",
+                                download_window);
+              cache_only = TRUE;
+            }
+          reboot = rpmostreed_get_update_window_reboot (daemon);
+          /* Coordinated through FleetLock if configured */
+          if (reboot)
+            dfault = static_cast<RpmOstreeTransactionDeployFlags> (
+                dfault | RPMOSTREE_TRANSACTION_DEPLOY_FLAG_AUTOMATIC_REBOOT);
+        }
     }
-  (void)arg_options_owned; /* Pacify static analysis */
 
-  return os_merge_or_start_deployment_txn (interface, invocation, dfault, arg_options, NULL, NULL,
-                                           automatic_update_trigger_completer);
+  if (vardict_lookup_bool (&dict, "quick", FALSE))
+    {
+      if (autoupdate_policy != RPMOSTREED_AUTOMATIC_UPDATE_POLICY_CHECK)
+        {
+          glnx_throw (error, "Quick update checks require the check mode");
+          g_dbus_method_invocation_take_error (invocation, util::move_nullify (local_error));
+          return TRUE;
+        }
+      dfault = static_cast<RpmOstreeTransactionDeployFlags> (
+          dfault | RPMOSTREE_TRANSACTION_DEPLOY_FLAG_QUICK_CHECK);
+    }
+
+  if (!check_when_idle (&dict, error))
+    {
+      g_dbus_method_invocation_take_error (invocation, util::move_nullify (local_error));
+      return TRUE;
+    }
+
+  /* Failures of the configured policy are tracked to back off; an explicit mode is a manual
+   * request. */
+  if (g_str_equal (mode, "auto"))
+    {
+      if (!check_autoupdate_backoff (error))
+        {
+          g_dbus_method_invocation_take_error (invocation, util::move_nullify (local_error));
+          return TRUE;
+        }
+      dfault = static_cast<RpmOstreeTransactionDeployFlags> (
+          dfault | RPMOSTREE_TRANSACTION_DEPLOY_FLAG_AUTOMATIC_POLICY);
+    }
+
+  auto trigger = g_new0 (AutoUpdateTrigger, 1);
+  trigger->interface = RPMOSTREE_OS (g_object_ref (interface));
+  trigger->invocation = G_DBUS_METHOD_INVOCATION (g_object_ref (invocation));
+  trigger->options = g_variant_ref_sink (g_variant_dict_end (&dict));
+  trigger->dfault = dfault;
+  trigger->reboot = reboot;
+  trigger->cache_only = cache_only;
+
+  /* Similarly, on metered connections, we only check for updates, and on battery power, we
+   * only download them. Only the system daemon can ask NetworkManager and UPower, which we
+   * do asynchronously, so as not to block the main loop on them. */
+  if (g_str_equal (mode, "auto") && autoupdate_policy != RPMOSTREED_AUTOMATIC_UPDATE_POLICY_CHECK
+      && rpmostreed_sysroot_is_system_daemon (rpmostreed_sysroot_get ()))
+    {
+      RpmostreedDaemon *daemon = rpmostreed_daemon_get ();
+      GDBusConnection *bus = rpmostreed_daemon_connection ();
+      if (!rpmostreed_get_allow_metered_downloads (daemon))
+        {
+          trigger->n_pending++;
+          g_dbus_connection_call (bus, NM_NAME, NM_PATH, "org.freedesktop.DBus.Properties", "Get",
+                                  g_variant_new ("(ss)", NM_NAME, "Metered"),
+                                  G_VARIANT_TYPE ("(v)"), G_DBUS_CALL_FLAGS_NO_AUTO_START,
+                                  UPDATE_CONDITION_TIMEOUT_MS, NULL, on_metered_reply, trigger);
+        }
+      if (!rpmostreed_get_allow_staging_on_battery (daemon))
+        {
+          trigger->n_pending++;
+          g_dbus_connection_call (bus, UPOWER_NAME, UPOWER_PATH, "org.freedesktop.DBus.Properties",
+                                  "Get", g_variant_new ("(ss)", UPOWER_NAME, "OnBattery"),
+                                  G_VARIANT_TYPE ("(v)"), G_DBUS_CALL_FLAGS_NO_AUTO_START,
+                                  UPDATE_CONDITION_TIMEOUT_MS, NULL, on_on_battery_reply, trigger);
+        }
+    }
+  if (trigger->n_pending == 0)
+    automatic_update_trigger_start (trigger);
+
+  return TRUE;
 }
 
 static gboolean
@@ -762,6 +1254,7 @@ os_handle_rollback (RPMOSTreeOS *interface, GDBusMethodInvocation *invocation,
   g_autoptr (GCancellable) cancellable = g_cancellable_new ();
   const char *osname;
   gboolean opt_reboot = FALSE;
+  const char *opt_target = NULL;
   GVariantDict options_dict;
   GError *local_error = NULL;
 
@@ -781,12 +1274,12 @@ os_handle_rollback (RPMOSTreeOS *interface, GDBusMethodInvocation *invocation,
       g_variant_dict_init (&options_dict, arg_options);
 
       g_variant_dict_lookup (&options_dict, "reboot", "b", &opt_reboot);
+      g_variant_dict_lookup (&options_dict, "to", "&s", &opt_target);
 
+      transaction = rpmostreed_transaction_new_rollback (invocation, ot_sysroot, osname, opt_target,
+                                                         opt_reboot, cancellable, &local_error);
       g_variant_dict_clear (&options_dict);
 
-      transaction = rpmostreed_transaction_new_rollback (invocation, ot_sysroot, osname, opt_reboot,
-                                                         cancellable, &local_error);
-
       if (transaction == NULL)
         return os_throw_dbus_invocation_error (invocation, &local_error);
 
@@ -826,6 +1319,9 @@ os_handle_refresh_md (RPMOSTreeOS *interface, GDBusMethodInvocation *invocation,
                                           &local_error))
         return os_throw_dbus_invocation_error (invocation, &local_error);
 
+      if (!check_when_idle (&dict, &local_error))
+        return os_throw_dbus_invocation_error (invocation, &local_error);
+
       osname = rpmostree_os_get_name (interface);
 
       if (vardict_lookup_bool (&dict, "force", FALSE))
@@ -852,6 +1348,52 @@ os_handle_refresh_md (RPMOSTreeOS *interface, GDBusMethodInvocation *invocation,
   return TRUE;
 }
 
+static gboolean
+os_handle_list_revisions (RPMOSTreeOS *interface, GDBusMethodInvocation *invocation,
+                          GVariant *arg_options)
+{
+  g_autoptr (OstreeSysroot) ot_sysroot = NULL;
+  g_autoptr (GCancellable) cancellable = g_cancellable_new ();
+  GError *local_error = NULL;
+  g_auto (GVariantDict) dict;
+  g_variant_dict_init (&dict, arg_options);
+
+  glnx_unref_object RpmostreedTransaction *transaction = NULL;
+  RpmostreedSysroot *rsysroot = rpmostreed_sysroot_get ();
+  if (!rpmostreed_sysroot_prep_for_txn (rsysroot, invocation, &transaction, &local_error))
+    return os_throw_dbus_invocation_error (invocation, &local_error);
+  if (transaction == NULL)
+    {
+      if (!rpmostreed_sysroot_load_state (rsysroot, cancellable, &ot_sysroot, NULL, &local_error))
+        return os_throw_dbus_invocation_error (invocation, &local_error);
+
+      guint page = 1;
+      g_variant_dict_lookup (&dict, "page", "u", &page);
+      if (page == 0)
+        {
+          glnx_throw (&local_error, "Pages are numbered from 1");
+          return os_throw_dbus_invocation_error (invocation, &local_error);
+        }
+
+      const char *refspec = NULL;
+      g_variant_dict_lookup (&dict, "refspec", "&s", &refspec);
+
+      const char *osname = rpmostree_os_get_name (interface);
+      transaction = rpmostreed_transaction_new_list_revisions (
+          invocation, ot_sysroot, osname, page, refspec, cancellable, &local_error);
+      if (transaction == NULL)
+        return os_throw_dbus_invocation_error (invocation, &local_error);
+
+      rpmostreed_sysroot_set_txn_and_title (rsysroot, transaction, "list-revisions");
+    }
+  g_assert (transaction != NULL);
+
+  const char *client_address = rpmostreed_transaction_get_client_address (transaction);
+  rpmostree_os_complete_list_revisions (interface, invocation, client_address);
+
+  return TRUE;
+}
+
 static gboolean
 os_handle_modify_yum_repo (RPMOSTreeOS *interface, GDBusMethodInvocation *invocation,
                            const char *arg_repo_id, GVariant *arg_settings)
@@ -889,6 +1431,35 @@ os_handle_modify_yum_repo (RPMOSTreeOS *interface, GDBusMethodInvocation *invoca
   return TRUE;
 }
 
+static gboolean
+add_image_auth (const char *registry, GVariant *options, GError **error)
+{
+  g_auto (GVariantDict) options_dict;
+  g_variant_dict_init (&options_dict, options);
+  const char *username = NULL;
+  const char *password = NULL;
+  if (!g_variant_dict_lookup (&options_dict, "username", "&s", &username)
+      || !g_variant_dict_lookup (&options_dict, "password", "&s", &password))
+    return glnx_throw (error, "Both username and password must be provided");
+  gboolean runtime = vardict_lookup_bool (&options_dict, "runtime", FALSE);
+
+  CXX_TRY (rpmostreecxx::image_auth_add (registry, username, password, runtime), error);
+  sd_journal_print (LOG_INFO, "Stored credentials for registry %s (user %s)", registry, username);
+  return TRUE;
+}
+
+static gboolean
+os_handle_add_image_auth (RPMOSTreeOS *interface, GDBusMethodInvocation *invocation,
+                          const char *arg_registry, GVariant *arg_options)
+{
+  GError *local_error = NULL;
+  if (!add_image_auth (arg_registry, arg_options, &local_error))
+    return os_throw_dbus_invocation_error (invocation, &local_error);
+
+  rpmostree_os_complete_add_image_auth (interface, invocation);
+  return TRUE;
+}
+
 static gboolean
 os_handle_finalize_deployment (RPMOSTreeOS *interface, GDBusMethodInvocation *invocation,
                                GVariant *arg_options)
@@ -1175,6 +1746,18 @@ os_handle_get_deployment_boot_config (RPMOSTreeOS *interface, GDBusMethodInvocat
 
       g_variant_dict_insert (&boot_config_dict, key, "s", value);
     }
+
+  /* And which layer each kernel argument comes from */
+  g_autoptr (RpmOstreeOrigin) origin
+      = rpmostree_origin_parse_deployment (target_deployment, &local_error);
+  if (!origin)
+    return os_throw_dbus_invocation_error (invocation, &local_error);
+  g_autoptr (GVariant) layers = NULL;
+  if (!rpmostree_origin_get_kargs_layers (
+          origin, ostree_sysroot_repo (ot_sysroot), ostree_deployment_get_csum (target_deployment),
+          ostree_bootconfig_parser_get (bootconfig, "options") ?: "", &layers, &local_error))
+    return os_throw_dbus_invocation_error (invocation, &local_error);
+  g_variant_dict_insert_value (&boot_config_dict, "options-layers", layers);
   boot_config_result = g_variant_dict_end (&boot_config_dict);
 
   g_dbus_method_invocation_return_value (invocation,
@@ -1218,6 +1801,12 @@ os_handle_cleanup (RPMOSTreeOS *interface, GDBusMethodInvocation *invocation,
             flags |= RPMOSTREE_TRANSACTION_CLEANUP_ROLLBACK_DEPLOY;
           else if (strcmp (v, "repomd") == 0)
             flags |= RPMOSTREE_TRANSACTION_CLEANUP_REPOMD;
+          else if (strcmp (v, "container-images") == 0)
+            flags |= RPMOSTREE_TRANSACTION_CLEANUP_CONTAINER_IMAGES;
+          else if (strcmp (v, "retention") == 0)
+            flags |= RPMOSTREE_TRANSACTION_CLEANUP_RETENTION;
+          else if (strcmp (v, "testdeploy") == 0)
+            flags |= RPMOSTREE_TRANSACTION_CLEANUP_TESTDEPLOY;
           else
             {
               g_set_error (&local_error, G_IO_ERROR, G_IO_ERROR_FAILED, "Invalid cleanup type: %s",
@@ -1242,6 +1831,40 @@ os_handle_cleanup (RPMOSTreeOS *interface, GDBusMethodInvocation *invocation,
   return TRUE;
 }
 
+static gboolean
+os_handle_pin_deployment (RPMOSTreeOS *interface, GDBusMethodInvocation *invocation,
+                          const char *arg_deployment, GVariant *arg_options)
+{
+  glnx_unref_object OstreeSysroot *ot_sysroot = NULL;
+  g_autoptr (GCancellable) cancellable = g_cancellable_new ();
+  GError *local_error = NULL;
+
+  /* try to merge with an existing transaction, otherwise start a new one */
+  glnx_unref_object RpmostreedTransaction *transaction = NULL;
+  RpmostreedSysroot *rsysroot = rpmostreed_sysroot_get ();
+  if (!rpmostreed_sysroot_prep_for_txn (rsysroot, invocation, &transaction, &local_error))
+    return os_throw_dbus_invocation_error (invocation, &local_error);
+  if (transaction == NULL)
+    {
+      if (!rpmostreed_sysroot_load_state (rpmostreed_sysroot_get (), cancellable, &ot_sysroot, NULL,
+                                          &local_error))
+        return os_throw_dbus_invocation_error (invocation, &local_error);
+
+      transaction = rpmostreed_transaction_new_pin (invocation, ot_sysroot, arg_deployment,
+                                                    arg_options, cancellable, &local_error);
+      if (transaction == NULL)
+        return os_throw_dbus_invocation_error (invocation, &local_error);
+
+      rpmostreed_sysroot_set_txn_and_title (rsysroot, transaction, "pin");
+    }
+  g_assert (transaction != NULL);
+
+  const char *client_address = rpmostreed_transaction_get_client_address (transaction);
+  rpmostree_os_complete_pin_deployment (interface, invocation, client_address);
+
+  return TRUE;
+}
+
 static gboolean
 os_handle_get_cached_rebase_rpm_diff (RPMOSTreeOS *interface, GDBusMethodInvocation *invocation,
                                       const char *arg_refspec, const char *const *arg_packages)
@@ -1533,21 +2156,17 @@ rpmostreed_os_load_internals (RpmostreedOS *self, GError **error)
   const gchar *name = rpmostree_os_get_name (RPMOSTREE_OS (self));
   g_debug ("loading %s", name);
 
-  OstreeSysroot *ot_sysroot = rpmostreed_sysroot_get_root (rpmostreed_sysroot_get ());
-  OstreeRepo *ot_repo = rpmostreed_sysroot_get_repo (rpmostreed_sysroot_get ());
+  RpmostreedSysroot *sysroot = rpmostreed_sysroot_get ();
+  OstreeSysroot *ot_sysroot = rpmostreed_sysroot_get_root (sysroot);
 
   /* Booted */
-  g_autofree gchar *booted_id = NULL;
   OstreeDeployment *booted_deployment = ostree_sysroot_get_booted_deployment (ot_sysroot);
   g_autoptr (GVariant) booted_variant = NULL; /* Strong ref as we reuse it below */
   if (booted_deployment && g_strcmp0 (ostree_deployment_get_osname (booted_deployment), name) == 0)
     {
-      if (!rpmostreed_deployment_generate_variant (ot_sysroot, booted_deployment, booted_id,
-                                                   ot_repo, TRUE, &booted_variant, error))
+      if (!rpmostreed_sysroot_get_deployment_variant (sysroot, booted_deployment, &booted_variant,
+                                                      error))
         return FALSE;
-      g_variant_ref_sink (booted_variant);
-      auto bootedid_v = rpmostreecxx::deployment_generate_id (*booted_deployment);
-      booted_id = g_strdup (bootedid_v.c_str ());
     }
   else
     booted_variant = g_variant_ref_sink (rpmostreed_deployment_generate_blank_variant ());
@@ -1562,24 +2181,23 @@ rpmostreed_os_load_internals (RpmostreedOS *self, GError **error)
   g_autoptr (GVariant) default_variant = NULL;
   if (pending_deployment)
     {
-      if (!rpmostreed_deployment_generate_variant (ot_sysroot, pending_deployment, booted_id,
-                                                   ot_repo, TRUE, &default_variant, error))
+      if (!rpmostreed_sysroot_get_deployment_variant (sysroot, pending_deployment,
+                                                      &default_variant, error))
         return FALSE;
-      g_variant_ref_sink (default_variant);
     }
   else
     default_variant = g_variant_ref (booted_variant); /* Default to booted */
   rpmostree_os_set_default_deployment (RPMOSTREE_OS (self), default_variant);
 
-  GVariant *rollback_variant = NULL; /* Floating */
+  g_autoptr (GVariant) rollback_variant = NULL;
   if (rollback_deployment)
     {
-      if (!rpmostreed_deployment_generate_variant (ot_sysroot, rollback_deployment, booted_id,
-                                                   ot_repo, TRUE, &rollback_variant, error))
+      if (!rpmostreed_sysroot_get_deployment_variant (sysroot, rollback_deployment,
+                                                      &rollback_variant, error))
         return FALSE;
     }
   else
-    rollback_variant = rpmostreed_deployment_generate_blank_variant ();
+    rollback_variant = g_variant_ref_sink (rpmostreed_deployment_generate_blank_variant ());
   rpmostree_os_set_rollback_deployment (RPMOSTREE_OS (self), rollback_variant);
 
   if (!refresh_cached_update (self, error))
@@ -1595,10 +2213,13 @@ rpmostreed_os_iface_init (RPMOSTreeOSIface *iface)
 {
   iface->handle_automatic_update_trigger = os_handle_automatic_update_trigger;
   iface->handle_cleanup = os_handle_cleanup;
+  iface->handle_pin_deployment = os_handle_pin_deployment;
   iface->handle_get_deployment_boot_config = os_handle_get_deployment_boot_config;
   iface->handle_kernel_args = os_handle_kernel_args;
   iface->handle_refresh_md = os_handle_refresh_md;
+  iface->handle_list_revisions = os_handle_list_revisions;
   iface->handle_modify_yum_repo = os_handle_modify_yum_repo;
+  iface->handle_add_image_auth = os_handle_add_image_auth;
   iface->handle_rollback = os_handle_rollback;
   iface->handle_initramfs_etc = os_handle_initramfs_etc;
   iface->handle_set_initramfs_state = os_handle_set_initramfs_state;
@@ -1613,7 +2234,9 @@ rpmostreed_os_iface_init (RPMOSTreeOSIface *iface)
   iface->handle_pkg_change = os_handle_pkg_change;
   /* cache API; used by Cockpit at least */
   iface->handle_get_deployments_rpm_diff = os_handle_get_deployments_rpm_diff;
+  iface->handle_get_deployment_origin = os_handle_get_deployment_origin;
   iface->handle_get_cached_update_rpm_diff = os_handle_get_cached_update_rpm_diff;
+  iface->handle_get_cached_update_diff = os_handle_get_cached_update_diff;
   iface->handle_get_cached_rebase_rpm_diff = os_handle_get_cached_rebase_rpm_diff;
   iface->handle_get_cached_deploy_rpm_diff = os_handle_get_cached_deploy_rpm_diff;
   iface->handle_download_update_rpm_diff = os_handle_download_update_rpm_diff;
@@ -77,6 +77,20 @@ struct _RpmostreedSysroot
   GHashTable *os_interfaces;
   GHashTable *osexperimental_interfaces;
 
+  /* Deployment variants by ID, valid as long as state_stamp is */
+  GHashTable *deployment_variants;
+  char *state_stamp;
+  gboolean state_cache_dirty;
+  /* Sizes of the objects unique to each deployment (a{st} by ID), computed on request
+   * and valid as long as the set of deployments is the one in unique_sizes_key */
+  GVariant *unique_sizes;
+  char *unique_sizes_key;
+  /* Package diffs from the booted deployment (a{sv} by ID), computed by the
+   * transactions which create the deployments; see rpmostreed_sysroot_add_rpm_diff() */
+  GHashTable *rpm_diffs;
+  /* Why a reboot is needed, empty if it isn't; NULL until deployments are loaded */
+  char *reboot_reason;
+
   GFileMonitor *monitor;
   guint sig_changed;
 };
@@ -115,6 +129,7 @@ sysroot_output_cb (RpmOstreeOutputType type, void *data, void *opaque)
   static char *progress_str;
   static bool progress_state_percent;
   static guint progress_state_n_items;
+  static gint64 progress_start_time;
 
   if (self->transaction)
     g_object_get (self->transaction, "output-to-self", &output_to_self, NULL);
@@ -137,6 +152,7 @@ sysroot_output_cb (RpmOstreeOutputType type, void *data, void *opaque)
         g_clear_pointer (&progress_str, g_free);
         progress_state_percent = false;
         progress_state_n_items = 0;
+        progress_start_time = g_get_monotonic_time ();
         if (begin->percent)
           {
             progress_str = g_strdup (begin->prefix);
@@ -154,6 +170,9 @@ sysroot_output_cb (RpmOstreeOutputType type, void *data, void *opaque)
           {
             rpmostree_transaction_emit_task_begin (transaction, begin->prefix);
           }
+        rpmostreed_transaction_emit_progress (transaction, rpmostree_output_get_stage (),
+                                              begin->prefix, 0, begin->n, 0, 0,
+                                              begin->percent ? 0 : -1, 0);
       }
       break;
     case RPMOSTREE_OUTPUT_PROGRESS_UPDATE:
@@ -176,6 +195,15 @@ sysroot_output_cb (RpmOstreeOutputType type, void *data, void *opaque)
           {
             rpmostree_transaction_emit_percent_progress (transaction, progress_str, update->c);
           }
+        guint64 elapsed_secs = (g_get_monotonic_time () - progress_start_time) / G_USEC_PER_SEC;
+        if (progress_state_n_items)
+          rpmostreed_transaction_emit_progress (transaction, rpmostree_output_get_stage (),
+                                                progress_str, update->c, progress_state_n_items, 0,
+                                                0, -1, elapsed_secs);
+        else
+          rpmostreed_transaction_emit_progress (transaction, rpmostree_output_get_stage (),
+                                                progress_str, 0, 0, 0, 0, update->c,
+                                                elapsed_secs);
       }
       break;
     case RPMOSTREE_OUTPUT_PROGRESS_SUB_MESSAGE:
@@ -205,8 +233,16 @@ handle_get_os (RPMOSTreeSysroot *object, GDBusMethodInvocation *invocation, cons
 
   if (arg_name[0] == '\0')
     {
-      rpmostree_sysroot_complete_get_os (object, invocation, rpmostree_sysroot_dup_booted (object));
-      return FALSE;
+      /* When operating on a sysroot we're not booted into, e.g. a disk image being
+       * provisioned by a private daemon, default to the OS of its default deployment */
+      g_autoptr (GPtrArray) deployments = ostree_sysroot_get_deployments (self->ot_sysroot);
+      if (ostree_sysroot_get_booted_deployment (self->ot_sysroot) != NULL || deployments->len == 0)
+        {
+          rpmostree_sysroot_complete_get_os (object, invocation,
+                                             rpmostree_sysroot_dup_booted (object));
+          return FALSE;
+        }
+      arg_name = ostree_deployment_get_osname ((OstreeDeployment *)deployments->pdata[0]);
     }
 
   g_autoptr (GDBusInterfaceSkeleton) os_interface
@@ -228,6 +264,441 @@ handle_get_os (RPMOSTreeSysroot *object, GDBusMethodInvocation *invocation, cons
   return TRUE;
 }
 
+/* Bump this when the format of the state cache changes */
+#define STATE_CACHE_VERSION 1
+
+/* Generating the variants of deployments is expensive (it loads commits,
+ * origins and container image metadata), so we keep them around, and persist
+ * them in RPMOSTREE_STATE_CACHE so that a daemon started again after exiting
+ * on idle doesn't have to redo it.  The stamp identifies the state they were
+ * generated from, using the mtimes ostree bumps whenever deployments or refs
+ * change.
+ */
+static char *
+sysroot_generate_state_stamp (RpmostreedSysroot *self, GError **error)
+{
+  int sysroot_fd = ostree_sysroot_get_fd (self->ot_sysroot);
+  struct stat deploy_stbuf;
+  if (!glnx_fstatat (sysroot_fd, "ostree/deploy", &deploy_stbuf, 0, error))
+    return NULL;
+  struct stat config_stbuf;
+  if (!glnx_fstatat (ostree_repo_get_dfd (self->repo), "config", &config_stbuf, 0, error))
+    return NULL;
+  struct stat remotes_stbuf = {
+    0,
+  };
+  if (!glnx_fstatat_allow_noent (sysroot_fd, "etc/ostree/remotes.d", &remotes_stbuf, 0, error))
+    return NULL;
+
+  g_autofree char *booted_id = NULL;
+  OstreeDeployment *booted = ostree_sysroot_get_booted_deployment (self->ot_sysroot);
+  if (booted)
+    {
+      auto id = rpmostreecxx::deployment_generate_id (*booted);
+      booted_id = g_strdup (id.c_str ());
+    }
+
+  const struct stat *stamps[]
+      = { &deploy_stbuf, &self->repo_last_stat, &config_stbuf, &remotes_stbuf };
+  g_autoptr (GString) stamp = g_string_new (NULL);
+  g_string_append_printf (stamp, "%u;%s;%s", STATE_CACHE_VERSION, PACKAGE_VERSION,
+                          booted_id ?: "");
+  for (guint i = 0; i < G_N_ELEMENTS (stamps); i++)
+    g_string_append_printf (stamp, ";%" G_GINT64_FORMAT ".%ld", (gint64)stamps[i]->st_mtim.tv_sec,
+                            stamps[i]->st_mtim.tv_nsec);
+  return g_string_free (util::move_nullify (stamp), FALSE);
+}
+
+/* Load the deployment variants from RPMOSTREE_STATE_CACHE, if it is still valid. */
+static gboolean
+sysroot_load_state_cache (RpmostreedSysroot *self, GError **error)
+{
+  glnx_autofd int fd = -1;
+  g_autoptr (GError) local_error = NULL;
+  if (!glnx_openat_rdonly (AT_FDCWD, RPMOSTREE_STATE_CACHE, TRUE, &fd, &local_error))
+    {
+      if (!g_error_matches (local_error, G_IO_ERROR, G_IO_ERROR_NOT_FOUND))
+        return g_propagate_error (error, util::move_nullify (local_error)), FALSE;
+      return TRUE; /* Note early return */
+    }
+
+  struct stat stbuf;
+  if (!glnx_fstat (fd, &stbuf, error))
+    return FALSE;
+  if (!rpmostree_check_size_within_limit (stbuf.st_size, OSTREE_MAX_METADATA_SIZE,
+                                          RPMOSTREE_STATE_CACHE, error))
+    return FALSE;
+
+  g_autoptr (GBytes) data = glnx_fd_readall_bytes (fd, NULL, error);
+  if (!data)
+    return FALSE;
+  g_autoptr (GVariant) cache
+      = g_variant_ref_sink (g_variant_new_from_bytes (G_VARIANT_TYPE_VARDICT, data, FALSE));
+  if (!g_variant_is_normal_form (cache))
+    {
+      sd_journal_print (LOG_INFO, "Ignoring invalid %s", RPMOSTREE_STATE_CACHE);
+      return TRUE;
+    }
+
+  g_auto (GVariantDict) dict;
+  g_variant_dict_init (&dict, cache);
+
+  /* These don't depend on the stamp, but only on the set of deployments */
+  g_autoptr (GVariant) unique_sizes
+      = g_variant_dict_lookup_value (&dict, "unique-sizes", G_VARIANT_TYPE ("a{st}"));
+  const char *unique_sizes_key = NULL;
+  if (unique_sizes && g_variant_dict_lookup (&dict, "unique-sizes-key", "&s", &unique_sizes_key))
+    {
+      g_clear_pointer (&self->unique_sizes, g_variant_unref);
+      self->unique_sizes = util::move_nullify (unique_sizes);
+      g_free (self->unique_sizes_key);
+      self->unique_sizes_key = g_strdup (unique_sizes_key);
+    }
+
+  /* The cache is in /run, so the booted deployment these are relative to is the same */
+  g_autoptr (GVariant) rpm_diffs
+      = g_variant_dict_lookup_value (&dict, "rpm-diffs", G_VARIANT_TYPE_VARDICT);
+  if (rpm_diffs)
+    {
+      GVariantIter rpm_diffs_iter;
+      g_variant_iter_init (&rpm_diffs_iter, rpm_diffs);
+      const char *id = NULL;
+      GVariant *rpm_diff = NULL;
+      while (g_variant_iter_next (&rpm_diffs_iter, "{&sv}", &id, &rpm_diff))
+        g_hash_table_replace (self->rpm_diffs, g_strdup (id), rpm_diff);
+    }
+
+  const char *stamp = NULL;
+  g_variant_dict_lookup (&dict, "stamp", "&s", &stamp);
+  g_autoptr (GVariant) deployments
+      = g_variant_dict_lookup_value (&dict, "deployments", G_VARIANT_TYPE ("aa{sv}"));
+  if (g_strcmp0 (stamp, self->state_stamp) != 0 || !deployments)
+    return TRUE; /* Note early return; we'll overwrite it */
+
+  GVariantIter iter;
+  g_variant_iter_init (&iter, deployments);
+  while (true)
+    {
+      GVariant *variant = g_variant_iter_next_value (&iter);
+      if (!variant)
+        break;
+      g_auto (GVariantDict) deployment_dict;
+      g_variant_dict_init (&deployment_dict, variant);
+      const char *id = NULL;
+      if (g_variant_dict_lookup (&deployment_dict, "id", "&s", &id))
+        g_hash_table_replace (self->deployment_variants, g_strdup (id), variant);
+      else
+        g_variant_unref (variant);
+    }
+  g_debug ("loaded %u deployments from %s", g_hash_table_size (self->deployment_variants),
+           RPMOSTREE_STATE_CACHE);
+  return TRUE;
+}
+
+static gboolean
+sysroot_write_state_cache (RpmostreedSysroot *self, GError **error)
+{
+  if (!glnx_shutil_mkdir_p_at (AT_FDCWD, RPMOSTREE_RUN_DIR, 0755, NULL, error))
+    return FALSE;
+
+  GVariantBuilder deployments;
+  g_variant_builder_init (&deployments, G_VARIANT_TYPE ("aa{sv}"));
+  GLNX_HASH_TABLE_FOREACH_V (self->deployment_variants, GVariant *, variant)
+    g_variant_builder_add_value (&deployments, variant);
+
+  g_auto (GVariantDict) dict;
+  g_variant_dict_init (&dict, NULL);
+  g_variant_dict_insert (&dict, "stamp", "s", self->state_stamp);
+  g_variant_dict_insert_value (&dict, "deployments", g_variant_builder_end (&deployments));
+  if (self->unique_sizes)
+    {
+      g_variant_dict_insert (&dict, "unique-sizes-key", "s", self->unique_sizes_key);
+      g_variant_dict_insert_value (&dict, "unique-sizes", self->unique_sizes);
+    }
+  if (g_hash_table_size (self->rpm_diffs) > 0)
+    {
+      GVariantBuilder rpm_diffs;
+      g_variant_builder_init (&rpm_diffs, G_VARIANT_TYPE_VARDICT);
+      GLNX_HASH_TABLE_FOREACH_KV (self->rpm_diffs, const char *, id, GVariant *, rpm_diff)
+        g_variant_builder_add (&rpm_diffs, "{sv}", id, rpm_diff);
+      g_variant_dict_insert_value (&dict, "rpm-diffs", g_variant_builder_end (&rpm_diffs));
+    }
+  g_autoptr (GVariant) cache = g_variant_ref_sink (g_variant_dict_end (&dict));
+
+  return glnx_file_replace_contents_at (
+      AT_FDCWD, RPMOSTREE_STATE_CACHE, static_cast<const guint8 *> (g_variant_get_data (cache)),
+      g_variant_get_size (cache), GLNX_FILE_REPLACE_NODATASYNC, NULL, error);
+}
+
+/* Drop the cached deployment variants if the state they were generated from changed,
+ * and try to load them from the persistent cache otherwise.
+ */
+static gboolean
+sysroot_refresh_state_cache (RpmostreedSysroot *self, GError **error)
+{
+  g_autofree char *stamp = sysroot_generate_state_stamp (self, error);
+  if (!stamp)
+    return FALSE;
+  if (g_strcmp0 (stamp, self->state_stamp) == 0)
+    return TRUE;
+
+  g_hash_table_remove_all (self->deployment_variants);
+  g_free (self->state_stamp);
+  self->state_stamp = util::move_nullify (stamp);
+  self->state_cache_dirty = TRUE;
+
+  /* The persistent cache is only for the system daemon */
+  if (!rpmostreed_sysroot_is_system_daemon (self))
+    return TRUE;
+
+  g_autoptr (GError) local_error = NULL;
+  if (!sysroot_load_state_cache (self, &local_error))
+    sd_journal_print (LOG_WARNING, "Failed to load %s: %s", RPMOSTREE_STATE_CACHE,
+                      local_error->message);
+  else
+    self->state_cache_dirty = FALSE;
+  return TRUE;
+}
+
+/* Forget all cached deployment variants, e.g. because the ostree configuration changed */
+static void
+sysroot_invalidate_state_cache (RpmostreedSysroot *self)
+{
+  g_hash_table_remove_all (self->deployment_variants);
+  g_clear_pointer (&self->state_stamp, g_free);
+  if (rpmostreed_sysroot_is_system_daemon (self))
+    (void)unlinkat (AT_FDCWD, RPMOSTREE_STATE_CACHE, 0);
+}
+
+/**
+ * rpmostreed_sysroot_get_deployment_variant:
+ *
+ * Returns the variant describing @deployment, as generated by
+ * rpmostreed_deployment_generate_variant(), from the cache if possible.
+ */
+gboolean
+rpmostreed_sysroot_get_deployment_variant (RpmostreedSysroot *self, OstreeDeployment *deployment,
+                                           GVariant **out_variant, GError **error)
+{
+  auto id = rpmostreecxx::deployment_generate_id (*deployment);
+  auto variant
+      = static_cast<GVariant *> (g_hash_table_lookup (self->deployment_variants, id.c_str ()));
+  if (variant)
+    {
+      *out_variant = g_variant_ref (variant);
+      return TRUE;
+    }
+
+  if (!rpmostreed_deployment_generate_variant (self->ot_sysroot, deployment, NULL, self->repo,
+                                               TRUE, &variant, error))
+    return FALSE;
+  g_variant_ref_sink (variant);
+  g_hash_table_replace (self->deployment_variants, g_strdup (id.c_str ()),
+                        g_variant_ref (variant));
+  self->state_cache_dirty = TRUE;
+  *out_variant = variant;
+  return TRUE;
+}
+
+/* Identifies the set of @deployments the unique sizes were computed for */
+static char *
+sysroot_unique_sizes_key (GPtrArray *deployments)
+{
+  g_autoptr (GString) key = g_string_new (NULL);
+  for (guint i = 0; deployments != NULL && i < deployments->len; i++)
+    {
+      auto id = rpmostreecxx::deployment_generate_id (
+          *static_cast<OstreeDeployment *> (deployments->pdata[i]));
+      g_string_append_printf (key, "%s;", id.c_str ());
+    }
+  return g_string_free (util::move_nullify (key), FALSE);
+}
+
+typedef struct
+{
+  GDBusMethodInvocation *invocation;
+  GPtrArray *deployments;
+  char *key;
+} UniqueSizesRequest;
+
+static void
+unique_sizes_request_free (UniqueSizesRequest *request)
+{
+  g_clear_object (&request->invocation);
+  g_clear_pointer (&request->deployments, g_ptr_array_unref);
+  g_free (request->key);
+  g_free (request);
+}
+
+/* Computing the size of the objects unique to each deployment means traversing all
+ * of them, so do it in a thread. */
+static void
+compute_unique_sizes_thread (GTask *task, gpointer source, gpointer task_data,
+                             GCancellable *cancellable)
+{
+  auto self = RPMOSTREED_SYSROOT (source);
+  auto request = static_cast<UniqueSizesRequest *> (task_data);
+  GPtrArray *deployments = request->deployments;
+
+  g_autofree guint64 *sizes = g_new0 (guint64, deployments->len);
+  g_autoptr (GError) local_error = NULL;
+  if (!rpmostreed_deployments_compute_unique_sizes (self->repo, deployments, sizes, cancellable,
+                                                    &local_error))
+    {
+      g_task_return_error (task, util::move_nullify (local_error));
+      return;
+    }
+
+  GVariantBuilder builder;
+  g_variant_builder_init (&builder, G_VARIANT_TYPE ("a{st}"));
+  for (guint i = 0; i < deployments->len; i++)
+    {
+      auto id = rpmostreecxx::deployment_generate_id (
+          *static_cast<OstreeDeployment *> (deployments->pdata[i]));
+      g_variant_builder_add (&builder, "{st}", id.c_str (), sizes[i]);
+    }
+  g_task_return_pointer (task, g_variant_ref_sink (g_variant_builder_end (&builder)),
+                         (GDestroyNotify)g_variant_unref);
+}
+
+static void
+on_unique_sizes_computed (GObject *source, GAsyncResult *result, gpointer user_data)
+{
+  auto self = RPMOSTREED_SYSROOT (source);
+  auto request = static_cast<UniqueSizesRequest *> (g_task_get_task_data (G_TASK (result)));
+
+  g_autoptr (GError) local_error = NULL;
+  g_autoptr (GVariant) sizes
+      = static_cast<GVariant *> (g_task_propagate_pointer (G_TASK (result), &local_error));
+  if (!sizes)
+    {
+      g_dbus_method_invocation_return_gerror (request->invocation, local_error);
+      return;
+    }
+
+  /* Keep them around unless the deployments changed meanwhile */
+  g_autoptr (GPtrArray) deployments = ostree_sysroot_get_deployments (self->ot_sysroot);
+  g_autofree char *key = sysroot_unique_sizes_key (deployments);
+  if (g_str_equal (key, request->key))
+    {
+      g_clear_pointer (&self->unique_sizes, g_variant_unref);
+      self->unique_sizes = g_variant_ref (sizes);
+      g_free (self->unique_sizes_key);
+      self->unique_sizes_key = util::move_nullify (key);
+      if (rpmostreed_sysroot_is_system_daemon (self))
+        {
+          if (!sysroot_write_state_cache (self, &local_error))
+            sd_journal_print (LOG_WARNING, "Failed to write %s: %s", RPMOSTREE_STATE_CACHE,
+                              local_error->message);
+        }
+    }
+
+  rpmostree_sysroot_complete_get_deployments_unique_sizes (RPMOSTREE_SYSROOT (self),
+                                                           request->invocation, sizes);
+}
+
+static gboolean
+handle_get_deployments_unique_sizes (RPMOSTreeSysroot *object, GDBusMethodInvocation *invocation)
+{
+  RpmostreedSysroot *self = RPMOSTREED_SYSROOT (object);
+
+  g_autoptr (GPtrArray) deployments = ostree_sysroot_get_deployments (self->ot_sysroot);
+  g_autofree char *key = sysroot_unique_sizes_key (deployments);
+  if (self->unique_sizes && g_str_equal (key, self->unique_sizes_key))
+    {
+      rpmostree_sysroot_complete_get_deployments_unique_sizes (object, invocation,
+                                                               self->unique_sizes);
+      return TRUE;
+    }
+
+  auto request = g_new0 (UniqueSizesRequest, 1);
+  request->invocation = (GDBusMethodInvocation *)g_object_ref (invocation);
+  request->deployments = util::move_nullify (deployments);
+  request->key = util::move_nullify (key);
+  g_autoptr (GTask) task = g_task_new (self, NULL, on_unique_sizes_computed, NULL);
+  g_task_set_task_data (task, request, (GDestroyNotify)unique_sizes_request_free);
+  g_task_run_in_thread (task, compute_unique_sizes_thread);
+  return TRUE;
+}
+
+/* Caches @rpm_diff, the package diff from the booted deployment to the one with @id,
+ * as computed by the transaction which created it, for `status` to show. */
+void
+rpmostreed_sysroot_add_rpm_diff (RpmostreedSysroot *self, const char *id, GVariant *rpm_diff)
+{
+  g_hash_table_replace (self->rpm_diffs, g_strdup (id), g_variant_ref (rpm_diff));
+  self->state_cache_dirty = TRUE;
+}
+
+/* Forget the package diffs of the deployments which are gone */
+static void
+sysroot_prune_rpm_diffs (RpmostreedSysroot *self, GPtrArray *deployments)
+{
+  g_autoptr (GHashTable) ids = g_hash_table_new_full (g_str_hash, g_str_equal, g_free, NULL);
+  for (guint i = 0; deployments != NULL && i < deployments->len; i++)
+    {
+      auto id = rpmostreecxx::deployment_generate_id (
+          *static_cast<OstreeDeployment *> (deployments->pdata[i]));
+      g_hash_table_add (ids, g_strdup (id.c_str ()));
+    }
+  GLNX_HASH_TABLE_FOREACH_IT (self->rpm_diffs, it, const char *, id, GVariant *, rpm_diff)
+    {
+      if (!g_hash_table_contains (ids, id))
+        {
+          g_hash_table_iter_remove (&it);
+          self->state_cache_dirty = TRUE;
+        }
+    }
+}
+
+/* Writes the metrics to the MetricsFile of the daemon config, if set.
+ * Failures are only logged, since metrics are best-effort. */
+void
+rpmostreed_sysroot_write_metrics (RpmostreedSysroot *self)
+{
+  const char *metrics_file = rpmostreed_get_metrics_file (rpmostreed_daemon_get ());
+  if (!metrics_file || !rpmostreed_sysroot_is_system_daemon (self))
+    return;
+
+  g_autoptr (GVariant) deployments = rpmostree_sysroot_dup_deployments (RPMOSTREE_SYSROOT (self));
+  if (!deployments)
+    return;
+
+  g_autoptr (GError) local_error = NULL;
+  if (!ROSCXX (metrics_write (metrics_file, *deployments), &local_error))
+    sd_journal_print (LOG_WARNING, "Failed to write metrics to %s: %s", metrics_file,
+                      local_error->message);
+}
+
+/* Emits UpdateNotification if enabled and a reboot became needed for a new reason,
+ * e.g. because an update was staged.  Nothing is emitted for the state found when
+ * the daemon starts, since it was announced already, or is stale.
+ */
+static void
+sysroot_maybe_notify_update (RpmostreedSysroot *self, GVariant *deployments,
+                             const char *reboot_reason)
+{
+  g_autofree char *previous_reason = util::move_nullify (self->reboot_reason);
+  self->reboot_reason = g_strdup (reboot_reason);
+  if (!previous_reason || !*reboot_reason || g_str_equal (previous_reason, reboot_reason))
+    return;
+  if (!rpmostreed_sysroot_is_system_daemon (self)
+      || !rpmostreed_get_update_notifications (rpmostreed_daemon_get ()))
+    return;
+
+  g_autoptr (GVariant) default_deployment = g_variant_get_child_value (deployments, 0);
+  g_auto (GVariantDict) dict;
+  g_variant_dict_init (&dict, default_deployment);
+  const char *version = NULL;
+  g_variant_dict_lookup (&dict, "version", "&s", &version);
+  g_autofree char *body = version ? g_strdup_printf ("%s (version %s). Reboot to apply it.",
+                                                     reboot_reason, version)
+                                  : g_strdup_printf ("%s. Reboot to apply it.", reboot_reason);
+  sd_journal_print (LOG_INFO, "Emitting update notification: %s", body);
+  rpmostree_sysroot_emit_update_notification (RPMOSTREE_SYSROOT (self), "System update ready",
+                                              body);
+}
+
 static gboolean
 sysroot_populate_deployments_unlocked (RpmostreedSysroot *self, gboolean *out_changed,
                                        GError **error)
@@ -255,13 +726,15 @@ sysroot_populate_deployments_unlocked (RpmostreedSysroot *self, gboolean *out_ch
 
   g_debug ("loading deployments");
 
+  if (!sysroot_refresh_state_cache (self, error))
+    return FALSE;
+
   GVariantBuilder builder;
   g_variant_builder_init (&builder, G_VARIANT_TYPE ("aa{sv}"));
 
   g_autoptr (GHashTable) seen_osnames = g_hash_table_new_full (g_str_hash, g_str_equal, NULL, NULL);
 
   /* Updated booted property; object owned by sysroot */
-  g_autofree gchar *booted_id = NULL;
   OstreeDeployment *booted = ostree_sysroot_get_booted_deployment (self->ot_sysroot);
   if (booted)
     {
@@ -272,8 +745,6 @@ sysroot_populate_deployments_unlocked (RpmostreedSysroot *self, gboolean *out_ch
                    error);
 
       rpmostree_sysroot_set_booted (RPMOSTREE_SYSROOT (self), path.c_str ());
-      auto bootedid_v = rpmostreecxx::deployment_generate_id (*booted);
-      booted_id = g_strdup (bootedid_v.c_str ());
     }
   else
     {
@@ -282,15 +753,30 @@ sysroot_populate_deployments_unlocked (RpmostreedSysroot *self, gboolean *out_ch
 
   /* Add deployment interfaces */
   g_autoptr (GPtrArray) deployments = ostree_sysroot_get_deployments (self->ot_sysroot);
+  sysroot_prune_rpm_diffs (self, deployments);
 
   for (guint i = 0; deployments != NULL && i < deployments->len; i++)
     {
       auto deployment = static_cast<OstreeDeployment *> (deployments->pdata[i]);
-      GVariant *variant = NULL;
-      if (!rpmostreed_deployment_generate_variant (self->ot_sysroot, deployment, booted_id,
-                                                   self->repo, TRUE, &variant, error))
+      g_autoptr (GVariant) variant = NULL;
+      if (!rpmostreed_sysroot_get_deployment_variant (self, deployment, &variant, error))
         return glnx_prefix_error (error, "Reading deployment %u", i);
 
+      GVariant *rpm_diff = NULL;
+      if (i == 0 && booted && !ostree_deployment_equal (deployment, booted))
+        {
+          auto id = rpmostreecxx::deployment_generate_id (*deployment);
+          rpm_diff = static_cast<GVariant *> (g_hash_table_lookup (self->rpm_diffs, id.c_str ()));
+        }
+      if (rpm_diff)
+        {
+          g_auto (GVariantDict) dict;
+          g_variant_dict_init (&dict, variant);
+          g_variant_dict_insert_value (&dict, "pending-rpm-diff", rpm_diff);
+          g_variant_unref (variant);
+          variant = g_variant_ref_sink (g_variant_dict_end (&dict));
+        }
+
       g_variant_builder_add_value (&builder, variant);
 
       const char *deployment_os = ostree_deployment_get_osname (deployment);
@@ -328,9 +814,26 @@ sysroot_populate_deployments_unlocked (RpmostreedSysroot *self, gboolean *out_ch
         }
     }
 
-  rpmostree_sysroot_set_deployments (RPMOSTREE_SYSROOT (self), g_variant_builder_end (&builder));
+  g_autoptr (GVariant) deployments_variant = g_variant_ref_sink (g_variant_builder_end (&builder));
+  rpmostree_sysroot_set_deployments (RPMOSTREE_SYSROOT (self), deployments_variant);
+  CXX_TRY_VAR (reboot_reason, rpmostreecxx::deployments_reboot_reason (*deployments_variant),
+               error);
+  rpmostree_sysroot_set_needs_reboot (RPMOSTREE_SYSROOT (self), !reboot_reason.empty ());
+  sysroot_maybe_notify_update (self, deployments_variant, reboot_reason.c_str ());
   g_debug ("finished deployments");
 
+  if (self->state_cache_dirty && rpmostreed_sysroot_is_system_daemon (self))
+    {
+      g_autoptr (GError) local_error = NULL;
+      if (!sysroot_write_state_cache (self, &local_error))
+        sd_journal_print (LOG_WARNING, "Failed to write %s: %s", RPMOSTREE_STATE_CACHE,
+                          local_error->message);
+      else
+        self->state_cache_dirty = FALSE;
+    }
+
+  rpmostreed_sysroot_write_metrics (self);
+
   if (out_changed)
     *out_changed = TRUE;
   return TRUE;
@@ -378,10 +881,50 @@ reset_config_properties (RpmostreedSysroot *self, GError **error)
   const char *policy_str = rpmostree_auto_update_policy_to_str (policy, NULL);
   g_assert (policy_str);
   rpmostree_sysroot_set_automatic_update_policy (RPMOSTREE_SYSROOT (self), policy_str);
+  rpmostree_sysroot_set_update_window (RPMOSTREE_SYSROOT (self),
+                                       rpmostreed_get_update_window (daemon) ?: "");
+  rpmostree_sysroot_set_download_window (RPMOSTREE_SYSROOT (self),
+                                         rpmostreed_get_download_window (daemon) ?: "");
+  const char *bootc_interop = rpmostreed_get_bootc_interop (daemon);
+  CXX_TRY_VAR (bootc_managed, rpmostreecxx::bootc_is_managed (), error);
+  if (!bootc_managed || g_str_equal (bootc_interop, "off"))
+    bootc_interop = "";
+  rpmostree_sysroot_set_bootc_interop (RPMOSTREE_SYSROOT (self), bootc_interop);
 
   return TRUE;
 }
 
+/* Update the AutomaticUpdateLastFailure property from the state recorded by the automatic
+ * update policy. */
+void
+rpmostreed_sysroot_refresh_autoupdate_failure (RpmostreedSysroot *self)
+{
+  g_auto (GVariantDict) dict;
+  g_variant_dict_init (&dict, NULL);
+  if (rpmostreed_sysroot_is_system_daemon (self))
+    {
+      try
+        {
+          auto failure = rpmostreecxx::autoupdate_last_failure ();
+          if (failure.count > 0)
+            {
+              g_variant_dict_insert (&dict, "category", "s", failure.category.c_str ());
+              g_variant_dict_insert (&dict, "message", "s", failure.message.c_str ());
+              g_variant_dict_insert (&dict, "count", "u", failure.count);
+              g_variant_dict_insert (&dict, "timestamp", "x", failure.timestamp);
+              g_variant_dict_insert (&dict, "next-attempt", "x", failure.next_attempt);
+            }
+        }
+      catch (std::exception &e)
+        {
+          sd_journal_print (LOG_WARNING, "Failed to load automatic update failure: %s",
+                            e.what ());
+        }
+    }
+  rpmostree_sysroot_set_automatic_update_last_failure (RPMOSTREE_SYSROOT (self),
+                                                       g_variant_dict_end (&dict));
+}
+
 typedef struct
 {
   RPMOSTreeSysroot *object;
@@ -439,6 +982,9 @@ reload_config (RpmostreedSysroot *self, GError **error)
   if (config_changed && !reset_config_properties (self, error))
     return glnx_prefix_error (error, "Remapping properties");
 
+  /* The remotes may have changed */
+  sysroot_invalidate_state_cache (self);
+
   gboolean sysroot_changed = FALSE;
   if (!sysroot_reload_ostree_configs_and_deployments (self, &sysroot_changed, error))
     return glnx_prefix_error (error, "Reloading ostree details");
@@ -525,6 +1071,12 @@ sysroot_finalize (GObject *object)
 
   g_hash_table_unref (self->os_interfaces);
   g_hash_table_unref (self->osexperimental_interfaces);
+  g_hash_table_unref (self->deployment_variants);
+  g_free (self->state_stamp);
+  g_clear_pointer (&self->unique_sizes, g_variant_unref);
+  g_free (self->unique_sizes_key);
+  g_hash_table_unref (self->rpm_diffs);
+  g_free (self->reboot_reason);
 
   g_clear_object (&self->monitor);
 
@@ -543,11 +1095,16 @@ rpmostreed_sysroot_init (RpmostreedSysroot *self)
       = g_hash_table_new_full (g_str_hash, g_str_equal, g_free, (GDestroyNotify)g_object_unref);
   self->osexperimental_interfaces
       = g_hash_table_new_full (g_str_hash, g_str_equal, g_free, (GDestroyNotify)g_object_unref);
+  self->deployment_variants
+      = g_hash_table_new_full (g_str_hash, g_str_equal, g_free, (GDestroyNotify)g_variant_unref);
+  self->rpm_diffs
+      = g_hash_table_new_full (g_str_hash, g_str_equal, g_free, (GDestroyNotify)g_variant_unref);
 
   self->monitor = NULL;
 
-  /* Only use polkit when running as root on system bus; self-tests don't need it */
-  if (!self->on_session_bus)
+  /* Only use polkit when running as root on system bus; self-tests and private daemons
+   * don't need it */
+  if (!self->on_session_bus && !rpmostreed_daemon_is_private (rpmostreed_daemon_get ()))
     {
       g_autoptr (GError) local_error = NULL;
       self->authority = polkit_authority_get_sync (NULL, &local_error);
@@ -574,9 +1131,15 @@ sysroot_authorize_method (GDBusInterfaceSkeleton *interface, GDBusMethodInvocati
       /* The daemon is on the session bus, running self tests */
       authorized = TRUE;
     }
-  else if (g_strcmp0 (method_name, "GetOS") == 0 || g_strcmp0 (method_name, "Reload") == 0)
+  else if (rpmostreed_daemon_is_private (rpmostreed_daemon_get ()))
     {
-      /* GetOS() and Reload() are always allowed */
+      /* A private daemon only serves the process which spawned it, with its privileges */
+      authorized = TRUE;
+    }
+  else if (g_strcmp0 (method_name, "GetOS") == 0 || g_strcmp0 (method_name, "Reload") == 0
+           || g_strcmp0 (method_name, "GetDeploymentsUniqueSizes") == 0)
+    {
+      /* GetOS(), Reload() and GetDeploymentsUniqueSizes() are always allowed */
       authorized = TRUE;
     }
   else if (g_strcmp0 (method_name, "ReloadConfig") == 0)
@@ -715,6 +1278,7 @@ static void
 rpmostreed_sysroot_iface_init (RPMOSTreeSysrootIface *iface)
 {
   iface->handle_get_os = handle_get_os;
+  iface->handle_get_deployments_unique_sizes = handle_get_deployments_unique_sizes;
   iface->handle_register_client = handle_register_client;
   iface->handle_unregister_client = handle_unregister_client;
   iface->handle_reload = handle_reload;
@@ -748,6 +1312,27 @@ rpmostreed_sysroot_populate (RpmostreedSysroot *self, GCancellable *cancellable,
   if (!ostree_sysroot_get_repo (self->ot_sysroot, &self->repo, cancellable, error))
     return FALSE;
 
+  /* Only the system daemon records the transaction in progress; don't let this
+   * prevent starting up, the next transaction cleans up anyway. */
+  if (rpmostreed_sysroot_is_system_daemon (self))
+    {
+      g_autoptr (GError) local_error = NULL;
+      if (!rpmostreed_transaction_recover_interrupted (self->ot_sysroot, self->repo, cancellable,
+                                                       &local_error))
+        sd_journal_print (LOG_WARNING, "%s", local_error->message);
+
+      /* Staged deployments only get their /etc merged when finalized; like
+       * when deploying, the report is purely informational */
+      try
+        {
+          rpmostreecxx::etc_conflicts_finalize (*self->ot_sysroot);
+        }
+      catch (std::exception &e)
+        {
+          sd_journal_print (LOG_WARNING, "Failed to record /etc merge conflicts: %s", e.what ());
+        }
+    }
+
   if (!sysroot_populate_deployments_unlocked (self, NULL, error))
     return FALSE;
 
@@ -755,6 +1340,7 @@ rpmostreed_sysroot_populate (RpmostreedSysroot *self, GCancellable *cancellable,
 
   if (!reset_config_properties (self, error))
     return FALSE;
+  rpmostreed_sysroot_refresh_autoupdate_failure (self);
 
   if (self->monitor == NULL)
     {
@@ -829,6 +1415,13 @@ rpmostreed_sysroot_has_txn (RpmostreedSysroot *self)
   return self->transaction != NULL;
 }
 
+/* Returns the active transaction, or %NULL */
+RpmostreedTransaction *
+rpmostreed_sysroot_get_txn (RpmostreedSysroot *self)
+{
+  return self->transaction;
+}
+
 static gboolean
 on_force_close (gpointer data)
 {
@@ -871,6 +1464,7 @@ rpmostreed_sysroot_set_txn (RpmostreedSysroot *self, RpmostreedTransaction *txn)
     {
       g_assert (self->transaction == NULL);
       self->transaction = (RpmostreedTransaction *)g_object_ref (txn);
+      rpmostree_output_set_stage (RPMOSTREE_OUTPUT_STAGE_OTHER);
 
       g_signal_connect (self->transaction, "notify::executed", G_CALLBACK (on_txn_executed_changed),
                         self);
@@ -878,7 +1472,7 @@ rpmostreed_sysroot_set_txn (RpmostreedSysroot *self, RpmostreedTransaction *txn)
       GDBusMethodInvocation *invocation = rpmostreed_transaction_get_invocation (self->transaction);
       g_autoptr (GVariant) v = g_variant_ref_sink (
           g_variant_new ("(sss)", g_dbus_method_invocation_get_method_name (invocation),
-                         g_dbus_method_invocation_get_sender (invocation),
+                         g_dbus_method_invocation_get_sender (invocation) ?: "",
                          g_dbus_method_invocation_get_object_path (invocation)));
       rpmostree_sysroot_set_active_transaction ((RPMOSTreeSysroot *)self, v);
       rpmostree_sysroot_set_active_transaction_path (
@@ -910,6 +1504,21 @@ rpmostreed_sysroot_finish_txn (RpmostreedSysroot *self, RpmostreedTransaction *t
   rpmostreed_sysroot_set_txn (self, NULL);
 }
 
+/* Called when the daemon is shutting down, so that we don't exit in the middle of a
+ * transaction. */
+void
+rpmostreed_sysroot_cancel_txn_sync (RpmostreedSysroot *self)
+{
+  if (!self->transaction)
+    return;
+
+  sd_journal_print (LOG_INFO, "Cancelling active transaction");
+  /* The transaction may be closed and dropped while we wait */
+  glnx_unref_object RpmostreedTransaction *txn
+      = (RpmostreedTransaction *)g_object_ref (self->transaction);
+  rpmostreed_transaction_cancel_sync (txn);
+}
+
 OstreeSysroot *
 rpmostreed_sysroot_get_root (RpmostreedSysroot *self)
 {
@@ -934,6 +1543,14 @@ rpmostreed_sysroot_is_on_session_bus (RpmostreedSysroot *self)
   return self->on_session_bus;
 }
 
+/* Whether we're the system daemon, rather than one running self tests or a private daemon;
+ * only the system daemon keeps state about the host */
+gboolean
+rpmostreed_sysroot_is_system_daemon (RpmostreedSysroot *self)
+{
+  return !self->on_session_bus && !rpmostreed_daemon_is_private (rpmostreed_daemon_get ());
+}
+
 /**
  * rpmostreed_sysroot_get:
  *
@@ -43,6 +43,11 @@ OstreeSysroot *rpmostreed_sysroot_get_root (RpmostreedSysroot *self);
 OstreeRepo *rpmostreed_sysroot_get_repo (RpmostreedSysroot *self);
 PolkitAuthority *rpmostreed_sysroot_get_polkit_authority (RpmostreedSysroot *self);
 gboolean rpmostreed_sysroot_is_on_session_bus (RpmostreedSysroot *self);
+gboolean rpmostreed_sysroot_is_system_daemon (RpmostreedSysroot *self);
+
+gboolean rpmostreed_sysroot_get_deployment_variant (RpmostreedSysroot *self,
+                                                   OstreeDeployment *deployment,
+                                                   GVariant **out_variant, GError **error);
 
 gboolean rpmostreed_sysroot_load_state (RpmostreedSysroot *self, GCancellable *cancellable,
                                         OstreeSysroot **out_sysroot, OstreeRepo **out_repo,
@@ -54,8 +59,12 @@ gboolean rpmostreed_sysroot_prep_for_txn (RpmostreedSysroot *self,
 
 gboolean rpmostreed_sysroot_has_txn (RpmostreedSysroot *self);
 
+RpmostreedTransaction *rpmostreed_sysroot_get_txn (RpmostreedSysroot *self);
+
 void rpmostreed_sysroot_finish_txn (RpmostreedSysroot *self, RpmostreedTransaction *txn);
 
+void rpmostreed_sysroot_cancel_txn_sync (RpmostreedSysroot *self);
+
 void rpmostreed_sysroot_set_txn (RpmostreedSysroot *self, RpmostreedTransaction *txn);
 
 void rpmostreed_sysroot_set_txn_and_title (RpmostreedSysroot *self, RpmostreedTransaction *txn,
@@ -63,4 +72,11 @@ void rpmostreed_sysroot_set_txn_and_title (RpmostreedSysroot *self, RpmostreedTr
 
 void rpmostreed_sysroot_emit_update (RpmostreedSysroot *self);
 
+void rpmostreed_sysroot_add_rpm_diff (RpmostreedSysroot *self, const char *id,
+                                      GVariant *rpm_diff);
+
+void rpmostreed_sysroot_write_metrics (RpmostreedSysroot *self);
+
+void rpmostreed_sysroot_refresh_autoupdate_failure (RpmostreedSysroot *self);
+
 G_END_DECLS
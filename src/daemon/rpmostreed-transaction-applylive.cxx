@@ -31,6 +31,7 @@
 #include "rpmostree-output.h"
 #include "rpmostree-sysroot-core.h"
 #include "rpmostree-util.h"
+#include "rpmostreed-daemon.h"
 #include "rpmostreed-deployment-utils.h"
 #include "rpmostreed-sysroot.h"
 #include "rpmostreed-transaction-types.h"
@@ -67,8 +68,12 @@ livefs_transaction_execute (RpmostreedTransaction *transaction, GCancellable *ca
   LiveFsTransaction *self = (LiveFsTransaction *)transaction;
   OstreeSysroot *sysroot = rpmostreed_transaction_get_sysroot (transaction);
 
+  g_autoptr (GVariantDict) dictv = g_variant_dict_new (self->options);
+  rpmostreed_add_live_restart_services (dictv);
+  g_autoptr (GVariant) options = g_variant_ref_sink (g_variant_dict_end (dictv));
+
   /* Run the transaction */
-  if (!ROSCXX (transaction_apply_live (*sysroot, *self->options), error))
+  if (!ROSCXX (transaction_apply_live (*sysroot, *options), error))
     {
       (void)rpmostree_syscore_bump_mtime (sysroot, NULL);
       return FALSE;
@@ -114,3 +119,36 @@ rpmostreed_transaction_new_apply_live (GDBusMethodInvocation *invocation, Ostree
 
   return (RpmostreedTransaction *)self;
 }
+
+/* Add the services to restart if they use files replaced by applying an update live, from
+ * LiveRestartServices=, to the options of transaction_apply_live(). That's an upper bound:
+ * if the client requested restarting services, only those also listed there are kept. */
+void
+rpmostreed_add_live_restart_services (GVariantDict *dict)
+{
+  const char *services = rpmostreed_get_live_restart_services (rpmostreed_daemon_get ());
+  g_auto (GStrv) allowed = g_strsplit (services ?: "", " ", -1);
+
+  const char *requested = NULL;
+  if (!g_variant_dict_lookup (dict, "restart-services", "&s", &requested))
+    {
+      if (services)
+        g_variant_dict_insert (dict, "restart-services", "s", services);
+      return;
+    }
+
+  g_auto (GStrv) requested_services = g_strsplit (requested, " ", -1);
+  g_autoptr (GPtrArray) kept = g_ptr_array_new ();
+  for (char **it = requested_services; it && *it; it++)
+    {
+      if (!**it)
+        continue;
+      if (g_strv_contains ((const char *const *)allowed, *it))
+        g_ptr_array_add (kept, *it);
+      else
+        rpmostree_output_message ("Not restarting %s since it isn't in LiveRestartServices", *it);
+    }
+  g_ptr_array_add (kept, NULL);
+  g_autofree char *kept_services = g_strjoinv (" ", (char **)kept->pdata);
+  g_variant_dict_insert (dict, "restart-services", "s", kept_services);
+}
@@ -22,6 +22,7 @@
 #include <gio/gunixoutputstream.h>
 #include <json-glib/json-glib.h>
 #include <libglnx.h>
+#include <optional>
 #include <systemd/sd-journal.h>
 
 #include "rpmostree-core.h"
@@ -39,6 +40,9 @@
 #include "rpmostreed-transaction.h"
 #include "rpmostreed-utils.h"
 
+#define RPMOSTREE_MESSAGE_AUTOMATIC_UPDATE_FAILED                                                  \
+  SD_ID128_MAKE (b6, 74, 5c, b5, 74, de, 4b, cc, 88, 39, 22, 4d, 53, d2, 70, 2c)
+
 static gboolean vardict_lookup_bool (GVariantDict *dict, const char *key, gboolean dfault);
 
 static void *vardict_lookup_ptr (GVariantDict *dict, const char *key, const char *fmt);
@@ -217,13 +221,28 @@ apply_revision_override (RpmostreedTransaction *transaction, OstreeRepo *repo,
   return TRUE;
 }
 
+/* Records the time of the last check for updates in the metrics, if enabled. */
+static void
+record_update_check (void)
+{
+  if (rpmostreed_get_metrics_file (rpmostreed_daemon_get ()))
+    {
+      g_autoptr (GError) local_error = NULL;
+      if (!ROSCXX (metrics_record_update_check (), &local_error))
+        sd_journal_print (LOG_WARNING, "Failed to record update check metrics: %s",
+                          local_error->message);
+    }
+}
+
 /* Generates the update GVariant and caches it to disk. This is set as the CachedUpdate
  * property of RPMOSTreeOS by refresh_cached_update, but we calculate during transactions
  * only, since it's potentially costly to do. See:
  * https://github.com/projectatomic/rpm-ostree/pull/1268 */
 static gboolean
 generate_update_variant (OstreeRepo *repo, OstreeDeployment *booted_deployment,
-                         OstreeDeployment *staged_deployment, DnfSack *sack, /* allow-none */
+                         OstreeDeployment *staged_deployment,
+                         const char *target_checksum, /* allow-none */
+                         DnfSack *sack,               /* allow-none */
                          GCancellable *cancellable, GError **error)
 {
   if (!glnx_shutil_mkdir_p_at (AT_FDCWD, dirname (strdupa (RPMOSTREE_AUTOUPDATES_CACHE_FILE)), 0775,
@@ -235,8 +254,8 @@ generate_update_variant (OstreeRepo *repo, OstreeDeployment *booted_deployment,
     return FALSE;
 
   g_autoptr (GVariant) update = NULL;
-  if (!rpmostreed_update_generate_variant (booted_deployment, staged_deployment, repo, sack,
-                                           &update, cancellable, error))
+  if (!rpmostreed_update_generate_variant (booted_deployment, staged_deployment, target_checksum,
+                                           repo, sack, &update, cancellable, error))
     return FALSE;
 
   if (update != NULL)
@@ -249,6 +268,194 @@ generate_update_variant (OstreeRepo *repo, OstreeDeployment *booted_deployment,
         return FALSE;
     }
 
+  record_update_check ();
+  return TRUE;
+}
+
+/* If the remote of @origin has an update graph (update-graph-url in its configuration), set
+ * @out_target to the commit to upgrade to from @base according to it, or to @base itself if
+ * there's no upgrade. */
+static gboolean
+get_update_graph_target (OstreeRepo *repo, RpmOstreeOrigin *origin, const char *base,
+                         char **out_target, GError **error)
+{
+  auto r = rpmostree_origin_get_refspec (origin);
+  if (r.kind != rpmostreecxx::RefspecType::Ostree)
+    return TRUE;
+  g_autofree char *remote = NULL;
+  if (!ostree_parse_refspec (r.refspec.c_str (), &remote, NULL, error))
+    return FALSE;
+  if (!remote)
+    return TRUE;
+  g_autofree char *url = NULL;
+  if (!ostree_repo_get_remote_option (repo, remote, "update-graph-url", NULL, &url, error))
+    return FALSE;
+  if (!url)
+    return TRUE;
+
+  CXX_TRY_VAR (target, rpmostreecxx::update_graph_target (url, base), error);
+  *out_target = g_strdup (target.empty () ? base : target.c_str ());
+  return TRUE;
+}
+
+/* For AutomaticUpdatePolicy=security: whether @update, as generated by
+ * rpmostreed_update_generate_variant(), addresses security advisories of at least
+ * @min_severity. */
+static gboolean
+update_has_security_advisories (GVariant *update, RpmOstreeAdvisorySeverity min_severity)
+{
+  g_auto (GVariantDict) dict;
+  g_variant_dict_init (&dict, update);
+  g_autoptr (GVariant) advisories
+      = g_variant_dict_lookup_value (&dict, "advisories", G_VARIANT_TYPE ("a(suuasa{sv})"));
+  if (!advisories)
+    return FALSE;
+
+  GVariantIter iter;
+  g_variant_iter_init (&iter, advisories);
+  while (TRUE)
+    {
+      g_autoptr (GVariant) child = g_variant_iter_next_value (&iter);
+      if (!child)
+        break;
+
+      guint32 kind;
+      guint32 severity;
+      g_variant_get_child (child, 1, "u", &kind);
+      g_variant_get_child (child, 2, "u", &severity);
+      if (kind == DNF_ADVISORY_KIND_SECURITY && severity >= min_severity)
+        return TRUE;
+    }
+  return FALSE;
+}
+
+/* For AutomaticUpdateBandwidthLimitKBps: limits package downloads for as long as it lives,
+ * then restores the limit for other downloads. */
+struct AutomaticBandwidthLimit
+{
+  AutomaticBandwidthLimit (guint64 limit_kbps)
+  {
+    rpmostree_set_download_bandwidth_limit (limit_kbps);
+  }
+  ~AutomaticBandwidthLimit ()
+  {
+    rpmostree_set_download_bandwidth_limit (
+        rpmostreed_get_bandwidth_limit (rpmostreed_daemon_get ()));
+  }
+};
+
+/* libostree doesn't allow limiting the rate of pulls, but they run on the main context of
+ * the transaction thread, which is also where the progress is updated; so to honor the
+ * bandwidth limits, we hold that context until the time at which the bytes transferred so
+ * far are due, or until cancelled. */
+struct PullThrottle
+{
+  guint64 limit_bytes; /* per second */
+  gint64 start_time;
+  GCancellable *cancellable;
+};
+
+static void
+on_pull_progress_throttle (OstreeAsyncProgress *progress, gpointer user_data)
+{
+  auto throttle = static_cast<PullThrottle *> (user_data);
+  guint64 bytes = ostree_async_progress_get_uint64 (progress, "bytes-transferred");
+  gint64 due = throttle->start_time + (gint64)(bytes * G_USEC_PER_SEC / throttle->limit_bytes);
+  /* Hold it for at most a few seconds at a time so that progress keeps being reported */
+  gint64 timeout_ms = MIN (due - g_get_monotonic_time (), 5 * G_USEC_PER_SEC) / 1000;
+  if (timeout_ms <= 0)
+    return;
+  GPollFD pollfd;
+  if (!g_cancellable_make_pollfd (throttle->cancellable, &pollfd))
+    return;
+  (void)g_poll (&pollfd, 1, timeout_ms);
+  g_cancellable_release_fd (throttle->cancellable);
+}
+
+/* Returns the kernel arguments of @deployment, without the ostree= one which differs
+ * between deployments anyway. */
+static char *
+deployment_get_kargs_without_ostree (OstreeDeployment *deployment)
+{
+  OstreeBootconfigParser *bootconfig = ostree_deployment_get_bootconfig (deployment);
+  const char *options = bootconfig ? ostree_bootconfig_parser_get (bootconfig, "options") : NULL;
+  g_autoptr (OstreeKernelArgs) kargs = ostree_kernel_args_from_string (options ?: "");
+  (void)ostree_kernel_args_delete_key_entry (kargs, "ostree", NULL);
+  return ostree_kernel_args_to_string (kargs);
+}
+
+/* If switching from @booted to @new_deployment requires a full reboot, i.e. it changes the
+ * kernel, initramfs or kernel arguments, returns why; otherwise NULL. */
+static const char *
+get_full_reboot_reason (OstreeDeployment *booted, OstreeDeployment *new_deployment)
+{
+  if (!g_str_equal (ostree_deployment_get_bootcsum (booted),
+                    ostree_deployment_get_bootcsum (new_deployment)))
+    return "the kernel or initramfs changed";
+  g_autofree char *booted_kargs = deployment_get_kargs_without_ostree (booted);
+  g_autofree char *new_kargs = deployment_get_kargs_without_ostree (new_deployment);
+  if (!g_str_equal (booted_kargs, new_kargs))
+    return "the kernel arguments changed";
+  return NULL;
+}
+
+/* For AutomaticUpdatePolicy=apply-live: apply the update in @new_deployment live, unless
+ * changing the kernel, initramfs or kernel arguments requires a reboot. Failing to apply it
+ * isn't an error, since it's still staged. Returns whether it was applied. */
+static gboolean
+maybe_apply_live (OstreeSysroot *sysroot, OstreeDeployment *new_deployment)
+{
+  OstreeDeployment *booted = ostree_sysroot_get_booted_deployment (sysroot);
+  g_assert (booted);
+
+  const char *reason = get_full_reboot_reason (booted, new_deployment);
+  if (reason)
+    {
+      rpmostree_output_message ("Not applying update live since %s; reboot to apply it",
+                                reason);
+      return FALSE;
+    }
+
+  /* Replacement is never requested here, since only the user can allow it through
+   * apply-live; files held by running processes aren't removed, and the update then
+   * stays staged. */
+  g_autoptr (GVariantDict) dictv = g_variant_dict_new (NULL);
+  rpmostreed_add_live_restart_services (dictv);
+  g_autoptr (GVariant) live_opts = g_variant_ref_sink (g_variant_dict_end (dictv));
+  g_autoptr (GError) local_error = NULL;
+  if (!ROSCXX (transaction_apply_live (*sysroot, *live_opts), &local_error))
+    {
+      rpmostree_output_message ("Failed to apply update live: %s; reboot to apply it",
+                                local_error->message);
+      return FALSE;
+    }
+  rpmostree_output_message ("Update applied live.");
+  return TRUE;
+}
+
+/* For automatic reboots: if FleetLockURL is set, take a slot of the reboot semaphore
+ * first. Sets @out_acquired to whether to go ahead with the reboot; if not, the update
+ * stays staged. Only fails if @cancellable is cancelled while waiting for a slot. */
+static gboolean
+acquire_reboot_slot (gboolean *out_acquired, GCancellable *cancellable, GError **error)
+{
+  RpmostreedDaemon *daemon = rpmostreed_daemon_get ();
+  const char *url = rpmostreed_get_fleet_lock_url (daemon);
+  *out_acquired = TRUE;
+  if (!url)
+    return TRUE;
+
+  const char *group = rpmostreed_get_fleet_lock_group (daemon);
+  rpmostree_output_message ("Taking reboot slot from %s (group %s)", url, group);
+  g_autoptr (GError) local_error = NULL;
+  if (!ROSCXX (fleet_lock_pre_reboot (url, group, *cancellable), &local_error))
+    {
+      if (g_cancellable_set_error_if_cancelled (cancellable, error))
+        return FALSE;
+      rpmostree_output_message ("Not rebooting: %s; reboot to apply the update",
+                                local_error->message);
+      *out_acquired = FALSE;
+    }
   return TRUE;
 }
 
@@ -293,6 +500,8 @@ package_diff_transaction_execute (RpmostreedTransaction *transaction, GCancellab
 
   if (self->revision != NULL || self->refspec != NULL)
     upgrader_flags |= RPMOSTREE_SYSROOT_UPGRADER_FLAGS_ALLOW_OLDER;
+  if (rpmostreed_get_enforce_container_sigpolicy (rpmostreed_daemon_get ()))
+    upgrader_flags |= RPMOSTREE_SYSROOT_UPGRADER_FLAGS_ENFORCE_CONTAINER_SIGPOLICY;
 
   OstreeSysroot *sysroot = rpmostreed_transaction_get_sysroot (transaction);
   g_autoptr (RpmOstreeSysrootUpgrader) upgrader = rpmostree_sysroot_upgrader_new (
@@ -364,7 +573,8 @@ package_diff_transaction_execute (RpmostreedTransaction *transaction, GCancellab
        * that's all we updated here. This conflicts with auto-updates for now, though we
        * need better test coverage before uniting those two paths. */
       OstreeDeployment *booted_deployment = ostree_sysroot_get_booted_deployment (sysroot);
-      if (!generate_update_variant (repo, booted_deployment, NULL, NULL, cancellable, error))
+      if (!generate_update_variant (repo, booted_deployment, NULL, NULL, NULL, cancellable,
+                                    error))
         return FALSE;
     }
 
@@ -418,6 +628,7 @@ typedef struct
 {
   RpmostreedTransaction parent;
   char *osname;
+  char *target;
   gboolean reboot;
 } RollbackTransaction;
 
@@ -434,10 +645,89 @@ rollback_transaction_finalize (GObject *object)
 
   self = (RollbackTransaction *)object;
   g_free (self->osname);
+  g_free (self->target);
 
   G_OBJECT_CLASS (rollback_transaction_parent_class)->finalize (object);
 }
 
+/* Minimum length of a checksum prefix identifying a deployment to roll back to, so that short
+ * versions aren't taken for checksums */
+#define ROLLBACK_TARGET_MIN_PREFIX 7
+
+/* Find the deployment to roll back to given @target: its index in the list of deployments,
+ * its checksum or a unique prefix of it, or its version. */
+static OstreeDeployment *
+find_rollback_target (OstreeSysroot *sysroot, const char *target, GError **error)
+{
+  g_autoptr (GPtrArray) deployments = ostree_sysroot_get_deployments (sysroot);
+  if (deployments->len == 0)
+    return (OstreeDeployment *)glnx_null_throw (error, "No deployments found");
+  guint64 index = 0;
+  if (g_ascii_string_to_unsigned (target, 10, 0, deployments->len - 1, &index, NULL))
+    return static_cast<OstreeDeployment *> (g_object_ref (deployments->pdata[index]));
+
+  OstreeRepo *repo = ostree_sysroot_repo (sysroot);
+  const gboolean by_checksum = strlen (target) >= ROLLBACK_TARGET_MIN_PREFIX;
+  g_autoptr (GPtrArray) matches = g_ptr_array_new ();
+  for (guint i = 0; i < deployments->len; i++)
+    {
+      auto deployment = static_cast<OstreeDeployment *> (deployments->pdata[i]);
+      const char *csum = ostree_deployment_get_csum (deployment);
+      if (by_checksum && g_str_has_prefix (csum, target))
+        {
+          g_ptr_array_add (matches, deployment);
+          continue;
+        }
+
+      g_autoptr (GVariant) commit = NULL;
+      if (!ostree_repo_load_commit (repo, csum, &commit, NULL, error))
+        return NULL;
+      g_autofree char *version = rpmostree_checksum_version (commit);
+      if (g_strcmp0 (version, target) == 0)
+        g_ptr_array_add (matches, deployment);
+    }
+
+  if (matches->len == 0)
+    return (OstreeDeployment *)glnx_null_throw (error, "No deployment matching '%s'", target);
+  if (matches->len > 1)
+    return (OstreeDeployment *)glnx_null_throw (
+        error, "Multiple deployments match '%s'; use the index of one instead", target);
+  return static_cast<OstreeDeployment *> (g_object_ref (matches->pdata[0]));
+}
+
+/* Check that @deployment can be made the default deployment of @osname; warn if it can't be
+ * updated anymore. */
+static gboolean
+check_rollback_target (OstreeSysroot *sysroot, OstreeDeployment *deployment, const char *osname,
+                       GError **error)
+{
+  const char *csum = ostree_deployment_get_csum (deployment);
+  int serial = ostree_deployment_get_deployserial (deployment);
+  if (ostree_deployment_is_staged (deployment))
+    return glnx_throw (error, "Deployment '%s.%d' is staged", csum, serial);
+  if (!g_str_equal (ostree_deployment_get_osname (deployment), osname))
+    return glnx_throw (error, "Deployment '%s.%d' belongs to stateroot '%s', not '%s'", csum,
+                       serial, ostree_deployment_get_osname (deployment), osname);
+
+  g_autoptr (RpmOstreeOrigin) origin = rpmostree_origin_parse_deployment (deployment, error);
+  if (!origin)
+    return glnx_prefix_error (error, "Deployment '%s.%d' has an incompatible origin", csum,
+                              serial);
+
+  auto r = rpmostree_origin_get_refspec (origin);
+  if (r.kind != rpmostreecxx::RefspecType::Ostree)
+    return TRUE;
+  g_autofree char *remote = NULL;
+  if (!ostree_parse_refspec (r.refspec.c_str (), &remote, NULL, error))
+    return FALSE;
+  g_autofree char *url = NULL;
+  if (remote && !ostree_repo_remote_get_url (ostree_sysroot_repo (sysroot), remote, &url, NULL))
+    rpmostree_output_message ("warning: Remote '%s' of deployment '%s.%d' no longer exists; "
+                              "it won't receive updates",
+                              remote, csum, serial);
+  return TRUE;
+}
+
 static gboolean
 rollback_transaction_execute (RpmostreedTransaction *transaction, GCancellable *cancellable,
                               GError **error)
@@ -451,6 +741,16 @@ rollback_transaction_execute (RpmostreedTransaction *transaction, GCancellable *
   ostree_sysroot_query_deployments_for (sysroot, self->osname, &pending_deployment,
                                         &rollback_deployment);
 
+  if (self->target)
+    {
+      g_clear_object (&rollback_deployment);
+      rollback_deployment = find_rollback_target (sysroot, self->target, error);
+      if (!rollback_deployment)
+        return FALSE;
+      if (!check_rollback_target (sysroot, rollback_deployment, self->osname, error))
+        return FALSE;
+    }
+
   if (!rollback_deployment && !pending_deployment) /* i.e. do we just have 1 deployment? */
     return glnx_throw (error, "No rollback deployment found");
   /* rollback with a staged deployment doesn't make any sense -- error out with hint */
@@ -468,6 +768,14 @@ rollback_transaction_execute (RpmostreedTransaction *transaction, GCancellable *
   g_autoptr (GPtrArray) old_deployments = ostree_sysroot_get_deployments (sysroot);
   g_autoptr (GPtrArray) new_deployments = g_ptr_array_new_with_free_func (g_object_unref);
 
+  if (self->target && ostree_deployment_equal (rollback_deployment, old_deployments->pdata[0]))
+    {
+      rpmostree_output_message ("Deployment '%s.%d' is already the default",
+                                ostree_deployment_get_csum (rollback_deployment),
+                                ostree_deployment_get_deployserial (rollback_deployment));
+      return TRUE;
+    }
+
   /* build out the reordered array; rollback is first now */
   g_ptr_array_add (new_deployments, g_object_ref (rollback_deployment));
 
@@ -517,8 +825,8 @@ rollback_transaction_init (RollbackTransaction *self)
 
 RpmostreedTransaction *
 rpmostreed_transaction_new_rollback (GDBusMethodInvocation *invocation, OstreeSysroot *sysroot,
-                                     const char *osname, gboolean reboot, GCancellable *cancellable,
-                                     GError **error)
+                                     const char *osname, const char *target, gboolean reboot,
+                                     GCancellable *cancellable, GError **error)
 {
 
   g_assert (G_IS_DBUS_METHOD_INVOCATION (invocation));
@@ -532,6 +840,7 @@ rpmostreed_transaction_new_rollback (GDBusMethodInvocation *invocation, OstreeSy
   if (self != NULL)
     {
       self->osname = g_strdup (osname);
+      self->target = g_strdup (target);
       self->reboot = reboot;
     }
 
@@ -574,21 +883,39 @@ deploy_transaction_finalize (GObject *object)
   G_OBJECT_CLASS (deploy_transaction_parent_class)->finalize (object);
 }
 
+/* Import the local RPM in @fd, verifying its signature first.  Unless @allow_unverified
+ * is set, an RPM which fails verification is rejected.  The result of the verification
+ * is recorded in @origin.
+ */
 static gboolean
-import_local_rpm (OstreeRepo *repo, OstreeSePolicy *policy, int *fd, char **out_sha256_nevra,
+import_local_rpm (OstreeRepo *repo, OstreeSePolicy *policy, RpmOstreeOrigin *origin,
+                  gboolean allow_unverified, int *fd, char **out_sha256_nevra,
                   GCancellable *cancellable, GError **error)
 {
+  g_autoptr (GError) verify_error = NULL;
+  gboolean verified = rpmostree_verify_rpm_signature (*fd, &verify_error);
+
   auto flags = rpmostreecxx::rpm_importer_flags_new_empty ();
   g_autoptr (RpmOstreeImporter) unpacker
       = rpmostree_importer_new_take_fd (fd, repo, NULL, *flags, policy, error);
   if (unpacker == NULL)
     return FALSE;
 
+  g_autofree char *nevra = rpmostree_importer_get_nevra (unpacker);
+  if (!verified)
+    {
+      if (!allow_unverified)
+        return glnx_throw (error, "Verifying %s: %s (use --allow-unverified-local to override)",
+                           nevra, verify_error->message);
+      rpmostree_output_message ("warning: Importing unverified package %s: %s", nevra,
+                                verify_error->message);
+    }
+
   g_autofree char *metadata_sha256 = NULL;
   if (!rpmostree_importer_run (unpacker, NULL, &metadata_sha256, cancellable, error))
     return FALSE;
 
-  g_autofree char *nevra = rpmostree_importer_get_nevra (unpacker);
+  rpmostree_origin_set_local_package_verified (origin, nevra, verified);
   g_autofree char *sha256_nevra = g_strconcat (metadata_sha256, ":", nevra, NULL);
 
   if (out_sha256_nevra)
@@ -619,7 +946,8 @@ unixfdlist_to_ptrarray (GUnixFDList *fdl)
 // XXX: Convert out_pkgs to return a rust::Vec<StringMapping> once all the related origin fields
 // have migrated to the treefile. Then simplify related treefile APIs.
 static gboolean
-import_many_local_rpms (OstreeRepo *repo, GUnixFDList *fdl, GPtrArray **out_pkgs,
+import_many_local_rpms (OstreeRepo *repo, GUnixFDList *fdl, RpmOstreeOrigin *origin,
+                        gboolean allow_unverified, GPtrArray **out_pkgs,
                         GCancellable *cancellable, GError **error)
 {
   /* Note that we record the SHA-256 of the RPM header in the origin to make sure that e.g.
@@ -653,7 +981,8 @@ import_many_local_rpms (OstreeRepo *repo, GUnixFDList *fdl, GPtrArray **out_pkgs
       fds->pdata[i] = GINT_TO_POINTER (-1);
       g_autofree char *sha256_nevra = NULL;
       /* Transfer fd to import */
-      if (!import_local_rpm (repo, policy, &fd, &sha256_nevra, cancellable, error))
+      if (!import_local_rpm (repo, policy, origin, allow_unverified, &fd, &sha256_nevra,
+                             cancellable, error))
         return FALSE;
 
       g_ptr_array_add (pkgs, util::move_nullify (sha256_nevra));
@@ -807,8 +1136,8 @@ get_driver_info (char **name, char **sd_unit, GError **error)
 }
 
 static gboolean
-deploy_transaction_execute (RpmostreedTransaction *transaction, GCancellable *cancellable,
-                            GError **error)
+deploy_transaction_execute_impl (RpmostreedTransaction *transaction, GCancellable *cancellable,
+                                 GError **error)
 {
   DeployTransaction *self = (DeployTransaction *)transaction;
   OstreeSysroot *sysroot = rpmostreed_transaction_get_sysroot (transaction);
@@ -917,6 +1246,19 @@ deploy_transaction_execute (RpmostreedTransaction *transaction, GCancellable *ca
   if (vardict_lookup_bool (self->options, "dry-run", FALSE)
       && vardict_lookup_bool (self->options, "download-only", FALSE))
     return glnx_throw (error, "Can't specify dry-run and download-only");
+  if (vardict_lookup_bool (self->options, "offline", FALSE)
+      && (vardict_lookup_bool (self->options, "cache-only", FALSE)
+          || vardict_lookup_bool (self->options, "dry-run", FALSE)
+          || vardict_lookup_bool (self->options, "apply-live", FALSE)))
+    return glnx_throw (error, "Can't specify offline with cache-only, dry-run or apply-live");
+  if (vardict_lookup_bool (self->options, "ephemeral", FALSE)
+      && (vardict_lookup_bool (self->options, "alternative", FALSE)
+          || vardict_lookup_bool (self->options, "offline", FALSE)
+          || vardict_lookup_bool (self->options, "apply-live", FALSE)))
+    return glnx_throw (error, "Can't specify ephemeral with alternative, offline or apply-live");
+  if (vardict_lookup_bool (self->options, "from-local-rpms", FALSE)
+      && vardict_lookup_bool (self->options, "cache-only", FALSE))
+    return glnx_throw (error, "Can't specify from-local-rpms and cache-only");
   if (override_replace_pkgs)
     return glnx_throw (error, "Non-local replacement overrides not implemented yet");
 
@@ -937,6 +1279,8 @@ deploy_transaction_execute (RpmostreedTransaction *transaction, GCancellable *ca
   const gboolean idempotent_layering = deploy_has_bool_option (self, "idempotent-layering");
   const gboolean download_only
       = ((self->flags & RPMOSTREE_TRANSACTION_DEPLOY_FLAG_DOWNLOAD_ONLY) > 0);
+  /* The update is only downloaded here, and deployed in an offline update boot */
+  const gboolean offline = deploy_has_bool_option (self, "offline");
   /* Mainly for the `install`, `module install`, and `override` commands */
   const gboolean no_pull_base
       = ((self->flags & RPMOSTREE_TRANSACTION_DEPLOY_FLAG_NO_PULL_BASE) > 0);
@@ -944,7 +1288,37 @@ deploy_transaction_execute (RpmostreedTransaction *transaction, GCancellable *ca
    * amount of metadata only to check if there's an upgrade */
   const gboolean download_metadata_only
       = ((self->flags & RPMOSTREE_TRANSACTION_DEPLOY_FLAG_DOWNLOAD_METADATA_ONLY) > 0);
+  /* Used by `upgrade --check --quick`; only check whether the base has an update, as
+   * cheaply as possible */
+  const gboolean quick_check = ((self->flags & RPMOSTREE_TRANSACTION_DEPLOY_FLAG_QUICK_CHECK) > 0);
+  if (quick_check)
+    g_assert (download_metadata_only);
+  /* Used by the layered automatic update policy; re-resolve layered packages against the
+   * current rpm-md on the same base */
+  const gboolean refresh_layered
+      = ((self->flags & RPMOSTREE_TRANSACTION_DEPLOY_FLAG_REFRESH_LAYERED) > 0);
+  if (refresh_layered)
+    g_assert (no_pull_base);
+  /* Used by the security automatic update policy; only deploy updates which address
+   * security advisories */
+  const gboolean security_only
+      = ((self->flags & RPMOSTREE_TRANSACTION_DEPLOY_FLAG_SECURITY_ONLY) > 0);
+  /* Whether this runs the configured automatic update policy; this is passed to update hooks */
+  const gboolean is_automatic
+      = ((self->flags & RPMOSTREE_TRANSACTION_DEPLOY_FLAG_AUTOMATIC_POLICY) > 0);
   const gboolean allow_inactive = deploy_has_bool_option (self, "allow-inactive");
+  const gboolean allow_protected = deploy_has_bool_option (self, "allow-protected");
+  const gboolean allow_unverified_local
+      = deploy_has_bool_option (self, "allow-unverified-local")
+        || !rpmostreed_get_verify_local_packages (rpmostreed_daemon_get ());
+  /* Write the new deployment as the alternative to the staged one rather than staging it */
+  const gboolean alternative = deploy_has_bool_option (self, "alternative");
+  /* Used by `testdeploy`; the deployment is removed again after its first boot */
+  const gboolean ephemeral = deploy_has_bool_option (self, "ephemeral");
+  if (ephemeral && !ostree_sysroot_get_booted_deployment (sysroot))
+    return glnx_throw (error, "Test deployments require a booted system");
+  guint transient_boots = 0;
+  g_variant_dict_lookup (self->options, "transient-boots", "u", &transient_boots);
   g_autofree const char *update_driver = deploy_has_string_option (self, "register-driver");
 
   g_autofree char **install_pkgs
@@ -961,6 +1335,14 @@ deploy_transaction_execute (RpmostreedTransaction *transaction, GCancellable *ca
       = vardict_lookup_strv_canonical (self->modifiers, "install-modules");
   g_autofree char **uninstall_modules
       = vardict_lookup_strv_canonical (self->modifiers, "uninstall-modules");
+  g_autofree char **reset_modules
+      = vardict_lookup_strv_canonical (self->modifiers, "reset-modules");
+  g_autofree char **switch_modules
+      = vardict_lookup_strv_canonical (self->modifiers, "switch-modules");
+  const gboolean enable_fips = vardict_lookup_bool (self->modifiers, "enable-fips", FALSE);
+  /* Used by `ex apply`; the desired state of the layered packages, overrides, kernel
+   * arguments and initramfs, as JSON */
+  auto apply_state = (const char *)vardict_lookup_ptr (self->modifiers, "ex-apply-state", "&s");
 
   gboolean is_install = FALSE;
   gboolean is_uninstall = FALSE;
@@ -968,11 +1350,21 @@ deploy_transaction_execute (RpmostreedTransaction *transaction, GCancellable *ca
 
   if (deploy_has_bool_option (self, "apply-live") && deploy_has_bool_option (self, "reboot"))
     return glnx_throw (error, "Cannot specify `apply-live` and `reboot`");
+  if (alternative
+      && (deploy_has_bool_option (self, "apply-live") || deploy_has_bool_option (self, "reboot")))
+    return glnx_throw (error, "Cannot specify `alternative` with `apply-live` or `reboot`");
   if (install_fileoverride_pkgs)
     return glnx_throw (error, "Non-local fileoverrides not implemented");
+  if (transient_boots > 0 && !install_pkgs)
+    return glnx_throw (error, "Can't specify transient-boots without install-packages");
+  /* The kernel arguments of the new base would be used instead */
+  if (enable_fips && (self->refspec || !no_pull_base))
+    return glnx_throw (error, "Cannot enable FIPS mode while upgrading or rebasing");
+  if (apply_state && (self->revision || !(self->refspec || no_pull_base)))
+    return glnx_throw (error, "Cannot apply a desired state while upgrading or deploying");
 
   /* In practice today */
-  if (no_pull_base)
+  if (no_pull_base && !refresh_layered && !apply_state)
     {
       /* this is a heuristic; by the end, once the proper switches are added, the two
        * commands can look indistinguishable at the D-Bus level */
@@ -981,7 +1373,7 @@ deploy_transaction_execute (RpmostreedTransaction *transaction, GCancellable *ca
       if (!is_override)
         {
           if (install_pkgs || install_local_pkgs || install_fileoverride_local_pkgs
-              || install_modules)
+              || install_modules || enable_fips)
             is_install = TRUE;
           else
             is_uninstall = TRUE;
@@ -994,6 +1386,9 @@ deploy_transaction_execute (RpmostreedTransaction *transaction, GCancellable *ca
   /* If we're not actively holding back pulling a new update and we're staying on the same
    * ref, then by definition we're upgrading. */
   const gboolean is_upgrade = (!no_pull_base && !self->refspec && !self->revision);
+  /* since the offline update boot just upgrades from the cache */
+  if (offline && (!is_upgrade || is_install || is_uninstall || is_override))
+    return glnx_throw (error, "Offline updates only support plain upgrades");
 
   /* Now set the transaction title before doing any work.
    * https://github.com/projectatomic/rpm-ostree/issues/454 */
@@ -1002,13 +1397,30 @@ deploy_transaction_execute (RpmostreedTransaction *transaction, GCancellable *ca
       /* special-case the automatic one, otherwise just use verbatim as title */
       const char *title = command_line;
       if (strstr (command_line, "--trigger-automatic-update-policy"))
-        title = download_metadata_only ? "automatic (check)" : "automatic (stage)";
+        {
+          if (download_metadata_only)
+            title = "automatic (check)";
+          else if (download_only)
+            title = "automatic (download)";
+          else if (cache_only)
+            title = "automatic (cache only)";
+          else if (refresh_layered)
+            title = "automatic (layered)";
+          else if (security_only)
+            title = "automatic (security)";
+          else
+            title = "automatic (stage)";
+        }
       rpmostree_transaction_set_title (RPMOSTREE_TRANSACTION (transaction), title);
     }
   else
     {
       g_autoptr (GString) txn_title = g_string_new ("");
-      if (is_install)
+      if (refresh_layered)
+        g_string_append (txn_title, "refresh layered packages");
+      else if (apply_state)
+        g_string_append (txn_title, "apply");
+      else if (is_install)
         g_string_append (txn_title, "install");
       else if (is_uninstall)
         g_string_append (txn_title, "uninstall");
@@ -1036,6 +1448,10 @@ deploy_transaction_execute (RpmostreedTransaction *transaction, GCancellable *ca
       if (uninstall_modules)
         g_string_append_printf (txn_title, "; module uninstall: %u",
                                 g_strv_length (uninstall_modules));
+      if (reset_modules)
+        g_string_append_printf (txn_title, "; module reset: %u", g_strv_length (reset_modules));
+      if (switch_modules)
+        g_string_append_printf (txn_title, "; module switch: %u", g_strv_length (switch_modules));
       if (install_pkgs)
         g_string_append_printf (txn_title, "; install: %u", g_strv_length (install_pkgs));
       if (install_local_pkgs)
@@ -1048,6 +1464,8 @@ deploy_transaction_execute (RpmostreedTransaction *transaction, GCancellable *ca
         g_string_append_printf (txn_title, "; module enable: %u", g_strv_length (enable_modules));
       if (install_modules)
         g_string_append_printf (txn_title, "; module install: %u", g_strv_length (install_modules));
+      if (enable_fips)
+        g_string_append (txn_title, "; enable FIPS");
 
       rpmostree_transaction_set_title (RPMOSTREE_TRANSACTION (transaction), txn_title->str);
     }
@@ -1066,6 +1484,24 @@ deploy_transaction_execute (RpmostreedTransaction *transaction, GCancellable *ca
         }
     }
 
+  /* Automatic updates may download more slowly than other operations */
+  std::optional<AutomaticBandwidthLimit> bandwidth_limit;
+  guint64 pull_bandwidth_limit = rpmostreed_get_bandwidth_limit (rpmostreed_daemon_get ());
+  gint64 automatic_bandwidth_limit
+      = rpmostreed_get_automatic_update_bandwidth_limit (rpmostreed_daemon_get ());
+  const gboolean prefetch = (self->flags & RPMOSTREE_TRANSACTION_DEPLOY_FLAG_PREFETCH) > 0;
+  if (prefetch)
+    {
+      pull_bandwidth_limit = rpmostreed_get_prefetch_bandwidth_limit (rpmostreed_daemon_get ());
+      bandwidth_limit.emplace (pull_bandwidth_limit);
+    }
+  else if ((self->flags & RPMOSTREE_TRANSACTION_DEPLOY_FLAG_AUTOMATIC_BANDWIDTH_LIMIT)
+           && automatic_bandwidth_limit >= 0)
+    {
+      pull_bandwidth_limit = automatic_bandwidth_limit;
+      bandwidth_limit.emplace (pull_bandwidth_limit);
+    }
+
   int upgrader_flags = 0;
   if (self->flags & RPMOSTREE_TRANSACTION_DEPLOY_FLAG_ALLOW_DOWNGRADE)
     upgrader_flags |= RPMOSTREE_SYSROOT_UPGRADER_FLAGS_ALLOW_OLDER;
@@ -1073,6 +1509,22 @@ deploy_transaction_execute (RpmostreedTransaction *transaction, GCancellable *ca
     upgrader_flags |= RPMOSTREE_SYSROOT_UPGRADER_FLAGS_DRY_RUN;
   if (deploy_has_bool_option (self, "lock-finalization"))
     upgrader_flags |= RPMOSTREE_SYSROOT_UPGRADER_FLAGS_LOCK_FINALIZATION;
+  if (deploy_has_bool_option (self, "enforce-container-sigpolicy")
+      || rpmostreed_get_enforce_container_sigpolicy (rpmostreed_daemon_get ()))
+    upgrader_flags |= RPMOSTREE_SYSROOT_UPGRADER_FLAGS_ENFORCE_CONTAINER_SIGPOLICY;
+  if (deploy_has_bool_option (self, "bypass-attestation"))
+    upgrader_flags |= RPMOSTREE_SYSROOT_UPGRADER_FLAGS_BYPASS_ATTESTATION;
+  if (deploy_has_bool_option (self, "from-local-rpms"))
+    upgrader_flags |= RPMOSTREE_SYSROOT_UPGRADER_FLAGS_FROM_LOCAL_RPMS;
+  if (deploy_has_bool_option (self, "force-policy-rebuild"))
+    upgrader_flags |= RPMOSTREE_SYSROOT_UPGRADER_FLAGS_FORCE_POLICY_REBUILD;
+  if (rpmostreed_get_restrict_layering (rpmostreed_daemon_get ())
+      && !deploy_has_bool_option (self, "bypass-layering-policy"))
+    upgrader_flags |= RPMOSTREE_SYSROOT_UPGRADER_FLAGS_RESTRICT_LAYERING;
+  if (rpmostreed_get_enable_fsverity (rpmostreed_daemon_get ()))
+    upgrader_flags |= RPMOSTREE_SYSROOT_UPGRADER_FLAGS_FSVERITY;
+  if (alternative)
+    upgrader_flags |= RPMOSTREE_SYSROOT_UPGRADER_FLAGS_ALTERNATIVE;
 
   /* DOWNLOAD_METADATA_ONLY isn't directly exposed at the D-Bus API level, so we shouldn't
    * ever run into these conflicting options */
@@ -1177,6 +1629,18 @@ deploy_transaction_execute (RpmostreedTransaction *transaction, GCancellable *ca
       rpmostree_origin_set_override_commit (origin, NULL);
     }
 
+  /* When upgrading from a remote with an update graph, deploy what it says rather than the
+   * tip of the ref */
+  g_autofree char *graph_target = NULL;
+  if (is_upgrade && !cache_only)
+    {
+      if (!get_update_graph_target (repo, origin, rpmostree_sysroot_upgrader_get_base (upgrader),
+                                    &graph_target, error))
+        return FALSE;
+      /* Only for this pull; it mustn't end up as a pin in the new origin */
+      rpmostree_sysroot_upgrader_set_pull_target (upgrader, graph_target);
+    }
+
   gboolean changed = FALSE;
   if (no_initramfs
       && (rpmostree_origin_get_regenerate_initramfs (origin)
@@ -1212,14 +1676,23 @@ deploy_transaction_execute (RpmostreedTransaction *transaction, GCancellable *ca
                                              util::rust_stringvec_from_strv (uninstall_pkgs),
                                              idempotent_layering, &changed, error))
         return FALSE;
-      if (rpmostree_origin_remove_modules (origin, util::rust_stringvec_from_strv (disable_modules),
-                                           TRUE))
-        changed = TRUE;
+      /* Uninstall before disabling, so that `module uninstall` and `module disable` of
+       * the same stream can be done in one transaction. */
       if (rpmostree_origin_remove_modules (
               origin, util::rust_stringvec_from_strv (uninstall_modules), FALSE))
         changed = TRUE;
+      if (!rpmostree_origin_disable_modules (
+              origin, util::rust_stringvec_from_strv (disable_modules), &changed, error))
+        return FALSE;
+      if (!rpmostree_origin_reset_modules (origin, util::rust_stringvec_from_strv (reset_modules),
+                                           &changed, error))
+        return FALSE;
     }
 
+  if (!rpmostree_origin_switch_module_streams (
+          origin, util::rust_stringvec_from_strv (switch_modules), &changed, error))
+    return FALSE;
+
   /* lazily loaded cache that's used in a few conditional blocks */
   g_autoptr (RpmOstreeRefSack) base_rsack = NULL;
 
@@ -1259,18 +1732,77 @@ deploy_transaction_execute (RpmostreedTransaction *transaction, GCancellable *ca
       auto pkgsv = util::rust_stringvec_from_strv (install_pkgs);
       if (!rpmostree_origin_add_packages (origin, pkgsv, idempotent_layering, &changed, error))
         return FALSE;
+      if (transient_boots > 0
+          && !rpmostree_origin_set_packages_transient (
+              origin, util::rust_stringvec_from_strv (install_pkgs), transient_boots, &changed,
+              error))
+        return FALSE;
+    }
+
+  /* FIPS mode: layer the packages it requires which the base lacks, regenerate the
+   * initramfs with the fips dracut module, and append the kernel arguments; all of which
+   * is tracked in the origin, so that it's kept when upgrading and rebasing */
+  if (enable_fips)
+    {
+      if (rpmostree_origin_get_fips (origin))
+        return glnx_throw (error, "FIPS mode is already enabled");
+
+      if (!base_rsack)
+        {
+          const char *base = rpmostree_sysroot_upgrader_get_base (upgrader);
+          base_rsack = rpmostree_get_refsack_for_commit (repo, base, cancellable, error);
+          if (base_rsack == NULL)
+            return FALSE;
+        }
+      rust::Vec<rust::String> fips_pkgs;
+      for (auto &pkg : rpmostreecxx::fips_packages ())
+        {
+          g_autoptr (GPtrArray) pkgs
+              = rpmostree_get_matching_packages (base_rsack->sack, pkg.c_str ());
+          if (pkgs->len == 0)
+            fips_pkgs.push_back (pkg);
+        }
+      if (!rpmostree_origin_add_packages (origin, fips_pkgs, TRUE, &changed, error))
+        return FALSE;
+
+      rust::Vec<rust::String> add_modules;
+      add_modules.push_back (rpmostreecxx::fips_dracut_module ());
+      rust::Vec<rust::String> none;
+      if (!rpmostree_origin_update_initramfs_dracut_config (origin, add_modules, none, none, none,
+                                                            none, &changed, error))
+        return FALSE;
+      rpmostree_origin_set_regenerate_initramfs (origin, TRUE,
+                                                 rpmostree_origin_get_initramfs_args (origin));
+
+      OstreeDeployment *merge_deployment
+          = rpmostree_sysroot_upgrader_get_merge_deployment (upgrader);
+      OstreeBootconfigParser *bootconfig = ostree_deployment_get_bootconfig (merge_deployment);
+      const char *current_kargs = ostree_bootconfig_parser_get (bootconfig, "options") ?: "";
+      CXX_TRY_VAR (fips_kargs, rpmostreecxx::fips_kargs (current_kargs), error);
+      g_autoptr (OstreeKernelArgs) kargs = ostree_kernel_args_from_string (current_kargs);
+      for (auto &arg : fips_kargs)
+        ostree_kernel_args_append (kargs, arg.c_str ());
+      g_autofree char *kargs_str = ostree_kernel_args_to_string (kargs);
+      rpmostree_origin_track_kargs (origin, current_kargs, kargs_str);
+      g_auto (GStrv) kargs_strv = ostree_kernel_args_to_strv (kargs);
+      rpmostree_sysroot_upgrader_set_kargs (upgrader, kargs_strv);
+
+      rpmostree_origin_set_fips (origin, TRUE);
+      changed = TRUE;
     }
 
-  if (rpmostree_origin_add_modules (origin, util::rust_stringvec_from_strv (enable_modules), TRUE))
-    changed = TRUE;
-  if (rpmostree_origin_add_modules (origin, util::rust_stringvec_from_strv (install_modules),
-                                    FALSE))
-    changed = TRUE;
+  if (!rpmostree_origin_add_modules (origin, util::rust_stringvec_from_strv (enable_modules), TRUE,
+                                     &changed, error))
+    return FALSE;
+  if (!rpmostree_origin_add_modules (origin, util::rust_stringvec_from_strv (install_modules),
+                                     FALSE, &changed, error))
+    return FALSE;
 
   if (install_local_pkgs != NULL)
     {
       g_autoptr (GPtrArray) pkgs = NULL;
-      if (!import_many_local_rpms (repo, install_local_pkgs, &pkgs, cancellable, error))
+      if (!import_many_local_rpms (repo, install_local_pkgs, origin, allow_unverified_local, &pkgs,
+                                   cancellable, error))
         return FALSE;
 
       if (pkgs->len > 0)
@@ -1287,8 +1819,8 @@ deploy_transaction_execute (RpmostreedTransaction *transaction, GCancellable *ca
   if (install_fileoverride_local_pkgs != NULL)
     {
       g_autoptr (GPtrArray) pkgs = NULL;
-      if (!import_many_local_rpms (repo, install_fileoverride_local_pkgs, &pkgs, cancellable,
-                                   error))
+      if (!import_many_local_rpms (repo, install_fileoverride_local_pkgs, origin,
+                                   allow_unverified_local, &pkgs, cancellable, error))
         return FALSE;
 
       if (pkgs->len > 0)
@@ -1408,10 +1940,11 @@ deploy_transaction_execute (RpmostreedTransaction *transaction, GCancellable *ca
       if (override_replace_local_pkgs)
         {
           g_autoptr (GPtrArray) pkgs = NULL;
-          if (!import_many_local_rpms (repo, override_replace_local_pkgs, &pkgs, cancellable,
-                                       error))
+          if (!import_many_local_rpms (repo, override_replace_local_pkgs, origin,
+                                       allow_unverified_local, &pkgs, cancellable, error))
             return FALSE;
 
+          rust::Vec<rust::String> replaced_names;
           for (guint i = 0; i < pkgs->len; i++)
             {
 
@@ -1424,12 +1957,15 @@ deploy_transaction_execute (RpmostreedTransaction *transaction, GCancellable *ca
 
               if (!rpmostree_decompose_nevra (pkg, &name, NULL, NULL, NULL, NULL, error))
                 return FALSE;
+              replaced_names.push_back (std::string (name));
 
               auto nevra = static_cast<const char *> (g_hash_table_lookup (name_to_nevra, name));
 
               if (nevra)
                 rpmostree_origin_remove_override_replace_local (origin, nevra);
             }
+          if (!allow_protected)
+            CXX_TRY (rpmostreecxx::check_protected_packages (replaced_names), error);
           if (pkgs->len > 0)
             {
               g_ptr_array_add (pkgs, NULL);
@@ -1441,24 +1977,125 @@ deploy_transaction_execute (RpmostreedTransaction *transaction, GCancellable *ca
       changed = TRUE;
     }
   auto treefile = (const char *)vardict_lookup_ptr (self->modifiers, "treefile", "&s");
+  if (treefile && !allow_protected)
+    {
+      CXX_TRY_VAR (tf, rpmostreecxx::treefile_new_from_string (treefile, true), error);
+      auto overridden = tf->get_packages_override_remove ();
+      for (auto &replacement : tf->get_packages_override_replace ())
+        {
+          for (auto &pkg : replacement.packages)
+            overridden.push_back (pkg);
+        }
+      CXX_TRY (rpmostreecxx::check_protected_packages (overridden), error);
+    }
   if (treefile && !rpmostree_origin_merge_treefile (origin, treefile, &changed, error))
     return FALSE;
 
+  /* The kernel arguments are tracked relative to those of the merge deployment, as for
+   * `kargs`; the tracked changes are then reapplied on the new base */
+  g_auto (GStrv) state_kargs = NULL;
+  if (apply_state)
+    {
+      OstreeDeployment *merge_deployment
+          = rpmostree_sysroot_upgrader_get_merge_deployment (upgrader);
+      OstreeBootconfigParser *bootconfig = ostree_deployment_get_bootconfig (merge_deployment);
+      const char *current_kargs = ostree_bootconfig_parser_get (bootconfig, "options") ?: "";
+      if (!rpmostree_origin_apply_state (origin, apply_state, current_kargs, allow_protected,
+                                         &changed, &state_kargs, error))
+        return FALSE;
+    }
+
   rpmostree_sysroot_upgrader_set_origin (upgrader, origin);
 
-  if (!no_pull_base)
+  /* For container images, checking for updates only needs the manifest and configuration,
+   * which are fetched when generating the update variant below */
+  const gboolean is_container_check
+      = download_metadata_only
+        && rpmostree_origin_get_refspec (origin).kind == rpmostreecxx::RefspecType::Container;
+
+  /* Quick checks go on with the usual check only if the base has an update, or if its
+   * fast path failed, e.g. because the remote has no summary */
+  if (quick_check)
     {
-      gboolean base_changed;
+      OstreeDeployment *booted_deployment = ostree_sysroot_get_booted_deployment (sysroot);
+      if (!booted_deployment)
+        return glnx_throw (error, "Not booted into any deployment");
+
+      gboolean available = FALSE;
+      g_autoptr (GError) local_error = NULL;
+      if (!rpmostreed_update_check_quick (booted_deployment, repo, &available, cancellable,
+                                          &local_error))
+        rpmostree_output_message ("%s; checking the commit instead", local_error->message);
+      else if (!available)
+        {
+          if (!glnx_shutil_rm_rf_at (AT_FDCWD, RPMOSTREE_AUTOUPDATES_CACHE_FILE, cancellable,
+                                     error))
+            return FALSE;
+          record_update_check ();
+          rpmostree_output_message ("No updates available.");
+          /* Note early return */
+          return TRUE;
+        }
+    }
 
+  /* Automatic updates leave phased updates which haven't reached us yet for later. For
+   * ostree refs, we only pull the commit object first to find out, so that a deferred
+   * update isn't downloaded; the update is still recorded below so that status can explain
+   * why it isn't staged. */
+  const gboolean check_rollout
+      = is_upgrade && !download_metadata_only
+        && (self->flags & RPMOSTREE_TRANSACTION_DEPLOY_FLAG_PHASED_ROLLOUT);
+  const gboolean rollout_commit_only
+      = check_rollout
+        && rpmostree_origin_get_refspec (origin).kind == rpmostreecxx::RefspecType::Ostree;
+  gboolean base_changed = FALSE;
+  gboolean rollout_deferred = FALSE;
+  if (!no_pull_base && !is_container_check)
+    {
       int flags = OSTREE_REPO_PULL_FLAGS_NONE;
       if (download_metadata_only)
         flags |= OSTREE_REPO_PULL_FLAGS_COMMIT_ONLY;
 
       g_autoptr (OstreeAsyncProgress) progress = ostree_async_progress_new ();
       rpmostreed_transaction_connect_download_progress (transaction, progress);
-      if (!rpmostree_sysroot_upgrader_pull_base (upgrader, NULL, (OstreeRepoPullFlags)flags,
+      if (pull_bandwidth_limit > 0)
+        {
+          auto throttle = g_new0 (PullThrottle, 1);
+          throttle->limit_bytes = pull_bandwidth_limit * 1024;
+          throttle->start_time = g_get_monotonic_time ();
+          throttle->cancellable = cancellable;
+          g_signal_connect_data (progress, "changed", G_CALLBACK (on_pull_progress_throttle),
+                                 throttle, (GClosureNotify)g_free, (GConnectFlags)0);
+        }
+      int first_flags = flags;
+      if (rollout_commit_only)
+        first_flags |= OSTREE_REPO_PULL_FLAGS_COMMIT_ONLY;
+      if (!rpmostree_sysroot_upgrader_pull_base (upgrader, NULL, (OstreeRepoPullFlags)first_flags,
                                                  progress, &base_changed, cancellable, error))
         return FALSE;
+
+      if (check_rollout && base_changed)
+        {
+          g_autoptr (GVariant) commit = NULL;
+          if (!ostree_repo_load_commit (repo, rpmostree_sysroot_upgrader_get_base (upgrader),
+                                        &commit, NULL, error))
+            return FALSE;
+          CXX_TRY_VAR (deferral, rpmostreecxx::rollout_deferral (*commit), error);
+          if (!deferral.empty ())
+            {
+              rpmostree_output_message ("Update deferred: %s", deferral.c_str ());
+              rollout_deferred = TRUE;
+            }
+          else if (rollout_commit_only)
+            {
+              /* It has reached us; now pull the rest of it */
+              gboolean unused_changed;
+              if (!rpmostree_sysroot_upgrader_pull_base (upgrader, NULL,
+                                                         (OstreeRepoPullFlags)flags, progress,
+                                                         &unused_changed, cancellable, error))
+                return FALSE;
+            }
+        }
       rpmostree_transaction_emit_progress_end (RPMOSTREE_TRANSACTION (transaction));
 
       if (base_changed)
@@ -1476,6 +2113,36 @@ deploy_transaction_execute (RpmostreedTransaction *transaction, GCancellable *ca
         }
     }
 
+  /* Likewise, the security policy leaves updates which don't address security advisories
+   * of the configured severity for later. Their advisories are found in the rpm-md of the
+   * booted deployment, as when checking for updates. */
+  gboolean no_security_fixes = FALSE;
+  g_autoptr (DnfSack) booted_sack = NULL;
+  if (security_only && !download_metadata_only && !rollout_deferred)
+    {
+      OstreeDeployment *booted_deployment = ostree_sysroot_get_booted_deployment (sysroot);
+      if (!booted_deployment)
+        return glnx_throw (error, "Not booted into any deployment");
+      if (!get_sack_for_booted (sysroot, repo, booted_deployment, &booted_sack, cancellable,
+                                error))
+        return FALSE;
+
+      g_autoptr (GVariant) update = NULL;
+      if (!rpmostreed_update_generate_variant (booted_deployment, NULL, graph_target, repo,
+                                               booted_sack, &update, cancellable, error))
+        return FALSE;
+
+      auto min_severity = rpmostreed_get_security_min_severity (rpmostreed_daemon_get ());
+      if (update && !update_has_security_advisories (update, min_severity))
+        {
+          rpmostree_output_message ("Update doesn't address security advisories%s; not staging it",
+                                    min_severity > RPM_OSTREE_ADVISORY_SEVERITY_NONE
+                                        ? " of the configured severity"
+                                        : "");
+          no_security_fixes = TRUE;
+        }
+    }
+
   /* let's figure out if those new overrides are valid and if so, canonicalize
    * them -- we could have just pulled the rpmdb dir before to do this, and then
    * do the full pull afterwards, though that would complicate the pull code and
@@ -1516,6 +2183,8 @@ deploy_transaction_execute (RpmostreedTransaction *transaction, GCancellable *ca
 
       g_ptr_array_add (pkgnames, NULL);
       auto pkgnamesv = util::rust_stringvec_from_strv ((char **)pkgnames->pdata);
+      if (!allow_protected)
+        CXX_TRY (rpmostreecxx::check_protected_packages (pkgnamesv), error);
       if (!rpmostree_origin_add_override_remove (origin, pkgnamesv, error))
         return FALSE;
 
@@ -1526,12 +2195,14 @@ deploy_transaction_execute (RpmostreedTransaction *transaction, GCancellable *ca
   /* Past this point we've computed the origin */
   auto final_refspec = rpmostree_origin_get_refspec (origin);
 
-  if (download_metadata_only)
+  if (download_metadata_only || rollout_deferred || no_security_fixes)
     {
       /* We have to short-circuit the usual path here; we already downloaded the ostree
        * metadata, so now we just need to update the rpmmd data (but only if we actually
        * have pkgs layered). This is still just a heuristic, since e.g. an InactiveRequest
-       * may in fact become active in the new base, but we don't have the full tree. */
+       * may in fact become active in the new base, but we don't have the full tree. This
+       * is also where updates deferred by their rollout or lacking security fixes end up,
+       * as if we only checked. */
 
       /* Note here that we use the booted deployment for releasever: the download metadata
        * only path is currently used only by the auto-update checker, and there we always
@@ -1543,14 +2214,15 @@ deploy_transaction_execute (RpmostreedTransaction *transaction, GCancellable *ca
           || !g_str_equal (self->osname, ostree_deployment_get_osname (booted_deployment)))
         return glnx_throw (error, "Refusing to download rpm-md for offline OS '%s'", self->osname);
 
-      g_autoptr (DnfSack) sack = NULL;
-      if (rpmostree_origin_has_packages (origin))
+      g_autoptr (DnfSack) sack = util::move_nullify (booted_sack);
+      if (!sack && rpmostree_origin_has_packages (origin) && !is_container_check && !quick_check)
         {
           if (!get_sack_for_booted (sysroot, repo, booted_deployment, &sack, cancellable, error))
             return FALSE;
         }
 
-      if (!generate_update_variant (repo, booted_deployment, NULL, sack, cancellable, error))
+      if (!generate_update_variant (repo, booted_deployment, NULL, graph_target, sack,
+                                    cancellable, error))
         return FALSE;
 
       /* Note early return */
@@ -1584,18 +2256,118 @@ deploy_transaction_execute (RpmostreedTransaction *transaction, GCancellable *ca
       /* Note early return; we stop short of actually writing the deployment */
       if (self->flags & RPMOSTREE_TRANSACTION_DEPLOY_FLAG_DOWNLOAD_ONLY)
         {
-          /* XXX: improve msg here; e.g. cache will be blown on next operation? */
-          if (changed)
-            rpmostree_output_message ("Update downloaded.");
-          else
+          /* Make sure downloaded packages survive until a cache-only operation uses them */
+          if (layering_changed)
+            {
+              if (!rpmostree_sysroot_upgrader_pin_pkgs (upgrader, cancellable, error))
+                return FALSE;
+            }
+          /* So that status shows the update waiting to be deployed, e.g. by automatic
+           * updates outside of their UpdateWindow */
+          if (changed && is_upgrade)
+            {
+              OstreeDeployment *booted_deployment
+                  = ostree_sysroot_get_booted_deployment (sysroot);
+              DnfSack *sack = rpmostree_sysroot_upgrader_get_sack (upgrader, error);
+              if (!generate_update_variant (repo, booted_deployment, NULL, graph_target, sack,
+                                            cancellable, error))
+                return FALSE;
+            }
+          if (!changed)
             rpmostree_output_message ("No changes.");
+          else if (offline)
+            {
+              ROSCXX_TRY (system_update_prepare (), error);
+              rpmostree_output_message (
+                  "Update downloaded; it will be applied in an offline update boot.");
+              if (deploy_has_bool_option (self, "reboot"))
+                {
+                  if (!check_sd_inhibitor_locks (cancellable, error))
+                    return FALSE;
+                  rpmostreed_daemon_reboot (rpmostreed_daemon_get ());
+                }
+            }
+          else
+            rpmostree_output_message ("Update downloaded.");
           return TRUE;
         }
 
+      /* The kernel arguments of the old base are replaced with those of the new one, and the
+       * changes made with `rpm-ostree kargs` are reapplied on top */
+      OstreeDeployment *merge_deployment
+          = rpmostree_sysroot_upgrader_get_merge_deployment (upgrader);
+      if (merge_deployment)
+        {
+          OstreeBootconfigParser *bootconfig
+              = ostree_deployment_get_bootconfig (merge_deployment);
+          g_autofree char *current_kargs
+              = state_kargs ? g_strjoinv (" ", state_kargs)
+                            : g_strdup (ostree_bootconfig_parser_get (bootconfig, "options") ?: "");
+          const char *merge_rev = ostree_deployment_get_csum (merge_deployment);
+          CXX_TRY_VAR (deploy_kargs,
+                       rpmostreecxx::kargs_for_deploy (
+                           *repo, current_kargs, merge_rev,
+                           rpmostree_sysroot_upgrader_get_base (upgrader),
+                           rpmostree_origin_get_kargs_append (origin),
+                           rpmostree_origin_get_kargs_delete (origin)),
+                       error);
+          for (auto &conflict : deploy_kargs.conflicts)
+            rpmostree_output_message ("Kernel argument conflict: %s", conflict.c_str ());
+          g_auto (GStrv) kargs_strv = rpmostree_cxx_string_vec_to_strv (deploy_kargs.kargs);
+          rpmostree_sysroot_upgrader_set_kargs (upgrader, kargs_strv);
+        }
+
+      ROSCXX_TRY (run_update_hooks ("pre-stage", self->osname, "", is_automatic), error);
+
       g_autoptr (OstreeDeployment) new_deployment = NULL;
       if (!rpmostree_sysroot_upgrader_deploy (upgrader, &new_deployment, cancellable, error))
         return FALSE;
 
+      /* If e.g. smoke tests fail, don't leave the update around to be booted into */
+      {
+        g_autoptr (GError) local_error = NULL;
+        if (!ROSCXX (run_update_hooks ("post-stage", self->osname,
+                                       ostree_deployment_get_csum (new_deployment), is_automatic),
+                     &local_error))
+          {
+            g_autoptr (GPtrArray) new_deployments = NULL;
+            if (alternative)
+              {
+                g_autoptr (GPtrArray) deployments = ostree_sysroot_get_deployments (sysroot);
+                new_deployments = g_ptr_array_new_with_free_func (g_object_unref);
+                for (guint i = 0; i < deployments->len; i++)
+                  {
+                    auto deployment = static_cast<OstreeDeployment *> (deployments->pdata[i]);
+                    if (!ostree_deployment_equal (deployment, new_deployment))
+                      g_ptr_array_add (new_deployments, g_object_ref (deployment));
+                  }
+              }
+            else
+              new_deployments
+                  = rpmostree_syscore_filter_deployments (sysroot, self->osname, TRUE, FALSE);
+            if (new_deployments
+                && !ostree_sysroot_write_deployments (sysroot, new_deployments, cancellable,
+                                                      error))
+              return FALSE;
+            g_propagate_error (error, util::move_nullify (local_error));
+            return FALSE;
+          }
+      }
+
+      if (ephemeral)
+        ROSCXX_TRY (testdeploy_mark (*sysroot, *new_deployment), error);
+
+      /* bootc refuses to update deployments with local changes; say who owns updates now */
+      const char *bootc_interop
+          = rpmostree_sysroot_get_bootc_interop (RPMOSTREE_SYSROOT (rpmostreed_sysroot_get ()));
+      if (*bootc_interop)
+        {
+          g_autoptr (RpmOstreeOrigin) new_origin = rpmostree_sysroot_upgrader_dup_origin (upgrader);
+          if (rpmostree_origin_may_require_local_assembly (new_origin))
+            rpmostree_output_message ("Note: This deployment has local changes, which bootc "
+                                      "doesn't support; update it with rpm-ostree");
+        }
+
       /* Are we rebasing?  May want to delete the previous ref */
       if (self->refspec && !(deploy_has_bool_option (self, "skip-purge")))
         {
@@ -1614,28 +2386,50 @@ deploy_transaction_execute (RpmostreedTransaction *transaction, GCancellable *ca
 
       /* Always write out an update variant on vanilla upgrades since it's clearly the most
        * up to date. If autoupdates "check" mode is enabled, the *next* run might yet
-       * overwrite it again because we always diff against the booted deployment. */
-      if (is_upgrade)
+       * overwrite it again because we always diff against the booted deployment. Same for
+       * refreshes of layered packages, so that status shows what they updated. */
+      if ((is_upgrade || refresh_layered) && !alternative)
         {
           OstreeDeployment *booted_deployment = ostree_sysroot_get_booted_deployment (sysroot);
 
           DnfSack *sack = rpmostree_sysroot_upgrader_get_sack (upgrader, error);
-          if (!generate_update_variant (repo, booted_deployment, new_deployment, sack, cancellable,
-                                        error))
+          if (!generate_update_variant (repo, booted_deployment, new_deployment, NULL, sack,
+                                        cancellable, error))
             return FALSE;
         }
 
+      if (alternative)
+        rpmostree_output_message (
+            "Wrote alternative deployment; run \"rpm-ostree finalize --alternative\" to boot "
+            "it instead of the staged deployment");
+
+      gboolean applied_live = FALSE;
       if (deploy_has_bool_option (self, "apply-live"))
         {
           g_autoptr (GVariantDict) dictv = g_variant_dict_new (NULL);
+          rpmostreed_add_live_restart_services (dictv);
           g_autoptr (GVariant) live_opts = g_variant_ref_sink (g_variant_dict_end (dictv));
           ROSCXX_TRY (transaction_apply_live (*sysroot, *live_opts), error);
+          applied_live = TRUE;
         }
-      else if (deploy_has_bool_option (self, "reboot"))
+      else if (self->flags & RPMOSTREE_TRANSACTION_DEPLOY_FLAG_APPLY_LIVE_IF_SAFE)
+        applied_live = maybe_apply_live (sysroot, new_deployment);
+
+      if (!applied_live && deploy_has_bool_option (self, "reboot"))
         {
           if (!check_sd_inhibitor_locks (cancellable, error))
             return FALSE;
-          rpmostreed_daemon_reboot (rpmostreed_daemon_get ());
+          /* Before taking a reboot slot, so that it isn't held if the hook fails */
+          ROSCXX_TRY (run_update_hooks ("pre-finalize", self->osname,
+                                        ostree_deployment_get_csum (new_deployment),
+                                        is_automatic),
+                      error);
+          gboolean may_reboot = TRUE;
+          if ((self->flags & RPMOSTREE_TRANSACTION_DEPLOY_FLAG_AUTOMATIC_REBOOT)
+              && !acquire_reboot_slot (&may_reboot, cancellable, error))
+            return FALSE;
+          if (may_reboot)
+            rpmostreed_daemon_reboot (rpmostreed_daemon_get ());
         }
     }
   else
@@ -1653,6 +2447,8 @@ deploy_transaction_execute (RpmostreedTransaction *transaction, GCancellable *ca
         }
       else if (is_upgrade)
         rpmostree_output_message ("No upgrade available.");
+      else if (refresh_layered)
+        rpmostree_output_message ("No updates of layered packages available.");
       else
         rpmostree_output_message ("No change.");
     }
@@ -1660,6 +2456,91 @@ deploy_transaction_execute (RpmostreedTransaction *transaction, GCancellable *ca
   return TRUE;
 }
 
+/* Classify a failure of the automatic update policy by the domain and code of @error:
+ * "network", "gpg", "depsolve", or "other". */
+static const char *
+classify_autoupdate_failure (GError *error)
+{
+  if (error->domain == OSTREE_GPG_ERROR)
+    return "gpg";
+  if (error->domain == G_RESOLVER_ERROR)
+    return "network";
+  if (error->domain == G_IO_ERROR)
+    {
+      switch (error->code)
+        {
+        case G_IO_ERROR_TIMED_OUT:
+        case G_IO_ERROR_HOST_NOT_FOUND:
+        case G_IO_ERROR_HOST_UNREACHABLE:
+        case G_IO_ERROR_NETWORK_UNREACHABLE:
+        case G_IO_ERROR_CONNECTION_REFUSED:
+        case G_IO_ERROR_CONNECTION_CLOSED:
+        case G_IO_ERROR_NOT_CONNECTED:
+        case G_IO_ERROR_PROXY_FAILED:
+          return "network";
+        default:
+          return "other";
+        }
+    }
+  if (error->domain == DNF_ERROR)
+    {
+      switch (error->code)
+        {
+        case DNF_ERROR_GPG_SIGNATURE_INVALID:
+          return "gpg";
+        case DNF_ERROR_REPO_NOT_AVAILABLE:
+        case DNF_ERROR_CANNOT_FETCH_SOURCE:
+          return "network";
+        case DNF_ERROR_PACKAGE_CONFLICTS:
+        case DNF_ERROR_PACKAGE_NOT_FOUND:
+        case DNF_ERROR_NO_SOLUTION:
+        case DNF_ERROR_REMOVAL_OF_PROTECTED_PKG:
+          return "depsolve";
+        default:
+          return "other";
+        }
+    }
+  return "other";
+}
+
+/* Failures of the automatic update policy are recorded so that it backs off, logged with
+ * their own MESSAGE_ID, and announced with the AutomaticUpdateFailed signal; the last one
+ * is also exposed as the AutomaticUpdateLastFailure property. */
+static gboolean
+deploy_transaction_execute (RpmostreedTransaction *transaction, GCancellable *cancellable,
+                            GError **error)
+{
+  DeployTransaction *self = (DeployTransaction *)transaction;
+  if (!(self->flags & RPMOSTREE_TRANSACTION_DEPLOY_FLAG_AUTOMATIC_POLICY))
+    return deploy_transaction_execute_impl (transaction, cancellable, error);
+
+  g_autoptr (GError) local_error = NULL;
+  if (deploy_transaction_execute_impl (transaction, cancellable, &local_error))
+    {
+      ROSCXX_TRY (autoupdate_record_success (), error);
+      rpmostreed_sysroot_refresh_autoupdate_failure (rpmostreed_sysroot_get ());
+      return TRUE;
+    }
+
+  const char *message = local_error->message;
+  const char *category = classify_autoupdate_failure (local_error);
+  CXX_TRY_VAR (failure, rpmostreecxx::autoupdate_record_failure (category, message), error);
+  rpmostreed_sysroot_refresh_autoupdate_failure (rpmostreed_sysroot_get ());
+  sd_journal_send ("MESSAGE_ID=" SD_ID128_FORMAT_STR,
+                   SD_ID128_FORMAT_VAL (RPMOSTREE_MESSAGE_AUTOMATIC_UPDATE_FAILED),
+                   "MESSAGE=Automatic update failed (%s error, %u in a row): %s; backing off for "
+                   "%" G_GUINT64_FORMAT "s",
+                   failure.category.c_str (), failure.count, message, failure.backoff_secs,
+                   "PRIORITY=%d", LOG_ERR, "FAILURE_CATEGORY=%s", failure.category.c_str (),
+                   "FAILURE_COUNT=%u", failure.count, "BACKOFF_SECS=%" G_GUINT64_FORMAT,
+                   failure.backoff_secs, NULL);
+  rpmostree_sysroot_emit_automatic_update_failed (RPMOSTREE_SYSROOT (rpmostreed_sysroot_get ()),
+                                                  failure.category.c_str (), message,
+                                                  failure.count);
+  g_propagate_error (error, util::move_nullify (local_error));
+  return FALSE;
+}
+
 static void
 deploy_transaction_class_init (DeployTransactionClass *clazz)
 {
@@ -1745,6 +2626,9 @@ deploy_flags_from_options (GVariantDict *dict, RpmOstreeTransactionDeployFlags d
     ret = set_deploy_flag (ret, RPMOSTREE_TRANSACTION_DEPLOY_FLAG_DRY_RUN, val);
   if (g_variant_dict_lookup (dict, "download-only", "b", &val))
     ret = set_deploy_flag (ret, RPMOSTREE_TRANSACTION_DEPLOY_FLAG_DOWNLOAD_ONLY, val);
+  /* offline updates are downloaded first */
+  if (vardict_lookup_bool (dict, "offline", FALSE))
+    ret = set_deploy_flag (ret, RPMOSTREE_TRANSACTION_DEPLOY_FLAG_DOWNLOAD_ONLY, TRUE);
   return ret;
 }
 
@@ -1851,6 +2735,8 @@ initramfs_etc_transaction_execute (RpmostreedTransaction *transaction, GCancella
   int upgrader_flags = 0;
   if (vardict_lookup_bool (self->options, "lock-finalization", FALSE))
     upgrader_flags |= RPMOSTREE_SYSROOT_UPGRADER_FLAGS_LOCK_FINALIZATION;
+  if (rpmostreed_get_enable_fsverity (rpmostreed_daemon_get ()))
+    upgrader_flags |= RPMOSTREE_SYSROOT_UPGRADER_FLAGS_FSVERITY;
 
   g_autoptr (RpmOstreeSysrootUpgrader) upgrader = rpmostree_sysroot_upgrader_new (
       sysroot, self->osname, static_cast<RpmOstreeSysrootUpgraderFlags> (upgrader_flags),
@@ -1870,16 +2756,40 @@ initramfs_etc_transaction_execute (RpmostreedTransaction *transaction, GCancella
     }
   else if (self->untrack)
     {
-      auto files = util::rust_stringvec_from_strv (self->untrack);
+      CXX_TRY_VAR (files,
+                   rpmostreecxx::initramfs_etc_normalize_paths (
+                       util::rust_stringvec_from_strv (self->untrack)),
+                   error);
       changed = rpmostree_origin_initramfs_etc_files_untrack (origin, files) || changed;
     }
 
+  /* Directories are tracked as a whole: their contents at the time of each
+   * deployment are included, so files added later don't need tracking. */
   if (self->track)
     {
-      auto files = util::rust_stringvec_from_strv (self->track);
+      CXX_TRY_VAR (files,
+                   rpmostreecxx::initramfs_etc_normalize_paths (
+                       util::rust_stringvec_from_strv (self->track)),
+                   error);
       changed = rpmostree_origin_initramfs_etc_files_track (origin, files) || changed;
     }
 
+  /* Even if the set of tracked files didn't change, their contents may have */
+  if (!changed && !self->force_sync && rpmostree_origin_has_initramfs_etc_files (origin))
+    {
+      OstreeDeployment *merge_deployment
+          = rpmostree_sysroot_upgrader_get_merge_deployment (upgrader);
+      char **overlays = ostree_deployment_get_overlay_initrds (merge_deployment);
+      auto files = rpmostree_origin_get_initramfs_etc_files (origin);
+      CXX_TRY_VAR (checksum, rpmostreecxx::initramfs_overlay_checksum (files, *cancellable),
+                   error);
+      if (!overlays || !g_strv_contains (overlays, checksum.c_str ()))
+        {
+          rpmostree_output_message ("Tracked files changed.");
+          changed = TRUE;
+        }
+    }
+
   if (!changed && !self->force_sync)
     {
       rpmostree_output_message ("No changes.");
@@ -1992,9 +2902,12 @@ initramfs_state_transaction_execute (RpmostreedTransaction *transaction, GCancel
   auto command_line
       = (const char *)vardict_lookup_ptr (self->options, "initiating-command-line", "&s");
 
-  int upgrader_flags = 0;
+  /* Don't reuse the current initramfs, even if none of the inputs we hash changed */
+  int upgrader_flags = RPMOSTREE_SYSROOT_UPGRADER_FLAGS_INITRAMFS_REGENERATE;
   if (vardict_lookup_bool (self->options, "lock-finalization", FALSE))
     upgrader_flags |= RPMOSTREE_SYSROOT_UPGRADER_FLAGS_LOCK_FINALIZATION;
+  if (rpmostreed_get_enable_fsverity (rpmostreed_daemon_get ()))
+    upgrader_flags |= RPMOSTREE_SYSROOT_UPGRADER_FLAGS_FSVERITY;
 
   g_autoptr (RpmOstreeSysrootUpgrader) upgrader = rpmostree_sysroot_upgrader_new (
       sysroot, self->osname, static_cast<RpmOstreeSysrootUpgraderFlags> (upgrader_flags),
@@ -2005,22 +2918,65 @@ initramfs_state_transaction_execute (RpmostreedTransaction *transaction, GCancel
   g_autoptr (RpmOstreeOrigin) origin = rpmostree_sysroot_upgrader_dup_origin (upgrader);
   gboolean current_regenerate = rpmostree_origin_get_regenerate_initramfs (origin);
   auto current_initramfs_args = rpmostree_origin_get_initramfs_args (origin);
+  const gboolean have_args = self->args != NULL && *self->args;
+
+  /* The modules and drivers for the generated dracut configuration */
+  g_autofree char **add_modules
+      = static_cast<char **> (vardict_lookup_strv_canonical (self->options, "add-modules"));
+  g_autofree char **omit_modules
+      = static_cast<char **> (vardict_lookup_strv_canonical (self->options, "omit-modules"));
+  g_autofree char **add_drivers
+      = static_cast<char **> (vardict_lookup_strv_canonical (self->options, "add-drivers"));
+  g_autofree char **remove_modules
+      = static_cast<char **> (vardict_lookup_strv_canonical (self->options, "remove-modules"));
+  g_autofree char **remove_drivers
+      = static_cast<char **> (vardict_lookup_strv_canonical (self->options, "remove-drivers"));
+  const gboolean have_dracut_config
+      = add_modules || omit_modules || add_drivers || remove_modules || remove_drivers;
+  if (have_dracut_config && !self->regenerate)
+    return glnx_throw (error, "Dracut modules and drivers require initramfs regeneration");
+  gboolean dracut_config_changed = FALSE;
+  if (have_dracut_config
+      && !rpmostree_origin_update_initramfs_dracut_config (
+          origin, util::rust_stringvec_from_strv (add_modules),
+          util::rust_stringvec_from_strv (omit_modules),
+          util::rust_stringvec_from_strv (add_drivers),
+          util::rust_stringvec_from_strv (remove_modules),
+          util::rust_stringvec_from_strv (remove_drivers), &dracut_config_changed, error))
+    return FALSE;
+
+  auto generator = (const char *)vardict_lookup_ptr (self->options, "generator", "&s");
+  if (generator && !self->regenerate)
+    return glnx_throw (error, "An initramfs generator requires initramfs regeneration");
+  gboolean generator_changed = FALSE;
+  if (generator
+      && !rpmostree_origin_set_initramfs_generator (origin, generator, &generator_changed, error))
+    return FALSE;
 
   /* We don't deep-compare the args right now, we assume if you were using them
-   * you want to rerun. This can be important if you edited a config file, which
-   * we can't really track without actually regenerating anyways.
+   * you want to rerun. This can be important if you edited a config file which
+   * isn't among the inputs hashed to decide whether upgrades regenerate it.
    */
-  if (current_regenerate == self->regenerate && (current_initramfs_args.empty ())
-      && (self->args == NULL || !*self->args))
+  if (current_regenerate == self->regenerate && (current_initramfs_args.empty ()) && !have_args
+      && !dracut_config_changed && !generator_changed)
     {
+      if (have_dracut_config)
+        return glnx_throw (error, "Dracut modules and drivers are already configured");
+      if (generator)
+        return glnx_throw (error, "Initramfs generator is already %s", generator);
       g_set_error (error, G_IO_ERROR, G_IO_ERROR_FAILED,
                    "initramfs regeneration state is already %s",
                    current_regenerate ? "enabled" : "disabled");
       return FALSE;
     }
 
-  auto argsv = util::rust_stringvec_from_strv (self->args);
-  rpmostree_origin_set_regenerate_initramfs (origin, self->regenerate, argsv);
+  /* Changing the modules and drivers or the generator keeps the current args, unless new
+   * ones are given */
+  if ((have_dracut_config || generator) && !have_args)
+    rpmostree_origin_set_regenerate_initramfs (origin, TRUE, current_initramfs_args);
+  else
+    rpmostree_origin_set_regenerate_initramfs (origin, self->regenerate,
+                                               util::rust_stringvec_from_strv (self->args));
   rpmostree_sysroot_upgrader_set_origin (upgrader, origin);
   rpmostree_sysroot_upgrader_set_caller_info (
       upgrader, command_line, rpmostreed_transaction_get_agent_id (RPMOSTREED_TRANSACTION (self)),
@@ -2155,6 +3111,20 @@ cleanup_transaction_execute (RpmostreedTransaction *transaction, GCancellable *c
   if (!ostree_sysroot_get_repo (sysroot, &repo, cancellable, error))
     return FALSE;
 
+  if (cleanup_pending || cleanup_rollback
+      || (self->flags & RPMOSTREE_TRANSACTION_CLEANUP_RETENTION))
+    {
+      ROSCXX_TRY (release_stale_pins (*sysroot), error);
+      ROSCXX_TRY (release_retention_pins (*sysroot, cleanup_pending || cleanup_rollback), error);
+      ROSCXX_TRY (release_alternative_pin (*sysroot, cleanup_pending || cleanup_rollback), error);
+    }
+
+  if (cleanup_pending)
+    {
+      CXX_TRY_VAR (had_offline_update, rpmostreecxx::system_update_cancel (), error);
+      if (had_offline_update)
+        rpmostree_output_message ("Cancelled pending offline update.");
+    }
   if (cleanup_pending || cleanup_rollback)
     {
       g_autoptr (GPtrArray) new_deployments = rpmostree_syscore_filter_deployments (
@@ -2177,6 +3147,45 @@ cleanup_transaction_execute (RpmostreedTransaction *transaction, GCancellable *c
           rpmostree_output_message ("Deployments unchanged.");
         }
     }
+  if (self->flags & RPMOSTREE_TRANSACTION_CLEANUP_RETENTION)
+    {
+      g_autoptr (GPtrArray) new_deployments = NULL;
+      if (!rpmostree_syscore_filter_deployments_retention (sysroot, self->osname,
+                                                           &new_deployments, error))
+        return FALSE;
+
+      if (new_deployments)
+        {
+          OstreeSysrootWriteDeploymentsOpts write_opts = { .do_postclean = FALSE };
+
+          if (!ostree_sysroot_write_deployments_with_options (sysroot, new_deployments, &write_opts,
+                                                              cancellable, error))
+            return FALSE;
+
+          self->flags = static_cast<RpmOstreeTransactionCleanupFlags> (
+              self->flags | RPMOSTREE_TRANSACTION_CLEANUP_BASE);
+        }
+      else
+        {
+          rpmostree_output_message ("No deployments to remove per the retention policy.");
+        }
+    }
+  if (self->flags & RPMOSTREE_TRANSACTION_CLEANUP_TESTDEPLOY)
+    {
+      CXX_TRY_VAR (changed, rpmostreecxx::testdeploy_cleanup (*sysroot), error);
+      if (changed)
+        self->flags = static_cast<RpmOstreeTransactionCleanupFlags> (
+            self->flags | RPMOSTREE_TRANSACTION_CLEANUP_BASE);
+    }
+  if (self->flags & RPMOSTREE_TRANSACTION_CLEANUP_CONTAINER_IMAGES)
+    {
+      CXX_TRY_VAR (n_pruned, rpmostreecxx::prune_container_images (*sysroot, *repo, 0), error);
+      if (n_pruned == 0)
+        rpmostree_output_message ("No unused container images.");
+      /* The objects are only freed by the prune in base cleanup */
+      self->flags = static_cast<RpmOstreeTransactionCleanupFlags> (
+          self->flags | RPMOSTREE_TRANSACTION_CLEANUP_BASE);
+    }
   if (self->flags & RPMOSTREE_TRANSACTION_CLEANUP_BASE)
     {
       if (!rpmostree_syscore_cleanup (sysroot, repo, cancellable, error))
@@ -2229,6 +3238,87 @@ rpmostreed_transaction_new_cleanup (GDBusMethodInvocation *invocation, OstreeSys
   return (RpmostreedTransaction *)self;
 }
 
+/* ================================ Pin ================================ */
+
+typedef struct
+{
+  RpmostreedTransaction parent;
+  char *deployment;
+  GVariantDict *options;
+} PinTransaction;
+
+typedef RpmostreedTransactionClass PinTransactionClass;
+
+GType pin_transaction_get_type (void);
+
+G_DEFINE_TYPE (PinTransaction, pin_transaction, RPMOSTREED_TYPE_TRANSACTION)
+
+static void
+pin_transaction_finalize (GObject *object)
+{
+  PinTransaction *self = (PinTransaction *)object;
+  g_free (self->deployment);
+  g_clear_pointer (&self->options, g_variant_dict_unref);
+
+  G_OBJECT_CLASS (pin_transaction_parent_class)->finalize (object);
+}
+
+static gboolean
+pin_transaction_execute (RpmostreedTransaction *transaction, GCancellable *cancellable,
+                         GError **error)
+{
+  PinTransaction *self = (PinTransaction *)transaction;
+  OstreeSysroot *sysroot = rpmostreed_transaction_get_sysroot (transaction);
+
+  const gboolean unpin = vardict_lookup_bool (self->options, "unpin", FALSE);
+  auto reason = static_cast<const char *> (vardict_lookup_ptr (self->options, "reason", "&s"));
+  gint64 expires = 0;
+  g_variant_dict_lookup (self->options, "expires", "x", &expires);
+  if (unpin && (reason || expires))
+    return glnx_throw (error, "Cannot specify a reason or an expiry when unpinning");
+  if (expires < 0)
+    return glnx_throw (error, "Invalid expiry: %" G_GINT64_FORMAT, expires);
+
+  ROSCXX_TRY (pin_deployment (*sysroot, self->deployment, unpin, reason ?: "", expires), error);
+
+  return TRUE;
+}
+
+static void
+pin_transaction_class_init (PinTransactionClass *clazz)
+{
+  GObjectClass *object_class = G_OBJECT_CLASS (clazz);
+  object_class->finalize = pin_transaction_finalize;
+
+  clazz->execute = pin_transaction_execute;
+}
+
+static void
+pin_transaction_init (PinTransaction *self)
+{
+}
+
+RpmostreedTransaction *
+rpmostreed_transaction_new_pin (GDBusMethodInvocation *invocation, OstreeSysroot *sysroot,
+                                const char *deployment, GVariant *options,
+                                GCancellable *cancellable, GError **error)
+{
+  g_assert (G_IS_DBUS_METHOD_INVOCATION (invocation));
+  g_assert (OSTREE_IS_SYSROOT (sysroot));
+
+  auto self = (PinTransaction *)g_initable_new (
+      pin_transaction_get_type (), cancellable, error, "invocation", invocation, "sysroot-path",
+      gs_file_get_path_cached (ostree_sysroot_get_path (sysroot)), NULL);
+
+  if (self != NULL)
+    {
+      self->deployment = g_strdup (deployment);
+      self->options = g_variant_dict_new (options);
+    }
+
+  return (RpmostreedTransaction *)self;
+}
+
 /* ================================ RefreshMd ================================ */
 
 typedef struct
@@ -2333,6 +3423,178 @@ rpmostreed_transaction_new_refresh_md (GDBusMethodInvocation *invocation, Ostree
   return (RpmostreedTransaction *)self;
 }
 
+/* ================================ ListRevisions ================================ */
+
+/* Number of commits or image tags shown per page by `deploy --list` */
+#define LIST_REVISIONS_PAGE_SIZE 20
+
+typedef struct
+{
+  RpmostreedTransaction parent;
+  char *osname;
+  guint page;
+  char *refspec;
+} ListRevisionsTransaction;
+
+typedef RpmostreedTransactionClass ListRevisionsTransactionClass;
+
+GType list_revisions_transaction_get_type (void);
+
+G_DEFINE_TYPE (ListRevisionsTransaction, list_revisions_transaction, RPMOSTREED_TYPE_TRANSACTION)
+
+static void
+list_revisions_transaction_finalize (GObject *object)
+{
+  ListRevisionsTransaction *self;
+
+  self = (ListRevisionsTransaction *)object;
+  g_free (self->osname);
+  g_free (self->refspec);
+
+  G_OBJECT_CLASS (list_revisions_transaction_parent_class)->finalize (object);
+}
+
+typedef struct
+{
+  const char *booted_checksum;
+  guint skip;
+  guint remaining;
+} ListRevisionsVisitorClosure;
+
+static gboolean
+list_revisions_visitor (OstreeRepo *repo, const char *checksum, GVariant *commit,
+                        gpointer user_data, gboolean *out_stop, GError **error)
+{
+  auto closure = static_cast<ListRevisionsVisitorClosure *> (user_data);
+
+  if (closure->skip > 0)
+    {
+      closure->skip--;
+      return TRUE;
+    }
+
+  g_autoptr (GVariant) metadict = g_variant_get_child_value (commit, 0);
+  const char *version = NULL;
+  if (!g_variant_lookup (metadict, OSTREE_COMMIT_META_KEY_VERSION, "&s", &version))
+    version = "(no version)";
+  g_autofree char *timestamp
+      = rpmostree_timestamp_str_from_unix_utc (ostree_commit_get_timestamp (commit));
+  const gboolean booted = g_strcmp0 (checksum, closure->booted_checksum) == 0;
+  rpmostree_output_message ("%s %-20s %s %s%s", booted ? "●" : " ", version, timestamp, checksum,
+                            booted ? " (booted)" : "");
+
+  closure->remaining--;
+  *out_stop = closure->remaining == 0;
+  return TRUE;
+}
+
+static gboolean
+list_revisions_transaction_execute (RpmostreedTransaction *transaction, GCancellable *cancellable,
+                                    GError **error)
+{
+  ListRevisionsTransaction *self = (ListRevisionsTransaction *)transaction;
+  OstreeSysroot *sysroot = rpmostreed_transaction_get_sysroot (transaction);
+  OstreeRepo *repo = ostree_sysroot_repo (sysroot);
+
+  g_autoptr (OstreeDeployment) merge_deployment
+      = rpmostree_syscore_get_origin_merge_deployment (sysroot, self->osname);
+  if (merge_deployment == NULL)
+    return glnx_throw (error, "No deployments found for os %s", self->osname);
+  g_autoptr (RpmOstreeOrigin) origin = rpmostree_origin_parse_deployment (merge_deployment, error);
+  if (!origin)
+    return FALSE;
+
+  auto r = rpmostree_origin_get_refspec (origin);
+  if (self->refspec)
+    {
+      if (rpmostreecxx::refspec_classify (self->refspec) != rpmostreecxx::RefspecType::Container)
+        return glnx_throw (error, "Not a container image reference: %s", self->refspec);
+      r.kind = rpmostreecxx::RefspecType::Container;
+      r.refspec = self->refspec;
+    }
+  else if (r.kind == rpmostreecxx::RefspecType::Checksum)
+    return glnx_throw (error, "Cannot list revisions while pinned to commit");
+
+  const guint skip = (self->page - 1) * LIST_REVISIONS_PAGE_SIZE;
+  if (r.kind == rpmostreecxx::RefspecType::Container)
+    {
+      rpmostree_output_message ("Listing tags of %s (page %u)", r.refspec.c_str (), self->page);
+      g_autoptr (GKeyFile) origin_kf = rpmostree_origin_dup_keyfile (origin);
+      auto pull_config
+          = rpmostreecxx::rpmostreed_get_container_pull_config (rpmostreed_daemon_get ());
+      ROSCXX_TRY (list_container_tags (r.refspec, *origin_kf, pull_config, skip,
+                                       LIST_REVISIONS_PAGE_SIZE),
+                  error);
+      return TRUE;
+    }
+
+  OstreeDeployment *booted_deployment = ostree_sysroot_get_booted_deployment (sysroot);
+  g_autofree char *booted_checksum = NULL;
+  if (booted_deployment != NULL)
+    {
+      CXX_TRY_VAR (layeredmeta,
+                   rpmostreecxx::deployment_layeredmeta_load (*repo, *booted_deployment), error);
+      booted_checksum = g_strdup (layeredmeta.base_commit.c_str ());
+    }
+
+  rpmostree_output_message ("Listing commits of %s (page %u)", r.refspec.c_str (), self->page);
+  rpmostreed_transaction_connect_signature_progress (transaction, repo);
+  g_autoptr (OstreeAsyncProgress) progress = ostree_async_progress_new ();
+  rpmostreed_transaction_connect_download_progress (transaction, progress);
+  ListRevisionsVisitorClosure closure = { booted_checksum, skip, LIST_REVISIONS_PAGE_SIZE };
+  if (!rpmostreed_repo_pull_ancestry (repo, r.refspec.c_str (), list_revisions_visitor, &closure,
+                                      progress, cancellable, error))
+    return FALSE;
+  rpmostree_transaction_emit_progress_end (RPMOSTREE_TRANSACTION (transaction));
+
+  if (closure.remaining == LIST_REVISIONS_PAGE_SIZE)
+    rpmostree_output_message ("No commits found on page %u.", self->page);
+  else if (closure.remaining == 0)
+    rpmostree_output_message ("Use --list-page=%u to show older commits.", self->page + 1);
+
+  return TRUE;
+}
+
+static void
+list_revisions_transaction_class_init (ListRevisionsTransactionClass *clazz)
+{
+  GObjectClass *object_class;
+
+  object_class = G_OBJECT_CLASS (clazz);
+  object_class->finalize = list_revisions_transaction_finalize;
+
+  clazz->execute = list_revisions_transaction_execute;
+}
+
+static void
+list_revisions_transaction_init (ListRevisionsTransaction *self)
+{
+}
+
+RpmostreedTransaction *
+rpmostreed_transaction_new_list_revisions (GDBusMethodInvocation *invocation,
+                                           OstreeSysroot *sysroot, const char *osname,
+                                           guint page, const char *refspec,
+                                           GCancellable *cancellable, GError **error)
+{
+  g_assert (G_IS_DBUS_METHOD_INVOCATION (invocation));
+  g_assert (OSTREE_IS_SYSROOT (sysroot));
+  g_assert (osname != NULL);
+
+  auto self = (ListRevisionsTransaction *)g_initable_new (
+      list_revisions_transaction_get_type (), cancellable, error, "invocation", invocation,
+      "sysroot-path", gs_file_get_path_cached (ostree_sysroot_get_path (sysroot)), NULL);
+
+  if (self != NULL)
+    {
+      self->osname = g_strdup (osname);
+      self->page = page;
+      self->refspec = g_strdup (refspec);
+    }
+
+  return (RpmostreedTransaction *)self;
+}
+
 /* ================================ ModifyYumRepo ================================ */
 
 typedef struct
@@ -2517,8 +3779,19 @@ finalize_deployment_transaction_execute (RpmostreedTransaction *transaction,
   if (!g_str_equal (ostree_deployment_get_osname (default_deployment), self->osname))
     return glnx_throw (error, "Staged deployment is not for osname '%s'", self->osname);
 
-  CXX_TRY_VAR (layeredmeta, rpmostreecxx::deployment_layeredmeta_load (*repo, *default_deployment),
-               error);
+  /* Either the staged deployment or its alternative becomes the default */
+  const gboolean select_alternative = vardict_lookup_bool (self->options, "alternative", FALSE);
+  OstreeDeployment *target = default_deployment;
+  if (select_alternative)
+    {
+      CXX_TRY_VAR (alternative_index, rpmostreecxx::alternative_deployment_index (*sysroot),
+                   error);
+      if (alternative_index < 0)
+        return glnx_throw (error, "No alternative deployment found");
+      target = static_cast<OstreeDeployment *> (deployments->pdata[alternative_index]);
+    }
+
+  CXX_TRY_VAR (layeredmeta, rpmostreecxx::deployment_layeredmeta_load (*repo, *target), error);
   const char *checksum = layeredmeta.base_commit.c_str ();
 
   auto expected_checksum = (char *)vardict_lookup_ptr (self->options, "checksum", "&s");
@@ -2527,13 +3800,28 @@ finalize_deployment_transaction_execute (RpmostreedTransaction *transaction,
   if (!expected_checksum && !allow_missing_checksum)
     return glnx_throw (error, "Missing expected checksum");
   if (expected_checksum && !g_str_equal (checksum, expected_checksum))
-    return glnx_throw (error, "Expected staged base checksum %s, but found %s", expected_checksum,
-                       checksum);
+    return glnx_throw (error, "Expected %s base checksum %s, but found %s",
+                       select_alternative ? "alternative" : "staged", expected_checksum, checksum);
+
+  const gboolean soft_reboot = vardict_lookup_bool (self->options, "soft-reboot", FALSE);
+  if (soft_reboot)
+    {
+      OstreeDeployment *booted = ostree_sysroot_get_booted_deployment (sysroot);
+      if (!booted)
+        return glnx_throw (error, "Not booted into an OSTree system");
+      const char *reason = get_full_reboot_reason (booted, target);
+      if (reason)
+        return glnx_throw (error, "Cannot soft-reboot since %s; reboot instead", reason);
+    }
 
   // Check for inhibitor locks before unlocking staged deployment.
   if (!check_sd_inhibitor_locks (cancellable, error))
     return FALSE;
 
+  ROSCXX_TRY (run_update_hooks ("pre-finalize", self->osname, ostree_deployment_get_csum (target),
+                                FALSE),
+              error);
+
   if (unlink (_OSTREE_SYSROOT_RUNSTATE_STAGED_LOCKED) < 0)
     {
       if (errno != ENOENT)
@@ -2543,13 +3831,43 @@ finalize_deployment_transaction_execute (RpmostreedTransaction *transaction,
         return glnx_throw (error, "Staged deployment already unlocked");
     }
 
+  /* Drop the staged deployment, and make the alternative the default */
+  if (select_alternative)
+    {
+      g_autoptr (GPtrArray) new_deployments = g_ptr_array_new_with_free_func (g_object_unref);
+      g_ptr_array_add (new_deployments, g_object_ref (target));
+      for (guint i = 1; i < deployments->len; i++)
+        {
+          auto deployment = static_cast<OstreeDeployment *> (deployments->pdata[i]);
+          if (!ostree_deployment_equal (deployment, target))
+            g_ptr_array_add (new_deployments, g_object_ref (deployment));
+        }
+      OstreeSysrootWriteDeploymentsOpts write_opts = { .do_postclean = FALSE };
+      if (!ostree_sysroot_write_deployments_with_options (sysroot, new_deployments, &write_opts,
+                                                          cancellable, error))
+        return FALSE;
+      ROSCXX_TRY (release_stale_pins (*sysroot), error);
+      ROSCXX_TRY (release_retention_pins (*sysroot, TRUE), error);
+      ROSCXX_TRY (release_alternative_pin (*sysroot, TRUE), error);
+      rpmostree_output_message ("Selected alternative deployment %s",
+                                ostree_deployment_get_csum (target));
+    }
+
   /* And bump sysroot mtime so we reload... a bit awkward, though this is similar to
    * libostree itself doing this for `ostree admin unlock` (and possibly an `ostree admin`
    * version of `rpm-ostree finalize-deployment`). */
   (void)rpmostree_syscore_bump_mtime (sysroot, NULL);
 
-  sd_journal_print (LOG_INFO, "Finalized deployment; rebooting into %s", checksum);
-  rpmostreed_daemon_reboot (rpmostreed_daemon_get ());
+  if (soft_reboot)
+    {
+      sd_journal_print (LOG_INFO, "Finalizing deployment; soft-rebooting into %s", checksum);
+      rpmostreed_daemon_soft_reboot (rpmostreed_daemon_get ());
+    }
+  else
+    {
+      sd_journal_print (LOG_INFO, "Finalized deployment; rebooting into %s", checksum);
+      rpmostreed_daemon_reboot (rpmostreed_daemon_get ());
+    }
   return TRUE;
 }
 
@@ -2642,9 +3960,39 @@ kernel_arg_apply (KernelArgTransaction *self, RpmOstreeSysrootUpgrader *upgrader
       return TRUE;
     }
 
+  g_autofree char *kargs_str = ostree_kernel_args_to_string (kargs);
+
+  /* Show what is going to change */
+  auto diff = rpmostreecxx::kargs_diff (self->existing_kernel_args, kargs_str);
+  if (diff.empty ())
+    rpmostree_output_message ("Kernel arguments reordered");
+  else
+    {
+      rpmostree_output_message ("Kernel arguments:");
+      for (auto &line : diff)
+        rpmostree_output_message ("  %s", line.c_str ());
+    }
+
+  /* And refuse obviously broken kernel arguments, unless forced */
+  auto validation = rpmostreecxx::kargs_validate (self->existing_kernel_args, kargs_str);
+  for (auto &warning : validation.warnings)
+    rpmostree_output_message ("warning: %s", warning.c_str ());
+  const gboolean force = vardict_lookup_bool (self->options, "force", FALSE);
+  for (auto &problem : validation.errors)
+    {
+      if (!force)
+        return glnx_throw (error, "%s (use --force to apply anyway)", problem.c_str ());
+      rpmostree_output_message ("warning: %s", problem.c_str ());
+    }
+
   g_auto (GStrv) kargs_strv = ostree_kernel_args_to_strv (kargs);
   rpmostree_sysroot_upgrader_set_kargs (upgrader, kargs_strv);
 
+  /* Track the changes in the origin, so that they're reapplied when rebasing */
+  g_autoptr (RpmOstreeOrigin) origin = rpmostree_sysroot_upgrader_dup_origin (upgrader);
+  rpmostree_origin_track_kargs (origin, self->existing_kernel_args, kargs_str);
+  rpmostree_sysroot_upgrader_set_origin (upgrader, origin);
+
   if (!rpmostree_sysroot_upgrader_deploy (upgrader, NULL, cancellable, error))
     return FALSE;
 
@@ -2736,12 +4084,124 @@ kernel_arg_apply_patching (KernelArgTransaction *self, RpmOstreeSysrootUpgrader
         }
     }
 
+  /* Profiles are toggled last, and tracked in the origin along with the arguments they add.
+   * They're defined in the deployment we're changing, not in the booted one. */
+  g_autofree char **enable_profiles
+      = static_cast<char **> (vardict_lookup_strv_canonical (self->options, "enable-profiles"));
+  g_autofree char **disable_profiles
+      = static_cast<char **> (vardict_lookup_strv_canonical (self->options, "disable-profiles"));
+  g_autoptr (OstreeKernelArgs) profiles_kargs = NULL;
+  if (enable_profiles || disable_profiles)
+    {
+      OstreeSysroot *sysroot = rpmostreed_transaction_get_sysroot (RPMOSTREED_TRANSACTION (self));
+      OstreeDeployment *merge_deployment
+          = rpmostree_sysroot_upgrader_get_merge_deployment (upgrader);
+      g_autofree char *deployment_path
+          = ostree_sysroot_get_deployment_dirpath (sysroot, merge_deployment);
+      glnx_autofd int deployment_dfd = -1;
+      if (!glnx_opendirat (ostree_sysroot_get_fd (sysroot), deployment_path, TRUE,
+                           &deployment_dfd, error))
+        return FALSE;
+      g_autoptr (RpmOstreeOrigin) origin = rpmostree_sysroot_upgrader_dup_origin (upgrader);
+      g_autofree char *kargs_str = ostree_kernel_args_to_string (kargs);
+      g_autofree char *new_kargs_str = NULL;
+      if (!rpmostree_origin_apply_kargs_profiles (
+              origin, deployment_dfd, kargs_str,
+              util::rust_stringvec_from_strv (enable_profiles),
+              util::rust_stringvec_from_strv (disable_profiles), &new_kargs_str, error))
+        return FALSE;
+      rpmostree_sysroot_upgrader_set_origin (upgrader, origin);
+      profiles_kargs = ostree_kernel_args_from_string (new_kargs_str);
+      kargs = profiles_kargs;
+      changed = TRUE;
+    }
+
   if (!kernel_arg_apply (self, upgrader, kargs, changed, cancellable, error))
     return FALSE;
 
   return TRUE;
 }
 
+/* Change the global kernel arguments, in place in all the deployments rather than by
+ * creating a new one; they are recorded once all of them were changed, so that this
+ * can be retried if it fails midway */
+static gboolean
+kernel_arg_apply_global (KernelArgTransaction *self, OstreeSysroot *sysroot,
+                         GCancellable *cancellable, GError **error)
+{
+  CXX_TRY_VAR (old_global, rpmostreecxx::kargs_global (), error);
+  CXX_TRY_VAR (new_global,
+               rpmostreecxx::kargs_global_change (
+                   old_global, util::rust_stringvec_from_strv (self->kernel_args_added),
+                   util::rust_stringvec_from_strv (self->kernel_args_deleted)),
+               error);
+
+  g_autoptr (GString) old_str = g_string_new ("");
+  for (auto &arg : old_global)
+    g_string_append_printf (old_str, "%s%s", old_str->len ? " " : "", arg.c_str ());
+  g_autoptr (GString) new_str = g_string_new ("");
+  for (auto &arg : new_global)
+    g_string_append_printf (new_str, "%s%s", new_str->len ? " " : "", arg.c_str ());
+  auto diff = rpmostreecxx::kargs_diff (old_str->str, new_str->str);
+  if (diff.empty ())
+    {
+      rpmostree_output_message ("No changes.");
+      return TRUE;
+    }
+  rpmostree_output_message ("Global kernel arguments:");
+  for (auto &line : diff)
+    rpmostree_output_message ("  %s", line.c_str ());
+
+  /* Validate the new kernel arguments of all the deployments before changing any */
+  const gboolean force = vardict_lookup_bool (self->options, "force", FALSE);
+  g_autoptr (GPtrArray) deployments = ostree_sysroot_get_deployments (sysroot);
+  g_autoptr (GPtrArray) new_kargs = g_ptr_array_new_with_free_func (g_free);
+  for (guint i = 0; i < deployments->len; i++)
+    {
+      auto deployment = static_cast<OstreeDeployment *> (deployments->pdata[i]);
+      OstreeBootconfigParser *bootconfig = ostree_deployment_get_bootconfig (deployment);
+      const char *kargs = ostree_bootconfig_parser_get (bootconfig, "options") ?: "";
+      auto kargs_new = rpmostreecxx::kargs_apply_global (kargs, old_global, new_global);
+      g_autofree char *name = g_strdup_printf ("%s.%d", ostree_deployment_get_csum (deployment),
+                                               ostree_deployment_get_deployserial (deployment));
+      auto validation = rpmostreecxx::kargs_validate (kargs, kargs_new);
+      for (auto &warning : validation.warnings)
+        rpmostree_output_message ("warning: deployment %s: %s", name, warning.c_str ());
+      for (auto &problem : validation.errors)
+        {
+          if (!force)
+            return glnx_throw (error, "deployment %s: %s (use --force to apply anyway)", name,
+                               problem.c_str ());
+          rpmostree_output_message ("warning: deployment %s: %s", name, problem.c_str ());
+        }
+      g_ptr_array_add (new_kargs, g_strdup (kargs_new.c_str ()));
+    }
+
+  for (guint i = 0; i < deployments->len; i++)
+    {
+      auto deployment = static_cast<OstreeDeployment *> (deployments->pdata[i]);
+      OstreeBootconfigParser *bootconfig = ostree_deployment_get_bootconfig (deployment);
+      const char *kargs = ostree_bootconfig_parser_get (bootconfig, "options") ?: "";
+      auto kargs_new = static_cast<char *> (new_kargs->pdata[i]);
+      if (g_str_equal (kargs, kargs_new))
+        continue;
+      if (!ostree_sysroot_deployment_set_kargs_in_place (sysroot, deployment, kargs_new,
+                                                         cancellable, error))
+        return FALSE;
+    }
+
+  CXX_TRY (rpmostreecxx::kargs_global_store (new_global), error);
+
+  if (vardict_lookup_bool (self->options, "reboot", FALSE))
+    {
+      if (!check_sd_inhibitor_locks (cancellable, error))
+        return FALSE;
+      rpmostreed_daemon_reboot (rpmostreed_daemon_get ());
+    }
+
+  return TRUE;
+}
+
 static gboolean
 kernel_arg_transaction_execute (RpmostreedTransaction *transaction, GCancellable *cancellable,
                                 GError **error)
@@ -2750,6 +4210,9 @@ kernel_arg_transaction_execute (RpmostreedTransaction *transaction, GCancellable
 
   KernelArgTransaction *self = (KernelArgTransaction *)transaction;
   OstreeSysroot *sysroot = rpmostreed_transaction_get_sysroot (transaction);
+
+  if (vardict_lookup_bool (self->options, "global", FALSE))
+    return kernel_arg_apply_global (self, sysroot, cancellable, error);
   auto command_line = static_cast<const char *> (
       vardict_lookup_ptr (self->options, "initiating-command-line", "&s"));
 
@@ -2759,6 +4222,8 @@ kernel_arg_transaction_execute (RpmostreedTransaction *transaction, GCancellable
   upgrader_flags |= RPMOSTREE_SYSROOT_UPGRADER_FLAGS_PKGCACHE_ONLY;
   if (vardict_lookup_bool (self->options, "lock-finalization", FALSE))
     upgrader_flags |= RPMOSTREE_SYSROOT_UPGRADER_FLAGS_LOCK_FINALIZATION;
+  if (rpmostreed_get_enable_fsverity (rpmostreed_daemon_get ()))
+    upgrader_flags |= RPMOSTREE_SYSROOT_UPGRADER_FLAGS_FSVERITY;
 
   /* Read in the existing kernel args and convert those to an #OstreeKernelArg instance for API
    * usage */
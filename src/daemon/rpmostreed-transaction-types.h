@@ -32,7 +32,8 @@ RpmostreedTransaction *rpmostreed_transaction_new_package_diff (
 
 RpmostreedTransaction *rpmostreed_transaction_new_rollback (GDBusMethodInvocation *invocation,
                                                             OstreeSysroot *sysroot,
-                                                            const char *osname, gboolean reboot,
+                                                            const char *osname,
+                                                            const char *target, gboolean reboot,
                                                             GCancellable *cancellable,
                                                             GError **error);
 
@@ -47,6 +48,15 @@ typedef enum
   RPMOSTREE_TRANSACTION_DEPLOY_FLAG_DRY_RUN = (1 << 5),
   RPMOSTREE_TRANSACTION_DEPLOY_FLAG_DOWNLOAD_ONLY = (1 << 8),
   RPMOSTREE_TRANSACTION_DEPLOY_FLAG_DOWNLOAD_METADATA_ONLY = (1 << 9),
+  RPMOSTREE_TRANSACTION_DEPLOY_FLAG_APPLY_LIVE_IF_SAFE = (1 << 10),
+  RPMOSTREE_TRANSACTION_DEPLOY_FLAG_AUTOMATIC_REBOOT = (1 << 11),
+  RPMOSTREE_TRANSACTION_DEPLOY_FLAG_PHASED_ROLLOUT = (1 << 12),
+  RPMOSTREE_TRANSACTION_DEPLOY_FLAG_REFRESH_LAYERED = (1 << 13),
+  RPMOSTREE_TRANSACTION_DEPLOY_FLAG_SECURITY_ONLY = (1 << 14),
+  RPMOSTREE_TRANSACTION_DEPLOY_FLAG_AUTOMATIC_BANDWIDTH_LIMIT = (1 << 15),
+  RPMOSTREE_TRANSACTION_DEPLOY_FLAG_QUICK_CHECK = (1 << 16),
+  RPMOSTREE_TRANSACTION_DEPLOY_FLAG_AUTOMATIC_POLICY = (1 << 17),
+  RPMOSTREE_TRANSACTION_DEPLOY_FLAG_PREFETCH = (1 << 18),
 } RpmOstreeTransactionDeployFlags;
 
 RpmostreedTransaction *
@@ -74,6 +84,9 @@ typedef enum
   RPMOSTREE_TRANSACTION_CLEANUP_PENDING_DEPLOY = (1 << 1),
   RPMOSTREE_TRANSACTION_CLEANUP_ROLLBACK_DEPLOY = (1 << 2),
   RPMOSTREE_TRANSACTION_CLEANUP_REPOMD = (1 << 3),
+  RPMOSTREE_TRANSACTION_CLEANUP_CONTAINER_IMAGES = (1 << 4),
+  RPMOSTREE_TRANSACTION_CLEANUP_RETENTION = (1 << 5),
+  RPMOSTREE_TRANSACTION_CLEANUP_TESTDEPLOY = (1 << 6),
 } RpmOstreeTransactionCleanupFlags;
 
 RpmostreedTransaction *
@@ -87,6 +100,8 @@ RpmostreedTransaction *rpmostreed_transaction_new_apply_live (GDBusMethodInvocat
                                                               GCancellable *cancellable,
                                                               GError **error);
 
+void rpmostreed_add_live_restart_services (GVariantDict *dict);
+
 typedef enum
 {
   RPMOSTREE_TRANSACTION_REFRESH_MD_FLAG_FORCE = (1 << 0),
@@ -97,6 +112,18 @@ rpmostreed_transaction_new_refresh_md (GDBusMethodInvocation *invocation, Ostree
                                        RpmOstreeTransactionRefreshMdFlags flags, const char *osname,
                                        GCancellable *cancellable, GError **error);
 
+RpmostreedTransaction *rpmostreed_transaction_new_list_revisions (GDBusMethodInvocation *invocation,
+                                                                  OstreeSysroot *sysroot,
+                                                                  const char *osname, guint page,
+                                                                  const char *refspec,
+                                                                  GCancellable *cancellable,
+                                                                  GError **error);
+
+RpmostreedTransaction *rpmostreed_transaction_new_pin (GDBusMethodInvocation *invocation,
+                                                      OstreeSysroot *sysroot,
+                                                      const char *deployment, GVariant *options,
+                                                      GCancellable *cancellable, GError **error);
+
 RpmostreedTransaction *rpmostreed_transaction_new_modify_yum_repo (
     GDBusMethodInvocation *invocation, OstreeSysroot *sysroot, const char *osname,
     const char *repo_id, GVariant *settings, GCancellable *cancellable, GError **error);
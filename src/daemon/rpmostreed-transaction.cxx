@@ -25,12 +25,26 @@
 #include <systemd/sd-journal.h>
 #include <systemd/sd-login.h>
 
+#include "rpmostree-core.h"
 #include "rpmostree-cxxrs.h"
+#include "rpmostree-sysroot-core.h"
+#include "rpmostree-util.h"
 #include "rpmostreed-daemon.h"
+#include "rpmostreed-deployment-utils.h"
 #include "rpmostreed-errors.h"
 #include "rpmostreed-sysroot.h"
 #include "rpmostreed-transaction.h"
 
+#define RPMOSTREE_MESSAGE_TRANSACTION_FINISHED                                                     \
+  SD_ID128_MAKE (e8, c4, 8a, 6a, c6, a3, 4d, 4a, 8b, 93, 62, d3, c6, 19, bb, 66)
+#define RPMOSTREE_MESSAGE_TRANSACTION_RECOVERED                                                    \
+  SD_ID128_MAKE (1c, 46, 64, 55, f7, c2, 49, b2, 91, 52, 4a, fb, 2f, 4b, 74, b9)
+
+/* Records the transaction in progress, so that we can clean up after it if it's
+ * interrupted; see rpmostreed_transaction_recover_interrupted().  This is persistent
+ * because e.g. the rootfs checkout survives a reboot. */
+#define RPMOSTREE_TXN_MARKER RPMOSTREE_STATE_DIR "txn-inprogress.gv"
+
 struct _RpmostreedTransactionPrivate
 {
   GDBusMethodInvocation *invocation;
@@ -51,6 +65,10 @@ struct _RpmostreedTransactionPrivate
 
   gint64 last_progress_journal;
 
+  /* For metrics */
+  gint64 start_time;
+  guint64 downloaded_bytes;
+
   gboolean redirect_output;
 
   GDBusServer *server;
@@ -59,6 +77,12 @@ struct _RpmostreedTransactionPrivate
   /* For emitting Finished signals to late connections. */
   GVariant *finished_params;
 
+  /* The package diff from the booted deployment to the pending one, if the
+   * transaction created it; handed to the daemon sysroot once it completes */
+  char *rpm_diff_id;
+  GVariant *rpm_diff;
+
+  gboolean started;
   guint watch_id;
 };
 
@@ -210,6 +234,28 @@ transaction_owner_vanished_cb (GDBusConnection *connection, const char *name, gp
     }
 }
 
+/* Emit the structured equivalent of the human-readable progress signals; see
+ * transaction_progress.rs. */
+void
+rpmostreed_transaction_emit_progress (RPMOSTreeTransaction *transaction, const char *stage,
+                                      const char *text, guint64 items_done, guint64 items_total,
+                                      guint64 bytes_done, guint64 bytes_total, int percentage,
+                                      guint64 elapsed_secs)
+{
+  rpmostreecxx::ProgressEvent event{
+    .stage = stage ?: "",
+    .text = text ?: "",
+    .items_done = items_done,
+    .items_total = items_total,
+    .bytes_done = bytes_done,
+    .bytes_total = bytes_total,
+    .percentage = percentage,
+    .elapsed_secs = elapsed_secs,
+  };
+  g_autoptr (GVariant) progress = (GVariant *)rpmostreecxx::progress_event_to_variant (event);
+  rpmostree_transaction_emit_progress (transaction, progress);
+}
+
 static void
 transaction_progress_changed_cb (OstreeAsyncProgress *progress, RPMOSTreeTransaction *transaction)
 {
@@ -224,6 +270,19 @@ transaction_progress_changed_cb (OstreeAsyncProgress *progress, RPMOSTreeTransac
   guint64 elapsed_secs = 0;
   guint64 bytes_sec = 0;
 
+  /* The count is per pull, so keep track of what we already added for this one */
+  auto counted = static_cast<guint64 *> (g_object_get_data (G_OBJECT (progress), "txn-bytes"));
+  if (counted == NULL)
+    {
+      counted = g_new0 (guint64, 1);
+      g_object_set_data_full (G_OBJECT (progress), "txn-bytes", counted, g_free);
+    }
+  if (bytes_transferred > *counted)
+    {
+      priv->downloaded_bytes += bytes_transferred - *counted;
+      *counted = bytes_transferred;
+    }
+
   gint64 current_monotonic = g_get_monotonic_time ();
   gint64 elapsed
       = MAX (current_monotonic, priv->last_progress_journal) - priv->last_progress_journal;
@@ -287,6 +346,20 @@ transaction_progress_changed_cb (OstreeAsyncProgress *progress, RPMOSTreeTransac
   /* This sinks the floating GVariant refs (I think...). */
   rpmostree_transaction_emit_download_progress (transaction, arg_time, arg_outstanding,
                                                 arg_metadata, arg_delta, arg_content, arg_transfer);
+
+  guint total_delta_parts = ostree_async_progress_get_uint (progress, "total-delta-parts");
+  if (total_delta_parts > 0)
+    rpmostreed_transaction_emit_progress (
+        transaction, "download", "Receiving delta parts",
+        ostree_async_progress_get_uint (progress, "fetched-delta-parts"), total_delta_parts,
+        bytes_transferred, ostree_async_progress_get_uint64 (progress, "total-delta-part-size"),
+        -1, elapsed_secs);
+  else
+    rpmostreed_transaction_emit_progress (
+        transaction, "download", "Receiving objects",
+        ostree_async_progress_get_uint (progress, "fetched"),
+        ostree_async_progress_get_uint (progress, "requested"), bytes_transferred, 0, -1,
+        elapsed_secs);
 }
 
 static void
@@ -309,6 +382,57 @@ transaction_gpg_verify_result_cb (OstreeRepo *repo, const char *checksum,
                                                  g_strdup (checksum));
 }
 
+/* The ID of the pending deployment of @sysroot, i.e. the default one if it isn't
+ * booted, or NULL if there is none. */
+static char *
+get_pending_deployment_id (OstreeSysroot *sysroot)
+{
+  OstreeDeployment *booted = ostree_sysroot_get_booted_deployment (sysroot);
+  g_autoptr (GPtrArray) deployments = ostree_sysroot_get_deployments (sysroot);
+  if (!booted || deployments->len == 0)
+    return NULL;
+  auto pending = static_cast<OstreeDeployment *> (deployments->pdata[0]);
+  if (ostree_deployment_equal (pending, booted))
+    return NULL;
+  auto id = rpmostreecxx::deployment_generate_id (*pending);
+  return g_strdup (id.c_str ());
+}
+
+/* Diffing the rpmdbs of the booted and pending deployments is what makes `status` slow
+ * when there is a pending deployment, so do it here in the transaction thread when the
+ * transaction created a new one, rather than on the main thread.  Failures are only
+ * logged; `status` computes the diff itself then.
+ */
+static void
+transaction_compute_rpm_diff (RpmostreedTransaction *self, const char *prev_pending_id,
+                              GCancellable *cancellable)
+{
+  RpmostreedTransactionPrivate *priv = rpmostreed_transaction_get_private (self);
+
+  g_autoptr (GError) local_error = NULL;
+  if (!ostree_sysroot_load_if_changed (priv->sysroot, NULL, cancellable, &local_error))
+    {
+      sd_journal_print (LOG_WARNING, "Failed to reload sysroot: %s", local_error->message);
+      return;
+    }
+  g_autofree char *pending_id = get_pending_deployment_id (priv->sysroot);
+  if (!pending_id || g_strcmp0 (pending_id, prev_pending_id) == 0)
+    return;
+
+  OstreeDeployment *booted = ostree_sysroot_get_booted_deployment (priv->sysroot);
+  g_autoptr (GPtrArray) deployments = ostree_sysroot_get_deployments (priv->sysroot);
+  auto pending = static_cast<OstreeDeployment *> (deployments->pdata[0]);
+  OstreeRepo *repo = ostree_sysroot_repo (priv->sysroot);
+  if (!rpmostreed_deployment_generate_rpm_diff (repo, booted, pending, &priv->rpm_diff,
+                                                cancellable, &local_error))
+    {
+      sd_journal_print (LOG_WARNING, "Failed to compute pending deployment diff: %s",
+                        local_error->message);
+      return;
+    }
+  priv->rpm_diff_id = util::move_nullify (pending_id);
+}
+
 static void
 transaction_execute_thread (GTask *task, gpointer source_object, gpointer task_data,
                             GCancellable *cancellable)
@@ -329,6 +453,9 @@ transaction_execute_thread (GTask *task, gpointer source_object, gpointer task_d
   // Further, we join the main Tokio async runtime.
   auto guard = rpmostreecxx::rpmostreed_daemon_tokio_enter (rpmostreed_daemon_get ());
 
+  g_autofree char *prev_pending_id
+      = priv->sysroot ? get_pending_deployment_id (priv->sysroot) : NULL;
+
   if (clazz->execute != NULL)
     {
       // This try/catch shouldn't be needed; every CXX call should be wrapped with the CXX macro.
@@ -344,6 +471,9 @@ transaction_execute_thread (GTask *task, gpointer source_object, gpointer task_d
         }
     }
 
+  if (success && local_error == NULL && priv->sysroot)
+    transaction_compute_rpm_diff (self, prev_pending_id, cancellable);
+
   if (local_error != NULL)
     {
       /* Also log to journal in addition to the client, so it's recorded
@@ -367,6 +497,76 @@ transaction_execute_thread (GTask *task, gpointer source_object, gpointer task_d
   g_main_context_pop_thread_default (mctx);
 }
 
+static gboolean
+transaction_write_marker (RpmostreedTransaction *self, GError **error)
+{
+  RpmostreedTransactionPrivate *priv = rpmostreed_transaction_get_private (self);
+  if (!glnx_shutil_mkdir_p_at (AT_FDCWD, RPMOSTREE_STATE_DIR, 0755, NULL, error))
+    return FALSE;
+
+  g_autofree char *args
+      = g_variant_print (g_dbus_method_invocation_get_parameters (priv->invocation), FALSE);
+  g_auto (GVariantDict) dict;
+  g_variant_dict_init (&dict, NULL);
+  g_variant_dict_insert (&dict, "method", "s",
+                         g_dbus_method_invocation_get_method_name (priv->invocation));
+  g_variant_dict_insert (&dict, "path", "s",
+                         g_dbus_method_invocation_get_object_path (priv->invocation));
+  g_variant_dict_insert (&dict, "address", "s", rpmostreed_transaction_get_client_address (self));
+  g_variant_dict_insert (&dict, "args", "s", args);
+  g_variant_dict_insert (&dict, "client", "s", priv->client_description ?: "");
+  g_autoptr (GVariant) marker = g_variant_ref_sink (g_variant_dict_end (&dict));
+
+  return glnx_file_replace_contents_at (
+      AT_FDCWD, RPMOSTREE_TXN_MARKER, static_cast<const guint8 *> (g_variant_get_data (marker)),
+      g_variant_get_size (marker), GLNX_FILE_REPLACE_DATASYNC_NEW, NULL, error);
+}
+
+/* Whether this transaction is recorded in RPMOSTREE_TXN_MARKER while it runs; only the
+ * system daemon does it, for the transactions which lock the sysroot. */
+static gboolean
+transaction_uses_marker (RpmostreedTransaction *self)
+{
+  RpmostreedTransactionPrivate *priv = rpmostreed_transaction_get_private (self);
+  return priv->sysroot_locked && rpmostreed_sysroot_is_system_daemon (rpmostreed_sysroot_get ());
+}
+
+/* Log the outcome of the transaction, along with what was requested and by whom, so that
+ * this record is complete even if the one logged when it started was rotated away; see
+ * on_active_txn_changed() and `rpm-ostree audit-log`. */
+static void
+transaction_log_finished (RpmostreedTransaction *self, gboolean success, const char *error_message)
+{
+  RpmostreedTransactionPrivate *priv = rpmostreed_transaction_get_private (self);
+  const char *method = g_dbus_method_invocation_get_method_name (priv->invocation);
+  const char *path = g_dbus_method_invocation_get_object_path (priv->invocation);
+  g_autofree char *args
+      = g_variant_print (g_dbus_method_invocation_get_parameters (priv->invocation), FALSE);
+
+  /* The default deployment, i.e. what the system boots into next */
+  OstreeSysroot *sysroot = rpmostreed_sysroot_get_root (rpmostreed_sysroot_get ());
+  g_autoptr (GPtrArray) deployments = ostree_sysroot_get_deployments (sysroot);
+  const char *checksum = "";
+  if (deployments->len > 0)
+    checksum = ostree_deployment_get_csum ((OstreeDeployment *)deployments->pdata[0]);
+
+  g_autofree char *message = NULL;
+  if (success)
+    message = g_strdup_printf ("Completed txn %s for %s: %s", method, priv->client_description,
+                               path);
+  else
+    message = g_strdup_printf ("Failed txn %s for %s: %s: %s", method, priv->client_description,
+                               path, error_message);
+  sd_journal_send ("MESSAGE_ID=" SD_ID128_FORMAT_STR,
+                   SD_ID128_FORMAT_VAL (RPMOSTREE_MESSAGE_TRANSACTION_FINISHED), "MESSAGE=%s",
+                   message, "PRIORITY=%d", success ? LOG_INFO : LOG_ERR, "TRANSACTION_METHOD=%s",
+                   method, "TRANSACTION_PATH=%s", path, "TRANSACTION_ADDRESS=%s",
+                   rpmostreed_transaction_get_client_address (self), "TRANSACTION_ARGS=%s", args,
+                   "TRANSACTION_SUCCESS=%d", success ? 1 : 0, "TRANSACTION_ERROR=%s",
+                   error_message, "CLIENT_ID=%s", priv->agent_id ?: "", "CLIENT_SD_UNIT=%s",
+                   priv->sd_unit ?: "", "DEPLOYMENT_CHECKSUM=%s", checksum, NULL);
+}
+
 static void
 transaction_execute_done_cb (GObject *source_object, GAsyncResult *result, gpointer user_data)
 {
@@ -381,6 +581,9 @@ transaction_execute_done_cb (GObject *source_object, GAsyncResult *result, gpoin
   success = g_task_propagate_boolean (G_TASK (result), &local_error);
   if (success)
     {
+      if (priv->rpm_diff)
+        rpmostreed_sysroot_add_rpm_diff (rpmostreed_sysroot_get (), priv->rpm_diff_id,
+                                         priv->rpm_diff);
       if (!rpmostreed_sysroot_reload (rpmostreed_sysroot_get (), &local_error))
         success = FALSE;
     }
@@ -397,6 +600,27 @@ transaction_execute_done_cb (GObject *source_object, GAsyncResult *result, gpoin
   g_debug ("%s (%p): Finished%s%s%s", G_OBJECT_TYPE_NAME (self), self,
            success ? "" : " (error: ", success ? "" : error_message, success ? "" : ")");
 
+  const char *metrics_file = rpmostreed_get_metrics_file (rpmostreed_daemon_get ());
+  if (metrics_file)
+    {
+      double duration = (g_get_monotonic_time () - priv->start_time) / (double)G_USEC_PER_SEC;
+      g_autoptr (GError) metrics_error = NULL;
+      if (!ROSCXX (metrics_record_transaction (
+                       g_dbus_method_invocation_get_method_name (priv->invocation), duration,
+                       success, priv->downloaded_bytes),
+                   &metrics_error))
+        sd_journal_print (LOG_WARNING, "Failed to record transaction metrics: %s",
+                          metrics_error->message);
+      rpmostreed_sysroot_write_metrics (rpmostreed_sysroot_get ());
+    }
+
+  transaction_log_finished (self, success, error_message);
+
+  if (transaction_uses_marker (self) && unlinkat (AT_FDCWD, RPMOSTREE_TXN_MARKER, 0) < 0
+      && errno != ENOENT)
+    sd_journal_print (LOG_WARNING, "Failed to delete %s: %s", RPMOSTREE_TXN_MARKER,
+                      g_strerror (errno));
+
   rpmostree_transaction_emit_finished (RPMOSTREE_TRANSACTION (self), success, error_message);
 
   /* Stash the Finished signal parameters in case we need
@@ -476,6 +700,8 @@ transaction_dispose (GObject *object)
   g_clear_pointer (&priv->sysroot_path, g_free);
 
   g_clear_pointer (&priv->finished_params, (GDestroyNotify)g_variant_unref);
+  g_clear_pointer (&priv->rpm_diff, (GDestroyNotify)g_variant_unref);
+  g_clear_pointer (&priv->rpm_diff_id, g_free);
 
   G_OBJECT_CLASS (rpmostreed_transaction_parent_class)->dispose (object);
 }
@@ -521,10 +747,14 @@ transaction_constructed (GObject *object)
       /* Watch the sender's bus name until the transaction is started.
        * This guards against a process initiating a transaction but then
        * terminating before calling Start().  If the bus name vanishes
-       * during this time, we abort the transaction. */
-      priv->watch_id
-          = g_bus_watch_name_on_connection (connection, sender, G_BUS_NAME_WATCHER_FLAGS_NONE, NULL,
-                                            transaction_owner_vanished_cb, self, NULL);
+       * during this time, we abort the transaction.  A private daemon has
+       * no bus names, but it exits when its client goes away anyway. */
+      if (sender != NULL)
+        priv->watch_id = g_bus_watch_name_on_connection (connection, sender,
+                                                         G_BUS_NAME_WATCHER_FLAGS_NONE, NULL,
+                                                         transaction_owner_vanished_cb, self, NULL);
+      else
+        sender = "";
 
       priv->client_description
           = rpmostreed_daemon_client_get_string (rpmostreed_daemon_get (), sender);
@@ -552,6 +782,19 @@ rpmostreed_transaction_force_close (RpmostreedTransaction *transaction)
   g_hash_table_foreach_remove (priv->peer_connections, foreach_close_peer, NULL);
 }
 
+/* Cancel @transaction and, if it was started, wait for it to stop executing.  Anything
+ * downloaded so far stays in the repo and is reused by the next transaction. */
+void
+rpmostreed_transaction_cancel_sync (RpmostreedTransaction *transaction)
+{
+  g_assert (RPMOSTREED_IS_TRANSACTION (transaction));
+
+  RpmostreedTransactionPrivate *priv = rpmostreed_transaction_get_private (transaction);
+  g_cancellable_cancel (priv->cancellable);
+  while (priv->started && !priv->executed)
+    g_main_context_iteration (NULL, TRUE);
+}
+
 static void
 on_sysroot_journal_msg (OstreeSysroot *sysroot, const char *msg, void *opaque)
 {
@@ -648,17 +891,28 @@ transaction_handle_start (RPMOSTreeTransaction *transaction, GDBusMethodInvocati
   RpmostreedTransactionPrivate *priv = rpmostreed_transaction_get_private (self);
   gboolean started = FALSE;
 
-  /* The bus name watch ID doubles as a "not-yet-started" flag.
-   * Once started the transaction proceeds independently of the
+  /* Once started the transaction proceeds independently of the
    * initiating process whose bus name we were watching. */
-  if (priv->watch_id > 0)
+  if (!priv->started)
     {
-      started = TRUE;
+      started = priv->started = TRUE;
+      priv->start_time = g_get_monotonic_time ();
 
       g_debug ("%s (%p): Started", G_OBJECT_TYPE_NAME (self), self);
 
-      g_bus_unwatch_name (priv->watch_id);
-      priv->watch_id = 0;
+      if (transaction_uses_marker (self))
+        {
+          g_autoptr (GError) marker_error = NULL;
+          if (!transaction_write_marker (self, &marker_error))
+            sd_journal_print (LOG_WARNING, "Failed to write %s: %s", RPMOSTREE_TXN_MARKER,
+                              marker_error->message);
+        }
+
+      if (priv->watch_id > 0)
+        {
+          g_bus_unwatch_name (priv->watch_id);
+          priv->watch_id = 0;
+        }
 
       GTask *task = g_task_new (transaction, priv->cancellable, transaction_execute_done_cb, NULL);
       /* Some of the async ops in rpmostree-core.c will cancel,
@@ -862,3 +1116,131 @@ rpmostreed_transaction_connect_signature_progress (RpmostreedTransaction *transa
   g_signal_connect_object (repo, "gpg-verify-result", G_CALLBACK (transaction_gpg_verify_result_cb),
                            transaction, static_cast<GConnectFlags> (0));
 }
+
+/* Delete @path in @dfd if it exists, adding @description to @actions if it did */
+static gboolean
+recover_rm_rf (int dfd, const char *path, const char *description, GPtrArray *actions,
+               GCancellable *cancellable, GError **error)
+{
+  if (!glnx_fstatat_allow_noent (dfd, path, NULL, AT_SYMLINK_NOFOLLOW, error))
+    return FALSE;
+  if (errno == ENOENT)
+    return TRUE;
+  if (!glnx_shutil_rm_rf_at (dfd, path, cancellable, error))
+    return FALSE;
+  g_ptr_array_add (actions, g_strdup (description));
+  return TRUE;
+}
+
+/* Called with the sysroot locked */
+static gboolean
+recover_clean (OstreeRepo *repo, GPtrArray *actions, GCancellable *cancellable, GError **error)
+{
+  int repo_dfd = ostree_repo_get_dfd (repo);
+  if (!recover_rm_rf (repo_dfd, RPMOSTREE_TMP_ROOTFS_DIR, "deleted rootfs checkout", actions,
+                      cancellable, error))
+    return FALSE;
+  if (!recover_rm_rf (repo_dfd, RPMOSTREE_OLD_TMP_ROOTFS_DIR, "deleted legacy rootfs checkout",
+                      actions, cancellable, error))
+    return FALSE;
+
+  g_autofree char *tmp_base = NULL;
+  if (!ostree_repo_resolve_rev (repo, RPMOSTREE_TMP_BASE_REF, TRUE, &tmp_base, error))
+    return FALSE;
+  if (tmp_base)
+    {
+      if (!ostree_repo_set_ref_immediate (repo, NULL, RPMOSTREE_TMP_BASE_REF, NULL, cancellable,
+                                          error))
+        return FALSE;
+      g_ptr_array_add (actions,
+                       g_strdup_printf ("deleted ref %s (%s)", RPMOSTREE_TMP_BASE_REF, tmp_base));
+    }
+
+  /* Only transactions take these, and they hold the sysroot lock meanwhile */
+  if (!recover_rm_rf (AT_FDCWD, RPMOSTREE_RUN_DIR RPMOSTREE_DIR_LOCK, "deleted libdnf locks",
+                      actions, cancellable, error))
+    return FALSE;
+
+  return TRUE;
+}
+
+/* If the system daemon was stopped or the machine went down in the middle of a
+ * transaction, as recorded in RPMOSTREE_TXN_MARKER, clean up what it may have left behind
+ * and log what was done.  ostree itself takes care of partial deployments and repo
+ * transactions, and the sysroot lock is released with the process holding it; what's left
+ * is our rootfs checkout, the ref holding the base commit, and the libdnf locks. */
+gboolean
+rpmostreed_transaction_recover_interrupted (OstreeSysroot *sysroot, OstreeRepo *repo,
+                                            GCancellable *cancellable, GError **error)
+{
+  GLNX_AUTO_PREFIX_ERROR ("Recovering interrupted transaction", error);
+
+  glnx_autofd int fd = -1;
+  g_autoptr (GError) local_error = NULL;
+  if (!glnx_openat_rdonly (AT_FDCWD, RPMOSTREE_TXN_MARKER, TRUE, &fd, &local_error))
+    {
+      if (!g_error_matches (local_error, G_IO_ERROR, G_IO_ERROR_NOT_FOUND))
+        return g_propagate_error (error, util::move_nullify (local_error)), FALSE;
+      return TRUE; /* Note early return */
+    }
+
+  struct stat stbuf;
+  if (!glnx_fstat (fd, &stbuf, error))
+    return FALSE;
+  if (!rpmostree_check_size_within_limit (stbuf.st_size, OSTREE_MAX_METADATA_SIZE,
+                                          RPMOSTREE_TXN_MARKER, error))
+    return FALSE;
+  g_autoptr (GBytes) data = glnx_fd_readall_bytes (fd, NULL, error);
+  if (!data)
+    return FALSE;
+  g_autoptr (GVariant) marker
+      = g_variant_ref_sink (g_variant_new_from_bytes (G_VARIANT_TYPE_VARDICT, data, FALSE));
+  g_auto (GVariantDict) dict;
+  g_variant_dict_init (&dict, g_variant_is_normal_form (marker) ? marker : NULL);
+  const char *method = "";
+  const char *path = "";
+  const char *address = "";
+  const char *args = "";
+  const char *client = "";
+  g_variant_dict_lookup (&dict, "method", "&s", &method);
+  g_variant_dict_lookup (&dict, "path", "&s", &path);
+  g_variant_dict_lookup (&dict, "address", "&s", &address);
+  g_variant_dict_lookup (&dict, "args", "&s", &args);
+  g_variant_dict_lookup (&dict, "client", "&s", &client);
+
+  gboolean lock_acquired = FALSE;
+  if (!ostree_sysroot_try_lock (sysroot, &lock_acquired, error))
+    return FALSE;
+  if (!lock_acquired)
+    {
+      /* Someone else, e.g. a private daemon, is operating on the sysroot; we'll
+       * try again the next time we start. */
+      sd_journal_print (LOG_INFO, "Not recovering interrupted txn %s: sysroot is locked", method);
+      return TRUE;
+    }
+
+  g_autoptr (GPtrArray) actions = g_ptr_array_new_with_free_func (g_free);
+  gboolean cleaned = recover_clean (repo, actions, cancellable, error);
+  ostree_sysroot_unlock (sysroot);
+  if (!cleaned)
+    return FALSE;
+
+  if (!glnx_unlinkat (AT_FDCWD, RPMOSTREE_TXN_MARKER, 0, error))
+    return FALSE;
+
+  g_autofree char *actions_str = NULL;
+  if (actions->len > 0)
+    {
+      g_ptr_array_add (actions, NULL);
+      actions_str = g_strjoinv ("; ", (char **)actions->pdata);
+    }
+  else
+    actions_str = g_strdup ("nothing to clean up");
+  sd_journal_send ("MESSAGE_ID=" SD_ID128_FORMAT_STR,
+                   SD_ID128_FORMAT_VAL (RPMOSTREE_MESSAGE_TRANSACTION_RECOVERED),
+                   "MESSAGE=Recovered from interrupted txn %s for %s: %s: %s", method, client,
+                   path, actions_str, "PRIORITY=%d", LOG_WARNING, "TRANSACTION_METHOD=%s", method,
+                   "TRANSACTION_PATH=%s", path, "TRANSACTION_ADDRESS=%s", address,
+                   "TRANSACTION_ARGS=%s", args, "RECOVERY_ACTIONS=%s", actions_str, NULL);
+  return TRUE;
+}
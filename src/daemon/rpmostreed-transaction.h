@@ -63,5 +63,13 @@ void rpmostreed_transaction_connect_download_progress (RpmostreedTransaction *tr
 void rpmostreed_transaction_connect_signature_progress (RpmostreedTransaction *transaction,
                                                         OstreeRepo *repo);
 void rpmostreed_transaction_force_close (RpmostreedTransaction *transaction);
+void rpmostreed_transaction_cancel_sync (RpmostreedTransaction *transaction);
+gboolean rpmostreed_transaction_recover_interrupted (OstreeSysroot *sysroot, OstreeRepo *repo,
+                                                     GCancellable *cancellable, GError **error);
+void rpmostreed_transaction_emit_progress (RPMOSTreeTransaction *transaction, const char *stage,
+                                           const char *text, guint64 items_done,
+                                           guint64 items_total, guint64 bytes_done,
+                                           guint64 bytes_total, int percentage,
+                                           guint64 elapsed_secs);
 
 G_END_DECLS
@@ -182,6 +182,9 @@ rpmostreed_repo_pull_ancestry (OstreeRepo *repo, const char *refspec,
             g_variant_dict_insert (&options, "depth", "i", depth);
           g_variant_dict_insert (&options, "flags", "i", flags);
           g_variant_dict_insert_value (&options, "refs", refs_value);
+          gint retries = rpmostreed_get_retry_count (rpmostreed_daemon_get ());
+          if (retries >= 0)
+            g_variant_dict_insert (&options, "n-network-retries", "u", (guint32)retries);
 
           if (!ostree_repo_pull_with_options (repo, remote, g_variant_dict_end (&options), progress,
                                               cancellable, error))
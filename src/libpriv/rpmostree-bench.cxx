@@ -0,0 +1,131 @@
+/* -*- mode: C; c-file-style: "gnu"; indent-tabs-mode: nil; -*-
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Lesser General Public License as published
+ * by the Free Software Foundation; either version 2 of the licence or (at
+ * your option) any later version.
+ *
+ * This library is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ * Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General
+ * Public License along with this library; if not, write to the
+ * Free Software Foundation, Inc., 59 Temple Place, Suite 330,
+ * Boston, MA 02111-1307, USA.
+ */
+
+#include "config.h"
+
+#include <libdnf/libdnf.h>
+
+#include "rpmostree-bench.h"
+#include "rpmostree-core.h"
+#include "rpmostree-importer.h"
+#include "rpmostree-util.h"
+
+static gboolean
+import_rpm (OstreeRepo *repo, const char *path, GError **error)
+{
+  glnx_autofd int fd = -1;
+  if (!glnx_openat_rdonly (AT_FDCWD, path, TRUE, &fd, error))
+    return FALSE;
+
+  g_auto (RpmOstreeRepoAutoTransaction) txn = {
+    0,
+  };
+  if (!rpmostree_repo_auto_transaction_start (&txn, repo, FALSE, NULL, error))
+    return FALSE;
+
+  auto flags = rpmostreecxx::rpm_importer_flags_new_empty ();
+  g_autoptr (RpmOstreeImporter) importer
+      = rpmostree_importer_new_take_fd (&fd, repo, NULL, *flags, NULL, error);
+  if (!importer)
+    return FALSE;
+  if (!rpmostree_importer_run (importer, NULL, NULL, NULL, error))
+    return FALSE;
+
+  if (!ostree_repo_commit_transaction (repo, NULL, NULL, error))
+    return FALSE;
+  txn.initialized = FALSE;
+  return TRUE;
+}
+
+static gboolean
+solve_goal (HyGoal goal, DnfSack *sack, const rust::Vec<rust::String> &pkgs, guint *out_n_installs,
+            GError **error)
+{
+  for (auto &name : pkgs)
+    {
+      auto name_c = std::string (name);
+      hy_autoquery HyQuery query = hy_query_create (sack);
+      hy_query_filter (query, HY_PKG_NAME, HY_EQ, name_c.c_str ());
+      g_autoptr (GPtrArray) matches = hy_query_run (query);
+      if (matches->len == 0)
+        return glnx_throw (error, "Package not found: %s", name_c.c_str ());
+      hy_goal_install (goal, static_cast<DnfPackage *> (matches->pdata[0]));
+    }
+
+  if (!dnf_goal_depsolve (goal, DNF_INSTALL, error))
+    return FALSE;
+  g_autoptr (GPtrArray) installs = hy_goal_list_installs (goal, error);
+  if (!installs)
+    return FALSE;
+  *out_n_installs = installs->len;
+  return TRUE;
+}
+
+/* Load the rpm-md repo from scratch and depsolve installing @pkgs into an empty root */
+static gboolean
+depsolve (const char *repomd, const char *primary, const char *cachedir,
+          const rust::Vec<rust::String> &pkgs, guint *out_n_installs, GError **error)
+{
+  ROSCXX_TRY (core_libdnf_process_global_init (), error);
+
+  g_autoptr (DnfSack) sack = dnf_sack_new ();
+  dnf_sack_set_cachedir (sack, cachedir);
+  if (!dnf_sack_setup (sack, 0, error))
+    return FALSE;
+
+  HyRepo hrepo = hy_repo_create ("bench");
+  hy_repo_set_string (hrepo, HY_REPO_MD_FN, repomd);
+  hy_repo_set_string (hrepo, HY_REPO_PRIMARY_FN, primary);
+  gboolean loaded = dnf_sack_load_repo (sack, hrepo, 0, error);
+  /* The sack holds its own reference */
+  hy_repo_free (hrepo);
+  if (!loaded)
+    return FALSE;
+
+  HyGoal goal = hy_goal_create (sack);
+  gboolean solved = solve_goal (goal, sack, pkgs, out_n_installs, error);
+  hy_goal_free (goal);
+  return solved;
+}
+
+namespace rpmostreecxx
+{
+void
+bench_import_rpm (const OstreeRepo &repo, rust::Str path)
+{
+  g_autoptr (GError) local_error = NULL;
+  auto path_c = std::string (path);
+  if (!import_rpm (&const_cast<OstreeRepo &> (repo), path_c.c_str (), &local_error))
+    util::throw_gerror (local_error);
+}
+
+uint32_t
+bench_depsolve (rust::Str repomd, rust::Str primary, rust::Str cachedir,
+                const rust::Vec<rust::String> &pkgs)
+{
+  g_autoptr (GError) local_error = NULL;
+  auto repomd_c = std::string (repomd);
+  auto primary_c = std::string (primary);
+  auto cachedir_c = std::string (cachedir);
+  guint n_installs = 0;
+  if (!depsolve (repomd_c.c_str (), primary_c.c_str (), cachedir_c.c_str (), pkgs, &n_installs,
+                 &local_error))
+    util::throw_gerror (local_error);
+  return n_installs;
+}
+}
@@ -53,10 +53,16 @@ struct _RpmOstreeContext
   gboolean unprivileged;
   OstreeSePolicy *sepolicy;
   char *passwd_dir;
+  char *semodule_cachedir;
+  gboolean semodule_cache_clear;
+  gboolean require_gpgcheck;
+  gboolean find_weakdeps;
+  GHashTable *pkg_signing_keys; /* nevra --> ID of the rpm-signing-keys key */
 
   guint async_index; /* Offset into array if applicable */
   guint n_async_running;
   guint n_async_max;
+  GThreadPool *async_import_pool;
   gboolean async_running;
   GCancellable *async_cancellable;
   std::unique_ptr<rpmostreecxx::Progress> async_progress;
@@ -73,10 +79,13 @@ struct _RpmOstreeContext
 
   GHashTable *fileoverride_pkgs; /* set of nevras */
 
+  GVariant *unsatisfied_weakdeps; /* a(sss): pkgname, kind, dependency */
+
   std::optional<rust::Box<rpmostreecxx::LockfileConfig> > lockfile;
   gboolean lockfile_strict;
 
   GLnxTmpDir tmpdir;
+  GLnxTmpDir creds_tmpdir; /* Decrypted repo credentials */
 
   gboolean kernel_changed;
 
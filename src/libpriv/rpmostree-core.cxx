@@ -24,6 +24,7 @@
 #include <glib-unix.h>
 #include <libdnf/libdnf.h>
 #include <librepo/librepo.h>
+#include <map>
 #include <rpm/rpmfi.h>
 #include <rpm/rpmlib.h>
 #include <rpm/rpmlog.h>
@@ -43,7 +44,9 @@
 #include "rpmostree-rpm-util.h"
 #include "rpmostree-scripts.h"
 
+#include "libdnf/dnf-context.hpp"
 #include "libdnf/nevra.hpp"
+#include "libdnf/repo/Repo.hpp"
 #include "rpmostree-util.h"
 
 #define RPMOSTREE_MESSAGE_COMMIT_STATS                                                             \
@@ -87,6 +90,7 @@ rpmostree_context_finalize (GObject *object)
   g_clear_object (&rctx->sepolicy);
 
   g_clear_pointer (&rctx->passwd_dir, g_free);
+  g_clear_pointer (&rctx->semodule_cachedir, g_free);
 
   g_clear_pointer (&rctx->pkgs, g_ptr_array_unref);
   g_clear_pointer (&rctx->pkgs_to_download, g_ptr_array_unref);
@@ -97,9 +101,12 @@ rpmostree_context_finalize (GObject *object)
   g_clear_pointer (&rctx->pkgs_to_replace, g_hash_table_unref);
 
   g_clear_pointer (&rctx->fileoverride_pkgs, g_hash_table_unref);
+  g_clear_pointer (&rctx->pkg_signing_keys, g_hash_table_unref);
+  g_clear_pointer (&rctx->unsatisfied_weakdeps, g_variant_unref);
 
   (void)glnx_tmpdir_delete (&rctx->tmpdir, NULL, NULL);
   (void)glnx_tmpdir_delete (&rctx->repo_tmpdir, NULL, NULL);
+  (void)glnx_tmpdir_delete (&rctx->creds_tmpdir, NULL, NULL);
 
   g_clear_pointer (&rctx->rootfs_usrlinks, g_hash_table_unref);
 
@@ -216,7 +223,8 @@ rpmostree_context_new_base (OstreeRepo *repo)
   /* we don't need any plugins */
   dnf_context_set_plugins_dir (self->dnfctx, NULL);
 
-  /* Force disable internal libdnf Count Me logic */
+  /* Force disable internal libdnf Count Me logic; we implement it for the
+   * host in download_metadata() */
   dnf_conf_add_setopt ("*.countme", DNF_CONF_COMMANDLINE, "false", NULL);
 
   /* Hack until libdnf+librepo know how to better negotaiate zchunk.
@@ -410,6 +418,35 @@ rpmostree_context_set_sepolicy (RpmOstreeContext *self, OstreeSePolicy *sepolicy
   g_set_object (&self->sepolicy, sepolicy);
 }
 
+/* Cache the policy rebuilt by semodule in scriptlets in @cachedir across
+ * transactions; see semodule-wrapper.sh.  If @clear is set, all previously
+ * cached policies are discarded. */
+void
+rpmostree_context_set_semodule_cache (RpmOstreeContext *self, const char *cachedir,
+                                      gboolean clear)
+{
+  g_free (self->semodule_cachedir);
+  self->semodule_cachedir = g_strdup (cachedir);
+  self->semodule_cache_clear = clear;
+}
+
+/* Refuse to install packages from repos which don't check signatures; see
+ * RestrictLayering in rpm-ostreed.conf. */
+void
+rpmostree_context_set_require_gpgcheck (RpmOstreeContext *self, gboolean require_gpgcheck)
+{
+  self->require_gpgcheck = require_gpgcheck;
+}
+
+/* Gather the unsatisfied weak dependencies of the packages to install during
+ * prepare(); see rpmostree_context_get_unsatisfied_weakdeps(). This costs a
+ * provides query per weak dependency, so it's only done for client layering. */
+void
+rpmostree_context_set_find_weakdeps (RpmOstreeContext *self, gboolean find_weakdeps)
+{
+  self->find_weakdeps = find_weakdeps;
+}
+
 void
 rpmostree_context_set_devino_cache (RpmOstreeContext *self, OstreeRepoDevInoCache *devino_cache)
 {
@@ -462,6 +499,49 @@ rpmostree_context_get_rpmmd_repo_commit_metadata (RpmOstreeContext *self)
   return g_variant_ref_sink (g_variant_builder_end (&repo_list_builder));
 }
 
+/* With rpm-signing-keys, return the IDs of the keys the packages are signed with as
+ * `rpmostree.rpm-signing-keys` (type as) and the key of each package as
+ * `rpmostree.rpm-signatures` (type a{ss}); otherwise return NULL for both.  Throws if
+ * a package wasn't verified.
+ */
+gboolean
+rpmostree_context_get_rpm_signatures (RpmOstreeContext *self, GVariant **out_keys,
+                                      GVariant **out_signatures, GError **error)
+{
+  *out_keys = NULL;
+  *out_signatures = NULL;
+  if (self->treefile_rs->get_rpm_signing_keys ().empty ())
+    return TRUE;
+
+  std::map<std::string, std::string> signatures;
+  std::set<std::string> keys;
+  for (guint i = 0; i < self->pkgs->len; i++)
+    {
+      auto pkg = static_cast<DnfPackage *> (self->pkgs->pdata[i]);
+      const char *nevra = dnf_package_get_nevra (pkg);
+      auto keyid = self->pkg_signing_keys ? static_cast<const char *> (
+                       g_hash_table_lookup (self->pkg_signing_keys, nevra))
+                                          : NULL;
+      if (!keyid)
+        return glnx_throw (error, "Package %s was not verified against rpm-signing-keys", nevra);
+      signatures[nevra] = keyid;
+      keys.insert (keyid);
+    }
+
+  g_auto (GVariantBuilder) builder;
+  g_variant_builder_init (&builder, (GVariantType *)"a{ss}");
+  for (auto &[nevra, keyid] : signatures)
+    g_variant_builder_add (&builder, "{ss}", nevra.c_str (), keyid.c_str ());
+  g_auto (GVariantBuilder) keys_builder;
+  g_variant_builder_init (&keys_builder, (GVariantType *)"as");
+  for (auto &keyid : keys)
+    g_variant_builder_add (&keys_builder, "s", keyid.c_str ());
+
+  *out_keys = g_variant_ref_sink (g_variant_builder_end (&keys_builder));
+  *out_signatures = g_variant_ref_sink (g_variant_builder_end (&builder));
+  return TRUE;
+}
+
 std::unique_ptr<rust::Vec<rpmostreecxx::StringMapping> >
 rpmostree_dnfcontext_get_varsubsts (DnfContext *context)
 {
@@ -926,18 +1006,22 @@ checkout_pkg_metadata_by_nevra (RpmOstreeContext *self, const char *nevra, const
   return checkout_pkg_metadata (self, nevra, header, cancellable, error);
 }
 
-/* Initiate download of rpm-md */
-gboolean
-rpmostree_context_download_metadata (RpmOstreeContext *self, DnfContextSetupSackFlags flags,
-                                     GCancellable *cancellable, GError **error)
+/* Initiate download of rpm-md. If @refresh_repos is not NULL, only the repos with
+ * the ids it contains are refreshed unconditionally; the others are refreshed once
+ * their cached metadata expires. */
+static gboolean
+download_metadata (RpmOstreeContext *self, DnfContextSetupSackFlags flags,
+                   GHashTable *refresh_repos, GCancellable *cancellable, GError **error)
 {
   g_assert (!self->empty);
 
+  rpmostree_output_set_stage (RPMOSTREE_OUTPUT_STAGE_DOWNLOAD);
+
   /* https://github.com/rpm-software-management/libdnf/pull/416
    * https://github.com/projectatomic/rpm-ostree/issues/1127
    */
-  if (flags & DNF_CONTEXT_SETUP_SACK_FLAG_SKIP_FILELISTS)
-    dnf_context_set_enable_filelists (self->dnfctx, FALSE);
+  const gboolean enable_filelists = !(flags & DNF_CONTEXT_SETUP_SACK_FLAG_SKIP_FILELISTS);
+  dnf_context_set_enable_filelists (self->dnfctx, enable_filelists);
 
   g_autoptr (GPtrArray) rpmmd_repos
       = rpmostree_get_enabled_rpmmd_repos (self->dnfctx, DNF_REPO_ENABLED_PACKAGES);
@@ -972,6 +1056,41 @@ rpmostree_context_download_metadata (RpmOstreeContext *self, DnfContextSetupSack
     }
   rpmostree_output_message ("%s", enabled_repos->str);
 
+  /* Apply the credentials encrypted with systemd-creds; those which are files are
+   * decrypted into a private directory which goes away with the context.  This is
+   * host configuration, so it doesn't apply to composes. */
+  if (self->is_system && !self->is_container && rpmmd_repos->len > 0)
+    {
+      if (!self->creds_tmpdir.initialized)
+        {
+          if (!glnx_shutil_mkdir_p_at (AT_FDCWD, "/run/rpm-ostree", 0755, cancellable, error))
+            return FALSE;
+          if (!glnx_mkdtempat (AT_FDCWD, "/run/rpm-ostree/creds.XXXXXX", 0700,
+                               &self->creds_tmpdir, error))
+            return FALSE;
+        }
+      for (guint i = 0; i < rpmmd_repos->len; i++)
+        {
+          auto repo = static_cast<DnfRepo *> (rpmmd_repos->pdata[i]);
+          CXX_TRY_VAR (creds,
+                       rpmostreecxx::repo_credentials_decrypt (dnf_repo_get_id (repo),
+                                                               self->creds_tmpdir.path),
+                       error);
+          if (creds.empty ())
+            continue;
+          /* This only changes the in-memory keyfile; we never dnf_repo_commit() it */
+          for (auto &cred : creds)
+            {
+              if (!dnf_repo_set_data (repo, cred.k.c_str (), cred.v.c_str (), error))
+                return FALSE;
+            }
+          /* And reload the repo settings from it */
+          if (!dnf_repo_setup (repo, error))
+            return glnx_prefix_error (error, "Applying credentials of repo '%s'",
+                                      dnf_repo_get_id (repo));
+        }
+    }
+
   g_autoptr (GHashTable) updated_repos = g_hash_table_new (NULL, NULL);
   /* Update each repo individually, and print its timestamp, so users can keep
    * track of repo up-to-dateness more easily.
@@ -1004,7 +1123,16 @@ rpmostree_context_download_metadata (RpmOstreeContext *self, DnfContextSetupSack
           cache_age = 0;
           break;
         }
-      if (!dnf_repo_check (repo, cache_age, hifstate, NULL))
+      /* The metadata of repos which the request doesn't reference is only used to
+       * resolve dependencies; don't force refreshing it, but respect metadata_expire. */
+      if (cache_age == 0 && refresh_repos
+          && !g_hash_table_contains (refresh_repos, dnf_repo_get_id (repo)))
+        cache_age = G_MAXUINT - 1;
+      gboolean cached = dnf_repo_check (repo, cache_age, hifstate, NULL);
+      /* The cache may have been populated while we didn't need the filelists */
+      if (cached && enable_filelists && !dnf_repo_get_filename_md (repo, "filelists"))
+        cached = FALSE;
+      if (!cached)
         {
           dnf_state_reset (hifstate);
           auto msg = g_strdup_printf ("Updating metadata for '%s'", dnf_repo_get_id (repo));
@@ -1025,6 +1153,40 @@ rpmostree_context_download_metadata (RpmOstreeContext *self, DnfContextSetupSack
   if (g_cancellable_set_error_if_cancelled (cancellable, error))
     return FALSE;
 
+  /* Refuse repos which could accept packages signed by keys other than those pinned in
+   * rpm-ostreed.conf; this is host configuration, so it doesn't apply to composes */
+  if (self->is_system && !self->is_container)
+    {
+      for (guint i = 0; i < rpmmd_repos->len; i++)
+        {
+          auto repo = static_cast<DnfRepo *> (rpmmd_repos->pdata[i]);
+          g_auto (GStrv) keys = dnf_repo_get_public_keys (repo);
+          rust::Vec<rust::String> rkeys;
+          for (char **it = keys; it && *it; it++)
+            rkeys.push_back (std::string (*it));
+          CXX_TRY (rpmostreecxx::repo_keys_check_rpmmd (dnf_repo_get_id (repo),
+                                                        dnf_repo_get_gpgcheck (repo), rkeys),
+                   error);
+        }
+    }
+
+  /* libdnf's own Count Me logic is disabled (see rpmostree_context_new_base()) since
+   * it would count composes and container builds too; for the host only, queue
+   * rpm-ostree-countme.service, which shares the window with its timer.
+   */
+  if (use_countme && self->is_system && !self->is_container
+      && g_hash_table_size (updated_repos) > 0)
+    {
+      rust::Vec<rust::String> repoids;
+      for (guint i = 0; i < rpmmd_repos->len; i++)
+        {
+          auto repo = static_cast<DnfRepo *> (rpmmd_repos->pdata[i]);
+          if (g_hash_table_contains (updated_repos, repo))
+            repoids.push_back (dnf_repo_get_id (repo));
+        }
+      rpmostreecxx::countme_rpmmd_fetched (repoids);
+    }
+
   /* The _setup_sack function among other things imports the metadata into libsolv */
   {
     g_autoptr (DnfState) hifstate = dnf_state_new ();
@@ -1061,6 +1223,13 @@ rpmostree_context_download_metadata (RpmOstreeContext *self, DnfContextSetupSack
   return TRUE;
 }
 
+gboolean
+rpmostree_context_download_metadata (RpmOstreeContext *self, DnfContextSetupSackFlags flags,
+                                     GCancellable *cancellable, GError **error)
+{
+  return download_metadata (self, flags, NULL, cancellable, error);
+}
+
 static void
 journal_rpmmd_info (RpmOstreeContext *self)
 {
@@ -1163,6 +1332,85 @@ pkg_is_cached (DnfPackage *pkg)
   return g_file_test (dnf_package_get_filename (pkg), G_FILE_TEST_EXISTS);
 }
 
+/* If the treefile sets rpm-signing-keys, verify the package @nevra in @fd (or just its
+ * headers) against only those keys, and record the key it's signed with for the commit
+ * metadata.
+ */
+static gboolean
+verify_rpm_signing_key (RpmOstreeContext *self, const char *nevra, int fd, GError **error)
+{
+  auto keys = self->treefile_rs->get_rpm_signing_keys ();
+  if (keys.empty ())
+    return TRUE;
+
+  g_autoptr (GPtrArray) keyfiles = g_ptr_array_new_with_free_func (g_free);
+  for (auto &key : keys)
+    g_ptr_array_add (keyfiles, g_strdup (key.c_str ()));
+  g_ptr_array_add (keyfiles, NULL);
+
+  g_autofree char *keyid = NULL;
+  if (!rpmostree_verify_rpm_signature_with_keys (fd, (const char *const *)keyfiles->pdata, &keyid,
+                                                 error))
+    return glnx_prefix_error (error, "Verifying %s", nevra);
+
+  if (!self->pkg_signing_keys)
+    self->pkg_signing_keys = g_hash_table_new_full (g_str_hash, g_str_equal, g_free, g_free);
+  g_hash_table_insert (self->pkg_signing_keys, g_strdup (nevra), util::move_nullify (keyid));
+  return TRUE;
+}
+
+/* If the repo of @pkg has keys pinned in rpm-ostreed.conf, require that the package in
+ * @fd is signed by one of them, rather than by any key librpm accepts.  This is host
+ * configuration, so it doesn't apply to composes.
+ */
+static gboolean
+verify_pinned_repo_key (RpmOstreeContext *self, DnfPackage *pkg, int fd, GError **error)
+{
+  if (!self->is_system || self->is_container || rpmostree_pkg_is_local (pkg))
+    return TRUE;
+  auto repo = static_cast<DnfRepo *> (dnf_package_get_repo (pkg));
+  if (!repo)
+    return TRUE;
+  CXX_TRY_VAR (pinned, rpmostreecxx::repo_keys_rpmmd_pinned (dnf_repo_get_id (repo)), error);
+  if (!pinned)
+    return TRUE;
+
+  /* The keys are local by now; remote ones were fetched for the gpgcheck */
+  g_auto (GStrv) keys = dnf_repo_get_public_keys (repo);
+  g_autofree char *keyid = NULL;
+  if (!rpmostree_verify_rpm_signature_with_keys (fd, (const char *const *)keys, &keyid, error))
+    return glnx_prefix_error (error, "Verifying %s", dnf_package_get_nevra (pkg));
+  rust::Vec<rust::String> rkeys;
+  for (char **it = keys; it && *it; it++)
+    rkeys.push_back (std::string (*it));
+  CXX_TRY (rpmostreecxx::repo_keys_check_signer (dnf_repo_get_id (repo), keyid, rkeys), error);
+  return TRUE;
+}
+
+/* Verify the headers of a package imported in the pkgcache with verify_rpm_signing_key(). */
+static gboolean
+verify_cached_rpm_signing_key (RpmOstreeContext *self, DnfPackage *pkg, GVariantDict *metadata,
+                               GError **error)
+{
+  if (self->treefile_rs->get_rpm_signing_keys ().empty ())
+    return TRUE;
+
+  g_autoptr (GVariant) header = g_variant_dict_lookup_value (
+      metadata, "rpmostree.metadata", (GVariantType *)"ay");
+  if (!header)
+    return glnx_throw (error, "Missing rpmostree.metadata");
+
+  g_auto (GLnxTmpfile) tmpf = {
+    0,
+  };
+  if (!glnx_open_anonymous_tmpfile (O_RDWR | O_CLOEXEC, &tmpf, error))
+    return FALSE;
+  if (glnx_loop_write (tmpf.fd, g_variant_get_data (header), g_variant_get_size (header)) < 0)
+    return glnx_throw_errno_prefix (error, "write");
+
+  return verify_rpm_signing_key (self, dnf_package_get_nevra (pkg), tmpf.fd, error);
+}
+
 /* Given @pkg, return its state in the pkgcache repo. It could be not present,
  * or present but have been imported with a different SELinux policy version
  * (and hence in need of relabeling).
@@ -1242,6 +1490,17 @@ find_pkg_in_ostree (RpmOstreeContext *self, DnfPackage *pkg, OstreeSePolicy *sep
         return TRUE;
     }
 
+  /* With rpm-signing-keys, the import needs to be verified again; if that fails, treat it
+   * as missing so that the package is downloaded and verified like the others. */
+  {
+    g_autoptr (GError) local_error = NULL;
+    if (!verify_cached_rpm_signing_key (self, pkg, metadata_dict, &local_error))
+      {
+        g_debug ("Ignoring pkgcache branch %s: %s", cachebranch, local_error->message);
+        return TRUE; /* Note early return */
+      }
+  }
+
   /* We found an import, let's load the sepolicy state */
   *out_in_ostree = TRUE;
   if (sepolicy)
@@ -1346,9 +1605,10 @@ sort_packages (RpmOstreeContext *self, GPtrArray *packages, GCancellable *cancel
   return TRUE;
 }
 
-/* Set @error with a string containing an error relating to @pkgs */
+/* Set @error with a string containing an error relating to @pkgs, with the @code of
+ * DNF_ERROR, so that callers can tell depsolving failures apart */
 static gboolean
-throw_package_list (GError **error, const char *prefix, GPtrArray *pkgs)
+throw_package_list (GError **error, DnfError code, const char *prefix, GPtrArray *pkgs)
 {
   if (!error)
     return FALSE; /* Note early simultaneously happy and sad return */
@@ -1366,7 +1626,7 @@ throw_package_list (GError **error, const char *prefix, GPtrArray *pkgs)
     }
 
   /* need a glnx_set_error_steal */
-  g_set_error_literal (error, G_IO_ERROR, G_IO_ERROR_FAILED, msg->str);
+  g_set_error_literal (error, DNF_ERROR, code, msg->str);
   return FALSE;
 }
 
@@ -1437,7 +1697,8 @@ check_goal_solution (RpmOstreeContext *self, GPtrArray *removed_pkgnames,
       }
 
     if (forbidden->len > 0)
-      return throw_package_list (error, "Base packages would be removed", forbidden);
+      return throw_package_list (error, DNF_ERROR_PACKAGE_CONFLICTS,
+                                 "Base packages would be removed", forbidden);
   }
 
   /* check that all the pkgs we expect to remove are marked for removal */
@@ -1452,7 +1713,8 @@ check_goal_solution (RpmOstreeContext *self, GPtrArray *removed_pkgnames,
       }
 
     if (forbidden->len > 0)
-      return throw_package_list (error, "Base packages not marked to be removed", forbidden);
+      return throw_package_list (error, DNF_ERROR_PACKAGE_CONFLICTS,
+                                 "Base packages not marked to be removed", forbidden);
   }
 
   /* REINSTALLs should never happen since it doesn't make sense in the rpm-ostree flow, and
@@ -1745,6 +2007,249 @@ find_locked_packages (RpmOstreeContext *self, GPtrArray **out_pkgs, GError **err
   return TRUE;
 }
 
+static void
+checksum_update_str (GChecksum *checksum, const char *s)
+{
+  /* include the NUL so that consecutive strings can't be confused */
+  g_checksum_update (checksum, (const guint8 *)s, strlen (s) + 1);
+}
+
+/* Compute the key under which we cache the depsolve result. It covers everything
+ * which can influence the solution: the treefile (which for client-side layering is
+ * derived from the origin), the rpm-md metadata and the base packages. Sets @out_key
+ * to NULL if the result shouldn't be cached. */
+static gboolean
+get_solve_cache_key (RpmOstreeContext *self, char **out_key, GError **error)
+{
+  *out_key = NULL;
+  /* Lockfiles already pin the solution, and in cache-only mode the sack depends on the
+   * contents of the pkgcache, which we don't track. */
+  if (self->lockfile || self->pkgcache_only || !self->ostreerepo
+      || g_getenv ("RPMOSTREE_DISABLE_SOLVE_CACHE"))
+    return TRUE;
+
+  DnfSack *sack = dnf_context_get_sack (self->dnfctx);
+  g_autoptr (GChecksum) checksum = g_checksum_new (G_CHECKSUM_SHA256);
+  checksum_update_str (checksum, dnf_context_get_base_arch (self->dnfctx));
+
+  CXX_TRY_VAR (tf_checksum, self->treefile_rs->get_checksum (*self->ostreerepo), error);
+  checksum_update_str (checksum, tf_checksum.c_str ());
+
+  g_autoptr (GPtrArray) repos
+      = rpmostree_get_enabled_rpmmd_repos (self->dnfctx, DNF_REPO_ENABLED_PACKAGES);
+  for (guint i = 0; i < repos->len; i++)
+    {
+      auto repo = static_cast<DnfRepo *> (repos->pdata[i]);
+      checksum_update_str (checksum, dnf_repo_get_id (repo));
+      g_autofree char *props
+          = g_strdup_printf ("%" G_GUINT64_FORMAT " %u %u", dnf_repo_get_timestamp_generated (repo),
+                             dnf_repo_get_n_solvables (repo), dnf_repo_get_cost (repo));
+      checksum_update_str (checksum, props);
+      /* The repomd.xml references all the other metadata by checksum */
+      g_autofree char *repomd_path
+          = g_build_filename (dnf_repo_get_location (repo), "repodata", "repomd.xml", NULL);
+      g_autofree char *repomd = NULL;
+      gsize repomd_len = 0;
+      if (g_file_get_contents (repomd_path, &repomd, &repomd_len, NULL))
+        g_checksum_update (checksum, (const guint8 *)repomd, repomd_len);
+    }
+
+  hy_autoquery HyQuery query = hy_query_create (sack);
+  hy_query_filter (query, HY_PKG_REPONAME, HY_EQ, HY_SYSTEM_REPO_NAME);
+  g_autoptr (GPtrArray) base_pkgs = hy_query_run (query);
+  g_ptr_array_sort (base_pkgs, compare_pkgs);
+  for (guint i = 0; i < base_pkgs->len; i++)
+    {
+      auto pkg = static_cast<DnfPackage *> (base_pkgs->pdata[i]);
+      checksum_update_str (checksum, dnf_package_get_nevra (pkg));
+    }
+
+  *out_key = g_strdup (g_checksum_get_string (checksum));
+  return TRUE;
+}
+
+static char *
+get_solve_cache_dir (RpmOstreeContext *self)
+{
+  g_autofree char *cachedir = g_path_get_dirname (dnf_context_get_cache_dir (self->dnfctx));
+  return g_build_filename (cachedir, RPMOSTREE_DIR_CACHE_DEPSOLVE, NULL);
+}
+
+/* If we previously solved the request with @key, exclude from the sack all the
+ * non-base packages which weren't part of that solution and return TRUE, storing the
+ * previous excludes in @out_prev_excludes so that they can be restored afterwards. */
+static gboolean
+exclude_unsolved_packages (RpmOstreeContext *self, const char *cachedir, const char *key,
+                           DnfPackageSet **out_prev_excludes)
+{
+  DnfSack *sack = dnf_context_get_sack (self->dnfctx);
+
+  g_autoptr (GError) local_error = NULL;
+  auto solved = ROSCXX_VAL (solve_cache_lookup (cachedir, key), &local_error);
+  if (!solved.has_value ())
+    {
+      sd_journal_print (LOG_WARNING, "Failed to look up cached depsolve: %s",
+                        local_error->message);
+      return FALSE;
+    }
+  if (solved->empty ())
+    return FALSE;
+
+  g_autoptr (DnfPackageSet) solved_pset = dnf_packageset_new (sack);
+  for (auto &solved_pkg : *solved)
+    {
+      hy_autoquery HyQuery query = hy_query_create (sack);
+      hy_query_filter (query, HY_PKG_NEVRA, HY_EQ, solved_pkg.nevra.c_str ());
+      hy_query_filter (query, HY_PKG_REPONAME, HY_EQ, solved_pkg.repo.c_str ());
+      g_autoptr (GPtrArray) matches = hy_query_run (query);
+      /* can happen e.g. if a local package was replaced by another with the same NEVRA */
+      if (matches->len == 0)
+        return FALSE;
+      for (guint i = 0; i < matches->len; i++)
+        dnf_packageset_add (solved_pset, static_cast<DnfPackage *> (matches->pdata[i]));
+    }
+
+  hy_autoquery HyQuery query = hy_query_create (sack);
+  hy_query_filter (query, HY_PKG_REPONAME, HY_NEQ, HY_SYSTEM_REPO_NAME);
+  g_autoptr (DnfPackageSet) pset = hy_query_run_set (query);
+  map_subtract (dnf_packageset_get_map (pset), dnf_packageset_get_map (solved_pset));
+
+  *out_prev_excludes = dnf_sack_get_excludes (sack);
+  dnf_sack_add_excludes (sack, pset);
+  return TRUE;
+}
+
+static void
+store_solve_cache (RpmOstreeContext *self, const char *cachedir, const char *key)
+{
+  auto packages = rust::Vec<rpmostreecxx::SolvedPackage> ();
+  for (guint i = 0; i < self->pkgs->len; i++)
+    {
+      auto pkg = static_cast<DnfPackage *> (self->pkgs->pdata[i]);
+      packages.push_back (rpmostreecxx::SolvedPackage{ dnf_package_get_nevra (pkg),
+                                                       dnf_package_get_reponame (pkg) });
+    }
+
+  g_autoptr (GError) local_error = NULL;
+  if (!ROSCXX (solve_cache_store (cachedir, key, packages), &local_error))
+    sd_journal_print (LOG_WARNING, "Failed to cache depsolve: %s", local_error->message);
+}
+
+/* Returns the ids of the rpm-md repos referenced by the request, or NULL if packages
+ * may come from any enabled repo. */
+static GHashTable *
+get_referenced_rpmmd_repos (RpmOstreeContext *self)
+{
+  if (!self->treefile_rs->get_packages ().empty ()
+      || !self->treefile_rs->get_modules_enable ().empty ()
+      || !self->treefile_rs->get_modules_install ().empty ())
+    return NULL;
+
+  g_autoptr (GHashTable) repos = g_hash_table_new_full (g_str_hash, g_str_equal, g_free, NULL);
+  auto repo_pkgs = self->treefile_rs->get_repo_packages ();
+  for (auto &repo_pkg : repo_pkgs)
+    {
+      auto repo = std::string (repo_pkg.repo);
+      g_hash_table_add (repos, g_strdup (repo.c_str ()));
+    }
+  for (auto &override_replace : self->treefile_rs->get_packages_override_replace ())
+    {
+      if (override_replace.from_kind == rpmostreecxx::OverrideReplacementType::Repo)
+        g_hash_table_add (repos, g_strdup (override_replace.from.c_str ()));
+    }
+  return util::move_nullify (repos);
+}
+
+/* Throw if a package to install comes from a repo which doesn't check signatures. */
+static gboolean
+check_pkgs_gpgcheck (RpmOstreeContext *self, GError **error)
+{
+  for (guint i = 0; i < self->pkgs->len; i++)
+    {
+      auto pkg = static_cast<DnfPackage *> (self->pkgs->pdata[i]);
+      if (rpmostree_pkg_is_local (pkg))
+        continue;
+      auto repo = static_cast<DnfRepo *> (dnf_package_get_repo (pkg));
+      if (repo && !dnf_repo_get_gpgcheck (repo))
+        return glnx_throw (error,
+                           "Package %s is from repo '%s' which doesn't check signatures; this is "
+                           "forbidden by RestrictLayering",
+                           dnf_package_get_nevra (pkg), dnf_repo_get_id (repo));
+    }
+  return TRUE;
+}
+
+/* Check the resolved packages against the forbidden-licenses and
+ * forbidden-packages of the treefile. */
+static gboolean
+check_pkgs_policy (RpmOstreeContext *self, GError **error)
+{
+  auto packages = rust::Vec<rpmostreecxx::PolicyPackage> ();
+  for (guint i = 0; i < self->pkgs->len; i++)
+    {
+      auto pkg = static_cast<DnfPackage *> (self->pkgs->pdata[i]);
+      const char *license = dnf_package_get_license (pkg);
+      packages.push_back (rpmostreecxx::PolicyPackage{
+          dnf_package_get_nevra (pkg), dnf_package_get_name (pkg), license ?: "" });
+    }
+  ROSCXX_TRY (package_policy_check (*self->treefile_rs, packages), error);
+  return TRUE;
+}
+
+/* Returns TRUE if some file dependency of the packages matching @names, or of the
+ * packages they pull in, isn't provided by any package in @sack. That's the case
+ * where the sack set up without the filelists may miss a provider. */
+static gboolean
+file_deps_need_filelists (DnfSack *sack, GPtrArray *names)
+{
+  g_autoptr (GHashTable) visited = g_hash_table_new (NULL, NULL);
+  g_autoptr (GPtrArray) queue = g_ptr_array_new_with_free_func (g_object_unref);
+  auto enqueue = [&] (GPtrArray *pkgs) {
+    for (guint i = 0; i < pkgs->len; i++)
+      {
+        auto pkg = static_cast<DnfPackage *> (pkgs->pdata[i]);
+        if (dnf_package_installed (pkg)
+            || !g_hash_table_add (visited, GINT_TO_POINTER (dnf_package_get_id (pkg))))
+          continue;
+        g_ptr_array_add (queue, g_object_ref (pkg));
+      }
+  };
+
+  for (guint i = 0; i < names->len; i++)
+    {
+      g_auto (HySubject) subject = hy_subject_create (static_cast<const char *> (names->pdata[i]));
+      hy_autoquery HyQuery query = hy_subject_get_best_solution (subject, sack, NULL, NULL, FALSE,
+                                                                 TRUE, TRUE, TRUE, FALSE);
+      g_autoptr (GPtrArray) pkgs = hy_query_run (query);
+      enqueue (pkgs);
+    }
+
+  for (guint i = 0; i < queue->len; i++)
+    {
+      auto pkg = static_cast<DnfPackage *> (queue->pdata[i]);
+      DnfReldepList *reqs = dnf_package_get_requires (pkg);
+      const int n = reqs ? dnf_reldep_list_count (reqs) : 0;
+      gboolean missing = FALSE;
+      for (int j = 0; j < n && !missing; j++)
+        {
+          DnfReldep *reldep = dnf_reldep_list_index (reqs, j);
+          hy_autoquery HyQuery query = hy_query_create (sack);
+          hy_query_filter_reldep (query, HY_PKG_PROVIDES, reldep);
+          g_autoptr (GPtrArray) providers = hy_query_run (query);
+          if (providers->len == 0)
+            missing = g_str_has_prefix (dnf_reldep_to_string (reldep), "/");
+          else
+            enqueue (providers);
+          dnf_reldep_free (reldep);
+        }
+      if (reqs)
+        dnf_reldep_list_free (reqs);
+      if (missing)
+        return TRUE;
+    }
+  return FALSE;
+}
+
 /* Check for/download new rpm-md, then depsolve */
 gboolean
 rpmostree_context_prepare (RpmOstreeContext *self, GCancellable *cancellable, GError **error)
@@ -1786,9 +2291,45 @@ rpmostree_context_prepare (RpmOstreeContext *self, GCancellable *cancellable, GE
     {
       /* default to loading updateinfo in this path; this allows the sack to be used later
        * on for advisories -- it's always downloaded anyway */
-      if (!rpmostree_context_download_metadata (self, DNF_CONTEXT_SETUP_SACK_FLAG_LOAD_UPDATEINFO,
-                                                cancellable, error))
-        return FALSE;
+      auto flags = DNF_CONTEXT_SETUP_SACK_FLAG_LOAD_UPDATEINFO;
+      /* The filelists are large, and the primary metadata already has the file provides
+       * commonly depended on (e.g. binaries), so we only fetch them if a path is requested
+       * directly, or if some file dependency of the request isn't provided otherwise. */
+      gboolean need_filelists = FALSE;
+      g_autoptr (GPtrArray) names = g_ptr_array_new_with_free_func (g_free);
+      for (auto &pkg : packages)
+        {
+          if (g_str_has_prefix (pkg.c_str (), "/"))
+            need_filelists = TRUE;
+          g_ptr_array_add (names, g_strdup (pkg.c_str ()));
+        }
+      for (auto &repo_pkg : self->treefile_rs->get_repo_packages ())
+        {
+          for (auto &pkg : repo_pkg.packages)
+            g_ptr_array_add (names, g_strdup (pkg.c_str ()));
+        }
+      for (auto &override_replace : packages_override_replace)
+        {
+          for (auto &pkg : override_replace.packages)
+            g_ptr_array_add (names, g_strdup (pkg.c_str ()));
+        }
+      /* only refresh the metadata of the repos the request references */
+      g_autoptr (GHashTable) refresh_repos = get_referenced_rpmmd_repos (self);
+      if (!need_filelists)
+        {
+          auto skip_flags = static_cast<DnfContextSetupSackFlags> (
+              flags | DNF_CONTEXT_SETUP_SACK_FLAG_SKIP_FILELISTS);
+          if (!download_metadata (self, skip_flags, refresh_repos, cancellable, error))
+            return FALSE;
+          need_filelists = file_deps_need_filelists (dnf_context_get_sack (dnfctx), names);
+          if (need_filelists)
+            rpmostree_output_message ("File dependencies require the filelists");
+        }
+      if (need_filelists)
+        {
+          if (!download_metadata (self, flags, refresh_repos, cancellable, error))
+            return FALSE;
+        }
       journal_rpmmd_info (self);
     }
   DnfSack *sack = dnf_context_get_sack (dnfctx);
@@ -1985,8 +2526,8 @@ rpmostree_context_prepare (RpmOstreeContext *self, GCancellable *cancellable, GE
   auto repo_pkgs = self->treefile_rs->get_repo_packages ();
   for (auto &repo_pkg : repo_pkgs)
     {
-      auto repo = std::string (repo_pkg.get_repo ());
-      auto pkgs = repo_pkg.get_packages ();
+      auto repo = std::string (repo_pkg.repo);
+      auto &pkgs = repo_pkg.packages;
       for (auto &pkg_str : pkgs)
         {
           auto pkg = std::string (pkg_str);
@@ -2111,7 +2652,8 @@ rpmostree_context_prepare (RpmOstreeContext *self, GCancellable *cancellable, GE
     }
 
   if (missing_pkgs && missing_pkgs->len > 0)
-    return throw_package_list (error, "Packages not found", missing_pkgs);
+    return throw_package_list (error, DNF_ERROR_PACKAGE_NOT_FOUND, "Packages not found",
+                               missing_pkgs);
 
   /* And lock all the base packages we don't expect to be replaced. */
   {
@@ -2135,20 +2677,133 @@ rpmostree_context_prepare (RpmOstreeContext *self, GCancellable *cancellable, GE
   auto actions = static_cast<DnfGoalActions> (DNF_INSTALL | DNF_ALLOW_UNINSTALL);
   if (!self->treefile_rs->get_recommends ())
     actions = static_cast<DnfGoalActions> (static_cast<int> (actions) | DNF_IGNORE_WEAK_DEPS);
+  g_autofree char *solve_cache_key = NULL;
+  if (!get_solve_cache_key (self, &solve_cache_key, error))
+    return FALSE;
+  g_autofree char *solve_cache_dir = get_solve_cache_dir (self);
+
+  rpmostree_output_set_stage (RPMOSTREE_OUTPUT_STAGE_RESOLVE);
   auto task = rpmostreecxx::progress_begin_task ("Resolving dependencies");
+  /* If we already solved this exact request, only consider the packages from the
+   * previous solution; libsolv then merely has to verify it rather than search the
+   * whole sack. Should that somehow fail, we fall back to the full depsolve. */
+  gboolean solved_from_cache = FALSE;
+  g_autoptr (DnfPackageSet) prev_excludes = NULL;
+  if (solve_cache_key
+      && exclude_unsolved_packages (self, solve_cache_dir, solve_cache_key, &prev_excludes))
+    {
+      g_autoptr (GError) local_error = NULL;
+      solved_from_cache = dnf_goal_depsolve (goal, actions, &local_error);
+      if (!solved_from_cache)
+        sd_journal_print (LOG_WARNING, "Failed to reuse cached depsolve: %s",
+                          local_error->message);
+      dnf_sack_set_excludes (sack, prev_excludes);
+    }
   /* XXX: consider a --allow-uninstall switch? */
-  if (!dnf_goal_depsolve (goal, actions, error)
-      || !check_goal_solution (self, removed_pkgnames, replaced_pkgnames, error))
+  if (!solved_from_cache && !dnf_goal_depsolve (goal, actions, error))
+    return FALSE;
+  if (!check_goal_solution (self, removed_pkgnames, replaced_pkgnames, error))
     return FALSE;
   g_clear_pointer (&self->pkgs, (GDestroyNotify)g_ptr_array_unref);
   self->pkgs = dnf_goal_get_packages (goal, DNF_PACKAGE_INFO_INSTALL, DNF_PACKAGE_INFO_UPDATE,
                                       DNF_PACKAGE_INFO_DOWNGRADE, -1);
+  if (self->require_gpgcheck && !check_pkgs_gpgcheck (self, error))
+    return FALSE;
+  if (!check_pkgs_policy (self, error))
+    return FALSE;
+  if (solved_from_cache)
+    task->end ("done (cached)");
+  else if (solve_cache_key && self->pkgs->len > 0)
+    store_solve_cache (self, solve_cache_dir, solve_cache_key);
   if (!sort_packages (self, self->pkgs, cancellable, error))
     return glnx_prefix_error (error, "Sorting packages");
 
+  g_clear_pointer (&self->unsatisfied_weakdeps, g_variant_unref);
+  if (self->find_weakdeps)
+    self->unsatisfied_weakdeps = find_unsatisfied_weakdeps (self);
+
   return TRUE;
 }
 
+/* Returns TRUE if some package providing @reldep will be in the final package
+ * set, i.e. it is either going to be installed or is in the base and not removed. */
+static gboolean
+reldep_is_satisfied (RpmOstreeContext *self, DnfReldep *reldep, GHashTable *installing_nevras)
+{
+  hy_autoquery HyQuery query = hy_query_create (dnf_context_get_sack (self->dnfctx));
+  hy_query_filter_reldep (query, HY_PKG_PROVIDES, reldep);
+  g_autoptr (GPtrArray) providers = hy_query_run (query);
+  for (guint i = 0; i < providers->len; i++)
+    {
+      auto provider = static_cast<DnfPackage *> (providers->pdata[i]);
+      if (g_hash_table_contains (installing_nevras, dnf_package_get_nevra (provider)))
+        return TRUE;
+      if (dnf_package_installed (provider)
+          && !(self->pkgs_to_remove
+               && g_hash_table_contains (self->pkgs_to_remove, dnf_package_get_name (provider))))
+        return TRUE;
+    }
+  return FALSE;
+}
+
+/* Gather the weak dependencies (Recommends and Suggests) of the packages to
+ * install which won't be satisfied by the final package set. These are either
+ * unavailable or were skipped (e.g. `recommends: false`); either way it's
+ * useful to tell users why optional functionality may be missing.
+ */
+static GVariant *
+find_unsatisfied_weakdeps (RpmOstreeContext *self)
+{
+  g_autoptr (GHashTable) installing_nevras = g_hash_table_new (g_str_hash, g_str_equal);
+  for (guint i = 0; i < self->pkgs->len; i++)
+    {
+      auto pkg = static_cast<DnfPackage *> (self->pkgs->pdata[i]);
+      g_hash_table_add (installing_nevras, (gpointer)dnf_package_get_nevra (pkg));
+    }
+
+  g_auto (GVariantBuilder) builder;
+  g_variant_builder_init (&builder, (GVariantType *)"a(sss)");
+  for (guint i = 0; i < self->pkgs->len; i++)
+    {
+      auto pkg = static_cast<DnfPackage *> (self->pkgs->pdata[i]);
+      struct
+      {
+        const char *kind;
+        DnfReldepList *deps;
+      } weakdeps[] = {
+        { "recommends", dnf_package_get_recommends (pkg) },
+        { "suggests", dnf_package_get_suggests (pkg) },
+      };
+      for (guint j = 0; j < G_N_ELEMENTS (weakdeps); j++)
+        {
+          const int n = weakdeps[j].deps ? dnf_reldep_list_count (weakdeps[j].deps) : 0;
+          for (int k = 0; k < n; k++)
+            {
+              DnfReldep *reldep = dnf_reldep_list_index (weakdeps[j].deps, k);
+              if (!reldep_is_satisfied (self, reldep, installing_nevras))
+                g_variant_builder_add (&builder, "(sss)", dnf_package_get_name (pkg),
+                                       weakdeps[j].kind, dnf_reldep_to_string (reldep));
+              dnf_reldep_free (reldep);
+            }
+          if (weakdeps[j].deps)
+            dnf_reldep_list_free (weakdeps[j].deps);
+        }
+    }
+  return g_variant_ref_sink (g_variant_builder_end (&builder));
+}
+
+/* Must have invoked rpmostree_context_prepare().
+ * Returns: (transfer none) (nullable): The weak dependencies of the depsolved
+ * packages which aren't satisfied, as an `a(sss)` of package name, kind
+ * ("recommends" or "suggests") and dependency, or %NULL if
+ * rpmostree_context_set_find_weakdeps() wasn't enabled.
+ */
+GVariant *
+rpmostree_context_get_unsatisfied_weakdeps (RpmOstreeContext *self)
+{
+  return self->unsatisfied_weakdeps;
+}
+
 /* Must have invoked rpmostree_context_prepare().
  * Returns: (transfer container): All packages in the depsolved list.
  */
@@ -2280,6 +2935,24 @@ rpmostree_context_get_state_sha512 (RpmOstreeContext *self, char **out_checksum,
   return TRUE;
 }
 
+/* Like rpmostree_context_get_state_sha512(), but only covering the packages,
+ * and not the rest of the treefile (e.g. kernel arguments). */
+gboolean
+rpmostree_context_get_packages_sha512 (RpmOstreeContext *self, char **out_checksum,
+                                       GError **error)
+{
+  g_autoptr (GChecksum) checksum = g_checksum_new (G_CHECKSUM_SHA512);
+  if (!self->empty)
+    {
+      if (!rpmostree_dnf_add_checksum_goal (checksum, dnf_context_get_goal (self->dnfctx),
+                                            get_pkgcache_repo (self), error))
+        return FALSE;
+    }
+
+  *out_checksum = g_strdup (g_checksum_get_string (checksum));
+  return TRUE;
+}
+
 static GHashTable *
 gather_source_to_packages (GPtrArray *packages)
 {
@@ -2319,8 +2992,11 @@ rpmostree_download_packages (GPtrArray *packages, GCancellable *cancellable, GEr
   GLNX_HASH_TABLE_FOREACH_KV (source_to_packages, DnfRepo *, src, GPtrArray *, src_packages)
     {
       glnx_unref_object DnfState *hifstate = dnf_state_new ();
+      /* Packages downloaded before a cancellation are kept in the cache */
+      dnf_state_set_cancellable (hifstate, cancellable);
       auto msg = g_strdup_printf ("Downloading from '%s'", dnf_repo_get_id (src));
-      auto progress = rpmostreecxx::progress_percent_begin (msg);
+      auto progress = rpmostreecxx::progress_percent_begin_timed (msg, "download",
+                                                                  src_packages->len);
       progress_sigid
           = g_signal_connect (hifstate, "percentage-changed",
                               G_CALLBACK (on_hifstate_percentage_changed), (void *)progress.get ());
@@ -2333,11 +3009,74 @@ rpmostree_download_packages (GPtrArray *packages, GCancellable *cancellable, GEr
         return FALSE;
 
       g_signal_handler_disconnect (hifstate, progress_sigid);
+      progress->end ("");
     }
 
   return TRUE;
 }
 
+/* Limit the rate of package downloads, for all the contexts set up afterwards. */
+void
+rpmostree_set_download_bandwidth_limit (guint64 bandwidth_limit_kbps)
+{
+  auto &config = libdnf::getGlobalMainConfig (false);
+  /* An absolute throttle is in bytes per second, 0 meaning no limit */
+  config.throttle ().set (libdnf::Option::Priority::RUNTIME, bandwidth_limit_kbps * 1024.0);
+}
+
+/* Number of packages to import concurrently; 0 means automatic */
+static guint import_jobs;
+
+/* Set the number of packages imported concurrently, for all imports started
+ * afterwards.  Zero means automatic, see rpm_import_jobs(). */
+void
+rpmostree_set_import_jobs (guint n_jobs)
+{
+  import_jobs = n_jobs;
+}
+
+/* Whether to reconstruct packages from deltas when possible */
+static gboolean use_deltarpm;
+
+/* Download packages as deltas against the versions installed on the host when the
+ * repos provide them, for all the downloads started afterwards. */
+void
+rpmostree_set_deltarpm (gboolean enabled)
+{
+  use_deltarpm = enabled;
+}
+
+/* Whether to report the host for DNF Count Me when fetching rpm-md */
+static gboolean use_countme;
+
+/* Count the host when the rpm-md of a repo with countme=1 is refreshed by a
+ * client operation, for all the contexts set up afterwards; see
+ * countme_rpmmd_fetched(). */
+void
+rpmostree_set_countme (gboolean enabled)
+{
+  use_countme = enabled;
+}
+
+/* Tune package downloads, for all the contexts set up afterwards.  Zero for
+ * @parallel_downloads and @bandwidth_limit_kbps, and -1 for @retries, mean the
+ * libdnf defaults. */
+void
+rpmostree_set_download_config (guint parallel_downloads, guint64 bandwidth_limit_kbps,
+                               gint retries)
+{
+  /* Don't load /etc/dnf/dnf.conf as a side effect; our values take precedence anyway */
+  auto &config = libdnf::getGlobalMainConfig (false);
+  const auto priority = libdnf::Option::Priority::RUNTIME;
+
+  auto &parallel = config.max_parallel_downloads ();
+  parallel.set (priority,
+                parallel_downloads > 0 ? parallel_downloads : parallel.getDefaultValue ());
+  rpmostree_set_download_bandwidth_limit (bandwidth_limit_kbps);
+  auto &retries_opt = config.retries ();
+  retries_opt.set (priority, retries >= 0 ? (guint)retries : retries_opt.getDefaultValue ());
+}
+
 gboolean
 rpmostree_find_and_download_packages (const char *const *packages, const char *source,
                                       const char *source_root, const char *repo_root,
@@ -2411,11 +3150,112 @@ rpmostree_find_and_download_packages (const char *const *packages, const char *s
   return TRUE;
 }
 
+/* Reconstruct @pkg from a delta against the version installed on the host, if the
+ * repo provides one. Sets @out_delta_size to the size of the delta, or 0 if there is
+ * none. */
+static gboolean
+download_pkg_delta (DnfPackage *pkg, guint64 *out_delta_size, GError **error)
+{
+  *out_delta_size = 0;
+
+  CXX_TRY_VAR (from_evr,
+               rpmostreecxx::deltarpm_installed_evr (dnf_package_get_name (pkg),
+                                                     dnf_package_get_arch (pkg)),
+               error);
+  if (from_evr.empty ())
+    return TRUE;
+  g_autoptr (DnfPackageDelta) delta = dnf_package_get_delta_from_evr (pkg, from_evr.c_str ());
+  if (!delta)
+    return TRUE;
+
+  /* Fetched through librepo with the repo's configuration (proxy, TLS, mirrors...);
+   * a relative location is resolved against the repo's mirrors */
+  const char *location = dnf_packagedelta_get_location (delta);
+  const char *baseurl = dnf_packagedelta_get_baseurl (delta);
+  g_autofree char *url
+      = baseurl ? g_build_path ("/", baseurl, location, NULL) : g_strdup (location);
+  g_auto (GLnxTmpfile) tmpf = {
+    0,
+  };
+  if (!glnx_open_anonymous_tmpfile (O_RDWR | O_CLOEXEC, &tmpf, error))
+    return FALSE;
+  try
+    {
+      dnf_repo_get_repo (dnf_package_get_repo (pkg))->downloadUrl (url, tmpf.fd);
+    }
+  catch (std::exception &e)
+    {
+      return glnx_throw (error, "Downloading %s: %s", url, e.what ());
+    }
+
+  CXX_TRY_VAR (chksum_repr, rpmostreecxx::get_repodata_chksum_repr (*pkg), error);
+  CXX_TRY (rpmostreecxx::deltarpm_apply (tmpf.fd, dnf_package_get_filename (pkg), chksum_repr),
+           error);
+  *out_delta_size = dnf_packagedelta_get_downloadsize (delta);
+  return TRUE;
+}
+
+/* Reconstruct what we can of @packages from deltas, and return in @out_remaining
+ * the packages which still need to be downloaded in full. Failing to apply a delta
+ * isn't fatal; we just fall back to the full package. */
+static gboolean
+download_deltas (RpmOstreeContext *self, GPtrArray *packages, GPtrArray **out_remaining,
+                 GCancellable *cancellable, GError **error)
+{
+  g_autoptr (GPtrArray) remaining = g_ptr_array_new_with_free_func (g_object_unref);
+  guint n_deltas = 0;
+  guint64 full_size = 0;
+  guint64 deltas_size = 0;
+  {
+    auto progress = rpmostreecxx::progress_nitems_begin (packages->len, "Applying deltas");
+    for (guint i = 0; i < packages->len; i++)
+      {
+        auto pkg = static_cast<DnfPackage *> (packages->pdata[i]);
+
+        if (g_cancellable_set_error_if_cancelled (cancellable, error))
+          return FALSE;
+
+        g_autoptr (GError) local_error = NULL;
+        guint64 delta_size = 0;
+        if (rpmostree_pkg_is_local (pkg)
+            || !download_pkg_delta (pkg, &delta_size, &local_error))
+          {
+            if (local_error)
+              sd_journal_print (LOG_WARNING, "Downloading %s in full: %s",
+                                dnf_package_get_nevra (pkg), local_error->message);
+            delta_size = 0;
+          }
+        if (delta_size > 0)
+          {
+            n_deltas++;
+            full_size += dnf_package_get_downloadsize (pkg);
+            deltas_size += delta_size;
+          }
+        else
+          g_ptr_array_add (remaining, g_object_ref (pkg));
+        progress->nitems_update (i + 1);
+      }
+  }
+
+  if (n_deltas > 0)
+    {
+      g_autofree char *full_sizestr = g_format_size (full_size);
+      g_autofree char *deltas_sizestr = g_format_size (deltas_size);
+      rpmostree_output_message ("Reconstructed %u package%s from deltas (%s instead of %s)",
+                                n_deltas, _NS (n_deltas), deltas_sizestr, full_sizestr);
+    }
+
+  *out_remaining = util::move_nullify (remaining);
+  return TRUE;
+}
+
 gboolean
 rpmostree_context_download (RpmOstreeContext *self, GCancellable *cancellable, GError **error)
 {
   int n = self->pkgs_to_download->len;
 
+  rpmostree_output_set_stage (RPMOSTREE_OUTPUT_STAGE_DOWNLOAD);
+
   if (n > 0)
     {
       guint64 size = dnf_package_array_get_download_size (self->pkgs_to_download);
@@ -2425,6 +3265,17 @@ rpmostree_context_download (RpmOstreeContext *self, GCancellable *cancellable, G
   else
     return TRUE;
 
+  /* Deltas are reconstructed from the files of the booted deployment, so this only
+   * makes sense for client-side layering */
+  if (use_deltarpm && self->is_system && !self->is_container
+      && rpmostreecxx::deltarpm_available ())
+    {
+      g_autoptr (GPtrArray) remaining = NULL;
+      if (!download_deltas (self, self->pkgs_to_download, &remaining, cancellable, error))
+        return FALSE;
+      return rpmostree_download_packages (remaining, cancellable, error);
+    }
+
   return rpmostree_download_packages (self->pkgs_to_download, cancellable, error);
 }
 
@@ -2473,7 +3324,8 @@ start_async_import_one_package (RpmOstreeContext *self, DnfPackage *pkg, GCancel
   if (!unpacker)
     return glnx_prefix_error (error, "creating importer");
 
-  rpmostree_importer_run_async (unpacker, cancellable, on_async_import_done, self);
+  rpmostree_importer_run_async (unpacker, self->async_import_pool, cancellable,
+                                on_async_import_done, self);
 
   return TRUE;
 }
@@ -2517,6 +3369,8 @@ rpmostree_context_import (RpmOstreeContext *self, GCancellable *cancellable, GEr
   if (n == 0)
     return TRUE;
 
+  rpmostree_output_set_stage (RPMOSTREE_OUTPUT_STAGE_ASSEMBLE);
+
   OstreeRepo *repo = get_pkgcache_repo (self);
   g_assert (repo != NULL);
 
@@ -2533,12 +3387,16 @@ rpmostree_context_import (RpmOstreeContext *self, GCancellable *cancellable, GEr
   self->async_running = TRUE;
   self->async_index = 0;
   self->n_async_running = 0;
-  /* We're CPU bound, so just use processors */
-  self->n_async_max = g_get_num_processors ();
+  /* We're mostly CPU bound, but also consider the storage of the repo */
+  self->n_async_max = rpmostreecxx::rpm_import_jobs (ostree_repo_get_dfd (repo), import_jobs);
+  self->n_async_max = MIN (self->n_async_max, (guint)n);
+  self->async_import_pool = rpmostree_importer_new_thread_pool (self->n_async_max, error);
+  if (!self->async_import_pool)
+    return FALSE;
   self->async_cancellable = cancellable;
 
-  self->async_progress
-      = rpmostreecxx::progress_nitems_begin (self->pkgs_to_import->len, "Importing packages");
+  self->async_progress = rpmostreecxx::progress_nitems_begin_timed (
+      self->pkgs_to_import->len, "Importing packages", "import");
 
   /* Process imports */
   GMainContext *mainctx = g_main_context_get_thread_default ();
@@ -2552,6 +3410,8 @@ rpmostree_context_import (RpmOstreeContext *self, GCancellable *cancellable, GEr
   self->async_error = NULL;
   while (self->async_running)
     g_main_context_iteration (mainctx, TRUE);
+  /* All imports completed; this just joins the threads */
+  g_thread_pool_free (util::move_nullify (self->async_import_pool), FALSE, TRUE);
   if (self->async_error)
     {
       g_propagate_error (error, util::move_nullify (self->async_error));
@@ -2567,13 +3427,15 @@ rpmostree_context_import (RpmOstreeContext *self, GCancellable *cancellable, GEr
 
   sd_journal_send ("MESSAGE_ID=" SD_ID128_FORMAT_STR,
                    SD_ID128_FORMAT_VAL (RPMOSTREE_MESSAGE_PKG_IMPORT), "MESSAGE=Imported %u pkg%s",
-                   n, _NS (n), "IMPORTED_N_PKGS=%u", n, NULL);
+                   n, _NS (n), "IMPORTED_N_PKGS=%u", n, "IMPORT_JOBS=%u", self->n_async_max,
+                   NULL);
 
   return TRUE;
 }
 
-/* Given a single package, verify its GPG signature (if enabled), open a file
- * descriptor for it, and delete the on-disk downloaded copy.
+/* Given a single package, verify its GPG signature (if enabled, and against the
+ * rpm-signing-keys of the treefile if set), open a file descriptor for it, and delete
+ * the on-disk downloaded copy.
  */
 gboolean
 rpmostree_context_consume_package (RpmOstreeContext *self, DnfPackage *pkg, int *out_fd,
@@ -2588,6 +3450,11 @@ rpmostree_context_consume_package (RpmOstreeContext *self, DnfPackage *pkg, int
   if (!glnx_openat_rdonly (AT_FDCWD, pkg_path, TRUE, &fd, error))
     return FALSE;
 
+  if (!verify_rpm_signing_key (self, dnf_package_get_nevra (pkg), fd, error))
+    return FALSE;
+  if (!verify_pinned_repo_key (self, pkg, fd, error))
+    return FALSE;
+
   /* And delete it now; this does mean if we fail it'll have been
    * deleted and hence more annoying to debug, but in practice people
    * should be able to redownload, and if the error was something like
@@ -2892,8 +3759,9 @@ checkout_package (OstreeRepo *repo, int dfd, const char *path, OstreeRepoDevInoC
 
   opts.devino_to_csum_cache = devino_cache;
 
-  /* Always want hardlinks */
-  opts.no_copy_fallback = TRUE;
+  /* Always want hardlinks, unless copies are cheap reflinks anyway; e.g. on btrfs,
+   * hardlinking fails across subvolumes but cloning works. */
+  opts.no_copy_fallback = !rpmostreecxx::fs_supports_reflink (dfd);
   /* Used in the no-rofiles-fuse path */
   opts.force_copy_zerosized = force_copy_zerosized;
 
@@ -3358,7 +4226,9 @@ relabel_if_necessary (RpmOstreeContext *self, GCancellable *cancellable, GError
     0,
   };
   const guint n_to_relabel = self->pkgs_to_relabel->len;
-  self->async_progress = rpmostreecxx::progress_nitems_begin (n_to_relabel, "Relabeling");
+  const gint64 start_time = g_get_monotonic_time ();
+  self->async_progress
+      = rpmostreecxx::progress_nitems_begin_timed (n_to_relabel, "Relabeling", "relabel");
   for (guint i = 0; i < n_to_relabel; i++)
     {
       auto pkg = static_cast<DnfPackage *> (self->pkgs_to_relabel->pdata[i]);
@@ -3384,10 +4254,12 @@ relabel_if_necessary (RpmOstreeContext *self, GCancellable *cancellable, GError
   if (!ostree_repo_commit_transaction (ostreerepo, NULL, cancellable, error))
     return FALSE;
 
+  const gint64 elapsed_ms = (g_get_monotonic_time () - start_time) / 1000;
   sd_journal_send ("MESSAGE_ID=" SD_ID128_FORMAT_STR,
                    SD_ID128_FORMAT_VAL (RPMOSTREE_MESSAGE_SELINUX_RELABEL),
-                   "MESSAGE=Relabeled %u/%u pkgs", data.n_changed_pkgs, n_to_relabel,
-                   "RELABELED_PKGS=%u/%u", data.n_changed_pkgs, n_to_relabel, NULL);
+                   "MESSAGE=Relabeled %u/%u pkgs in %" G_GINT64_FORMAT "ms", data.n_changed_pkgs,
+                   n_to_relabel, elapsed_ms, "RELABELED_PKGS=%u/%u", data.n_changed_pkgs,
+                   n_to_relabel, "RELABEL_ELAPSED_MS=%" G_GINT64_FORMAT, elapsed_ms, NULL);
 
   g_clear_pointer (&self->pkgs_to_relabel, (GDestroyNotify)g_ptr_array_unref);
   self->n_async_pkgs_relabeled = 0;
@@ -3425,6 +4297,104 @@ rpmostree_context_force_relabel (RpmOstreeContext *self, GCancellable *cancellab
   return relabel_if_necessary (self, cancellable, error);
 }
 
+/* Whether @pkg can be imported without downloading it, i.e. it's already in the
+ * pkgcache, or we have a copy matching the checksum from the rpm-md. */
+static gboolean
+pkg_is_available_locally (RpmOstreeContext *self, DnfPackage *pkg, gboolean *out_available,
+                          GError **error)
+{
+  gboolean in_ostree = FALSE;
+  gboolean selinux_match = FALSE;
+  if (!find_pkg_in_ostree (self, pkg, self->sepolicy, &in_ostree, &selinux_match, error))
+    return FALSE;
+  if (in_ostree)
+    {
+      *out_available = TRUE;
+      return TRUE;
+    }
+
+  /* This covers both local repos and previous downloads */
+  return dnf_package_check_filename (pkg, out_available, error);
+}
+
+/* Import the packages of @commit, as listed in its rpmdb, which are available
+ * without downloading anything: from local repos, or left in the cache by a
+ * previous download.  Packages already in the pkgcache are relabeled if needed.
+ * Since ostree objects are content-addressed, the files which come out of the
+ * import the same as in @commit (i.e. those not changed by scriptlets or
+ * postprocessing) then don't need to be pulled.  The sepolicy of @self should
+ * be that of @commit, and its rpm-md must have been loaded, but it must not
+ * have been prepared.  Sets @out_n_pkgs to the number of packages found. */
+gboolean
+rpmostree_context_import_local_pkgs (RpmOstreeContext *self, GVariant *commit, guint *out_n_pkgs,
+                                     GCancellable *cancellable, GError **error)
+{
+  *out_n_pkgs = 0;
+
+  g_autoptr (GVariant) metadata = g_variant_get_child_value (commit, 0);
+  g_autoptr (GVariantDict) metadata_dict = g_variant_dict_new (metadata);
+  g_autoptr (GVariant) pkglist = g_variant_dict_lookup_value (
+      metadata_dict, "rpmostree.rpmdb.pkglist", G_VARIANT_TYPE ("a(sssss)"));
+  if (!pkglist)
+    return TRUE; /* Note early return */
+
+  DnfSack *sack = dnf_context_get_sack (self->dnfctx);
+  g_autoptr (GPtrArray) pkgs = g_ptr_array_new_with_free_func (g_object_unref);
+  const guint n = g_variant_n_children (pkglist);
+  for (guint i = 0; i < n; i++)
+    {
+      if (g_cancellable_set_error_if_cancelled (cancellable, error))
+        return FALSE;
+
+      const char *name, *epoch, *version, *release, *arch;
+      g_variant_get_child (pkglist, i, "(&s&s&s&s&s)", &name, &epoch, &version, &release,
+                           &arch);
+      /* Same format as dnf_package_get_nevra(), which omits a zero epoch */
+      g_autofree char *nevra
+          = g_str_equal (epoch, "0")
+                ? g_strdup_printf ("%s-%s-%s.%s", name, version, release, arch)
+                : g_strdup_printf ("%s-%s:%s-%s.%s", name, epoch, version, release, arch);
+
+      hy_autoquery HyQuery query = hy_query_create (sack);
+      hy_query_filter (query, HY_PKG_NEVRA, HY_EQ, nevra);
+      hy_query_filter (query, HY_PKG_REPONAME, HY_NEQ, HY_SYSTEM_REPO_NAME);
+      g_autoptr (GPtrArray) matches = hy_query_run (query);
+      rpmostree_set_repos_on_packages (self->dnfctx, matches);
+      for (guint j = 0; j < matches->len; j++)
+        {
+          auto pkg = static_cast<DnfPackage *> (matches->pdata[j]);
+          gboolean available = FALSE;
+          if (!pkg_is_available_locally (self, pkg, &available, error))
+            return glnx_prefix_error (error, "Looking for %s", nevra);
+          if (available)
+            {
+              g_ptr_array_add (pkgs, g_object_ref (pkg));
+              break;
+            }
+        }
+    }
+
+  if (pkgs->len == 0)
+    return TRUE; /* Note early return */
+
+  if (!sort_packages (self, pkgs, cancellable, error))
+    return FALSE;
+  /* We only picked packages which don't need downloading above; but the cache may
+   * have changed under us since */
+  if (self->pkgs_to_download->len > 0)
+    return glnx_throw (error, "%u local package%s no longer available",
+                       self->pkgs_to_download->len,
+                       self->pkgs_to_download->len == 1 ? " is" : "s are");
+
+  if (!rpmostree_context_import (self, cancellable, error))
+    return FALSE;
+  if (!relabel_if_necessary (self, cancellable, error))
+    return FALSE;
+
+  *out_n_pkgs = pkgs->len;
+  return TRUE;
+}
+
 typedef struct
 {
   FD_t current_trans_fd;
@@ -3535,7 +4505,8 @@ run_script_sync (RpmOstreeContext *self, int rootfs_dfd, GLnxTmpDir *var_lib_rpm
     return FALSE;
 
   if (!rpmostree_script_run_sync (pkg, hdr, kind, rootfs_dfd, var_lib_rpm_statedir,
-                                  self->enable_rofiles, out_n_run, cancellable, error))
+                                  self->enable_rofiles, self->semodule_cachedir, out_n_run,
+                                  cancellable, error))
     return FALSE;
 
   return TRUE;
@@ -3756,7 +4727,8 @@ run_all_transfiletriggers (RpmOstreeContext *self, rpmts ts, int rootfs_dfd, gui
       while ((hdr = rpmdbNextIterator (mi)) != NULL)
         {
           if (!rpmostree_transfiletriggers_run_sync (hdr, rootfs_dfd, self->enable_rofiles,
-                                                     out_n_run, cancellable, error))
+                                                     self->semodule_cachedir, out_n_run,
+                                                     cancellable, error))
             return FALSE;
         }
     }
@@ -3774,8 +4746,9 @@ run_all_transfiletriggers (RpmOstreeContext *self, rpmts ts, int rootfs_dfd, gui
       if (!get_package_metainfo (self, path, &hdr, NULL, error))
         return FALSE;
 
-      if (!rpmostree_transfiletriggers_run_sync (hdr, rootfs_dfd, self->enable_rofiles, out_n_run,
-                                                 cancellable, error))
+      if (!rpmostree_transfiletriggers_run_sync (hdr, rootfs_dfd, self->enable_rofiles,
+                                                 self->semodule_cachedir, out_n_run, cancellable,
+                                                 error))
         return FALSE;
     }
   return TRUE;
@@ -3844,8 +4817,8 @@ process_one_ostree_layer (RpmOstreeContext *self, int rootfs_dfd, const char *re
    * the input commits right now.
    * opts.devino_to_csum_cache = self->devino_cache;
    */
-  /* Always want hardlinks */
-  opts.no_copy_fallback = TRUE;
+  /* Always want hardlinks, unless copies are cheap reflinks anyway */
+  opts.no_copy_fallback = !rpmostreecxx::fs_supports_reflink (rootfs_dfd);
 
   g_autofree char *rev = NULL;
   if (!ostree_repo_resolve_rev (repo, ref, FALSE, &rev, error))
@@ -3888,6 +4861,49 @@ process_ostree_layers (RpmOstreeContext *self, int rootfs_dfd, GCancellable *can
   return TRUE;
 }
 
+/* Write the headers of the packages @nvras from the rpmdb of @ts to a new anonymous file,
+ * like `rpmdb --exportdb` does, for the target's rpm to import them. */
+static gboolean
+export_rpmdb_headers (rpmts ts, const rust::Vec<rust::String> &nvras, int *out_fd,
+                      GError **error)
+{
+  g_autoptr (GHashTable) wanted = g_hash_table_new_full (g_str_hash, g_str_equal, g_free, NULL);
+  for (auto &nvra : nvras)
+    g_hash_table_add (wanted, g_strdup (nvra.c_str ()));
+
+  g_auto (GLnxTmpfile) tmpf = {
+    0,
+  };
+  if (!glnx_open_anonymous_tmpfile (O_RDWR | O_CLOEXEC, &tmpf, error))
+    return FALSE;
+  g_auto (FD_t) rpmfd = fdDup (tmpf.fd);
+  if (rpmfd == NULL)
+    return glnx_throw_errno_prefix (error, "fdDup");
+
+  guint n_found = 0;
+  g_auto (rpmdbMatchIterator) mi = rpmtsInitIterator (ts, RPMDBI_PACKAGES, NULL, 0);
+  Header h;
+  while ((h = rpmdbNextIterator (mi)) != NULL)
+    {
+      g_autofree char *nvra = headerFormat (h, "%{NAME}-%{VERSION}-%{RELEASE}.%{ARCH}", NULL);
+      if (nvra == NULL || !g_hash_table_contains (wanted, nvra))
+        continue;
+      if (headerWrite (rpmfd, h, HEADER_MAGIC_YES) != 0)
+        return glnx_throw (error, "Writing header of %s: %s", nvra, Fstrerror (rpmfd));
+      n_found++;
+    }
+  if (n_found != g_hash_table_size (wanted))
+    return glnx_throw (error, "Found %u of %u added packages in the rpmdb", n_found,
+                       g_hash_table_size (wanted));
+  if (Fflush (rpmfd) != 0)
+    return glnx_throw (error, "Writing headers: %s", Fstrerror (rpmfd));
+  if (lseek (tmpf.fd, 0, SEEK_SET) < 0)
+    return glnx_throw_errno_prefix (error, "lseek");
+
+  *out_fd = glnx_steal_fd (&tmpf.fd);
+  return TRUE;
+}
+
 static gboolean
 write_rpmdb (RpmOstreeContext *self, int tmprootfs_dfd, GPtrArray *overlays,
              GPtrArray *overrides_replace, GPtrArray *overrides_remove, gboolean have_fileoverride,
@@ -3898,6 +4914,17 @@ write_rpmdb (RpmOstreeContext *self, int tmprootfs_dfd, GPtrArray *overlays,
   if (!glnx_shutil_mkdir_p_at (tmprootfs_dfd, RPMOSTREE_RPMDB_LOCATION, 0755, cancellable, error))
     return FALSE;
 
+  /* If we're layering, keep the original rpmdb to update it incrementally in the target's
+   * format below, rather than regenerating it from scratch */
+  const gboolean rpmdb_for_target
+      = self->treefile_rs && self->treefile_rs->rpmdb_backend_is_target ();
+  gboolean have_rpmdb_snapshot = FALSE;
+  if (rpmdb_for_target && !self->treefile_rs->should_normalize_rpmdb ())
+    {
+      CXX_TRY_VAR (snapshotted, rpmostreecxx::rpmdb_snapshot (tmprootfs_dfd), error);
+      have_rpmdb_snapshot = snapshotted;
+    }
+
   /* Now, we use the separate rpmdb ts which *doesn't* have a rootdir set,
    * because if it did rpmtsRun() would try to chroot which it won't be able to
    * if we're unprivileged, even though we're not trying to run %post scripts
@@ -3997,18 +5024,60 @@ write_rpmdb (RpmOstreeContext *self, int tmprootfs_dfd, GPtrArray *overlays,
         return FALSE;
     }
 
+  /* Note the packages which changed, including the ones replaced by upgrades */
+  rust::Vec<rust::String> rpmdb_added;
+  rust::Vec<rust::String> rpmdb_removed;
+  {
+    rpmtsi tsi = rpmtsiInit (rpmdb_ts);
+    rpmte te;
+    while ((te = rpmtsiNext (tsi, (rpmElementTypes)0)) != NULL)
+      {
+        g_autofree char *nvra = g_strdup_printf ("%s-%s-%s.%s", rpmteN (te), rpmteV (te),
+                                                 rpmteR (te), rpmteA (te) ?: "(none)");
+        if (rpmteType (te) == TR_ADDED)
+          rpmdb_added.push_back (nvra);
+        else if (rpmteType (te) == TR_REMOVED)
+          rpmdb_removed.push_back (nvra);
+      }
+    rpmtsiFree (tsi);
+  }
+
+  /* Parsed by librpm here, rather than from the output of `rpmdb --exportdb` later */
+  glnx_autofd int added_headers_fd = -1;
+  if (have_rpmdb_snapshot && rpmdb_added.size () > 0)
+    {
+      g_autoptr (GError) local_error = NULL;
+      if (!export_rpmdb_headers (rpmdb_ts, rpmdb_added, &added_headers_fd, &local_error))
+        g_print ("Exporting the headers of the added packages: %s\n", local_error->message);
+    }
+
   task->end ("");
 
   /* And finally revert the _dbpath setting because libsolv relies on it as well
    * to find the rpmdb and RPM macros are global state. */
   set_rpm_macro_define ("_dbpath", "/" RPMOSTREE_RPMDB_LOCATION);
 
-  if (self->treefile_rs && self->treefile_rs->rpmdb_backend_is_target ())
+  if (rpmdb_for_target)
     {
-      g_print ("Regenerating rpmdb for target\n");
-      ROSCXX_TRY (
-          rewrite_rpmdb_for_target (tmprootfs_dfd, self->treefile_rs->should_normalize_rpmdb ()),
-          error);
+      gboolean updated = FALSE;
+      if (have_rpmdb_snapshot)
+        {
+          CXX_TRY_VAR (incremental,
+                       rpmostreecxx::rpmdb_update_for_target (tmprootfs_dfd, rpmdb_added,
+                                                              added_headers_fd, rpmdb_removed),
+                       error);
+          updated = incremental;
+        }
+      if (!updated)
+        {
+          g_print ("Regenerating rpmdb for target\n");
+          ROSCXX_TRY (rewrite_rpmdb_for_target (tmprootfs_dfd,
+                                                self->treefile_rs->should_normalize_rpmdb ()),
+                      error);
+        }
+
+      /* e.g. an incrementally updated base rpmdb in an older format */
+      ROSCXX_TRY (rpmdb_convert_for_target (tmprootfs_dfd, *self->treefile_rs), error);
     }
   else
     {
@@ -4038,6 +5107,8 @@ ensure_tmprootfs_dfd (RpmOstreeContext *self, GError **error)
 gboolean
 rpmostree_context_assemble (RpmOstreeContext *self, GCancellable *cancellable, GError **error)
 {
+  rpmostree_output_set_stage (RPMOSTREE_OUTPUT_STAGE_ASSEMBLE);
+
   if (!ensure_tmprootfs_dfd (self, error))
     return FALSE;
   int tmprootfs_dfd = self->tmprootfs_dfd; /* Alias to avoid bigger diff */
@@ -4299,6 +5370,9 @@ rpmostree_context_assemble (RpmOstreeContext *self, GCancellable *cancellable, G
   if (overlays->len > 0 || overrides_replace->len > 0)
     {
       CXX_TRY_VAR (fs_prep, rpmostreecxx::prepare_filesystem_script_prep (tmprootfs_dfd), error);
+      if (self->semodule_cachedir)
+        ROSCXX_TRY (semodule_cache_prepare (self->semodule_cachedir, self->semodule_cache_clear),
+                    error);
 
       auto passwd_entries = rpmostreecxx::new_passwd_entries ();
 
@@ -4495,6 +5569,206 @@ rpmostree_context_assemble_end (RpmOstreeContext *self, GCancellable *cancellabl
   return TRUE;
 }
 
+/* When the SELinux policy changed, every file of the tree has to be relabeled and
+ * checksummed again, which dominates the time to commit large trees if done in a single
+ * thread as libostree does.  So we commit each directory of /usr, where nearly all of the
+ * content is, in a pool of threads, and the rest as usual. */
+typedef struct
+{
+  OstreeRepo *repo;
+  int rootfs_dfd;
+  OstreeSePolicy *sepolicy;
+  OstreeRepoDevInoCache *devino_cache;
+  OstreeRepoCommitModifierFlags modflags;
+  GCancellable *cancellable;
+} ParallelCommitData;
+
+typedef struct
+{
+  ParallelCommitData *common;
+  char *name; /* under usr/ */
+  char *contents_checksum;
+  char *metadata_checksum;
+  GError *error;
+} ParallelCommitDir;
+
+static void
+parallel_commit_dir_free (ParallelCommitDir *dir)
+{
+  g_free (dir->name);
+  g_free (dir->contents_checksum);
+  g_free (dir->metadata_checksum);
+  g_clear_error (&dir->error);
+  g_free (dir);
+}
+
+/* libostree labels files by their path relative to the directory being committed, so we
+ * do it ourselves here from their full path; the other xattrs are kept as on disk. */
+static GVariant *
+label_usr_subdir_xattrs_cb (OstreeRepo *repo, const char *relpath, GFileInfo *file_info,
+                            gpointer user_data)
+{
+  auto dir = static_cast<ParallelCommitDir *> (user_data);
+  if (dir->error)
+    return NULL;
+
+  while (*relpath == '/')
+    relpath++;
+  g_autofree char *path = *relpath ? g_strconcat ("/usr/", dir->name, "/", relpath, NULL)
+                                   : g_strconcat ("/usr/", dir->name, NULL);
+
+  g_autoptr (GVariant) existing_xattrs = NULL;
+  if (!glnx_dfd_name_get_all_xattrs (dir->common->rootfs_dfd, path + 1, &existing_xattrs, NULL,
+                                     &dir->error))
+    return NULL;
+  g_autofree char *label = NULL;
+  if (!ostree_sepolicy_get_label (dir->common->sepolicy, path,
+                                  g_file_info_get_attribute_uint32 (file_info, "unix::mode"),
+                                  &label, NULL, &dir->error))
+    return NULL;
+
+  GVariantBuilder builder;
+  g_variant_builder_init (&builder, G_VARIANT_TYPE ("a(ayay)"));
+  GVariantIter viter;
+  g_variant_iter_init (&viter, existing_xattrs);
+  GVariant *key, *value;
+  while (g_variant_iter_loop (&viter, "(@ay@ay)", &key, &value))
+    {
+      if (!g_str_equal (g_variant_get_bytestring (key), "security.selinux"))
+        g_variant_builder_add (&builder, "(@ay@ay)", key, value);
+    }
+  if (label)
+    g_variant_builder_add (&builder, "(@ay@ay)", g_variant_new_bytestring ("security.selinux"),
+                           g_variant_new_bytestring (label));
+  return g_variant_ref_sink (g_variant_builder_end (&builder));
+}
+
+static void
+commit_usr_subdir_thread (gpointer data, gpointer user_data)
+{
+  auto dir = static_cast<ParallelCommitDir *> (data);
+  ParallelCommitData *common = dir->common;
+  g_autoptr (GError) local_error = NULL;
+
+  g_autoptr (OstreeRepoCommitModifier) modifier
+      = ostree_repo_commit_modifier_new (common->modflags, NULL, NULL, NULL);
+  ostree_repo_commit_modifier_set_xattr_callback (modifier, label_usr_subdir_xattrs_cb, NULL, dir);
+  if (common->devino_cache)
+    ostree_repo_commit_modifier_set_devino_cache (modifier, common->devino_cache);
+
+  g_autoptr (OstreeMutableTree) mtree = ostree_mutable_tree_new ();
+  g_autoptr (GFile) root = NULL;
+  const char *path = glnx_strjoina ("usr/", dir->name);
+  if (!ostree_repo_write_dfd_to_mtree (common->repo, common->rootfs_dfd, path, mtree, modifier,
+                                       common->cancellable, &local_error)
+      || !ostree_repo_write_mtree (common->repo, mtree, &root, common->cancellable, &local_error))
+    {
+      if (!dir->error)
+        dir->error = util::move_nullify (local_error);
+      return;
+    }
+
+  auto repo_root = OSTREE_REPO_FILE (root);
+  dir->contents_checksum = g_strdup (ostree_repo_file_tree_get_contents_checksum (repo_root));
+  dir->metadata_checksum = g_strdup (ostree_repo_file_tree_get_metadata_checksum (repo_root));
+}
+
+static OstreeRepoCommitFilterResult
+skip_parallel_dirs_filter (OstreeRepo *repo, const char *path, GFileInfo *file_info,
+                           gpointer user_data)
+{
+  auto skipped = static_cast<GHashTable *> (user_data);
+  if (g_hash_table_contains (skipped, path))
+    return OSTREE_REPO_COMMIT_FILTER_SKIP;
+  return OSTREE_REPO_COMMIT_FILTER_ALLOW;
+}
+
+/* Like ostree_repo_write_dfd_to_mtree() for the whole of @rootfs_dfd with @sepolicy, but
+ * committing the directories of /usr in parallel. */
+static gboolean
+write_rootfs_to_mtree_parallel (OstreeRepo *repo, int rootfs_dfd, OstreeMutableTree *mtree,
+                                OstreeRepoCommitModifierFlags modflags, OstreeSePolicy *sepolicy,
+                                OstreeRepoDevInoCache *devino_cache, GCancellable *cancellable,
+                                GError **error)
+{
+  ParallelCommitData common = { repo, rootfs_dfd, sepolicy, devino_cache, modflags, cancellable };
+  g_autoptr (GPtrArray) dirs
+      = g_ptr_array_new_with_free_func ((GDestroyNotify)parallel_commit_dir_free);
+  g_autoptr (GHashTable) skipped = g_hash_table_new_full (g_str_hash, g_str_equal, g_free, NULL);
+
+  g_auto (GLnxDirFdIterator) dfd_iter = {
+    FALSE,
+  };
+  if (!glnx_dirfd_iterator_init_at (rootfs_dfd, "usr", TRUE, &dfd_iter, error))
+    return FALSE;
+  while (TRUE)
+    {
+      struct dirent *dent = NULL;
+      if (!glnx_dirfd_iterator_next_dent_ensure_dtype (&dfd_iter, &dent, cancellable, error))
+        return FALSE;
+      if (dent == NULL)
+        break;
+      if (dent->d_type != DT_DIR)
+        continue;
+      auto dir = g_new0 (ParallelCommitDir, 1);
+      dir->common = &common;
+      dir->name = g_strdup (dent->d_name);
+      g_ptr_array_add (dirs, dir);
+      g_hash_table_add (skipped, g_strconcat ("/usr/", dent->d_name, NULL));
+    }
+
+  const guint n_threads = MIN (g_get_num_processors (), MAX (dirs->len, 1));
+  GThreadPool *pool = g_thread_pool_new (commit_usr_subdir_thread, NULL, n_threads, TRUE, error);
+  if (!pool)
+    return FALSE;
+  for (guint i = 0; i < dirs->len; i++)
+    {
+      if (!g_thread_pool_push (pool, dirs->pdata[i], error))
+        {
+          g_thread_pool_free (pool, TRUE, TRUE);
+          return FALSE;
+        }
+    }
+
+  /* And the rest of the tree in the meantime */
+  g_autoptr (GError) local_error = NULL;
+  g_autoptr (OstreeRepoCommitModifier) modifier
+      = ostree_repo_commit_modifier_new (modflags, skip_parallel_dirs_filter, skipped, NULL);
+  ostree_repo_commit_modifier_set_sepolicy (modifier, sepolicy);
+  if (devino_cache)
+    ostree_repo_commit_modifier_set_devino_cache (modifier, devino_cache);
+  gboolean rest_ok = ostree_repo_write_dfd_to_mtree (repo, rootfs_dfd, ".", mtree, modifier,
+                                                     cancellable, &local_error);
+  /* Wait for the threads */
+  g_thread_pool_free (pool, FALSE, TRUE);
+  if (!rest_ok)
+    {
+      g_propagate_error (error, util::move_nullify (local_error));
+      return FALSE;
+    }
+
+  g_autoptr (OstreeMutableTree) usr_mtree = NULL;
+  if (!ostree_mutable_tree_ensure_dir (mtree, "usr", &usr_mtree, error))
+    return FALSE;
+  for (guint i = 0; i < dirs->len; i++)
+    {
+      auto dir = static_cast<ParallelCommitDir *> (dirs->pdata[i]);
+      if (dir->error)
+        {
+          g_propagate_error (error, util::move_nullify (dir->error));
+          return glnx_prefix_error (error, "Committing /usr/%s", dir->name);
+        }
+      g_autoptr (OstreeMutableTree) subdir = NULL;
+      if (!ostree_mutable_tree_ensure_dir (usr_mtree, dir->name, &subdir, error))
+        return FALSE;
+      if (!ostree_mutable_tree_fill_empty_from_dirtree (subdir, repo, dir->contents_checksum,
+                                                        dir->metadata_checksum))
+        return glnx_throw (error, "Adding /usr/%s to tree", dir->name);
+    }
+
+  return TRUE;
+}
+
 gboolean
 rpmostree_context_commit (RpmOstreeContext *self, const char *parent,
                           RpmOstreeAssembleType assemble_type, char **out_commit,
@@ -4562,6 +5836,11 @@ rpmostree_context_commit (RpmOstreeContext *self, const char *parent,
         g_variant_builder_add (&metadata_builder, "{sv}", "rpmostree.modules",
                                g_variant_builder_end (modules_v));
 
+        /* embed weak deps we couldn't satisfy; surfaced by `status --recommendations` */
+        if (self->unsatisfied_weakdeps)
+          g_variant_builder_add (&metadata_builder, "{sv}", "rpmostree.unsatisfied-weakdeps",
+                                 self->unsatisfied_weakdeps);
+
         /* embed packages removed */
         /* we have to embed both the pkgname and the full nevra to make it easier to match
          * them up with origin directives. the full nevra is used for status -v */
@@ -4654,6 +5933,7 @@ rpmostree_context_commit (RpmOstreeContext *self, const char *parent,
     /* if we're SELinux aware, then reload the final policy from the tmprootfs in case it
      * was changed by a scriptlet; this covers the foobar/foobar-selinux path */
     g_autoptr (OstreeSePolicy) final_sepolicy = NULL;
+    gboolean relabel = FALSE;
     if (self->sepolicy)
       {
         if (!rpmostree_prepare_rootfs_get_sepolicy (self->tmprootfs_dfd, &final_sepolicy,
@@ -4670,6 +5950,8 @@ rpmostree_context_commit (RpmOstreeContext *self, const char *parent,
                    == 0)
           modflags = static_cast<OstreeRepoCommitModifierFlags> (
               static_cast<int> (modflags) | OSTREE_REPO_COMMIT_MODIFIER_FLAGS_DEVINO_CANONICAL);
+        else
+          relabel = TRUE;
       }
 
     commit_modifier = ostree_repo_commit_modifier_new (modflags, NULL, NULL, NULL);
@@ -4682,8 +5964,22 @@ rpmostree_context_commit (RpmOstreeContext *self, const char *parent,
     mtree = ostree_mutable_tree_new ();
 
     const guint64 start_time_ms = g_get_monotonic_time () / 1000;
-    if (!ostree_repo_write_dfd_to_mtree (self->ostreerepo, self->tmprootfs_dfd, ".", mtree,
-                                         commit_modifier, cancellable, error))
+    /* We only label files ourselves on the system repo; otherwise, their xattrs on disk
+     * aren't the ones to commit */
+    if (relabel && ostree_repo_get_mode (self->ostreerepo) == OSTREE_REPO_MODE_BARE)
+      {
+        task->set_sub_message ("relabeling");
+        if (!write_rootfs_to_mtree_parallel (self->ostreerepo, self->tmprootfs_dfd, mtree,
+                                             modflags, final_sepolicy, self->devino_cache,
+                                             cancellable, error))
+          return glnx_prefix_error (error, "Relabeling");
+        const guint64 relabel_ms = g_get_monotonic_time () / 1000 - start_time_ms;
+        rpmostree_output_message ("Relabeled tree for new SELinux policy in %" G_GUINT64_FORMAT
+                                  ".%" G_GUINT64_FORMAT "s",
+                                  relabel_ms / 1000, (relabel_ms % 1000) / 100);
+      }
+    else if (!ostree_repo_write_dfd_to_mtree (self->ostreerepo, self->tmprootfs_dfd, ".", mtree,
+                                              commit_modifier, cancellable, error))
       return FALSE;
 
     if (!ostree_repo_write_mtree (self->ostreerepo, mtree, &root, cancellable, error))
@@ -38,6 +38,7 @@ G_BEGIN_DECLS
 #define RPMOSTREE_CORE_CACHEDIR "/var/cache/rpm-ostree/"
 #define RPMOSTREE_DIR_CACHE_REPOMD "repomd"
 #define RPMOSTREE_DIR_CACHE_SOLV "solv"
+#define RPMOSTREE_DIR_CACHE_DEPSOLVE "depsolve"
 #define RPMOSTREE_DIR_LOCK "lock"
 
 // Extended attribute used at build time.
@@ -53,6 +54,8 @@ G_BEGIN_DECLS
 
 /* put it in cache dir so it gets destroyed naturally with a `cleanup -m` */
 #define RPMOSTREE_AUTOUPDATES_CACHE_FILE RPMOSTREE_CORE_CACHEDIR "cached-update.gv"
+/* pkgcache refs kept alive for a later cache-only operation; also dropped by `cleanup -m` */
+#define RPMOSTREE_PKGCACHE_PINS_FILE RPMOSTREE_CORE_CACHEDIR "pinned-pkgcache"
 
 #define RPMOSTREE_STATE_DIR "/var/lib/rpm-ostree/"
 #define RPMOSTREE_HISTORY_DIR RPMOSTREE_STATE_DIR "history"
@@ -99,6 +102,9 @@ DnfContext *rpmostree_context_get_dnf (RpmOstreeContext *self);
 
 GVariant *rpmostree_context_get_rpmmd_repo_commit_metadata (RpmOstreeContext *self);
 
+gboolean rpmostree_context_get_rpm_signatures (RpmOstreeContext *self, GVariant **out_keys,
+                                               GVariant **out_signatures, GError **error);
+
 void rpmostree_context_set_treefile (RpmOstreeContext *self, rpmostreecxx::Treefile &treefile);
 
 gboolean rpmostree_context_setup (RpmOstreeContext *self, const char *install_root,
@@ -118,6 +124,10 @@ void rpmostree_context_set_devino_cache (RpmOstreeContext *self,
                                          OstreeRepoDevInoCache *devino_cache);
 void rpmostree_context_disable_rofiles (RpmOstreeContext *self);
 void rpmostree_context_set_sepolicy (RpmOstreeContext *self, OstreeSePolicy *sepolicy);
+void rpmostree_context_set_semodule_cache (RpmOstreeContext *self, const char *cachedir,
+                                           gboolean clear);
+void rpmostree_context_set_require_gpgcheck (RpmOstreeContext *self, gboolean require_gpgcheck);
+void rpmostree_context_set_find_weakdeps (RpmOstreeContext *self, gboolean find_weakdeps);
 
 gboolean rpmostree_dnf_add_checksum_goal (GChecksum *checksum, HyGoal goal,
                                           OstreeRepo *pkgcache_repo, GError **error);
@@ -125,6 +135,9 @@ gboolean rpmostree_dnf_add_checksum_goal (GChecksum *checksum, HyGoal goal,
 gboolean rpmostree_context_get_state_sha512 (RpmOstreeContext *self, char **out_checksum,
                                              GError **error);
 
+gboolean rpmostree_context_get_packages_sha512 (RpmOstreeContext *self, char **out_checksum,
+                                                GError **error);
+
 gboolean rpmostree_pkgcache_find_pkg_header (OstreeRepo *pkgcache, const char *nevra,
                                              const char *expected_sha256, GVariant **out_header,
                                              GCancellable *cancellable, GError **error);
@@ -141,6 +154,8 @@ GPtrArray *rpmostree_context_get_packages (RpmOstreeContext *self);
 
 GPtrArray *rpmostree_context_get_packages_to_import (RpmOstreeContext *self);
 
+GVariant *rpmostree_context_get_unsatisfied_weakdeps (RpmOstreeContext *self);
+
 gboolean rpmostree_context_set_lockfile (RpmOstreeContext *self, char **lockfiles, gboolean strict,
                                          GError **error);
 
@@ -152,6 +167,17 @@ gboolean rpmostree_find_and_download_packages (const char *const *packages, cons
 gboolean rpmostree_download_packages (GPtrArray *packages, GCancellable *cancellable,
                                       GError **error);
 
+void rpmostree_set_download_bandwidth_limit (guint64 bandwidth_limit_kbps);
+
+void rpmostree_set_import_jobs (guint n_jobs);
+
+void rpmostree_set_deltarpm (gboolean enabled);
+
+void rpmostree_set_countme (gboolean enabled);
+
+void rpmostree_set_download_config (guint parallel_downloads, guint64 bandwidth_limit_kbps,
+                                    gint retries);
+
 gboolean rpmostree_context_download (RpmOstreeContext *self, GCancellable *cancellable,
                                      GError **error);
 
@@ -166,6 +192,10 @@ gboolean rpmostree_context_import (RpmOstreeContext *self, GCancellable *cancell
 gboolean rpmostree_context_force_relabel (RpmOstreeContext *self, GCancellable *cancellable,
                                           GError **error);
 
+gboolean rpmostree_context_import_local_pkgs (RpmOstreeContext *self, GVariant *commit,
+                                              guint *out_n_pkgs, GCancellable *cancellable,
+                                              GError **error);
+
 typedef enum
 {
   RPMOSTREE_ASSEMBLE_TYPE_SERVER_BASE,
@@ -231,14 +231,19 @@ rpmostree_importer_new_take_fd (int *fd, OstreeRepo *repo, DnfPackage *pkg,
   g_auto (rpmfi) fi = NULL;
   gsize cpio_offset = 0;
 
-  g_autoptr (archive) ar = rpmostree_unpack_rpm2cpio (*fd, error);
-  if (ar == NULL)
-    return NULL;
-
   if (!rpmostree_importer_read_metainfo (*fd, &hdr, &cpio_offset, &fi, error))
     return (RpmOstreeImporter *)glnx_prefix_error_null (error, "Reading metainfo");
   g_assert (hdr != NULL);
 
+  /* Fast path for zstd, which is what current distributions use */
+  g_autoptr (archive) ar = NULL;
+  if (g_strcmp0 (headerGetString (hdr, RPMTAG_PAYLOADCOMPRESSOR), "zstd") == 0)
+    ar = rpmostree_unpack_rpm_zstd_payload (*fd, cpio_offset, error);
+  else
+    ar = rpmostree_unpack_rpm2cpio (*fd, error);
+  if (ar == NULL)
+    return NULL;
+
   const char *pkg_name = headerGetString (hdr, RPMTAG_NAME);
   g_assert (pkg_name != NULL);
   g_autofree char *ostree_branch = rpmostree_get_cache_branch_header (hdr);
@@ -301,27 +306,20 @@ static gboolean
 get_lead_sig_header_as_bytes (RpmOstreeImporter *self, GBytes **out_metadata,
                               GCancellable *cancellable, GError **error)
 {
-  /* Inline a pread() based reader here to avoid affecting the file
-   * offset since both librpm and libarchive have references.
+  /* Map the file rather than reading the headers into a buffer; for packages
+   * with many files they're large, and this way they're backed by the page
+   * cache.  This also doesn't affect the file offset since both librpm and
+   * libarchive have references.
    */
-  g_autofree char *buf = (char *)g_malloc (self->cpio_offset);
-  char *bufp = buf;
-  size_t bytes_remaining = self->cpio_offset;
-  while (bytes_remaining > 0)
-    {
-      ssize_t bytes_read = TEMP_FAILURE_RETRY (pread (self->fd, bufp, bytes_remaining, bufp - buf));
-      if (bytes_read < 0)
-        return glnx_throw_errno_prefix (error, "pread");
-      if (bytes_read == 0)
-        break;
-      bufp += bytes_read;
-      bytes_remaining -= bytes_read;
-    }
-  if (bytes_remaining > 0)
+  g_autoptr (GMappedFile) mfile = g_mapped_file_new_from_fd (self->fd, FALSE, error);
+  if (!mfile)
+    return glnx_prefix_error (error, "Mapping package");
+  g_autoptr (GBytes) contents = g_mapped_file_get_bytes (mfile);
+  if (g_bytes_get_size (contents) < (gsize)self->cpio_offset)
     return glnx_throw (error, "Failed to read %" G_GSIZE_FORMAT " bytes of metadata",
-                       bytes_remaining);
+                       (gsize)self->cpio_offset);
 
-  *out_metadata = g_bytes_new_take (util::move_nullify (buf), self->cpio_offset);
+  *out_metadata = g_bytes_new_from_bytes (contents, 0, self->cpio_offset);
   return TRUE;
 }
 
@@ -592,6 +590,9 @@ import_rpm_to_repo (RpmOstreeImporter *self, char **out_csum, char **out_metadat
   if (!ostree_repo_import_archive_to_mtree (repo, &opts, self->archive, mtree, modifier,
                                             cancellable, error))
     return glnx_prefix_error (error, "Importing archive");
+  /* The payload is fully consumed; release the decompressor state now rather
+   * than holding on to it while writing out the tree and commit. */
+  (void)archive_read_free (util::move_nullify (self->archive));
 
   /* check if any of the cbs set an error */
   if (cb_error != NULL)
@@ -700,12 +701,30 @@ import_in_thread (GTask *task, gpointer source, gpointer task_data, GCancellable
     g_task_return_pointer (task, util::move_nullify (rev), g_free);
 }
 
+static void
+import_in_pool (gpointer data, gpointer user_data)
+{
+  g_autoptr (GTask) task = static_cast<GTask *> (data);
+  import_in_thread (task, g_task_get_source_object (task), NULL, g_task_get_cancellable (task));
+}
+
+/* Create a pool of @n_workers threads for rpmostree_importer_run_async().  The
+ * default GTask pool only runs a few tasks concurrently.
+ */
+GThreadPool *
+rpmostree_importer_new_thread_pool (guint n_workers, GError **error)
+{
+  return g_thread_pool_new (import_in_pool, NULL, n_workers, FALSE, error);
+}
+
 void
-rpmostree_importer_run_async (RpmOstreeImporter *self, GCancellable *cancellable,
-                              GAsyncReadyCallback callback, gpointer user_data)
+rpmostree_importer_run_async (RpmOstreeImporter *self, GThreadPool *pool,
+                              GCancellable *cancellable, GAsyncReadyCallback callback,
+                              gpointer user_data)
 {
   g_autoptr (GTask) task = g_task_new (self, cancellable, callback, user_data);
-  g_task_run_in_thread (task, import_in_thread);
+  /* Pushing to a non-exclusive pool can't fail */
+  g_thread_pool_push (pool, g_steal_pointer (&task), NULL);
 }
 
 char *
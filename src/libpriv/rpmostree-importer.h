@@ -50,8 +50,11 @@ gboolean rpmostree_importer_run (RpmOstreeImporter *unpacker, char **out_commit,
                                  char **out_metadata_sha256, GCancellable *cancellable,
                                  GError **error);
 
-void rpmostree_importer_run_async (RpmOstreeImporter *unpacker, GCancellable *cancellable,
-                                   GAsyncReadyCallback callback, gpointer user_data);
+GThreadPool *rpmostree_importer_new_thread_pool (guint n_workers, GError **error);
+
+void rpmostree_importer_run_async (RpmOstreeImporter *unpacker, GThreadPool *pool,
+                                   GCancellable *cancellable, GAsyncReadyCallback callback,
+                                   gpointer user_data);
 
 char *rpmostree_importer_run_async_finish (RpmOstreeImporter *self, GAsyncResult *res,
                                            GError **error);
@@ -432,30 +432,17 @@ struct Unlinker
   ~Unlinker () { (void)unlinkat (rootfs_dfd, path, 0); }
 };
 
-gboolean
-rpmostree_run_dracut (int rootfs_dfd, const char *const *argv, const char *kver,
-                      const char *rebuild_from_initramfs, gboolean use_root_etc,
-                      GLnxTmpDir *dracut_host_tmpdir, GLnxTmpfile *out_initramfs_tmpf,
-                      GCancellable *cancellable, GError **error)
+/* Run @wrapper, a shell script writing an initramfs to fd 3, with @argv in a
+ * sandbox of @rootfs_dfd.  This is shared by dracut and the other initramfs
+ * generators.  If @reproducible, the environment is normalized and the network
+ * isolated, so that the same inputs give the same initramfs.
+ */
+static gboolean
+run_initramfs_wrapper (int rootfs_dfd, const char *wrapper, const char *const *argv,
+                       gboolean use_root_etc, gboolean reproducible, GLnxTmpDir *host_tmpdir,
+                       GLnxTmpfile *out_initramfs_tmpf, GCancellable *cancellable, GError **error)
 {
-  auto destdir = rpmostreecxx::cliwrap_destdir ();
-  /* Shell wrapper around dracut to write to the O_TMPFILE fd;
-   * at some point in the future we should add --fd X instead of -f
-   * to dracut.
-   */
-  static const char rpmostree_dracut_wrapper_path[] = "usr/bin/rpmostree-dracut-wrapper";
-  /* This also hardcodes a few arguments */
-  g_autofree char *rpmostree_dracut_wrapper
-      = g_strdup_printf ("#!/usr/bin/bash\n"
-                         "set -euo pipefail\n"
-                         "export PATH=%s:${PATH}\n"
-                         "extra_argv=; if (dracut --help; true) | grep -q -e --reproducible; then "
-                         "extra_argv=\"--reproducible\"; fi\n"
-                         "mkdir -p /tmp/dracut && dracut $extra_argv -v --add ostree "
-                         "--tmpdir=/tmp/dracut -f /tmp/initramfs.img \"$@\"\n"
-                         "cat /tmp/initramfs.img >/proc/self/fd/3\n",
-                         destdir.c_str ());
-  g_autoptr (GPtrArray) rebuild_argv = NULL;
+  static const char wrapper_path[] = "usr/bin/rpmostree-initramfs-wrapper";
   g_auto (GLnxTmpfile) tmpf = {
     0,
   };
@@ -468,38 +455,18 @@ rpmostree_run_dracut (int rootfs_dfd, const char *const *argv, const char *kver,
 
   CXX_TRY_VAR (have_passwd, rpmostreecxx::prepare_rpm_layering (rootfs_dfd, ""), error);
 
-  /* Note rebuild_from_initramfs now is only used as a fallback in the client-side regen
-   * path when we can't fetch the canonical initramfs args to use. */
-
-  if (rebuild_from_initramfs)
-    {
-      rebuild_argv = g_ptr_array_new ();
-      g_ptr_array_add (rebuild_argv, (char *)"--rebuild");
-      g_ptr_array_add (rebuild_argv, (char *)rebuild_from_initramfs);
-
-      /* In this case, any args specified in argv are *additional*
-       * to the rebuild from the base.
-       */
-      for (char **iter = (char **)argv; iter && *iter; iter++)
-        g_ptr_array_add (rebuild_argv, *iter);
-      g_ptr_array_add (rebuild_argv, NULL);
-      argv = (const char *const *)rebuild_argv->pdata;
-    }
-
   /* First tempfile is just our shell script */
   if (!glnx_open_tmpfile_linkable_at (rootfs_dfd, "usr/bin", O_RDWR | O_CLOEXEC, &tmpf, error))
     return FALSE;
-  if (glnx_loop_write (tmpf.fd, rpmostree_dracut_wrapper, strlen (rpmostree_dracut_wrapper)) < 0
-      || fchmod (tmpf.fd, 0755) < 0)
+  if (glnx_loop_write (tmpf.fd, wrapper, strlen (wrapper)) < 0 || fchmod (tmpf.fd, 0755) < 0)
     return glnx_throw_errno_prefix (error, "writing");
 
-  if (!glnx_link_tmpfile_at (&tmpf, GLNX_LINK_TMPFILE_NOREPLACE, rootfs_dfd,
-                             rpmostree_dracut_wrapper_path, error))
+  if (!glnx_link_tmpfile_at (&tmpf, GLNX_LINK_TMPFILE_NOREPLACE, rootfs_dfd, wrapper_path, error))
     return FALSE;
   /* Close the fd now, otherwise an exec will fail */
   glnx_tmpfile_clear (&tmpf);
 
-  auto unlinker = Unlinker{ .rootfs_dfd = rootfs_dfd, .path = rpmostree_dracut_wrapper_path };
+  auto unlinker = Unlinker{ .rootfs_dfd = rootfs_dfd, .path = wrapper_path };
 
   /* Second tempfile is the initramfs contents.  Note we generate the tmpfile
    * in . since in the current rpm-ostree design the temporary rootfs may not have tmp/
@@ -520,20 +487,17 @@ rpmostree_run_dracut (int rootfs_dfd, const char *const *argv, const char *kver,
       bwrap->bind_read ("usr", "/usr");
     }
 
-  if (dracut_host_tmpdir)
-    bwrap->bind_readwrite (dracut_host_tmpdir->path, "/tmp/dracut");
+  if (host_tmpdir)
+    bwrap->bind_readwrite (host_tmpdir->path, "/tmp/dracut");
+
+  if (reproducible)
+    CXX_TRY (bwrap->set_reproducible (rpmostreecxx::initramfs_source_date_epoch ()), error);
 
   /* Set up argv and run */
-  bwrap->append_child_arg ((const char *)glnx_basename (rpmostree_dracut_wrapper_path));
+  bwrap->append_child_arg ((const char *)glnx_basename (wrapper_path));
   for (char **iter = (char **)argv; iter && *iter; iter++)
     bwrap->append_child_arg (*iter);
 
-  if (kver)
-    {
-      bwrap->append_child_arg ("--kver");
-      bwrap->append_child_arg (kver);
-    }
-
   // Pass the tempfile to the child as fd 3
   glnx_autofd int tmpf_child = fcntl (tmpf.fd, F_DUPFD_CLOEXEC, 3);
   if (tmpf_child < 0)
@@ -556,9 +520,6 @@ rpmostree_run_dracut (int rootfs_dfd, const char *const *argv, const char *kver,
   if (glnx_loop_write (tmpf.fd, random_cpio_data.data (), random_cpio_data.length ()) < 0)
     return glnx_throw_errno_prefix (error, "write");
 
-  if (rebuild_from_initramfs)
-    (void)unlinkat (rootfs_dfd, rebuild_from_initramfs, 0);
-
   if (have_passwd)
     ROSCXX_TRY (complete_rpm_layering (rootfs_dfd), error);
 
@@ -568,3 +529,79 @@ rpmostree_run_dracut (int rootfs_dfd, const char *const *argv, const char *kver,
   tmpf.initialized = FALSE; /* Transfer */
   return TRUE;
 }
+
+gboolean
+rpmostree_run_dracut (int rootfs_dfd, const char *const *argv, const char *kver,
+                      const char *rebuild_from_initramfs, gboolean use_root_etc,
+                      gboolean reproducible, GLnxTmpDir *dracut_host_tmpdir,
+                      GLnxTmpfile *out_initramfs_tmpf, GCancellable *cancellable, GError **error)
+{
+  auto destdir = rpmostreecxx::cliwrap_destdir ();
+  /* Shell wrapper around dracut to write to the O_TMPFILE fd;
+   * at some point in the future we should add --fd X instead of -f
+   * to dracut.
+   */
+  /* This also hardcodes a few arguments */
+  g_autofree char *rpmostree_dracut_wrapper
+      = g_strdup_printf ("#!/usr/bin/bash\n"
+                         "set -euo pipefail\n"
+                         "export PATH=%s:${PATH}\n"
+                         "extra_argv=; if (dracut --help; true) | grep -q -e --reproducible; then "
+                         "extra_argv=\"--reproducible\"; fi\n"
+                         "mkdir -p /tmp/dracut && dracut $extra_argv -v --add ostree "
+                         "--tmpdir=/tmp/dracut -f /tmp/initramfs.img \"$@\"\n"
+                         "cat /tmp/initramfs.img >/proc/self/fd/3\n",
+                         destdir.c_str ());
+  g_autoptr (GPtrArray) dracut_argv = g_ptr_array_new ();
+
+  /* Note rebuild_from_initramfs now is only used as a fallback in the client-side regen
+   * path when we can't fetch the canonical initramfs args to use. */
+
+  if (rebuild_from_initramfs)
+    {
+      g_ptr_array_add (dracut_argv, (char *)"--rebuild");
+      g_ptr_array_add (dracut_argv, (char *)rebuild_from_initramfs);
+      /* In this case, any args specified in argv are *additional*
+       * to the rebuild from the base.
+       */
+    }
+  for (char **iter = (char **)argv; iter && *iter; iter++)
+    g_ptr_array_add (dracut_argv, *iter);
+  if (kver)
+    {
+      g_ptr_array_add (dracut_argv, (char *)"--kver");
+      g_ptr_array_add (dracut_argv, (char *)kver);
+    }
+  g_ptr_array_add (dracut_argv, NULL);
+
+  if (!run_initramfs_wrapper (rootfs_dfd, rpmostree_dracut_wrapper,
+                              (const char *const *)dracut_argv->pdata, use_root_etc, reproducible,
+                              dracut_host_tmpdir, out_initramfs_tmpf, cancellable, error))
+    return FALSE;
+
+  if (rebuild_from_initramfs)
+    (void)unlinkat (rootfs_dfd, rebuild_from_initramfs, 0);
+
+  return TRUE;
+}
+
+gboolean
+rpmostree_run_initramfs_generator (int rootfs_dfd, const char *command, const char *const *argv,
+                                   const char *kver, GLnxTmpfile *out_initramfs_tmpf,
+                                   GCancellable *cancellable, GError **error)
+{
+  auto destdir = rpmostreecxx::cliwrap_destdir ();
+  g_autofree char *quoted_kver = g_shell_quote (kver);
+  /* The command writes the initramfs for $KVER to $INITRAMFS, and is given the
+   * initramfs arguments. */
+  g_autofree char *wrapper = g_strdup_printf ("#!/usr/bin/bash\n"
+                                              "set -euo pipefail\n"
+                                              "export PATH=%s:${PATH}\n"
+                                              "export KVER=%s INITRAMFS=/tmp/initramfs.img\n"
+                                              "%s \"$@\"\n"
+                                              "cat /tmp/initramfs.img >/proc/self/fd/3\n",
+                                              destdir.c_str (), quoted_kver, command);
+  /* Like dracut when regenerating on the client, it uses the real root's /etc */
+  return run_initramfs_wrapper (rootfs_dfd, wrapper, argv, TRUE, TRUE, NULL, out_initramfs_tmpf,
+                                cancellable, error);
+}
@@ -43,7 +43,13 @@ gboolean rpmostree_finalize_kernel (int rootfs_dfd, const char *bootdir, const c
 
 gboolean rpmostree_run_dracut (int rootfs_dfd, const char *const *argv, const char *kver,
                                const char *rebuild_from_initramfs, gboolean use_root_etc,
-                               GLnxTmpDir *dracut_host_tmpdir, GLnxTmpfile *out_initramfs_tmpf,
-                               GCancellable *cancellable, GError **error);
+                               gboolean reproducible, GLnxTmpDir *dracut_host_tmpdir,
+                               GLnxTmpfile *out_initramfs_tmpf, GCancellable *cancellable,
+                               GError **error);
+
+gboolean rpmostree_run_initramfs_generator (int rootfs_dfd, const char *command,
+                                            const char *const *argv, const char *kver,
+                                            GLnxTmpfile *out_initramfs_tmpf,
+                                            GCancellable *cancellable, GError **error);
 
 G_END_DECLS
\ No newline at end of file
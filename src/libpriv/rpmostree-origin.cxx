@@ -207,6 +207,54 @@ rpmostree_origin_get_initramfs_args (RpmOstreeOrigin *origin)
   return (*origin->treefile)->get_initramfs_args ();
 }
 
+/* Mutability: getter */
+rust::String
+rpmostree_origin_get_initramfs_dracut_conf (RpmOstreeOrigin *origin)
+{
+  return (*origin->treefile)->get_initramfs_dracut_conf ();
+}
+
+/* Mutability: getter */
+rust::String
+rpmostree_origin_get_initramfs_generator (RpmOstreeOrigin *origin)
+{
+  return (*origin->treefile)->get_initramfs_generator ();
+}
+
+/* Mutability: getter */
+bool
+rpmostree_origin_get_fips (RpmOstreeOrigin *origin)
+{
+  return (*origin->treefile)->get_fips ();
+}
+
+/* Mutability: getter */
+rust::Vec<rust::String>
+rpmostree_origin_get_kargs_append (RpmOstreeOrigin *origin)
+{
+  return (*origin->treefile)->get_kargs_append ();
+}
+
+/* Mutability: getter */
+rust::Vec<rust::String>
+rpmostree_origin_get_kargs_delete (RpmOstreeOrigin *origin)
+{
+  return (*origin->treefile)->get_kargs_delete ();
+}
+
+/* Mutability: getter */
+gboolean
+rpmostree_origin_get_kargs_layers (RpmOstreeOrigin *origin, OstreeRepo *repo, const char *rev,
+                                   const char *kargs, GVariant **out_layers, GError **error)
+{
+  CXX_TRY_VAR (layers, (*origin->treefile)->kargs_layers (*repo, rev, kargs), error);
+  g_autoptr (GVariantBuilder) builder = g_variant_builder_new (G_VARIANT_TYPE ("a(ss)"));
+  for (auto &layer : layers)
+    g_variant_builder_add (builder, "(ss)", layer.arg.c_str (), layer.layer.c_str ());
+  *out_layers = g_variant_ref_sink (g_variant_builder_end (builder));
+  return TRUE;
+}
+
 /* Mutability: getter */
 rust::String
 rpmostree_origin_get_unconfigured_state (RpmOstreeOrigin *origin)
@@ -266,6 +314,40 @@ rpmostree_origin_set_regenerate_initramfs (RpmOstreeOrigin *origin, gboolean reg
   (*origin->treefile)->set_initramfs_regenerate (regenerate, args);
 }
 
+/* Mutability: setter */
+gboolean
+rpmostree_origin_update_initramfs_dracut_config (
+    RpmOstreeOrigin *origin, rust::Vec<rust::String> add_modules,
+    rust::Vec<rust::String> omit_modules, rust::Vec<rust::String> add_drivers,
+    rust::Vec<rust::String> remove_modules, rust::Vec<rust::String> remove_drivers,
+    gboolean *out_changed, GError **error)
+{
+  CXX_TRY_VAR (changed,
+               (*origin->treefile)
+                   ->initramfs_dracut_config_update (add_modules, omit_modules, add_drivers,
+                                                     remove_modules, remove_drivers),
+               error);
+  *out_changed = changed;
+  return TRUE;
+}
+
+/* Mutability: setter */
+gboolean
+rpmostree_origin_set_initramfs_generator (RpmOstreeOrigin *origin, const char *name,
+                                          gboolean *out_changed, GError **error)
+{
+  CXX_TRY_VAR (changed, (*origin->treefile)->set_initramfs_generator (name), error);
+  *out_changed = changed;
+  return TRUE;
+}
+
+/* Mutability: setter */
+void
+rpmostree_origin_set_fips (RpmOstreeOrigin *origin, gboolean enabled)
+{
+  (*origin->treefile)->set_fips (enabled);
+}
+
 /* Mutability: setter */
 void
 rpmostree_origin_set_override_commit (RpmOstreeOrigin *origin, const char *checksum)
@@ -273,6 +355,27 @@ rpmostree_origin_set_override_commit (RpmOstreeOrigin *origin, const char *check
   (*origin->treefile)->set_override_commit (checksum ?: "");
 }
 
+/* Mutability: setter */
+void
+rpmostree_origin_track_kargs (RpmOstreeOrigin *origin, const char *existing, const char *kargs)
+{
+  (*origin->treefile)->kargs_track (existing, kargs);
+}
+
+/* Mutability: setter */
+gboolean
+rpmostree_origin_apply_kargs_profiles (RpmOstreeOrigin *origin, int rootfs_dfd, const char *kargs,
+                                       rust::Vec<rust::String> enable,
+                                       rust::Vec<rust::String> disable, char **out_kargs,
+                                       GError **error)
+{
+  CXX_TRY_VAR (new_kargs,
+               (*origin->treefile)->kargs_profiles_apply (rootfs_dfd, kargs, enable, disable),
+               error);
+  *out_kargs = g_strdup (new_kargs.c_str ());
+  return TRUE;
+}
+
 /* Mutability: getter */
 bool
 rpmostree_origin_get_cliwrap (RpmOstreeOrigin *origin)
@@ -346,6 +449,14 @@ rpmostree_origin_add_local_fileoverride_packages (RpmOstreeOrigin *origin,
   return TRUE;
 }
 
+/* Mutability: setter */
+void
+rpmostree_origin_set_local_package_verified (RpmOstreeOrigin *origin, const char *nevra,
+                                             gboolean verified)
+{
+  (*origin->treefile)->set_local_package_verified (nevra, verified);
+}
+
 /* Mutability: setter */
 gboolean
 rpmostree_origin_remove_packages (RpmOstreeOrigin *origin, rust::Vec<rust::String> packages,
@@ -356,13 +467,24 @@ rpmostree_origin_remove_packages (RpmOstreeOrigin *origin, rust::Vec<rust::Strin
   return TRUE;
 }
 
+/* Mutability: setter */
+gboolean
+rpmostree_origin_set_packages_transient (RpmOstreeOrigin *origin, rust::Vec<rust::String> packages,
+                                         guint boots, gboolean *out_changed, GError **error)
+{
+  CXX_TRY_VAR (changed, (*origin->treefile)->set_packages_transient (packages, boots), error);
+  set_changed (out_changed, changed);
+  return TRUE;
+}
+
 /* Mutability: setter */
 gboolean
 rpmostree_origin_add_modules (RpmOstreeOrigin *origin, rust::Vec<rust::String> modules,
-                              gboolean enable_only)
+                              gboolean enable_only, gboolean *out_changed, GError **error)
 {
-  auto changed = (*origin->treefile)->add_modules (modules, enable_only);
-  return changed;
+  CXX_TRY_VAR (changed, (*origin->treefile)->add_modules (modules, enable_only), error);
+  set_changed (out_changed, changed);
+  return TRUE;
 }
 
 /* Mutability: setter */
@@ -374,6 +496,36 @@ rpmostree_origin_remove_modules (RpmOstreeOrigin *origin, rust::Vec<rust::String
   return changed;
 }
 
+/* Mutability: setter */
+gboolean
+rpmostree_origin_disable_modules (RpmOstreeOrigin *origin, rust::Vec<rust::String> modules,
+                                  gboolean *out_changed, GError **error)
+{
+  CXX_TRY_VAR (changed, (*origin->treefile)->disable_modules (modules), error);
+  set_changed (out_changed, changed);
+  return TRUE;
+}
+
+/* Mutability: setter */
+gboolean
+rpmostree_origin_reset_modules (RpmOstreeOrigin *origin, rust::Vec<rust::String> names,
+                                gboolean *out_changed, GError **error)
+{
+  CXX_TRY_VAR (changed, (*origin->treefile)->reset_modules (names), error);
+  set_changed (out_changed, changed);
+  return TRUE;
+}
+
+/* Mutability: setter */
+gboolean
+rpmostree_origin_switch_module_streams (RpmOstreeOrigin *origin, rust::Vec<rust::String> modules,
+                                        gboolean *out_changed, GError **error)
+{
+  CXX_TRY_VAR (changed, (*origin->treefile)->switch_module_streams (modules), error);
+  set_changed (out_changed, changed);
+  return TRUE;
+}
+
 /* Mutability: setter */
 gboolean
 rpmostree_origin_remove_all_packages (RpmOstreeOrigin *origin)
@@ -440,3 +592,16 @@ rpmostree_origin_merge_treefile (RpmOstreeOrigin *origin, const char *treefile,
   set_changed (out_changed, changed);
   return TRUE;
 }
+
+/* Mutability: setter; @out_kargs is set to the new kernel arguments if they changed,
+ * and to %NULL otherwise */
+gboolean
+rpmostree_origin_apply_state (RpmOstreeOrigin *origin, const char *state, const char *kargs,
+                              gboolean allow_protected, gboolean *out_changed, char ***out_kargs,
+                              GError **error)
+{
+  CXX_TRY_VAR (applied, (*origin->treefile)->apply_state (state, kargs, allow_protected), error);
+  set_changed (out_changed, applied.changed);
+  *out_kargs = applied.kargs_changed ? rpmostree_cxx_string_vec_to_strv (applied.kargs) : NULL;
+  return TRUE;
+}
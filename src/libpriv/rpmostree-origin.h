@@ -69,6 +69,20 @@ bool rpmostree_origin_get_regenerate_initramfs (RpmOstreeOrigin *origin);
 
 rust::Vec<rust::String> rpmostree_origin_get_initramfs_args (RpmOstreeOrigin *origin);
 
+rust::String rpmostree_origin_get_initramfs_dracut_conf (RpmOstreeOrigin *origin);
+
+rust::String rpmostree_origin_get_initramfs_generator (RpmOstreeOrigin *origin);
+
+bool rpmostree_origin_get_fips (RpmOstreeOrigin *origin);
+
+rust::Vec<rust::String> rpmostree_origin_get_kargs_append (RpmOstreeOrigin *origin);
+
+rust::Vec<rust::String> rpmostree_origin_get_kargs_delete (RpmOstreeOrigin *origin);
+
+gboolean rpmostree_origin_get_kargs_layers (RpmOstreeOrigin *origin, OstreeRepo *repo,
+                                            const char *rev, const char *kargs,
+                                            GVariant **out_layers, GError **error);
+
 rust::String rpmostree_origin_get_unconfigured_state (RpmOstreeOrigin *origin);
 
 bool rpmostree_origin_may_require_local_assembly (RpmOstreeOrigin *origin);
@@ -88,8 +102,27 @@ bool rpmostree_origin_initramfs_etc_files_untrack_all (RpmOstreeOrigin *origin);
 void rpmostree_origin_set_regenerate_initramfs (RpmOstreeOrigin *origin, gboolean regenerate,
                                                 rust::Vec<rust::String> args);
 
+gboolean rpmostree_origin_update_initramfs_dracut_config (
+    RpmOstreeOrigin *origin, rust::Vec<rust::String> add_modules,
+    rust::Vec<rust::String> omit_modules, rust::Vec<rust::String> add_drivers,
+    rust::Vec<rust::String> remove_modules, rust::Vec<rust::String> remove_drivers,
+    gboolean *out_changed, GError **error);
+
+gboolean rpmostree_origin_set_initramfs_generator (RpmOstreeOrigin *origin, const char *name,
+                                                   gboolean *out_changed, GError **error);
+
+void rpmostree_origin_set_fips (RpmOstreeOrigin *origin, gboolean enabled);
+
 void rpmostree_origin_set_override_commit (RpmOstreeOrigin *origin, const char *checksum);
 
+void rpmostree_origin_track_kargs (RpmOstreeOrigin *origin, const char *existing,
+                                   const char *kargs);
+
+gboolean rpmostree_origin_apply_kargs_profiles (RpmOstreeOrigin *origin, int rootfs_dfd,
+                                                const char *kargs, rust::Vec<rust::String> enable,
+                                                rust::Vec<rust::String> disable, char **out_kargs,
+                                                GError **error);
+
 bool rpmostree_origin_get_cliwrap (RpmOstreeOrigin *origin);
 void rpmostree_origin_set_cliwrap (RpmOstreeOrigin *origin, bool cliwrap);
 
@@ -112,17 +145,35 @@ gboolean rpmostree_origin_add_local_fileoverride_packages (RpmOstreeOrigin *orig
                                                            gboolean allow_existing,
                                                            gboolean *out_changed, GError **error);
 
+void rpmostree_origin_set_local_package_verified (RpmOstreeOrigin *origin, const char *nevra,
+                                                  gboolean verified);
+
 gboolean rpmostree_origin_remove_packages (RpmOstreeOrigin *origin,
                                            rust::Vec<rust::String> packages, gboolean allow_noent,
                                            gboolean *out_changed, GError **error);
+gboolean rpmostree_origin_set_packages_transient (RpmOstreeOrigin *origin,
+                                                  rust::Vec<rust::String> packages, guint boots,
+                                                  gboolean *out_changed, GError **error);
 gboolean rpmostree_origin_remove_all_packages (RpmOstreeOrigin *origin);
 
 gboolean rpmostree_origin_add_modules (RpmOstreeOrigin *origin, rust::Vec<rust::String> modules,
-                                       gboolean enable_only);
+                                       gboolean enable_only, gboolean *out_changed,
+                                       GError **error);
 
 gboolean rpmostree_origin_remove_modules (RpmOstreeOrigin *origin, rust::Vec<rust::String> modules,
                                           gboolean enable_only);
 
+gboolean rpmostree_origin_disable_modules (RpmOstreeOrigin *origin,
+                                           rust::Vec<rust::String> modules,
+                                           gboolean *out_changed, GError **error);
+
+gboolean rpmostree_origin_reset_modules (RpmOstreeOrigin *origin, rust::Vec<rust::String> names,
+                                         gboolean *out_changed, GError **error);
+
+gboolean rpmostree_origin_switch_module_streams (RpmOstreeOrigin *origin,
+                                                 rust::Vec<rust::String> modules,
+                                                 gboolean *out_changed, GError **error);
+
 gboolean rpmostree_origin_add_override_remove (RpmOstreeOrigin *origin,
                                                rust::Vec<rust::String> packages, GError **error);
 gboolean rpmostree_origin_add_override_replace_local (RpmOstreeOrigin *origin,
@@ -140,3 +191,7 @@ gboolean rpmostree_origin_remove_all_overrides (RpmOstreeOrigin *origin);
 
 gboolean rpmostree_origin_merge_treefile (RpmOstreeOrigin *origin, const char *treefile,
                                           gboolean *out_changed, GError **error);
+
+gboolean rpmostree_origin_apply_state (RpmOstreeOrigin *origin, const char *state,
+                                       const char *kargs, gboolean allow_protected,
+                                       gboolean *out_changed, char ***out_kargs, GError **error);
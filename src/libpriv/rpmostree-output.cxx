@@ -82,6 +82,33 @@ rpmostree_output_set_callback (void (*cb) (RpmOstreeOutputType, void *, void *),
   active_cb_opaque = opaque;
 }
 
+static RpmOstreeOutputStage active_stage = RPMOSTREE_OUTPUT_STAGE_OTHER;
+
+void
+rpmostree_output_set_stage (RpmOstreeOutputStage stage)
+{
+  active_stage = stage;
+}
+
+const char *
+rpmostree_output_get_stage (void)
+{
+  switch (active_stage)
+    {
+    case RPMOSTREE_OUTPUT_STAGE_DOWNLOAD:
+      return "download";
+    case RPMOSTREE_OUTPUT_STAGE_RESOLVE:
+      return "resolve";
+    case RPMOSTREE_OUTPUT_STAGE_ASSEMBLE:
+      return "assemble";
+    case RPMOSTREE_OUTPUT_STAGE_DEPLOY:
+      return "deploy";
+    case RPMOSTREE_OUTPUT_STAGE_OTHER:
+      break;
+    }
+  return "other";
+}
+
 #define strdup_vprintf(format)                                                                     \
   ({                                                                                               \
     va_list args;                                                                                  \
@@ -161,6 +188,48 @@ progress_percent_begin (const rust::Str msg) noexcept
   return std::make_unique<Progress> (ProgressType::PERCENT);
 }
 
+// The message to show for the timed operation @key of @size, with an estimate
+// of how long it will take if we have one.
+static std::string
+timed_message (const rust::Str msg, const rust::Str key, guint64 size)
+{
+  auto msg_c = std::string (msg);
+  auto estimate = rpmostreecxx::timings_describe (key, size);
+  if (!estimate.empty ())
+    msg_c += " (" + std::string (estimate) + ")";
+  return msg_c;
+}
+
+static std::unique_ptr<Progress>
+start_timing (std::unique_ptr<Progress> progress, const rust::Str key, guint64 size)
+{
+  progress->timing_key = std::string (key);
+  progress->timing_size = size;
+  progress->timing_start = g_get_monotonic_time ();
+  return progress;
+}
+
+std::unique_ptr<Progress>
+progress_begin_task_timed (const rust::Str msg, const rust::Str key) noexcept
+{
+  auto msg_c = timed_message (msg, key, 0);
+  return start_timing (progress_begin_task (msg_c), key, 0);
+}
+
+std::unique_ptr<Progress>
+progress_nitems_begin_timed (guint n, const rust::Str msg, const rust::Str key) noexcept
+{
+  auto msg_c = timed_message (msg, key, n);
+  return start_timing (progress_nitems_begin (n, msg_c), key, n);
+}
+
+std::unique_ptr<Progress>
+progress_percent_begin_timed (const rust::Str msg, const rust::Str key, guint64 size) noexcept
+{
+  auto msg_c = timed_message (msg, key, size);
+  return start_timing (progress_percent_begin (msg_c), key, size);
+}
+
 // Update the percentage.
 void
 Progress::percent_update (guint n)
@@ -178,6 +247,12 @@ Progress::end (const rust::Str msg)
   RpmOstreeOutputProgressEnd done = { final_msg };
   active_cb (RPMOSTREE_OUTPUT_PROGRESS_END, &done, active_cb_opaque);
   this->ended = true;
+
+  if (!this->timing_key.empty ())
+    {
+      guint64 elapsed_ms = (g_get_monotonic_time () - this->timing_start) / 1000;
+      rpmostreecxx::timings_record (this->timing_key, this->timing_size, elapsed_ms);
+    }
 }
 
 } /* namespace */
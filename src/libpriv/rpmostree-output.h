@@ -21,6 +21,7 @@
 #include "rust/cxx.h"
 #include <memory>
 #include <stdbool.h>
+#include <string>
 
 // C++ APIs here
 namespace rpmostreecxx
@@ -46,20 +47,37 @@ public:
   ~Progress ()
   {
     if (!this->ended)
-      this->end ("");
+      {
+        /* Only record the duration of operations which completed */
+        this->timing_key.clear ();
+        this->end ("");
+      }
   }
   Progress (ProgressType t)
   {
     ptype = t;
     ended = false;
+    timing_size = 0;
+    timing_start = 0;
   }
   ProgressType ptype;
   bool ended;
+  std::string timing_key;
+  guint64 timing_size;
+  gint64 timing_start;
 };
 
 std::unique_ptr<Progress> progress_begin_task (rust::Str msg) noexcept;
 std::unique_ptr<Progress> progress_nitems_begin (guint n, rust::Str msg) noexcept;
 std::unique_ptr<Progress> progress_percent_begin (rust::Str msg) noexcept;
+
+/* Like the above, but also record how long the operation @key takes when it ends,
+ * and show an estimate based on earlier ones.  See timings.rs. */
+std::unique_ptr<Progress> progress_begin_task_timed (rust::Str msg, rust::Str key) noexcept;
+std::unique_ptr<Progress> progress_nitems_begin_timed (guint n, rust::Str msg,
+                                                       rust::Str key) noexcept;
+std::unique_ptr<Progress> progress_percent_begin_timed (rust::Str msg, rust::Str key,
+                                                        guint64 size) noexcept;
 }
 
 // C APIs
@@ -78,6 +96,26 @@ void rpmostree_output_default_handler (RpmOstreeOutputType type, void *data, voi
 
 void rpmostree_output_set_callback (void (*cb) (RpmOstreeOutputType, void *, void *), void *);
 
+/* The stage of the operation that subsequent output belongs to; reported to
+ * clients alongside progress.
+ */
+typedef enum
+{
+  RPMOSTREE_OUTPUT_STAGE_OTHER,
+  /* Fetching ostree content, container images, packages or repository metadata */
+  RPMOSTREE_OUTPUT_STAGE_DOWNLOAD,
+  /* Resolving package dependencies */
+  RPMOSTREE_OUTPUT_STAGE_RESOLVE,
+  /* Building the new filesystem tree */
+  RPMOSTREE_OUTPUT_STAGE_ASSEMBLE,
+  /* Writing the new deployment */
+  RPMOSTREE_OUTPUT_STAGE_DEPLOY,
+} RpmOstreeOutputStage;
+
+void rpmostree_output_set_stage (RpmOstreeOutputStage stage);
+
+const char *rpmostree_output_get_stage (void);
+
 typedef struct
 {
   const char *text;
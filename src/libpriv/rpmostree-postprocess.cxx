@@ -217,7 +217,8 @@ process_kernel_and_initramfs (int rootfs_dfd, rpmostreecxx::Treefile &treefile,
     if (!glnx_mkdtempat (rootfs_dfd, "rpmostree-dracut.XXXXXX", 0700, &dracut_host_tmpd, error))
       return FALSE;
     if (!rpmostree_run_dracut (rootfs_dfd, (const char *const *)dracut_argv->pdata, kver, NULL,
-                               FALSE, &dracut_host_tmpd, &initramfs_tmpf, cancellable, error))
+                               FALSE, FALSE, &dracut_host_tmpd, &initramfs_tmpf, cancellable,
+                               error))
       return FALSE;
     /* No reason to have the initramfs not be world-readable since
      * it's server-side generated and shouldn't contain any secrets.
@@ -382,6 +383,7 @@ postprocess_final (int rootfs_dfd, rpmostreecxx::Treefile &treefile, gboolean un
   auto selinux = treefile.get_selinux ();
 
   ROSCXX_TRY (compose_postprocess_final (rootfs_dfd), error);
+  ROSCXX_TRY (secureboot_audit (rootfs_dfd, treefile), error);
 
   if (selinux)
     {
@@ -396,16 +398,31 @@ postprocess_final (int rootfs_dfd, rpmostreecxx::Treefile &treefile, gboolean un
 
   auto container = treefile.get_container ();
 
-  g_print ("Migrating /usr/etc/passwd to /usr/lib/\n");
-  ROSCXX_TRY (migrate_passwd_except_root (rootfs_dfd), error);
+  ROSCXX_TRY (compose_generate_sysusers (rootfs_dfd, treefile), error);
 
-  rust::Vec<rust::String> preserve_groups_set = treefile.get_etc_group_members ();
+  /* With systemd-homed, regular users stay in /etc */
+  const bool homed = treefile.get_systemd_homed ();
 
-  g_print ("Migrating /usr/etc/group to /usr/lib/\n");
-  ROSCXX_TRY (migrate_group_except_root (rootfs_dfd, preserve_groups_set), error);
+  if (treefile.get_nss_altfiles ())
+    {
+      g_print ("Migrating /usr/etc/passwd to /usr/lib/\n");
+      ROSCXX_TRY (migrate_passwd_except_root (rootfs_dfd, homed), error);
+
+      rust::Vec<rust::String> preserve_groups_set = treefile.get_etc_group_members ();
 
-  /* NSS configuration to look at the new files */
-  ROSCXX_TRY (composepost_nsswitch_altfiles (rootfs_dfd), error);
+      g_print ("Migrating /usr/etc/group to /usr/lib/\n");
+      ROSCXX_TRY (migrate_group_except_root (rootfs_dfd, preserve_groups_set, homed), error);
+
+      /* NSS configuration to look at the new files */
+      ROSCXX_TRY (composepost_nsswitch_altfiles (rootfs_dfd), error);
+    }
+  else
+    {
+      /* Everything stays in /etc, and is also written as user records */
+      ROSCXX_TRY (compose_userdb (rootfs_dfd, treefile), error);
+    }
+
+  ROSCXX_TRY (compose_systemd_homed (rootfs_dfd, treefile), error);
 
   if (selinux)
     {
@@ -415,6 +432,10 @@ postprocess_final (int rootfs_dfd, rpmostreecxx::Treefile &treefile, gboolean un
 
   ROSCXX_TRY (rootfs_prepare_links (rootfs_dfd), error);
 
+  ROSCXX_TRY (compose_postprocess_ownership (rootfs_dfd, treefile), error);
+
+  ROSCXX_TRY (compose_ownership_audit (rootfs_dfd, treefile), error);
+
   ROSCXX_TRY (convert_var_to_tmpfiles_d (rootfs_dfd, *cancellable), error);
 
   if (!rpmostree_rootfs_postprocess_common (rootfs_dfd, cancellable, error))
@@ -751,7 +772,8 @@ on_progress_timeout (gpointer datap)
 gboolean
 rpmostree_compose_commit (int rootfs_fd, OstreeRepo *repo, const char *parent_revision,
                           GVariant *src_metadata, GVariant *detached_metadata,
-                          const char *gpg_keyid, gboolean enable_selinux,
+                          const char *gpg_keyid, const char *ima_sign_key,
+                          const char *ima_sign_algorithm, gboolean enable_selinux,
                           OstreeRepoDevInoCache *devino_cache, char **out_new_revision,
                           GCancellable *cancellable, GError **error)
 {
@@ -836,6 +858,17 @@ rpmostree_compose_commit (int rootfs_fd, OstreeRepo *repo, const char *parent_re
                                  (OstreeRepoFile *)root_tree, &new_revision, cancellable, error))
     return glnx_prefix_error (error, "While writing commit");
 
+  /* This rewrites the commit, so it must happen before signing it */
+  if (ima_sign_key)
+    {
+      CXX_TRY_VAR (signed_revision,
+                   rpmostreecxx::compose_ima_sign (*repo, new_revision, ima_sign_key,
+                                                   ima_sign_algorithm),
+                   error);
+      g_free (new_revision);
+      new_revision = g_strdup (signed_revision.c_str ());
+    }
+
   if (detached_metadata != NULL)
     {
       if (!ostree_repo_write_commit_detached_metadata (repo, new_revision, detached_metadata,
@@ -34,6 +34,7 @@
 #include <sys/capability.h>
 #include <sys/ioctl.h>
 
+#include <rpm/rpmkeyring.h>
 #include <rpm/rpmts.h>
 
 static inline void
@@ -433,6 +434,23 @@ rpmhdrs_list (struct RpmHeaders *l1)
     }
 }
 
+/* Print each package formatted with the rpm query format @queryformat, e.g.
+ * "%{NAME} %{LICENSE}\n" (like `rpm -qa --queryformat`). */
+gboolean
+rpmhdrs_list_queryformat (struct RpmHeaders *l1, const char *queryformat, GError **error)
+{
+  for (guint num = 0; num < l1->hs->len; num++)
+    {
+      auto h1 = static_cast<Header> (l1->hs->pdata[num]);
+      errmsg_t errmsg = NULL;
+      g_autofree char *formatted = headerFormat (h1, queryformat, &errmsg);
+      if (!formatted)
+        return glnx_throw (error, "Invalid query format: %s", errmsg ?: "unknown error");
+      g_print ("%s", formatted);
+    }
+  return TRUE;
+}
+
 char *
 rpmhdrs_rpmdbv (struct RpmHeaders *l1, GCancellable *cancellable, GError **error)
 {
@@ -485,6 +503,150 @@ _rpmhdrs_diff_cmp_end (const GPtrArray *hs1, const GPtrArray *hs2)
   return header_name_cmp (h1, h2);
 }
 
+typedef void (*RpmChangelogFunc) (uint64_t date, const char *name, const char *text,
+                                  gpointer user_data);
+
+/* Call @func for each %changelog entry of @hn newer than the latest one of
+ * @ho, starting at the newest. */
+static void
+rpmhdrs_foreach_new_changelog (Header ho, Header hn, RpmChangelogFunc func, gpointer user_data)
+{
+  struct rpmtd_s ochanges_date_s;
+  _cleanup_rpmtddata_ rpmtd ochanges_date = NULL;
+  struct rpmtd_s ochanges_name_s;
+  _cleanup_rpmtddata_ rpmtd ochanges_name = NULL;
+  struct rpmtd_s ochanges_text_s;
+  _cleanup_rpmtddata_ rpmtd ochanges_text = NULL;
+  struct rpmtd_s nchanges_date_s;
+  _cleanup_rpmtddata_ rpmtd nchanges_date = NULL;
+  struct rpmtd_s nchanges_name_s;
+  _cleanup_rpmtddata_ rpmtd nchanges_name = NULL;
+  struct rpmtd_s nchanges_text_s;
+  _cleanup_rpmtddata_ rpmtd nchanges_text = NULL;
+  int ocnum = 0;
+  int ncnum = 0;
+  uint64_t ochange_date = 0;
+  const char *ochange_name = NULL;
+  const char *ochange_text = NULL;
+
+  /* Load the old %changelog entries */
+  ochanges_date = &ochanges_date_s;
+  headerGet (ho, RPMTAG_CHANGELOGTIME, ochanges_date, HEADERGET_MINMEM);
+  ochanges_name = &ochanges_name_s;
+  headerGet (ho, RPMTAG_CHANGELOGNAME, ochanges_name, HEADERGET_MINMEM);
+  ochanges_text = &ochanges_text_s;
+  headerGet (ho, RPMTAG_CHANGELOGTEXT, ochanges_text, HEADERGET_MINMEM);
+
+  ocnum = rpmtdCount (ochanges_date);
+  if (!ocnum)
+    return;
+
+  /* Load the new %changelog entries */
+  nchanges_date = &nchanges_date_s;
+  headerGet (hn, RPMTAG_CHANGELOGTIME, nchanges_date, HEADERGET_MINMEM);
+  nchanges_name = &nchanges_name_s;
+  headerGet (hn, RPMTAG_CHANGELOGNAME, nchanges_name, HEADERGET_MINMEM);
+  nchanges_text = &nchanges_text_s;
+  headerGet (hn, RPMTAG_CHANGELOGTEXT, nchanges_text, HEADERGET_MINMEM);
+
+  ncnum = rpmtdCount (nchanges_date);
+  if (!ncnum)
+    return;
+
+  /* Load the latest old %changelog entry. */
+  ochange_date = rpmtdGetNumber (ochanges_date);
+  ochange_name = rpmtdGetString (ochanges_name);
+  ochange_text = rpmtdGetString (ochanges_text);
+
+  while (ncnum > 0)
+    {
+      uint64_t nchange_date = 0;
+      const char *nchange_name = NULL;
+      const char *nchange_text = NULL;
+
+      /* Load next new %changelog entry, starting at the newest. */
+      rpmtdNext (nchanges_date);
+      rpmtdNext (nchanges_name);
+      rpmtdNext (nchanges_text);
+      nchange_date = rpmtdGetNumber (nchanges_date);
+      nchange_name = rpmtdGetString (nchanges_name);
+      nchange_text = rpmtdGetString (nchanges_text);
+
+      /*  If we are now older than, or match, the latest old %changelog
+       * then we are done. */
+      if (ochange_date > nchange_date)
+        break;
+      if ((ochange_date == nchange_date) && g_str_equal (ochange_name, nchange_name)
+          && g_str_equal (ochange_text, nchange_text))
+        break;
+
+      func (nchange_date, nchange_name, nchange_text, user_data);
+
+      --ncnum;
+    }
+}
+
+static void
+print_changelog_entry (uint64_t date, const char *name, const char *text, gpointer user_data)
+{
+#define CHANGELOG_INDENTATION "    "
+
+  g_autofree char *indented_text = NULL;
+  if (strchr (text, '\n'))
+    {
+      g_auto (GStrv) lines = g_strsplit (text, "\n", 0);
+      indented_text = g_strjoinv ("\n" CHANGELOG_INDENTATION, lines);
+    }
+
+  g_autoptr (GDateTime) dt = g_date_time_new_from_unix_utc (date);
+  g_autofree char *date_time_str = g_date_time_format (dt, "%a %b %d %Y");
+
+  g_print (CHANGELOG_INDENTATION "* %s %s\n" CHANGELOG_INDENTATION "%s\n\n", date_time_str,
+           name, indented_text ?: text);
+
+#undef CHANGELOG_INDENTATION
+}
+
+static void
+add_changelog_entry (uint64_t date, const char *name, const char *text, gpointer user_data)
+{
+  auto entries = static_cast<GVariantBuilder *> (user_data);
+  g_auto (GVariantDict) entry;
+  g_variant_dict_init (&entry, NULL);
+  g_variant_dict_insert (&entry, "time", "t", date);
+  g_variant_dict_insert (&entry, "author", "s", name);
+  g_variant_dict_insert (&entry, "text", "s", text);
+  g_variant_builder_add_value (entries, g_variant_dict_end (&entry));
+}
+
+/* Returns the new %changelog entries of the upgraded packages, as a{sv} of
+ * package name to aa{sv} of entries with "time", "author" and "text" keys,
+ * newest first.  Like the printing functions, this frees @diff. */
+GVariant *
+rpmhdrs_diff_changelogs_variant (struct RpmHeadersDiff *diff)
+{
+  g_assert (diff->hs_mod_old->len == diff->hs_mod_new->len);
+
+  g_auto (GVariantBuilder) builder;
+  g_variant_builder_init (&builder, G_VARIANT_TYPE ("a{sv}"));
+  for (guint num = 0; num < diff->hs_mod_new->len; ++num)
+    {
+      auto ho = static_cast<Header> (diff->hs_mod_old->pdata[num]);
+      auto hn = static_cast<Header> (diff->hs_mod_new->pdata[num]);
+      if (rpmVersionCompare (ho, hn) > 0)
+        continue;
+
+      g_auto (GVariantBuilder) entries;
+      g_variant_builder_init (&entries, G_VARIANT_TYPE ("aa{sv}"));
+      rpmhdrs_foreach_new_changelog (ho, hn, add_changelog_entry, &entries);
+      g_variant_builder_add (&builder, "{sv}", headerGetString (hn, RPMTAG_NAME),
+                             g_variant_builder_end (&entries));
+    }
+
+  rpmhdrs_diff_free (diff);
+  return g_variant_ref_sink (g_variant_builder_end (&builder));
+}
+
 void
 rpmhdrs_diff_prnt_block (gboolean changelogs, struct RpmHeadersDiff *diff)
 {
@@ -502,23 +664,6 @@ rpmhdrs_diff_prnt_block (gboolean changelogs, struct RpmHeadersDiff *diff)
         {
           auto ho = static_cast<Header> (diff->hs_mod_old->pdata[num]);
           auto hn = static_cast<Header> (diff->hs_mod_new->pdata[num]);
-          struct rpmtd_s ochanges_date_s;
-          _cleanup_rpmtddata_ rpmtd ochanges_date = NULL;
-          struct rpmtd_s ochanges_name_s;
-          _cleanup_rpmtddata_ rpmtd ochanges_name = NULL;
-          struct rpmtd_s ochanges_text_s;
-          _cleanup_rpmtddata_ rpmtd ochanges_text = NULL;
-          struct rpmtd_s nchanges_date_s;
-          _cleanup_rpmtddata_ rpmtd nchanges_date = NULL;
-          struct rpmtd_s nchanges_name_s;
-          _cleanup_rpmtddata_ rpmtd nchanges_name = NULL;
-          struct rpmtd_s nchanges_text_s;
-          _cleanup_rpmtddata_ rpmtd nchanges_text = NULL;
-          int ocnum = 0;
-          int ncnum = 0;
-          uint64_t ochange_date = 0;
-          const char *ochange_name = NULL;
-          const char *ochange_text = NULL;
 
           g_assert (!header_name_cmp (ho, hn));
           if (rpmVersionCompare (ho, hn) > 0)
@@ -550,80 +695,7 @@ rpmhdrs_diff_prnt_block (gboolean changelogs, struct RpmHeadersDiff *diff)
           if (g_strcmp0 (current_srpm, next_srpm) == 0)
             continue;
 
-          /* Load the old %changelog entries */
-          ochanges_date = &ochanges_date_s;
-          headerGet (ho, RPMTAG_CHANGELOGTIME, ochanges_date, HEADERGET_MINMEM);
-          ochanges_name = &ochanges_name_s;
-          headerGet (ho, RPMTAG_CHANGELOGNAME, ochanges_name, HEADERGET_MINMEM);
-          ochanges_text = &ochanges_text_s;
-          headerGet (ho, RPMTAG_CHANGELOGTEXT, ochanges_text, HEADERGET_MINMEM);
-
-          ocnum = rpmtdCount (ochanges_date);
-          if (!ocnum)
-            continue;
-
-          /* Load the new %changelog entries */
-          nchanges_date = &nchanges_date_s;
-          headerGet (hn, RPMTAG_CHANGELOGTIME, nchanges_date, HEADERGET_MINMEM);
-          nchanges_name = &nchanges_name_s;
-          headerGet (hn, RPMTAG_CHANGELOGNAME, nchanges_name, HEADERGET_MINMEM);
-          nchanges_text = &nchanges_text_s;
-          headerGet (hn, RPMTAG_CHANGELOGTEXT, nchanges_text, HEADERGET_MINMEM);
-
-          ncnum = rpmtdCount (nchanges_date);
-          if (!ncnum)
-            continue;
-
-          /* Load the latest old %changelog entry. */
-          ochange_date = rpmtdGetNumber (ochanges_date);
-          ochange_name = rpmtdGetString (ochanges_name);
-          ochange_text = rpmtdGetString (ochanges_text);
-
-          while (ncnum > 0)
-            {
-              uint64_t nchange_date = 0;
-              const char *nchange_name = NULL;
-              const char *nchange_text = NULL;
-              GDateTime *dt = NULL;
-              g_autofree char *date_time_str = NULL;
-
-              /* Load next new %changelog entry, starting at the newest. */
-              rpmtdNext (nchanges_date);
-              rpmtdNext (nchanges_name);
-              rpmtdNext (nchanges_text);
-              nchange_date = rpmtdGetNumber (nchanges_date);
-              nchange_name = rpmtdGetString (nchanges_name);
-              nchange_text = rpmtdGetString (nchanges_text);
-
-              /*  If we are now older than, or match, the latest old %changelog
-               * then we are done. */
-              if (ochange_date > nchange_date)
-                break;
-              if ((ochange_date == nchange_date) && g_str_equal (ochange_name, nchange_name)
-                  && g_str_equal (ochange_text, nchange_text))
-                break;
-
-#define CHANGELOG_INDENTATION "    "
-
-              g_autofree char *indented_nchange_text = NULL;
-              if (strchr (nchange_text, '\n'))
-                {
-                  g_auto (GStrv) lines = g_strsplit (nchange_text, "\n", 0);
-                  indented_nchange_text = g_strjoinv ("\n" CHANGELOG_INDENTATION, lines);
-                }
-
-              /* Otherwise, print. */
-              dt = g_date_time_new_from_unix_utc (nchange_date);
-              date_time_str = g_date_time_format (dt, "%a %b %d %Y");
-              g_date_time_unref (dt);
-
-              g_print (CHANGELOG_INDENTATION "* %s %s\n" CHANGELOG_INDENTATION "%s\n\n",
-                       date_time_str, nchange_name, indented_nchange_text ?: nchange_text);
-
-#undef CHANGELOG_INDENTATION
-
-              --ncnum;
-            }
+          rpmhdrs_foreach_new_changelog (ho, hn, print_changelog_entry, NULL);
         }
 
       done = FALSE;
@@ -1331,6 +1403,125 @@ rpmostree_is_valid_nevra (const char *subject)
   return rpmostree_decompose_nevra (subject, NULL, NULL, NULL, NULL, NULL, NULL);
 }
 
+/* Read the RPM in @fd with the keys of @keyring, which librpm uses to verify its
+ * header signature.  This uses a separate open file description, so the offset of
+ * @fd is left untouched.
+ */
+static gboolean
+read_rpm_with_keyring (int fd, rpmKeyring keyring, rpmRC *out_rc, Header *out_hdr, GError **error)
+{
+  g_auto (rpmts) ts = rpmtsCreate ();
+  rpmtsSetKeyring (ts, keyring);
+
+  g_autofree char *abspath = g_strdup_printf ("/proc/self/fd/%d", fd);
+  g_auto (FD_t) rpmfd = Fopen (abspath, "r.fdio");
+  if (rpmfd == NULL)
+    return glnx_throw (error, "Failed to open %s", abspath);
+  if (Ferror (rpmfd))
+    return glnx_throw (error, "Opening %s: %s", abspath, Fstrerror (rpmfd));
+
+  *out_rc = rpmReadPackageFile (ts, rpmfd, abspath, out_hdr);
+  return TRUE;
+}
+
+static gboolean
+header_is_signed (Header hdr)
+{
+  /* librpm only checks the digests of unsigned packages */
+  return headerIsEntry (hdr, RPMTAG_RSAHEADER) || headerIsEntry (hdr, RPMTAG_DSAHEADER)
+         || headerIsEntry (hdr, RPMTAG_SIGPGP) || headerIsEntry (hdr, RPMTAG_SIGGPG);
+}
+
+/* Verify the GPG signature of the RPM in @fd against the keys imported on the host, i.e.
+ * the ones in the rpmdb and in /etc/pki/rpm-gpg.  This uses a separate open file
+ * description, so the offset of @fd is left untouched.
+ */
+gboolean
+rpmostree_verify_rpm_signature (int fd, GError **error)
+{
+  ROSCXX_TRY (core_libdnf_process_global_init (), error);
+
+  g_auto (rpmts) keys_ts = rpmtsCreate ();
+  rpmKeyring keyring = rpmtsGetKeyring (keys_ts, 1);
+  gboolean keys_loaded = dnf_keyring_add_public_keys (keyring, error);
+  if (!keys_loaded)
+    {
+      rpmKeyringFree (keyring);
+      return glnx_prefix_error (error, "Loading GPG keys");
+    }
+
+  rpmRC rc;
+  g_auto (Header) hdr = NULL;
+  gboolean read_ok = read_rpm_with_keyring (fd, keyring, &rc, &hdr, error);
+  rpmKeyringFree (keyring);
+  if (!read_ok)
+    return FALSE;
+  switch (rc)
+    {
+    case RPMRC_OK:
+      if (!header_is_signed (hdr))
+        return glnx_throw (error, "Package is not signed");
+      return TRUE;
+    case RPMRC_NOKEY:
+      return glnx_throw (error, "Package is signed with a key not imported on this host");
+    case RPMRC_NOTTRUSTED:
+      return glnx_throw (error, "Package is signed with an untrusted key");
+    default:
+      return glnx_throw (error, "Package has a missing or invalid signature");
+    }
+}
+
+/* Verify the GPG signature of the RPM in @fd against only the keys in the files
+ * @keyfiles, ignoring those imported on the host.  @fd may also hold just the lead
+ * and headers of the RPM, as stored in the pkgcache, since the header signature
+ * covers the digests of the payload.  Returns the ID of the signing key in
+ * @out_keyid.
+ */
+gboolean
+rpmostree_verify_rpm_signature_with_keys (int fd, const char *const *keyfiles, char **out_keyid,
+                                          GError **error)
+{
+  ROSCXX_TRY (core_libdnf_process_global_init (), error);
+
+  rpmKeyring keyring = rpmKeyringNew ();
+  for (const char *const *it = keyfiles; it && *it; it++)
+    {
+      if (!dnf_keyring_add_public_key (keyring, *it, error))
+        {
+          rpmKeyringFree (keyring);
+          return glnx_prefix_error (error, "Loading GPG key %s", *it);
+        }
+    }
+
+  rpmRC rc;
+  g_auto (Header) hdr = NULL;
+  gboolean read_ok = read_rpm_with_keyring (fd, keyring, &rc, &hdr, error);
+  rpmKeyringFree (keyring);
+  if (!read_ok)
+    return FALSE;
+  switch (rc)
+    {
+    case RPMRC_OK:
+      break;
+    case RPMRC_NOKEY:
+    case RPMRC_NOTTRUSTED:
+      return glnx_throw (error, "Package is signed with a key not in rpm-signing-keys");
+    default:
+      return glnx_throw (error, "Package has a missing or invalid signature");
+    }
+  if (!header_is_signed (hdr))
+    return glnx_throw (error, "Package is not signed");
+
+  /* e.g. "RSA/SHA256, Tue Jan 10 2023, Key ID 809a8d7ceb10b464" */
+  g_autofree char *sig = headerFormat (
+      hdr, "%|RSAHEADER?{%{RSAHEADER:pgpsig}}:{%|DSAHEADER?{%{DSAHEADER:pgpsig}}:{}|}|", NULL);
+  const char *keyid = sig ? strstr (sig, "Key ID ") : NULL;
+  if (!keyid)
+    return glnx_throw (error, "Package has no header signature");
+  *out_keyid = g_strdup (keyid + strlen ("Key ID "));
+  return TRUE;
+}
+
 /* translates NEVRA to its cache branch */
 namespace rpmostreecxx
 {
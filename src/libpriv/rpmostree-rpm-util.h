@@ -67,10 +67,15 @@ struct RpmHeadersDiff *rpmhdrs_diff (struct RpmHeaders *l1, struct RpmHeaders *l
 
 void rpmhdrs_list (struct RpmHeaders *l1);
 
+gboolean rpmhdrs_list_queryformat (struct RpmHeaders *l1, const char *queryformat,
+                                   GError **error);
+
 char *rpmhdrs_rpmdbv (struct RpmHeaders *l1, GCancellable *cancellable, GError **error);
 
 void rpmhdrs_diff_prnt_block (gboolean changelogs, struct RpmHeadersDiff *diff);
 
+GVariant *rpmhdrs_diff_changelogs_variant (struct RpmHeadersDiff *diff);
+
 /* Define cleanup functions for librpm here. Note that this
  * will break if one day librpm ever decides to define these
  * itself. TODO: Move them to libdnf */
@@ -168,6 +173,11 @@ gboolean rpmostree_decompose_nevra (const char *nevra, char **out_name, /* allow
 
 gboolean rpmostree_is_valid_nevra (const char *subject);
 
+gboolean rpmostree_verify_rpm_signature (int fd, GError **error);
+
+gboolean rpmostree_verify_rpm_signature_with_keys (int fd, const char *const *keyfiles,
+                                                   char **out_keyid, GError **error);
+
 gboolean rpmostree_nevra_to_cache_branch (const char *nevra, char **cache_branch, GError **error);
 
 GPtrArray *rpmostree_get_enabled_rpmmd_repos (DnfContext *dnfctx, DnfRepoEnabled enablement);
@@ -0,0 +1,191 @@
+/* -*- mode: C; c-file-style: "gnu"; indent-tabs-mode: nil; -*-
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Lesser General Public License as published
+ * by the Free Software Foundation; either version 2 of the licence or (at
+ * your option) any later version.
+ *
+ * This library is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ * Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General
+ * Public License along with this library; if not, write to the
+ * Free Software Foundation, Inc., 59 Temple Place, Suite 330,
+ * Boston, MA 02111-1307, USA.
+ */
+
+#include "config.h"
+
+#include <libdnf/libdnf.h>
+
+#include "rpmostree-core.h"
+#include "rpmostree-refsack.h"
+#include "rpmostree-rpm-util.h"
+#include "rpmostree-scan.h"
+#include "rpmostree-util.h"
+
+/* Find the rpm-md file of @type (e.g. primary) in @repodata, as named by librepo, i.e.
+ * either `primary.xml.gz` or `<checksum>-primary.xml.zst`. */
+static gboolean
+find_repodata_file (const char *repodata, const char *type, char **out_path, GError **error)
+{
+  g_auto (GLnxDirFdIterator) dfd_iter = {
+    FALSE,
+  };
+  if (!glnx_dirfd_iterator_init_at (AT_FDCWD, repodata, TRUE, &dfd_iter, error))
+    return FALSE;
+  g_autofree char *suffix = g_strconcat (type, ".xml", NULL);
+  while (TRUE)
+    {
+      struct dirent *dent = NULL;
+      if (!glnx_dirfd_iterator_next_dent (&dfd_iter, &dent, NULL, error))
+        return FALSE;
+      if (dent == NULL)
+        break;
+
+      const char *match = strstr (dent->d_name, suffix);
+      if (!match || (match != dent->d_name && match[-1] != '-'))
+        continue;
+      *out_path = g_build_filename (repodata, dent->d_name, NULL);
+      break;
+    }
+  return TRUE;
+}
+
+/* Load the rpm-md repos cached under @cachedir by the last refresh, with their updateinfo;
+ * repos without it are skipped since they can't tell us anything. */
+static gboolean
+load_cached_repos (DnfSack *sack, const char *cachedir, GError **error)
+{
+  g_autofree char *repomd_dir = g_build_filename (cachedir, RPMOSTREE_DIR_CACHE_REPOMD, NULL);
+  if (!glnx_fstatat_allow_noent (AT_FDCWD, repomd_dir, NULL, 0, error))
+    return FALSE;
+  if (errno == ENOENT)
+    return TRUE;
+
+  g_auto (GLnxDirFdIterator) dfd_iter = {
+    FALSE,
+  };
+  if (!glnx_dirfd_iterator_init_at (AT_FDCWD, repomd_dir, TRUE, &dfd_iter, error))
+    return FALSE;
+  while (TRUE)
+    {
+      struct dirent *dent = NULL;
+      if (!glnx_dirfd_iterator_next_dent_ensure_dtype (&dfd_iter, &dent, NULL, error))
+        return FALSE;
+      if (dent == NULL)
+        break;
+      if (dent->d_type != DT_DIR)
+        continue;
+
+      g_autofree char *repodata = g_build_filename (repomd_dir, dent->d_name, "repodata", NULL);
+      g_autofree char *repomd = g_build_filename (repodata, "repomd.xml", NULL);
+      if (!glnx_fstatat_allow_noent (AT_FDCWD, repomd, NULL, 0, error))
+        return FALSE;
+      if (errno == ENOENT)
+        continue;
+
+      g_autofree char *primary = NULL;
+      g_autofree char *updateinfo = NULL;
+      if (!find_repodata_file (repodata, "primary", &primary, error))
+        return FALSE;
+      if (!find_repodata_file (repodata, "updateinfo", &updateinfo, error))
+        return FALSE;
+      if (!primary || !updateinfo)
+        continue;
+
+      HyRepo hrepo = hy_repo_create (dent->d_name);
+      hy_repo_set_string (hrepo, HY_REPO_MD_FN, repomd);
+      hy_repo_set_string (hrepo, HY_REPO_PRIMARY_FN, primary);
+      hy_repo_set_string (hrepo, HY_REPO_UPDATEINFO_FN, updateinfo);
+      gboolean loaded = dnf_sack_load_repo (sack, hrepo, DNF_SACK_LOAD_FLAG_USE_UPDATEINFO, error);
+      /* The sack holds its own reference */
+      hy_repo_free (hrepo);
+      if (!loaded)
+        return glnx_prefix_error (error, "Loading cached repo %s", dent->d_name);
+    }
+  return TRUE;
+}
+
+/* Add an entry for each security advisory fixing @pkg in a newer version. */
+static void
+add_advisories_for_pkg (GVariantBuilder *builder, DnfPackage *pkg)
+{
+  const char *name = dnf_package_get_name (pkg);
+  const char *arch = dnf_package_get_arch (pkg);
+  g_autoptr (GPtrArray) advisories = dnf_package_get_advisories (pkg, HY_GT);
+  for (guint i = 0; i < advisories->len; i++)
+    {
+      auto advisory = static_cast<DnfAdvisory *> (advisories->pdata[i]);
+      if (dnf_advisory_get_kind (advisory) != DNF_ADVISORY_KIND_SECURITY)
+        continue;
+
+      const char *fixed = NULL;
+      g_autoptr (GPtrArray) advpkgs = dnf_advisory_get_packages (advisory);
+      for (guint j = 0; j < advpkgs->len && !fixed; j++)
+        {
+          auto advpkg = static_cast<DnfAdvisoryPkg *> (advpkgs->pdata[j]);
+          if (g_str_equal (dnf_advisorypkg_get_name (advpkg), name)
+              && g_str_equal (dnf_advisorypkg_get_arch (advpkg), arch))
+            fixed = dnf_advisorypkg_get_evr (advpkg);
+        }
+      if (!fixed)
+        continue;
+
+      g_auto (GVariantBuilder) cves;
+      g_variant_builder_init (&cves, G_VARIANT_TYPE ("as"));
+      g_autoptr (GPtrArray) refs = dnf_advisory_get_references (advisory);
+      for (guint j = 0; j < refs->len; j++)
+        {
+          auto ref = static_cast<DnfAdvisoryRef *> (refs->pdata[j]);
+          if (dnf_advisoryref_get_kind (ref) == DNF_REFERENCE_KIND_CVE
+              && dnf_advisoryref_get_id (ref))
+            g_variant_builder_add (&cves, "s", dnf_advisoryref_get_id (ref));
+        }
+
+      const char *severity = dnf_advisory_get_severity (advisory);
+      g_variant_builder_add (builder, "(ssssss@as)", name, dnf_package_get_evr (pkg), arch,
+                             dnf_advisory_get_id (advisory), severity ?: "", fixed,
+                             g_variant_builder_end (&cves));
+    }
+}
+
+/* Match the packages of @rev against the security advisories of the cached repos. */
+static gboolean
+scan_updateinfo_impl (OstreeRepo *repo, const char *rev, const char *cachedir,
+                      GVariant **out_matches, GError **error)
+{
+  g_autoptr (RpmOstreeRefSack) rsack = rpmostree_get_refsack_for_commit (repo, rev, NULL, error);
+  if (!rsack)
+    return FALSE;
+  if (!load_cached_repos (rsack->sack, cachedir, error))
+    return FALSE;
+
+  g_auto (GVariantBuilder) builder;
+  g_variant_builder_init (&builder, RPMOSTREE_SCAN_VARIANT_FORMAT);
+  hy_autoquery HyQuery query = hy_query_create (rsack->sack);
+  hy_query_filter (query, HY_PKG_REPONAME, HY_EQ, HY_SYSTEM_REPO_NAME);
+  g_autoptr (GPtrArray) pkgs = hy_query_run (query);
+  for (guint i = 0; i < pkgs->len; i++)
+    add_advisories_for_pkg (&builder, static_cast<DnfPackage *> (pkgs->pdata[i]));
+  *out_matches = g_variant_ref_sink (g_variant_builder_end (&builder));
+  return TRUE;
+}
+
+namespace rpmostreecxx
+{
+GVariant *
+scan_updateinfo (const OstreeRepo &repo, rust::Str rev, rust::Str cachedir)
+{
+  g_autoptr (GError) local_error = NULL;
+  auto rev_c = std::string (rev);
+  auto cachedir_c = std::string (cachedir);
+  g_autoptr (GVariant) matches = NULL;
+  if (!scan_updateinfo_impl (&const_cast<OstreeRepo &> (repo), rev_c.c_str (), cachedir_c.c_str (),
+                             &matches, &local_error))
+    util::throw_gerror (local_error);
+  return util::move_nullify (matches);
+}
+}
@@ -0,0 +1,39 @@
+/* -*- mode: C; c-file-style: "gnu"; indent-tabs-mode: nil; -*-
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Lesser General Public License as published
+ * by the Free Software Foundation; either version 2 of the licence or (at
+ * your option) any later version.
+ *
+ * This library is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+ * Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General
+ * Public License along with this library; if not, write to the
+ * Free Software Foundation, Inc., 59 Temple Place, Suite 330,
+ * Boston, MA 02111-1307, USA.
+ */
+
+#pragma once
+
+#include <ostree.h>
+
+#include "rust/cxx.h"
+
+/*  s     package name
+    s     package EVR
+    s     package arch
+    s     advisory id
+    s     advisory severity, or empty if unknown
+    s     EVR of the package fixing it
+    as    CVEs
+*/
+#define RPMOSTREE_SCAN_VARIANT_FORMAT G_VARIANT_TYPE ("a(ssssssas)")
+
+// Backend of `rpm-ostree ex scan`; the OSV records and output are handled in scan.rs.
+namespace rpmostreecxx
+{
+GVariant *scan_updateinfo (const OstreeRepo &repo, rust::Str rev, rust::Str cachedir);
+}
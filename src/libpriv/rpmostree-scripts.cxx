@@ -303,7 +303,8 @@ dump_buffered_output_noerr (const char *prefix, GLnxTmpfile *tmpf)
  */
 gboolean
 rpmostree_run_script_in_bwrap_container (int rootfs_fd, GLnxTmpDir *var_lib_rpm_statedir,
-                                         gboolean enable_fuse, const char *name,
+                                         gboolean enable_fuse, const char *semodule_cachedir,
+                                         const char *name,
                                          const char *scriptdesc, const char *interp,
                                          const char *script, const char *script_arg,
                                          int provided_stdin_fd, GCancellable *cancellable,
@@ -364,6 +365,16 @@ rpmostree_run_script_in_bwrap_container (int rootfs_fd, GLnxTmpDir *var_lib_rpm_
   if (var_lib_rpm_statedir)
     bwrap->bind_readwrite (var_lib_rpm_statedir->path, "/var/lib/rpm-state");
 
+  /* Picked up by our semodule wrapper; see semodule-wrapper.sh.  Only new
+   * entries can be written, and they're checked in after the script. */
+  g_autofree char *semodule_incoming = NULL;
+  if (semodule_cachedir)
+    {
+      semodule_incoming = g_build_filename (semodule_cachedir, "incoming", NULL);
+      bwrap->bind_read (semodule_cachedir, "/run/rpmostree-semodule-cache");
+      bwrap->bind_readwrite (semodule_incoming, "/run/rpmostree-semodule-cache-incoming");
+    }
+
   gboolean debugging_script = g_strcmp0 (g_getenv ("RPMOSTREE_SCRIPT_DEBUG"), pkg_script) == 0;
 
   /* https://github.com/systemd/systemd/pull/7631 AKA
@@ -455,6 +466,9 @@ rpmostree_run_script_in_bwrap_container (int rootfs_fd, GLnxTmpDir *var_lib_rpm_
     }
   dump_buffered_output_noerr (pkg_script, &buffered_output);
 
+  if (semodule_cachedir)
+    ROSCXX_TRY (semodule_cache_import (semodule_cachedir), error);
+
   return TRUE;
 }
 
@@ -465,7 +479,7 @@ rpmostree_run_script_in_bwrap_container (int rootfs_fd, GLnxTmpDir *var_lib_rpm_
 static gboolean
 impl_run_rpm_script (const KnownRpmScriptKind *rpmscript, DnfPackage *pkg, Header hdr,
                      int rootfs_fd, GLnxTmpDir *var_lib_rpm_statedir, gboolean enable_fuse,
-                     GCancellable *cancellable, GError **error)
+                     const char *semodule_cachedir, GCancellable *cancellable, GError **error)
 {
   struct rpmtd_s td;
   g_autofree char **args = NULL;
@@ -556,8 +570,9 @@ impl_run_rpm_script (const KnownRpmScriptKind *rpmscript, DnfPackage *pkg, Heade
 
   guint64 start_time_ms = g_get_monotonic_time () / 1000;
   if (!rpmostree_run_script_in_bwrap_container (rootfs_fd, var_lib_rpm_statedir, enable_fuse,
-                                                dnf_package_get_name (pkg), rpmscript->desc, interp,
-                                                script, script_arg, -1, cancellable, error))
+                                                semodule_cachedir, dnf_package_get_name (pkg),
+                                                rpmscript->desc, interp, script, script_arg, -1,
+                                                cancellable, error))
     return glnx_prefix_error (error, "Running %s for %s", rpmscript->desc,
                               dnf_package_get_name (pkg));
   guint64 end_time_ms = g_get_monotonic_time () / 1000;
@@ -577,8 +592,8 @@ impl_run_rpm_script (const KnownRpmScriptKind *rpmscript, DnfPackage *pkg, Heade
  */
 static gboolean
 run_script (const KnownRpmScriptKind *rpmscript, DnfPackage *pkg, Header hdr, int rootfs_fd,
-            GLnxTmpDir *var_lib_rpm_statedir, gboolean enable_fuse, gboolean *out_did_run,
-            GCancellable *cancellable, GError **error)
+            GLnxTmpDir *var_lib_rpm_statedir, gboolean enable_fuse, const char *semodule_cachedir,
+            gboolean *out_did_run, GCancellable *cancellable, GError **error)
 {
   rpmTagVal tagval = rpmscript->tag;
   rpmTagVal progtagval = rpmscript->progtag;
@@ -598,7 +613,7 @@ run_script (const KnownRpmScriptKind *rpmscript, DnfPackage *pkg, Header hdr, in
 
   *out_did_run = TRUE;
   return impl_run_rpm_script (rpmscript, pkg, hdr, rootfs_fd, var_lib_rpm_statedir, enable_fuse,
-                              cancellable, error);
+                              semodule_cachedir, cancellable, error);
 }
 
 static gboolean
@@ -703,7 +718,8 @@ find_and_write_matching_files (int rootfs_fd, const char *pattern, FILE *f, guin
  */
 gboolean
 rpmostree_script_run_sync (DnfPackage *pkg, Header hdr, RpmOstreeScriptKind kind, int rootfs_fd,
-                           GLnxTmpDir *var_lib_rpm_statedir, gboolean enable_fuse, guint *out_n_run,
+                           GLnxTmpDir *var_lib_rpm_statedir, gboolean enable_fuse,
+                           const char *semodule_cachedir, guint *out_n_run,
                            GCancellable *cancellable, GError **error)
 {
   const KnownRpmScriptKind *scriptkind;
@@ -723,8 +739,8 @@ rpmostree_script_run_sync (DnfPackage *pkg, Header hdr, RpmOstreeScriptKind kind
     }
 
   gboolean did_run = FALSE;
-  if (!run_script (scriptkind, pkg, hdr, rootfs_fd, var_lib_rpm_statedir, enable_fuse, &did_run,
-                   cancellable, error))
+  if (!run_script (scriptkind, pkg, hdr, rootfs_fd, var_lib_rpm_statedir, enable_fuse,
+                   semodule_cachedir, &did_run, cancellable, error))
     return FALSE;
 
   if (did_run)
@@ -737,7 +753,8 @@ rpmostree_script_run_sync (DnfPackage *pkg, Header hdr, RpmOstreeScriptKind kind
  */
 gboolean
 rpmostree_transfiletriggers_run_sync (Header hdr, int rootfs_fd, gboolean enable_fuse,
-                                      guint *out_n_run, GCancellable *cancellable, GError **error)
+                                      const char *semodule_cachedir, guint *out_n_run,
+                                      GCancellable *cancellable, GError **error)
 {
   const char *pkg_name = headerGetString (hdr, RPMTAG_NAME);
   g_assert (pkg_name);
@@ -909,7 +926,8 @@ rpmostree_transfiletriggers_run_sync (Header hdr, int rootfs_fd, gboolean enable
 
       /* Run it, and log the result */
       guint64 start_time_ms = g_get_monotonic_time () / 1000;
-      if (!rpmostree_run_script_in_bwrap_container (rootfs_fd, NULL, enable_fuse, pkg_name,
+      if (!rpmostree_run_script_in_bwrap_container (rootfs_fd, NULL, enable_fuse,
+                                                    semodule_cachedir, pkg_name,
                                                     "%transfiletriggerin", interp, script, NULL,
                                                     fileno (tmpf_file), cancellable, error))
         return FALSE;
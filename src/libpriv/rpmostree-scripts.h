@@ -46,12 +46,13 @@ gboolean rpmostree_script_txn_validate (DnfPackage *package, Header hdr, GCancel
 
 gboolean rpmostree_script_run_sync (DnfPackage *pkg, Header hdr, RpmOstreeScriptKind kind,
                                     int rootfs_fd, GLnxTmpDir *var_lib_rpm_statedir,
-                                    gboolean enable_rofiles, guint *out_n_run,
-                                    GCancellable *cancellable, GError **error);
+                                    gboolean enable_rofiles, const char *semodule_cachedir,
+                                    guint *out_n_run, GCancellable *cancellable,
+                                    GError **error);
 
 gboolean rpmostree_transfiletriggers_run_sync (Header hdr, int rootfs_fd, gboolean enable_rofiles,
-                                               guint *out_n_run, GCancellable *cancellable,
-                                               GError **error);
+                                               const char *semodule_cachedir, guint *out_n_run,
+                                               GCancellable *cancellable, GError **error);
 
 gboolean rpmostree_deployment_sanitycheck_true (int rootfs_fd, GCancellable *cancellable,
                                                 GError **error);
@@ -61,7 +62,8 @@ gboolean rpmostree_deployment_sanitycheck_rpmdb (int rootfs_fd, GPtrArray *overl
                                                  GError **error);
 
 gboolean rpmostree_run_script_in_bwrap_container (int rootfs_fd, GLnxTmpDir *var_lib_rpm_statedir,
-                                                  gboolean enable_fuse, const char *name,
+                                                  gboolean enable_fuse,
+                                                  const char *semodule_cachedir, const char *name,
                                                   const char *scriptdesc, const char *interp,
                                                   const char *script, const char *script_arg,
                                                   int stdin_fd, GCancellable *cancellable,
@@ -34,6 +34,9 @@ typedef enum
   RPMOSTREED_AUTOMATIC_UPDATE_POLICY_NONE,
   RPMOSTREED_AUTOMATIC_UPDATE_POLICY_CHECK,
   RPMOSTREED_AUTOMATIC_UPDATE_POLICY_STAGE,
+  RPMOSTREED_AUTOMATIC_UPDATE_POLICY_APPLY_LIVE,
+  RPMOSTREED_AUTOMATIC_UPDATE_POLICY_LAYERED,
+  RPMOSTREED_AUTOMATIC_UPDATE_POLICY_SECURITY,
 } RpmostreedAutomaticUpdatePolicy;
 
 typedef enum
@@ -105,3 +105,84 @@ rpmostree_unpack_rpm2cpio (int fd, GError **error)
 
   return util::move_nullify (ar);
 }
+
+/* Matches the input buffer size zstd recommends for streaming */
+#define ZSTD_PAYLOAD_BUFSIZE (128 * 1024)
+
+typedef struct
+{
+  FD_t rpmfd;
+  char buf[ZSTD_PAYLOAD_BUFSIZE];
+} ZstdPayloadReader;
+
+static la_ssize_t
+zstd_payload_read (struct archive *ar, void *client_data, const void **out_buf)
+{
+  auto reader = static_cast<ZstdPayloadReader *> (client_data);
+  ssize_t n = Fread (reader->buf, 1, sizeof (reader->buf), reader->rpmfd);
+  if (n < 0 || Ferror (reader->rpmfd))
+    {
+      archive_set_error (ar, EIO, "Decompressing zstd payload: %s", Fstrerror (reader->rpmfd));
+      return -1;
+    }
+  *out_buf = reader->buf;
+  return n;
+}
+
+static int
+zstd_payload_close (struct archive *ar, void *client_data)
+{
+  auto reader = static_cast<ZstdPayloadReader *> (client_data);
+  (void)Fclose (reader->rpmfd);
+  g_free (reader);
+  return ARCHIVE_OK;
+}
+
+/**
+ * rpmostree_unpack_rpm_zstd_payload:
+ * @fd: An open file descriptor for an RPM package
+ * @payload_offset: Offset of the payload in @fd, after the RPM headers
+ * @error: GError
+ *
+ * Like rpmostree_unpack_rpm2cpio(), but for a package with a zstd compressed
+ * payload, which is streamed through librpm's zstd support rather than
+ * libarchive's.  This doesn't depend on libarchive having been built with
+ * libzstd rather than falling back to an external program, and skips parsing
+ * the RPM headers again.  @fd's file offset isn't changed.
+ */
+struct archive *
+rpmostree_unpack_rpm_zstd_payload (int fd, gsize payload_offset, GError **error)
+{
+  g_autoptr (archive) ar = archive_read_new ();
+  if (ar == NULL)
+    return (struct archive *)glnx_null_throw (error,
+                                              "Failed to initialize zstd payload archive object");
+  /* The payload is decompressed already, so only cpio is left */
+  if (archive_read_support_format_cpio (ar) != ARCHIVE_OK)
+    return throw_libarchive_error (ar, error, "Setting up zstd payload");
+
+  /* Open separately to have our own file offset, like
+   * rpmostree_importer_read_metainfo() */
+  g_autofree char *abspath = g_strdup_printf ("/proc/self/fd/%d", fd);
+  g_auto (FD_t) rpmfd = Fopen (abspath, "r.fdio");
+  if (rpmfd == NULL || Ferror (rpmfd))
+    return (struct archive *)glnx_null_throw (error, "Opening %s: %s", abspath,
+                                              rpmfd ? Fstrerror (rpmfd) : "failed");
+  if (Fseek (rpmfd, payload_offset, SEEK_SET) < 0)
+    return (struct archive *)glnx_null_throw (error, "Seeking to payload: %s",
+                                              Fstrerror (rpmfd));
+  /* This pushes the zstd layer onto the same FD_t */
+  FD_t zstdfd = Fdopen (rpmfd, "r.zstdio");
+  if (zstdfd == NULL || Ferror (zstdfd))
+    return (struct archive *)glnx_null_throw (error, "Opening zstd payload: %s",
+                                              Fstrerror (rpmfd));
+
+  auto reader = g_new0 (ZstdPayloadReader, 1);
+  reader->rpmfd = zstdfd;
+  rpmfd = NULL; /* Same as zstdfd, like in rpm2cpio */
+  /* The close callback frees the reader, also on failure */
+  if (archive_read_open (ar, reader, NULL, zstd_payload_read, zstd_payload_close) != ARCHIVE_OK)
+    return throw_libarchive_error (ar, error, "Reading zstd payload");
+
+  return util::move_nullify (ar);
+}
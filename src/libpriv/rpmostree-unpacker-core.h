@@ -34,4 +34,6 @@ G_DEFINE_AUTOPTR_CLEANUP_FUNC (archive, archive_read_free);
 
 struct archive *rpmostree_unpack_rpm2cpio (int fd, GError **error);
 
+struct archive *rpmostree_unpack_rpm_zstd_payload (int fd, gsize payload_offset, GError **error);
+
 G_END_DECLS
\ No newline at end of file
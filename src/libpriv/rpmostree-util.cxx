@@ -1027,6 +1027,12 @@ rpmostree_auto_update_policy_to_str (RpmostreedAutomaticUpdatePolicy policy, GEr
       return "check";
     case RPMOSTREED_AUTOMATIC_UPDATE_POLICY_STAGE:
       return "stage";
+    case RPMOSTREED_AUTOMATIC_UPDATE_POLICY_APPLY_LIVE:
+      return "apply-live";
+    case RPMOSTREED_AUTOMATIC_UPDATE_POLICY_LAYERED:
+      return "layered";
+    case RPMOSTREED_AUTOMATIC_UPDATE_POLICY_SECURITY:
+      return "security";
     default:
       return (char *)glnx_null_throw (error, "Invalid policy value %u", policy);
     }
@@ -1043,6 +1049,12 @@ rpmostree_str_to_auto_update_policy (const char *str, RpmostreedAutomaticUpdateP
     *out_policy = RPMOSTREED_AUTOMATIC_UPDATE_POLICY_CHECK;
   else if (g_str_equal (str, "stage") || g_str_equal (str, "ex-stage") /* backcompat */)
     *out_policy = RPMOSTREED_AUTOMATIC_UPDATE_POLICY_STAGE;
+  else if (g_str_equal (str, "apply-live"))
+    *out_policy = RPMOSTREED_AUTOMATIC_UPDATE_POLICY_APPLY_LIVE;
+  else if (g_str_equal (str, "layered"))
+    *out_policy = RPMOSTREED_AUTOMATIC_UPDATE_POLICY_LAYERED;
+  else if (g_str_equal (str, "security"))
+    *out_policy = RPMOSTREED_AUTOMATIC_UPDATE_POLICY_SECURITY;
   else
     return glnx_throw (error, "Invalid value for AutomaticUpdatePolicy: '%s'", str);
   return TRUE;
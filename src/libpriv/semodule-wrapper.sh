@@ -0,0 +1,54 @@
+#!/usr/bin/bash
+# Used by rpmostree-core.c to cache the policy built by `semodule` calls in
+# scriptlets when layering packages client-side.  The result of a call is
+# stored as a tarball of /etc/selinux, keyed on the arguments, the content of
+# the files they reference, semodule and its libraries, and /etc/selinux
+# itself (i.e. the base policy and the set of modules).  The cache is
+# read-only here; cached policies are only used if they match the checksums
+# recorded by rpm-ostree, and new ones are handed over to it in the incoming
+# directory.
+set -euo pipefail
+
+real=/usr/sbin/semodule.rpmostreesave
+cachedir=/run/rpmostree-semodule-cache
+incoming=/run/rpmostree-semodule-cache-incoming
+
+if ! test -d "${incoming}" || ! command -v tar >/dev/null || ! command -v ldd >/dev/null; then
+    exec "${real}" "$@"
+fi
+for arg in "$@"; do
+    case "${arg}" in
+        -l*|--list*|-E*|--extract*|-m*|--checksum|-c|--cil|-H|--hll|-h|--help)
+            exec "${real}" "$@";;
+    esac
+done
+
+# The libraries semodule is linked against in this root, wherever they are
+libs=$(ldd "${real}" | awk '/libsemanage|libsepol/ { print $3 }')
+key=$( (
+    printf '%s\0' "$@"
+    for arg in "$@"; do
+        if test -f "${arg}"; then
+            sha256sum < "${arg}"
+        fi
+    done
+    sha256sum < "${real}"
+    for lib in ${libs}; do
+        sha256sum < "${lib}"
+    done
+    find /etc/selinux -type f -print0 | sort -z | xargs -0r sha256sum
+) | sha256sum | cut -d' ' -f1)
+cached="${key}.tar"
+
+if test -f "${cachedir}/${cached}" && \
+   (cd "${cachedir}" && grep -F "  ${cached}" SHA256SUMS | sha256sum -c --status); then
+    echo "rpm-ostree-semodule: Using cached policy"
+    find /etc/selinux -mindepth 1 -delete
+    tar -xf "${cachedir}/${cached}" -C /etc/selinux
+    touch "${incoming}/${key}.used"
+    exit 0
+fi
+
+"${real}" "$@"
+tar -cf "${incoming}/${cached}.tmp" -C /etc/selinux .
+mv "${incoming}/${cached}.tmp" "${incoming}/${cached}"
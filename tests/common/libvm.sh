@@ -670,7 +670,7 @@ vm_change_update_policy() {
     policy=$1; shift
     vm_shell_inline <<EOF
     cp /usr/etc/rpm-ostreed.conf /etc
-    echo -e "[Daemon]\nAutomaticUpdatePolicy=$policy" > /etc/rpm-ostreed.conf
+    echo -e "[Daemon]\nAutomaticUpdatePolicy=$policy\nVerifyLocalPackages=false" > /etc/rpm-ostreed.conf
     rpm-ostree reload
 EOF
 }
@@ -115,6 +115,11 @@ assert_file_has_content_literal pkglist.txt 'foobar-1.0-1.x86_64'
 assert_not_file_has_content pkglist.txt 'foobar-rec'
 echo "ok compose pkglist"
 
+rpm-ostree db list --repo=${repo} ${treeref} --queryformat='%{NAME}|%{ARCH}\n' foobar > qf.txt
+assert_file_has_content_literal qf.txt 'foobar|x86_64'
+assert_not_file_has_content qf.txt 'ostree commit'
+echo "ok db list --queryformat"
+
 ostree --repo=${repo} cat ${treeref} /usr/share/rpm-ostree/treefile.json > treefile.json
 assert_jq treefile.json '.basearch == "x86_64"'
 echo "ok basearch"
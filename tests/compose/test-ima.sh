@@ -51,3 +51,19 @@ ostree --repo="${repo}" ls -X "${treeref}" /usr/bin/test-ima-signed > ima.txt
 # this is just a sanity check for now.
 assert_file_has_content_literal ima.txt "(b'security.ima', [byte 0x"
 echo "ok ima signature"
+
+# Without a key, only the files of the signed RPM carry a signature
+treefile_pyedit "tf['ima-verify'] = True"
+if runcompose &> err.txt; then
+  fatal "composed with unsigned files and ima-verify"
+fi
+assert_file_has_content err.txt 'files without an IMA signature'
+echo "ok ima-verify"
+
+treefile_pyedit "tf['ima-sign-key'] = '${test_tmpdir}/privkey_ima.pem'"
+runcompose
+ostree --repo="${repo}" ls -X "${treeref}" /usr/bin/bash > ima.txt
+assert_file_has_content_literal ima.txt "(b'security.ima', [byte 0x"
+ostree --repo="${repo}" ls -X "${treeref}" /usr/bin/test-ima-signed > ima.txt
+assert_file_has_content_literal ima.txt "(b'security.ima', [byte 0x"
+echo "ok ima-sign-key"
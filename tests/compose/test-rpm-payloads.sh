@@ -0,0 +1,40 @@
+#!/bin/bash
+set -xeuo pipefail
+
+dn=$(cd "$(dirname "$0")" && pwd)
+# shellcheck source=libcomposetest.sh
+. "${dn}/libcomposetest.sh"
+
+# Add a local rpm-md repo so we can mutate local test packages
+treefile_append "repos" '["test-repo"]'
+
+# zstd payloads are streamed through librpm; others still go through rpm2cpio
+for compressor in zstd xz; do
+  case $compressor in
+    zstd) payload=w19.zstdio;;
+    xz) payload=w2.xzdio;;
+  esac
+  build_rpm test-payload-${compressor} \
+               payload ${payload} \
+               build "seq 1 200000 > %{name}.data" \
+               install "mkdir -p %{buildroot}/usr/share/%{name}
+                        install -m 0644 %{name}.data %{buildroot}/usr/share/%{name}" \
+               files "/usr/share/%{name}"
+  rpm -qp --qf '%{PAYLOADCOMPRESSOR}\n' \
+    "${test_tmpdir}"/yumrepo/packages/$(arch)/test-payload-${compressor}-*.rpm > compressor.txt
+  assert_file_has_content compressor.txt "^${compressor}$"
+done
+
+echo gpgcheck=0 >> yumrepo.repo
+ln "$PWD/yumrepo.repo" config/yumrepo.repo
+treefile_append "packages" '["test-payload-zstd", "test-payload-xz"]'
+
+runcompose
+
+seq 1 200000 > expected.data
+for compressor in zstd xz; do
+  ostree --repo="${repo}" cat "${treeref}" \
+    /usr/share/test-payload-${compressor}/test-payload-${compressor}.data > actual.data
+  cmp expected.data actual.data
+done
+echo "ok import zstd and xz payloads"
@@ -0,0 +1,29 @@
+#!/bin/bash
+set -xeuo pipefail
+
+dn=$(cd "$(dirname "$0")" && pwd)
+# shellcheck source=libcomposetest.sh
+. "${dn}/libcomposetest.sh"
+
+# A key nothing in the compose is signed with
+cd "${test_tmpdir}"
+export GNUPGHOME="${test_tmpdir}/gnupg"
+mkdir -m 0700 "${GNUPGHOME}"
+gpg --batch --passphrase '' --quick-gen-key 'Test rpm-signing-keys' rsa2048 sign never
+gpg --armor --export 'Test rpm-signing-keys' > untrusted.asc
+
+treefile_set rpm-signing-keys "['${test_tmpdir}/untrusted.asc']"
+if runcompose &> err.txt; then
+  fatal "composed with packages not signed by rpm-signing-keys"
+fi
+assert_file_has_content err.txt 'Package is signed with a key not in rpm-signing-keys'
+echo "ok rpm-signing-keys untrusted"
+
+fedora_key=/etc/pki/rpm-gpg/RPM-GPG-KEY-fedora-$(rpm -E %fedora)-primary
+treefile_set rpm-signing-keys "['${fedora_key}']"
+runcompose
+ostree --repo="${repo}" show --print-metadata-key rpmostree.rpm-signing-keys "${treeref}" > keys.txt
+assert_file_has_content keys.txt "'[0-9a-f]*'"
+ostree --repo="${repo}" show --print-metadata-key rpmostree.rpm-signatures "${treeref}" > sigs.txt
+assert_file_has_content sigs.txt "'bash-[^']*': '[0-9a-f]*'"
+echo "ok rpm-signing-keys metadata"
@@ -0,0 +1,26 @@
+#!/bin/bash
+set -xeuo pipefail
+
+dn=$(cd "$(dirname "$0")" && pwd)
+# shellcheck source=libcomposetest.sh
+. "${dn}/libcomposetest.sh"
+
+# A certificate nothing in the compose is signed with
+cd "${test_tmpdir}"
+openssl req -new -nodes -utf8 -sha256 -days 36500 -batch -x509 \
+            -subj "/CN=Test secureboot-audit/" \
+            -out audit.pem -keyout audit-key.pem
+
+treefile_pyedit "tf['secureboot-audit'] = {'certificates': ['${test_tmpdir}/audit.pem']}"
+runcompose |& tee out.txt
+assert_file_has_content out.txt 'Auditing kernel and module signatures'
+assert_file_has_content out.txt 'invalid: usr/lib/modules/.*/vmlinuz'
+assert_file_has_content out.txt 'files: [1-9][0-9]* without a valid signature'
+echo "ok secureboot-audit report"
+
+treefile_pyedit "tf['secureboot-audit']['fatal'] = True"
+if runcompose &> err.txt; then
+  fatal "composed with untrusted signatures and a fatal secureboot-audit"
+fi
+assert_file_has_content err.txt 'secureboot-audit: [0-9]* files without a valid signature'
+echo "ok secureboot-audit fatal"
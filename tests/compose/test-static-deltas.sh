@@ -0,0 +1,30 @@
+#!/bin/bash
+set -xeuo pipefail
+
+dn=$(cd "$(dirname "$0")" && pwd)
+# shellcheck source=libcomposetest.sh
+. "${dn}/libcomposetest.sh"
+
+# Nothing to generate for a commit without a parent
+runcompose --generate-static-deltas=2
+rev1=$(ostree --repo="${repo}" rev-parse "${treeref}")
+ostree --repo="${repo}" static-delta list > deltas.txt
+assert_not_file_has_content deltas.txt "${rev1}"
+echo "ok no deltas for first commit"
+
+runcompose --force-nocache
+rev2=$(ostree --repo="${repo}" rev-parse "${treeref}")
+runcompose --force-nocache --generate-static-deltas=2 |& tee out.txt
+rev3=$(ostree --repo="${repo}" rev-parse "${treeref}")
+assert_file_has_content_literal out.txt "Generating static delta: ${rev2}-${rev3}"
+ostree --repo="${repo}" static-delta list > deltas.txt
+assert_file_has_content_literal deltas.txt "${rev2}-${rev3}"
+assert_file_has_content_literal deltas.txt "${rev1}-${rev3}"
+assert_not_file_has_content deltas.txt "${rev1}-${rev2}"
+echo "ok deltas from last N commits"
+
+if runcompose --generate-static-deltas=-1 &> err.txt; then
+  fatal "compose with negative --generate-static-deltas succeeded"
+fi
+assert_file_has_content_literal err.txt "--generate-static-deltas must not be negative"
+echo "ok negative count rejected"
@@ -57,6 +57,16 @@ rpm-ostree status --jsonpath '$.deployments[0].booted' > jsonpath.txt
 assert_file_has_content_literal jsonpath.txt 'true'
 echo "ok jsonpath"
 
+rpm-ostree status --query '.deployments[0].booted' > query.txt
+assert_file_has_content_literal query.txt 'true'
+checksum=$(rpm-ostree status --json | jq -r '.deployments[-1].checksum')
+test "$(rpm-ostree status -q '.deployments[-1]["checksum"]')" = "${checksum}"
+if rpm-ostree status --query '.deployments[0].nosuchkey' 2>err.txt; then
+    fatal "queried a missing key"
+fi
+assert_file_has_content_literal err.txt 'No value at .deployments[0].nosuchkey'
+echo "ok query"
+
 # Verify operations as non-root
 runuser -u core rpm-ostree status
 echo "ok status doesn't require root"
@@ -76,6 +86,12 @@ assert_not_file_has_content status.txt StateRoot:
 assert_not_file_has_content status.txt Unlocked:
 rpm-ostree status -v > status.txt
 assert_file_has_content status.txt StateRoot:
+assert_file_has_content status.txt UniqueSize:
+rpm-ostree status --json > status.json
+assert_jq status.json '.deployments[0]["unique-size"] == null'
+rpm-ostree status --json -v > status.json
+assert_jq status.json '.deployments[0]["unique-size"] | type == "number"'
+rm status.json
 echo "ok status text"
 
 # Also check that we can do status as non-root non-active
@@ -102,6 +118,27 @@ assert_file_has_content_literal err.txt 'not allowed for non-root user (polkit n
 systemctl unmask polkit
 echo "ok worked without polkit"
 
+# Verify the fine-grained actions: allow changing kernel arguments, and nothing else
+cat > /etc/polkit-1/rules.d/50-rpmostree-test.rules <<'EOF'
+polkit.addRule(function(action, subject) {
+    if (action.id == "org.projectatomic.rpmostree1.kargs" && subject.user == "core") {
+        return polkit.Result.YES;
+    }
+});
+EOF
+systemctl restart polkit
+runuser -u core -- rpm-ostree kargs --append=rpmostree.testkarg=1
+rpm-ostree kargs > kargs.txt
+assert_file_has_content_literal kargs.txt 'rpmostree.testkarg=1'
+if runuser -u core -- rpm-ostree initramfs --enable 2>err.txt; then
+    assert_not_reached "Was able to enable initramfs with only the kargs action!"
+fi
+assert_file_has_content_literal err.txt 'not allowed for user'
+rm -f /etc/polkit-1/rules.d/50-rpmostree-test.rules kargs.txt err.txt
+systemctl restart polkit
+rpm-ostree cleanup -p
+echo "ok polkit kargs action"
+
 rpm-ostree status -b > status.txt
 assert_file_has_content status.txt BootedDeployment:
 echo "ok status -b"
@@ -190,8 +227,61 @@ unshare -m /bin/bash -c "mount -o remount,rw /sysroot && cp -a ${originpath}{.or
 rpm-ostree reload
 echo "ok unconfigured-state"
 
+rpm-ostree audit-log --json > audit.json
+assert_jq audit.json '.[-1].method == "Upgrade"' '.[-1].success == false' \
+  '.[-1].error|contains("ONE BILLION DOLLARS")' '.[-1]["client-uid"] == 0' \
+  '.[-1].args|contains("initiating-command-line")'
+rpm-ostree audit-log -n 1 > audit.txt
+assert_file_has_content audit.txt 'Upgrade failed: .*ONE BILLION DOLLARS'
+rm -f audit.json audit.txt
+echo "ok audit-log"
+
+# Operate on a sysroot we're not booted into through a private daemon
+osname=$(rpm-ostree status --json | jq -r '.deployments[0].osname')
+checksum=$(rpm-ostree status --json | jq -r '.deployments[0].checksum')
+target=/var/tmp/target-sysroot
+ostree admin init-fs --modern ${target}
+ostree --repo=${target}/ostree/repo pull-local /ostree/repo ${checksum}
+ostree admin --sysroot=${target} os-init ${osname}
+ostree admin --sysroot=${target} deploy --os=${osname} ${checksum}
+rpm-ostree status --sysroot=${target} --json > status.json
+assert_jq status.json '.deployments|length == 1' '.deployments[0].booted == false'
+rpm-ostree kargs --sysroot=${target} --append=rpmostree.offlinekarg=1
+cat ${target}/boot/loader/entries/*.conf > entries.txt
+assert_file_has_content_literal entries.txt 'rpmostree.offlinekarg=1'
+rpm-ostree kargs > kargs.txt
+assert_not_file_has_content_literal kargs.txt 'rpmostree.offlinekarg=1'
+rm -rf ${target} status.json entries.txt kargs.txt
+echo "ok operate on offline sysroot"
+
+if rpm-ostree db diff --image quay.io/fedora/fedora-coreos:stable 2>err.txt; then
+  assert_not_reached "db diff with a single --image"
+fi
+assert_file_has_content err.txt 'exactly twice'
+echo "ok db diff --image usage"
+
 ### Stuff following here may mutate the host persistently ###
 
 rpm-ostree usroverlay
 echo some content > /usr/share/testcontent
 echo "ok usroverlay"
+
+cp /etc/containers/policy.json policy.json.orig
+openssl genpkey -algorithm EC -pkeyopt ec_paramgen_curve:P-256 -out cosign.key
+openssl pkey -in cosign.key -pubout -out cosign.pub
+rpm-ostree ex sigpolicy add-sigstore-key registry.example.com/os cosign.pub
+assert_jq /etc/containers/policy.json \
+  '.transports.docker["registry.example.com/os"][0].type == "sigstoreSigned"' \
+  '.transports.docker["registry.example.com/os"][0].keyPath == "/etc/pki/containers/cosign.pub"'
+assert_file_has_content /etc/containers/registries.d/rpm-ostree-sigpolicy.yaml 'use-sigstore-attachments: true'
+rpm-ostree ex sigpolicy show --image registry.example.com/os:stable > sigpolicy.txt
+assert_file_has_content_literal sigpolicy.txt 'registry.example.com/os: sigstoreSigned'
+if rpm-ostree ex sigpolicy add-sigstore-key registry.example.com/os policy.json.orig 2>err.txt; then
+  assert_not_reached "added an invalid key"
+fi
+assert_file_has_content err.txt 'PEM'
+rpm-ostree ex sigpolicy remove registry.example.com/os
+test ! -f /etc/containers/registries.d/rpm-ostree-sigpolicy.yaml
+cp policy.json.orig /etc/containers/policy.json
+rm -f /etc/pki/containers/cosign.pub cosign.key cosign.pub sigpolicy.txt err.txt policy.json.orig
+echo "ok sigpolicy"
@@ -0,0 +1,97 @@
+#!/bin/bash
+#
+# Copyright (C) 2022 Red Hat, Inc.
+#
+# This library is free software; you can redistribute it and/or
+# modify it under the terms of the GNU Lesser General Public
+# License as published by the Free Software Foundation; either
+# version 2 of the License, or (at your option) any later version.
+#
+# This library is distributed in the hope that it will be useful,
+# but WITHOUT ANY WARRANTY; without even the implied warranty of
+# MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the GNU
+# Lesser General Public License for more details.
+#
+# You should have received a copy of the GNU Lesser General Public
+# License along with this library; if not, write to the
+# Free Software Foundation, Inc., 59 Temple Place - Suite 330,
+# Boston, MA 02111-1307, USA.
+
+
+set -euo pipefail
+
+. ${commondir}/libtest.sh
+. ${commondir}/libvm.sh
+
+set -x
+
+# create a new vmcheck commit which has foo and bar in it already so that the
+# state can remove and replace them
+vm_build_rpm foo version 1.0
+vm_build_rpm bar
+vm_rpmostree install foo bar
+vm_cmd ostree refs $(vm_get_pending_csum) --create vmcheck_tmp/with_foo_and_bar
+vm_rpmostree cleanup -p
+vm_ostree_commit_layered_as_base vmcheck_tmp/with_foo_and_bar vmcheck
+vm_rpmostree upgrade
+vm_reboot
+if ! vm_has_packages foo bar; then
+    assert_not_reached "foo or bar not in base layer"
+fi
+echo "ok setup"
+
+vm_build_rpm foo version 2.0
+vm_build_rpm baz
+vm_send_inline /tmp/state.yaml <<EOS
+packages: [baz]
+override-remove: [bar]
+override-replace:
+  - from: {repo: test-repo}
+    packages: [foo]
+EOS
+vm_rpmostree ex apply /tmp/state.yaml
+vm_assert_status_jq \
+  '.deployments[0]["requested-packages"] == ["baz"]' \
+  '.deployments[0]["requested-base-removals"] == ["bar"]' \
+  '.deployments[0]["requested-base-remote-replacements"]|length == 1' \
+  '.deployments[0]["requested-base-remote-replacements"][0][0] == "repo=test-repo"' \
+  '.deployments[0]["requested-base-remote-replacements"][0][1] == ["foo"]' \
+  '.deployments[0]["base-remote-replacements"]["repo=test-repo"][0][0][0] == "foo-2.0-1.x86_64"'
+echo "ok apply state"
+
+# applying the same state again is a no-op
+pending=$(vm_get_pending_csum)
+vm_rpmostree ex apply /tmp/state.yaml
+assert_streq "$(vm_get_pending_csum)" "${pending}"
+echo "ok apply state again"
+
+# only the aspects which are present are changed
+vm_send_inline /tmp/state.yaml <<EOS
+packages: []
+override-replace: []
+EOS
+vm_rpmostree ex apply /tmp/state.yaml
+vm_assert_status_jq \
+  '.deployments[0]["requested-packages"]|length == 0' \
+  '.deployments[0]["requested-base-removals"] == ["bar"]' \
+  '.deployments[0]["requested-base-remote-replacements"]|length == 0'
+echo "ok apply partial state"
+
+vm_send_inline /tmp/state.yaml <<EOS
+override-remove: [foo]
+override-replace:
+  - from: {repo: test-repo}
+    packages: [foo]
+EOS
+if vm_rpmostree ex apply /tmp/state.yaml 2>err.txt; then
+  assert_not_reached "applied a state removing and replacing foo"
+fi
+assert_file_has_content err.txt "foo is both in override-remove and override-replace"
+vm_send_inline /tmp/state.yaml <<EOS
+initramfs:
+  etc: [/usr/foo]
+EOS
+if vm_rpmostree ex apply /tmp/state.yaml 2>err.txt; then
+  assert_not_reached "applied a state tracking a file outside /etc"
+fi
+echo "ok invalid states"
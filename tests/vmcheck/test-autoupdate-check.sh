@@ -90,6 +90,21 @@ assert_file_has_content out.txt "No updates available."
 assert_file_has_content out-verbose.txt "No updates available."
 echo "ok --check/--preview no updates"
 
+# and so does --quick, which only needs the summary
+if vm_rpmostree upgrade --quick 2>err.txt; then
+  assert_not_reached "--quick without --check succeeded?"
+fi
+assert_file_has_content err.txt "--quick requires --check"
+vm_shell_inline_sysroot_rw <<EOF
+ostree summary --repo=$REMOTE_OSTREE -u
+EOF
+rc=0
+vm_rpmostree upgrade --check --quick > out.txt || rc=$?
+assert_streq $rc 77
+assert_file_has_content out.txt "No updates available."
+assert_not_file_has_content out.txt "checking the commit instead"
+echo "ok --check --quick no updates"
+
 # ok, let's test out check
 vm_change_update_policy check
 vm_rpmostree status | grep 'AutomaticUpdates: check'
@@ -141,7 +156,8 @@ assert_not_file_has_content out.txt "VMCHECK-ENH"
 
 assert_output() {
   assert_file_has_content out.txt \
-    "SecAdvisories: 1 unknown severity, 1 low, 1 critical"
+    "SecAdvisories: 1 unknown severity, 1 low, 1 critical" \
+    "LayeredSecAdvisories: 1 unknown severity, 1 low, 1 critical"
   assert_file_has_content out-verbose.txt \
     "SecAdvisories: VMCHECK-SEC-NONE  Unknown    layered-sec-none-2.0-1.x86_64" \
     "               VMCHECK-SEC-LOW   Low        layered-sec-low-2.0-1.x86_64" \
@@ -283,3 +299,58 @@ assert_not_file_has_content out-verbose.txt "Available update"
 assert_output2
 assert_default_deployment_is_update
 echo "ok upgrade"
+
+vm_shell_inline <<EOF
+echo "MetricsFile=/run/rpm-ostree-metrics.prom" >> /etc/rpm-ostreed.conf
+rpm-ostree reload
+EOF
+vm_rpmostree upgrade --check || true
+vm_cmd cat /run/rpm-ostree-metrics.prom > metrics.txt
+assert_file_has_content metrics.txt '^rpm_ostree_last_update_check_timestamp_seconds [0-9]\+$'
+assert_file_has_content metrics.txt '^rpm_ostree_transaction_duration_seconds_count{method="AutomaticUpdateTrigger"} 1$'
+assert_file_has_content metrics.txt '^rpm_ostree_transactions_failed_total{method="AutomaticUpdateTrigger"} 0$'
+assert_file_has_content metrics.txt '^rpm_ostree_downloaded_bytes_total [0-9]\+$'
+echo "ok metrics"
+
+# the layered policy only picks up updates of layered packages, on the same base
+vm_ostreeupdate_create_noop v3
+vm_build_rpm layered-cake version 2.1 release 5
+vm_change_update_policy layered
+vm_rpmostree status > out.txt
+assert_file_has_content out.txt 'AutomaticUpdates: layered'
+vm_rpmostree upgrade --trigger-automatic-update-policy
+vm_assert_status_jq \
+    '.deployments[0]["staged"]' \
+    '.deployments[0]["version"] == "v2"' \
+    '.deployments[0]["packages"]|length == 6'
+vm_rpmostree db list $(vm_get_pending_csum) > list.txt
+assert_file_has_content list.txt 'layered-cake-2.1-5.x86_64'
+vm_rpmostree upgrade --trigger-automatic-update-policy > out.txt
+assert_file_has_content out.txt 'No updates of layered packages available.'
+echo "ok layered policy"
+
+# the security policy only stages updates addressing advisories of the configured severity
+vm_reboot
+vm_start_httpd ostree_server $REMOTE_OSTREE 8888
+vm_uinfo add VMCHECK-SEC-LOW2 security low
+vm_uinfo add VMCHECK-SEC-CRIT2 security critical
+vm_build_rpm layered-sec-low version 3.0 uinfo VMCHECK-SEC-LOW2
+vm_shell_inline <<EOF2
+echo -e "[Daemon]\nAutomaticUpdatePolicy=security\nSecurityMinSeverity=important" > /etc/rpm-ostreed.conf
+rpm-ostree reload
+EOF2
+vm_rpmostree status > out.txt
+assert_file_has_content out.txt 'AutomaticUpdates: security'
+vm_rpmostree upgrade --trigger-automatic-update-policy > out.txt
+assert_file_has_content out.txt "Update doesn't address security advisories of the configured severity"
+vm_assert_status_jq '.deployments[0]["booted"]' \
+  '.["cached-update"]["version"] == "v3"'
+vm_rpmostree status > out.txt
+assert_file_has_content out.txt "SecAdvisories: 1 low"
+vm_build_rpm layered-sec-crit version 3.0 uinfo VMCHECK-SEC-CRIT2
+vm_rpmostree upgrade --trigger-automatic-update-policy
+vm_assert_status_jq '.deployments[0]["staged"]' \
+  '.deployments[0]["version"] == "v3"'
+vm_rpmostree db list $(vm_get_pending_csum) > list.txt
+assert_file_has_content list.txt 'layered-sec-low-3.0-1.x86_64' 'layered-sec-crit-3.0-1.x86_64'
+echo "ok security policy"
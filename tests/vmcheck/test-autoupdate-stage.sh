@@ -42,10 +42,79 @@ vm_change_update_policy ex-stage
 vm_rpmostree status > status.txt
 assert_file_has_content_literal status.txt 'AutomaticUpdates: stage; rpm-ostreed-automatic.timer: inactive'
 
+# Outside of the update window, we only check for updates
+tomorrow=$(vm_cmd date -d tomorrow +%a)
+vm_cmd "echo 'UpdateWindow=${tomorrow} 00:00-00:01' >> /etc/rpm-ostreed.conf"
+vm_rpmostree reload
+vm_rpmostree status > status.txt
+assert_file_has_content status.txt "UpdateWindow: ${tomorrow} 00:00-00:01; next ${tomorrow} "
+vm_rpmostree upgrade --trigger-automatic-update-policy
+vm_assert_status_jq ".deployments[0][\"booted\"]" \
+                    ".deployments[0][\"staged\"]|not"
+vm_rpmostree status > status.txt
+assert_file_has_content status.txt "AvailableUpdate"
+vm_cmd "sed -i -e 's/^UpdateWindow=.*/UpdateWindow=00:00-23:59; 23:59-00:00/' /etc/rpm-ostreed.conf"
+vm_rpmostree reload
+vm_rpmostree status > status.txt
+assert_file_has_content status.txt "UpdateWindow: .*; open until "
+vm_cmd "sed -i -e '/^UpdateWindow=/d' /etc/rpm-ostreed.conf"
+echo "ok autoupdate update window"
+
+# Inside of the download window but outside of the update window, updates are only
+# downloaded; outside of the download window, those are deployed without downloading
+vm_cmd "echo 'UpdateWindow=${tomorrow} 00:00-00:01' >> /etc/rpm-ostreed.conf"
+vm_cmd "echo 'DownloadWindow=00:00-23:59; 23:59-00:00' >> /etc/rpm-ostreed.conf"
+vm_cmd "echo 'AutomaticUpdateBandwidthLimitKBps=1024' >> /etc/rpm-ostreed.conf"
+vm_rpmostree reload
+vm_rpmostree status > status.txt
+assert_file_has_content status.txt "DownloadWindow: .*; open until "
+vm_rpmostree upgrade --trigger-automatic-update-policy > upgrade.txt
+assert_file_has_content_literal upgrade.txt 'Update downloaded.'
+vm_assert_status_jq ".deployments[0][\"booted\"]" \
+                    ".deployments[0][\"staged\"]|not" \
+                    '.["cached-update"]["version"] == "v2"'
+vm_cmd "sed -i -e 's/^UpdateWindow=.*/UpdateWindow=00:00-23:59; 23:59-00:00/' \
+  -e 's/^DownloadWindow=.*/DownloadWindow=${tomorrow} 00:00-00:01/' /etc/rpm-ostreed.conf"
+vm_rpmostree reload
+vm_rpmostree status > status.txt
+assert_file_has_content status.txt "DownloadWindow: ${tomorrow} 00:00-00:01; next ${tomorrow} "
+vm_rpmostree upgrade --trigger-automatic-update-policy
+vm_assert_status_jq ".deployments[0][\"staged\"]" \
+                    ".deployments[0][\"version\"] == \"v2\""
+vm_rpmostree cleanup -p
+vm_cmd "sed -i -e '/^UpdateWindow=/d' -e '/^DownloadWindow=/d' \
+  -e '/^AutomaticUpdateBandwidthLimitKBps=/d' /etc/rpm-ostreed.conf"
+vm_rpmostree reload
+echo "ok autoupdate download window"
+
+# With prefetching, updates are downloaded in the background outside of the update window
+vm_cmd "echo 'UpdateWindow=${tomorrow} 00:00-00:01' >> /etc/rpm-ostreed.conf"
+vm_cmd "echo 'PrefetchUpdates=true' >> /etc/rpm-ostreed.conf"
+vm_rpmostree reload
+cursor=$(vm_get_journal_cursor)
+vm_rpmostree upgrade --trigger-automatic-update-policy > upgrade.txt
+assert_file_has_content_literal upgrade.txt 'Update downloaded.'
+vm_wait_content_after_cursor $cursor 'Outside of UpdateWindow .*; prefetching updates'
+vm_assert_status_jq ".deployments[0][\"booted\"]" \
+                    ".deployments[0][\"staged\"]|not" \
+                    '.["cached-update"]["version"] == "v2"'
+vm_cmd "sed -i -e '/^UpdateWindow=/d' /etc/rpm-ostreed.conf"
+if vm_rpmostree reload 2>err.txt; then
+  assert_not_reached "PrefetchUpdates without UpdateWindow"
+fi
+assert_file_has_content err.txt "PrefetchUpdates requires UpdateWindow"
+vm_cmd "sed -i -e '/^PrefetchUpdates=/d' /etc/rpm-ostreed.conf"
+vm_rpmostree reload
+echo "ok autoupdate prefetch"
+
+vm_cmd 'echo UpdateNotifications=true >> /etc/rpm-ostreed.conf'
+vm_rpmostree reload
+cursor=$(vm_get_journal_cursor)
 vm_rpmostree upgrade --trigger-automatic-update-policy
 vm_assert_status_jq ".deployments[1][\"booted\"]" \
                     ".deployments[0][\"staged\"]" \
                     ".deployments[0][\"version\"] == \"v2\""
+vm_wait_content_after_cursor $cursor 'Emitting update notification: .* is staged (version v2)'
 vm_rpmostree status -v > status.txt
 assert_file_has_content status.txt "Staged: yes"
 vm_rpmostree upgrade > upgrade.txt
@@ -60,3 +129,60 @@ vm_assert_status_jq ".deployments[0][\"booted\"]" \
 vm_cmd cat /etc/somenewfile > somenewfile.txt
 assert_file_has_content somenewfile.txt new-content-in-etc
 echo "ok autoupdate staged"
+
+# With apply-live, the update is also applied to the booted deployment
+vm_ostreeupdate_create_noop v3
+vm_change_update_policy apply-live
+vm_rpmostree status > status.txt
+assert_file_has_content_literal status.txt 'AutomaticUpdates: apply-live; rpm-ostreed-automatic.timer: inactive'
+vm_rpmostree upgrade --trigger-automatic-update-policy > upgrade.txt
+assert_file_has_content_literal upgrade.txt 'Update applied live.'
+vm_assert_status_jq ".deployments[0][\"staged\"]" \
+                    ".deployments[0][\"version\"] == \"v3\"" \
+                    ".deployments[1][\"booted\"]" \
+                    ".deployments[1][\"live-replaced\"] == .deployments[0][\"checksum\"]"
+echo "ok autoupdate apply-live"
+
+# Phased updates wait for their rollout to reach us, unless upgrading manually
+vm_ostreeupdate_create_noop v4
+vm_shell_inline_sysroot_rw <<EOF
+ostree commit --repo=$REMOTE_OSTREE -b vmcheck --tree=ref=vmcheck \
+  --keep-metadata=version --keep-metadata=rpmostree.rpmdb.pkglist \
+  --add-metadata=rpmostree.rollout.duration='uint64 31536000'
+EOF
+vm_change_update_policy stage
+vm_rpmostree upgrade --trigger-automatic-update-policy > upgrade.txt
+assert_file_has_content upgrade.txt 'Update deferred: rolled out to 0% of machines, this one is at [0-9]*%; expected '
+vm_assert_status_jq ".deployments[0][\"staged\"]" \
+                    ".deployments[0][\"version\"] == \"v3\"" \
+                    '.["cached-update"]["version"] == "v4"' \
+                    '.["cached-update"]["rollout-deferred"]'
+# Only its commit object was pulled
+v4rev=$(vm_rpmostree status --json | jq -r '.["cached-update"]["checksum"]')
+vm_cmd test -f /ostree/repo/state/${v4rev}.commitpartial
+vm_rpmostree status > status.txt
+assert_file_has_content status.txt 'Rollout: deferred; rolled out to 0% of machines'
+vm_rpmostree upgrade
+vm_assert_status_jq ".deployments[0][\"staged\"]" \
+                    ".deployments[0][\"version\"] == \"v4\""
+echo "ok autoupdate phased rollout"
+
+# Failures of automatic updates are reported, and make them back off
+vm_cmd ostree remote delete vmcheckmote
+vm_cmd ostree remote add vmcheckmote --no-gpg-verify http://nonexistent.invalid/
+cursor=$(vm_get_journal_cursor)
+if vm_rpmostree upgrade --trigger-automatic-update-policy; then
+  assert_not_reached "automatic update succeeded with an unreachable remote"
+fi
+vm_wait_content_after_cursor $cursor 'Automatic update failed (network error, 1 in a row)'
+vm_rpmostree status > status.txt
+assert_file_has_content status.txt 'LastFailure: .* (network error, 1 in a row): '
+vm_rpmostree upgrade --trigger-automatic-update-policy > upgrade.txt
+assert_file_has_content upgrade.txt 'Backing off after 1 consecutive failures of automatic updates'
+vm_cmd ostree remote delete vmcheckmote
+vm_cmd ostree remote add vmcheckmote --no-gpg-verify http://localhost:8888/
+vm_cmd rm /var/lib/rpm-ostree/autoupdate-failure.json
+vm_rpmostree upgrade --trigger-automatic-update-policy
+vm_rpmostree status > status.txt
+assert_not_file_has_content status.txt 'LastFailure:'
+echo "ok autoupdate failure backoff"
@@ -67,6 +67,11 @@ assert_file_has_content statusv.txt \
   "pkg-to-remove" \
   "pkg-to-replace" \
   "pkg-to-replace-archtrans"
+# the diff is computed and cached by the daemon
+vm_rpmostree status --json > status.json
+assert_jq status.json \
+  '.deployments[0]["pending-rpm-diff"]["added"] | length == 4' \
+  '.deployments[1]["pending-rpm-diff"] == null'
 echo "ok db diff in status"
 
 booted_csum=$(vm_get_booted_csum)
@@ -86,6 +91,10 @@ assert_jq diff.json \
   '[.pkgdiff|map(select(.[1] == 0))[][0]]|index("pkg-to-remove") >= 0' \
   '[.pkgdiff|map(select(.[1] == 0))[][0]]|index("pkg-to-replace") >= 0' \
   '[.pkgdiff|map(select(.[1] == 0))[][0]]|index("pkg-to-replace-archtrans") >= 0'
+vm_rpmostree db diff --format=json --changelogs $booted_csum $pending_csum > diff.json
+assert_jq diff.json \
+  '.changelogs | type == "object"' \
+  '.advisories != null'
 
 # check that it's the default behaviour without both args
 check_diff "" "" \
@@ -166,6 +175,83 @@ grep -A1 '^Upgraded:' diff.txt | grep 'pkg-to-replace 15-8 -> 15.4-4'
 grep -A1 '^Downgraded:' diff.txt | grep zzz-pkg-to-downgrade
 echo "ok db diff"
 
+vm_rpmostree db search 'pkg-to-replace*' > search.txt
+assert_file_has_content search.txt \
+  pkg-to-replace-15.4-4 \
+  'deployment 0'
+if vm_rpmostree db search 'does-not-exist*' 2>err.txt; then
+  assert_not_reached "Found packages matching does-not-exist*?"
+fi
+assert_file_has_content err.txt 'No packages matching'
+echo "ok db search"
+
+# only the booted and pending deployments are known here; the first pending
+# one was replaced without ever being booted
+vm_rpmostree db history pkg-to-replace > history.txt
+assert_file_has_content history.txt \
+  'deployment 0 (.*): introduced pkg-to-replace-15.4-4'
+vm_rpmostree db history pkg-to-overlay > history.txt
+assert_file_has_content history.txt \
+  'deployment 0 (.*): introduced pkg-to-overlay-1.0-1'
+if vm_rpmostree db history does-not-exist 2>err.txt; then
+  assert_not_reached "Found history for does-not-exist?"
+fi
+assert_file_has_content err.txt 'not found in any deployment'
+echo "ok db history"
+
+vm_rpmostree db export-manifest $pending_csum > manifest.txt
+assert_file_has_content manifest.txt \
+  '^# rpm-ostree package manifest v1$' \
+  "^# commit: ${pending_csum}$" \
+  '^pkg-to-replace-15-8.x86_64 [0-9a-f]\{64\}$'
+vm_rpmostree db export-manifest -o /tmp/booted-manifest.txt
+vm_rpmostree db compare-manifest /tmp/booted-manifest.txt > compare.txt
+assert_file_has_content compare.txt 'matches manifest'
+vm_rpmostree db export-manifest $pending_csum -o /tmp/manifest.txt
+if vm_rpmostree db compare-manifest /tmp/manifest.txt $pending_layered_csum > compare.txt 2>err.txt; then
+  assert_not_reached "Pending layered commit matches pending manifest?"
+fi
+assert_file_has_content compare.txt \
+  '^-pkg-to-remove-1.0-1.x86_64$' \
+  '^-pkg-to-replace-15-8.x86_64$' \
+  '^+pkg-to-overlay-1.0-1.x86_64$'
+assert_file_has_content err.txt 'differs from manifest'
+echo "ok db export-manifest and compare-manifest"
+
+vm_rpmostree db whatprovides -d 0 /usr/bin/pkg-to-overlay > whatprovides.txt
+assert_file_has_content whatprovides.txt pkg-to-overlay-1.0-1.x86_64
+vm_rpmostree db whatprovides -d 0 /bin/sh /usr/bin/pkg-to-replace > whatprovides.txt
+assert_file_has_content whatprovides.txt \
+  '^/bin/sh: bash-' \
+  '^/usr/bin/pkg-to-replace: pkg-to-replace-15.4-4'
+if vm_rpmostree db whatprovides /usr/bin/pkg-to-overlay 2>err.txt; then
+  assert_not_reached "Booted deployment has pkg-to-overlay?"
+fi
+assert_file_has_content err.txt 'not owned by any package'
+echo "ok db whatprovides"
+
+vm_rpmostree db verify -d 0 pkg-to-overlay > verify.txt
+assert_file_has_content verify.txt '0 unexpected changes'
+vm_cmd cp /etc/motd /etc/motd.bak
+vm_cmd 'echo modified >> /etc/motd'
+vm_rpmostree db verify setup > verify.txt
+assert_file_has_content verify.txt \
+  'Expected divergence:' \
+  'config .*/etc/motd \[digest\] (setup-'
+vm_cmd mv /etc/motd.bak /etc/motd
+if vm_rpmostree db verify nosuchpackage 2>err.txt; then
+  assert_not_reached "Verified a package which isn't installed?"
+fi
+assert_file_has_content err.txt 'nosuchpackage is not installed'
+echo "ok db verify"
+
+vm_rpmostree db changelogs > changelogs.txt
+assert_file_has_content changelogs.txt \
+  "^Changes from booted deployment (${booted_csum:0:10}) to pending deployment (${pending_layered_csum:0:10}):" \
+  '^Added:' \
+  'pkg-to-overlay-1.0-1.x86_64'
+echo "ok db changelogs"
+
 # this is a bit convoluted; basically, we prune the commit and only keep its
 # metadata to check that `db diff` is indeed using the rpmdb.pkglist metadata
 commit_path=$(get_obj_path /ostree/repo $pending_layered_csum commit)
@@ -133,6 +133,18 @@ vm_assert_status_jq ".deployments|length == 2" \
                     ".deployments[0][\"requested-local-packages\"]|length == 1"
 echo "ok offline local RPM install"
 
+vm_rpmostree cleanup -prmb
+vm_rpmostree install --download-only foobar
+# downloaded packages must survive garbage collection until they're deployed
+vm_rpmostree cleanup -b
+go_offline
+vm_rpmostree install --cache-only foobar
+go_online
+vm_assert_status_jq ".deployments|length == 2" \
+                    ".deployments[0][\"booted\"] == false" \
+                    ".deployments[0][\"requested-packages\"]|length == 1"
+echo "ok offline install after cleanup"
+
 # synthesize update with foobar and barbaz builtin
 pending=$(vm_get_deployment_info 0 checksum)
 $REMOTE_OSTREE pull-local /ostree/repo $pending
@@ -156,4 +168,55 @@ vm_assert_status_jq \
 go_online
 echo "ok offline upgrade with local RPM replacement"
 
+vm_rpmostree cleanup -p
+$REMOTE_OSTREE commit -b vmcheck --tree=ref=vmcheck
+vm_rpmostree upgrade --offline > out.txt
+assert_file_has_content_literal out.txt 'it will be applied in an offline update boot'
+vm_assert_status_jq ".deployments[0][\"booted\"] == true"
+vm_cmd readlink /etc/system-update > link.txt
+assert_file_has_content_literal link.txt /var/lib/rpm-ostree/system-update
+if vm_rpmostree upgrade --offline --cache-only; then
+  assert_not_reached "allowed --offline and --cache-only?"
+fi
+vm_rpmostree cleanup -p > out.txt
+assert_file_has_content_literal out.txt 'Cancelled pending offline update.'
+if vm_cmd test -L /etc/system-update; then
+  assert_not_reached "/etc/system-update still exists after cleanup -p"
+fi
+echo "ok offline update boot"
+
+if vm_rpmostree upgrade --from-local-rpms --cache-only; then
+  assert_not_reached "allowed --from-local-rpms and --cache-only?"
+fi
+# the packages of the base are also available from a local repo
+vm_send_inline /etc/yum.repos.d/vmcheck-local.repo <<EOF
+[vmcheck-local]
+name=vmcheck-local
+baseurl=file:///var/tmp/vmcheck/yumrepo
+gpgcheck=0
+EOF
+csum=$($REMOTE_OSTREE commit -b vmcheck --tree=ref=vmcheck \
+         --keep-metadata=rpmostree.rpmdb.pkglist)
+vm_rpmostree upgrade --from-local-rpms > out.txt
+assert_file_has_content out.txt 'Reassembling update from [0-9]* local package'
+vm_assert_status_jq \
+  "(.deployments[0][\"base-checksum\"] // .deployments[0][\"checksum\"]) == \"$csum\""
+vm_rpmostree cleanup -p
+echo "ok upgrade from local RPMs"
+
+csum=$($REMOTE_OSTREE commit -b vmcheck --tree=ref=vmcheck \
+         --keep-metadata=rpmostree.rpmdb.pkglist)
+vm_rpmostree upgrade --from-local-rpms --download-only > out.txt
+assert_file_has_content out.txt 'Reassembling update from [0-9]* local package'
+vm_assert_status_jq ".deployments|length == 1" \
+                    ".deployments[0][\"booted\"] == true"
+go_offline
+vm_rpmostree upgrade --cache-only
+go_online
+vm_assert_status_jq ".deployments|length == 2" \
+  "(.deployments[0][\"base-checksum\"] // .deployments[0][\"checksum\"]) == \"$csum\""
+vm_cmd rm -f /etc/yum.repos.d/vmcheck-local.repo
+vm_rpmostree cleanup -p
+echo "ok download-only upgrade from local RPMs"
+
 vm_stop_httpd vmcheck
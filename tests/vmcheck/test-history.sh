@@ -39,14 +39,23 @@ echo "ok setup"
 vm_rpmostree ex history > out.txt
 assert_file_has_content out.txt "CreateCommand: install foo"
 assert_file_has_content out.txt "LayeredPackages: foo"
+assert_file_has_content out.txt "LayeredPackages: +foo"
+assert_file_has_content out.txt "Diff: 1 added"
 vm_rpmostree ex history --json | jq . --slurp > out.json
 assert_jq out.json \
   '.[0]["deployment-create-command-line"] == "install foo"' \
   '.[0]["deployment-create-timestamp"] != null' \
   '.[0]["deployment"]["packages"][0] == "foo"' \
+  '.[0]["deployment"]["history-diff"] == null' \
+  '.[0]["deployment"]["base-commit-meta"]["rpmostree.rpmdb.pkglist"] | length > 0' \
   '.[0]["first-boot-timestamp"] != null' \
   '.[0]["last-boot-timestamp"] != null' \
-  '.[0]["boot-count"] == 1'
+  '.[0]["boot-count"] == 1' \
+  '.[0]["diff"]["checksum"] != null' \
+  '.[0]["diff"]["requests"]["requested-packages"]["added"] == ["foo"]' \
+  '.[0]["diff"]["requests"]["requested-packages"]["removed"] == []' \
+  '.[0]["diff"]["rpm-diff"]["added"] == [[1, "foo", "1.0-1", "x86_64"]]' \
+  '.[0]["diff"]["rpm-diff"]["removed"] == []'
 echo "ok install first boot"
 
 vm_reboot
@@ -74,7 +83,9 @@ assert_jq out.json \
   '.[0]["deployment-create-timestamp"] != null' \
   '.[0]["first-boot-timestamp"] != null' \
   '.[0]["last-boot-timestamp"] != null' \
-  '.[0]["boot-count"] == 1'
+  '.[0]["boot-count"] == 1' \
+  '.[0]["diff"]["requests"]["requested-packages"]["removed"] == ["foo"]' \
+  '.[0]["diff"]["rpm-diff"]["removed"] == [[1, "foo", "1.0-1", "x86_64"]]'
 assert_jq out.json \
   '.[1]["deployment-create-command-line"] == "install foo"' \
   '.[1]["deployment-create-timestamp"] != null' \
@@ -103,3 +114,43 @@ if [ $(wc -l entries.txt) != 1 ]; then
   assert_not_reached "Expected only 1 entry, got $(cat entries.txt)"
 fi
 echo "ok prune"
+
+# A deployment failing its health check is rolled back automatically
+vm_cmd "echo AutomaticRollbackBoots=1 >> /etc/rpm-ostreed.conf"
+vm_send_inline /etc/systemd/system/failing-check.service <<'EOF2'
+[Unit]
+ConditionPathExists=/usr/bin/bar
+Before=boot-complete.target
+[Service]
+Type=oneshot
+ExecStart=/bin/false
+[Install]
+RequiredBy=boot-complete.target
+EOF2
+# The boot trial units are enabled statically
+vm_cmd test -L /usr/lib/systemd/system/multi-user.target.wants/rpm-ostree-boot-trial.service
+vm_cmd systemctl enable failing-check.service
+# trust the booted deployment
+vm_cmd rpm-ostree boot-trial begin
+good_csum=$(vm_get_booted_csum)
+vm_build_rpm bar
+vm_rpmostree install bar
+vm_reboot
+vm_cmd systemctl is-active rpm-ostree-boot-complete.service && \
+  assert_not_reached "Failed boot completed?"
+vm_cmd journalctl -u rpm-ostree-boot-trial.service > out.txt
+assert_file_has_content out.txt 'is on trial: boot 1 of 1'
+# the next boot rolls back and reboots again
+vm_reboot
+bootid=$(vm_get_boot_id)
+vm_ssh_wait 240 $bootid
+if [ "$(vm_get_booted_csum)" != "$good_csum" ]; then
+  assert_not_reached "Failed deployment not rolled back"
+fi
+vm_rpmostree ex history > out.txt
+assert_file_has_content out.txt 'BootFailure: boot-complete.target not reached in 1 boot(s); failed units: failing-check.service'
+vm_rpmostree ex history --json | jq . --slurp > out.json
+assert_jq out.json 'map(select(.["boot-failure"])) | .[0]["boot-failure"]["failed-boots"] == 1'
+vm_cmd rm /etc/systemd/system/failing-check.service
+vm_cmd sed -i /AutomaticRollbackBoots/d /etc/rpm-ostreed.conf
+echo "ok automatic rollback"
@@ -74,6 +74,36 @@ fi
 assert_file_has_content err.txt "already.*enabled"
 echo "ok initramfs enabled"
 
+# the initramfs is only regenerated when its inputs changed
+vm_rpmostree kargs --append=rpmostree-initramfs-testing > kargs.txt
+assert_file_has_content kargs.txt "Initramfs inputs unchanged; reusing initramfs"
+assert_not_file_has_content kargs.txt "Generating initramfs"
+vm_cmd touch /etc/dracut.conf.d/rpmostree-initramfs-testing.conf
+vm_rpmostree kargs --delete=rpmostree-initramfs-testing > kargs.txt
+assert_file_has_content kargs.txt "Generating initramfs"
+assert_not_file_has_content kargs.txt "reusing initramfs"
+vm_cmd rm -f /etc/dracut.conf.d/rpmostree-initramfs-testing.conf
+vm_rpmostree cleanup -p
+echo "ok initramfs reuse"
+
+# regenerating the initramfs is reproducible, and its inputs are recorded
+kver=$(vm_cmd uname -r)
+vm_cmd cat /usr/lib/modules/$kver/rpmostree-initramfs-inputs.json > inputs.json
+assert_jq inputs.json \
+  ".kver == \"$kver\"" \
+  '.["source-date-epoch"] == "0"' \
+  '.files["/etc/dracut.conf.d"]' \
+  '.files["/usr/lib/modules/'$kver'/vmlinuz"]'
+vm_rpmostree initramfs --enable --arg=-v
+root=$(vm_get_deployment_root 0)
+csum1=$(vm_cmd sha256sum $root/usr/lib/modules/$kver/initramfs.img | cut -d' ' -f1)
+vm_rpmostree initramfs --enable --arg=-v
+root=$(vm_get_deployment_root 0)
+csum2=$(vm_cmd sha256sum $root/usr/lib/modules/$kver/initramfs.img | cut -d' ' -f1)
+assert_streq "$csum1" "$csum2"
+vm_rpmostree cleanup -p
+echo "ok initramfs reproducible"
+
 vm_rpmostree initramfs --disable > initramfs.txt
 assert_file_has_content initramfs.txt "Initramfs regeneration.*disabled"
 vm_rpmostree initramfs > initramfs.txt
@@ -137,3 +167,93 @@ for x in passwd group; do
 done
 rm -f lsinitrd.txt
 echo "ok initramfs has passwd"
+
+vm_rpmostree initramfs --add-module=qemu --omit-module=plymouth --add-driver=nvme > initramfs.txt
+assert_file_has_content initramfs.txt "Initramfs regeneration is now: enabled"
+vm_rpmostree initramfs > initramfs.txt
+assert_file_has_content initramfs.txt "Initramfs added modules: qemu"
+assert_file_has_content initramfs.txt "Initramfs omitted modules: plymouth"
+assert_file_has_content initramfs.txt "Initramfs added drivers: nvme"
+vm_assert_status_jq \
+  '.deployments[0]["regenerate-initramfs"]' \
+  '.deployments[0]["initramfs-add-modules"] == ["qemu"]' \
+  '.deployments[0]["initramfs-omit-modules"] == ["plymouth"]' \
+  '.deployments[0]["initramfs-add-drivers"] == ["nvme"]'
+vm_rpmostree status > status.txt
+assert_file_has_content status.txt "InitramfsAddModules: qemu"
+if vm_rpmostree initramfs --add-module="foo bar" 2>err.txt; then
+    assert_not_reached "invalid module name worked?"
+fi
+assert_file_has_content err.txt "Invalid dracut module name: foo bar"
+if vm_rpmostree initramfs --remove-driver=e1000 2>err.txt; then
+    assert_not_reached "removing unconfigured driver worked?"
+fi
+assert_file_has_content err.txt "Driver not configured: e1000"
+vm_reboot
+vm_cmd cat /usr/lib/dracut/dracut.conf.d/50-rpm-ostree.conf > dracut-conf.txt
+assert_file_has_content_literal dracut-conf.txt 'add_dracutmodules+=" qemu "'
+assert_file_has_content_literal dracut-conf.txt 'omit_dracutmodules+=" plymouth "'
+assert_file_has_content_literal dracut-conf.txt 'add_drivers+=" nvme "'
+initramfs=$(vm_cmd grep ^initrd /boot/loader/entries/ostree-2-$osname.conf | sed -e 's,initrd ,/boot/,')
+vm_cmd lsinitrd $initramfs > lsinitrd.txt
+assert_file_has_content lsinitrd.txt '^qemu$'
+assert_not_file_has_content lsinitrd.txt '^plymouth$'
+echo "ok initramfs dracut modules and drivers"
+
+vm_rpmostree initramfs --remove-module=qemu --remove-module=plymouth --remove-driver=nvme
+vm_assert_status_jq \
+  '.deployments[0]["regenerate-initramfs"]' \
+  '.deployments[0]["initramfs-add-modules"]|length == 0' \
+  '.deployments[0]["initramfs-add-drivers"]|length == 0'
+vm_rpmostree initramfs --disable
+vm_rpmostree cleanup -p
+echo "ok initramfs remove dracut modules and drivers"
+
+vm_shell_inline <<'EOF'
+mkdir -p /etc/rpm-ostree/initramfs-generators.d
+cat > /etc/rpm-ostree/initramfs-generators.d/custom.conf <<EOF2
+[initramfs-generator]
+command=dracut --no-hostonly --add ostree --include /etc/os-release /custom-generator -f "\$INITRAMFS" --kver "\$KVER"
+EOF2
+EOF
+if vm_rpmostree initramfs --generator=custom 2>err.txt; then
+    assert_not_reached "--generator without --enable worked?"
+fi
+assert_file_has_content err.txt "--generator must be used with --enable"
+if vm_rpmostree initramfs --enable --generator=missing 2>err.txt; then
+    assert_not_reached "missing generator worked?"
+fi
+assert_file_has_content err.txt "Initramfs generator not found: missing"
+vm_rpmostree initramfs --enable --generator=custom
+vm_rpmostree initramfs > initramfs.txt
+assert_file_has_content initramfs.txt "Initramfs generator: custom"
+vm_assert_status_jq '.deployments[0]["initramfs-generator"] == "custom"'
+vm_rpmostree status > status.txt
+assert_file_has_content status.txt "InitramfsGenerator: custom"
+if vm_rpmostree initramfs --enable --generator=custom 2>err.txt; then
+    assert_not_reached "setting the same generator worked?"
+fi
+assert_file_has_content err.txt "Initramfs generator is already custom"
+vm_reboot
+initramfs=$(vm_cmd grep ^initrd /boot/loader/entries/ostree-2-$osname.conf | sed -e 's,initrd ,/boot/,')
+vm_cmd lsinitrd $initramfs > lsinitrd.txt
+assert_file_has_content lsinitrd.txt 'custom-generator'
+vm_rpmostree initramfs --enable --generator=dracut
+vm_assert_status_jq '.deployments[0]["initramfs-generator"]|not'
+vm_rpmostree initramfs --disable
+vm_rpmostree cleanup -p
+vm_cmd rm -rf /etc/rpm-ostree/initramfs-generators.d
+echo "ok initramfs generator"
+
+vm_rpmostree ex fips enable
+vm_rpmostree status > status.txt
+assert_file_has_content_literal status.txt 'FIPS: enabled'
+assert_file_has_content_literal status.txt 'InitramfsAddModules: fips'
+vm_rpmostree kargs --deploy-index=0 > kargs.txt
+assert_file_has_content_literal kargs.txt 'fips=1'
+if vm_rpmostree ex fips enable 2>err.txt; then
+    assert_not_reached "enabled FIPS mode twice"
+fi
+assert_file_has_content_literal err.txt 'FIPS mode is already enabled'
+vm_rpmostree cleanup -p
+echo "ok fips enable"
@@ -190,11 +190,147 @@ assert_streq $rc 77
 vm_rpmostree kargs > kargs.txt
 assert_not_file_has_content_literal kargs.txt 'nonexisting'
 assert_not_file_has_content_literal kargs.txt 'editorkey=editorvalue'
-EDITOR="sed -i 's/$/ editorkey=editorvalue/'" vm_rpmostree kargs --editor
+EDITOR="sed -i '1a editorkey=editorvalue'" vm_rpmostree kargs --editor > editor.txt
+assert_file_has_content_literal editor.txt '+editorkey=editorvalue'
 vm_rpmostree kargs > kargs.txt
 assert_file_has_content_literal kargs.txt 'editorkey=editorvalue'
 echo "ok kargs editor"
 
+# The result of the editor is validated
+if EDITOR="sed -i '1a editorkey=\\\"unbalanced'" vm_rpmostree kargs --editor 2>err.txt; then
+  assert_not_reached "kargs --editor with unbalanced quotes succeeded"
+fi
+assert_file_has_content_literal err.txt 'Unbalanced quotes on line'
+if EDITOR="sed -i '1a editorkey=editorvalue'" vm_rpmostree kargs --editor 2>err.txt; then
+  assert_not_reached "kargs --editor with a duplicate argument succeeded"
+fi
+assert_file_has_content_literal err.txt 'Kernel argument editorkey=editorvalue is given twice'
+EDITOR="sed -i '1a editorkey=othervalue'" vm_rpmostree kargs --editor > editor.txt 2>err.txt
+assert_file_has_content_literal err.txt 'Key editorkey is given multiple times with different values'
+assert_file_has_content_literal editor.txt '+editorkey=othervalue'
+EDITOR="sed -i '/^editorkey=othervalue$/d'" vm_rpmostree kargs --editor > editor.txt
+assert_file_has_content_literal editor.txt '-editorkey=othervalue'
+vm_rpmostree kargs > kargs.txt
+assert_not_file_has_content_literal kargs.txt 'editorkey=othervalue'
+echo "ok kargs editor validation"
+
+# Obviously broken kernel arguments are refused unless forced
+if vm_rpmostree kargs --append=root=/dev/nonexistent > out.txt 2>err.txt; then
+  assert_not_reached "appended a second root="
+fi
+assert_file_has_content_literal out.txt '+root=/dev/nonexistent'
+assert_file_has_content_literal err.txt 'root= is given multiple times'
+assert_file_has_content_literal err.txt 'use --force to apply anyway'
+vm_rpmostree kargs --append=root=/dev/nonexistent --force > out.txt
+assert_file_has_content_literal out.txt 'warning: root= is given multiple times'
+vm_rpmostree kargs > kargs.txt
+assert_file_has_content_literal kargs.txt 'root=/dev/nonexistent'
+vm_rpmostree cleanup -p
+echo "ok kargs validation"
+
+# Changes are reapplied on top of the kernel arguments of the new base when deploying it
+vm_rpmostree kargs --append=mitigations=off --delete=editorkey=editorvalue
+vm_cmd mkdir -p /var/tmp/kargs-base/usr/lib/bootc/kargs.d
+vm_cmd "echo 'kargs = [\"mitigations=auto\", \"basekey=basevalue\"] # from the base' > \
+  /var/tmp/kargs-base/usr/lib/bootc/kargs.d/10-base.toml"
+vm_cmd "printf 'kargs = [\"otherarch\"]\nmatch-architectures = [\"nonexistent\"]\n' > \
+  /var/tmp/kargs-base/usr/lib/bootc/kargs.d/20-otherarch.toml"
+vm_cmd ostree commit -b vmcheck_tmp/kargs --fsync=no --tree=ref=$(vm_get_booted_csum) \
+  --tree=dir=/var/tmp/kargs-base
+vm_rpmostree rebase :vmcheck_tmp/kargs > rebase.txt
+assert_file_has_content_literal rebase.txt \
+  'Kernel argument conflict: mitigations=off replaces mitigations=auto provided by the new base'
+vm_rpmostree kargs > kargs.txt
+assert_file_has_content_literal kargs.txt 'mitigations=off'
+assert_file_has_content_literal kargs.txt 'basekey=basevalue'
+assert_not_file_has_content_literal kargs.txt 'mitigations=auto'
+assert_not_file_has_content_literal kargs.txt 'editorkey=editorvalue'
+assert_not_file_has_content_literal kargs.txt 'otherarch'
+echo "ok kargs reapplied when rebasing"
+
+# And on upgrades
+vm_cmd "echo 'kargs = [\"basekey=newvalue\"]' > /var/tmp/kargs-base/usr/lib/bootc/kargs.d/10-base.toml"
+vm_cmd ostree commit -b vmcheck_tmp/kargs --fsync=no --tree=ref=$(vm_get_booted_csum) \
+  --tree=dir=/var/tmp/kargs-base
+vm_rpmostree upgrade > upgrade.txt
+assert_not_file_has_content_literal upgrade.txt 'Kernel argument conflict'
+vm_rpmostree kargs > kargs.txt
+assert_file_has_content_literal kargs.txt 'mitigations=off'
+assert_file_has_content_literal kargs.txt 'basekey=newvalue'
+assert_not_file_has_content_literal kargs.txt 'basekey=basevalue'
+vm_rpmostree cleanup -p
+vm_cmd rm -rf /var/tmp/kargs-base
+echo "ok kargs reapplied when upgrading"
+
+# Named profiles
+vm_cmd mkdir -p /etc/rpm-ostree/kargs-profiles.d
+vm_cmd "echo 'profilekey=1 quiet # debugging' > /etc/rpm-ostree/kargs-profiles.d/testprofile.kargs"
+vm_rpmostree kargs --append-if-missing=quiet
+vm_rpmostree kargs --enable-profile=testprofile
+vm_rpmostree kargs > kargs.txt
+assert_file_has_content_literal kargs.txt 'profilekey=1'
+vm_rpmostree status > status.txt
+assert_file_has_content_literal status.txt 'KernelArgProfiles: testprofile'
+if vm_rpmostree kargs --enable-profile=testprofile 2>err.txt; then
+  assert_not_reached "enabled testprofile twice"
+fi
+assert_file_has_content_literal err.txt 'Kernel argument profile is already enabled: testprofile'
+if vm_rpmostree kargs --enable-profile=nonexistent 2>err.txt; then
+  assert_not_reached "enabled a nonexistent profile"
+fi
+assert_file_has_content_literal err.txt 'Kernel argument profile not found: nonexistent'
+# The arguments it appended are deleted, even if its definition changed
+vm_cmd "echo 'otherkey=1' > /etc/rpm-ostree/kargs-profiles.d/testprofile.kargs"
+vm_rpmostree kargs --disable-profile=testprofile
+vm_rpmostree kargs > kargs.txt
+assert_not_file_has_content_literal kargs.txt 'profilekey=1'
+assert_file_has_content_literal kargs.txt 'quiet'
+vm_rpmostree status > status.txt
+assert_not_file_has_content_literal status.txt 'KernelArgProfiles'
+vm_cmd rm -rf /etc/rpm-ostree/kargs-profiles.d
+echo "ok kargs profiles"
+
+# Profiles are read from the deployment being changed, here the pending one
+vm_cmd mkdir -p /var/tmp/kargs-base/usr/lib/rpm-ostree/kargs-profiles.d
+vm_cmd "echo 'baseprofilekey=1' > \
+  /var/tmp/kargs-base/usr/lib/rpm-ostree/kargs-profiles.d/baseprofile.kargs"
+vm_cmd ostree commit -b vmcheck_tmp/kargs-profiles --fsync=no \
+  --tree=ref=$(vm_get_booted_csum) --tree=dir=/var/tmp/kargs-base
+vm_rpmostree rebase :vmcheck_tmp/kargs-profiles
+vm_rpmostree kargs --enable-profile=baseprofile
+vm_rpmostree kargs > kargs.txt
+assert_file_has_content_literal kargs.txt 'baseprofilekey=1'
+vm_rpmostree cleanup -p
+vm_cmd rm -rf /var/tmp/kargs-base
+echo "ok kargs profiles of the pending deployment"
+
+# Global kernel arguments are changed in place, in all the deployments
+vm_rpmostree kargs --append=localkey=1
+vm_rpmostree kargs --global --append=globalkey=1
+vm_rpmostree kargs --global > kargs.txt
+assert_file_has_content_literal kargs.txt 'globalkey=1'
+for index in 0 1; do
+  vm_rpmostree kargs --deploy-index=${index} > kargs.txt
+  assert_file_has_content_literal kargs.txt 'globalkey=1'
+done
+vm_rpmostree kargs --show-layers > layers.txt
+assert_file_has_content layers.txt '^global *globalkey=1$'
+assert_file_has_content layers.txt '^local *localkey=1$'
+assert_file_has_content layers.txt '^ostree *ostree='
+vm_rpmostree status > status.txt
+assert_file_has_content_literal status.txt 'GlobalKernelArgs: globalkey=1'
+if vm_rpmostree kargs --global --delete=localkey=1 2>err.txt; then
+  assert_not_reached "deleted a non-global kernel argument"
+fi
+assert_file_has_content_literal err.txt 'Kernel argument is not global: localkey=1'
+vm_rpmostree kargs --global --delete=globalkey=1
+vm_rpmostree kargs --deploy-index=1 > kargs.txt
+assert_not_file_has_content_literal kargs.txt 'globalkey=1'
+vm_rpmostree status > status.txt
+assert_not_file_has_content_literal status.txt 'GlobalKernelArgs'
+vm_rpmostree cleanup -p
+echo "ok global kargs"
+
 # XXX: uncomment this when we migrate CI to FCOS
 # # And reset this bit
 # vm_cmd ostree config --repo /sysroot/ostree/repo set sysroot.readonly false
@@ -169,6 +169,12 @@ vm_rpmostree install foo-1.0 --unchanged-exit-77 > foo-install.txt
 assert_file_has_content_literal foo-install.txt 'Checking out packages...done'
 echo "ok install not on a tty"
 
+# We solved this exact request against the same metadata when first adding foo
+assert_file_has_content_literal foo-install.txt 'Resolving dependencies...done (cached)'
+vm_cmd find /var/cache/rpm-ostree/depsolve -name '*.json' > depsolve-cache.txt
+assert_file_has_content depsolve-cache.txt '\.json$'
+echo "ok depsolve cache"
+
 # check that by default we diff booted vs pending
 vm_rpmostree db diff --format=diff > out.txt
 assert_file_has_content out.txt +foo-1.0
@@ -205,6 +211,10 @@ echo "ok correct output"
 booted_csum=$(vm_get_booted_csum)
 vm_cmd ostree ls $booted_csum /usr/share/rpm > out.txt
 assert_not_file_has_content out.txt __db
+# nor from the incremental update of the rpmdb in the target's format
+vm_cmd ostree ls $booted_csum /usr/share > out.txt
+assert_not_file_has_content out.txt rpm.rpmostree-
+vm_cmd rpm -q foo
 echo "ok no leftover rpmdb files"
 
 # upgrade to a layer with foo already builtin
@@ -290,3 +300,10 @@ vm_assert_status_jq '.deployments|length == 2'
 vm_rpmostree cleanup -pr
 vm_assert_status_jq '.deployments|length == 1'
 echo "ok cleanup"
+
+# the layering operations above should have recorded how long they took
+vm_cmd cat /var/lib/rpm-ostree/timings.json > timings.json
+assert_jq timings.json \
+    '.operations.import|length > 0' \
+    '.operations.checkout|length > 0'
+echo "ok timings recorded"
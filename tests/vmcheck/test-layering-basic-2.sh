@@ -149,3 +149,23 @@ vm_assert_status_jq \
   '.deployments[0]["requested-packages"]|length == 1' \
   '.deployments[0]["requested-local-packages"]|length == 0'
 echo "ok uninstall --all --install <pkg>"
+
+# Adding packages to a staged deployment builds on its tree
+vm_rpmostree cleanup -p
+vm_build_rpm test-amend-pkg1
+vm_build_rpm test-amend-pkg2
+vm_rpmostree install test-amend-pkg1
+vm_rpmostree install test-amend-pkg2 |& tee out.txt
+assert_file_has_content out.txt "Amending staged deployment"
+vm_assert_status_jq \
+  '.deployments[0]["staged"]' \
+  '.deployments[0]["packages"]|index("test-amend-pkg1") >= 0' \
+  '.deployments[0]["packages"]|index("test-amend-pkg2") >= 0'
+vm_cmd rpm-ostree db list $(vm_get_pending_csum) > out.txt
+assert_file_has_content out.txt test-amend-pkg1
+assert_file_has_content out.txt test-amend-pkg2
+# Removing a package needs a fresh tree
+vm_rpmostree uninstall test-amend-pkg1 |& tee out.txt
+assert_not_file_has_content out.txt "Amending staged deployment"
+vm_rpmostree cleanup -p
+echo "ok amend staged deployment"
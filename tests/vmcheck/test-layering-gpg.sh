@@ -35,3 +35,20 @@ if vm_rpmostree pkg-add foo-4.5 2>err.txt; then
 fi
 assert_file_has_content err.txt 'cannot be verified'
 echo "ok failed to install unsigned package"
+
+vm_cmd sed -i s/^VerifyLocalPackages=false/VerifyLocalPackages=true/ /etc/rpm-ostreed.conf
+vm_rpmostree reload
+YUMREPO=/var/tmp/vmcheck/yumrepo/packages/x86_64
+if vm_rpmostree install $YUMREPO/foo-4.5-6.x86_64.rpm 2>err.txt; then
+    assert_not_reached "Installed unsigned local package"
+fi
+assert_file_has_content err.txt 'Package is not signed'
+assert_file_has_content err.txt 'allow-unverified-local'
+vm_rpmostree install --allow-unverified-local $YUMREPO/foo-4.5-6.x86_64.rpm
+vm_assert_status_jq '.deployments[0]["unverified-local-packages"] == ["foo-4.5-6.x86_64"]'
+vm_rpmostree status > status.txt
+assert_file_has_content status.txt 'UnverifiedPackages: foo-4.5-6.x86_64'
+vm_rpmostree cleanup -p
+vm_cmd sed -i s/^VerifyLocalPackages=true/VerifyLocalPackages=false/ /etc/rpm-ostreed.conf
+vm_rpmostree reload
+echo "ok local package signature policy"
@@ -71,9 +71,13 @@ echo "ok upgrade selinux pkg"
 # we do this by just baking in a layered RPM that recompiles the policy
 vm_rpmostree cleanup -p
 vm_build_selinux_rpm baz-selinux /usr/bin/baz install_exec_t
-vm_rpmostree install baz-selinux
+vm_rpmostree install baz-selinux > install.txt
+# the policy changed, so the whole tree was relabeled
+assert_file_has_content install.txt 'Relabeled tree for new SELinux policy in'
 se_csum=$(vm_cmd ostree checksum /usr/etc/selinux/targeted/policy/policy.*)
 root=$(vm_get_deployment_root 0)
+assert_actual_label $root/usr/bin/bash shell_exec_t
+assert_actual_label $root/usr/lib/systemd/systemd init_exec_t
 se_new_csum=$(vm_cmd ostree checksum $root/usr/etc/selinux/targeted/policy/policy.*)
 assert_not_streq "$se_csum" "$se_new_csum"
 csum=$(vm_get_deployment_info 0 checksum)
@@ -227,6 +227,29 @@ vm_wait_content_after_cursor "${cursor}" "Txn.*failed.*Running %post for post-th
 vm_cmd systemctl restart rpm-ostreed
 echo "ok cancel infinite post via `rpm-ostree cancel`"
 
+# Stopping the daemon cancels the transaction and waits for it
+cursor=$(vm_get_journal_cursor)
+background_install_post_that_hangs "${cursor}"
+vm_cmd systemctl stop rpm-ostreed
+vm_wait_content_after_cursor "${cursor}" "Txn.*failed.*Running %post for post-that-hangs"
+echo "ok cancel infinite post via daemon stop"
+
+# Killing the daemon outright leaves the transaction state behind, which is
+# cleaned up when it starts again
+cursor=$(vm_get_journal_cursor)
+background_install_post_that_hangs "${cursor}"
+vm_cmd systemctl kill --signal=KILL rpm-ostreed
+vm_cmd systemctl stop vmcheck-install-hang || true
+vm_cmd test -f /var/lib/rpm-ostree/txn-inprogress.gv
+vm_rpmostree status
+vm_wait_content_after_cursor "${cursor}" \
+  "Recovered from interrupted txn UpdateDeployment.*deleted rootfs checkout"
+vm_cmd test ! -f /var/lib/rpm-ostree/txn-inprogress.gv
+vm_cmd rpm-ostree audit-log -n 1 > audit.txt
+assert_file_has_content audit.txt 'UpdateDeployment interrupted'
+assert_file_has_content audit.txt 'Recovery: .*deleted rootfs checkout'
+echo "ok recover from killed daemon"
+
 # Test rm -rf /!
 vm_cmd touch /home/core/somedata /tmp/sometmpfile /var/tmp/sometmpfile
 vm_build_rpm rmrf post "rm --no-preserve-root -rf / &>/dev/null || true"
@@ -178,6 +178,31 @@ vm_rpmostree cleanup -p
 vm_assert_status_jq ".deployments|length == 1"
 echo "ok unpin"
 
+vm_rpmostree pin booted --reason "known-good" --expires 30d > pin.txt
+assert_file_has_content_literal pin.txt 'is now pinned until'
+vm_rpmostree status > status.txt
+assert_file_has_content_literal status.txt "Pinned: yes; known-good (until "
+vm_assert_status_jq '.deployments[0]["pinned"] == true' \
+                    '.deployments[0]["pin-reason"] == "known-good"'
+vm_rpmostree pin --unpin booted > pin.txt
+assert_file_has_content_literal pin.txt 'is now unpinned'
+vm_assert_status_jq '.deployments[0]["pinned"] == false' \
+                    '.deployments[0]["pin-reason"] == null'
+vm_cmd test ! -f /var/lib/rpm-ostree/pins.json
+echo "ok pin with reason"
+
+vm_rpmostree install bar
+vm_rpmostree pin 0 --expires 1h
+vm_rpmostree cleanup -p
+vm_assert_status_jq ".deployments|length == 2"
+# pretend the pin expired
+vm_cmd "sed -i -e 's/\"expires\":[0-9]*/\"expires\":1/' /var/lib/rpm-ostree/pins.json"
+vm_rpmostree cleanup -p > out.txt
+assert_file_has_content_literal out.txt 'Released expired pin of deployment'
+vm_assert_status_jq ".deployments|length == 1"
+vm_cmd test ! -f /var/lib/rpm-ostree/pins.json
+echo "ok pin expiry"
+
 # https://github.com/ostreedev/ostree/pull/1055
 vm_cmd ostree commit -b vmcheck --tree=ref=vmcheck --timestamp=\"October 25 1985\"
 if vm_rpmostree upgrade 2>err.txt; then
@@ -377,3 +402,216 @@ vm_rpmostree upgrade 2>err.txt
 assert_not_file_has_content err.txt 'Updates and deployments are driven by OtherTestDriver'
 vm_rpmostree cleanup -p
 echo "ok upgrade without --bypass-driver when same systemd unit"
+
+# The retention policy keeps more rollback deployments than the default, and prunes those
+# beyond the configured number
+vm_cmd "echo 'KeepRollbackDeployments=2' >> /etc/rpm-ostreed.conf"
+vm_rpmostree reload
+for i in 1 2 3; do
+  vm_rpmostree kargs --append=retention=${i}
+  vm_reboot
+done
+vm_rpmostree cleanup --retention
+vm_assert_status_jq '.deployments|length == 3' \
+                    '.deployments[0]["booted"]'
+# The rollbacks are ordered from the most recent one
+vm_rpmostree kargs --deploy-index=1 > out.txt
+assert_file_has_content_literal out.txt 'retention=2'
+assert_not_file_has_content_literal out.txt 'retention=3'
+vm_rpmostree kargs --deploy-index=2 > out.txt
+assert_not_file_has_content_literal out.txt 'retention=2'
+vm_rpmostree cleanup --retention > out.txt
+assert_file_has_content_literal out.txt 'No deployments to remove per the retention policy.'
+# Finalizing the staged deployment would drop the rollbacks but the booted one otherwise
+vm_rpmostree kargs --append=retention=4
+vm_assert_status_jq '.deployments[1]["booted"]' \
+                    '.deployments[2]["retained"]' \
+                    '.deployments[2]["pin-reason"] == null' \
+                    '.deployments[3]["pinned"] == false'
+vm_cmd test -f /var/lib/rpm-ostree/retained-rollbacks.json
+vm_rpmostree status > out.txt
+assert_file_has_content_literal out.txt 'Retained: yes; rollback kept per KeepRollbackDeployments'
+vm_rpmostree cleanup -p
+vm_assert_status_jq '.deployments|length == 3' \
+                    '.deployments[1]["pinned"] == false' \
+                    '.deployments[1]["retained"] == null'
+vm_cmd test ! -f /var/lib/rpm-ostree/retained-rollbacks.json
+vm_cmd "sed -i -e '/^KeepRollbackDeployments=/d' /etc/rpm-ostreed.conf"
+vm_rpmostree reload
+echo "ok retention policy"
+
+# Roll back to an arbitrary deployment; the retention test left three of the same commit
+serial=$(vm_get_deployment_info 2 serial)
+vm_rpmostree rollback --to 2
+vm_assert_status_jq ".deployments[0][\"serial\"] == ${serial}" \
+                    '.deployments[1]["booted"]'
+vm_rpmostree rollback --to 0 > out.txt
+assert_file_has_content out.txt 'is already the default'
+if vm_rpmostree rollback --to $(vm_get_booted_csum) 2>err.txt; then
+  assert_not_reached "rolled back to ambiguous checksum?"
+fi
+assert_file_has_content err.txt 'Multiple deployments match'
+if vm_rpmostree rollback --to nosuchversion 2>err.txt; then
+  assert_not_reached "rolled back to nonexistent version?"
+fi
+assert_file_has_content err.txt "No deployment matching 'nosuchversion'"
+vm_rpmostree rollback --to 1
+vm_assert_status_jq '.deployments[0]["booted"]'
+echo "ok rollback --to"
+
+# Update hooks run around new deployments, and can abort them
+vm_build_rpm hooktest
+vm_cmd mkdir -p /etc/rpm-ostree/hooks.d
+vm_send_inline /etc/rpm-ostree/hooks.d/10-log.hook <<'EOF2'
+[Hook]
+When=pre-stage
+Exec=/bin/sh -c 'echo "$RPMOSTREE_HOOK $RPMOSTREE_AUTOMATIC" >> /run/rpm-ostree-hooks.log'
+EOF2
+vm_send_inline /etc/rpm-ostree/hooks.d/20-log.hook <<'EOF2'
+[Hook]
+When=post-stage
+Exec=/bin/sh -c 'echo "$RPMOSTREE_HOOK $RPMOSTREE_CHECKSUM" >> /run/rpm-ostree-hooks.log'
+EOF2
+vm_rpmostree install hooktest > out.txt
+assert_file_has_content_literal out.txt 'Running pre-stage hook 10-log.hook' \
+  'Running post-stage hook 20-log.hook'
+vm_cmd cat /run/rpm-ostree-hooks.log > hooks.txt
+assert_file_has_content hooks.txt '^pre-stage 0$' "^post-stage $(vm_get_pending_csum)$"
+vm_send_inline /etc/rpm-ostree/hooks.d/30-smoke.hook <<'EOF2'
+[Hook]
+When=post-stage
+Exec=/bin/false
+EOF2
+if vm_rpmostree uninstall hooktest 2>err.txt; then
+  assert_not_reached "Deployment succeeded despite failed hook?"
+fi
+assert_file_has_content_literal err.txt 'post-stage hook 30-smoke.hook'
+vm_assert_status_jq '.deployments[0]["booted"]'
+vm_cmd "echo OnFailure=ignore >> /etc/rpm-ostree/hooks.d/30-smoke.hook"
+vm_rpmostree install hooktest > out.txt
+assert_file_has_content_literal out.txt 'Ignoring failure of post-stage hook 30-smoke.hook'
+vm_rpmostree cleanup -p
+vm_cmd rm -rf /etc/rpm-ostree/hooks.d /run/rpm-ostree-hooks.log
+echo "ok update hooks"
+
+# Compare the booted and pending deployments
+vm_build_rpm difftest files "/usr/share/difftest" install "mkdir -p %{buildroot}/usr/share/difftest"
+vm_rpmostree install difftest
+vm_rpmostree diff booted pending > out.txt
+assert_file_has_content out.txt '^Added:' '^  difftest-1.0-1.x86_64$' '^  packages: '
+assert_not_file_has_content out.txt '^Files:'
+vm_rpmostree diff booted pending --files > out.txt
+assert_file_has_content out.txt '^  A /usr/share/difftest$'
+vm_rpmostree diff booted pending --json --files > out.json
+assert_jq out.json \
+  '.packages.added[0].name == "difftest"' \
+  '.packages.removed == []' \
+  '.origin.packages.to == ["difftest"]' \
+  '.files.added_dirs | index("/usr/share/difftest") != null'
+vm_rpmostree cleanup -p
+echo "ok diff"
+
+# Soft-reboot into a deployment which only changes userspace
+vm_rpmostree kargs --append=softreboottest
+if vm_rpmostree finalize --soft-reboot 2>err.txt; then
+  assert_not_reached "soft-rebooted with changed kargs?"
+fi
+assert_file_has_content_literal err.txt 'Cannot soft-reboot since the kernel arguments changed'
+vm_rpmostree cleanup -p
+vm_build_rpm softreboottest
+vm_rpmostree install softreboottest
+pending=$(vm_get_pending_csum)
+bootid=$(vm_get_boot_id)
+vm_cmd rpm-ostree finalize --soft-reboot || :
+# The kernel keeps running, so the boot ID doesn't change
+for i in $(seq 120); do
+  if [ "$(vm_get_booted_csum 2>/dev/null)" == "${pending}" ]; then
+    break
+  fi
+  sleep 1
+done
+assert_streq "$(vm_get_booted_csum)" "${pending}"
+assert_streq "$(vm_get_boot_id)" "${bootid}"
+vm_has_packages softreboottest
+vm_rpmostree uninstall softreboottest
+vm_reboot
+echo "ok finalize --soft-reboot"
+
+# Prepare an alternative to the staged deployment, and pick either when finalizing
+vm_build_rpm alttest-staged
+vm_build_rpm alttest-alt
+vm_rpmostree install alttest-staged
+vm_rpmostree upgrade --alternative --install alttest-alt > out.txt
+assert_file_has_content_literal out.txt 'Wrote alternative deployment'
+vm_assert_status_jq '.deployments[0]["staged"]' \
+                    '.deployments[0]["packages"] == ["alttest-staged"]' \
+                    '.deployments[1]["booted"]' \
+                    '.deployments[2]["pinned"]' \
+                    '.deployments[2]["alternative"]' \
+                    '.deployments[2]["pin-reason"] == null' \
+                    '.deployments[2]["packages"] == ["alttest-alt"]'
+vm_cmd test -f /var/lib/rpm-ostree/alternative.json
+vm_rpmostree status > out.txt
+assert_file_has_content_literal out.txt 'Alternative: yes'
+vm_reboot_cmd rpm-ostree finalize --alternative
+vm_assert_status_jq '.deployments[0]["booted"]' \
+                    '.deployments[0]["packages"] == ["alttest-alt"]' \
+                    '.deployments[0]["pinned"] | not' \
+                    '.deployments[0]["alternative"] == null' \
+                    '[.deployments[] | select(.packages | index("alttest-staged"))] == []'
+vm_rpmostree install alttest-staged
+vm_rpmostree upgrade --alternative --uninstall alttest-alt
+vm_reboot_cmd rpm-ostree finalize
+vm_assert_status_jq '.deployments[0]["booted"]' \
+                    '.deployments[0]["packages"] == ["alttest-alt", "alttest-staged"]' \
+                    '[.deployments[] | select(.pinned and .packages == [])] | length == 1'
+vm_rpmostree cleanup -r
+vm_assert_status_jq '[.deployments[] | select(.pinned and .packages == [])] == []'
+vm_cmd test ! -f /var/lib/rpm-ostree/alternative.json
+echo "ok alternative deployments"
+
+# Test deployments are booted once, then the previous default comes back
+vm_cmd ostree commit -b vmcheck_tmp/testdeploy --fsync=no --tree=ref=$(vm_get_booted_csum)
+vm_rpmostree testdeploy :vmcheck_tmp/testdeploy > out.txt
+assert_file_has_content_literal out.txt 'Test deployment staged'
+vm_assert_status_jq '.deployments[0]["staged"]' \
+                    '.deployments[0]["ephemeral"]' \
+                    '.deployments[0]["origin"] == "vmcheck_tmp/testdeploy"'
+vm_reboot
+# Wait for the service, in case it's still running
+vm_cmd systemctl start rpm-ostree-testdeploy.service
+vm_assert_status_jq '.deployments[0]["booted"] | not' \
+                    '.deployments[0]["origin"] != "vmcheck_tmp/testdeploy"' \
+                    '.deployments[1]["booted"]' \
+                    '.deployments[1]["ephemeral"]'
+vm_reboot
+vm_cmd systemctl start rpm-ostree-testdeploy.service
+vm_assert_status_jq '.deployments[0]["booted"]' \
+                    '[.deployments[] | select(.origin == "vmcheck_tmp/testdeploy")] == []'
+vm_cmd test ! -f /var/lib/rpm-ostree/testdeploy.json
+vm_cmd journalctl -b -u rpm-ostree-testdeploy.service > out.txt
+assert_file_has_content_literal out.txt 'Removed test deployment'
+echo "ok testdeploy"
+
+# Shell completion, with the values listed from the system
+vm_cmd rpm-ostree completion bash > completion.sh
+assert_file_has_content_literal completion.sh 'rpm-ostree__db,diff) cmdpath=rpm-ostree__db__diff ;;'
+bash -n completion.sh
+vm_cmd rpm-ostree completion zsh > completion.zsh
+assert_file_has_content_literal completion.zsh 'compdef _rpm-ostree rpm-ostree'
+vm_cmd rpm-ostree completion fish > completion.fish
+assert_file_has_content_literal completion.fish '__rpm_ostree_at rpm-ostree__uninstall'
+vm_cmd rpm-ostree completion complete deployments > out.txt
+assert_file_has_content out.txt '^0$'
+assert_file_has_content out.txt '^booted$'
+vm_cmd rpm-ostree completion complete revisions > out.txt
+assert_file_has_content_literal out.txt "$(vm_get_booted_csum)"
+vm_cmd ostree remote add completiontest --no-gpg-verify http://localhost:1/
+vm_cmd rpm-ostree completion complete --describe remotes > out.txt
+assert_file_has_content_literal out.txt "$(printf 'completiontest\thttp://localhost:1/')"
+vm_cmd ostree remote delete completiontest
+vm_cmd rpm-ostree completion complete options rpm-ostree__upgrade > out.txt
+assert_file_has_content out.txt '^--reboot$'
+vm_cmd rpm-ostree completion complete options rpm-ostree__pin > out.txt
+assert_file_has_content out.txt '^--unpin$'
+echo "ok completion"
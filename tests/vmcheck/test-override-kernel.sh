@@ -78,3 +78,28 @@ vm_cmd lsinitrd -m ${newroot}/usr/lib/modules/${kernel_release}/initramfs.img >
 # FCOS omits lvm; check that we still omit lvm here too
 assert_not_file_has_content_literal lsinitrd-modules.txt lvm
 echo "ok override kernel uses base initramfs args"
+
+vm_rpmostree override reset --all
+# replace-kernel refuses to replace only part of the kernel
+if vm_rpmostree override replace-kernel \
+  "$URL_ROOT/kernel{,-core}-$kernel_release.rpm" 2>err.txt; then
+  assert_not_reached "replaced partial kernel"
+fi
+assert_file_has_content err.txt 'the kernel must be replaced as a unit'
+vm_rpmostree override replace-kernel \
+  "$URL_ROOT/kernel{,-core,-modules{,-extra}}-$kernel_release.rpm"
+newroot=$(vm_get_deployment_root 0)
+vm_cmd lsinitrd ${newroot}/usr/lib/modules/${kernel_release}/initramfs.img > lsinitrd.txt
+assert_file_has_content_literal lsinitrd.txt etc/foobar.conf
+echo "ok override replace-kernel"
+
+# kernel-install plugins are run for the new kernel, before depmod
+vm_build_rpm kernel-install-plugin \
+  install "mkdir -p %{buildroot}/usr/lib/kernel/install.d
+           printf '#!/bin/sh\ntouch /usr/lib/modules/\$2/kernel-install-plugin-ran\n' > %{buildroot}/usr/lib/kernel/install.d/50-testing.install
+           chmod a+x %{buildroot}/usr/lib/kernel/install.d/50-testing.install" \
+  files "/usr/lib/kernel/install.d/50-testing.install"
+vm_rpmostree install kernel-install-plugin
+newroot=$(vm_get_deployment_root 0)
+vm_cmd test -f ${newroot}/usr/lib/modules/${kernel_release}/kernel-install-plugin-ran
+echo "ok override kernel runs kernel-install plugins"
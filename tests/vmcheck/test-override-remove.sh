@@ -226,3 +226,15 @@ vm_rpmostree override remove foo --install boo
 vm_status_watch_check "Transaction: override remove foo --install boo"
 vm_rpmostree cleanup -p
 echo "ok remove and --install at the same time"
+
+vm_cmd mkdir -p /etc/rpm-ostree/protected.d
+vm_cmd "echo foo > /etc/rpm-ostree/protected.d/test.conf"
+if vm_rpmostree override remove foo 2>err.txt; then
+  assert_not_reached "override remove of protected pkg succeeded?"
+fi
+assert_file_has_content err.txt 'Refusing to override protected packages: foo'
+vm_rpmostree override remove foo --allow-protected
+vm_assert_status_jq '.deployments[0]["base-removals"]|length == 1'
+vm_rpmostree cleanup -p
+vm_cmd rm -rf /etc/rpm-ostree/protected.d
+echo "ok override remove protected pkg"
@@ -87,3 +87,47 @@ vm_assert_status_jq ".deployments[0][\"booted\"]|not" \
                     ".deployments[0][\"version\"] == \"v3\""
 
 echo "ok deploy"
+
+vm_rpmostree deploy --list > list.txt
+assert_file_has_content list.txt "^  v3 .* ${v3rev}$"
+assert_file_has_content list.txt '^● v1 .* (booted)$'
+assert_not_file_has_content list.txt 'list-page'
+vm_rpmostree deploy --list-page=2 > list.txt
+assert_file_has_content list.txt 'No commits found on page 2'
+if vm_rpmostree deploy --list v3 2>err.txt; then
+  assert_not_reached "deploy --list with a revision succeeded"
+fi
+assert_file_has_content err.txt 'cannot be combined with REVISION'
+echo "ok deploy --list"
+
+# Upgrades follow the update graph of the remote, if it has one
+v1rev=$(vm_get_booted_csum)
+vm_rpmostree cleanup -p
+vm_rpmostree rebase otherremote:vmcheck ${v1rev}
+vm_cmd "echo update-graph-url=http://localhost:8888/graph.json >> /etc/ostree/remotes.d/otherremote.conf"
+write_graph() {
+  vm_shell_inline_sysroot_rw <<EOF
+  cat > $REMOTE_OSTREE/graph.json <<EOG
+{ "nodes": [ { "version": "v1", "payload": "${v1rev}" },
+             { "version": "v3", "payload": "${v3rev}" } ],
+  "edges": [$1] }
+EOG
+EOF
+}
+write_graph ""
+vm_rpmostree upgrade > out.txt
+assert_file_has_content out.txt "No upgrade from v1 in update graph"
+vm_assert_status_jq ".deployments[0][\"version\"] == \"v1\""
+write_graph "[0, 1]"
+vm_rpmostree upgrade --check > out.txt
+assert_file_has_content out.txt "Update graph path: v1 -> v3"
+vm_rpmostree upgrade > out.txt
+assert_file_has_content out.txt "Update graph path: v1 -> v3"
+vm_assert_status_jq ".deployments[0][\"booted\"]|not" \
+                    ".deployments[0][\"version\"] == \"v3\""
+# The target isn't pinned in the new origin
+root=$(vm_get_deployment_root 0)
+vm_cmd cat ${root}.origin > origin.txt
+assert_not_file_has_content origin.txt "override-commit"
+vm_cmd sed -i -e /update-graph-url/d /etc/ostree/remotes.d/otherremote.conf
+echo "ok upgrade with update graph"